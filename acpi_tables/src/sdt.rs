@@ -68,6 +68,30 @@ impl Sdt {
         sdt
     }
 
+    /// Create a table from its raw content, such as a compiled AML file.
+    /// Returns None if the content doesn't match the length from its header.
+    pub fn from_bytes(data: Vec<u8>) -> Option<Self> {
+        if data.len() < 36 {
+            return None;
+        }
+
+        let length = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        if length as usize != data.len() {
+            return None;
+        }
+
+        Some(Sdt { data })
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        [self.data[0], self.data[1], self.data[2], self.data[3]]
+    }
+
+    pub fn set_oem_table_id(&mut self, oem_table_id: [u8; 8]) {
+        self.data[16..24].copy_from_slice(&oem_table_id);
+        self.update_checksum();
+    }
+
     pub fn update_checksum(&mut self) {
         self.data[9] = 0;
         let checksum = super::generate_checksum(self.data.as_slice());
@@ -143,4 +167,23 @@ mod tests {
             .fold(0u8, |acc, x| acc.wrapping_add(*x));
         assert_eq!(sum, 0);
     }
+
+    #[test]
+    fn test_sdt_from_bytes() {
+        let sdt = Sdt::new(*b"SSDT", 40, 2, *b"CLOUDH", *b"TESTTEST", 1);
+        let mut table = Sdt::from_bytes(sdt.as_slice().to_vec()).unwrap();
+        assert_eq!(table.signature(), *b"SSDT");
+        assert_eq!(table.as_slice(), sdt.as_slice());
+
+        table.set_oem_table_id(*b"MYSSDT  ");
+        assert_eq!(&table.as_slice()[16..24], b"MYSSDT  ");
+        let sum: u8 = table
+            .as_slice()
+            .iter()
+            .fold(0u8, |acc, x| acc.wrapping_add(*x));
+        assert_eq!(sum, 0);
+
+        assert!(Sdt::from_bytes(sdt.as_slice()[..36].to_vec()).is_none());
+        assert!(Sdt::from_bytes(b"SSDT".to_vec()).is_none());
+    }
 }
@@ -0,0 +1,223 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Named CPU models used as a baseline for the features exposed to the guest
+//! through the ID registers. Each model describes the features of a
+//! processor generation, so that a VM started with a given model can be
+//! migrated between hosts of that generation or any newer one.
+//!
+//! Lowering the ID registers requires a host kernel allowing them to be
+//! written from userspace.
+
+use hypervisor::aarch64::{ID_AA64ISAR0_EL1, ID_AA64ISAR1_EL1};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum CpuModel {
+    NeoverseN1,
+    NeoverseV1,
+    NeoverseN2,
+}
+
+// Names of the 4 bits fields of ID_AA64ISAR0_EL1, from the lowest one. The
+// fields without a name aren't restricted by the models.
+const ISAR0_FIELDS: [Option<&str>; 16] = [
+    None,
+    Some("aes"),
+    Some("sha1"),
+    Some("sha2"),
+    Some("crc32"),
+    Some("atomic"),
+    Some("tme"),
+    Some("rdm"),
+    Some("sha3"),
+    Some("sm3"),
+    Some("sm4"),
+    Some("dp"),
+    Some("fhm"),
+    Some("ts"),
+    Some("tlb"),
+    Some("rndr"),
+];
+
+// Names of the 4 bits fields of ID_AA64ISAR1_EL1. Pointer authentication
+// (APA, API, GPA and GPI) isn't enabled on the vCPUs, hence always hidden.
+const ISAR1_FIELDS: [Option<&str>; 16] = [
+    Some("dpb"),
+    None,
+    None,
+    Some("jscvt"),
+    Some("fcma"),
+    Some("lrcpc"),
+    None,
+    None,
+    Some("frintts"),
+    Some("sb"),
+    Some("specres"),
+    Some("bf16"),
+    Some("dgh"),
+    Some("i8mm"),
+    Some("xs"),
+    Some("ls64"),
+];
+
+/// Errors associated with the CPU models.
+#[derive(Debug)]
+pub enum Error {
+    /// The host CPU lacks some features of the CPU model.
+    Unsupported(CpuModel, Vec<&'static str>),
+
+    /// Error reading an ID register.
+    GetIdRegister(hypervisor::HypervisorCpuError),
+
+    /// Error writing an ID register. The host kernel may not allow it.
+    SetIdRegister(hypervisor::HypervisorCpuError),
+}
+
+// Value of each field of an ID register.
+fn fields(value: u64) -> [u8; 16] {
+    let mut fields = [0; 16];
+    for (i, field) in fields.iter_mut().enumerate() {
+        *field = ((value >> (i * 4)) & 0xf) as u8;
+    }
+    fields
+}
+
+impl CpuModel {
+    pub const ALL: [CpuModel; 3] = [
+        CpuModel::NeoverseN1,
+        CpuModel::NeoverseV1,
+        CpuModel::NeoverseN2,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuModel::NeoverseN1 => "neoverse-n1",
+            CpuModel::NeoverseV1 => "neoverse-v1",
+            CpuModel::NeoverseN2 => "neoverse-n2",
+        }
+    }
+
+    // Fields of ID_AA64ISAR0_EL1 and ID_AA64ISAR1_EL1 for the model.
+    fn id_registers(&self) -> [(u64, &'static [Option<&'static str>; 16], [u8; 16]); 2] {
+        let (isar0, isar1) = match self {
+            CpuModel::NeoverseN1 => (
+                [0, 2, 1, 1, 1, 2, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0],
+                [1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            ),
+            CpuModel::NeoverseV1 => (
+                [0, 2, 1, 2, 1, 2, 0, 1, 1, 1, 1, 1, 1, 1, 2, 1],
+                [2, 0, 0, 1, 1, 2, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0],
+            ),
+            CpuModel::NeoverseN2 => (
+                [0, 2, 1, 2, 1, 2, 0, 1, 1, 1, 1, 1, 1, 2, 2, 1],
+                [2, 0, 0, 1, 1, 2, 0, 0, 1, 1, 1, 1, 0, 1, 0, 0],
+            ),
+        };
+        [
+            (ID_AA64ISAR0_EL1, &ISAR0_FIELDS, isar0),
+            (ID_AA64ISAR1_EL1, &ISAR1_FIELDS, isar1),
+        ]
+    }
+
+    // Lowers the fields of the host ID register to the ones of the model,
+    // returning the names of the fields the host doesn't provide.
+    fn restrict(
+        host: u64,
+        names: &[Option<&'static str>; 16],
+        model: &[u8; 16],
+    ) -> (u64, Vec<&'static str>) {
+        let mut value = host;
+        let mut missing = Vec::new();
+        for (i, field) in fields(host).iter().enumerate() {
+            let name = match names[i] {
+                Some(name) => name,
+                None => continue,
+            };
+            if *field < model[i] {
+                missing.push(name);
+            } else {
+                value &= !(0xf << (i * 4));
+                value |= (model[i] as u64) << (i * 4);
+            }
+        }
+        (value, missing)
+    }
+
+    /// Restricts the ID registers of the vCPU to the features of the model.
+    /// Must be called before the vCPU first runs.
+    pub fn apply(&self, vcpu: &Arc<dyn hypervisor::Vcpu>) -> Result<(), Error> {
+        let mut values = Vec::new();
+        let mut missing = Vec::new();
+        for (reg, names, model) in self.id_registers() {
+            let host = vcpu.get_reg(reg).map_err(Error::GetIdRegister)?;
+            let (value, reg_missing) = Self::restrict(host, names, &model);
+            values.push((reg, host, value));
+            missing.extend(reg_missing);
+        }
+        if !missing.is_empty() {
+            return Err(Error::Unsupported(*self, missing));
+        }
+
+        for (reg, host, value) in values {
+            if value != host {
+                vcpu.set_reg(reg, value).map_err(Error::SetIdRegister)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CpuModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for CpuModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CpuModel::ALL
+            .iter()
+            .find(|model| model.name() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown CPU model: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_model_names() {
+        for model in CpuModel::ALL {
+            assert_eq!(model.name().parse::<CpuModel>().unwrap(), model);
+        }
+        assert!("cortex-a53".parse::<CpuModel>().is_err());
+    }
+
+    #[test]
+    fn test_cpu_model_restrict() {
+        // Host providing more features than the N1, including pointer
+        // authentication.
+        let host_isar1: u64 = 0x0011_1110_2112_1122;
+        let [_, (_, names, model)] = CpuModel::NeoverseN1.id_registers();
+        let (value, missing) = CpuModel::restrict(host_isar1, names, &model);
+        assert!(missing.is_empty());
+        // Only DPB and LRCPC are kept, lowered to their N1 values, while the
+        // pointer authentication fields are untouched.
+        assert_eq!(value, 0x0000_0000_2110_0121);
+
+        let (_, missing) = CpuModel::restrict(0, names, &model);
+        assert_eq!(missing, vec!["dpb", "lrcpc"]);
+    }
+}
@@ -6,9 +6,11 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the THIRD-PARTY file.
 
-use crate::{NumaNodes, PciSpaceInfo};
+use crate::{HypervisorInfo, NumaNodes, PayloadBlob, PciSpaceInfo};
 use byteorder::{BigEndian, ByteOrder};
 use hypervisor::arch::aarch64::gic::Vgic;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::cmp;
 use std::collections::HashMap;
 use std::ffi::CStr;
@@ -21,8 +23,8 @@ use super::super::DeviceType;
 use super::super::GuestMemoryMmap;
 use super::super::InitramfsConfig;
 use super::layout::{
-    IRQ_BASE, MEM_32BIT_DEVICES_SIZE, MEM_32BIT_DEVICES_START, MEM_PCI_IO_SIZE, MEM_PCI_IO_START,
-    PCI_HIGH_BASE, PCI_MMIO_CONFIG_SIZE_PER_SEGMENT,
+    FDT_MAX_SIZE, IRQ_BASE, MEM_32BIT_DEVICES_SIZE, MEM_32BIT_DEVICES_START, MEM_PCI_IO_SIZE,
+    MEM_PCI_IO_START, PCI_HIGH_BASE, PCI_MMIO_CONFIG_SIZE_PER_SEGMENT,
 };
 use vm_fdt::{FdtWriter, FdtWriterResult};
 use vm_memory::{Address, Bytes, GuestMemory, GuestMemoryError, GuestMemoryRegion};
@@ -80,6 +82,22 @@ pub trait DeviceInfoForFdt {
 pub enum Error {
     /// Failure in writing FDT in memory.
     WriteFdtToMemory(GuestMemoryError),
+    /// Failure in parsing the FDT.
+    ParseFdt,
+    /// Failure in parsing a FDT overlay.
+    ParseFdtOverlay,
+    /// Invalid or unknown target for a FDT overlay fragment.
+    FdtOverlayTarget(String),
+    /// Failure in writing the FDT with overlays applied.
+    WriteFdt(vm_fdt::Error),
+    /// Unknown node targeted by a FDT edit.
+    FdtEditTarget(String),
+    /// Invalid FDT edit.
+    InvalidFdtEdit(String),
+    /// Node generated for the VM not fitting the cells of the pre-built FDT.
+    InvalidFdtPatch(String),
+    /// FDT larger than the guest memory reserved for it.
+    FdtTooLarge(usize),
 }
 type Result<T> = result::Result<T, Error>;
 
@@ -89,14 +107,17 @@ pub fn create_fdt<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHash
     guest_mem: &GuestMemoryMmap,
     cmdline: &str,
     vcpu_mpidr: Vec<u64>,
-    vcpu_topology: Option<(u8, u8, u8)>,
+    vcpu_topology: Option<(u8, u8, u8, u8)>,
     device_info: &HashMap<(DeviceType, String), T, S>,
     gic_device: &Arc<Mutex<dyn Vgic>>,
     initrd: &Option<InitramfsConfig>,
+    payload_blobs: &[PayloadBlob],
     pci_space_info: &[PciSpaceInfo],
     numa_nodes: &NumaNodes,
     virtio_iommu_bdf: Option<u32>,
     pmu_supported: bool,
+    hypervisor_info: Option<&HypervisorInfo>,
+    acpi_direct_boot: bool,
 ) -> FdtWriterResult<Vec<u8>> {
     // Allocate stuff necessary for the holding the blob.
     let mut fdt = FdtWriter::new().unwrap();
@@ -118,6 +139,9 @@ pub fn create_fdt<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHash
     create_cpu_nodes(&mut fdt, &vcpu_mpidr, vcpu_topology, numa_nodes)?;
     create_memory_node(&mut fdt, guest_mem, numa_nodes)?;
     create_chosen_node(&mut fdt, cmdline, initrd)?;
+    if !payload_blobs.is_empty() || acpi_direct_boot {
+        create_reserved_memory_node(&mut fdt, payload_blobs, acpi_direct_boot)?;
+    }
     create_gic_node(&mut fdt, gic_device)?;
     create_timer_node(&mut fdt)?;
     if pmu_supported {
@@ -125,6 +149,9 @@ pub fn create_fdt<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHash
     }
     create_clock_node(&mut fdt)?;
     create_psci_node(&mut fdt)?;
+    if let Some(hypervisor_info) = hypervisor_info {
+        create_hypervisor_info_node(&mut fdt, hypervisor_info)?;
+    }
     create_devices_node(&mut fdt, device_info)?;
     create_pci_nodes(&mut fdt, pci_space_info, virtio_iommu_bdf)?;
     if numa_nodes.len() > 1 {
@@ -147,11 +174,103 @@ pub fn write_fdt_to_memory(fdt_final: Vec<u8>, guest_mem: &GuestMemoryMmap) -> R
     Ok(())
 }
 
+/// Topology of the vCPUs, described by the `cpu-map` node. The clusters stand
+/// for the dies of the packages, the sockets.
+struct CpuTopology {
+    threads_per_core: usize,
+    cores_per_cluster: usize,
+    clusters_per_socket: usize,
+    sockets: usize,
+}
+
+impl CpuTopology {
+    fn new(vcpu_topology: (u8, u8, u8, u8)) -> Option<Self> {
+        let (threads_per_core, cores_per_die, dies_per_package, packages) = vcpu_topology;
+        if threads_per_core == 0 || cores_per_die == 0 || dies_per_package == 0 || packages == 0 {
+            return None;
+        }
+
+        Some(CpuTopology {
+            threads_per_core: threads_per_core as usize,
+            cores_per_cluster: cores_per_die as usize,
+            clusters_per_socket: dies_per_package as usize,
+            sockets: packages as usize,
+        })
+    }
+}
+
+fn create_cluster_nodes(
+    fdt: &mut FdtWriter,
+    topology: &CpuTopology,
+    num_cpus: usize,
+    cpu_id: &mut usize,
+) -> FdtWriterResult<()> {
+    for cluster_idx in 0..topology.clusters_per_socket {
+        if *cpu_id >= num_cpus {
+            break;
+        }
+        let cluster_node = fdt.begin_node(&format!("cluster{}", cluster_idx))?;
+
+        for core_idx in 0..topology.cores_per_cluster {
+            if *cpu_id >= num_cpus {
+                break;
+            }
+            let core_node = fdt.begin_node(&format!("core{}", core_idx))?;
+            if topology.threads_per_core > 1 {
+                for thread_idx in 0..topology.threads_per_core {
+                    if *cpu_id >= num_cpus {
+                        break;
+                    }
+                    let thread_node = fdt.begin_node(&format!("thread{}", thread_idx))?;
+                    fdt.property_u32("cpu", *cpu_id as u32 + FIRST_VCPU_PHANDLE)?;
+                    fdt.end_node(thread_node)?;
+                    *cpu_id += 1;
+                }
+            } else {
+                fdt.property_u32("cpu", *cpu_id as u32 + FIRST_VCPU_PHANDLE)?;
+                *cpu_id += 1;
+            }
+            fdt.end_node(core_node)?;
+        }
+
+        fdt.end_node(cluster_node)?;
+    }
+
+    Ok(())
+}
+
+fn create_cpu_map_node(
+    fdt: &mut FdtWriter,
+    topology: &CpuTopology,
+    num_cpus: usize,
+) -> FdtWriterResult<()> {
+    // See https://github.com/torvalds/linux/blob/master/Documentation/devicetree/bindings/cpu/cpu-topology.txt.
+    // Only the vCPUs described by a node are mapped, a reference to a
+    // missing node making the guest drop the whole map.
+    let cpu_map_node = fdt.begin_node("cpu-map")?;
+    let mut cpu_id = 0;
+    // Linux only parses the socket nodes from 6.0, older versions ignoring
+    // the clusters they contain, hence they are only used when needed.
+    if topology.sockets > 1 {
+        for socket_idx in 0..topology.sockets {
+            if cpu_id >= num_cpus {
+                break;
+            }
+            let socket_node = fdt.begin_node(&format!("socket{}", socket_idx))?;
+            create_cluster_nodes(fdt, topology, num_cpus, &mut cpu_id)?;
+            fdt.end_node(socket_node)?;
+        }
+    } else {
+        create_cluster_nodes(fdt, topology, num_cpus, &mut cpu_id)?;
+    }
+    fdt.end_node(cpu_map_node)
+}
+
 // Following are the auxiliary function for creating the different nodes that we append to our FDT.
-fn create_cpu_nodes(
+pub fn create_cpu_nodes(
     fdt: &mut FdtWriter,
     vcpu_mpidr: &[u64],
-    vcpu_topology: Option<(u8, u8, u8)>,
+    vcpu_topology: Option<(u8, u8, u8, u8)>,
     numa_nodes: &NumaNodes,
 ) -> FdtWriterResult<()> {
     // See https://github.com/torvalds/linux/blob/master/Documentation/devicetree/bindings/arm/cpus.yaml.
@@ -160,6 +279,7 @@ fn create_cpu_nodes(
     fdt.property_u32("#size-cells", 0x0)?;
 
     let num_cpus = vcpu_mpidr.len();
+    let topology = vcpu_topology.and_then(CpuTopology::new);
 
     for (cpu_id, mpidr) in vcpu_mpidr.iter().enumerate().take(num_cpus) {
         let cpu_name = format!("cpu@{:x}", cpu_id);
@@ -188,34 +308,8 @@ fn create_cpu_nodes(
         fdt.end_node(cpu_node)?;
     }
 
-    if let Some(topology) = vcpu_topology {
-        let (threads_per_core, cores_per_package, packages) = topology;
-        let cpu_map_node = fdt.begin_node("cpu-map")?;
-
-        // Create device tree nodes with regard of above mapping.
-        for cluster_idx in 0..packages {
-            let cluster_name = format!("cluster{:x}", cluster_idx);
-            let cluster_node = fdt.begin_node(&cluster_name)?;
-
-            for core_idx in 0..cores_per_package {
-                let core_name = format!("core{:x}", core_idx);
-                let core_node = fdt.begin_node(&core_name)?;
-
-                for thread_idx in 0..threads_per_core {
-                    let thread_name = format!("thread{:x}", thread_idx);
-                    let thread_node = fdt.begin_node(&thread_name)?;
-                    let cpu_idx = threads_per_core * cores_per_package * cluster_idx
-                        + threads_per_core * core_idx
-                        + thread_idx;
-                    fdt.property_u32("cpu", cpu_idx as u32 + FIRST_VCPU_PHANDLE)?;
-                    fdt.end_node(thread_node)?;
-                }
-
-                fdt.end_node(core_node)?;
-            }
-            fdt.end_node(cluster_node)?;
-        }
-        fdt.end_node(cpu_map_node)?;
+    if let Some(topology) = &topology {
+        create_cpu_map_node(fdt, topology, num_cpus)?;
     } else {
         debug!("Boot using device tree, CPU topology is not (correctly) specified");
     }
@@ -316,6 +410,41 @@ fn create_chosen_node(
     Ok(())
 }
 
+// The payload blobs are described as reserved memory, so that the guest
+// doesn't reuse it, and identified through their "id" property. So are the
+// ACPI tables when the guest isn't given their location by UEFI firmware,
+// the memory being left mapped for the kernel to access them.
+fn create_reserved_memory_node(
+    fdt: &mut FdtWriter,
+    payload_blobs: &[PayloadBlob],
+    acpi_tables: bool,
+) -> FdtWriterResult<()> {
+    let reserved_memory_node = fdt.begin_node("reserved-memory")?;
+    fdt.property_u32("#address-cells", ADDRESS_CELLS)?;
+    fdt.property_u32("#size-cells", SIZE_CELLS)?;
+    fdt.property_null("ranges")?;
+
+    if acpi_tables {
+        let address = super::layout::ACPI_START.raw_value();
+        let acpi_node = fdt.begin_node(&format!("acpi@{:x}", address))?;
+        fdt.property_array_u64("reg", &[address, super::layout::ACPI_MAX_SIZE])?;
+        fdt.end_node(acpi_node)?;
+    }
+
+    for payload_blob in payload_blobs {
+        let address = payload_blob.address.raw_value();
+        let payload_blob_node = fdt.begin_node(&format!("payload@{:x}", address))?;
+        fdt.property_string("compatible", "craton,payload-blob")?;
+        fdt.property_array_u64("reg", &[address, payload_blob.size as u64])?;
+        fdt.property_string("id", &payload_blob.id)?;
+        fdt.end_node(payload_blob_node)?;
+    }
+
+    fdt.end_node(reserved_memory_node)?;
+
+    Ok(())
+}
+
 fn create_gic_node(fdt: &mut FdtWriter, gic_device: &Arc<Mutex<dyn Vgic>>) -> FdtWriterResult<()> {
     let gic_reg_prop = gic_device.lock().unwrap().device_properties();
 
@@ -407,12 +536,37 @@ fn create_psci_node(fdt: &mut FdtWriter) -> FdtWriterResult<()> {
     Ok(())
 }
 
+// Describes the VMM and the paravirtual features enabled for the VM. The
+// "hypervisor" node name is left to the Xen binding.
+fn create_hypervisor_info_node(fdt: &mut FdtWriter, info: &HypervisorInfo) -> FdtWriterResult<()> {
+    let hypervisor_info_node = fdt.begin_node("hypervisor-info")?;
+    fdt.property_string("compatible", "craton,hypervisor-info")?;
+    fdt.property_array_u32("version", &info.version)?;
+    fdt.property_u32("features", info.features)?;
+    if let Some(uuid) = &info.uuid {
+        fdt.property_string("uuid", &uuid.to_string())?;
+    }
+    fdt.end_node(hypervisor_info_node)?;
+
+    Ok(())
+}
+
 fn create_virtio_node<T: DeviceInfoForFdt + Clone + Debug>(
     fdt: &mut FdtWriter,
     dev_info: &T,
+    shm_info: Option<&T>,
 ) -> FdtWriterResult<()> {
-    let device_reg_prop = [dev_info.addr(), dev_info.length()];
-    let irq = [GIC_FDT_IRQ_TYPE_SPI, dev_info.irq(), IRQ_TYPE_EDGE_RISING];
+    // The shared memory window of the device follows its registers, so that
+    // the guest keeps it away from the other devices.
+    let mut device_reg_prop = vec![dev_info.addr(), dev_info.length()];
+    if let Some(shm_info) = shm_info {
+        device_reg_prop.extend([shm_info.addr(), shm_info.length()]);
+    }
+    let irq = [
+        GIC_FDT_IRQ_TYPE_SPI,
+        dev_info.irq() - IRQ_BASE,
+        IRQ_TYPE_EDGE_RISING,
+    ];
 
     let virtio_node = fdt.begin_node(&format!("virtio_mmio@{:x}", dev_info.addr()))?;
     fdt.property_string("compatible", "virtio,mmio")?;
@@ -510,32 +664,159 @@ fn create_gpio_node<T: DeviceInfoForFdt + Clone + Debug>(
     Ok(())
 }
 
+fn create_pvpanic_node<T: DeviceInfoForFdt + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> FdtWriterResult<()> {
+    let pvpanic_reg_prop = [dev_info.addr(), dev_info.length()];
+
+    let pvpanic_node = fdt.begin_node(&format!("pvpanic-mmio@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "qemu,pvpanic-mmio")?;
+    fdt.property_array_u64("reg", &pvpanic_reg_prop)?;
+    fdt.end_node(pvpanic_node)?;
+
+    Ok(())
+}
+
+fn create_debug_console_node<T: DeviceInfoForFdt + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> FdtWriterResult<()> {
+    let reg_prop = [dev_info.addr(), dev_info.length()];
+
+    let node = fdt.begin_node(&format!("debug-console@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "craton,debug-console")?;
+    fdt.property_array_u64("reg", &reg_prop)?;
+    fdt.end_node(node)?;
+
+    Ok(())
+}
+
+fn create_partition_services_node<T: DeviceInfoForFdt + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> FdtWriterResult<()> {
+    let reg_prop = [dev_info.addr(), dev_info.length()];
+
+    let node = fdt.begin_node(&format!("partition-services@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "craton,partition-services")?;
+    fdt.property_array_u64("reg", &reg_prop)?;
+    fdt.end_node(node)?;
+
+    Ok(())
+}
+
+fn create_dt_hotplug_node<T: DeviceInfoForFdt + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> FdtWriterResult<()> {
+    let reg_prop = [dev_info.addr(), dev_info.length()];
+    let irq = [
+        GIC_FDT_IRQ_TYPE_SPI,
+        dev_info.irq() - IRQ_BASE,
+        IRQ_TYPE_EDGE_RISING,
+    ];
+
+    let node = fdt.begin_node(&format!("dt-hotplug@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "craton,dt-hotplug")?;
+    fdt.property_array_u64("reg", &reg_prop)?;
+    fdt.property_array_u32("interrupts", &irq)?;
+    fdt.end_node(node)?;
+
+    Ok(())
+}
+
+fn create_watchdog_node<T: DeviceInfoForFdt + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    dev_info: &T,
+) -> FdtWriterResult<()> {
+    // The control frame is followed by the refresh frame.
+    let frame_size = dev_info.length() / 2;
+    let watchdog_reg_prop = [
+        dev_info.addr(),
+        frame_size,
+        dev_info.addr() + frame_size,
+        frame_size,
+    ];
+    let irq = [
+        GIC_FDT_IRQ_TYPE_SPI,
+        dev_info.irq() - IRQ_BASE,
+        IRQ_TYPE_LEVEL_HI,
+    ];
+
+    let watchdog_node = fdt.begin_node(&format!("watchdog@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "arm,sbsa-gwdt")?;
+    fdt.property_array_u64("reg", &watchdog_reg_prop)?;
+    fdt.property_array_u32("interrupts", &irq)?;
+    fdt.end_node(watchdog_node)?;
+
+    Ok(())
+}
+
+fn create_doorbell_node<T: DeviceInfoForFdt + Clone + Debug>(
+    fdt: &mut FdtWriter,
+    id: &str,
+    dev_info: &T,
+) -> FdtWriterResult<()> {
+    let doorbell_reg_prop = [dev_info.addr(), dev_info.length()];
+    let irq = [
+        GIC_FDT_IRQ_TYPE_SPI,
+        dev_info.irq() - IRQ_BASE,
+        IRQ_TYPE_EDGE_RISING,
+    ];
+
+    let doorbell_node = fdt.begin_node(&format!("doorbell@{:x}", dev_info.addr()))?;
+    fdt.property_string("compatible", "craton,doorbell")?;
+    fdt.property_array_u64("reg", &doorbell_reg_prop)?;
+    fdt.property_array_u32("interrupts", &irq)?;
+    fdt.property_string("id", id)?;
+    fdt.end_node(doorbell_node)?;
+
+    Ok(())
+}
+
 fn create_devices_node<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHasher>(
     fdt: &mut FdtWriter,
     dev_info: &HashMap<(DeviceType, String), T, S>,
 ) -> FdtWriterResult<()> {
-    // Create one temp Vec to store all virtio devices
-    let mut ordered_virtio_device: Vec<&T> = Vec::new();
-
-    for ((device_type, _device_id), info) in dev_info {
+    for ((device_type, device_id), info) in dev_info {
         match device_type {
+            DeviceType::DebugConsole => create_debug_console_node(fdt, info)?,
+            DeviceType::Doorbell => create_doorbell_node(fdt, device_id, info)?,
             DeviceType::Gpio => create_gpio_node(fdt, info)?,
             DeviceType::Rtc => create_rtc_node(fdt, info)?,
+            DeviceType::PvPanic => create_pvpanic_node(fdt, info)?,
+            DeviceType::PartitionServices => create_partition_services_node(fdt, info)?,
+            DeviceType::DtHotplug => create_dt_hotplug_node(fdt, info)?,
             DeviceType::Serial => create_serial_node(fdt, info)?,
-            DeviceType::Virtio(_) => {
-                ordered_virtio_device.push(info);
-            }
+            DeviceType::Watchdog => create_watchdog_node(fdt, info)?,
+            // Described by create_virtio_nodes().
+            DeviceType::Virtio(_) | DeviceType::SharedMemory => {}
         }
     }
 
+    create_virtio_nodes(fdt, dev_info)
+}
+
+fn create_virtio_nodes<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHasher>(
+    fdt: &mut FdtWriter,
+    dev_info: &HashMap<(DeviceType, String), T, S>,
+) -> FdtWriterResult<()> {
+    let mut ordered_virtio_device: Vec<(&String, &T)> = dev_info
+        .iter()
+        .filter(|((device_type, _), _)| matches!(device_type, DeviceType::Virtio(_)))
+        .map(|((_, device_id), info)| (device_id, info))
+        .collect();
+
     // Sort out virtio devices by address from low to high and insert them into fdt table.
-    ordered_virtio_device.sort_by_key(|&a| a.addr());
+    ordered_virtio_device.sort_by_key(|&(_, a)| a.addr());
     // Current address allocation strategy in cloud-hypervisor is: the first created device
     // will be allocated to higher address. Here we reverse the vector to make sure that
     // the older created device will appear in front of the newer created device in FDT.
     ordered_virtio_device.reverse();
-    for ordered_device_info in ordered_virtio_device.drain(..) {
-        create_virtio_node(fdt, ordered_device_info)?;
+    for (device_id, ordered_device_info) in ordered_virtio_device.drain(..) {
+        let shm_info = dev_info.get(&(DeviceType::SharedMemory, device_id.clone()));
+        create_virtio_node(fdt, ordered_device_info, shm_info)?;
     }
 
     Ok(())
@@ -594,16 +875,22 @@ fn create_pci_nodes(
         let pci_device_base_32bit: u64 = MEM_32BIT_DEVICES_START.0
             + pci_device_size_32bit * pci_device_info_elem.pci_segment_id as u64;
 
-        let ranges = [
-            // io addresses. Since AArch64 will not use IO address,
-            // we can set the same IO address range for every segment.
-            0x1000000,
-            0_u32,
-            0_u32,
-            (MEM_PCI_IO_START.0 >> 32) as u32,
-            MEM_PCI_IO_START.0 as u32,
-            (MEM_PCI_IO_SIZE >> 32) as u32,
-            MEM_PCI_IO_SIZE as u32,
+        let mut ranges = Vec::new();
+        // io addresses. The I/O ports are reached through a single window,
+        // given to the default segment so that the CPU addresses of the
+        // segments don't overlap.
+        if pci_device_info_elem.pci_segment_id == 0 {
+            ranges.extend_from_slice(&[
+                0x1000000,
+                0_u32,
+                0_u32,
+                (MEM_PCI_IO_START.0 >> 32) as u32,
+                MEM_PCI_IO_START.0 as u32,
+                (MEM_PCI_IO_SIZE >> 32) as u32,
+                MEM_PCI_IO_SIZE as u32,
+            ]);
+        }
+        ranges.extend_from_slice(&[
             // mmio addresses
             0x2000000,                            // (ss = 10: 32-bit memory space)
             (pci_device_base_32bit >> 32) as u32, // PCI address
@@ -620,8 +907,10 @@ fn create_pci_nodes(
             pci_device_base_64bit as u32,
             (pci_device_size_64bit >> 32) as u32, // size
             pci_device_size_64bit as u32,
-        ];
+        ]);
         let bus_range = [0, 0]; // Only bus 0
+
+        // Each segment has its own ECAM region.
         let reg = [
             pci_device_info_elem.mmio_config_address,
             PCI_MMIO_CONFIG_SIZE_PER_SEGMENT,
@@ -633,7 +922,8 @@ fn create_pci_nodes(
             // msi-controller: A single phandle to an MSI controller.
             MSI_PHANDLE,
             // msi-base: An msi-specifier describing the msi-specifier produced for the
-            // first RID matched by the entry.
+            // first RID matched by the entry. The device IDs of the ITS replace the
+            // bus number with the segment.
             (pci_device_info_elem.pci_segment_id as u32) << 8,
             // length: A single cell describing how many consecutive RIDs are matched
             // following the rid-base.
@@ -661,22 +951,21 @@ fn create_pci_nodes(
         fdt.property_array_u32("msi-map", &msi_map)?;
         fdt.property_u32("msi-parent", MSI_PHANDLE)?;
 
-        if pci_device_info_elem.pci_segment_id == 0 {
-            if let Some(virtio_iommu_bdf) = virtio_iommu_bdf {
-                // See kernel document Documentation/devicetree/bindings/pci/pci-iommu.txt
-                // for 'iommu-map' attribute setting.
-                let iommu_map = [
-                    0_u32,
-                    VIRTIO_IOMMU_PHANDLE,
-                    0_u32,
-                    virtio_iommu_bdf,
-                    virtio_iommu_bdf + 1,
-                    VIRTIO_IOMMU_PHANDLE,
-                    virtio_iommu_bdf + 1,
-                    0xffff - virtio_iommu_bdf,
-                ];
+        if let Some(virtio_iommu_bdf) = virtio_iommu_bdf {
+            // See kernel document Documentation/devicetree/bindings/pci/pci-iommu.txt
+            // for 'iommu-map' attribute setting. Only the devices placed behind
+            // the virtio-iommu are translated, each RID being mapped to the
+            // endpoint ID of the device, which is its BDF including the segment.
+            let iommu_map: Vec<u32> = pci_device_info_elem
+                .iommu_attached_devices
+                .iter()
+                .flat_map(|bdf| [bdf & 0xffff, VIRTIO_IOMMU_PHANDLE, *bdf, 1])
+                .collect();
+            if !iommu_map.is_empty() {
                 fdt.property_array_u32("iommu-map", &iommu_map)?;
+            }
 
+            if virtio_iommu_bdf >> 16 == pci_device_info_elem.pci_segment_id as u32 {
                 // See kernel document Documentation/devicetree/bindings/virtio/iommu.txt
                 // for virtio-iommu node settings.
                 let virtio_iommu_node_name = format!("virtio_iommu@{:x}", virtio_iommu_bdf);
@@ -688,7 +977,7 @@ fn create_pci_nodes(
                 // (phys.hi phys.mid phys.lo size.hi size.lo). phys.hi should contain the
                 // device's BDF as 0b00000000 bbbbbbbb dddddfff 00000000. The other cells
                 // should be zero.
-                let reg = [virtio_iommu_bdf << 8, 0_u32, 0_u32, 0_u32, 0_u32];
+                let reg = [(virtio_iommu_bdf & 0xffff) << 8, 0_u32, 0_u32, 0_u32, 0_u32];
                 fdt.property_array_u32("reg", &reg)?;
                 fdt.property_u32("phandle", VIRTIO_IOMMU_PHANDLE)?;
 
@@ -822,3 +1111,453 @@ fn print_node(node: fdt_parser::node::FdtNode<'_, '_>, n_spaces: usize) {
         print_node(child, n_spaces + 2);
     }
 }
+
+// In-memory device tree node, allowing overlays to be merged into a FDT
+// before writing it again.
+#[derive(Clone, Debug, Default)]
+struct DeviceTreeNode {
+    name: String,
+    properties: Vec<(String, Vec<u8>)>,
+    children: Vec<DeviceTreeNode>,
+}
+
+impl DeviceTreeNode {
+    fn parse(dtb: &[u8]) -> Option<Self> {
+        let fdt = fdt_parser::Fdt::new(dtb).ok()?;
+        let mut root = Self::from_fdt_node(fdt.find_node("/")?);
+        // The root node is written with an empty name.
+        root.name.clear();
+        Some(root)
+    }
+
+    fn from_fdt_node(node: fdt_parser::node::FdtNode<'_, '_>) -> Self {
+        DeviceTreeNode {
+            name: node.name.to_owned(),
+            properties: node
+                .properties()
+                .map(|p| (p.name.to_owned(), p.value.to_vec()))
+                .collect(),
+            children: node.children().map(Self::from_fdt_node).collect(),
+        }
+    }
+
+    fn property_str(&self, name: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, v)| CStr::from_bytes_with_nul(v).ok())
+            .and_then(|v| v.to_str().ok())
+    }
+
+    fn property_u32(&self, name: &str) -> Option<u32> {
+        self.properties
+            .iter()
+            .find(|(n, _)| n == name)
+            .filter(|(_, v)| v.len() == 4)
+            .map(|(_, v)| BigEndian::read_u32(v))
+    }
+
+    fn is_memory(&self) -> bool {
+        self.property_str("device_type") == Some("memory")
+    }
+
+    fn is_virtio_mmio(&self) -> bool {
+        self.property_str("compatible") == Some("virtio,mmio")
+    }
+
+    fn set_property(&mut self, name: &str, value: Vec<u8>) {
+        match self.properties.iter_mut().find(|(n, _)| n == name) {
+            Some(property) => property.1 = value,
+            None => self.properties.push((name.to_owned(), value)),
+        }
+    }
+
+    fn find_mut(&mut self, path: &str) -> Option<&mut DeviceTreeNode> {
+        let mut node = self;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            node = node.children.iter_mut().find(|c| c.name == name)?;
+        }
+        Some(node)
+    }
+
+    // Properties of the overlay replace the existing ones, while nodes
+    // are merged recursively.
+    fn merge(&mut self, overlay: &DeviceTreeNode) {
+        for (name, value) in overlay.properties.iter() {
+            self.set_property(name, value.clone());
+        }
+
+        for child in overlay.children.iter() {
+            match self.children.iter_mut().find(|c| c.name == child.name) {
+                Some(node) => node.merge(child),
+                None => self.children.push(child.clone()),
+            }
+        }
+    }
+
+    // Replaces the memory and virtio-mmio nodes of this root node with the
+    // ones of the patch, if it has any. The `reg` properties of the patch,
+    // made of two address and size cells, are encoded again with the cells
+    // of this root node. The interrupts of the patch refer to the GIC of the
+    // generated device tree, hence are left to the `interrupt-parent` of
+    // this root node.
+    fn patch(&mut self, patch: &DeviceTreeNode) -> Result<()> {
+        let address_cells = self.property_u32("#address-cells").unwrap_or(2);
+        let size_cells = self.property_u32("#size-cells").unwrap_or(1);
+
+        for kind in [Self::is_memory, Self::is_virtio_mmio] {
+            if patch.children.iter().any(kind) {
+                self.children.retain(|c| !kind(c));
+            }
+        }
+
+        for node in patch.children.iter() {
+            let mut node = node.clone();
+            node.properties
+                .retain(|(name, _)| name != "interrupt-parent");
+            if let Some((_, reg)) = node.properties.iter_mut().find(|(n, _)| n == "reg") {
+                let mut cells = Vec::new();
+                for region in reg.chunks(16) {
+                    if region.len() != 16 {
+                        return Err(Error::InvalidFdtPatch(node.name.clone()));
+                    }
+                    push_cells(&mut cells, BigEndian::read_u64(&region[..8]), address_cells)
+                        .ok_or_else(|| Error::InvalidFdtPatch(node.name.clone()))?;
+                    push_cells(&mut cells, BigEndian::read_u64(&region[8..]), size_cells)
+                        .ok_or_else(|| Error::InvalidFdtPatch(node.name.clone()))?;
+                }
+                *reg = cells;
+            }
+            self.children.push(node);
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, fdt: &mut FdtWriter) -> FdtWriterResult<()> {
+        let node = fdt.begin_node(&self.name)?;
+        for (name, value) in self.properties.iter() {
+            fdt.property(name, value)?;
+        }
+        for child in self.children.iter() {
+            child.write(fdt)?;
+        }
+        fdt.end_node(node)
+    }
+
+    fn to_dtb(&self) -> Result<Vec<u8>> {
+        let mut fdt = FdtWriter::new().map_err(Error::WriteFdt)?;
+        self.write(&mut fdt).map_err(Error::WriteFdt)?;
+        fdt.finish().map_err(Error::WriteFdt)
+    }
+
+    // Applies a single edit to the tree this node is the root of.
+    fn edit(&mut self, edit: &FdtEdit) -> Result<()> {
+        match edit {
+            FdtEdit::SetReg { path, regions } => {
+                let (parent_path, _) = split_node_path(path)?;
+                let parent = self
+                    .find_mut(parent_path)
+                    .ok_or_else(|| Error::FdtEditTarget(path.clone()))?;
+                // Default values from the devicetree specification.
+                let address_cells = parent.property_u32("#address-cells").unwrap_or(2);
+                let size_cells = parent.property_u32("#size-cells").unwrap_or(1);
+
+                let mut reg = Vec::new();
+                for region in regions.iter() {
+                    push_cells(&mut reg, region.address, address_cells)
+                        .ok_or_else(|| Error::InvalidFdtEdit(path.clone()))?;
+                    push_cells(&mut reg, region.size, size_cells)
+                        .ok_or_else(|| Error::InvalidFdtEdit(path.clone()))?;
+                }
+
+                self.find_mut(path)
+                    .ok_or_else(|| Error::FdtEditTarget(path.clone()))?
+                    .set_property("reg", reg);
+            }
+            FdtEdit::DeleteNode { path } => {
+                let (parent_path, name) = split_node_path(path)?;
+                let parent = self
+                    .find_mut(parent_path)
+                    .ok_or_else(|| Error::FdtEditTarget(path.clone()))?;
+                let index = parent
+                    .children
+                    .iter()
+                    .position(|c| c.name == name)
+                    .ok_or_else(|| Error::FdtEditTarget(path.clone()))?;
+                parent.children.remove(index);
+            }
+            FdtEdit::AddNode { path } => {
+                let (parent_path, name) = split_node_path(path)?;
+                let parent = self
+                    .find_mut(parent_path)
+                    .ok_or_else(|| Error::FdtEditTarget(path.clone()))?;
+                if !parent.children.iter().any(|c| c.name == name) {
+                    parent.children.push(DeviceTreeNode {
+                        name: name.to_owned(),
+                        ..Default::default()
+                    });
+                }
+            }
+            FdtEdit::SetProperty { path, name, value } => {
+                self.find_mut(path)
+                    .ok_or_else(|| Error::FdtEditTarget(path.clone()))?
+                    .set_property(name, value.to_bytes());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Splits the path of a node, other than the root node, into the path of its
+// parent and its name.
+fn split_node_path(path: &str) -> Result<(&str, &str)> {
+    match path.trim_end_matches('/').rsplit_once('/') {
+        Some((parent, name)) if !name.is_empty() => Ok((parent, name)),
+        _ => Err(Error::InvalidFdtEdit(path.to_owned())),
+    }
+}
+
+// Appends a value encoded on the given number of 32 bits cells.
+fn push_cells(data: &mut Vec<u8>, value: u64, cells: u32) -> Option<()> {
+    match cells {
+        0 if value == 0 => {}
+        1 => data.extend_from_slice(&u32::try_from(value).ok()?.to_be_bytes()),
+        2 => data.extend_from_slice(&value.to_be_bytes()),
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Value of a property set through a [`FdtEdit`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum FdtPropertyValue {
+    Empty,
+    String(String),
+    U32(Vec<u32>),
+    U64(Vec<u64>),
+    Bytes(Vec<u8>),
+}
+
+impl FdtPropertyValue {
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            FdtPropertyValue::Empty => Vec::new(),
+            FdtPropertyValue::String(s) => {
+                let mut value = s.as_bytes().to_vec();
+                value.push(0);
+                value
+            }
+            FdtPropertyValue::U32(cells) => cells.iter().flat_map(|c| c.to_be_bytes()).collect(),
+            FdtPropertyValue::U64(cells) => cells.iter().flat_map(|c| c.to_be_bytes()).collect(),
+            FdtPropertyValue::Bytes(bytes) => bytes.clone(),
+        }
+    }
+}
+
+/// Memory region of a `reg` property set through a [`FdtEdit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct FdtRegion {
+    pub address: u64,
+    pub size: u64,
+}
+
+/// Structured edit of a device tree, with nodes designated by their full
+/// path such as `/soc/serial@9000000`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum FdtEdit {
+    /// Replaces the `reg` property of the node, encoding the regions with
+    /// the `#address-cells` and `#size-cells` of its parent.
+    SetReg {
+        path: String,
+        regions: Vec<FdtRegion>,
+    },
+    /// Deletes the node and all of its children.
+    DeleteNode { path: String },
+    /// Adds an empty node, unless it exists already.
+    AddNode { path: String },
+    /// Adds or replaces a property of the node.
+    SetProperty {
+        path: String,
+        name: String,
+        value: FdtPropertyValue,
+    },
+}
+
+/// Pre-built DTB given to the guest instead of the generated one.
+pub struct FdtBase<'a> {
+    pub dtb: &'a [u8],
+    /// Replace the memory nodes of the DTB with the ones describing the
+    /// guest RAM.
+    pub patch_memory: bool,
+    /// Replace the virtio-mmio nodes of the DTB with the ones describing
+    /// the virtio devices.
+    pub patch_virtio: bool,
+    pub edits: &'a [FdtEdit],
+}
+
+/// Creates the nodes replacing their counterparts in a pre-built DTB: the
+/// memory nodes if `memory` is given, and the virtio-mmio nodes if
+/// `device_info` is given. They are written in the root node of a device
+/// tree, with two address and size cells.
+pub fn create_fdt_patch<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::BuildHasher>(
+    memory: Option<(&GuestMemoryMmap, &NumaNodes)>,
+    device_info: Option<&HashMap<(DeviceType, String), T, S>>,
+) -> FdtWriterResult<Vec<u8>> {
+    let mut fdt = FdtWriter::new()?;
+
+    let root_node = fdt.begin_node("")?;
+    fdt.property_u32("#address-cells", ADDRESS_CELLS)?;
+    fdt.property_u32("#size-cells", SIZE_CELLS)?;
+    if let Some((guest_mem, numa_nodes)) = memory {
+        create_memory_node(&mut fdt, guest_mem, numa_nodes)?;
+    }
+    if let Some(device_info) = device_info {
+        create_virtio_nodes(&mut fdt, device_info)?;
+    }
+    fdt.end_node(root_node)?;
+
+    fdt.finish()
+}
+
+fn write_virtio_overlay<T: DeviceInfoForFdt + Clone + Debug>(
+    dev_info: &T,
+    shm_info: Option<&T>,
+) -> FdtWriterResult<Vec<u8>> {
+    let mut fdt = FdtWriter::new()?;
+
+    let root_node = fdt.begin_node("")?;
+    let fragment_node = fdt.begin_node("fragment@0")?;
+    fdt.property_string("target-path", "/")?;
+    let overlay_node = fdt.begin_node("__overlay__")?;
+    create_virtio_node(&mut fdt, dev_info, shm_info)?;
+    fdt.end_node(overlay_node)?;
+    fdt.end_node(fragment_node)?;
+    fdt.end_node(root_node)?;
+
+    fdt.finish()
+}
+
+/// Creates the overlay adding the node of a hot-added virtio-mmio device,
+/// along with its shared memory window if any, to the root node.
+pub fn create_virtio_overlay<T: DeviceInfoForFdt + Clone + Debug>(
+    dev_info: &T,
+    shm_info: Option<&T>,
+) -> Result<Vec<u8>> {
+    write_virtio_overlay(dev_info, shm_info).map_err(Error::WriteFdt)
+}
+
+/// Creates the flattened device tree for this aarch64 VM from a
+/// pre-built DTB.
+///
+/// The `/chosen` node is updated with the kernel command line and the
+/// initramfs location, the memory of the ACPI tables is reserved when they
+/// are given to the kernel without UEFI firmware, the nodes of the patch
+/// created by [`create_fdt_patch`] replace their counterparts, and the edits
+/// are then applied in order.
+pub fn create_fdt_from_base(
+    base: &[u8],
+    cmdline: &str,
+    initrd: &Option<InitramfsConfig>,
+    patch: Option<&[u8]>,
+    edits: &[FdtEdit],
+    acpi_direct_boot: bool,
+) -> Result<Vec<u8>> {
+    let mut root = DeviceTreeNode::parse(base).ok_or(Error::ParseFdt)?;
+
+    root.edit(&FdtEdit::AddNode {
+        path: "/chosen".to_owned(),
+    })?;
+    let chosen = root.find_mut("/chosen").unwrap();
+    chosen.set_property(
+        "bootargs",
+        FdtPropertyValue::String(cmdline.to_owned()).to_bytes(),
+    );
+    if let Some(initrd_config) = initrd {
+        let initrd_start = initrd_config.address.raw_value() as u64;
+        let initrd_end = initrd_config.address.raw_value() + initrd_config.size as u64;
+        chosen.set_property("linux,initrd-start", initrd_start.to_be_bytes().to_vec());
+        chosen.set_property("linux,initrd-end", initrd_end.to_be_bytes().to_vec());
+    }
+
+    if acpi_direct_boot {
+        if root.find_mut("/reserved-memory").is_none() {
+            root.edit(&FdtEdit::AddNode {
+                path: "/reserved-memory".to_owned(),
+            })?;
+            let reserved_memory = root.find_mut("/reserved-memory").unwrap();
+            reserved_memory.set_property("#address-cells", ADDRESS_CELLS.to_be_bytes().to_vec());
+            reserved_memory.set_property("#size-cells", SIZE_CELLS.to_be_bytes().to_vec());
+            reserved_memory.set_property("ranges", Vec::new());
+        }
+        let address = super::layout::ACPI_START.raw_value();
+        let path = format!("/reserved-memory/acpi@{:x}", address);
+        root.edit(&FdtEdit::AddNode { path: path.clone() })?;
+        root.edit(&FdtEdit::SetReg {
+            path,
+            regions: vec![FdtRegion {
+                address,
+                size: super::layout::ACPI_MAX_SIZE,
+            }],
+        })?;
+    }
+
+    if let Some(patch) = patch {
+        root.patch(&DeviceTreeNode::parse(patch).ok_or(Error::ParseFdt)?)?;
+    }
+
+    for edit in edits.iter() {
+        root.edit(edit)?;
+    }
+
+    check_fdt_size(root.to_dtb()?)
+}
+
+// The FDT can only be as large as the guest memory reserved for it, which the
+// user provided content could make it exceed.
+fn check_fdt_size(dtb: Vec<u8>) -> Result<Vec<u8>> {
+    if dtb.len() as u64 > FDT_MAX_SIZE {
+        return Err(Error::FdtTooLarge(dtb.len()));
+    }
+
+    Ok(dtb)
+}
+
+/// Applies device tree overlays (`.dtbo`) to a flattened device tree.
+///
+/// Each fragment of an overlay must designate the node it applies to
+/// through its `target-path` property, as the generated device tree doesn't
+/// carry the symbols needed to resolve a `target` phandle.
+pub fn apply_overlays(dtb: &[u8], overlays: &[Vec<u8>]) -> Result<Vec<u8>> {
+    if overlays.is_empty() {
+        return Ok(dtb.to_vec());
+    }
+
+    let mut root = DeviceTreeNode::parse(dtb).ok_or(Error::ParseFdt)?;
+
+    for overlay in overlays.iter() {
+        let overlay = DeviceTreeNode::parse(overlay).ok_or(Error::ParseFdtOverlay)?;
+        // Skip the __fixups__, __local_fixups__ and __symbols__ nodes.
+        for fragment in overlay
+            .children
+            .iter()
+            .filter(|c| !c.name.starts_with("__"))
+        {
+            let target_path = fragment
+                .property_str("target-path")
+                .ok_or_else(|| Error::FdtOverlayTarget(fragment.name.clone()))?;
+            let content = fragment
+                .children
+                .iter()
+                .find(|c| c.name == "__overlay__")
+                .ok_or(Error::ParseFdtOverlay)?;
+
+            root.find_mut(target_path)
+                .ok_or_else(|| Error::FdtOverlayTarget(target_path.to_owned()))?
+                .merge(content);
+        }
+    }
+
+    check_fdt_size(root.to_dtb()?)
+}
@@ -76,11 +76,27 @@ pub const GIC_V3_ITS_SIZE: u64 = 0x02_0000;
 pub const LEGACY_SERIAL_MAPPED_IO_START: GuestAddress = GuestAddress(0x0900_0000);
 pub const LEGACY_RTC_MAPPED_IO_START: GuestAddress = GuestAddress(0x0901_0000);
 pub const LEGACY_GPIO_MAPPED_IO_START: GuestAddress = GuestAddress(0x0902_0000);
+pub const LEGACY_PVPANIC_MAPPED_IO_START: GuestAddress = GuestAddress(0x0903_0000);
 
 /// Space 0x0905_0000 ~ 0x0906_0000 is reserved for pcie io address
 pub const MEM_PCI_IO_START: GuestAddress = GuestAddress(0x0905_0000);
 pub const MEM_PCI_IO_SIZE: u64 = 0x10000;
 
+/// Space 0x0906_0000 ~ 0x0906_1000 is reserved for the partition services mailbox.
+pub const LEGACY_PARTITION_SERVICES_MAPPED_IO_START: GuestAddress = GuestAddress(0x0906_0000);
+
+/// Space 0x0907_0000 ~ 0x0907_1000 is reserved for the device tree hotplug notifier.
+pub const LEGACY_DT_HOTPLUG_MAPPED_IO_START: GuestAddress = GuestAddress(0x0907_0000);
+
+/// Size of the space of the devices above, from MAPPED_IO_START, the devices
+/// reserving their range out of it.
+pub const LEGACY_MAPPED_IO_SIZE: u64 = 0x8_0000;
+
+/// PCI I/O ports assigned to the devices, accessed through the window above.
+/// The first 4 KiB are left out as the guest doesn't assign them to devices.
+pub const PCI_IO_PORT_START: GuestAddress = GuestAddress(0x1000);
+pub const PCI_IO_PORT_SIZE: u64 = MEM_PCI_IO_SIZE - PCI_IO_PORT_START.0;
+
 /// Starting from 0x1000_0000 (256MiB) to 0x3000_0000 (768MiB) is used for PCIE MMIO
 pub const MEM_32BIT_DEVICES_START: GuestAddress = GuestAddress(0x1000_0000);
 pub const MEM_32BIT_DEVICES_SIZE: u64 = 0x2000_0000;
@@ -2,6 +2,8 @@
 // Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+/// Module for the CPU models used as baseline.
+pub mod cpu_model;
 /// Module for the flattened device tree.
 pub mod fdt;
 /// Layout for this aarch64 system.
@@ -28,6 +30,12 @@ pub enum Error {
     /// Failed to write FDT to memory.
     WriteFdtToMemory(fdt::Error),
 
+    /// Failed to apply the FDT overlays.
+    ApplyFdtOverlays(fdt::Error),
+
+    /// Failed to create the FDT from the pre-built DTB.
+    SetupFdtFromBase(fdt::Error),
+
     /// Failed to create a GIC.
     SetupGic,
 
@@ -42,6 +50,9 @@ pub enum Error {
 
     /// Error initializing PMU for vcpu
     VcpuInitPmu,
+
+    /// Error applying the CPU model used as baseline
+    CpuModel(cpu_model::Error),
 }
 
 impl From<Error> for super::Error {
@@ -63,7 +74,12 @@ pub fn configure_vcpu(
     vcpu: &Arc<dyn hypervisor::Vcpu>,
     id: u8,
     kernel_entry_point: Option<EntryPoint>,
+    cpu_model: Option<cpu_model::CpuModel>,
 ) -> super::Result<u64> {
+    if let Some(cpu_model) = cpu_model {
+        cpu_model.apply(vcpu).map_err(Error::CpuModel)?;
+    }
+
     if let Some(kernel_entry_point) = kernel_entry_point {
         vcpu.setup_regs(
             id,
@@ -138,29 +154,56 @@ pub fn configure_system<T: DeviceInfoForFdt + Clone + Debug, S: ::std::hash::Bui
     guest_mem: &GuestMemoryMmap,
     cmdline: &str,
     vcpu_mpidr: Vec<u64>,
-    vcpu_topology: Option<(u8, u8, u8)>,
+    vcpu_topology: Option<(u8, u8, u8, u8)>,
     device_info: &HashMap<(DeviceType, String), T, S>,
     initrd: &Option<super::InitramfsConfig>,
+    payload_blobs: &[super::PayloadBlob],
     pci_space_info: &[PciSpaceInfo],
     virtio_iommu_bdf: Option<u32>,
     gic_device: &Arc<Mutex<dyn Vgic>>,
     numa_nodes: &NumaNodes,
     pmu_supported: bool,
+    hypervisor_info: Option<&super::HypervisorInfo>,
+    acpi_direct_boot: bool,
+    fdt_overlays: &[Vec<u8>],
+    fdt_base: Option<fdt::FdtBase>,
 ) -> super::Result<()> {
-    let fdt_final = fdt::create_fdt(
-        guest_mem,
-        cmdline,
-        vcpu_mpidr,
-        vcpu_topology,
-        device_info,
-        gic_device,
-        initrd,
-        pci_space_info,
-        numa_nodes,
-        virtio_iommu_bdf,
-        pmu_supported,
-    )
-    .map_err(|_| Error::SetupFdt)?;
+    let fdt_final = if let Some(base) = fdt_base {
+        let patch = fdt::create_fdt_patch(
+            base.patch_memory.then(|| (guest_mem, numa_nodes)),
+            base.patch_virtio.then(|| device_info),
+        )
+        .map_err(|_| Error::SetupFdt)?;
+        fdt::create_fdt_from_base(
+            base.dtb,
+            cmdline,
+            initrd,
+            Some(&patch),
+            base.edits,
+            acpi_direct_boot,
+        )
+        .map_err(Error::SetupFdtFromBase)?
+    } else {
+        fdt::create_fdt(
+            guest_mem,
+            cmdline,
+            vcpu_mpidr,
+            vcpu_topology,
+            device_info,
+            gic_device,
+            initrd,
+            payload_blobs,
+            pci_space_info,
+            numa_nodes,
+            virtio_iommu_bdf,
+            pmu_supported,
+            hypervisor_info,
+            acpi_direct_boot,
+        )
+        .map_err(|_| Error::SetupFdt)?
+    };
+    let fdt_final =
+        fdt::apply_overlays(&fdt_final, fdt_overlays).map_err(Error::ApplyFdtOverlays)?;
 
     if log_enabled!(Level::Debug) {
         fdt::print_fdt(&fdt_final);
@@ -233,4 +276,338 @@ mod tests {
         assert_eq!(RegionType::Ram, regions[4].2);
         assert_eq!(((1usize << 32) - ram_32bit_space_size), regions[4].1);
     }
+
+    fn create_cpus_fdt(topology: (u8, u8, u8, u8), num_cpus: u64) -> Vec<u8> {
+        let mut fdt = vm_fdt::FdtWriter::new().unwrap();
+        let root = fdt.begin_node("").unwrap();
+        let vcpu_mpidr: Vec<u64> = (0..num_cpus).collect();
+        fdt::create_cpu_nodes(&mut fdt, &vcpu_mpidr, Some(topology), &NumaNodes::new()).unwrap();
+        fdt.end_node(root).unwrap();
+        fdt.finish().unwrap()
+    }
+
+    #[test]
+    fn test_create_cpu_map() {
+        let cpu = |dtb: &[u8], path: &str| {
+            fdt_parser::Fdt::new(dtb)
+                .unwrap()
+                .find_node(path)
+                .map(|node| node.property("cpu").unwrap().as_usize().unwrap())
+        };
+
+        // With a single socket, the clusters are the top-level nodes.
+        let dtb = create_cpus_fdt((1, 2, 2, 1), 4);
+        assert_eq!(cpu(&dtb, "/cpus/cpu-map/cluster0/core0"), Some(6));
+        assert_eq!(cpu(&dtb, "/cpus/cpu-map/cluster1/core1"), Some(9));
+        assert!(fdt_parser::Fdt::new(&dtb)
+            .unwrap()
+            .find_node("/cpus/cpu-map/socket0")
+            .is_none());
+
+        // The clusters are grouped in sockets when there are several.
+        let dtb = create_cpus_fdt((2, 1, 2, 2), 8);
+        assert_eq!(
+            cpu(&dtb, "/cpus/cpu-map/socket0/cluster1/core0/thread0"),
+            Some(8)
+        );
+        assert_eq!(
+            cpu(&dtb, "/cpus/cpu-map/socket1/cluster1/core0/thread1"),
+            Some(13)
+        );
+
+        // Only the present vCPUs are mapped.
+        let dtb = create_cpus_fdt((1, 2, 1, 2), 3);
+        assert_eq!(cpu(&dtb, "/cpus/cpu-map/socket1/cluster0/core0"), Some(8));
+        assert_eq!(cpu(&dtb, "/cpus/cpu-map/socket1/cluster0/core1"), None);
+    }
+
+    #[test]
+    fn test_apply_fdt_overlays() {
+        let mut base = vm_fdt::FdtWriter::new().unwrap();
+        let root = base.begin_node("").unwrap();
+        base.property_string("compatible", "linux,dummy-virt")
+            .unwrap();
+        let chosen = base.begin_node("chosen").unwrap();
+        base.property_string("bootargs", "console=hvc0").unwrap();
+        base.end_node(chosen).unwrap();
+        base.end_node(root).unwrap();
+        let base = base.finish().unwrap();
+
+        let mut overlay = vm_fdt::FdtWriter::new().unwrap();
+        let root = overlay.begin_node("").unwrap();
+        let fragment = overlay.begin_node("fragment@0").unwrap();
+        overlay.property_string("target-path", "/chosen").unwrap();
+        let content = overlay.begin_node("__overlay__").unwrap();
+        overlay
+            .property_string("bootargs", "console=ttyAMA0")
+            .unwrap();
+        overlay.end_node(content).unwrap();
+        overlay.end_node(fragment).unwrap();
+        let fragment = overlay.begin_node("fragment@1").unwrap();
+        overlay.property_string("target-path", "/").unwrap();
+        let content = overlay.begin_node("__overlay__").unwrap();
+        let node = overlay.begin_node("vendor@0").unwrap();
+        overlay.property_string("compatible", "vendor,dev").unwrap();
+        overlay.end_node(node).unwrap();
+        overlay.end_node(content).unwrap();
+        overlay.end_node(fragment).unwrap();
+        overlay.end_node(root).unwrap();
+        let overlay = overlay.finish().unwrap();
+
+        let dtb = fdt::apply_overlays(&base, &[overlay]).unwrap();
+        let parsed = fdt_parser::Fdt::new(&dtb).unwrap();
+        let chosen = parsed.find_node("/chosen").unwrap();
+        assert_eq!(
+            chosen.property("bootargs").unwrap().as_str(),
+            Some("console=ttyAMA0")
+        );
+        let vendor = parsed.find_node("/vendor@0").unwrap();
+        assert_eq!(
+            vendor.property("compatible").unwrap().as_str(),
+            Some("vendor,dev")
+        );
+
+        // Fragments must give a target path
+        let mut overlay = vm_fdt::FdtWriter::new().unwrap();
+        let root = overlay.begin_node("").unwrap();
+        let fragment = overlay.begin_node("fragment@0").unwrap();
+        overlay.property_u32("target", 1).unwrap();
+        overlay.end_node(fragment).unwrap();
+        overlay.end_node(root).unwrap();
+        let overlay = overlay.finish().unwrap();
+        assert!(fdt::apply_overlays(&base, &[overlay]).is_err());
+    }
+
+    #[test]
+    fn test_create_virtio_overlay() {
+        let mut base = vm_fdt::FdtWriter::new().unwrap();
+        let root = base.begin_node("").unwrap();
+        base.property_u32("#address-cells", 2).unwrap();
+        base.property_u32("#size-cells", 2).unwrap();
+        base.end_node(root).unwrap();
+        let base = base.finish().unwrap();
+
+        let dev_info = crate::MmioDeviceInfo {
+            addr: 0x1000_0000,
+            len: 0x1000,
+            irq: 40,
+        };
+        let overlay = fdt::create_virtio_overlay(&dev_info, None).unwrap();
+
+        let dtb = fdt::apply_overlays(&base, &[overlay]).unwrap();
+        let parsed = fdt_parser::Fdt::new(&dtb).unwrap();
+        let virtio = parsed.find_node("/virtio_mmio@10000000").unwrap();
+        assert_eq!(
+            virtio.property("compatible").unwrap().as_str(),
+            Some("virtio,mmio")
+        );
+    }
+
+    #[test]
+    fn test_create_fdt_from_base() {
+        let mut base = vm_fdt::FdtWriter::new().unwrap();
+        let root = base.begin_node("").unwrap();
+        base.property_u32("#address-cells", 2).unwrap();
+        base.property_u32("#size-cells", 2).unwrap();
+        let soc = base.begin_node("soc").unwrap();
+        base.property_u32("#address-cells", 1).unwrap();
+        base.property_u32("#size-cells", 1).unwrap();
+        let uart = base.begin_node("serial@9000000").unwrap();
+        base.property_string("compatible", "arm,pl011").unwrap();
+        base.end_node(uart).unwrap();
+        let gpu = base.begin_node("gpu@a000000").unwrap();
+        base.end_node(gpu).unwrap();
+        base.end_node(soc).unwrap();
+        base.end_node(root).unwrap();
+        let base = base.finish().unwrap();
+
+        let edits = vec![
+            fdt::FdtEdit::SetReg {
+                path: "/soc/serial@9000000".to_owned(),
+                regions: vec![fdt::FdtRegion {
+                    address: 0x900_0000,
+                    size: 0x1000,
+                }],
+            },
+            fdt::FdtEdit::DeleteNode {
+                path: "/soc/gpu@a000000".to_owned(),
+            },
+            fdt::FdtEdit::AddNode {
+                path: "/soc/vendor@b000000".to_owned(),
+            },
+            fdt::FdtEdit::SetProperty {
+                path: "/soc/vendor@b000000".to_owned(),
+                name: "compatible".to_owned(),
+                value: fdt::FdtPropertyValue::String("vendor,dev".to_owned()),
+            },
+        ];
+        let dtb = fdt::create_fdt_from_base(&base, "console=ttyAMA0", &None, None, &edits, false)
+            .unwrap();
+        let parsed = fdt_parser::Fdt::new(&dtb).unwrap();
+        assert_eq!(
+            parsed
+                .find_node("/chosen")
+                .unwrap()
+                .property("bootargs")
+                .unwrap()
+                .as_str(),
+            Some("console=ttyAMA0")
+        );
+        assert_eq!(
+            parsed
+                .find_node("/soc/serial@9000000")
+                .unwrap()
+                .property("reg")
+                .unwrap()
+                .value,
+            [0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00]
+        );
+        assert!(parsed.find_node("/soc/gpu@a000000").is_none());
+        assert_eq!(
+            parsed
+                .find_node("/soc/vendor@b000000")
+                .unwrap()
+                .property("compatible")
+                .unwrap()
+                .as_str(),
+            Some("vendor,dev")
+        );
+
+        // The address doesn't fit the single address cell of the parent
+        let edits = vec![fdt::FdtEdit::SetReg {
+            path: "/soc/serial@9000000".to_owned(),
+            regions: vec![fdt::FdtRegion {
+                address: 1 << 32,
+                size: 0x1000,
+            }],
+        }];
+        assert!(fdt::create_fdt_from_base(&base, "", &None, None, &edits, false).is_err());
+
+        let edits = vec![fdt::FdtEdit::DeleteNode {
+            path: "/soc/unknown".to_owned(),
+        }];
+        assert!(fdt::create_fdt_from_base(&base, "", &None, None, &edits, false).is_err());
+
+        let edits = vec![fdt::FdtEdit::SetProperty {
+            path: "/soc".to_owned(),
+            name: "blob".to_owned(),
+            value: fdt::FdtPropertyValue::Bytes(vec![0; layout::FDT_MAX_SIZE as usize]),
+        }];
+        assert!(matches!(
+            fdt::create_fdt_from_base(&base, "", &None, None, &edits, false),
+            Err(fdt::Error::FdtTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_fdt_from_base_patch() {
+        let mut base = vm_fdt::FdtWriter::new().unwrap();
+        let root = base.begin_node("").unwrap();
+        base.property_u32("#address-cells", 1).unwrap();
+        base.property_u32("#size-cells", 1).unwrap();
+        let memory = base.begin_node("memory@80000000").unwrap();
+        base.property_string("device_type", "memory").unwrap();
+        base.property_array_u32("reg", &[0x8000_0000, 0x1000_0000])
+            .unwrap();
+        base.end_node(memory).unwrap();
+        let virtio = base.begin_node("virtio_mmio@a000000").unwrap();
+        base.property_string("compatible", "virtio,mmio").unwrap();
+        base.end_node(virtio).unwrap();
+        base.end_node(root).unwrap();
+        let base = base.finish().unwrap();
+
+        let patch = |memory_start: u64| {
+            let mut patch = vm_fdt::FdtWriter::new().unwrap();
+            let root = patch.begin_node("").unwrap();
+            let memory = patch.begin_node("memory").unwrap();
+            patch.property_string("device_type", "memory").unwrap();
+            patch
+                .property_array_u64("reg", &[memory_start, 0x2000_0000])
+                .unwrap();
+            patch.end_node(memory).unwrap();
+            patch.end_node(root).unwrap();
+            patch.finish().unwrap()
+        };
+
+        // The memory node is replaced, while the virtio-mmio node is kept as
+        // the patch doesn't describe any.
+        let dtb =
+            fdt::create_fdt_from_base(&base, "", &None, Some(&patch(0x4000_0000)), &[], false)
+                .unwrap();
+        let parsed = fdt_parser::Fdt::new(&dtb).unwrap();
+        assert!(parsed.find_node("/memory@80000000").is_none());
+        assert_eq!(
+            parsed
+                .find_node("/memory")
+                .unwrap()
+                .property("reg")
+                .unwrap()
+                .value,
+            [0x40, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00]
+        );
+        assert!(parsed.find_node("/virtio_mmio@a000000").is_some());
+
+        // The memory doesn't fit the single address cell of the base
+        assert!(
+            fdt::create_fdt_from_base(&base, "", &None, Some(&patch(1 << 32)), &[], false).is_err()
+        );
+    }
+
+    #[test]
+    fn test_create_fdt_from_base_acpi_direct_boot() {
+        let acpi_reg = |dtb: &[u8]| {
+            let parsed = fdt_parser::Fdt::new(dtb).unwrap();
+            parsed
+                .find_node(&format!("/reserved-memory/acpi@{:x}", layout::ACPI_START.0))
+                .map(|node| node.property("reg").unwrap().value.to_vec())
+        };
+
+        // The reserved memory node is created along with the one of the ACPI
+        // tables.
+        let mut base = vm_fdt::FdtWriter::new().unwrap();
+        let root = base.begin_node("").unwrap();
+        base.property_u32("#address-cells", 2).unwrap();
+        base.property_u32("#size-cells", 2).unwrap();
+        base.end_node(root).unwrap();
+        let base = base.finish().unwrap();
+
+        let dtb = fdt::create_fdt_from_base(&base, "", &None, None, &[], false).unwrap();
+        assert!(acpi_reg(&dtb).is_none());
+        let dtb = fdt::create_fdt_from_base(&base, "", &None, None, &[], true).unwrap();
+        assert_eq!(
+            acpi_reg(&dtb).unwrap(),
+            [
+                layout::ACPI_START.0.to_be_bytes(),
+                layout::ACPI_MAX_SIZE.to_be_bytes()
+            ]
+            .concat()
+        );
+
+        // The existing reserved memory node is kept, the ACPI tables being
+        // described with its cells.
+        let mut base = vm_fdt::FdtWriter::new().unwrap();
+        let root = base.begin_node("").unwrap();
+        let reserved_memory = base.begin_node("reserved-memory").unwrap();
+        base.property_u32("#address-cells", 1).unwrap();
+        base.property_u32("#size-cells", 1).unwrap();
+        let region = base.begin_node("region@50000000").unwrap();
+        base.end_node(region).unwrap();
+        base.end_node(reserved_memory).unwrap();
+        base.end_node(root).unwrap();
+        let base = base.finish().unwrap();
+
+        let dtb = fdt::create_fdt_from_base(&base, "", &None, None, &[], true).unwrap();
+        assert_eq!(
+            acpi_reg(&dtb).unwrap(),
+            [
+                (layout::ACPI_START.0 as u32).to_be_bytes(),
+                (layout::ACPI_MAX_SIZE as u32).to_be_bytes()
+            ]
+            .concat()
+        );
+        assert!(fdt_parser::Fdt::new(&dtb)
+            .unwrap()
+            .find_node("/reserved-memory/region@50000000")
+            .is_some());
+    }
 }
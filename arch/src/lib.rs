@@ -80,9 +80,9 @@ pub mod aarch64;
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::{
-    arch_memory_regions, configure_system, configure_vcpu, fdt::DeviceInfoForFdt,
-    get_host_cpu_phys_bits, initramfs_load_addr, layout, layout::CMDLINE_MAX_SIZE,
-    layout::IRQ_BASE, uefi, EntryPoint,
+    arch_memory_regions, configure_system, configure_vcpu, cpu_model::CpuModel,
+    fdt::DeviceInfoForFdt, get_host_cpu_phys_bits, initramfs_load_addr, layout,
+    layout::CMDLINE_MAX_SIZE, layout::IRQ_BASE, uefi, EntryPoint,
 };
 
 #[cfg(target_arch = "x86_64")]
@@ -90,9 +90,9 @@ pub mod x86_64;
 
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::{
-    arch_memory_regions, configure_system, configure_vcpu, generate_common_cpuid,
-    get_host_cpu_phys_bits, initramfs_load_addr, layout, layout::CMDLINE_MAX_SIZE,
-    layout::CMDLINE_START, regs, CpuidFeatureEntry, EntryPoint,
+    arch_memory_regions, configure_system, configure_vcpu, cpu_model::CpuModel,
+    generate_common_cpuid, get_host_cpu_phys_bits, initramfs_load_addr, layout,
+    layout::CMDLINE_MAX_SIZE, layout::CMDLINE_START, regs, CpuidFeatureEntry, EntryPoint,
 };
 
 /// Safe wrapper for `sysconf(_SC_PAGESIZE)`.
@@ -112,6 +112,10 @@ pub struct NumaNode {
     pub memory_zones: Vec<String>,
     #[cfg(target_arch = "x86_64")]
     pub sgx_epc_sections: Vec<SgxEpcSection>,
+    /// Identifiers of the pmem devices placed on the node.
+    pub pmem_devices: Vec<String>,
+    /// Whether the memory hotplugged through ACPI belongs to the node.
+    pub memory_hotplug: bool,
 }
 
 pub type NumaNodes = BTreeMap<u32, NumaNode>;
@@ -124,6 +128,18 @@ pub struct InitramfsConfig {
     pub size: usize,
 }
 
+/// Type for passing information about a blob loaded in the guest memory
+/// along with the kernel, which the guest must not reuse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadBlob {
+    /// Identifier of the blob
+    pub id: String,
+    /// Load address of the blob in guest memory
+    pub address: vm_memory::GuestAddress,
+    /// Size of the blob in guest memory
+    pub size: usize,
+}
+
 /// Types of devices that can get attached to this platform.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Copy)]
 pub enum DeviceType {
@@ -138,11 +154,64 @@ pub enum DeviceType {
     /// Device Type: GPIO.
     #[cfg(target_arch = "aarch64")]
     Gpio,
+    /// Device Type: pvpanic.
+    #[cfg(target_arch = "aarch64")]
+    PvPanic,
+    /// Device Type: SBSA generic watchdog.
+    #[cfg(target_arch = "aarch64")]
+    Watchdog,
+    /// Device Type: Doorbell.
+    #[cfg(target_arch = "aarch64")]
+    Doorbell,
+    /// Device Type: Debug console.
+    #[cfg(target_arch = "aarch64")]
+    DebugConsole,
+    /// Device Type: Partition services mailbox.
+    #[cfg(target_arch = "aarch64")]
+    PartitionServices,
+    /// Device Type: Device tree hotplug notifier.
+    #[cfg(target_arch = "aarch64")]
+    DtHotplug,
+    /// Shared memory window of the virtio-mmio device with the same id.
+    #[cfg(target_arch = "aarch64")]
+    SharedMemory,
 }
 
 /// Default (smallest) memory page size for the supported architectures.
 pub const PAGE_SIZE: usize = 4096;
 
+/// Identification of the VMM exposed to the guest, along with the paravirtual
+/// features enabled for the VM, so that guest software can adapt to them
+/// without probing the devices. It is described through CPUID leaves on
+/// x86_64 and through a device tree node on aarch64.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HypervisorInfo {
+    /// Major, minor and patch version of the VMM.
+    pub version: [u32; 3],
+    /// Bitmap of the `HYPERVISOR_INFO_FEATURE_*` enabled for the VM.
+    pub features: u32,
+    /// Platform UUID of the VM, if any.
+    pub uuid: Option<uuid::Uuid>,
+}
+
+/// Hyper-V enlightenments are exposed to the guest.
+pub const HYPERVISOR_INFO_FEATURE_HYPERV: u32 = 1 << 0;
+/// vCPUs can be hotplugged.
+pub const HYPERVISOR_INFO_FEATURE_CPU_HOTPLUG: u32 = 1 << 1;
+/// Memory can be hotplugged.
+pub const HYPERVISOR_INFO_FEATURE_MEMORY_HOTPLUG: u32 = 1 << 2;
+/// A virtio-balloon device is present.
+pub const HYPERVISOR_INFO_FEATURE_BALLOON: u32 = 1 << 3;
+/// A pvpanic device is present.
+pub const HYPERVISOR_INFO_FEATURE_PVPANIC: u32 = 1 << 4;
+/// A watchdog device is present.
+pub const HYPERVISOR_INFO_FEATURE_WATCHDOG: u32 = 1 << 5;
+/// A virtio-iommu device is present.
+pub const HYPERVISOR_INFO_FEATURE_IOMMU: u32 = 1 << 6;
+/// The guest memory is encrypted (TDX or SEV-SNP), devices DMA through the
+/// swiotlb.
+pub const HYPERVISOR_INFO_FEATURE_CONFIDENTIAL: u32 = 1 << 7;
+
 impl fmt::Display for DeviceType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -166,6 +235,8 @@ pub struct PciSpaceInfo {
     pub mmio_config_address: u64,
     pub pci_device_space_start: u64,
     pub pci_device_space_size: u64,
+    /// BDFs of the devices of the segment placed behind the virtio-iommu.
+    pub iommu_attached_devices: Vec<u32>,
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -0,0 +1,600 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Named CPU models used as a baseline for the CPUID features exposed to
+//! the guest. Each model describes the features of a processor generation,
+//! so that a VM started with a given model can be migrated between hosts of
+//! that generation or any newer one.
+
+use super::CpuidReg;
+use hypervisor::x86_64::CpuId;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum CpuModel {
+    SkylakeServer,
+    CascadelakeServer,
+    IcelakeServer,
+    SapphireRapids,
+    EpycRome,
+    EpycMilan,
+    EpycGenoa,
+}
+
+// Feature bits of a CPUID register, along with their names.
+struct CpuidFeatures {
+    function: u32,
+    index: u32,
+    reg: CpuidReg,
+    bits: &'static [(u8, &'static str)],
+}
+
+// Registers whose features are restricted to the ones of the model. The
+// other registers are left untouched.
+const MASKED_REGISTERS: [(u32, u32, CpuidReg); 9] = [
+    (0x1, 0, CpuidReg::ECX),
+    (0x1, 0, CpuidReg::EDX),
+    (0x7, 0, CpuidReg::EBX),
+    (0x7, 0, CpuidReg::ECX),
+    (0x7, 0, CpuidReg::EDX),
+    (0x7, 1, CpuidReg::EAX),
+    (0xd, 1, CpuidReg::EAX),
+    (0x8000_0001, 0, CpuidReg::ECX),
+    (0x8000_0001, 0, CpuidReg::EDX),
+];
+
+// Features exposed when the host provides them, whatever the model. They
+// either reflect the state of the guest (OSXSAVE, OSPKE), are synthesized
+// by the VMM or the hypervisor, or advertise mitigations the guest
+// shouldn't be deprived of.
+const OPTIONAL_FEATURES: &[CpuidFeatures] = &[
+    CpuidFeatures {
+        function: 0x1,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[(27, "osxsave"), (31, "hypervisor")],
+    },
+    CpuidFeatures {
+        function: 0x1,
+        index: 0,
+        reg: CpuidReg::EDX,
+        bits: &[(27, "ss"), (28, "ht")],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EBX,
+        bits: &[
+            (1, "tsc_adjust"),
+            (6, "fdp_excptn_only"),
+            (13, "zero_fcs_fds"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[(4, "ospke")],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EDX,
+        bits: &[
+            (10, "md_clear"),
+            (26, "spec_ctrl"),
+            (27, "stibp"),
+            (28, "flush_l1d"),
+            (29, "arch_capabilities"),
+            (31, "ssbd"),
+        ],
+    },
+];
+
+// Features common to all the models.
+const BASE_FEATURES: &[CpuidFeatures] = &[
+    CpuidFeatures {
+        function: 0x1,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[
+            (0, "sse3"),
+            (1, "pclmulqdq"),
+            (9, "ssse3"),
+            (12, "fma"),
+            (13, "cx16"),
+            (19, "sse4_1"),
+            (20, "sse4_2"),
+            (21, "x2apic"),
+            (22, "movbe"),
+            (23, "popcnt"),
+            (24, "tsc_deadline_timer"),
+            (25, "aes"),
+            (26, "xsave"),
+            (28, "avx"),
+            (29, "f16c"),
+            (30, "rdrand"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x1,
+        index: 0,
+        reg: CpuidReg::EDX,
+        bits: &[
+            (0, "fpu"),
+            (1, "vme"),
+            (2, "de"),
+            (3, "pse"),
+            (4, "tsc"),
+            (5, "msr"),
+            (6, "pae"),
+            (7, "mce"),
+            (8, "cx8"),
+            (9, "apic"),
+            (11, "sep"),
+            (12, "mtrr"),
+            (13, "pge"),
+            (14, "mca"),
+            (15, "cmov"),
+            (16, "pat"),
+            (17, "pse36"),
+            (19, "clflush"),
+            (23, "mmx"),
+            (24, "fxsr"),
+            (25, "sse"),
+            (26, "sse2"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EBX,
+        bits: &[
+            (0, "fsgsbase"),
+            (3, "bmi1"),
+            (5, "avx2"),
+            (7, "smep"),
+            (8, "bmi2"),
+            (18, "rdseed"),
+            (19, "adx"),
+            (20, "smap"),
+            (23, "clflushopt"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0xd,
+        index: 1,
+        reg: CpuidReg::EAX,
+        bits: &[
+            (0, "xsaveopt"),
+            (1, "xsavec"),
+            (2, "xgetbv1"),
+            (3, "xsaves"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x8000_0001,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[(0, "lahf_lm"), (5, "abm"), (8, "3dnowprefetch")],
+    },
+    CpuidFeatures {
+        function: 0x8000_0001,
+        index: 0,
+        reg: CpuidReg::EDX,
+        bits: &[
+            (11, "syscall"),
+            (20, "nx"),
+            (26, "pdpe1gb"),
+            (27, "rdtscp"),
+            (29, "lm"),
+        ],
+    },
+];
+
+const SKYLAKE_SERVER_FEATURES: &[CpuidFeatures] = &[
+    CpuidFeatures {
+        function: 0x1,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[(17, "pcid")],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EBX,
+        bits: &[
+            (9, "erms"),
+            (10, "invpcid"),
+            (16, "avx512f"),
+            (17, "avx512dq"),
+            (24, "clwb"),
+            (28, "avx512cd"),
+            (30, "avx512bw"),
+            (31, "avx512vl"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[(3, "pku")],
+    },
+];
+
+const CASCADELAKE_SERVER_FEATURES: &[CpuidFeatures] = &[CpuidFeatures {
+    function: 0x7,
+    index: 0,
+    reg: CpuidReg::ECX,
+    bits: &[(11, "avx512_vnni")],
+}];
+
+const ICELAKE_SERVER_FEATURES: &[CpuidFeatures] = &[
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EBX,
+        bits: &[(21, "avx512ifma"), (29, "sha_ni")],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[
+            (1, "avx512vbmi"),
+            (2, "umip"),
+            (6, "avx512_vbmi2"),
+            (8, "gfni"),
+            (9, "vaes"),
+            (10, "vpclmulqdq"),
+            (12, "avx512_bitalg"),
+            (14, "avx512_vpopcntdq"),
+            (16, "la57"),
+            (22, "rdpid"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EDX,
+        bits: &[(4, "fsrm")],
+    },
+];
+
+const SAPPHIRE_RAPIDS_FEATURES: &[CpuidFeatures] = &[
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[(25, "cldemote"), (27, "movdiri"), (28, "movdir64b")],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EDX,
+        bits: &[
+            (14, "serialize"),
+            (22, "amx_bf16"),
+            (23, "avx512_fp16"),
+            (24, "amx_tile"),
+            (25, "amx_int8"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 1,
+        reg: CpuidReg::EAX,
+        bits: &[(4, "avx_vnni"), (5, "avx512_bf16")],
+    },
+];
+
+const EPYC_ROME_FEATURES: &[CpuidFeatures] = &[
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EBX,
+        bits: &[(24, "clwb"), (29, "sha_ni")],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[(2, "umip"), (22, "rdpid")],
+    },
+    CpuidFeatures {
+        function: 0x8000_0001,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[
+            (1, "cmp_legacy"),
+            (6, "sse4a"),
+            (7, "misalignsse"),
+            (9, "osvw"),
+            (22, "topoext"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x8000_0001,
+        index: 0,
+        reg: CpuidReg::EDX,
+        bits: &[(22, "mmxext"), (25, "fxsr_opt")],
+    },
+];
+
+const EPYC_MILAN_FEATURES: &[CpuidFeatures] = &[
+    CpuidFeatures {
+        function: 0x1,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[(17, "pcid")],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EBX,
+        bits: &[(9, "erms"), (10, "invpcid")],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[(3, "pku"), (9, "vaes"), (10, "vpclmulqdq")],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EDX,
+        bits: &[(4, "fsrm")],
+    },
+];
+
+const EPYC_GENOA_FEATURES: &[CpuidFeatures] = &[
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::EBX,
+        bits: &[
+            (16, "avx512f"),
+            (17, "avx512dq"),
+            (21, "avx512ifma"),
+            (28, "avx512cd"),
+            (30, "avx512bw"),
+            (31, "avx512vl"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 0,
+        reg: CpuidReg::ECX,
+        bits: &[
+            (1, "avx512vbmi"),
+            (6, "avx512_vbmi2"),
+            (8, "gfni"),
+            (11, "avx512_vnni"),
+            (12, "avx512_bitalg"),
+            (14, "avx512_vpopcntdq"),
+            (16, "la57"),
+        ],
+    },
+    CpuidFeatures {
+        function: 0x7,
+        index: 1,
+        reg: CpuidReg::EAX,
+        bits: &[(5, "avx512_bf16")],
+    },
+];
+
+fn reg_value(cpuid: &CpuId, function: u32, index: u32, reg: CpuidReg) -> Option<u32> {
+    cpuid
+        .as_slice()
+        .iter()
+        .find(|entry| entry.function == function && entry.index == index)
+        .map(|entry| match reg {
+            CpuidReg::EAX => entry.eax,
+            CpuidReg::EBX => entry.ebx,
+            CpuidReg::ECX => entry.ecx,
+            CpuidReg::EDX => entry.edx,
+        })
+}
+
+impl CpuModel {
+    pub const ALL: [CpuModel; 7] = [
+        CpuModel::SkylakeServer,
+        CpuModel::CascadelakeServer,
+        CpuModel::IcelakeServer,
+        CpuModel::SapphireRapids,
+        CpuModel::EpycRome,
+        CpuModel::EpycMilan,
+        CpuModel::EpycGenoa,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuModel::SkylakeServer => "skylake-server",
+            CpuModel::CascadelakeServer => "cascadelake-server",
+            CpuModel::IcelakeServer => "icelake-server",
+            CpuModel::SapphireRapids => "sapphire-rapids",
+            CpuModel::EpycRome => "epyc-rome",
+            CpuModel::EpycMilan => "epyc-milan",
+            CpuModel::EpycGenoa => "epyc-genoa",
+        }
+    }
+
+    // Each generation includes the features of the previous one.
+    fn features(&self) -> Vec<&'static CpuidFeatures> {
+        let groups: &[&[CpuidFeatures]] = match self {
+            CpuModel::SkylakeServer => &[BASE_FEATURES, SKYLAKE_SERVER_FEATURES],
+            CpuModel::CascadelakeServer => &[
+                BASE_FEATURES,
+                SKYLAKE_SERVER_FEATURES,
+                CASCADELAKE_SERVER_FEATURES,
+            ],
+            CpuModel::IcelakeServer => &[
+                BASE_FEATURES,
+                SKYLAKE_SERVER_FEATURES,
+                CASCADELAKE_SERVER_FEATURES,
+                ICELAKE_SERVER_FEATURES,
+            ],
+            CpuModel::SapphireRapids => &[
+                BASE_FEATURES,
+                SKYLAKE_SERVER_FEATURES,
+                CASCADELAKE_SERVER_FEATURES,
+                ICELAKE_SERVER_FEATURES,
+                SAPPHIRE_RAPIDS_FEATURES,
+            ],
+            CpuModel::EpycRome => &[BASE_FEATURES, EPYC_ROME_FEATURES],
+            CpuModel::EpycMilan => &[BASE_FEATURES, EPYC_ROME_FEATURES, EPYC_MILAN_FEATURES],
+            CpuModel::EpycGenoa => &[
+                BASE_FEATURES,
+                EPYC_ROME_FEATURES,
+                EPYC_MILAN_FEATURES,
+                EPYC_GENOA_FEATURES,
+            ],
+        };
+        groups.iter().flat_map(|group| group.iter()).collect()
+    }
+
+    // Bits of the register set by the features of the model, or by the
+    // optional features.
+    fn mask(&self, function: u32, index: u32, reg: CpuidReg) -> u32 {
+        self.features()
+            .into_iter()
+            .chain(OPTIONAL_FEATURES.iter())
+            .filter(|f| f.function == function && f.index == index && f.reg == reg)
+            .flat_map(|f| f.bits.iter())
+            .fold(0, |mask, (bit, _)| mask | (1 << bit))
+    }
+
+    /// Returns the names of the features of the model the CPUID doesn't
+    /// provide.
+    pub fn missing_features(&self, cpuid: &CpuId) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        for features in self.features() {
+            let value = reg_value(cpuid, features.function, features.index, features.reg)
+                .unwrap_or_default();
+            for (bit, name) in features.bits {
+                if value & (1 << bit) == 0 {
+                    missing.push(*name);
+                }
+            }
+        }
+        missing
+    }
+
+    /// Clears the features the model doesn't provide from the CPUID.
+    pub fn apply(&self, cpuid: &mut CpuId) {
+        for (function, index, reg) in MASKED_REGISTERS {
+            let mask = self.mask(function, index, reg);
+            for entry in cpuid.as_mut_slice().iter_mut() {
+                if entry.function == function && entry.index == index {
+                    match reg {
+                        CpuidReg::EAX => entry.eax &= mask,
+                        CpuidReg::EBX => entry.ebx &= mask,
+                        CpuidReg::ECX => entry.ecx &= mask,
+                        CpuidReg::EDX => entry.edx &= mask,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for CpuModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for CpuModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CpuModel::ALL
+            .iter()
+            .find(|model| model.name() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown CPU model: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hypervisor::x86_64::CpuIdEntry;
+
+    fn host_cpuid() -> CpuId {
+        CpuId::from_entries(&[
+            CpuIdEntry {
+                function: 0x1,
+                ecx: 0xffff_ffff,
+                edx: 0xffff_ffff,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x7,
+                ebx: 0xffff_ffff,
+                ecx: 0xffff_ffff,
+                edx: 0xffff_ffff,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x7,
+                index: 1,
+                eax: 0xffff_ffff,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0xd,
+                index: 1,
+                eax: 0xffff_ffff,
+                ..Default::default()
+            },
+            CpuIdEntry {
+                function: 0x8000_0001,
+                ecx: 0xffff_ffff,
+                edx: 0xffff_ffff,
+                ..Default::default()
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cpu_model_names() {
+        for model in CpuModel::ALL {
+            assert_eq!(model.name().parse::<CpuModel>().unwrap(), model);
+        }
+        assert!("pentium".parse::<CpuModel>().is_err());
+    }
+
+    #[test]
+    fn test_cpu_model_apply() {
+        let mut cpuid = host_cpuid();
+        CpuModel::CascadelakeServer.apply(&mut cpuid);
+
+        let ebx = reg_value(&cpuid, 0x7, 0, CpuidReg::EBX).unwrap();
+        // avx512f is kept while sha_ni, only found on Ice Lake, is cleared.
+        assert_ne!(ebx & (1 << 16), 0);
+        assert_eq!(ebx & (1 << 29), 0);
+        // Optional features are kept.
+        let edx = reg_value(&cpuid, 0x7, 0, CpuidReg::EDX).unwrap();
+        assert_ne!(edx & (1 << 26), 0);
+        // None of the models expose the virtualization extensions.
+        let ecx = reg_value(&cpuid, 0x1, 0, CpuidReg::ECX).unwrap();
+        assert_eq!(ecx & (1 << 5), 0);
+
+        assert!(CpuModel::CascadelakeServer
+            .missing_features(&cpuid)
+            .is_empty());
+        assert_eq!(
+            CpuModel::IcelakeServer.missing_features(&cpuid)[..2],
+            ["avx512ifma", "sha_ni"]
+        );
+    }
+}
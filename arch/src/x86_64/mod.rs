@@ -7,6 +7,7 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE-BSD-3-Clause file.
 use std::sync::Arc;
+pub mod cpu_model;
 pub mod interrupts;
 pub mod layout;
 mod mpspec;
@@ -14,7 +15,9 @@ mod mptable;
 pub mod regs;
 use crate::GuestMemoryMmap;
 use crate::InitramfsConfig;
+use crate::PayloadBlob;
 use crate::RegionType;
+use cpu_model::CpuModel;
 use hypervisor::x86_64::{CpuId, CpuIdEntry, CPUID_FLAG_VALID_INDEX};
 use hypervisor::HypervisorError;
 use linux_loader::loader::bootparam::boot_params;
@@ -29,6 +32,8 @@ use vm_memory::{
 };
 mod smbios;
 use std::arch::x86_64;
+#[cfg(feature = "sev_snp")]
+pub mod sev_snp;
 #[cfg(feature = "tdx")]
 pub mod tdx;
 
@@ -36,6 +41,9 @@ pub mod tdx;
 const TSC_DEADLINE_TIMER_ECX_BIT: u8 = 24; // tsc deadline timer ecx bit.
 const HYPERVISOR_ECX_BIT: u8 = 31; // Hypervisor ecx bit.
 const MTRR_EDX_BIT: u8 = 12; // Hypervisor ecx bit.
+const VMX_ECX_BIT: u8 = 5; // Virtual Machine Extensions ecx bit.
+const SVM_ECX_BIT: u8 = 2; // Secure Virtual Machine ecx bit (extended leaf).
+const INVARIANT_TSC_EDX_BIT: u8 = 8; // Invariant TSC edx bit (extended leaf).
 
 // KVM feature bits
 const KVM_FEATURE_ASYNC_PF_INT_BIT: u8 = 14;
@@ -185,6 +193,12 @@ pub enum Error {
     /// Error populating CPUID with CPU identification
     CpuidIdentification(vmm_sys_util::fam::Error),
 
+    /// Error populating CPUID with the identification of the VMM
+    CpuidHypervisorInfo(vmm_sys_util::fam::Error),
+
+    /// Error populating CPUID with the invariant TSC leaf
+    CpuidInvariantTsc(vmm_sys_util::fam::Error),
+
     /// Error checking CPUID compatibility
     CpuidCheckCompatibility,
 
@@ -194,6 +208,12 @@ pub enum Error {
     /// Error retrieving TDX capabilities through the hypervisor (kvm/mshv) API
     #[cfg(feature = "tdx")]
     TdxCapabilities(HypervisorError),
+
+    /// The host CPU lacks some features of the CPU model used as baseline
+    CpuModelUnsupported(CpuModel, Vec<&'static str>),
+
+    /// The hypervisor doesn't expose VMX nor SVM to its guests
+    NestedUnsupported,
 }
 
 impl From<Error> for super::Error {
@@ -203,7 +223,7 @@ impl From<Error> for super::Error {
 }
 
 #[allow(dead_code, clippy::upper_case_acronyms)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CpuidReg {
     EAX,
     EBX,
@@ -557,6 +577,9 @@ pub fn generate_common_cpuid(
     sgx_epc_sections: Option<Vec<SgxEpcSection>>,
     phys_bits: u8,
     kvm_hyperv: bool,
+    nested: bool,
+    cpu_model: Option<CpuModel>,
+    tsc_khz: Option<u32>,
     #[cfg(feature = "tdx")] tdx_enabled: bool,
 ) -> super::Result<CpuId> {
     let cpuid_patches = vec![
@@ -597,6 +620,36 @@ pub fn generate_common_cpuid(
 
     CpuidPatch::patch_cpuid(&mut cpuid, cpuid_patches);
 
+    // The hypervisor reports VMX or SVM when it supports nested
+    // virtualization, which is passed through to the guest by default. When
+    // explicitly requested, it is required and kept by the CPU model.
+    let host_vmx = CpuidPatch::is_feature_enabled(&cpuid, 1, 0, CpuidReg::ECX, VMX_ECX_BIT.into());
+    let host_svm =
+        CpuidPatch::is_feature_enabled(&cpuid, 0x8000_0001, 0, CpuidReg::ECX, SVM_ECX_BIT.into());
+    if nested && !host_vmx && !host_svm {
+        return Err(Error::NestedUnsupported.into());
+    }
+
+    // Restrict the features to the ones of the CPU model, making sure the
+    // host provides all of them.
+    if let Some(cpu_model) = cpu_model {
+        let missing_features = cpu_model.missing_features(&cpuid);
+        if !missing_features.is_empty() {
+            return Err(Error::CpuModelUnsupported(cpu_model, missing_features).into());
+        }
+        cpu_model.apply(&mut cpuid);
+    }
+
+    if nested {
+        update_cpuid_nested(&mut cpuid, host_vmx, host_svm);
+    }
+
+    // The TSC keeps the same frequency across a restore or a migration when
+    // it is set explicitly, so the guest can rely on it as a clocksource.
+    if tsc_khz.is_some() {
+        update_cpuid_invariant_tsc(&mut cpuid)?;
+    }
+
     if let Some(t) = topology {
         update_cpuid_topology(&mut cpuid, t.0, t.1, t.2);
     }
@@ -743,6 +796,25 @@ pub fn generate_common_cpuid(
     Ok(cpuid)
 }
 
+fn update_cpuid_invariant_tsc(cpuid: &mut CpuId) -> super::Result<()> {
+    match cpuid
+        .as_mut_slice()
+        .iter_mut()
+        .find(|entry| entry.function == 0x8000_0007)
+    {
+        Some(entry) => entry.edx |= 1 << INVARIANT_TSC_EDX_BIT,
+        None => cpuid
+            .push(CpuIdEntry {
+                function: 0x8000_0007,
+                edx: 1 << INVARIANT_TSC_EDX_BIT,
+                ..Default::default()
+            })
+            .map_err(Error::CpuidInvariantTsc)?,
+    }
+
+    Ok(())
+}
+
 pub fn configure_vcpu(
     vcpu: &Arc<dyn hypervisor::Vcpu>,
     id: u8,
@@ -836,17 +908,19 @@ pub fn configure_system(
     guest_mem: &GuestMemoryMmap,
     cmdline_addr: GuestAddress,
     initramfs: &Option<InitramfsConfig>,
+    payload_blobs: &[PayloadBlob],
     _num_cpus: u8,
     rsdp_addr: Option<GuestAddress>,
     sgx_epc_region: Option<SgxEpcRegion>,
     serial_number: Option<&str>,
+    uuid: Option<&str>,
 ) -> super::Result<()> {
     // Write EBDA address to location where ACPICA expects to find it
     guest_mem
         .write_obj((layout::EBDA_START.0 >> 4) as u16, layout::EBDA_POINTER)
         .map_err(Error::EbdaSetup)?;
 
-    let size = smbios::setup_smbios(guest_mem, serial_number).map_err(Error::SmbiosSetup)?;
+    let size = smbios::setup_smbios(guest_mem, serial_number, uuid).map_err(Error::SmbiosSetup)?;
 
     // Place the MP table after the SMIOS table aligned to 16 bytes
     let offset = GuestAddress(layout::SMBIOS_START).unchecked_add(size);
@@ -864,6 +938,7 @@ pub fn configure_system(
         guest_mem,
         cmdline_addr,
         initramfs,
+        payload_blobs,
         rsdp_addr,
         sgx_epc_region,
     )
@@ -873,6 +948,7 @@ fn configure_pvh(
     guest_mem: &GuestMemoryMmap,
     cmdline_addr: GuestAddress,
     initramfs: &Option<InitramfsConfig>,
+    payload_blobs: &[PayloadBlob],
     rsdp_addr: Option<GuestAddress>,
     sgx_epc_region: Option<SgxEpcRegion>,
 ) -> super::Result<()> {
@@ -957,6 +1033,17 @@ fn configure_pvh(
         );
     }
 
+    // The payload blobs are loaded in RAM, the reserved entries take
+    // precedence over the RAM ones they overlap.
+    for payload_blob in payload_blobs {
+        add_memmap_entry(
+            &mut memmap,
+            payload_blob.address.raw_value(),
+            payload_blob.size as u64,
+            E820_RESERVED,
+        );
+    }
+
     start_info.0.memmap_entries = memmap.len() as u32;
 
     // Copy the vector with the memmap table to the MEMMAP_START address
@@ -1055,6 +1142,18 @@ pub fn get_host_cpu_phys_bits() -> u8 {
     }
 }
 
+// Exposes the virtualization extensions reported by the hypervisor, which
+// the CPU model may have masked.
+fn update_cpuid_nested(cpuid: &mut CpuId, vmx: bool, svm: bool) {
+    for entry in cpuid.as_mut_slice().iter_mut() {
+        match entry.function {
+            1 if vmx => entry.ecx |= 1 << VMX_ECX_BIT,
+            0x8000_0001 if svm => entry.ecx |= 1 << SVM_ECX_BIT,
+            _ => {}
+        }
+    }
+}
+
 fn update_cpuid_topology(
     cpuid: &mut CpuId,
     threads_per_core: u8,
@@ -1164,6 +1263,58 @@ fn update_cpuid_sgx(cpuid: &mut CpuId, epc_sections: Vec<SgxEpcSection>) -> Resu
     Ok(())
 }
 
+// First of the CPUID leaves identifying the VMM, above the ones of KVM and
+// Hyper-V so that the guest keeps finding them.
+pub const HYPERVISOR_INFO_CPUID_BASE: u32 = 0x4000_0100;
+const HYPERVISOR_INFO_SIGNATURE: &[u8; 12] = b"CratonVMM\0\0\0";
+
+// Adds the leaves identifying the VMM, from HYPERVISOR_INFO_CPUID_BASE:
+// - 0: the highest leaf in EAX, the signature in EBX, ECX and EDX;
+// - 1: the major, minor and patch version in EAX, EBX and ECX;
+// - 2: the bitmap of the enabled features in EAX;
+// - 3: the platform UUID in EAX to EDX, or zero.
+pub fn update_cpuid_hypervisor_info(
+    cpuid: &mut CpuId,
+    info: &crate::HypervisorInfo,
+) -> super::Result<()> {
+    fn words(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect()
+    }
+    let signature = words(HYPERVISOR_INFO_SIGNATURE);
+    let uuid = words(info.uuid.as_ref().map_or(&[0; 16], |uuid| uuid.as_bytes()));
+
+    let leaves = [
+        [
+            HYPERVISOR_INFO_CPUID_BASE + 3,
+            signature[0],
+            signature[1],
+            signature[2],
+        ],
+        [info.version[0], info.version[1], info.version[2], 0],
+        [info.features, 0, 0, 0],
+        [uuid[0], uuid[1], uuid[2], uuid[3]],
+    ];
+    for (i, [eax, ebx, ecx, edx]) in leaves.into_iter().enumerate() {
+        let function = HYPERVISOR_INFO_CPUID_BASE + i as u32;
+        cpuid.retain(|c| c.function != function);
+        cpuid
+            .push(CpuIdEntry {
+                function,
+                eax,
+                ebx,
+                ecx,
+                edx,
+                ..Default::default()
+            })
+            .map_err(Error::CpuidHypervisorInfo)?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1192,10 +1343,12 @@ mod tests {
             &gm,
             GuestAddress(0),
             &None,
+            &[],
             1,
             Some(layout::RSDP_POINTER),
             None,
             None,
+            None,
         );
         assert!(config_err.is_err());
 
@@ -1209,7 +1362,18 @@ mod tests {
             .collect();
         let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
 
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            &[],
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
 
         // Now assigning some memory that is equal to the start of the 32bit memory hole.
         let mem_size = 3328 << 20;
@@ -1220,9 +1384,31 @@ mod tests {
             .map(|r| (r.0, r.1))
             .collect();
         let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            &[],
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
 
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            &[],
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
 
         // Now assigning some memory that falls after the 32bit memory hole.
         let mem_size = 3330 << 20;
@@ -1233,9 +1419,31 @@ mod tests {
             .map(|r| (r.0, r.1))
             .collect();
         let gm = GuestMemoryMmap::from_ranges(&ram_regions).unwrap();
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            &[],
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
 
-        configure_system(&gm, GuestAddress(0), &None, no_vcpus, None, None, None).unwrap();
+        configure_system(
+            &gm,
+            GuestAddress(0),
+            &None,
+            &[],
+            no_vcpus,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
     }
 
     #[test]
@@ -1262,4 +1470,98 @@ mod tests {
 
         assert_eq!(format!("{:?}", memmap), format!("{:?}", expected_memmap));
     }
+
+    #[test]
+    fn test_update_cpuid_hypervisor_info() {
+        let mut cpuid = CpuId::new(0).unwrap();
+        let info = crate::HypervisorInfo {
+            version: [24, 0, 1],
+            features: crate::HYPERVISOR_INFO_FEATURE_BALLOON,
+            uuid: Some(uuid::Uuid::from_bytes([
+                0x4e, 0x0b, 0xa8, 0xd2, 0x6a, 0x8c, 0x4f, 0x11, 0x9d, 0x7e, 0x1c, 0x2b, 0x3a, 0x4d,
+                0x5e, 0x6f,
+            ])),
+        };
+        update_cpuid_hypervisor_info(&mut cpuid, &info).unwrap();
+
+        let leaf = |function| {
+            let entries = cpuid.as_slice();
+            let entry = entries.iter().find(|e| e.function == function).unwrap();
+            [entry.eax, entry.ebx, entry.ecx, entry.edx]
+        };
+        // "Crat", "onVM", "M\0\0\0"
+        assert_eq!(
+            leaf(0x4000_0100),
+            [0x4000_0103, 0x7461_7243, 0x4d56_6e6f, 0x0000_004d]
+        );
+        assert_eq!(leaf(0x4000_0101), [24, 0, 1, 0]);
+        assert_eq!(leaf(0x4000_0102), [1 << 3, 0, 0, 0]);
+        assert_eq!(
+            leaf(0x4000_0103),
+            [0xd2a8_0b4e, 0x114f_8c6a, 0x2b1c_7e9d, 0x6f5e_4d3a]
+        );
+
+        // The leaves are replaced rather than duplicated.
+        update_cpuid_hypervisor_info(&mut cpuid, &info).unwrap();
+        assert_eq!(cpuid.as_slice().len(), 4);
+    }
+
+    #[test]
+    fn test_update_cpuid_nested() {
+        let mut cpuid = CpuId::new(0).unwrap();
+        for function in [1, 0x8000_0001] {
+            cpuid
+                .push(CpuIdEntry {
+                    function,
+                    ecx: 1,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let ecx = |cpuid: &CpuId, function| {
+            let entries = cpuid.as_slice();
+            entries.iter().find(|e| e.function == function).unwrap().ecx
+        };
+
+        // Only the extension reported by the hypervisor is exposed, on top
+        // of the existing features.
+        update_cpuid_nested(&mut cpuid, true, false);
+        assert_eq!(ecx(&cpuid, 1), 1 | 1 << VMX_ECX_BIT);
+        assert_eq!(ecx(&cpuid, 0x8000_0001), 1);
+
+        update_cpuid_nested(&mut cpuid, false, true);
+        assert_eq!(ecx(&cpuid, 1), 1 | 1 << VMX_ECX_BIT);
+        assert_eq!(ecx(&cpuid, 0x8000_0001), 1 | 1 << SVM_ECX_BIT);
+    }
+
+    #[test]
+    fn test_update_cpuid_invariant_tsc() {
+        let edx = |cpuid: &CpuId| {
+            let entries = cpuid.as_slice();
+            entries
+                .iter()
+                .find(|e| e.function == 0x8000_0007)
+                .unwrap()
+                .edx
+        };
+
+        // The leaf is added when missing.
+        let mut cpuid = CpuId::new(0).unwrap();
+        update_cpuid_invariant_tsc(&mut cpuid).unwrap();
+        assert_eq!(edx(&cpuid), 1 << INVARIANT_TSC_EDX_BIT);
+
+        // The existing bits are kept.
+        let mut cpuid = CpuId::new(0).unwrap();
+        cpuid
+            .push(CpuIdEntry {
+                function: 0x8000_0007,
+                edx: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        update_cpuid_invariant_tsc(&mut cpuid).unwrap();
+        assert_eq!(cpuid.as_slice().len(), 1);
+        assert_eq!(edx(&cpuid), 1 | 1 << INVARIANT_TSC_EDX_BIT);
+    }
 }
@@ -0,0 +1,532 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use hypervisor::x86_64::CpuIdEntry;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::Read;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SevSnpError {
+    #[error("Failed to read the firmware: {0}")]
+    ReadFirmware(#[source] std::io::Error),
+    #[error("Missing OVMF table footer")]
+    MissingTableFooter,
+    #[error("Invalid OVMF table")]
+    InvalidTable,
+    #[error("Missing SEV metadata")]
+    MissingMetadata,
+    #[error("Invalid SEV metadata")]
+    InvalidMetadata,
+    #[error("Too many CPUID entries: {0}")]
+    TooManyCpuidEntries(usize),
+    #[error("Overlapping SEV metadata sections at 0x{0:x}")]
+    OverlappingSections(u64),
+}
+
+// GUIDs in their binary representation, as found in the firmware.
+// 96b582de-1fb2-45f7-baea-a366c55a082d
+const OVMF_TABLE_FOOTER_GUID: [u8; 16] = [
+    0xde, 0x82, 0xb5, 0x96, 0xb2, 0x1f, 0xf7, 0x45, 0xba, 0xea, 0xa3, 0x66, 0xc5, 0x5a, 0x08, 0x2d,
+];
+// dc886566-984a-4798-a75e-5585a7bf67cc
+const OVMF_SEV_METADATA_GUID: [u8; 16] = [
+    0x66, 0x65, 0x88, 0xdc, 0x4a, 0x98, 0x98, 0x47, 0xa7, 0x5e, 0x55, 0x85, 0xa7, 0xbf, 0x67, 0xcc,
+];
+
+// The table footer GUID is located 48 bytes from the end of the firmware,
+// right before the reset vector.
+const OVMF_TABLE_FOOTER_OFFSET: usize = 48;
+
+const SEV_METADATA_SIGNATURE: &[u8; 4] = b"ASEV";
+const SEV_METADATA_HEADER_SIZE: usize = 16;
+const SEV_METADATA_DESC_SIZE: usize = 12;
+
+/// Maximum number of entries of the CPUID page
+const SNP_CPUID_MAX_ENTRIES: usize = 64;
+const SNP_CPUID_HEADER_SIZE: usize = 16;
+const SNP_CPUID_ENTRY_SIZE: usize = 48;
+pub const SNP_CPUID_PAGE_SIZE: usize = 4096;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Type of a memory range described by the SEV metadata of the firmware.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SevSnpSectionType {
+    /// Memory the firmware expects to be accepted and zeroed
+    Memory,
+    /// Page filled with the secrets of the guest
+    Secrets,
+    /// Page holding the CPUID values
+    Cpuid,
+    /// Area of the hashes of the kernel, initrd and command line
+    KernelHashes,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SevSnpSection {
+    pub address: u64,
+    pub size: u64,
+    pub r#type: SevSnpSectionType,
+}
+
+/// Type of the pages of a range added to the launch measurement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SevSnpLaunchType {
+    /// Pages whose content is measured
+    Normal,
+    /// Pages the firmware zeroes and validates
+    Zero,
+    /// Secrets page
+    Secrets,
+    /// CPUID page, checked by the firmware
+    Cpuid,
+}
+
+/// Range of guest memory added to the launch measurement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SevSnpLaunchRange {
+    pub address: u64,
+    pub size: u64,
+    pub r#type: SevSnpLaunchType,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// Looks for the entry with the given GUID in the table found at the end of
+// the OVMF firmware. Each entry is made of its data, followed by its length
+// (including the GUID and the length) and the GUID, the table being walked
+// from its end.
+fn find_table_entry<'a>(firmware: &'a [u8], guid: &[u8; 16]) -> Result<&'a [u8], SevSnpError> {
+    let footer = firmware
+        .len()
+        .checked_sub(OVMF_TABLE_FOOTER_OFFSET)
+        .filter(|footer| *footer >= 2)
+        .ok_or(SevSnpError::MissingTableFooter)?;
+    if firmware[footer..footer + 16] != OVMF_TABLE_FOOTER_GUID {
+        return Err(SevSnpError::MissingTableFooter);
+    }
+
+    let table_len = read_u16(firmware, footer - 2).ok_or(SevSnpError::InvalidTable)? as usize;
+    let table_start = (footer + 16)
+        .checked_sub(table_len)
+        .ok_or(SevSnpError::InvalidTable)?;
+
+    // Start from the end of the last entry, right before the footer length.
+    let mut end = footer - 2;
+    while end > table_start {
+        let entry_guid = end.checked_sub(16).ok_or(SevSnpError::InvalidTable)?;
+        let data_end = entry_guid.checked_sub(2).ok_or(SevSnpError::InvalidTable)?;
+        let entry_len = read_u16(firmware, data_end).ok_or(SevSnpError::InvalidTable)?;
+        let data_len = (entry_len as usize)
+            .checked_sub(18)
+            .ok_or(SevSnpError::InvalidTable)?;
+        let data_start = data_end
+            .checked_sub(data_len)
+            .filter(|start| *start >= table_start)
+            .ok_or(SevSnpError::InvalidTable)?;
+
+        if firmware[entry_guid..end] == guid[..] {
+            return Ok(&firmware[data_start..data_end]);
+        }
+
+        end = data_start;
+    }
+
+    Err(SevSnpError::MissingMetadata)
+}
+
+/// Parses the memory ranges the firmware expects to be populated before the
+/// launch of the SEV-SNP guest, from the SEV metadata of the firmware.
+pub fn parse_sev_snp_sections(firmware: &[u8]) -> Result<Vec<SevSnpSection>, SevSnpError> {
+    let entry = find_table_entry(firmware, &OVMF_SEV_METADATA_GUID)?;
+    // The entry holds the offset of the metadata from the end of the firmware.
+    let offset = read_u32(entry, 0).ok_or(SevSnpError::InvalidMetadata)? as usize;
+    let metadata = firmware
+        .len()
+        .checked_sub(offset)
+        .map(|start| &firmware[start..])
+        .ok_or(SevSnpError::InvalidMetadata)?;
+
+    if metadata.get(0..4) != Some(&SEV_METADATA_SIGNATURE[..]) {
+        return Err(SevSnpError::InvalidMetadata);
+    }
+    let version = read_u32(metadata, 8).ok_or(SevSnpError::InvalidMetadata)?;
+    let num_desc = read_u32(metadata, 12).ok_or(SevSnpError::InvalidMetadata)? as usize;
+    if version != 1 {
+        return Err(SevSnpError::InvalidMetadata);
+    }
+
+    let mut sections = Vec::new();
+    for i in 0..num_desc {
+        let desc = SEV_METADATA_HEADER_SIZE + i * SEV_METADATA_DESC_SIZE;
+        let address = read_u32(metadata, desc).ok_or(SevSnpError::InvalidMetadata)?;
+        let size = read_u32(metadata, desc + 4).ok_or(SevSnpError::InvalidMetadata)?;
+        let r#type = match read_u32(metadata, desc + 8).ok_or(SevSnpError::InvalidMetadata)? {
+            1 => SevSnpSectionType::Memory,
+            2 => SevSnpSectionType::Secrets,
+            3 => SevSnpSectionType::Cpuid,
+            0x10 => SevSnpSectionType::KernelHashes,
+            t => {
+                warn!("Ignoring SEV metadata section of unknown type {}", t);
+                continue;
+            }
+        };
+        sections.push(SevSnpSection {
+            address: address as u64,
+            size: size as u64,
+            r#type,
+        });
+    }
+
+    Ok(sections)
+}
+
+/// Reads the firmware and parses its SEV metadata.
+pub fn parse_sev_snp_firmware(file: &mut File) -> Result<Vec<SevSnpSection>, SevSnpError> {
+    let mut firmware = Vec::new();
+    file.read_to_end(&mut firmware)
+        .map_err(SevSnpError::ReadFirmware)?;
+    parse_sev_snp_sections(&firmware)
+}
+
+/// Lays out the ranges of guest memory added to the launch measurement, from
+/// the ranges filled by the VMM (such as the firmware or the ACPI tables),
+/// and the sections described by the SEV metadata. A page is never measured
+/// twice: the sections take precedence over the content ranges, which are
+/// otherwise measured as normal pages. The sections must be page aligned and
+/// must not overlap.
+pub fn launch_ranges(
+    contents: &[(u64, u64)],
+    sections: &[SevSnpSection],
+) -> Result<Vec<SevSnpLaunchRange>, SevSnpError> {
+    let mut sections: Vec<SevSnpSection> =
+        sections.iter().filter(|s| s.size != 0).copied().collect();
+    sections.sort_by_key(|s| s.address);
+    let mut end = 0;
+    for section in sections.iter() {
+        let section_end = section
+            .address
+            .checked_add(section.size)
+            .ok_or(SevSnpError::InvalidMetadata)?;
+        if section.address % PAGE_SIZE != 0 || section.size % PAGE_SIZE != 0 {
+            return Err(SevSnpError::InvalidMetadata);
+        }
+        if section.address < end {
+            return Err(SevSnpError::OverlappingSections(section.address));
+        }
+        end = section_end;
+    }
+
+    // Page aligned content ranges, merged when they overlap or are
+    // contiguous.
+    let mut contents: Vec<(u64, u64)> = contents
+        .iter()
+        .filter(|(_, size)| *size != 0)
+        .map(|(address, size)| {
+            let start = address & !(PAGE_SIZE - 1);
+            let end = address
+                .checked_add(*size)
+                .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+                .map_or(u64::MAX & !(PAGE_SIZE - 1), |end| end & !(PAGE_SIZE - 1));
+            (start, end)
+        })
+        .collect();
+    contents.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in contents {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let mut ranges = Vec::new();
+    for (mut start, end) in merged {
+        for section in sections.iter() {
+            let section_end = section.address + section.size;
+            if section_end <= start || section.address >= end {
+                continue;
+            }
+            if section.address > start {
+                ranges.push(SevSnpLaunchRange {
+                    address: start,
+                    size: section.address - start,
+                    r#type: SevSnpLaunchType::Normal,
+                });
+            }
+            start = section_end;
+        }
+        if start < end {
+            ranges.push(SevSnpLaunchRange {
+                address: start,
+                size: end - start,
+                r#type: SevSnpLaunchType::Normal,
+            });
+        }
+    }
+
+    ranges.extend(sections.iter().map(|section| SevSnpLaunchRange {
+        address: section.address,
+        size: section.size,
+        r#type: match section.r#type {
+            SevSnpSectionType::Memory => SevSnpLaunchType::Zero,
+            SevSnpSectionType::Secrets => SevSnpLaunchType::Secrets,
+            SevSnpSectionType::Cpuid => SevSnpLaunchType::Cpuid,
+            SevSnpSectionType::KernelHashes => SevSnpLaunchType::Normal,
+        },
+    }));
+    ranges.sort_by_key(|r| r.address);
+
+    Ok(ranges)
+}
+
+/// Creates the content of the CPUID page, which lets the guest check the
+/// CPUID values against the ones validated by the SEV-SNP firmware.
+pub fn create_cpuid_page(entries: &[CpuIdEntry]) -> Result<Vec<u8>, SevSnpError> {
+    if entries.len() > SNP_CPUID_MAX_ENTRIES {
+        return Err(SevSnpError::TooManyCpuidEntries(entries.len()));
+    }
+
+    let mut page = vec![0u8; SNP_CPUID_PAGE_SIZE];
+    page[0..4].copy_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (i, entry) in entries.iter().enumerate() {
+        let offset = SNP_CPUID_HEADER_SIZE + i * SNP_CPUID_ENTRY_SIZE;
+        // XCR0 must be provided for the leaves reporting the XSAVE area size.
+        let xcr0_in: u64 = if entry.function == 0xd && entry.index <= 1 {
+            1
+        } else {
+            0
+        };
+        let fields = [
+            &entry.function.to_le_bytes()[..],
+            &entry.index.to_le_bytes()[..],
+            &xcr0_in.to_le_bytes()[..],
+            &0u64.to_le_bytes()[..],
+            &entry.eax.to_le_bytes()[..],
+            &entry.ebx.to_le_bytes()[..],
+            &entry.ecx.to_le_bytes()[..],
+            &entry.edx.to_le_bytes()[..],
+        ];
+        let mut field_offset = offset;
+        for field in fields.iter() {
+            page[field_offset..field_offset + field.len()].copy_from_slice(field);
+            field_offset += field.len();
+        }
+    }
+
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a firmware image of the given size, with a table holding the
+    // SEV metadata entry pointing to the given descriptors.
+    fn build_firmware(size: usize, descs: &[(u32, u32, u32)]) -> Vec<u8> {
+        let mut firmware = vec![0u8; size];
+
+        // SEV metadata at the start of the firmware.
+        let mut metadata = Vec::new();
+        metadata.extend_from_slice(SEV_METADATA_SIGNATURE);
+        metadata.extend_from_slice(
+            &((SEV_METADATA_HEADER_SIZE + descs.len() * SEV_METADATA_DESC_SIZE) as u32)
+                .to_le_bytes(),
+        );
+        metadata.extend_from_slice(&1u32.to_le_bytes());
+        metadata.extend_from_slice(&(descs.len() as u32).to_le_bytes());
+        for (base, len, r#type) in descs {
+            metadata.extend_from_slice(&base.to_le_bytes());
+            metadata.extend_from_slice(&len.to_le_bytes());
+            metadata.extend_from_slice(&r#type.to_le_bytes());
+        }
+        firmware[..metadata.len()].copy_from_slice(&metadata);
+
+        // Table made of the metadata entry followed by an unrelated entry.
+        let mut table = Vec::new();
+        table.extend_from_slice(&(size as u32).to_le_bytes());
+        table.extend_from_slice(&22u16.to_le_bytes());
+        table.extend_from_slice(&OVMF_SEV_METADATA_GUID);
+        table.extend_from_slice(&[0xaa; 4]);
+        table.extend_from_slice(&22u16.to_le_bytes());
+        table.extend_from_slice(&[0x11; 16]);
+        table.extend_from_slice(&((table.len() + 18) as u16).to_le_bytes());
+        table.extend_from_slice(&OVMF_TABLE_FOOTER_GUID);
+
+        let footer_end = size - OVMF_TABLE_FOOTER_OFFSET + 16;
+        firmware[footer_end - table.len()..footer_end].copy_from_slice(&table);
+        firmware
+    }
+
+    #[test]
+    fn test_parse_sev_snp_sections() {
+        let firmware = build_firmware(
+            0x1000,
+            &[
+                (0x80_0000, 0x9000, 1),
+                (0x80_9000, 0x1000, 2),
+                (0x80_a000, 0x1000, 3),
+                (0x80_b000, 0x1000, 0x42),
+            ],
+        );
+        assert_eq!(
+            parse_sev_snp_sections(&firmware).unwrap(),
+            vec![
+                SevSnpSection {
+                    address: 0x80_0000,
+                    size: 0x9000,
+                    r#type: SevSnpSectionType::Memory
+                },
+                SevSnpSection {
+                    address: 0x80_9000,
+                    size: 0x1000,
+                    r#type: SevSnpSectionType::Secrets
+                },
+                SevSnpSection {
+                    address: 0x80_a000,
+                    size: 0x1000,
+                    r#type: SevSnpSectionType::Cpuid
+                },
+            ]
+        );
+
+        let mut firmware = firmware;
+        let footer = firmware.len() - OVMF_TABLE_FOOTER_OFFSET;
+        firmware[footer] = 0;
+        assert!(matches!(
+            parse_sev_snp_sections(&firmware),
+            Err(SevSnpError::MissingTableFooter)
+        ));
+    }
+
+    #[test]
+    fn test_create_cpuid_page() {
+        let entries = vec![
+            CpuIdEntry {
+                function: 0xd,
+                index: 1,
+                eax: 0x1,
+                ebx: 0x2,
+                ecx: 0x3,
+                edx: 0x4,
+                ..Default::default()
+            };
+            2
+        ];
+        let page = create_cpuid_page(&entries).unwrap();
+        assert_eq!(page.len(), SNP_CPUID_PAGE_SIZE);
+        assert_eq!(read_u32(&page, 0), Some(2));
+        let entry = SNP_CPUID_HEADER_SIZE + SNP_CPUID_ENTRY_SIZE;
+        assert_eq!(read_u32(&page, entry), Some(0xd));
+        assert_eq!(read_u32(&page, entry + 4), Some(1));
+        assert_eq!(read_u32(&page, entry + 8), Some(1));
+        assert_eq!(read_u32(&page, entry + 24), Some(0x1));
+        assert_eq!(read_u32(&page, entry + 36), Some(0x4));
+
+        assert!(create_cpuid_page(&[CpuIdEntry::default(); 65]).is_err());
+    }
+
+    fn section(address: u64, size: u64, r#type: SevSnpSectionType) -> SevSnpSection {
+        SevSnpSection {
+            address,
+            size,
+            r#type,
+        }
+    }
+
+    fn range(address: u64, size: u64, r#type: SevSnpLaunchType) -> SevSnpLaunchRange {
+        SevSnpLaunchRange {
+            address,
+            size,
+            r#type,
+        }
+    }
+
+    #[test]
+    fn test_launch_ranges() {
+        // Firmware right below 4GiB, ACPI tables in the low memory, and the
+        // sections of OVMF.
+        let ranges = launch_ranges(
+            &[(0xffc0_0000, 0x40_0000), (0xa_0000, 0x1234)],
+            &[
+                section(0x80_0000, 0x9000, SevSnpSectionType::Memory),
+                section(0x80_a000, 0x1000, SevSnpSectionType::Cpuid),
+                section(0x80_9000, 0x1000, SevSnpSectionType::Secrets),
+                section(0x80_b000, 0x1000, SevSnpSectionType::KernelHashes),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                range(0xa_0000, 0x2000, SevSnpLaunchType::Normal),
+                range(0x80_0000, 0x9000, SevSnpLaunchType::Zero),
+                range(0x80_9000, 0x1000, SevSnpLaunchType::Secrets),
+                range(0x80_a000, 0x1000, SevSnpLaunchType::Cpuid),
+                range(0x80_b000, 0x1000, SevSnpLaunchType::Normal),
+                range(0xffc0_0000, 0x40_0000, SevSnpLaunchType::Normal),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_launch_ranges_overlap() {
+        // The secrets and CPUID pages found in the middle of a content range
+        // are only measured with their own type.
+        let ranges = launch_ranges(
+            &[(0, 0x10_0000), (0x8_0000, 0x4_0000)],
+            &[
+                section(0x9000, 0x1000, SevSnpSectionType::Secrets),
+                section(0xa000, 0x1000, SevSnpSectionType::Cpuid),
+                section(0xf_f000, 0x2000, SevSnpSectionType::Memory),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                range(0, 0x9000, SevSnpLaunchType::Normal),
+                range(0x9000, 0x1000, SevSnpLaunchType::Secrets),
+                range(0xa000, 0x1000, SevSnpLaunchType::Cpuid),
+                range(0xb000, 0xf_4000, SevSnpLaunchType::Normal),
+                range(0xf_f000, 0x2000, SevSnpLaunchType::Zero),
+            ]
+        );
+
+        // No page is ever measured twice.
+        for pair in ranges.windows(2) {
+            assert!(pair[0].address + pair[0].size <= pair[1].address);
+        }
+    }
+
+    #[test]
+    fn test_launch_ranges_invalid_sections() {
+        assert!(matches!(
+            launch_ranges(
+                &[],
+                &[
+                    section(0x8000, 0x2000, SevSnpSectionType::Memory),
+                    section(0x9000, 0x1000, SevSnpSectionType::Secrets),
+                ],
+            ),
+            Err(SevSnpError::OverlappingSections(0x9000))
+        ));
+        assert!(matches!(
+            launch_ranges(&[], &[section(0x8800, 0x1000, SevSnpSectionType::Cpuid)]),
+            Err(SevSnpError::InvalidMetadata)
+        ));
+    }
+}
@@ -12,6 +12,7 @@ use std::fmt::{self, Display};
 use std::mem;
 use std::result;
 use std::slice;
+use uuid::Uuid;
 use vm_memory::ByteValued;
 use vm_memory::{Address, Bytes, GuestAddress};
 
@@ -28,6 +29,8 @@ pub enum Error {
     WriteSmbiosEp,
     /// Failure to write additional data to memory
     WriteData,
+    /// Failure to parse the UUID
+    ParseUuid,
 }
 
 impl std::error::Error for Error {}
@@ -42,6 +45,7 @@ impl Display for Error {
             Clear => "Failure while zeroing out the memory for the SMBIOS table",
             WriteSmbiosEp => "Failure to write SMBIOS entrypoint structure",
             WriteData => "Failure to write additional data to memory",
+            ParseUuid => "Failure to parse the UUID",
         };
 
         write!(f, "SMBIOS error: {}", description)
@@ -162,7 +166,11 @@ fn write_string(
     Ok(curptr)
 }
 
-pub fn setup_smbios(mem: &GuestMemoryMmap, serial_number: Option<&str>) -> Result<u64> {
+pub fn setup_smbios(
+    mem: &GuestMemoryMmap,
+    serial_number: Option<&str>,
+    uuid: Option<&str>,
+) -> Result<u64> {
     let physptr = GuestAddress(SMBIOS_START)
         .checked_add(mem::size_of::<Smbios30Entrypoint>() as u64)
         .ok_or(Error::NotEnoughMemory)?;
@@ -196,6 +204,13 @@ pub fn setup_smbios(mem: &GuestMemoryMmap, serial_number: Option<&str>) -> Resul
             manufacturer: 1, // First string written in this section
             product_name: 2, // Second string written in this section
             serial_number: serial_number.map(|_| 3).unwrap_or_default(), // 3rd string
+            // The first three fields are stored in little endian.
+            uuid: uuid
+                .map(Uuid::parse_str)
+                .transpose()
+                .map_err(|_| Error::ParseUuid)?
+                .map(|uuid| uuid.to_bytes_le())
+                .unwrap_or_default(),
             ..Default::default()
         };
         curptr = write_and_incr(mem, smbios_sysinfo, curptr)?;
@@ -267,10 +282,34 @@ mod tests {
     fn entrypoint_checksum() {
         let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(SMBIOS_START), 4096)]).unwrap();
 
-        setup_smbios(&mem, None).unwrap();
+        setup_smbios(&mem, None, None).unwrap();
 
         let smbios_ep: Smbios30Entrypoint = mem.read_obj(GuestAddress(SMBIOS_START)).unwrap();
 
         assert_eq!(compute_checksum(&smbios_ep), 0);
     }
+
+    #[test]
+    fn system_uuid() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(SMBIOS_START), 4096)]).unwrap();
+
+        setup_smbios(&mem, None, Some("4e0ba8d2-6a8c-4f11-9d7e-1c2b3a4d5e6f")).unwrap();
+
+        let smbios_ep: Smbios30Entrypoint = mem.read_obj(GuestAddress(SMBIOS_START)).unwrap();
+        let sysinfo_addr = GuestAddress(smbios_ep.physptr)
+            .unchecked_add(mem::size_of::<SmbiosBiosInfo>() as u64)
+            .unchecked_add(b"cloud-hypervisor\00\0\0".len() as u64);
+        let sysinfo: SmbiosSysInfo = mem.read_obj(sysinfo_addr).unwrap();
+
+        assert_eq!(sysinfo.typ, SYSTEM_INFORMATION);
+        assert_eq!(
+            sysinfo.uuid,
+            [
+                0xd2, 0xa8, 0x0b, 0x4e, 0x8c, 0x6a, 0x11, 0x4f, 0x9d, 0x7e, 0x1c, 0x2b, 0x3a, 0x4d,
+                0x5e, 0x6f
+            ]
+        );
+
+        assert!(setup_smbios(&mem, None, Some("not-a-uuid")).is_err());
+    }
 }
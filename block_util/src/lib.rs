@@ -17,6 +17,7 @@ pub mod fixed_vhd_sync;
 pub mod qcow_sync;
 pub mod raw_async;
 pub mod raw_sync;
+pub mod raw_threads;
 pub mod vhd;
 pub mod vhdx_sync;
 
@@ -30,17 +31,15 @@ use std::io::{self, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write};
 use std::os::linux::fs::MetadataExt;
 use std::path::Path;
 use std::result;
-use std::sync::Arc;
 use std::sync::MutexGuard;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_bindings::bindings::virtio_blk::*;
-use virtio_queue::DescriptorChain;
 use vm_memory::{
     bitmap::AtomicBitmap, bitmap::Bitmap, ByteValued, Bytes, GuestAddress, GuestMemory,
-    GuestMemoryError, GuestMemoryLoadGuard,
+    GuestMemoryError,
 };
-use vm_virtio::{AccessPlatform, Translatable};
+use vm_virtio::chain::{ChainLimits, ValidChain};
 use vmm_sys_util::eventfd::EventFd;
 
 type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
@@ -48,6 +47,9 @@ type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
 const SECTOR_SHIFT: u8 = 9;
 pub const SECTOR_SIZE: u64 = 0x01 << SECTOR_SHIFT;
 
+// Size of the header starting a request, made of its type and sector.
+const REQUEST_HEADER_SIZE: u64 = 16;
+
 #[derive(Debug)]
 pub enum Error {
     /// Guest gave us bad memory addresses.
@@ -161,6 +163,16 @@ pub fn request_type(
     }
 }
 
+/// Limits of the descriptor chains holding a request, which starts with its
+/// header and ends with its status byte.
+pub fn request_chain_limits() -> ChainLimits {
+    ChainLimits {
+        min_readable: REQUEST_HEADER_SIZE,
+        min_writable: 1,
+        ..Default::default()
+    }
+}
+
 fn sector(mem: &GuestMemoryMmap, desc_addr: GuestAddress) -> result::Result<u64, Error> {
     const SECTOR_OFFSET: usize = 8;
     let addr = match mem.checked_offset(desc_addr, SECTOR_OFFSET) {
@@ -190,92 +202,53 @@ pub struct Request {
 }
 
 impl Request {
-    pub fn parse(
-        desc_chain: &mut DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
-        access_platform: Option<&Arc<dyn AccessPlatform>>,
-    ) -> result::Result<Request, Error> {
-        let hdr_desc = desc_chain
-            .next()
-            .ok_or(Error::DescriptorChainTooShort)
-            .map_err(|e| {
-                error!("Missing head descriptor");
-                e
-            })?;
-
-        // The head contains the request type which MUST be readable.
-        if hdr_desc.is_write_only() {
-            return Err(Error::UnexpectedWriteOnlyDescriptor);
-        }
-
-        let hdr_desc_addr = hdr_desc
-            .addr()
-            .translate_gva(access_platform, hdr_desc.len() as usize);
+    /// Parses the request held by a chain validated against the limits of
+    /// `request_chain_limits()`, which starts with a readable header and
+    /// ends with a writable status.
+    pub fn parse(desc_chain: &ValidChain, mem: &GuestMemoryMmap) -> result::Result<Request, Error> {
+        let descriptors = desc_chain.descriptors();
+        let (hdr_desc, descriptors) = descriptors
+            .split_first()
+            .ok_or(Error::DescriptorChainTooShort)?;
 
         let mut req = Request {
-            request_type: request_type(desc_chain.memory(), hdr_desc_addr)?,
-            sector: sector(desc_chain.memory(), hdr_desc_addr)?,
+            request_type: request_type(mem, hdr_desc.addr())?,
+            sector: sector(mem, hdr_desc.addr())?,
             data_descriptors: Vec::new(),
             status_addr: GuestAddress(0),
             writeback: true,
             aligned_operations: Vec::new(),
         };
 
-        let status_desc;
-        let mut desc = desc_chain
-            .next()
-            .ok_or(Error::DescriptorChainTooShort)
-            .map_err(|e| {
-                error!("Only head descriptor present: request = {:?}", req);
-                e
-            })?;
-
-        if !desc.has_next() {
-            status_desc = desc;
-            // Only flush requests are allowed to skip the data descriptor.
-            if req.request_type != RequestType::Flush {
-                error!("Need a data descriptor: request = {:?}", req);
-                return Err(Error::DescriptorChainTooShort);
-            }
-        } else {
-            while desc.has_next() {
-                if desc.is_write_only() && req.request_type == RequestType::Out {
-                    return Err(Error::UnexpectedWriteOnlyDescriptor);
-                }
-                if !desc.is_write_only() && req.request_type == RequestType::In {
-                    return Err(Error::UnexpectedReadOnlyDescriptor);
-                }
-                if !desc.is_write_only() && req.request_type == RequestType::GetDeviceId {
-                    return Err(Error::UnexpectedReadOnlyDescriptor);
-                }
+        let (status_desc, data_descriptors) = descriptors
+            .split_last()
+            .ok_or(Error::DescriptorChainTooShort)?;
 
-                req.data_descriptors.push((
-                    desc.addr()
-                        .translate_gva(access_platform, desc.len() as usize),
-                    desc.len(),
-                ));
-                desc = desc_chain
-                    .next()
-                    .ok_or(Error::DescriptorChainTooShort)
-                    .map_err(|e| {
-                        error!("DescriptorChain corrupted: request = {:?}", req);
-                        e
-                    })?;
-            }
-            status_desc = desc;
+        // Only flush requests are allowed to skip the data descriptor.
+        if data_descriptors.is_empty() && req.request_type != RequestType::Flush {
+            return Err(Error::DescriptorChainTooShort);
         }
 
-        // The status MUST always be writable.
-        if !status_desc.is_write_only() {
-            return Err(Error::UnexpectedReadOnlyDescriptor);
+        for desc in data_descriptors {
+            if desc.is_write_only() && req.request_type == RequestType::Out {
+                return Err(Error::UnexpectedWriteOnlyDescriptor);
+            }
+            if !desc.is_write_only() && req.request_type == RequestType::In {
+                return Err(Error::UnexpectedReadOnlyDescriptor);
+            }
+            if !desc.is_write_only() && req.request_type == RequestType::GetDeviceId {
+                return Err(Error::UnexpectedReadOnlyDescriptor);
+            }
+
+            req.data_descriptors.push((desc.addr(), desc.len()));
         }
 
+        // The status is the last writable descriptor, which may be empty.
         if status_desc.len() < 1 {
             return Err(Error::DescriptorLengthTooSmall);
         }
 
-        req.status_addr = status_desc
-            .addr()
-            .translate_gva(access_platform, status_desc.len() as usize);
+        req.status_addr = status_desc.addr();
 
         Ok(req)
     }
@@ -0,0 +1,255 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
+
+use crate::async_io::{
+    AsyncIo, AsyncIoError, AsyncIoResult, DiskFile, DiskFileError, DiskFileResult, DiskTopology,
+};
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use vmm_sys_util::eventfd::EventFd;
+
+// Number of threads performing the I/O of each queue. They let several
+// requests be in flight at the same time, without relying on io_uring.
+const WORKER_THREADS: usize = 4;
+
+pub struct RawFileDiskThreads {
+    file: File,
+}
+
+impl RawFileDiskThreads {
+    pub fn new(file: File) -> Self {
+        RawFileDiskThreads { file }
+    }
+}
+
+impl DiskFile for RawFileDiskThreads {
+    fn size(&mut self) -> DiskFileResult<u64> {
+        Ok(self
+            .file
+            .seek(SeekFrom::End(0))
+            .map_err(DiskFileError::Size)? as u64)
+    }
+
+    fn new_async_io(&self, _ring_depth: u32) -> DiskFileResult<Box<dyn AsyncIo>> {
+        Ok(
+            Box::new(RawFileThreads::new(self.file.as_raw_fd()).map_err(DiskFileError::NewAsyncIo)?)
+                as Box<dyn AsyncIo>,
+        )
+    }
+
+    fn topology(&mut self) -> DiskTopology {
+        if let Ok(topology) = DiskTopology::probe(&mut self.file) {
+            topology
+        } else {
+            warn!("Unable to get device topology. Using default topology");
+            DiskTopology::default()
+        }
+    }
+}
+
+enum Request {
+    Read(libc::off_t, Vec<libc::iovec>, u64),
+    Write(libc::off_t, Vec<libc::iovec>, u64),
+    Fsync(u64),
+}
+
+// SAFETY: the iovecs point to the guest memory, which stays mapped as long
+// as the request is in flight, the same way as with io_uring.
+unsafe impl Send for Request {}
+
+impl Request {
+    // Returns the user data of the request along with its result, the
+    // negated errno on failure.
+    fn execute(&self, fd: RawFd) -> (u64, i32) {
+        let (user_data, result) = match self {
+            // SAFETY: FFI call with valid iovecs
+            Request::Read(offset, iovecs, user_data) => (*user_data, unsafe {
+                libc::preadv(
+                    fd as libc::c_int,
+                    iovecs.as_ptr() as *const libc::iovec,
+                    iovecs.len() as libc::c_int,
+                    *offset,
+                )
+            }),
+            // SAFETY: FFI call with valid iovecs
+            Request::Write(offset, iovecs, user_data) => (*user_data, unsafe {
+                libc::pwritev(
+                    fd as libc::c_int,
+                    iovecs.as_ptr() as *const libc::iovec,
+                    iovecs.len() as libc::c_int,
+                    *offset,
+                )
+            }),
+            // SAFETY: FFI call with a valid fd
+            Request::Fsync(user_data) => (*user_data, unsafe { libc::fsync(fd) } as isize),
+        };
+
+        if result < 0 {
+            (
+                user_data,
+                -std::io::Error::last_os_error().raw_os_error().unwrap(),
+            )
+        } else {
+            (user_data, result as i32)
+        }
+    }
+}
+
+pub struct RawFileThreads {
+    fd: RawFd,
+    eventfd: EventFd,
+    request_tx: Option<Sender<Request>>,
+    completion_list: Arc<Mutex<Vec<(u64, i32)>>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl RawFileThreads {
+    pub fn new(fd: RawFd) -> std::io::Result<Self> {
+        let eventfd = EventFd::new(libc::EFD_NONBLOCK)?;
+        let (request_tx, request_rx) = channel();
+        let request_rx = Arc::new(Mutex::new(request_rx));
+        let completion_list = Arc::new(Mutex::new(Vec::new()));
+
+        let mut workers = Vec::new();
+        for i in 0..WORKER_THREADS {
+            let request_rx: Arc<Mutex<Receiver<Request>>> = request_rx.clone();
+            let completion_list = completion_list.clone();
+            let eventfd = eventfd.try_clone()?;
+            workers.push(thread::Builder::new().name(format!("disk_io{}", i)).spawn(
+                move || loop {
+                    // The requests are over once the sender is dropped.
+                    let request = request_rx.lock().unwrap().recv();
+                    let request = match request {
+                        Ok(request) => request,
+                        Err(_) => break,
+                    };
+
+                    let completion = request.execute(fd);
+                    completion_list.lock().unwrap().push(completion);
+                    eventfd.write(1).unwrap();
+                },
+            )?);
+        }
+
+        Ok(RawFileThreads {
+            fd,
+            eventfd,
+            request_tx: Some(request_tx),
+            completion_list,
+            workers,
+        })
+    }
+
+    fn submit(&self, request: Request) {
+        // The workers only stop once the sender is dropped.
+        self.request_tx.as_ref().unwrap().send(request).unwrap();
+    }
+}
+
+impl Drop for RawFileThreads {
+    fn drop(&mut self) {
+        self.request_tx.take();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("Disk I/O thread panicked");
+            }
+        }
+    }
+}
+
+impl AsyncIo for RawFileThreads {
+    fn notifier(&self) -> &EventFd {
+        &self.eventfd
+    }
+
+    fn read_vectored(
+        &mut self,
+        offset: libc::off_t,
+        iovecs: Vec<libc::iovec>,
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.submit(Request::Read(offset, iovecs, user_data));
+        Ok(())
+    }
+
+    fn write_vectored(
+        &mut self,
+        offset: libc::off_t,
+        iovecs: Vec<libc::iovec>,
+        user_data: u64,
+    ) -> AsyncIoResult<()> {
+        self.submit(Request::Write(offset, iovecs, user_data));
+        Ok(())
+    }
+
+    fn fsync(&mut self, user_data: Option<u64>) -> AsyncIoResult<()> {
+        match user_data {
+            Some(user_data) => self.submit(Request::Fsync(user_data)),
+            None => {
+                // SAFETY: FFI call with a valid fd
+                if unsafe { libc::fsync(self.fd) } < 0 {
+                    return Err(AsyncIoError::Fsync(std::io::Error::last_os_error()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn complete(&mut self) -> Vec<(u64, i32)> {
+        self.completion_list.lock().unwrap().drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use vmm_sys_util::tempfile::TempFile;
+
+    fn wait_completions(async_io: &mut dyn AsyncIo) -> Vec<(u64, i32)> {
+        loop {
+            let completions = async_io.complete();
+            if !completions.is_empty() {
+                return completions;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_raw_file_threads() {
+        let mut file = TempFile::new().unwrap().into_file();
+        file.write_all(&[0xaa; 0x1000]).unwrap();
+        let disk = RawFileDiskThreads::new(file);
+        let mut async_io = disk.new_async_io(1).unwrap();
+
+        let mut data = vec![0x55u8; 0x200];
+        let iovecs = vec![libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        }];
+        async_io.write_vectored(0x200, iovecs, 1).unwrap();
+
+        let completions = wait_completions(async_io.as_mut());
+        assert_eq!(completions, vec![(1, 0x200)]);
+
+        let mut buf = vec![0u8; 0x400];
+        let iovecs = vec![libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        }];
+        async_io.read_vectored(0x100, iovecs, 2).unwrap();
+
+        let completions = wait_completions(async_io.as_mut());
+        assert_eq!(completions, vec![(2, 0x400)]);
+        assert_eq!(&buf[..0x100], &[0xaa; 0x100]);
+        assert_eq!(&buf[0x100..0x300], &data[..]);
+        assert_eq!(&buf[0x300..], &[0xaa; 0x100]);
+    }
+}
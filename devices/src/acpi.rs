@@ -64,6 +64,9 @@ pub struct AcpiGedDevice {
     notification_type: AcpiNotificationFlags,
     ged_irq: u32,
     address: GuestAddress,
+    // ACPI paths of the virtio-mmio devices notified when they are
+    // hot-added, each one being described upfront on reserved resources.
+    mmio_hotplug_devices: Vec<String>,
 }
 
 impl AcpiGedDevice {
@@ -77,9 +80,16 @@ impl AcpiGedDevice {
             notification_type: AcpiNotificationFlags::NO_DEVICES_CHANGED,
             ged_irq,
             address,
+            mmio_hotplug_devices: Vec::new(),
         }
     }
 
+    /// Notifies the device at the given ACPI path, e.g. `\_SB_.VSCK`, when
+    /// the virtio-mmio devices change.
+    pub fn add_mmio_hotplug_device(&mut self, path: String) {
+        self.mmio_hotplug_devices.push(path);
+    }
+
     pub fn notify(
         &mut self,
         notification_type: AcpiNotificationFlags,
@@ -102,6 +112,33 @@ impl BusDevice for AcpiGedDevice {
     }
 }
 
+// Notifies the hot-pluggable virtio-mmio devices when they change, nothing
+// being generated if there isn't any.
+struct MmioHotplugEvent<'a>(&'a [String]);
+
+impl<'a> Aml for MmioHotplugEvent<'a> {
+    fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        let mmio_devices_changed = AcpiNotificationFlags::MMIO_DEVICES_CHANGED.bits() as usize;
+        let paths: Vec<aml::Path> = self.0.iter().map(|path| aml::Path::new(path)).collect();
+        let notifies: Vec<aml::Notify> = paths
+            .iter()
+            .map(|path| aml::Notify::new(path, &aml::ONE))
+            .collect();
+
+        aml::And::new(&aml::Local(1), &aml::Local(0), &mmio_devices_changed)
+            .append_aml_bytes(bytes);
+        aml::If::new(
+            &aml::Equal::new(&aml::Local(1), &mmio_devices_changed),
+            notifies.iter().map(|notify| notify as &dyn Aml).collect(),
+        )
+        .append_aml_bytes(bytes);
+    }
+}
+
 impl Aml for AcpiGedDevice {
     fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
         aml::Device::new(
@@ -160,6 +197,7 @@ impl Aml for AcpiGedDevice {
                                 &0x80usize,
                             )],
                         ),
+                        &MmioHotplugEvent(&self.mmio_hotplug_devices),
                     ],
                 ),
             ],
@@ -205,3 +243,83 @@ impl BusDevice for AcpiPmTimerDevice {
         data.copy_from_slice(&counter.to_le_bytes());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_device::interrupt::{InterruptIndex, InterruptSourceConfig};
+
+    struct TestInterrupt {
+        event_fd: EventFd,
+    }
+
+    impl InterruptSourceGroup for TestInterrupt {
+        fn trigger(&self, _index: InterruptIndex) -> Result<(), std::io::Error> {
+            self.event_fd.write(1)
+        }
+        fn update(
+            &self,
+            _index: InterruptIndex,
+            _config: InterruptSourceConfig,
+            _masked: bool,
+        ) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+        fn notifier(&self, _index: InterruptIndex) -> Option<EventFd> {
+            Some(self.event_fd.try_clone().unwrap())
+        }
+    }
+
+    fn ged_device() -> (AcpiGedDevice, EventFd) {
+        let event_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let interrupt = Arc::new(TestInterrupt {
+            event_fd: event_fd.try_clone().unwrap(),
+        });
+        (
+            AcpiGedDevice::new(interrupt, 42, GuestAddress(0x1000)),
+            event_fd,
+        )
+    }
+
+    #[test]
+    fn test_ged_notify() {
+        let (mut ged, event_fd) = ged_device();
+
+        ged.notify(AcpiNotificationFlags::MMIO_DEVICES_CHANGED)
+            .unwrap();
+        ged.notify(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+            .unwrap();
+        assert_eq!(event_fd.read().unwrap(), 2);
+
+        // The notifications are cleared once read by the guest.
+        let mut data = [0u8];
+        ged.read(0, 0, &mut data);
+        assert_eq!(
+            data[0],
+            (AcpiNotificationFlags::MMIO_DEVICES_CHANGED
+                | AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+                .bits()
+        );
+        ged.read(0, 0, &mut data);
+        assert_eq!(data[0], 0);
+    }
+
+    #[test]
+    fn test_ged_mmio_hotplug_aml() {
+        // Notify of the \_SB_.VSCK device.
+        let notify = [
+            0x86, b'\\', 0x2e, b'_', b'S', b'B', b'_', b'V', b'S', b'C', b'K',
+        ];
+        let contains_notify = |ged: &AcpiGedDevice| {
+            let mut bytes = Vec::new();
+            ged.append_aml_bytes(&mut bytes);
+            bytes.windows(notify.len()).any(|w| w == notify)
+        };
+
+        let (mut ged, _event_fd) = ged_device();
+        assert!(!contains_notify(&ged));
+
+        ged.add_mmio_hotplug_device("\\_SB_.VSCK".to_owned());
+        assert!(contains_notify(&ged));
+    }
+}
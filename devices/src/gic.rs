@@ -75,6 +75,18 @@ impl Gic {
         let vgic = self.vgic.as_ref().unwrap().clone();
         vgic.lock().unwrap().set_gicr_typers(vcpu_states);
     }
+
+    // Sets an SGI pending on the vCPU with the given MPIDR, which must be
+    // paused.
+    pub fn inject_sgi(&self, mpidr: u64, sgi: u8) -> Result<()> {
+        self.vgic
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .set_sgi_pending(mpidr, sgi)
+            .map_err(Error::InjectSgi)
+    }
 }
 
 impl InterruptController for Gic {
@@ -26,6 +26,9 @@ pub enum Error {
     EnableInterrupt(io::Error),
     /// Failed creating GIC device.
     CreateGic(hypervisor::HypervisorVmError),
+    /// Failed injecting an SGI.
+    #[cfg(target_arch = "aarch64")]
+    InjectSgi(hypervisor::arch::aarch64::gic::Error),
 }
 
 type Result<T> = result::Result<T, Error>;
@@ -22,6 +22,7 @@ const DATA_LEN: usize = 128;
 pub struct Cmos {
     index: u8,
     data: [u8; DATA_LEN],
+    initial_data: [u8; DATA_LEN],
     reset_evt: EventFd,
 }
 
@@ -49,9 +50,17 @@ impl Cmos {
         Cmos {
             index: 0,
             data,
+            initial_data: data,
             reset_evt,
         }
     }
+
+    /// Brings the CMOS memory back to the data it was constructed with,
+    /// discarding what the guest wrote to it.
+    pub fn reset(&mut self) {
+        self.index = 0;
+        self.data = self.initial_data;
+    }
 }
 
 impl BusDevice for Cmos {
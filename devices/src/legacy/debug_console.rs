@@ -0,0 +1,148 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::io;
+use std::sync::{Arc, Barrier};
+use vm_device::BusDevice;
+
+// Value read back from the register, which lets the guest detect the device,
+// as done by QEMU.
+const DEBUG_CONSOLE_READBACK: u8 = 0xe9;
+
+// Longest line reported at once through the event monitor.
+const MAX_LINE_LEN: usize = 256;
+
+pub enum DebugConsoleOutput {
+    /// The output is written as is.
+    Writer(Box<dyn io::Write + Send>),
+    /// The output is reported line by line through the event monitor.
+    Event,
+}
+
+/// Output only console made of a single byte register, similar to the
+/// debugcon device from QEMU. It gives firmware and early boot code an output
+/// channel which doesn't need any initialization.
+pub struct DebugConsole {
+    out: DebugConsoleOutput,
+    line: Vec<u8>,
+}
+
+impl DebugConsole {
+    pub fn new(out: DebugConsoleOutput) -> Self {
+        Self {
+            out,
+            line: Vec::new(),
+        }
+    }
+
+    fn flush_line(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        event!("debug-console", "output", "line", line);
+        self.line.clear();
+    }
+
+    fn output(&mut self, data: &[u8]) {
+        match &mut self.out {
+            DebugConsoleOutput::Writer(out) => {
+                if let Err(e) = out.write_all(data).and_then(|_| out.flush()) {
+                    error!("Error writing debug console output: {}", e);
+                }
+            }
+            DebugConsoleOutput::Event => {
+                for &byte in data {
+                    match byte {
+                        b'\n' => self.flush_line(),
+                        b'\r' => {}
+                        _ => {
+                            self.line.push(byte);
+                            if self.line.len() >= MAX_LINE_LEN {
+                                self.flush_line();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl BusDevice for DebugConsole {
+    fn read(&mut self, _base: u64, _offset: u64, data: &mut [u8]) {
+        if data.len() == 1 {
+            data[0] = DEBUG_CONSOLE_READBACK;
+        } else {
+            warn!("Invalid debug console read: len {}", data.len());
+        }
+    }
+
+    fn write(&mut self, _base: u64, _offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        self.output(data);
+        None
+    }
+}
+
+impl Drop for DebugConsole {
+    fn drop(&mut self) {
+        if let DebugConsoleOutput::Event = self.out {
+            self.flush_line();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Clone)]
+    struct SharedBuffer {
+        buf: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buf.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_debug_console_output() {
+        let out = SharedBuffer {
+            buf: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut debug_console =
+            DebugConsole::new(DebugConsoleOutput::Writer(Box::new(out.clone())));
+
+        let mut data = [0u8];
+        debug_console.read(0, 0, &mut data);
+        assert_eq!(data[0], DEBUG_CONSOLE_READBACK);
+
+        for byte in b"BdsDxe: starting\n" {
+            debug_console.write(0, 0, &[*byte]);
+        }
+        assert_eq!(out.buf.lock().unwrap().as_slice(), b"BdsDxe: starting\n");
+    }
+
+    #[test]
+    fn test_debug_console_event_lines() {
+        let mut debug_console = DebugConsole::new(DebugConsoleOutput::Event);
+
+        debug_console.write(0, 0, b"abc\r");
+        assert_eq!(debug_console.line, b"abc");
+        debug_console.write(0, 0, b"\n");
+        assert!(debug_console.line.is_empty());
+
+        debug_console.write(0, 0, &[b'a'; MAX_LINE_LEN + 1]);
+        assert_eq!(debug_console.line, b"a");
+    }
+}
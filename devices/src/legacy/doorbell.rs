@@ -0,0 +1,414 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Paravirtual doorbell device
+//!
+//! Minimal event channel between the guest and the host, made of a pair of
+//! eventfds per channel, without the queues of a virtio device. Writing the
+//! index of a channel to the ring register signals its ring eventfd, while
+//! the notify eventfd of the channel being signaled marks the channel as
+//! pending and raises the interrupt of the device, if enabled for the
+//! channel. The host supervisor can give the ring eventfd of a VM as the
+//! notify eventfd of another one, wiring a channel between the two.
+//!
+//! The notify eventfds are polled through the epoll file descriptor of the
+//! device, by the control loop of the VMM rather than by a thread of each
+//! device.
+//!
+//! | Offset | Register | Access | Description                               |
+//! | ------ | -------- | ------ | ----------------------------------------- |
+//! | 0x00   | MAGIC    | RO     | "CRDB" (0x42445243)                       |
+//! | 0x04   | VERSION  | RO     | Version of the device, 1                  |
+//! | 0x08   | CHANNELS | RO     | Number of channels                        |
+//! | 0x10   | RING     | WO     | Index of the channel to ring              |
+//! | 0x14   | PENDING  | RO     | Bitmap of the notified channels           |
+//! | 0x18   | ACK      | WO     | Bitmap of the pending channels to clear   |
+//! | 0x1c   | ENABLE   | RW     | Bitmap of the channels raising interrupts |
+
+use crate::{read_le_u32, write_le_u32};
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::{Arc, Barrier};
+use std::{io, result};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_device::interrupt::InterruptSourceGroup;
+use vm_device::BusDevice;
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionMapped,
+};
+use vmm_sys_util::eventfd::EventFd;
+
+/// Size of the register frame.
+pub const DOORBELL_SIZE: u64 = 0x1000;
+/// Maximum number of channels, one bit each in the bitmap registers.
+pub const DOORBELL_MAX_CHANNELS: usize = 32;
+
+const MAGIC: u64 = 0x00;
+const VERSION: u64 = 0x04;
+const CHANNELS: u64 = 0x08;
+const RING: u64 = 0x10;
+const PENDING: u64 = 0x14;
+const ACK: u64 = 0x18;
+const ENABLE: u64 = 0x1c;
+
+const MAGIC_VALUE: u32 = 0x4244_5243;
+const VERSION_VALUE: u32 = 1;
+
+/// Eventfds of a channel.
+pub struct DoorbellChannel {
+    /// Signaled when the guest rings the channel.
+    pub ring: EventFd,
+    /// Signaled to notify the guest.
+    pub notify: EventFd,
+}
+
+#[derive(Default)]
+struct Registers {
+    pending: u32,
+    enable: u32,
+    paused: bool,
+}
+
+#[derive(Versionize)]
+pub struct DoorbellState {
+    pending: u32,
+    enable: u32,
+}
+
+impl VersionMapped for DoorbellState {}
+
+/// A doorbell device with up to `DOORBELL_MAX_CHANNELS` channels.
+pub struct Doorbell {
+    id: String,
+    ring_evts: Vec<EventFd>,
+    notify_evts: Vec<EventFd>,
+    epoll_file: File,
+    regs: Registers,
+    interrupt: Arc<dyn InterruptSourceGroup>,
+    // Rings of channels which don't exist, reported with a decreasing
+    // frequency as the guest can keep writing them.
+    invalid_rings: u64,
+}
+
+impl Doorbell {
+    pub fn new(
+        id: String,
+        channels: Vec<DoorbellChannel>,
+        interrupt: Arc<dyn InterruptSourceGroup>,
+    ) -> io::Result<Self> {
+        if channels.is_empty() || channels.len() > DOORBELL_MAX_CHANNELS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid number of doorbell channels: {}", channels.len()),
+            ));
+        }
+
+        let (ring_evts, notify_evts): (Vec<EventFd>, Vec<EventFd>) = channels
+            .into_iter()
+            .map(|channel| (channel.ring, channel.notify))
+            .unzip();
+
+        // SAFETY: epoll_create1() returns a new file descriptor, owned by
+        // the file.
+        let epoll_file = unsafe { File::from_raw_fd(epoll::create(true)?) };
+        for (index, evt) in notify_evts.iter().enumerate() {
+            epoll::ctl(
+                epoll_file.as_raw_fd(),
+                epoll::ControlOptions::EPOLL_CTL_ADD,
+                evt.as_raw_fd(),
+                epoll::Event::new(epoll::Events::EPOLLIN, index as u64),
+            )?;
+        }
+
+        Ok(Doorbell {
+            id,
+            ring_evts,
+            notify_evts,
+            epoll_file,
+            regs: Registers::default(),
+            interrupt,
+            invalid_rings: 0,
+        })
+    }
+
+    /// Marks the notified channels as pending, once the epoll file
+    /// descriptor of the device is readable.
+    pub fn process_notifications(&mut self) {
+        let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); self.notify_evts.len()];
+        let num_events = match epoll::wait(self.epoll_file.as_raw_fd(), 0, &mut events[..]) {
+            Ok(num_events) => num_events,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::Interrupted {
+                    error!("Failed to wait for the doorbell events: {}", e);
+                }
+                return;
+            }
+        };
+
+        let mut pending = 0;
+        for event in events.iter().take(num_events) {
+            let index = event.data as usize;
+            match self.notify_evts[index].read() {
+                Ok(_) => pending |= 1 << index,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => error!("Failed to read the doorbell notify event: {}", e),
+            }
+        }
+        if pending != 0 {
+            self.update(|regs| regs.pending |= pending);
+        }
+    }
+
+    // Raise the interrupt when an enabled channel is pending. The interrupt
+    // is edge triggered, hence raised again for each new notification.
+    fn update<F: FnOnce(&mut Registers)>(&mut self, f: F) {
+        f(&mut self.regs);
+        if !self.regs.paused && self.regs.pending & self.regs.enable != 0 {
+            if let Err(e) = self.interrupt.trigger(0) {
+                error!("Failed to trigger the doorbell interrupt: {}", e);
+            }
+        }
+    }
+
+    fn channels(&self) -> u32 {
+        self.ring_evts.len() as u32
+    }
+
+    fn ring(&mut self, index: u32) {
+        match self.ring_evts.get(index as usize) {
+            Some(evt) => {
+                if let Err(e) = evt.write(1) {
+                    error!("Failed to ring doorbell channel {}: {}", index, e);
+                }
+            }
+            None => {
+                self.invalid_rings += 1;
+                if self.invalid_rings.is_power_of_two() {
+                    warn!(
+                        "Invalid doorbell channel: {} ({} invalid rings so far)",
+                        index, self.invalid_rings
+                    );
+                }
+            }
+        }
+    }
+
+    fn state(&self) -> DoorbellState {
+        DoorbellState {
+            pending: self.regs.pending,
+            enable: self.regs.enable,
+        }
+    }
+
+    fn set_state(&mut self, state: &DoorbellState) {
+        self.regs.pending = state.pending;
+        self.regs.enable = state.enable;
+    }
+}
+
+impl AsRawFd for Doorbell {
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll_file.as_raw_fd()
+    }
+}
+
+impl BusDevice for Doorbell {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        if data.len() != 4 {
+            warn!(
+                "Invalid doorbell read: offset {}, data length {}",
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        let value = match offset {
+            MAGIC => MAGIC_VALUE,
+            VERSION => VERSION_VALUE,
+            CHANNELS => self.channels(),
+            RING | ACK => 0,
+            PENDING => self.regs.pending,
+            ENABLE => self.regs.enable,
+            _ => {
+                warn!("Invalid doorbell read: offset {}", offset);
+                0
+            }
+        };
+        write_le_u32(data, value);
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        if data.len() != 4 {
+            warn!(
+                "Invalid doorbell write: offset {}, data length {}",
+                offset,
+                data.len()
+            );
+            return None;
+        }
+
+        let value = read_le_u32(data);
+        match offset {
+            RING => self.ring(value),
+            ACK => self.update(|regs| regs.pending &= !value),
+            ENABLE => {
+                let mask = u32::MAX >> (32 - self.channels());
+                self.update(|regs| regs.enable = value & mask)
+            }
+            _ => warn!("Invalid doorbell write: offset {}", offset),
+        }
+
+        None
+    }
+}
+
+impl Snapshottable for Doorbell {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.id, &self.state())
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> result::Result<(), MigratableError> {
+        self.set_state(&snapshot.to_versioned_state(&self.id)?);
+        Ok(())
+    }
+}
+
+impl Pausable for Doorbell {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.update(|regs| regs.paused = true);
+        Ok(())
+    }
+
+    // The notifications received while paused are delivered on resume.
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.update(|regs| regs.paused = false);
+        Ok(())
+    }
+}
+
+impl Transportable for Doorbell {}
+impl Migratable for Doorbell {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_device::interrupt::{InterruptIndex, InterruptSourceConfig};
+
+    struct TestInterrupt {
+        event_fd: EventFd,
+    }
+
+    impl InterruptSourceGroup for TestInterrupt {
+        fn trigger(&self, _index: InterruptIndex) -> result::Result<(), std::io::Error> {
+            self.event_fd.write(1)
+        }
+
+        fn update(
+            &self,
+            _index: InterruptIndex,
+            _config: InterruptSourceConfig,
+            _masked: bool,
+        ) -> result::Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn notifier(&self, _index: InterruptIndex) -> Option<EventFd> {
+            Some(self.event_fd.try_clone().unwrap())
+        }
+    }
+
+    fn read_reg(doorbell: &mut Doorbell, offset: u64) -> u32 {
+        let mut data = [0; 4];
+        doorbell.read(0, offset, &mut data);
+        read_le_u32(&data)
+    }
+
+    fn write_reg(doorbell: &mut Doorbell, offset: u64, value: u32) {
+        let mut data = [0; 4];
+        write_le_u32(&mut data, value);
+        doorbell.write(0, offset, &data);
+    }
+
+    fn notify(doorbell: &mut Doorbell, notify_evt: &EventFd) {
+        notify_evt.write(1).unwrap();
+        doorbell.process_notifications();
+    }
+
+    #[test]
+    fn test_doorbell() {
+        let intr_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let ring_evts: Vec<EventFd> = (0..2)
+            .map(|_| EventFd::new(libc::EFD_NONBLOCK).unwrap())
+            .collect();
+        let notify_evts: Vec<EventFd> = (0..2)
+            .map(|_| EventFd::new(libc::EFD_NONBLOCK).unwrap())
+            .collect();
+        let channels = ring_evts
+            .iter()
+            .zip(notify_evts.iter())
+            .map(|(ring, notify)| DoorbellChannel {
+                ring: ring.try_clone().unwrap(),
+                notify: notify.try_clone().unwrap(),
+            })
+            .collect();
+        let mut doorbell = Doorbell::new(
+            String::from("doorbell"),
+            channels,
+            Arc::new(TestInterrupt {
+                event_fd: intr_evt.try_clone().unwrap(),
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(read_reg(&mut doorbell, MAGIC), MAGIC_VALUE);
+        assert_eq!(read_reg(&mut doorbell, VERSION), VERSION_VALUE);
+        assert_eq!(read_reg(&mut doorbell, CHANNELS), 2);
+
+        // Ringing a channel signals its ring eventfd only.
+        write_reg(&mut doorbell, RING, 1);
+        assert_eq!(ring_evts[1].read().unwrap(), 1);
+        assert!(ring_evts[0].read().is_err());
+        write_reg(&mut doorbell, RING, 2);
+        assert!(ring_evts.iter().all(|evt| evt.read().is_err()));
+        write_reg(&mut doorbell, RING, u32::MAX);
+        assert_eq!(doorbell.invalid_rings, 2);
+
+        // A notification marks the channel as pending, without interrupt
+        // while the channel isn't enabled.
+        notify(&mut doorbell, &notify_evts[0]);
+        assert_eq!(read_reg(&mut doorbell, PENDING), 1);
+        assert!(intr_evt.read().is_err());
+        write_reg(&mut doorbell, ENABLE, u32::MAX);
+        assert_eq!(read_reg(&mut doorbell, ENABLE), 0b11);
+        assert_eq!(intr_evt.read().unwrap(), 1);
+
+        notify(&mut doorbell, &notify_evts[1]);
+        assert_eq!(read_reg(&mut doorbell, PENDING), 0b11);
+        assert_eq!(intr_evt.read().unwrap(), 1);
+
+        // Acknowledging a channel leaves the other one pending, raising the
+        // interrupt again.
+        write_reg(&mut doorbell, ACK, 1);
+        assert_eq!(read_reg(&mut doorbell, PENDING), 0b10);
+        assert_eq!(intr_evt.read().unwrap(), 1);
+        write_reg(&mut doorbell, ACK, 0b10);
+        assert_eq!(read_reg(&mut doorbell, PENDING), 0);
+        assert!(intr_evt.read().is_err());
+
+        // Notifications are delivered on resume.
+        doorbell.pause().unwrap();
+        notify(&mut doorbell, &notify_evts[1]);
+        assert_eq!(read_reg(&mut doorbell, PENDING), 0b10);
+        assert!(intr_evt.read().is_err());
+        doorbell.resume().unwrap();
+        assert_eq!(intr_evt.read().unwrap(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Device tree hotplug notifier
+//!
+//! Notifies a guest booted without ACPI of the devices hot-added to it,
+//! through device tree overlays (`.dtbo`) describing them. The overlays are
+//! queued by the VMM and raise the interrupt of the device, the guest reads
+//! the first one from the overlay window, applies it, and acknowledges it to
+//! move on to the next one. The interrupt is edge triggered, hence raised
+//! again after an acknowledgement while overlays are left.
+//!
+//! | Offset | Register | Access | Description                               |
+//! | ------ | -------- | ------ | ----------------------------------------- |
+//! | 0x000  | MAGIC    | RO     | "CRDH" (0x48445243)                       |
+//! | 0x004  | VERSION  | RO     | Version of the device, 1                  |
+//! | 0x008  | PENDING  | RO     | Number of overlays queued                 |
+//! | 0x00c  | SIZE     | RO     | Size of the first overlay, 0 if none      |
+//! | 0x010  | ACK      | WO     | Drops the first overlay                   |
+//! | 0x100  | OVERLAY  | RO     | Content of the first overlay              |
+
+use crate::write_le_u32;
+use std::collections::VecDeque;
+use std::sync::{Arc, Barrier};
+use std::{io, result};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_device::interrupt::InterruptSourceGroup;
+use vm_device::BusDevice;
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionMapped,
+};
+
+/// Size of the register frame.
+pub const DT_HOTPLUG_SIZE: u64 = 0x1000;
+
+const MAGIC: u64 = 0x00;
+const VERSION: u64 = 0x04;
+const PENDING: u64 = 0x08;
+const SIZE: u64 = 0x0c;
+const ACK: u64 = 0x10;
+const OVERLAY: u64 = 0x100;
+
+/// Largest overlay fitting in the overlay window.
+pub const DT_HOTPLUG_MAX_OVERLAY_SIZE: usize = (DT_HOTPLUG_SIZE - OVERLAY) as usize;
+
+const MAGIC_VALUE: u32 = 0x4844_5243;
+const VERSION_VALUE: u32 = 1;
+
+#[derive(Versionize)]
+pub struct DtHotplugState {
+    overlays: Vec<Vec<u8>>,
+}
+
+impl VersionMapped for DtHotplugState {}
+
+pub struct DtHotplug {
+    id: String,
+    overlays: VecDeque<Vec<u8>>,
+    interrupt: Arc<dyn InterruptSourceGroup>,
+    paused: bool,
+}
+
+impl DtHotplug {
+    pub fn new(id: String, interrupt: Arc<dyn InterruptSourceGroup>) -> Self {
+        DtHotplug {
+            id,
+            overlays: VecDeque::new(),
+            interrupt,
+            paused: false,
+        }
+    }
+
+    /// Queues an overlay for the guest to apply, raising the interrupt.
+    pub fn notify(&mut self, overlay: Vec<u8>) -> io::Result<()> {
+        if overlay.is_empty() || overlay.len() > DT_HOTPLUG_MAX_OVERLAY_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid device tree overlay size: {}", overlay.len()),
+            ));
+        }
+
+        self.overlays.push_back(overlay);
+        self.update();
+        Ok(())
+    }
+
+    fn update(&self) {
+        if !self.paused && !self.overlays.is_empty() {
+            if let Err(e) = self.interrupt.trigger(0) {
+                error!("Failed to trigger the device tree hotplug interrupt: {}", e);
+            }
+        }
+    }
+
+    fn read_overlay(&self, offset: usize, data: &mut [u8]) {
+        data.fill(0);
+        if let Some(overlay) = self.overlays.front() {
+            if offset < overlay.len() {
+                let len = data.len().min(overlay.len() - offset);
+                data[..len].copy_from_slice(&overlay[offset..offset + len]);
+            }
+        }
+    }
+
+    fn state(&self) -> DtHotplugState {
+        DtHotplugState {
+            overlays: self.overlays.iter().cloned().collect(),
+        }
+    }
+
+    fn set_state(&mut self, state: &DtHotplugState) {
+        self.overlays = state.overlays.iter().cloned().collect();
+    }
+}
+
+impl BusDevice for DtHotplug {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        if offset >= OVERLAY {
+            self.read_overlay((offset - OVERLAY) as usize, data);
+            return;
+        }
+
+        if data.len() != 4 {
+            warn!(
+                "Invalid device tree hotplug read: offset {}, data length {}",
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        let value = match offset {
+            MAGIC => MAGIC_VALUE,
+            VERSION => VERSION_VALUE,
+            PENDING => self.overlays.len() as u32,
+            SIZE => self.overlays.front().map_or(0, |o| o.len() as u32),
+            ACK => 0,
+            _ => {
+                warn!("Invalid device tree hotplug read: offset {}", offset);
+                0
+            }
+        };
+        write_le_u32(data, value);
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        if data.len() != 4 {
+            warn!(
+                "Invalid device tree hotplug write: offset {}, data length {}",
+                offset,
+                data.len()
+            );
+            return None;
+        }
+
+        match offset {
+            ACK => {
+                if self.overlays.pop_front().is_some() {
+                    self.update();
+                }
+            }
+            _ => warn!("Invalid device tree hotplug write: offset {}", offset),
+        }
+
+        None
+    }
+}
+
+impl Snapshottable for DtHotplug {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.id, &self.state())
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> result::Result<(), MigratableError> {
+        self.set_state(&snapshot.to_versioned_state(&self.id)?);
+        Ok(())
+    }
+}
+
+impl Pausable for DtHotplug {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.paused = true;
+        Ok(())
+    }
+
+    // The overlays left pending, or queued while paused, are notified again
+    // on resume.
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.paused = false;
+        self.update();
+        Ok(())
+    }
+}
+
+impl Transportable for DtHotplug {}
+impl Migratable for DtHotplug {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_le_u32;
+    use vm_device::interrupt::{InterruptIndex, InterruptSourceConfig};
+    use vmm_sys_util::eventfd::EventFd;
+
+    struct TestInterrupt {
+        event_fd: EventFd,
+    }
+
+    impl InterruptSourceGroup for TestInterrupt {
+        fn trigger(&self, _index: InterruptIndex) -> result::Result<(), std::io::Error> {
+            self.event_fd.write(1)
+        }
+
+        fn update(
+            &self,
+            _index: InterruptIndex,
+            _config: InterruptSourceConfig,
+            _masked: bool,
+        ) -> result::Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn notifier(&self, _index: InterruptIndex) -> Option<EventFd> {
+            Some(self.event_fd.try_clone().unwrap())
+        }
+    }
+
+    fn read_reg(device: &mut DtHotplug, offset: u64) -> u32 {
+        let mut data = [0; 4];
+        device.read(0, offset, &mut data);
+        read_le_u32(&data)
+    }
+
+    #[test]
+    fn test_dt_hotplug() {
+        let intr_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut device = DtHotplug::new(
+            String::from("dt-hotplug"),
+            Arc::new(TestInterrupt {
+                event_fd: intr_evt.try_clone().unwrap(),
+            }),
+        );
+
+        assert_eq!(read_reg(&mut device, MAGIC), MAGIC_VALUE);
+        assert_eq!(read_reg(&mut device, VERSION), VERSION_VALUE);
+        assert_eq!(read_reg(&mut device, PENDING), 0);
+        assert_eq!(read_reg(&mut device, SIZE), 0);
+
+        assert!(device.notify(Vec::new()).is_err());
+        assert!(device
+            .notify(vec![0; DT_HOTPLUG_MAX_OVERLAY_SIZE + 1])
+            .is_err());
+        assert!(intr_evt.read().is_err());
+
+        device.notify(vec![1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(intr_evt.read().unwrap(), 1);
+        device.notify(vec![6; 8]).unwrap();
+        assert_eq!(read_reg(&mut device, PENDING), 2);
+        assert_eq!(read_reg(&mut device, SIZE), 5);
+
+        // The overlay is read at any size, padded with zeroes.
+        let mut data = [0xff; 8];
+        device.read(0, OVERLAY + 1, &mut data);
+        assert_eq!(data, [2, 3, 4, 5, 0, 0, 0, 0]);
+
+        // Acknowledging the first overlay moves on to the next one, raising
+        // the interrupt again.
+        intr_evt.read().unwrap();
+        device.write(0, ACK, &[0; 4]);
+        assert_eq!(read_reg(&mut device, PENDING), 1);
+        assert_eq!(read_reg(&mut device, SIZE), 8);
+        assert_eq!(intr_evt.read().unwrap(), 1);
+        device.write(0, ACK, &[0; 4]);
+        assert_eq!(read_reg(&mut device, PENDING), 0);
+        assert!(intr_evt.read().is_err());
+
+        // Overlays queued while paused are notified on resume.
+        device.pause().unwrap();
+        device.notify(vec![7; 4]).unwrap();
+        assert!(intr_evt.read().is_err());
+        device.resume().unwrap();
+        assert_eq!(intr_evt.read().unwrap(), 1);
+    }
+}
@@ -6,25 +6,37 @@
 // found in the LICENSE-BSD-3-Clause file.
 
 mod cmos;
+mod debug_console;
 #[cfg(target_arch = "x86_64")]
 mod debug_port;
+mod doorbell;
+mod dt_hotplug;
 #[cfg(feature = "fwdebug")]
 mod fwdebug;
 #[cfg(target_arch = "aarch64")]
 mod gpio_pl061;
 mod i8042;
+mod partition_services;
+mod pvpanic;
 #[cfg(target_arch = "aarch64")]
 mod rtc_pl031;
+#[cfg(target_arch = "aarch64")]
+mod sbsa_gwdt;
 mod serial;
 #[cfg(target_arch = "aarch64")]
 mod uart_pl011;
 
 pub use self::cmos::Cmos;
+pub use self::debug_console::{DebugConsole, DebugConsoleOutput};
 #[cfg(target_arch = "x86_64")]
 pub use self::debug_port::DebugPort;
+pub use self::doorbell::{Doorbell, DoorbellChannel, DOORBELL_MAX_CHANNELS, DOORBELL_SIZE};
+pub use self::dt_hotplug::{DtHotplug, DT_HOTPLUG_MAX_OVERLAY_SIZE, DT_HOTPLUG_SIZE};
 #[cfg(feature = "fwdebug")]
 pub use self::fwdebug::FwDebugDevice;
 pub use self::i8042::I8042Device;
+pub use self::partition_services::{PartitionServices, PARTITION_SERVICES_SIZE};
+pub use self::pvpanic::{PvPanic, PVPANIC_CRASH_LOADED, PVPANIC_PANICKED};
 pub use self::serial::Serial;
 
 #[cfg(target_arch = "aarch64")]
@@ -34,4 +46,6 @@ pub use self::gpio_pl061::Gpio;
 #[cfg(target_arch = "aarch64")]
 pub use self::rtc_pl031::Rtc;
 #[cfg(target_arch = "aarch64")]
+pub use self::sbsa_gwdt::{SbsaWatchdog, SBSA_GWDT_FRAME_SIZE};
+#[cfg(target_arch = "aarch64")]
 pub use self::uart_pl011::Pl011;
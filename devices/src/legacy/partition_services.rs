@@ -0,0 +1,239 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Partition services mailbox
+//!
+//! Lets the guest request services from the VMM through a small register
+//! frame, handled synchronously when the vCPU exits on the access. The guest
+//! places the arguments of a command in the DATA registers, writes the
+//! command to the COMMAND register, and reads back its status and results.
+//! The ABI is described for the guest by
+//! `resources/include/craton_partition_services.h`.
+//!
+//! | Offset | Register | Access | Description                               |
+//! | ------ | -------- | ------ | ----------------------------------------- |
+//! | 0x00   | MAGIC    | RO     | "CRPS" (0x53505243)                       |
+//! | 0x04   | VERSION  | RO     | Version of the ABI, 1                     |
+//! | 0x08   | COMMAND  | WO     | Command to run                            |
+//! | 0x0c   | STATUS   | RO     | Status of the last command                |
+//! | 0x10   | DATA0    | RW     | Arguments and results of the commands     |
+//! | 0x14   | DATA1    | RW     |                                           |
+//! | 0x18   | DATA2    | RW     |                                           |
+//! | 0x1c   | DATA3    | RW     |                                           |
+//!
+//! | Command | Name              | Arguments       | Results                   |
+//! | ------- | ----------------- | --------------- | ------------------------- |
+//! | 1       | GET_PARTITION_ID  |                 | DATA0-1: partition id     |
+//! | 2       | REQUEST_RESTART   |                 |                           |
+//! | 3       | SIGNAL_READY      | DATA0: status   |                           |
+//! | 4       | GET_WALL_CLOCK    |                 | DATA0-1: seconds since    |
+//! |         |                   |                 | the epoch, DATA2: nanos   |
+
+use std::convert::TryInto;
+use std::fmt;
+use std::sync::{Arc, Barrier};
+use std::time::{SystemTime, UNIX_EPOCH};
+use vm_device::BusDevice;
+use vmm_sys_util::eventfd::EventFd;
+
+/// Size of the register frame.
+pub const PARTITION_SERVICES_SIZE: u64 = 0x20;
+
+const MAGIC: u64 = 0x00;
+const VERSION: u64 = 0x04;
+const COMMAND: u64 = 0x08;
+const STATUS: u64 = 0x0c;
+const DATA: u64 = 0x10;
+const DATA_LAST: u64 = 0x1c;
+
+const MAGIC_VALUE: u32 = 0x5350_5243;
+const VERSION_VALUE: u32 = 1;
+
+const CMD_GET_PARTITION_ID: u32 = 1;
+const CMD_REQUEST_RESTART: u32 = 2;
+const CMD_SIGNAL_READY: u32 = 3;
+const CMD_GET_WALL_CLOCK: u32 = 4;
+
+const STATUS_SUCCESS: u32 = 0;
+const STATUS_UNKNOWN_COMMAND: u32 = 1;
+const STATUS_FAILED: u32 = 2;
+
+pub struct PartitionServices {
+    partition_id: u64,
+    reset_evt: EventFd,
+    status: u32,
+    data: [u32; 4],
+    // Whether the guest already misused the mailbox or signaled readiness,
+    // the next occurrences being logged at the debug level so that the
+    // guest can't flood the logs.
+    warned: bool,
+    ready: bool,
+}
+
+impl PartitionServices {
+    /// Mailbox reporting `partition_id` to the guest, and signaling
+    /// `reset_evt` when the guest requests a restart.
+    pub fn new(partition_id: u64, reset_evt: EventFd) -> Self {
+        PartitionServices {
+            partition_id,
+            reset_evt,
+            status: STATUS_SUCCESS,
+            data: [0; 4],
+            warned: false,
+            ready: false,
+        }
+    }
+
+    fn warn(&mut self, args: fmt::Arguments) {
+        if self.warned {
+            debug!("{}", args);
+        } else {
+            self.warned = true;
+            warn!("{} (further ones logged at debug level)", args);
+        }
+    }
+
+    fn set_u64(&mut self, index: usize, value: u64) {
+        self.data[index] = value as u32;
+        self.data[index + 1] = (value >> 32) as u32;
+    }
+
+    fn run_command(&mut self, command: u32) -> u32 {
+        match command {
+            CMD_GET_PARTITION_ID => {
+                self.set_u64(0, self.partition_id);
+                STATUS_SUCCESS
+            }
+            CMD_REQUEST_RESTART => {
+                debug!("Restart requested through the partition services");
+                match self.reset_evt.write(1) {
+                    Ok(()) => STATUS_SUCCESS,
+                    Err(e) => {
+                        error!("Error signaling the restart request: {}", e);
+                        STATUS_FAILED
+                    }
+                }
+            }
+            CMD_SIGNAL_READY => {
+                if self.ready {
+                    debug!("Guest signaled readiness with status {}", self.data[0]);
+                } else {
+                    self.ready = true;
+                    info!("Guest signaled readiness with status {}", self.data[0]);
+                }
+                event!("vm", "guest-ready", "status", self.data[0].to_string());
+                STATUS_SUCCESS
+            }
+            CMD_GET_WALL_CLOCK => match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(now) => {
+                    self.set_u64(0, now.as_secs());
+                    self.data[2] = now.subsec_nanos();
+                    STATUS_SUCCESS
+                }
+                Err(e) => {
+                    error!("Error reading the wall clock: {}", e);
+                    STATUS_FAILED
+                }
+            },
+            _ => {
+                self.warn(format_args!(
+                    "Unknown partition services command: {}",
+                    command
+                ));
+                STATUS_UNKNOWN_COMMAND
+            }
+        }
+    }
+}
+
+impl BusDevice for PartitionServices {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        if data.len() != 4 {
+            self.warn(format_args!(
+                "Invalid partition services read: offset {}, len {}",
+                offset,
+                data.len()
+            ));
+            return;
+        }
+
+        let value = match offset {
+            MAGIC => MAGIC_VALUE,
+            VERSION => VERSION_VALUE,
+            STATUS => self.status,
+            DATA..=DATA_LAST if offset % 4 == 0 => self.data[((offset - DATA) / 4) as usize],
+            _ => 0,
+        };
+        data.copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        let value = match data.try_into() {
+            Ok(bytes) => u32::from_le_bytes(bytes),
+            Err(_) => {
+                self.warn(format_args!(
+                    "Invalid partition services write: offset {}, len {}",
+                    offset,
+                    data.len()
+                ));
+                return None;
+            }
+        };
+
+        match offset {
+            COMMAND => self.status = self.run_command(value),
+            DATA..=DATA_LAST if offset % 4 == 0 => {
+                self.data[((offset - DATA) / 4) as usize] = value
+            }
+            _ => self.warn(format_args!(
+                "Invalid partition services write: offset {}",
+                offset
+            )),
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(device: &mut PartitionServices, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        device.read(0, offset, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    #[test]
+    fn test_partition_services() {
+        let reset_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut device = PartitionServices::new(0x1_0000_002a, reset_evt.try_clone().unwrap());
+        assert_eq!(read(&mut device, MAGIC), MAGIC_VALUE);
+        assert_eq!(read(&mut device, VERSION), 1);
+
+        device.write(0, COMMAND, &CMD_GET_PARTITION_ID.to_le_bytes());
+        assert_eq!(read(&mut device, STATUS), STATUS_SUCCESS);
+        assert_eq!(read(&mut device, DATA), 0x2a);
+        assert_eq!(read(&mut device, DATA + 4), 1);
+
+        device.write(0, COMMAND, &CMD_GET_WALL_CLOCK.to_le_bytes());
+        assert_eq!(read(&mut device, STATUS), STATUS_SUCCESS);
+        assert!(read(&mut device, DATA) != 0);
+        assert!(read(&mut device, DATA + 8) < 1_000_000_000);
+
+        assert!(reset_evt.read().is_err());
+        device.write(0, COMMAND, &CMD_REQUEST_RESTART.to_le_bytes());
+        assert_eq!(reset_evt.read().unwrap(), 1);
+
+        device.write(0, COMMAND, &0x42u32.to_le_bytes());
+        assert_eq!(read(&mut device, STATUS), STATUS_UNKNOWN_COMMAND);
+        assert!(device.warned);
+
+        device.write(0, COMMAND, &CMD_SIGNAL_READY.to_le_bytes());
+        assert_eq!(read(&mut device, STATUS), STATUS_SUCCESS);
+        assert!(device.ready);
+    }
+}
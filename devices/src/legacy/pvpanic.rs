@@ -0,0 +1,101 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::sync::{Arc, Barrier};
+use vm_device::BusDevice;
+use vmm_sys_util::eventfd::EventFd;
+
+/// The guest kernel panicked.
+pub const PVPANIC_PANICKED: u8 = 1 << 0;
+/// The guest kernel panicked and is about to boot a crash kernel.
+pub const PVPANIC_CRASH_LOADED: u8 = 1 << 1;
+
+const PVPANIC_EVENTS: u8 = PVPANIC_PANICKED | PVPANIC_CRASH_LOADED;
+
+/// Guest panic notification device, compatible with the pvpanic device from
+/// QEMU (see docs/specs/pvpanic.txt in the QEMU code). It is made of a
+/// single byte register, reading the events supported by the device and
+/// taking the events the guest reports.
+pub struct PvPanic {
+    panic_evt: EventFd,
+    events: u8,
+}
+
+impl PvPanic {
+    pub fn new(panic_evt: EventFd) -> Self {
+        Self {
+            panic_evt,
+            events: 0,
+        }
+    }
+
+    /// Events reported by the guest since the device was created or reset.
+    pub fn events(&self) -> u8 {
+        self.events
+    }
+
+    pub fn reset(&mut self) {
+        self.events = 0;
+    }
+}
+
+impl BusDevice for PvPanic {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        if offset == 0 && data.len() == 1 {
+            data[0] = PVPANIC_EVENTS;
+        } else {
+            warn!(
+                "Invalid pvpanic read: offset {}, len {}",
+                offset,
+                data.len()
+            );
+        }
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        if offset != 0 || data.len() != 1 {
+            warn!(
+                "Invalid pvpanic write: offset {}, len {}",
+                offset,
+                data.len()
+            );
+            return None;
+        }
+
+        let events = data[0] & PVPANIC_EVENTS;
+        if events != 0 {
+            self.events |= events;
+            if let Err(e) = self.panic_evt.write(1) {
+                error!("Error signaling guest panic: {}", e);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pvpanic_events() {
+        let panic_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut pvpanic = PvPanic::new(panic_evt.try_clone().unwrap());
+
+        let mut data = [0u8];
+        pvpanic.read(0, 0, &mut data);
+        assert_eq!(data[0], PVPANIC_PANICKED | PVPANIC_CRASH_LOADED);
+
+        // Unknown events are ignored.
+        pvpanic.write(0, 0, &[1 << 4]);
+        assert!(panic_evt.read().is_err());
+        assert_eq!(pvpanic.events(), 0);
+
+        pvpanic.write(0, 0, &[PVPANIC_CRASH_LOADED]);
+        assert_eq!(panic_evt.read().unwrap(), 1);
+        assert_eq!(pvpanic.events(), PVPANIC_CRASH_LOADED);
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! ARM SBSA Generic Watchdog
+//!
+//! The watchdog is made of a control frame followed by a refresh frame. Once
+//! enabled, it signals its first stage (WS0) through an interrupt when the
+//! compare value is reached without being refreshed, and its second stage
+//! (WS1) through the watchdog event when the compare value is reached again,
+//! leaving the VMM to act upon the guest hang.
+//!
+//! The compare value is expressed in ticks of the system counter, which is
+//! approximated from the time elapsed since the VM was started.
+
+use crate::{read_le_u32, write_le_u32};
+use std::sync::{Arc, Barrier, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{io, result};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_device::interrupt::InterruptSourceGroup;
+use vm_device::BusDevice;
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionMapped,
+};
+use vmm_sys_util::eventfd::EventFd;
+
+/// Size of the control and refresh frames.
+pub const SBSA_GWDT_FRAME_SIZE: u64 = 0x1000;
+
+const WCS: u64 = 0x000; // Watchdog Control and Status Register
+const WOR: u64 = 0x008; // Watchdog Offset Register
+const WCV_LOW: u64 = 0x010; // Watchdog Compare Value Register
+const WCV_HIGH: u64 = 0x014;
+const WRR: u64 = SBSA_GWDT_FRAME_SIZE; // Watchdog Refresh Register
+const W_IIDR: u64 = 0xfcc; // Watchdog Interface Identification Register
+const W_IIDR_REFRESH: u64 = SBSA_GWDT_FRAME_SIZE + W_IIDR;
+
+const WCS_EN: u32 = 1 << 0;
+const WCS_WS0: u32 = 1 << 1;
+const WCS_WS1: u32 = 1 << 2;
+
+// Architecture version 0, implemented by ARM.
+const W_IIDR_VALUE: u32 = 0x43b;
+
+// Frequency of the system counter, which the guest reads from CNTFRQ_EL0 as
+// well.
+fn counter_frequency() -> u64 {
+    let frequency: u64;
+    // SAFETY: CNTFRQ_EL0 is readable from EL0 without side effects.
+    unsafe { std::arch::asm!("mrs {}, cntfrq_el0", out(reg) frequency) };
+    frequency
+}
+
+#[derive(Clone, Copy)]
+struct Clock {
+    start: Instant,
+    frequency: u64,
+}
+
+impl Clock {
+    fn counter(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.start).as_nanos() * self.frequency as u128
+            / 1_000_000_000) as u64
+    }
+
+    fn instant(&self, counter: u64) -> Instant {
+        let nanos = counter as u128 * 1_000_000_000 / self.frequency as u128;
+        self.start + Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+}
+
+#[derive(Default)]
+struct Registers {
+    wcs: u32,
+    wor: u32,
+    wcv: u64,
+    paused: bool,
+    exit: bool,
+}
+
+impl Registers {
+    // Explicit refresh, restarting the watchdog from its first stage.
+    fn refresh(&mut self, clock: &Clock) {
+        self.wcs &= !(WCS_WS0 | WCS_WS1);
+        self.wcv = clock.counter(Instant::now()) + self.wor as u64;
+    }
+}
+
+type Shared = Arc<(Mutex<Registers>, Condvar)>;
+
+#[derive(Versionize)]
+pub struct SbsaWatchdogState {
+    wcs: u32,
+    wor: u32,
+}
+
+impl VersionMapped for SbsaWatchdogState {}
+
+/// A watchdog following the SBSA Generic Watchdog specification.
+pub struct SbsaWatchdog {
+    id: String,
+    clock: Clock,
+    shared: Shared,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SbsaWatchdog {
+    /// Creates the watchdog, whose system counter started with the VM at the
+    /// given instant.
+    pub fn new(
+        id: String,
+        interrupt: Arc<dyn InterruptSourceGroup>,
+        watchdog_evt: EventFd,
+        start: Instant,
+    ) -> io::Result<Self> {
+        let clock = Clock {
+            start,
+            frequency: counter_frequency(),
+        };
+        let shared: Shared = Arc::new((Mutex::new(Registers::default()), Condvar::new()));
+
+        let thread_shared = shared.clone();
+        let thread = thread::Builder::new()
+            .name(id.clone())
+            .spawn(move || Self::run(thread_shared, clock, interrupt, watchdog_evt))?;
+
+        Ok(SbsaWatchdog {
+            id,
+            clock,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    // Waits for the compare value to be reached, signaling the stages of the
+    // watchdog.
+    fn run(
+        shared: Shared,
+        clock: Clock,
+        interrupt: Arc<dyn InterruptSourceGroup>,
+        watchdog_evt: EventFd,
+    ) {
+        let (lock, cvar) = &*shared;
+        let mut regs = lock.lock().unwrap();
+        loop {
+            if regs.exit {
+                break;
+            }
+            if regs.paused || regs.wcs & WCS_EN == 0 || regs.wcs & WCS_WS1 != 0 {
+                regs = cvar.wait(regs).unwrap();
+                continue;
+            }
+
+            let now = Instant::now();
+            let deadline = clock.instant(regs.wcv);
+            if now < deadline {
+                regs = cvar.wait_timeout(regs, deadline - now).unwrap().0;
+                continue;
+            }
+
+            if regs.wcs & WCS_WS0 == 0 {
+                regs.wcs |= WCS_WS0;
+                regs.wcv = clock.counter(now) + regs.wor as u64;
+                if let Err(e) = interrupt.trigger(0) {
+                    error!("Failed to trigger the watchdog interrupt: {}", e);
+                }
+            } else {
+                regs.wcs |= WCS_WS1;
+                error!("Watchdog triggered: not refreshed by the guest");
+                if let Err(e) = watchdog_evt.write(1) {
+                    error!("Error signaling the watchdog expiry: {}", e);
+                }
+            }
+        }
+    }
+
+    fn update<F: FnOnce(&mut Registers, &Clock)>(&self, f: F) {
+        let (lock, cvar) = &*self.shared;
+        f(&mut lock.lock().unwrap(), &self.clock);
+        cvar.notify_one();
+    }
+
+    fn state(&self) -> SbsaWatchdogState {
+        let regs = self.shared.0.lock().unwrap();
+        SbsaWatchdogState {
+            wcs: regs.wcs,
+            wor: regs.wor,
+        }
+    }
+
+    fn set_state(&mut self, state: &SbsaWatchdogState) {
+        // The compare value is set when resuming, so that the time the VM
+        // was stopped isn't accounted for.
+        self.update(|regs, _| {
+            regs.wcs = state.wcs;
+            regs.wor = state.wor;
+        });
+    }
+}
+
+impl Drop for SbsaWatchdog {
+    fn drop(&mut self) {
+        self.update(|regs, _| regs.exit = true);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Error joining the watchdog thread");
+            }
+        }
+    }
+}
+
+impl BusDevice for SbsaWatchdog {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        if data.len() != 4 {
+            warn!(
+                "Invalid SBSA watchdog read: offset {}, data length {}",
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        let regs = self.shared.0.lock().unwrap();
+        let value = match offset {
+            WCS => regs.wcs,
+            WOR => regs.wor,
+            WCV_LOW => regs.wcv as u32,
+            WCV_HIGH => (regs.wcv >> 32) as u32,
+            WRR => 0,
+            W_IIDR | W_IIDR_REFRESH => W_IIDR_VALUE,
+            _ => {
+                warn!("Invalid SBSA watchdog read: offset {}", offset);
+                0
+            }
+        };
+        write_le_u32(data, value);
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        if data.len() != 4 {
+            warn!(
+                "Invalid SBSA watchdog write: offset {}, data length {}",
+                offset,
+                data.len()
+            );
+            return None;
+        }
+
+        let value = read_le_u32(data);
+        match offset {
+            WCS => self.update(|regs, clock| {
+                regs.wcs = (regs.wcs & !WCS_EN) | (value & WCS_EN);
+                regs.refresh(clock);
+            }),
+            WOR => self.update(|regs, clock| {
+                regs.wor = value;
+                regs.refresh(clock);
+            }),
+            WCV_LOW => self.update(|regs, _| {
+                regs.wcv = (regs.wcv & !0xffff_ffff) | value as u64;
+            }),
+            WCV_HIGH => self.update(|regs, _| {
+                regs.wcv = (regs.wcv & 0xffff_ffff) | (value as u64) << 32;
+            }),
+            WRR => self.update(|regs, clock| regs.refresh(clock)),
+            _ => warn!("Invalid SBSA watchdog write: offset {}", offset),
+        }
+
+        None
+    }
+}
+
+impl Snapshottable for SbsaWatchdog {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.id, &self.state())
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> result::Result<(), MigratableError> {
+        self.set_state(&snapshot.to_versioned_state(&self.id)?);
+        Ok(())
+    }
+}
+
+impl Pausable for SbsaWatchdog {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.update(|regs, _| regs.paused = true);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.update(|regs, clock| {
+            regs.paused = false;
+            if regs.wcs & WCS_EN != 0 {
+                regs.refresh(clock);
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Transportable for SbsaWatchdog {}
+impl Migratable for SbsaWatchdog {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_device::interrupt::{InterruptIndex, InterruptSourceConfig};
+
+    struct TestInterrupt {
+        event_fd: EventFd,
+    }
+
+    impl InterruptSourceGroup for TestInterrupt {
+        fn trigger(&self, _index: InterruptIndex) -> result::Result<(), std::io::Error> {
+            self.event_fd.write(1)
+        }
+
+        fn update(
+            &self,
+            _index: InterruptIndex,
+            _config: InterruptSourceConfig,
+            _masked: bool,
+        ) -> result::Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn notifier(&self, _index: InterruptIndex) -> Option<EventFd> {
+            Some(self.event_fd.try_clone().unwrap())
+        }
+    }
+
+    fn read_reg(watchdog: &mut SbsaWatchdog, offset: u64) -> u32 {
+        let mut data = [0; 4];
+        watchdog.read(0, offset, &mut data);
+        read_le_u32(&data)
+    }
+
+    fn write_reg(watchdog: &mut SbsaWatchdog, offset: u64, value: u32) {
+        let mut data = [0; 4];
+        write_le_u32(&mut data, value);
+        watchdog.write(0, offset, &data);
+    }
+
+    #[test]
+    fn test_sbsa_watchdog_expiry() {
+        let intr_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let watchdog_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut watchdog = SbsaWatchdog::new(
+            String::from("watchdog"),
+            Arc::new(TestInterrupt {
+                event_fd: intr_evt.try_clone().unwrap(),
+            }),
+            watchdog_evt.try_clone().unwrap(),
+            Instant::now(),
+        )
+        .unwrap();
+
+        assert_eq!(read_reg(&mut watchdog, W_IIDR), W_IIDR_VALUE);
+        assert_eq!(read_reg(&mut watchdog, W_IIDR_REFRESH), W_IIDR_VALUE);
+
+        // Stages of 10ms each.
+        let wor = (counter_frequency() / 100) as u32;
+        write_reg(&mut watchdog, WOR, wor);
+        assert_eq!(read_reg(&mut watchdog, WOR), wor);
+        write_reg(&mut watchdog, WCS, WCS_EN);
+        assert_eq!(read_reg(&mut watchdog, WCS), WCS_EN);
+        let wcv = (read_reg(&mut watchdog, WCV_HIGH) as u64) << 32
+            | read_reg(&mut watchdog, WCV_LOW) as u64;
+        assert!(wcv >= wor as u64);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while watchdog_evt.read().is_err() {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(intr_evt.read().unwrap(), 1);
+        assert_eq!(read_reg(&mut watchdog, WCS), WCS_EN | WCS_WS0 | WCS_WS1);
+
+        // A refresh restarts the watchdog from its first stage, here with an
+        // offset long enough not to expire during the test.
+        write_reg(&mut watchdog, WOR, u32::MAX);
+        assert_eq!(read_reg(&mut watchdog, WCS), WCS_EN);
+        write_reg(&mut watchdog, WRR, 0);
+        assert_eq!(read_reg(&mut watchdog, WCS), WCS_EN);
+
+        write_reg(&mut watchdog, WCS, 0);
+        assert_eq!(read_reg(&mut watchdog, WCS), 0);
+    }
+}
@@ -128,6 +128,20 @@ impl Serial {
         self.out = Some(out);
     }
 
+    /// Brings the registers back to their power-on values, dropping the input
+    /// the guest didn't read, the output being kept.
+    pub fn reset(&mut self) {
+        self.interrupt_enable = 0;
+        self.interrupt_identification = DEFAULT_INTERRUPT_IDENTIFICATION;
+        self.line_control = DEFAULT_LINE_CONTROL;
+        self.line_status = DEFAULT_LINE_STATUS;
+        self.modem_control = DEFAULT_MODEM_CONTROL;
+        self.modem_status = DEFAULT_MODEM_STATUS;
+        self.scratch = 0;
+        self.baud_divisor = DEFAULT_BAUD_DIVISOR;
+        self.in_buffer.clear();
+    }
+
     /// Queues raw bytes for the guest to read and signals the interrupt if the line status would
     /// change.
     pub fn queue_input_bytes(&mut self, c: &[u8]) -> Result<()> {
@@ -517,4 +531,27 @@ mod tests {
         serial.read(0, SCR as u64, &mut data[..]);
         assert_eq!(data[0], 0x12);
     }
+
+    #[test]
+    fn serial_reset() {
+        let intr_evt = EventFd::new(0).unwrap();
+        let mut serial = Serial::new_sink(
+            String::from(SERIAL_NAME),
+            Arc::new(TestInterrupt::new(intr_evt.try_clone().unwrap())),
+        );
+
+        serial.write(0, SCR as u64, &[0x12]);
+        serial.write(0, MCR as u64, &[MCR_LOOP_BIT]);
+        serial.write(0, DATA as u64, &[b'a']);
+
+        serial.reset();
+
+        let mut data = [0u8];
+        serial.read(0, SCR as u64, &mut data[..]);
+        assert_eq!(data[0], 0);
+        serial.read(0, MCR as u64, &mut data[..]);
+        assert_eq!(data[0], DEFAULT_MODEM_CONTROL);
+        serial.read(0, LSR as u64, &mut data[..]);
+        assert_eq!(data[0] & LSR_DATA_BIT, 0);
+    }
 }
@@ -10,6 +10,8 @@
 #[macro_use]
 extern crate bitflags;
 #[macro_use]
+extern crate event_monitor;
+#[macro_use]
 extern crate log;
 
 pub mod acpi;
@@ -29,6 +31,7 @@ bitflags! {
         const MEMORY_DEVICES_CHANGED = 0b10;
         const PCI_DEVICES_CHANGED = 0b100;
         const POWER_BUTTON_CHANGED = 0b1000;
+        const MMIO_DEVICES_CHANGED = 0b10000;
     }
 }
 
@@ -7,11 +7,48 @@ use serde::Serialize;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
+// Source of the events streamed to the subscribers, the ones of the VM
+// lifecycle.
+const STREAM_SOURCE: &str = "vm";
+
+// Size of the events a subscriber can lag behind before being disconnected.
+const MAX_PENDING: usize = 64 << 10;
+
 static mut MONITOR: Option<(File, Instant)> = None;
+static mut STREAM: Option<(Mutex<Vec<Subscriber>>, Instant)> = None;
+
+// Client of the event stream, along with the part of the events which didn't
+// fit in its socket buffer yet.
+struct Subscriber {
+    stream: UnixStream,
+    pending: Vec<u8>,
+}
+
+impl Subscriber {
+    // Queues the line and writes as much of the queued events as the socket
+    // takes, returning whether the client is to be kept.
+    fn send(&mut self, line: &[u8]) -> bool {
+        self.pending.extend_from_slice(line);
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => return false,
+            }
+        }
+        self.pending.len() <= MAX_PENDING
+    }
+}
 
 /// This function must only be called once from the main process before any threads
 /// are created to avoid race conditions
@@ -32,6 +69,33 @@ pub fn set_monitor(file: File) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+/// Enables streaming the VM lifecycle events to the subscribers.
+///
+/// This function must only be called once from the main process before any threads
+/// are created to avoid race conditions
+pub fn enable_stream() {
+    assert!(unsafe { STREAM.is_none() });
+    unsafe {
+        STREAM = Some((Mutex::new(Vec::new()), Instant::now()));
+    }
+}
+
+/// Adds a client to the subscribers of the VM lifecycle events, which are
+/// written to it as newline-delimited JSON. As the events are written from
+/// the threads of the VMM, the ones which don't fit in the socket buffer are
+/// kept for the next event, and a client lagging too far behind is
+/// disconnected rather than waited for.
+pub fn add_subscriber(stream: UnixStream) -> Result<(), std::io::Error> {
+    stream.set_nonblocking(true)?;
+    if let Some((subscribers, _)) = unsafe { STREAM.as_ref() } {
+        subscribers.lock().unwrap().push(Subscriber {
+            stream,
+            pending: Vec::new(),
+        });
+    }
+    Ok(())
+}
+
 #[derive(Serialize)]
 struct Event<'a> {
     timestamp: Duration,
@@ -53,6 +117,35 @@ pub fn event_log(source: &str, event: &str, properties: Option<&HashMap<Cow<str>
         let mut file = file;
         file.write_all(b"\n\n").ok();
     }
+
+    if source != STREAM_SOURCE {
+        return;
+    }
+    if let Some((subscribers, start)) = unsafe { STREAM.as_ref() } {
+        let e = Event {
+            timestamp: start.elapsed(),
+            source,
+            event,
+            properties,
+        };
+        let mut line = match serde_json::to_vec(&e) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        line.push(b'\n');
+
+        let mut subscribers = subscribers.lock().unwrap();
+        *subscribers = subscribers
+            .drain(..)
+            .filter_map(|mut subscriber| {
+                if subscriber.send(&line) {
+                    Some(subscriber)
+                } else {
+                    None
+                }
+            })
+            .collect();
+    }
 }
 
 /*
@@ -77,3 +170,68 @@ macro_rules! event {
      };
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn new_subscriber() -> (Subscriber, UnixStream) {
+        let (stream, client) = UnixStream::pair().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        (
+            Subscriber {
+                stream,
+                pending: Vec::new(),
+            },
+            client,
+        )
+    }
+
+    #[test]
+    fn test_subscriber_pending_events() {
+        let (mut subscriber, mut client) = new_subscriber();
+        let line = [b"x".repeat(999), b"\n".to_vec()].concat();
+
+        // Fill the socket buffer, the rest of the line being kept.
+        let mut sent = 0;
+        while subscriber.pending.is_empty() {
+            assert!(subscriber.send(&line));
+            sent += 1;
+        }
+        assert!(subscriber.pending.len() <= line.len());
+
+        // Once the client catches up, the rest of the line is written along
+        // with the next event.
+        let mut received = vec![0u8; sent * line.len()];
+        let pending = subscriber.pending.len();
+        client
+            .read_exact(&mut received[..sent * line.len() - pending])
+            .unwrap();
+        assert!(subscriber.send(&line));
+        assert!(subscriber.pending.is_empty());
+        client
+            .read_exact(&mut received[sent * line.len() - pending..])
+            .unwrap();
+        assert!(received.chunks(line.len()).all(|chunk| chunk == line));
+        let mut next = vec![0u8; line.len()];
+        client.read_exact(&mut next).unwrap();
+        assert_eq!(next, line);
+    }
+
+    #[test]
+    fn test_subscriber_disconnected() {
+        // A client lagging too far behind is disconnected.
+        let (mut subscriber, _client) = new_subscriber();
+        let line = [b"x".repeat(999), b"\n".to_vec()].concat();
+        while subscriber.pending.len() + line.len() <= MAX_PENDING {
+            assert!(subscriber.send(&line));
+        }
+        assert!(!subscriber.send(&line));
+
+        // So is a client which closed its end of the socket.
+        let (mut subscriber, client) = new_subscriber();
+        drop(client);
+        assert!(!subscriber.send(b"{}\n"));
+    }
+}
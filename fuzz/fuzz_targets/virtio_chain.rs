@@ -0,0 +1,74 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use virtio_queue::{Queue, QueueState};
+use vm_memory::{bitmap::AtomicBitmap, Bytes, GuestAddress, GuestMemory, GuestMemoryAtomic};
+use vm_virtio::chain::{validate_chain, ChainLimits};
+
+type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
+
+const MEM_SIZE: usize = 0x10_0000;
+const DESC_SIZE: usize = 16; // Bytes in one virtio descriptor.
+const QUEUE_SIZE: u16 = 16; // Max entries in the queue.
+const DESC_TABLE_ADDR: u64 = 0;
+const AVAIL_RING_ADDR: u64 = 0x1000;
+const USED_RING_ADDR: u64 = 0x2000;
+// Where the rest of the fuzz data lands, for the descriptors to point to.
+const DATA_ADDR: u64 = 0x3000;
+
+// The fuzz data is interpreted as the descriptor table of the queue, every
+// descriptor being made available as the head of a chain, followed by data
+// for the indirect tables.
+fuzz_target!(|bytes| {
+    let table_size = QUEUE_SIZE as usize * DESC_SIZE;
+    if bytes.len() < table_size || bytes.len() - table_size > MEM_SIZE - DATA_ADDR as usize {
+        return;
+    }
+
+    let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), MEM_SIZE)]).unwrap();
+    mem.write_slice(&bytes[..table_size], GuestAddress(DESC_TABLE_ADDR))
+        .unwrap();
+    mem.write_slice(&bytes[table_size..], GuestAddress(DATA_ADDR))
+        .unwrap();
+
+    // Available ring, made of its flags, its index and its entries.
+    mem.write_obj(0u16, GuestAddress(AVAIL_RING_ADDR)).unwrap();
+    mem.write_obj(QUEUE_SIZE, GuestAddress(AVAIL_RING_ADDR + 2))
+        .unwrap();
+    for i in 0..QUEUE_SIZE {
+        mem.write_obj(i, GuestAddress(AVAIL_RING_ADDR + 4 + 2 * i as u64))
+            .unwrap();
+    }
+
+    let mut q = Queue::<GuestMemoryAtomic<GuestMemoryMmap>, QueueState>::new(
+        GuestMemoryAtomic::new(mem),
+        QUEUE_SIZE,
+    );
+    q.state.ready = true;
+    q.state.size = QUEUE_SIZE;
+    q.state.desc_table = GuestAddress(DESC_TABLE_ADDR);
+    q.state.avail_ring = GuestAddress(AVAIL_RING_ADDR);
+    q.state.used_ring = GuestAddress(USED_RING_ADDR);
+
+    let limits = ChainLimits::default();
+    for mut chain in q.iter().unwrap() {
+        if let Ok(valid_chain) = validate_chain(&mut chain, &limits, None) {
+            // Every buffer of a valid chain lies within the guest memory.
+            let mut len = 0;
+            for desc in valid_chain.descriptors() {
+                if desc.len() > 0 {
+                    chain
+                        .memory()
+                        .get_slice(desc.addr(), desc.len() as usize)
+                        .unwrap();
+                }
+                len += desc.len() as u64;
+            }
+            assert_eq!(len, valid_chain.readable_len() + valid_chain.writable_len());
+        }
+    }
+});
@@ -56,4 +56,8 @@ pub trait Vgic: Send + Sync {
 
     /// Saves GIC internal data tables into RAM.
     fn save_data_tables(&self) -> Result<()>;
+
+    /// Sets the given SGI pending on the redistributor of the vCPU with the
+    /// given MPIDR. The vCPUs must be paused.
+    fn set_sgi_pending(&self, mpidr: u64, sgi: u8) -> Result<()>;
 }
@@ -12,6 +12,8 @@
 use crate::aarch64::VcpuInit;
 #[cfg(target_arch = "aarch64")]
 use crate::aarch64::{RegList, Register, StandardRegisters};
+#[cfg(feature = "sev_snp")]
+use crate::kvm::SevSnpConversion;
 #[cfg(feature = "tdx")]
 use crate::kvm::{TdxExitDetails, TdxExitStatus};
 #[cfg(all(feature = "mshv", target_arch = "x86_64"))]
@@ -237,6 +239,26 @@ pub enum HypervisorCpuError {
     #[error("Failed to check if vcpu has attribute: {0}")]
     HasVcpuAttribute(#[source] anyhow::Error),
     ///
+    /// Getting the TSC frequency error
+    ///
+    #[error("Failed to get TSC frequency: {0}")]
+    GetTscKhz(#[source] anyhow::Error),
+    ///
+    /// Setting the TSC frequency error
+    ///
+    #[error("Failed to set TSC frequency: {0}")]
+    SetTscKhz(#[source] anyhow::Error),
+    ///
+    /// Getting the TSC offset error
+    ///
+    #[error("Failed to get TSC offset: {0}")]
+    GetTscOffset(#[source] anyhow::Error),
+    ///
+    /// Setting the TSC offset error
+    ///
+    #[error("Failed to set TSC offset: {0}")]
+    SetTscOffset(#[source] anyhow::Error),
+    ///
     /// Failed to initialize TDX on CPU
     ///
     #[cfg(feature = "tdx")]
@@ -248,6 +270,12 @@ pub enum HypervisorCpuError {
     #[cfg(feature = "tdx")]
     #[error("Unknown TDX VM call")]
     UnknownTdxVmCall,
+    ///
+    /// Invalid SEV-SNP memory conversion request
+    ///
+    #[cfg(feature = "sev_snp")]
+    #[error("Invalid SEV-SNP memory conversion: {0}")]
+    SevSnpConversion(#[source] anyhow::Error),
 }
 
 #[derive(Debug)]
@@ -266,6 +294,8 @@ pub enum VmExit<'a> {
     Hyperv,
     #[cfg(feature = "tdx")]
     Tdx,
+    #[cfg(feature = "sev_snp")]
+    SevSnp,
     #[cfg(feature = "kvm")]
     Debug,
 }
@@ -406,6 +436,49 @@ pub trait Vcpu: Send + Sync {
     /// Sets debug registers to set hardware breakpoints and/or enable single step.
     ///
     fn set_guest_debug(&self, addrs: &[GuestAddress], singlestep: bool) -> Result<()>;
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Returns the frequency of the guest TSC in kHz, if known.
+    ///
+    fn tsc_khz(&self) -> Result<Option<u32>> {
+        Ok(None)
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Sets the frequency of the guest TSC in kHz.
+    ///
+    fn set_tsc_khz(&self, _freq: u32) -> Result<()> {
+        Ok(())
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Returns the offset of the guest TSC from the host TSC, if it can be
+    /// controlled.
+    ///
+    fn tsc_offset(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Sets the offset of the guest TSC from the host TSC.
+    ///
+    fn set_tsc_offset(&self, _offset: u64) -> Result<()> {
+        Ok(())
+    }
+    ///
+    /// Returns the counter the guest reads the time from, the TSC on x86-64
+    /// and the virtual counter (CNTVCT) on AArch64, if it can be accessed.
+    ///
+    fn guest_counter(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+    ///
+    /// Sets the counter the guest reads the time from, which keeps counting
+    /// from the given value.
+    ///
+    fn set_guest_counter(&self, _value: u64) -> Result<()> {
+        Ok(())
+    }
     ///
     /// Sets the type of CPU to be exposed to the guest and optional features.
     ///
@@ -501,4 +574,14 @@ pub trait Vcpu: Send + Sync {
     /// Set the status code for TDX exit
     ///
     fn set_tdx_status(&mut self, status: TdxExitStatus);
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Returns the memory range the SEV-SNP guest converts
+    ///
+    fn get_sev_snp_conversion(&mut self) -> Result<SevSnpConversion>;
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Reports the result of the conversion to the SEV-SNP guest
+    ///
+    fn set_sev_snp_conversion_result(&mut self, success: bool);
 }
@@ -9,7 +9,7 @@ use crate::kvm::kvm_bindings;
 use crate::{CpuState, Device, Vm};
 use dist_regs::{get_dist_regs, read_ctlr, set_dist_regs, write_ctlr};
 use icc_regs::{get_icc_regs, set_icc_regs};
-use redist_regs::{construct_gicr_typers, get_redist_regs, set_redist_regs};
+use redist_regs::{construct_gicr_typers, get_redist_regs, set_redist_regs, set_sgi_pending};
 use serde::{Deserialize, Serialize};
 use std::any::Any;
 use std::convert::TryInto;
@@ -491,12 +491,17 @@ impl Vgic for KvmGicV3Its {
         // Flush ITS tables to guest RAM.
         gicv3_its_tables_access(self.its_device().unwrap(), true)
     }
+
+    fn set_sgi_pending(&self, mpidr: u64, sgi: u8) -> Result<()> {
+        set_sgi_pending(self.device(), mpidr, sgi)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::aarch64::gic::{
         get_dist_regs, get_icc_regs, get_redist_regs, set_dist_regs, set_icc_regs, set_redist_regs,
+        set_sgi_pending,
     };
     use crate::kvm::KvmGicV3Its;
 
@@ -568,6 +573,25 @@ mod tests {
         assert!(set_redist_regs(gic.device(), &gicr_typer, &state).is_ok());
     }
 
+    #[test]
+    fn test_set_sgi_pending() {
+        let hv = crate::new().unwrap();
+        let vm = hv.create_vm().unwrap();
+        let _ = vm.create_vcpu(0, None).unwrap();
+        let gic = KvmGicV3Its::new(
+            &*vm,
+            1,
+            0x0900_0000 - 0x01_0000,
+            0x01_0000,
+            0x02_0000,
+            0x02_0000,
+            256,
+        )
+        .expect("Cannot create gic");
+
+        assert!(set_sgi_pending(gic.device(), 0, 1).is_ok());
+    }
+
     #[test]
     fn test_get_set_icc_regs() {
         let hv = crate::new().unwrap();
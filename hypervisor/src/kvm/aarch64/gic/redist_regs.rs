@@ -175,6 +175,20 @@ pub fn set_redist_regs(gic: &Arc<dyn Device>, gicr_typer: &[u64], state: &[u32])
     )
 }
 
+/// Set an SGI pending on the redistributor of the vCPU with the given MPIDR.
+pub fn set_sgi_pending(gic: &Arc<dyn Device>, mpidr: u64, sgi: u8) -> Result<()> {
+    // Affinity in the same layout as in the top 32 bits of GICR_TYPER.
+    let mut cpu_affid = mpidr & 0xff_00ff_ffff;
+    cpu_affid = ((cpu_affid & 0xff_0000_0000) >> 8) | (cpu_affid & 0xff_ffff);
+    redist_attr_access(
+        gic,
+        GICR_ISPENDR0,
+        cpu_affid << KVM_DEV_ARM_VGIC_V3_MPIDR_SHIFT,
+        &(1 << sgi),
+        true,
+    )
+}
+
 pub fn construct_gicr_typers(vcpu_states: &[CpuState]) -> Vec<u64> {
     /* Pre-construct the GICR_TYPER:
      * For our implementation:
@@ -0,0 +1,243 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+//
+
+//! Dirty pages tracking through the per vCPU rings of KVM
+//! (KVM_CAP_DIRTY_LOG_RING), used instead of the dirty bitmaps when the host
+//! supports it. The cost of collecting the dirty pages depends on the number
+//! of pages dirtied instead of the size of the guest memory.
+
+use kvm_bindings::KVMIO;
+use kvm_ioctls::{VcpuFd, VmFd};
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr::addr_of;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use vmm_sys_util::ioctl::{ioctl, ioctl_with_val};
+use vmm_sys_util::{ioctl_expr, ioctl_io_nr, ioctl_ioc_nr};
+
+pub const KVM_CAP_DIRTY_LOG_RING: u32 = 192;
+pub const KVM_EXIT_DIRTY_RING_FULL: u32 = 31;
+
+// Offset, in pages, of the dirty ring in the vCPU file.
+const KVM_DIRTY_LOG_PAGE_OFFSET: i64 = 64;
+
+const KVM_DIRTY_GFN_F_DIRTY: u32 = 1 << 0;
+const KVM_DIRTY_GFN_F_RESET: u32 = 1 << 1;
+
+// Number of entries of the ring of each vCPU. A bigger ring makes the vCPUs
+// exit less often to have their ring collected, at the expense of memory
+// allocated by KVM for each vCPU.
+const DIRTY_RING_ENTRIES: u32 = 4096;
+
+ioctl_io_nr!(KVM_CHECK_EXTENSION, KVMIO, 0x03);
+ioctl_io_nr!(KVM_RESET_DIRTY_RINGS, KVMIO, 0xc7);
+
+#[repr(C)]
+struct KvmDirtyGfn {
+    flags: u32,
+    slot: u32,
+    offset: u64,
+}
+
+/// Returns the number of entries of the dirty ring to enable on the VM, if
+/// the host supports it.
+pub fn dirty_ring_entries(vm_fd: &VmFd) -> Option<u32> {
+    // SAFETY: FFI call with valid arguments
+    let max_size =
+        unsafe { ioctl_with_val(vm_fd, KVM_CHECK_EXTENSION(), KVM_CAP_DIRTY_LOG_RING as u64) };
+    if max_size <= 0 {
+        return None;
+    }
+
+    // The maximum size is a power of two, as is the size of an entry.
+    let max_entries = max_size as u32 / std::mem::size_of::<KvmDirtyGfn>() as u32;
+    Some(std::cmp::min(DIRTY_RING_ENTRIES, max_entries))
+}
+
+// Ring shared with KVM, where the pages dirtied by a vCPU are pushed.
+struct DirtyRing {
+    gfns: *mut KvmDirtyGfn,
+    entries: u32,
+    // Index of the next entry to collect.
+    next: u32,
+}
+
+// SAFETY: The ring is only accessed through the mutex of DirtyRings.
+unsafe impl Send for DirtyRing {}
+
+impl DirtyRing {
+    fn new(vcpu_fd: &VcpuFd, entries: u32) -> io::Result<Self> {
+        let size = entries as usize * std::mem::size_of::<KvmDirtyGfn>();
+        // SAFETY: FFI call with valid arguments, the result is checked.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        // SAFETY: FFI call with valid arguments, the result is checked.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                vcpu_fd.as_raw_fd(),
+                KVM_DIRTY_LOG_PAGE_OFFSET * page_size,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(DirtyRing {
+            gfns: addr as *mut KvmDirtyGfn,
+            entries,
+            next: 0,
+        })
+    }
+
+    // Collects the entries pushed by KVM, and marks them as ready to be
+    // reset. Returns the number of entries collected.
+    fn collect(&mut self, bitmaps: &mut HashMap<u32, Vec<u64>>) -> usize {
+        let mut count = 0;
+        loop {
+            // SAFETY: The index is within the ring mapped in new().
+            let gfn = unsafe { self.gfns.add((self.next & (self.entries - 1)) as usize) };
+            // SAFETY: The flags are naturally aligned, and shared with KVM
+            // which updates them atomically.
+            let flags = unsafe { &*(addr_of!((*gfn).flags) as *const AtomicU32) };
+            if flags.load(Ordering::Acquire) & KVM_DIRTY_GFN_F_DIRTY == 0 {
+                break;
+            }
+
+            // SAFETY: The entry is owned by userspace until it is reset.
+            let (slot, offset) = unsafe { ((*gfn).slot, (*gfn).offset) };
+            set_dirty(bitmaps.entry(slot).or_default(), offset);
+
+            flags.store(KVM_DIRTY_GFN_F_RESET, Ordering::Release);
+            self.next = self.next.wrapping_add(1);
+            count += 1;
+        }
+
+        count
+    }
+}
+
+impl Drop for DirtyRing {
+    fn drop(&mut self) {
+        let size = self.entries as usize * std::mem::size_of::<KvmDirtyGfn>();
+        // SAFETY: The mapping was created in new() with the same size.
+        unsafe { libc::munmap(self.gfns as *mut libc::c_void, size) };
+    }
+}
+
+fn set_dirty(bitmap: &mut Vec<u64>, page: u64) {
+    let index = (page / 64) as usize;
+    if bitmap.len() <= index {
+        bitmap.resize(index + 1, 0);
+    }
+    bitmap[index] |= 1 << (page % 64);
+}
+
+struct DirtyRingsState {
+    rings: HashMap<u8, DirtyRing>,
+    // Pages collected from the rings and not yet reported, per slot.
+    bitmaps: HashMap<u32, Vec<u64>>,
+}
+
+/// Dirty rings of the vCPUs of a VM.
+pub struct DirtyRings {
+    vm_fd: Arc<VmFd>,
+    entries: u32,
+    state: Mutex<DirtyRingsState>,
+}
+
+impl DirtyRings {
+    /// Enables the dirty ring on the VM, which must not have any vCPU yet.
+    pub fn new(vm_fd: Arc<VmFd>, entries: u32) -> io::Result<Self> {
+        let mut cap = kvm_bindings::kvm_enable_cap {
+            cap: KVM_CAP_DIRTY_LOG_RING,
+            ..Default::default()
+        };
+        cap.args[0] = (entries as usize * std::mem::size_of::<KvmDirtyGfn>()) as u64;
+        vm_fd
+            .enable_cap(&cap)
+            .map_err(|e| io::Error::from_raw_os_error(e.errno()))?;
+
+        Ok(DirtyRings {
+            vm_fd,
+            entries,
+            state: Mutex::new(DirtyRingsState {
+                rings: HashMap::new(),
+                bitmaps: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Maps the ring of a newly created vCPU.
+    pub fn add_vcpu(&self, id: u8, vcpu_fd: &VcpuFd) -> io::Result<()> {
+        let ring = DirtyRing::new(vcpu_fd, self.entries)?;
+        self.state.lock().unwrap().rings.insert(id, ring);
+        Ok(())
+    }
+
+    // Collects the entries from all the rings and lets KVM reuse them.
+    fn collect(&self, state: &mut DirtyRingsState) -> io::Result<()> {
+        let mut count = 0;
+        for ring in state.rings.values_mut() {
+            count += ring.collect(&mut state.bitmaps);
+        }
+
+        if count > 0 {
+            // SAFETY: FFI call with valid arguments
+            let ret = unsafe { ioctl(&*self.vm_fd, KVM_RESET_DIRTY_RINGS()) };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collects the rings so that the vCPU which exited because its ring is
+    /// full can run again.
+    pub fn collect_full(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.collect(&mut state)
+    }
+
+    /// Returns the bitmap of the pages of the slot dirtied since the last
+    /// call, one bit per page.
+    pub fn dirty_log(&self, slot: u32, memory_size: u64) -> io::Result<Vec<u64>> {
+        let mut state = self.state.lock().unwrap();
+        self.collect(&mut state)?;
+
+        let page_size = 4096;
+        let words = ((memory_size / page_size + 63) / 64) as usize;
+        let mut bitmap = state.bitmaps.remove(&slot).unwrap_or_default();
+        bitmap.resize(words, 0);
+        Ok(bitmap)
+    }
+
+    /// Drops the pages collected so far.
+    pub fn clear(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.collect(&mut state)?;
+        state.bitmaps.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_dirty() {
+        let mut bitmap = Vec::new();
+        set_dirty(&mut bitmap, 0);
+        set_dirty(&mut bitmap, 65);
+        set_dirty(&mut bitmap, 127);
+        assert_eq!(bitmap, vec![1, (1 << 1) | (1 << 63)]);
+    }
+}
@@ -13,7 +13,7 @@ use crate::aarch64::gic::KvmGicV3Its;
 #[cfg(target_arch = "aarch64")]
 pub use crate::aarch64::{
     check_required_kvm_extensions, gic::Gicv3ItsState as GicState, is_system_register, VcpuInit,
-    VcpuKvmState as CpuState, MPIDR_EL1,
+    VcpuKvmState as CpuState, KVM_REG_ARM_TIMER_CNT, MPIDR_EL1,
 };
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::gic::Vgic;
@@ -41,16 +41,28 @@ use std::sync::{Arc, RwLock};
 use vmm_sys_util::eventfd::EventFd;
 // x86_64 dependencies
 #[cfg(target_arch = "x86_64")]
+mod dirty_ring;
+#[cfg(feature = "sev_snp")]
+mod sev_snp;
+#[cfg(target_arch = "x86_64")]
+mod tsc;
+#[cfg(target_arch = "x86_64")]
 pub mod x86_64;
 #[cfg(target_arch = "x86_64")]
-use crate::arch::x86::NUM_IOAPIC_PINS;
+use crate::arch::x86::{msr_index, NUM_IOAPIC_PINS};
 #[cfg(target_arch = "aarch64")]
 use aarch64::{RegList, Register, StandardRegisters};
 #[cfg(target_arch = "x86_64")]
+use dirty_ring::{dirty_ring_entries, DirtyRings, KVM_EXIT_DIRTY_RING_FULL};
+#[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
-    kvm_enable_cap, kvm_guest_debug, kvm_msr_entry, MsrList, KVM_CAP_HYPERV_SYNIC,
-    KVM_CAP_SPLIT_IRQCHIP, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP, KVM_GUESTDBG_USE_HW_BP,
+    kvm_guest_debug, kvm_msi, kvm_msr_entry, MsrList, KVM_CAP_HYPERV_SYNIC, KVM_CAP_SPLIT_IRQCHIP,
+    KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP, KVM_GUESTDBG_USE_HW_BP,
 };
+#[cfg(feature = "sev_snp")]
+use sev_snp::SevSnp;
+#[cfg(feature = "sev_snp")]
+pub use sev_snp::{SevSnpConversion, SevSnpPageType, KVM_X86_SNP_VM};
 #[cfg(target_arch = "x86_64")]
 use x86_64::{check_required_kvm_extensions, FpuState, SpecialRegisters, StandardRegisters};
 #[cfg(target_arch = "x86_64")]
@@ -62,6 +74,7 @@ pub use x86_64::{
 #[cfg(target_arch = "aarch64")]
 pub mod aarch64;
 pub use kvm_bindings;
+use kvm_bindings::kvm_enable_cap;
 #[cfg(feature = "tdx")]
 use kvm_bindings::KVMIO;
 pub use kvm_bindings::{
@@ -95,6 +108,7 @@ pub use {
 
 #[cfg(target_arch = "x86_64")]
 const KVM_CAP_SGX_ATTRIBUTE: u32 = 196;
+const KVM_CAP_HALT_POLL: u32 = 182;
 
 #[cfg(feature = "tdx")]
 const KVM_EXIT_TDX: u32 = 35;
@@ -179,6 +193,11 @@ pub struct KvmVm {
     msrs: MsrEntries,
     state: KvmVmState,
     dirty_log_slots: Arc<RwLock<HashMap<u32, KvmDirtyLogSlot>>>,
+    #[cfg(target_arch = "x86_64")]
+    dirty_rings: Option<Arc<DirtyRings>>,
+    // guest_memfd holding the private memory of each slot of a SEV-SNP VM
+    #[cfg(feature = "sev_snp")]
+    guest_memfds: Option<RwLock<HashMap<u32, File>>>,
 }
 
 ///
@@ -246,6 +265,12 @@ impl vm::Vm for KvmVm {
             .fd
             .create_vcpu(id as u64)
             .map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
+        #[cfg(target_arch = "x86_64")]
+        if let Some(dirty_rings) = &self.dirty_rings {
+            dirty_rings
+                .add_vcpu(id, &vc)
+                .map_err(|e| vm::HypervisorVmError::CreateVcpu(e.into()))?;
+        }
         let vcpu = KvmVcpu {
             fd: vc,
             #[cfg(target_arch = "x86_64")]
@@ -253,6 +278,10 @@ impl vm::Vm for KvmVm {
             vm_ops,
             #[cfg(target_arch = "x86_64")]
             hyperv_synic: AtomicBool::new(false),
+            #[cfg(target_arch = "x86_64")]
+            dirty_rings: self.dirty_rings.clone(),
+            #[cfg(feature = "sev_snp")]
+            sev_snp: self.guest_memfds.is_some(),
         };
         Ok(Arc::new(vcpu))
     }
@@ -450,6 +479,37 @@ impl vm::Vm for KvmVm {
             region.flags = 0;
         }
 
+        // The RAM of a SEV-SNP guest is backed by a guest_memfd, and starts
+        // private so that it can be added to the launch measurement.
+        #[cfg(feature = "sev_snp")]
+        if let Some(guest_memfds) = &self.guest_memfds {
+            if (region.flags & KVM_MEM_READONLY) == 0 {
+                let guest_memfd = sev_snp::create_guest_memfd(&self.fd, region.memory_size)
+                    .map_err(|e| vm::HypervisorVmError::CreateUserMemory(e.into()))?;
+                sev_snp::set_user_memory_region2(
+                    &self.fd,
+                    region.slot,
+                    region.guest_phys_addr,
+                    region.memory_size,
+                    region.userspace_addr,
+                    &guest_memfd,
+                )
+                .map_err(|e| vm::HypervisorVmError::CreateUserMemory(e.into()))?;
+                sev_snp::set_memory_private(
+                    &self.fd,
+                    region.guest_phys_addr,
+                    region.memory_size,
+                    true,
+                )
+                .map_err(|e| vm::HypervisorVmError::CreateUserMemory(e.into()))?;
+                guest_memfds
+                    .write()
+                    .unwrap()
+                    .insert(region.slot, guest_memfd);
+                return Ok(());
+            }
+        }
+
         // SAFETY: Safe because guest regions are guaranteed not to overlap.
         unsafe {
             self.fd
@@ -468,6 +528,23 @@ impl vm::Vm for KvmVm {
 
         // Setting the size to 0 means "remove"
         region.memory_size = 0;
+
+        // The guest_memfd is released along with the slot.
+        #[cfg(feature = "sev_snp")]
+        if let Some(guest_memfds) = &self.guest_memfds {
+            if let Some(guest_memfd) = guest_memfds.write().unwrap().remove(&region.slot) {
+                return sev_snp::set_user_memory_region2(
+                    &self.fd,
+                    region.slot,
+                    region.guest_phys_addr,
+                    0,
+                    region.userspace_addr,
+                    &guest_memfd,
+                )
+                .map_err(|e| vm::HypervisorVmError::RemoveUserMemory(e.into()));
+            }
+        }
+
         // SAFETY: Safe because guest regions are guaranteed not to overlap.
         unsafe {
             self.fd
@@ -523,6 +600,34 @@ impl vm::Vm for KvmVm {
             .map_err(|e| vm::HypervisorVmError::EnableSgxAttribute(e.into()))?;
         Ok(())
     }
+    #[cfg(target_arch = "x86_64")]
+    fn inject_nmi(&self, apic_id: u32) -> vm::Result<()> {
+        // Unlike KVM_NMI, an MSI doesn't need to take the vCPU out of the
+        // guest. The message targets the APIC ID in physical destination
+        // mode, with the NMI delivery mode.
+        let msi = kvm_msi {
+            address_lo: 0xfee0_0000 | (apic_id << 12),
+            data: 0x400,
+            ..Default::default()
+        };
+        self.fd
+            .signal_msi(msi)
+            .map_err(|e| vm::HypervisorVmError::InjectNmi(e.into()))?;
+        Ok(())
+    }
+    ///
+    /// Overrides the halt_poll_ns module parameter of KVM for this VM.
+    ///
+    fn set_halt_poll_ns(&self, ns: u32) -> vm::Result<()> {
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_HALT_POLL,
+            ..Default::default()
+        };
+        cap.args[0] = ns as u64;
+        self.fd
+            .enable_cap(&cap)
+            .map_err(|e| vm::HypervisorVmError::SetHaltPoll(e.into()))
+    }
     /// Retrieve guest clock.
     #[cfg(target_arch = "x86_64")]
     fn get_clock(&self) -> vm::Result<ClockData> {
@@ -569,6 +674,14 @@ impl vm::Vm for KvmVm {
     /// Start logging dirty pages
     ///
     fn start_dirty_log(&self) -> vm::Result<()> {
+        // Drop the pages left in the rings by a previous dirty log.
+        #[cfg(target_arch = "x86_64")]
+        if let Some(dirty_rings) = &self.dirty_rings {
+            dirty_rings
+                .clear()
+                .map_err(|e| vm::HypervisorVmError::StartDirtyLog(e.into()))?;
+        }
+
         let dirty_log_slots = self.dirty_log_slots.read().unwrap();
         for (_, s) in dirty_log_slots.iter() {
             let region = MemoryRegion {
@@ -610,6 +723,13 @@ impl vm::Vm for KvmVm {
             }
         }
 
+        #[cfg(target_arch = "x86_64")]
+        if let Some(dirty_rings) = &self.dirty_rings {
+            dirty_rings
+                .clear()
+                .map_err(|e| vm::HypervisorVmError::StopDirtyLog(e.into()))?;
+        }
+
         Ok(())
     }
 
@@ -617,6 +737,14 @@ impl vm::Vm for KvmVm {
     /// Get dirty pages bitmap (one bit per page)
     ///
     fn get_dirty_log(&self, slot: u32, _base_gpa: u64, memory_size: u64) -> vm::Result<Vec<u64>> {
+        // The dirty bitmaps aren't available when the dirty ring is used.
+        #[cfg(target_arch = "x86_64")]
+        if let Some(dirty_rings) = &self.dirty_rings {
+            return dirty_rings
+                .dirty_log(slot, memory_size)
+                .map_err(|e| vm::HypervisorVmError::GetDirtyLog(e.into()));
+        }
+
         self.fd
             .get_dirty_log(slot, memory_size as usize)
             .map_err(|e| vm::HypervisorVmError::GetDirtyLog(e.into()))
@@ -698,6 +826,53 @@ impl vm::Vm for KvmVm {
         )
         .map_err(vm::HypervisorVmError::InitMemRegionTdx)
     }
+
+    ///
+    /// Initialize SEV-SNP for this VM
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_init(&self, policy: u64) -> vm::Result<()> {
+        SevSnp::new()
+            .and_then(|sev_snp| sev_snp.launch_start(&self.fd, policy))
+            .map_err(vm::HypervisorVmError::InitializeSevSnp)
+    }
+
+    ///
+    /// Convert a range of the memory of the SEV-SNP VM to private or shared
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_set_memory_private(&self, address: u64, size: u64, private: bool) -> vm::Result<()> {
+        sev_snp::set_memory_private(&self.fd, address, size, private)
+            .map_err(vm::HypervisorVmError::ConvertSevSnpMemory)
+    }
+
+    ///
+    /// Encrypt memory pages of the SEV-SNP VM before its launch
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_launch_update(
+        &self,
+        host_address: u64,
+        guest_address: u64,
+        size: u64,
+        page_type: SevSnpPageType,
+    ) -> vm::Result<()> {
+        SevSnp::new()
+            .and_then(|sev_snp| {
+                sev_snp.launch_update(&self.fd, host_address, guest_address, size, page_type)
+            })
+            .map_err(vm::HypervisorVmError::LaunchUpdateSevSnp)
+    }
+
+    ///
+    /// Finalize the SEV-SNP launch of this VM
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_finalize(&self) -> vm::Result<()> {
+        SevSnp::new()
+            .and_then(|sev_snp| sev_snp.launch_finish(&self.fd))
+            .map_err(vm::HypervisorVmError::FinalizeSevSnp)
+    }
 }
 
 #[cfg(feature = "tdx")]
@@ -806,11 +981,45 @@ impl hypervisor::Hypervisor for KvmHypervisor {
                 msr_entries[pos].index = *index;
             }
 
+            // Track the dirty pages through the per vCPU rings when the
+            // host supports it, as collecting them doesn't depend on the
+            // size of the guest memory, unlike the dirty bitmaps.
+            let dirty_rings = match dirty_ring_entries(&vm_fd) {
+                Some(entries) => match DirtyRings::new(vm_fd.clone(), entries) {
+                    Ok(dirty_rings) => {
+                        info!("Using dirty ring of {} entries per vCPU", entries);
+                        Some(Arc::new(dirty_rings))
+                    }
+                    Err(e) => {
+                        warn!("Cannot enable dirty ring, using dirty bitmaps: {}", e);
+                        None
+                    }
+                },
+                None => None,
+            };
+
+            // Without guest_memfd, the memory of a SEV-SNP guest can't be
+            // made private.
+            #[cfg(feature = "sev_snp")]
+            let guest_memfds = if vm_type == KVM_X86_SNP_VM {
+                if !sev_snp::check_private_memory(&vm_fd) {
+                    return Err(hypervisor::HypervisorError::VmCreate(anyhow!(
+                        "guest_memfd and private memory attributes are required for SEV-SNP"
+                    )));
+                }
+                Some(RwLock::new(HashMap::new()))
+            } else {
+                None
+            };
+
             Ok(Arc::new(KvmVm {
                 fd: vm_fd,
                 msrs,
                 state: VmState {},
                 dirty_log_slots: Arc::new(RwLock::new(HashMap::new())),
+                dirty_rings,
+                #[cfg(feature = "sev_snp")]
+                guest_memfds,
             }))
         }
 
@@ -908,6 +1117,10 @@ pub struct KvmVcpu {
     vm_ops: Option<Arc<dyn vm::VmOps>>,
     #[cfg(target_arch = "x86_64")]
     hyperv_synic: AtomicBool,
+    #[cfg(target_arch = "x86_64")]
+    dirty_rings: Option<Arc<DirtyRings>>,
+    #[cfg(feature = "sev_snp")]
+    sev_snp: bool,
 }
 /// Implementation of Vcpu trait for KVM
 /// Example:
@@ -1211,6 +1424,21 @@ impl cpu::Vcpu for KvmVcpu {
                 VcpuExit::Hyperv => Ok(cpu::VmExit::Hyperv),
                 #[cfg(feature = "tdx")]
                 VcpuExit::Unsupported(KVM_EXIT_TDX) => Ok(cpu::VmExit::Tdx),
+                // The only hypercall forwarded by KVM is KVM_HC_MAP_GPA_RANGE.
+                #[cfg(feature = "sev_snp")]
+                VcpuExit::Hypercall if self.sev_snp => Ok(cpu::VmExit::SevSnp),
+                // The ring of the vCPU is full and must be collected before
+                // it can run again.
+                #[cfg(target_arch = "x86_64")]
+                VcpuExit::Unsupported(KVM_EXIT_DIRTY_RING_FULL) => match &self.dirty_rings {
+                    Some(dirty_rings) => dirty_rings
+                        .collect_full()
+                        .map(|_| cpu::VmExit::Ignore)
+                        .map_err(|e| cpu::HypervisorCpuError::RunVcpu(e.into())),
+                    None => Err(cpu::HypervisorCpuError::RunVcpu(anyhow!(
+                        "Unexpected dirty ring full exit"
+                    ))),
+                },
                 VcpuExit::Debug(_) => Ok(cpu::VmExit::Debug),
 
                 r => Err(cpu::HypervisorCpuError::RunVcpu(anyhow!(
@@ -1221,6 +1449,11 @@ impl cpu::Vcpu for KvmVcpu {
 
             Err(ref e) => match e.errno() {
                 libc::EAGAIN | libc::EINTR => Ok(cpu::VmExit::Ignore),
+                // An access not matching the private attribute of the page
+                // is reported as a KVM_EXIT_MEMORY_FAULT, the page needing
+                // an implicit conversion.
+                #[cfg(feature = "sev_snp")]
+                libc::EFAULT if self.sev_snp => Ok(cpu::VmExit::SevSnp),
                 _ => Err(cpu::HypervisorCpuError::RunVcpu(anyhow!(
                     "VCPU error {:?}",
                     e
@@ -1304,6 +1537,100 @@ impl cpu::Vcpu for KvmVcpu {
             .set_guest_debug(&dbg)
             .map_err(|e| cpu::HypervisorCpuError::SetDebugRegs(e.into()))
     }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Returns the frequency of the guest TSC in kHz. KVM returns -EIO when
+    /// the frequency isn't known, for instance with an unstable host TSC.
+    ///
+    fn tsc_khz(&self) -> cpu::Result<Option<u32>> {
+        match self.fd.get_tsc_khz() {
+            Ok(freq) => Ok(Some(freq)),
+            Err(e) if e.errno() == libc::EIO => Ok(None),
+            Err(e) => Err(cpu::HypervisorCpuError::GetTscKhz(e.into())),
+        }
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Sets the frequency of the guest TSC in kHz. A frequency different
+    /// from the host one requires TSC scaling support.
+    ///
+    fn set_tsc_khz(&self, freq: u32) -> cpu::Result<()> {
+        self.fd
+            .set_tsc_khz(freq)
+            .map_err(|e| cpu::HypervisorCpuError::SetTscKhz(e.into()))
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Returns the guest TSC.
+    ///
+    fn guest_counter(&self) -> cpu::Result<Option<u64>> {
+        let mut msrs = MsrEntries::from_entries(&[kvm_msr_entry {
+            index: msr_index::MSR_IA32_TSC,
+            ..Default::default()
+        }])
+        .unwrap();
+        if self.get_msrs(&mut msrs)? != 1 {
+            return Err(cpu::HypervisorCpuError::GetMsrEntries(anyhow!(
+                "Could not read the TSC"
+            )));
+        }
+        Ok(Some(msrs.as_slice()[0].data))
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Sets the guest TSC. KVM keeps the TSC of the vCPUs synchronized when
+    /// they are set to the same value.
+    ///
+    fn set_guest_counter(&self, value: u64) -> cpu::Result<()> {
+        let msrs = MsrEntries::from_entries(&[kvm_msr_entry {
+            index: msr_index::MSR_IA32_TSC,
+            data: value,
+            ..Default::default()
+        }])
+        .unwrap();
+        if self.set_msrs(&msrs)? != 1 {
+            return Err(cpu::HypervisorCpuError::SetMsrEntries(anyhow!(
+                "Could not write the TSC"
+            )));
+        }
+        Ok(())
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Returns the offset of the guest TSC from the host TSC, if the host
+    /// supports KVM_VCPU_TSC_CTRL.
+    ///
+    fn tsc_offset(&self) -> cpu::Result<Option<u64>> {
+        tsc::tsc_offset(&self.fd).map_err(|e| cpu::HypervisorCpuError::GetTscOffset(e.into()))
+    }
+    #[cfg(target_arch = "x86_64")]
+    ///
+    /// Sets the offset of the guest TSC from the host TSC.
+    ///
+    fn set_tsc_offset(&self, offset: u64) -> cpu::Result<()> {
+        tsc::set_tsc_offset(&self.fd, offset)
+            .map_err(|e| cpu::HypervisorCpuError::SetTscOffset(e.into()))
+    }
+    #[cfg(target_arch = "aarch64")]
+    ///
+    /// Returns the guest virtual counter.
+    ///
+    fn guest_counter(&self) -> cpu::Result<Option<u64>> {
+        self.fd
+            .get_one_reg(KVM_REG_ARM_TIMER_CNT)
+            .map(Some)
+            .map_err(|e| cpu::HypervisorCpuError::GetSysRegister(e.into()))
+    }
+    #[cfg(target_arch = "aarch64")]
+    ///
+    /// Sets the guest virtual counter, KVM adjusting its offset (CNTVOFF)
+    /// from the host counter.
+    ///
+    fn set_guest_counter(&self, value: u64) -> cpu::Result<()> {
+        self.fd
+            .set_one_reg(KVM_REG_ARM_TIMER_CNT, value)
+            .map_err(|e| cpu::HypervisorCpuError::SetSysRegister(e.into()))
+    }
     #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
     fn vcpu_init(&self, kvi: &VcpuInit) -> cpu::Result<()> {
         self.fd
@@ -1575,21 +1902,33 @@ impl cpu::Vcpu for KvmVcpu {
         // PSR (Processor State Register) bits.
         // Taken from arch/arm64/include/uapi/asm/ptrace.h.
         const PSR_MODE_EL1h: u64 = 0x0000_0005;
+        const PSR_MODE_EL2h: u64 = 0x0000_0009;
+        const PSR_MODE_MASK: u64 = 0x0000_000f;
         const PSR_F_BIT: u64 = 0x0000_0040;
         const PSR_I_BIT: u64 = 0x0000_0080;
         const PSR_A_BIT: u64 = 0x0000_0100;
         const PSR_D_BIT: u64 = 0x0000_0200;
         // Taken from arch/arm64/kvm/inject_fault.c.
-        const PSTATE_FAULT_BITS_64: u64 =
-            PSR_MODE_EL1h | PSR_A_BIT | PSR_F_BIT | PSR_I_BIT | PSR_D_BIT;
+        const PSTATE_FAULT_BITS_64: u64 = PSR_A_BIT | PSR_F_BIT | PSR_I_BIT | PSR_D_BIT;
 
         let kreg_off = offset__of!(kvm_regs, regs);
 
         // Get the register index of the PSTATE (Processor State) register.
         let pstate = offset__of!(user_pt_regs, pstate) + kreg_off;
+
+        // The vCPUs created with the EL2 are reset at EL2, where the kernel
+        // is entered so that it can run its own guests.
+        let mode = match self
+            .get_reg(arm64_core_reg_id!(KVM_REG_SIZE_U64, pstate))
+            .map_err(|e| cpu::HypervisorCpuError::GetCoreRegister(e.into()))?
+            & PSR_MODE_MASK
+        {
+            PSR_MODE_EL2h => PSR_MODE_EL2h,
+            _ => PSR_MODE_EL1h,
+        };
         self.set_reg(
             arm64_core_reg_id!(KVM_REG_SIZE_U64, pstate),
-            PSTATE_FAULT_BITS_64,
+            mode | PSTATE_FAULT_BITS_64,
         )
         .map_err(|e| cpu::HypervisorCpuError::SetCoreRegister(e.into()))?;
 
@@ -1728,6 +2067,7 @@ impl cpu::Vcpu for KvmVcpu {
         };
 
         let vcpu_events = self.get_vcpu_events()?;
+        let tsc_khz = self.tsc_khz()?;
 
         Ok(CpuState {
             cpuid,
@@ -1740,6 +2080,7 @@ impl cpu::Vcpu for KvmVcpu {
             xsave,
             xcrs,
             mp_state,
+            tsc_khz,
         })
     }
     ///
@@ -1782,6 +2123,9 @@ impl cpu::Vcpu for KvmVcpu {
     /// SET_LAPIC must come before SET_MSRS, because the TSC deadline MSR
     /// only restores successfully, when the LAPIC is correctly configured.
     ///
+    /// SET_TSC_KHZ must come before SET_MSRS, so that the TSC value is
+    /// restored against the frequency the guest was running with.
+    ///
     /// Arguments: CpuState
     /// # Example
     ///
@@ -1799,6 +2143,9 @@ impl cpu::Vcpu for KvmVcpu {
     /// ```
     fn set_state(&self, state: &CpuState) -> cpu::Result<()> {
         self.set_cpuid2(&state.cpuid)?;
+        if let Some(freq) = state.tsc_khz {
+            self.set_tsc_khz(freq)?;
+        }
         self.set_mp_state(state.mp_state)?;
         self.set_regs(&state.regs)?;
         self.set_sregs(&state.sregs)?;
@@ -1905,6 +2252,68 @@ impl cpu::Vcpu for KvmVcpu {
             TdxExitStatus::InvalidOperand => TDG_VP_VMCALL_INVALID_OPERAND,
         };
     }
+
+    ///
+    /// Returns the memory range the SEV-SNP guest converts
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn get_sev_snp_conversion(&mut self) -> cpu::Result<SevSnpConversion> {
+        let kvm_run = self.fd.get_kvm_run();
+        match kvm_run.exit_reason {
+            kvm_bindings::KVM_EXIT_HYPERCALL => {
+                // SAFETY: the hypercall member is the one set by KVM for
+                // this exit reason.
+                let hypercall = unsafe { &kvm_run.__bindgen_anon_1.hypercall };
+                if hypercall.nr != sev_snp::KVM_HC_MAP_GPA_RANGE {
+                    return Err(cpu::HypervisorCpuError::SevSnpConversion(anyhow!(
+                        "Unexpected hypercall {}",
+                        hypercall.nr
+                    )));
+                }
+                Ok(SevSnpConversion::from_hypercall(&hypercall.args))
+            }
+            sev_snp::KVM_EXIT_MEMORY_FAULT => {
+                // SAFETY: the memory fault member, made of its flags, guest
+                // address and size, is the one set by KVM for this exit
+                // reason.
+                let padding = unsafe { &kvm_run.__bindgen_anon_1.padding };
+                let field = |i: usize| {
+                    let mut bytes = [0u8; 8];
+                    for (byte, c) in bytes.iter_mut().zip(&padding[i * 8..(i + 1) * 8]) {
+                        *byte = *c as u8;
+                    }
+                    u64::from_ne_bytes(bytes)
+                };
+                Ok(SevSnpConversion::from_memory_fault(
+                    field(0),
+                    field(1),
+                    field(2),
+                ))
+            }
+            reason => Err(cpu::HypervisorCpuError::SevSnpConversion(anyhow!(
+                "Unexpected exit reason {}",
+                reason
+            ))),
+        }
+    }
+
+    ///
+    /// Reports the result of the conversion to the SEV-SNP guest
+    ///
+    #[cfg(feature = "sev_snp")]
+    fn set_sev_snp_conversion_result(&mut self, success: bool) {
+        let kvm_run = self.fd.get_kvm_run();
+        if kvm_run.exit_reason == kvm_bindings::KVM_EXIT_HYPERCALL {
+            // SAFETY: the hypercall member is the one set by KVM for this
+            // exit reason.
+            let hypercall = unsafe { &mut kvm_run.__bindgen_anon_1.hypercall };
+            hypercall.ret = if success {
+                0
+            } else {
+                -(libc::EINVAL as i64) as u64
+            };
+        }
+    }
 }
 
 /// Device struct for KVM
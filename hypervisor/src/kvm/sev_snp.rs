@@ -0,0 +1,417 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+//
+
+// The memory of a SEV-SNP guest is backed by a guest_memfd, holding the
+// private pages only the guest can access, along with the regular mapping of
+// the VMM for the pages it shares. All the guest RAM starts private, the
+// guest converting pages back and forth through the KVM_HC_MAP_GPA_RANGE
+// hypercall, or implicitly with an access KVM reports as a memory fault.
+// See Documentation/virt/kvm/api.rst and
+// Documentation/virt/kvm/x86/amd-memory-encryption.rst in the kernel code.
+
+use kvm_bindings::{kvm_enable_cap, KVMIO};
+use kvm_ioctls::VmFd;
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use vmm_sys_util::ioctl::{ioctl_with_mut_ref, ioctl_with_ref, ioctl_with_val};
+use vmm_sys_util::{ioctl_expr, ioctl_io_nr, ioctl_ioc_nr, ioctl_iow_nr, ioctl_iowr_nr};
+
+/// VM type of the SEV-SNP guests
+pub const KVM_X86_SNP_VM: u64 = 4;
+
+/// Exit reason of the accesses to a page whose private attribute doesn't
+/// match the access, reported along with an EFAULT error.
+pub const KVM_EXIT_MEMORY_FAULT: u32 = 39;
+// The access is a private one.
+const KVM_MEMORY_EXIT_FLAG_PRIVATE: u64 = 1 << 3;
+
+/// Hypercall the guest converts pages with, its arguments being the guest
+/// physical address, the number of pages and the attributes.
+pub const KVM_HC_MAP_GPA_RANGE: u64 = 12;
+// The pages are converted to private.
+const KVM_MAP_GPA_RANGE_ENCRYPTED: u64 = 1 << 4;
+const KVM_CAP_EXIT_HYPERCALL: u32 = 201;
+
+const KVM_CAP_MEMORY_ATTRIBUTES: u64 = 233;
+const KVM_CAP_GUEST_MEMFD: u64 = 234;
+const KVM_MEM_GUEST_MEMFD: u32 = 1 << 2;
+const KVM_MEMORY_ATTRIBUTE_PRIVATE: u64 = 1 << 3;
+
+const PAGE_SHIFT: u64 = 12;
+
+// Guest policy bit that must always be set.
+const SEV_SNP_POLICY_RESERVED: u64 = 1 << 17;
+
+ioctl_io_nr!(KVM_CHECK_EXTENSION, KVMIO, 0x03);
+ioctl_iowr_nr!(KVM_MEMORY_ENCRYPT_OP, KVMIO, 0xba, std::os::raw::c_ulong);
+ioctl_iow_nr!(
+    KVM_SET_USER_MEMORY_REGION2,
+    KVMIO,
+    0x49,
+    KvmUserspaceMemoryRegion2
+);
+ioctl_iow_nr!(KVM_SET_MEMORY_ATTRIBUTES, KVMIO, 0xd2, KvmMemoryAttributes);
+ioctl_iowr_nr!(KVM_CREATE_GUEST_MEMFD, KVMIO, 0xd4, KvmCreateGuestMemfd);
+
+#[repr(u32)]
+enum SevCommand {
+    Init2 = 22,
+    SnpLaunchStart = 100,
+    SnpLaunchUpdate = 101,
+    SnpLaunchFinish = 102,
+}
+
+/// Type of the pages added to the guest memory before launching a SEV-SNP
+/// guest, as defined by the SEV-SNP firmware ABI.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SevSnpPageType {
+    /// Pages encrypted and included in the launch measurement
+    Normal = 1,
+    /// Pages zeroed and included in the launch measurement
+    Zero = 3,
+    /// Pages encrypted without being measured
+    Unmeasured = 4,
+    /// Page filled by the firmware with the secrets of the guest
+    Secrets = 5,
+    /// Page holding the CPUID values, checked by the firmware
+    Cpuid = 6,
+}
+
+#[repr(C)]
+struct KvmSevCmd {
+    id: u32,
+    pad0: u32,
+    data: u64,
+    error: u32,
+    sev_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevInit {
+    vmsa_features: u64,
+    flags: u32,
+    ghcb_version: u16,
+    pad1: u16,
+    pad2: [u32; 8],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevSnpLaunchStart {
+    policy: u64,
+    gosvw: [u8; 16],
+    flags: u16,
+    pad0: [u8; 6],
+    pad1: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevSnpLaunchUpdate {
+    gfn_start: u64,
+    uaddr: u64,
+    len: u64,
+    r#type: u8,
+    pad0: u8,
+    flags: u16,
+    pad1: u32,
+    pad2: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSevSnpLaunchFinish {
+    id_block_uaddr: u64,
+    id_auth_uaddr: u64,
+    id_block_en: u8,
+    auth_key_en: u8,
+    vcek_disabled: u8,
+    host_data: [u8; 32],
+    pad0: [u8; 3],
+    flags: u16,
+    pad1: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmCreateGuestMemfd {
+    size: u64,
+    flags: u64,
+    reserved: [u64; 6],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmUserspaceMemoryRegion2 {
+    slot: u32,
+    flags: u32,
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    guest_memfd_offset: u64,
+    guest_memfd: u32,
+    pad1: u32,
+    pad2: [u64; 14],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmMemoryAttributes {
+    address: u64,
+    size: u64,
+    attributes: u64,
+    flags: u64,
+}
+
+/// Range of guest memory a SEV-SNP guest converts between private and
+/// shared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SevSnpConversion {
+    pub gpa: u64,
+    pub size: u64,
+    pub private: bool,
+    /// Requested by an access to the memory rather than a hypercall, the
+    /// guest not being able to make progress until it is done.
+    pub implicit: bool,
+}
+
+impl SevSnpConversion {
+    /// Decodes the arguments of a KVM_HC_MAP_GPA_RANGE hypercall.
+    pub fn from_hypercall(args: &[u64; 6]) -> Self {
+        SevSnpConversion {
+            gpa: args[0],
+            size: args[1] << PAGE_SHIFT,
+            private: args[2] & KVM_MAP_GPA_RANGE_ENCRYPTED != 0,
+            implicit: false,
+        }
+    }
+
+    /// Decodes the flags, address and size of a KVM_EXIT_MEMORY_FAULT exit,
+    /// the page being converted to match the access.
+    pub fn from_memory_fault(flags: u64, gpa: u64, size: u64) -> Self {
+        SevSnpConversion {
+            gpa,
+            size,
+            private: flags & KVM_MEMORY_EXIT_FLAG_PRIVATE != 0,
+            implicit: true,
+        }
+    }
+}
+
+/// Launch context of a SEV-SNP guest, holding the connection to the
+/// firmware used by KVM to issue the launch commands.
+pub struct SevSnp {
+    sev: File,
+}
+
+impl SevSnp {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(SevSnp {
+            sev: File::open("/dev/sev")?,
+        })
+    }
+
+    fn command(&self, vm_fd: &VmFd, id: SevCommand, data: u64) -> std::io::Result<()> {
+        let mut cmd = KvmSevCmd {
+            id: id as u32,
+            pad0: 0,
+            data,
+            error: 0,
+            sev_fd: self.sev.as_raw_fd() as u32,
+        };
+        // SAFETY: FFI call. All input parameters are valid.
+        let ret = unsafe { ioctl_with_mut_ref(vm_fd, KVM_MEMORY_ENCRYPT_OP(), &mut cmd) };
+        if ret < 0 {
+            let e = std::io::Error::last_os_error();
+            if cmd.error != 0 {
+                return Err(std::io::Error::new(
+                    e.kind(),
+                    format!("{} (firmware error 0x{:x})", e, cmd.error),
+                ));
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Initializes the SEV-SNP context of the VM and starts the launch with
+    /// the given guest policy. Must be called before the vCPUs are created.
+    pub fn launch_start(&self, vm_fd: &VmFd, policy: u64) -> std::io::Result<()> {
+        let init = KvmSevInit::default();
+        self.command(vm_fd, SevCommand::Init2, &init as *const _ as u64)?;
+
+        // The conversions requested by the guest are handled by the VMM.
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_EXIT_HYPERCALL,
+            ..Default::default()
+        };
+        cap.args[0] = 1 << KVM_HC_MAP_GPA_RANGE;
+        vm_fd.enable_cap(&cap).map_err(std::io::Error::from)?;
+
+        let start = KvmSevSnpLaunchStart {
+            policy: policy | SEV_SNP_POLICY_RESERVED,
+            ..Default::default()
+        };
+        self.command(vm_fd, SevCommand::SnpLaunchStart, &start as *const _ as u64)
+    }
+
+    /// Encrypts the pages at the given guest address with the content found
+    /// at the host address, including them in the launch measurement
+    /// depending on their type. The pages must be private.
+    pub fn launch_update(
+        &self,
+        vm_fd: &VmFd,
+        host_address: u64,
+        guest_address: u64,
+        size: u64,
+        page_type: SevSnpPageType,
+    ) -> std::io::Result<()> {
+        let update = KvmSevSnpLaunchUpdate {
+            gfn_start: guest_address >> 12,
+            uaddr: host_address,
+            len: size,
+            r#type: page_type as u8,
+            ..Default::default()
+        };
+        self.command(
+            vm_fd,
+            SevCommand::SnpLaunchUpdate,
+            &update as *const _ as u64,
+        )
+    }
+
+    /// Completes the launch. The VMSA of each vCPU is created from its
+    /// current register state, and the launch measurement can't be extended
+    /// afterwards.
+    pub fn launch_finish(&self, vm_fd: &VmFd) -> std::io::Result<()> {
+        let finish = KvmSevSnpLaunchFinish::default();
+        self.command(
+            vm_fd,
+            SevCommand::SnpLaunchFinish,
+            &finish as *const _ as u64,
+        )
+    }
+}
+
+/// Whether the host can back the private memory of the guests with a
+/// guest_memfd, and convert it on request.
+pub fn check_private_memory(vm_fd: &VmFd) -> bool {
+    let check_extension = |cap: u64| {
+        // SAFETY: FFI call. The capability is passed by value.
+        unsafe { ioctl_with_val(vm_fd, KVM_CHECK_EXTENSION(), cap) }
+    };
+    check_extension(KVM_CAP_GUEST_MEMFD) > 0
+        && (check_extension(KVM_CAP_MEMORY_ATTRIBUTES) as u64 & KVM_MEMORY_ATTRIBUTE_PRIVATE) != 0
+}
+
+/// Creates the guest_memfd holding the private pages of a memory slot.
+pub fn create_guest_memfd(vm_fd: &VmFd, size: u64) -> std::io::Result<File> {
+    let gmem = KvmCreateGuestMemfd {
+        size,
+        ..Default::default()
+    };
+    // SAFETY: FFI call. All input parameters are valid.
+    let fd = unsafe { ioctl_with_ref(vm_fd, KVM_CREATE_GUEST_MEMFD(), &gmem) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: the file descriptor was just created, and is owned by the file
+    // from now on.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Creates, or removes with a size of zero, a memory slot whose private
+/// pages are backed by the guest_memfd, the shared ones by the mapping at
+/// `userspace_addr`.
+pub fn set_user_memory_region2(
+    vm_fd: &VmFd,
+    slot: u32,
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    guest_memfd: &File,
+) -> std::io::Result<()> {
+    let region = KvmUserspaceMemoryRegion2 {
+        slot,
+        flags: KVM_MEM_GUEST_MEMFD,
+        guest_phys_addr,
+        memory_size,
+        userspace_addr,
+        guest_memfd_offset: 0,
+        guest_memfd: guest_memfd.as_raw_fd() as u32,
+        ..Default::default()
+    };
+    // SAFETY: FFI call. All input parameters are valid, and the guest
+    // regions are guaranteed not to overlap.
+    let ret = unsafe { ioctl_with_ref(vm_fd, KVM_SET_USER_MEMORY_REGION2(), &region) };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Makes a range of guest memory private, backed by the guest_memfd of its
+/// slot, or shared, backed by the mapping of the VMM.
+pub fn set_memory_private(
+    vm_fd: &VmFd,
+    address: u64,
+    size: u64,
+    private: bool,
+) -> std::io::Result<()> {
+    let attributes = KvmMemoryAttributes {
+        address,
+        size,
+        attributes: if private {
+            KVM_MEMORY_ATTRIBUTE_PRIVATE
+        } else {
+            0
+        },
+        flags: 0,
+    };
+    // SAFETY: FFI call. All input parameters are valid.
+    let ret = unsafe { ioctl_with_ref(vm_fd, KVM_SET_MEMORY_ATTRIBUTES(), &attributes) };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sev_snp_conversion() {
+        assert_eq!(
+            SevSnpConversion::from_hypercall(&[0x10_0000, 4, KVM_MAP_GPA_RANGE_ENCRYPTED, 0, 0, 0]),
+            SevSnpConversion {
+                gpa: 0x10_0000,
+                size: 0x4000,
+                private: true,
+                implicit: false,
+            }
+        );
+        assert_eq!(
+            SevSnpConversion::from_hypercall(&[0x10_0000, 1, 0, 0, 0, 0]),
+            SevSnpConversion {
+                gpa: 0x10_0000,
+                size: 0x1000,
+                private: false,
+                implicit: false,
+            }
+        );
+        assert_eq!(
+            SevSnpConversion::from_memory_fault(KVM_MEMORY_EXIT_FLAG_PRIVATE, 0x2000, 0x1000),
+            SevSnpConversion {
+                gpa: 0x2000,
+                size: 0x1000,
+                private: true,
+                implicit: true,
+            }
+        );
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+//
+
+//! Offset of the guest TSC from the host TSC, through the KVM_VCPU_TSC_CTRL
+//! attributes of the vCPUs. Writing the TSC MSR of each vCPU one after the
+//! other leaves them a few cycles apart, which a guest checking the TSC
+//! synchronization notices. Giving all the vCPUs the same offset keeps their
+//! TSCs in sync.
+
+use kvm_bindings::{kvm_device_attr, KVMIO};
+use kvm_ioctls::VcpuFd;
+use std::io;
+use vmm_sys_util::ioctl::ioctl_with_ref;
+use vmm_sys_util::{ioctl_expr, ioctl_ioc_nr, ioctl_iow_nr};
+
+const KVM_VCPU_TSC_CTRL: u32 = 0;
+const KVM_VCPU_TSC_OFFSET: u64 = 0;
+
+ioctl_iow_nr!(KVM_SET_DEVICE_ATTR, KVMIO, 0xe1, kvm_device_attr);
+ioctl_iow_nr!(KVM_GET_DEVICE_ATTR, KVMIO, 0xe2, kvm_device_attr);
+ioctl_iow_nr!(KVM_HAS_DEVICE_ATTR, KVMIO, 0xe3, kvm_device_attr);
+
+fn tsc_offset_attr(addr: u64) -> kvm_device_attr {
+    kvm_device_attr {
+        flags: 0,
+        group: KVM_VCPU_TSC_CTRL,
+        attr: KVM_VCPU_TSC_OFFSET,
+        addr,
+    }
+}
+
+/// Returns the offset of the guest TSC of the vCPU, if the host supports
+/// controlling it.
+pub fn tsc_offset(vcpu_fd: &VcpuFd) -> io::Result<Option<u64>> {
+    let mut offset = 0u64;
+    let attr = tsc_offset_attr(&mut offset as *mut u64 as u64);
+
+    // SAFETY: FFI call with a valid attribute
+    if unsafe { ioctl_with_ref(vcpu_fd, KVM_HAS_DEVICE_ATTR(), &attr) } < 0 {
+        return Ok(None);
+    }
+
+    // SAFETY: FFI call with a valid attribute, KVM writes the offset to the
+    // u64 it points to.
+    if unsafe { ioctl_with_ref(vcpu_fd, KVM_GET_DEVICE_ATTR(), &attr) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(Some(offset))
+}
+
+/// Sets the offset of the guest TSC of the vCPU.
+pub fn set_tsc_offset(vcpu_fd: &VcpuFd, offset: u64) -> io::Result<()> {
+    let attr = tsc_offset_attr(&offset as *const u64 as u64);
+
+    // SAFETY: FFI call with a valid attribute
+    if unsafe { ioctl_with_ref(vcpu_fd, KVM_SET_DEVICE_ATTR(), &attr) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
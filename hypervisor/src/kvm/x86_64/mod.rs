@@ -147,4 +147,6 @@ pub struct VcpuKvmState {
     pub xsave: Xsave,
     pub xcrs: ExtendedControlRegisters,
     pub mp_state: MpState,
+    #[serde(default)]
+    pub tsc_khz: Option<u32>,
 }
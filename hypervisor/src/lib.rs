@@ -32,6 +32,10 @@ pub mod arch;
 /// KVM implementation module
 pub mod kvm;
 
+/// Mock implementation module, standing in for KVM in the tests
+#[cfg(all(feature = "mock", feature = "kvm", target_arch = "x86_64"))]
+pub mod mock;
+
 /// Microsoft Hypervisor implementation module
 #[cfg(all(feature = "mshv", target_arch = "x86_64"))]
 pub mod mshv;
@@ -0,0 +1,629 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+//
+
+//! Hypervisor standing in for KVM, so that the VMM can create, boot, pause,
+//! snapshot and shut VMs down where /dev/kvm isn't available, such as in CI.
+//!
+//! The guest never runs: the vCPUs keep the state they are given and exit
+//! without executing anything, and the VM accepts the memory regions, the
+//! interrupt routes and the devices without handing them to a kernel. The
+//! types are the ones of the KVM implementation, so that the VMM doesn't tell
+//! the two apart.
+
+use crate::arch::x86::msr_index;
+use crate::cpu::{self, Vcpu, VmExit};
+use crate::device::{self, Device};
+use crate::hypervisor::{self, Hypervisor};
+use crate::kvm::{
+    kvm_irq_routing_entry, Cap, ClockData, CpuState, CreateDevice, DeviceAttr, IoEventAddress,
+    IrqRoutingEntry, MemoryRegion, MpState, VcpuEvents, VmState, KVM_IRQ_ROUTING_IRQCHIP,
+    KVM_IRQ_ROUTING_MSI, KVM_MEM_LOG_DIRTY_PAGES, KVM_MEM_READONLY,
+};
+#[cfg(feature = "sev_snp")]
+use crate::kvm::{SevSnpConversion, SevSnpPageType};
+#[cfg(feature = "tdx")]
+use crate::kvm::{TdxCapabilities, TdxExitDetails, TdxExitStatus};
+use crate::vm::{self, DataMatch, InterruptSourceConfig, Vm, VmOps};
+use crate::x86_64::{
+    boot_msr_entries, CpuId, CpuIdEntry, ExtendedControlRegisters, FpuState, LapicState,
+    MsrEntries, MsrEntry, MsrList, SpecialRegisters, StandardRegisters, Xsave,
+};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use vm_memory::GuestAddress;
+use vmm_sys_util::eventfd::EventFd;
+
+// How long a vCPU pretends to run the guest before exiting, so that the vCPU
+// threads don't spin.
+const RUN_DURATION: Duration = Duration::from_millis(1);
+
+// CPUID of the host, restricted to the first subleaf of the basic and
+// extended leaves.
+fn host_cpuid() -> CpuId {
+    let mut entries = Vec::new();
+    for base in [0, 0x8000_0000] {
+        // SAFETY: CPUID is available on all x86_64 CPUs.
+        let max = unsafe { std::arch::x86_64::__cpuid(base) }.eax;
+        for function in base..=max {
+            // SAFETY: the leaf is within the range the CPU reports.
+            let leaf = unsafe { std::arch::x86_64::__cpuid_count(function, 0) };
+            entries.push(CpuIdEntry {
+                function,
+                index: 0,
+                eax: leaf.eax,
+                ebx: leaf.ebx,
+                ecx: leaf.ecx,
+                edx: leaf.edx,
+                ..Default::default()
+            });
+        }
+    }
+    CpuId::from_entries(&entries).unwrap()
+}
+
+/// Hypervisor creating mock VMs.
+#[derive(Default)]
+pub struct MockHypervisor {}
+
+impl MockHypervisor {
+    pub fn new() -> Self {
+        MockHypervisor {}
+    }
+}
+
+impl Hypervisor for MockHypervisor {
+    fn create_vm(&self) -> hypervisor::Result<Arc<dyn Vm>> {
+        Ok(Arc::new(MockVm::default()))
+    }
+
+    fn create_vm_with_type(&self, _vm_type: u64) -> hypervisor::Result<Arc<dyn Vm>> {
+        self.create_vm()
+    }
+
+    fn get_cpuid(&self) -> hypervisor::Result<CpuId> {
+        Ok(host_cpuid())
+    }
+
+    fn get_msr_list(&self) -> hypervisor::Result<MsrList> {
+        let mut indices: Vec<u32> = boot_msr_entries()
+            .as_slice()
+            .iter()
+            .map(|entry| entry.index)
+            .collect();
+        indices.push(msr_index::MSR_IA32_TSC);
+        Ok(MsrList::from_entries(&indices).unwrap())
+    }
+
+    #[cfg(feature = "tdx")]
+    fn tdx_capabilities(&self) -> hypervisor::Result<TdxCapabilities> {
+        Ok(TdxCapabilities::default())
+    }
+}
+
+/// VM accepting everything it is given, and creating mock vCPUs.
+#[derive(Default)]
+pub struct MockVm {
+    clock: Mutex<ClockData>,
+}
+
+impl Vm for MockVm {
+    fn set_identity_map_address(&self, _address: u64) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn set_tss_address(&self, _offset: usize) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn create_irq_chip(&self) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn register_irqfd(&self, _fd: &EventFd, _gsi: u32) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn unregister_irqfd(&self, _fd: &EventFd, _gsi: u32) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn create_vcpu(&self, _id: u8, _vm_ops: Option<Arc<dyn VmOps>>) -> vm::Result<Arc<dyn Vcpu>> {
+        Ok(Arc::new(MockVcpu::new()))
+    }
+
+    fn register_ioevent(
+        &self,
+        _fd: &EventFd,
+        _addr: &IoEventAddress,
+        _datamatch: Option<DataMatch>,
+    ) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn unregister_ioevent(&self, _fd: &EventFd, _addr: &IoEventAddress) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn make_routing_entry(&self, gsi: u32, config: &InterruptSourceConfig) -> IrqRoutingEntry {
+        let mut route = kvm_irq_routing_entry {
+            gsi,
+            ..Default::default()
+        };
+        match config {
+            InterruptSourceConfig::MsiIrq(cfg) => {
+                route.type_ = KVM_IRQ_ROUTING_MSI;
+                route.u.msi.address_lo = cfg.low_addr;
+                route.u.msi.address_hi = cfg.high_addr;
+                route.u.msi.data = cfg.data;
+            }
+            InterruptSourceConfig::LegacyIrq(cfg) => {
+                route.type_ = KVM_IRQ_ROUTING_IRQCHIP;
+                route.u.irqchip.irqchip = cfg.irqchip;
+                route.u.irqchip.pin = cfg.pin;
+            }
+        }
+        route
+    }
+
+    fn set_gsi_routing(&self, _entries: &[IrqRoutingEntry]) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn make_user_memory_region(
+        &self,
+        slot: u32,
+        guest_phys_addr: u64,
+        memory_size: u64,
+        userspace_addr: u64,
+        readonly: bool,
+        log_dirty_pages: bool,
+    ) -> MemoryRegion {
+        MemoryRegion {
+            slot,
+            guest_phys_addr,
+            memory_size,
+            userspace_addr,
+            flags: if readonly { KVM_MEM_READONLY } else { 0 }
+                | if log_dirty_pages {
+                    KVM_MEM_LOG_DIRTY_PAGES
+                } else {
+                    0
+                },
+        }
+    }
+
+    fn create_user_memory_region(&self, _user_memory_region: MemoryRegion) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn remove_user_memory_region(&self, _user_memory_region: MemoryRegion) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn create_device(&self, _device: &mut CreateDevice) -> vm::Result<Arc<dyn Device>> {
+        Ok(Arc::new(MockDevice::new().map_err(|e| {
+            vm::HypervisorVmError::CreateDevice(e.into())
+        })?))
+    }
+
+    fn enable_split_irq(&self) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn enable_sgx_attribute(&self, _file: File) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn inject_nmi(&self, _apic_id: u32) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn set_halt_poll_ns(&self, _ns: u32) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn get_clock(&self) -> vm::Result<ClockData> {
+        Ok(*self.clock.lock().unwrap())
+    }
+
+    fn set_clock(&self, data: &ClockData) -> vm::Result<()> {
+        *self.clock.lock().unwrap() = *data;
+        Ok(())
+    }
+
+    fn check_extension(&self, _c: Cap) -> bool {
+        false
+    }
+
+    fn create_passthrough_device(&self) -> vm::Result<Arc<dyn Device>> {
+        Ok(Arc::new(MockDevice::new().map_err(|e| {
+            vm::HypervisorVmError::CreatePassthroughDevice(e.into())
+        })?))
+    }
+
+    fn state(&self) -> vm::Result<VmState> {
+        Ok(VmState {})
+    }
+
+    fn set_state(&self, _state: VmState) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn start_dirty_log(&self) -> vm::Result<()> {
+        Ok(())
+    }
+
+    fn stop_dirty_log(&self) -> vm::Result<()> {
+        Ok(())
+    }
+
+    // No page is ever dirtied by the guest.
+    fn get_dirty_log(&self, _slot: u32, _base_gpa: u64, memory_size: u64) -> vm::Result<Vec<u64>> {
+        let pages = memory_size / 4096;
+        Ok(vec![0; ((pages + 63) / 64) as usize])
+    }
+
+    #[cfg(feature = "tdx")]
+    fn tdx_init(&self, _cpuid: &CpuId, _max_vcpus: u32) -> vm::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "tdx")]
+    fn tdx_finalize(&self) -> vm::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "tdx")]
+    fn tdx_init_memory_region(
+        &self,
+        _host_address: u64,
+        _guest_address: u64,
+        _size: u64,
+        _measure: bool,
+    ) -> vm::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_init(&self, _policy: u64) -> vm::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_set_memory_private(
+        &self,
+        _address: u64,
+        _size: u64,
+        _private: bool,
+    ) -> vm::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_launch_update(
+        &self,
+        _host_address: u64,
+        _guest_address: u64,
+        _size: u64,
+        _page_type: SevSnpPageType,
+    ) -> vm::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_finalize(&self) -> vm::Result<()> {
+        Ok(())
+    }
+}
+
+/// vCPU keeping the state it is given, and exiting without running the guest.
+pub struct MockVcpu {
+    state: Mutex<CpuState>,
+    msrs: Mutex<BTreeMap<u32, u64>>,
+    immediate_exit: AtomicBool,
+}
+
+impl MockVcpu {
+    pub fn new() -> Self {
+        MockVcpu {
+            state: Mutex::new(CpuState {
+                cpuid: CpuId::new(0).unwrap(),
+                msrs: MsrEntries::new(0).unwrap(),
+                vcpu_events: VcpuEvents::default(),
+                regs: StandardRegisters::default(),
+                sregs: SpecialRegisters::default(),
+                fpu: FpuState::default(),
+                lapic_state: LapicState::default(),
+                xsave: Xsave::default(),
+                xcrs: ExtendedControlRegisters::default(),
+                mp_state: MpState::default(),
+                tsc_khz: None,
+            }),
+            msrs: Mutex::new(BTreeMap::new()),
+            immediate_exit: AtomicBool::new(false),
+        }
+    }
+
+    fn set_msr_entries(&self, entries: &[MsrEntry]) -> usize {
+        let mut msrs = self.msrs.lock().unwrap();
+        for entry in entries {
+            msrs.insert(entry.index, entry.data);
+        }
+        entries.len()
+    }
+}
+
+impl Default for MockVcpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vcpu for MockVcpu {
+    fn get_regs(&self) -> cpu::Result<StandardRegisters> {
+        Ok(self.state.lock().unwrap().regs)
+    }
+
+    fn set_regs(&self, regs: &StandardRegisters) -> cpu::Result<()> {
+        self.state.lock().unwrap().regs = *regs;
+        Ok(())
+    }
+
+    fn get_sregs(&self) -> cpu::Result<SpecialRegisters> {
+        Ok(self.state.lock().unwrap().sregs)
+    }
+
+    fn set_sregs(&self, sregs: &SpecialRegisters) -> cpu::Result<()> {
+        self.state.lock().unwrap().sregs = *sregs;
+        Ok(())
+    }
+
+    fn get_fpu(&self) -> cpu::Result<FpuState> {
+        Ok(self.state.lock().unwrap().fpu)
+    }
+
+    fn set_fpu(&self, fpu: &FpuState) -> cpu::Result<()> {
+        self.state.lock().unwrap().fpu = *fpu;
+        Ok(())
+    }
+
+    fn set_cpuid2(&self, cpuid: &CpuId) -> cpu::Result<()> {
+        self.state.lock().unwrap().cpuid = cpuid.clone();
+        Ok(())
+    }
+
+    fn enable_hyperv_synic(&self) -> cpu::Result<()> {
+        Ok(())
+    }
+
+    fn get_cpuid2(&self, _num_entries: usize) -> cpu::Result<CpuId> {
+        Ok(self.state.lock().unwrap().cpuid.clone())
+    }
+
+    fn get_lapic(&self) -> cpu::Result<LapicState> {
+        Ok(self.state.lock().unwrap().lapic_state)
+    }
+
+    fn set_lapic(&self, lapic: &LapicState) -> cpu::Result<()> {
+        self.state.lock().unwrap().lapic_state = *lapic;
+        Ok(())
+    }
+
+    // The MSRs never set read as 0, as if the guest never wrote them.
+    fn get_msrs(&self, msrs: &mut MsrEntries) -> cpu::Result<usize> {
+        let values = self.msrs.lock().unwrap();
+        for entry in msrs.as_mut_slice() {
+            entry.data = values.get(&entry.index).copied().unwrap_or_default();
+        }
+        Ok(msrs.as_slice().len())
+    }
+
+    fn set_msrs(&self, msrs: &MsrEntries) -> cpu::Result<usize> {
+        Ok(self.set_msr_entries(msrs.as_slice()))
+    }
+
+    fn get_mp_state(&self) -> cpu::Result<MpState> {
+        Ok(self.state.lock().unwrap().mp_state)
+    }
+
+    fn set_mp_state(&self, mp_state: MpState) -> cpu::Result<()> {
+        self.state.lock().unwrap().mp_state = mp_state;
+        Ok(())
+    }
+
+    fn get_xsave(&self) -> cpu::Result<Xsave> {
+        Ok(self.state.lock().unwrap().xsave)
+    }
+
+    fn set_xsave(&self, xsave: &Xsave) -> cpu::Result<()> {
+        self.state.lock().unwrap().xsave = *xsave;
+        Ok(())
+    }
+
+    fn get_xcrs(&self) -> cpu::Result<ExtendedControlRegisters> {
+        Ok(self.state.lock().unwrap().xcrs)
+    }
+
+    fn set_xcrs(&self, xcrs: &ExtendedControlRegisters) -> cpu::Result<()> {
+        self.state.lock().unwrap().xcrs = *xcrs;
+        Ok(())
+    }
+
+    fn get_vcpu_events(&self) -> cpu::Result<VcpuEvents> {
+        Ok(self.state.lock().unwrap().vcpu_events)
+    }
+
+    fn set_vcpu_events(&self, events: &VcpuEvents) -> cpu::Result<()> {
+        self.state.lock().unwrap().vcpu_events = *events;
+        Ok(())
+    }
+
+    fn notify_guest_clock_paused(&self) -> cpu::Result<()> {
+        Ok(())
+    }
+
+    fn set_guest_debug(&self, _addrs: &[GuestAddress], _singlestep: bool) -> cpu::Result<()> {
+        Ok(())
+    }
+
+    fn tsc_khz(&self) -> cpu::Result<Option<u32>> {
+        Ok(self.state.lock().unwrap().tsc_khz)
+    }
+
+    fn set_tsc_khz(&self, freq: u32) -> cpu::Result<()> {
+        self.state.lock().unwrap().tsc_khz = Some(freq);
+        Ok(())
+    }
+
+    fn guest_counter(&self) -> cpu::Result<Option<u64>> {
+        let msrs = self.msrs.lock().unwrap();
+        Ok(Some(
+            msrs.get(&msr_index::MSR_IA32_TSC)
+                .copied()
+                .unwrap_or_default(),
+        ))
+    }
+
+    fn set_guest_counter(&self, value: u64) -> cpu::Result<()> {
+        self.msrs
+            .lock()
+            .unwrap()
+            .insert(msr_index::MSR_IA32_TSC, value);
+        Ok(())
+    }
+
+    fn state(&self) -> cpu::Result<CpuState> {
+        let entries: Vec<MsrEntry> = self
+            .msrs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(index, data)| MsrEntry {
+                index: *index,
+                data: *data,
+                ..Default::default()
+            })
+            .collect();
+
+        let mut state = self.state.lock().unwrap().clone();
+        state.msrs = MsrEntries::from_entries(&entries).unwrap();
+        Ok(state)
+    }
+
+    fn set_state(&self, state: &CpuState) -> cpu::Result<()> {
+        self.set_msr_entries(state.msrs.as_slice());
+        *self.state.lock().unwrap() = state.clone();
+        Ok(())
+    }
+
+    // Wait a bit, unless asked to exit right away, and exit without any
+    // guest instruction having run.
+    fn run(&self) -> std::result::Result<VmExit, cpu::HypervisorCpuError> {
+        if !self.immediate_exit.load(Ordering::SeqCst) {
+            thread::sleep(RUN_DURATION);
+        }
+        Ok(VmExit::Ignore)
+    }
+
+    fn translate_gva(&self, gva: u64, _flags: u64) -> cpu::Result<(u64, u32)> {
+        Ok((gva, 0))
+    }
+
+    #[cfg(feature = "tdx")]
+    fn tdx_init(&self, _hob_address: u64) -> cpu::Result<()> {
+        Ok(())
+    }
+
+    fn set_immediate_exit(&self, exit: bool) {
+        self.immediate_exit.store(exit, Ordering::SeqCst);
+    }
+
+    #[cfg(feature = "tdx")]
+    fn get_tdx_exit_details(&mut self) -> cpu::Result<TdxExitDetails> {
+        Err(cpu::HypervisorCpuError::UnknownTdxVmCall)
+    }
+
+    #[cfg(feature = "tdx")]
+    fn set_tdx_status(&mut self, _status: TdxExitStatus) {}
+
+    #[cfg(feature = "sev_snp")]
+    fn get_sev_snp_conversion(&mut self) -> cpu::Result<SevSnpConversion> {
+        Err(cpu::HypervisorCpuError::SevSnpConversion(anyhow!(
+            "No conversion pending"
+        )))
+    }
+
+    #[cfg(feature = "sev_snp")]
+    fn set_sev_snp_conversion_result(&mut self, _success: bool) {}
+}
+
+/// In-kernel device backed by an event file descriptor, ignoring its
+/// attributes.
+pub struct MockDevice {
+    fd: EventFd,
+}
+
+impl MockDevice {
+    fn new() -> std::io::Result<Self> {
+        Ok(MockDevice {
+            fd: EventFd::new(libc::EFD_NONBLOCK)?,
+        })
+    }
+}
+
+impl AsRawFd for MockDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl Device for MockDevice {
+    fn set_device_attr(&self, _attr: &DeviceAttr) -> device::Result<()> {
+        Ok(())
+    }
+
+    fn get_device_attr(&self, _attr: &mut DeviceAttr) -> device::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_vcpu_state() {
+        let hv = MockHypervisor::new();
+        let vm = hv.create_vm().unwrap();
+        let vcpu = vm.create_vcpu(0, None).unwrap();
+
+        let cpuid = hv.get_cpuid().unwrap();
+        vcpu.set_cpuid2(&cpuid).unwrap();
+        vcpu.set_msrs(&boot_msr_entries()).unwrap();
+        vcpu.set_guest_counter(42).unwrap();
+        let mut regs = vcpu.get_regs().unwrap();
+        regs.rip = 0x1000;
+        vcpu.set_regs(&regs).unwrap();
+
+        // The state round-trips through a fresh vCPU.
+        let state = vcpu.state().unwrap();
+        let other = vm.create_vcpu(1, None).unwrap();
+        other.set_state(&state).unwrap();
+        assert_eq!(other.get_regs().unwrap().rip, 0x1000);
+        assert_eq!(other.guest_counter().unwrap(), Some(42));
+        assert_eq!(
+            other.get_cpuid2(0).unwrap().as_slice().len(),
+            cpuid.as_slice().len()
+        );
+
+        vcpu.set_immediate_exit(true);
+        assert!(matches!(vcpu.run(), Ok(VmExit::Ignore)));
+    }
+}
@@ -851,6 +851,25 @@ impl vm::Vm for MshvVm {
     fn enable_sgx_attribute(&self, _file: File) -> vm::Result<()> {
         Ok(())
     }
+    #[cfg(target_arch = "x86_64")]
+    fn inject_nmi(&self, apic_id: u32) -> vm::Result<()> {
+        let request = InterruptRequest {
+            interrupt_type: hv_interrupt_type_HV_X64_INTERRUPT_TYPE_NMI,
+            apic_id: apic_id as u64,
+            vector: 0,
+            level_triggered: false,
+            logical_destination_mode: false,
+            long_mode: false,
+        };
+        self.fd
+            .request_virtual_interrupt(&request)
+            .map_err(|e| vm::HypervisorVmError::InjectNmi(e.into()))
+    }
+    fn set_halt_poll_ns(&self, _ns: u32) -> vm::Result<()> {
+        Err(vm::HypervisorVmError::SetHaltPoll(anyhow!(
+            "Halt polling is not supported by MSHV"
+        )))
+    }
     fn register_ioevent(
         &self,
         fd: &EventFd,
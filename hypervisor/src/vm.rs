@@ -16,6 +16,8 @@ use crate::cpu::Vcpu;
 use crate::device::Device;
 #[cfg(feature = "kvm")]
 use crate::kvm::KvmVmState as VmState;
+#[cfg(feature = "sev_snp")]
+use crate::kvm::SevSnpPageType;
 #[cfg(feature = "mshv")]
 use crate::mshv::HvState as VmState;
 #[cfg(feature = "tdx")]
@@ -214,11 +216,45 @@ pub enum HypervisorVmError {
     ///
     #[error("Failed to initialize memory region TDX: {0}")]
     InitMemRegionTdx(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error initializing SEV-SNP on the VM
+    ///
+    #[error("Failed to initialize SEV-SNP: {0}")]
+    InitializeSevSnp(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error converting memory between private and shared
+    ///
+    #[error("Failed to convert SEV-SNP memory: {0}")]
+    ConvertSevSnpMemory(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error adding pages to the memory of a SEV-SNP VM
+    ///
+    #[error("Failed to update SEV-SNP launch memory: {0}")]
+    LaunchUpdateSevSnp(#[source] std::io::Error),
+    #[cfg(feature = "sev_snp")]
+    ///
+    /// Error finalizing the SEV-SNP launch
+    ///
+    #[error("Failed to finalize SEV-SNP: {0}")]
+    FinalizeSevSnp(#[source] std::io::Error),
     ///
     /// Create Vgic error
     ///
     #[error("Failed to create Vgic: {0}")]
     CreateVgic(#[source] anyhow::Error),
+    ///
+    /// Error injecting an NMI
+    ///
+    #[error("Failed to inject NMI: {0}")]
+    InjectNmi(#[source] anyhow::Error),
+    ///
+    /// Error setting the halt polling time
+    ///
+    #[error("Failed to set the halt polling time: {0}")]
+    SetHaltPoll(#[source] anyhow::Error),
 }
 ///
 /// Result type for returning from a function
@@ -326,6 +362,13 @@ pub trait Vm: Send + Sync {
     fn enable_split_irq(&self) -> Result<()>;
     #[cfg(target_arch = "x86_64")]
     fn enable_sgx_attribute(&self, file: File) -> Result<()>;
+    /// Injects an NMI into the vCPU with the given APIC ID, whether it is
+    /// running or not.
+    #[cfg(target_arch = "x86_64")]
+    fn inject_nmi(&self, apic_id: u32) -> Result<()>;
+    /// Sets the maximum time, in nanoseconds, a halted vCPU polls for a
+    /// wake up before yielding its host CPU.
+    fn set_halt_poll_ns(&self, ns: u32) -> Result<()>;
     /// Retrieve guest clock.
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
     fn get_clock(&self) -> Result<ClockData>;
@@ -362,6 +405,24 @@ pub trait Vm: Send + Sync {
         size: u64,
         measure: bool,
     ) -> Result<()>;
+    #[cfg(feature = "sev_snp")]
+    /// Initialize SEV-SNP on this VM and start the launch with the given policy
+    fn sev_snp_init(&self, policy: u64) -> Result<()>;
+    #[cfg(feature = "sev_snp")]
+    /// Make a range of guest memory private or shared
+    fn sev_snp_set_memory_private(&self, address: u64, size: u64, private: bool) -> Result<()>;
+    #[cfg(feature = "sev_snp")]
+    /// Encrypt guest memory pages before launching the SEV-SNP VM
+    fn sev_snp_launch_update(
+        &self,
+        host_address: u64,
+        guest_address: u64,
+        size: u64,
+        page_type: SevSnpPageType,
+    ) -> Result<()>;
+    #[cfg(feature = "sev_snp")]
+    /// Finalize the SEV-SNP launch, creating the VMSA of each vCPU
+    fn sev_snp_finalize(&self) -> Result<()>;
 }
 
 pub trait VmOps: Send + Sync {
@@ -5,6 +5,7 @@
 use crate::GuestMemoryMmap;
 use crate::Tap;
 use libc::c_uint;
+use std::mem::size_of;
 use std::sync::Arc;
 use virtio_bindings::bindings::virtio_net::{
     VIRTIO_NET_CTRL_GUEST_OFFLOADS, VIRTIO_NET_CTRL_GUEST_OFFLOADS_SET, VIRTIO_NET_CTRL_MQ,
@@ -15,7 +16,14 @@ use virtio_bindings::bindings::virtio_net::{
 };
 use virtio_queue::Queue;
 use vm_memory::{ByteValued, Bytes, GuestMemoryAtomic, GuestMemoryError};
-use vm_virtio::{AccessPlatform, Translatable};
+use vm_virtio::chain::{ChainLimits, ChainValidator};
+
+/// Feature letting the driver coalesce the notifications of the rx and tx
+/// queues through the control queue.
+pub const VIRTIO_NET_F_NOTF_COAL: u32 = 53;
+const VIRTIO_NET_CTRL_NOTF_COAL: u32 = 6;
+const VIRTIO_NET_CTRL_NOTF_COAL_TX_SET: u32 = 0;
+const VIRTIO_NET_CTRL_NOTF_COAL_RX_SET: u32 = 1;
 
 #[derive(Debug)]
 pub enum Error {
@@ -47,40 +55,76 @@ pub struct ControlHeader {
 // SAFETY: ControlHeader only contains a series of integers
 unsafe impl ByteValued for ControlHeader {}
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct NotfCoal {
+    max_packets: u32,
+    usecs: u32,
+}
+
+// SAFETY: NotfCoal only contains a series of integers
+unsafe impl ByteValued for NotfCoal {}
+
+/// Coalescing of the notifications of the rx or tx queues requested by the
+/// driver, a `usecs` of 0 disabling it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CtrlNotfCoal {
+    pub rx: bool,
+    pub max_packets: u32,
+    pub usecs: u32,
+}
+
 pub struct CtrlQueue {
     pub taps: Vec<Tap>,
+    // Commands are expected to start with their header and end with their
+    // status.
+    pub chain_validator: ChainValidator,
+    // Applies the notifications coalescing, for the devices offering
+    // VIRTIO_NET_F_NOTF_COAL.
+    pub notf_coal: Option<Box<dyn FnMut(CtrlNotfCoal) + Send>>,
 }
 
 impl CtrlQueue {
     pub fn new(taps: Vec<Tap>) -> Self {
-        CtrlQueue { taps }
+        CtrlQueue {
+            taps,
+            chain_validator: ChainValidator::new(
+                ChainLimits {
+                    min_readable: size_of::<ControlHeader>() as u64,
+                    min_writable: 1,
+                    ..Default::default()
+                },
+                None,
+                Arc::default(),
+            ),
+            notf_coal: None,
+        }
     }
 
-    pub fn process(
-        &mut self,
-        queue: &mut Queue<GuestMemoryAtomic<GuestMemoryMmap>>,
-        access_platform: Option<&Arc<dyn AccessPlatform>>,
-    ) -> Result<()> {
+    pub fn process(&mut self, queue: &mut Queue<GuestMemoryAtomic<GuestMemoryMmap>>) -> Result<()> {
         let mut used_desc_heads = Vec::new();
         loop {
             for mut desc_chain in queue.iter().map_err(Error::QueueIterator)? {
-                let ctrl_desc = desc_chain.next().ok_or(Error::NoControlHeaderDescriptor)?;
+                let valid_chain = match self.chain_validator.validate(&mut desc_chain) {
+                    Ok(valid_chain) => valid_chain,
+                    Err(_) => {
+                        used_desc_heads.push((desc_chain.head_index(), 0));
+                        continue;
+                    }
+                };
+                let mut descs = valid_chain.descriptors().iter();
+
+                let ctrl_desc = descs.next().ok_or(Error::NoControlHeaderDescriptor)?;
 
                 let ctrl_hdr: ControlHeader = desc_chain
                     .memory()
-                    .read_obj(
-                        ctrl_desc
-                            .addr()
-                            .translate_gva(access_platform, ctrl_desc.len() as usize),
-                    )
+                    .read_obj(ctrl_desc.addr())
                     .map_err(Error::GuestMemory)?;
-                let data_desc = desc_chain.next().ok_or(Error::NoDataDescriptor)?;
+                let data_desc = descs.next().ok_or(Error::NoDataDescriptor)?;
 
-                let data_desc_addr = data_desc
-                    .addr()
-                    .translate_gva(access_platform, data_desc.len() as usize);
+                let data_desc_addr = data_desc.addr();
 
-                let status_desc = desc_chain.next().ok_or(Error::NoStatusDescriptor)?;
+                let status_desc = descs.next().ok_or(Error::NoStatusDescriptor)?;
 
                 let ok = match u32::from(ctrl_hdr.class) {
                     VIRTIO_NET_CTRL_MQ => {
@@ -123,6 +167,31 @@ impl CtrlQueue {
                             ok
                         }
                     }
+                    VIRTIO_NET_CTRL_NOTF_COAL if self.notf_coal.is_some() => {
+                        let coal = desc_chain
+                            .memory()
+                            .read_obj::<NotfCoal>(data_desc_addr)
+                            .map_err(Error::GuestMemory)?;
+                        let rx = match u32::from(ctrl_hdr.cmd) {
+                            VIRTIO_NET_CTRL_NOTF_COAL_TX_SET => Some(false),
+                            VIRTIO_NET_CTRL_NOTF_COAL_RX_SET => Some(true),
+                            _ => None,
+                        };
+                        match (rx, self.notf_coal.as_mut()) {
+                            (Some(rx), Some(notf_coal)) => {
+                                notf_coal(CtrlNotfCoal {
+                                    rx,
+                                    max_packets: u32::from_le(coal.max_packets),
+                                    usecs: u32::from_le(coal.usecs),
+                                });
+                                true
+                            }
+                            _ => {
+                                warn!("Unsupported command: {}", ctrl_hdr.cmd);
+                                false
+                            }
+                        }
+                    }
                     _ => {
                         warn!("Unsupported command {:?}", ctrl_hdr);
                         false
@@ -133,9 +202,7 @@ impl CtrlQueue {
                     .memory()
                     .write_obj(
                         if ok { VIRTIO_NET_OK } else { VIRTIO_NET_ERR } as u8,
-                        status_desc
-                            .addr()
-                            .translate_gva(access_platform, status_desc.len() as usize),
+                        status_desc.addr(),
                     )
                     .map_err(Error::GuestMemory)?;
                 let len = ctrl_desc.len() + data_desc.len() + status_desc.len();
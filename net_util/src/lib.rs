@@ -34,7 +34,7 @@ use vm_memory::{bitmap::AtomicBitmap, ByteValued};
 
 type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
 
-pub use ctrl_queue::{CtrlQueue, Error as CtrlQueueError};
+pub use ctrl_queue::{CtrlNotfCoal, CtrlQueue, Error as CtrlQueueError, VIRTIO_NET_F_NOTF_COAL};
 pub use mac::{MacAddr, MAC_ADDR_LEN};
 pub use open_tap::{open_tap, Error as OpenTapError};
 pub use queue_pair::{NetCounters, NetQueuePair, NetQueuePairError, RxVirtio, TxVirtio};
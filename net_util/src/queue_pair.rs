@@ -12,12 +12,18 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use virtio_queue::Queue;
 use vm_memory::{Bytes, GuestMemory, GuestMemoryAtomic};
-use vm_virtio::{AccessPlatform, Translatable};
+use vm_virtio::chain::{ChainLimits, ChainValidator};
+#[cfg(feature = "fault_injection")]
+use vm_virtio::fault_injection::Faults;
 
 #[derive(Clone)]
 pub struct TxVirtio {
     pub counter_bytes: Wrapping<u64>,
     pub counter_frames: Wrapping<u64>,
+    // Frames are expected to start with their virtio-net header.
+    pub chain_validator: ChainValidator,
+    #[cfg(feature = "fault_injection")]
+    pub faults: Option<Arc<Faults>>,
 }
 
 impl Default for TxVirtio {
@@ -31,6 +37,16 @@ impl TxVirtio {
         TxVirtio {
             counter_bytes: Wrapping(0),
             counter_frames: Wrapping(0),
+            chain_validator: ChainValidator::new(
+                ChainLimits {
+                    min_readable: vnet_hdr_len() as u64,
+                    ..Default::default()
+                },
+                None,
+                Arc::default(),
+            ),
+            #[cfg(feature = "fault_injection")]
+            faults: None,
         }
     }
 
@@ -39,7 +55,6 @@ impl TxVirtio {
         tap: &mut Tap,
         queue: &mut Queue<GuestMemoryAtomic<GuestMemoryMmap>>,
         rate_limiter: &mut Option<RateLimiter>,
-        access_platform: Option<&Arc<dyn AccessPlatform>>,
     ) -> Result<bool, NetQueuePairError> {
         let mut retry_write = false;
         let mut rate_limit_reached = false;
@@ -56,13 +71,15 @@ impl TxVirtio {
                     break;
                 }
 
-                let mut next_desc = desc_chain.next();
+                // A rejected chain is returned without sending anything.
+                let valid_chain = self
+                    .chain_validator
+                    .validate(&mut desc_chain)
+                    .unwrap_or_default();
 
                 let mut iovecs = Vec::new();
-                while let Some(desc) = next_desc {
-                    let desc_addr = desc
-                        .addr()
-                        .translate_gva(access_platform, desc.len() as usize);
+                for desc in valid_chain.descriptors() {
+                    let desc_addr = desc.addr();
                     if !desc.is_write_only() && desc.len() > 0 {
                         let buf = desc_chain
                             .memory()
@@ -83,7 +100,12 @@ impl TxVirtio {
                         );
                         return Err(NetQueuePairError::DescriptorChainInvalid);
                     }
-                    next_desc = desc_chain.next();
+                }
+
+                // The dropped packet is completed as if it had been sent.
+                #[cfg(feature = "fault_injection")]
+                if self.faults.as_ref().map_or(false, |f| f.drop_packet()) {
+                    iovecs.clear();
                 }
 
                 let len = if !iovecs.is_empty() {
@@ -148,6 +170,10 @@ impl TxVirtio {
 pub struct RxVirtio {
     pub counter_bytes: Wrapping<u64>,
     pub counter_frames: Wrapping<u64>,
+    // Frames are expected to start with their virtio-net header.
+    pub chain_validator: ChainValidator,
+    #[cfg(feature = "fault_injection")]
+    pub faults: Option<Arc<Faults>>,
 }
 
 impl Default for RxVirtio {
@@ -161,6 +187,16 @@ impl RxVirtio {
         RxVirtio {
             counter_bytes: Wrapping(0),
             counter_frames: Wrapping(0),
+            chain_validator: ChainValidator::new(
+                ChainLimits {
+                    min_writable: vnet_hdr_len() as u64,
+                    ..Default::default()
+                },
+                None,
+                Arc::default(),
+            ),
+            #[cfg(feature = "fault_injection")]
+            faults: None,
         }
     }
 
@@ -169,7 +205,6 @@ impl RxVirtio {
         tap: &mut Tap,
         queue: &mut Queue<GuestMemoryAtomic<GuestMemoryMmap>>,
         rate_limiter: &mut Option<RateLimiter>,
-        access_platform: Option<&Arc<dyn AccessPlatform>>,
     ) -> Result<bool, NetQueuePairError> {
         let mut exhausted_descs = true;
         let mut rate_limit_reached = false;
@@ -187,25 +222,25 @@ impl RxVirtio {
                     break;
                 }
 
-                let desc = desc_chain
-                    .next()
-                    .ok_or(NetQueuePairError::DescriptorChainTooShort)?;
+                // A rejected chain is returned without receiving anything.
+                let valid_chain = self
+                    .chain_validator
+                    .validate(&mut desc_chain)
+                    .unwrap_or_default();
 
-                let num_buffers_addr = desc_chain
-                    .memory()
-                    .checked_offset(
-                        desc.addr()
-                            .translate_gva(access_platform, desc.len() as usize),
-                        10,
-                    )
-                    .unwrap();
-                let mut next_desc = Some(desc);
+                let num_buffers_addr = match valid_chain.descriptors().first() {
+                    Some(desc) => Some(
+                        desc_chain
+                            .memory()
+                            .checked_offset(desc.addr(), 10)
+                            .ok_or(NetQueuePairError::DescriptorChainInvalid)?,
+                    ),
+                    None => None,
+                };
 
                 let mut iovecs = Vec::new();
-                while let Some(desc) = next_desc {
-                    let desc_addr = desc
-                        .addr()
-                        .translate_gva(access_platform, desc.len() as usize);
+                for desc in valid_chain.descriptors() {
+                    let desc_addr = desc.addr();
                     if desc.is_write_only() && desc.len() > 0 {
                         let buf = desc_chain
                             .memory()
@@ -226,7 +261,6 @@ impl RxVirtio {
                         );
                         return Err(NetQueuePairError::DescriptorChainInvalid);
                     }
-                    next_desc = desc_chain.next();
                 }
 
                 let len = if !iovecs.is_empty() {
@@ -251,11 +285,19 @@ impl RxVirtio {
                         return Err(NetQueuePairError::ReadTap(e));
                     }
 
+                    // The descriptor chain is reused for the next frame, so
+                    // that the guest never sees the dropped one.
+                    #[cfg(feature = "fault_injection")]
+                    if self.faults.as_ref().map_or(false, |f| f.drop_packet()) {
+                        avail_iter.go_to_previous_position();
+                        continue;
+                    }
+
                     // Write num_buffers to guest memory. We simply write 1 as we
                     // never spread the frame over more than one descriptor chain.
                     desc_chain
                         .memory()
-                        .write_obj(1u16, num_buffers_addr)
+                        .write_obj(1u16, num_buffers_addr.unwrap())
                         .map_err(NetQueuePairError::GuestMemory)?;
 
                     self.counter_bytes += Wrapping(result as u64 - vnet_hdr_len() as u64);
@@ -319,8 +361,6 @@ pub enum NetQueuePairError {
     GuestMemory(vm_memory::GuestMemoryError),
     /// Returned an error while iterating through the queue
     QueueIteratorFailed(virtio_queue::Error),
-    /// Descriptor chain is too short
-    DescriptorChainTooShort,
     /// Descriptor chain does not contain valid descriptors
     DescriptorChainInvalid,
     /// Failed to determine if queue needed notification
@@ -349,7 +389,6 @@ pub struct NetQueuePair {
     pub rx_desc_avail: bool,
     pub rx_rate_limiter: Option<RateLimiter>,
     pub tx_rate_limiter: Option<RateLimiter>,
-    pub access_platform: Option<Arc<dyn AccessPlatform>>,
 }
 
 impl NetQueuePair {
@@ -357,12 +396,9 @@ impl NetQueuePair {
         &mut self,
         queue: &mut Queue<GuestMemoryAtomic<GuestMemoryMmap>>,
     ) -> Result<bool, NetQueuePairError> {
-        let tx_tap_retry = self.tx.process_desc_chain(
-            &mut self.tap,
-            queue,
-            &mut self.tx_rate_limiter,
-            self.access_platform.as_ref(),
-        )?;
+        let tx_tap_retry =
+            self.tx
+                .process_desc_chain(&mut self.tap, queue, &mut self.tx_rate_limiter)?;
 
         // We got told to try again when writing to the tap. Wait for the TAP to be writable
         if tx_tap_retry && !self.tx_tap_listening {
@@ -405,12 +441,10 @@ impl NetQueuePair {
         &mut self,
         queue: &mut Queue<GuestMemoryAtomic<GuestMemoryMmap>>,
     ) -> Result<bool, NetQueuePairError> {
-        self.rx_desc_avail = !self.rx.process_desc_chain(
-            &mut self.tap,
-            queue,
-            &mut self.rx_rate_limiter,
-            self.access_platform.as_ref(),
-        )?;
+        self.rx_desc_avail =
+            !self
+                .rx
+                .process_desc_chain(&mut self.tap, queue, &mut self.rx_rate_limiter)?;
         let rate_limit_reached = self
             .rx_rate_limiter
             .as_ref()
@@ -369,6 +369,11 @@ impl Tap {
     pub fn get_if_name(&self) -> Vec<u8> {
         self.if_name.clone()
     }
+
+    /// File of the tap queue, to hand it over to a vhost-net backend.
+    pub fn file(&self) -> &File {
+        &self.tap_file
+    }
 }
 
 impl Read for Tap {
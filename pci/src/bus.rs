@@ -17,6 +17,10 @@ use vm_device::{Bus, BusDevice};
 const VENDOR_ID_INTEL: u16 = 0x8086;
 const DEVICE_ID_INTEL_VIRT_PCIE_HOST: u16 = 0x0d57;
 const NUM_DEVICE_IDS: usize = 32;
+// Register holding the header type, and the bit of the header type flagging
+// multi-function devices.
+const HEADER_TYPE_REG: usize = 3;
+const HEADER_TYPE_MULTIFUNCTION: u32 = 0x0080_0000;
 
 /// Errors for device manager.
 #[derive(Debug)]
@@ -35,6 +39,8 @@ pub enum PciRootError {
     InvalidPciDeviceSlot(usize),
     /// Valid PCI device identifier but already used.
     AlreadyInUsePciDeviceSlot(usize),
+    /// Function already used, or added to a slot without function 0.
+    InvalidPciFunction(usize, usize),
 }
 pub type Result<T> = std::result::Result<T, PciRootError>;
 
@@ -95,7 +101,8 @@ impl PciDevice for PciRoot {
 }
 
 pub struct PciBus {
-    /// Devices attached to this bus.
+    /// Devices attached to this bus, indexed by their device and function
+    /// numbers (devfn).
     /// Device 0 is host bridge.
     devices: HashMap<u32, Arc<Mutex<dyn PciDevice>>>,
     device_reloc: Arc<dyn DeviceRelocation>,
@@ -120,19 +127,16 @@ impl PciBus {
     pub fn register_mapping(
         &self,
         dev: Arc<Mutex<dyn BusDevice>>,
-        #[cfg(target_arch = "x86_64")] io_bus: &Bus,
+        io_bus: &Bus,
         mmio_bus: &Bus,
         bars: Vec<PciBarConfiguration>,
     ) -> Result<()> {
         for bar in bars {
             match bar.region_type() {
                 PciBarRegionType::IoRegion => {
-                    #[cfg(target_arch = "x86_64")]
                     io_bus
                         .insert(dev.clone(), bar.addr(), bar.size())
                         .map_err(PciRootError::PioInsert)?;
-                    #[cfg(target_arch = "aarch64")]
-                    error!("I/O region is not supported");
                 }
                 PciBarRegionType::Memory32BitRegion | PciBarRegionType::Memory64BitRegion => {
                     mmio_bus
@@ -145,10 +149,62 @@ impl PciBus {
     }
 
     pub fn add_device(&mut self, device_id: u32, device: Arc<Mutex<dyn PciDevice>>) -> Result<()> {
-        self.devices.insert(device_id, device);
+        self.add_function(device_id, 0, device)
+    }
+
+    /// Add a device as one of the functions of the given slot. Functions
+    /// other than 0 can only be added once function 0 is present.
+    pub fn add_function(
+        &mut self,
+        device_id: u32,
+        function: u32,
+        device: Arc<Mutex<dyn PciDevice>>,
+    ) -> Result<()> {
+        let key = devfn(device_id as usize, function as usize);
+        if self.devices.contains_key(&key)
+            || (function != 0 && !self.devices.contains_key(&devfn(device_id as usize, 0)))
+        {
+            return Err(PciRootError::InvalidPciFunction(
+                device_id as usize,
+                function as usize,
+            ));
+        }
+
+        self.devices.insert(key, device);
         Ok(())
     }
 
+    /// Whether functions other than function 0 are present in the slot.
+    pub fn is_multifunction(&self, device_id: u32) -> bool {
+        (1..8).any(|function| {
+            self.devices
+                .contains_key(&devfn(device_id as usize, function))
+        })
+    }
+
+    fn read_config_register(&self, device: usize, function: usize, register: usize) -> u32 {
+        let value = self
+            .devices
+            .get(&devfn(device, function))
+            .map_or(0xffff_ffff, |d| {
+                d.lock().unwrap().read_config_register(register)
+            });
+
+        // The guest only looks for other functions if function 0 reports
+        // being part of a multi-function device, which emulated devices
+        // don't, and which depends on the host topology for passed through
+        // ones.
+        if function == 0 && register == HEADER_TYPE_REG && value != 0xffff_ffff {
+            if self.is_multifunction(device as u32) {
+                return value | HEADER_TYPE_MULTIFUNCTION;
+            } else {
+                return value & !HEADER_TYPE_MULTIFUNCTION;
+            }
+        }
+
+        value
+    }
+
     pub fn remove_by_device(&mut self, device: &Arc<Mutex<dyn PciDevice>>) -> Result<()> {
         self.devices.retain(|_, dev| !Arc::ptr_eq(dev, device));
         Ok(())
@@ -216,20 +272,11 @@ impl PciConfigIo {
             return 0xffff_ffff;
         }
 
-        // Don't support multi-function devices.
-        if function > 0 {
-            return 0xffff_ffff;
-        }
-
         self.pci_bus
             .as_ref()
             .lock()
             .unwrap()
-            .devices
-            .get(&(device as u32))
-            .map_or(0xffff_ffff, |d| {
-                d.lock().unwrap().read_config_register(register)
-            })
+            .read_config_register(device, function, register)
     }
 
     pub fn config_space_write(&mut self, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
@@ -242,7 +289,7 @@ impl PciConfigIo {
             return None;
         }
 
-        let (bus, device, _function, register) =
+        let (bus, device, function, register) =
             parse_io_config_address(self.config_address & !0x8000_0000);
 
         // Only support one bus.
@@ -251,7 +298,7 @@ impl PciConfigIo {
         }
 
         let pci_bus = self.pci_bus.as_ref().lock().unwrap();
-        if let Some(d) = pci_bus.devices.get(&(device as u32)) {
+        if let Some(d) = pci_bus.devices.get(&devfn(device, function)) {
             let mut device = d.lock().unwrap();
 
             // Find out if one of the device's BAR is being reprogrammed, and
@@ -345,7 +392,7 @@ impl PciConfigMmio {
     }
 
     fn config_space_read(&self, config_address: u32) -> u32 {
-        let (bus, device, _function, register) = parse_mmio_config_address(config_address);
+        let (bus, device, function, register) = parse_mmio_config_address(config_address);
 
         // Only support one bus.
         if bus != 0 {
@@ -355,11 +402,7 @@ impl PciConfigMmio {
         self.pci_bus
             .lock()
             .unwrap()
-            .devices
-            .get(&(device as u32))
-            .map_or(0xffff_ffff, |d| {
-                d.lock().unwrap().read_config_register(register)
-            })
+            .read_config_register(device, function, register)
     }
 
     fn config_space_write(&mut self, config_address: u32, offset: u64, data: &[u8]) {
@@ -367,7 +410,7 @@ impl PciConfigMmio {
             return;
         }
 
-        let (bus, device, _function, register) = parse_mmio_config_address(config_address);
+        let (bus, device, function, register) = parse_mmio_config_address(config_address);
 
         // Only support one bus.
         if bus != 0 {
@@ -375,7 +418,7 @@ impl PciConfigMmio {
         }
 
         let pci_bus = self.pci_bus.lock().unwrap();
-        if let Some(d) = pci_bus.devices.get(&(device as u32)) {
+        if let Some(d) = pci_bus.devices.get(&devfn(device, function)) {
             let mut device = d.lock().unwrap();
 
             // Find out if one of the device's BAR is being reprogrammed, and
@@ -429,6 +472,37 @@ impl BusDevice for PciConfigMmio {
     }
 }
 
+/// Emulates the memory-mapped window giving access to the PCI I/O space, on
+/// architectures without port I/O instructions. An access at a given offset
+/// of the window is an access to the port of the same number.
+pub struct PciIoWindow {
+    io_bus: Arc<Bus>,
+}
+
+impl PciIoWindow {
+    pub fn new(io_bus: Arc<Bus>) -> Self {
+        PciIoWindow { io_bus }
+    }
+}
+
+impl BusDevice for PciIoWindow {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        if self.io_bus.read(offset, data).is_err() {
+            for d in data {
+                *d = 0xff;
+            }
+        }
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        self.io_bus.write(offset, data).unwrap_or(None)
+    }
+}
+
+fn devfn(device: usize, function: usize) -> u32 {
+    ((device << 3) | function) as u32
+}
+
 fn shift_and_mask(value: u32, offset: usize, mask: u32) -> usize {
     ((value >> offset) & mask) as usize
 }
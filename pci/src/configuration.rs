@@ -15,6 +15,8 @@ use vm_migration::{MigratableError, Pausable, Snapshot, Snapshottable, VersionMa
 // The number of 32bit registers in the config space, 4096 bytes.
 const NUM_CONFIGURATION_REGISTERS: usize = 1024;
 
+const COMMAND_REG: usize = 1;
+const COMMAND_REG_MASK: u32 = 0x0000_ffff;
 const STATUS_REG: usize = 1;
 const STATUS_REG_CAPABILITIES_USED_MASK: u32 = 0x0010_0000;
 const BAR0_REG: usize = 4;
@@ -510,6 +512,21 @@ impl PciConfiguration {
         self.msix_cap_reg_idx = state.msix_cap_reg_idx;
     }
 
+    /// Brings the registers the guest programs when enabling the device back
+    /// to their reset value, namely the command register and the MSI-X
+    /// message control. The BARs are left untouched since the resources of
+    /// the device remain mapped at their current location.
+    pub fn reset(&mut self) {
+        self.registers[COMMAND_REG] &= !COMMAND_REG_MASK;
+
+        if let Some(msix_cap_reg_idx) = self.msix_cap_reg_idx {
+            self.registers[msix_cap_reg_idx] &= !MSIX_CAPABILITY_REGISTER_MASK;
+            if let Some(msix_config) = &self.msix_config {
+                msix_config.lock().unwrap().reset();
+            }
+        }
+    }
+
     /// Reads a 32bit register from `reg_idx` in the register map.
     pub fn read_reg(&self, reg_idx: usize) -> u32 {
         *(self.registers.get(reg_idx).unwrap_or(&0xffff_ffff))
@@ -1025,6 +1042,7 @@ mod tests {
     use vm_memory::ByteValued;
 
     use super::*;
+    use crate::MsixCap;
 
     #[repr(packed)]
     #[derive(Clone, Copy, Default)]
@@ -1092,6 +1110,46 @@ mod tests {
         assert_eq!((cap2_data >> 24) & 0xFF, 0x55); // cap2.foo
     }
 
+    #[test]
+    fn reset() {
+        let mut cfg = PciConfiguration::new(
+            0x1234,
+            0x5678,
+            0x1,
+            PciClassCode::MultimediaController,
+            &PciMultimediaSubclass::AudioController,
+            None,
+            PciHeaderType::Device,
+            0xABCD,
+            0x2468,
+            None,
+        );
+
+        let msix_cap = MsixCap::new(0, 4, 0, 0, 0x1000);
+        let msix_cap_offset = cfg.add_capability(&msix_cap).unwrap();
+        let msix_cap_reg_idx = msix_cap_offset / 4;
+
+        // Enable memory decoding and bus mastering, then enable MSI-X with
+        // the function masked.
+        cfg.write_config_register(COMMAND_REG, 0, &[0x06, 0x00]);
+        cfg.write_config_register(msix_cap_reg_idx, 2, &[0x00, 0xc0]);
+        assert_eq!(cfg.read_reg(COMMAND_REG) & COMMAND_REG_MASK, 0x6);
+        assert_eq!(
+            cfg.read_reg(msix_cap_reg_idx) & MSIX_CAPABILITY_REGISTER_MASK,
+            MSIX_CAPABILITY_REGISTER_MASK
+        );
+
+        cfg.reset();
+
+        assert_eq!(cfg.read_reg(COMMAND_REG) & COMMAND_REG_MASK, 0);
+        assert_eq!(
+            cfg.read_reg(msix_cap_reg_idx) & MSIX_CAPABILITY_REGISTER_MASK,
+            0
+        );
+        // The table size is preserved.
+        assert_eq!((cfg.read_reg(msix_cap_reg_idx) >> 16) & 0x7ff, 3);
+    }
+
     #[derive(Copy, Clone)]
     enum TestPi {
         Test = 0x5a,
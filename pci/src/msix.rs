@@ -156,6 +156,24 @@ impl MsixConfig {
         self.enabled
     }
 
+    /// Brings the MSI-X configuration back to its initial state, with every
+    /// vector masked and MSI-X disabled.
+    pub fn reset(&mut self) {
+        if self.enabled && !self.masked {
+            debug!("MSI-X disabled for device 0x{:x}", self.devid);
+            if let Err(e) = self.interrupt_source_group.disable() {
+                error!("Failed disabling irq_fd: {:?}", e);
+            }
+        }
+
+        self.table_entries
+            .iter_mut()
+            .for_each(|entry| *entry = MsixTableEntry::default());
+        self.pba_entries.iter_mut().for_each(|entry| *entry = 0);
+        self.masked = true;
+        self.enabled = false;
+    }
+
     pub fn set_msg_ctl(&mut self, reg: u16) {
         let old_masked = self.masked;
         let old_enabled = self.enabled;
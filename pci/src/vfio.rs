@@ -530,11 +530,7 @@ impl VfioCommon {
 
             let bar_addr = match region_type {
                 PciBarRegionType::IoRegion => {
-                    #[cfg(target_arch = "aarch64")]
-                    unimplemented!();
-
                     // The address needs to be 4 bytes aligned.
-                    #[cfg(not(target_arch = "aarch64"))]
                     allocator
                         .lock()
                         .unwrap()
@@ -604,10 +600,7 @@ impl VfioCommon {
         for region in self.mmio_regions.iter() {
             match region.type_ {
                 PciBarRegionType::IoRegion => {
-                    #[cfg(target_arch = "x86_64")]
                     allocator.free_io_addresses(region.start, region.length);
-                    #[cfg(target_arch = "aarch64")]
-                    error!("I/O region is not supported");
                 }
                 PciBarRegionType::Memory32BitRegion => {
                     allocator.free_mmio_hole_addresses(region.start, region.length);
@@ -1080,7 +1073,7 @@ impl Snapshottable for VfioCommon {
         Ok(vfio_common_snapshot)
     }
 
-    fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
+    fn restore(&mut self, mut snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         if let Some(vfio_common_section) = snapshot
             .snapshot_data
             .get(&format!("{}-section", self.id()))
@@ -1093,14 +1086,14 @@ impl Snapshottable for VfioCommon {
                 })?;
 
             // Restore PciConfiguration
-            if let Some(pci_config_snapshot) = snapshot.snapshots.get(&self.configuration.id()) {
-                self.configuration.restore(*pci_config_snapshot.clone())?;
+            if let Some(pci_config_snapshot) = snapshot.snapshots.remove(&self.configuration.id()) {
+                self.configuration.restore(*pci_config_snapshot)?;
             }
 
             // Restore MSI
             if let Some(msi) = &mut self.interrupt.msi {
-                if let Some(msi_snapshot) = snapshot.snapshots.get(&msi.cfg.id()) {
-                    msi.cfg.restore(*msi_snapshot.clone())?;
+                if let Some(msi_snapshot) = snapshot.snapshots.remove(&msi.cfg.id()) {
+                    msi.cfg.restore(*msi_snapshot)?;
                 }
                 if msi.cfg.enabled() {
                     self.enable_msi().unwrap();
@@ -1109,8 +1102,8 @@ impl Snapshottable for VfioCommon {
 
             // Restore MSI-X
             if let Some(msix) = &mut self.interrupt.msix {
-                if let Some(msix_snapshot) = snapshot.snapshots.get(&msix.bar.id()) {
-                    msix.bar.restore(*msix_snapshot.clone())?;
+                if let Some(msix_snapshot) = snapshot.snapshots.remove(&msix.bar.id()) {
+                    msix.bar.restore(*msix_snapshot)?;
                 }
                 if msix.bar.enabled() {
                     self.enable_msix().unwrap();
@@ -1638,10 +1631,10 @@ impl Snapshottable for VfioPciDevice {
         Ok(vfio_pci_dev_snapshot)
     }
 
-    fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
+    fn restore(&mut self, mut snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         // Restore VfioCommon
-        if let Some(vfio_common_snapshot) = snapshot.snapshots.get(&self.common.id()) {
-            self.common.restore(*vfio_common_snapshot.clone())?;
+        if let Some(vfio_common_snapshot) = snapshot.take_snapshot(&self.common.id()) {
+            self.common.restore(vfio_common_snapshot)?;
             self.map_mmio_regions().map_err(|e| {
                 MigratableError::Restore(anyhow!(
                     "Could not map MMIO regions for VfioPciDevice on restore {:?}",
@@ -573,10 +573,10 @@ impl Snapshottable for VfioUserPciDevice {
         Ok(vfio_pci_dev_snapshot)
     }
 
-    fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
+    fn restore(&mut self, mut snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         // Restore VfioCommon
-        if let Some(vfio_common_snapshot) = snapshot.snapshots.get(&self.common.id()) {
-            self.common.restore(*vfio_common_snapshot.clone())?;
+        if let Some(vfio_common_snapshot) = snapshot.take_snapshot(&self.common.id()) {
+            self.common.restore(vfio_common_snapshot)?;
             self.map_mmio_regions().map_err(|e| {
                 MigratableError::Restore(anyhow!(
                     "Could not map MMIO regions for VfioUserPciDevice on restore {:?}",
@@ -0,0 +1,52 @@
+/* SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause */
+/*
+ * Copyright © 2022, Microsoft Corporation
+ *
+ * Guest interface of the partition services mailbox of Cloud Hypervisor,
+ * see docs/partition_services.md.
+ *
+ * The mailbox is a frame of 32-bit little-endian registers, accessed with
+ * 32-bit accesses only:
+ * - on x86_64, at the I/O port CRPS_X86_IO_PORT;
+ * - on aarch64, at the MMIO address CRPS_ARM64_MMIO_BASE, also described by
+ *   the "craton,partition-services" device tree node.
+ *
+ * A command is run by writing its arguments to the DATA registers, then the
+ * command to the COMMAND register. Its status and results can be read back
+ * right after, as the command is run before the write completes. The
+ * mailbox is shared by all the vCPUs, which have to serialize the commands.
+ */
+
+#ifndef CRATON_PARTITION_SERVICES_H
+#define CRATON_PARTITION_SERVICES_H
+
+#define CRPS_X86_IO_PORT		0x540
+#define CRPS_ARM64_MMIO_BASE		0x09060000ULL
+#define CRPS_SIZE			0x20
+
+/* Registers, as offsets in the frame. */
+#define CRPS_REG_MAGIC			0x00
+#define CRPS_REG_VERSION		0x04
+#define CRPS_REG_COMMAND		0x08
+#define CRPS_REG_STATUS			0x0c
+#define CRPS_REG_DATA(n)		(0x10 + 4 * (n))
+
+/* "CRPS", read from CRPS_REG_MAGIC. */
+#define CRPS_MAGIC			0x53505243
+/* Version of the ABI described here, read from CRPS_REG_VERSION. */
+#define CRPS_VERSION			1
+
+/* DATA0-1: partition id, low then high 32 bits. */
+#define CRPS_CMD_GET_PARTITION_ID	1
+/* Restart the VM. */
+#define CRPS_CMD_REQUEST_RESTART	2
+/* DATA0: guest defined status, reported to the orchestrator. */
+#define CRPS_CMD_SIGNAL_READY		3
+/* DATA0-1: seconds since the epoch, low then high 32 bits, DATA2: nanoseconds. */
+#define CRPS_CMD_GET_WALL_CLOCK		4
+
+#define CRPS_STATUS_SUCCESS		0
+#define CRPS_STATUS_UNKNOWN_COMMAND	1
+#define CRPS_STATUS_FAILED		2
+
+#endif /* CRATON_PARTITION_SERVICES_H */
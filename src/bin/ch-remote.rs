@@ -8,20 +8,40 @@ extern crate clap;
 
 use api_client::simple_api_command;
 use api_client::simple_api_command_with_fds;
+use api_client::simple_api_full_command;
 use api_client::Error as ApiClientError;
 use clap::{Arg, ArgMatches, Command};
 use option_parser::{ByteSized, ByteSizedParseError};
 use std::fmt;
+use std::os::unix::fs::MetadataExt;
 use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::process;
 
 #[derive(Debug)]
 enum Error {
     Connect(std::io::Error),
+    Fleet(std::io::Error),
+    FleetMemberNotFound(String),
+    FleetMemberWithoutApiSocket(String),
+    FleetMemberApiSocketOwner(PathBuf),
+    MissingFleetMember,
+    MissingFleetSocket,
     ApiClient(ApiClientError),
     InvalidCpuCount(std::num::ParseIntError),
     InvalidMemorySize(ByteSizedParseError),
     InvalidBalloonSize(ByteSizedParseError),
+    InvalidThrottlePercentage(std::num::ParseIntError),
+    InvalidCpuId(std::num::ParseIntError),
+    InvalidSgi(std::num::ParseIntError),
+    #[cfg(feature = "fault_injection")]
+    InvalidFaultData(serde_json::Error),
+    InvalidMaintenanceLease(std::num::ParseIntError),
+    InvalidMaintenanceExpiryAction(String),
+    InvalidRateLimiter(vmm::config::Error),
+    InvalidInterruptCoalescing(vmm::config::Error),
+    InvalidQueueSize(std::num::ParseIntError),
+    InvalidSnapshotFormat(vmm::config::ParseSnapshotFormatError),
     AddDeviceConfig(vmm::config::Error),
     AddDiskConfig(vmm::config::Error),
     AddFsConfig(vmm::config::Error),
@@ -39,9 +59,36 @@ impl fmt::Display for Error {
         match self {
             ApiClient(e) => e.fmt(f),
             Connect(e) => write!(f, "Error opening HTTP socket: {}", e),
+            Fleet(e) => write!(f, "Error querying the fleet registry: {}", e),
+            FleetMemberNotFound(s) => write!(f, "No VM named {} in the fleet", s),
+            FleetMemberWithoutApiSocket(s) => {
+                write!(f, "VM {} of the fleet has no API socket path", s)
+            }
+            FleetMemberApiSocketOwner(p) => write!(
+                f,
+                "API socket {:?} of the fleet member not owned by the owner of the registry",
+                p
+            ),
+            MissingFleetMember => write!(f, "--vm is required along with --fleet-socket"),
+            MissingFleetSocket => write!(f, "--fleet-socket is required to list the fleet"),
             InvalidCpuCount(e) => write!(f, "Error parsing CPU count: {}", e),
             InvalidMemorySize(e) => write!(f, "Error parsing memory size: {:?}", e),
             InvalidBalloonSize(e) => write!(f, "Error parsing balloon size: {:?}", e),
+            InvalidThrottlePercentage(e) => write!(f, "Error parsing throttle percentage: {}", e),
+            InvalidCpuId(e) => write!(f, "Error parsing CPU identifier: {}", e),
+            InvalidSgi(e) => write!(f, "Error parsing SGI: {}", e),
+            #[cfg(feature = "fault_injection")]
+            InvalidFaultData(e) => write!(f, "Error parsing fault data: {}", e),
+            InvalidMaintenanceLease(e) => write!(f, "Error parsing maintenance lease: {}", e),
+            InvalidMaintenanceExpiryAction(s) => {
+                write!(f, "Error parsing maintenance expiry action: {}", s)
+            }
+            InvalidRateLimiter(e) => write!(f, "Error parsing rate limiter syntax: {}", e),
+            InvalidInterruptCoalescing(e) => {
+                write!(f, "Error parsing interrupt coalescing syntax: {}", e)
+            }
+            InvalidQueueSize(e) => write!(f, "Error parsing queue size: {}", e),
+            InvalidSnapshotFormat(e) => write!(f, "Error parsing snapshot format: {}", e),
             AddDeviceConfig(e) => write!(f, "Error parsing device syntax: {}", e),
             AddDiskConfig(e) => write!(f, "Error parsing disk syntax: {}", e),
             AddFsConfig(e) => write!(f, "Error parsing filesystem syntax: {}", e),
@@ -122,6 +169,148 @@ fn resize_zone_api_command(socket: &mut UnixStream, id: &str, size: &str) -> Res
     .map_err(Error::ApiClient)
 }
 
+fn throttle_vcpus_api_command(socket: &mut UnixStream, percentage: &str) -> Result<(), Error> {
+    let throttle_vcpus = vmm::api::VmThrottleVcpusData {
+        percentage: percentage
+            .parse()
+            .map_err(Error::InvalidThrottlePercentage)?,
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "throttle-vcpus",
+        Some(&serde_json::to_string(&throttle_vcpus).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn set_rate_limiter_api_command(
+    socket: &mut UnixStream,
+    id: &str,
+    rate_limiter: Option<&str>,
+) -> Result<(), Error> {
+    let set_rate_limiter = vmm::api::VmSetRateLimiterData {
+        id: id.to_owned(),
+        rate_limiter_config: vmm::config::parse_rate_limiter(rate_limiter.unwrap_or_default())
+            .map_err(Error::InvalidRateLimiter)?,
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "set-rate-limiter",
+        Some(&serde_json::to_string(&set_rate_limiter).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn set_interrupt_coalescing_api_command(
+    socket: &mut UnixStream,
+    id: &str,
+    interrupt_coalescing: Option<&str>,
+) -> Result<(), Error> {
+    let set_interrupt_coalescing = vmm::api::VmSetInterruptCoalescingData {
+        id: id.to_owned(),
+        interrupt_coalescing: vmm::config::parse_interrupt_coalescing(
+            interrupt_coalescing.unwrap_or_default(),
+        )
+        .map_err(Error::InvalidInterruptCoalescing)?,
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "set-interrupt-coalescing",
+        Some(&serde_json::to_string(&set_interrupt_coalescing).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn set_queue_size_api_command(
+    socket: &mut UnixStream,
+    id: &str,
+    queue_size: &str,
+) -> Result<(), Error> {
+    let set_queue_size = vmm::api::VmSetQueueSizeData {
+        id: id.to_owned(),
+        queue_size: queue_size.parse().map_err(Error::InvalidQueueSize)?,
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "set-queue-size",
+        Some(&serde_json::to_string(&set_queue_size).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn inject_nmi_api_command(
+    socket: &mut UnixStream,
+    cpu_id: &str,
+    sgi: Option<&str>,
+) -> Result<(), Error> {
+    let inject_nmi = vmm::api::VmInjectNmiData {
+        cpu_id: cpu_id.parse().map_err(Error::InvalidCpuId)?,
+        sgi: sgi
+            .map(|sgi| sgi.parse().map_err(Error::InvalidSgi))
+            .transpose()?
+            .unwrap_or(vmm::api::DEFAULT_NMI_SGI),
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "inject-nmi",
+        Some(&serde_json::to_string(&inject_nmi).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn maintenance_api_command(
+    socket: &mut UnixStream,
+    lease: Option<&str>,
+    on_expiry: Option<&str>,
+    checkpoint: Option<&str>,
+) -> Result<(), Error> {
+    let maintenance = vmm::api::VmMaintenanceData {
+        lease: lease
+            .map(|lease| lease.parse().map_err(Error::InvalidMaintenanceLease))
+            .transpose()?,
+        on_expiry: on_expiry
+            .map(|on_expiry| {
+                on_expiry
+                    .parse()
+                    .map_err(|_| Error::InvalidMaintenanceExpiryAction(on_expiry.to_owned()))
+            })
+            .transpose()?,
+        checkpoint: checkpoint.map(std::path::PathBuf::from),
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "maintenance",
+        Some(&serde_json::to_string(&maintenance).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+#[cfg(feature = "fault_injection")]
+fn inject_fault_api_command(socket: &mut UnixStream, fault_data: &str) -> Result<(), Error> {
+    let fault_data: vmm::api::VmInjectFaultData =
+        serde_json::from_str(fault_data).map_err(Error::InvalidFaultData)?;
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "inject-fault",
+        Some(&serde_json::to_string(&fault_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
 fn add_device_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Error> {
     let device_config = vmm::config::DeviceConfig::parse(config).map_err(Error::AddDeviceConfig)?;
 
@@ -238,9 +427,19 @@ fn add_vsock_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Er
     .map_err(Error::ApiClient)
 }
 
-fn snapshot_api_command(socket: &mut UnixStream, url: &str) -> Result<(), Error> {
+fn snapshot_api_command(
+    socket: &mut UnixStream,
+    url: &str,
+    format: Option<&str>,
+    incremental: bool,
+) -> Result<(), Error> {
     let snapshot_config = vmm::api::VmSnapshotConfig {
         destination_url: String::from(url),
+        format: format
+            .map(|f| f.parse().map_err(Error::InvalidSnapshotFormat))
+            .transpose()?
+            .unwrap_or_default(),
+        incremental,
     };
 
     simple_api_command(
@@ -252,6 +451,20 @@ fn snapshot_api_command(socket: &mut UnixStream, url: &str) -> Result<(), Error>
     .map_err(Error::ApiClient)
 }
 
+fn memory_template_api_command(socket: &mut UnixStream, url: &str) -> Result<(), Error> {
+    let memory_template_data = vmm::api::VmMemoryTemplateData {
+        destination_url: String::from(url),
+    };
+
+    simple_api_command(
+        socket,
+        "PUT",
+        "memory-template",
+        Some(&serde_json::to_string(&memory_template_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
 fn restore_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Error> {
     let restore_config = vmm::config::RestoreConfig::parse(config).map_err(Error::Restore)?;
 
@@ -264,9 +477,32 @@ fn restore_api_command(socket: &mut UnixStream, config: &str) -> Result<(), Erro
     .map_err(Error::ApiClient)
 }
 
-fn coredump_api_command(socket: &mut UnixStream, destination_url: &str) -> Result<(), Error> {
+fn snapshot_info_api_command(socket: &mut UnixStream, url: &str) -> Result<(), Error> {
+    let snapshot_info_data = vmm::api::VmmSnapshotInfoData {
+        source_url: String::from(url),
+    };
+
+    simple_api_full_command(
+        socket,
+        "PUT",
+        "vmm.snapshot-info",
+        Some(&serde_json::to_string(&snapshot_info_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn coredump_api_command(
+    socket: &mut UnixStream,
+    destination_url: &str,
+    sparse: bool,
+    exclude_free_pages: bool,
+    compress: bool,
+) -> Result<(), Error> {
     let coredump_config = vmm::api::VmCoredumpData {
         destination_url: String::from(destination_url),
+        sparse,
+        exclude_free_pages,
+        compress,
     };
 
     simple_api_command(
@@ -309,9 +545,66 @@ fn send_migration_api_command(
     .map_err(Error::ApiClient)
 }
 
+fn accounting_api_command(socket: &mut UnixStream, reset: bool) -> Result<(), Error> {
+    if !reset {
+        return simple_api_command(socket, "GET", "accounting", None).map_err(Error::ApiClient);
+    }
+
+    let accounting_data = vmm::api::VmAccountingData { reset };
+    simple_api_command(
+        socket,
+        "PUT",
+        "accounting",
+        Some(&serde_json::to_string(&accounting_data).unwrap()),
+    )
+    .map_err(Error::ApiClient)
+}
+
+fn fleet_list_command(fleet_socket: &Path) -> Result<(), Error> {
+    let members = vmm::fleet::list_members(fleet_socket).map_err(Error::Fleet)?;
+    println!("{}", serde_json::to_string_pretty(&members).unwrap());
+    Ok(())
+}
+
+// Finds the API socket of a VM through the fleet registry.
+fn fleet_api_socket(fleet_socket: &Path, name: &str) -> Result<PathBuf, Error> {
+    let member = vmm::fleet::list_members(fleet_socket)
+        .map_err(Error::Fleet)?
+        .into_iter()
+        .find(|entry| entry.member.name == name)
+        .ok_or_else(|| Error::FleetMemberNotFound(name.to_owned()))?
+        .member;
+
+    let api_socket = member
+        .api_socket
+        .ok_or_else(|| Error::FleetMemberWithoutApiSocket(name.to_owned()))?;
+
+    // The commands, which may carry paths and devices, are only sent to a
+    // socket of the user serving the registry.
+    let registry_uid = std::fs::metadata(fleet_socket).map_err(Error::Fleet)?.uid();
+    match std::fs::metadata(&api_socket) {
+        Ok(metadata) if metadata.uid() == registry_uid => Ok(api_socket),
+        _ => Err(Error::FleetMemberApiSocketOwner(api_socket)),
+    }
+}
+
 fn do_command(matches: &ArgMatches) -> Result<(), Error> {
-    let mut socket =
-        UnixStream::connect(matches.value_of("api-socket").unwrap()).map_err(Error::Connect)?;
+    if matches.subcommand_name() == Some("fleet-list") {
+        let fleet_socket = matches
+            .value_of("fleet-socket")
+            .ok_or(Error::MissingFleetSocket)?;
+        return fleet_list_command(Path::new(fleet_socket));
+    }
+
+    let api_socket = if let Some(api_socket) = matches.value_of("api-socket") {
+        PathBuf::from(api_socket)
+    } else {
+        // Safe to unwrap as clap requires either socket.
+        let fleet_socket = Path::new(matches.value_of("fleet-socket").unwrap());
+        let name = matches.value_of("vm").ok_or(Error::MissingFleetMember)?;
+        fleet_api_socket(fleet_socket, name)?
+    };
+    let mut socket = UnixStream::connect(api_socket).map_err(Error::Connect)?;
 
     match matches.subcommand_name() {
         Some("info") => {
@@ -320,6 +613,28 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
         Some("counters") => {
             simple_api_command(&mut socket, "GET", "counters", None).map_err(Error::ApiClient)
         }
+        Some("config-schema") => {
+            simple_api_full_command(&mut socket, "GET", "vmm.config-schema", None)
+                .map_err(Error::ApiClient)
+        }
+        Some("accounting") => accounting_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("accounting")
+                .unwrap()
+                .is_present("reset"),
+        ),
+        Some("pstore") => {
+            simple_api_command(&mut socket, "GET", "pstore", None).map_err(Error::ApiClient)
+        }
+        Some("security-status") => simple_api_command(&mut socket, "GET", "security-status", None)
+            .map_err(Error::ApiClient),
+        Some("device-tree") => {
+            simple_api_command(&mut socket, "GET", "device-tree", None).map_err(Error::ApiClient)
+        }
+        Some("queues") => {
+            simple_api_command(&mut socket, "GET", "queues", None).map_err(Error::ApiClient)
+        }
         Some("resize") => resize_api_command(
             &mut socket,
             matches
@@ -348,6 +663,87 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .value_of("size")
                 .unwrap(),
         ),
+        Some("throttle-vcpus") => throttle_vcpus_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("throttle-vcpus")
+                .unwrap()
+                .value_of("percentage")
+                .unwrap(),
+        ),
+        Some("set-rate-limiter") => set_rate_limiter_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("set-rate-limiter")
+                .unwrap()
+                .value_of("id")
+                .unwrap(),
+            matches
+                .subcommand_matches("set-rate-limiter")
+                .unwrap()
+                .value_of("rate_limiter"),
+        ),
+        Some("set-interrupt-coalescing") => set_interrupt_coalescing_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("set-interrupt-coalescing")
+                .unwrap()
+                .value_of("id")
+                .unwrap(),
+            matches
+                .subcommand_matches("set-interrupt-coalescing")
+                .unwrap()
+                .value_of("interrupt_coalescing"),
+        ),
+        Some("set-queue-size") => set_queue_size_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("set-queue-size")
+                .unwrap()
+                .value_of("id")
+                .unwrap(),
+            matches
+                .subcommand_matches("set-queue-size")
+                .unwrap()
+                .value_of("queue_size")
+                .unwrap(),
+        ),
+        Some("maintenance") => maintenance_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("maintenance")
+                .unwrap()
+                .value_of("lease"),
+            matches
+                .subcommand_matches("maintenance")
+                .unwrap()
+                .value_of("on_expiry"),
+            matches
+                .subcommand_matches("maintenance")
+                .unwrap()
+                .value_of("checkpoint"),
+        ),
+        Some("inject-nmi") => inject_nmi_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("inject-nmi")
+                .unwrap()
+                .value_of("cpu_id")
+                .unwrap(),
+            matches
+                .subcommand_matches("inject-nmi")
+                .unwrap()
+                .value_of("sgi"),
+        ),
+        #[cfg(feature = "fault_injection")]
+        Some("inject-fault") => inject_fault_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("inject-fault")
+                .unwrap()
+                .value_of("fault_data")
+                .unwrap(),
+        ),
         Some("add-device") => add_device_api_command(
             &mut socket,
             matches
@@ -427,6 +823,22 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .unwrap()
                 .value_of("snapshot_config")
                 .unwrap(),
+            matches
+                .subcommand_matches("snapshot")
+                .unwrap()
+                .value_of("format"),
+            matches
+                .subcommand_matches("snapshot")
+                .unwrap()
+                .is_present("incremental"),
+        ),
+        Some("memory-template") => memory_template_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("memory-template")
+                .unwrap()
+                .value_of("destination_url")
+                .unwrap(),
         ),
         Some("restore") => restore_api_command(
             &mut socket,
@@ -436,6 +848,14 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .value_of("restore_config")
                 .unwrap(),
         ),
+        Some("snapshot-info") => snapshot_info_api_command(
+            &mut socket,
+            matches
+                .subcommand_matches("snapshot-info")
+                .unwrap()
+                .value_of("source_url")
+                .unwrap(),
+        ),
         Some("coredump") => coredump_api_command(
             &mut socket,
             matches
@@ -443,6 +863,18 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
                 .unwrap()
                 .value_of("coredump_config")
                 .unwrap(),
+            matches
+                .subcommand_matches("coredump")
+                .unwrap()
+                .is_present("coredump_sparse"),
+            matches
+                .subcommand_matches("coredump")
+                .unwrap()
+                .is_present("coredump_exclude_free_pages"),
+            matches
+                .subcommand_matches("coredump")
+                .unwrap()
+                .is_present("coredump_compress"),
         ),
         Some("send-migration") => send_migration_api_command(
             &mut socket,
@@ -470,7 +902,8 @@ fn do_command(matches: &ArgMatches) -> Result<(), Error> {
 }
 
 fn main() {
-    let app = Command::new("ch-remote")
+    #[allow(unused_mut)]
+    let mut app = Command::new("ch-remote")
         .author(crate_authors!())
         .subcommand_required(true)
         .about("Remotely control a cloud-hypervisor VMM.")
@@ -480,7 +913,23 @@ fn main() {
                 .help("HTTP API socket path (UNIX domain socket).")
                 .takes_value(true)
                 .number_of_values(1)
-                .required(true),
+                .required_unless_present("fleet-socket"),
+        )
+        .arg(
+            Arg::new("fleet-socket")
+                .long("fleet-socket")
+                .help("Fleet registry socket path, to find the API socket of the VM from.")
+                .takes_value(true)
+                .number_of_values(1)
+                .conflicts_with("api-socket"),
+        )
+        .arg(
+            Arg::new("vm")
+                .long("vm")
+                .help("Name of the VM in the fleet to attach to.")
+                .takes_value(true)
+                .number_of_values(1)
+                .requires("fleet-socket"),
         )
         .subcommand(
             Command::new("add-device").about("Add VFIO device").arg(
@@ -550,8 +999,54 @@ fn main() {
                 .arg(Arg::new("id").index(1).help("<device_id>")),
         )
         .subcommand(Command::new("info").about("Info on the VM"))
+        .subcommand(Command::new("fleet-list").about("VMs of the fleet, along with their status"))
         .subcommand(Command::new("counters").about("Counters from the VM"))
+        .subcommand(
+            Command::new("config-schema")
+                .about("JSON Schema of the VM configuration accepted by the VMM"),
+        )
+        .subcommand(
+            Command::new("accounting")
+                .about("Resource usage of the VM")
+                .arg(
+                    Arg::new("reset")
+                        .long("reset")
+                        .help("Start the resource usage over once returned")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            Command::new("maintenance")
+                .about("Pause the VM for the maintenance of the host")
+                .arg(
+                    Arg::new("lease")
+                        .long("lease")
+                        .help("Seconds the VM stays paused without the lease being renewed")
+                        .takes_value(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::new("on_expiry")
+                        .long("on-expiry")
+                        .help("Action once the lease expires: resume|shutdown")
+                        .takes_value(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::new("checkpoint")
+                        .long("checkpoint")
+                        .help("File the checkpoint header is kept in")
+                        .takes_value(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(Command::new("maintenance-end").about("End the maintenance, resuming the VM"))
+        .subcommand(Command::new("maintenance-renew").about("Renew the maintenance lease"))
         .subcommand(Command::new("pause").about("Pause the VM"))
+        .subcommand(Command::new("pstore").about("Pstore records written by the guest"))
+        .subcommand(Command::new("security-status").about("Security mitigations active for the VM"))
+        .subcommand(Command::new("device-tree").about("Devices plugged into the VM"))
+        .subcommand(Command::new("queues").about("Queues of the virtio devices of the VM"))
         .subcommand(Command::new("reboot").about("Reboot the VM"))
         .subcommand(Command::new("power-button").about("Trigger a power button in the VM"))
         .subcommand(
@@ -599,6 +1094,51 @@ fn main() {
         )
         .subcommand(Command::new("resume").about("Resume the VM"))
         .subcommand(Command::new("shutdown").about("Shutdown the VM"))
+        .subcommand(
+            Command::new("throttle-vcpus")
+                .about("Throttle the vCPUs")
+                .arg(
+                    Arg::new("percentage")
+                        .index(1)
+                        .help("Percentage of the vCPU time to take away (0 to lift throttling)"),
+                ),
+        )
+        .subcommand(
+            Command::new("set-rate-limiter")
+                .about("Change the rate limiting of a disk or network device")
+                .arg(Arg::new("id").index(1).help("<device_id>"))
+                .arg(
+                    Arg::new("rate_limiter")
+                        .index(2)
+                        .help(vmm::config::RATE_LIMITER_SYNTAX),
+                ),
+        )
+        .subcommand(
+            Command::new("set-interrupt-coalescing")
+                .about("Change the interrupt coalescing of a disk or network device")
+                .arg(Arg::new("id").index(1).help("<device_id>"))
+                .arg(
+                    Arg::new("interrupt_coalescing")
+                        .index(2)
+                        .help(vmm::config::INTERRUPT_COALESCING_SYNTAX),
+                ),
+        )
+        .subcommand(
+            Command::new("set-queue-size")
+                .about("Change the size of the queues of a disk, network or filesystem device")
+                .arg(Arg::new("id").index(1).help("<device_id>"))
+                .arg(Arg::new("queue_size").index(2).help("<queue_size>")),
+        )
+        .subcommand(
+            Command::new("inject-nmi")
+                .about("Inject an NMI into a vCPU")
+                .arg(Arg::new("cpu_id").index(1).help("<cpu_id>"))
+                .arg(
+                    Arg::new("sgi")
+                        .index(2)
+                        .help("SGI set pending instead of the NMI on aarch64 (default 8)"),
+                ),
+        )
         .subcommand(
             Command::new("snapshot")
                 .about("Create a snapshot from VM")
@@ -606,6 +1146,26 @@ fn main() {
                     Arg::new("snapshot_config")
                         .index(1)
                         .help("<destination_url>"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .index(2)
+                        .help("Guest memory format: raw (default) or zstd"),
+                )
+                .arg(
+                    Arg::new("incremental")
+                        .long("incremental")
+                        .help("Only store the guest memory written since the previous snapshot")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            Command::new("memory-template")
+                .about("Write the memory of each zone of a paused VM as a template")
+                .arg(
+                    Arg::new("destination_url")
+                        .index(1)
+                        .help("<destination_url>"),
                 ),
         )
         .subcommand(
@@ -617,10 +1177,33 @@ fn main() {
                         .help(vmm::config::RestoreConfig::SYNTAX),
                 ),
         )
+        .subcommand(
+            Command::new("snapshot-info")
+                .about("Describe a snapshot without restoring it")
+                .arg(Arg::new("source_url").index(1).help("<source_url>")),
+        )
         .subcommand(
             Command::new("coredump")
                 .about("Create a coredump from VM")
-                .arg(Arg::new("coredump_config").index(1).help("<file_path>")),
+                .arg(Arg::new("coredump_config").index(1).help("<file_path>"))
+                .arg(
+                    Arg::new("coredump_sparse")
+                        .long("sparse")
+                        .help("Leave holes in the file for the zero pages")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("coredump_exclude_free_pages")
+                        .long("exclude-free-pages")
+                        .help("Leave the pages inflated in the balloon out of the coredump")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("coredump_compress")
+                        .long("compress")
+                        .help("Compress the coredump with zstd")
+                        .takes_value(false),
+                ),
         )
         .subcommand(
             Command::new("send-migration")
@@ -646,6 +1229,17 @@ fn main() {
                 ),
         );
 
+    #[cfg(feature = "fault_injection")]
+    {
+        app = app.subcommand(
+            Command::new("inject-fault")
+                .about("Inject faults into a device or the VMM, for testing")
+                .arg(Arg::new("fault_data").index(1).help(
+                    "<fault_data> as JSON, e.g. {\"id\":\"_disk0\",\"disk_io_errors\":[[0,2047]]}",
+                )),
+        );
+    }
+
     let matches = app.get_matches();
 
     if let Err(e) = do_command(&matches) {
@@ -16,7 +16,10 @@ use seccompiler::SeccompAction;
 use signal_hook::consts::SIGSYS;
 use std::env;
 use std::fs::File;
+use std::net::TcpListener;
 use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
@@ -64,6 +67,26 @@ enum Error {
     BareEventMonitor,
     #[error("Error doing event monitor I/O: {0}")]
     EventMonitorIo(std::io::Error),
+    #[error("Error parsing --event-stream: {0}")]
+    ParsingEventStream(option_parser::OptionParserError),
+    #[error("Error parsing --event-stream: path required")]
+    BareEventStream,
+    #[error("Error binding the event stream socket: {0}")]
+    EventStreamSocketBind(std::io::Error),
+    #[error("Error parsing --metrics: {0}")]
+    ParsingMetrics(option_parser::OptionParserError),
+    #[error("Error parsing --metrics: path or tcp required")]
+    BareMetrics,
+    #[error("Error binding the metrics socket: {0}")]
+    MetricsSocketBind(std::io::Error),
+    #[error("Error parsing --fleet: {0}")]
+    ParsingFleet(option_parser::OptionParserError),
+    #[error("Error parsing --fleet: socket required")]
+    BareFleet,
+    #[error("Error parsing --fleet: interval must be between 1 and 3600 seconds")]
+    InvalidFleetInterval,
+    #[error("Error binding the fleet registry socket: {0}")]
+    FleetSocketBind(std::io::Error),
     #[cfg(feature = "gdb")]
     #[error("Error parsing --gdb: {0}")]
     ParsingGdb(option_parser::OptionParserError),
@@ -76,9 +99,51 @@ enum Error {
     LoggerSetup(log::SetLoggerError),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    /// Human readable lines.
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid log format: {}", s)),
+        }
+    }
+}
+
 struct Logger {
     output: Mutex<Box<dyn std::io::Write + Send>>,
     start: std::time::Instant,
+    format: LogFormat,
+}
+
+impl Logger {
+    fn json_record(&self, record: &log::Record, duration: std::time::Duration) -> String {
+        let timestamp_us = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or_default();
+
+        serde_json::json!({
+            "timestamp_us": timestamp_us,
+            "elapsed_us": duration.as_micros() as u64,
+            "thread": std::thread::current().name().unwrap_or("anonymous"),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "file": record.file(),
+            "line": record.line(),
+            "message": record.args().to_string(),
+        })
+        .to_string()
+    }
 }
 
 impl log::Log for Logger {
@@ -94,7 +159,13 @@ impl log::Log for Logger {
         let now = std::time::Instant::now();
         let duration = now.duration_since(self.start);
 
-        if record.file().is_some() && record.line().is_some() {
+        if self.format == LogFormat::Json {
+            writeln!(
+                *(*(self.output.lock().unwrap())),
+                "{}",
+                self.json_record(record, duration)
+            )
+        } else if record.file().is_some() && record.line().is_some() {
             writeln!(
                 *(*(self.output.lock().unwrap())),
                 "cloud-hypervisor: {:?}: <{}> {}:{}:{} -- {}",
@@ -150,9 +221,13 @@ fn create_app<'a>(
                 .help(
                     "boot=<boot_vcpus>,max=<max_vcpus>,\
                     topology=<threads_per_core>:<cores_per_die>:<dies_per_package>:<packages>,\
-                    kvm_hyperv=on|off,max_phys_bits=<maximum_number_of_physical_bits>,\
+                    kvm_hyperv=on|off,nested=on|off,\
+                    max_phys_bits=<maximum_number_of_physical_bits>,\
                     affinity=<list_of_vcpus_with_their_associated_cpuset>,\
-                    features=<list_of_features_to_enable>",
+                    features=<list_of_features_to_enable>,\
+                    weight=<cpu_weight>,quota=<cpu_quota_percentage>,\
+                    tsc_khz=<tsc_frequency_in_khz>,baseline=<cpu_model>,pmu=on|off,\
+                    freeze_counter=on|off",
                 )
                 .default_value(default_vcpus)
                 .group("vm-config"),
@@ -161,7 +236,7 @@ fn create_app<'a>(
             Arg::new("platform")
                 .long("platform")
                 .help(
-                    "num_pci_segments=<num pci segments>,iommu_segments=<list_of_segments>,serial_number=<(DMI) device serial number>",
+                    "num_pci_segments=<num pci segments>,iommu_segments=<list_of_segments>,serial_number=<(DMI) device serial number>,ioeventfd=on|off,uuid=<(DMI) device uuid>,hypervisor_info=on|off,mmio_hotplug_slots=<number_of_slots>",
                 )
                 .takes_value(true)
                 .group("vm-config"),
@@ -176,7 +251,9 @@ fn create_app<'a>(
                      hotplug_method=acpi|virtio-mem,\
                      hotplug_size=<hotpluggable_memory_size>,\
                      hotplugged_size=<hotplugged_memory_size>,\
-                     prefault=on|off\"",
+                     prefault=on|off,hugepage_pool=on|off,\
+                     hugepage_pool_limit=<hugepage_pool_size>,\
+                     virtio_mem_transport=pci|mmio,sealed=on|off\"",
                 )
                 .default_value(default_memory)
                 .group("vm-config"),
@@ -192,7 +269,7 @@ fn create_app<'a>(
                      host_numa_node=<node_id>,\
                      id=<zone_identifier>,hotplug_size=<hotpluggable_memory_size>,\
                      hotplugged_size=<hotplugged_memory_size>,\
-                     prefault=on|off\"",
+                     prefault=on|off,template=on|off,sealed=on|off\"",
                 )
                 .takes_value(true)
                 .min_values(1)
@@ -211,8 +288,20 @@ fn create_app<'a>(
         .arg(
             Arg::new("initramfs")
                 .long("initramfs")
-                .help("Path to initramfs image")
+                .help(
+                    "Path to initramfs image. Several images are concatenated in the order \
+                they are given",
+                )
                 .takes_value(true)
+                .min_values(1)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("payload-blob")
+                .long("payload-blob")
+                .help(config::PayloadBlobConfig::SYNTAX)
+                .takes_value(true)
+                .min_values(1)
                 .group("vm-config"),
         )
         .arg(
@@ -270,6 +359,106 @@ fn create_app<'a>(
                 .min_values(1)
                 .group("vm-config"),
         )
+        .arg(
+            Arg::new("pstore")
+                .long("pstore")
+                .help(config::PstoreConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("crashkernel")
+                .long("crashkernel")
+                .help(config::CrashKernelConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("ptp")
+                .long("ptp")
+                .help(config::PtpConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("acpi")
+                .long("acpi")
+                .help(config::AcpiConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("security")
+                .long("security")
+                .help(config::SecurityConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("deterministic")
+                .long("deterministic")
+                .help(config::DeterministicConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("snapshot-schedule")
+                .long("snapshot-schedule")
+                .help(config::SnapshotScheduleConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("maintenance")
+                .long("maintenance")
+                .help(config::MaintenanceConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("virtio-features")
+                .long("virtio-features")
+                .help(config::VirtioFeaturesConfig::SYNTAX)
+                .takes_value(true)
+                .min_values(1)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("irqs")
+                .long("irqs")
+                .help(config::IrqConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("doorbell")
+                .long("doorbell")
+                .help(config::DoorbellConfig::SYNTAX)
+                .takes_value(true)
+                .min_values(1)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("partition-services")
+                .long("partition-services")
+                .help(config::PartitionServicesConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("priority")
+                .long("priority")
+                .help(config::PriorityConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("debug-console")
+                .long("debug-console")
+                .help(config::DebugConsoleConfig::SYNTAX)
+                .takes_value(true)
+                .group("vm-config"),
+        )
         .arg(
             Arg::new("serial")
                 .long("serial")
@@ -318,6 +507,14 @@ fn create_app<'a>(
                 .number_of_values(1)
                 .group("vm-config"),
         )
+        .arg(
+            Arg::new("gpu")
+                .long("gpu")
+                .help(config::GpuConfig::SYNTAX)
+                .takes_value(true)
+                .number_of_values(1)
+                .group("vm-config"),
+        )
         .arg(
             Arg::new("numa")
                 .long("numa")
@@ -329,7 +526,22 @@ fn create_app<'a>(
         .arg(
             Arg::new("watchdog")
                 .long("watchdog")
-                .help("Enable virtio-watchdog")
+                .help(config::WatchdogConfig::SYNTAX)
+                .takes_value(true)
+                .min_values(0)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("pvpanic")
+                .long("pvpanic")
+                .help("Enable the pvpanic guest panic notification device")
+                .takes_value(false)
+                .group("vm-config"),
+        )
+        .arg(
+            Arg::new("boot-paused")
+                .long("boot-paused")
+                .help("Boot the VM paused, its vCPUs only running once the VM is resumed")
                 .takes_value(false)
                 .group("vm-config"),
         )
@@ -348,6 +560,15 @@ fn create_app<'a>(
                 .min_values(1)
                 .group("logging"),
         )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .help("Format of the log messages")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .group("logging"),
+        )
         .arg(
             Arg::new("api-socket")
                 .long("api-socket")
@@ -364,6 +585,34 @@ fn create_app<'a>(
                 .min_values(1)
                 .group("vmm-config"),
         )
+        .arg(
+            Arg::new("event-stream")
+                .long("event-stream")
+                .help("Socket streaming the VM lifecycle events: path=</path/to/a/socket>")
+                .takes_value(true)
+                .min_values(1)
+                .group("vmm-config"),
+        )
+        .arg(
+            Arg::new("metrics")
+                .long("metrics")
+                .help("Prometheus metrics endpoint: path=</path/to/a/socket> or tcp=<address:port>")
+                .takes_value(true)
+                .min_values(1)
+                .group("vmm-config"),
+        )
+        .arg(
+            Arg::new("fleet")
+                .long("fleet")
+                .help(
+                    "Fleet registry to report to: \
+                     socket=</path/to/a/socket>,name=<member_name>,\
+                     interval=<heartbeat_seconds>,registry=on|off",
+                )
+                .takes_value(true)
+                .min_values(1)
+                .group("vmm-config"),
+        )
         .arg(
             Arg::new("restore")
                 .long("restore")
@@ -378,6 +627,20 @@ fn create_app<'a>(
                 .takes_value(true)
                 .possible_values(&["true", "false", "log"])
                 .default_value("true"),
+        )
+        .arg(
+            Arg::new("leak-check")
+                .long("leak-check")
+                .help("Verify that the resources of the VM are released on shutdown")
+                .takes_value(true)
+                .possible_values(&["off", "log", "assert"])
+                .default_value("off"),
+        )
+        .arg(
+            Arg::new("print-config-schema")
+                .long("print-config-schema")
+                .help("Print the JSON Schema of the VM configuration accepted by this build and exit")
+                .takes_value(false),
         );
 
     #[cfg(target_arch = "x86_64")]
@@ -390,6 +653,25 @@ fn create_app<'a>(
             .group("vm-config"),
     );
 
+    #[cfg(target_arch = "aarch64")]
+    let app = app.arg(
+        Arg::new("fdt-overlay")
+            .long("fdt-overlay")
+            .help("Path to a device tree overlay (.dtbo) applied to the generated FDT")
+            .takes_value(true)
+            .min_values(1)
+            .group("vm-config"),
+    );
+
+    #[cfg(target_arch = "aarch64")]
+    let app = app.arg(
+        Arg::new("fdt")
+            .long("fdt")
+            .help(config::FdtConfig::SYNTAX)
+            .takes_value(true)
+            .group("vm-config"),
+    );
+
     #[cfg(feature = "gdb")]
     let app = app.arg(
         Arg::new("gdb")
@@ -408,10 +690,21 @@ fn create_app<'a>(
             .group("vm-config"),
     );
 
+    #[cfg(feature = "sev_snp")]
+    let app = app.arg(
+        Arg::new("sev-snp")
+            .long("sev-snp")
+            .help(config::SevSnpConfig::SYNTAX)
+            .takes_value(true)
+            .group("vm-config"),
+    );
+
     app
 }
 
-fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
+// Returns the paths of the sockets bound by the VMM, to be removed once it
+// exited.
+fn start_vmm(cmd_arguments: ArgMatches) -> Result<Vec<PathBuf>, Error> {
     let log_level = match cmd_arguments.occurrences_of("v") {
         0 => LevelFilter::Warn,
         1 => LevelFilter::Info,
@@ -427,9 +720,17 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         Box::new(std::io::stderr())
     };
 
+    // The user providing an invalid value will be rejected by clap
+    let log_format = cmd_arguments
+        .value_of("log-format")
+        .unwrap()
+        .parse::<LogFormat>()
+        .unwrap();
+
     log::set_boxed_logger(Box::new(Logger {
         output: Mutex::new(log_file),
         start: std::time::Instant::now(),
+        format: log_format,
     }))
     .map(|()| log::set_max_level(log_level))
     .map_err(Error::LoggerSetup)?;
@@ -482,6 +783,100 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         event_monitor::set_monitor(file).map_err(Error::EventMonitorIo)?;
     }
 
+    // Sockets created by the VMM, removed once it exits.
+    let mut socket_paths = Vec::new();
+
+    let event_stream_listener =
+        if let Some(event_stream_config) = cmd_arguments.value_of("event-stream") {
+            let mut parser = OptionParser::new();
+            parser.add("path");
+            parser
+                .parse(event_stream_config)
+                .map_err(Error::ParsingEventStream)?;
+
+            let path = parser.get("path").ok_or(Error::BareEventStream)?;
+            let listener = UnixListener::bind(&path).map_err(Error::EventStreamSocketBind)?;
+            socket_paths.push(PathBuf::from(path));
+            event_monitor::enable_stream();
+            Some(listener)
+        } else {
+            None
+        };
+
+    let metrics_listener = if let Some(metrics_config) = cmd_arguments.value_of("metrics") {
+        let mut parser = OptionParser::new();
+        parser.add("path").add("tcp");
+        parser
+            .parse(metrics_config)
+            .map_err(Error::ParsingMetrics)?;
+
+        Some(if let Some(path) = parser.get("path") {
+            let listener = UnixListener::bind(&path).map_err(Error::MetricsSocketBind)?;
+            socket_paths.push(PathBuf::from(path));
+            vmm::metrics::MetricsListener::Unix(listener)
+        } else if let Some(address) = parser.get("tcp") {
+            vmm::metrics::MetricsListener::Tcp(
+                TcpListener::bind(address).map_err(Error::MetricsSocketBind)?,
+            )
+        } else {
+            return Err(Error::BareMetrics);
+        })
+    } else {
+        None
+    };
+
+    let fleet_config = if let Some(fleet_config) = cmd_arguments.value_of("fleet") {
+        let mut parser = OptionParser::new();
+        parser
+            .add("socket")
+            .add("name")
+            .add("interval")
+            .add("registry");
+        parser.parse(fleet_config).map_err(Error::ParsingFleet)?;
+
+        let socket = std::path::PathBuf::from(parser.get("socket").ok_or(Error::BareFleet)?);
+        let name = parser
+            .get("name")
+            .unwrap_or_else(|| format!("vmm-{}", std::process::id()));
+        let interval = parser
+            .convert("interval")
+            .map_err(Error::ParsingFleet)?
+            .unwrap_or(vmm::fleet::DEFAULT_FLEET_INTERVAL);
+        if interval == 0 || interval > vmm::fleet::MAX_FLEET_INTERVAL {
+            return Err(Error::InvalidFleetInterval);
+        }
+        let registry = if parser
+            .convert::<option_parser::Toggle>("registry")
+            .map_err(Error::ParsingFleet)?
+            .unwrap_or(option_parser::Toggle(false))
+            .0
+        {
+            // The socket left by a registry which exited, as no one listens
+            // on it anymore, is replaced.
+            if std::os::unix::net::UnixStream::connect(&socket)
+                .map_err(|e| e.kind() == std::io::ErrorKind::ConnectionRefused)
+                .err()
+                .unwrap_or(false)
+            {
+                std::fs::remove_file(&socket).map_err(Error::FleetSocketBind)?;
+            }
+            let listener = UnixListener::bind(&socket).map_err(Error::FleetSocketBind)?;
+            socket_paths.push(socket.clone());
+            Some(listener)
+        } else {
+            None
+        };
+
+        Some(vmm::fleet::FleetConfig {
+            socket,
+            name,
+            interval,
+            registry,
+        })
+    } else {
+        None
+    };
+
     let (api_request_sender, api_request_receiver) = channel();
     let api_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::CreateApiEventFd)?;
 
@@ -500,6 +895,13 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         SeccompAction::Trap
     };
 
+    // The user providing an invalid value will be rejected by clap
+    let leak_check = cmd_arguments
+        .value_of("leak-check")
+        .unwrap()
+        .parse::<vmm::leak_check::LeakCheck>()
+        .unwrap();
+
     if seccomp_action == SeccompAction::Trap {
         // SAFETY: We only using signal_hook for managing signals and only execute signal
         // handler safe functions (writing to stderr) and manipulating signals.
@@ -517,9 +919,9 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         .ok();
     }
 
-    // Before we start any threads, mask the signals we'll be
-    // installing handlers for, to make sure they only ever run on the
-    // dedicated signal handling thread we'll start in a bit.
+    // Before we start any threads, mask the signals handled by the VMM
+    // thread, to make sure they stay pending until it reads them from its
+    // signalfd.
     for sig in &vmm::vm::HANDLED_SIGNALS {
         if let Err(e) = block_signal(*sig) {
             eprintln!("Error blocking signals: {}", e);
@@ -553,6 +955,9 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         env!("CARGO_PKG_VERSION").to_string(),
         &api_socket_path,
         api_socket_fd,
+        metrics_listener,
+        event_stream_listener,
+        fleet_config,
         api_evt.try_clone().unwrap(),
         http_sender,
         api_request_receiver,
@@ -564,19 +969,26 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         vm_debug_evt.try_clone().unwrap(),
         &seccomp_action,
         hypervisor,
+        leak_check,
     )
     .map_err(Error::StartVmmThread)?;
 
-    // Can't test for "vm-config" group as some have default values. The kernel (or tdx if enabled)
-    // is the only required option for booting the VM.
+    // Can't test for "vm-config" group as some have default values. The kernel (or tdx/sev-snp if
+    // enabled) is the only required option for booting the VM.
+    #[allow(unused_mut)]
+    let mut firmware_or_kernel_present = cmd_arguments.is_present("kernel");
+
     #[cfg(feature = "tdx")]
-    let tdx_or_kernel_present =
-        cmd_arguments.is_present("kernel") || cmd_arguments.is_present("tdx");
+    {
+        firmware_or_kernel_present |= cmd_arguments.is_present("tdx");
+    }
 
-    #[cfg(not(feature = "tdx"))]
-    let tdx_or_kernel_present = cmd_arguments.is_present("kernel");
+    #[cfg(feature = "sev_snp")]
+    {
+        firmware_or_kernel_present |= cmd_arguments.is_present("sev-snp");
+    }
 
-    if tdx_or_kernel_present {
+    if firmware_or_kernel_present {
         let vm_params = config::VmParams::from_arg_matches(&cmd_arguments);
         let vm_config = config::VmConfig::parse(vm_params).map_err(Error::ParsingConfig)?;
 
@@ -603,7 +1015,8 @@ fn start_vmm(cmd_arguments: ArgMatches) -> Result<Option<String>, Error> {
         .map_err(Error::ThreadJoin)?
         .map_err(Error::VmmThread)?;
 
-    Ok(api_socket_path)
+    socket_paths.extend(api_socket_path.map(PathBuf::from));
+    Ok(socket_paths)
 }
 
 fn main() {
@@ -612,9 +1025,20 @@ fn main() {
 
     let (default_vcpus, default_memory, default_rng) = prepare_default_values();
     let cmd_arguments = create_app(&default_vcpus, &default_memory, &default_rng).get_matches();
+
+    if cmd_arguments.is_present("print-config-schema") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&config::vm_config_schema()).unwrap()
+        );
+        return;
+    }
+
     let exit_code = match start_vmm(cmd_arguments) {
-        Ok(path) => {
-            path.map(|s| std::fs::remove_file(s).ok());
+        Ok(paths) => {
+            for path in paths {
+                std::fs::remove_file(path).ok();
+            }
             0
         }
         Err(e) => {
@@ -629,11 +1053,12 @@ fn main() {
 #[cfg(test)]
 mod unit_tests {
     use crate::config::HotplugMethod;
-    use crate::{create_app, prepare_default_values};
+    use crate::{create_app, prepare_default_values, LogFormat, Logger};
     use std::path::PathBuf;
+    use std::sync::Mutex;
     use vmm::config::{
         CmdlineConfig, ConsoleConfig, ConsoleOutputMode, CpuFeatures, CpusConfig, KernelConfig,
-        MemoryConfig, RngConfig, VmConfig, VmParams,
+        MemoryConfig, RngConfig, VirtioTransportType, VmConfig, VmParams,
     };
 
     fn get_vm_config_from_vec(args: &[&str]) -> VmConfig {
@@ -678,9 +1103,19 @@ mod unit_tests {
                 max_vcpus: 1,
                 topology: None,
                 kvm_hyperv: false,
+                nested: false,
                 max_phys_bits: 46,
                 affinity: None,
                 features: CpuFeatures::default(),
+                weight: None,
+                quota: None,
+                #[cfg(target_arch = "x86_64")]
+                tsc_khz: None,
+                baseline: None,
+                #[cfg(target_arch = "aarch64")]
+                pmu: true,
+                #[cfg(target_arch = "aarch64")]
+                freeze_counter: true,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
@@ -693,11 +1128,16 @@ mod unit_tests {
                 hugepage_size: None,
                 prefault: false,
                 zones: None,
+                hugepage_pool: false,
+                hugepage_pool_limit: None,
+                virtio_mem_transport: VirtioTransportType::Pci,
+                sealed: false,
             },
             kernel: Some(KernelConfig {
                 path: PathBuf::from("/path/to/kernel"),
             }),
             initramfs: None,
+            payload_blobs: None,
             cmdline: CmdlineConfig {
                 args: String::from(""),
             },
@@ -727,13 +1167,37 @@ mod unit_tests {
             iommu: false,
             #[cfg(target_arch = "x86_64")]
             sgx_epc: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt: None,
             numa: None,
             watchdog: false,
+            watchdog_config: None,
+            pvpanic: false,
+            boot_paused: false,
             #[cfg(feature = "tdx")]
             tdx: None,
+            #[cfg(feature = "sev_snp")]
+            sev_snp: None,
             #[cfg(feature = "gdb")]
             gdb: false,
             platform: None,
+            pstore: None,
+            crashkernel: None,
+            ptp: None,
+            debug_console: None,
+            acpi: None,
+            security: None,
+            deterministic: None,
+            snapshot_schedule: None,
+            irqs: None,
+            doorbells: None,
+            partition_services: None,
+            priority: None,
+            gpu: None,
+            maintenance: None,
+            virtio_features: None,
         };
 
         assert_eq!(expected_vm_config, result_vm_config);
@@ -894,6 +1358,41 @@ mod unit_tests {
         });
     }
 
+    #[test]
+    fn test_valid_vm_config_boot_paused() {
+        vec![
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--boot-paused",
+                ],
+                r#"{
+                    "kernel": {"path": "/path/to/kernel"},
+                    "boot_paused": true
+                }"#,
+                true,
+            ),
+            (
+                vec![
+                    "cloud-hypervisor",
+                    "--kernel",
+                    "/path/to/kernel",
+                    "--boot-paused",
+                ],
+                r#"{
+                    "kernel": {"path": "/path/to/kernel"}
+                }"#,
+                false,
+            ),
+        ]
+        .iter()
+        .for_each(|(cli, openapi, equal)| {
+            compare_vm_config_cli_vs_json(cli, openapi, *equal);
+        });
+    }
+
     #[test]
     fn test_valid_vm_config_disks() {
         vec![
@@ -1654,4 +2153,33 @@ mod unit_tests {
             compare_vm_config_cli_vs_json(cli, openapi, *equal);
         });
     }
+
+    #[test]
+    fn test_log_format_json() {
+        let logger = Logger {
+            output: Mutex::new(Box::new(std::io::sink())),
+            start: std::time::Instant::now(),
+            format: LogFormat::Json,
+        };
+        let record = log::Record::builder()
+            .args(format_args!("Booting VM"))
+            .level(log::Level::Info)
+            .target("vmm::vm")
+            .file(Some("vmm/src/vm.rs"))
+            .line(Some(42))
+            .build();
+
+        let line = logger.json_record(&record, std::time::Duration::from_micros(1500));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["elapsed_us"], 1500);
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["target"], "vmm::vm");
+        assert_eq!(value["file"], "vmm/src/vm.rs");
+        assert_eq!(value["line"], 42);
+        assert_eq!(value["message"], "Booting VM");
+        assert!(value["timestamp_us"].as_u64().unwrap() > 0);
+
+        assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
 }
@@ -4156,6 +4156,113 @@ mod parallel {
         handle_child_output(r, &output);
     }
 
+    #[test]
+    fn test_boot_paused() {
+        let focal = UbuntuDiskConfig::new(FOCAL_IMAGE_NAME.to_string());
+        let guest = Guest::new(Box::new(focal));
+        let kernel_path = direct_kernel_boot_path();
+
+        let api_socket = temp_api_path(&guest.tmp_dir);
+
+        let mut child = GuestCommand::new(&guest)
+            .args(&["--api-socket", &api_socket])
+            .args(&["--cpus", "boot=1"])
+            .args(&["--memory", "size=512M"])
+            .args(&["--kernel", kernel_path.to_str().unwrap()])
+            .args(&["--cmdline", DIRECT_KERNEL_BOOT_CMDLINE])
+            .default_disks()
+            .default_net()
+            .args(&["--boot-paused"])
+            .capture_output()
+            .spawn()
+            .unwrap();
+
+        let r = std::panic::catch_unwind(|| {
+            // The guest doesn't run until the VM is resumed.
+            assert!(guest.wait_vm_boot(Some(30)).is_err());
+            let (cmd_success, cmd_output) = remote_command_w_output(&api_socket, "info", None);
+            assert!(cmd_success);
+            let info: serde_json::Value = serde_json::from_slice(&cmd_output).unwrap_or_default();
+            assert_eq!(info["state"], "Paused");
+
+            assert!(remote_command(&api_socket, "resume", None));
+            guest.wait_vm_boot(None).unwrap();
+            assert_eq!(guest.get_cpu_count().unwrap_or_default(), 1);
+        });
+
+        let _ = child.kill();
+        let output = child.wait_with_output().unwrap();
+
+        handle_child_output(r, &output);
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let focal = UbuntuDiskConfig::new(FOCAL_IMAGE_NAME.to_string());
+        let guest = Guest::new(Box::new(focal));
+        let kernel_path = direct_kernel_boot_path();
+
+        let api_socket = temp_api_path(&guest.tmp_dir);
+        let event_stream_path = guest.tmp_dir.as_path().join("event-stream.sock");
+
+        let mut child = GuestCommand::new(&guest)
+            .args(&["--api-socket", &api_socket])
+            .args(&[
+                "--event-stream",
+                format!("path={}", event_stream_path.to_str().unwrap()).as_str(),
+            ])
+            .args(&["--cpus", "boot=1"])
+            .args(&["--memory", "size=512M"])
+            .args(&["--kernel", kernel_path.to_str().unwrap()])
+            .args(&["--cmdline", DIRECT_KERNEL_BOOT_CMDLINE])
+            .default_disks()
+            .default_net()
+            .capture_output()
+            .spawn()
+            .unwrap();
+
+        let r = std::panic::catch_unwind(|| {
+            guest.wait_vm_boot(None).unwrap();
+
+            // The client receives the events emitted once it connected, one
+            // JSON object per line.
+            let stream = std::os::unix::net::UnixStream::connect(&event_stream_path).unwrap();
+            stream
+                .set_read_timeout(Some(std::time::Duration::from_secs(10)))
+                .unwrap();
+            assert!(remote_command(&api_socket, "pause", None));
+            assert!(remote_command(&api_socket, "resume", None));
+
+            let mut lines = io::BufReader::new(stream).lines();
+            for expected in ["pausing", "paused", "resuming", "resumed"] {
+                let line = lines.next().unwrap().unwrap();
+                let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+                assert_eq!(event["source"], "vm");
+                assert_eq!(event["event"], expected);
+            }
+
+            curl_command(
+                &api_socket,
+                "PUT",
+                "http://localhost/api/v1/vmm.shutdown",
+                None,
+            );
+        });
+
+        let _ = child.wait_timeout(std::time::Duration::from_secs(20));
+        let _ = child.kill();
+        let output = child.wait_with_output().unwrap();
+        handle_child_output(r, &output);
+
+        let r = std::panic::catch_unwind(|| {
+            // The socket is removed once the VMM exits.
+            assert!(output.status.success());
+            assert!(!event_stream_path.exists());
+        });
+
+        handle_child_output(r, &output);
+    }
+
     #[test]
     fn test_virtio_iommu() {
         _test_virtio_iommu(cfg!(target_arch = "x86_64"))
@@ -4996,6 +5103,111 @@ mod parallel {
         handle_child_output(r, &output);
     }
 
+    #[test]
+    fn test_optional_device_skipped() {
+        #[cfg(target_arch = "aarch64")]
+        let focal_image = FOCAL_IMAGE_UPDATE_KERNEL_NAME.to_string();
+        #[cfg(target_arch = "x86_64")]
+        let focal_image = FOCAL_IMAGE_NAME.to_string();
+        let focal = UbuntuDiskConfig::new(focal_image);
+        let guest = Guest::new(Box::new(focal));
+
+        #[cfg(target_arch = "x86_64")]
+        let kernel_path = direct_kernel_boot_path();
+        #[cfg(target_arch = "aarch64")]
+        let kernel_path = edk2_path();
+
+        let api_socket = temp_api_path(&guest.tmp_dir);
+        let event_path = temp_event_monitor_path(&guest.tmp_dir);
+
+        // The backing file of the pmem device doesn't exist yet.
+        let pmem_path = guest.tmp_dir.as_path().join("pmem");
+        let pmem = format!("file={},size=128M,id=pmem0", pmem_path.to_str().unwrap());
+
+        // Without the optional parameter, the VM doesn't boot.
+        let output = GuestCommand::new(&guest)
+            .args(&["--cpus", "boot=1"])
+            .args(&["--memory", "size=512M"])
+            .args(&["--kernel", kernel_path.to_str().unwrap()])
+            .args(&["--cmdline", DIRECT_KERNEL_BOOT_CMDLINE])
+            .default_disks()
+            .default_net()
+            .args(&["--pmem", pmem.as_str()])
+            .capture_output()
+            .spawn()
+            .unwrap()
+            .wait_with_output()
+            .unwrap();
+        assert!(!output.status.success());
+
+        let mut child = GuestCommand::new(&guest)
+            .args(&["--api-socket", &api_socket])
+            .args(&["--cpus", "boot=1"])
+            .args(&["--memory", "size=512M"])
+            .args(&["--kernel", kernel_path.to_str().unwrap()])
+            .args(&["--cmdline", DIRECT_KERNEL_BOOT_CMDLINE])
+            .default_disks()
+            .default_net()
+            .args(&["--pmem", format!("{},optional=on", pmem).as_str()])
+            .args(&["--event-monitor", format!("path={}", event_path).as_str()])
+            .capture_output()
+            .spawn()
+            .unwrap();
+
+        let r = std::panic::catch_unwind(|| {
+            guest.wait_vm_boot(None).unwrap();
+
+            // Check /dev/pmem0 is not there
+            assert_eq!(
+                guest
+                    .ssh_command("ls /dev/pmem0 2>/dev/null | wc -l")
+                    .unwrap()
+                    .trim()
+                    .parse::<u32>()
+                    .unwrap_or(1),
+                0
+            );
+
+            // The skipped device is reported, and left out of the
+            // configuration of the VM.
+            let expected_events = [&MetaEvent {
+                event: "device-skipped".to_string(),
+                device_id: Some("pmem0".to_string()),
+            }];
+            assert!(check_sequential_events(&expected_events, &event_path));
+
+            let (cmd_success, cmd_output) = remote_command_w_output(&api_socket, "info", None);
+            assert!(cmd_success);
+            let info: serde_json::Value = serde_json::from_slice(&cmd_output).unwrap_or_default();
+            assert!(info["config"]["pmem"]
+                .as_array()
+                .map_or(true, |pmem| pmem.is_empty()));
+
+            // The device can still be added once it is available.
+            fs::File::create(&pmem_path)
+                .unwrap()
+                .set_len(128 << 20)
+                .unwrap();
+            assert!(remote_command(&api_socket, "add-pmem", Some(pmem.as_str())));
+            thread::sleep(std::time::Duration::new(10, 0));
+
+            assert_eq!(
+                guest
+                    .ssh_command("ls /dev/pmem0 2>/dev/null | wc -l")
+                    .unwrap()
+                    .trim()
+                    .parse::<u32>()
+                    .unwrap_or_default(),
+                1
+            );
+        });
+
+        let _ = child.kill();
+        let output = child.wait_with_output().unwrap();
+
+        handle_child_output(r, &output);
+    }
+
     #[test]
     fn test_pmem_hotplug() {
         _test_pmem_hotplug(None)
@@ -5658,13 +5870,13 @@ mod parallel {
         let r = std::panic::catch_unwind(|| {
             guest.wait_vm_boot(None).unwrap();
 
-            assert!(remote_command(&api_socket, "pause", None));
-
+            // The VM is paused and resumed around the dump
             assert!(remote_command(
                 &api_socket,
                 "coredump",
                 Some(format!("file://{}", vmcore_file).as_str()),
             ));
+            assert_eq!(guest.get_cpu_count().unwrap_or_default(), 4);
 
             // the num of CORE notes should equals to vcpu
             let readelf_core_num_cmd = format!(
@@ -5678,6 +5890,52 @@ mod parallel {
             let readelf_vmm_num_cmd = format!("readelf --all {} |grep QEMU |wc -l", vmcore_file);
             let vmm_num_in_elf = exec_host_command_output(&readelf_vmm_num_cmd);
             assert_eq!(String::from_utf8_lossy(&vmm_num_in_elf.stdout).trim(), "4");
+
+            // A filtered and sparse dump of the mostly free guest memory
+            // takes less space than the full one.
+            let filtered_vmcore_file = format!("{}.filtered", vmcore_file);
+            assert!(Command::new(clh_command("ch-remote"))
+                .args(&[
+                    &format!("--api-socket={}", api_socket),
+                    "coredump",
+                    &format!("file://{}", filtered_vmcore_file),
+                    "--sparse",
+                    "--exclude-free-pages",
+                ])
+                .status()
+                .expect("Failed to launch ch-remote")
+                .success());
+            let core_num_in_elf = exec_host_command_output(&format!(
+                "readelf --all {} |grep CORE |grep -v Type |wc -l",
+                filtered_vmcore_file
+            ));
+            assert_eq!(String::from_utf8_lossy(&core_num_in_elf.stdout).trim(), "4");
+            let disk_usage = |file: &str| -> u64 {
+                String::from_utf8_lossy(
+                    &exec_host_command_output(&format!("du -k {} | cut -f1", file)).stdout,
+                )
+                .trim()
+                .parse()
+                .unwrap()
+            };
+            assert!(disk_usage(&filtered_vmcore_file) < disk_usage(&vmcore_file));
+
+            // A compressed dump is a zstd frame, smaller than the full one.
+            let compressed_vmcore_file = format!("{}.zst", vmcore_file);
+            assert!(Command::new(clh_command("ch-remote"))
+                .args(&[
+                    &format!("--api-socket={}", api_socket),
+                    "coredump",
+                    &format!("file://{}", compressed_vmcore_file),
+                    "--compress",
+                ])
+                .status()
+                .expect("Failed to launch ch-remote")
+                .success());
+            let compressed_vmcore = std::fs::read(&compressed_vmcore_file).unwrap();
+            assert_eq!(compressed_vmcore[..4], [0x28, 0xb5, 0x2f, 0xfd]);
+            assert!(disk_usage(&compressed_vmcore_file) < disk_usage(&vmcore_file));
+            assert!(!std::path::Path::new(&format!("{}.partial", compressed_vmcore_file)).exists());
         });
 
         let _ = child.kill();
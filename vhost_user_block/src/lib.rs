@@ -8,7 +8,7 @@
 //
 // SPDX-License-Identifier: (Apache-2.0 AND BSD-3-Clause)
 
-use block_util::{build_disk_image_id, Request, VirtioBlockConfig};
+use block_util::{build_disk_image_id, request_chain_limits, Request, VirtioBlockConfig};
 use libc::EFD_NONBLOCK;
 use log::*;
 use option_parser::{OptionParser, OptionParserError, Toggle};
@@ -33,6 +33,7 @@ use vhost_user_backend::{VhostUserBackendMut, VhostUserDaemon, VringRwLock, Vrin
 use virtio_bindings::bindings::virtio_blk::*;
 use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use vm_memory::{bitmap::AtomicBitmap, ByteValued, Bytes, GuestMemoryAtomic};
+use vm_virtio::chain::ChainValidator;
 use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
@@ -93,6 +94,7 @@ struct VhostUserBlkThread {
     event_idx: bool,
     kill_evt: EventFd,
     writeback: Arc<AtomicBool>,
+    chain_validator: ChainValidator,
 }
 
 impl VhostUserBlkThread {
@@ -109,6 +111,7 @@ impl VhostUserBlkThread {
             event_idx: false,
             kill_evt: EventFd::new(EFD_NONBLOCK).map_err(Error::CreateKillEventFd)?,
             writeback,
+            chain_validator: ChainValidator::new(request_chain_limits(), None, Arc::default()),
         })
     }
 
@@ -120,8 +123,16 @@ impl VhostUserBlkThread {
 
         for mut desc_chain in vring.get_queue_mut().iter().unwrap() {
             debug!("got an element in the queue");
+            let valid_chain = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain,
+                Err(_) => {
+                    used_desc_heads.push((desc_chain.head_index(), 0));
+                    continue;
+                }
+            };
+
             let len;
-            match Request::parse(&mut desc_chain, None) {
+            match Request::parse(&valid_chain, desc_chain.memory()) {
                 Ok(mut request) => {
                     debug!("element is a valid request");
                     request.set_writeback(self.writeback.load(Ordering::Acquire));
@@ -20,14 +20,17 @@ use crate::{
 };
 use libc::EFD_NONBLOCK;
 use seccompiler::SeccompAction;
+use std::collections::{BTreeMap, HashMap};
 use std::io;
 use std::mem::size_of;
+use std::num::Wrapping;
 use std::os::unix::io::AsRawFd;
 use std::result;
 use std::sync::{
     atomic::{AtomicBool, AtomicU64, Ordering},
     mpsc, Arc, Barrier, Mutex,
 };
+use std::time::Duration;
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_queue::Queue;
@@ -36,11 +39,17 @@ use vm_memory::{
     GuestMemoryRegion,
 };
 use vm_migration::{
-    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionMapped,
+    protocol::MemoryRange, Migratable, MigratableError, Pausable, Snapshot, Snapshottable,
+    Transportable, VersionMapped,
 };
+use vm_virtio::chain::{ChainLimits, ChainValidator};
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::timerfd::TimerFd;
 
 const QUEUE_SIZE: u16 = 128;
+const STATS_QUEUE_SIZE: u16 = 2;
+// The statistics queue, when enabled, always follows the deflate queue.
+const STATS_QUEUE_INDEX: usize = 2;
 const REPORTING_QUEUE_SIZE: u16 = 32;
 const MIN_NUM_QUEUES: usize = 2;
 
@@ -52,16 +61,79 @@ const INFLATE_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 const DEFLATE_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
 // Reporting virtio queue event.
 const REPORTING_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
+// Statistics virtio queue event.
+const STATS_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
+// Statistics polling timer event.
+const STATS_TIMER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 6;
 
 // Size of a PFN in the balloon interface.
 const VIRTIO_BALLOON_PFN_SHIFT: u64 = 12;
 
+// Enable an additional virtqueue to let the guest report memory statistics.
+const VIRTIO_BALLOON_F_STATS_VQ: u64 = 1;
 // Deflate balloon on OOM
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u64 = 2;
 // Enable an additional virtqueue to let the guest notify the host about free
 // pages.
 const VIRTIO_BALLOON_F_REPORTING: u64 = 5;
 
+// Memory statistics names, indexed by their tag as defined in
+// include/uapi/linux/virtio_balloon.h
+const VIRTIO_BALLOON_STATS: [&str; 16] = [
+    "swap_in",
+    "swap_out",
+    "major_faults",
+    "minor_faults",
+    "free_memory",
+    "total_memory",
+    "available_memory",
+    "disk_caches",
+    "hugetlb_allocations",
+    "hugetlb_failures",
+    "oom_kills",
+    "alloc_stalls",
+    "async_scans",
+    "direct_scans",
+    "async_reclaims",
+    "direct_reclaims",
+];
+// Size of a single statistic: a 16 bits tag followed by a 64 bits value.
+const VIRTIO_BALLOON_STAT_SIZE: u64 = 10;
+// Percentage of the guest memory under which the guest is considered to be
+// under memory pressure.
+const MEMORY_PRESSURE_THRESHOLD: u64 = 10;
+
+// Read the statistics from the buffer the guest gave, ignoring the ones this
+// device doesn't know about.
+fn read_stats(
+    memory: &GuestMemoryMmap,
+    addr: GuestAddress,
+    len: u32,
+) -> result::Result<Vec<(&'static str, u64)>, Error> {
+    let mut stats = Vec::new();
+    let mut offset = 0u64;
+    while offset < len as u64 {
+        let tag_addr = addr.checked_add(offset).ok_or(Error::InvalidRequest)?;
+        let val_addr = tag_addr
+            .checked_add(size_of::<u16>() as u64)
+            .ok_or(Error::InvalidRequest)?;
+        let tag: u16 = memory.read_obj(tag_addr).map_err(Error::GuestMemory)?;
+        let val: u64 = memory.read_obj(val_addr).map_err(Error::GuestMemory)?;
+        offset += VIRTIO_BALLOON_STAT_SIZE;
+
+        if let Some(name) = VIRTIO_BALLOON_STATS.get(tag as usize) {
+            stats.push((*name, val));
+        }
+    }
+
+    Ok(stats)
+}
+
+// The statistics come from the guest, and can't be trusted not to overflow.
+fn memory_pressure(available: u64, total: u64) -> bool {
+    (available as u128) * 100 < (total as u128) * (MEMORY_PRESSURE_THRESHOLD as u128)
+}
+
 #[derive(Debug)]
 pub enum Error {
     // Guest gave us bad memory addresses.
@@ -92,6 +164,8 @@ pub enum Error {
     QueueAddUsed(virtio_queue::Error),
     /// Failed creating an iterator over the queue
     QueueIterator(virtio_queue::Error),
+    /// Failed reading from the statistics timer
+    StatsTimer(io::Error),
 }
 
 // Got from include/uapi/linux/virtio_balloon.h
@@ -160,7 +234,59 @@ impl VirtioBalloonResize {
     }
 }
 
+/// Pages currently given to the balloon by the guest, kept as runs of
+/// consecutive page frame numbers indexed by their first one.
+#[derive(Default)]
+struct InflatedPages {
+    runs: BTreeMap<u64, u64>,
+}
+
+impl InflatedPages {
+    fn insert(&mut self, pfn: u64) {
+        let mut start = pfn;
+        let mut end = pfn + 1;
+        if let Some((&prev_start, &prev_end)) = self.runs.range(..=pfn).next_back() {
+            if prev_end > pfn {
+                return;
+            }
+            if prev_end == pfn {
+                start = prev_start;
+            }
+        }
+        if let Some(next_end) = self.runs.remove(&end) {
+            end = next_end;
+        }
+        self.runs.insert(start, end);
+    }
+
+    fn remove(&mut self, pfn: u64) {
+        if let Some((&start, &end)) = self.runs.range(..=pfn).next_back() {
+            if end <= pfn {
+                return;
+            }
+            self.runs.remove(&start);
+            if start < pfn {
+                self.runs.insert(start, pfn);
+            }
+            if pfn + 1 < end {
+                self.runs.insert(pfn + 1, end);
+            }
+        }
+    }
+
+    fn ranges(&self) -> Vec<MemoryRange> {
+        self.runs
+            .iter()
+            .map(|(start, end)| MemoryRange {
+                gpa: start << VIRTIO_BALLOON_PFN_SHIFT,
+                length: (end - start) << VIRTIO_BALLOON_PFN_SHIFT,
+            })
+            .collect()
+    }
+}
+
 struct BalloonEpollHandler {
+    id: String,
     config: Arc<Mutex<VirtioBalloonConfig>>,
     resize_receiver: VirtioBalloonResizeReceiver,
     queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
@@ -168,8 +294,16 @@ struct BalloonEpollHandler {
     inflate_queue_evt: EventFd,
     deflate_queue_evt: EventFd,
     reporting_queue_evt: Option<EventFd>,
+    stats_queue_evt: Option<EventFd>,
+    stats_timer: Option<TimerFd>,
+    stats: Arc<Mutex<HashMap<&'static str, Wrapping<u64>>>>,
+    // Statistics buffer held until new statistics are requested
+    stats_desc_index: Option<u16>,
+    memory_pressure: bool,
+    inflated_pages: Arc<Mutex<InflatedPages>>,
     kill_evt: EventFd,
     pause_evt: EventFd,
+    chain_validator: ChainValidator,
 }
 
 impl BalloonEpollHandler {
@@ -249,7 +383,13 @@ impl BalloonEpollHandler {
             .iter()
             .map_err(Error::QueueIterator)?
         {
-            let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+            let desc = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain.descriptors()[0],
+                Err(_) => {
+                    used_descs.push((desc_chain.head_index(), 0));
+                    continue;
+                }
+            };
 
             used_descs.push((desc_chain.head_index(), desc.len()));
 
@@ -267,7 +407,10 @@ impl BalloonEpollHandler {
 
             let mut offset = 0u64;
             while offset < desc.len() as u64 {
-                let addr = desc.addr().checked_add(offset).unwrap();
+                let addr = desc
+                    .addr()
+                    .checked_add(offset)
+                    .ok_or(Error::InvalidRequest)?;
                 let pfn: u32 = desc_chain
                     .memory()
                     .read_obj(addr)
@@ -280,6 +423,7 @@ impl BalloonEpollHandler {
                 match queue_index {
                     0 => {
                         Self::release_memory_range(desc_chain.memory(), range_base, range_len)?;
+                        self.inflated_pages.lock().unwrap().insert(pfn as u64);
                     }
                     1 => {
                         Self::advise_memory_range(
@@ -288,6 +432,7 @@ impl BalloonEpollHandler {
                             range_len,
                             libc::MADV_WILLNEED,
                         )?;
+                        self.inflated_pages.lock().unwrap().remove(pfn as u64);
                     }
                     _ => return Err(Error::InvalidQueueIndex(queue_index)),
                 }
@@ -304,8 +449,16 @@ impl BalloonEpollHandler {
             .iter()
             .map_err(Error::QueueIterator)?
         {
+            let valid_chain = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain,
+                Err(_) => {
+                    used_descs.push((desc_chain.head_index(), 0));
+                    continue;
+                }
+            };
+
             let mut descs_len = 0;
-            while let Some(desc) = desc_chain.next() {
+            for desc in valid_chain.descriptors() {
                 descs_len += desc.len();
                 Self::release_memory_range(desc_chain.memory(), desc.addr(), desc.len() as usize)?;
             }
@@ -316,6 +469,111 @@ impl BalloonEpollHandler {
         self.notify_queue(queue_index, used_descs)
     }
 
+    fn reporting_queue_index(&self) -> usize {
+        if self.stats_queue_evt.is_some() {
+            3
+        } else {
+            2
+        }
+    }
+
+    fn process_stats_queue(&mut self) -> result::Result<(), Error> {
+        let queue_index = STATS_QUEUE_INDEX;
+        let mut used_descs = Vec::new();
+        let mut stats = Vec::new();
+
+        for mut desc_chain in self.queues[queue_index]
+            .iter()
+            .map_err(Error::QueueIterator)?
+        {
+            let desc = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain.descriptors()[0],
+                Err(_) => {
+                    used_descs.push((desc_chain.head_index(), 0));
+                    continue;
+                }
+            };
+
+            // A previously held buffer is replaced by the new one, which is
+            // not expected from a well-behaved driver.
+            if let Some(index) = self.stats_desc_index.replace(desc_chain.head_index()) {
+                used_descs.push((index, 0));
+            }
+
+            if desc.is_write_only() {
+                error!("The statistics buffer is write only");
+                return Err(Error::UnexpectedWriteOnlyDescriptor);
+            }
+            if desc.len() as u64 % VIRTIO_BALLOON_STAT_SIZE != 0 {
+                error!("the statistics size {} is not right", desc.len());
+                return Err(Error::InvalidRequest);
+            }
+
+            stats.extend(read_stats(desc_chain.memory(), desc.addr(), desc.len())?);
+        }
+
+        if !stats.is_empty() {
+            self.update_stats(stats);
+        }
+
+        self.notify_queue(queue_index, used_descs)
+    }
+
+    fn update_stats(&mut self, new_stats: Vec<(&'static str, u64)>) {
+        let mut stats = self.stats.lock().unwrap();
+        let previous_oom_kills = stats.get("oom_kills").map(|v| v.0).unwrap_or(0);
+
+        for (name, val) in new_stats {
+            stats.insert(name, Wrapping(val));
+        }
+
+        if let Some(oom_kills) = stats.get("oom_kills").map(|v| v.0) {
+            if oom_kills > previous_oom_kills {
+                event!(
+                    "balloon",
+                    "oom-kill",
+                    "id",
+                    &self.id,
+                    "oom_kills",
+                    oom_kills.to_string()
+                );
+            }
+        }
+
+        if let (Some(available), Some(total)) = (
+            stats.get("available_memory").map(|v| v.0),
+            stats.get("total_memory").map(|v| v.0),
+        ) {
+            let memory_pressure = memory_pressure(available, total);
+            if memory_pressure != self.memory_pressure {
+                self.memory_pressure = memory_pressure;
+                event!(
+                    "balloon",
+                    if memory_pressure {
+                        "memory-pressure"
+                    } else {
+                        "memory-pressure-cleared"
+                    },
+                    "id",
+                    &self.id,
+                    "available_memory",
+                    available.to_string(),
+                    "total_memory",
+                    total.to_string()
+                );
+            }
+        }
+    }
+
+    fn request_stats(&mut self) -> result::Result<(), Error> {
+        // Returning the buffer to the guest is what asks for new statistics.
+        if let Some(index) = self.stats_desc_index.take() {
+            self.notify_queue(STATS_QUEUE_INDEX, vec![(index, 0)])?;
+        }
+
+        Ok(())
+    }
+
     fn run(
         &mut self,
         paused: Arc<AtomicBool>,
@@ -328,6 +586,12 @@ impl BalloonEpollHandler {
         if let Some(reporting_queue_evt) = self.reporting_queue_evt.as_ref() {
             helper.add_event(reporting_queue_evt.as_raw_fd(), REPORTING_QUEUE_EVENT)?;
         }
+        if let Some(stats_queue_evt) = self.stats_queue_evt.as_ref() {
+            helper.add_event(stats_queue_evt.as_raw_fd(), STATS_QUEUE_EVENT)?;
+        }
+        if let Some(stats_timer) = self.stats_timer.as_ref() {
+            helper.add_event(stats_timer.as_raw_fd(), STATS_TIMER_EVENT)?;
+        }
         helper.run(paused, paused_sync, self)?;
 
         Ok(())
@@ -390,7 +654,9 @@ impl EpollHelperHandler for BalloonEpollHandler {
                     if let Err(e) = reporting_queue_evt.read() {
                         error!("Failed to get reporting queue event: {:?}", e);
                         return true;
-                    } else if let Err(e) = self.process_reporting_queue(2) {
+                    } else if let Err(e) =
+                        self.process_reporting_queue(self.reporting_queue_index())
+                    {
                         error!("Failed to signal used inflate queue: {:?}", e);
                         return true;
                     }
@@ -399,6 +665,34 @@ impl EpollHelperHandler for BalloonEpollHandler {
                     return true;
                 }
             }
+            STATS_QUEUE_EVENT => {
+                if let Some(stats_queue_evt) = self.stats_queue_evt.as_ref() {
+                    if let Err(e) = stats_queue_evt.read() {
+                        error!("Failed to get statistics queue event: {:?}", e);
+                        return true;
+                    } else if let Err(e) = self.process_stats_queue() {
+                        error!("Failed to process statistics queue: {:?}", e);
+                        return true;
+                    }
+                } else {
+                    error!("Invalid statistics queue event as no eventfd registered");
+                    return true;
+                }
+            }
+            STATS_TIMER_EVENT => {
+                if let Some(stats_timer) = self.stats_timer.as_ref() {
+                    if let Err(e) = stats_timer.wait().map_err(Error::StatsTimer) {
+                        error!("Failed to read statistics timer: {:?}", e);
+                        return true;
+                    } else if let Err(e) = self.request_stats() {
+                        error!("Failed to request statistics: {:?}", e);
+                        return true;
+                    }
+                } else {
+                    error!("Invalid statistics timer event as no timer registered");
+                    return true;
+                }
+            }
             _ => {
                 error!("Unknown event for virtio-balloon");
                 return true;
@@ -426,15 +720,20 @@ pub struct Balloon {
     config: Arc<Mutex<VirtioBalloonConfig>>,
     seccomp_action: SeccompAction,
     exit_evt: EventFd,
+    stats_polling_interval: u64,
+    stats: Arc<Mutex<HashMap<&'static str, Wrapping<u64>>>>,
+    inflated_pages: Arc<Mutex<InflatedPages>>,
 }
 
 impl Balloon {
     // Create a new virtio-balloon.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
         size: u64,
         deflate_on_oom: bool,
         free_page_reporting: bool,
+        stats_polling_interval: u64,
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
     ) -> io::Result<Self> {
@@ -443,6 +742,11 @@ impl Balloon {
         if deflate_on_oom {
             avail_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
         }
+        // The statistics queue comes before the reporting queue.
+        if stats_polling_interval > 0 {
+            avail_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
+            queue_sizes.push(STATS_QUEUE_SIZE);
+        }
         if free_page_reporting {
             avail_features |= 1u64 << VIRTIO_BALLOON_F_REPORTING;
             queue_sizes.push(REPORTING_QUEUE_SIZE);
@@ -467,6 +771,9 @@ impl Balloon {
             config: Arc::new(Mutex::new(config)),
             seccomp_action,
             exit_evt,
+            stats_polling_interval,
+            stats: Arc::new(Mutex::new(HashMap::new())),
+            inflated_pages: Arc::new(Mutex::new(InflatedPages::default())),
         })
     }
 
@@ -479,6 +786,13 @@ impl Balloon {
         (self.config.lock().unwrap().actual as u64) << VIRTIO_BALLOON_PFN_SHIFT
     }
 
+    // Get the ranges of guest memory currently inflated in the balloon. The
+    // pages reported as free aren't included, as the guest may use them again
+    // without notifying the device.
+    pub fn inflated_ranges(&self) -> Vec<MemoryRange> {
+        self.inflated_pages.lock().unwrap().ranges()
+    }
+
     fn state(&self) -> BalloonState {
         BalloonState {
             avail_features: self.common.avail_features,
@@ -550,6 +864,21 @@ impl VirtioDevice for Balloon {
 
         let inflate_queue_evt = queue_evts.remove(0);
         let deflate_queue_evt = queue_evts.remove(0);
+        let (stats_queue_evt, stats_timer) =
+            if self.common.feature_acked(VIRTIO_BALLOON_F_STATS_VQ) && !queue_evts.is_empty() {
+                let mut timer = TimerFd::new().map_err(|e| {
+                    error!("failed to create statistics timer: {:?}", e);
+                    ActivateError::BadActivate
+                })?;
+                let interval = Duration::from_secs(self.stats_polling_interval);
+                timer.reset(interval, Some(interval)).map_err(|e| {
+                    error!("failed to arm statistics timer: {:?}", e);
+                    ActivateError::BadActivate
+                })?;
+                (Some(queue_evts.remove(0)), Some(timer))
+            } else {
+                (None, None)
+            };
         let reporting_queue_evt =
             if self.common.feature_acked(VIRTIO_BALLOON_F_REPORTING) && !queue_evts.is_empty() {
                 Some(queue_evts.remove(0))
@@ -558,6 +887,7 @@ impl VirtioDevice for Balloon {
             };
 
         let mut handler = BalloonEpollHandler {
+            id: self.id.clone(),
             config: self.config.clone(),
             resize_receiver: self.resize.get_receiver().map_err(|e| {
                 error!("failed to clone resize EventFd: {:?}", e);
@@ -568,8 +898,15 @@ impl VirtioDevice for Balloon {
             inflate_queue_evt,
             deflate_queue_evt,
             reporting_queue_evt,
+            stats_queue_evt,
+            stats_timer,
+            stats: self.stats.clone(),
+            stats_desc_index: None,
+            memory_pressure: false,
+            inflated_pages: self.inflated_pages.clone(),
             kill_evt,
             pause_evt,
+            chain_validator: self.common.chain_validator(ChainLimits::default()),
         };
 
         let paused = self.common.paused.clone();
@@ -582,6 +919,7 @@ impl VirtioDevice for Balloon {
             Thread::VirtioBalloon,
             &mut epoll_threads,
             &self.exit_evt,
+            self.common.thread_nice,
             move || {
                 if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                     error!("Error running worker: {:?}", e);
@@ -594,11 +932,26 @@ impl VirtioDevice for Balloon {
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
+        // The guest gets all of its memory back on reset.
+        self.inflated_pages.lock().unwrap().runs.clear();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
+
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        let mut counters = self.common.counters();
+        if self.stats_polling_interval != 0 {
+            counters.extend(self.stats.lock().unwrap().clone());
+        }
+
+        Some(counters)
+    }
 }
 
 impl Pausable for Balloon {
@@ -627,3 +980,99 @@ impl Snapshottable for Balloon {
 }
 impl Transportable for Balloon {}
 impl Migratable for Balloon {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_stat(memory: &GuestMemoryMmap, addr: GuestAddress, tag: u16, val: u64) {
+        memory.write_obj(tag, addr).unwrap();
+        memory
+            .write_obj(val, addr.unchecked_add(size_of::<u16>() as u64))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_read_stats() {
+        let memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        // available_memory, total_memory and an unknown tag.
+        write_stat(&memory, GuestAddress(0), 6, u64::MAX);
+        write_stat(&memory, GuestAddress(10), 5, u64::MAX);
+        write_stat(&memory, GuestAddress(20), 42, 1);
+
+        let stats = read_stats(&memory, GuestAddress(0), 30).unwrap();
+        assert_eq!(
+            stats,
+            vec![("available_memory", u64::MAX), ("total_memory", u64::MAX)]
+        );
+
+        // A buffer outside of the guest memory, or wrapping around the
+        // address space, is rejected.
+        assert!(matches!(
+            read_stats(&memory, GuestAddress(0x1000), 10),
+            Err(Error::GuestMemory(_))
+        ));
+        assert!(matches!(
+            read_stats(&memory, GuestAddress(u64::MAX - 1), 10),
+            Err(Error::InvalidRequest)
+        ));
+    }
+
+    #[test]
+    fn test_memory_pressure() {
+        assert!(!memory_pressure(u64::MAX, u64::MAX));
+        assert!(memory_pressure(u64::MAX / 20, u64::MAX));
+        assert!(!memory_pressure(u64::MAX, 0));
+        assert!(memory_pressure(0, u64::MAX));
+        assert!(memory_pressure(9, 100));
+        assert!(!memory_pressure(10, 100));
+    }
+
+    #[test]
+    fn test_inflated_pages() {
+        let mut pages = InflatedPages::default();
+        for pfn in [3, 1, 2, 5, 2] {
+            pages.insert(pfn);
+        }
+        assert_eq!(
+            pages.ranges(),
+            vec![
+                MemoryRange {
+                    gpa: 0x1000,
+                    length: 0x3000
+                },
+                MemoryRange {
+                    gpa: 0x5000,
+                    length: 0x1000
+                },
+            ]
+        );
+
+        // Deflating a page splits its run, and unknown pages are ignored.
+        pages.remove(2);
+        pages.remove(5);
+        pages.remove(8);
+        assert_eq!(
+            pages.ranges(),
+            vec![
+                MemoryRange {
+                    gpa: 0x1000,
+                    length: 0x1000
+                },
+                MemoryRange {
+                    gpa: 0x3000,
+                    length: 0x1000
+                },
+            ]
+        );
+
+        pages.insert(2);
+        assert_eq!(
+            pages.ranges(),
+            vec![MemoryRange {
+                gpa: 0x1000,
+                length: 0x3000
+            }]
+        );
+    }
+}
@@ -11,16 +11,18 @@
 use super::Error as DeviceError;
 use super::{
     ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler,
-    RateLimiterConfig, VirtioCommon, VirtioDevice, VirtioDeviceType, VirtioInterruptType,
-    EPOLL_HELPER_EVENT_LAST,
+    InterruptCoalescingConfig, RateLimiterConfig, VirtioCommon, VirtioDevice, VirtioDeviceType,
+    VirtioInterruptType, EPOLL_HELPER_EVENT_LAST,
 };
+use crate::interrupt_coalescing::InterruptCoalescer;
+use crate::rate_limiter_update::RateLimiterUpdate;
 use crate::seccomp_filters::Thread;
 use crate::thread_helper::spawn_virtio_thread;
 use crate::GuestMemoryMmap;
 use crate::VirtioInterrupt;
 use block_util::{
-    async_io::AsyncIo, async_io::AsyncIoError, async_io::DiskFile, build_disk_image_id, Request,
-    RequestType, VirtioBlockConfig,
+    async_io::AsyncIo, async_io::AsyncIoError, async_io::DiskFile, build_disk_image_id,
+    request_chain_limits, Request, RequestType, VirtioBlockConfig,
 };
 use rate_limiter::{RateLimiter, TokenType};
 use seccompiler::SeccompAction;
@@ -30,7 +32,7 @@ use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::result;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Barrier};
+use std::sync::{Arc, Barrier, Mutex};
 use std::{collections::HashMap, convert::TryInto};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
@@ -39,6 +41,9 @@ use virtio_queue::Queue;
 use vm_memory::{ByteValued, Bytes, GuestAddressSpace, GuestMemoryAtomic};
 use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::chain::ChainValidator;
+#[cfg(feature = "fault_injection")]
+use vm_virtio::fault_injection::Faults;
 use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -51,6 +56,10 @@ const QUEUE_AVAIL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 const COMPLETION_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 // New 'wake up' event from the rate limiter
 const RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+// New rate limiter handed over at runtime
+const RATE_LIMITER_UPDATE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
+// The coalesced interrupt is due
+const INTERRUPT_COALESCING_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
 
 #[derive(Debug)]
 pub enum Error {
@@ -97,11 +106,35 @@ struct BlockEpollHandler {
     queue_evt: EventFd,
     request_list: HashMap<u16, Request>,
     rate_limiter: Option<RateLimiter>,
-    access_platform: Option<Arc<dyn AccessPlatform>>,
+    rate_limiter_update: RateLimiterUpdate,
+    interrupt_coalescer: InterruptCoalescer,
+    chain_validator: ChainValidator,
+    #[cfg(feature = "fault_injection")]
+    faults: Option<Arc<Faults>>,
 }
 
 impl BlockEpollHandler {
-    fn process_queue_submit(&mut self) -> Result<bool> {
+    // Whether the request accesses sectors the disk has been asked to fail.
+    #[cfg(feature = "fault_injection")]
+    fn io_error_injected(&self, request: &Request) -> bool {
+        let faults = match &self.faults {
+            Some(faults) => faults,
+            None => return false,
+        };
+
+        if request.request_type != RequestType::In && request.request_type != RequestType::Out {
+            return false;
+        }
+
+        let len: u64 = request
+            .data_descriptors
+            .iter()
+            .map(|(_, data_len)| *data_len as u64)
+            .sum();
+        faults.disk_io_error(request.sector, len >> SECTOR_SHIFT)
+    }
+
+    fn process_queue_submit(&mut self) -> Result<u32> {
         let queue = &mut self.queue;
 
         let mut used_desc_heads = Vec::new();
@@ -109,8 +142,19 @@ impl BlockEpollHandler {
 
         let mut avail_iter = queue.iter().map_err(Error::QueueIterator)?;
         for mut desc_chain in &mut avail_iter {
-            let mut request = Request::parse(&mut desc_chain, self.access_platform.as_ref())
-                .map_err(Error::RequestParsing)?;
+            // The chain is returned untouched, as it gives no status to
+            // write to.
+            let valid_chain = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain,
+                Err(_) => {
+                    used_desc_heads.push((desc_chain.head_index(), 0));
+                    used_count += 1;
+                    continue;
+                }
+            };
+
+            let mut request =
+                Request::parse(&valid_chain, desc_chain.memory()).map_err(Error::RequestParsing)?;
 
             if let Some(rate_limiter) = &mut self.rate_limiter {
                 // If limiter.consume() fails it means there is no more TokenType::Ops
@@ -143,6 +187,20 @@ impl BlockEpollHandler {
                 };
             }
 
+            #[cfg(feature = "fault_injection")]
+            if self.io_error_injected(&request) {
+                // We use unwrap because the request parsing process already
+                // checked that the status_addr was valid.
+                desc_chain
+                    .memory()
+                    .write_obj(VIRTIO_BLK_S_IOERR as u8, request.status_addr)
+                    .unwrap();
+
+                used_desc_heads.push((desc_chain.head_index(), 0));
+                used_count += 1;
+                continue;
+            }
+
             request.set_writeback(self.writeback.load(Ordering::Acquire));
 
             if request
@@ -177,10 +235,10 @@ impl BlockEpollHandler {
                 .map_err(Error::QueueAddUsed)?;
         }
 
-        Ok(used_count > 0)
+        Ok(used_count)
     }
 
-    fn process_queue_complete(&mut self) -> Result<bool> {
+    fn process_queue_complete(&mut self) -> Result<u32> {
         let queue = &mut self.queue;
 
         let mut used_desc_heads = Vec::new();
@@ -257,7 +315,7 @@ impl BlockEpollHandler {
             .read_ops
             .fetch_add(read_ops.0, Ordering::AcqRel);
 
-        Ok(used_count > 0)
+        Ok(used_count)
     }
 
     fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
@@ -269,6 +327,20 @@ impl BlockEpollHandler {
             })
     }
 
+    // Signal the used buffers, right away or once the interrupt is due when
+    // it is coalesced.
+    fn notify_used(&mut self, used_count: u32) -> result::Result<(), DeviceError> {
+        if self
+            .interrupt_coalescer
+            .used(used_count)
+            .map_err(DeviceError::IoError)?
+        {
+            self.signal_used_queue()?;
+        }
+
+        Ok(())
+    }
+
     fn run(
         &mut self,
         paused: Arc<AtomicBool>,
@@ -276,10 +348,18 @@ impl BlockEpollHandler {
     ) -> result::Result<(), EpollHelperError> {
         let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
         helper.add_event(self.queue_evt.as_raw_fd(), QUEUE_AVAIL_EVENT)?;
+        helper.add_event(
+            self.interrupt_coalescer.timer().as_raw_fd(),
+            INTERRUPT_COALESCING_EVENT,
+        )?;
         helper.add_event(self.disk_image.notifier().as_raw_fd(), COMPLETION_EVENT)?;
         if let Some(rate_limiter) = &self.rate_limiter {
             helper.add_event(rate_limiter.as_raw_fd(), RATE_LIMITER_EVENT)?;
         }
+        helper.add_event(
+            self.rate_limiter_update.evt().as_raw_fd(),
+            RATE_LIMITER_UPDATE_EVENT,
+        )?;
         helper.run(paused, paused_sync, self)?;
 
         Ok(())
@@ -287,7 +367,7 @@ impl BlockEpollHandler {
 }
 
 impl EpollHelperHandler for BlockEpollHandler {
-    fn handle_event(&mut self, _helper: &mut EpollHelper, event: &epoll::Event) -> bool {
+    fn handle_event(&mut self, helper: &mut EpollHelper, event: &epoll::Event) -> bool {
         let ev_type = event.data as u16;
         match ev_type {
             QUEUE_AVAIL_EVENT => {
@@ -302,12 +382,10 @@ impl EpollHelperHandler for BlockEpollHandler {
                 // Process the queue only when the rate limit is not reached
                 if !rate_limit_reached {
                     match self.process_queue_submit() {
-                        Ok(needs_notification) => {
-                            if needs_notification {
-                                if let Err(e) = self.signal_used_queue() {
-                                    error!("Failed to signal used queue: {:?}", e);
-                                    return true;
-                                }
+                        Ok(used_count) => {
+                            if let Err(e) = self.notify_used(used_count) {
+                                error!("Failed to signal used queue: {:?}", e);
+                                return true;
                             }
                         }
                         Err(e) => {
@@ -324,12 +402,10 @@ impl EpollHelperHandler for BlockEpollHandler {
                 }
 
                 match self.process_queue_complete() {
-                    Ok(needs_notification) => {
-                        if needs_notification {
-                            if let Err(e) = self.signal_used_queue() {
-                                error!("Failed to signal used queue: {:?}", e);
-                                return true;
-                            }
+                    Ok(used_count) => {
+                        if let Err(e) = self.notify_used(used_count) {
+                            error!("Failed to signal used queue: {:?}", e);
+                            return true;
                         }
                     }
                     Err(e) => {
@@ -344,12 +420,10 @@ impl EpollHelperHandler for BlockEpollHandler {
                     // and restart processing the queue.
                     if rate_limiter.event_handler().is_ok() {
                         match self.process_queue_submit() {
-                            Ok(needs_notification) => {
-                                if needs_notification {
-                                    if let Err(e) = self.signal_used_queue() {
-                                        error!("Failed to signal used queue: {:?}", e);
-                                        return true;
-                                    }
+                            Ok(used_count) => {
+                                if let Err(e) = self.notify_used(used_count) {
+                                    error!("Failed to signal used queue: {:?}", e);
+                                    return true;
                                 }
                             }
                             Err(e) => {
@@ -363,6 +437,29 @@ impl EpollHelperHandler for BlockEpollHandler {
                     return true;
                 }
             }
+            RATE_LIMITER_UPDATE_EVENT => {
+                if let Err(e) = self.rate_limiter_update.receive(
+                    &mut self.rate_limiter,
+                    helper,
+                    RATE_LIMITER_EVENT,
+                ) {
+                    error!("Failed to update the rate limiter: {:?}", e);
+                    return true;
+                }
+            }
+            INTERRUPT_COALESCING_EVENT => match self.interrupt_coalescer.expired() {
+                Ok(true) => {
+                    if let Err(e) = self.signal_used_queue() {
+                        error!("Failed to signal used queue: {:?}", e);
+                        return true;
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Failed to get interrupt coalescing event: {:?}", e);
+                    return true;
+                }
+            },
             _ => {
                 error!("Unexpected event: {}", ev_type);
                 return true;
@@ -370,6 +467,18 @@ impl EpollHelperHandler for BlockEpollHandler {
         }
         false
     }
+
+    fn pause(&mut self) {
+        match self.interrupt_coalescer.flush() {
+            Ok(true) => {
+                if let Err(e) = self.signal_used_queue() {
+                    error!("Failed to signal used queue: {:?}", e);
+                }
+            }
+            Ok(false) => {}
+            Err(e) => error!("Failed to flush interrupt coalescing: {:?}", e),
+        }
+    }
 }
 
 /// Virtio device for exposing block level read/write operations on a host file.
@@ -384,7 +493,11 @@ pub struct Block {
     counters: BlockCounters,
     seccomp_action: SeccompAction,
     rate_limiter_config: Option<RateLimiterConfig>,
+    rate_limiter_updates: Vec<RateLimiterUpdate>,
+    interrupt_coalescing: Arc<Mutex<Option<InterruptCoalescingConfig>>>,
     exit_evt: EventFd,
+    #[cfg(feature = "fault_injection")]
+    faults: Option<Arc<Faults>>,
 }
 
 #[derive(Versionize)]
@@ -492,7 +605,11 @@ impl Block {
             counters: BlockCounters::default(),
             seccomp_action,
             rate_limiter_config,
+            rate_limiter_updates: Vec::new(),
+            interrupt_coalescing: Arc::new(Mutex::new(None)),
             exit_evt,
+            #[cfg(feature = "fault_injection")]
+            faults: None,
         })
     }
 
@@ -553,6 +670,10 @@ impl VirtioDevice for Block {
         &self.common.queue_sizes
     }
 
+    fn set_queue_max_size(&mut self, max_size: u16) -> result::Result<(), DeviceError> {
+        self.common.set_queue_max_size(max_size)
+    }
+
     fn features(&self) -> u64 {
         self.common.avail_features
     }
@@ -595,6 +716,7 @@ impl VirtioDevice for Block {
         let disk_image_id = build_disk_image_id(&self.disk_path);
         self.update_writeback();
 
+        self.rate_limiter_updates.clear();
         let mut epoll_threads = Vec::new();
         for i in 0..queues.len() {
             let queue_evt = queue_evts.remove(0);
@@ -607,6 +729,15 @@ impl VirtioDevice for Block {
                 .map(RateLimiterConfig::try_into)
                 .transpose()
                 .map_err(ActivateError::CreateRateLimiter)?;
+            let rate_limiter_update =
+                RateLimiterUpdate::new().map_err(ActivateError::CreateRateLimiter)?;
+            self.rate_limiter_updates.push(
+                rate_limiter_update
+                    .try_clone()
+                    .map_err(ActivateError::CreateRateLimiter)?,
+            );
+            let interrupt_coalescer = InterruptCoalescer::new(self.interrupt_coalescing.clone())
+                .map_err(ActivateError::CreateInterruptCoalescer)?;
 
             let mut handler = BlockEpollHandler {
                 queue_index: i as u16,
@@ -629,7 +760,11 @@ impl VirtioDevice for Block {
                 queue_evt,
                 request_list: HashMap::with_capacity(queue_size.into()),
                 rate_limiter,
-                access_platform: self.common.access_platform.clone(),
+                rate_limiter_update,
+                interrupt_coalescer,
+                chain_validator: self.common.chain_validator(request_chain_limits()),
+                #[cfg(feature = "fault_injection")]
+                faults: self.faults.clone(),
             };
 
             let paused = self.common.paused.clone();
@@ -641,6 +776,7 @@ impl VirtioDevice for Block {
                 Thread::VirtioBlock,
                 &mut epoll_threads,
                 &self.exit_evt,
+                self.common.thread_nice,
                 move || {
                     if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                         error!("Error running worker: {:?}", e);
@@ -655,14 +791,19 @@ impl VirtioDevice for Block {
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
+        self.rate_limiter_updates.clear();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
 
     fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
-        let mut counters = HashMap::new();
+        let mut counters = self.common.counters();
 
         counters.insert(
             "read_bytes",
@@ -687,6 +828,34 @@ impl VirtioDevice for Block {
     fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
         self.common.set_access_platform(access_platform)
     }
+
+    #[cfg(feature = "fault_injection")]
+    fn set_faults(&mut self, faults: Arc<Faults>) {
+        self.faults = Some(faults);
+    }
+
+    fn set_rate_limiter_config(
+        &mut self,
+        rate_limiter_config: Option<RateLimiterConfig>,
+    ) -> result::Result<(), DeviceError> {
+        for rate_limiter_update in self.rate_limiter_updates.iter() {
+            rate_limiter_update
+                .send(rate_limiter_config)
+                .map_err(DeviceError::UpdateRateLimiter)?;
+        }
+        self.rate_limiter_config = rate_limiter_config;
+
+        Ok(())
+    }
+
+    fn set_interrupt_coalescing_config(
+        &mut self,
+        interrupt_coalescing_config: Option<InterruptCoalescingConfig>,
+    ) -> result::Result<(), DeviceError> {
+        *self.interrupt_coalescing.lock().unwrap() = interrupt_coalescing_config;
+
+        Ok(())
+    }
 }
 
 impl Pausable for Block {
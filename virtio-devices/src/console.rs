@@ -14,10 +14,11 @@ use crate::VirtioInterrupt;
 use libc::{EFD_NONBLOCK, TIOCGWINSZ};
 use seccompiler::SeccompAction;
 use std::cmp;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
+use std::num::Wrapping;
 use std::os::unix::io::AsRawFd;
 use std::result;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
@@ -28,7 +29,8 @@ use virtio_queue::Queue;
 use vm_memory::{ByteValued, Bytes, GuestMemoryAtomic};
 use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
-use vm_virtio::{AccessPlatform, Translatable};
+use vm_virtio::chain::{ChainLimits, ChainValidator};
+use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
 
 const QUEUE_SIZE: u16 = 256;
@@ -86,7 +88,7 @@ struct ConsoleEpollHandler {
     resize_pipe: Option<File>,
     kill_evt: EventFd,
     pause_evt: EventFd,
-    access_platform: Option<Arc<dyn AccessPlatform>>,
+    chain_validator: ChainValidator,
 }
 
 pub enum Endpoint {
@@ -144,15 +146,26 @@ impl ConsoleEpollHandler {
 
         let mut avail_iter = recv_queue.iter().unwrap();
         for mut desc_chain in &mut avail_iter {
-            let desc = desc_chain.next().unwrap();
+            let desc = match self
+                .chain_validator
+                .validate(&mut desc_chain)
+                .map(|valid_chain| valid_chain.writable().first().copied())
+            {
+                Ok(Some(desc)) => desc,
+                _ => {
+                    used_desc_heads[used_count] = (desc_chain.head_index(), 0);
+                    used_count += 1;
+                    continue;
+                }
+            };
+
             let len = cmp::min(desc.len() as u32, in_buffer.len() as u32);
             let source_slice = in_buffer.drain(..len as usize).collect::<Vec<u8>>();
 
-            if let Err(e) = desc_chain.memory().write_slice(
-                &source_slice[..],
-                desc.addr()
-                    .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
-            ) {
+            if let Err(e) = desc_chain
+                .memory()
+                .write_slice(&source_slice[..], desc.addr())
+            {
                 error!("Failed to write slice: {:?}", e);
                 avail_iter.go_to_previous_position();
                 break;
@@ -186,14 +199,23 @@ impl ConsoleEpollHandler {
         let mut used_count = 0;
 
         for mut desc_chain in trans_queue.iter().unwrap() {
-            let desc = desc_chain.next().unwrap();
+            let desc = match self
+                .chain_validator
+                .validate(&mut desc_chain)
+                .map(|valid_chain| valid_chain.readable().first().copied())
+            {
+                Ok(Some(desc)) => desc,
+                _ => {
+                    used_desc_heads[used_count] = (desc_chain.head_index(), 0);
+                    used_count += 1;
+                    continue;
+                }
+            };
+
             if let Some(ref mut out) = self.endpoint.out_file() {
-                let _ = desc_chain.memory().write_to(
-                    desc.addr()
-                        .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
-                    out,
-                    desc.len() as usize,
-                );
+                let _ = desc_chain
+                    .memory()
+                    .write_to(desc.addr(), out, desc.len() as usize);
                 let _ = out.flush();
             }
             used_desc_heads[used_count] = (desc_chain.head_index(), desc.len());
@@ -520,7 +542,7 @@ impl VirtioDevice for Console {
             resizer: Arc::clone(&self.resizer),
             kill_evt,
             pause_evt,
-            access_platform: self.common.access_platform.clone(),
+            chain_validator: self.common.chain_validator(ChainLimits::default()),
         };
 
         let paused = self.common.paused.clone();
@@ -533,6 +555,7 @@ impl VirtioDevice for Console {
             Thread::VirtioConsole,
             &mut epoll_threads,
             &self.exit_evt,
+            self.common.thread_nice,
             move || {
                 if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                     error!("Error running worker: {:?}", e);
@@ -546,12 +569,20 @@ impl VirtioDevice for Console {
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
 
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
+
     fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
         self.common.set_access_platform(access_platform)
     }
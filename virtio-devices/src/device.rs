@@ -8,20 +8,23 @@
 
 use crate::{
     ActivateError, ActivateResult, Error, GuestMemoryMmap, GuestRegionMmap,
-    VIRTIO_F_RING_INDIRECT_DESC,
+    InterruptCoalescingConfig, RateLimiterConfig, VIRTIO_F_RING_INDIRECT_DESC,
 };
 use libc::EFD_NONBLOCK;
 use std::collections::HashMap;
 use std::io::Write;
 use std::num::Wrapping;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Arc, Barrier,
 };
 use std::thread;
 use virtio_queue::Queue;
 use vm_memory::{GuestAddress, GuestMemoryAtomic, GuestUsize};
 use vm_migration::{MigratableError, Pausable};
+use vm_virtio::chain::{ChainLimits, ChainValidator};
+#[cfg(feature = "fault_injection")]
+use vm_virtio::fault_injection::Faults;
 use vm_virtio::AccessPlatform;
 use vm_virtio::VirtioDeviceType;
 use vmm_sys_util::eventfd::EventFd;
@@ -198,6 +201,42 @@ pub trait VirtioDevice: Send {
     /// Set the access platform trait to let the device perform address
     /// translations if needed.
     fn set_access_platform(&mut self, _access_platform: Arc<dyn AccessPlatform>) {}
+
+    /// Set the nice value the threads of the device run with, inherited
+    /// from the VMM thread when `None`. Must be called before the device is
+    /// activated.
+    fn set_thread_nice(&mut self, _nice: Option<i32>) {}
+
+    /// Set the faults injected into the device. Devices without any fault
+    /// specific to their type ignore them.
+    #[cfg(feature = "fault_injection")]
+    fn set_faults(&mut self, _faults: Arc<Faults>) {}
+
+    /// Change the rate limiting of the device, taking effect right away if
+    /// the device is activated. Devices without rate limiting refuse it.
+    fn set_rate_limiter_config(
+        &mut self,
+        _rate_limiter_config: Option<RateLimiterConfig>,
+    ) -> std::result::Result<(), Error> {
+        Err(Error::RateLimiterUnsupported)
+    }
+
+    /// Change the coalescing of the used queue interrupts of the device,
+    /// applying to the next used buffers. Devices without interrupt
+    /// coalescing refuse it.
+    fn set_interrupt_coalescing_config(
+        &mut self,
+        _interrupt_coalescing_config: Option<InterruptCoalescingConfig>,
+    ) -> std::result::Result<(), Error> {
+        Err(Error::InterruptCoalescingUnsupported)
+    }
+
+    /// Change the maximum size of the queues of the device, before the
+    /// driver sets them up. Devices whose queue size isn't configurable
+    /// refuse it.
+    fn set_queue_max_size(&mut self, _max_size: u16) -> std::result::Result<(), Error> {
+        Err(Error::QueueSizeUnsupported)
+    }
 }
 
 /// Trait providing address translation the same way a physical DMA remapping
@@ -229,9 +268,25 @@ pub struct VirtioCommon {
     pub device_type: u32,
     pub min_queues: u16,
     pub access_platform: Option<Arc<dyn AccessPlatform>>,
+    // Descriptor chains rejected by the workers of the device.
+    pub rejected_chains: Arc<AtomicU64>,
+    // Nice value of the threads of the device, inherited from the VMM
+    // thread when not set.
+    pub thread_nice: Option<i32>,
 }
 
 impl VirtioCommon {
+    pub fn set_queue_max_size(&mut self, max_size: u16) -> std::result::Result<(), Error> {
+        if !max_size.is_power_of_two() {
+            return Err(Error::InvalidQueueSize(max_size));
+        }
+
+        self.queue_sizes
+            .iter_mut()
+            .for_each(|size| *size = max_size);
+        Ok(())
+    }
+
     pub fn feature_acked(&self, feature: u64) -> bool {
         self.acked_features & 1 << feature == 1 << feature
     }
@@ -338,6 +393,26 @@ impl VirtioCommon {
         // requires the addresses held by the descriptors to be translated.
         self.avail_features &= !(1 << VIRTIO_F_RING_INDIRECT_DESC);
     }
+
+    /// Creates the validator of the descriptor chains processed by a worker
+    /// of the device, to be created once the access platform is known.
+    pub fn chain_validator(&self, limits: ChainLimits) -> ChainValidator {
+        ChainValidator::new(
+            limits,
+            self.access_platform.clone(),
+            self.rejected_chains.clone(),
+        )
+    }
+
+    /// Counters common to all devices, for the devices to extend.
+    pub fn counters(&self) -> HashMap<&'static str, Wrapping<u64>> {
+        let mut counters = HashMap::new();
+        counters.insert(
+            "rejected_chains",
+            Wrapping(self.rejected_chains.load(Ordering::Acquire)),
+        );
+        counters
+    }
 }
 
 impl Pausable for VirtioCommon {
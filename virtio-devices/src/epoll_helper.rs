@@ -36,6 +36,9 @@ pub const EPOLL_HELPER_EVENT_LAST: u16 = 15;
 pub trait EpollHelperHandler {
     // Return true if the loop execution should be stopped
     fn handle_event(&mut self, helper: &mut EpollHelper, event: &epoll::Event) -> bool;
+
+    // Complete the work deferred by the handler before the loop is paused
+    fn pause(&mut self) {}
 }
 
 impl EpollHelper {
@@ -138,6 +141,8 @@ impl EpollHelper {
                     EPOLL_HELPER_EVENT_PAUSE => {
                         info!("PAUSE_EVENT received, pausing epoll loop");
 
+                        handler.pause();
+
                         // Acknowledge the pause is effective by using the
                         // paused_sync barrier.
                         paused_sync.wait();
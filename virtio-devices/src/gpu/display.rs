@@ -0,0 +1,459 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Display backends of the virtio-gpu device. A single viewer connects to the
+// socket of the device at a time, and receives messages made of a header of
+// eight little endian 32 bits words, the first one being the message type,
+// possibly followed by pixels:
+//
+// - SCANOUT: width, height, DRM fourcc and stride of the scanout, all zeroes
+//   when the scanout is disabled. With the dmabuf backend, the dmabuf the
+//   scanout is rendered to is attached to the message.
+// - UPDATE: x, y, width and height of the part of the scanout updated. With
+//   the socket backend, the pixels of the rectangle follow, row by row.
+// - CURSOR: x, y, hot spot x and y, size and DRM fourcc of the cursor, whose
+//   size x size pixels follow. A size of zero hides the cursor.
+// - MOVE_CURSOR: x and y of the cursor.
+
+use super::renderer::Rect;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use vmm_sys_util::sock_ctrl_msg::ScmSocket;
+
+const MSG_SCANOUT: u32 = 1;
+const MSG_UPDATE: u32 = 2;
+const MSG_CURSOR: u32 = 3;
+const MSG_MOVE_CURSOR: u32 = 4;
+
+const BYTES_PER_PIXEL: u32 = 4;
+
+// The messages are written to the viewer without blocking, those it doesn't
+// take right away being kept until it becomes writable. A viewer not reading
+// its messages is disconnected past that much of them, rather than stalling
+// the guest or growing without bounds.
+const MAX_VIEWER_BACKLOG: usize = 64 << 20;
+
+// See include/uapi/linux/udmabuf.h in the kernel code.
+const UDMABUF_CREATE: u64 = 0x4018_7542;
+const UDMABUF_FLAGS_CLOEXEC: u32 = 0x01;
+
+#[repr(C)]
+struct UdmabufCreate {
+    memfd: u32,
+    flags: u32,
+    offset: u64,
+    size: u64,
+}
+
+/// Size and format of the scanout shown on the display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scanout {
+    pub width: u32,
+    pub height: u32,
+    pub fourcc: u32,
+}
+
+/// Cursor image and position.
+#[derive(Clone, Debug)]
+pub struct Cursor {
+    pub x: u32,
+    pub y: u32,
+    pub hot_x: u32,
+    pub hot_y: u32,
+    pub size: u32,
+    pub fourcc: u32,
+    pub image: Vec<u8>,
+}
+
+/// Presents the scanout of the virtio-gpu device to the viewer connected to
+/// the device. Nothing is presented while no viewer is connected.
+pub trait DisplayBackend: Send {
+    /// Replaces the viewer, which the whole scanout is presented to next.
+    fn connect(&mut self, stream: UnixStream) -> io::Result<()>;
+    /// Sets the size and format of the scanout, or disables it.
+    fn set_scanout(&mut self, scanout: Option<Scanout>) -> io::Result<()>;
+    /// Presents the `damage` rectangle of the scanout, whose content is
+    /// `frame` laid out with `stride` bytes per row.
+    fn update(&mut self, frame: &[u8], stride: u32, damage: Rect) -> io::Result<()>;
+    /// Sets the cursor image and position, or hides the cursor.
+    fn set_cursor(&mut self, cursor: Option<&Cursor>) -> io::Result<()>;
+    /// Moves the cursor.
+    fn move_cursor(&mut self, x: u32, y: u32) -> io::Result<()>;
+    /// Writes the messages the viewer didn't take yet.
+    fn flush(&mut self);
+    /// Socket of the viewer while it has messages pending, to be flushed once
+    /// it becomes writable.
+    fn pending_viewer(&self) -> Option<RawFd>;
+}
+
+// Connection to the viewer, which is dropped on the first error.
+#[derive(Default)]
+struct Viewer {
+    stream: Option<UnixStream>,
+    // Messages not written to the viewer yet.
+    backlog: Vec<u8>,
+}
+
+impl Viewer {
+    fn connect(&mut self, stream: UnixStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        if self.stream.replace(stream).is_some() {
+            info!("virtio-gpu viewer replaced");
+        }
+        self.backlog.clear();
+        Ok(())
+    }
+
+    fn disconnect(&mut self, e: io::Error) {
+        info!("virtio-gpu viewer disconnected: {}", e);
+        self.stream = None;
+        self.backlog.clear();
+    }
+
+    fn flush(&mut self) {
+        while !self.backlog.is_empty() {
+            let stream = match self.stream.as_mut() {
+                Some(stream) => stream,
+                None => return,
+            };
+            match stream.write(&self.backlog) {
+                Ok(0) => return self.disconnect(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(len) => {
+                    self.backlog.drain(..len);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return self.disconnect(e),
+            }
+        }
+    }
+
+    fn pending(&self) -> Option<RawFd> {
+        match self.stream.as_ref() {
+            Some(stream) if !self.backlog.is_empty() => Some(stream.as_raw_fd()),
+            _ => None,
+        }
+    }
+
+    fn send(&mut self, words: &[u32], payload: &[u8], fd: Option<RawFd>) -> io::Result<()> {
+        if self.stream.is_none() {
+            return Ok(());
+        }
+
+        let mut header = [0u8; 32];
+        for (i, word) in words.iter().enumerate() {
+            header[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+
+        self.flush();
+        match (fd, self.stream.as_mut()) {
+            (_, None) => return Ok(()),
+            // The file descriptor is sent along with the first bytes of the
+            // header, which can't be queued behind the messages pending.
+            (Some(fd), Some(stream)) => {
+                if !self.backlog.is_empty() {
+                    self.disconnect(io::Error::from(io::ErrorKind::WouldBlock));
+                    return Ok(());
+                }
+                match stream.send_with_fd(&header[..], fd) {
+                    Ok(len) if len > 0 => self.backlog.extend_from_slice(&header[len..]),
+                    Ok(_) => {
+                        self.disconnect(io::Error::from(io::ErrorKind::WriteZero));
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        self.disconnect(io::Error::from_raw_os_error(e.errno()));
+                        return Ok(());
+                    }
+                }
+            }
+            (None, Some(_)) => self.backlog.extend_from_slice(&header),
+        }
+        self.backlog.extend_from_slice(payload);
+
+        if self.backlog.len() > MAX_VIEWER_BACKLOG {
+            self.disconnect(io::Error::new(
+                io::ErrorKind::Other,
+                "too many messages pending",
+            ));
+            return Ok(());
+        }
+        self.flush();
+
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, cursor: Option<&Cursor>) -> io::Result<()> {
+        match cursor {
+            Some(c) => self.send(
+                &[MSG_CURSOR, c.x, c.y, c.hot_x, c.hot_y, c.size, c.fourcc],
+                &c.image,
+                None,
+            ),
+            None => self.send(&[MSG_CURSOR], &[], None),
+        }
+    }
+
+    fn move_cursor(&mut self, x: u32, y: u32) -> io::Result<()> {
+        self.send(&[MSG_MOVE_CURSOR, x, y], &[], None)
+    }
+}
+
+/// Sends the pixels of the updated parts of the scanout to the viewer, e.g.
+/// a remote viewer relaying them over the network.
+#[derive(Default)]
+pub struct SocketDisplay {
+    viewer: Viewer,
+}
+
+impl SocketDisplay {
+    pub fn new() -> Self {
+        SocketDisplay::default()
+    }
+}
+
+impl DisplayBackend for SocketDisplay {
+    fn connect(&mut self, stream: UnixStream) -> io::Result<()> {
+        self.viewer.connect(stream)
+    }
+
+    fn set_scanout(&mut self, scanout: Option<Scanout>) -> io::Result<()> {
+        match scanout {
+            Some(s) => self.viewer.send(
+                &[
+                    MSG_SCANOUT,
+                    s.width,
+                    s.height,
+                    s.fourcc,
+                    s.width * BYTES_PER_PIXEL,
+                ],
+                &[],
+                None,
+            ),
+            None => self.viewer.send(&[MSG_SCANOUT], &[], None),
+        }
+    }
+
+    fn update(&mut self, frame: &[u8], stride: u32, damage: Rect) -> io::Result<()> {
+        let row_len = (damage.width * BYTES_PER_PIXEL) as usize;
+        let mut pixels = Vec::with_capacity(row_len * damage.height as usize);
+        for y in damage.y..damage.y + damage.height {
+            let start = (y * stride + damage.x * BYTES_PER_PIXEL) as usize;
+            pixels.extend_from_slice(&frame[start..start + row_len]);
+        }
+
+        self.viewer.send(
+            &[MSG_UPDATE, damage.x, damage.y, damage.width, damage.height],
+            &pixels,
+            None,
+        )
+    }
+
+    fn set_cursor(&mut self, cursor: Option<&Cursor>) -> io::Result<()> {
+        self.viewer.set_cursor(cursor)
+    }
+
+    fn move_cursor(&mut self, x: u32, y: u32) -> io::Result<()> {
+        self.viewer.move_cursor(x, y)
+    }
+
+    fn flush(&mut self) {
+        self.viewer.flush()
+    }
+
+    fn pending_viewer(&self) -> Option<RawFd> {
+        self.viewer.pending()
+    }
+}
+
+// Scanout rendered to host memory shared with the viewer as a dmabuf.
+struct DmabufBuffer {
+    scanout: Scanout,
+    stride: u32,
+    dmabuf: File,
+    addr: *mut u8,
+    size: usize,
+}
+
+// SAFETY: the mapping is only accessed through the buffer, which owns it.
+unsafe impl Send for DmabufBuffer {}
+
+impl Drop for DmabufBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created with this address and size, and
+        // isn't referenced anymore.
+        unsafe { libc::munmap(self.addr as *mut libc::c_void, self.size) };
+    }
+}
+
+/// Renders the scanout to a dmabuf handed over to the viewer, typically the
+/// host compositor, which is then only told about the updated parts of the
+/// scanout. The dmabuf is created through `/dev/udmabuf`.
+pub struct DmabufDisplay {
+    udmabuf: File,
+    viewer: Viewer,
+    buffer: Option<DmabufBuffer>,
+}
+
+impl DmabufDisplay {
+    pub fn new() -> io::Result<Self> {
+        Ok(DmabufDisplay {
+            udmabuf: OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(Path::new("/dev/udmabuf"))?,
+            viewer: Viewer::default(),
+            buffer: None,
+        })
+    }
+
+    fn create_buffer(&self, scanout: Scanout) -> io::Result<DmabufBuffer> {
+        let stride = scanout.width * BYTES_PER_PIXEL;
+        // SAFETY: FFI call with valid arguments.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let size =
+            (stride as usize * scanout.height as usize + page_size - 1) / page_size * page_size;
+
+        // SAFETY: FFI call with a valid name.
+        let fd = unsafe {
+            libc::memfd_create(
+                b"virtio-gpu\0".as_ptr() as *const libc::c_char,
+                libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the file descriptor was just created, and is owned by the
+        // file from now on.
+        let memfd = unsafe { File::from_raw_fd(fd) };
+        memfd.set_len(size as u64)?;
+        // The dmabuf requires the memory not to be shrunk under its feet.
+        // SAFETY: FFI call with valid arguments.
+        if unsafe { libc::fcntl(memfd.as_raw_fd(), libc::F_ADD_SEALS, libc::F_SEAL_SHRINK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: FFI call with valid arguments, the mapping being owned by
+        // the buffer.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                memfd.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buffer = DmabufBuffer {
+            scanout,
+            stride,
+            dmabuf: memfd,
+            addr: addr as *mut u8,
+            size,
+        };
+
+        let create = UdmabufCreate {
+            memfd: buffer.dmabuf.as_raw_fd() as u32,
+            flags: UDMABUF_FLAGS_CLOEXEC,
+            offset: 0,
+            size: size as u64,
+        };
+        // SAFETY: FFI call with a valid argument of the expected layout.
+        let fd = unsafe { libc::ioctl(self.udmabuf.as_raw_fd(), UDMABUF_CREATE as _, &create) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the file descriptor was just created, the memfd being kept
+        // alive by the dmabuf.
+        buffer.dmabuf = unsafe { File::from_raw_fd(fd) };
+
+        Ok(buffer)
+    }
+}
+
+impl DisplayBackend for DmabufDisplay {
+    fn connect(&mut self, stream: UnixStream) -> io::Result<()> {
+        self.viewer.connect(stream)
+    }
+
+    fn set_scanout(&mut self, scanout: Option<Scanout>) -> io::Result<()> {
+        let scanout = match scanout {
+            Some(scanout) => scanout,
+            None => {
+                self.buffer = None;
+                return self.viewer.send(&[MSG_SCANOUT], &[], None);
+            }
+        };
+
+        if self.buffer.as_ref().map(|b| b.scanout) != Some(scanout) {
+            self.buffer = Some(self.create_buffer(scanout)?);
+        }
+
+        let buffer = self.buffer.as_ref().unwrap();
+        self.viewer.send(
+            &[
+                MSG_SCANOUT,
+                scanout.width,
+                scanout.height,
+                scanout.fourcc,
+                buffer.stride,
+            ],
+            &[],
+            Some(buffer.dmabuf.as_raw_fd()),
+        )
+    }
+
+    fn update(&mut self, frame: &[u8], stride: u32, damage: Rect) -> io::Result<()> {
+        let buffer = match self.buffer.as_ref() {
+            Some(buffer) => buffer,
+            None => return Ok(()),
+        };
+
+        let row_len = (damage.width * BYTES_PER_PIXEL) as usize;
+        for y in damage.y..damage.y + damage.height {
+            let src = (y * stride + damage.x * BYTES_PER_PIXEL) as usize;
+            let dst = (y * buffer.stride + damage.x * BYTES_PER_PIXEL) as usize;
+            // SAFETY: the damage is within the scanout, which fits in the
+            // mapping of the buffer.
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    frame[src..src + row_len].as_ptr(),
+                    buffer.addr.add(dst),
+                    row_len,
+                )
+            };
+        }
+
+        self.viewer.send(
+            &[MSG_UPDATE, damage.x, damage.y, damage.width, damage.height],
+            &[],
+            None,
+        )
+    }
+
+    fn set_cursor(&mut self, cursor: Option<&Cursor>) -> io::Result<()> {
+        self.viewer.set_cursor(cursor)
+    }
+
+    fn move_cursor(&mut self, x: u32, y: u32) -> io::Result<()> {
+        self.viewer.move_cursor(x, y)
+    }
+
+    fn flush(&mut self) {
+        self.viewer.flush()
+    }
+
+    fn pending_viewer(&self) -> Option<RawFd> {
+        self.viewer.pending()
+    }
+}
@@ -0,0 +1,506 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+mod display;
+mod renderer;
+
+pub use self::display::{Cursor, DisplayBackend, DmabufDisplay, Scanout, SocketDisplay};
+pub use self::renderer::Rect;
+use self::renderer::{Renderer, RendererState};
+use super::Error as DeviceError;
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioDevice, VirtioDeviceType, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::GuestMemoryMmap;
+use crate::{VirtioInterrupt, VirtioInterruptType};
+use anyhow::anyhow;
+use seccompiler::SeccompAction;
+use std::collections::HashMap;
+use std::io;
+use std::num::Wrapping;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier, Mutex};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::Queue;
+use vm_memory::{ByteValued, Bytes, GuestMemoryAtomic};
+use vm_migration::VersionMapped;
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::chain::{ChainLimits, ChainValidator};
+use vm_virtio::AccessPlatform;
+use vmm_sys_util::eventfd::EventFd;
+
+const QUEUE_SIZE: u16 = 256;
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE, QUEUE_SIZE];
+
+// Control and cursor queues
+const CONTROL_QUEUE: usize = 0;
+const CURSOR_QUEUE: usize = 1;
+
+// New descriptors are pending on the control queue.
+const CONTROL_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+// New descriptors are pending on the cursor queue.
+const CURSOR_QUEUE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+// A viewer connects to the display socket.
+const VIEWER_CONNECT_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
+// The viewer can take more of the messages pending for it.
+const VIEWER_WRITABLE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
+
+// The largest request is the attachment of the maximum number of backing
+// entries to a resource.
+const MAX_REQUEST_SIZE: usize = 1 << 20;
+
+// Host memory the resources of the guest can take, enough for a few
+// framebuffers of the largest resolution.
+const MAX_RESOURCES_MEMORY: u64 = 256 << 20;
+
+// Got from include/uapi/linux/virtio_gpu.h
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Versionize)]
+pub struct VirtioGpuConfig {
+    events_read: u32,
+    events_clear: u32,
+    num_scanouts: u32,
+    num_capsets: u32,
+}
+
+const CONFIG_EVENTS_CLEAR_OFFSET: u64 = 4;
+const CONFIG_EVENTS_CLEAR_SIZE: usize = 4;
+
+// SAFETY: it only has data and has no implicit padding.
+unsafe impl ByteValued for VirtioGpuConfig {}
+
+struct GpuEpollHandler {
+    queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
+    renderer: Arc<Mutex<Renderer>>,
+    listener: UnixListener,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    queue_evts: Vec<EventFd>,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+    chain_validator: ChainValidator,
+    // Socket of the viewer, while registered for the writable events.
+    viewer_fd: Option<RawFd>,
+}
+
+impl GpuEpollHandler {
+    fn process_queue(&mut self, queue_index: usize) -> bool {
+        let queue = &mut self.queues[queue_index];
+
+        let mut used_desc_heads = [(0, 0); QUEUE_SIZE as usize];
+        let mut used_count = 0;
+        for mut desc_chain in queue.iter().unwrap() {
+            let mut len = 0;
+
+            // The request is made of the device readable descriptors, which
+            // the validator ensures are followed by the device writable one
+            // for the response.
+            let mut request = Vec::new();
+            let mut resp_desc = None;
+            let mut valid = false;
+            if let Ok(valid_chain) = self.chain_validator.validate(&mut desc_chain) {
+                valid = valid_chain.readable_len() <= MAX_REQUEST_SIZE as u64;
+                for desc in valid_chain.readable() {
+                    if !valid {
+                        break;
+                    }
+                    let start = request.len();
+                    request.resize(start + desc.len() as usize, 0);
+                    if let Err(e) = desc_chain
+                        .memory()
+                        .read_slice(&mut request[start..], desc.addr())
+                    {
+                        error!("Failed reading virtio-gpu request: {:?}", e);
+                        valid = false;
+                    }
+                }
+                resp_desc = valid_chain.writable().first().copied();
+            }
+
+            if valid {
+                let response = self.renderer.lock().unwrap().process_request(
+                    desc_chain.memory(),
+                    self.access_platform.as_ref(),
+                    &request,
+                );
+
+                // The driver doesn't wait for a response to the cursor
+                // commands.
+                if let Some(resp_desc) = resp_desc {
+                    let resp_len = std::cmp::min(resp_desc.len() as usize, response.len());
+                    match desc_chain
+                        .memory()
+                        .write_slice(&response[..resp_len], resp_desc.addr())
+                    {
+                        Ok(_) => len = resp_len as u32,
+                        Err(e) => error!("Failed writing virtio-gpu response: {:?}", e),
+                    }
+                }
+            } else {
+                error!("Invalid virtio-gpu descriptor chain");
+            }
+
+            used_desc_heads[used_count] = (desc_chain.head_index(), len);
+            used_count += 1;
+        }
+
+        for &(desc_index, len) in &used_desc_heads[..used_count] {
+            queue.add_used(desc_index, len).unwrap();
+        }
+        used_count > 0
+    }
+
+    fn signal_used_queue(&self, queue_index: usize) -> result::Result<(), DeviceError> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(queue_index as u16))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                DeviceError::FailedSignalingUsedQueue(e)
+            })
+    }
+
+    fn accept_viewer(&mut self) {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("Failed accepting virtio-gpu viewer: {}", e);
+                return;
+            }
+        };
+
+        let mut renderer = self.renderer.lock().unwrap();
+        if let Err(e) = renderer.display().connect(stream) {
+            error!("Failed connecting virtio-gpu viewer: {}", e);
+            return;
+        }
+        // The socket of the previous viewer is closed, and with it its
+        // registration, its number possibly being reused by the new one.
+        self.viewer_fd = None;
+        info!("virtio-gpu viewer connected");
+        renderer.redraw();
+    }
+
+    // Only waits for the viewer to be writable while it has messages pending,
+    // which are written without blocking the processing of the queues.
+    fn update_viewer_event(&mut self, helper: &mut EpollHelper) -> bool {
+        let pending = self.renderer.lock().unwrap().display().pending_viewer();
+        if pending == self.viewer_fd {
+            return true;
+        }
+
+        if let Some(fd) = self.viewer_fd.take() {
+            // The registration is already gone if the viewer was dropped.
+            let _ = helper.del_event_custom(fd, VIEWER_WRITABLE_EVENT, epoll::Events::EPOLLOUT);
+        }
+        if let Some(fd) = pending {
+            if let Err(e) =
+                helper.add_event_custom(fd, VIEWER_WRITABLE_EVENT, epoll::Events::EPOLLOUT)
+            {
+                error!("Failed waiting for the virtio-gpu viewer: {:?}", e);
+                return false;
+            }
+            self.viewer_fd = Some(fd);
+        }
+
+        true
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(
+            self.queue_evts[CONTROL_QUEUE].as_raw_fd(),
+            CONTROL_QUEUE_EVENT,
+        )?;
+        helper.add_event(
+            self.queue_evts[CURSOR_QUEUE].as_raw_fd(),
+            CURSOR_QUEUE_EVENT,
+        )?;
+        helper.add_event(self.listener.as_raw_fd(), VIEWER_CONNECT_EVENT)?;
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for GpuEpollHandler {
+    fn handle_event(&mut self, helper: &mut EpollHelper, event: &epoll::Event) -> bool {
+        let ev_type = event.data as u16;
+        let queue_index = match ev_type {
+            CONTROL_QUEUE_EVENT => CONTROL_QUEUE,
+            CURSOR_QUEUE_EVENT => CURSOR_QUEUE,
+            VIEWER_CONNECT_EVENT => {
+                self.accept_viewer();
+                return !self.update_viewer_event(helper);
+            }
+            VIEWER_WRITABLE_EVENT => {
+                self.renderer.lock().unwrap().display().flush();
+                return !self.update_viewer_event(helper);
+            }
+            _ => {
+                error!("Unexpected event: {}", ev_type);
+                return true;
+            }
+        };
+
+        if let Err(e) = self.queue_evts[queue_index].read() {
+            error!("Failed to get queue event: {:?}", e);
+            return true;
+        } else if self.process_queue(queue_index) {
+            if let Err(e) = self.signal_used_queue(queue_index) {
+                error!("Failed to signal used queue: {:?}", e);
+                return true;
+            }
+        }
+        !self.update_viewer_event(helper)
+    }
+}
+
+/// Virtio GPU device, 2D only, exposing a single display of a fixed size.
+/// The guest framebuffers are copied to the host, and presented through a
+/// display backend to the viewer connecting to the socket of the device.
+pub struct Gpu {
+    common: VirtioCommon,
+    id: String,
+    config: VirtioGpuConfig,
+    renderer: Arc<Mutex<Renderer>>,
+    listener: UnixListener,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+#[derive(Versionize)]
+pub struct GpuState {
+    pub avail_features: u64,
+    pub acked_features: u64,
+    pub config: VirtioGpuConfig,
+    pub renderer: RendererState,
+}
+
+impl VersionMapped for GpuState {}
+
+impl Gpu {
+    /// Create a new virtio GPU device with a display of the given size,
+    /// presented to the viewer connecting to `socket` through `display`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: String,
+        width: u32,
+        height: u32,
+        socket: &Path,
+        display: Box<dyn DisplayBackend>,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+    ) -> io::Result<Gpu> {
+        let listener = UnixListener::bind(socket)?;
+        listener.set_nonblocking(true)?;
+
+        let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+        if iommu {
+            avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+        }
+
+        Ok(Gpu {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Gpu as u32,
+                queue_sizes: QUEUE_SIZES.to_vec(),
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                avail_features,
+                min_queues: 2,
+                ..Default::default()
+            },
+            id,
+            config: VirtioGpuConfig {
+                num_scanouts: 1,
+                ..Default::default()
+            },
+            renderer: Arc::new(Mutex::new(Renderer::new(
+                width,
+                height,
+                MAX_RESOURCES_MEMORY,
+                display,
+            ))),
+            listener,
+            seccomp_action,
+            exit_evt,
+        })
+    }
+
+    fn state(&self) -> GpuState {
+        GpuState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+            config: self.config,
+            renderer: self.renderer.lock().unwrap().state(),
+        }
+    }
+
+    fn set_state(&mut self, state: GpuState) -> anyhow::Result<()> {
+        self.renderer.lock().unwrap().set_state(state.renderer)?;
+        self.common.avail_features = state.avail_features;
+        self.common.acked_features = state.acked_features;
+        self.config = state.config;
+        Ok(())
+    }
+}
+
+impl Drop for Gpu {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+    }
+}
+
+impl VirtioDevice for Gpu {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) {
+        self.read_config_from_slice(self.config.as_slice(), offset, data);
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        // The events are cleared by writing to the "events_clear" field, the
+        // only writable one.
+        if offset != CONFIG_EVENTS_CLEAR_OFFSET || data.len() != CONFIG_EVENTS_CLEAR_SIZE {
+            error!(
+                "Attempt to write to read-only field: offset {:x} length {}",
+                offset,
+                data.len()
+            );
+            return;
+        }
+
+        let events_clear = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        self.config.events_read &= !events_clear;
+    }
+
+    fn activate(
+        &mut self,
+        _mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
+        queue_evts: Vec<EventFd>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &queue_evts, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        let listener = self.listener.try_clone().map_err(|e| {
+            error!("failed cloning virtio-gpu socket: {}", e);
+            ActivateError::BadActivate
+        })?;
+        let mut handler = GpuEpollHandler {
+            queues,
+            renderer: self.renderer.clone(),
+            listener,
+            interrupt_cb,
+            queue_evts,
+            kill_evt,
+            pause_evt,
+            access_platform: self.common.access_platform.clone(),
+            chain_validator: self.common.chain_validator(ChainLimits::default()),
+            viewer_fd: None,
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioGpu,
+            &mut epoll_threads,
+            &self.exit_evt,
+            self.common.thread_nice,
+            move || {
+                if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
+                    error!("Error running worker: {:?}", e);
+                }
+            },
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        // The resources of the guest are gone along with the driver.
+        self.renderer.lock().unwrap().clear();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Gpu {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Gpu {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.id, &self.state())
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
+        self.set_state(snapshot.to_versioned_state(&self.id)?)
+            .map_err(|e| MigratableError::Restore(anyhow!("Invalid virtio-gpu state: {:?}", e)))
+    }
+}
+
+impl Transportable for Gpu {}
+impl Migratable for Gpu {}
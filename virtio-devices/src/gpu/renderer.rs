@@ -0,0 +1,1236 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// 2D rendering of the virtio-gpu commands: the resources are host copies of
+// the guest framebuffers, updated on transfer and shown on the display backend
+// when flushed. See include/uapi/linux/virtio_gpu.h in the kernel code for the
+// structures and the values.
+
+use super::display::{Cursor, DisplayBackend, Scanout};
+use crate::GuestMemoryMmap;
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::sync::Arc;
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use vm_memory::{ByteValued, Bytes, GuestAddress};
+use vm_virtio::{AccessPlatform, Translatable};
+
+// 2D commands
+const VIRTIO_GPU_CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const VIRTIO_GPU_CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const VIRTIO_GPU_CMD_RESOURCE_UNREF: u32 = 0x0102;
+const VIRTIO_GPU_CMD_SET_SCANOUT: u32 = 0x0103;
+const VIRTIO_GPU_CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING: u32 = 0x0107;
+
+// Cursor commands
+const VIRTIO_GPU_CMD_UPDATE_CURSOR: u32 = 0x0300;
+const VIRTIO_GPU_CMD_MOVE_CURSOR: u32 = 0x0301;
+
+// Success responses
+const VIRTIO_GPU_RESP_OK_NODATA: u32 = 0x1100;
+const VIRTIO_GPU_RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+// Error responses
+const VIRTIO_GPU_RESP_ERR_UNSPEC: u32 = 0x1200;
+const VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY: u32 = 0x1201;
+const VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID: u32 = 0x1202;
+const VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID: u32 = 0x1203;
+const VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER: u32 = 0x1205;
+
+const VIRTIO_GPU_FLAG_FENCE: u32 = 1 << 0;
+
+// Formats, all of them 32 bits per pixel.
+const VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM: u32 = 1;
+const VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM: u32 = 2;
+const VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM: u32 = 3;
+const VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM: u32 = 4;
+const VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM: u32 = 67;
+const VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM: u32 = 68;
+const VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM: u32 = 121;
+const VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM: u32 = 134;
+const BYTES_PER_PIXEL: u32 = 4;
+
+const VIRTIO_GPU_MAX_SCANOUTS: usize = 16;
+
+// Size of the cursor images.
+const CURSOR_SIZE: u32 = 64;
+
+// Bounds the guest memory a single resource can be backed with.
+const MAX_BACKING_ENTRIES: u32 = 16384;
+
+// Bounds the number of resources the guest can create, however small.
+const MAX_RESOURCES: usize = 1024;
+
+// Returns the DRM fourcc code of the format, which the display backends
+// describe the frames with.
+fn drm_fourcc(format: u32) -> Option<u32> {
+    let fourcc = |code: &[u8; 4]| u32::from_le_bytes(*code);
+
+    // The virtio formats list the components in memory order, while the DRM
+    // ones list them from the most significant bits of a little endian word.
+    Some(match format {
+        VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM => fourcc(b"AR24"),
+        VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM => fourcc(b"XR24"),
+        VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM => fourcc(b"BA24"),
+        VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM => fourcc(b"BX24"),
+        VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM => fourcc(b"AB24"),
+        VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM => fourcc(b"RX24"),
+        VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM => fourcc(b"RA24"),
+        VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM => fourcc(b"XB24"),
+        _ => return None,
+    })
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct CtrlHeader {
+    type_: u32,
+    flags: u32,
+    fence_id: u64,
+    ctx_id: u32,
+    _ring_idx: u8,
+    _padding: [u8; 3],
+}
+
+/// Rectangle within a resource or a scanout.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Versionize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    // Whether the rectangle fits in an area of the given size.
+    fn fits(&self, width: u32, height: u32) -> bool {
+        self.width > 0
+            && self.height > 0
+            && self.x.checked_add(self.width).map_or(false, |w| w <= width)
+            && self
+                .y
+                .checked_add(self.height)
+                .map_or(false, |h| h <= height)
+    }
+
+    fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        if x < right && y < bottom {
+            Some(Rect {
+                x,
+                y,
+                width: right - x,
+                height: bottom - y,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct ResourceCreate2d {
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct ResourceUnref {
+    resource_id: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct SetScanout {
+    r: Rect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct ResourceFlush {
+    r: Rect,
+    resource_id: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct TransferToHost2d {
+    r: Rect,
+    offset: u64,
+    resource_id: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct ResourceAttachBacking {
+    resource_id: u32,
+    nr_entries: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct MemEntry {
+    addr: u64,
+    length: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct ResourceDetachBacking {
+    resource_id: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct CursorPos {
+    scanout_id: u32,
+    x: u32,
+    y: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct UpdateCursor {
+    pos: CursorPos,
+    resource_id: u32,
+    hot_x: u32,
+    hot_y: u32,
+    _padding: u32,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct DisplayOne {
+    r: Rect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct RespDisplayInfo {
+    hdr: CtrlHeader,
+    pmodes: [DisplayOne; VIRTIO_GPU_MAX_SCANOUTS],
+}
+
+// SAFETY: these data structures only contain plain data, without implicit
+// padding.
+unsafe impl ByteValued for CtrlHeader {}
+unsafe impl ByteValued for Rect {}
+unsafe impl ByteValued for ResourceCreate2d {}
+unsafe impl ByteValued for ResourceUnref {}
+unsafe impl ByteValued for SetScanout {}
+unsafe impl ByteValued for ResourceFlush {}
+unsafe impl ByteValued for TransferToHost2d {}
+unsafe impl ByteValued for ResourceAttachBacking {}
+unsafe impl ByteValued for MemEntry {}
+unsafe impl ByteValued for ResourceDetachBacking {}
+unsafe impl ByteValued for CursorPos {}
+unsafe impl ByteValued for UpdateCursor {}
+unsafe impl ByteValued for DisplayOne {}
+unsafe impl ByteValued for RespDisplayInfo {}
+
+// Reads a structure from a request, which isn't aligned in any way.
+fn read_obj<T: ByteValued>(data: &[u8], offset: usize) -> Option<T> {
+    let bytes = data.get(offset..offset.checked_add(size_of::<T>())?)?;
+    let mut obj = T::default();
+    obj.as_mut_slice().copy_from_slice(bytes);
+    Some(obj)
+}
+
+enum Response {
+    NoData,
+    DisplayInfo(RespDisplayInfo),
+}
+
+type CommandResult = std::result::Result<Response, u32>;
+
+/// Range of guest memory backing a resource.
+#[derive(Clone, Copy, Debug, Versionize)]
+pub struct BackingEntry {
+    pub addr: u64,
+    pub length: u32,
+}
+
+struct Resource {
+    format: u32,
+    width: u32,
+    height: u32,
+    backing: Vec<BackingEntry>,
+    // Host copy of the content, with a stride of `width` pixels.
+    data: Vec<u8>,
+}
+
+impl Resource {
+    fn stride(&self) -> u32 {
+        self.width * BYTES_PER_PIXEL
+    }
+
+    // Host memory taken by the resource, its content as well as its backing
+    // and bookkeeping.
+    fn memory(&self) -> u64 {
+        size_of::<Resource>() as u64
+            + self.data.len() as u64
+            + Self::backing_memory(self.backing.len())
+    }
+
+    fn backing_memory(entries: usize) -> u64 {
+        (entries * size_of::<BackingEntry>()) as u64
+    }
+
+    // Copies the backing from `offset` to `buf`.
+    fn read_backing(&self, mem: &GuestMemoryMmap, mut offset: u64, buf: &mut [u8]) -> bool {
+        let mut copied = 0;
+        for entry in self.backing.iter() {
+            if copied == buf.len() {
+                break;
+            }
+            let length = entry.length as u64;
+            if offset >= length {
+                offset -= length;
+                continue;
+            }
+
+            let count = std::cmp::min((length - offset) as usize, buf.len() - copied);
+            let addr = match entry.addr.checked_add(offset) {
+                Some(addr) => addr,
+                None => return false,
+            };
+            if mem
+                .read_slice(&mut buf[copied..copied + count], GuestAddress(addr))
+                .is_err()
+            {
+                return false;
+            }
+            copied += count;
+            offset = 0;
+        }
+
+        copied == buf.len()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ScanoutResource {
+    resource_id: u32,
+    // Part of the resource shown on the scanout.
+    rect: Rect,
+}
+
+#[derive(Versionize)]
+pub struct ResourceState {
+    pub id: u32,
+    pub format: u32,
+    pub width: u32,
+    pub height: u32,
+    pub backing: Vec<BackingEntry>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Versionize)]
+pub struct ScanoutState {
+    pub resource_id: u32,
+    pub rect: Rect,
+}
+
+#[derive(Versionize)]
+pub struct RendererState {
+    pub resources: Vec<ResourceState>,
+    pub scanout: Option<ScanoutState>,
+}
+
+/// Processes the commands of the guest, keeping the resources it renders to
+/// and showing the one set on the single scanout on the display backend.
+pub struct Renderer {
+    width: u32,
+    height: u32,
+    max_memory: u64,
+    resources: BTreeMap<u32, Resource>,
+    scanout: Option<ScanoutResource>,
+    cursor: Option<Cursor>,
+    display: Box<dyn DisplayBackend>,
+}
+
+impl Renderer {
+    /// Creates a renderer for a display of the given size, the resources
+    /// taking up to `max_memory` bytes of host memory.
+    pub fn new(width: u32, height: u32, max_memory: u64, display: Box<dyn DisplayBackend>) -> Self {
+        Renderer {
+            width,
+            height,
+            max_memory,
+            resources: BTreeMap::new(),
+            scanout: None,
+            cursor: None,
+            display,
+        }
+    }
+
+    /// Processes a request, returning the response to it.
+    pub fn process_request(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        access_platform: Option<&Arc<dyn AccessPlatform>>,
+        request: &[u8],
+    ) -> Vec<u8> {
+        let hdr: CtrlHeader = match read_obj(request, 0) {
+            Some(hdr) => hdr,
+            None => {
+                error!("Invalid virtio-gpu request of {} bytes", request.len());
+                return Self::response(&CtrlHeader::default(), Err(VIRTIO_GPU_RESP_ERR_UNSPEC));
+            }
+        };
+        let body = &request[size_of::<CtrlHeader>()..];
+
+        let result = match hdr.type_ {
+            VIRTIO_GPU_CMD_GET_DISPLAY_INFO => Ok(self.display_info()),
+            VIRTIO_GPU_CMD_RESOURCE_CREATE_2D => read_obj(body, 0)
+                .ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)
+                .and_then(|cmd| self.resource_create_2d(cmd)),
+            VIRTIO_GPU_CMD_RESOURCE_UNREF => read_obj(body, 0)
+                .ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)
+                .and_then(|cmd| self.resource_unref(cmd)),
+            VIRTIO_GPU_CMD_SET_SCANOUT => read_obj(body, 0)
+                .ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)
+                .and_then(|cmd| self.set_scanout(cmd)),
+            VIRTIO_GPU_CMD_RESOURCE_FLUSH => read_obj(body, 0)
+                .ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)
+                .and_then(|cmd| self.resource_flush(cmd)),
+            VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D => read_obj(body, 0)
+                .ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)
+                .and_then(|cmd| self.transfer_to_host_2d(mem, cmd)),
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING => {
+                self.resource_attach_backing(access_platform, body)
+            }
+            VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING => read_obj(body, 0)
+                .ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)
+                .and_then(|cmd| self.resource_detach_backing(cmd)),
+            VIRTIO_GPU_CMD_UPDATE_CURSOR => read_obj(body, 0)
+                .ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)
+                .and_then(|cmd| self.update_cursor(cmd)),
+            VIRTIO_GPU_CMD_MOVE_CURSOR => read_obj(body, 0)
+                .ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)
+                .and_then(|cmd| self.move_cursor(cmd)),
+            // 3D and blob resources aren't supported.
+            _ => {
+                debug!("Unsupported virtio-gpu command {:#x}", hdr.type_);
+                Err(VIRTIO_GPU_RESP_ERR_UNSPEC)
+            }
+        };
+
+        if let Err(e) = result {
+            debug!("virtio-gpu command {:#x} failed: {:#x}", hdr.type_, e);
+        }
+
+        Self::response(&hdr, result)
+    }
+
+    fn response(hdr: &CtrlHeader, result: CommandResult) -> Vec<u8> {
+        // The commands being processed synchronously, the fence is signaled
+        // along with the response.
+        let resp_hdr = CtrlHeader {
+            type_: 0,
+            flags: hdr.flags & VIRTIO_GPU_FLAG_FENCE,
+            fence_id: hdr.fence_id,
+            ctx_id: hdr.ctx_id,
+            _ring_idx: 0,
+            _padding: [0; 3],
+        };
+
+        match result {
+            Ok(Response::NoData) => CtrlHeader {
+                type_: VIRTIO_GPU_RESP_OK_NODATA,
+                ..resp_hdr
+            }
+            .as_slice()
+            .to_vec(),
+            Ok(Response::DisplayInfo(mut info)) => {
+                info.hdr = CtrlHeader {
+                    type_: VIRTIO_GPU_RESP_OK_DISPLAY_INFO,
+                    ..resp_hdr
+                };
+                info.as_slice().to_vec()
+            }
+            Err(type_) => CtrlHeader { type_, ..resp_hdr }.as_slice().to_vec(),
+        }
+    }
+
+    fn display_info(&self) -> Response {
+        let mut info = RespDisplayInfo::default();
+        info.pmodes[0] = DisplayOne {
+            r: Rect {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            },
+            enabled: 1,
+            flags: 0,
+        };
+
+        Response::DisplayInfo(info)
+    }
+
+    fn memory_used(&self) -> u64 {
+        self.resources.values().map(|r| r.memory()).sum()
+    }
+
+    fn resource_create_2d(&mut self, cmd: ResourceCreate2d) -> CommandResult {
+        if cmd.resource_id == 0 || self.resources.contains_key(&cmd.resource_id) {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID);
+        }
+        if drm_fourcc(cmd.format).is_none() || cmd.width == 0 || cmd.height == 0 {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+        if self.resources.len() >= MAX_RESOURCES {
+            return Err(VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY);
+        }
+
+        let size = cmd.width as u64 * cmd.height as u64 * BYTES_PER_PIXEL as u64;
+        if self.memory_used() + size_of::<Resource>() as u64 + size > self.max_memory {
+            return Err(VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY);
+        }
+
+        self.resources.insert(
+            cmd.resource_id,
+            Resource {
+                format: cmd.format,
+                width: cmd.width,
+                height: cmd.height,
+                backing: Vec::new(),
+                data: vec![0; size as usize],
+            },
+        );
+
+        Ok(Response::NoData)
+    }
+
+    fn resource_unref(&mut self, cmd: ResourceUnref) -> CommandResult {
+        if self.resources.remove(&cmd.resource_id).is_none() {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID);
+        }
+
+        if self
+            .scanout
+            .map_or(false, |s| s.resource_id == cmd.resource_id)
+        {
+            self.scanout = None;
+            self.present(None);
+        }
+
+        Ok(Response::NoData)
+    }
+
+    fn set_scanout(&mut self, cmd: SetScanout) -> CommandResult {
+        if cmd.scanout_id != 0 {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID);
+        }
+
+        if cmd.resource_id == 0 {
+            self.scanout = None;
+            self.present(None);
+            return Ok(Response::NoData);
+        }
+
+        let resource = self
+            .resources
+            .get(&cmd.resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+        if !cmd.r.fits(resource.width, resource.height) {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+
+        self.scanout = Some(ScanoutResource {
+            resource_id: cmd.resource_id,
+            rect: cmd.r,
+        });
+        self.redraw();
+
+        Ok(Response::NoData)
+    }
+
+    fn resource_flush(&mut self, cmd: ResourceFlush) -> CommandResult {
+        let resource = self
+            .resources
+            .get(&cmd.resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+        if !cmd.r.fits(resource.width, resource.height) {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+
+        if let Some(scanout) = self.scanout {
+            if scanout.resource_id == cmd.resource_id {
+                if let Some(damage) = cmd.r.intersection(&scanout.rect) {
+                    self.present(Some(Rect {
+                        x: damage.x - scanout.rect.x,
+                        y: damage.y - scanout.rect.y,
+                        ..damage
+                    }));
+                }
+            }
+        }
+
+        Ok(Response::NoData)
+    }
+
+    fn transfer_to_host_2d(
+        &mut self,
+        mem: &GuestMemoryMmap,
+        cmd: TransferToHost2d,
+    ) -> CommandResult {
+        let resource = self
+            .resources
+            .get_mut(&cmd.resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+        if !cmd.r.fits(resource.width, resource.height) {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+        if resource.backing.is_empty() {
+            return Err(VIRTIO_GPU_RESP_ERR_UNSPEC);
+        }
+
+        // The backing is laid out as the resource, each row of the rectangle
+        // being copied from `offset` onwards.
+        let stride = resource.stride() as usize;
+        let row_len = (cmd.r.width * BYTES_PER_PIXEL) as usize;
+        let mut data = std::mem::take(&mut resource.data);
+        let mut result = Ok(Response::NoData);
+        for row in 0..cmd.r.height as usize {
+            let src = match (row as u64)
+                .checked_mul(stride as u64)
+                .and_then(|o| o.checked_add(cmd.offset))
+            {
+                Some(src) => src,
+                None => {
+                    result = Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+                    break;
+                }
+            };
+            let dst = (cmd.r.y as usize + row) * stride + (cmd.r.x * BYTES_PER_PIXEL) as usize;
+            if !resource.read_backing(mem, src, &mut data[dst..dst + row_len]) {
+                result = Err(VIRTIO_GPU_RESP_ERR_UNSPEC);
+                break;
+            }
+        }
+        resource.data = data;
+
+        result
+    }
+
+    fn resource_attach_backing(
+        &mut self,
+        access_platform: Option<&Arc<dyn AccessPlatform>>,
+        body: &[u8],
+    ) -> CommandResult {
+        let cmd: ResourceAttachBacking = read_obj(body, 0).ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+        let memory_used = self.memory_used();
+        let max_memory = self.max_memory;
+        let resource = self
+            .resources
+            .get_mut(&cmd.resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+        if cmd.nr_entries > MAX_BACKING_ENTRIES {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+        }
+        // The backing replaces the current one, if any.
+        if memory_used - Resource::backing_memory(resource.backing.len())
+            + Resource::backing_memory(cmd.nr_entries as usize)
+            > max_memory
+        {
+            return Err(VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY);
+        }
+
+        // The entries follow the command.
+        let mut backing = Vec::with_capacity(cmd.nr_entries as usize);
+        for i in 0..cmd.nr_entries as usize {
+            let entry: MemEntry = read_obj(
+                body,
+                size_of::<ResourceAttachBacking>() + i * size_of::<MemEntry>(),
+            )
+            .ok_or(VIRTIO_GPU_RESP_ERR_UNSPEC)?;
+            backing.push(BackingEntry {
+                addr: GuestAddress(entry.addr)
+                    .translate_gva(access_platform, entry.length as usize)
+                    .0,
+                length: entry.length,
+            });
+        }
+        resource.backing = backing;
+
+        Ok(Response::NoData)
+    }
+
+    fn resource_detach_backing(&mut self, cmd: ResourceDetachBacking) -> CommandResult {
+        let resource = self
+            .resources
+            .get_mut(&cmd.resource_id)
+            .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+        resource.backing.clear();
+
+        Ok(Response::NoData)
+    }
+
+    fn update_cursor(&mut self, cmd: UpdateCursor) -> CommandResult {
+        if cmd.pos.scanout_id != 0 {
+            return Err(VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID);
+        }
+
+        if cmd.resource_id == 0 {
+            self.cursor = None;
+        } else {
+            let resource = self
+                .resources
+                .get(&cmd.resource_id)
+                .ok_or(VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID)?;
+            if resource.width != CURSOR_SIZE || resource.height != CURSOR_SIZE {
+                return Err(VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+            }
+
+            self.cursor = Some(Cursor {
+                x: cmd.pos.x,
+                y: cmd.pos.y,
+                hot_x: cmd.hot_x,
+                hot_y: cmd.hot_y,
+                size: CURSOR_SIZE,
+                fourcc: drm_fourcc(resource.format).unwrap(),
+                image: resource.data.clone(),
+            });
+        }
+
+        if let Err(e) = self.display.set_cursor(self.cursor.as_ref()) {
+            warn!("Failed updating the virtio-gpu cursor: {}", e);
+        }
+
+        Ok(Response::NoData)
+    }
+
+    fn move_cursor(&mut self, cmd: UpdateCursor) -> CommandResult {
+        if let Some(cursor) = self.cursor.as_mut() {
+            cursor.x = cmd.pos.x;
+            cursor.y = cmd.pos.y;
+            if let Err(e) = self.display.move_cursor(cursor.x, cursor.y) {
+                warn!("Failed moving the virtio-gpu cursor: {}", e);
+            }
+        }
+
+        Ok(Response::NoData)
+    }
+
+    // Shows the given part of the scanout on the display, or disables the
+    // display if there is nothing to show.
+    fn present(&mut self, damage: Option<Rect>) {
+        let result = match (self.scanout, damage) {
+            (Some(scanout), Some(damage)) => {
+                // The scanout is disabled when its resource is released.
+                let resource = &self.resources[&scanout.resource_id];
+                let stride = resource.stride();
+                let start = (scanout.rect.y * stride + scanout.rect.x * BYTES_PER_PIXEL) as usize;
+                self.display.update(&resource.data[start..], stride, damage)
+            }
+            (None, _) => self.display.set_scanout(None),
+            (Some(_), None) => Ok(()),
+        };
+
+        if let Err(e) = result {
+            warn!("Failed updating the virtio-gpu display: {}", e);
+        }
+    }
+
+    /// Shows the whole scanout and the cursor again, e.g. when a viewer
+    /// connects to the display backend.
+    pub fn redraw(&mut self) {
+        let scanout = match self.scanout {
+            Some(scanout) => scanout,
+            None => {
+                self.present(None);
+                return;
+            }
+        };
+
+        let format = self.resources[&scanout.resource_id].format;
+        if let Err(e) = self.display.set_scanout(Some(Scanout {
+            width: scanout.rect.width,
+            height: scanout.rect.height,
+            fourcc: drm_fourcc(format).unwrap(),
+        })) {
+            warn!("Failed setting the virtio-gpu scanout: {}", e);
+            return;
+        }
+        self.present(Some(Rect {
+            x: 0,
+            y: 0,
+            width: scanout.rect.width,
+            height: scanout.rect.height,
+        }));
+
+        if let Err(e) = self.display.set_cursor(self.cursor.as_ref()) {
+            warn!("Failed updating the virtio-gpu cursor: {}", e);
+        }
+    }
+
+    pub fn display(&mut self) -> &mut dyn DisplayBackend {
+        self.display.as_mut()
+    }
+
+    pub fn state(&self) -> RendererState {
+        RendererState {
+            resources: self
+                .resources
+                .iter()
+                .map(|(id, r)| ResourceState {
+                    id: *id,
+                    format: r.format,
+                    width: r.width,
+                    height: r.height,
+                    backing: r.backing.clone(),
+                    data: r.data.clone(),
+                })
+                .collect(),
+            scanout: self.scanout.map(|s| ScanoutState {
+                resource_id: s.resource_id,
+                rect: s.rect,
+            }),
+        }
+    }
+
+    /// Restores the resources and the scanout, checked as if the guest had
+    /// created them since they come from outside of the VMM.
+    pub fn set_state(&mut self, state: RendererState) -> anyhow::Result<()> {
+        if state.resources.len() > MAX_RESOURCES {
+            return Err(anyhow!("Too many resources: {}", state.resources.len()));
+        }
+
+        let mut resources = BTreeMap::new();
+        for r in state.resources {
+            if r.id == 0 || resources.contains_key(&r.id) {
+                return Err(anyhow!("Invalid resource id {}", r.id));
+            }
+            if drm_fourcc(r.format).is_none()
+                || r.width == 0
+                || r.height == 0
+                || r.data.len() as u64 != r.width as u64 * r.height as u64 * BYTES_PER_PIXEL as u64
+                || r.backing.len() > MAX_BACKING_ENTRIES as usize
+            {
+                return Err(anyhow!("Invalid resource {}", r.id));
+            }
+            resources.insert(
+                r.id,
+                Resource {
+                    format: r.format,
+                    width: r.width,
+                    height: r.height,
+                    backing: r.backing,
+                    data: r.data,
+                },
+            );
+        }
+        let memory: u64 = resources.values().map(|r| r.memory()).sum();
+        if memory > self.max_memory {
+            return Err(anyhow!("Resources taking {} bytes", memory));
+        }
+
+        let scanout = match state.scanout {
+            Some(s) => {
+                let resource = resources
+                    .get(&s.resource_id)
+                    .ok_or_else(|| anyhow!("Invalid scanout resource id {}", s.resource_id))?;
+                if !s.rect.fits(resource.width, resource.height) {
+                    return Err(anyhow!("Invalid scanout rectangle {:?}", s.rect));
+                }
+                Some(ScanoutResource {
+                    resource_id: s.resource_id,
+                    rect: s.rect,
+                })
+            }
+            None => None,
+        };
+
+        self.resources = resources;
+        self.scanout = scanout;
+        self.cursor = None;
+        self.redraw();
+
+        Ok(())
+    }
+
+    /// Releases all the resources and disables the display.
+    pub fn clear(&mut self) {
+        self.resources.clear();
+        self.scanout = None;
+        self.cursor = None;
+        self.redraw();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::os::unix::io::RawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Frames {
+        scanout: Option<Scanout>,
+        pixels: Vec<u8>,
+        damage: Vec<Rect>,
+    }
+
+    // Keeps the content shown on the display, as a viewer would.
+    struct TestDisplay(Arc<Mutex<Frames>>);
+
+    impl DisplayBackend for TestDisplay {
+        fn connect(&mut self, _stream: UnixStream) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_scanout(&mut self, scanout: Option<Scanout>) -> io::Result<()> {
+            let mut frames = self.0.lock().unwrap();
+            frames.pixels = scanout
+                .map(|s| vec![0; (s.width * s.height * BYTES_PER_PIXEL) as usize])
+                .unwrap_or_default();
+            frames.scanout = scanout;
+            Ok(())
+        }
+
+        fn update(&mut self, frame: &[u8], stride: u32, damage: Rect) -> io::Result<()> {
+            let mut frames = self.0.lock().unwrap();
+            let width = frames.scanout.unwrap().width;
+            for y in damage.y..damage.y + damage.height {
+                for x in damage.x..damage.x + damage.width {
+                    let src = (y * stride + x * BYTES_PER_PIXEL) as usize;
+                    let dst = ((y * width + x) * BYTES_PER_PIXEL) as usize;
+                    frames.pixels[dst..dst + 4].copy_from_slice(&frame[src..src + 4]);
+                }
+            }
+            frames.damage.push(damage);
+            Ok(())
+        }
+
+        fn set_cursor(&mut self, _cursor: Option<&Cursor>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn move_cursor(&mut self, _x: u32, _y: u32) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn flush(&mut self) {}
+
+        fn pending_viewer(&self) -> Option<RawFd> {
+            None
+        }
+    }
+
+    fn command<T: ByteValued>(type_: u32, cmd: T) -> Vec<u8> {
+        let mut request = CtrlHeader {
+            type_,
+            flags: VIRTIO_GPU_FLAG_FENCE,
+            fence_id: 7,
+            ..Default::default()
+        }
+        .as_slice()
+        .to_vec();
+        request.extend_from_slice(cmd.as_slice());
+        request
+    }
+
+    fn response_type(response: &[u8]) -> u32 {
+        let hdr: CtrlHeader = read_obj(response, 0).unwrap();
+        assert_eq!(hdr.fence_id, 7);
+        assert_eq!(hdr.flags, VIRTIO_GPU_FLAG_FENCE);
+        hdr.type_
+    }
+
+    #[test]
+    fn test_renderer_2d() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap();
+        let frames = Arc::new(Mutex::new(Frames::default()));
+        let mut renderer = Renderer::new(4, 2, 0x1000, Box::new(TestDisplay(frames.clone())));
+        let mut process = |request: Vec<u8>| renderer.process_request(&mem, None, &request);
+
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 2,
+        };
+        let create = ResourceCreate2d {
+            resource_id: 1,
+            format: VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM,
+            width: 4,
+            height: 2,
+        };
+        assert_eq!(
+            response_type(&process(command(VIRTIO_GPU_CMD_RESOURCE_CREATE_2D, create))),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        // Resources exceeding the host memory limit are rejected.
+        assert_eq!(
+            response_type(&process(command(
+                VIRTIO_GPU_CMD_RESOURCE_CREATE_2D,
+                ResourceCreate2d {
+                    resource_id: 2,
+                    width: 1024,
+                    height: 768,
+                    ..create
+                }
+            ))),
+            VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY
+        );
+
+        // The framebuffer is split over two guest memory ranges.
+        mem.write_slice(&[0x11; 16], GuestAddress(0x1000)).unwrap();
+        mem.write_slice(&[0x22; 16], GuestAddress(0x3000)).unwrap();
+        let mut attach = command(
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING,
+            ResourceAttachBacking {
+                resource_id: 1,
+                nr_entries: 2,
+            },
+        );
+        for addr in [0x1000, 0x3000] {
+            attach.extend_from_slice(
+                MemEntry {
+                    addr,
+                    length: 16,
+                    _padding: 0,
+                }
+                .as_slice(),
+            );
+        }
+        assert_eq!(response_type(&process(attach)), VIRTIO_GPU_RESP_OK_NODATA);
+
+        let transfer = TransferToHost2d {
+            r: rect,
+            offset: 0,
+            resource_id: 1,
+            _padding: 0,
+        };
+        assert_eq!(
+            response_type(&process(command(
+                VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D,
+                transfer
+            ))),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert_eq!(
+            response_type(&process(command(
+                VIRTIO_GPU_CMD_SET_SCANOUT,
+                SetScanout {
+                    r: rect,
+                    scanout_id: 0,
+                    resource_id: 1,
+                }
+            ))),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        {
+            let frames = frames.lock().unwrap();
+            assert_eq!(frames.scanout.unwrap().width, 4);
+            assert_eq!(&frames.pixels[..16], &[0x11; 16]);
+            assert_eq!(&frames.pixels[16..], &[0x22; 16]);
+        }
+
+        // Only the flushed part of the scanout is updated.
+        mem.write_slice(&[0x33; 16], GuestAddress(0x3000)).unwrap();
+        process(command(VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D, transfer));
+        let flushed = Rect {
+            x: 2,
+            y: 1,
+            width: 2,
+            height: 1,
+        };
+        assert_eq!(
+            response_type(&process(command(
+                VIRTIO_GPU_CMD_RESOURCE_FLUSH,
+                ResourceFlush {
+                    r: flushed,
+                    resource_id: 1,
+                    _padding: 0,
+                }
+            ))),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        {
+            let frames = frames.lock().unwrap();
+            assert_eq!(frames.damage.last(), Some(&flushed));
+            assert_eq!(&frames.pixels[16..24], &[0x22; 8]);
+            assert_eq!(&frames.pixels[24..], &[0x33; 8]);
+        }
+
+        // Rectangles out of the resource are rejected.
+        assert_eq!(
+            response_type(&process(command(
+                VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D,
+                TransferToHost2d {
+                    r: Rect { x: 1, ..rect },
+                    ..transfer
+                }
+            ))),
+            VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+        );
+
+        // Releasing the resource shown disables the display.
+        assert_eq!(
+            response_type(&process(command(
+                VIRTIO_GPU_CMD_RESOURCE_UNREF,
+                ResourceUnref {
+                    resource_id: 1,
+                    _padding: 0,
+                }
+            ))),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        assert!(frames.lock().unwrap().scanout.is_none());
+    }
+
+    #[test]
+    fn test_renderer_limits() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap();
+        let frames = Arc::new(Mutex::new(Frames::default()));
+        let mut renderer = Renderer::new(4, 2, 0x10_0000, Box::new(TestDisplay(frames)));
+
+        let create = ResourceCreate2d {
+            resource_id: 1,
+            format: VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM,
+            width: 1,
+            height: 1,
+        };
+        // However small, the number of resources is bounded.
+        for resource_id in 1..=MAX_RESOURCES as u32 {
+            let request = command(
+                VIRTIO_GPU_CMD_RESOURCE_CREATE_2D,
+                ResourceCreate2d {
+                    resource_id,
+                    ..create
+                },
+            );
+            assert_eq!(
+                response_type(&renderer.process_request(&mem, None, &request)),
+                VIRTIO_GPU_RESP_OK_NODATA
+            );
+        }
+        let request = command(
+            VIRTIO_GPU_CMD_RESOURCE_CREATE_2D,
+            ResourceCreate2d {
+                resource_id: MAX_RESOURCES as u32 + 1,
+                ..create
+            },
+        );
+        assert_eq!(
+            response_type(&renderer.process_request(&mem, None, &request)),
+            VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY
+        );
+
+        // The backing counts against the host memory limit.
+        renderer.max_memory = renderer.memory_used() + 0x100;
+        let mut attach = command(
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING,
+            ResourceAttachBacking {
+                resource_id: 1,
+                nr_entries: 64,
+            },
+        );
+        for _ in 0..64 {
+            attach.extend_from_slice(
+                MemEntry {
+                    addr: 0x1000,
+                    length: 4,
+                    _padding: 0,
+                }
+                .as_slice(),
+            );
+        }
+        assert_eq!(
+            response_type(&renderer.process_request(&mem, None, &attach)),
+            VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY
+        );
+
+        // Offsets and addresses overflowing are rejected.
+        let mut attach = command(
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING,
+            ResourceAttachBacking {
+                resource_id: 1,
+                nr_entries: 1,
+            },
+        );
+        attach.extend_from_slice(
+            MemEntry {
+                addr: u64::MAX,
+                length: 4,
+                _padding: 0,
+            }
+            .as_slice(),
+        );
+        assert_eq!(
+            response_type(&renderer.process_request(&mem, None, &attach)),
+            VIRTIO_GPU_RESP_OK_NODATA
+        );
+        let transfer = TransferToHost2d {
+            r: Rect {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+            },
+            offset: 2,
+            resource_id: 1,
+            _padding: 0,
+        };
+        assert_eq!(
+            response_type(&renderer.process_request(
+                &mem,
+                None,
+                &command(VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D, transfer)
+            )),
+            VIRTIO_GPU_RESP_ERR_UNSPEC
+        );
+
+        // Invalid states are rejected, leaving the current one in place.
+        let mut state = renderer.state();
+        state.resources[0].format = 0;
+        assert!(renderer.set_state(state).is_err());
+        let mut state = renderer.state();
+        state.resources[0].data.clear();
+        assert!(renderer.set_state(state).is_err());
+        let mut state = renderer.state();
+        state.scanout = Some(ScanoutState {
+            resource_id: 1,
+            rect: Rect {
+                x: 1,
+                y: 0,
+                width: 1,
+                height: 1,
+            },
+        });
+        assert!(renderer.set_state(state).is_err());
+        assert_eq!(renderer.resources.len(), MAX_RESOURCES);
+        assert!(renderer.set_state(renderer.state()).is_ok());
+    }
+}
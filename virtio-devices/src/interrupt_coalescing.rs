@@ -0,0 +1,132 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::InterruptCoalescingConfig;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use vmm_sys_util::timerfd::TimerFd;
+
+/// Coalesces the used queue interrupts of a worker thread. Rather than
+/// signaling the guest each time used buffers are added to the queue, the
+/// interrupt is signaled once `max_events` used buffers are pending, or
+/// `usecs` after the first pending one otherwise. The configuration is shared
+/// with the device, so that a change made at runtime applies to the next used
+/// buffers. A `usecs` of 0 disables the coalescing, as the timer can't be
+/// armed with it.
+pub struct InterruptCoalescer {
+    config: Arc<Mutex<Option<InterruptCoalescingConfig>>>,
+    timer: TimerFd,
+    armed: bool,
+    pending: u32,
+}
+
+impl InterruptCoalescer {
+    /// The timer is created from the VMM thread, as the seccomp filters of
+    /// the worker threads don't let them create it.
+    pub fn new(config: Arc<Mutex<Option<InterruptCoalescingConfig>>>) -> io::Result<Self> {
+        Ok(InterruptCoalescer {
+            config,
+            timer: TimerFd::new()?,
+            armed: false,
+            pending: 0,
+        })
+    }
+
+    /// Timer to register with the epoll helper of the worker thread.
+    pub fn timer(&self) -> &TimerFd {
+        &self.timer
+    }
+
+    /// Account for `count` new used buffers, returning whether the interrupt
+    /// has to be signaled right away.
+    pub fn used(&mut self, count: u32) -> io::Result<bool> {
+        if count == 0 {
+            return Ok(false);
+        }
+
+        let config = match *self.config.lock().unwrap() {
+            Some(config) if config.usecs != 0 => config,
+            _ => {
+                self.pending = 0;
+                return Ok(true);
+            }
+        };
+
+        self.pending = self.pending.saturating_add(count);
+        if config.max_events != 0 && self.pending >= config.max_events {
+            // The timer is left armed, as it can already have expired
+            // without the worker thread having read it yet.
+            self.pending = 0;
+            return Ok(true);
+        }
+
+        if !self.armed {
+            self.timer
+                .reset(Duration::from_micros(config.usecs.into()), None)?;
+            self.armed = true;
+        }
+
+        Ok(false)
+    }
+
+    /// Handle the expiry of the timer, returning whether the interrupt has
+    /// to be signaled for the used buffers still pending.
+    pub fn expired(&mut self) -> io::Result<bool> {
+        self.timer.wait()?;
+        self.armed = false;
+
+        Ok(std::mem::take(&mut self.pending) > 0)
+    }
+
+    /// Disarm the timer, returning whether the interrupt has to be signaled
+    /// for the used buffers still pending. Used when the device is paused, so
+    /// that no interrupt is left pending across a snapshot.
+    pub fn flush(&mut self) -> io::Result<bool> {
+        if self.armed {
+            self.timer.clear()?;
+            self.armed = false;
+        }
+
+        Ok(std::mem::take(&mut self.pending) > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupt_coalescer() {
+        let config = Arc::new(Mutex::new(None));
+        let mut coalescer = InterruptCoalescer::new(config.clone()).unwrap();
+        assert!(!coalescer.used(0).unwrap());
+        assert!(coalescer.used(1).unwrap());
+
+        *config.lock().unwrap() = Some(InterruptCoalescingConfig {
+            max_events: 4,
+            usecs: 100,
+        });
+        assert!(!coalescer.used(2).unwrap());
+        assert!(coalescer.used(2).unwrap());
+
+        assert!(!coalescer.used(1).unwrap());
+        assert!(coalescer.expired().unwrap());
+        assert!(!coalescer.used(1).unwrap());
+        assert!(coalescer.used(3).unwrap());
+        assert!(!coalescer.expired().unwrap());
+
+        assert!(!coalescer.used(1).unwrap());
+        assert!(coalescer.flush().unwrap());
+        assert!(!coalescer.flush().unwrap());
+
+        // Without a delay, the interrupt can't be coalesced.
+        *config.lock().unwrap() = Some(InterruptCoalescingConfig {
+            max_events: 4,
+            usecs: 0,
+        });
+        assert!(coalescer.used(1).unwrap());
+    }
+}
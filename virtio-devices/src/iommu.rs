@@ -12,10 +12,11 @@ use crate::thread_helper::spawn_virtio_thread;
 use crate::GuestMemoryMmap;
 use crate::{DmaRemapping, VirtioInterrupt, VirtioInterruptType};
 use seccompiler::SeccompAction;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Display};
 use std::io;
 use std::mem::size_of;
+use std::num::Wrapping;
 use std::ops::Bound::Included;
 use std::os::unix::io::AsRawFd;
 use std::result;
@@ -23,14 +24,12 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Barrier, Mutex, RwLock};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
-use virtio_queue::{DescriptorChain, Queue};
+use virtio_queue::Queue;
 use vm_device::dma_mapping::ExternalDmaMapping;
-use vm_memory::{
-    Address, ByteValued, Bytes, GuestAddress, GuestMemoryAtomic, GuestMemoryError,
-    GuestMemoryLoadGuard,
-};
+use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryAtomic, GuestMemoryError};
 use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::chain::{ChainLimits, ChainValidator, ValidChain};
 use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -365,32 +364,28 @@ impl Request {
     // is created based on the information provided from the guest driver for
     // virtio-iommu (giving the link device_id <=> domain).
     fn parse(
-        desc_chain: &mut DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
+        desc_chain: &ValidChain,
+        mem: &GuestMemoryMmap,
         mapping: &Arc<IommuMapping>,
         ext_mapping: &BTreeMap<u32, Arc<dyn ExternalDmaMapping>>,
         msi_iova_space: (u64, u64),
     ) -> result::Result<usize, Error> {
+        // The request is readable and the status writable, as checked by
+        // the chain validation.
         let desc = desc_chain
-            .next()
+            .readable()
+            .first()
             .ok_or(Error::DescriptorChainTooShort)
             .map_err(|e| {
                 error!("Missing head descriptor");
                 e
             })?;
 
-        // The descriptor contains the request type which MUST be readable.
-        if desc.is_write_only() {
-            return Err(Error::UnexpectedWriteOnlyDescriptor);
-        }
-
         if (desc.len() as usize) < size_of::<VirtioIommuReqHead>() {
             return Err(Error::InvalidRequest);
         }
 
-        let req_head: VirtioIommuReqHead = desc_chain
-            .memory()
-            .read_obj(desc.addr())
-            .map_err(Error::GuestMemory)?;
+        let req_head: VirtioIommuReqHead = mem.read_obj(desc.addr()).map_err(Error::GuestMemory)?;
         let req_offset = size_of::<VirtioIommuReqHead>();
         let desc_size_left = (desc.len() as usize) - req_offset;
         let req_addr = if let Some(addr) = desc.addr().checked_add(req_offset as u64) {
@@ -414,8 +409,7 @@ impl Request {
                         return Err(Error::InvalidAttachRequest);
                     }
 
-                    let req: VirtioIommuReqAttach = desc_chain
-                        .memory()
+                    let req: VirtioIommuReqAttach = mem
                         .read_obj(req_addr as GuestAddress)
                         .map_err(Error::GuestMemory)?;
                     debug!("Attach request {:?}", req);
@@ -447,8 +441,7 @@ impl Request {
                         return Err(Error::InvalidDetachRequest);
                     }
 
-                    let req: VirtioIommuReqDetach = desc_chain
-                        .memory()
+                    let req: VirtioIommuReqDetach = mem
                         .read_obj(req_addr as GuestAddress)
                         .map_err(Error::GuestMemory)?;
                     debug!("Detach request {:?}", req);
@@ -481,8 +474,7 @@ impl Request {
                         return Err(Error::InvalidMapRequest);
                     }
 
-                    let req: VirtioIommuReqMap = desc_chain
-                        .memory()
+                    let req: VirtioIommuReqMap = mem
                         .read_obj(req_addr as GuestAddress)
                         .map_err(Error::GuestMemory)?;
                     debug!("Map request {:?}", req);
@@ -542,8 +534,7 @@ impl Request {
                         return Err(Error::InvalidUnmapRequest);
                     }
 
-                    let req: VirtioIommuReqUnmap = desc_chain
-                        .memory()
+                    let req: VirtioIommuReqUnmap = mem
                         .read_obj(req_addr as GuestAddress)
                         .map_err(Error::GuestMemory)?;
                     debug!("Unmap request {:?}", req);
@@ -598,8 +589,7 @@ impl Request {
                         return Err(Error::InvalidProbeRequest);
                     }
 
-                    let req: VirtioIommuReqProbe = desc_chain
-                        .memory()
+                    let req: VirtioIommuReqProbe = mem
                         .read_obj(req_addr as GuestAddress)
                         .map_err(Error::GuestMemory)?;
                     debug!("Probe request {:?}", req);
@@ -628,12 +618,10 @@ impl Request {
             Ok(())
         })();
 
-        let status_desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
-
-        // The status MUST always be writable
-        if !status_desc.is_write_only() {
-            return Err(Error::UnexpectedReadOnlyDescriptor);
-        }
+        let status_desc = desc_chain
+            .writable()
+            .first()
+            .ok_or(Error::DescriptorChainTooShort)?;
 
         if status_desc.len() < hdr_len + size_of::<VirtioIommuReqTail>() as u32 {
             return Err(Error::BufferLengthTooSmall);
@@ -647,9 +635,7 @@ impl Request {
 
         // Make sure we return the result of the request to the guest before
         // we return a potential error internally.
-        desc_chain
-            .memory()
-            .write_slice(reply.as_slice(), status_desc.addr())
+        mem.write_slice(reply.as_slice(), status_desc.addr())
             .map_err(Error::GuestMemory)?;
 
         // Return the error if the result was not Ok().
@@ -668,6 +654,7 @@ struct IommuEpollHandler {
     mapping: Arc<IommuMapping>,
     ext_mapping: Arc<Mutex<BTreeMap<u32, Arc<dyn ExternalDmaMapping>>>>,
     msi_iova_space: (u64, u64),
+    chain_validator: ChainValidator,
 }
 
 impl IommuEpollHandler {
@@ -675,8 +662,18 @@ impl IommuEpollHandler {
         let mut used_desc_heads = [(0, 0); QUEUE_SIZE as usize];
         let mut used_count = 0;
         for mut desc_chain in self.queues[0].iter().unwrap() {
+            let valid_chain = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain,
+                Err(_) => {
+                    used_desc_heads[used_count] = (desc_chain.head_index(), 0);
+                    used_count += 1;
+                    continue;
+                }
+            };
+
             let len = match Request::parse(
-                &mut desc_chain,
+                &valid_chain,
+                desc_chain.memory(),
                 &self.mapping,
                 &self.ext_mapping.lock().unwrap(),
                 self.msi_iova_space,
@@ -1066,6 +1063,11 @@ impl VirtioDevice for Iommu {
             mapping: self.mapping.clone(),
             ext_mapping: self.ext_mapping.clone(),
             msi_iova_space: self.msi_iova_space,
+            chain_validator: self.common.chain_validator(ChainLimits {
+                min_readable: size_of::<VirtioIommuReqHead>() as u64,
+                min_writable: size_of::<VirtioIommuReqTail>() as u64,
+                ..Default::default()
+            }),
         };
 
         let paused = self.common.paused.clone();
@@ -1077,6 +1079,7 @@ impl VirtioDevice for Iommu {
             Thread::VirtioIommu,
             &mut epoll_threads,
             &self.exit_evt,
+            self.common.thread_nice,
             move || {
                 if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                     error!("Error running worker: {:?}", e);
@@ -1090,11 +1093,19 @@ impl VirtioDevice for Iommu {
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
+
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
 }
 
 impl Pausable for Iommu {
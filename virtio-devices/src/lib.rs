@@ -13,8 +13,11 @@
 #[macro_use]
 extern crate event_monitor;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 use std::io;
@@ -25,10 +28,14 @@ pub mod balloon;
 pub mod block;
 mod console;
 pub mod epoll_helper;
+pub mod gpu;
+mod interrupt_coalescing;
 mod iommu;
 pub mod mem;
 pub mod net;
 mod pmem;
+mod ptp;
+mod rate_limiter_update;
 mod rng;
 pub mod seccomp_filters;
 mod thread_helper;
@@ -43,10 +50,12 @@ pub use self::block::*;
 pub use self::console::*;
 pub use self::device::*;
 pub use self::epoll_helper::*;
+pub use self::gpu::Gpu;
 pub use self::iommu::*;
 pub use self::mem::*;
 pub use self::net::*;
 pub use self::pmem::*;
+pub use self::ptp::*;
 pub use self::rng::*;
 pub use self::vdpa::*;
 pub use self::vsock::*;
@@ -101,8 +110,12 @@ pub enum ActivateError {
     CreateSeccompFilter(seccompiler::Error),
     /// Cannot create rate limiter
     CreateRateLimiter(std::io::Error),
+    /// Cannot create the timer coalescing the interrupts
+    CreateInterruptCoalescer(std::io::Error),
     /// Failed activating the vDPA device
     ActivateVdpa(vdpa::Error),
+    /// Failed to setup the vhost-net backend.
+    VhostNetSetup(net::Error),
 }
 
 pub type ActivateResult = std::result::Result<(), ActivateError>;
@@ -116,21 +129,29 @@ pub enum Error {
     VdpaUpdateMemory(vdpa::Error),
     VhostUserUpdateMemory(vhost_user::Error),
     VhostUserAddMemoryRegion(vhost_user::Error),
+    VhostNetUpdateMemory(net::Error),
     SetShmRegionsNotSupported,
     NetQueuePair(::net_util::NetQueuePairError),
     ApplySeccompFilter(seccompiler::Error),
     QueueAddUsed(virtio_queue::Error),
     QueueIterator(virtio_queue::Error),
+    RateLimiterUnsupported,
+    UpdateRateLimiter(io::Error),
+    InterruptCoalescingUnsupported,
+    QueueRingIndex(virtio_queue::Error),
+    QueueSizeUnsupported,
+    InvalidQueueSize(u16),
+    DriverInitialized,
 }
 
-#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, JsonSchema)]
 pub struct TokenBucketConfig {
     pub size: u64,
     pub one_time_burst: Option<u64>,
     pub refill_time: u64,
 }
 
-#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct RateLimiterConfig {
     pub bandwidth: Option<TokenBucketConfig>,
@@ -154,6 +175,18 @@ impl TryInto<rate_limiter::RateLimiter> for RateLimiterConfig {
     }
 }
 
+/// Coalescing of the used queue interrupts of a device, which signals the
+/// guest once `max_events` used buffers are pending, or `usecs` after the
+/// first pending one otherwise. A `max_events` of 0 doesn't bound the number
+/// of pending used buffers.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct InterruptCoalescingConfig {
+    #[serde(default)]
+    pub max_events: u32,
+    pub usecs: u32,
+}
+
 /// Convert an absolute address into an address space (GuestMemory)
 /// to a host pointer and verify that the provided size define a valid
 /// range within a single memory region.
@@ -24,9 +24,10 @@ use crate::{VirtioInterrupt, VirtioInterruptType};
 use anyhow::anyhow;
 use libc::EFD_NONBLOCK;
 use seccompiler::SeccompAction;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io;
 use std::mem::size_of;
+use std::num::Wrapping;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::result;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
@@ -34,16 +35,17 @@ use std::sync::mpsc;
 use std::sync::{Arc, Barrier, Mutex};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
-use virtio_queue::{DescriptorChain, Queue};
+use virtio_queue::Queue;
 use vm_device::dma_mapping::ExternalDmaMapping;
 use vm_memory::{
     Address, ByteValued, Bytes, GuestAddress, GuestMemoryAtomic, GuestMemoryError,
-    GuestMemoryLoadGuard, GuestMemoryRegion,
+    GuestMemoryRegion,
 };
 use vm_migration::protocol::MemoryRangeTable;
 use vm_migration::{
     Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionMapped,
 };
+use vm_virtio::chain::{ChainLimits, ChainValidator, Error as ChainError, ValidChain};
 use vmm_sys_util::eventfd::EventFd;
 
 const QUEUE_SIZE: u16 = 128;
@@ -139,6 +141,8 @@ pub enum Error {
     InvalidDmaMappingHandler,
     // Not activated by the guest
     NotActivatedByGuest,
+    // Guest gave us an invalid descriptor chain.
+    InvalidChain(ChainError),
 }
 
 #[repr(C)]
@@ -278,28 +282,22 @@ struct Request {
 }
 
 impl Request {
-    fn parse(
-        desc_chain: &mut DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
-    ) -> result::Result<Request, Error> {
-        let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
-        // The descriptor contains the request type which MUST be readable.
-        if desc.is_write_only() {
-            return Err(Error::UnexpectedWriteOnlyDescriptor);
-        }
+    fn parse(desc_chain: &ValidChain, mem: &GuestMemoryMmap) -> result::Result<Request, Error> {
+        // The request type is readable and the status writable, as checked
+        // by the chain validation.
+        let desc = desc_chain
+            .readable()
+            .first()
+            .ok_or(Error::DescriptorChainTooShort)?;
         if desc.len() as usize != size_of::<VirtioMemReq>() {
             return Err(Error::InvalidRequest);
         }
-        let req: VirtioMemReq = desc_chain
-            .memory()
-            .read_obj(desc.addr())
-            .map_err(Error::GuestMemory)?;
-
-        let status_desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
+        let req: VirtioMemReq = mem.read_obj(desc.addr()).map_err(Error::GuestMemory)?;
 
-        // The status MUST always be writable
-        if !status_desc.is_write_only() {
-            return Err(Error::UnexpectedReadOnlyDescriptor);
-        }
+        let status_desc = desc_chain
+            .writable()
+            .first()
+            .ok_or(Error::DescriptorChainTooShort)?;
 
         if (status_desc.len() as usize) < size_of::<VirtioMemResp>() {
             return Err(Error::BufferLengthTooSmall);
@@ -474,6 +472,7 @@ struct MemEpollHandler {
     pause_evt: EventFd,
     hugepages: bool,
     dma_mapping_handlers: Arc<Mutex<BTreeMap<VirtioMemMappingSource, Arc<dyn ExternalDmaMapping>>>>,
+    chain_validator: ChainValidator,
 }
 
 impl MemEpollHandler {
@@ -667,9 +666,14 @@ impl MemEpollHandler {
         let mut used_count = 0;
 
         for mut desc_chain in self.queue.iter().unwrap() {
+            let request = self
+                .chain_validator
+                .validate(&mut desc_chain)
+                .map_err(Error::InvalidChain)
+                .and_then(|valid_chain| Request::parse(&valid_chain, desc_chain.memory()));
             request_list.push((
                 desc_chain.head_index(),
-                Request::parse(&mut desc_chain),
+                request,
                 desc_chain.memory().clone(),
             ));
         }
@@ -1022,6 +1026,11 @@ impl VirtioDevice for Mem {
             pause_evt,
             hugepages: self.hugepages,
             dma_mapping_handlers: Arc::clone(&self.dma_mapping_handlers),
+            chain_validator: self.common.chain_validator(ChainLimits {
+                min_readable: size_of::<VirtioMemReq>() as u64,
+                min_writable: size_of::<VirtioMemResp>() as u64,
+                ..Default::default()
+            }),
         };
 
         let unplugged_memory_ranges = self.blocks_state.lock().unwrap().memory_ranges(0, false);
@@ -1049,6 +1058,7 @@ impl VirtioDevice for Mem {
             Thread::VirtioMem,
             &mut epoll_threads,
             &self.exit_evt,
+            self.common.thread_nice,
             move || {
                 if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                     error!("Error running worker: {:?}", e);
@@ -1063,12 +1073,20 @@ impl VirtioDevice for Mem {
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         self.resize.activated.store(false, Ordering::Release);
         let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
+
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
 }
 
 impl Pausable for Mem {
@@ -8,37 +8,47 @@
 use super::Error as DeviceError;
 use super::{
     ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler,
-    RateLimiterConfig, VirtioCommon, VirtioDevice, VirtioDeviceType, VirtioInterruptType,
-    EPOLL_HELPER_EVENT_LAST,
+    InterruptCoalescingConfig, RateLimiterConfig, VirtioCommon, VirtioDevice, VirtioDeviceType,
+    VirtioInterruptType, EPOLL_HELPER_EVENT_LAST,
 };
+use crate::interrupt_coalescing::InterruptCoalescer;
+use crate::rate_limiter_update::RateLimiterUpdate;
 use crate::seccomp_filters::Thread;
 use crate::thread_helper::spawn_virtio_thread;
-use crate::GuestMemoryMmap;
 use crate::VirtioInterrupt;
-use net_util::CtrlQueue;
+use crate::{GuestMemoryMmap, GuestRegionMmap};
+use anyhow::anyhow;
 use net_util::{
     build_net_config_space, build_net_config_space_with_mq, open_tap,
     virtio_features_to_tap_offload, MacAddr, NetCounters, NetQueuePair, OpenTapError, RxVirtio,
     Tap, TapError, TxVirtio, VirtioNetConfig,
 };
+use net_util::{CtrlNotfCoal, CtrlQueue, VIRTIO_NET_F_NOTF_COAL};
 use seccompiler::SeccompAction;
 use std::net::Ipv4Addr;
 use std::num::Wrapping;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::result;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Barrier};
+use std::sync::{Arc, Barrier, Mutex};
 use std::thread;
 use std::vec::Vec;
 use std::{collections::HashMap, convert::TryInto};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
+use vhost::net::VhostNet;
+use vhost::vhost_kern::net::Net as VhostKernNet;
+use vhost::{VhostBackend, VhostUserMemoryRegionInfo, VringConfigData};
 use virtio_bindings::bindings::virtio_net::*;
 use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use virtio_queue::Queue;
-use vm_memory::{ByteValued, GuestMemoryAtomic};
+use vm_memory::{
+    Address, ByteValued, GuestAddressSpace, GuestMemory, GuestMemoryAtomic, GuestMemoryRegion,
+};
 use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+#[cfg(feature = "fault_injection")]
+use vm_virtio::fault_injection::Faults;
 use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -52,7 +62,6 @@ pub struct NetCtrlEpollHandler {
     pub ctrl_q: CtrlQueue,
     pub queue_evt: EventFd,
     pub queue: Queue<GuestMemoryAtomic<GuestMemoryMmap>>,
-    pub access_platform: Option<Arc<dyn AccessPlatform>>,
     pub interrupt_cb: Arc<dyn VirtioInterrupt>,
     pub queue_index: u16,
 }
@@ -89,10 +98,7 @@ impl EpollHelperHandler for NetCtrlEpollHandler {
                     error!("Failed to get control queue event: {:?}", e);
                     return true;
                 }
-                if let Err(e) = self
-                    .ctrl_q
-                    .process(&mut self.queue, self.access_platform.as_ref())
-                {
+                if let Err(e) = self.ctrl_q.process(&mut self.queue) {
                     error!("Failed to process control queue: {:?}", e);
                     return true;
                 } else {
@@ -134,6 +140,14 @@ pub const TX_TAP_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 4;
 pub const RX_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 5;
 // New 'wake up' event from the tx rate limiter
 pub const TX_RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 6;
+// New rx rate limiter handed over at runtime
+const RX_RATE_LIMITER_UPDATE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 7;
+// New tx rate limiter handed over at runtime
+const TX_RATE_LIMITER_UPDATE_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 8;
+// The coalesced rx interrupt is due
+const RX_INTERRUPT_COALESCING_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 9;
+// The coalesced tx interrupt is due
+const TX_INTERRUPT_COALESCING_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 10;
 
 #[derive(Debug)]
 pub enum Error {
@@ -145,6 +159,21 @@ pub enum Error {
 
     // Error calling dup() on tap fd
     DuplicateTapFd(std::io::Error),
+
+    /// Failed to open the vhost-net device.
+    VhostNetOpen(vhost::Error),
+
+    /// vhost-net can't access the guest memory through the virtual IOMMU.
+    VhostNetIommu,
+
+    /// Failed to set up the vhost-net backend.
+    VhostNetSetup(vhost::Error),
+
+    /// No eventfd to signal the used queue from vhost-net.
+    VhostNetMissingNotifier(u16),
+
+    /// Failed to get the available index of a queue.
+    GetAvailableIndex(virtio_queue::Error),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -157,6 +186,10 @@ struct NetEpollHandler {
     queue_index_base: u16,
     queue_pair: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
     queue_evt_pair: Vec<EventFd>,
+    rx_rate_limiter_update: RateLimiterUpdate,
+    tx_rate_limiter_update: RateLimiterUpdate,
+    rx_interrupt_coalescer: InterruptCoalescer,
+    tx_interrupt_coalescer: InterruptCoalescer,
     // Always generate interrupts until the driver has signalled to the device.
     // This mitigates a problem with interrupts from tap events being "lost" upon
     // a restore as the vCPU thread isn't ready to handle the interrupt. This causes
@@ -174,6 +207,12 @@ impl NetEpollHandler {
             })
     }
 
+    fn used_idx(&self, queue: usize) -> result::Result<Wrapping<u16>, DeviceError> {
+        self.queue_pair[queue]
+            .used_idx(Ordering::Acquire)
+            .map_err(DeviceError::QueueRingIndex)
+    }
+
     fn handle_rx_event(&mut self) -> result::Result<(), DeviceError> {
         let queue_evt = &self.queue_evt_pair[0];
         if let Err(e) = queue_evt.read() {
@@ -204,12 +243,16 @@ impl NetEpollHandler {
     }
 
     fn process_tx(&mut self) -> result::Result<(), DeviceError> {
-        if self
+        let used_idx = self.used_idx(1)?;
+        let needs_notification = self
             .net
             .process_tx(&mut self.queue_pair[1])
             .map_err(DeviceError::NetQueuePair)?
-            || !self.driver_awake
-        {
+            && self
+                .tx_interrupt_coalescer
+                .used((self.used_idx(1)? - used_idx).0.into())
+                .map_err(DeviceError::IoError)?;
+        if needs_notification || !self.driver_awake {
             self.signal_used_queue(self.queue_index_base + 1)?;
             debug!("Signalling TX queue");
         } else {
@@ -233,12 +276,16 @@ impl NetEpollHandler {
     }
 
     fn handle_rx_tap_event(&mut self) -> result::Result<(), DeviceError> {
-        if self
+        let used_idx = self.used_idx(0)?;
+        let needs_notification = self
             .net
             .process_rx(&mut self.queue_pair[0])
             .map_err(DeviceError::NetQueuePair)?
-            || !self.driver_awake
-        {
+            && self
+                .rx_interrupt_coalescer
+                .used((self.used_idx(0)? - used_idx).0.into())
+                .map_err(DeviceError::IoError)?;
+        if needs_notification || !self.driver_awake {
             self.signal_used_queue(self.queue_index_base)?;
             debug!("Signalling RX queue");
         } else {
@@ -261,6 +308,22 @@ impl NetEpollHandler {
         if let Some(rate_limiter) = &self.net.tx_rate_limiter {
             helper.add_event(rate_limiter.as_raw_fd(), TX_RATE_LIMITER_EVENT)?;
         }
+        helper.add_event(
+            self.rx_rate_limiter_update.evt().as_raw_fd(),
+            RX_RATE_LIMITER_UPDATE_EVENT,
+        )?;
+        helper.add_event(
+            self.tx_rate_limiter_update.evt().as_raw_fd(),
+            TX_RATE_LIMITER_UPDATE_EVENT,
+        )?;
+        helper.add_event(
+            self.rx_interrupt_coalescer.timer().as_raw_fd(),
+            RX_INTERRUPT_COALESCING_EVENT,
+        )?;
+        helper.add_event(
+            self.tx_interrupt_coalescer.timer().as_raw_fd(),
+            TX_INTERRUPT_COALESCING_EVENT,
+        )?;
 
         // If there are some already available descriptors on the RX queue,
         // then we can start the thread while listening onto the TAP.
@@ -286,7 +349,7 @@ impl NetEpollHandler {
 }
 
 impl EpollHelperHandler for NetEpollHandler {
-    fn handle_event(&mut self, _helper: &mut EpollHelper, event: &epoll::Event) -> bool {
+    fn handle_event(&mut self, helper: &mut EpollHelper, event: &epoll::Event) -> bool {
         let ev_type = event.data as u16;
         match ev_type {
             RX_QUEUE_EVENT => {
@@ -370,6 +433,52 @@ impl EpollHelperHandler for NetEpollHandler {
                     return true;
                 }
             }
+            RX_RATE_LIMITER_UPDATE_EVENT => {
+                if let Err(e) = self.rx_rate_limiter_update.receive(
+                    &mut self.net.rx_rate_limiter,
+                    helper,
+                    RX_RATE_LIMITER_EVENT,
+                ) {
+                    error!("Failed to update the rx rate limiter: {:?}", e);
+                    return true;
+                }
+            }
+            TX_RATE_LIMITER_UPDATE_EVENT => {
+                if let Err(e) = self.tx_rate_limiter_update.receive(
+                    &mut self.net.tx_rate_limiter,
+                    helper,
+                    TX_RATE_LIMITER_EVENT,
+                ) {
+                    error!("Failed to update the tx rate limiter: {:?}", e);
+                    return true;
+                }
+            }
+            RX_INTERRUPT_COALESCING_EVENT => match self.rx_interrupt_coalescer.expired() {
+                Ok(true) => {
+                    if let Err(e) = self.signal_used_queue(self.queue_index_base) {
+                        error!("Failed to signal used queue: {:?}", e);
+                        return true;
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Failed to get rx interrupt coalescing event: {:?}", e);
+                    return true;
+                }
+            },
+            TX_INTERRUPT_COALESCING_EVENT => match self.tx_interrupt_coalescer.expired() {
+                Ok(true) => {
+                    if let Err(e) = self.signal_used_queue(self.queue_index_base + 1) {
+                        error!("Failed to signal used queue: {:?}", e);
+                        return true;
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Failed to get tx interrupt coalescing event: {:?}", e);
+                    return true;
+                }
+            },
             _ => {
                 error!("Unknown event: {}", ev_type);
                 return true;
@@ -377,6 +486,78 @@ impl EpollHelperHandler for NetEpollHandler {
         }
         false
     }
+
+    fn pause(&mut self) {
+        for (queue_index, coalescer) in [
+            (self.queue_index_base, &mut self.rx_interrupt_coalescer),
+            (self.queue_index_base + 1, &mut self.tx_interrupt_coalescer),
+        ] {
+            match coalescer.flush() {
+                Ok(true) => {
+                    if let Err(e) = self
+                        .interrupt_cb
+                        .trigger(VirtioInterruptType::Queue(queue_index))
+                    {
+                        error!("Failed to signal used queue: {:?}", e);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => error!("Failed to flush interrupt coalescing: {:?}", e),
+            }
+        }
+    }
+}
+
+// Features of the rings handled by vhost-net, the other ones being either
+// handled by the tap or by the control queue.
+const VHOST_NET_RING_FEATURES: u64 = 1 << VIRTIO_RING_F_EVENT_IDX | 1 << VIRTIO_F_VERSION_1;
+
+/// In-kernel datapath of the queue pairs, each tap queue being handled by its
+/// own vhost-net instance.
+struct VhostNetBackend {
+    mem: GuestMemoryAtomic<GuestMemoryMmap>,
+    handles: Vec<VhostKernNet<GuestMemoryAtomic<GuestMemoryMmap>>>,
+    features: u64,
+    active_pairs: usize,
+}
+
+impl VhostNetBackend {
+    fn set_mem_table(&self) -> Result<()> {
+        let regions: Vec<VhostUserMemoryRegionInfo> = self
+            .mem
+            .memory()
+            .iter()
+            .map(|region| VhostUserMemoryRegionInfo {
+                guest_phys_addr: region.start_addr().raw_value(),
+                memory_size: region.len() as u64,
+                userspace_addr: region.as_ptr() as u64,
+                mmap_offset: 0,
+                mmap_handle: -1,
+            })
+            .collect();
+
+        for handle in self.handles.iter() {
+            handle
+                .set_mem_table(regions.as_slice())
+                .map_err(Error::VhostNetSetup)?;
+        }
+
+        Ok(())
+    }
+
+    // Attach or detach the taps of the active queue pairs, vhost-net only
+    // processing the rings while its backend is set.
+    fn set_backends(&self, taps: &[Tap], enable: bool) -> Result<()> {
+        for (handle, tap) in self.handles.iter().zip(taps).take(self.active_pairs) {
+            for queue_index in 0..2 {
+                handle
+                    .set_backend(queue_index, if enable { Some(tap.file()) } else { None })
+                    .map_err(Error::VhostNetSetup)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 pub struct Net {
@@ -388,7 +569,15 @@ pub struct Net {
     counters: NetCounters,
     seccomp_action: SeccompAction,
     rate_limiter_config: Option<RateLimiterConfig>,
+    rate_limiter_updates: Vec<RateLimiterUpdate>,
+    // The driver can set the coalescing of the rx and tx queues on their
+    // own through the control queue.
+    rx_interrupt_coalescing: Arc<Mutex<Option<InterruptCoalescingConfig>>>,
+    tx_interrupt_coalescing: Arc<Mutex<Option<InterruptCoalescingConfig>>>,
+    vhost_net: Option<VhostNetBackend>,
     exit_evt: EventFd,
+    #[cfg(feature = "fault_injection")]
+    faults: Option<Arc<Faults>>,
 }
 
 #[derive(Versionize)]
@@ -427,7 +616,8 @@ impl Net {
             | 1 << VIRTIO_NET_F_HOST_TSO6
             | 1 << VIRTIO_NET_F_HOST_UFO
             | 1 << VIRTIO_RING_F_EVENT_IDX
-            | 1 << VIRTIO_F_VERSION_1;
+            | 1 << VIRTIO_F_VERSION_1
+            | 1 << VIRTIO_NET_F_NOTF_COAL;
 
         if iommu {
             avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
@@ -459,7 +649,13 @@ impl Net {
             counters: NetCounters::default(),
             seccomp_action,
             rate_limiter_config,
+            rate_limiter_updates: Vec::new(),
+            rx_interrupt_coalescing: Arc::new(Mutex::new(None)),
+            tx_interrupt_coalescing: Arc::new(Mutex::new(None)),
+            vhost_net: None,
             exit_evt,
+            #[cfg(feature = "fault_injection")]
+            faults: None,
         })
     }
 
@@ -534,6 +730,105 @@ impl Net {
         )
     }
 
+    /// Offload the datapath of the queue pairs to the vhost-net kernel
+    /// driver, the control queue being still handled by the VMM. The rate
+    /// limiter, the interrupt coalescing and the counters don't apply to the
+    /// queue pairs handled by vhost-net.
+    pub fn enable_vhost_net(&mut self, mem: GuestMemoryAtomic<GuestMemoryMmap>) -> Result<()> {
+        if self.common.avail_features & (1u64 << VIRTIO_F_IOMMU_PLATFORM) != 0 {
+            return Err(Error::VhostNetIommu);
+        }
+
+        let mut handles = Vec::new();
+        for _ in self.taps.iter() {
+            let handle = VhostKernNet::new(mem.clone()).map_err(Error::VhostNetOpen)?;
+            handle.set_owner().map_err(Error::VhostNetSetup)?;
+            handles.push(handle);
+        }
+        let features = handles[0].get_features().map_err(Error::VhostNetSetup)?;
+        self.common.avail_features &= !VHOST_NET_RING_FEATURES | features;
+        self.common.avail_features &= !(1 << VIRTIO_NET_F_NOTF_COAL);
+
+        self.vhost_net = Some(VhostNetBackend {
+            mem,
+            handles,
+            features,
+            active_pairs: 0,
+        });
+
+        Ok(())
+    }
+
+    fn activate_vhost_net(
+        &mut self,
+        interrupt_cb: &Arc<dyn VirtioInterrupt>,
+        queues: &[Queue<GuestMemoryAtomic<GuestMemoryMmap>>],
+        queue_evts: &[EventFd],
+    ) -> Result<()> {
+        let vhost_net = self.vhost_net.as_mut().unwrap();
+        let features = self.common.acked_features & vhost_net.features;
+        vhost_net.set_mem_table()?;
+
+        for (i, (handle, tap)) in vhost_net
+            .handles
+            .iter()
+            .zip(self.taps.iter())
+            .take(queues.len() / 2)
+            .enumerate()
+        {
+            tap.set_offload(virtio_features_to_tap_offload(self.common.acked_features))
+                .map_err(Error::TapError)?;
+            handle
+                .set_features(features)
+                .map_err(Error::VhostNetSetup)?;
+
+            for queue_index in 0..2 {
+                let queue = &queues[i * 2 + queue_index];
+                let queue_size = queue.state.size;
+                handle
+                    .set_vring_num(queue_index, queue_size)
+                    .map_err(Error::VhostNetSetup)?;
+
+                let config_data = VringConfigData {
+                    queue_max_size: queue.max_size(),
+                    queue_size,
+                    flags: 0u32,
+                    desc_table_addr: queue.state.desc_table.raw_value(),
+                    used_ring_addr: queue.state.used_ring.raw_value(),
+                    avail_ring_addr: queue.state.avail_ring.raw_value(),
+                    log_addr: None,
+                };
+                handle
+                    .set_vring_addr(queue_index, &config_data)
+                    .map_err(Error::VhostNetSetup)?;
+                handle
+                    .set_vring_base(
+                        queue_index,
+                        queue
+                            .avail_idx(Ordering::Acquire)
+                            .map_err(Error::GetAvailableIndex)?
+                            .0,
+                    )
+                    .map_err(Error::VhostNetSetup)?;
+
+                // vhost-net signals the used queue straight to the guest.
+                let virtio_queue_index = (i * 2 + queue_index) as u16;
+                let eventfd = interrupt_cb
+                    .notifier(VirtioInterruptType::Queue(virtio_queue_index))
+                    .ok_or(Error::VhostNetMissingNotifier(virtio_queue_index))?;
+                handle
+                    .set_vring_call(queue_index, &eventfd)
+                    .map_err(Error::VhostNetSetup)?;
+                handle
+                    .set_vring_kick(queue_index, &queue_evts[i * 2 + queue_index])
+                    .map_err(Error::VhostNetSetup)?;
+            }
+        }
+
+        vhost_net.active_pairs = queues.len() / 2;
+        vhost_net.set_backends(&self.taps, true)
+    }
+
     fn state(&self) -> NetState {
         NetState {
             avail_features: self.common.avail_features,
@@ -569,6 +864,10 @@ impl VirtioDevice for Net {
         &self.common.queue_sizes
     }
 
+    fn set_queue_max_size(&mut self, max_size: u16) -> result::Result<(), DeviceError> {
+        self.common.set_queue_max_size(max_size)
+    }
+
     fn features(&self) -> u64 {
         self.common.avail_features
     }
@@ -600,22 +899,50 @@ impl VirtioDevice for Net {
             ctrl_queue.set_event_idx(event_idx);
 
             let (kill_evt, pause_evt) = self.common.dup_eventfds();
+            let mut ctrl_q = CtrlQueue::new(self.taps.clone());
+            ctrl_q.chain_validator = self
+                .common
+                .chain_validator(*ctrl_q.chain_validator.limits());
+            if self.common.feature_acked(VIRTIO_NET_F_NOTF_COAL.into()) {
+                let rx_interrupt_coalescing = self.rx_interrupt_coalescing.clone();
+                let tx_interrupt_coalescing = self.tx_interrupt_coalescing.clone();
+                ctrl_q.notf_coal = Some(Box::new(move |coal: CtrlNotfCoal| {
+                    let interrupt_coalescing = if coal.rx {
+                        &rx_interrupt_coalescing
+                    } else {
+                        &tx_interrupt_coalescing
+                    };
+                    // A delay of 0 disables the coalescing.
+                    *interrupt_coalescing.lock().unwrap() = if coal.usecs != 0 {
+                        Some(InterruptCoalescingConfig {
+                            max_events: coal.max_packets,
+                            usecs: coal.usecs,
+                        })
+                    } else {
+                        None
+                    };
+                }));
+            }
             let mut ctrl_handler = NetCtrlEpollHandler {
                 kill_evt,
                 pause_evt,
-                ctrl_q: CtrlQueue::new(self.taps.clone()),
+                ctrl_q,
                 queue: ctrl_queue,
                 queue_evt: ctrl_queue_evt,
-                access_platform: self.common.access_platform.clone(),
                 queue_index: ctrl_queue_index as u16,
                 interrupt_cb: interrupt_cb.clone(),
             };
 
             let paused = self.common.paused.clone();
-            // Let's update the barrier as we need 1 for each RX/TX pair +
-            // 1 for the control queue + 1 for the main thread signalling
-            // the pause.
-            self.common.paused_sync = Some(Arc::new(Barrier::new(self.taps.len() + 2)));
+            // Let's update the barrier as we need 1 for each RX/TX pair
+            // handled by the VMM + 1 for the control queue + 1 for the main
+            // thread signalling the pause.
+            let num_pair_threads = if self.vhost_net.is_some() {
+                0
+            } else {
+                self.taps.len()
+            };
+            self.common.paused_sync = Some(Arc::new(Barrier::new(num_pair_threads + 2)));
             let paused_sync = self.common.paused_sync.clone();
 
             let mut epoll_threads = Vec::new();
@@ -625,6 +952,7 @@ impl VirtioDevice for Net {
                 Thread::VirtioNetCtl,
                 &mut epoll_threads,
                 &self.exit_evt,
+                self.common.thread_nice,
                 move || {
                     if let Err(e) = ctrl_handler.run_ctrl(paused, paused_sync.unwrap()) {
                         error!("Error running worker: {:?}", e);
@@ -634,11 +962,29 @@ impl VirtioDevice for Net {
             self.ctrl_queue_epoll_thread = Some(epoll_threads.remove(0));
         }
 
+        self.rate_limiter_updates.clear();
+
+        if self.vhost_net.is_some() {
+            // Without a control queue, there is no thread left to wait for
+            // when pausing the device.
+            if queues.len() == num_queues {
+                self.common.paused_sync = Some(Arc::new(Barrier::new(1)));
+            }
+            self.activate_vhost_net(&interrupt_cb, &queues, &queue_evts)
+                .map_err(ActivateError::VhostNetSetup)?;
+            self.common.epoll_threads = Some(Vec::new());
+
+            event!("virtio-device", "activated", "id", &self.id);
+            return Ok(());
+        }
+
         let mut epoll_threads = Vec::new();
         let mut taps = self.taps.clone();
         for i in 0..queues.len() / 2 {
-            let rx = RxVirtio::new();
-            let tx = TxVirtio::new();
+            let mut rx = RxVirtio::new();
+            let mut tx = TxVirtio::new();
+            rx.chain_validator = self.common.chain_validator(*rx.chain_validator.limits());
+            tx.chain_validator = self.common.chain_validator(*tx.chain_validator.limits());
             let rx_tap_listening = false;
 
             let mut queue_pair = vec![queues.remove(0), queues.remove(0)];
@@ -661,6 +1007,25 @@ impl VirtioDevice for Net {
                 .transpose()
                 .map_err(ActivateError::CreateRateLimiter)?;
 
+            let rx_rate_limiter_update =
+                RateLimiterUpdate::new().map_err(ActivateError::CreateRateLimiter)?;
+            let tx_rate_limiter_update =
+                RateLimiterUpdate::new().map_err(ActivateError::CreateRateLimiter)?;
+            for rate_limiter_update in [&rx_rate_limiter_update, &tx_rate_limiter_update] {
+                self.rate_limiter_updates.push(
+                    rate_limiter_update
+                        .try_clone()
+                        .map_err(ActivateError::CreateRateLimiter)?,
+                );
+            }
+
+            let rx_interrupt_coalescer =
+                InterruptCoalescer::new(self.rx_interrupt_coalescing.clone())
+                    .map_err(ActivateError::CreateInterruptCoalescer)?;
+            let tx_interrupt_coalescer =
+                InterruptCoalescer::new(self.tx_interrupt_coalescing.clone())
+                    .map_err(ActivateError::CreateInterruptCoalescer)?;
+
             let tap = taps.remove(0);
             tap.set_offload(virtio_features_to_tap_offload(self.common.acked_features))
                 .map_err(|e| {
@@ -683,17 +1048,26 @@ impl VirtioDevice for Net {
                     rx_desc_avail: false,
                     rx_rate_limiter,
                     tx_rate_limiter,
-                    access_platform: self.common.access_platform.clone(),
                 },
                 queue_index_base: (i * 2) as u16,
                 queue_pair,
                 queue_evt_pair,
+                rx_rate_limiter_update,
+                tx_rate_limiter_update,
+                rx_interrupt_coalescer,
+                tx_interrupt_coalescer,
                 interrupt_cb: interrupt_cb.clone(),
                 kill_evt,
                 pause_evt,
                 driver_awake: false,
             };
 
+            #[cfg(feature = "fault_injection")]
+            {
+                handler.net.rx.faults = self.faults.clone();
+                handler.net.tx.faults = self.faults.clone();
+            }
+
             let paused = self.common.paused.clone();
             let paused_sync = self.common.paused_sync.clone();
 
@@ -703,6 +1077,7 @@ impl VirtioDevice for Net {
                 Thread::VirtioNet,
                 &mut epoll_threads,
                 &self.exit_evt,
+                self.common.thread_nice,
                 move || {
                     if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                         error!("Error running worker: {:?}", e);
@@ -717,14 +1092,26 @@ impl VirtioDevice for Net {
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        if let Some(vhost_net) = self.vhost_net.as_mut() {
+            if let Err(e) = vhost_net.set_backends(&self.taps, false) {
+                error!("Failed to stop the vhost-net backends: {:?}", e);
+            }
+            vhost_net.active_pairs = 0;
+        }
+
         let result = self.common.reset();
+        self.rate_limiter_updates.clear();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
 
     fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
-        let mut counters = HashMap::new();
+        let mut counters = self.common.counters();
 
         counters.insert(
             "rx_bytes",
@@ -749,16 +1136,78 @@ impl VirtioDevice for Net {
     fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
         self.common.set_access_platform(access_platform)
     }
+
+    #[cfg(feature = "fault_injection")]
+    fn set_faults(&mut self, faults: Arc<Faults>) {
+        self.faults = Some(faults);
+    }
+
+    fn set_rate_limiter_config(
+        &mut self,
+        rate_limiter_config: Option<RateLimiterConfig>,
+    ) -> result::Result<(), DeviceError> {
+        if self.vhost_net.is_some() {
+            return Err(DeviceError::RateLimiterUnsupported);
+        }
+
+        for rate_limiter_update in self.rate_limiter_updates.iter() {
+            rate_limiter_update
+                .send(rate_limiter_config)
+                .map_err(DeviceError::UpdateRateLimiter)?;
+        }
+        self.rate_limiter_config = rate_limiter_config;
+
+        Ok(())
+    }
+
+    fn set_interrupt_coalescing_config(
+        &mut self,
+        interrupt_coalescing_config: Option<InterruptCoalescingConfig>,
+    ) -> result::Result<(), DeviceError> {
+        if self.vhost_net.is_some() {
+            return Err(DeviceError::InterruptCoalescingUnsupported);
+        }
+
+        *self.rx_interrupt_coalescing.lock().unwrap() = interrupt_coalescing_config;
+        *self.tx_interrupt_coalescing.lock().unwrap() = interrupt_coalescing_config;
+
+        Ok(())
+    }
+
+    fn add_memory_region(
+        &mut self,
+        _region: &Arc<GuestRegionMmap>,
+    ) -> result::Result<(), DeviceError> {
+        if let Some(vhost_net) = &self.vhost_net {
+            vhost_net
+                .set_mem_table()
+                .map_err(DeviceError::VhostNetUpdateMemory)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Pausable for Net {
     fn pause(&mut self) -> result::Result<(), MigratableError> {
+        if let Some(vhost_net) = &self.vhost_net {
+            vhost_net.set_backends(&self.taps, false).map_err(|e| {
+                MigratableError::Pause(anyhow!("Error stopping the vhost-net backends: {:?}", e))
+            })?;
+        }
+
         self.common.pause()
     }
 
     fn resume(&mut self) -> result::Result<(), MigratableError> {
         self.common.resume()?;
 
+        if let Some(vhost_net) = &self.vhost_net {
+            vhost_net.set_backends(&self.taps, true).map_err(|e| {
+                MigratableError::Resume(anyhow!("Error starting the vhost-net backends: {:?}", e))
+            })?;
+        }
+
         if let Some(ctrl_queue_epoll_thread) = &self.ctrl_queue_epoll_thread {
             ctrl_queue_epoll_thread.thread().unpark();
         }
@@ -772,6 +1221,18 @@ impl Snapshottable for Net {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        // The indices of the rings handled by vhost-net aren't read back,
+        // hence the queues would be restored with stale ones.
+        if self
+            .vhost_net
+            .as_ref()
+            .map_or(false, |vhost_net| vhost_net.active_pairs > 0)
+        {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Snapshot not supported with vhost-net"
+            )));
+        }
+
         Snapshot::new_from_versioned_state(&self.id, &self.state())
     }
 
@@ -781,4 +1242,16 @@ impl Snapshottable for Net {
     }
 }
 impl Transportable for Net {}
-impl Migratable for Net {}
+
+impl Migratable for Net {
+    fn start_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        // vhost-net writes to the guest memory behind the back of the VMM.
+        if self.vhost_net.is_some() {
+            return Err(MigratableError::StartDirtyLog(anyhow!(
+                "Live migration not supported with vhost-net"
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -17,24 +17,24 @@ use crate::thread_helper::spawn_virtio_thread;
 use crate::{GuestMemoryMmap, MmapRegion};
 use crate::{VirtioInterrupt, VirtioInterruptType};
 use seccompiler::SeccompAction;
+use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::fs::File;
 use std::io;
 use std::mem::size_of;
+use std::num::Wrapping;
 use std::os::unix::io::AsRawFd;
 use std::result;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Barrier};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
-use virtio_queue::{DescriptorChain, Queue};
-use vm_memory::{
-    Address, ByteValued, Bytes, GuestAddress, GuestMemoryAtomic, GuestMemoryError,
-    GuestMemoryLoadGuard,
-};
+use virtio_queue::Queue;
+use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryAtomic, GuestMemoryError};
 use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
-use vm_virtio::{AccessPlatform, Translatable};
+use vm_virtio::chain::{ChainLimits, ChainValidator, ValidChain};
+use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
 
 const QUEUE_SIZE: u16 = 256;
@@ -117,39 +117,29 @@ struct Request {
 }
 
 impl Request {
-    fn parse(
-        desc_chain: &mut DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
-        access_platform: Option<&Arc<dyn AccessPlatform>>,
-    ) -> result::Result<Request, Error> {
-        let desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
-        // The descriptor contains the request type which MUST be readable.
-        if desc.is_write_only() {
-            return Err(Error::UnexpectedWriteOnlyDescriptor);
-        }
+    fn parse(desc_chain: &ValidChain, mem: &GuestMemoryMmap) -> result::Result<Request, Error> {
+        // The request type is readable and the status writable, as checked
+        // by the chain validation.
+        let desc = desc_chain
+            .readable()
+            .first()
+            .ok_or(Error::DescriptorChainTooShort)?;
 
         if desc.len() as usize != size_of::<VirtioPmemReq>() {
             return Err(Error::InvalidRequest);
         }
 
-        let request: VirtioPmemReq = desc_chain
-            .memory()
-            .read_obj(
-                desc.addr()
-                    .translate_gva(access_platform, desc.len() as usize),
-            )
-            .map_err(Error::GuestMemory)?;
+        let request: VirtioPmemReq = mem.read_obj(desc.addr()).map_err(Error::GuestMemory)?;
 
         let request_type = match request.type_ {
             VIRTIO_PMEM_REQ_TYPE_FLUSH => RequestType::Flush,
             _ => return Err(Error::InvalidRequest),
         };
 
-        let status_desc = desc_chain.next().ok_or(Error::DescriptorChainTooShort)?;
-
-        // The status MUST always be writable
-        if !status_desc.is_write_only() {
-            return Err(Error::UnexpectedReadOnlyDescriptor);
-        }
+        let status_desc = desc_chain
+            .writable()
+            .first()
+            .ok_or(Error::DescriptorChainTooShort)?;
 
         if (status_desc.len() as usize) < size_of::<VirtioPmemResp>() {
             return Err(Error::BufferLengthTooSmall);
@@ -157,9 +147,7 @@ impl Request {
 
         Ok(Request {
             type_: request_type,
-            status_addr: status_desc
-                .addr()
-                .translate_gva(access_platform, status_desc.len() as usize),
+            status_addr: status_desc.addr(),
         })
     }
 }
@@ -171,7 +159,7 @@ struct PmemEpollHandler {
     queue_evt: EventFd,
     kill_evt: EventFd,
     pause_evt: EventFd,
-    access_platform: Option<Arc<dyn AccessPlatform>>,
+    chain_validator: ChainValidator,
 }
 
 impl PmemEpollHandler {
@@ -179,7 +167,16 @@ impl PmemEpollHandler {
         let mut used_desc_heads = [(0, 0); QUEUE_SIZE as usize];
         let mut used_count = 0;
         for mut desc_chain in self.queue.iter().unwrap() {
-            let len = match Request::parse(&mut desc_chain, self.access_platform.as_ref()) {
+            let valid_chain = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain,
+                Err(_) => {
+                    used_desc_heads[used_count] = (desc_chain.head_index(), 0);
+                    used_count += 1;
+                    continue;
+                }
+            };
+
+            let len = match Request::parse(&valid_chain, desc_chain.memory()) {
                 Ok(ref req) if (req.type_ == RequestType::Flush) => {
                     let status_code = match self.disk.sync_all() {
                         Ok(()) => VIRTIO_PMEM_RESP_TYPE_OK,
@@ -396,7 +393,11 @@ impl VirtioDevice for Pmem {
                 queue_evt: queue_evts.remove(0),
                 kill_evt,
                 pause_evt,
-                access_platform: self.common.access_platform.clone(),
+                chain_validator: self.common.chain_validator(ChainLimits {
+                    min_readable: size_of::<VirtioPmemReq>() as u64,
+                    min_writable: size_of::<VirtioPmemResp>() as u64,
+                    ..Default::default()
+                }),
             };
 
             let paused = self.common.paused.clone();
@@ -409,6 +410,7 @@ impl VirtioDevice for Pmem {
                 Thread::VirtioPmem,
                 &mut epoll_threads,
                 &self.exit_evt,
+                self.common.thread_nice,
                 move || {
                     if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                         error!("Error running worker: {:?}", e);
@@ -424,6 +426,10 @@ impl VirtioDevice for Pmem {
         Err(ActivateError::BadActivate)
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
@@ -434,6 +440,10 @@ impl VirtioDevice for Pmem {
         vec![self.mapping.clone()]
     }
 
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
+
     fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
         self.common.set_access_platform(access_platform)
     }
@@ -0,0 +1,439 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use super::Error as DeviceError;
+use super::{
+    ActivateError, ActivateResult, EpollHelper, EpollHelperError, EpollHelperHandler, VirtioCommon,
+    VirtioDevice, VirtioDeviceType, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IOMMU_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use crate::seccomp_filters::Thread;
+use crate::thread_helper::spawn_virtio_thread;
+use crate::GuestMemoryMmap;
+use crate::{VirtioInterrupt, VirtioInterruptType};
+use seccompiler::SeccompAction;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::num::Wrapping;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::result;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Barrier};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::Queue;
+use vm_memory::{Bytes, GuestMemoryAtomic};
+use vm_migration::VersionMapped;
+use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::chain::{ChainLimits, ChainValidator};
+use vm_virtio::AccessPlatform;
+use vmm_sys_util::eventfd::EventFd;
+
+const QUEUE_SIZE: u16 = 64;
+const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
+
+// New descriptors are pending on the virtio queue.
+const QUEUE_AVAIL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+
+// Requests, see include/uapi/linux/virtio_rtc.h in the kernel code.
+const VIRTIO_RTC_REQ_READ: u16 = 0x0001;
+const VIRTIO_RTC_REQ_CFG: u16 = 0x1000;
+const VIRTIO_RTC_REQ_CLOCK_CAP: u16 = 0x1001;
+const VIRTIO_RTC_REQ_CROSS_CAP: u16 = 0x1002;
+
+// Response status
+const VIRTIO_RTC_S_OK: u8 = 0;
+const VIRTIO_RTC_S_EOPNOTSUPP: u8 = 2;
+const VIRTIO_RTC_S_ENODEV: u8 = 3;
+const VIRTIO_RTC_S_EINVAL: u8 = 4;
+const VIRTIO_RTC_S_EIO: u8 = 5;
+
+// Clock type. A PHC synchronized through PTP runs on the TAI timescale.
+const VIRTIO_RTC_CLOCK_TAI: u8 = 1;
+
+// Both the request and the response are made of an 8 bytes header, followed
+// by the request specific fields. The requests handled here fit in 16 bytes.
+const REQ_SIZE: usize = 16;
+const RESP_SIZE: usize = 16;
+
+// The device exposes a single clock.
+const CLOCK_ID: u16 = 0;
+
+// PTP hardware clock of the host, read through its dynamic POSIX clock.
+struct PtpClock {
+    file: File,
+}
+
+impl PtpClock {
+    fn read(&self) -> io::Result<u64> {
+        // See FD_TO_CLOCKID() in include/linux/posix-timers.h in the kernel
+        // code.
+        let clock_id = ((!self.file.as_raw_fd()) << 3) | 3;
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let ret = unsafe { libc::clock_gettime(clock_id, &mut ts) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64)
+    }
+
+    fn handle_request(&self, req: &[u8; REQ_SIZE]) -> [u8; RESP_SIZE] {
+        let mut resp = [0u8; RESP_SIZE];
+
+        let msg_type = u16::from_le_bytes([req[0], req[1]]);
+        let clock_id = u16::from_le_bytes([req[8], req[9]]);
+
+        resp[0] = match msg_type {
+            VIRTIO_RTC_REQ_CFG => {
+                // Number of clocks
+                resp[8..10].copy_from_slice(&1u16.to_le_bytes());
+                VIRTIO_RTC_S_OK
+            }
+            VIRTIO_RTC_REQ_CLOCK_CAP | VIRTIO_RTC_REQ_CROSS_CAP | VIRTIO_RTC_REQ_READ
+                if clock_id != CLOCK_ID =>
+            {
+                VIRTIO_RTC_S_ENODEV
+            }
+            VIRTIO_RTC_REQ_CLOCK_CAP => {
+                // No leap second smearing, nor alarm.
+                resp[8] = VIRTIO_RTC_CLOCK_TAI;
+                VIRTIO_RTC_S_OK
+            }
+            // Cross-timestamping is not supported, leaving the flags empty.
+            VIRTIO_RTC_REQ_CROSS_CAP => VIRTIO_RTC_S_OK,
+            VIRTIO_RTC_REQ_READ => match self.read() {
+                Ok(ns) => {
+                    resp[8..16].copy_from_slice(&ns.to_le_bytes());
+                    VIRTIO_RTC_S_OK
+                }
+                Err(e) => {
+                    error!("Failed reading the PTP clock: {}", e);
+                    VIRTIO_RTC_S_EIO
+                }
+            },
+            _ => VIRTIO_RTC_S_EOPNOTSUPP,
+        };
+
+        resp
+    }
+}
+
+struct PtpEpollHandler {
+    queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
+    clock: PtpClock,
+    interrupt_cb: Arc<dyn VirtioInterrupt>,
+    queue_evt: EventFd,
+    kill_evt: EventFd,
+    pause_evt: EventFd,
+    chain_validator: ChainValidator,
+}
+
+impl PtpEpollHandler {
+    fn process_queue(&mut self) -> bool {
+        let queue = &mut self.queues[0];
+
+        let mut used_desc_heads = [(0, 0); QUEUE_SIZE as usize];
+        let mut used_count = 0;
+        for mut desc_chain in queue.iter().unwrap() {
+            let valid_chain = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain,
+                Err(_) => {
+                    used_desc_heads[used_count] = (desc_chain.head_index(), 0);
+                    used_count += 1;
+                    continue;
+                }
+            };
+
+            let mut len = 0;
+
+            // Every request is made of a device readable descriptor holding
+            // the request, followed by a device writable one for the
+            // response.
+            if let (Some(req_desc), Some(resp_desc)) = (
+                valid_chain.readable().first(),
+                valid_chain.writable().first(),
+            ) {
+                let mut req = [0u8; REQ_SIZE];
+                let req_len = std::cmp::min(req_desc.len() as usize, REQ_SIZE);
+                let resp_len = std::cmp::min(resp_desc.len() as usize, RESP_SIZE);
+
+                let resp = if let Err(e) = desc_chain
+                    .memory()
+                    .read_slice(&mut req[..req_len], req_desc.addr())
+                {
+                    error!("Failed reading virtio-rtc request: {:?}", e);
+                    None
+                } else if req_len < 2 {
+                    let mut resp = [0u8; RESP_SIZE];
+                    resp[0] = VIRTIO_RTC_S_EINVAL;
+                    Some(resp)
+                } else {
+                    Some(self.clock.handle_request(&req))
+                };
+
+                if let Some(resp) = resp {
+                    match desc_chain
+                        .memory()
+                        .write_slice(&resp[..resp_len], resp_desc.addr())
+                    {
+                        Ok(_) => len = resp_len as u32,
+                        Err(e) => error!("Failed writing virtio-rtc response: {:?}", e),
+                    }
+                }
+            } else {
+                error!("Invalid virtio-rtc descriptor chain");
+            }
+
+            used_desc_heads[used_count] = (desc_chain.head_index(), len);
+            used_count += 1;
+        }
+
+        for &(desc_index, len) in &used_desc_heads[..used_count] {
+            queue.add_used(desc_index, len).unwrap();
+        }
+        used_count > 0
+    }
+
+    fn signal_used_queue(&self) -> result::Result<(), DeviceError> {
+        self.interrupt_cb
+            .trigger(VirtioInterruptType::Queue(0))
+            .map_err(|e| {
+                error!("Failed to signal used queue: {:?}", e);
+                DeviceError::FailedSignalingUsedQueue(e)
+            })
+    }
+
+    fn run(
+        &mut self,
+        paused: Arc<AtomicBool>,
+        paused_sync: Arc<Barrier>,
+    ) -> result::Result<(), EpollHelperError> {
+        let mut helper = EpollHelper::new(&self.kill_evt, &self.pause_evt)?;
+        helper.add_event(self.queue_evt.as_raw_fd(), QUEUE_AVAIL_EVENT)?;
+        helper.run(paused, paused_sync, self)?;
+
+        Ok(())
+    }
+}
+
+impl EpollHelperHandler for PtpEpollHandler {
+    fn handle_event(&mut self, _helper: &mut EpollHelper, event: &epoll::Event) -> bool {
+        let ev_type = event.data as u16;
+        match ev_type {
+            QUEUE_AVAIL_EVENT => {
+                if let Err(e) = self.queue_evt.read() {
+                    error!("Failed to get queue event: {:?}", e);
+                    return true;
+                } else if self.process_queue() {
+                    if let Err(e) = self.signal_used_queue() {
+                        error!("Failed to signal used queue: {:?}", e);
+                        return true;
+                    }
+                }
+            }
+            _ => {
+                error!("Unexpected event: {}", ev_type);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Virtio clock device exposing a PTP hardware clock of the host to the
+/// guest, which registers it as a PTP clock of its own.
+pub struct Ptp {
+    common: VirtioCommon,
+    id: String,
+    clock_file: File,
+    seccomp_action: SeccompAction,
+    exit_evt: EventFd,
+}
+
+#[derive(Versionize)]
+pub struct PtpState {
+    pub avail_features: u64,
+    pub acked_features: u64,
+}
+
+impl VersionMapped for PtpState {}
+
+impl Ptp {
+    /// Create a new virtio clock device reading the time from the given PTP
+    /// hardware clock, such as /dev/ptp0.
+    pub fn new(
+        id: String,
+        path: &Path,
+        iommu: bool,
+        seccomp_action: SeccompAction,
+        exit_evt: EventFd,
+    ) -> io::Result<Ptp> {
+        let clock_file = File::open(path)?;
+        // Make sure this is a clock before exposing it.
+        PtpClock {
+            file: clock_file.try_clone()?,
+        }
+        .read()?;
+
+        let mut avail_features = 1u64 << VIRTIO_F_VERSION_1;
+
+        if iommu {
+            avail_features |= 1u64 << VIRTIO_F_IOMMU_PLATFORM;
+        }
+
+        Ok(Ptp {
+            common: VirtioCommon {
+                device_type: VirtioDeviceType::Clock as u32,
+                queue_sizes: QUEUE_SIZES.to_vec(),
+                paused_sync: Some(Arc::new(Barrier::new(2))),
+                avail_features,
+                min_queues: 1,
+                ..Default::default()
+            },
+            id,
+            clock_file,
+            seccomp_action,
+            exit_evt,
+        })
+    }
+
+    fn state(&self) -> PtpState {
+        PtpState {
+            avail_features: self.common.avail_features,
+            acked_features: self.common.acked_features,
+        }
+    }
+
+    fn set_state(&mut self, state: &PtpState) {
+        self.common.avail_features = state.avail_features;
+        self.common.acked_features = state.acked_features;
+    }
+}
+
+impl Drop for Ptp {
+    fn drop(&mut self) {
+        if let Some(kill_evt) = self.common.kill_evt.take() {
+            // Ignore the result because there is nothing we can do about it.
+            let _ = kill_evt.write(1);
+        }
+    }
+}
+
+impl VirtioDevice for Ptp {
+    fn device_type(&self) -> u32 {
+        self.common.device_type
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &self.common.queue_sizes
+    }
+
+    fn features(&self) -> u64 {
+        self.common.avail_features
+    }
+
+    fn ack_features(&mut self, value: u64) {
+        self.common.ack_features(value)
+    }
+
+    fn activate(
+        &mut self,
+        _mem: GuestMemoryAtomic<GuestMemoryMmap>,
+        interrupt_cb: Arc<dyn VirtioInterrupt>,
+        queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
+        mut queue_evts: Vec<EventFd>,
+    ) -> ActivateResult {
+        self.common.activate(&queues, &queue_evts, &interrupt_cb)?;
+        let (kill_evt, pause_evt) = self.common.dup_eventfds();
+
+        let file = self.clock_file.try_clone().map_err(|e| {
+            error!("failed cloning PTP clock: {}", e);
+            ActivateError::BadActivate
+        })?;
+        let mut handler = PtpEpollHandler {
+            queues,
+            clock: PtpClock { file },
+            interrupt_cb,
+            queue_evt: queue_evts.remove(0),
+            kill_evt,
+            pause_evt,
+            chain_validator: self.common.chain_validator(ChainLimits::default()),
+        };
+
+        let paused = self.common.paused.clone();
+        let paused_sync = self.common.paused_sync.clone();
+        let mut epoll_threads = Vec::new();
+        spawn_virtio_thread(
+            &self.id,
+            &self.seccomp_action,
+            Thread::VirtioPtp,
+            &mut epoll_threads,
+            &self.exit_evt,
+            self.common.thread_nice,
+            move || {
+                if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
+                    error!("Error running worker: {:?}", e);
+                }
+            },
+        )?;
+
+        self.common.epoll_threads = Some(epoll_threads);
+
+        event!("virtio-device", "activated", "id", &self.id);
+        Ok(())
+    }
+
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
+    fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
+        let result = self.common.reset();
+        event!("virtio-device", "reset", "id", &self.id);
+        result
+    }
+
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
+
+    fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
+        self.common.set_access_platform(access_platform)
+    }
+}
+
+impl Pausable for Ptp {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        self.common.pause()
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        self.common.resume()
+    }
+}
+
+impl Snapshottable for Ptp {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.id, &self.state())
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
+        self.set_state(&snapshot.to_versioned_state(&self.id)?);
+        Ok(())
+    }
+}
+
+impl Transportable for Ptp {}
+impl Migratable for Ptp {}
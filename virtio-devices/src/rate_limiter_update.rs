@@ -0,0 +1,189 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::{EpollHelper, EpollHelperError, RateLimiterConfig};
+use libc::EFD_NONBLOCK;
+use rate_limiter::{BucketUpdate, RateLimiter, TokenBucket};
+use std::convert::TryInto;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use vmm_sys_util::eventfd::EventFd;
+
+fn bucket_update(bucket: Option<&TokenBucket>) -> BucketUpdate {
+    match bucket {
+        Some(bucket) => BucketUpdate::Update(bucket.clone()),
+        None => BucketUpdate::Disabled,
+    }
+}
+
+/// Hands a new rate limiter over to a worker thread when the rate limiting
+/// of a device is changed at runtime. The rate limiter is created from the
+/// VMM thread, as the seccomp filters of the worker threads don't let them
+/// create the timer it relies on.
+pub struct RateLimiterUpdate {
+    evt: EventFd,
+    rate_limiter: Arc<Mutex<Option<RateLimiter>>>,
+}
+
+impl RateLimiterUpdate {
+    pub fn new() -> io::Result<Self> {
+        Ok(RateLimiterUpdate {
+            evt: EventFd::new(EFD_NONBLOCK)?,
+            rate_limiter: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(RateLimiterUpdate {
+            evt: self.evt.try_clone()?,
+            rate_limiter: self.rate_limiter.clone(),
+        })
+    }
+
+    /// Event notifying the worker thread of a new rate limiter.
+    pub fn evt(&self) -> &EventFd {
+        &self.evt
+    }
+
+    /// Hand a rate limiter for the configuration over to the worker thread,
+    /// no configuration disabling the rate limiting.
+    pub fn send(&self, config: Option<RateLimiterConfig>) -> io::Result<()> {
+        let rate_limiter = config.unwrap_or_default().try_into()?;
+        self.rate_limiter.lock().unwrap().replace(rate_limiter);
+        self.evt.write(1)
+    }
+
+    /// Apply the rate limiter handed over to the one of the worker thread.
+    /// The current rate limiter is kept with its new buckets, so that a
+    /// pending timer still wakes the thread up, otherwise the new one is
+    /// registered with the epoll helper under `event`.
+    pub fn receive(
+        &self,
+        rate_limiter: &mut Option<RateLimiter>,
+        helper: &mut EpollHelper,
+        event: u16,
+    ) -> Result<(), EpollHelperError> {
+        self.evt.read().map_err(EpollHelperError::IoError)?;
+
+        let new_rate_limiter = match self.rate_limiter.lock().unwrap().take() {
+            Some(new_rate_limiter) => new_rate_limiter,
+            None => return Ok(()),
+        };
+
+        match rate_limiter {
+            Some(rate_limiter) => rate_limiter.update_buckets(
+                bucket_update(new_rate_limiter.bandwidth()),
+                bucket_update(new_rate_limiter.ops()),
+            ),
+            None => {
+                helper.add_event(new_rate_limiter.as_raw_fd(), event)?;
+                *rate_limiter = Some(new_rate_limiter);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TokenBucketConfig, EPOLL_HELPER_EVENT_LAST};
+
+    const RATE_LIMITER_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
+
+    fn bucket_config(size: u64) -> Option<TokenBucketConfig> {
+        Some(TokenBucketConfig {
+            size,
+            one_time_burst: None,
+            refill_time: 1000,
+        })
+    }
+
+    #[test]
+    fn test_rate_limiter_update() {
+        let kill_evt = EventFd::new(EFD_NONBLOCK).unwrap();
+        let pause_evt = EventFd::new(EFD_NONBLOCK).unwrap();
+        let mut helper = EpollHelper::new(&kill_evt, &pause_evt).unwrap();
+
+        let update = RateLimiterUpdate::new().unwrap();
+        let worker_update = update.try_clone().unwrap();
+        let mut rate_limiter = None;
+
+        // Without a rate limiter, the worker thread takes the new one.
+        update
+            .send(Some(RateLimiterConfig {
+                bandwidth: bucket_config(0x1000),
+                ops: None,
+            }))
+            .unwrap();
+        worker_update
+            .receive(&mut rate_limiter, &mut helper, RATE_LIMITER_EVENT)
+            .unwrap();
+        let fd = rate_limiter.as_ref().unwrap().as_raw_fd();
+        assert_eq!(
+            rate_limiter
+                .as_ref()
+                .unwrap()
+                .bandwidth()
+                .unwrap()
+                .capacity(),
+            0x1000
+        );
+        assert!(rate_limiter.as_ref().unwrap().ops().is_none());
+
+        // Otherwise its buckets are updated in place.
+        update
+            .send(Some(RateLimiterConfig {
+                bandwidth: None,
+                ops: bucket_config(100),
+            }))
+            .unwrap();
+        worker_update
+            .receive(&mut rate_limiter, &mut helper, RATE_LIMITER_EVENT)
+            .unwrap();
+        assert_eq!(rate_limiter.as_ref().unwrap().as_raw_fd(), fd);
+        assert!(rate_limiter.as_ref().unwrap().bandwidth().is_none());
+        assert_eq!(
+            rate_limiter.as_ref().unwrap().ops().unwrap().capacity(),
+            100
+        );
+
+        // No configuration disables the rate limiting.
+        update.send(None).unwrap();
+        worker_update
+            .receive(&mut rate_limiter, &mut helper, RATE_LIMITER_EVENT)
+            .unwrap();
+        assert!(rate_limiter.as_ref().unwrap().bandwidth().is_none());
+        assert!(rate_limiter.as_ref().unwrap().ops().is_none());
+
+        // Only the last rate limiter sent is applied.
+        update
+            .send(Some(RateLimiterConfig {
+                bandwidth: bucket_config(0x2000),
+                ops: None,
+            }))
+            .unwrap();
+        update
+            .send(Some(RateLimiterConfig {
+                bandwidth: bucket_config(0x3000),
+                ops: None,
+            }))
+            .unwrap();
+        worker_update
+            .receive(&mut rate_limiter, &mut helper, RATE_LIMITER_EVENT)
+            .unwrap();
+        assert_eq!(
+            rate_limiter
+                .as_ref()
+                .unwrap()
+                .bandwidth()
+                .unwrap()
+                .capacity(),
+            0x3000
+        );
+    }
+}
@@ -13,19 +13,25 @@ use crate::thread_helper::spawn_virtio_thread;
 use crate::GuestMemoryMmap;
 use crate::{VirtioInterrupt, VirtioInterruptType};
 use seccompiler::SeccompAction;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io;
+use std::io::{self, Read};
+use std::num::Wrapping;
 use std::os::unix::io::AsRawFd;
 use std::result;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Barrier};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use virtio_queue::Queue;
 use vm_memory::{Bytes, GuestMemoryAtomic};
 use vm_migration::VersionMapped;
-use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
-use vm_virtio::{AccessPlatform, Translatable};
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, SnapshotDataSection, Snapshottable,
+    Transportable,
+};
+use vm_virtio::chain::{ChainLimits, ChainValidator};
+use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
 
 const QUEUE_SIZE: u16 = 256;
@@ -34,14 +40,72 @@ const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE];
 // New descriptors are pending on the virtio queue.
 const QUEUE_AVAIL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 
+// Pseudo random generator (SplitMix64) used instead of the host entropy
+// source when running deterministically, so that the guest reads the same
+// sequence of bytes from one run to the next. Its state is shared with the
+// device, which saves it along with the device state and restores it.
+#[derive(Clone)]
+struct SeededRandom {
+    state: Arc<AtomicU64>,
+}
+
+impl SeededRandom {
+    fn new(seed: u64) -> Self {
+        SeededRandom {
+            state: Arc::new(AtomicU64::new(seed)),
+        }
+    }
+
+    fn state(&self) -> u64 {
+        self.state.load(Ordering::Acquire)
+    }
+
+    fn set_state(&self, state: u64) {
+        self.state.store(state, Ordering::Release)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let state = self.state().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.set_state(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl Read for SeededRandom {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+}
+
+enum RandomSource {
+    File(File),
+    Seeded(SeededRandom),
+}
+
+impl Read for RandomSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            RandomSource::File(file) => file.read(buf),
+            RandomSource::Seeded(random) => random.read(buf),
+        }
+    }
+}
+
 struct RngEpollHandler {
     queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
-    random_file: File,
+    random_source: RandomSource,
     interrupt_cb: Arc<dyn VirtioInterrupt>,
     queue_evt: EventFd,
     kill_evt: EventFd,
     pause_evt: EventFd,
-    access_platform: Option<Arc<dyn AccessPlatform>>,
+    chain_validator: ChainValidator,
 }
 
 impl RngEpollHandler {
@@ -51,23 +115,21 @@ impl RngEpollHandler {
         let mut used_desc_heads = [(0, 0); QUEUE_SIZE as usize];
         let mut used_count = 0;
         for mut desc_chain in queue.iter().unwrap() {
-            let desc = desc_chain.next().unwrap();
             let mut len = 0;
 
-            // Drivers can only read from the random device.
-            if desc.is_write_only() {
-                // Fill the read with data from the random device on the host.
-                if desc_chain
-                    .memory()
-                    .read_from(
-                        desc.addr()
-                            .translate_gva(self.access_platform.as_ref(), desc.len() as usize),
-                        &mut self.random_file,
-                        desc.len() as usize,
-                    )
-                    .is_ok()
-                {
-                    len = desc.len();
+            if let Ok(valid_chain) = self.chain_validator.validate(&mut desc_chain) {
+                let desc = valid_chain.descriptors()[0];
+
+                // Drivers can only read from the random device.
+                if desc.is_write_only() {
+                    // Fill the read with data from the random device on the host.
+                    if desc_chain
+                        .memory()
+                        .read_from(desc.addr(), &mut self.random_source, desc.len() as usize)
+                        .is_ok()
+                    {
+                        len = desc.len();
+                    }
                 }
             }
 
@@ -132,6 +194,8 @@ pub struct Rng {
     common: VirtioCommon,
     id: String,
     random_file: Option<File>,
+    seed: Option<u64>,
+    seeded_random: Option<SeededRandom>,
     seccomp_action: SeccompAction,
     exit_evt: EventFd,
 }
@@ -146,10 +210,13 @@ impl VersionMapped for RngState {}
 
 impl Rng {
     /// Create a new virtio rng device that gets random data from /dev/urandom.
+    /// When a seed is given, the data is generated from it instead, the same
+    /// sequence being provided again after each reset of the device.
     pub fn new(
         id: String,
         path: &str,
         iommu: bool,
+        seed: Option<u64>,
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
     ) -> io::Result<Rng> {
@@ -171,6 +238,8 @@ impl Rng {
             },
             id,
             random_file: Some(random_file),
+            seed,
+            seeded_random: seed.map(SeededRandom::new),
             seccomp_action,
             exit_evt,
         })
@@ -187,6 +256,10 @@ impl Rng {
         self.common.avail_features = state.avail_features;
         self.common.acked_features = state.acked_features;
     }
+
+    fn seeded_random_id(&self) -> String {
+        format!("{}-seeded-random", self.id)
+    }
 }
 
 impl Drop for Rng {
@@ -226,18 +299,21 @@ impl VirtioDevice for Rng {
         let (kill_evt, pause_evt) = self.common.dup_eventfds();
 
         if let Some(file) = self.random_file.as_ref() {
-            let random_file = file.try_clone().map_err(|e| {
-                error!("failed cloning rng source: {}", e);
-                ActivateError::BadActivate
-            })?;
+            let random_source = match self.seeded_random.as_ref() {
+                Some(random) => RandomSource::Seeded(random.clone()),
+                None => RandomSource::File(file.try_clone().map_err(|e| {
+                    error!("failed cloning rng source: {}", e);
+                    ActivateError::BadActivate
+                })?),
+            };
             let mut handler = RngEpollHandler {
                 queues,
-                random_file,
+                random_source,
                 interrupt_cb,
                 queue_evt: queue_evts.remove(0),
                 kill_evt,
                 pause_evt,
-                access_platform: self.common.access_platform.clone(),
+                chain_validator: self.common.chain_validator(ChainLimits::default()),
             };
 
             let paused = self.common.paused.clone();
@@ -249,6 +325,7 @@ impl VirtioDevice for Rng {
                 Thread::VirtioRng,
                 &mut epoll_threads,
                 &self.exit_evt,
+                self.common.thread_nice,
                 move || {
                     if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                         error!("Error running worker: {:?}", e);
@@ -264,12 +341,23 @@ impl VirtioDevice for Rng {
         Err(ActivateError::BadActivate)
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
+        if let (Some(random), Some(seed)) = (self.seeded_random.as_ref(), self.seed) {
+            random.set_state(seed);
+        }
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
 
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
+
     fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
         self.common.set_access_platform(access_platform)
     }
@@ -291,14 +379,69 @@ impl Snapshottable for Rng {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
-        Snapshot::new_from_versioned_state(&self.id, &self.state())
+        let mut snapshot = Snapshot::new_from_versioned_state(&self.id, &self.state())?;
+
+        // The position in the pseudo random sequence, for the restored
+        // device to carry on with it.
+        if let Some(random) = self.seeded_random.as_ref() {
+            snapshot.add_data_section(SnapshotDataSection::new_from_state(
+                &self.seeded_random_id(),
+                &random.state(),
+            )?);
+        }
+
+        Ok(snapshot)
     }
 
     fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         self.set_state(&snapshot.to_versioned_state(&self.id)?);
+
+        if let Some(random) = self.seeded_random.as_ref() {
+            let id = self.seeded_random_id();
+            if snapshot
+                .snapshot_data
+                .contains_key(&format!("{}-section", id))
+            {
+                random.set_state(snapshot.to_state(&id)?);
+            }
+        }
+
         Ok(())
     }
 }
 
 impl Transportable for Rng {}
 impl Migratable for Rng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_random() {
+        let mut first = [0u8; 20];
+        let mut second = [0u8; 20];
+        SeededRandom::new(42).read_exact(&mut first).unwrap();
+        SeededRandom::new(42).read_exact(&mut second).unwrap();
+        assert_eq!(first, second);
+
+        SeededRandom::new(43).read_exact(&mut second).unwrap();
+        assert_ne!(first, second);
+
+        // Reading in several steps doesn't change the first bytes.
+        let mut random = SeededRandom::new(42);
+        let mut head = [0u8; 8];
+        random.read_exact(&mut head).unwrap();
+        assert_eq!(head, first[..8]);
+
+        // A copy sharing the state carries on with the sequence, which
+        // starts over from where its state is set back to.
+        let state = random.state();
+        let mut tail = [0u8; 12];
+        random.clone().read_exact(&mut tail).unwrap();
+        assert_eq!(tail, first[8..]);
+        random.set_state(state);
+        random.read_exact(&mut tail).unwrap();
+        assert_eq!(tail, first[8..]);
+    }
+}
@@ -8,17 +8,21 @@ use seccompiler::{
     BpfProgram, Error, SeccompAction, SeccompCmpArgLen as ArgLen, SeccompCmpOp::Eq,
     SeccompCondition as Cond, SeccompFilter, SeccompRule,
 };
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::sync::Mutex;
 
 pub enum Thread {
     VirtioBalloon,
     VirtioBlock,
     VirtioConsole,
+    VirtioGpu,
     VirtioIommu,
     VirtioMem,
     VirtioNet,
     VirtioNetCtl,
     VirtioPmem,
+    VirtioPtp,
     VirtioRng,
     VirtioVhostBlock,
     VirtioVhostFs,
@@ -28,6 +32,75 @@ pub enum Thread {
     VirtioWatchdog,
 }
 
+impl Thread {
+    pub const ALL: [Thread; 17] = [
+        Thread::VirtioBalloon,
+        Thread::VirtioBlock,
+        Thread::VirtioConsole,
+        Thread::VirtioGpu,
+        Thread::VirtioIommu,
+        Thread::VirtioMem,
+        Thread::VirtioNet,
+        Thread::VirtioNetCtl,
+        Thread::VirtioPmem,
+        Thread::VirtioPtp,
+        Thread::VirtioRng,
+        Thread::VirtioVhostBlock,
+        Thread::VirtioVhostFs,
+        Thread::VirtioVhostNet,
+        Thread::VirtioVhostNetCtl,
+        Thread::VirtioVsock,
+        Thread::VirtioWatchdog,
+    ];
+
+    /// Name of the thread type, as used by the seccomp policy file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Thread::VirtioBalloon => "virtio-balloon",
+            Thread::VirtioBlock => "virtio-block",
+            Thread::VirtioConsole => "virtio-console",
+            Thread::VirtioGpu => "virtio-gpu",
+            Thread::VirtioIommu => "virtio-iommu",
+            Thread::VirtioMem => "virtio-mem",
+            Thread::VirtioNet => "virtio-net",
+            Thread::VirtioNetCtl => "virtio-net-ctl",
+            Thread::VirtioPmem => "virtio-pmem",
+            Thread::VirtioPtp => "virtio-ptp",
+            Thread::VirtioRng => "virtio-rng",
+            Thread::VirtioVhostBlock => "virtio-vhost-block",
+            Thread::VirtioVhostFs => "virtio-vhost-fs",
+            Thread::VirtioVhostNet => "virtio-vhost-net",
+            Thread::VirtioVhostNetCtl => "virtio-vhost-net-ctl",
+            Thread::VirtioVsock => "virtio-vsock",
+            Thread::VirtioWatchdog => "virtio-watchdog",
+        }
+    }
+}
+
+lazy_static! {
+    // Syscalls allowed on top of the built-in rules, indexed by the name of
+    // the thread type. Filled from the seccomp policy of the VM.
+    static ref EXTRA_SYSCALLS: Mutex<HashMap<String, Vec<i64>>> = Mutex::new(HashMap::new());
+}
+
+/// Replaces the syscalls allowed on top of the built-in rules of each thread
+/// type, indexed by the thread names. Only the filters generated afterwards
+/// are affected.
+pub fn set_extra_syscalls(extra_syscalls: HashMap<String, Vec<i64>>) {
+    *EXTRA_SYSCALLS.lock().unwrap() = extra_syscalls;
+}
+
+/// Returns the syscalls allowed on top of the built-in rules of the thread
+/// type. They are allowed whatever their arguments.
+pub fn extra_syscalls(thread_name: &str) -> Vec<(i64, Vec<SeccompRule>)> {
+    EXTRA_SYSCALLS
+        .lock()
+        .unwrap()
+        .get(thread_name)
+        .map(|syscalls| syscalls.iter().map(|nr| (*nr, vec![])).collect())
+        .unwrap_or_default()
+}
+
 /// Shorthand for chaining `SeccompCondition`s with the `and` operator  in a `SeccompRule`.
 /// The rule will take the `Allow` action if _all_ the conditions are true.
 ///
@@ -57,6 +130,9 @@ const VFIO_IOMMU_UNMAP_DMA: u64 = 0x3b72;
 // See include/uapi/linux/if_tun.h in the kernel code.
 const TUNSETOFFLOAD: u64 = 0x4004_54d0;
 
+// See include/uapi/linux/udmabuf.h in the kernel code.
+const UDMABUF_CREATE: u64 = 0x4018_7542;
+
 fn create_virtio_console_ioctl_seccomp_rule() -> Vec<SeccompRule> {
     or![and![Cond::new(1, ArgLen::Dword, Eq, TIOCGWINSZ).unwrap()]]
 }
@@ -110,6 +186,28 @@ fn virtio_console_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     ]
 }
 
+fn create_virtio_gpu_ioctl_seccomp_rule() -> Vec<SeccompRule> {
+    or![
+        and![Cond::new(1, ArgLen::Dword, Eq, UDMABUF_CREATE).unwrap()],
+        and![Cond::new(1, ArgLen::Dword, Eq, FIONBIO).unwrap()],
+    ]
+}
+
+fn virtio_gpu_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![
+        (libc::SYS_accept4, vec![]),
+        (libc::SYS_fcntl, vec![]),
+        (libc::SYS_ftruncate, vec![]),
+        (libc::SYS_ioctl, create_virtio_gpu_ioctl_seccomp_rule()),
+        (libc::SYS_memfd_create, vec![]),
+        (libc::SYS_mprotect, vec![]),
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_sendmsg, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+    ]
+}
+
 fn virtio_iommu_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
         (libc::SYS_ioctl, create_virtio_iommu_ioctl_seccomp_rule()),
@@ -146,6 +244,15 @@ fn virtio_pmem_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![(libc::SYS_fsync, vec![])]
 }
 
+fn virtio_ptp_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
+    vec![
+        (libc::SYS_mprotect, vec![]),
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+    ]
+}
+
 fn virtio_rng_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
         (libc::SYS_mprotect, vec![]),
@@ -218,15 +325,18 @@ fn virtio_watchdog_thread_rules() -> Vec<(i64, Vec<SeccompRule>)> {
 }
 
 fn get_seccomp_rules(thread_type: Thread) -> Vec<(i64, Vec<SeccompRule>)> {
+    let thread_name = thread_type.name();
     let mut rules = match thread_type {
         Thread::VirtioBalloon => virtio_balloon_thread_rules(),
         Thread::VirtioBlock => virtio_block_thread_rules(),
         Thread::VirtioConsole => virtio_console_thread_rules(),
+        Thread::VirtioGpu => virtio_gpu_thread_rules(),
         Thread::VirtioIommu => virtio_iommu_thread_rules(),
         Thread::VirtioMem => virtio_mem_thread_rules(),
         Thread::VirtioNet => virtio_net_thread_rules(),
         Thread::VirtioNetCtl => virtio_net_ctl_thread_rules(),
         Thread::VirtioPmem => virtio_pmem_thread_rules(),
+        Thread::VirtioPtp => virtio_ptp_thread_rules(),
         Thread::VirtioRng => virtio_rng_thread_rules(),
         Thread::VirtioVhostBlock => virtio_vhost_block_thread_rules(),
         Thread::VirtioVhostFs => virtio_vhost_fs_thread_rules(),
@@ -236,6 +346,9 @@ fn get_seccomp_rules(thread_type: Thread) -> Vec<(i64, Vec<SeccompRule>)> {
         Thread::VirtioWatchdog => virtio_watchdog_thread_rules(),
     };
     rules.append(&mut virtio_thread_common());
+    // Appended last so that they replace the conditional rules of the same
+    // syscalls when collected into the filter.
+    rules.append(&mut extra_syscalls(thread_name));
     rules
 }
 
@@ -243,6 +356,9 @@ fn virtio_thread_common() -> Vec<(i64, Vec<SeccompRule>)> {
     vec![
         (libc::SYS_brk, vec![]),
         (libc::SYS_clock_gettime, vec![]),
+        // Interrupt delay injected through the fault injection API
+        #[cfg(feature = "fault_injection")]
+        (libc::SYS_clock_nanosleep, vec![]),
         (libc::SYS_close, vec![]),
         (libc::SYS_dup, vec![]),
         (libc::SYS_epoll_create1, vec![]),
@@ -255,6 +371,8 @@ fn virtio_thread_common() -> Vec<(i64, Vec<SeccompRule>)> {
         (libc::SYS_madvise, vec![]),
         (libc::SYS_mmap, vec![]),
         (libc::SYS_munmap, vec![]),
+        #[cfg(feature = "fault_injection")]
+        (libc::SYS_nanosleep, vec![]),
         (libc::SYS_openat, vec![]),
         (libc::SYS_read, vec![]),
         (libc::SYS_rt_sigprocmask, vec![]),
@@ -9,6 +9,7 @@ use crate::{
 };
 use seccompiler::{apply_filter, SeccompAction};
 use std::{
+    io,
     panic::AssertUnwindSafe,
     thread::{self, JoinHandle},
 };
@@ -20,6 +21,7 @@ pub(crate) fn spawn_virtio_thread<F>(
     thread_type: Thread,
     epoll_threads: &mut Vec<JoinHandle<()>>,
     exit_evt: &EventFd,
+    nice: Option<i32>,
     f: F,
 ) -> Result<(), ActivateError>
 where
@@ -37,6 +39,23 @@ where
     thread::Builder::new()
         .name(name.to_string())
         .spawn(move || {
+            if let Some(nice) = nice {
+                // SAFETY: FFI call with valid arguments, the thread ID being
+                // the one of the calling thread.
+                let ret = unsafe {
+                    let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+                    libc::setpriority(libc::PRIO_PROCESS, tid, nice)
+                };
+                if ret != 0 {
+                    error!(
+                        "Error setting the nice value of {} thread: {}",
+                        thread_name,
+                        io::Error::last_os_error()
+                    );
+                    thread_exit_evt.write(1).ok();
+                    return;
+                }
+            }
             if !seccomp_filter.is_empty() {
                 if let Err(e) = apply_filter(&seccomp_filter) {
                     error!("Error applying seccomp filter: {:?}", e);
@@ -0,0 +1,1035 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+//
+// Portions Copyright 2017 The Chromium OS Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE-BSD-3-Clause file.
+//
+// Copyright © 2022 Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
+
+use super::pci_device::QueueState;
+#[cfg(feature = "fault_injection")]
+use crate::transport::FaultyInterrupt;
+use crate::transport::{
+    queue_depths, set_queues_max_size, FeaturesOverride, QueueInfo, VirtioDeviceActivator,
+};
+use crate::GuestMemoryMmap;
+use crate::{
+    ActivateError, ActivateResult, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    DEVICE_ACKNOWLEDGE, DEVICE_DRIVER, DEVICE_DRIVER_OK, DEVICE_FAILED, DEVICE_FEATURES_OK,
+    DEVICE_INIT,
+};
+use anyhow::anyhow;
+use byteorder::{ByteOrder, LittleEndian};
+use libc::EFD_NONBLOCK;
+use std::result;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use versionize::{VersionMap, Versionize, VersionizeResult};
+use versionize_derive::Versionize;
+use virtio_queue::{Error as QueueError, Queue};
+use vm_device::interrupt::InterruptSourceGroup;
+use vm_device::BusDevice;
+use vm_memory::{GuestAddress, GuestMemoryAtomic};
+use vm_migration::{
+    Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionMapped,
+};
+#[cfg(feature = "fault_injection")]
+use vm_virtio::fault_injection::Faults;
+use vmm_sys_util::{errno::Result, eventfd::EventFd};
+
+// Registers of the virtio-mmio transport (version 2), as defined by the
+// virtio specification, section 4.2.2.
+const MAGIC_VALUE_REG: u64 = 0x000;
+const VERSION_REG: u64 = 0x004;
+const DEVICE_ID_REG: u64 = 0x008;
+const VENDOR_ID_REG: u64 = 0x00c;
+const DEVICE_FEATURES_REG: u64 = 0x010;
+const DEVICE_FEATURES_SEL_REG: u64 = 0x014;
+const DRIVER_FEATURES_REG: u64 = 0x020;
+const DRIVER_FEATURES_SEL_REG: u64 = 0x024;
+const QUEUE_SEL_REG: u64 = 0x030;
+const QUEUE_NUM_MAX_REG: u64 = 0x034;
+const QUEUE_NUM_REG: u64 = 0x038;
+const QUEUE_READY_REG: u64 = 0x044;
+const QUEUE_NOTIFY_REG: u64 = 0x050;
+const INTERRUPT_STATUS_REG: u64 = 0x060;
+const INTERRUPT_ACK_REG: u64 = 0x064;
+const STATUS_REG: u64 = 0x070;
+const QUEUE_DESC_LOW_REG: u64 = 0x080;
+const QUEUE_DESC_HIGH_REG: u64 = 0x084;
+const QUEUE_DRIVER_LOW_REG: u64 = 0x090;
+const QUEUE_DRIVER_HIGH_REG: u64 = 0x094;
+const QUEUE_DEVICE_LOW_REG: u64 = 0x0a0;
+const QUEUE_DEVICE_HIGH_REG: u64 = 0x0a4;
+const SHM_SEL_REG: u64 = 0x0ac;
+const SHM_LEN_LOW_REG: u64 = 0x0b0;
+const SHM_LEN_HIGH_REG: u64 = 0x0b4;
+const SHM_BASE_LOW_REG: u64 = 0x0b8;
+const SHM_BASE_HIGH_REG: u64 = 0x0bc;
+const CONFIG_GENERATION_REG: u64 = 0x0fc;
+const DEVICE_CONFIG_OFFSET: u64 = 0x100;
+
+// "virt" in little endian.
+pub const MMIO_MAGIC_VALUE: u32 = 0x7472_6976;
+const MMIO_VERSION: u32 = 2;
+const VENDOR_ID: u32 = 0;
+
+// Bits of the interrupt status register.
+const VIRTIO_MMIO_INT_VRING: usize = 0x01;
+const VIRTIO_MMIO_INT_CONFIG: usize = 0x02;
+
+#[derive(Debug)]
+enum Error {
+    /// Failed to retrieve queue ring's index.
+    QueueRingIndex(QueueError),
+    /// Number of queues in the saved state doesn't match the device.
+    QueueCountMismatch(usize, usize),
+    /// Failed to clone a queue EventFd.
+    QueueEventFdClone(std::io::Error),
+}
+
+#[derive(Versionize)]
+struct VirtioMmioDeviceState {
+    device_activated: bool,
+    queues: Vec<QueueState>,
+    interrupt_status: usize,
+    driver_status: u8,
+    queue_select: u32,
+    device_features_select: u32,
+    driver_features_select: u32,
+}
+
+impl VersionMapped for VirtioMmioDeviceState {}
+
+/// Interrupt of a virtio-mmio device, delivered through a legacy interrupt
+/// line shared by the queues and the configuration change notifications.
+struct VirtioInterruptIntx {
+    interrupt_status: Arc<AtomicUsize>,
+    interrupt_source_group: Arc<dyn InterruptSourceGroup>,
+}
+
+impl VirtioInterrupt for VirtioInterruptIntx {
+    fn trigger(&self, int_type: VirtioInterruptType) -> std::result::Result<(), std::io::Error> {
+        let status = match int_type {
+            VirtioInterruptType::Config => VIRTIO_MMIO_INT_CONFIG,
+            VirtioInterruptType::Queue(_) => VIRTIO_MMIO_INT_VRING,
+        };
+        self.interrupt_status.fetch_or(status, Ordering::AcqRel);
+
+        self.interrupt_source_group.trigger(0)
+    }
+}
+
+/// Implements the virtio-mmio transport, for guests without PCI support.
+///
+/// The device is exposed through a single MMIO region, starting with the
+/// transport registers and followed by the configuration space of the
+/// device. Queue notifications are written to the QueueNotify register,
+/// either delivered through ioeventfds matching the index of the queue, or
+/// trapped and forwarded to the queue EventFd by the transport.
+pub struct VirtioMmioDevice {
+    id: String,
+
+    // Virtio device reference and status
+    device: Arc<Mutex<dyn VirtioDevice>>,
+    device_activated: Arc<AtomicBool>,
+
+    // Transport registers
+    driver_status: u8,
+    queue_select: u32,
+    device_features_select: u32,
+    driver_features_select: u32,
+    // Not saved, as it comes from the configuration of the VM.
+    features_override: FeaturesOverride,
+    // Not saved, as the drivers select a shared memory region right before
+    // reading its registers.
+    shm_select: u32,
+
+    // Interrupt
+    interrupt_status: Arc<AtomicUsize>,
+    virtio_interrupt: Option<Arc<dyn VirtioInterrupt>>,
+
+    // virtio queues
+    queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
+    queue_evts: Vec<EventFd>,
+
+    // Guest memory
+    memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+
+    // EventFd to signal on to request activation
+    activate_evt: EventFd,
+
+    // Pending activations
+    pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
+
+    // Whether queue notifications are delivered through ioeventfds, or
+    // trapped and forwarded to the queue EventFd by the transport.
+    use_ioeventfd: bool,
+}
+
+impl VirtioMmioDevice {
+    /// Constructs a new MMIO transport for the given virtio device.
+    pub fn new(
+        id: String,
+        memory: GuestMemoryAtomic<GuestMemoryMmap>,
+        device: Arc<Mutex<dyn VirtioDevice>>,
+        interrupt_source_group: Arc<dyn InterruptSourceGroup>,
+        activate_evt: EventFd,
+        pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
+        use_ioeventfd: bool,
+    ) -> Result<Self> {
+        let locked_device = device.lock().unwrap();
+        let mut queue_evts = Vec::new();
+        for _ in locked_device.queue_max_sizes().iter() {
+            queue_evts.push(EventFd::new(EFD_NONBLOCK)?)
+        }
+
+        let queues = locked_device
+            .queue_max_sizes()
+            .iter()
+            .map(|&s| {
+                Queue::<GuestMemoryAtomic<GuestMemoryMmap>, virtio_queue::QueueState>::new(
+                    memory.clone(),
+                    s,
+                )
+            })
+            .collect();
+        drop(locked_device);
+
+        let interrupt_status = Arc::new(AtomicUsize::new(0));
+        let virtio_interrupt: Arc<dyn VirtioInterrupt> = Arc::new(VirtioInterruptIntx {
+            interrupt_status: interrupt_status.clone(),
+            interrupt_source_group,
+        });
+
+        Ok(VirtioMmioDevice {
+            id,
+            device,
+            device_activated: Arc::new(AtomicBool::new(false)),
+            driver_status: DEVICE_INIT as u8,
+            queue_select: 0,
+            device_features_select: 0,
+            driver_features_select: 0,
+            features_override: FeaturesOverride::default(),
+            shm_select: 0,
+            interrupt_status,
+            virtio_interrupt: Some(virtio_interrupt),
+            queues,
+            queue_evts,
+            memory: Some(memory),
+            activate_evt,
+            pending_activations,
+            use_ioeventfd,
+        })
+    }
+
+    fn state(&self) -> VirtioMmioDeviceState {
+        VirtioMmioDeviceState {
+            device_activated: self.device_activated.load(Ordering::Acquire),
+            interrupt_status: self.interrupt_status.load(Ordering::Acquire),
+            queues: self
+                .queues
+                .iter()
+                .map(|q| QueueState {
+                    max_size: q.max_size(),
+                    size: q.state.size,
+                    ready: q.state.ready,
+                    desc_table: q.state.desc_table.0,
+                    avail_ring: q.state.avail_ring.0,
+                    used_ring: q.state.used_ring.0,
+                })
+                .collect(),
+            driver_status: self.driver_status,
+            queue_select: self.queue_select,
+            device_features_select: self.device_features_select,
+            driver_features_select: self.driver_features_select,
+        }
+    }
+
+    fn set_state(&mut self, state: &VirtioMmioDeviceState) -> std::result::Result<(), Error> {
+        self.device_activated
+            .store(state.device_activated, Ordering::Release);
+        self.interrupt_status
+            .store(state.interrupt_status, Ordering::Release);
+        self.driver_status = state.driver_status;
+        self.queue_select = state.queue_select;
+        self.device_features_select = state.device_features_select;
+        self.driver_features_select = state.driver_features_select;
+
+        if state.queues.len() != self.queues.len() {
+            return Err(Error::QueueCountMismatch(
+                state.queues.len(),
+                self.queues.len(),
+            ));
+        }
+
+        // Update virtqueues indexes for both available and used rings.
+        for (queue, queue_state) in self.queues.iter_mut().zip(state.queues.iter()) {
+            queue.state.size = queue_state.size;
+            queue.state.ready = queue_state.ready;
+            queue.state.desc_table = GuestAddress(queue_state.desc_table);
+            queue.state.avail_ring = GuestAddress(queue_state.avail_ring);
+            queue.state.used_ring = GuestAddress(queue_state.used_ring);
+            queue.set_next_avail(
+                queue
+                    .used_idx(Ordering::Acquire)
+                    .map_err(Error::QueueRingIndex)?
+                    .0,
+            );
+            queue.set_next_used(
+                queue
+                    .used_idx(Ordering::Acquire)
+                    .map_err(Error::QueueRingIndex)?
+                    .0,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the queue EventFds to register as ioeventfds, along with the
+    /// address and the value written by the driver to notify each queue.
+    pub fn ioeventfds(&self, base_addr: u64) -> Vec<(&EventFd, u64, u32)> {
+        if !self.use_ioeventfd {
+            return Vec::new();
+        }
+
+        self.queue_evts
+            .iter()
+            .enumerate()
+            .map(|(i, event)| (event, base_addr + QUEUE_NOTIFY_REG, i as u32))
+            .collect()
+    }
+
+    pub fn virtio_device(&self) -> Arc<Mutex<dyn VirtioDevice>> {
+        self.device.clone()
+    }
+
+    pub fn is_activated(&self) -> bool {
+        self.device_activated.load(Ordering::SeqCst)
+    }
+
+    pub fn set_features_override(&mut self, features_override: FeaturesOverride) {
+        self.features_override = features_override;
+    }
+
+    pub fn queues_info(&self) -> Vec<QueueInfo> {
+        self.queues.iter().map(QueueInfo::from).collect()
+    }
+
+    /// Returns the number of descriptor chains made available by the driver
+    /// and not used by the device yet, for each queue of the device.
+    pub fn queue_depths(&self) -> Vec<u16> {
+        queue_depths(&self.queues)
+    }
+
+    /// Changes the maximum size of the queues of the device, which the driver
+    /// reads from QueueNumMax. It is refused once the driver started
+    /// initializing the device.
+    pub fn set_queue_max_size(&mut self, max_size: u16) -> result::Result<(), crate::Error> {
+        if self.is_activated() || self.driver_status != DEVICE_INIT as u8 {
+            return Err(crate::Error::DriverInitialized);
+        }
+
+        self.device.lock().unwrap().set_queue_max_size(max_size)?;
+        set_queues_max_size(&mut self.queues, max_size);
+        Ok(())
+    }
+
+    fn is_driver_ready(&self) -> bool {
+        let ready_bits =
+            (DEVICE_ACKNOWLEDGE | DEVICE_DRIVER | DEVICE_DRIVER_OK | DEVICE_FEATURES_OK) as u8;
+        self.driver_status == ready_bits && self.driver_status & DEVICE_FAILED as u8 == 0
+    }
+
+    fn needs_activation(&self) -> bool {
+        !self.device_activated.load(Ordering::SeqCst) && self.is_driver_ready()
+    }
+
+    fn with_queue<U, F>(&self, d: U, f: F) -> U
+    where
+        F: FnOnce(&Queue<GuestMemoryAtomic<GuestMemoryMmap>>) -> U,
+    {
+        match self.queues.get(self.queue_select as usize) {
+            Some(queue) => f(queue),
+            None => d,
+        }
+    }
+
+    fn with_queue_mut<F: FnOnce(&mut Queue<GuestMemoryAtomic<GuestMemoryMmap>>)>(&mut self, f: F) {
+        match self.queues.get_mut(self.queue_select as usize) {
+            Some(queue) => f(queue),
+            None => warn!("{}: Invalid queue selected: {}", self.id, self.queue_select),
+        }
+    }
+
+    /// Applies the interrupt delay from `faults` to the interrupts sent to
+    /// the guest. Must be called before the device is activated.
+    #[cfg(feature = "fault_injection")]
+    pub fn set_faults(&mut self, faults: Arc<Faults>) {
+        self.virtio_interrupt = self
+            .virtio_interrupt
+            .take()
+            .map(|interrupt| FaultyInterrupt::wrap(interrupt, faults));
+    }
+
+    fn prepare_activator(
+        &mut self,
+        barrier: Option<Arc<Barrier>>,
+    ) -> std::result::Result<VirtioDeviceActivator, Error> {
+        let mut queues = Vec::new();
+        let mut queue_evts = Vec::new();
+        for (i, (queue, queue_evt)) in self.queues.iter().zip(self.queue_evts.iter()).enumerate() {
+            if !queue.state.ready {
+                continue;
+            }
+            if !queue.is_valid() {
+                error!("Queue {} is not valid", i);
+            }
+            queues.push(vm_virtio::clone_queue(queue));
+            queue_evts.push(queue_evt.try_clone().map_err(Error::QueueEventFdClone)?);
+        }
+
+        Ok(VirtioDeviceActivator {
+            interrupt: self.virtio_interrupt.take(),
+            memory: self.memory.clone(),
+            device: self.device.clone(),
+            queues: Some(queues),
+            device_activated: self.device_activated.clone(),
+            queue_evts: Some(queue_evts),
+            barrier,
+            id: self.id.clone(),
+        })
+    }
+
+    fn activate(&mut self) -> ActivateResult {
+        self.prepare_activator(None)
+            .map_err(|e| {
+                error!("{}: Failed preparing activation: {:?}", self.id, e);
+                ActivateError::BadActivate
+            })?
+            .activate()
+    }
+
+    fn set_device_failed(&mut self, reason: &str) {
+        error!("{}: Device failed: {}", self.id, reason);
+        self.driver_status |= DEVICE_FAILED as u8;
+        event!("virtio-device", "failed", "id", &self.id, "reason", reason);
+    }
+
+    // Resets the underlying device, along with the queues. Returns false if
+    // the device doesn't support being reset.
+    fn reset_device(&mut self) -> bool {
+        let virtio_interrupt = match self.device.lock().unwrap().reset() {
+            Some(virtio_interrupt) => virtio_interrupt,
+            None => return false,
+        };
+
+        // Upon reset the device returns its interrupt EventFD
+        self.virtio_interrupt = Some(virtio_interrupt);
+        self.device_activated.store(false, Ordering::SeqCst);
+
+        self.queues.iter_mut().for_each(Queue::reset);
+        self.queue_select = 0;
+        self.interrupt_status.store(0, Ordering::Release);
+
+        true
+    }
+
+    /// Brings the transport back to its initial state, as done on a VM
+    /// reboot. Returns false if the device couldn't be reset.
+    pub fn reset(&mut self) -> bool {
+        if self.device_activated.load(Ordering::SeqCst) && !self.reset_device() {
+            return false;
+        }
+
+        self.queues.iter_mut().for_each(Queue::reset);
+        self.driver_status = DEVICE_INIT as u8;
+        self.queue_select = 0;
+        self.device_features_select = 0;
+        self.driver_features_select = 0;
+        self.shm_select = 0;
+        self.interrupt_status.store(0, Ordering::Release);
+
+        true
+    }
+
+    // Guest address and length of the selected shared memory region, if the
+    // device has such a region.
+    fn shm_region(&self) -> Option<(u64, u64)> {
+        let shm_list = self.device.lock().unwrap().get_shm_regions()?;
+        shm_list
+            .region_list
+            .get(self.shm_select as usize)
+            .map(|shm| (shm_list.addr.0 + shm.offset, shm.len))
+    }
+
+    fn read_register(&self, offset: u64) -> u32 {
+        match offset {
+            MAGIC_VALUE_REG => MMIO_MAGIC_VALUE,
+            VERSION_REG => MMIO_VERSION,
+            DEVICE_ID_REG => self.device.lock().unwrap().device_type(),
+            VENDOR_ID_REG => VENDOR_ID,
+            DEVICE_FEATURES_REG => {
+                // Only 64 bits of features (2 pages) are defined for now.
+                if self.device_features_select < 2 {
+                    let features = self.device.lock().unwrap().features();
+                    (self.features_override.offered(features) >> (self.device_features_select * 32))
+                        as u32
+                } else {
+                    0
+                }
+            }
+            QUEUE_NUM_MAX_REG => self.with_queue(0, |q| u32::from(q.max_size())),
+            QUEUE_READY_REG => self.with_queue(0, |q| u32::from(q.state.ready)),
+            INTERRUPT_STATUS_REG => self.interrupt_status.load(Ordering::Acquire) as u32,
+            STATUS_REG => u32::from(self.driver_status),
+            // A region which doesn't exist has a length and a base of -1.
+            SHM_LEN_LOW_REG => self.shm_region().map_or(u32::MAX, |(_, len)| len as u32),
+            SHM_LEN_HIGH_REG => self
+                .shm_region()
+                .map_or(u32::MAX, |(_, len)| (len >> 32) as u32),
+            SHM_BASE_LOW_REG => self.shm_region().map_or(u32::MAX, |(base, _)| base as u32),
+            SHM_BASE_HIGH_REG => self
+                .shm_region()
+                .map_or(u32::MAX, |(base, _)| (base >> 32) as u32),
+            CONFIG_GENERATION_REG => 0,
+            _ => {
+                warn!("{}: Invalid virtio-mmio read: 0x{:x}", self.id, offset);
+                0
+            }
+        }
+    }
+
+    fn write_register(&mut self, offset: u64, value: u32) {
+        fn hi(v: &mut GuestAddress, x: u32) {
+            *v = (*v & 0xffff_ffff) | ((u64::from(x)) << 32)
+        }
+
+        fn lo(v: &mut GuestAddress, x: u32) {
+            *v = (*v & !0xffff_ffff) | (u64::from(x))
+        }
+
+        match offset {
+            DEVICE_FEATURES_SEL_REG => self.device_features_select = value,
+            DRIVER_FEATURES_REG => {
+                if self.driver_features_select < 2 {
+                    let features = u64::from(value) << (self.driver_features_select * 32);
+                    self.device
+                        .lock()
+                        .unwrap()
+                        .ack_features(self.features_override.acked(features));
+                } else {
+                    warn!(
+                        "{}: Invalid ack_features (page {}, value 0x{:x})",
+                        self.id, self.driver_features_select, value
+                    );
+                }
+            }
+            DRIVER_FEATURES_SEL_REG => self.driver_features_select = value,
+            QUEUE_SEL_REG => self.queue_select = value,
+            QUEUE_NUM_REG => self.with_queue_mut(|q| q.state.size = value as u16),
+            QUEUE_READY_REG => self.with_queue_mut(|q| q.set_ready(value == 1)),
+            QUEUE_NOTIFY_REG => {
+                if self.use_ioeventfd {
+                    // Handled with ioeventfds.
+                    error!("{}: Unexpected write to QueueNotify", self.id);
+                } else {
+                    match self.queue_evts.get(value as usize) {
+                        Some(queue_evt) => {
+                            if let Err(e) = queue_evt.write(1) {
+                                error!("{}: Failed notifying queue {}: {}", self.id, value, e);
+                            }
+                        }
+                        None => warn!("{}: Notification for invalid queue {}", self.id, value),
+                    }
+                }
+            }
+            INTERRUPT_ACK_REG => {
+                self.interrupt_status
+                    .fetch_and(!(value as usize), Ordering::AcqRel);
+            }
+            STATUS_REG => self.driver_status = value as u8,
+            QUEUE_DESC_LOW_REG => self.with_queue_mut(|q| lo(&mut q.state.desc_table, value)),
+            QUEUE_DESC_HIGH_REG => self.with_queue_mut(|q| hi(&mut q.state.desc_table, value)),
+            QUEUE_DRIVER_LOW_REG => self.with_queue_mut(|q| lo(&mut q.state.avail_ring, value)),
+            QUEUE_DRIVER_HIGH_REG => self.with_queue_mut(|q| hi(&mut q.state.avail_ring, value)),
+            QUEUE_DEVICE_LOW_REG => self.with_queue_mut(|q| lo(&mut q.state.used_ring, value)),
+            QUEUE_DEVICE_HIGH_REG => self.with_queue_mut(|q| hi(&mut q.state.used_ring, value)),
+            SHM_SEL_REG => self.shm_select = value,
+            _ => warn!("{}: Invalid virtio-mmio write: 0x{:x}", self.id, offset),
+        }
+    }
+}
+
+impl BusDevice for VirtioMmioDevice {
+    fn read(&mut self, _base: u64, offset: u64, data: &mut [u8]) {
+        if offset >= DEVICE_CONFIG_OFFSET {
+            let device = self.device.lock().unwrap();
+            device.read_config(offset - DEVICE_CONFIG_OFFSET, data);
+            return;
+        }
+
+        // The transport registers should only be accessed with 32-bit wide
+        // aligned accesses, but the other naturally aligned ones are served
+        // from the registers they cover.
+        match data.len() {
+            4 if offset % 4 == 0 => LittleEndian::write_u32(data, self.read_register(offset)),
+            8 if offset % 8 == 0 => {
+                LittleEndian::write_u32(&mut data[..4], self.read_register(offset));
+                LittleEndian::write_u32(&mut data[4..], self.read_register(offset + 4));
+            }
+            len @ (1 | 2) if offset % len as u64 == 0 => {
+                let value = self.read_register(offset & !3) >> ((offset % 4) * 8);
+                data.copy_from_slice(&value.to_le_bytes()[..len]);
+            }
+            _ => warn!(
+                "{}: Invalid virtio-mmio read: offset 0x{:x}, length {}",
+                self.id,
+                offset,
+                data.len()
+            ),
+        }
+    }
+
+    fn write(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        if offset >= DEVICE_CONFIG_OFFSET {
+            let mut device = self.device.lock().unwrap();
+            device.write_config(offset - DEVICE_CONFIG_OFFSET, data);
+            return None;
+        }
+
+        match data.len() {
+            4 if offset % 4 == 0 => self.write_register(offset, LittleEndian::read_u32(data)),
+            // The 64-bit addresses of the queues can be written at once.
+            8 if offset % 8 == 0 => {
+                self.write_register(offset, LittleEndian::read_u32(&data[..4]));
+                self.write_register(offset + 4, LittleEndian::read_u32(&data[4..]));
+            }
+            // Some drivers update the device status with byte wide writes,
+            // which are merged into the current status.
+            len @ (1 | 2) if offset & !3 == STATUS_REG && offset % len as u64 == 0 => {
+                let shift = (offset % 4) * 8;
+                let mask = (u32::MAX >> (32 - len * 8)) << shift;
+                let mut value = [0u8; 4];
+                value[..len].copy_from_slice(data);
+                let value =
+                    (u32::from(self.driver_status) & !mask) | (u32::from_le_bytes(value) << shift);
+                self.write_register(STATUS_REG, value);
+            }
+            // Writes can't be split or merged without side effects on the
+            // other registers, hence the driver is told it misbehaved.
+            _ => {
+                self.set_device_failed(&format!(
+                    "invalid virtio-mmio write: offset 0x{:x}, length {}",
+                    offset,
+                    data.len()
+                ));
+                return None;
+            }
+        }
+
+        // Try and activate the device if the driver status has changed
+        if self.needs_activation() {
+            let barrier = Arc::new(Barrier::new(2));
+            let activator = match self.prepare_activator(Some(barrier.clone())) {
+                Ok(activator) => activator,
+                Err(e) => {
+                    self.set_device_failed(&format!("cannot prepare activation: {:?}", e));
+                    return None;
+                }
+            };
+            self.pending_activations.lock().unwrap().push(activator);
+            info!(
+                "{}: Needs activation; writing to activate event fd",
+                self.id
+            );
+            self.activate_evt.write(1).ok();
+            info!("{}: Needs activation; returning barrier", self.id);
+            return Some(barrier);
+        }
+
+        // Device has been reset by the driver
+        if self.device_activated.load(Ordering::SeqCst)
+            && self.driver_status == DEVICE_INIT as u8
+            && !self.reset_device()
+        {
+            self.set_device_failed("reset not implemented in underlying device");
+        }
+
+        None
+    }
+}
+
+impl Pausable for VirtioMmioDevice {
+    fn pause(&mut self) -> result::Result<(), MigratableError> {
+        Ok(())
+    }
+
+    fn resume(&mut self) -> result::Result<(), MigratableError> {
+        Ok(())
+    }
+}
+
+impl Snapshottable for VirtioMmioDevice {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
+        Snapshot::new_from_versioned_state(&self.id, &self.state())
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
+        if let Some(section) = snapshot.snapshot_data.get(&format!("{}-section", self.id)) {
+            // First restore the status of the virtqueues.
+            self.set_state(&section.to_versioned_state()?)
+                .map_err(|e| {
+                    MigratableError::Restore(anyhow!(
+                        "Could not restore VIRTIO_MMIO_DEVICE state {:?}",
+                        e
+                    ))
+                })?;
+
+            // Then we can activate the device, as we know at this point that
+            // the virtqueues are in the right state and the device is ready
+            // to be activated, which will spawn each virtio worker thread.
+            if self.device_activated.load(Ordering::SeqCst) && self.is_driver_ready() {
+                self.activate().map_err(|e| {
+                    MigratableError::Restore(anyhow!("Failed activating the device: {:?}", e))
+                })?;
+            }
+
+            return Ok(());
+        }
+
+        Err(MigratableError::Restore(anyhow!(
+            "Could not find VIRTIO_MMIO_DEVICE snapshot section"
+        )))
+    }
+}
+impl Transportable for VirtioMmioDevice {}
+impl Migratable for VirtioMmioDevice {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{VirtioSharedMemory, VirtioSharedMemoryList};
+    use vm_device::interrupt::{InterruptIndex, InterruptSourceConfig};
+
+    struct DummyDevice {
+        acked_features: u64,
+    }
+
+    impl VirtioDevice for DummyDevice {
+        fn device_type(&self) -> u32 {
+            19
+        }
+
+        fn queue_max_sizes(&self) -> &[u16] {
+            &[256, 128]
+        }
+
+        fn set_queue_max_size(&mut self, _max_size: u16) -> std::result::Result<(), crate::Error> {
+            Ok(())
+        }
+
+        fn features(&self) -> u64 {
+            (1 << 32) | 1
+        }
+
+        fn ack_features(&mut self, value: u64) {
+            self.acked_features |= value;
+        }
+
+        fn get_shm_regions(&self) -> Option<VirtioSharedMemoryList> {
+            Some(VirtioSharedMemoryList {
+                host_addr: 0,
+                mem_slot: 0,
+                addr: GuestAddress(0x1_0000_0000),
+                len: 0x40_0000,
+                region_list: vec![VirtioSharedMemory {
+                    offset: 0x20_0000,
+                    len: 0x20_0000,
+                }],
+            })
+        }
+
+        fn activate(
+            &mut self,
+            _mem: GuestMemoryAtomic<GuestMemoryMmap>,
+            _interrupt_evt: Arc<dyn VirtioInterrupt>,
+            _queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
+            _queue_evts: Vec<EventFd>,
+        ) -> ActivateResult {
+            Ok(())
+        }
+    }
+
+    struct DummyInterrupt;
+
+    impl InterruptSourceGroup for DummyInterrupt {
+        fn trigger(&self, _index: InterruptIndex) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn notifier(&self, _index: InterruptIndex) -> Option<EventFd> {
+            None
+        }
+
+        fn update(
+            &self,
+            _index: InterruptIndex,
+            _config: InterruptSourceConfig,
+            _masked: bool,
+        ) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    fn read_u32(device: &mut VirtioMmioDevice, offset: u64) -> u32 {
+        let mut data = [0u8; 4];
+        device.read(0, offset, &mut data);
+        LittleEndian::read_u32(&data)
+    }
+
+    fn write_u32(device: &mut VirtioMmioDevice, offset: u64, value: u32) -> Option<Arc<Barrier>> {
+        let mut data = [0u8; 4];
+        LittleEndian::write_u32(&mut data, value);
+        device.write(0, offset, &data)
+    }
+
+    #[test]
+    fn test_virtio_mmio_registers() {
+        let memory = GuestMemoryAtomic::new(
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap(),
+        );
+        let device = Arc::new(Mutex::new(DummyDevice { acked_features: 0 }));
+        let pending_activations = Arc::new(Mutex::new(Vec::new()));
+        let mut mmio = VirtioMmioDevice::new(
+            "_virtio-mmio-test".to_owned(),
+            memory,
+            device.clone(),
+            Arc::new(DummyInterrupt),
+            EventFd::new(EFD_NONBLOCK).unwrap(),
+            pending_activations.clone(),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(read_u32(&mut mmio, MAGIC_VALUE_REG), MMIO_MAGIC_VALUE);
+        assert_eq!(read_u32(&mut mmio, VERSION_REG), MMIO_VERSION);
+        assert_eq!(read_u32(&mut mmio, DEVICE_ID_REG), 19);
+
+        assert_eq!(read_u32(&mut mmio, DEVICE_FEATURES_REG), 1);
+        write_u32(&mut mmio, DEVICE_FEATURES_SEL_REG, 1);
+        assert_eq!(read_u32(&mut mmio, DEVICE_FEATURES_REG), 1);
+        write_u32(&mut mmio, DEVICE_FEATURES_SEL_REG, 2);
+        assert_eq!(read_u32(&mut mmio, DEVICE_FEATURES_REG), 0);
+
+        write_u32(&mut mmio, DRIVER_FEATURES_SEL_REG, 1);
+        write_u32(&mut mmio, DRIVER_FEATURES_REG, 1);
+        assert_eq!(device.lock().unwrap().acked_features, 1 << 32);
+
+        write_u32(&mut mmio, QUEUE_SEL_REG, 1);
+        assert_eq!(read_u32(&mut mmio, QUEUE_NUM_MAX_REG), 128);
+        write_u32(&mut mmio, QUEUE_NUM_REG, 64);
+        write_u32(&mut mmio, QUEUE_DESC_LOW_REG, 0x1000);
+        write_u32(&mut mmio, QUEUE_DRIVER_LOW_REG, 0x2000);
+        write_u32(&mut mmio, QUEUE_DEVICE_LOW_REG, 0x3000);
+        write_u32(&mut mmio, QUEUE_READY_REG, 1);
+        assert_eq!(read_u32(&mut mmio, QUEUE_READY_REG), 1);
+        assert_eq!(mmio.queues[1].state.size, 64);
+        assert_eq!(mmio.queues[1].state.desc_table, GuestAddress(0x1000));
+
+        // An out of range queue selector must not be fatal.
+        write_u32(&mut mmio, QUEUE_SEL_REG, 2);
+        assert_eq!(read_u32(&mut mmio, QUEUE_NUM_MAX_REG), 0);
+        write_u32(&mut mmio, QUEUE_NUM_REG, 64);
+
+        // The shared memory regions are reported by the selected region
+        // registers, the ones which don't exist with all bits set.
+        assert_eq!(read_u32(&mut mmio, SHM_LEN_LOW_REG), 0x20_0000);
+        assert_eq!(read_u32(&mut mmio, SHM_LEN_HIGH_REG), 0);
+        assert_eq!(read_u32(&mut mmio, SHM_BASE_LOW_REG), 0x20_0000);
+        assert_eq!(read_u32(&mut mmio, SHM_BASE_HIGH_REG), 1);
+        write_u32(&mut mmio, SHM_SEL_REG, 1);
+        assert_eq!(read_u32(&mut mmio, SHM_LEN_LOW_REG), u32::MAX);
+        assert_eq!(read_u32(&mut mmio, SHM_LEN_HIGH_REG), u32::MAX);
+
+        assert_eq!(mmio.ioeventfds(0x1000).len(), 2);
+        assert_eq!(mmio.ioeventfds(0x1000)[1].1, 0x1000 + QUEUE_NOTIFY_REG);
+        assert_eq!(mmio.ioeventfds(0x1000)[1].2, 1);
+
+        // Setting DRIVER_OK requests the activation of the device.
+        let status = DEVICE_ACKNOWLEDGE | DEVICE_DRIVER | DEVICE_FEATURES_OK | DEVICE_DRIVER_OK;
+        assert!(write_u32(&mut mmio, STATUS_REG, status).is_some());
+        assert_eq!(pending_activations.lock().unwrap().len(), 1);
+        assert_eq!(read_u32(&mut mmio, STATUS_REG), status);
+    }
+
+    #[test]
+    fn test_virtio_mmio_access_sizes() {
+        let memory = GuestMemoryAtomic::new(
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap(),
+        );
+        let device = Arc::new(Mutex::new(DummyDevice { acked_features: 0 }));
+        let mut mmio = VirtioMmioDevice::new(
+            "_virtio-mmio-test".to_owned(),
+            memory,
+            device,
+            Arc::new(DummyInterrupt),
+            EventFd::new(EFD_NONBLOCK).unwrap(),
+            Arc::new(Mutex::new(Vec::new())),
+            true,
+        )
+        .unwrap();
+
+        // Sub-word and 64-bit reads return the bytes of the registers.
+        let mut data = [0u8; 1];
+        mmio.read(0, MAGIC_VALUE_REG + 1, &mut data);
+        assert_eq!(data[0], (MMIO_MAGIC_VALUE >> 8) as u8);
+        let mut data = [0u8; 2];
+        mmio.read(0, MAGIC_VALUE_REG + 2, &mut data);
+        assert_eq!(
+            LittleEndian::read_u16(&data),
+            (MMIO_MAGIC_VALUE >> 16) as u16
+        );
+        let mut data = [0u8; 8];
+        mmio.read(0, MAGIC_VALUE_REG, &mut data);
+        assert_eq!(
+            LittleEndian::read_u64(&data),
+            (u64::from(MMIO_VERSION) << 32) | u64::from(MMIO_MAGIC_VALUE)
+        );
+
+        // Byte wide writes update the device status.
+        mmio.write(0, STATUS_REG, &[DEVICE_ACKNOWLEDGE as u8]);
+        mmio.write(0, STATUS_REG, &[(DEVICE_ACKNOWLEDGE | DEVICE_DRIVER) as u8]);
+        mmio.write(0, STATUS_REG + 1, &[0xff]);
+        assert_eq!(
+            read_u32(&mut mmio, STATUS_REG),
+            DEVICE_ACKNOWLEDGE | DEVICE_DRIVER
+        );
+
+        // 64-bit writes set both halves of the queue addresses.
+        let mut data = [0u8; 8];
+        LittleEndian::write_u64(&mut data, 0x1_2345_6000);
+        mmio.write(0, QUEUE_DESC_LOW_REG, &data);
+        assert_eq!(mmio.queues[0].state.desc_table, GuestAddress(0x1_2345_6000));
+
+        // Other sub-word and unaligned writes fail the device.
+        mmio.write(0, QUEUE_SEL_REG, &[1, 0]);
+        assert_eq!(mmio.queue_select, 0);
+        assert_ne!(read_u32(&mut mmio, STATUS_REG) & DEVICE_FAILED, 0);
+        mmio.reset();
+        mmio.write(0, QUEUE_SEL_REG + 2, &[1, 0, 0, 0]);
+        assert_ne!(read_u32(&mut mmio, STATUS_REG) & DEVICE_FAILED, 0);
+    }
+
+    #[test]
+    fn test_virtio_mmio_interrupt() {
+        let interrupt_status = Arc::new(AtomicUsize::new(0));
+        let interrupt = VirtioInterruptIntx {
+            interrupt_status: interrupt_status.clone(),
+            interrupt_source_group: Arc::new(DummyInterrupt),
+        };
+
+        interrupt.trigger(VirtioInterruptType::Queue(0)).unwrap();
+        assert_eq!(
+            interrupt_status.load(Ordering::Acquire),
+            VIRTIO_MMIO_INT_VRING
+        );
+        interrupt.trigger(VirtioInterruptType::Config).unwrap();
+        assert_eq!(
+            interrupt_status.load(Ordering::Acquire),
+            VIRTIO_MMIO_INT_VRING | VIRTIO_MMIO_INT_CONFIG
+        );
+    }
+
+    #[test]
+    fn test_virtio_mmio_queue_max_size() {
+        let memory = GuestMemoryAtomic::new(
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap(),
+        );
+        let device = Arc::new(Mutex::new(DummyDevice { acked_features: 0 }));
+        let mut mmio = VirtioMmioDevice::new(
+            "_virtio-mmio-test".to_owned(),
+            memory,
+            device,
+            Arc::new(DummyInterrupt),
+            EventFd::new(EFD_NONBLOCK).unwrap(),
+            Arc::new(Mutex::new(Vec::new())),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            mmio.queues_info()[1],
+            QueueInfo {
+                size: 128,
+                max_size: 128,
+                ready: false
+            }
+        );
+
+        // The new maximum is offered to the driver for every queue.
+        mmio.set_queue_max_size(1024).unwrap();
+        write_u32(&mut mmio, QUEUE_SEL_REG, 1);
+        assert_eq!(read_u32(&mut mmio, QUEUE_NUM_MAX_REG), 1024);
+        assert!(mmio
+            .queues_info()
+            .iter()
+            .all(|q| q.size == 1024 && q.max_size == 1024));
+
+        // It can't change anymore once the driver started initializing the
+        // device.
+        write_u32(&mut mmio, STATUS_REG, DEVICE_ACKNOWLEDGE);
+        assert!(matches!(
+            mmio.set_queue_max_size(64),
+            Err(crate::Error::DriverInitialized)
+        ));
+        assert_eq!(read_u32(&mut mmio, QUEUE_NUM_MAX_REG), 1024);
+    }
+
+    #[test]
+    fn test_virtio_mmio_features_override() {
+        let memory = GuestMemoryAtomic::new(
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap(),
+        );
+        let device = Arc::new(Mutex::new(DummyDevice { acked_features: 0 }));
+        let mut mmio = VirtioMmioDevice::new(
+            "_virtio-mmio-test".to_owned(),
+            memory,
+            device.clone(),
+            Arc::new(DummyInterrupt),
+            EventFd::new(EFD_NONBLOCK).unwrap(),
+            Arc::new(Mutex::new(Vec::new())),
+            true,
+        )
+        .unwrap();
+        let features_override = FeaturesOverride {
+            disable: 1,
+            enable: 1 << 32,
+        };
+        assert_eq!(features_override.unsupported((1 << 32) | 1), 0);
+        mmio.set_features_override(features_override);
+
+        assert_eq!(read_u32(&mut mmio, DEVICE_FEATURES_REG), 0);
+        write_u32(&mut mmio, DEVICE_FEATURES_SEL_REG, 1);
+        assert_eq!(read_u32(&mut mmio, DEVICE_FEATURES_REG), 1);
+
+        // The features the device doesn't implement can't be enabled.
+        let features_override = FeaturesOverride {
+            disable: 0,
+            enable: 1 << 28,
+        };
+        assert_eq!(features_override.unsupported((1 << 32) | 1), 1 << 28);
+
+        // A disabled feature acknowledged anyway isn't passed on to the
+        // device.
+        write_u32(&mut mmio, DRIVER_FEATURES_REG, 1 | (1 << 28));
+        assert_eq!(device.lock().unwrap().acked_features, 1 << 28);
+    }
+}
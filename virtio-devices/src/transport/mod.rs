@@ -2,12 +2,162 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(feature = "fault_injection")]
+use crate::VirtioInterruptType;
+use crate::{ActivateResult, GuestMemoryMmap, VirtioDevice, VirtioInterrupt};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use virtio_queue::Queue;
+use vm_memory::GuestMemoryAtomic;
+#[cfg(feature = "fault_injection")]
+use vm_virtio::fault_injection::Faults;
 use vmm_sys_util::eventfd::EventFd;
+mod mmio;
 mod pci_common_config;
 mod pci_device;
+pub use mmio::{VirtioMmioDevice, MMIO_MAGIC_VALUE};
 pub use pci_common_config::VirtioPciCommonConfig;
-pub use pci_device::{VirtioPciDevice, VirtioPciDeviceActivator};
+pub use pci_device::VirtioPciDevice;
 
 pub trait VirtioTransport {
     fn ioeventfds(&self, base_addr: u64) -> Vec<(&EventFd, u64)>;
 }
+
+/// Feature bits hidden from the driver, to work around drivers mishandling
+/// some of them, or required from the device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeaturesOverride {
+    pub disable: u64,
+    pub enable: u64,
+}
+
+impl FeaturesOverride {
+    /// Features offered to the driver out of the `features` of the device.
+    pub fn offered(&self, features: u64) -> u64 {
+        features & !self.disable
+    }
+
+    /// Enabled features the device doesn't implement, which can't be offered
+    /// as the device would drop them once acknowledged by the driver.
+    pub fn unsupported(&self, features: u64) -> u64 {
+        self.enable & !features
+    }
+
+    /// Features acknowledged by the driver passed on to the device, which
+    /// never include the disabled ones.
+    pub fn acked(&self, features: u64) -> u64 {
+        features & !self.disable
+    }
+}
+
+/// Configuration of a virtqueue: the size set by the driver, the largest
+/// size offered by the device, and whether the driver enabled it.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct QueueInfo {
+    pub size: u16,
+    pub max_size: u16,
+    pub ready: bool,
+}
+
+impl From<&Queue<GuestMemoryAtomic<GuestMemoryMmap>>> for QueueInfo {
+    fn from(queue: &Queue<GuestMemoryAtomic<GuestMemoryMmap>>) -> Self {
+        QueueInfo {
+            size: queue.state.size,
+            max_size: queue.state.max_size,
+            ready: queue.state.ready,
+        }
+    }
+}
+
+// Returns the number of descriptor chains made available by the driver and
+// not used by the device yet, for each queue. Queues which are not ready, or
+// whose rings can't be read, have a depth of 0.
+fn queue_depths(queues: &[Queue<GuestMemoryAtomic<GuestMemoryMmap>>]) -> Vec<u16> {
+    queues
+        .iter()
+        .map(|queue| {
+            if !queue.state.ready {
+                return 0;
+            }
+            match (
+                queue.avail_idx(Ordering::Acquire),
+                queue.used_idx(Ordering::Acquire),
+            ) {
+                (Ok(avail_idx), Ok(used_idx)) => (avail_idx - used_idx).0,
+                _ => 0,
+            }
+        })
+        .collect()
+}
+
+// Offers a new maximum size for the queues, the size of a queue defaulting
+// to its maximum until the driver sets it.
+fn set_queues_max_size(queues: &mut [Queue<GuestMemoryAtomic<GuestMemoryMmap>>], max_size: u16) {
+    for queue in queues.iter_mut() {
+        queue.state.max_size = max_size;
+        queue.state.size = max_size;
+    }
+}
+
+/// Interrupt delivered after the delay injected through the faults of the
+/// device. The notifiers are handed over as is, meaning the interrupts
+/// triggered from outside of the VMM, e.g. by a vhost-user backend, aren't
+/// delayed.
+#[cfg(feature = "fault_injection")]
+struct FaultyInterrupt {
+    interrupt: Arc<dyn VirtioInterrupt>,
+    faults: Arc<Faults>,
+}
+
+#[cfg(feature = "fault_injection")]
+impl FaultyInterrupt {
+    fn wrap(interrupt: Arc<dyn VirtioInterrupt>, faults: Arc<Faults>) -> Arc<dyn VirtioInterrupt> {
+        Arc::new(FaultyInterrupt { interrupt, faults })
+    }
+}
+
+#[cfg(feature = "fault_injection")]
+impl VirtioInterrupt for FaultyInterrupt {
+    fn trigger(&self, int_type: VirtioInterruptType) -> std::result::Result<(), std::io::Error> {
+        self.faults.delay_interrupt();
+        self.interrupt.trigger(int_type)
+    }
+
+    fn notifier(&self, int_type: VirtioInterruptType) -> Option<EventFd> {
+        self.interrupt.notifier(int_type)
+    }
+}
+
+/// Activation of a virtio device requested by the driver through one of the
+/// transports, deferred to the VMM thread.
+pub struct VirtioDeviceActivator {
+    interrupt: Option<Arc<dyn VirtioInterrupt>>,
+    memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+    device: Arc<Mutex<dyn VirtioDevice>>,
+    device_activated: Arc<AtomicBool>,
+    queues: Option<Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>>,
+    queue_evts: Option<Vec<EventFd>>,
+    barrier: Option<Arc<Barrier>>,
+    id: String,
+}
+
+impl VirtioDeviceActivator {
+    pub fn activate(&mut self) -> ActivateResult {
+        self.device.lock().unwrap().activate(
+            self.memory.take().unwrap(),
+            self.interrupt.take().unwrap(),
+            self.queues.take().unwrap(),
+            self.queue_evts.take().unwrap(),
+        )?;
+        self.device_activated.store(true, Ordering::SeqCst);
+
+        if let Some(barrier) = self.barrier.take() {
+            info!("{}: Waiting for barrier", self.id);
+            barrier.wait();
+            info!("{}: Barrier released", self.id);
+        }
+
+        Ok(())
+    }
+}
@@ -6,7 +6,8 @@
 //
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
-use crate::{GuestMemoryMmap, VirtioDevice};
+use super::FeaturesOverride;
+use crate::{GuestMemoryMmap, VirtioDevice, DEVICE_FAILED};
 use byteorder::{ByteOrder, LittleEndian};
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Arc, Mutex};
@@ -61,6 +62,8 @@ pub struct VirtioPciCommonConfig {
     pub queue_select: u16,
     pub msix_config: Arc<AtomicU16>,
     pub msix_queues: Arc<Mutex<Vec<u16>>>,
+    // Not saved, as it comes from the configuration of the VM.
+    pub features_override: FeaturesOverride,
 }
 
 impl VirtioPciCommonConfig {
@@ -170,7 +173,13 @@ impl VirtioPciCommonConfig {
             0x12 => queues.len() as u16, // num_queues
             0x16 => self.queue_select,
             0x18 => self.with_queue(queues, |q| q.state.size).unwrap_or(0),
-            0x1a => self.msix_queues.lock().unwrap()[self.queue_select as usize],
+            0x1a => self
+                .msix_queues
+                .lock()
+                .unwrap()
+                .get(self.queue_select as usize)
+                .copied()
+                .unwrap_or(0),
             0x1c => {
                 if self.with_queue(queues, |q| q.state.ready).unwrap_or(false) {
                     1
@@ -197,43 +206,74 @@ impl VirtioPciCommonConfig {
             0x10 => self.msix_config.store(value, Ordering::Release),
             0x16 => self.queue_select = value,
             0x18 => self.with_queue_mut(queues, |q| q.state.size = value),
-            0x1a => self.msix_queues.lock().unwrap()[self.queue_select as usize] = value,
-            0x1c => self.with_queue_mut(queues, |q| {
-                let ready = value == 1;
-                q.set_ready(ready);
-                // Translate address of descriptor table and vrings.
-                if let Some(access_platform) = &self.access_platform {
-                    if ready {
-                        let desc_table = access_platform
-                            .translate_gva(q.state.desc_table.0, 0)
-                            .unwrap();
-                        let avail_ring = access_platform
-                            .translate_gva(q.state.avail_ring.0, 0)
-                            .unwrap();
-                        let used_ring = access_platform
-                            .translate_gva(q.state.used_ring.0, 0)
-                            .unwrap();
-                        q.set_desc_table_address(
-                            Some((desc_table & 0xffff_ffff) as u32),
-                            Some((desc_table >> 32) as u32),
-                        );
-                        q.set_avail_ring_address(
-                            Some((avail_ring & 0xffff_ffff) as u32),
-                            Some((avail_ring >> 32) as u32),
-                        );
-                        q.set_used_ring_address(
-                            Some((used_ring & 0xffff_ffff) as u32),
-                            Some((used_ring >> 32) as u32),
-                        );
+            0x1a => {
+                if let Some(vector) = self
+                    .msix_queues
+                    .lock()
+                    .unwrap()
+                    .get_mut(self.queue_select as usize)
+                {
+                    *vector = value;
+                } else {
+                    warn!(
+                        "invalid queue selected for MSI-X vector write: {}",
+                        self.queue_select
+                    );
+                }
+            }
+            0x1c => {
+                let mut translation_failed = false;
+                self.with_queue_mut(queues, |q| {
+                    let ready = value == 1;
+                    q.set_ready(ready);
+                    // Translate address of descriptor table and vrings.
+                    if let Some(access_platform) = &self.access_platform {
+                        if ready {
+                            if let Err(e) = Self::translate_queue_addresses(access_platform, q) {
+                                error!("Failed translating queue addresses: {}", e);
+                                q.set_ready(false);
+                                translation_failed = true;
+                            }
+                        }
                     }
+                });
+
+                // The guest programmed addresses that cannot be translated,
+                // which means the device cannot operate. Report it through
+                // the status register instead of aborting the VMM.
+                if translation_failed {
+                    self.driver_status |= DEVICE_FAILED as u8;
                 }
-            }),
+            }
             _ => {
                 warn!("invalid virtio register word write: 0x{:x}", offset);
             }
         }
     }
 
+    fn translate_queue_addresses(
+        access_platform: &Arc<dyn AccessPlatform>,
+        q: &mut Queue<GuestMemoryAtomic<GuestMemoryMmap>>,
+    ) -> std::io::Result<()> {
+        let desc_table = access_platform.translate_gva(q.state.desc_table.0, 0)?;
+        let avail_ring = access_platform.translate_gva(q.state.avail_ring.0, 0)?;
+        let used_ring = access_platform.translate_gva(q.state.used_ring.0, 0)?;
+        q.set_desc_table_address(
+            Some((desc_table & 0xffff_ffff) as u32),
+            Some((desc_table >> 32) as u32),
+        );
+        q.set_avail_ring_address(
+            Some((avail_ring & 0xffff_ffff) as u32),
+            Some((avail_ring >> 32) as u32),
+        );
+        q.set_used_ring_address(
+            Some((used_ring & 0xffff_ffff) as u32),
+            Some((used_ring >> 32) as u32),
+        );
+
+        Ok(())
+    }
+
     fn read_common_config_dword(&self, offset: u64, device: Arc<Mutex<dyn VirtioDevice>>) -> u32 {
         debug!("read_common_config_dword: offset 0x{:x}", offset);
         match offset {
@@ -243,7 +283,8 @@ impl VirtioPciCommonConfig {
                 // Only 64 bits of features (2 pages) are defined for now, so limit
                 // device_feature_select to avoid shifting by 64 or more bits.
                 if self.device_feature_select < 2 {
-                    (locked_device.features() >> (self.device_feature_select * 32)) as u32
+                    (self.features_override.offered(locked_device.features())
+                        >> (self.device_feature_select * 32)) as u32
                 } else {
                     0
                 }
@@ -278,8 +319,10 @@ impl VirtioPciCommonConfig {
             0x0c => {
                 if self.driver_feature_select < 2 {
                     let mut locked_device = device.lock().unwrap();
-                    locked_device
-                        .ack_features(u64::from(value) << (self.driver_feature_select * 32));
+                    locked_device.ack_features(
+                        self.features_override
+                            .acked(u64::from(value) << (self.driver_feature_select * 32)),
+                    );
                 } else {
                     warn!(
                         "invalid ack_features (page {}, value 0x{:x})",
@@ -413,6 +456,7 @@ mod tests {
             queue_select: 0xff,
             msix_config: Arc::new(AtomicU16::new(0)),
             msix_queues: Arc::new(Mutex::new(vec![0; 3])),
+            features_override: FeaturesOverride::default(),
         };
 
         let dev = Arc::new(Mutex::new(DummyDevice(0)));
@@ -453,4 +497,61 @@ mod tests {
         assert_eq!(read_back[0], 0xaa);
         assert_eq!(read_back[1], 0x55);
     }
+
+    #[test]
+    fn out_of_range_queue_select() {
+        let mut regs = VirtioPciCommonConfig {
+            access_platform: None,
+            driver_status: 0,
+            config_generation: 0,
+            device_feature_select: 0x0,
+            driver_feature_select: 0x0,
+            queue_select: 0,
+            msix_config: Arc::new(AtomicU16::new(0)),
+            msix_queues: Arc::new(Mutex::new(vec![0; 1])),
+            features_override: FeaturesOverride::default(),
+        };
+
+        let dev = Arc::new(Mutex::new(DummyDevice(0)));
+        let mut queues = Vec::new();
+
+        // Select a queue that doesn't exist.
+        regs.write(0x16, &[0x10, 0x00], &mut queues, dev.clone());
+
+        // Accessing the queue MSI-X vector must be ignored rather than
+        // aborting the VMM.
+        regs.write(0x1a, &[0x01, 0x00], &mut queues, dev.clone());
+        let mut read_back = vec![0xff, 0xff];
+        regs.read(0x1a, &mut read_back, &mut queues, dev);
+        assert_eq!(LittleEndian::read_u16(&read_back), 0);
+        assert_eq!(*regs.msix_queues.lock().unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn features_override() {
+        let mut regs = VirtioPciCommonConfig {
+            access_platform: None,
+            driver_status: 0,
+            config_generation: 0,
+            device_feature_select: 0x0,
+            driver_feature_select: 0x0,
+            queue_select: 0,
+            msix_config: Arc::new(AtomicU16::new(0)),
+            msix_queues: Arc::new(Mutex::new(vec![0; 1])),
+            features_override: FeaturesOverride {
+                disable: 0xaaaa,
+                enable: 1 << 30,
+            },
+        };
+
+        let dev = Arc::new(Mutex::new(DummyDevice(0)));
+        let mut queues = Vec::new();
+
+        let mut read_back = vec![0, 0, 0, 0];
+        regs.read(0x04, &mut read_back, &mut queues, dev.clone());
+        assert_eq!(LittleEndian::read_u32(&read_back), 0x5555_0000);
+        regs.write(0x00, &[1, 0, 0, 0], &mut queues, dev.clone());
+        regs.read(0x04, &mut read_back, &mut queues, dev);
+        assert_eq!(LittleEndian::read_u32(&read_back), 0);
+    }
 }
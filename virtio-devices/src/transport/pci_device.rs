@@ -7,12 +7,17 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 
 use super::VirtioPciCommonConfig;
-use crate::transport::VirtioTransport;
+#[cfg(feature = "fault_injection")]
+use crate::transport::FaultyInterrupt;
+use crate::transport::{
+    queue_depths, set_queues_max_size, FeaturesOverride, QueueInfo, VirtioDeviceActivator,
+    VirtioTransport,
+};
 use crate::GuestMemoryMmap;
 use crate::{
-    ActivateResult, VirtioDevice, VirtioDeviceType, VirtioInterrupt, VirtioInterruptType,
-    DEVICE_ACKNOWLEDGE, DEVICE_DRIVER, DEVICE_DRIVER_OK, DEVICE_FAILED, DEVICE_FEATURES_OK,
-    DEVICE_INIT,
+    ActivateError, ActivateResult, VirtioDevice, VirtioDeviceType, VirtioInterrupt,
+    VirtioInterruptType, DEVICE_ACKNOWLEDGE, DEVICE_DRIVER, DEVICE_DRIVER_OK, DEVICE_FAILED,
+    DEVICE_FEATURES_OK, DEVICE_INIT,
 };
 use anyhow::anyhow;
 use libc::EFD_NONBLOCK;
@@ -40,6 +45,8 @@ use vm_memory::{Address, ByteValued, GuestAddress, GuestMemoryAtomic, Le32};
 use vm_migration::{
     Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionMapped,
 };
+#[cfg(feature = "fault_injection")]
+use vm_virtio::fault_injection::Faults;
 use vm_virtio::AccessPlatform;
 use vmm_sys_util::{errno::Result, eventfd::EventFd};
 
@@ -50,6 +57,10 @@ const VIRTQ_MSI_NO_VECTOR: u16 = 0xffff;
 enum Error {
     /// Failed to retrieve queue ring's index.
     QueueRingIndex(QueueError),
+    /// Number of queues in the saved state doesn't match the device.
+    QueueCountMismatch(usize, usize),
+    /// Failed to clone a queue EventFd.
+    QueueEventFdClone(std::io::Error),
 }
 
 #[allow(clippy::enum_variant_names)]
@@ -269,13 +280,13 @@ const VIRTIO_PCI_VENDOR_ID: u16 = 0x1af4;
 const VIRTIO_PCI_DEVICE_ID_BASE: u16 = 0x1040; // Add to device type to get device ID.
 
 #[derive(Versionize)]
-struct QueueState {
-    max_size: u16,
-    size: u16,
-    ready: bool,
-    desc_table: u64,
-    avail_ring: u64,
-    used_ring: u64,
+pub(super) struct QueueState {
+    pub(super) max_size: u16,
+    pub(super) size: u16,
+    pub(super) ready: bool,
+    pub(super) desc_table: u64,
+    pub(super) avail_ring: u64,
+    pub(super) used_ring: u64,
 }
 
 #[derive(Versionize)]
@@ -287,37 +298,6 @@ struct VirtioPciDeviceState {
 
 impl VersionMapped for VirtioPciDeviceState {}
 
-pub struct VirtioPciDeviceActivator {
-    interrupt: Option<Arc<dyn VirtioInterrupt>>,
-    memory: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
-    device: Arc<Mutex<dyn VirtioDevice>>,
-    device_activated: Arc<AtomicBool>,
-    queues: Option<Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>>,
-    queue_evts: Option<Vec<EventFd>>,
-    barrier: Option<Arc<Barrier>>,
-    id: String,
-}
-
-impl VirtioPciDeviceActivator {
-    pub fn activate(&mut self) -> ActivateResult {
-        self.device.lock().unwrap().activate(
-            self.memory.take().unwrap(),
-            self.interrupt.take().unwrap(),
-            self.queues.take().unwrap(),
-            self.queue_evts.take().unwrap(),
-        )?;
-        self.device_activated.store(true, Ordering::SeqCst);
-
-        if let Some(barrier) = self.barrier.take() {
-            info!("{}: Waiting for barrier", self.id);
-            barrier.wait();
-            info!("{}: Barrier released", self.id);
-        }
-
-        Ok(())
-    }
-}
-
 pub struct VirtioPciDevice {
     id: String,
 
@@ -373,7 +353,11 @@ pub struct VirtioPciDevice {
     dma_handler: Option<Arc<dyn ExternalDmaMapping>>,
 
     // Pending activations
-    pending_activations: Arc<Mutex<Vec<VirtioPciDeviceActivator>>>,
+    pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
+
+    // Whether queue notifications are delivered through ioeventfds, or
+    // trapped and forwarded to the queue EventFd by the transport.
+    use_ioeventfd: bool,
 }
 
 impl VirtioPciDevice {
@@ -390,7 +374,8 @@ impl VirtioPciDevice {
         activate_evt: EventFd,
         use_64bit_bar: bool,
         dma_handler: Option<Arc<dyn ExternalDmaMapping>>,
-        pending_activations: Arc<Mutex<Vec<VirtioPciDeviceActivator>>>,
+        pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
+        use_ioeventfd: bool,
     ) -> Result<Self> {
         let device_clone = device.clone();
         let mut locked_device = device_clone.lock().unwrap();
@@ -474,6 +459,7 @@ impl VirtioPciDevice {
                 queue_select: 0,
                 msix_config: Arc::new(AtomicU16::new(VIRTQ_MSI_NO_VECTOR)),
                 msix_queues: Arc::new(Mutex::new(vec![VIRTQ_MSI_NO_VECTOR; num_queues])),
+                features_override: FeaturesOverride::default(),
             },
             msix_config,
             msix_num,
@@ -492,6 +478,7 @@ impl VirtioPciDevice {
             activate_evt,
             dma_handler,
             pending_activations,
+            use_ioeventfd,
         };
 
         if let Some(msix_config) = &virtio_pci_device.msix_config {
@@ -531,13 +518,20 @@ impl VirtioPciDevice {
         self.interrupt_status
             .store(state.interrupt_status, Ordering::Release);
 
+        if state.queues.len() != self.queues.len() {
+            return Err(Error::QueueCountMismatch(
+                state.queues.len(),
+                self.queues.len(),
+            ));
+        }
+
         // Update virtqueues indexes for both available and used rings.
-        for (i, queue) in self.queues.iter_mut().enumerate() {
-            queue.state.size = state.queues[i].size;
-            queue.state.ready = state.queues[i].ready;
-            queue.state.desc_table = GuestAddress(state.queues[i].desc_table);
-            queue.state.avail_ring = GuestAddress(state.queues[i].avail_ring);
-            queue.state.used_ring = GuestAddress(state.queues[i].used_ring);
+        for (queue, queue_state) in self.queues.iter_mut().zip(state.queues.iter()) {
+            queue.state.size = queue_state.size;
+            queue.state.ready = queue_state.ready;
+            queue.state.desc_table = GuestAddress(queue_state.desc_table);
+            queue.state.avail_ring = GuestAddress(queue_state.avail_ring);
+            queue.state.used_ring = GuestAddress(queue_state.used_ring);
             queue.set_next_avail(
                 queue
                     .used_idx(Ordering::Acquire)
@@ -698,37 +692,146 @@ impl VirtioPciDevice {
         self.device.clone()
     }
 
-    fn prepare_activator(&mut self, barrier: Option<Arc<Barrier>>) -> VirtioPciDeviceActivator {
+    /// Applies the interrupt delay from `faults` to the interrupts sent to
+    /// the guest. Must be called before the device is activated.
+    #[cfg(feature = "fault_injection")]
+    pub fn set_faults(&mut self, faults: Arc<Faults>) {
+        self.virtio_interrupt = self
+            .virtio_interrupt
+            .take()
+            .map(|interrupt| FaultyInterrupt::wrap(interrupt, faults));
+    }
+
+    fn prepare_activator(
+        &mut self,
+        barrier: Option<Arc<Barrier>>,
+    ) -> std::result::Result<VirtioDeviceActivator, Error> {
+        let mut queues = Vec::new();
         let mut queue_evts = Vec::new();
-        let mut queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>> =
-            self.queues.iter().map(vm_virtio::clone_queue).collect();
-        queues.retain(|q| q.state.ready);
-        for (i, queue) in queues.iter().enumerate() {
-            queue_evts.push(self.queue_evts[i].try_clone().unwrap());
+        for (i, (queue, queue_evt)) in self.queues.iter().zip(self.queue_evts.iter()).enumerate() {
+            if !queue.state.ready {
+                continue;
+            }
             if !queue.is_valid() {
                 error!("Queue {} is not valid", i);
             }
+            queues.push(vm_virtio::clone_queue(queue));
+            queue_evts.push(queue_evt.try_clone().map_err(Error::QueueEventFdClone)?);
         }
 
-        VirtioPciDeviceActivator {
+        Ok(VirtioDeviceActivator {
             interrupt: self.virtio_interrupt.take(),
             memory: self.memory.clone(),
             device: self.device.clone(),
             queues: Some(queues),
             device_activated: self.device_activated.clone(),
-            queue_evts: Some(
-                queue_evts
-                    .iter()
-                    .map(|fd| fd.try_clone().unwrap())
-                    .collect(),
-            ),
+            queue_evts: Some(queue_evts),
             barrier,
             id: self.id.clone(),
-        }
+        })
     }
 
     fn activate(&mut self) -> ActivateResult {
-        self.prepare_activator(None).activate()
+        self.prepare_activator(None)
+            .map_err(|e| {
+                error!("{}: Failed preparing activation: {:?}", self.id, e);
+                ActivateError::BadActivate
+            })?
+            .activate()
+    }
+
+    /// Flags the device as failed from the guest point of view. This is used
+    /// whenever the driver drives the device into a state it can't recover
+    /// from, so that the guest gets notified through the status register
+    /// rather than the VMM aborting.
+    fn set_device_failed(&mut self, reason: &str) {
+        error!("{}: Device failed: {}", self.id, reason);
+        self.common_config.driver_status |= DEVICE_FAILED as u8;
+        event!("virtio-device", "failed", "id", &self.id, "reason", reason);
+    }
+
+    // Resets the underlying device, along with the queues. Returns false if
+    // the device doesn't support being reset.
+    fn reset_device(&mut self) -> bool {
+        let virtio_interrupt = match self.device.lock().unwrap().reset() {
+            Some(virtio_interrupt) => virtio_interrupt,
+            None => return false,
+        };
+
+        // Upon reset the device returns its interrupt EventFD
+        self.virtio_interrupt = Some(virtio_interrupt);
+        self.device_activated.store(false, Ordering::SeqCst);
+
+        // Reset queue readiness (changes queue_enable), queue sizes
+        // and selected_queue as per spec for reset
+        self.queues.iter_mut().for_each(Queue::reset);
+        self.common_config.queue_select = 0;
+
+        true
+    }
+
+    pub fn is_activated(&self) -> bool {
+        self.device_activated.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of descriptor chains made available by the driver
+    /// and not used by the device yet, for each queue of the device. Queues
+    /// which are not ready, or whose rings can't be read, have a depth of 0.
+    pub fn queue_depths(&self) -> Vec<u16> {
+        queue_depths(&self.queues)
+    }
+
+    pub fn set_features_override(&mut self, features_override: FeaturesOverride) {
+        self.common_config.features_override = features_override;
+    }
+
+    pub fn queues_info(&self) -> Vec<QueueInfo> {
+        self.queues.iter().map(QueueInfo::from).collect()
+    }
+
+    /// Changes the maximum size of the queues of the device, which the driver
+    /// discovers when setting the queues up. It is refused once the driver
+    /// started initializing the device.
+    pub fn set_queue_max_size(&mut self, max_size: u16) -> result::Result<(), crate::Error> {
+        if self.is_activated() || !self.is_driver_init() {
+            return Err(crate::Error::DriverInitialized);
+        }
+
+        self.device.lock().unwrap().set_queue_max_size(max_size)?;
+        set_queues_max_size(&mut self.queues, max_size);
+        Ok(())
+    }
+
+    /// Brings the transport and the underlying device back to their initial
+    /// state, as if the VM had just been created. Unlike a reset initiated
+    /// by the driver, this also clears the status and the MSI-X vectors
+    /// programmed through the common configuration, and it reprograms the
+    /// PCI configuration with MSI-X disabled. Returns false if the device is
+    /// activated and doesn't support being reset.
+    pub fn reset(&mut self) -> bool {
+        if self.device_activated.load(Ordering::SeqCst) && !self.reset_device() {
+            return false;
+        }
+
+        self.queues.iter_mut().for_each(Queue::reset);
+        self.common_config.driver_status = DEVICE_INIT as u8;
+        self.common_config.config_generation = 0;
+        self.common_config.device_feature_select = 0;
+        self.common_config.driver_feature_select = 0;
+        self.common_config.queue_select = 0;
+        self.common_config
+            .msix_config
+            .store(VIRTQ_MSI_NO_VECTOR, Ordering::Release);
+        self.common_config
+            .msix_queues
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .for_each(|v| *v = VIRTQ_MSI_NO_VECTOR);
+        self.interrupt_status.store(0, Ordering::Release);
+        self.configuration.reset();
+
+        true
     }
 
     fn needs_activation(&self) -> bool {
@@ -742,6 +845,10 @@ impl VirtioPciDevice {
 
 impl VirtioTransport for VirtioPciDevice {
     fn ioeventfds(&self, base_addr: u64) -> Vec<(&EventFd, u64)> {
+        if !self.use_ioeventfd {
+            return Vec::new();
+        }
+
         let notify_base = base_addr + NOTIFICATION_BAR_OFFSET;
         self.queue_evts()
             .iter()
@@ -793,7 +900,15 @@ impl VirtioInterrupt for VirtioInterruptMsix {
         }
 
         let config = &mut self.msix_config.lock().unwrap();
-        let entry = &config.table_entries[vector as usize];
+        let entry = match config.table_entries.get(vector as usize) {
+            Some(entry) => entry,
+            None => {
+                // The vector is programmed by the guest, make sure a bogus
+                // value doesn't take the VMM down.
+                warn!("Invalid MSI-X vector {}", vector);
+                return Ok(());
+            }
+        };
         // In case the vector control register associated with the entry
         // has its first bit set, this means the vector is masked and the
         // device should not inject the interrupt.
@@ -1044,6 +1159,8 @@ impl PciDevice for VirtioPciDevice {
     }
 
     fn write_bar(&mut self, _base: u64, offset: u64, data: &[u8]) -> Option<Arc<Barrier>> {
+        let device_failed = self.common_config.driver_status & DEVICE_FAILED as u8 != 0;
+
         match offset {
             o if o < COMMON_CONFIG_BAR_OFFSET + COMMON_CONFIG_SIZE => self.common_config.write(
                 o - COMMON_CONFIG_BAR_OFFSET,
@@ -1066,8 +1183,21 @@ impl PciDevice for VirtioPciDevice {
             o if (NOTIFICATION_BAR_OFFSET..NOTIFICATION_BAR_OFFSET + NOTIFICATION_SIZE)
                 .contains(&o) =>
             {
-                // Handled with ioeventfds.
-                error!("Unexpected write to notification BAR: offset = 0x{:x}", o);
+                if self.use_ioeventfd {
+                    // Handled with ioeventfds.
+                    error!("Unexpected write to notification BAR: offset = 0x{:x}", o);
+                } else {
+                    let queue_index =
+                        ((o - NOTIFICATION_BAR_OFFSET) / u64::from(NOTIFY_OFF_MULTIPLIER)) as usize;
+                    match self.queue_evts.get(queue_index) {
+                        Some(queue_evt) => {
+                            if let Err(e) = queue_evt.write(1) {
+                                error!("Failed notifying queue {}: {}", queue_index, e);
+                            }
+                        }
+                        None => warn!("Notification for invalid queue {}", queue_index),
+                    }
+                }
             }
             o if (MSIX_TABLE_BAR_OFFSET..MSIX_TABLE_BAR_OFFSET + MSIX_TABLE_SIZE).contains(&o) => {
                 if let Some(msix_config) = &self.msix_config {
@@ -1088,10 +1218,22 @@ impl PciDevice for VirtioPciDevice {
             _ => (),
         };
 
+        // The common configuration may have flagged the device as failed
+        // while processing the access (e.g. untranslatable queue addresses).
+        if !device_failed && self.common_config.driver_status & DEVICE_FAILED as u8 != 0 {
+            event!("virtio-device", "failed", "id", &self.id);
+        }
+
         // Try and activate the device if the driver status has changed
         if self.needs_activation() {
             let barrier = Arc::new(Barrier::new(2));
-            let activator = self.prepare_activator(Some(barrier.clone()));
+            let activator = match self.prepare_activator(Some(barrier.clone())) {
+                Ok(activator) => activator,
+                Err(e) => {
+                    self.set_device_failed(&format!("cannot prepare activation: {:?}", e));
+                    return None;
+                }
+            };
             self.pending_activations.lock().unwrap().push(activator);
             info!(
                 "{}: Needs activation; writing to activate event fd",
@@ -1103,21 +1245,11 @@ impl PciDevice for VirtioPciDevice {
         }
 
         // Device has been reset by the driver
-        if self.device_activated.load(Ordering::SeqCst) && self.is_driver_init() {
-            let mut device = self.device.lock().unwrap();
-            if let Some(virtio_interrupt) = device.reset() {
-                // Upon reset the device returns its interrupt EventFD
-                self.virtio_interrupt = Some(virtio_interrupt);
-                self.device_activated.store(false, Ordering::SeqCst);
-
-                // Reset queue readiness (changes queue_enable), queue sizes
-                // and selected_queue as per spec for reset
-                self.queues.iter_mut().for_each(Queue::reset);
-                self.common_config.queue_select = 0;
-            } else {
-                error!("Attempt to reset device when not implemented in underlying device");
-                self.common_config.driver_status = crate::DEVICE_FAILED as u8;
-            }
+        if self.device_activated.load(Ordering::SeqCst)
+            && self.is_driver_init()
+            && !self.reset_device()
+        {
+            self.set_device_failed("reset not implemented in underlying device");
         }
 
         None
@@ -1175,30 +1307,28 @@ impl Snapshottable for VirtioPciDevice {
         Ok(virtio_pci_dev_snapshot)
     }
 
-    fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
+    fn restore(&mut self, mut snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         if let Some(virtio_pci_dev_section) =
             snapshot.snapshot_data.get(&format!("{}-section", self.id))
         {
             // Restore MSI-X
             if let Some(msix_config) = &self.msix_config {
                 let id = msix_config.lock().unwrap().id();
-                if let Some(msix_snapshot) = snapshot.snapshots.get(&id) {
-                    msix_config
-                        .lock()
-                        .unwrap()
-                        .restore(*msix_snapshot.clone())?;
+                if let Some(msix_snapshot) = snapshot.snapshots.remove(&id) {
+                    msix_config.lock().unwrap().restore(*msix_snapshot)?;
                 }
             }
 
             // Restore VirtioPciCommonConfig
-            if let Some(virtio_config_snapshot) = snapshot.snapshots.get(&self.common_config.id()) {
-                self.common_config
-                    .restore(*virtio_config_snapshot.clone())?;
+            if let Some(virtio_config_snapshot) =
+                snapshot.snapshots.remove(&self.common_config.id())
+            {
+                self.common_config.restore(*virtio_config_snapshot)?;
             }
 
             // Restore PciConfiguration
-            if let Some(pci_config_snapshot) = snapshot.snapshots.get(&self.configuration.id()) {
-                self.configuration.restore(*pci_config_snapshot.clone())?;
+            if let Some(pci_config_snapshot) = snapshot.snapshots.remove(&self.configuration.id()) {
+                self.configuration.restore(*pci_config_snapshot)?;
             }
 
             // First restore the status of the virtqueues.
@@ -1229,3 +1359,157 @@ impl Snapshottable for VirtioPciDevice {
 }
 impl Transportable for VirtioPciDevice {}
 impl Migratable for VirtioPciDevice {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_device::interrupt::InterruptSourceConfig;
+
+    struct DummyDevice {
+        queue_sizes: Vec<u16>,
+        resizable: bool,
+    }
+
+    impl VirtioDevice for DummyDevice {
+        fn device_type(&self) -> u32 {
+            2
+        }
+
+        fn queue_max_sizes(&self) -> &[u16] {
+            &self.queue_sizes
+        }
+
+        fn set_queue_max_size(&mut self, max_size: u16) -> result::Result<(), crate::Error> {
+            if !self.resizable {
+                return Err(crate::Error::QueueSizeUnsupported);
+            }
+            self.queue_sizes
+                .iter_mut()
+                .for_each(|size| *size = max_size);
+            Ok(())
+        }
+
+        fn activate(
+            &mut self,
+            _mem: GuestMemoryAtomic<GuestMemoryMmap>,
+            _interrupt_evt: Arc<dyn VirtioInterrupt>,
+            _queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
+            _queue_evts: Vec<EventFd>,
+        ) -> ActivateResult {
+            Ok(())
+        }
+    }
+
+    struct DummyInterrupt;
+
+    impl InterruptSourceGroup for DummyInterrupt {
+        fn trigger(&self, _index: InterruptIndex) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+
+        fn notifier(&self, _index: InterruptIndex) -> Option<EventFd> {
+            None
+        }
+
+        fn update(
+            &self,
+            _index: InterruptIndex,
+            _config: InterruptSourceConfig,
+            _masked: bool,
+        ) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    struct DummyInterruptManager;
+
+    impl InterruptManager for DummyInterruptManager {
+        type GroupConfig = MsiIrqGroupConfig;
+
+        fn create_group(
+            &self,
+            _config: Self::GroupConfig,
+        ) -> std::result::Result<Arc<dyn InterruptSourceGroup>, std::io::Error> {
+            Ok(Arc::new(DummyInterrupt))
+        }
+
+        fn destroy_group(
+            &self,
+            _group: Arc<dyn InterruptSourceGroup>,
+        ) -> std::result::Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    fn virtio_pci_device(resizable: bool) -> VirtioPciDevice {
+        let memory = GuestMemoryAtomic::new(
+            GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10_0000)]).unwrap(),
+        );
+        let device = Arc::new(Mutex::new(DummyDevice {
+            queue_sizes: vec![128, 128],
+            resizable,
+        }));
+        let interrupt_manager: Arc<dyn InterruptManager<GroupConfig = MsiIrqGroupConfig>> =
+            Arc::new(DummyInterruptManager);
+        VirtioPciDevice::new(
+            "_virtio-pci-test".to_owned(),
+            memory,
+            device,
+            3,
+            None,
+            &interrupt_manager,
+            0,
+            EventFd::new(EFD_NONBLOCK).unwrap(),
+            true,
+            None,
+            Arc::new(Mutex::new(Vec::new())),
+            false,
+        )
+        .unwrap()
+    }
+
+    fn read_queue_size(device: &mut VirtioPciDevice, queue: u16) -> u16 {
+        device.write_bar(0, COMMON_CONFIG_BAR_OFFSET + 0x16, &queue.to_le_bytes());
+        let mut data = [0u8; 2];
+        device.read_bar(0, COMMON_CONFIG_BAR_OFFSET + 0x18, &mut data);
+        u16::from_le_bytes(data)
+    }
+
+    #[test]
+    fn test_virtio_pci_queue_max_size() {
+        let mut device = virtio_pci_device(true);
+        assert_eq!(read_queue_size(&mut device, 1), 128);
+
+        // The new maximum is offered to the driver for every queue.
+        device.set_queue_max_size(1024).unwrap();
+        assert_eq!(read_queue_size(&mut device, 0), 1024);
+        assert_eq!(read_queue_size(&mut device, 1), 1024);
+        assert!(device
+            .queues_info()
+            .iter()
+            .all(|q| q.size == 1024 && q.max_size == 1024));
+
+        // It can't change anymore once the driver started initializing the
+        // device.
+        device.write_bar(
+            0,
+            COMMON_CONFIG_BAR_OFFSET + 0x14,
+            &[DEVICE_ACKNOWLEDGE as u8],
+        );
+        assert!(matches!(
+            device.set_queue_max_size(64),
+            Err(crate::Error::DriverInitialized)
+        ));
+        assert_eq!(read_queue_size(&mut device, 0), 1024);
+    }
+
+    #[test]
+    fn test_virtio_pci_queue_max_size_unsupported() {
+        let mut device = virtio_pci_device(false);
+        assert!(matches!(
+            device.set_queue_max_size(256),
+            Err(crate::Error::QueueSizeUnsupported)
+        ));
+        assert_eq!(read_queue_size(&mut device, 0), 128);
+    }
+}
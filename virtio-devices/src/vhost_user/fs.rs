@@ -1,7 +1,9 @@
 // Copyright 2019 Intel Corporation. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::vu_common_ctrl::VhostUserHandle;
+use super::vu_common_ctrl::{
+    protocol_features_from_bits, VhostUserHandle, VHOST_USER_PROTOCOL_F_DEVICE_STATE,
+};
 use super::{Error, Result, DEFAULT_VIRTIO_FEATURES};
 use crate::seccomp_filters::Thread;
 use crate::thread_helper::spawn_virtio_thread;
@@ -16,6 +18,7 @@ use seccompiler::SeccompAction;
 use std::io;
 use std::os::unix::io::AsRawFd;
 use std::result;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Barrier, Mutex};
 use std::thread;
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -48,6 +51,8 @@ pub struct State {
     pub acked_protocol_features: u64,
     pub vu_num_queues: usize,
     pub slave_req_support: bool,
+    // Internal state of the backend, only transferred on live migration.
+    pub backend_state: Option<Vec<u8>>,
 }
 
 impl VersionMapped for State {}
@@ -369,6 +374,9 @@ impl Fs {
         if cache.is_some() {
             avail_protocol_features |= slave_protocol_features;
         }
+        // Transferring the backend state allows the filesystem to be used
+        // seamlessly by the guest after a live migration.
+        avail_protocol_features |= protocol_features_from_bits(VHOST_USER_PROTOCOL_F_DEVICE_STATE);
 
         let (acked_features, acked_protocol_features) =
             vu.negotiate_features_vhost_user(avail_features, avail_protocol_features)?;
@@ -443,6 +451,7 @@ impl Fs {
             acked_protocol_features: self.vu_common.acked_protocol_features,
             vu_num_queues: self.vu_common.vu_num_queues,
             slave_req_support: self.slave_req_support,
+            backend_state: None,
         }
     }
 
@@ -565,6 +574,7 @@ impl VirtioDevice for Fs {
             Thread::VirtioVhostFs,
             &mut epoll_threads,
             &self.exit_evt,
+            self.common.thread_nice,
             move || {
                 if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                     error!("Error running worker: {:?}", e);
@@ -577,6 +587,10 @@ impl VirtioDevice for Fs {
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         // We first must resume the virtio thread if it was paused.
         if self.common.pause_evt.take().is_some() {
@@ -671,11 +685,22 @@ impl Snapshottable for Fs {
     }
 
     fn snapshot(&mut self) -> std::result::Result<Snapshot, MigratableError> {
-        self.vu_common.snapshot(&self.id(), &self.state())
+        let mut state = self.state();
+        if self.common.paused.load(Ordering::SeqCst) {
+            state.backend_state = self.vu_common.save_device_state()?;
+        }
+
+        self.vu_common.snapshot(&self.id(), &state)
     }
 
     fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
-        self.set_state(&snapshot.to_versioned_state(&self.id)?);
+        let state: State = snapshot.to_versioned_state(&self.id)?;
+        self.set_state(&state);
+
+        if let Some(backend_state) = &state.backend_state {
+            self.vu_common.load_device_state(backend_state)?;
+        }
+
         Ok(())
     }
 }
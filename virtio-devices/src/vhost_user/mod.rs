@@ -3,9 +3,9 @@
 
 use crate::{
     ActivateError, EpollHelper, EpollHelperError, EpollHelperHandler, GuestMemoryMmap,
-    GuestRegionMmap, VirtioInterrupt, EPOLL_HELPER_EVENT_LAST, VIRTIO_F_IN_ORDER,
-    VIRTIO_F_NOTIFICATION_DATA, VIRTIO_F_ORDER_PLATFORM, VIRTIO_F_RING_EVENT_IDX,
-    VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1,
+    GuestRegionMmap, VirtioInterrupt, VirtioInterruptType, EPOLL_HELPER_EVENT_LAST,
+    VIRTIO_F_IN_ORDER, VIRTIO_F_NOTIFICATION_DATA, VIRTIO_F_ORDER_PLATFORM,
+    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1,
 };
 use anyhow::anyhow;
 use std::io;
@@ -25,8 +25,8 @@ use vm_memory::{
     GuestMemoryAtomic,
 };
 use vm_migration::{protocol::MemoryRangeTable, MigratableError, Snapshot, VersionMapped};
-use vmm_sys_util::eventfd::EventFd;
-use vu_common_ctrl::VhostUserHandle;
+use vmm_sys_util::eventfd::{EventFd, EFD_NONBLOCK};
+use vu_common_ctrl::{VhostUserHandle, VHOST_USER_PROTOCOL_F_DEVICE_STATE};
 
 pub mod blk;
 pub mod fs;
@@ -142,6 +142,16 @@ pub enum Error {
     NewMmapRegion(MmapRegionError),
     /// Could not find the shm log region
     MissingShmLogRegion,
+    /// Failed exchanging a device state message with the backend.
+    VhostUserDeviceStateRequest(vmm_sys_util::errno::Error),
+    /// Invalid reply to a device state message.
+    VhostUserDeviceStateInvalidReply,
+    /// The backend failed transferring its state.
+    VhostUserDeviceStateFailed(u64),
+    /// Failed transferring the device state.
+    VhostUserDeviceStateTransfer(io::Error),
+    /// Failed cloning a vring eventfd.
+    CloneVringEventFd(io::Error),
 }
 type Result<T> = std::result::Result<T, Error>;
 
@@ -155,6 +165,8 @@ pub const DEFAULT_VIRTIO_FEATURES: u64 = 1 << VIRTIO_F_RING_INDIRECT_DESC
 
 const HUP_CONNECTION_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 1;
 const SLAVE_REQ_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
+// The call eventfd of each queue is registered from this event on.
+const CALL_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 3;
 
 #[derive(Default)]
 pub struct Inflight {
@@ -170,6 +182,9 @@ pub struct VhostUserEpollHandler<S: VhostUserMasterReqHandler> {
     pub queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
     pub queue_evts: Vec<EventFd>,
     pub virtio_interrupt: Arc<dyn VirtioInterrupt>,
+    // Eventfds the backend signals the queues without a notifier through,
+    // such as the ones of the virtio-mmio transport.
+    pub call_evts: Vec<Option<EventFd>>,
     pub acked_features: u64,
     pub acked_protocol_features: u64,
     pub socket_path: String,
@@ -195,6 +210,12 @@ impl<S: VhostUserMasterReqHandler> VhostUserEpollHandler<S> {
             helper.add_event(slave_req_handler.as_raw_fd(), SLAVE_REQ_EVENT)?;
         }
 
+        for (queue_index, call_evt) in self.call_evts.iter().enumerate() {
+            if let Some(call_evt) = call_evt {
+                helper.add_event(call_evt.as_raw_fd(), CALL_EVENT + queue_index as u16)?;
+            }
+        }
+
         helper.run(paused, paused_sync, self)?;
 
         Ok(())
@@ -230,6 +251,7 @@ impl<S: VhostUserMasterReqHandler> VhostUserEpollHandler<S> {
                     .map(|q| q.try_clone().unwrap())
                     .collect(),
                 &self.virtio_interrupt,
+                &self.call_evts,
                 self.acked_features,
                 self.acked_protocol_features,
                 &self.slave_req_handler,
@@ -274,6 +296,21 @@ impl<S: VhostUserMasterReqHandler> EpollHelperHandler for VhostUserEpollHandler<
                     }
                 }
             }
+            ev_type if ev_type >= CALL_EVENT => {
+                let queue_index = ev_type - CALL_EVENT;
+                if let Some(Some(call_evt)) = self.call_evts.get(queue_index as usize) {
+                    // The eventfd may have been read already, the interrupt
+                    // being raised once for both signals.
+                    let _ = call_evt.read();
+                    if let Err(e) = self
+                        .virtio_interrupt
+                        .trigger(VirtioInterruptType::Queue(queue_index))
+                    {
+                        error!("Failed to signal used queue: {:?}", e);
+                        return true;
+                    }
+                }
+            }
             _ => {
                 error!("Unknown event for vhost-user thread");
                 return true;
@@ -319,6 +356,23 @@ impl VhostUserCommon {
             error!("Missing vhost-user handle");
             return Err(ActivateError::BadActivate);
         }
+        // The queues the transport can't provide a notifier for, such as
+        // the ones of the virtio-mmio transport, are signaled by the backend
+        // through eventfds forwarded by the epoll handler.
+        let call_evts = (0..queues.len())
+            .map(|queue_index| {
+                if interrupt_cb
+                    .notifier(VirtioInterruptType::Queue(queue_index as u16))
+                    .is_some()
+                {
+                    Ok(None)
+                } else {
+                    EventFd::new(EFD_NONBLOCK).map(Some)
+                }
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|_| ActivateError::VhostIrqCreate)?;
+
         let vu = self.vu.as_ref().unwrap();
         vu.lock()
             .unwrap()
@@ -327,6 +381,7 @@ impl VhostUserCommon {
                 queues.iter().map(vm_virtio::clone_queue).collect(),
                 queue_evts.iter().map(|q| q.try_clone().unwrap()).collect(),
                 &interrupt_cb,
+                &call_evts,
                 acked_features,
                 &slave_req_handler,
                 inflight.as_mut(),
@@ -341,6 +396,7 @@ impl VhostUserCommon {
             queues,
             queue_evts,
             virtio_interrupt: interrupt_cb,
+            call_evts,
             acked_features,
             acked_protocol_features: self.acked_protocol_features,
             socket_path: self.socket_path.clone(),
@@ -415,6 +471,9 @@ impl VhostUserCommon {
     }
 
     pub fn resume(&mut self) -> std::result::Result<(), MigratableError> {
+        // The device only resumes when the migration didn't complete.
+        self.migration_started = false;
+
         if let Some(vu) = &self.vu {
             vu.lock()
                 .unwrap()
@@ -440,7 +499,11 @@ impl VhostUserCommon {
     {
         let snapshot = Snapshot::new_from_versioned_state(id, state)?;
 
-        if self.migration_started {
+        // The backends transferring their state have been stopped instead,
+        // so that they can be restarted if the migration doesn't complete.
+        if self.migration_started
+            && self.acked_protocol_features & VHOST_USER_PROTOCOL_F_DEVICE_STATE == 0
+        {
             self.shutdown();
         }
 
@@ -512,6 +575,51 @@ impl VhostUserCommon {
         Ok(())
     }
 
+    /// Retrieves the internal state of the backend when the device is being
+    /// migrated, provided the backend supports transferring it. This stops
+    /// the backend, hence it must only be called in the final stage of the
+    /// migration, once the device is paused.
+    pub fn save_device_state(&mut self) -> std::result::Result<Option<Vec<u8>>, MigratableError> {
+        if !self.migration_started
+            || self.acked_protocol_features & VHOST_USER_PROTOCOL_F_DEVICE_STATE == 0
+        {
+            return Ok(None);
+        }
+
+        if let Some(vu) = &self.vu {
+            vu.lock()
+                .unwrap()
+                .save_device_state()
+                .map(Some)
+                .map_err(|e| {
+                    MigratableError::Snapshot(anyhow!(
+                        "Error saving vhost-user backend state: {:?}",
+                        e
+                    ))
+                })
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn load_device_state(&mut self, state: &[u8]) -> std::result::Result<(), MigratableError> {
+        if self.acked_protocol_features & VHOST_USER_PROTOCOL_F_DEVICE_STATE == 0 {
+            return Err(MigratableError::Restore(anyhow!(
+                "vhost-user backend doesn't support loading its state"
+            )));
+        }
+
+        if let Some(vu) = &self.vu {
+            vu.lock().unwrap().load_device_state(state).map_err(|e| {
+                MigratableError::Restore(anyhow!("Error loading vhost-user backend state: {:?}", e))
+            })
+        } else {
+            Err(MigratableError::Restore(anyhow!(
+                "Missing vhost-user handle"
+            )))
+        }
+    }
+
     pub fn complete_migration(
         &mut self,
         kill_evt: Option<EventFd>,
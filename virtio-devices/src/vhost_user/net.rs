@@ -12,6 +12,8 @@ use crate::{
 use crate::{GuestMemoryMmap, GuestRegionMmap};
 use net_util::{build_net_config_space, CtrlQueue, MacAddr, VirtioNetConfig};
 use seccompiler::SeccompAction;
+use std::collections::HashMap;
+use std::num::Wrapping;
 use std::result;
 use std::sync::{Arc, Barrier, Mutex};
 use std::thread;
@@ -289,13 +291,16 @@ impl VirtioDevice for Net {
 
             let (kill_evt, pause_evt) = self.common.dup_eventfds();
 
+            let mut ctrl_q = CtrlQueue::new(Vec::new());
+            ctrl_q.chain_validator = self
+                .common
+                .chain_validator(*ctrl_q.chain_validator.limits());
             let mut ctrl_handler = NetCtrlEpollHandler {
                 kill_evt,
                 pause_evt,
-                ctrl_q: CtrlQueue::new(Vec::new()),
+                ctrl_q,
                 queue: ctrl_queue,
                 queue_evt: ctrl_queue_evt,
-                access_platform: None,
                 interrupt_cb: interrupt_cb.clone(),
                 queue_index: ctrl_queue_index as u16,
             };
@@ -314,6 +319,7 @@ impl VirtioDevice for Net {
                 Thread::VirtioVhostNetCtl,
                 &mut epoll_threads,
                 &self.exit_evt,
+                self.common.thread_nice,
                 move || {
                     if let Err(e) = ctrl_handler.run_ctrl(paused, paused_sync.unwrap()) {
                         error!("Error running worker: {:?}", e);
@@ -354,6 +360,7 @@ impl VirtioDevice for Net {
             Thread::VirtioVhostNet,
             &mut epoll_threads,
             &self.exit_evt,
+            self.common.thread_nice,
             move || {
                 if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                     error!("Error running worker: {:?}", e);
@@ -365,6 +372,10 @@ impl VirtioDevice for Net {
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         // We first must resume the virtio thread if it was paused.
         if self.common.pause_evt.take().is_some() {
@@ -393,6 +404,10 @@ impl VirtioDevice for Net {
         Some(self.common.interrupt_cb.take().unwrap())
     }
 
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
+
     fn shutdown(&mut self) {
         self.vu_common.shutdown();
     }
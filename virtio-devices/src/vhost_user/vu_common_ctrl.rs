@@ -10,8 +10,10 @@ use crate::{
 use std::convert::TryInto;
 use std::ffi;
 use std::fs::File;
+use std::io::{Read, Write};
+use std::mem::ManuallyDrop;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::os::unix::net::UnixListener;
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread::sleep;
@@ -29,10 +31,34 @@ use vm_memory::{
 };
 use vm_migration::protocol::MemoryRangeTable;
 use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::sock_ctrl_msg::ScmSocket;
 
 // Size of a dirty page for vhost-user.
 const VHOST_LOG_PAGE: u64 = 0x1000;
 
+// The device state transfer isn't supported by the vhost crate, hence the
+// related messages are built here and exchanged directly on the socket.
+pub const VHOST_USER_PROTOCOL_F_DEVICE_STATE: u64 = 1 << 19;
+const VHOST_USER_GET_PROTOCOL_FEATURES: u32 = 15;
+const VHOST_USER_SET_DEVICE_STATE_FD: u32 = 42;
+const VHOST_USER_CHECK_DEVICE_STATE: u32 = 43;
+const VHOST_USER_VERSION: u32 = 0x1;
+const VHOST_USER_REPLY: u32 = 0x4;
+const VHOST_USER_HEADER_SIZE: usize = 12;
+const VHOST_USER_TRANSFER_STATE_DIRECTION_SAVE: u32 = 0;
+const VHOST_USER_TRANSFER_STATE_DIRECTION_LOAD: u32 = 1;
+const VHOST_USER_TRANSFER_STATE_PHASE_STOPPED: u32 = 0;
+// Set in the reply to SET_DEVICE_STATE_FD when the backend doesn't provide
+// its own file descriptor for the transfer.
+const VHOST_USER_DEVICE_STATE_INVALID_FD: u64 = 1 << 8;
+
+// The vhost crate doesn't keep the protocol features it doesn't know about,
+// while they must be acked along with the other ones.
+pub fn protocol_features_from_bits(bits: u64) -> VhostUserProtocolFeatures {
+    // SAFETY: the flags are only used to carry the bits to the backend.
+    unsafe { VhostUserProtocolFeatures::from_bits_unchecked(bits) }
+}
+
 #[derive(Debug, Clone)]
 pub struct VhostUserConfig {
     pub socket: String,
@@ -44,6 +70,9 @@ pub struct VhostUserConfig {
 struct VringInfo {
     config_data: VringConfigData,
     used_guest_addr: u64,
+    // Kept to restart the vring once stopped for a device state transfer.
+    kick_evt: Arc<EventFd>,
+    call_evt: Option<Arc<EventFd>>,
 }
 
 #[derive(Clone)]
@@ -54,6 +83,8 @@ pub struct VhostUserHandle {
     shm_log: Option<Arc<MmapRegion>>,
     acked_features: u64,
     vrings_info: Option<Vec<VringInfo>>,
+    // Bases of the vrings stopped to save the device state.
+    stopped_vring_bases: Option<Vec<u16>>,
 }
 
 impl VhostUserHandle {
@@ -120,11 +151,16 @@ impl VhostUserHandle {
 
         let acked_protocol_features =
             if acked_features & VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits() != 0 {
-                let backend_protocol_features = self
+                let mut backend_protocol_features = self
                     .vu
                     .get_protocol_features()
                     .map_err(Error::VhostUserGetProtocolFeatures)?;
 
+                if avail_protocol_features.bits() & VHOST_USER_PROTOCOL_F_DEVICE_STATE != 0 {
+                    backend_protocol_features |=
+                        protocol_features_from_bits(self.get_device_state_protocol_feature()?);
+                }
+
                 let acked_protocol_features = avail_protocol_features & backend_protocol_features;
 
                 self.vu
@@ -154,6 +190,7 @@ impl VhostUserHandle {
         queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
         queue_evts: Vec<EventFd>,
         virtio_interrupt: &Arc<dyn VirtioInterrupt>,
+        call_evts: &[Option<EventFd>],
         acked_features: u64,
         slave_req_handler: &Option<MasterReqHandler<S>>,
         inflight: Option<&mut Inflight>,
@@ -202,7 +239,7 @@ impl VhostUserHandle {
         let num_queues = queues.len() as usize;
 
         let mut vrings_info = Vec::new();
-        for (queue_index, queue) in queues.into_iter().enumerate() {
+        for (queue_index, (queue, queue_evt)) in queues.into_iter().zip(queue_evts).enumerate() {
             let actual_size: usize = queue.max_size().try_into().unwrap();
 
             let config_data = VringConfigData {
@@ -234,11 +271,6 @@ impl VhostUserHandle {
                 log_addr: None,
             };
 
-            vrings_info.push(VringInfo {
-                config_data,
-                used_guest_addr: queue.state.used_ring.raw_value(),
-            });
-
             self.vu
                 .set_vring_addr(queue_index, &config_data)
                 .map_err(Error::VhostUserSetVringAddr)?;
@@ -252,17 +284,29 @@ impl VhostUserHandle {
                 )
                 .map_err(Error::VhostUserSetVringBase)?;
 
-            if let Some(eventfd) =
+            // The queues the transport can't provide a notifier for are
+            // signaled through an eventfd forwarded by the VMM.
+            let call_evt = if let Some(Some(call_evt)) = call_evts.get(queue_index) {
+                Some(call_evt.try_clone().map_err(Error::CloneVringEventFd)?)
+            } else {
                 virtio_interrupt.notifier(VirtioInterruptType::Queue(queue_index as u16))
-            {
+            };
+            if let Some(call_evt) = &call_evt {
                 self.vu
-                    .set_vring_call(queue_index, &eventfd)
+                    .set_vring_call(queue_index, call_evt)
                     .map_err(Error::VhostUserSetVringCall)?;
             }
 
             self.vu
-                .set_vring_kick(queue_index, &queue_evts[queue_index])
+                .set_vring_kick(queue_index, &queue_evt)
                 .map_err(Error::VhostUserSetVringKick)?;
+
+            vrings_info.push(VringInfo {
+                config_data,
+                used_guest_addr: queue.state.used_ring.raw_value(),
+                kick_evt: Arc::new(queue_evt),
+                call_evt: call_evt.map(Arc::new),
+            });
         }
 
         self.enable_vhost_user_vrings(num_queues, true)?;
@@ -274,6 +318,7 @@ impl VhostUserHandle {
         }
 
         self.vrings_info = Some(vrings_info);
+        self.stopped_vring_bases = None;
         self.ready = true;
 
         Ok(())
@@ -307,16 +352,13 @@ impl VhostUserHandle {
             .map_err(Error::VhostUserGetFeatures)?;
 
         if acked_features & VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits() != 0 {
-            if let Some(acked_protocol_features) =
-                VhostUserProtocolFeatures::from_bits(acked_protocol_features)
-            {
-                self.vu
-                    .set_protocol_features(acked_protocol_features)
-                    .map_err(Error::VhostUserSetProtocolFeatures)?;
+            let acked_protocol_features = protocol_features_from_bits(acked_protocol_features);
+            self.vu
+                .set_protocol_features(acked_protocol_features)
+                .map_err(Error::VhostUserSetProtocolFeatures)?;
 
-                if acked_protocol_features.contains(VhostUserProtocolFeatures::REPLY_ACK) {
-                    self.vu.set_hdr_flags(VhostUserHeaderFlag::NEED_REPLY);
-                }
+            if acked_protocol_features.contains(VhostUserProtocolFeatures::REPLY_ACK) {
+                self.vu.set_hdr_flags(VhostUserHeaderFlag::NEED_REPLY);
             }
         }
 
@@ -332,6 +374,7 @@ impl VhostUserHandle {
         queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
         queue_evts: Vec<EventFd>,
         virtio_interrupt: &Arc<dyn VirtioInterrupt>,
+        call_evts: &[Option<EventFd>],
         acked_features: u64,
         acked_protocol_features: u64,
         slave_req_handler: &Option<MasterReqHandler<S>>,
@@ -344,6 +387,7 @@ impl VhostUserHandle {
             queues,
             queue_evts,
             virtio_interrupt,
+            call_evts,
             acked_features,
             slave_req_handler,
             inflight,
@@ -373,6 +417,7 @@ impl VhostUserHandle {
                 shm_log: None,
                 acked_features: 0,
                 vrings_info: None,
+                stopped_vring_bases: None,
             })
         } else {
             let now = Instant::now();
@@ -388,6 +433,7 @@ impl VhostUserHandle {
                             shm_log: None,
                             acked_features: 0,
                             vrings_info: None,
+                            stopped_vring_bases: None,
                         })
                     }
                     Err(e) => e,
@@ -420,6 +466,29 @@ impl VhostUserHandle {
     }
 
     pub fn resume_vhost_user(&mut self, num_queues: usize) -> Result<()> {
+        // The vrings stopped to save the device state are restarted from
+        // where they were stopped, as the device keeps running here when
+        // the migration doesn't complete.
+        if let (Some(bases), Some(vrings_info)) =
+            (self.stopped_vring_bases.take(), &self.vrings_info)
+        {
+            for (queue_index, (base, vring_info)) in bases.into_iter().zip(vrings_info).enumerate()
+            {
+                self.vu
+                    .set_vring_base(queue_index, base)
+                    .map_err(Error::VhostUserSetVringBase)?;
+                if let Some(call_evt) = &vring_info.call_evt {
+                    self.vu
+                        .set_vring_call(queue_index, call_evt)
+                        .map_err(Error::VhostUserSetVringCall)?;
+                }
+                self.vu
+                    .set_vring_kick(queue_index, &vring_info.kick_evt)
+                    .map_err(Error::VhostUserSetVringKick)?;
+            }
+            self.ready = true;
+        }
+
         if self.ready {
             self.enable_vhost_user_vrings(num_queues, true)?;
         }
@@ -571,6 +640,154 @@ impl VhostUserHandle {
             Err(Error::MissingShmLogRegion)
         }
     }
+
+    fn send_request(&mut self, request: u32, payload: &[u8], fds: &[RawFd]) -> Result<()> {
+        let mut hdr = [0u8; VHOST_USER_HEADER_SIZE];
+        hdr[0..4].copy_from_slice(&request.to_le_bytes());
+        hdr[4..8].copy_from_slice(&VHOST_USER_VERSION.to_le_bytes());
+        hdr[8..12].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+
+        // The socket is owned by the vhost-user master, it must not be closed.
+        let sock = ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(self.vu.as_raw_fd()) });
+        sock.send_with_fds(&[&hdr[..], payload], fds)
+            .map_err(Error::VhostUserDeviceStateRequest)?;
+
+        Ok(())
+    }
+
+    // All the requests sent directly are replied with a single u64.
+    fn recv_reply(&mut self, request: u32) -> Result<(u64, Option<File>)> {
+        let mut reply = [0u8; VHOST_USER_HEADER_SIZE + 8];
+        let mut fds = [-1; 1];
+
+        let sock = ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(self.vu.as_raw_fd()) });
+        let (len, fd_count) = sock
+            .recv_with_fds(&mut [&mut reply[..]], &mut fds)
+            .map_err(Error::VhostUserDeviceStateRequest)?;
+
+        let file = if fd_count > 0 {
+            Some(unsafe { File::from_raw_fd(fds[0]) })
+        } else {
+            None
+        };
+
+        let reply_request = u32::from_le_bytes(reply[0..4].try_into().unwrap());
+        let flags = u32::from_le_bytes(reply[4..8].try_into().unwrap());
+        let size = u32::from_le_bytes(reply[8..12].try_into().unwrap());
+        if len != reply.len()
+            || reply_request != request
+            || flags & VHOST_USER_REPLY == 0
+            || size != 8
+        {
+            return Err(Error::VhostUserDeviceStateInvalidReply);
+        }
+
+        Ok((
+            u64::from_le_bytes(reply[VHOST_USER_HEADER_SIZE..].try_into().unwrap()),
+            file,
+        ))
+    }
+
+    // Returns the DEVICE_STATE protocol feature if the backend supports it,
+    // as the vhost crate drops it from the backend protocol features.
+    fn get_device_state_protocol_feature(&mut self) -> Result<u64> {
+        self.send_request(VHOST_USER_GET_PROTOCOL_FEATURES, &[], &[])?;
+        let (backend_protocol_features, _) = self.recv_reply(VHOST_USER_GET_PROTOCOL_FEATURES)?;
+
+        Ok(backend_protocol_features & VHOST_USER_PROTOCOL_F_DEVICE_STATE)
+    }
+
+    // Hands over one end of a pipe to the backend for the state transfer,
+    // and returns the file the VMM must use on its side. This is either the
+    // backend provided one, or the other end of the pipe.
+    fn set_device_state_fd(&mut self, direction: u32) -> Result<File> {
+        let mut fds = [-1; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(Error::VhostUserDeviceStateTransfer(
+                std::io::Error::last_os_error(),
+            ));
+        }
+        let read_end = unsafe { File::from_raw_fd(fds[0]) };
+        let write_end = unsafe { File::from_raw_fd(fds[1]) };
+
+        let (backend_end, vmm_end) = if direction == VHOST_USER_TRANSFER_STATE_DIRECTION_SAVE {
+            (write_end, read_end)
+        } else {
+            (read_end, write_end)
+        };
+
+        let mut payload = [0u8; 8];
+        payload[0..4].copy_from_slice(&direction.to_le_bytes());
+        payload[4..8].copy_from_slice(&VHOST_USER_TRANSFER_STATE_PHASE_STOPPED.to_le_bytes());
+        self.send_request(
+            VHOST_USER_SET_DEVICE_STATE_FD,
+            &payload,
+            &[backend_end.as_raw_fd()],
+        )?;
+        // Close the backend end of the pipe so that the transfer ends when
+        // the backend closes its own copy.
+        drop(backend_end);
+
+        let (status, file) = self.recv_reply(VHOST_USER_SET_DEVICE_STATE_FD)?;
+        if status & 0xff != 0 {
+            return Err(Error::VhostUserDeviceStateFailed(status));
+        }
+
+        if status & VHOST_USER_DEVICE_STATE_INVALID_FD == 0 {
+            file.ok_or(Error::VhostUserDeviceStateInvalidReply)
+        } else {
+            Ok(vmm_end)
+        }
+    }
+
+    fn check_device_state(&mut self) -> Result<()> {
+        self.send_request(VHOST_USER_CHECK_DEVICE_STATE, &[], &[])?;
+        let (status, _) = self.recv_reply(VHOST_USER_CHECK_DEVICE_STATE)?;
+        if status != 0 {
+            return Err(Error::VhostUserDeviceStateFailed(status));
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves the internal state of the backend. The vrings are stopped
+    /// in the process, they are only restarted by `resume_vhost_user()` if
+    /// the VM keeps running here.
+    pub fn save_device_state(&mut self) -> Result<Vec<u8>> {
+        if let Some(vrings_info) = &self.vrings_info {
+            let mut bases = Vec::with_capacity(vrings_info.len());
+            for queue_index in 0..vrings_info.len() {
+                let base = self
+                    .vu
+                    .get_vring_base(queue_index)
+                    .map_err(Error::VhostUserGetVringBase)?;
+                bases.push(base as u16);
+            }
+            self.stopped_vring_bases = Some(bases);
+        }
+        self.ready = false;
+
+        let mut file = self.set_device_state_fd(VHOST_USER_TRANSFER_STATE_DIRECTION_SAVE)?;
+        let mut state = Vec::new();
+        file.read_to_end(&mut state)
+            .map_err(Error::VhostUserDeviceStateTransfer)?;
+        drop(file);
+
+        self.check_device_state()?;
+
+        Ok(state)
+    }
+
+    /// Loads a state previously saved from another backend, before the
+    /// device is started.
+    pub fn load_device_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut file = self.set_device_state_fd(VHOST_USER_TRANSFER_STATE_DIRECTION_LOAD)?;
+        file.write_all(state)
+            .map_err(Error::VhostUserDeviceStateTransfer)?;
+        drop(file);
+
+        self.check_device_state()
+    }
 }
 
 fn memfd_create(name: &ffi::CStr, flags: u32) -> std::result::Result<RawFd, std::io::Error> {
@@ -817,15 +817,9 @@ mod tests {
             let vsock_test_ctx = TestContext::new();
             let mut handler_ctx = vsock_test_ctx.create_epoll_handler_context();
             let stream = TestStream::new();
-            let mut pkt = VsockPacket::from_rx_virtq_head(
-                &mut handler_ctx.handler.queues[0]
-                    .iter()
-                    .unwrap()
-                    .next()
-                    .unwrap(),
-                None,
-            )
-            .unwrap();
+            let mut pkt =
+                VsockPacket::from_rx_virtq_head(&handler_ctx.valid_chain(0), &vsock_test_ctx.mem)
+                    .unwrap();
             let conn = match conn_state {
                 ConnState::PeerInit => VsockConnection::<TestStream>::new_peer_init(
                     stream,
@@ -39,7 +39,9 @@ use crate::{
 };
 use byteorder::{ByteOrder, LittleEndian};
 use seccompiler::SeccompAction;
+use std::collections::HashMap;
 use std::io;
+use std::num::Wrapping;
 use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::result;
@@ -52,6 +54,7 @@ use vm_memory::GuestMemoryAtomic;
 use vm_migration::{
     Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable, VersionMapped,
 };
+use vm_virtio::chain::{ChainLimits, ChainValidator};
 use vm_virtio::AccessPlatform;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -94,7 +97,7 @@ pub struct VsockEpollHandler<B: VsockBackend> {
     pub pause_evt: EventFd,
     pub interrupt_cb: Arc<dyn VirtioInterrupt>,
     pub backend: Arc<RwLock<B>>,
-    pub access_platform: Option<Arc<dyn AccessPlatform>>,
+    pub chain_validator: ChainValidator,
 }
 
 impl<B> VsockEpollHandler<B>
@@ -126,10 +129,17 @@ where
 
         let mut avail_iter = self.queues[0].iter().map_err(DeviceError::QueueIterator)?;
         for mut desc_chain in &mut avail_iter {
-            let used_len = match VsockPacket::from_rx_virtq_head(
-                &mut desc_chain,
-                self.access_platform.as_ref(),
-            ) {
+            let valid_chain = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain,
+                Err(_) => {
+                    used_desc_heads[used_count] = (desc_chain.head_index(), 0);
+                    used_count += 1;
+                    continue;
+                }
+            };
+
+            let used_len = match VsockPacket::from_rx_virtq_head(&valid_chain, desc_chain.memory())
+            {
                 Ok(mut pkt) => {
                     if self.backend.write().unwrap().recv_pkt(&mut pkt).is_ok() {
                         pkt.hdr().len() as u32 + pkt.len()
@@ -174,10 +184,16 @@ where
 
         let mut avail_iter = self.queues[1].iter().map_err(DeviceError::QueueIterator)?;
         for mut desc_chain in &mut avail_iter {
-            let pkt = match VsockPacket::from_tx_virtq_head(
-                &mut desc_chain,
-                self.access_platform.as_ref(),
-            ) {
+            let valid_chain = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain,
+                Err(_) => {
+                    used_desc_heads[used_count] = (desc_chain.head_index(), 0);
+                    used_count += 1;
+                    continue;
+                }
+            };
+
+            let pkt = match VsockPacket::from_tx_virtq_head(&valid_chain, desc_chain.memory()) {
                 Ok(pkt) => pkt,
                 Err(e) => {
                     error!("vsock: error reading TX packet: {:?}", e);
@@ -446,7 +462,7 @@ where
             pause_evt,
             interrupt_cb,
             backend: self.backend.clone(),
-            access_platform: self.common.access_platform.clone(),
+            chain_validator: self.common.chain_validator(ChainLimits::default()),
         };
 
         let paused = self.common.paused.clone();
@@ -459,6 +475,7 @@ where
             Thread::VirtioVsock,
             &mut epoll_threads,
             &self.exit_evt,
+            self.common.thread_nice,
             move || {
                 if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                     error!("Error running worker: {:?}", e);
@@ -472,6 +489,10 @@ where
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
@@ -482,6 +503,10 @@ where
         std::fs::remove_file(&self.path).ok();
     }
 
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
+
     fn set_access_platform(&mut self, access_platform: Arc<dyn AccessPlatform>) {
         self.common.set_access_platform(access_platform)
     }
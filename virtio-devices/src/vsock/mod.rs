@@ -172,6 +172,7 @@ mod tests {
     use std::sync::{Arc, RwLock};
     use virtio_queue::{defs::VIRTQ_DESC_F_NEXT, defs::VIRTQ_DESC_F_WRITE};
     use vm_memory::{GuestAddress, GuestMemoryAtomic};
+    use vm_virtio::chain::{validate_chain, ChainLimits, ChainValidator, ValidChain};
     use vm_virtio::queue::testing::VirtQueue as GuestQ;
     use vmm_sys_util::eventfd::EventFd;
 
@@ -328,7 +329,11 @@ mod tests {
                     pause_evt: EventFd::new(EFD_NONBLOCK).unwrap(),
                     interrupt_cb,
                     backend: Arc::new(RwLock::new(TestBackend::new())),
-                    access_platform: None,
+                    chain_validator: ChainValidator::new(
+                        ChainLimits::default(),
+                        None,
+                        Arc::default(),
+                    ),
                 },
             }
         }
@@ -342,6 +347,14 @@ mod tests {
     }
 
     impl<'a> EpollHandlerContext<'a> {
+        pub fn valid_chain(&mut self, queue_index: usize) -> ValidChain {
+            let mut desc_chain = self.handler.queues[queue_index]
+                .iter()
+                .unwrap()
+                .next()
+                .unwrap();
+            validate_chain(&mut desc_chain, &ChainLimits::default(), None).unwrap()
+        }
         pub fn signal_txq_event(&mut self) {
             self.handler.queue_evts[1].write(1).unwrap();
             let events = epoll::Events::EPOLLIN;
@@ -16,14 +16,11 @@
 /// to temporary buffers, before passing it on to the vsock backend.
 ///
 use byteorder::{ByteOrder, LittleEndian};
-use std::sync::Arc;
 
 use super::defs;
 use super::{Result, VsockError};
 use crate::{get_host_address_range, GuestMemoryMmap};
-use virtio_queue::DescriptorChain;
-use vm_memory::GuestMemoryLoadGuard;
-use vm_virtio::{AccessPlatform, Translatable};
+use vm_virtio::chain::ValidChain;
 
 // The vsock packet header is defined by the C struct:
 //
@@ -107,11 +104,9 @@ impl VsockPacket {
     /// descriptor can optionally end the chain. Bounds and pointer checks are performed when
     /// creating the wrapper.
     ///
-    pub fn from_tx_virtq_head(
-        desc_chain: &mut DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
-        access_platform: Option<&Arc<dyn AccessPlatform>>,
-    ) -> Result<Self> {
-        let head = desc_chain.next().ok_or(VsockError::HdrDescMissing)?;
+    pub fn from_tx_virtq_head(desc_chain: &ValidChain, mem: &GuestMemoryMmap) -> Result<Self> {
+        let mut descs = desc_chain.descriptors().iter();
+        let head = descs.next().ok_or(VsockError::HdrDescMissing)?;
 
         // All buffers in the TX queue must be readable.
         //
@@ -125,13 +120,8 @@ impl VsockPacket {
         }
 
         let mut pkt = Self {
-            hdr: get_host_address_range(
-                desc_chain.memory(),
-                head.addr()
-                    .translate_gva(access_platform, head.len() as usize),
-                VSOCK_PKT_HDR_SIZE,
-            )
-            .ok_or(VsockError::GuestMemory)? as *mut u8,
+            hdr: get_host_address_range(mem, head.addr(), VSOCK_PKT_HDR_SIZE)
+                .ok_or(VsockError::GuestMemory)? as *mut u8,
             buf: None,
             buf_size: 0,
         };
@@ -148,7 +138,7 @@ impl VsockPacket {
         }
 
         // If the packet header showed a non-zero length, there should be a data descriptor here.
-        let buf_desc = desc_chain.next().ok_or(VsockError::BufDescMissing)?;
+        let buf_desc = descs.next().ok_or(VsockError::BufDescMissing)?;
 
         // TX data should be read-only.
         if buf_desc.is_write_only() {
@@ -163,14 +153,8 @@ impl VsockPacket {
 
         pkt.buf_size = buf_desc.len() as usize;
         pkt.buf = Some(
-            get_host_address_range(
-                desc_chain.memory(),
-                buf_desc
-                    .addr()
-                    .translate_gva(access_platform, buf_desc.len() as usize),
-                pkt.buf_size,
-            )
-            .ok_or(VsockError::GuestMemory)? as *mut u8,
+            get_host_address_range(mem, buf_desc.addr(), pkt.buf_size)
+                .ok_or(VsockError::GuestMemory)? as *mut u8,
         );
 
         Ok(pkt)
@@ -181,11 +165,9 @@ impl VsockPacket {
     /// There must be two descriptors in the chain, both writable: a header descriptor and a data
     /// descriptor. Bounds and pointer checks are performed when creating the wrapper.
     ///
-    pub fn from_rx_virtq_head(
-        desc_chain: &mut DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
-        access_platform: Option<&Arc<dyn AccessPlatform>>,
-    ) -> Result<Self> {
-        let head = desc_chain.next().ok_or(VsockError::HdrDescMissing)?;
+    pub fn from_rx_virtq_head(desc_chain: &ValidChain, mem: &GuestMemoryMmap) -> Result<Self> {
+        let mut descs = desc_chain.descriptors().iter();
+        let head = descs.next().ok_or(VsockError::HdrDescMissing)?;
 
         // All RX buffers must be writable.
         //
@@ -199,29 +181,15 @@ impl VsockPacket {
         }
 
         // All RX descriptor chains should have a header and a data descriptor.
-        if !head.has_next() {
-            return Err(VsockError::BufDescMissing);
-        }
-        let buf_desc = desc_chain.next().ok_or(VsockError::BufDescMissing)?;
+        let buf_desc = descs.next().ok_or(VsockError::BufDescMissing)?;
         let buf_size = buf_desc.len() as usize;
 
         Ok(Self {
-            hdr: get_host_address_range(
-                desc_chain.memory(),
-                head.addr()
-                    .translate_gva(access_platform, head.len() as usize),
-                VSOCK_PKT_HDR_SIZE,
-            )
-            .ok_or(VsockError::GuestMemory)? as *mut u8,
-            buf: Some(
-                get_host_address_range(
-                    desc_chain.memory(),
-                    buf_desc
-                        .addr()
-                        .translate_gva(access_platform, buf_desc.len() as usize),
-                    buf_size,
-                )
+            hdr: get_host_address_range(mem, head.addr(), VSOCK_PKT_HDR_SIZE)
                 .ok_or(VsockError::GuestMemory)? as *mut u8,
+            buf: Some(
+                get_host_address_range(mem, buf_desc.addr(), buf_size)
+                    .ok_or(VsockError::GuestMemory)? as *mut u8,
             ),
             buf_size,
         })
@@ -400,14 +368,7 @@ mod tests {
             expect_asm_error!($test_ctx, $handler_ctx, $err, from_rx_virtq_head, 0);
         };
         ($test_ctx:expr, $handler_ctx:expr, $err:pat, $ctor:ident, $vq:expr) => {
-            match VsockPacket::$ctor(
-                &mut $handler_ctx.handler.queues[$vq]
-                    .iter()
-                    .unwrap()
-                    .next()
-                    .unwrap(),
-                None,
-            ) {
+            match VsockPacket::$ctor(&$handler_ctx.valid_chain($vq), &$test_ctx.mem) {
                 Err($err) => (),
                 Ok(_) => panic!("Packet assembly should've failed!"),
                 Err(other) => panic!("Packet assembly failed with: {:?}", other),
@@ -431,15 +392,8 @@ mod tests {
         {
             create_context!(test_ctx, handler_ctx);
 
-            let pkt = VsockPacket::from_tx_virtq_head(
-                &mut handler_ctx.handler.queues[1]
-                    .iter()
-                    .unwrap()
-                    .next()
-                    .unwrap(),
-                None,
-            )
-            .unwrap();
+            let pkt = VsockPacket::from_tx_virtq_head(&handler_ctx.valid_chain(1), &test_ctx.mem)
+                .unwrap();
             assert_eq!(pkt.hdr().len(), VSOCK_PKT_HDR_SIZE);
             assert_eq!(
                 pkt.buf().unwrap().len(),
@@ -469,15 +423,9 @@ mod tests {
         {
             create_context!(test_ctx, handler_ctx);
             set_pkt_len(0, &handler_ctx.guest_txvq.dtable[0], &test_ctx.mem);
-            let mut pkt = VsockPacket::from_tx_virtq_head(
-                &mut handler_ctx.handler.queues[1]
-                    .iter()
-                    .unwrap()
-                    .next()
-                    .unwrap(),
-                None,
-            )
-            .unwrap();
+            let mut pkt =
+                VsockPacket::from_tx_virtq_head(&handler_ctx.valid_chain(1), &test_ctx.mem)
+                    .unwrap();
             assert!(pkt.buf().is_none());
             assert!(pkt.buf_mut().is_none());
         }
@@ -527,15 +475,8 @@ mod tests {
         // Test case: successful RX packet assembly.
         {
             create_context!(test_ctx, handler_ctx);
-            let pkt = VsockPacket::from_rx_virtq_head(
-                &mut handler_ctx.handler.queues[0]
-                    .iter()
-                    .unwrap()
-                    .next()
-                    .unwrap(),
-                None,
-            )
-            .unwrap();
+            let pkt = VsockPacket::from_rx_virtq_head(&handler_ctx.valid_chain(0), &test_ctx.mem)
+                .unwrap();
             assert_eq!(pkt.hdr().len(), VSOCK_PKT_HDR_SIZE);
             assert_eq!(
                 pkt.buf().unwrap().len(),
@@ -584,15 +525,8 @@ mod tests {
         const FWD_CNT: u32 = 10;
 
         create_context!(test_ctx, handler_ctx);
-        let mut pkt = VsockPacket::from_rx_virtq_head(
-            &mut handler_ctx.handler.queues[0]
-                .iter()
-                .unwrap()
-                .next()
-                .unwrap(),
-            None,
-        )
-        .unwrap();
+        let mut pkt =
+            VsockPacket::from_rx_virtq_head(&handler_ctx.valid_chain(0), &test_ctx.mem).unwrap();
 
         // Test field accessors.
         pkt.set_src_cid(SRC_CID)
@@ -675,15 +609,8 @@ mod tests {
     #[test]
     fn test_packet_buf() {
         create_context!(test_ctx, handler_ctx);
-        let mut pkt = VsockPacket::from_rx_virtq_head(
-            &mut handler_ctx.handler.queues[0]
-                .iter()
-                .unwrap()
-                .next()
-                .unwrap(),
-            None,
-        )
-        .unwrap();
+        let mut pkt =
+            VsockPacket::from_rx_virtq_head(&handler_ctx.valid_chain(0), &test_ctx.mem).unwrap();
 
         assert_eq!(
             pkt.buf().unwrap().len(),
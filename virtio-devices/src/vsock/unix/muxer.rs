@@ -840,15 +840,9 @@ mod tests {
         fn new(name: &str) -> Self {
             let vsock_test_ctx = VsockTestContext::new();
             let mut handler_ctx = vsock_test_ctx.create_epoll_handler_context();
-            let pkt = VsockPacket::from_rx_virtq_head(
-                &mut handler_ctx.handler.queues[0]
-                    .iter()
-                    .unwrap()
-                    .next()
-                    .unwrap(),
-                None,
-            )
-            .unwrap();
+            let pkt =
+                VsockPacket::from_rx_virtq_head(&handler_ctx.valid_chain(0), &vsock_test_ctx.mem)
+                    .unwrap();
             let uds_path = format!("test_vsock_{}.sock", name);
             let muxer = VsockMuxer::new(PEER_CID, uds_path).unwrap();
 
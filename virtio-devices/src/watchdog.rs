@@ -16,8 +16,10 @@ use crate::GuestMemoryMmap;
 use crate::{VirtioInterrupt, VirtioInterruptType};
 use anyhow::anyhow;
 use seccompiler::SeccompAction;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, Read};
+use std::num::Wrapping;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::result;
 use std::sync::atomic::AtomicBool;
@@ -29,6 +31,7 @@ use virtio_queue::Queue;
 use vm_memory::{Bytes, GuestMemoryAtomic};
 use vm_migration::VersionMapped;
 use vm_migration::{Migratable, MigratableError, Pausable, Snapshot, Snapshottable, Transportable};
+use vm_virtio::chain::{ChainLimits, ChainValidator};
 use vmm_sys_util::eventfd::EventFd;
 
 const QUEUE_SIZE: u16 = 8;
@@ -41,10 +44,7 @@ const TIMER_EXPIRED_EVENT: u16 = EPOLL_HELPER_EVENT_LAST + 2;
 
 // Number of seconds to check to see if there has been a ping
 // This needs to match what the driver is using.
-const WATCHDOG_TIMER_INTERVAL: i64 = 15;
-
-// Number of seconds since last ping to trigger reboot
-const WATCHDOG_TIMEOUT: u64 = WATCHDOG_TIMER_INTERVAL as u64 + 5;
+pub const WATCHDOG_TIMER_INTERVAL: i64 = 15;
 
 struct WatchdogEpollHandler {
     queues: Vec<Queue<GuestMemoryAtomic<GuestMemoryMmap>>>,
@@ -54,7 +54,9 @@ struct WatchdogEpollHandler {
     pause_evt: EventFd,
     timer: File,
     last_ping_time: Arc<Mutex<Option<Instant>>>,
-    reset_evt: EventFd,
+    timeout: u64,
+    watchdog_evt: EventFd,
+    chain_validator: ChainValidator,
 }
 
 impl WatchdogEpollHandler {
@@ -65,7 +67,14 @@ impl WatchdogEpollHandler {
         let mut used_desc_heads = [(0, 0); QUEUE_SIZE as usize];
         let mut used_count = 0;
         for mut desc_chain in queue.iter().unwrap() {
-            let desc = desc_chain.next().unwrap();
+            let desc = match self.chain_validator.validate(&mut desc_chain) {
+                Ok(valid_chain) => valid_chain.descriptors()[0],
+                Err(_) => {
+                    used_desc_heads[used_count] = (desc_chain.head_index(), 0);
+                    used_count += 1;
+                    continue;
+                }
+            };
 
             let mut len = 0;
 
@@ -140,12 +149,14 @@ impl EpollHelperHandler for WatchdogEpollHandler {
                     error!("Error reading from timer fd: {:}", e);
                     return true;
                 }
-                if let Some(last_ping_time) = self.last_ping_time.lock().unwrap().as_ref() {
-                    let now = Instant::now();
-                    let gap = now.duration_since(*last_ping_time).as_secs();
-                    if gap > WATCHDOG_TIMEOUT {
+                let mut last_ping_time = self.last_ping_time.lock().unwrap();
+                if let Some(gap) = last_ping_time.map(|time| time.elapsed().as_secs()) {
+                    if gap > self.timeout {
                         error!("Watchdog triggered: {} seconds since last ping", gap);
-                        self.reset_evt.write(1).ok();
+                        // Not triggered again until the next ping, in case
+                        // the VM keeps running.
+                        last_ping_time.take();
+                        self.watchdog_evt.write(1).ok();
                     }
                 }
                 return false;
@@ -164,8 +175,9 @@ pub struct Watchdog {
     common: VirtioCommon,
     id: String,
     seccomp_action: SeccompAction,
-    reset_evt: EventFd,
+    watchdog_evt: EventFd,
     last_ping_time: Arc<Mutex<Option<Instant>>>,
+    timeout: u64,
     timer: File,
     exit_evt: EventFd,
 }
@@ -180,10 +192,13 @@ pub struct WatchdogState {
 impl VersionMapped for WatchdogState {}
 
 impl Watchdog {
-    /// Create a new virtio watchdog device that will reboot VM if the guest hangs
+    /// Create a new virtio watchdog device that will signal the watchdog
+    /// event if the guest doesn't ping it for longer than the timeout, in
+    /// seconds.
     pub fn new(
         id: String,
-        reset_evt: EventFd,
+        watchdog_evt: EventFd,
+        timeout: u64,
         seccomp_action: SeccompAction,
         exit_evt: EventFd,
     ) -> io::Result<Watchdog> {
@@ -204,8 +219,9 @@ impl Watchdog {
             },
             id,
             seccomp_action,
-            reset_evt,
+            watchdog_evt,
             last_ping_time: Arc::new(Mutex::new(None)),
+            timeout,
             timer,
             exit_evt,
         })
@@ -297,8 +313,8 @@ impl VirtioDevice for Watchdog {
         self.common.activate(&queues, &queue_evts, &interrupt_cb)?;
         let (kill_evt, pause_evt) = self.common.dup_eventfds();
 
-        let reset_evt = self.reset_evt.try_clone().map_err(|e| {
-            error!("Failed to clone reset_evt eventfd: {}", e);
+        let watchdog_evt = self.watchdog_evt.try_clone().map_err(|e| {
+            error!("Failed to clone watchdog_evt eventfd: {}", e);
             ActivateError::BadActivate
         })?;
 
@@ -315,7 +331,12 @@ impl VirtioDevice for Watchdog {
             pause_evt,
             timer,
             last_ping_time: self.last_ping_time.clone(),
-            reset_evt,
+            timeout: self.timeout,
+            watchdog_evt,
+            chain_validator: self.common.chain_validator(ChainLimits {
+                min_writable: 1,
+                ..Default::default()
+            }),
         };
 
         let paused = self.common.paused.clone();
@@ -328,6 +349,7 @@ impl VirtioDevice for Watchdog {
             Thread::VirtioWatchdog,
             &mut epoll_threads,
             &self.exit_evt,
+            self.common.thread_nice,
             move || {
                 if let Err(e) = handler.run(paused, paused_sync.unwrap()) {
                     error!("Error running worker: {:?}", e);
@@ -341,11 +363,19 @@ impl VirtioDevice for Watchdog {
         Ok(())
     }
 
+    fn set_thread_nice(&mut self, nice: Option<i32>) {
+        self.common.thread_nice = nice;
+    }
+
     fn reset(&mut self) -> Option<Arc<dyn VirtioInterrupt>> {
         let result = self.common.reset();
         event!("virtio-device", "reset", "id", &self.id);
         result
     }
+
+    fn counters(&self) -> Option<HashMap<&'static str, Wrapping<u64>>> {
+        Some(self.common.counters())
+    }
 }
 
 impl Pausable for Watchdog {
@@ -4,6 +4,7 @@
 
 #[cfg(target_arch = "x86_64")]
 use std::collections::btree_map::BTreeMap;
+use std::collections::BTreeSet;
 use std::result;
 
 #[derive(Debug)]
@@ -35,6 +36,7 @@ pub struct GsiAllocator {
     apics: BTreeMap<u32, u32>,
     next_irq: u32,
     next_gsi: u32,
+    reserved_irqs: BTreeSet<u32>,
 }
 
 impl GsiAllocator {
@@ -45,6 +47,7 @@ impl GsiAllocator {
             apics: BTreeMap::new(),
             next_irq: 0xffff_ffff,
             next_gsi: 0,
+            reserved_irqs: BTreeSet::new(),
         };
 
         for apic in &apics {
@@ -69,6 +72,7 @@ impl GsiAllocator {
         GsiAllocator {
             next_irq: arch::IRQ_BASE,
             next_gsi: arch::IRQ_BASE,
+            reserved_irqs: BTreeSet::new(),
         }
     }
 
@@ -79,9 +83,23 @@ impl GsiAllocator {
         Ok(gsi)
     }
 
-    #[cfg(target_arch = "x86_64")]
-    /// Allocate an IRQ
+    /// Reserve an IRQ, which is then never returned by `allocate_irq`
+    pub fn reserve_irq(&mut self, irq: u32) {
+        self.reserved_irqs.insert(irq);
+    }
+
+    /// Allocate an IRQ, skipping the reserved ones
     pub fn allocate_irq(&mut self) -> Result<u32> {
+        loop {
+            let irq = self.next_irq()?;
+            if !self.reserved_irqs.contains(&irq) {
+                return Ok(irq);
+            }
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn next_irq(&mut self) -> Result<u32> {
         let mut irq: u32 = 0;
         for (base, irqs) in self.apics.iter() {
             // HACKHACK - This only works with 1 single IOAPIC...
@@ -99,10 +117,27 @@ impl GsiAllocator {
     }
 
     #[cfg(target_arch = "aarch64")]
-    /// Allocate an IRQ
-    pub fn allocate_irq(&mut self) -> Result<u32> {
+    fn next_irq(&mut self) -> Result<u32> {
         let irq = self.next_irq;
         self.next_irq = self.next_irq.checked_add(1).ok_or(Error::Overflow)?;
         Ok(irq)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserved_irqs() {
+        #[cfg(target_arch = "x86_64")]
+        let (mut allocator, base) = (GsiAllocator::new(vec![GsiApic::new(5, 19)]), 5);
+        #[cfg(target_arch = "aarch64")]
+        let (mut allocator, base) = (GsiAllocator::new(), arch::IRQ_BASE);
+
+        allocator.reserve_irq(base + 1);
+        allocator.reserve_irq(base + 2);
+        assert_eq!(allocator.allocate_irq().unwrap(), base);
+        assert_eq!(allocator.allocate_irq().unwrap(), base + 3);
+    }
+}
@@ -34,11 +34,12 @@ fn pagesize() -> usize {
 /// # use vm_allocator::SystemAllocator;
 /// # use vm_memory::{Address, GuestAddress, GuestUsize};
 ///   let mut allocator = SystemAllocator::new(
-///           #[cfg(target_arch = "x86_64")] GuestAddress(0x1000),
-///           #[cfg(target_arch = "x86_64")] 0x10000,
+///           GuestAddress(0x1000), 0x10000,
 ///           GuestAddress(0x10000000), 0x10000000,
 ///           GuestAddress(0x20000000), 0x100000,
-///           #[cfg(target_arch = "x86_64")] vec![GsiApic::new(5, 19)]).unwrap();
+///           #[cfg(target_arch = "x86_64")] vec![GsiApic::new(5, 19)],
+///           #[cfg(target_arch = "aarch64")] GuestAddress(0x9000000),
+///           #[cfg(target_arch = "aarch64")] 0x80000).unwrap();
 ///   #[cfg(target_arch = "x86_64")]
 ///   assert_eq!(allocator.allocate_irq(), Some(5));
 ///   #[cfg(target_arch = "aarch64")]
@@ -51,10 +52,11 @@ fn pagesize() -> usize {
 ///
 /// ```
 pub struct SystemAllocator {
-    #[cfg(target_arch = "x86_64")]
     io_address_space: AddressAllocator,
     platform_mmio_address_space: AddressAllocator,
     mmio_hole_address_space: AddressAllocator,
+    #[cfg(target_arch = "aarch64")]
+    legacy_mmio_address_space: AddressAllocator,
     gsi_allocator: GsiAllocator,
 }
 
@@ -62,31 +64,36 @@ impl SystemAllocator {
     /// Creates a new `SystemAllocator` for managing addresses and irq numvers.
     /// Can return `None` if `base` + `size` overflows a u64
     ///
-    /// * `io_base` - (X86) The starting address of IO memory.
-    /// * `io_size` - (X86) The size of IO memory.
+    /// * `io_base` - The starting address of IO memory.
+    /// * `io_size` - The size of IO memory.
     /// * `platform_mmio_base` - The starting address of platform MMIO memory.
     /// * `platform_mmio_size` - The size of platform MMIO memory.
     /// * `mmio_hole_base` - The starting address of MMIO memory in 32-bit address space.
     /// * `mmio_hole_size` - The size of MMIO memory in 32-bit address space.
     /// * `apics` - (X86) Vector of APIC's.
+    /// * `legacy_mmio_base` - (AArch64) The starting address of the MMIO memory of the legacy devices.
+    /// * `legacy_mmio_size` - (AArch64) The size of the MMIO memory of the legacy devices.
     ///
     pub fn new(
-        #[cfg(target_arch = "x86_64")] io_base: GuestAddress,
-        #[cfg(target_arch = "x86_64")] io_size: GuestUsize,
+        io_base: GuestAddress,
+        io_size: GuestUsize,
         platform_mmio_base: GuestAddress,
         platform_mmio_size: GuestUsize,
         mmio_hole_base: GuestAddress,
         mmio_hole_size: GuestUsize,
         #[cfg(target_arch = "x86_64")] apics: Vec<GsiApic>,
+        #[cfg(target_arch = "aarch64")] legacy_mmio_base: GuestAddress,
+        #[cfg(target_arch = "aarch64")] legacy_mmio_size: GuestUsize,
     ) -> Option<Self> {
         Some(SystemAllocator {
-            #[cfg(target_arch = "x86_64")]
             io_address_space: AddressAllocator::new(io_base, io_size)?,
             platform_mmio_address_space: AddressAllocator::new(
                 platform_mmio_base,
                 platform_mmio_size,
             )?,
             mmio_hole_address_space: AddressAllocator::new(mmio_hole_base, mmio_hole_size)?,
+            #[cfg(target_arch = "aarch64")]
+            legacy_mmio_address_space: AddressAllocator::new(legacy_mmio_base, legacy_mmio_size)?,
             #[cfg(target_arch = "x86_64")]
             gsi_allocator: GsiAllocator::new(apics),
             #[cfg(target_arch = "aarch64")]
@@ -94,6 +101,12 @@ impl SystemAllocator {
         })
     }
 
+    /// Reserves a system irq number, so that it is never returned by
+    /// `allocate_irq`.
+    pub fn reserve_irq(&mut self, irq: u32) {
+        self.gsi_allocator.reserve_irq(irq)
+    }
+
     /// Reserves the next available system irq number.
     pub fn allocate_irq(&mut self) -> Option<u32> {
         self.gsi_allocator.allocate_irq().ok()
@@ -104,7 +117,6 @@ impl SystemAllocator {
         self.gsi_allocator.allocate_gsi().ok()
     }
 
-    #[cfg(target_arch = "x86_64")]
     /// Reserves a section of `size` bytes of IO address space.
     pub fn allocate_io_addresses(
         &mut self,
@@ -144,7 +156,22 @@ impl SystemAllocator {
         )
     }
 
-    #[cfg(target_arch = "x86_64")]
+    /// Reserves a section of `size` bytes of the MMIO address space of the
+    /// legacy devices, which the guest finds at fixed addresses.
+    #[cfg(target_arch = "aarch64")]
+    pub fn allocate_legacy_mmio_addresses(
+        &mut self,
+        address: Option<GuestAddress>,
+        size: GuestUsize,
+        align_size: Option<GuestUsize>,
+    ) -> Option<GuestAddress> {
+        self.legacy_mmio_address_space.allocate(
+            address,
+            size,
+            Some(align_size.unwrap_or(pagesize() as u64)),
+        )
+    }
+
     /// Free an IO address range.
     /// We can only free a range if it matches exactly an already allocated range.
     pub fn free_io_addresses(&mut self, address: GuestAddress, size: GuestUsize) {
@@ -3,9 +3,19 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+#[macro_use]
+extern crate log;
+
 use crate::protocol::MemoryRangeTable;
 use anyhow::anyhow;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
 use thiserror::Error;
 use versionize::{VersionMap, Versionize};
 
@@ -16,6 +26,10 @@ const MAJOR_VERSION: u16 = 24;
 const MINOR_VERSION: u16 = 0;
 const VMM_VERSION: u16 = MAJOR_VERSION << 12 | MINOR_VERSION & 0b1111;
 
+/// Version of the snapshot state written by `Snapshot::write_state`. The
+/// state of the snapshots without a version embeds the data of the sections.
+pub const SNAPSHOT_STATE_VERSION: u16 = 2;
+
 pub trait VersionMapped {
     fn version_map() -> VersionMap {
         VersionMap::new()
@@ -81,17 +95,140 @@ pub struct SnapshotDataSection {
     /// The section id.
     pub id: String,
 
-    /// The section serialized snapshot.
+    /// The section serialized snapshot, empty when the section is read
+    /// from the data file of a snapshot.
     pub snapshot: Vec<u8>,
+
+    /// The hex encoded SHA-256 digest of the serialized snapshot.
+    #[serde(default)]
+    pub checksum: Option<String>,
+
+    /// The VMM version the section was serialized with, for versioned
+    /// sections.
+    #[serde(default)]
+    pub version: Option<u16>,
+
+    #[serde(skip)]
+    stored: Option<StoredSectionData>,
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// Location of the data of a section in the data file of the snapshot it
+/// was read from.
+#[derive(Clone)]
+struct StoredSectionData {
+    file: Arc<File>,
+    offset: u64,
+    length: u64,
+}
+
+/// Reader of the data of a section left in the data file of a snapshot.
+struct StoredSectionReader<'a> {
+    file: &'a File,
+    offset: u64,
+    remaining: u64,
+}
+
+impl Read for StoredSectionReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len() as u64, self.remaining) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let count = self.file.read_at(&mut buf[..len], self.offset)?;
+        self.offset += count as u64;
+        self.remaining -= count as u64;
+        Ok(count)
+    }
 }
 
 impl SnapshotDataSection {
+    /// Create a section from already serialized data, recording its checksum
+    pub fn new(id: &str, snapshot: Vec<u8>) -> Self {
+        let checksum = Some(sha256_hex(&snapshot));
+
+        SnapshotDataSection {
+            id: format!("{}-section", id),
+            snapshot,
+            checksum,
+            version: None,
+            stored: None,
+        }
+    }
+
+    /// Size of the serialized data
+    pub fn len(&self) -> u64 {
+        match &self.stored {
+            Some(stored) => stored.length,
+            None => self.snapshot.len() as u64,
+        }
+    }
+
+    /// Whether the serialized data is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reader of the serialized data, streaming it from the data file of
+    /// the snapshot for the sections read from one.
+    fn reader(&self) -> Box<dyn Read + '_> {
+        match &self.stored {
+            Some(stored) => Box::new(BufReader::new(StoredSectionReader {
+                file: &stored.file,
+                offset: stored.offset,
+                remaining: stored.length,
+            })),
+            None => Box::new(self.snapshot.as_slice()),
+        }
+    }
+
+    /// Human readable version the section was serialized with, if any
+    pub fn version_string(&self) -> Option<String> {
+        self.version.map(|v| format!("{}.{}", v >> 12, v & 0b1111))
+    }
+
+    /// Check the serialized data against the recorded checksum. Sections
+    /// written before the checksums were recorded are accepted unverified.
+    pub fn verify(&self) -> Result<(), MigratableError> {
+        let expected = match self.checksum.as_ref() {
+            Some(expected) => expected,
+            None => {
+                warn!("No checksum for section {}, not verifying it", self.id);
+                return Ok(());
+            }
+        };
+
+        let mut hasher = Sha256::new();
+        io::copy(&mut self.reader(), &mut hasher).map_err(|e| {
+            MigratableError::Restore(anyhow!("Error reading section {}: {}", self.id, e))
+        })?;
+        let actual = hex(&hasher.finalize());
+        if actual != *expected {
+            return Err(MigratableError::Restore(anyhow!(
+                "Checksum mismatch for section {}: expected {} found {}",
+                self.id,
+                expected,
+                actual
+            )));
+        }
+
+        Ok(())
+    }
+
     /// Generate the state data from the snapshot data
-    pub fn to_state<'a, T>(&'a self) -> Result<T, MigratableError>
+    pub fn to_state<T>(&self) -> Result<T, MigratableError>
     where
-        T: Deserialize<'a>,
+        T: DeserializeOwned,
     {
-        serde_json::from_slice(&self.snapshot).map_err(|e| {
+        serde_json::from_reader(self.reader()).map_err(|e| {
             MigratableError::Restore(anyhow!("Error deserialising: {} {}", self.id, e))
         })
     }
@@ -101,12 +238,9 @@ impl SnapshotDataSection {
     where
         T: Versionize + VersionMapped,
     {
-        T::deserialize(
-            &mut self.snapshot.as_slice(),
-            &T::version_map(),
-            VMM_VERSION,
-        )
-        .map_err(|e| MigratableError::Restore(anyhow!("Error deserialising: {} {}", self.id, e)))
+        T::deserialize(&mut self.reader(), &T::version_map(), VMM_VERSION).map_err(|e| {
+            MigratableError::Restore(anyhow!("Error deserialising: {} {}", self.id, e))
+        })
     }
 
     /// Create from state that can be serialized
@@ -117,12 +251,7 @@ impl SnapshotDataSection {
         let snapshot = serde_json::to_vec(state)
             .map_err(|e| MigratableError::Snapshot(anyhow!("Error serialising: {} {}", id, e)))?;
 
-        let snapshot_data = SnapshotDataSection {
-            id: format!("{}-section", id),
-            snapshot,
-        };
-
-        Ok(snapshot_data)
+        Ok(SnapshotDataSection::new(id, snapshot))
     }
 
     /// Create from versioned state
@@ -135,15 +264,40 @@ impl SnapshotDataSection {
             .serialize(&mut snapshot, &T::version_map(), VMM_VERSION)
             .map_err(|e| MigratableError::Snapshot(anyhow!("Error serialising: {} {}", id, e)))?;
 
-        let snapshot_data = SnapshotDataSection {
-            id: format!("{}-section", id),
-            snapshot,
-        };
+        let mut section = SnapshotDataSection::new(id, snapshot);
+        section.version = Some(VMM_VERSION);
 
-        Ok(snapshot_data)
+        Ok(section)
     }
 }
 
+/// Description of a section in the state of a snapshot, its data being
+/// stored in the data file of the snapshot.
+#[derive(Deserialize, Serialize)]
+struct SnapshotSectionIndex {
+    id: String,
+    offset: u64,
+    length: u64,
+    #[serde(default)]
+    checksum: Option<String>,
+    #[serde(default)]
+    version: Option<u16>,
+}
+
+/// Description of a snapshot tree in the state of a snapshot.
+#[derive(Deserialize, Serialize)]
+struct SnapshotIndex {
+    id: String,
+    snapshots: BTreeMap<String, SnapshotIndex>,
+    snapshot_data: BTreeMap<String, SnapshotSectionIndex>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SnapshotState {
+    version: u16,
+    snapshot: SnapshotIndex,
+}
+
 /// A Snapshottable component's snapshot is a tree of snapshots, where leafs
 /// contain the snapshot data. Nodes of this tree track all their children
 /// through the snapshots field, which is basically their sub-components.
@@ -161,11 +315,11 @@ pub struct Snapshot {
     pub id: String,
 
     /// The Snapshottable component snapshots.
-    pub snapshots: std::collections::BTreeMap<String, Box<Snapshot>>,
+    pub snapshots: BTreeMap<String, Box<Snapshot>>,
 
     /// The Snapshottable component's snapshot data.
     /// A map of snapshot sections, indexed by the section ids.
-    pub snapshot_data: std::collections::HashMap<String, SnapshotDataSection>,
+    pub snapshot_data: BTreeMap<String, SnapshotDataSection>,
 }
 
 impl Snapshot {
@@ -205,15 +359,149 @@ impl Snapshot {
             .insert(snapshot.id.clone(), Box::new(snapshot));
     }
 
+    /// Remove a sub-component's Snapshot, handing it over to the component
+    /// restored from it rather than cloning it.
+    pub fn take_snapshot(&mut self, id: &str) -> Option<Snapshot> {
+        self.snapshots.remove(id).map(|snapshot| *snapshot)
+    }
+
+    /// Copy of the component's Snapshot without its sub-components.
+    pub fn without_snapshots(&self) -> Snapshot {
+        Snapshot {
+            id: self.id.clone(),
+            snapshots: BTreeMap::new(),
+            snapshot_data: self.snapshot_data.clone(),
+        }
+    }
+
     /// Add a SnapshotDatasection to the component snapshot data.
     pub fn add_data_section(&mut self, section: SnapshotDataSection) {
         self.snapshot_data.insert(section.id.clone(), section);
     }
 
+    /// Write the state of the snapshot, describing the snapshot tree to
+    /// `state` and writing the data of its sections one after the other to
+    /// `data`.
+    pub fn write_state<W, D>(&self, state: W, data: &mut D) -> Result<(), MigratableError>
+    where
+        W: Write,
+        D: Write,
+    {
+        let mut offset = 0;
+        let snapshot = self.write_sections(data, &mut offset)?;
+
+        serde_json::to_writer(
+            state,
+            &SnapshotState {
+                version: SNAPSHOT_STATE_VERSION,
+                snapshot,
+            },
+        )
+        .map_err(|e| MigratableError::MigrateSend(e.into()))
+    }
+
+    fn write_sections<D: Write>(
+        &self,
+        data: &mut D,
+        offset: &mut u64,
+    ) -> Result<SnapshotIndex, MigratableError> {
+        let mut snapshot_data = BTreeMap::new();
+        for (id, section) in self.snapshot_data.iter() {
+            let length = io::copy(&mut section.reader(), data)
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+            snapshot_data.insert(
+                id.clone(),
+                SnapshotSectionIndex {
+                    id: section.id.clone(),
+                    offset: *offset,
+                    length,
+                    checksum: section.checksum.clone(),
+                    version: section.version,
+                },
+            );
+            *offset += length;
+        }
+
+        let mut snapshots = BTreeMap::new();
+        for (id, snapshot) in self.snapshots.iter() {
+            snapshots.insert(id.clone(), snapshot.write_sections(data, offset)?);
+        }
+
+        Ok(SnapshotIndex {
+            id: self.id.clone(),
+            snapshots,
+            snapshot_data,
+        })
+    }
+
+    /// Read the state of a snapshot written by `write_state`. The data of
+    /// the sections is left in the `data` file, and only read when the
+    /// sections are verified or restored.
+    pub fn read_state<R: Read>(state: R, data: File) -> Result<Snapshot, MigratableError> {
+        let state: SnapshotState = serde_json::from_reader(state)
+            .map_err(|e| MigratableError::MigrateReceive(e.into()))?;
+        if state.version != SNAPSHOT_STATE_VERSION {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "Unsupported snapshot state version {}",
+                state.version
+            )));
+        }
+
+        Ok(Snapshot::from_index(state.snapshot, &Arc::new(data)))
+    }
+
+    fn from_index(index: SnapshotIndex, data: &Arc<File>) -> Snapshot {
+        let snapshots = index
+            .snapshots
+            .into_iter()
+            .map(|(id, snapshot)| (id, Box::new(Snapshot::from_index(snapshot, data))))
+            .collect();
+        let snapshot_data = index
+            .snapshot_data
+            .into_iter()
+            .map(|(id, section)| {
+                (
+                    id,
+                    SnapshotDataSection {
+                        id: section.id,
+                        snapshot: Vec::new(),
+                        checksum: section.checksum,
+                        version: section.version,
+                        stored: Some(StoredSectionData {
+                            file: data.clone(),
+                            offset: section.offset,
+                            length: section.length,
+                        }),
+                    },
+                )
+            })
+            .collect();
+
+        Snapshot {
+            id: index.id,
+            snapshots,
+            snapshot_data,
+        }
+    }
+
+    /// Verify the checksums of all the sections of this snapshot and of
+    /// all its sub-component snapshots.
+    pub fn verify(&self) -> Result<(), MigratableError> {
+        for section in self.snapshot_data.values() {
+            section.verify()?;
+        }
+
+        for snapshot in self.snapshots.values() {
+            snapshot.verify()?;
+        }
+
+        Ok(())
+    }
+
     /// Generate the state data from the snapshot
-    pub fn to_state<'a, T>(&'a self, id: &str) -> Result<T, MigratableError>
+    pub fn to_state<T>(&self, id: &str) -> Result<T, MigratableError>
     where
-        T: Deserialize<'a>,
+        T: DeserializeOwned,
     {
         self.snapshot_data
             .get(&format!("{}-section", id))
@@ -310,3 +598,122 @@ pub trait Migratable: Send + Pausable + Snapshottable + Transportable {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_verify() {
+        let mut snapshot = Snapshot::new("foo");
+        let mut child = Snapshot::new_from_state("bar", &vec![1u32, 2, 3]).unwrap();
+        snapshot.add_data_section(SnapshotDataSection::new("foo", vec![0xaa; 16]));
+        snapshot.add_snapshot(child.clone());
+        assert!(snapshot.verify().is_ok());
+
+        // Corrupt the data of a nested section
+        child
+            .snapshot_data
+            .get_mut("bar-section")
+            .unwrap()
+            .snapshot
+            .push(0);
+        snapshot.add_snapshot(child.clone());
+        assert!(snapshot.verify().is_err());
+
+        // Sections without a checksum are accepted unverified
+        let section = child.snapshot_data.get_mut("bar-section").unwrap();
+        section.snapshot.pop();
+        section.checksum = None;
+        snapshot.add_snapshot(child);
+        assert!(snapshot.verify().is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_state() {
+        let mut snapshot = Snapshot::new("foo");
+        snapshot.add_snapshot(Snapshot::new_from_state("bar", &vec![1u32, 2, 3]).unwrap());
+        snapshot.add_data_section(SnapshotDataSection::new("foo", vec![0x00, 0xab, 0x7f]));
+
+        let data_path = std::env::temp_dir().join(format!("snapshot-state-{}", std::process::id()));
+        let mut data = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&data_path)
+            .unwrap();
+        std::fs::remove_file(&data_path).unwrap();
+
+        // The state only describes the sections, their data being written
+        // to the data file.
+        let mut state = Vec::new();
+        snapshot.write_state(&mut state, &mut data).unwrap();
+        let state_json = String::from_utf8(state.clone()).unwrap();
+        assert!(state_json.starts_with(&format!("{{\"version\":{}", SNAPSHOT_STATE_VERSION)));
+        assert!(!state_json.contains("\"snapshot\":["));
+
+        let mut restored =
+            Snapshot::read_state(state.as_slice(), data.try_clone().unwrap()).unwrap();
+        assert!(restored.verify().is_ok());
+        assert_eq!(restored.snapshot_data["foo-section"].len(), 3);
+
+        // Sub-component snapshots are handed over once.
+        let child = restored.take_snapshot("bar").unwrap();
+        assert_eq!(child.to_state::<Vec<u32>>("bar").unwrap(), [1, 2, 3]);
+        assert!(restored.take_snapshot("bar").is_none());
+        assert!(restored
+            .without_snapshots()
+            .snapshot_data
+            .contains_key("foo-section"));
+
+        // A corrupted data file fails the verification.
+        data.write_all_at(&[0xff], 0).unwrap();
+        let restored = Snapshot::read_state(state.as_slice(), data).unwrap();
+        assert!(restored.verify().is_err());
+
+        // Unknown versions are rejected.
+        let state = state_json.replacen(
+            &format!("\"version\":{}", SNAPSHOT_STATE_VERSION),
+            "\"version\":1000",
+            1,
+        );
+        let data = File::open(std::env::temp_dir()).unwrap();
+        assert!(Snapshot::read_state(state.as_bytes(), data).is_err());
+
+        // The snapshots without a version embed the data of the sections.
+        let data = r#"{"id":"foo","snapshots":{},"snapshot_data":{"foo-section":{"id":"foo-section","snapshot":[0,171,127]}}}"#;
+        let restored: Snapshot = serde_json::from_str(data).unwrap();
+        assert_eq!(
+            restored.snapshot_data["foo-section"].snapshot,
+            [0x00, 0xab, 0x7f]
+        );
+    }
+
+    #[test]
+    fn test_memory_range_table_operations() {
+        use crate::protocol::MemoryRange;
+
+        let table = |ranges: &[(u64, u64)]| {
+            let mut table = MemoryRangeTable::default();
+            for (gpa, length) in ranges {
+                table.push(MemoryRange {
+                    gpa: *gpa,
+                    length: *length,
+                });
+            }
+            table
+        };
+
+        let dirty = table(&[(0x1000, 0x3000), (0x8000, 0x1000), (0x10000, 0x1000)]);
+        let layout = table(&[(0x2000, 0x7000), (0xa000, 0x1000)]);
+        assert_eq!(
+            dirty.intersection(&layout),
+            table(&[(0x2000, 0x2000), (0x8000, 0x1000)])
+        );
+        assert!(dirty.intersection(&MemoryRangeTable::default()).is_empty());
+
+        let mut dirty = table(&[(0x8000, 0x1000), (0x1000, 0x2000), (0x2000, 0x2000)]);
+        dirty.coalesce();
+        assert_eq!(dirty, table(&[(0x1000, 0x3000), (0x8000, 0x1000)]));
+    }
+}
@@ -199,13 +199,13 @@ impl Response {
 }
 
 #[repr(C)]
-#[derive(Clone, Default, Serialize, Deserialize, Versionize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Versionize)]
 pub struct MemoryRange {
     pub gpa: u64,
     pub length: u64,
 }
 
-#[derive(Clone, Default, Serialize, Deserialize, Versionize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Versionize)]
 pub struct MemoryRangeTable {
     data: Vec<MemoryRange>,
 }
@@ -245,6 +245,11 @@ impl MemoryRangeTable {
         &self.data
     }
 
+    /// Sort the ranges by guest physical address
+    pub fn sort(&mut self) {
+        self.data.sort_by_key(|r| r.gpa);
+    }
+
     pub fn push(&mut self, range: MemoryRange) {
         self.data.push(range)
     }
@@ -290,6 +295,43 @@ impl MemoryRangeTable {
         self.data.extend(table.data)
     }
 
+    /// Sort the ranges and merge the ones overlapping or adjacent to each
+    /// other.
+    pub fn coalesce(&mut self) {
+        self.sort();
+        let mut data: Vec<MemoryRange> = Vec::with_capacity(self.data.len());
+        for range in self.data.drain(..) {
+            match data.last_mut() {
+                Some(last) if range.gpa <= last.gpa + last.length => {
+                    last.length = (range.gpa + range.length).max(last.gpa + last.length) - last.gpa;
+                }
+                _ => data.push(range),
+            }
+        }
+        self.data = data;
+    }
+
+    /// Returns the parts of the ranges that are also covered by the ranges
+    /// of `other`, sorted by guest physical address.
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut table = MemoryRangeTable::default();
+        for range in &self.data {
+            for other_range in &other.data {
+                let start = range.gpa.max(other_range.gpa);
+                let end = (range.gpa + range.length).min(other_range.gpa + other_range.length);
+                if start < end {
+                    table.push(MemoryRange {
+                        gpa: start,
+                        length: end - start,
+                    });
+                }
+            }
+        }
+        table.sort();
+
+        table
+    }
+
     pub fn new_from_tables(tables: Vec<Self>) -> Self {
         let mut data = Vec::new();
         for table in tables {
@@ -0,0 +1,471 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Validation of the descriptor chains made available by the driver, before
+//! a device worker processes them. A chain is walked once, checking that it
+//! terminates, that every buffer lies within the guest memory, and that its
+//! layout fits the limits of the device. The chains rejected are accounted
+//! for, and left for the worker to return to the driver unprocessed.
+//!
+//! The descriptors are copied while walking the chain, their addresses being
+//! translated through the access platform. The device workers only process
+//! that copy, as the driver could modify the descriptors in the guest memory
+//! once they have been checked. They are expected to validate every chain
+//! through this module rather than checking the descriptors on their own,
+//! leaving them with the checks specific to their protocol.
+
+use crate::AccessPlatform;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use virtio_queue::DescriptorChain;
+use vm_memory::{GuestAddress, GuestMemory};
+
+/// Default limit on the number of descriptors of a chain, indirect ones
+/// included, matching the largest queue size allowed by the specification.
+pub const DEFAULT_MAX_DESCRIPTORS: usize = 32768;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The chain has no descriptor.
+    Empty,
+    /// The chain loops, or refers to a descriptor out of its table.
+    Broken,
+    /// The chain has more descriptors than allowed.
+    TooManyDescriptors(usize),
+    /// A buffer of the chain could not be translated by the access platform.
+    Translation(GuestAddress, u32),
+    /// A buffer of the chain lies outside of the guest memory.
+    OutOfBounds(GuestAddress, u32),
+    /// A device readable buffer follows a device writable one.
+    ReadableAfterWritable,
+    /// The buffers of the chain are longer than allowed.
+    TooLong(u64),
+    /// The device readable buffers are shorter than required.
+    ReadableTooShort(u64),
+    /// The device writable buffers are shorter than required.
+    WritableTooShort(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+
+        match self {
+            Empty => write!(f, "empty descriptor chain"),
+            Broken => write!(f, "looping or truncated descriptor chain"),
+            TooManyDescriptors(max) => write!(f, "descriptor chain longer than {}", max),
+            Translation(addr, len) => write!(
+                f,
+                "untranslatable buffer at 0x{:x} of length {}",
+                addr.0, len
+            ),
+            OutOfBounds(addr, len) => write!(
+                f,
+                "buffer at 0x{:x} of length {} out of guest memory",
+                addr.0, len
+            ),
+            ReadableAfterWritable => write!(f, "device readable buffer after a writable one"),
+            TooLong(len) => write!(f, "buffers of length {} too long", len),
+            ReadableTooShort(len) => write!(f, "readable buffers of length {} too short", len),
+            WritableTooShort(len) => write!(f, "writable buffers of length {} too short", len),
+        }
+    }
+}
+
+/// Limits a chain must fit in to be processed by a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainLimits {
+    /// Largest number of descriptors, indirect ones included.
+    pub max_descriptors: usize,
+    /// Largest length of all the buffers.
+    pub max_len: u64,
+    /// Smallest length of the device readable buffers.
+    pub min_readable: u64,
+    /// Smallest length of the device writable buffers.
+    pub min_writable: u64,
+}
+
+impl Default for ChainLimits {
+    fn default() -> Self {
+        ChainLimits {
+            max_descriptors: DEFAULT_MAX_DESCRIPTORS,
+            // The length used by the device is reported as 32 bits.
+            max_len: u32::MAX as u64,
+            min_readable: 0,
+            min_writable: 0,
+        }
+    }
+}
+
+/// Copy of a descriptor of a valid chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainDescriptor {
+    addr: GuestAddress,
+    len: u32,
+    write_only: bool,
+}
+
+impl ChainDescriptor {
+    pub fn new(addr: GuestAddress, len: u32, write_only: bool) -> Self {
+        ChainDescriptor {
+            addr,
+            len,
+            write_only,
+        }
+    }
+
+    /// Address of the buffer, already translated through the access
+    /// platform, if any.
+    pub fn addr(&self) -> GuestAddress {
+        self.addr
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_write_only(&self) -> bool {
+        self.write_only
+    }
+}
+
+/// Descriptors of a chain which fits the limits of a device, the device
+/// readable ones coming first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidChain {
+    head_index: u16,
+    descriptors: Vec<ChainDescriptor>,
+    readable_count: usize,
+    readable_len: u64,
+    writable_len: u64,
+}
+
+impl ValidChain {
+    /// Index of the head of the chain, to return it to the driver.
+    pub fn head_index(&self) -> u16 {
+        self.head_index
+    }
+
+    pub fn descriptors(&self) -> &[ChainDescriptor] {
+        &self.descriptors
+    }
+
+    /// Device readable descriptors.
+    pub fn readable(&self) -> &[ChainDescriptor] {
+        &self.descriptors[..self.readable_count]
+    }
+
+    /// Device writable descriptors.
+    pub fn writable(&self) -> &[ChainDescriptor] {
+        &self.descriptors[self.readable_count..]
+    }
+
+    pub fn readable_len(&self) -> u64 {
+        self.readable_len
+    }
+
+    pub fn writable_len(&self) -> u64 {
+        self.writable_len
+    }
+}
+
+/// Validates a chain against the limits of a device, consuming it.
+///
+/// The buffers are translated through the access platform, if any, before
+/// being checked against the guest memory. The chain is only read once, the
+/// descriptors being returned for the device to process.
+pub fn validate_chain<M>(
+    chain: &mut DescriptorChain<M>,
+    limits: &ChainLimits,
+    access_platform: Option<&Arc<dyn AccessPlatform>>,
+) -> Result<ValidChain, Error>
+where
+    M: Deref + Clone,
+    M::Target: GuestMemory,
+{
+    let mut valid_chain = ValidChain {
+        head_index: chain.head_index(),
+        ..Default::default()
+    };
+    let mut has_next = false;
+
+    // The iterator stops on a loop, as well as on an invalid descriptor index
+    // or indirect table, without telling. Such a chain is recognized by its
+    // last descriptor still claiming a next one.
+    while let Some(desc) = chain.next() {
+        if valid_chain.descriptors.len() == limits.max_descriptors {
+            return Err(Error::TooManyDescriptors(limits.max_descriptors));
+        }
+        has_next = desc.has_next();
+
+        let len = desc.len();
+        let addr = match access_platform {
+            Some(access_platform) if len > 0 => access_platform
+                .translate_gva(desc.addr().0, len as u64)
+                .map(GuestAddress)
+                .map_err(|_| Error::Translation(desc.addr(), len))?,
+            _ => desc.addr(),
+        };
+        if len > 0 && !chain.memory().check_range(addr, len as usize) {
+            return Err(Error::OutOfBounds(desc.addr(), len));
+        }
+
+        if desc.is_write_only() {
+            valid_chain.writable_len += len as u64;
+        } else if valid_chain.descriptors.len() > valid_chain.readable_count {
+            return Err(Error::ReadableAfterWritable);
+        } else {
+            valid_chain.readable_len += len as u64;
+            valid_chain.readable_count += 1;
+        }
+        valid_chain
+            .descriptors
+            .push(ChainDescriptor::new(addr, len, desc.is_write_only()));
+
+        let total_len = valid_chain.readable_len + valid_chain.writable_len;
+        if total_len > limits.max_len {
+            return Err(Error::TooLong(total_len));
+        }
+    }
+
+    if valid_chain.descriptors.is_empty() {
+        return Err(Error::Empty);
+    }
+    if has_next {
+        return Err(Error::Broken);
+    }
+    if valid_chain.readable_len < limits.min_readable {
+        return Err(Error::ReadableTooShort(valid_chain.readable_len));
+    }
+    if valid_chain.writable_len < limits.min_writable {
+        return Err(Error::WritableTooShort(valid_chain.writable_len));
+    }
+
+    Ok(valid_chain)
+}
+
+/// Validator owned by a device worker, counting the chains it rejects.
+#[derive(Clone)]
+pub struct ChainValidator {
+    limits: ChainLimits,
+    access_platform: Option<Arc<dyn AccessPlatform>>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl ChainValidator {
+    pub fn new(
+        limits: ChainLimits,
+        access_platform: Option<Arc<dyn AccessPlatform>>,
+        rejected: Arc<AtomicU64>,
+    ) -> Self {
+        ChainValidator {
+            limits,
+            access_platform,
+            rejected,
+        }
+    }
+
+    pub fn limits(&self) -> &ChainLimits {
+        &self.limits
+    }
+
+    /// Validates the chain, consuming it. The device only processes the
+    /// descriptors returned.
+    pub fn validate<M>(&self, chain: &mut DescriptorChain<M>) -> Result<ValidChain, Error>
+    where
+        M: Deref + Clone,
+        M::Target: GuestMemory,
+    {
+        validate_chain(chain, &self.limits, self.access_platform.as_ref()).map_err(|e| {
+            // The driver decides how many chains are rejected, which are
+            // accounted for in the counters of the device rather than logged
+            // at a level enabled by default.
+            self.rejected.fetch_add(1, Ordering::AcqRel);
+            debug!("Rejected descriptor chain {}: {}", chain.head_index(), e);
+            e
+        })
+    }
+
+    /// Number of chains rejected, shared by the validators of a device.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::testing::VirtQueue;
+    use virtio_queue::defs::{VIRTQ_DESC_F_INDIRECT, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+    use vm_memory::bitmap::AtomicBitmap;
+
+    type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
+
+    const MEM_SIZE: usize = 0x10_0000;
+
+    fn validate(vq: &VirtQueue, limits: &ChainLimits) -> Result<ValidChain, Error> {
+        vq.avail.ring[0].set(0);
+        vq.avail.idx.set(1);
+        let mut queue = vq.create_queue();
+        let mut chain = queue.iter().unwrap().next().unwrap();
+        validate_chain(&mut chain, limits, None)
+    }
+
+    #[test]
+    fn test_validate_chain() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), MEM_SIZE)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let limits = ChainLimits::default();
+
+        vq.dtable[0].set(0x1000, 16, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(0x2000, 512, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 2);
+        vq.dtable[2].set(0x3000, 1, VIRTQ_DESC_F_WRITE, 0);
+        let chain = validate(&vq, &limits).unwrap();
+        assert_eq!(chain.head_index(), 0);
+        assert_eq!(
+            chain.readable(),
+            &[ChainDescriptor::new(GuestAddress(0x1000), 16, false)]
+        );
+        assert_eq!(
+            chain.writable(),
+            &[
+                ChainDescriptor::new(GuestAddress(0x2000), 512, true),
+                ChainDescriptor::new(GuestAddress(0x3000), 1, true)
+            ]
+        );
+        assert_eq!(chain.readable_len(), 16);
+        assert_eq!(chain.writable_len(), 513);
+
+        // The descriptors rewritten by the driver once validated aren't seen
+        // by the device.
+        let descriptors = chain.descriptors().to_vec();
+        vq.dtable[0].set(MEM_SIZE as u64, 32, VIRTQ_DESC_F_INDIRECT, 0);
+        assert_eq!(chain.descriptors(), &descriptors[..]);
+        vq.dtable[0].set(0x1000, 16, VIRTQ_DESC_F_NEXT, 1);
+
+        // Layout limits
+        assert_eq!(
+            validate(
+                &vq,
+                &ChainLimits {
+                    max_descriptors: 2,
+                    ..limits
+                }
+            ),
+            Err(Error::TooManyDescriptors(2))
+        );
+        assert_eq!(
+            validate(
+                &vq,
+                &ChainLimits {
+                    max_len: 512,
+                    ..limits
+                }
+            ),
+            Err(Error::TooLong(528))
+        );
+        assert_eq!(
+            validate(
+                &vq,
+                &ChainLimits {
+                    min_readable: 17,
+                    ..limits
+                }
+            ),
+            Err(Error::ReadableTooShort(16))
+        );
+        assert_eq!(
+            validate(
+                &vq,
+                &ChainLimits {
+                    min_writable: 514,
+                    ..limits
+                }
+            ),
+            Err(Error::WritableTooShort(513))
+        );
+
+        // A readable buffer after a writable one, even an empty one
+        vq.dtable[2].set(0x3000, 1, 0, 0);
+        assert_eq!(validate(&vq, &limits), Err(Error::ReadableAfterWritable));
+        vq.dtable[1].set(0x2000, 0, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 2);
+        assert_eq!(validate(&vq, &limits), Err(Error::ReadableAfterWritable));
+        vq.dtable[1].set(0x2000, 512, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 2);
+
+        // A buffer crossing the end of the guest memory, while an empty one
+        // is not checked.
+        vq.dtable[2].set(MEM_SIZE as u64 - 1, 2, VIRTQ_DESC_F_WRITE, 0);
+        assert_eq!(
+            validate(&vq, &limits),
+            Err(Error::OutOfBounds(GuestAddress(MEM_SIZE as u64 - 1), 2))
+        );
+        vq.dtable[2].set(MEM_SIZE as u64, 0, VIRTQ_DESC_F_WRITE, 0);
+        assert!(validate(&vq, &limits).is_ok());
+
+        // A loop, and a descriptor index out of the table
+        vq.dtable[2].set(0x3000, 1, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 1);
+        assert_eq!(validate(&vq, &limits), Err(Error::Broken));
+        vq.dtable[2].set(0x3000, 1, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE, 16);
+        assert_eq!(validate(&vq, &limits), Err(Error::Broken));
+
+        // An indirect table, which descriptors are accounted for
+        vq.dtable[0].set(0x4000, 32, VIRTQ_DESC_F_INDIRECT, 0);
+        let table = VirtQueue::new(GuestAddress(0x4000), &mem, 2);
+        table.dtable[0].set(0x1000, 16, VIRTQ_DESC_F_NEXT, 1);
+        table.dtable[1].set(0x2000, 64, VIRTQ_DESC_F_WRITE, 0);
+        assert_eq!(
+            validate(&vq, &limits).unwrap().descriptors(),
+            &[
+                ChainDescriptor::new(GuestAddress(0x1000), 16, false),
+                ChainDescriptor::new(GuestAddress(0x2000), 64, true)
+            ]
+        );
+
+        // An indirect table out of the guest memory
+        vq.dtable[0].set(MEM_SIZE as u64, 32, VIRTQ_DESC_F_INDIRECT, 0);
+        assert_eq!(validate(&vq, &limits), Err(Error::Empty));
+    }
+
+    #[test]
+    fn test_chain_validator_counts_rejected() {
+        let mem = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), MEM_SIZE)]).unwrap();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        let rejected = Arc::new(AtomicU64::new(0));
+        let validator = ChainValidator::new(
+            ChainLimits {
+                min_writable: 1,
+                ..Default::default()
+            },
+            None,
+            rejected.clone(),
+        );
+
+        vq.dtable[0].set(0x1000, 16, VIRTQ_DESC_F_NEXT, 1);
+        vq.dtable[1].set(0x2000, 1, VIRTQ_DESC_F_WRITE, 0);
+        vq.dtable[2].set(0x1000, 16, 0, 0);
+        vq.avail.ring[0].set(0);
+        vq.avail.ring[1].set(2);
+        vq.avail.idx.set(2);
+
+        let mut queue = vq.create_queue();
+        let mut avail_iter = queue.iter().unwrap();
+        assert!(validator.validate(&mut avail_iter.next().unwrap()).is_ok());
+        assert_eq!(
+            validator.validate(&mut avail_iter.next().unwrap()),
+            Err(Error::WritableTooShort(0))
+        );
+        assert_eq!(validator.rejected(), 1);
+        assert_eq!(
+            validator.clone().rejected(),
+            rejected.load(Ordering::Acquire)
+        );
+    }
+}
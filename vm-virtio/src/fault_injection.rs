@@ -0,0 +1,113 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Faults injected on demand into a virtio device, in order to validate how
+//! the guest and the orchestrator cope with failing hardware. The faults are
+//! shared between the VMM, which updates them, and the device threads, which
+//! apply them.
+
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct Faults {
+    // Ranges of sectors the disk fails to read from or write to.
+    disk_io_errors: RwLock<Vec<RangeInclusive<u64>>>,
+    // One packet out of this number is dropped, 0 disables the fault.
+    net_drop_interval: AtomicU64,
+    net_packets: AtomicU64,
+    // Delay applied before the interrupts are delivered to the guest.
+    interrupt_delay_us: AtomicU64,
+}
+
+impl Faults {
+    pub fn set_disk_io_errors(&self, ranges: Vec<RangeInclusive<u64>>) {
+        *self.disk_io_errors.write().unwrap() = ranges;
+    }
+
+    /// Whether an access to `num_sectors` sectors starting at `sector` must
+    /// fail.
+    pub fn disk_io_error(&self, sector: u64, num_sectors: u64) -> bool {
+        let last_sector = sector.saturating_add(num_sectors.saturating_sub(1));
+        self.disk_io_errors
+            .read()
+            .unwrap()
+            .iter()
+            .any(|range| sector <= *range.end() && last_sector >= *range.start())
+    }
+
+    pub fn set_net_drop_interval(&self, interval: u64) {
+        self.net_packets.store(0, Ordering::Release);
+        self.net_drop_interval.store(interval, Ordering::Release);
+    }
+
+    /// Accounts for a packet going through the device, returning whether it
+    /// must be dropped.
+    pub fn drop_packet(&self) -> bool {
+        let interval = self.net_drop_interval.load(Ordering::Acquire);
+        if interval == 0 {
+            return false;
+        }
+
+        (self.net_packets.fetch_add(1, Ordering::AcqRel) + 1) % interval == 0
+    }
+
+    pub fn set_interrupt_delay(&self, delay: Duration) {
+        self.interrupt_delay_us
+            .store(delay.as_micros() as u64, Ordering::Release);
+    }
+
+    /// Blocks the calling thread for the configured interrupt delay.
+    pub fn delay_interrupt(&self) {
+        let delay_us = self.interrupt_delay_us.load(Ordering::Acquire);
+        if delay_us != 0 {
+            thread::sleep(Duration::from_micros(delay_us));
+        }
+    }
+
+    pub fn clear(&self) {
+        self.set_disk_io_errors(Vec::new());
+        self.set_net_drop_interval(0);
+        self.set_interrupt_delay(Duration::ZERO);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_io_error() {
+        let faults = Faults::default();
+        assert!(!faults.disk_io_error(0, 8));
+
+        faults.set_disk_io_errors(vec![16..=31, 100..=100]);
+        assert!(!faults.disk_io_error(0, 16));
+        assert!(faults.disk_io_error(8, 9));
+        assert!(faults.disk_io_error(31, 1));
+        assert!(!faults.disk_io_error(32, 68));
+        assert!(faults.disk_io_error(32, 69));
+        assert!(!faults.disk_io_error(101, 8));
+
+        faults.clear();
+        assert!(!faults.disk_io_error(16, 16));
+    }
+
+    #[test]
+    fn test_drop_packet() {
+        let faults = Faults::default();
+        assert!(!faults.drop_packet());
+
+        faults.set_net_drop_interval(3);
+        let dropped: Vec<bool> = (0..6).map(|_| faults.drop_packet()).collect();
+        assert_eq!(dropped, vec![false, false, true, false, false, true]);
+
+        faults.set_net_drop_interval(0);
+        assert!(!faults.drop_packet());
+    }
+}
@@ -10,6 +10,9 @@
 
 //! Implements virtio queues
 
+#[macro_use]
+extern crate log;
+
 use std::fmt::{self, Debug};
 use std::sync::Arc;
 use virtio_queue::Queue;
@@ -17,6 +20,9 @@ use vm_memory::{bitmap::AtomicBitmap, GuestAddress, GuestMemoryAtomic};
 
 type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
 
+pub mod chain;
+#[cfg(feature = "fault_injection")]
+pub mod fault_injection;
 pub mod queue;
 pub use queue::*;
 
@@ -35,6 +41,7 @@ pub enum VirtioDeviceType {
     Balloon = 5,
     Fs9P = 9,
     Gpu = 16,
+    Clock = 17,
     Input = 18,
     Vsock = 19,
     Iommu = 23,
@@ -55,6 +62,7 @@ impl From<u32> for VirtioDeviceType {
             5 => VirtioDeviceType::Balloon,
             9 => VirtioDeviceType::Fs9P,
             16 => VirtioDeviceType::Gpu,
+            17 => VirtioDeviceType::Clock,
             18 => VirtioDeviceType::Input,
             19 => VirtioDeviceType::Vsock,
             23 => VirtioDeviceType::Iommu,
@@ -79,6 +87,7 @@ impl fmt::Display for VirtioDeviceType {
             VirtioDeviceType::Rng => "rng",
             VirtioDeviceType::Balloon => "balloon",
             VirtioDeviceType::Gpu => "gpu",
+            VirtioDeviceType::Clock => "clock",
             VirtioDeviceType::Fs9P => "9p",
             VirtioDeviceType::Input => "input",
             VirtioDeviceType::Vsock => "vsock",
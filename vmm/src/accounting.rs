@@ -0,0 +1,232 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Cumulative usage of the VM resources, for billing. It is accumulated by
+//! the VMM from the counters of the vCPUs and of the devices, rather than
+//! kept by the VM, so that it survives the reboots re-creating the VM and the
+//! removal of devices. The counters are read when the usage is requested,
+//! and before the VM or a device goes away.
+//!
+//! The usage is reported since the last reset, reading and resetting it
+//! being done at once so that nothing is lost between the two.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::num::Wrapping;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const VCPU_COUNTERS_PREFIX: &str = "__vcpu";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub read_ops: u64,
+    pub write_ops: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NetUsage {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_frames: u64,
+    pub tx_frames: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct VmUsage {
+    /// Milliseconds since the UNIX epoch at which the accounting started or
+    /// was last reset.
+    pub since_ms: u64,
+    /// CPU time consumed by the vCPUs running the guest.
+    pub vcpu_time_ns: u64,
+    /// Usage of the block devices, by device.
+    pub disks: BTreeMap<String, DiskUsage>,
+    /// Usage of the network devices, by device.
+    pub nets: BTreeMap<String, NetUsage>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+pub struct Accounting {
+    since_ms: u64,
+    // Last value read from each counter of the current VM, by device.
+    last: HashMap<String, HashMap<&'static str, u64>>,
+    // Increase of each counter since the last reset, by device.
+    totals: BTreeMap<String, BTreeMap<&'static str, u64>>,
+}
+
+impl Default for Accounting {
+    fn default() -> Self {
+        Accounting {
+            since_ms: now_ms(),
+            last: HashMap::new(),
+            totals: BTreeMap::new(),
+        }
+    }
+}
+
+impl Accounting {
+    /// Start the accounting over, for a new VM.
+    pub fn clear(&mut self) {
+        *self = Accounting::default();
+    }
+
+    /// Account for the increase of the counters since they were last read.
+    pub fn update(&mut self, counters: &HashMap<String, HashMap<&'static str, Wrapping<u64>>>) {
+        for (id, device_counters) in counters.iter() {
+            let last = self.last.entry(id.clone()).or_default();
+            let totals = self.totals.entry(id.clone()).or_default();
+            for (name, value) in device_counters.iter() {
+                let previous = last.insert(*name, value.0).unwrap_or_default();
+                // The counter started over, for instance when the vCPU was
+                // unplugged and plugged again.
+                let increase = if value.0 >= previous {
+                    value.0 - previous
+                } else {
+                    value.0
+                };
+                *totals.entry(*name).or_default() += increase;
+            }
+        }
+    }
+
+    /// Forget the counters of a device once it is removed, as they start
+    /// over if a device with the same identifier is added.
+    pub fn remove_device(&mut self, id: &str) {
+        self.last.remove(id);
+    }
+
+    /// Forget the counters of the VM once it is shut down, as they start
+    /// over when it is created again.
+    pub fn remove_vm(&mut self) {
+        self.last.clear();
+    }
+
+    fn total(&self, id: &str, name: &str) -> u64 {
+        self.totals
+            .get(id)
+            .and_then(|totals| totals.get(name))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn usage(&self) -> VmUsage {
+        let mut usage = VmUsage {
+            since_ms: self.since_ms,
+            ..Default::default()
+        };
+
+        for (id, totals) in self.totals.iter() {
+            if id.starts_with(VCPU_COUNTERS_PREFIX) {
+                usage.vcpu_time_ns += self.total(id, "cpu_time_ns");
+            } else if totals.contains_key("read_bytes") {
+                usage.disks.insert(
+                    id.clone(),
+                    DiskUsage {
+                        read_bytes: self.total(id, "read_bytes"),
+                        write_bytes: self.total(id, "write_bytes"),
+                        read_ops: self.total(id, "read_ops"),
+                        write_ops: self.total(id, "write_ops"),
+                    },
+                );
+            } else if totals.contains_key("rx_bytes") {
+                usage.nets.insert(
+                    id.clone(),
+                    NetUsage {
+                        rx_bytes: self.total(id, "rx_bytes"),
+                        tx_bytes: self.total(id, "tx_bytes"),
+                        rx_frames: self.total(id, "rx_frames"),
+                        tx_frames: self.total(id, "tx_frames"),
+                    },
+                );
+            }
+        }
+
+        usage
+    }
+
+    /// Return the usage since the last reset, and start it over.
+    pub fn reset(&mut self) -> VmUsage {
+        let usage = self.usage();
+        self.totals.clear();
+        self.since_ms = now_ms();
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(
+        entries: &[(&str, &[(&'static str, u64)])],
+    ) -> HashMap<String, HashMap<&'static str, Wrapping<u64>>> {
+        entries
+            .iter()
+            .map(|(id, values)| {
+                (
+                    id.to_string(),
+                    values
+                        .iter()
+                        .map(|(name, value)| (*name, Wrapping(*value)))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_accounting() {
+        let mut accounting = Accounting::default();
+        accounting.update(&counters(&[
+            ("__vcpu0", &[("cpu_time_ns", 100), ("exits", 5)]),
+            ("__vcpu1", &[("cpu_time_ns", 50)]),
+            ("_disk0", &[("read_bytes", 4096), ("write_bytes", 512)]),
+            ("_net0", &[("rx_bytes", 1500), ("rx_frames", 1)]),
+            ("__rng", &[("bytes", 64)]),
+        ]));
+        accounting.update(&counters(&[
+            ("__vcpu0", &[("cpu_time_ns", 150)]),
+            ("__vcpu1", &[("cpu_time_ns", 50)]),
+            ("_disk0", &[("read_bytes", 8192), ("write_bytes", 512)]),
+        ]));
+
+        let usage = accounting.usage();
+        assert_eq!(usage.vcpu_time_ns, 200);
+        assert_eq!(usage.disks.len(), 1);
+        assert_eq!(usage.disks["_disk0"].read_bytes, 8192);
+        assert_eq!(usage.disks["_disk0"].write_bytes, 512);
+        assert_eq!(usage.nets["_net0"].rx_bytes, 1500);
+        assert_eq!(usage.nets["_net0"].rx_frames, 1);
+
+        // The usage of the devices and of the VM which went away is kept,
+        // while their counters start over.
+        accounting.remove_device("_disk0");
+        accounting.update(&counters(&[("_disk0", &[("read_bytes", 1024)])]));
+        accounting.remove_vm();
+        accounting.update(&counters(&[("__vcpu0", &[("cpu_time_ns", 10)])]));
+        let usage = accounting.usage();
+        assert_eq!(usage.vcpu_time_ns, 210);
+        assert_eq!(usage.disks["_disk0"].read_bytes, 9216);
+
+        // A counter lower than it was last read started over.
+        accounting.update(&counters(&[("__vcpu0", &[("cpu_time_ns", 5)])]));
+        assert_eq!(accounting.usage().vcpu_time_ns, 215);
+
+        // Resetting returns the usage up to the reset.
+        assert_eq!(accounting.reset().vcpu_time_ns, 215);
+        let usage = accounting.usage();
+        assert_eq!(usage.vcpu_time_ns, 0);
+        assert!(usage.disks.is_empty());
+        accounting.update(&counters(&[("__vcpu0", &[("cpu_time_ns", 25)])]));
+        assert_eq!(accounting.usage().vcpu_time_ns, 20);
+    }
+}
@@ -2,25 +2,39 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 //
+use crate::config::AcpiConfig;
 use crate::cpu::CpuManager;
 use crate::device_manager::DeviceManager;
 use crate::memory_manager::MemoryManager;
 use crate::pci_segment::PciSegment;
 use crate::{GuestMemoryMmap, GuestRegionMmap};
 use acpi_tables::sdt::GenericAddress;
-use acpi_tables::{aml::Aml, rsdp::Rsdp, sdt::Sdt};
+use acpi_tables::{aml, aml::Aml, rsdp::Rsdp, sdt::Sdt};
 #[cfg(target_arch = "aarch64")]
 use arch::aarch64::DeviceInfoForFdt;
 #[cfg(target_arch = "aarch64")]
 use arch::DeviceType;
 use arch::NumaNodes;
+use arch::PayloadBlob;
 
 use bitflags::bitflags;
 use pci::PciBdf;
+use std::io;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
+use thiserror::Error;
 use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryRegion};
 
+#[derive(Debug, Error)]
+pub enum SsdtError {
+    #[error("Cannot read SSDT file {0:?}: {1}")]
+    ReadFile(PathBuf, #[source] io::Error),
+
+    #[error("Invalid SSDT table in {0:?}")]
+    Invalid(PathBuf),
+}
+
 /* Values for Type in APIC sub-headers */
 #[cfg(target_arch = "x86_64")]
 pub const ACPI_APIC_PROCESSOR: u8 = 0;
@@ -246,7 +260,11 @@ fn create_mcfg_table(pci_segments: &[PciSegment]) -> Sdt {
     mcfg
 }
 
-fn create_srat_table(numa_nodes: &NumaNodes) -> Sdt {
+fn create_srat_table(
+    numa_nodes: &NumaNodes,
+    device_manager: &Arc<Mutex<DeviceManager>>,
+    memory_manager: &Arc<Mutex<MemoryManager>>,
+) -> Sdt {
     let mut srat = Sdt::new(*b"SRAT", 36, 3, *b"CLOUDH", *b"CHSRAT  ", 1);
     // SRAT reserved 12 bytes
     srat.append_slice(&[0u8; 12]);
@@ -274,6 +292,30 @@ fn create_srat_table(numa_nodes: &NumaNodes) -> Sdt {
             ))
         }
 
+        if node.memory_hotplug {
+            if let Some((start, size)) = memory_manager.lock().unwrap().acpi_hotplug_range() {
+                srat.append(MemoryAffinity::from_range(
+                    start.raw_value(),
+                    size,
+                    proximity_domain,
+                    MemAffinityFlags::ENABLE | MemAffinityFlags::HOTPLUGGABLE,
+                ))
+            }
+        }
+
+        // The guest finds the node of a pmem device from the affinity of
+        // the range backing it, as for a NVDIMM.
+        for pmem in &node.pmem_devices {
+            if let Some((start, size)) = device_manager.lock().unwrap().pmem_range(pmem) {
+                srat.append(MemoryAffinity::from_range(
+                    start.raw_value(),
+                    size,
+                    proximity_domain,
+                    MemAffinityFlags::ENABLE | MemAffinityFlags::NON_VOLATILE,
+                ))
+            }
+        }
+
         #[cfg(target_arch = "x86_64")]
         for section in &node.sgx_epc_sections {
             srat.append(MemoryAffinity::from_range(
@@ -585,26 +627,93 @@ fn create_viot_table(iommu_bdf: &PciBdf, devices_bdf: &[PciBdf]) -> Sdt {
     viot
 }
 
+// Replace the OEM table ID of the table if the user asked to override it
+// for this table signature.
+fn override_oem_table_id(table: &mut Sdt, acpi_config: Option<&AcpiConfig>) {
+    let signature = table.signature();
+    if let Some(override_config) = acpi_config.and_then(|c| {
+        c.oem_table_ids
+            .iter()
+            .find(|o| o.signature.as_bytes() == signature)
+    }) {
+        let mut oem_table_id = [b' '; 8];
+        oem_table_id[..override_config.oem_table_id.len()]
+            .copy_from_slice(override_config.oem_table_id.as_bytes());
+        table.set_oem_table_id(oem_table_id);
+    }
+}
+
+/// Load the user provided SSDT tables, each file containing a complete
+/// table including its header, such as the output of `iasl`.
+pub fn load_ssdt_tables(acpi_config: Option<&AcpiConfig>) -> Result<Vec<Sdt>, SsdtError> {
+    let mut tables = Vec::new();
+    for path in acpi_config.map(|c| c.ssdt.as_slice()).unwrap_or_default() {
+        let data = std::fs::read(path).map_err(|e| SsdtError::ReadFile(path.to_path_buf(), e))?;
+        let table = Sdt::from_bytes(data).ok_or_else(|| SsdtError::Invalid(path.to_path_buf()))?;
+        if table.signature() != *b"SSDT" {
+            return Err(SsdtError::Invalid(path.to_path_buf()));
+        }
+        tables.push(table);
+    }
+
+    Ok(tables)
+}
+
+/// Describe the payload blobs loaded in guest memory, each as a device whose
+/// `_UID` is the identifier of the blob.
+pub fn create_payload_blobs_ssdt(payload_blobs: &[PayloadBlob]) -> Sdt {
+    let mut ssdt = Sdt::new(*b"SSDT", 36, 2, *b"CLOUDH", *b"CHPAYLD ", 1);
+
+    let mut bytes = Vec::new();
+    for (i, payload_blob) in payload_blobs.iter().enumerate() {
+        let start = payload_blob.address.raw_value();
+        aml::Device::new(
+            format!("_SB_.PB{:02X}", i).as_str().into(),
+            vec![
+                &aml::Name::new("_HID".into(), &"CRTN0001"),
+                &aml::Name::new("_UID".into(), &payload_blob.id),
+                &aml::Name::new(
+                    "_CRS".into(),
+                    &aml::ResourceTemplate::new(vec![&aml::AddressSpace::new_memory(
+                        aml::AddressSpaceCachable::Cacheable,
+                        false,
+                        start,
+                        start + payload_blob.size as u64 - 1,
+                    )]),
+                ),
+            ],
+        )
+        .append_aml_bytes(&mut bytes);
+    }
+    ssdt.append_slice(&bytes);
+
+    ssdt
+}
+
 pub fn create_acpi_tables(
     guest_mem: &GuestMemoryMmap,
     device_manager: &Arc<Mutex<DeviceManager>>,
     cpu_manager: &Arc<Mutex<CpuManager>>,
     memory_manager: &Arc<Mutex<MemoryManager>>,
     numa_nodes: &NumaNodes,
+    acpi_config: Option<&AcpiConfig>,
+    ssdt_tables: Vec<Sdt>,
 ) -> GuestAddress {
     let start_time = Instant::now();
     let rsdp_offset = arch::layout::RSDP_POINTER;
     let mut tables: Vec<u64> = Vec::new();
 
     // DSDT
-    let dsdt = create_dsdt_table(device_manager, cpu_manager, memory_manager);
+    let mut dsdt = create_dsdt_table(device_manager, cpu_manager, memory_manager);
+    override_oem_table_id(&mut dsdt, acpi_config);
     let dsdt_offset = rsdp_offset.checked_add(Rsdp::len() as u64).unwrap();
     guest_mem
         .write_slice(dsdt.as_slice(), dsdt_offset)
         .expect("Error writing DSDT table");
 
     // FACP aka FADT
-    let facp = create_facp_table(dsdt_offset);
+    let mut facp = create_facp_table(dsdt_offset);
+    override_oem_table_id(&mut facp, acpi_config);
     let facp_offset = dsdt_offset.checked_add(dsdt.len() as u64).unwrap();
     guest_mem
         .write_slice(facp.as_slice(), facp_offset)
@@ -612,7 +721,8 @@ pub fn create_acpi_tables(
     tables.push(facp_offset.0);
 
     // MADT
-    let madt = cpu_manager.lock().unwrap().create_madt();
+    let mut madt = cpu_manager.lock().unwrap().create_madt();
+    override_oem_table_id(&mut madt, acpi_config);
     let madt_offset = facp_offset.checked_add(facp.len() as u64).unwrap();
     guest_mem
         .write_slice(madt.as_slice(), madt_offset)
@@ -624,7 +734,8 @@ pub fn create_acpi_tables(
     // PPTT
     #[cfg(target_arch = "aarch64")]
     {
-        let pptt = cpu_manager.lock().unwrap().create_pptt();
+        let mut pptt = cpu_manager.lock().unwrap().create_pptt();
+        override_oem_table_id(&mut pptt, acpi_config);
         let pptt_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
         guest_mem
             .write_slice(pptt.as_slice(), pptt_offset)
@@ -637,7 +748,8 @@ pub fn create_acpi_tables(
     // GTDT
     #[cfg(target_arch = "aarch64")]
     {
-        let gtdt = create_gtdt_table();
+        let mut gtdt = create_gtdt_table();
+        override_oem_table_id(&mut gtdt, acpi_config);
         let gtdt_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
         guest_mem
             .write_slice(gtdt.as_slice(), gtdt_offset)
@@ -648,7 +760,8 @@ pub fn create_acpi_tables(
     }
 
     // MCFG
-    let mcfg = create_mcfg_table(device_manager.lock().unwrap().pci_segments());
+    let mut mcfg = create_mcfg_table(device_manager.lock().unwrap().pci_segments());
+    override_oem_table_id(&mut mcfg, acpi_config);
     let mcfg_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
     guest_mem
         .write_slice(mcfg.as_slice(), mcfg_offset)
@@ -683,7 +796,8 @@ pub fn create_acpi_tables(
         };
 
         // SPCR
-        let spcr = create_spcr_table(serial_device_addr, serial_device_irq);
+        let mut spcr = create_spcr_table(serial_device_addr, serial_device_irq);
+        override_oem_table_id(&mut spcr, acpi_config);
         let spcr_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
         guest_mem
             .write_slice(spcr.as_slice(), spcr_offset)
@@ -693,7 +807,8 @@ pub fn create_acpi_tables(
         prev_tbl_off = spcr_offset;
 
         // DBG2
-        let dbg2 = create_dbg2_table(serial_device_addr);
+        let mut dbg2 = create_dbg2_table(serial_device_addr);
+        override_oem_table_id(&mut dbg2, acpi_config);
         let dbg2_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
         guest_mem
             .write_slice(dbg2.as_slice(), dbg2_offset)
@@ -707,7 +822,8 @@ pub fn create_acpi_tables(
     // Only created if the NUMA nodes list is not empty.
     if !numa_nodes.is_empty() {
         // SRAT
-        let srat = create_srat_table(numa_nodes);
+        let mut srat = create_srat_table(numa_nodes, device_manager, memory_manager);
+        override_oem_table_id(&mut srat, acpi_config);
         let srat_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
         guest_mem
             .write_slice(srat.as_slice(), srat_offset)
@@ -715,7 +831,8 @@ pub fn create_acpi_tables(
         tables.push(srat_offset.0);
 
         // SLIT
-        let slit = create_slit_table(numa_nodes);
+        let mut slit = create_slit_table(numa_nodes);
+        override_oem_table_id(&mut slit, acpi_config);
         let slit_offset = srat_offset.checked_add(srat.len() as u64).unwrap();
         guest_mem
             .write_slice(slit.as_slice(), slit_offset)
@@ -728,7 +845,8 @@ pub fn create_acpi_tables(
 
     #[cfg(target_arch = "aarch64")]
     {
-        let iort = create_iort_table(device_manager.lock().unwrap().pci_segments());
+        let mut iort = create_iort_table(device_manager.lock().unwrap().pci_segments());
+        override_oem_table_id(&mut iort, acpi_config);
         let iort_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
         guest_mem
             .write_slice(iort.as_slice(), iort_offset)
@@ -741,7 +859,8 @@ pub fn create_acpi_tables(
     // VIOT
     if let Some((iommu_bdf, devices_bdf)) = device_manager.lock().unwrap().iommu_attached_devices()
     {
-        let viot = create_viot_table(iommu_bdf, devices_bdf);
+        let mut viot = create_viot_table(iommu_bdf, devices_bdf);
+        override_oem_table_id(&mut viot, acpi_config);
 
         let viot_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
         guest_mem
@@ -752,12 +871,25 @@ pub fn create_acpi_tables(
         prev_tbl_off = viot_offset;
     }
 
+    // Additional SSDT tables provided by the user
+    for mut ssdt in ssdt_tables {
+        override_oem_table_id(&mut ssdt, acpi_config);
+        let ssdt_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
+        guest_mem
+            .write_slice(ssdt.as_slice(), ssdt_offset)
+            .expect("Error writing SSDT table");
+        tables.push(ssdt_offset.0);
+        prev_tbl_len = ssdt.len() as u64;
+        prev_tbl_off = ssdt_offset;
+    }
+
     // XSDT
     let mut xsdt = Sdt::new(*b"XSDT", 36, 1, *b"CLOUDH", *b"CHXSDT  ", 1);
     for table in tables {
         xsdt.append(table);
     }
     xsdt.update_checksum();
+    override_oem_table_id(&mut xsdt, acpi_config);
     let xsdt_offset = prev_tbl_off.checked_add(prev_tbl_len).unwrap();
     guest_mem
         .write_slice(xsdt.as_slice(), xsdt_offset)
@@ -783,6 +915,8 @@ pub fn create_acpi_tables_tdx(
     cpu_manager: &Arc<Mutex<CpuManager>>,
     memory_manager: &Arc<Mutex<MemoryManager>>,
     numa_nodes: &NumaNodes,
+    acpi_config: Option<&AcpiConfig>,
+    ssdt_tables: Vec<Sdt>,
 ) -> Vec<Sdt> {
     // DSDT
     let mut tables = vec![create_dsdt_table(
@@ -806,7 +940,11 @@ pub fn create_acpi_tables_tdx(
     // Only created if the NUMA nodes list is not empty.
     if !numa_nodes.is_empty() {
         // SRAT
-        tables.push(create_srat_table(numa_nodes));
+        tables.push(create_srat_table(
+            numa_nodes,
+            device_manager,
+            memory_manager,
+        ));
 
         // SLIT
         tables.push(create_slit_table(numa_nodes));
@@ -818,5 +956,12 @@ pub fn create_acpi_tables_tdx(
         tables.push(create_viot_table(iommu_bdf, devices_bdf));
     }
 
+    // Additional SSDT tables provided by the user
+    tables.extend(ssdt_tables);
+
+    for table in tables.iter_mut() {
+        override_oem_table_id(table, acpi_config);
+    }
+
     tables
 }
@@ -3,7 +3,9 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use crate::api::http_endpoint::{VmActionHandler, VmCreate, VmInfo, VmmPing, VmmShutdown};
+use crate::api::http_endpoint::{
+    VmActionHandler, VmCreate, VmInfo, VmmConfigSchema, VmmPing, VmmShutdown, VmmSnapshotInfo,
+};
 use crate::api::{ApiError, ApiRequest, VmAction};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 use crate::{Error as VmmError, Result};
@@ -136,6 +138,7 @@ lazy_static! {
             routes: HashMap::new(),
         };
 
+        r.routes.insert(endpoint!("/vm.accounting"), Box::new(VmActionHandler::new(VmAction::Accounting(Arc::default()))));
         r.routes.insert(endpoint!("/vm.add-device"), Box::new(VmActionHandler::new(VmAction::AddDevice(Arc::default()))));
         r.routes.insert(endpoint!("/vm.add-user-device"), Box::new(VmActionHandler::new(VmAction::AddUserDevice(Arc::default()))));
         r.routes.insert(endpoint!("/vm.add-disk"), Box::new(VmActionHandler::new(VmAction::AddDisk(Arc::default()))));
@@ -149,7 +152,11 @@ lazy_static! {
         r.routes.insert(endpoint!("/vm.create"), Box::new(VmCreate {}));
         r.routes.insert(endpoint!("/vm.delete"), Box::new(VmActionHandler::new(VmAction::Delete)));
         r.routes.insert(endpoint!("/vm.info"), Box::new(VmInfo {}));
+        r.routes.insert(endpoint!("/vm.maintenance"), Box::new(VmActionHandler::new(VmAction::EnterMaintenance(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.maintenance-end"), Box::new(VmActionHandler::new(VmAction::EndMaintenance)));
+        r.routes.insert(endpoint!("/vm.maintenance-renew"), Box::new(VmActionHandler::new(VmAction::RenewMaintenance)));
         r.routes.insert(endpoint!("/vm.pause"), Box::new(VmActionHandler::new(VmAction::Pause)));
+        r.routes.insert(endpoint!("/vm.pstore"), Box::new(VmActionHandler::new(VmAction::Pstore)));
         r.routes.insert(endpoint!("/vm.power-button"), Box::new(VmActionHandler::new(VmAction::PowerButton)));
         r.routes.insert(endpoint!("/vm.reboot"), Box::new(VmActionHandler::new(VmAction::Reboot)));
         r.routes.insert(endpoint!("/vm.receive-migration"), Box::new(VmActionHandler::new(VmAction::ReceiveMigration(Arc::default()))));
@@ -158,13 +165,26 @@ lazy_static! {
         r.routes.insert(endpoint!("/vm.resize-zone"), Box::new(VmActionHandler::new(VmAction::ResizeZone(Arc::default()))));
         r.routes.insert(endpoint!("/vm.restore"), Box::new(VmActionHandler::new(VmAction::Restore(Arc::default()))));
         r.routes.insert(endpoint!("/vm.resume"), Box::new(VmActionHandler::new(VmAction::Resume)));
+        r.routes.insert(endpoint!("/vm.security-status"), Box::new(VmActionHandler::new(VmAction::SecurityStatus)));
+        r.routes.insert(endpoint!("/vm.device-tree"), Box::new(VmActionHandler::new(VmAction::DeviceTree)));
+        r.routes.insert(endpoint!("/vm.queues"), Box::new(VmActionHandler::new(VmAction::Queues)));
         r.routes.insert(endpoint!("/vm.send-migration"), Box::new(VmActionHandler::new(VmAction::SendMigration(Arc::default()))));
         r.routes.insert(endpoint!("/vm.shutdown"), Box::new(VmActionHandler::new(VmAction::Shutdown)));
         r.routes.insert(endpoint!("/vm.snapshot"), Box::new(VmActionHandler::new(VmAction::Snapshot(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.memory-template"), Box::new(VmActionHandler::new(VmAction::MemoryTemplate(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.throttle-vcpus"), Box::new(VmActionHandler::new(VmAction::ThrottleVcpus(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.set-rate-limiter"), Box::new(VmActionHandler::new(VmAction::SetRateLimiter(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.set-interrupt-coalescing"), Box::new(VmActionHandler::new(VmAction::SetInterruptCoalescing(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.set-queue-size"), Box::new(VmActionHandler::new(VmAction::SetQueueSize(Arc::default()))));
+        r.routes.insert(endpoint!("/vm.inject-nmi"), Box::new(VmActionHandler::new(VmAction::InjectNmi(Arc::default()))));
+        #[cfg(feature = "fault_injection")]
+        r.routes.insert(endpoint!("/vm.inject-fault"), Box::new(VmActionHandler::new(VmAction::InjectFault(Arc::default()))));
         #[cfg(feature = "guest_debug")]
         r.routes.insert(endpoint!("/vm.coredump"), Box::new(VmActionHandler::new(VmAction::Coredump(Arc::default()))));
+        r.routes.insert(endpoint!("/vmm.config-schema"), Box::new(VmmConfigSchema {}));
         r.routes.insert(endpoint!("/vmm.ping"), Box::new(VmmPing {}));
         r.routes.insert(endpoint!("/vmm.shutdown"), Box::new(VmmShutdown {}));
+        r.routes.insert(endpoint!("/vmm.snapshot-info"), Box::new(VmmSnapshotInfo {}));
 
         r
     };
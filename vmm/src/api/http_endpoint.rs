@@ -6,14 +6,19 @@
 use crate::api::http::{error_response, EndpointHandler, HttpError};
 #[cfg(feature = "guest_debug")]
 use crate::api::vm_coredump;
+#[cfg(feature = "fault_injection")]
+use crate::api::vm_inject_fault;
 use crate::api::{
-    vm_add_device, vm_add_disk, vm_add_fs, vm_add_net, vm_add_pmem, vm_add_user_device,
-    vm_add_vdpa, vm_add_vsock, vm_boot, vm_counters, vm_create, vm_delete, vm_info, vm_pause,
-    vm_power_button, vm_reboot, vm_receive_migration, vm_remove_device, vm_resize, vm_resize_zone,
-    vm_restore, vm_resume, vm_send_migration, vm_shutdown, vm_snapshot, vmm_ping, vmm_shutdown,
-    ApiRequest, VmAction, VmConfig,
+    vm_accounting, vm_add_device, vm_add_disk, vm_add_fs, vm_add_net, vm_add_pmem,
+    vm_add_user_device, vm_add_vdpa, vm_add_vsock, vm_boot, vm_counters, vm_create, vm_delete,
+    vm_device_tree, vm_end_maintenance, vm_enter_maintenance, vm_info, vm_inject_nmi,
+    vm_memory_template, vm_pause, vm_power_button, vm_pstore, vm_queues, vm_reboot,
+    vm_receive_migration, vm_remove_device, vm_renew_maintenance, vm_resize, vm_resize_zone,
+    vm_restore, vm_resume, vm_security_status, vm_send_migration, vm_set_interrupt_coalescing,
+    vm_set_queue_size, vm_set_rate_limiter, vm_shutdown, vm_snapshot, vm_throttle_vcpus, vmm_ping,
+    vmm_shutdown, vmm_snapshot_info, ApiRequest, VmAction, VmConfig,
 };
-use crate::config::NetConfig;
+use crate::config::{vm_config_schema, NetConfig};
 use micro_http::{Body, Method, Request, Response, StatusCode, Version};
 use std::fs::File;
 use std::os::unix::io::IntoRawFd;
@@ -43,6 +48,27 @@ impl EndpointHandler for VmCreate {
                             Err(e) => return error_response(e, StatusCode::BadRequest),
                         };
 
+                        // The file descriptors of the doorbell channels can
+                        // only be given on the command line, the numbers
+                        // coming with the request being any file of the VMM.
+                        if vm_config
+                            .doorbells
+                            .as_ref()
+                            .map_or(false, |d| !d.is_empty())
+                        {
+                            return error_response(HttpError::BadRequest, StatusCode::BadRequest);
+                        }
+
+                        // Neither can the watchdog hook, the helper process
+                        // running it being able to execute any program.
+                        if vm_config
+                            .watchdog_config
+                            .as_ref()
+                            .map_or(false, |w| w.hook.is_some())
+                        {
+                            return error_response(HttpError::BadRequest, StatusCode::BadRequest);
+                        }
+
                         // Call vm_create()
                         match vm_create(api_notifier, api_sender, Arc::new(Mutex::new(vm_config)))
                             .map_err(HttpError::ApiError)
@@ -143,6 +169,37 @@ impl EndpointHandler for VmActionHandler {
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 ),
+                ThrottleVcpus(_) => vm_throttle_vcpus(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                SetRateLimiter(_) => vm_set_rate_limiter(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                SetInterruptCoalescing(_) => vm_set_interrupt_coalescing(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                SetQueueSize(_) => vm_set_queue_size(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                InjectNmi(_) => vm_inject_nmi(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                #[cfg(feature = "fault_injection")]
+                InjectFault(_) => vm_inject_fault(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
                 Restore(_) => vm_restore(
                     api_notifier,
                     api_sender,
@@ -153,6 +210,11 @@ impl EndpointHandler for VmActionHandler {
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 ),
+                MemoryTemplate(_) => vm_memory_template(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
                 #[cfg(feature = "guest_debug")]
                 Coredump(_) => vm_coredump(
                     api_notifier,
@@ -169,6 +231,16 @@ impl EndpointHandler for VmActionHandler {
                     api_sender,
                     Arc::new(serde_json::from_slice(body.raw())?),
                 ),
+                Accounting(_) => vm_accounting(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
+                EnterMaintenance(_) => vm_enter_maintenance(
+                    api_notifier,
+                    api_sender,
+                    Arc::new(serde_json::from_slice(body.raw())?),
+                ),
 
                 _ => return Err(HttpError::BadRequest),
             }
@@ -181,6 +253,11 @@ impl EndpointHandler for VmActionHandler {
                 Pause => vm_pause(api_notifier, api_sender),
                 Resume => vm_resume(api_notifier, api_sender),
                 PowerButton => vm_power_button(api_notifier, api_sender),
+                EnterMaintenance(_) => {
+                    vm_enter_maintenance(api_notifier, api_sender, Arc::default())
+                }
+                RenewMaintenance => vm_renew_maintenance(api_notifier, api_sender),
+                EndMaintenance => vm_end_maintenance(api_notifier, api_sender),
                 _ => return Err(HttpError::BadRequest),
             }
         }
@@ -196,6 +273,15 @@ impl EndpointHandler for VmActionHandler {
         use VmAction::*;
         match self.action {
             Counters => vm_counters(api_notifier, api_sender).map_err(HttpError::ApiError),
+            Accounting(_) => {
+                vm_accounting(api_notifier, api_sender, Arc::default()).map_err(HttpError::ApiError)
+            }
+            Pstore => vm_pstore(api_notifier, api_sender).map_err(HttpError::ApiError),
+            SecurityStatus => {
+                vm_security_status(api_notifier, api_sender).map_err(HttpError::ApiError)
+            }
+            DeviceTree => vm_device_tree(api_notifier, api_sender).map_err(HttpError::ApiError),
+            Queues => vm_queues(api_notifier, api_sender).map_err(HttpError::ApiError),
             _ => Err(HttpError::BadRequest),
         }
     }
@@ -254,6 +340,74 @@ impl EndpointHandler for VmmPing {
     }
 }
 
+// /api/v1/vmm.snapshot-info handler
+pub struct VmmSnapshotInfo {}
+
+impl EndpointHandler for VmmSnapshotInfo {
+    fn handle_request(
+        &self,
+        req: &Request,
+        api_notifier: EventFd,
+        api_sender: Sender<ApiRequest>,
+    ) -> Response {
+        match req.method() {
+            Method::Put => match &req.body {
+                Some(body) => {
+                    let info_data = match serde_json::from_slice(body.raw())
+                        .map_err(HttpError::SerdeJsonDeserialize)
+                    {
+                        Ok(data) => data,
+                        Err(e) => return error_response(e, StatusCode::BadRequest),
+                    };
+
+                    match vmm_snapshot_info(api_notifier, api_sender, Arc::new(info_data))
+                        .map_err(HttpError::ApiError)
+                    {
+                        Ok(info) => {
+                            let mut response = Response::new(Version::Http11, StatusCode::OK);
+                            let info_serialized = serde_json::to_string(&info).unwrap();
+
+                            response.set_body(Body::new(info_serialized));
+                            response
+                        }
+                        Err(e) => error_response(e, StatusCode::InternalServerError),
+                    }
+                }
+
+                None => Response::new(Version::Http11, StatusCode::BadRequest),
+            },
+
+            _ => error_response(HttpError::BadRequest, StatusCode::BadRequest),
+        }
+    }
+}
+
+// /api/v1/vmm.config-schema handler
+pub struct VmmConfigSchema {}
+
+impl EndpointHandler for VmmConfigSchema {
+    fn handle_request(
+        &self,
+        req: &Request,
+        _api_notifier: EventFd,
+        _api_sender: Sender<ApiRequest>,
+    ) -> Response {
+        match req.method() {
+            // The schema only depends on the build, the VMM thread doesn't
+            // need to be involved.
+            Method::Get => {
+                let mut response = Response::new(Version::Http11, StatusCode::OK);
+                let schema_serialized = serde_json::to_string(&vm_config_schema()).unwrap();
+
+                response.set_body(Body::new(schema_serialized));
+                response
+            }
+
+            _ => error_response(HttpError::BadRequest, StatusCode::BadRequest),
+        }
+    }
+}
+
 // /api/v1/vmm.shutdown handler
 pub struct VmmShutdown {}
 
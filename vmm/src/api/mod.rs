@@ -35,16 +35,21 @@ pub mod http;
 pub mod http_endpoint;
 
 use crate::config::{
-    DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig, UserDeviceConfig,
-    VdpaConfig, VmConfig, VsockConfig,
+    DeviceConfig, DiskConfig, FsConfig, MaintenanceExpiryAction, NetConfig, PmemConfig,
+    RestoreConfig, SnapshotFormat, UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig,
 };
 use crate::device_tree::DeviceTree;
+use crate::maintenance::MaintenanceCheckpoint;
+use crate::migration::SnapshotInfo;
+use crate::state_history::VmStateTransition;
 use crate::vm::{Error as VmError, VmState};
 use micro_http::Body;
 use serde::{Deserialize, Serialize};
 use std::io;
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
+use virtio_devices::{InterruptCoalescingConfig, RateLimiterConfig};
 use vm_migration::MigratableError;
 use vmm_sys_util::eventfd::EventFd;
 
@@ -96,6 +101,9 @@ pub enum ApiError {
     /// The VM could not be snapshotted.
     VmSnapshot(VmError),
 
+    /// The memory templates could not be written.
+    VmMemoryTemplate(VmError),
+
     /// The VM could not restored.
     VmRestore(VmError),
 
@@ -105,12 +113,46 @@ pub enum ApiError {
     /// The VMM could not shutdown.
     VmmShutdown(VmError),
 
+    /// The snapshot could not be inspected.
+    VmmSnapshotInfo(VmError),
+
+    /// The pstore records could not be retrieved.
+    VmPstore(VmError),
+
+    /// The security status could not be retrieved.
+    VmSecurityStatus(VmError),
+
+    /// The device tree could not be retrieved.
+    VmDeviceTree(VmError),
+
+    /// The queues of the devices could not be retrieved.
+    VmQueues(VmError),
+
     /// The VM could not be resized
     VmResize(VmError),
 
     /// The memory zone could not be resized.
     VmResizeZone(VmError),
 
+    /// The vCPUs could not be throttled.
+    VmThrottleVcpus(VmError),
+
+    /// The rate limiting of the device could not be changed.
+    VmSetRateLimiter(VmError),
+
+    /// The interrupt coalescing of the device could not be changed.
+    VmSetInterruptCoalescing(VmError),
+
+    /// The size of the queues of the device could not be changed.
+    VmSetQueueSize(VmError),
+
+    /// The NMI could not be injected.
+    VmInjectNmi(VmError),
+
+    /// The faults could not be injected.
+    #[cfg(feature = "fault_injection")]
+    VmInjectFault(VmError),
+
     /// The device could not be added to the VM.
     VmAddDevice(VmError),
 
@@ -152,6 +194,15 @@ pub enum ApiError {
 
     /// Error triggering power button
     VmPowerButton(VmError),
+
+    /// The VM could not enter maintenance.
+    VmEnterMaintenance(VmError),
+
+    /// The maintenance lease could not be renewed.
+    VmRenewMaintenance(VmError),
+
+    /// The VM could not leave maintenance.
+    VmEndMaintenance(VmError),
 }
 pub type ApiResult<T> = std::result::Result<T, ApiError>;
 
@@ -161,6 +212,13 @@ pub struct VmInfo {
     pub state: VmState,
     pub memory_actual_size: u64,
     pub device_tree: Option<Arc<Mutex<DeviceTree>>>,
+    pub guest_panicked: bool,
+    #[serde(default)]
+    pub uptime_ms: u64,
+    #[serde(default)]
+    pub state_history: Vec<VmStateTransition>,
+    #[serde(default)]
+    pub maintenance: Option<MaintenanceCheckpoint>,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -181,6 +239,105 @@ pub struct VmResizeZoneData {
     pub desired_ram: u64,
 }
 
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmThrottleVcpusData {
+    pub percentage: u8,
+}
+
+/// Rate limiting of a disk or network device, which is lifted when no
+/// configuration is given.
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmSetRateLimiterData {
+    pub id: String,
+    #[serde(default)]
+    pub rate_limiter_config: Option<RateLimiterConfig>,
+}
+
+/// Interrupt coalescing of a disk or network device, which is lifted when no
+/// configuration is given.
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmSetInterruptCoalescingData {
+    pub id: String,
+    #[serde(default)]
+    pub interrupt_coalescing: Option<InterruptCoalescingConfig>,
+}
+
+/// Size of the queues of a disk, network or filesystem device.
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmSetQueueSizeData {
+    pub id: String,
+    pub queue_size: u16,
+}
+
+/// Request for the resource usage of the VM, which is started over once
+/// returned when `reset` is set.
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmAccountingData {
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// Maintenance requested by the orchestrator. The settings not provided are
+/// the ones of the VM configuration, or the defaults.
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmMaintenanceData {
+    #[serde(default)]
+    pub lease: Option<u64>,
+    #[serde(default)]
+    pub on_expiry: Option<MaintenanceExpiryAction>,
+    #[serde(default)]
+    pub checkpoint: Option<PathBuf>,
+}
+
+/// SGI set pending by default in place of an NMI on aarch64. Linux keeps
+/// the SGIs 0 to 7 for its IPIs, SGI 0 rescheduling the vCPU, while the
+/// SGIs 8 to 15 are left to the secure firmware, which the guest doesn't
+/// have.
+pub const DEFAULT_NMI_SGI: u8 = 8;
+
+fn default_nmi_sgi() -> u8 {
+    DEFAULT_NMI_SGI
+}
+
+/// NMI injected into a vCPU. On aarch64, where there is no NMI, the given
+/// SGI is set pending instead, which the guest may handle as a pseudo-NMI.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct VmInjectNmiData {
+    pub cpu_id: u8,
+    #[serde(default = "default_nmi_sgi")]
+    pub sgi: u8,
+}
+
+impl Default for VmInjectNmiData {
+    fn default() -> Self {
+        VmInjectNmiData {
+            cpu_id: 0,
+            sgi: DEFAULT_NMI_SGI,
+        }
+    }
+}
+
+/// Faults injected into a device, or into the VMM when no device identifier
+/// is provided. Each request replaces the faults previously injected into
+/// the same target, an empty request lifting them.
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmInjectFaultData {
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Inclusive ranges of sectors the disk fails to access with EIO
+    #[serde(default)]
+    pub disk_io_errors: Vec<(u64, u64)>,
+    /// One network packet out of this number is dropped
+    #[serde(default)]
+    pub net_drop_interval: u64,
+    /// Delay applied before the device interrupts are delivered
+    #[serde(default)]
+    pub interrupt_delay_us: u64,
+    /// Whether the device hotplug requests fail
+    #[serde(default)]
+    pub hotplug_failure: bool,
+}
+
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmRemoveDeviceData {
     pub id: String,
@@ -190,12 +347,40 @@ pub struct VmRemoveDeviceData {
 pub struct VmSnapshotConfig {
     /// The snapshot destination URL
     pub destination_url: String,
+    /// The format the guest memory is stored with
+    #[serde(default)]
+    pub format: SnapshotFormat,
+    /// Only store the guest memory written since the previous snapshot of
+    /// the VM, which the snapshot is based on
+    #[serde(default)]
+    pub incremental: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmMemoryTemplateData {
+    /// The directory the memory templates are written to
+    pub destination_url: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Default, Debug)]
+pub struct VmmSnapshotInfoData {
+    /// The snapshot source URL
+    pub source_url: String,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
 pub struct VmCoredumpData {
     /// The coredump destination file
     pub destination_url: String,
+    /// Leave holes in the destination file for the zero pages
+    #[serde(default)]
+    pub sparse: bool,
+    /// Leave the pages inflated in the balloon out of the coredump
+    #[serde(default)]
+    pub exclude_free_pages: bool,
+    /// Compress the coredump with zstd
+    #[serde(default)]
+    pub compress: bool,
 }
 
 #[derive(Clone, Deserialize, Serialize, Default, Debug)]
@@ -213,6 +398,7 @@ pub struct VmSendMigrationData {
     pub local: bool,
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum ApiResponsePayload {
     /// No data is sent on the channel.
     Empty,
@@ -223,6 +409,9 @@ pub enum ApiResponsePayload {
     /// Vmm ping response
     VmmPing(VmmPingResponse),
 
+    /// Description of a stored snapshot
+    VmmSnapshotInfo(SnapshotInfo),
+
     /// Vm action response
     VmAction(Option<Vec<u8>>),
 }
@@ -256,6 +445,9 @@ pub enum ApiRequest {
     /// Request the VMM API server status
     VmmPing(Sender<ApiResponse>),
 
+    /// Describe a stored snapshot, without creating a VM from it.
+    VmmSnapshotInfo(Arc<VmmSnapshotInfoData>, Sender<ApiResponse>),
+
     /// Pause a VM.
     VmPause(Sender<ApiResponse>),
 
@@ -265,6 +457,24 @@ pub enum ApiRequest {
     /// Get counters for a VM.
     VmCounters(Sender<ApiResponse>),
 
+    /// Get the resource usage of a VM, for billing.
+    VmAccounting(Arc<VmAccountingData>, Sender<ApiResponse>),
+
+    /// Get the metrics of a VM in Prometheus format.
+    VmMetrics(Sender<ApiResponse>),
+
+    /// Get the pstore records written by the guest.
+    VmPstore(Sender<ApiResponse>),
+
+    /// Get the security mitigations active for a VM.
+    VmSecurityStatus(Sender<ApiResponse>),
+
+    /// Get the devices plugged into a VM.
+    VmDeviceTree(Sender<ApiResponse>),
+
+    /// Get the queues of the virtio devices of a VM.
+    VmQueues(Sender<ApiResponse>),
+
     /// Shut the previously booted virtual machine down.
     /// If the VM was not previously booted or created, the VMM API server
     /// will send a VmShutdown error back.
@@ -286,6 +496,25 @@ pub enum ApiRequest {
     /// Resize the memory zone.
     VmResizeZone(Arc<VmResizeZoneData>, Sender<ApiResponse>),
 
+    /// Throttle the vCPUs.
+    VmThrottleVcpus(Arc<VmThrottleVcpusData>, Sender<ApiResponse>),
+
+    /// Change the rate limiting of a device.
+    VmSetRateLimiter(Arc<VmSetRateLimiterData>, Sender<ApiResponse>),
+
+    /// Change the interrupt coalescing of a disk or network device.
+    VmSetInterruptCoalescing(Arc<VmSetInterruptCoalescingData>, Sender<ApiResponse>),
+
+    /// Change the size of the queues of a disk, network or filesystem device.
+    VmSetQueueSize(Arc<VmSetQueueSizeData>, Sender<ApiResponse>),
+
+    /// Inject an NMI into a vCPU.
+    VmInjectNmi(Arc<VmInjectNmiData>, Sender<ApiResponse>),
+
+    /// Inject faults into the VM devices or the VMM.
+    #[cfg(feature = "fault_injection")]
+    VmInjectFault(Arc<VmInjectFaultData>, Sender<ApiResponse>),
+
     /// Add a device to the VM.
     VmAddDevice(Arc<DeviceConfig>, Sender<ApiResponse>),
 
@@ -316,6 +545,9 @@ pub enum ApiRequest {
     /// Take a VM snapshot
     VmSnapshot(Arc<VmSnapshotConfig>, Sender<ApiResponse>),
 
+    /// Write the memory templates of the VM
+    VmMemoryTemplate(Arc<VmMemoryTemplateData>, Sender<ApiResponse>),
+
     /// Restore from a VM snapshot
     VmRestore(Arc<RestoreConfig>, Sender<ApiResponse>),
 
@@ -331,6 +563,15 @@ pub enum ApiRequest {
 
     // Trigger power button
     VmPowerButton(Sender<ApiResponse>),
+
+    /// Pause the VM for the maintenance of the host.
+    VmEnterMaintenance(Arc<VmMaintenanceData>, Sender<ApiResponse>),
+
+    /// Renew the lease of the maintenance.
+    VmRenewMaintenance(Sender<ApiResponse>),
+
+    /// End the maintenance, resuming the VM.
+    VmEndMaintenance(Sender<ApiResponse>),
 }
 
 pub fn vm_create(
@@ -376,6 +617,24 @@ pub enum VmAction {
     /// Return VM counters
     Counters,
 
+    /// Return VM resource usage
+    Accounting(Arc<VmAccountingData>),
+
+    /// Return VM metrics
+    Metrics,
+
+    /// Return pstore records
+    Pstore,
+
+    /// Return security status
+    SecurityStatus,
+
+    /// Return device tree
+    DeviceTree,
+
+    /// Return device queues
+    Queues,
+
     /// Add VFIO device
     AddDevice(Arc<DeviceConfig>),
 
@@ -409,12 +668,34 @@ pub enum VmAction {
     /// Resize memory zone
     ResizeZone(Arc<VmResizeZoneData>),
 
+    /// Throttle vCPUs
+    ThrottleVcpus(Arc<VmThrottleVcpusData>),
+
+    /// Set rate limiter
+    SetRateLimiter(Arc<VmSetRateLimiterData>),
+
+    /// Set interrupt coalescing
+    SetInterruptCoalescing(Arc<VmSetInterruptCoalescingData>),
+
+    /// Set queue size
+    SetQueueSize(Arc<VmSetQueueSizeData>),
+
+    /// Inject NMI
+    InjectNmi(Arc<VmInjectNmiData>),
+
+    /// Inject faults
+    #[cfg(feature = "fault_injection")]
+    InjectFault(Arc<VmInjectFaultData>),
+
     /// Restore VM
     Restore(Arc<RestoreConfig>),
 
     /// Snapshot VM
     Snapshot(Arc<VmSnapshotConfig>),
 
+    /// Write the memory templates
+    MemoryTemplate(Arc<VmMemoryTemplateData>),
+
     /// Coredump VM
     #[cfg(feature = "guest_debug")]
     Coredump(Arc<VmCoredumpData>),
@@ -427,6 +708,15 @@ pub enum VmAction {
 
     /// Power Button for clean shutdown
     PowerButton,
+
+    /// Enter maintenance
+    EnterMaintenance(Arc<VmMaintenanceData>),
+
+    /// Renew the maintenance lease
+    RenewMaintenance,
+
+    /// End maintenance
+    EndMaintenance,
 }
 
 fn vm_action(
@@ -445,6 +735,12 @@ fn vm_action(
         Pause => ApiRequest::VmPause(response_sender),
         Resume => ApiRequest::VmResume(response_sender),
         Counters => ApiRequest::VmCounters(response_sender),
+        Accounting(v) => ApiRequest::VmAccounting(v, response_sender),
+        Metrics => ApiRequest::VmMetrics(response_sender),
+        Pstore => ApiRequest::VmPstore(response_sender),
+        SecurityStatus => ApiRequest::VmSecurityStatus(response_sender),
+        DeviceTree => ApiRequest::VmDeviceTree(response_sender),
+        Queues => ApiRequest::VmQueues(response_sender),
         AddDevice(v) => ApiRequest::VmAddDevice(v, response_sender),
         AddDisk(v) => ApiRequest::VmAddDisk(v, response_sender),
         AddFs(v) => ApiRequest::VmAddFs(v, response_sender),
@@ -456,13 +752,24 @@ fn vm_action(
         RemoveDevice(v) => ApiRequest::VmRemoveDevice(v, response_sender),
         Resize(v) => ApiRequest::VmResize(v, response_sender),
         ResizeZone(v) => ApiRequest::VmResizeZone(v, response_sender),
+        ThrottleVcpus(v) => ApiRequest::VmThrottleVcpus(v, response_sender),
+        SetRateLimiter(v) => ApiRequest::VmSetRateLimiter(v, response_sender),
+        SetInterruptCoalescing(v) => ApiRequest::VmSetInterruptCoalescing(v, response_sender),
+        SetQueueSize(v) => ApiRequest::VmSetQueueSize(v, response_sender),
+        InjectNmi(v) => ApiRequest::VmInjectNmi(v, response_sender),
+        #[cfg(feature = "fault_injection")]
+        InjectFault(v) => ApiRequest::VmInjectFault(v, response_sender),
         Restore(v) => ApiRequest::VmRestore(v, response_sender),
         Snapshot(v) => ApiRequest::VmSnapshot(v, response_sender),
+        MemoryTemplate(v) => ApiRequest::VmMemoryTemplate(v, response_sender),
         #[cfg(feature = "guest_debug")]
         Coredump(v) => ApiRequest::VmCoredump(v, response_sender),
         ReceiveMigration(v) => ApiRequest::VmReceiveMigration(v, response_sender),
         SendMigration(v) => ApiRequest::VmSendMigration(v, response_sender),
         PowerButton => ApiRequest::VmPowerButton(response_sender),
+        EnterMaintenance(v) => ApiRequest::VmEnterMaintenance(v, response_sender),
+        RenewMaintenance => ApiRequest::VmRenewMaintenance(response_sender),
+        EndMaintenance => ApiRequest::VmEndMaintenance(response_sender),
     };
 
     // Send the VM request.
@@ -506,6 +813,37 @@ pub fn vm_counters(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResul
     vm_action(api_evt, api_sender, VmAction::Counters)
 }
 
+pub fn vm_accounting(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmAccountingData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Accounting(data))
+}
+
+pub fn vm_metrics(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Metrics)
+}
+
+pub fn vm_pstore(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Pstore)
+}
+
+pub fn vm_security_status(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::SecurityStatus)
+}
+
+pub fn vm_device_tree(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::DeviceTree)
+}
+
+pub fn vm_queues(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::Queues)
+}
+
 pub fn vm_power_button(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -513,6 +851,28 @@ pub fn vm_power_button(
     vm_action(api_evt, api_sender, VmAction::PowerButton)
 }
 
+pub fn vm_enter_maintenance(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmMaintenanceData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::EnterMaintenance(data))
+}
+
+pub fn vm_renew_maintenance(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::RenewMaintenance)
+}
+
+pub fn vm_end_maintenance(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::EndMaintenance)
+}
+
 pub fn vm_receive_migration(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -537,6 +897,14 @@ pub fn vm_snapshot(
     vm_action(api_evt, api_sender, VmAction::Snapshot(data))
 }
 
+pub fn vm_memory_template(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmMemoryTemplateData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::MemoryTemplate(data))
+}
+
 pub fn vm_restore(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
@@ -587,6 +955,26 @@ pub fn vmm_ping(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<V
     }
 }
 
+pub fn vmm_snapshot_info(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmmSnapshotInfoData>,
+) -> ApiResult<SnapshotInfo> {
+    let (response_sender, response_receiver) = channel();
+
+    api_sender
+        .send(ApiRequest::VmmSnapshotInfo(data, response_sender))
+        .map_err(ApiError::RequestSend)?;
+    api_evt.write(1).map_err(ApiError::EventFdWrite)?;
+
+    let snapshot_info = response_receiver.recv().map_err(ApiError::ResponseRecv)??;
+
+    match snapshot_info {
+        ApiResponsePayload::VmmSnapshotInfo(info) => Ok(info),
+        _ => Err(ApiError::ResponsePayloadType),
+    }
+}
+
 pub fn vmm_shutdown(api_evt: EventFd, api_sender: Sender<ApiRequest>) -> ApiResult<()> {
     let (response_sender, response_receiver) = channel();
 
@@ -617,6 +1005,55 @@ pub fn vm_resize_zone(
     vm_action(api_evt, api_sender, VmAction::ResizeZone(data))
 }
 
+pub fn vm_throttle_vcpus(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmThrottleVcpusData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::ThrottleVcpus(data))
+}
+
+pub fn vm_set_rate_limiter(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmSetRateLimiterData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::SetRateLimiter(data))
+}
+
+pub fn vm_set_interrupt_coalescing(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmSetInterruptCoalescingData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::SetInterruptCoalescing(data))
+}
+
+pub fn vm_set_queue_size(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmSetQueueSizeData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::SetQueueSize(data))
+}
+
+pub fn vm_inject_nmi(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmInjectNmiData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::InjectNmi(data))
+}
+
+#[cfg(feature = "fault_injection")]
+pub fn vm_inject_fault(
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    data: Arc<VmInjectFaultData>,
+) -> ApiResult<Option<Body>> {
+    vm_action(api_evt, api_sender, VmAction::InjectFault(data))
+}
+
 pub fn vm_add_device(
     api_evt: EventFd,
     api_sender: Sender<ApiRequest>,
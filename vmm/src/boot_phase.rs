@@ -0,0 +1,40 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Timing of the phases of the creation and the boot of a VM. Each phase is
+//! logged under the `boot` target when it starts and once it ends, along
+//! with its duration, so that boot time regressions can be located from the
+//! logs, which `--log-format json` makes easy to process.
+
+use std::time::Instant;
+
+pub const BOOT_PHASE_TARGET: &str = "boot";
+
+/// Phase of the boot, ended when dropped, including when it fails.
+pub struct BootPhase {
+    name: &'static str,
+    start: Instant,
+}
+
+impl BootPhase {
+    pub fn start(name: &'static str) -> Self {
+        debug!(target: BOOT_PHASE_TARGET, "Boot phase {} started", name);
+        BootPhase {
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for BootPhase {
+    fn drop(&mut self) {
+        info!(
+            target: BOOT_PHASE_TARGET,
+            "Boot phase {} ended after {}us",
+            self.name,
+            self.start.elapsed().as_micros()
+        );
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+const VCPUS_CGROUP: &str = "vcpus";
+
+// Period the CPU bandwidth quota applies to.
+pub const CPU_PERIOD_US: u64 = 100_000;
+// Default value of cpu.weight.
+pub const DEFAULT_CPU_WEIGHT: u16 = 100;
+
+// Path of the cgroup v2 the current process belongs to.
+fn current_cgroup() -> io::Result<PathBuf> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup")?;
+    let path = cgroups
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no cgroup v2 hierarchy"))?;
+
+    Ok(Path::new(CGROUP_MOUNT).join(path.trim_start_matches('/')))
+}
+
+// Threaded cgroup created under the cgroup of the VMM, holding the vCPU
+// threads so that CPU weight and bandwidth limits only apply to them.
+pub struct VcpusCgroup {
+    path: PathBuf,
+}
+
+impl VcpusCgroup {
+    pub fn new(weight: Option<u16>, quota: Option<u32>) -> io::Result<Self> {
+        let parent = current_cgroup()?;
+        let path = parent.join(VCPUS_CGROUP);
+
+        // The cgroup is left behind when the VM is shut down, and is reused
+        // if the VM is created again from the same process.
+        if let Err(e) = fs::create_dir(&path) {
+            if e.kind() != io::ErrorKind::AlreadyExists {
+                return Err(e);
+            }
+        }
+
+        // The cpu controller being threaded, the vCPU threads can be moved
+        // into a child cgroup while the other threads stay in the parent.
+        fs::write(path.join("cgroup.type"), "threaded")?;
+        fs::write(parent.join("cgroup.subtree_control"), "+cpu")?;
+
+        fs::write(
+            path.join("cpu.weight"),
+            weight.unwrap_or(DEFAULT_CPU_WEIGHT).to_string(),
+        )?;
+        let max = quota
+            .map(|q| (u64::from(q) * CPU_PERIOD_US / 100).to_string())
+            .unwrap_or_else(|| "max".to_owned());
+        fs::write(path.join("cpu.max"), format!("{} {}", max, CPU_PERIOD_US))?;
+
+        Ok(VcpusCgroup { path })
+    }
+
+    pub fn add_thread(&self, tid: u64) -> io::Result<()> {
+        fs::write(self.path.join("cgroup.threads"), tid.to_string())
+    }
+}
@@ -3,21 +3,28 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use crate::hugepages;
+use crate::priority::PriorityProfile;
+use crate::pstore::PSTORE_RECORD_SIZE;
+#[cfg(target_arch = "aarch64")]
+use arch::aarch64::fdt::FdtEdit;
+use arch::CpuModel;
 use clap::ArgMatches;
 use net_util::MacAddr;
 use option_parser::{
     ByteSized, IntegerList, OptionParser, OptionParserError, StringList, Toggle, Tuple,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeSet, HashMap};
 use std::convert::From;
 use std::fmt;
 use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use std::path::{Component, PathBuf};
 use std::result;
 use std::str::FromStr;
 use thiserror::Error;
-use virtio_devices::{RateLimiterConfig, TokenBucketConfig};
+use virtio_devices::{InterruptCoalescingConfig, RateLimiterConfig, TokenBucketConfig};
 
 pub const DEFAULT_VCPUS: u8 = 1;
 pub const DEFAULT_MEMORY_MB: u64 = 512;
@@ -35,6 +42,13 @@ pub const DEFAULT_QUEUE_SIZE_VUBLK: u16 = 128;
 
 pub const DEFAULT_NUM_PCI_SEGMENTS: u16 = 1;
 const MAX_NUM_PCI_SEGMENTS: u16 = 16;
+#[cfg(target_arch = "aarch64")]
+const MAX_MMIO_HOTPLUG_SLOTS: u8 = 16;
+
+pub const DEFAULT_PSTORE_SIZE: u64 = 1 << 20;
+
+// The payload blobs are named PB00 to PBFF in the ACPI namespace.
+const MAX_PAYLOAD_BLOBS: usize = 256;
 
 /// Errors associated with VM configuration parameters.
 #[derive(Debug, Error)]
@@ -65,6 +79,14 @@ pub enum Error {
     ParseDisk(OptionParserError),
     /// Error parsing network options
     ParseNetwork(OptionParserError),
+    /// Error parsing rate limiting options
+    ParseRateLimiter(OptionParserError),
+    /// Rate given along with the token bucket it is a shorthand for
+    ParseRateLimiterConflict(&'static str),
+    /// Error parsing interrupt coalescing options
+    ParseInterruptCoalescing(OptionParserError),
+    /// Interrupts coalesced without the delay bounding their latency
+    ParseInterruptCoalescingUsecsMissing,
     /// Error parsing RNG options
     ParseRng(OptionParserError),
     /// Error parsing balloon options
@@ -101,6 +123,12 @@ pub enum Error {
     #[cfg(feature = "tdx")]
     /// No TDX firmware
     FirmwarePathMissing,
+    #[cfg(feature = "sev_snp")]
+    /// Failed parsing SEV-SNP config
+    ParseSevSnp(OptionParserError),
+    #[cfg(feature = "sev_snp")]
+    /// No SEV-SNP firmware
+    SevSnpFirmwarePathMissing,
     /// Failed parsing userspace device
     ParseUserDevice(OptionParserError),
     /// Missing socket for userspace device
@@ -111,6 +139,68 @@ pub enum Error {
     ParseVdpa(OptionParserError),
     /// Missing path for vDPA device
     ParseVdpaPathMissing,
+    /// Failed parsing pstore parameters
+    ParsePstore(OptionParserError),
+    /// Missing pstore backing file parameter.
+    ParsePstoreFileMissing,
+    /// Failed parsing crash kernel parameters
+    ParseCrashKernel(OptionParserError),
+    /// Missing crash kernel size parameter.
+    ParseCrashKernelSizeMissing,
+    /// Failed parsing payload blob parameters
+    ParsePayloadBlob(OptionParserError),
+    /// Missing payload blob path parameter.
+    ParsePayloadBlobPathMissing,
+    /// Failed parsing PTP clock parameters
+    ParsePtp(OptionParserError),
+    /// Missing PTP clock path parameter.
+    ParsePtpPathMissing,
+    /// Failed parsing debug console parameters
+    ParseDebugConsole(OptionParserError),
+    /// Missing debug console output parameter.
+    ParseDebugConsoleOutputMissing,
+    /// Failed parsing ACPI parameters
+    ParseAcpi(OptionParserError),
+    /// Failed parsing FDT parameters
+    ParseFdt(OptionParserError),
+    /// Missing FDT base DTB parameter.
+    ParseFdtBaseMissing,
+    /// Failed parsing security parameters
+    ParseSecurity(OptionParserError),
+    /// Failed parsing deterministic execution parameters
+    ParseDeterministic(OptionParserError),
+    /// Missing seed for the deterministic execution
+    ParseDeterministicSeedMissing,
+    /// Failed parsing watchdog parameters
+    ParseWatchdog(OptionParserError),
+    /// Failed parsing snapshot schedule parameters
+    ParseSnapshotSchedule(OptionParserError),
+    /// Missing destination for the scheduled snapshots
+    ParseSnapshotScheduleDestinationMissing,
+    /// Missing interval between the scheduled snapshots
+    ParseSnapshotScheduleIntervalMissing,
+    /// Failed parsing IRQ layout parameters
+    ParseIrqs(OptionParserError),
+    /// Failed parsing doorbell parameters
+    ParseDoorbell(OptionParserError),
+    /// Missing file descriptors for the doorbell channels
+    ParseDoorbellFdsMissing,
+    /// Failed parsing partition services parameters
+    ParsePartitionServices(OptionParserError),
+    /// Failed parsing priority parameters
+    ParsePriority(OptionParserError),
+    /// Missing class for the priority
+    ParsePriorityClassMissing,
+    /// Failed parsing GPU parameters
+    ParseGpu(OptionParserError),
+    /// Missing socket path for the GPU
+    ParseGpuSocketMissing,
+    /// Failed parsing maintenance parameters
+    ParseMaintenance(OptionParserError),
+    /// Failed parsing virtio features parameters
+    ParseVirtioFeatures(OptionParserError),
+    /// Missing device identifier for the virtio features
+    ParseVirtioFeaturesIdMissing,
 }
 
 #[derive(Debug, PartialEq, Error)]
@@ -123,6 +213,10 @@ pub enum ValidationError {
     ConsoleFileMissing,
     /// Max is less than boot
     CpusMaxLowerThanBoot,
+    /// CPU weight out of the [1, 10000] range
+    InvalidCpuWeight(u16),
+    /// CPU quota must be non-zero
+    InvalidCpuQuota,
     /// Both socket and path specified
     DiskSocketAndPath,
     /// Using vhost user requires shared memory
@@ -137,9 +231,6 @@ pub enum ValidationError {
     CpuTopologyCount,
     /// One part of the CPU topology was zero
     CpuTopologyZeroPart,
-    #[cfg(target_arch = "aarch64")]
-    /// Dies per package must be 1
-    CpuTopologyDiesPerPackage,
     /// Virtio needs a min of 2 queues
     VnetQueueLowerThan2,
     /// The input queue number for virtio_net must match the number of input fds
@@ -150,19 +241,43 @@ pub enum ValidationError {
     HugePageSizeWithoutHugePages,
     /// Huge page size is not power of 2
     InvalidHugePageSize(u64),
+    /// Hugepage pool management turned on without any memory backed by huge pages
+    HugepagePoolWithoutHugePages,
+    /// Hugepage pool limit specified but hugepage pool management not enabled
+    HugepagePoolLimitWithoutHugepagePool,
     /// CPU Hotplug is not permitted with TDX
     #[cfg(feature = "tdx")]
     TdxNoCpuHotplug,
+    /// CPU Hotplug is not permitted with SEV-SNP
+    #[cfg(feature = "sev_snp")]
+    SevSnpNoCpuHotplug,
+    /// Memory Hotplug is not permitted with SEV-SNP
+    #[cfg(feature = "sev_snp")]
+    SevSnpNoMemoryHotplug,
+    /// SEV-SNP and TDX can't be enabled together
+    #[cfg(all(feature = "sev_snp", feature = "tdx"))]
+    SevSnpWithTdx,
     /// Insuffient vCPUs for queues
     TooManyQueues,
     /// Need shared memory for vfio-user
     UserDevicesRequireSharedMemory,
     /// Memory zone is reused across NUMA nodes
     MemoryZoneReused(String, u32, u32),
+    /// Unknown pmem device placed on a NUMA node
+    InvalidNumaPmem(String),
+    /// pmem device is reused across NUMA nodes
+    PmemReused(String, u32, u32),
+    /// Memory hotplug placed on a NUMA node without ACPI memory hotplug
+    NumaMemoryHotplugDisabled,
+    /// Memory hotplug placed on multiple NUMA nodes
+    NumaMemoryHotplugReused(u32, u32),
     /// Invalid number of PCI segments
     InvalidNumPciSegments(u16),
     /// Invalid PCI segment id
     InvalidPciSegment(u16),
+    /// Too many virtio-mmio slots reserved for hotplug
+    #[cfg(target_arch = "aarch64")]
+    InvalidMmioHotplugSlots(u8),
     /// Balloon too big
     BalloonLargerThanRam(u64, u64),
     /// On a IOMMU segment but not behind IOMMU
@@ -175,6 +290,114 @@ pub enum ValidationError {
     InvalidIdentifier(String),
     /// Placing the device behind a virtual IOMMU is not supported
     IommuNotSupported,
+    /// Invalid pstore region size
+    InvalidPstoreSize(u64),
+    /// Invalid crash kernel region size
+    InvalidCrashKernelSize(u64),
+    /// Payload blob address not page aligned
+    InvalidPayloadBlobAddress(u64),
+    /// Too many payload blobs
+    TooManyPayloadBlobs(usize),
+    /// The I/O engine can't be selected for a vhost-user disk
+    DiskIoEngineVhostUser,
+    /// Invalid VM UUID
+    InvalidUuid(String),
+    /// The PCI function and the device sharing its slot must be set together
+    MultifunctionIncomplete,
+    /// Invalid PCI function number
+    InvalidPciFunction(u8),
+    /// Invalid device to share the PCI slot with
+    InvalidMultifunctionDevice(String),
+    /// PCI function already used in the slot
+    PciFunctionInUse(String, u8),
+    /// Invalid debug console I/O port or MMIO address
+    InvalidDebugConsoleIobase(u64),
+    /// Debug console output to a file without a path
+    DebugConsoleFileMissing,
+    /// Invalid ACPI table signature for an OEM table ID override
+    InvalidAcpiTableSignature(String),
+    /// Invalid ACPI OEM table ID
+    InvalidAcpiOemTableId(String),
+    /// Device not placed behind the virtual IOMMU while it is required
+    IommuRequired(String),
+    /// virtio-mmio transport not supported on this architecture
+    VirtioMmioUnsupported,
+    /// Device on the virtio-mmio transport placed behind the virtual IOMMU
+    VirtioMmioIommu,
+    /// Network device on the virtio-mmio transport without a vhost-user backend
+    VirtioMmioNetVhostUserOnly,
+    /// Transport of the virtio-mem devices given without virtio-mem hotplug
+    VirtioMemTransportWithoutVirtioMem,
+    /// Network device with both vhost-net and a vhost-user backend
+    VhostNetVhostUser,
+    /// Network device with vhost-net and a rate limiter or interrupt coalescing
+    VhostNetRateLimiterOrCoalescing,
+    /// DAX cache of a virtio-fs device not a non-zero multiple of 2MiB
+    InvalidFsCacheSize(u64),
+    #[cfg(target_arch = "x86_64")]
+    /// Deterministic execution without a fixed TSC frequency
+    DeterministicTscKhzMissing,
+    /// Deterministic execution with a multi-queue device
+    DeterministicMultiQueue(String),
+    /// Watchdog parameters given without enabling the watchdog
+    WatchdogConfigWithoutWatchdog,
+    /// SBSA generic watchdog not supported on this architecture
+    SbsaWatchdogUnsupported,
+    /// Watchdog timeout too short for the watchdog model
+    InvalidWatchdogTimeout(u32),
+    /// Watchdog hook action without a hook
+    WatchdogHookMissing,
+    /// Scheduled snapshots with a zero interval
+    InvalidSnapshotScheduleInterval,
+    /// Scheduled snapshots with a zero retention count
+    InvalidSnapshotScheduleRetention,
+    /// Template memory zone without a backing file
+    MemoryZoneTemplateFileMissing(String),
+    /// Template memory zone with incompatible options
+    MemoryZoneTemplateIncompatible(String),
+    /// Sealed memory zone backed by a file
+    MemoryZoneSealedWithFile(String),
+    /// Sealed memory zone not shared
+    MemoryZoneSealedNotShared(String),
+    /// Sealed memory not shared
+    MemorySealedNotShared,
+    /// Sealed memory along with memory zones, which must be sealed instead
+    MemorySealedWithZones,
+    /// IRQ outside of the ones available to the devices
+    InvalidIrq(u32),
+    /// IRQ pinned or reserved more than once
+    DuplicateIrq(u32),
+    /// Device given more than one pinned IRQ
+    DuplicateIrqPin(String),
+    /// IRQ pinned for an unknown device
+    UnknownIrqPinDevice(String),
+    /// IRQ pinned for a device not given a legacy IRQ by the VMM
+    IrqPinUnsupportedDevice(String),
+    /// IRQ pinned for the x86_64 serial port, which always uses IRQ 4
+    #[cfg(target_arch = "x86_64")]
+    IrqPinSerial,
+    /// Doorbell devices not supported on this architecture
+    DoorbellUnsupported,
+    /// Doorbell file descriptors not making up 1 to 32 channels
+    InvalidDoorbellChannels(usize),
+    /// Doorbell file descriptors overlapping with the standard ones
+    DoorbellReservedFd,
+    /// GPU resolution empty or larger than supported
+    InvalidGpuResolution(u32, u32),
+    /// Maintenance with a zero or too long lease
+    InvalidMaintenanceLease,
+    /// Maintenance checkpoint header not given an absolute file path
+    InvalidMaintenanceCheckpoint(PathBuf),
+    /// Interrupt coalescing without a delay
+    InvalidInterruptCoalescingUsecs,
+    /// Virtio features both disabled and enabled
+    VirtioFeaturesConflict(String, u64),
+    /// VIRTIO_F_VERSION_1 disabled, which the transports require
+    VirtioFeaturesVersion1(String),
+    /// Virtio features overridden more than once for a device
+    DuplicateVirtioFeatures(String),
+    /// Virtio features overridden for an unknown device
+    UnknownVirtioFeaturesDevice(String),
 }
 
 type ValidationResult<T> = std::result::Result<T, ValidationError>;
@@ -187,6 +410,10 @@ impl fmt::Display for ValidationError {
             KernelMissing => write!(f, "No kernel specified"),
             ConsoleFileMissing => write!(f, "Path missing when using file console mode"),
             CpusMaxLowerThanBoot => write!(f, "Max CPUs lower than boot CPUs"),
+            InvalidCpuWeight(weight) => {
+                write!(f, "CPU weight ({}) must be between 1 and 10000", weight)
+            }
+            InvalidCpuQuota => write!(f, "CPU quota must be non-zero"),
             DiskSocketAndPath => write!(f, "Disk path and vhost socket both provided"),
             VhostUserRequiresSharedMemory => {
                 write!(f, "Using vhost-user requires using shared memory")
@@ -199,8 +426,6 @@ impl fmt::Display for ValidationError {
                 f,
                 "Product of CPU topology parts does not match maximum vCPUs"
             ),
-            #[cfg(target_arch = "aarch64")]
-            CpuTopologyDiesPerPackage => write!(f, "Dies per package must be 1"),
             VnetQueueLowerThan2 => write!(f, "Number of queues to virtio_net less than 2"),
             VnetQueueFdMismatch => write!(
                 f,
@@ -213,10 +438,34 @@ impl fmt::Display for ValidationError {
             InvalidHugePageSize(s) => {
                 write!(f, "Huge page size is not power of 2: {}", s)
             }
+            HugepagePoolWithoutHugePages => {
+                write!(
+                    f,
+                    "Hugepage pool enabled but no memory backed by huge pages"
+                )
+            }
+            HugepagePoolLimitWithoutHugepagePool => {
+                write!(
+                    f,
+                    "Hugepage pool limit specified but hugepage pool not enabled"
+                )
+            }
             #[cfg(feature = "tdx")]
             TdxNoCpuHotplug => {
                 write!(f, "CPU hotplug is not permitted with TDX")
             }
+            #[cfg(feature = "sev_snp")]
+            SevSnpNoCpuHotplug => {
+                write!(f, "CPU hotplug is not permitted with SEV-SNP")
+            }
+            #[cfg(feature = "sev_snp")]
+            SevSnpNoMemoryHotplug => {
+                write!(f, "Memory hotplug is not permitted with SEV-SNP")
+            }
+            #[cfg(all(feature = "sev_snp", feature = "tdx"))]
+            SevSnpWithTdx => {
+                write!(f, "SEV-SNP and TDX can't be enabled together")
+            }
             TooManyQueues => {
                 write!(f, "Number of vCPUs is insufficient for number of queues")
             }
@@ -230,6 +479,29 @@ impl fmt::Display for ValidationError {
                     s, u1, u2
                 )
             }
+            InvalidNumaPmem(s) => {
+                write!(f, "Unknown pmem device {} placed on a NUMA node", s)
+            }
+            PmemReused(s, u1, u2) => {
+                write!(
+                    f,
+                    "pmem device: {} belongs to multiple NUMA nodes {} and {}",
+                    s, u1, u2
+                )
+            }
+            NumaMemoryHotplugDisabled => {
+                write!(
+                    f,
+                    "Memory hotplug placed on a NUMA node without ACPI memory hotplug"
+                )
+            }
+            NumaMemoryHotplugReused(u1, u2) => {
+                write!(
+                    f,
+                    "Memory hotplug belongs to multiple NUMA nodes {} and {}",
+                    u1, u2
+                )
+            }
             InvalidNumPciSegments(n) => {
                 write!(
                     f,
@@ -240,6 +512,14 @@ impl fmt::Display for ValidationError {
             InvalidPciSegment(pci_segment) => {
                 write!(f, "Invalid PCI segment id: {}", pci_segment)
             }
+            #[cfg(target_arch = "aarch64")]
+            InvalidMmioHotplugSlots(n) => {
+                write!(
+                    f,
+                    "Number of virtio-mmio hotplug slots ({}) greater than {}",
+                    n, MAX_MMIO_HOTPLUG_SLOTS
+                )
+            }
             BalloonLargerThanRam(balloon_size, ram_size) => {
                 write!(
                     f,
@@ -270,6 +550,273 @@ impl fmt::Display for ValidationError {
             IommuNotSupported => {
                 write!(f, "Device does not support being placed behind IOMMU")
             }
+            InvalidPstoreSize(size) => {
+                write!(
+                    f,
+                    "Pstore size ({}) must be a multiple of 64KiB and at least 128KiB",
+                    size
+                )
+            }
+            InvalidPayloadBlobAddress(address) => {
+                write!(
+                    f,
+                    "Payload blob address (0x{:x}) must be aligned on 4KiB",
+                    address
+                )
+            }
+            TooManyPayloadBlobs(count) => {
+                write!(
+                    f,
+                    "Too many payload blobs ({}), at most {} are supported",
+                    count, MAX_PAYLOAD_BLOBS
+                )
+            }
+            InvalidCrashKernelSize(size) => {
+                write!(
+                    f,
+                    "Crash kernel size ({}) must be a non-zero multiple of 1MiB, smaller than the boot RAM",
+                    size
+                )
+            }
+            DiskIoEngineVhostUser => {
+                write!(f, "The I/O engine can't be selected for a vhost-user disk")
+            }
+            InvalidUuid(uuid) => write!(f, "Invalid VM UUID: {}", uuid),
+            MultifunctionIncomplete => write!(
+                f,
+                "The PCI function and the device sharing its slot must be provided together"
+            ),
+            InvalidPciFunction(function) => write!(
+                f,
+                "Invalid PCI function {} (must be between 1 and 7)",
+                function
+            ),
+            InvalidMultifunctionDevice(id) => write!(
+                f,
+                "Device {} can't share its PCI slot, it must be a passthrough device \
+                on the same PCI segment using function 0",
+                id
+            ),
+            PciFunctionInUse(id, function) => {
+                write!(
+                    f,
+                    "PCI function {} of device {} is already used",
+                    function, id
+                )
+            }
+            InvalidDebugConsoleIobase(iobase) => {
+                write!(f, "Invalid debug console I/O base: 0x{:x}", iobase)
+            }
+            DebugConsoleFileMissing => {
+                write!(f, "Debug console output to a file requires a path")
+            }
+            InvalidAcpiTableSignature(s) => {
+                write!(
+                    f,
+                    "Invalid ACPI table signature {:?}: must be 4 ASCII characters",
+                    s
+                )
+            }
+            VirtioMmioUnsupported => {
+                write!(f, "The virtio-mmio transport is only supported on AArch64")
+            }
+            VirtioMmioIommu => {
+                write!(
+                    f,
+                    "Devices on the virtio-mmio transport can't be placed behind the virtual IOMMU"
+                )
+            }
+            VirtioMmioNetVhostUserOnly => {
+                write!(
+                    f,
+                    "Network devices on the virtio-mmio transport require a vhost-user backend"
+                )
+            }
+            VirtioMemTransportWithoutVirtioMem => {
+                write!(
+                    f,
+                    "The transport of the virtio-mem devices requires the virtio-mem hotplug method"
+                )
+            }
+            VhostNetVhostUser => {
+                write!(f, "vhost-net can't be used with a vhost-user backend")
+            }
+            VhostNetRateLimiterOrCoalescing => {
+                write!(
+                    f,
+                    "vhost-net can't be used with a rate limiter or interrupt coalescing"
+                )
+            }
+            InvalidFsCacheSize(s) => {
+                write!(
+                    f,
+                    "Invalid virtio-fs DAX cache size, must be a multiple of 2MiB: {}",
+                    s
+                )
+            }
+            #[cfg(target_arch = "x86_64")]
+            DeterministicTscKhzMissing => {
+                write!(
+                    f,
+                    "Deterministic execution requires the TSC frequency to be set with --cpus tsc_khz"
+                )
+            }
+            DeterministicMultiQueue(s) => {
+                write!(
+                    f,
+                    "Deterministic execution doesn't support multiple queues, used by {}",
+                    s
+                )
+            }
+            IommuRequired(s) => {
+                write!(
+                    f,
+                    "Device {} is not placed behind the virtual IOMMU, which the security configuration requires",
+                    s
+                )
+            }
+            InvalidAcpiOemTableId(s) => {
+                write!(
+                    f,
+                    "Invalid ACPI OEM table ID {:?}: must be at most 8 ASCII characters",
+                    s
+                )
+            }
+            WatchdogConfigWithoutWatchdog => {
+                write!(f, "Watchdog parameters given without enabling the watchdog")
+            }
+            SbsaWatchdogUnsupported => {
+                write!(f, "The SBSA generic watchdog is only supported on AArch64")
+            }
+            InvalidWatchdogTimeout(timeout) => {
+                write!(
+                    f,
+                    "Invalid watchdog timeout {}: the virtio watchdog requires more than {} \
+                    seconds, the SBSA one at least 1 second",
+                    timeout,
+                    virtio_devices::WATCHDOG_TIMER_INTERVAL
+                )
+            }
+            WatchdogHookMissing => write!(f, "Watchdog hook action without a hook"),
+            InvalidSnapshotScheduleInterval => {
+                write!(f, "Interval between scheduled snapshots must be non-zero")
+            }
+            InvalidSnapshotScheduleRetention => {
+                write!(f, "Number of scheduled snapshots kept must be non-zero")
+            }
+            MemoryZoneTemplateFileMissing(s) => {
+                write!(f, "Template memory zone ({}) requires a backing file", s)
+            }
+            MemoryZoneTemplateIncompatible(s) => {
+                write!(
+                    f,
+                    "Template memory zone ({}) can't be shared, backed by huge \
+                    pages or hotplugged to",
+                    s
+                )
+            }
+            MemoryZoneSealedWithFile(s) => {
+                write!(f, "Sealed memory zone ({}) can't be backed by a file", s)
+            }
+            MemoryZoneSealedNotShared(s) => {
+                write!(f, "Sealed memory zone ({}) must be shared", s)
+            }
+            MemorySealedNotShared => write!(f, "Sealed memory must be shared"),
+            MemorySealedWithZones => {
+                write!(
+                    f,
+                    "Memory can't be sealed when described by memory zones, which \
+                    must be sealed instead"
+                )
+            }
+            InvalidIrq(irq) => {
+                write!(
+                    f,
+                    "IRQ {} is outside of the range {}-{} available to the devices",
+                    irq,
+                    DEVICE_IRQS.start,
+                    DEVICE_IRQS.end - 1
+                )
+            }
+            DuplicateIrq(irq) => {
+                write!(f, "IRQ {} is pinned or reserved more than once", irq)
+            }
+            DuplicateIrqPin(s) => {
+                write!(f, "Device {} is given more than one pinned IRQ", s)
+            }
+            UnknownIrqPinDevice(s) => write!(f, "IRQ pinned for unknown device {}", s),
+            IrqPinUnsupportedDevice(s) => {
+                write!(
+                    f,
+                    "IRQ can't be pinned for device {}, only the virtio-mmio, doorbell \
+                    and platform devices are given a legacy IRQ",
+                    s
+                )
+            }
+            #[cfg(target_arch = "x86_64")]
+            IrqPinSerial => write!(
+                f,
+                "IRQ can't be pinned for the serial port, which uses IRQ 4"
+            ),
+            DoorbellUnsupported => {
+                write!(f, "Doorbell devices are only supported on AArch64")
+            }
+            InvalidDoorbellChannels(fds) => {
+                write!(
+                    f,
+                    "Doorbell devices require a pair of file descriptors for each of \
+                    their 1 to {} channels, {} file descriptors given",
+                    devices::legacy::DOORBELL_MAX_CHANNELS,
+                    fds
+                )
+            }
+            DoorbellReservedFd => {
+                write!(
+                    f,
+                    "Doorbell file descriptors can't be the standard input, output or error"
+                )
+            }
+            InvalidGpuResolution(width, height) => {
+                write!(
+                    f,
+                    "GPU resolution {}x{} must be non-zero and at most {}x{}",
+                    width, height, MAX_GPU_RESOLUTION, MAX_GPU_RESOLUTION
+                )
+            }
+            InvalidMaintenanceLease => {
+                write!(
+                    f,
+                    "Maintenance lease must be between 1 and {} seconds",
+                    MAX_MAINTENANCE_LEASE
+                )
+            }
+            InvalidMaintenanceCheckpoint(path) => {
+                write!(
+                    f,
+                    "Maintenance checkpoint header {} must be an absolute file path \
+                    without parent directory components",
+                    path.display()
+                )
+            }
+            InvalidInterruptCoalescingUsecs => {
+                write!(f, "Interrupt coalescing delay must be non-zero")
+            }
+            VirtioFeaturesConflict(id, features) => {
+                write!(
+                    f,
+                    "Virtio features 0x{:x} of {} can't be both disabled and enabled",
+                    features, id
+                )
+            }
+            VirtioFeaturesVersion1(id) => {
+                write!(f, "VIRTIO_F_VERSION_1 of {} can't be disabled", id)
+            }
+            DuplicateVirtioFeatures(id) => {
+                write!(f, "Virtio features of {} overridden more than once", id)
+            }
+            UnknownVirtioFeaturesDevice(id) => {
+                write!(f, "Virtio features overridden for unknown device {}", id)
+            }
         }
     }
 }
@@ -298,6 +845,19 @@ impl fmt::Display for Error {
             ParseMemoryZone(o) => write!(f, "Error parsing --memory-zone: {}", o),
             ParseMemoryZoneIdMissing => write!(f, "Error parsing --memory-zone: id missing"),
             ParseNetwork(o) => write!(f, "Error parsing --net: {}", o),
+            ParseRateLimiter(o) => write!(f, "Error parsing rate limiter: {}", o),
+            ParseRateLimiterConflict(o) => write!(
+                f,
+                "Error parsing rate limiter: {0} can't be combined with {0}_size and {0}_refill_time",
+                o
+            ),
+            ParseInterruptCoalescing(o) => {
+                write!(f, "Error parsing interrupt coalescing: {}", o)
+            }
+            ParseInterruptCoalescingUsecsMissing => write!(
+                f,
+                "Error parsing interrupt coalescing: coalesce_usecs is required and can't be 0"
+            ),
             ParseDisk(o) => write!(f, "Error parsing --disk: {}", o),
             ParseRng(o) => write!(f, "Error parsing --rng: {}", o),
             ParseBalloon(o) => write!(f, "Error parsing --balloon: {}", o),
@@ -319,9 +879,61 @@ impl fmt::Display for Error {
             ParseTdx(o) => write!(f, "Error parsing --tdx: {}", o),
             #[cfg(feature = "tdx")]
             FirmwarePathMissing => write!(f, "TDX firmware missing"),
+            #[cfg(feature = "sev_snp")]
+            ParseSevSnp(o) => write!(f, "Error parsing --sev-snp: {}", o),
+            #[cfg(feature = "sev_snp")]
+            SevSnpFirmwarePathMissing => write!(f, "SEV-SNP firmware missing"),
             ParsePlatform(o) => write!(f, "Error parsing --platform: {}", o),
             ParseVdpa(o) => write!(f, "Error parsing --vdpa: {}", o),
             ParseVdpaPathMissing => write!(f, "Error parsing --vdpa: path missing"),
+            ParsePstore(o) => write!(f, "Error parsing --pstore: {}", o),
+            ParsePstoreFileMissing => write!(f, "Error parsing --pstore: file missing"),
+            ParseCrashKernel(o) => write!(f, "Error parsing --crashkernel: {}", o),
+            ParseCrashKernelSizeMissing => write!(f, "Error parsing --crashkernel: size missing"),
+            ParsePayloadBlob(o) => write!(f, "Error parsing --payload-blob: {}", o),
+            ParsePayloadBlobPathMissing => write!(f, "Error parsing --payload-blob: path missing"),
+            ParsePtp(o) => write!(f, "Error parsing --ptp: {}", o),
+            ParsePtpPathMissing => write!(f, "Error parsing --ptp: path missing"),
+            ParseDebugConsole(o) => write!(f, "Error parsing --debug-console: {}", o),
+            ParseDebugConsoleOutputMissing => {
+                write!(
+                    f,
+                    "Error parsing --debug-console: file or log output missing"
+                )
+            }
+            ParseAcpi(o) => write!(f, "Error parsing --acpi: {}", o),
+            ParseFdt(o) => write!(f, "Error parsing --fdt: {}", o),
+            ParseFdtBaseMissing => write!(f, "Error parsing --fdt: base missing"),
+            ParseSecurity(o) => write!(f, "Error parsing --security: {}", o),
+            ParseDeterministic(o) => write!(f, "Error parsing --deterministic: {}", o),
+            ParseDeterministicSeedMissing => {
+                write!(f, "Error parsing --deterministic: seed missing")
+            }
+            ParseWatchdog(o) => write!(f, "Error parsing --watchdog: {}", o),
+            ParseSnapshotSchedule(o) => write!(f, "Error parsing --snapshot-schedule: {}", o),
+            ParseSnapshotScheduleDestinationMissing => {
+                write!(f, "Error parsing --snapshot-schedule: destination missing")
+            }
+            ParseSnapshotScheduleIntervalMissing => {
+                write!(f, "Error parsing --snapshot-schedule: interval missing")
+            }
+            ParseIrqs(o) => write!(f, "Error parsing --irqs: {}", o),
+            ParseDoorbell(o) => write!(f, "Error parsing --doorbell: {}", o),
+            ParseDoorbellFdsMissing => {
+                write!(f, "Error parsing --doorbell: fds missing")
+            }
+            ParsePartitionServices(o) => write!(f, "Error parsing --partition-services: {}", o),
+            ParsePriority(o) => write!(f, "Error parsing --priority: {}", o),
+            ParsePriorityClassMissing => {
+                write!(f, "Error parsing --priority: class missing")
+            }
+            ParseGpu(o) => write!(f, "Error parsing --gpu: {}", o),
+            ParseGpuSocketMissing => write!(f, "Error parsing --gpu: socket missing"),
+            ParseMaintenance(o) => write!(f, "Error parsing --maintenance: {}", o),
+            ParseVirtioFeatures(o) => write!(f, "Error parsing --virtio-features: {}", o),
+            ParseVirtioFeaturesIdMissing => {
+                write!(f, "Error parsing --virtio-features: id missing")
+            }
         }
     }
 }
@@ -341,7 +953,8 @@ pub struct VmParams<'a> {
     pub memory: &'a str,
     pub memory_zones: Option<Vec<&'a str>>,
     pub kernel: Option<&'a str>,
-    pub initramfs: Option<&'a str>,
+    pub initramfs: Option<Vec<&'a str>>,
+    pub payload_blobs: Option<Vec<&'a str>>,
     pub cmdline: Option<&'a str>,
     pub disks: Option<Vec<&'a str>>,
     pub net: Option<Vec<&'a str>>,
@@ -357,13 +970,37 @@ pub struct VmParams<'a> {
     pub vsock: Option<&'a str>,
     #[cfg(target_arch = "x86_64")]
     pub sgx_epc: Option<Vec<&'a str>>,
+    #[cfg(target_arch = "aarch64")]
+    pub fdt_overlays: Option<Vec<&'a str>>,
+    #[cfg(target_arch = "aarch64")]
+    pub fdt: Option<&'a str>,
     pub numa: Option<Vec<&'a str>>,
     pub watchdog: bool,
+    pub watchdog_config: Option<&'a str>,
+    pub pvpanic: bool,
+    pub boot_paused: bool,
     #[cfg(feature = "tdx")]
     pub tdx: Option<&'a str>,
+    #[cfg(feature = "sev_snp")]
+    pub sev_snp: Option<&'a str>,
     #[cfg(feature = "gdb")]
     pub gdb: bool,
     pub platform: Option<&'a str>,
+    pub pstore: Option<&'a str>,
+    pub crashkernel: Option<&'a str>,
+    pub ptp: Option<&'a str>,
+    pub debug_console: Option<&'a str>,
+    pub acpi: Option<&'a str>,
+    pub security: Option<&'a str>,
+    pub deterministic: Option<&'a str>,
+    pub snapshot_schedule: Option<&'a str>,
+    pub irqs: Option<&'a str>,
+    pub doorbells: Option<Vec<&'a str>>,
+    pub partition_services: Option<&'a str>,
+    pub priority: Option<&'a str>,
+    pub gpu: Option<&'a str>,
+    pub maintenance: Option<&'a str>,
+    pub virtio_features: Option<Vec<&'a str>>,
 }
 
 impl<'a> VmParams<'a> {
@@ -376,7 +1013,8 @@ impl<'a> VmParams<'a> {
         let serial = args.value_of("serial").unwrap();
 
         let kernel = args.value_of("kernel");
-        let initramfs = args.value_of("initramfs");
+        let initramfs: Option<Vec<&str>> = args.values_of("initramfs").map(|x| x.collect());
+        let payload_blobs: Option<Vec<&str>> = args.values_of("payload-blob").map(|x| x.collect());
         let cmdline = args.value_of("cmdline");
 
         let disks: Option<Vec<&str>> = args.values_of("disk").map(|x| x.collect());
@@ -391,11 +1029,36 @@ impl<'a> VmParams<'a> {
         let vsock: Option<&str> = args.value_of("vsock");
         #[cfg(target_arch = "x86_64")]
         let sgx_epc: Option<Vec<&str>> = args.values_of("sgx-epc").map(|x| x.collect());
+        #[cfg(target_arch = "aarch64")]
+        let fdt_overlays: Option<Vec<&str>> = args.values_of("fdt-overlay").map(|x| x.collect());
+        #[cfg(target_arch = "aarch64")]
+        let fdt = args.value_of("fdt");
         let numa: Option<Vec<&str>> = args.values_of("numa").map(|x| x.collect());
         let watchdog = args.is_present("watchdog");
+        let watchdog_config = args.value_of("watchdog");
+        let pvpanic = args.is_present("pvpanic");
+        let boot_paused = args.is_present("boot-paused");
         let platform = args.value_of("platform");
+        let pstore = args.value_of("pstore");
+        let crashkernel = args.value_of("crashkernel");
+        let ptp = args.value_of("ptp");
+        let debug_console = args.value_of("debug-console");
+        let acpi = args.value_of("acpi");
+        let security = args.value_of("security");
+        let deterministic = args.value_of("deterministic");
+        let snapshot_schedule = args.value_of("snapshot-schedule");
+        let irqs = args.value_of("irqs");
+        let doorbells: Option<Vec<&str>> = args.values_of("doorbell").map(|x| x.collect());
+        let partition_services = args.value_of("partition-services");
+        let priority = args.value_of("priority");
+        let gpu = args.value_of("gpu");
+        let maintenance = args.value_of("maintenance");
+        let virtio_features: Option<Vec<&str>> =
+            args.values_of("virtio-features").map(|x| x.collect());
         #[cfg(feature = "tdx")]
         let tdx = args.value_of("tdx");
+        #[cfg(feature = "sev_snp")]
+        let sev_snp = args.value_of("sev-snp");
         #[cfg(feature = "gdb")]
         let gdb = args.is_present("gdb");
         VmParams {
@@ -404,6 +1067,7 @@ impl<'a> VmParams<'a> {
             memory_zones,
             kernel,
             initramfs,
+            payload_blobs,
             cmdline,
             disks,
             net,
@@ -419,18 +1083,42 @@ impl<'a> VmParams<'a> {
             vsock,
             #[cfg(target_arch = "x86_64")]
             sgx_epc,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays,
+            #[cfg(target_arch = "aarch64")]
+            fdt,
             numa,
             watchdog,
+            watchdog_config,
+            pvpanic,
+            boot_paused,
             #[cfg(feature = "tdx")]
             tdx,
+            #[cfg(feature = "sev_snp")]
+            sev_snp,
             #[cfg(feature = "gdb")]
             gdb,
             platform,
+            pstore,
+            crashkernel,
+            ptp,
+            debug_console,
+            acpi,
+            security,
+            deterministic,
+            snapshot_schedule,
+            irqs,
+            doorbells,
+            partition_services,
+            priority,
+            gpu,
+            maintenance,
+            virtio_features,
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub enum HotplugMethod {
     Acpi,
     VirtioMem,
@@ -459,13 +1147,13 @@ impl FromStr for HotplugMethod {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct CpuAffinity {
     pub vcpu: u8,
     pub host_cpus: Vec<u8>,
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct CpuFeatures {
     #[cfg(all(feature = "amx", target_arch = "x86_64"))]
     pub amx: bool,
@@ -475,7 +1163,7 @@ pub enum CpuTopologyParseError {
     InvalidValue(String),
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct CpuTopology {
     pub threads_per_core: u8,
     pub cores_per_die: u8,
@@ -516,7 +1204,12 @@ fn default_cpuconfig_max_phys_bits() -> u8 {
     DEFAULT_MAX_PHYS_BITS
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[cfg(target_arch = "aarch64")]
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct CpusConfig {
     pub boot_vcpus: u8,
     pub max_vcpus: u8,
@@ -524,12 +1217,29 @@ pub struct CpusConfig {
     pub topology: Option<CpuTopology>,
     #[serde(default)]
     pub kvm_hyperv: bool,
+    #[serde(default)]
+    pub nested: bool,
     #[serde(default = "default_cpuconfig_max_phys_bits")]
     pub max_phys_bits: u8,
     #[serde(default)]
     pub affinity: Option<Vec<CpuAffinity>>,
     #[serde(default)]
     pub features: CpuFeatures,
+    #[serde(default)]
+    pub weight: Option<u16>,
+    #[serde(default)]
+    pub quota: Option<u32>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub tsc_khz: Option<u32>,
+    #[serde(default)]
+    pub baseline: Option<CpuModel>,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default = "default_true")]
+    pub pmu: bool,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default = "default_true")]
+    pub freeze_counter: bool,
 }
 
 impl CpusConfig {
@@ -540,9 +1250,17 @@ impl CpusConfig {
             .add("max")
             .add("topology")
             .add("kvm_hyperv")
+            .add("nested")
             .add("max_phys_bits")
             .add("affinity")
-            .add("features");
+            .add("features")
+            .add("weight")
+            .add("quota")
+            .add("baseline");
+        #[cfg(target_arch = "x86_64")]
+        parser.add("tsc_khz");
+        #[cfg(target_arch = "aarch64")]
+        parser.add("pmu").add("freeze_counter");
         parser.parse(cpus).map_err(Error::ParseCpus)?;
 
         let boot_vcpus: u8 = parser
@@ -559,6 +1277,11 @@ impl CpusConfig {
             .map_err(Error::ParseCpus)?
             .unwrap_or(Toggle(false))
             .0;
+        let nested = parser
+            .convert::<Toggle>("nested")
+            .map_err(Error::ParseCpus)?
+            .unwrap_or(Toggle(false))
+            .0;
         let max_phys_bits = parser
             .convert::<u8>("max_phys_bits")
             .map_err(Error::ParseCpus)?
@@ -595,15 +1318,42 @@ impl CpusConfig {
                 _ => Err(Error::InvalidCpuFeatures(s)),
             }?;
         }
+        let weight = parser.convert("weight").map_err(Error::ParseCpus)?;
+        let quota = parser.convert("quota").map_err(Error::ParseCpus)?;
+        #[cfg(target_arch = "x86_64")]
+        let tsc_khz = parser.convert("tsc_khz").map_err(Error::ParseCpus)?;
+        let baseline = parser.convert("baseline").map_err(Error::ParseCpus)?;
+        #[cfg(target_arch = "aarch64")]
+        let pmu = parser
+            .convert::<Toggle>("pmu")
+            .map_err(Error::ParseCpus)?
+            .unwrap_or(Toggle(true))
+            .0;
+        #[cfg(target_arch = "aarch64")]
+        let freeze_counter = parser
+            .convert::<Toggle>("freeze_counter")
+            .map_err(Error::ParseCpus)?
+            .unwrap_or(Toggle(true))
+            .0;
 
         Ok(CpusConfig {
             boot_vcpus,
             max_vcpus,
             topology,
             kvm_hyperv,
+            nested,
             max_phys_bits,
             affinity,
             features,
+            weight,
+            quota,
+            #[cfg(target_arch = "x86_64")]
+            tsc_khz,
+            baseline,
+            #[cfg(target_arch = "aarch64")]
+            pmu,
+            #[cfg(target_arch = "aarch64")]
+            freeze_counter,
         })
     }
 }
@@ -615,9 +1365,19 @@ impl Default for CpusConfig {
             max_vcpus: DEFAULT_VCPUS,
             topology: None,
             kvm_hyperv: false,
+            nested: false,
             max_phys_bits: DEFAULT_MAX_PHYS_BITS,
             affinity: None,
             features: CpuFeatures::default(),
+            weight: None,
+            quota: None,
+            #[cfg(target_arch = "x86_64")]
+            tsc_khz: None,
+            baseline: None,
+            #[cfg(target_arch = "aarch64")]
+            pmu: true,
+            #[cfg(target_arch = "aarch64")]
+            freeze_counter: true,
         }
     }
 }
@@ -626,7 +1386,11 @@ fn default_platformconfig_num_pci_segments() -> u16 {
     DEFAULT_NUM_PCI_SEGMENTS
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+fn default_platformconfig_ioeventfd() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct PlatformConfig {
     #[serde(default = "default_platformconfig_num_pci_segments")]
     pub num_pci_segments: u16,
@@ -634,6 +1398,17 @@ pub struct PlatformConfig {
     pub iommu_segments: Option<Vec<u16>>,
     #[serde(default)]
     pub serial_number: Option<String>,
+    #[serde(default = "default_platformconfig_ioeventfd")]
+    pub ioeventfd: bool,
+    #[serde(default)]
+    pub uuid: Option<String>,
+    #[serde(default)]
+    pub hypervisor_info: bool,
+    /// Number of virtio-mmio slots reserved for the devices hot-added to a
+    /// guest booted without ACPI.
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub mmio_hotplug_slots: u8,
 }
 
 impl PlatformConfig {
@@ -642,6 +1417,11 @@ impl PlatformConfig {
         parser.add("num_pci_segments");
         parser.add("iommu_segments");
         parser.add("serial_number");
+        parser.add("ioeventfd");
+        parser.add("uuid");
+        parser.add("hypervisor_info");
+        #[cfg(target_arch = "aarch64")]
+        parser.add("mmio_hotplug_slots");
         parser.parse(platform).map_err(Error::ParsePlatform)?;
 
         let num_pci_segments: u16 = parser
@@ -655,10 +1435,31 @@ impl PlatformConfig {
         let serial_number = parser
             .convert("serial_number")
             .map_err(Error::ParsePlatform)?;
+        let ioeventfd = parser
+            .convert::<Toggle>("ioeventfd")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(true))
+            .0;
+        let uuid = parser.get("uuid");
+        let hypervisor_info = parser
+            .convert::<Toggle>("hypervisor_info")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or(Toggle(false))
+            .0;
+        #[cfg(target_arch = "aarch64")]
+        let mmio_hotplug_slots = parser
+            .convert("mmio_hotplug_slots")
+            .map_err(Error::ParsePlatform)?
+            .unwrap_or_default();
         Ok(PlatformConfig {
             num_pci_segments,
             iommu_segments,
             serial_number,
+            ioeventfd,
+            uuid,
+            hypervisor_info,
+            #[cfg(target_arch = "aarch64")]
+            mmio_hotplug_slots,
         })
     }
 
@@ -677,6 +1478,19 @@ impl PlatformConfig {
             }
         }
 
+        if let Some(uuid) = &self.uuid {
+            if uuid::Uuid::parse_str(uuid).is_err() {
+                return Err(ValidationError::InvalidUuid(uuid.clone()));
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        if self.mmio_hotplug_slots > MAX_MMIO_HOTPLUG_SLOTS {
+            return Err(ValidationError::InvalidMmioHotplugSlots(
+                self.mmio_hotplug_slots,
+            ));
+        }
+
         Ok(())
     }
 }
@@ -687,11 +1501,16 @@ impl Default for PlatformConfig {
             num_pci_segments: DEFAULT_NUM_PCI_SEGMENTS,
             iommu_segments: None,
             serial_number: None,
+            ioeventfd: default_platformconfig_ioeventfd(),
+            uuid: None,
+            hypervisor_info: false,
+            #[cfg(target_arch = "aarch64")]
+            mmio_hotplug_slots: 0,
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct MemoryZoneConfig {
     pub id: String,
     pub size: u64,
@@ -711,9 +1530,13 @@ pub struct MemoryZoneConfig {
     pub hotplugged_size: Option<u64>,
     #[serde(default)]
     pub prefault: bool,
+    #[serde(default)]
+    pub template: bool,
+    #[serde(default)]
+    pub sealed: bool,
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct MemoryConfig {
     pub size: u64,
     #[serde(default)]
@@ -734,6 +1557,14 @@ pub struct MemoryConfig {
     pub prefault: bool,
     #[serde(default)]
     pub zones: Option<Vec<MemoryZoneConfig>>,
+    #[serde(default)]
+    pub hugepage_pool: bool,
+    #[serde(default)]
+    pub hugepage_pool_limit: Option<u64>,
+    #[serde(default)]
+    pub virtio_mem_transport: VirtioTransportType,
+    #[serde(default)]
+    pub sealed: bool,
 }
 
 impl MemoryConfig {
@@ -749,7 +1580,11 @@ impl MemoryConfig {
             .add("shared")
             .add("hugepages")
             .add("hugepage_size")
-            .add("prefault");
+            .add("prefault")
+            .add("hugepage_pool")
+            .add("hugepage_pool_limit")
+            .add("virtio_mem_transport")
+            .add("sealed");
         parser.parse(memory).map_err(Error::ParseMemory)?;
 
         let size = parser
@@ -793,6 +1628,24 @@ impl MemoryConfig {
             .map_err(Error::ParseMemory)?
             .unwrap_or(Toggle(false))
             .0;
+        let hugepage_pool = parser
+            .convert::<Toggle>("hugepage_pool")
+            .map_err(Error::ParseMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let hugepage_pool_limit = parser
+            .convert::<ByteSized>("hugepage_pool_limit")
+            .map_err(Error::ParseMemory)?
+            .map(|v| v.0);
+        let virtio_mem_transport = parser
+            .convert("virtio_mem_transport")
+            .map_err(Error::ParseMemory)?
+            .unwrap_or_default();
+        let sealed = parser
+            .convert::<Toggle>("sealed")
+            .map_err(Error::ParseMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
 
         let zones: Option<Vec<MemoryZoneConfig>> = if let Some(memory_zones) = &memory_zones {
             let mut zones = Vec::new();
@@ -808,7 +1661,9 @@ impl MemoryConfig {
                     .add("host_numa_node")
                     .add("hotplug_size")
                     .add("hotplugged_size")
-                    .add("prefault");
+                    .add("prefault")
+                    .add("template")
+                    .add("sealed");
                 parser.parse(memory_zone).map_err(Error::ParseMemoryZone)?;
 
                 let id = parser.get("id").ok_or(Error::ParseMemoryZoneIdMissing)?;
@@ -849,8 +1704,18 @@ impl MemoryConfig {
                     .map_err(Error::ParseMemoryZone)?
                     .unwrap_or(Toggle(false))
                     .0;
-
-                zones.push(MemoryZoneConfig {
+                let template = parser
+                    .convert::<Toggle>("template")
+                    .map_err(Error::ParseMemoryZone)?
+                    .unwrap_or(Toggle(false))
+                    .0;
+                let sealed = parser
+                    .convert::<Toggle>("sealed")
+                    .map_err(Error::ParseMemoryZone)?
+                    .unwrap_or(Toggle(false))
+                    .0;
+
+                zones.push(MemoryZoneConfig {
                     id,
                     size,
                     file,
@@ -861,6 +1726,8 @@ impl MemoryConfig {
                     hotplug_size,
                     hotplugged_size,
                     prefault,
+                    template,
+                    sealed,
                 });
             }
             Some(zones)
@@ -879,6 +1746,10 @@ impl MemoryConfig {
             hugepage_size,
             prefault,
             zones,
+            hugepage_pool,
+            hugepage_pool_limit,
+            virtio_mem_transport,
+            sealed,
         })
     }
 
@@ -914,21 +1785,132 @@ impl Default for MemoryConfig {
             hugepage_size: None,
             prefault: false,
             zones: None,
+            hugepage_pool: false,
+            hugepage_pool_limit: None,
+            virtio_mem_transport: VirtioTransportType::Pci,
+            sealed: false,
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct KernelConfig {
     pub path: PathBuf,
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct InitramfsConfig {
     pub path: PathBuf,
+    /// Initramfs files concatenated after the one at `path`, in order, as a
+    /// bootloader would.
+    #[serde(default)]
+    pub extra_paths: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PayloadBlobConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub address: Option<u64>,
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+impl PayloadBlobConfig {
+    pub const SYNTAX: &'static str = "Blob loaded into the guest memory at boot \
+    \"path=</path/to/a/file>,address=<guest_address>,id=<blob_id>\"";
+    pub fn parse(payload_blob: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("path").add("address").add("id");
+        parser
+            .parse(payload_blob)
+            .map_err(Error::ParsePayloadBlob)?;
+
+        let path = parser
+            .get("path")
+            .map(PathBuf::from)
+            .ok_or(Error::ParsePayloadBlobPathMissing)?;
+        let address = parser
+            .get("address")
+            .map(|address| {
+                let value = if let Some(hex) = address.strip_prefix("0x") {
+                    u64::from_str_radix(hex, 16)
+                } else {
+                    address.parse::<u64>()
+                };
+                value.map_err(|_| {
+                    Error::ParsePayloadBlob(OptionParserError::Conversion(
+                        "address".to_owned(),
+                        address,
+                    ))
+                })
+            })
+            .transpose()?;
+        let id = parser.get("id");
+
+        Ok(PayloadBlobConfig { path, address, id })
+    }
+
+    pub fn validate(&self) -> ValidationResult<()> {
+        if let Some(address) = self.address {
+            if address % (4 << 10) != 0 {
+                return Err(ValidationError::InvalidPayloadBlobAddress(address));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct FdtOverlayConfig {
+    pub path: PathBuf,
+}
+
+#[cfg(target_arch = "aarch64")]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct FdtConfig {
+    pub base: PathBuf,
+    #[serde(default)]
+    pub patch_memory: bool,
+    #[serde(default)]
+    pub patch_virtio: bool,
+    #[serde(default)]
+    pub edits: Vec<FdtEdit>,
+}
+
+#[cfg(target_arch = "aarch64")]
+impl FdtConfig {
+    pub const SYNTAX: &'static str = "Pre-built DTB given to the guest instead of the \
+    generated FDT \"base=<dtb_path>,patch_memory=on|off,patch_virtio=on|off\"";
+    pub fn parse(fdt: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("base").add("patch_memory").add("patch_virtio");
+        parser.parse(fdt).map_err(Error::ParseFdt)?;
+
+        let base = PathBuf::from(parser.get("base").ok_or(Error::ParseFdtBaseMissing)?);
+        let patch_memory = parser
+            .convert::<Toggle>("patch_memory")
+            .map_err(Error::ParseFdt)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let patch_virtio = parser
+            .convert::<Toggle>("patch_virtio")
+            .map_err(Error::ParseFdt)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(FdtConfig {
+            base,
+            patch_memory,
+            patch_virtio,
+            edits: Vec::new(),
+        })
+    }
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct CmdlineConfig {
     pub args: String,
 }
@@ -943,7 +1925,7 @@ impl CmdlineConfig {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct DiskConfig {
     pub path: Option<PathBuf>,
     #[serde(default)]
@@ -964,12 +1946,18 @@ pub struct DiskConfig {
     #[serde(default)]
     pub rate_limiter_config: Option<RateLimiterConfig>,
     #[serde(default)]
+    pub interrupt_coalescing: Option<InterruptCoalescingConfig>,
+    #[serde(default)]
     pub id: Option<String>,
     // For testing use only. Not exposed in API.
     #[serde(default)]
     pub disable_io_uring: bool,
     #[serde(default)]
     pub pci_segment: u16,
+    #[serde(default)]
+    pub io_engine: Option<DiskIoEngine>,
+    #[serde(default)]
+    pub optional: bool,
 }
 
 fn default_diskconfig_num_queues() -> usize {
@@ -999,19 +1987,140 @@ impl Default for DiskConfig {
             id: None,
             disable_io_uring: false,
             rate_limiter_config: None,
+            interrupt_coalescing: None,
             pci_segment: 0,
+            io_engine: None,
+            optional: false,
+        }
+    }
+}
+
+// Number of milliseconds the "bw" and "ops" rates are given for.
+const RATE_LIMITER_RATE_REFILL_TIME: u64 = 1000;
+
+fn parse_token_bucket_config(
+    parser: &OptionParser,
+    prefix: &'static str,
+    error: fn(OptionParserError) -> Error,
+) -> Result<Option<TokenBucketConfig>> {
+    let rate: Option<u64> = parser.convert(prefix).map_err(error)?;
+    let size_option = format!("{}_size", prefix);
+    let refill_time_option = format!("{}_refill_time", prefix);
+    if rate.is_some() && (parser.is_set(&size_option) || parser.is_set(&refill_time_option)) {
+        return Err(Error::ParseRateLimiterConflict(prefix));
+    }
+
+    let (size, refill_time) = match rate {
+        Some(rate) => (Some(rate), Some(RATE_LIMITER_RATE_REFILL_TIME)),
+        None => (
+            parser.convert(&size_option).map_err(error)?,
+            parser.convert(&refill_time_option).map_err(error)?,
+        ),
+    };
+    let one_time_burst = parser
+        .convert(&format!("{}_one_time_burst", prefix))
+        .map_err(error)?
+        .unwrap_or_default();
+
+    Ok(match (size, refill_time) {
+        (Some(size), Some(refill_time)) if size != 0 && refill_time != 0 => {
+            Some(TokenBucketConfig {
+                size,
+                one_time_burst: Some(one_time_burst),
+                refill_time,
+            })
         }
+        _ => None,
+    })
+}
+
+// Rate limiting of the disk and network devices, either from the token
+// buckets or from the "bw" and "ops" rates per second they are a shorthand for.
+fn parse_rate_limiter_config(
+    parser: &OptionParser,
+    error: fn(OptionParserError) -> Error,
+) -> Result<Option<RateLimiterConfig>> {
+    let bandwidth = parse_token_bucket_config(parser, "bw", error)?;
+    let ops = parse_token_bucket_config(parser, "ops", error)?;
+
+    Ok(if bandwidth.is_some() || ops.is_some() {
+        Some(RateLimiterConfig { bandwidth, ops })
+    } else {
+        None
+    })
+}
+
+pub const RATE_LIMITER_SYNTAX: &str = "Rate limiting parameters \
+    \"bw=<bytes_per_second>,bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,\
+    ops=<io_ops_per_second>,ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>\"";
+
+// Coalescing of the used queue interrupts of the disk and network devices,
+// which requires the delay bounding the latency of the interrupts.
+fn parse_interrupt_coalescing_config(
+    parser: &OptionParser,
+    error: fn(OptionParserError) -> Error,
+) -> Result<Option<InterruptCoalescingConfig>> {
+    let max_events: Option<u32> = parser.convert("coalesce_events").map_err(error)?;
+    let usecs: Option<u32> = parser.convert("coalesce_usecs").map_err(error)?;
+
+    match usecs {
+        Some(usecs) if usecs != 0 => Ok(Some(InterruptCoalescingConfig {
+            max_events: max_events.unwrap_or_default(),
+            usecs,
+        })),
+        _ if max_events.is_some() || usecs.is_some() => {
+            Err(Error::ParseInterruptCoalescingUsecsMissing)
+        }
+        _ => Ok(None),
     }
 }
 
+pub const INTERRUPT_COALESCING_SYNTAX: &str = "Interrupt coalescing parameters \
+    \"coalesce_events=<used_buffers>,coalesce_usecs=<us>\"";
+
+/// Parse the interrupt coalescing options of the disk and network devices on
+/// their own, no coalescing being given by an empty string.
+pub fn parse_interrupt_coalescing(
+    interrupt_coalescing: &str,
+) -> Result<Option<InterruptCoalescingConfig>> {
+    let mut parser = OptionParser::new();
+    parser.add("coalesce_events").add("coalesce_usecs");
+    parser
+        .parse(interrupt_coalescing)
+        .map_err(Error::ParseInterruptCoalescing)?;
+
+    parse_interrupt_coalescing_config(&parser, Error::ParseInterruptCoalescing)
+}
+
+/// Parse the rate limiting options of the disk and network devices on their
+/// own, no rate limiting being given by an empty string.
+pub fn parse_rate_limiter(rate_limiter: &str) -> Result<Option<RateLimiterConfig>> {
+    let mut parser = OptionParser::new();
+    parser
+        .add("bw")
+        .add("bw_size")
+        .add("bw_one_time_burst")
+        .add("bw_refill_time")
+        .add("ops")
+        .add("ops_size")
+        .add("ops_one_time_burst")
+        .add("ops_refill_time");
+    parser
+        .parse(rate_limiter)
+        .map_err(Error::ParseRateLimiter)?;
+
+    parse_rate_limiter_config(&parser, Error::ParseRateLimiter)
+}
+
 impl DiskConfig {
     pub const SYNTAX: &'static str = "Disk parameters \
          \"path=<disk_image_path>,readonly=on|off,direct=on|off,iommu=on|off,\
          num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,\
          vhost_user=on|off,socket=<vhost_user_socket_path>,poll_queue=on|off,\
-         bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,\
-         ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,\
-         id=<device_id>,pci_segment=<segment_id>\"";
+         bw=<bytes_per_second>,bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,\
+         ops=<io_ops_per_second>,ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,\
+         coalesce_events=<used_buffers>,coalesce_usecs=<us>,\
+         id=<device_id>,pci_segment=<segment_id>,io_engine=io_uring|threads|sync,optional=on|off\"";
 
     pub fn parse(disk: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -1025,15 +2134,21 @@ impl DiskConfig {
             .add("vhost_user")
             .add("socket")
             .add("poll_queue")
+            .add("bw")
             .add("bw_size")
             .add("bw_one_time_burst")
             .add("bw_refill_time")
+            .add("ops")
             .add("ops_size")
             .add("ops_one_time_burst")
             .add("ops_refill_time")
+            .add("coalesce_events")
+            .add("coalesce_usecs")
             .add("id")
             .add("_disable_io_uring")
-            .add("pci_segment");
+            .add("pci_segment")
+            .add("io_engine")
+            .add("optional");
         parser.parse(disk).map_err(Error::ParseDisk)?;
 
         let path = parser.get("path").map(PathBuf::from);
@@ -1081,61 +2196,20 @@ impl DiskConfig {
             .convert("pci_segment")
             .map_err(Error::ParseDisk)?
             .unwrap_or_default();
-        let bw_size = parser
-            .convert("bw_size")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let bw_one_time_burst = parser
-            .convert("bw_one_time_burst")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let bw_refill_time = parser
-            .convert("bw_refill_time")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let ops_size = parser
-            .convert("ops_size")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let ops_one_time_burst = parser
-            .convert("ops_one_time_burst")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let ops_refill_time = parser
-            .convert("ops_refill_time")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let bw_tb_config = if bw_size != 0 && bw_refill_time != 0 {
-            Some(TokenBucketConfig {
-                size: bw_size,
-                one_time_burst: Some(bw_one_time_burst),
-                refill_time: bw_refill_time,
-            })
-        } else {
-            None
-        };
-        let ops_tb_config = if ops_size != 0 && ops_refill_time != 0 {
-            Some(TokenBucketConfig {
-                size: ops_size,
-                one_time_burst: Some(ops_one_time_burst),
-                refill_time: ops_refill_time,
-            })
-        } else {
-            None
-        };
-        let rate_limiter_config = if bw_tb_config.is_some() || ops_tb_config.is_some() {
-            Some(RateLimiterConfig {
-                bandwidth: bw_tb_config,
-                ops: ops_tb_config,
-            })
-        } else {
-            None
-        };
+        let io_engine = parser.convert("io_engine").map_err(Error::ParseDisk)?;
+        let rate_limiter_config = parse_rate_limiter_config(&parser, Error::ParseDisk)?;
+        let interrupt_coalescing = parse_interrupt_coalescing_config(&parser, Error::ParseDisk)?;
 
         if parser.is_set("poll_queue") && !vhost_user {
             warn!("poll_queue parameter currently only has effect when used vhost_user=true");
         }
 
+        let optional = parser
+            .convert::<Toggle>("optional")
+            .map_err(Error::ParseDisk)?
+            .unwrap_or(Toggle(false))
+            .0;
+
         Ok(DiskConfig {
             path,
             readonly,
@@ -1147,9 +2221,12 @@ impl DiskConfig {
             vhost_socket,
             poll_queue,
             rate_limiter_config,
+            interrupt_coalescing,
             id,
             disable_io_uring,
             pci_segment,
+            io_engine,
+            optional,
         })
     }
 
@@ -1162,6 +2239,14 @@ impl DiskConfig {
             return Err(ValidationError::IommuNotSupported);
         }
 
+        if self.vhost_user && self.io_engine.is_some() {
+            return Err(ValidationError::DiskIoEngineVhostUser);
+        }
+
+        if matches!(self.interrupt_coalescing, Some(c) if c.usecs == 0) {
+            return Err(ValidationError::InvalidInterruptCoalescingUsecs);
+        }
+
         if let Some(platform_config) = vm_config.platform.as_ref() {
             if self.pci_segment >= platform_config.num_pci_segments {
                 return Err(ValidationError::InvalidPciSegment(self.pci_segment));
@@ -1178,7 +2263,35 @@ impl DiskConfig {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+/// Backend used to perform the I/O of a disk from the device worker
+/// thread. When none is selected, io_uring is used if the host supports it,
+/// and if the disk image format allows it.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum DiskIoEngine {
+    IoUring,
+    Threads,
+    Sync,
+}
+
+#[derive(Debug)]
+pub enum ParseDiskIoEngineError {
+    InvalidValue(String),
+}
+
+impl FromStr for DiskIoEngine {
+    type Err = ParseDiskIoEngineError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "io_uring" => Ok(DiskIoEngine::IoUring),
+            "threads" => Ok(DiskIoEngine::Threads),
+            "sync" => Ok(DiskIoEngine::Sync),
+            _ => Err(ParseDiskIoEngineError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub enum VhostMode {
     Client,
     Server,
@@ -1207,7 +2320,7 @@ impl FromStr for VhostMode {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct NetConfig {
     #[serde(default = "default_netconfig_tap")]
     pub tap: Option<String>,
@@ -1216,8 +2329,10 @@ pub struct NetConfig {
     #[serde(default = "default_netconfig_mask")]
     pub mask: Ipv4Addr,
     #[serde(default = "default_netconfig_mac")]
+    #[schemars(with = "String")]
     pub mac: MacAddr,
     #[serde(default)]
+    #[schemars(with = "Option<String>")]
     pub host_mac: Option<MacAddr>,
     #[serde(default)]
     pub iommu: bool,
@@ -1231,13 +2346,21 @@ pub struct NetConfig {
     #[serde(default)]
     pub vhost_mode: VhostMode,
     #[serde(default)]
+    pub vhost_net: bool,
+    #[serde(default)]
     pub id: Option<String>,
     #[serde(default)]
     pub fds: Option<Vec<i32>>,
     #[serde(default)]
     pub rate_limiter_config: Option<RateLimiterConfig>,
     #[serde(default)]
+    pub interrupt_coalescing: Option<InterruptCoalescingConfig>,
+    #[serde(default)]
     pub pci_segment: u16,
+    #[serde(default)]
+    pub optional: bool,
+    #[serde(default)]
+    pub transport: VirtioTransportType,
 }
 
 fn default_netconfig_tap() -> Option<String> {
@@ -1278,10 +2401,14 @@ impl Default for NetConfig {
             vhost_user: false,
             vhost_socket: None,
             vhost_mode: VhostMode::Client,
+            vhost_net: false,
             id: None,
             fds: None,
             rate_limiter_config: None,
+            interrupt_coalescing: None,
             pci_segment: 0,
+            optional: false,
+            transport: VirtioTransportType::Pci,
         }
     }
 }
@@ -1291,8 +2418,10 @@ impl NetConfig {
     \"tap=<if_name>,ip=<ip_addr>,mask=<net_mask>,mac=<mac_addr>,fd=<fd1,fd2...>,iommu=on|off,\
     num_queues=<number_of_queues>,queue_size=<size_of_each_queue>,id=<device_id>,\
     vhost_user=<vhost_user_enable>,socket=<vhost_user_socket_path>,vhost_mode=client|server,\
-    bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,\
-    ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,pci_segment=<segment_id>\"";
+    vhost_net=on|off,bw=<bytes_per_second>,bw_size=<bytes>,bw_one_time_burst=<bytes>,bw_refill_time=<ms>,\
+    ops=<io_ops_per_second>,ops_size=<io_ops>,ops_one_time_burst=<io_ops>,ops_refill_time=<ms>,\
+    coalesce_events=<used_buffers>,coalesce_usecs=<us>,pci_segment=<segment_id>,\
+    optional=on|off,transport=pci|mmio\"";
 
     pub fn parse(net: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
@@ -1309,15 +2438,22 @@ impl NetConfig {
             .add("vhost_user")
             .add("socket")
             .add("vhost_mode")
+            .add("vhost_net")
             .add("id")
             .add("fd")
+            .add("bw")
             .add("bw_size")
             .add("bw_one_time_burst")
             .add("bw_refill_time")
+            .add("ops")
             .add("ops_size")
             .add("ops_one_time_burst")
             .add("ops_refill_time")
-            .add("pci_segment");
+            .add("coalesce_events")
+            .add("coalesce_usecs")
+            .add("pci_segment")
+            .add("optional")
+            .add("transport");
         parser.parse(net).map_err(Error::ParseNetwork)?;
 
         let tap = parser.get("tap");
@@ -1357,6 +2493,11 @@ impl NetConfig {
             .convert("vhost_mode")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_default();
+        let vhost_net = parser
+            .convert::<Toggle>("vhost_net")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or(Toggle(false))
+            .0;
         let id = parser.get("id");
         let fds = parser
             .convert::<IntegerList>("fd")
@@ -1366,56 +2507,18 @@ impl NetConfig {
             .convert("pci_segment")
             .map_err(Error::ParseNetwork)?
             .unwrap_or_default();
-        let bw_size = parser
-            .convert("bw_size")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let bw_one_time_burst = parser
-            .convert("bw_one_time_burst")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let bw_refill_time = parser
-            .convert("bw_refill_time")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let ops_size = parser
-            .convert("ops_size")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let ops_one_time_burst = parser
-            .convert("ops_one_time_burst")
-            .map_err(Error::ParseDisk)?
-            .unwrap_or_default();
-        let ops_refill_time = parser
-            .convert("ops_refill_time")
-            .map_err(Error::ParseDisk)?
+        let rate_limiter_config = parse_rate_limiter_config(&parser, Error::ParseNetwork)?;
+        let interrupt_coalescing = parse_interrupt_coalescing_config(&parser, Error::ParseNetwork)?;
+
+        let optional = parser
+            .convert::<Toggle>("optional")
+            .map_err(Error::ParseNetwork)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let transport = parser
+            .convert("transport")
+            .map_err(Error::ParseNetwork)?
             .unwrap_or_default();
-        let bw_tb_config = if bw_size != 0 && bw_refill_time != 0 {
-            Some(TokenBucketConfig {
-                size: bw_size,
-                one_time_burst: Some(bw_one_time_burst),
-                refill_time: bw_refill_time,
-            })
-        } else {
-            None
-        };
-        let ops_tb_config = if ops_size != 0 && ops_refill_time != 0 {
-            Some(TokenBucketConfig {
-                size: ops_size,
-                one_time_burst: Some(ops_one_time_burst),
-                refill_time: ops_refill_time,
-            })
-        } else {
-            None
-        };
-        let rate_limiter_config = if bw_tb_config.is_some() || ops_tb_config.is_some() {
-            Some(RateLimiterConfig {
-                bandwidth: bw_tb_config,
-                ops: ops_tb_config,
-            })
-        } else {
-            None
-        };
 
         let config = NetConfig {
             tap,
@@ -1429,10 +2532,14 @@ impl NetConfig {
             vhost_user,
             vhost_socket,
             vhost_mode,
+            vhost_net,
             id,
             fds,
             rate_limiter_config,
+            interrupt_coalescing,
             pci_segment,
+            optional,
+            transport,
         };
         Ok(config)
     }
@@ -1458,10 +2565,47 @@ impl NetConfig {
             return Err(ValidationError::TooManyQueues);
         }
 
+        if matches!(self.interrupt_coalescing, Some(c) if c.usecs == 0) {
+            return Err(ValidationError::InvalidInterruptCoalescingUsecs);
+        }
+
         if self.vhost_user && self.iommu {
             return Err(ValidationError::IommuNotSupported);
         }
 
+        if self.vhost_net {
+            if self.vhost_user {
+                return Err(ValidationError::VhostNetVhostUser);
+            }
+            if self.iommu {
+                return Err(ValidationError::IommuNotSupported);
+            }
+            if self.rate_limiter_config.is_some() || self.interrupt_coalescing.is_some() {
+                return Err(ValidationError::VhostNetRateLimiterOrCoalescing);
+            }
+        }
+
+        if self.transport == VirtioTransportType::Mmio {
+            // Only the vhost-user backends are supported, the device model
+            // of the TAP backend being tied to the PCI transport.
+            #[cfg(not(target_arch = "aarch64"))]
+            return Err(ValidationError::VirtioMmioUnsupported);
+
+            #[cfg(target_arch = "aarch64")]
+            {
+                if !self.vhost_user {
+                    return Err(ValidationError::VirtioMmioNetVhostUserOnly);
+                }
+                if self.iommu {
+                    return Err(ValidationError::VirtioMmioIommu);
+                }
+                if self.pci_segment != 0 {
+                    return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+                }
+                return Ok(());
+            }
+        }
+
         if let Some(platform_config) = vm_config.platform.as_ref() {
             if self.pci_segment >= platform_config.num_pci_segments {
                 return Err(ValidationError::InvalidPciSegment(self.pci_segment));
@@ -1478,7 +2622,7 @@ impl NetConfig {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct RngConfig {
     pub src: PathBuf,
     #[serde(default)]
@@ -1515,496 +2659,631 @@ impl Default for RngConfig {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
-pub struct BalloonConfig {
-    pub size: u64,
-    /// Option to deflate the balloon in case the guest is out of memory.
-    #[serde(default)]
-    pub deflate_on_oom: bool,
-    /// Option to enable free page reporting from the guest.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PtpConfig {
+    pub path: PathBuf,
     #[serde(default)]
-    pub free_page_reporting: bool,
+    pub iommu: bool,
 }
 
-impl BalloonConfig {
-    pub const SYNTAX: &'static str =
-        "Balloon parameters \"size=<balloon_size>,deflate_on_oom=on|off,\
-        free_page_reporting=on|off\"";
-
-    pub fn parse(balloon: &str) -> Result<Self> {
+impl PtpConfig {
+    pub const SYNTAX: &'static str = "PTP hardware clock of the host exposed to the guest \
+    \"path=<ptp_device_path>,iommu=on|off\"";
+    pub fn parse(ptp: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("size");
-        parser.add("deflate_on_oom");
-        parser.add("free_page_reporting");
-        parser.parse(balloon).map_err(Error::ParseBalloon)?;
+        parser.add("path").add("iommu");
+        parser.parse(ptp).map_err(Error::ParsePtp)?;
 
-        let size = parser
-            .convert::<ByteSized>("size")
-            .map_err(Error::ParseBalloon)?
-            .map(|v| v.0)
-            .unwrap_or(0);
-
-        let deflate_on_oom = parser
-            .convert::<Toggle>("deflate_on_oom")
-            .map_err(Error::ParseBalloon)?
-            .unwrap_or(Toggle(false))
-            .0;
-
-        let free_page_reporting = parser
-            .convert::<Toggle>("free_page_reporting")
-            .map_err(Error::ParseBalloon)?
+        let path = PathBuf::from(parser.get("path").ok_or(Error::ParsePtpPathMissing)?);
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParsePtp)?
             .unwrap_or(Toggle(false))
             .0;
 
-        Ok(BalloonConfig {
-            size,
-            deflate_on_oom,
-            free_page_reporting,
-        })
+        Ok(PtpConfig { path, iommu })
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
-pub struct FsConfig {
-    pub tag: String,
-    pub socket: PathBuf,
-    #[serde(default = "default_fsconfig_num_queues")]
-    pub num_queues: usize,
-    #[serde(default = "default_fsconfig_queue_size")]
-    pub queue_size: u16,
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum DebugConsoleOutputMode {
+    File,
+    Log,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct DebugConsoleConfig {
+    pub mode: DebugConsoleOutputMode,
     #[serde(default)]
-    pub id: Option<String>,
+    pub file: Option<PathBuf>,
     #[serde(default)]
-    pub pci_segment: u16,
+    pub iobase: Option<u64>,
 }
 
-fn default_fsconfig_num_queues() -> usize {
-    1
-}
+impl DebugConsoleConfig {
+    pub const SYNTAX: &'static str = "Debug console (debugcon) output for the firmware \
+    \"file=</path/to/a/file>|log,iobase=<io_port_or_mmio_address>\"";
+    pub fn parse(debug_console: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("file").add_valueless("log").add("iobase");
+        parser
+            .parse(debug_console)
+            .map_err(Error::ParseDebugConsole)?;
 
-fn default_fsconfig_queue_size() -> u16 {
-    1024
-}
+        let (mode, file) = if let Some(file) = parser.get("file") {
+            (DebugConsoleOutputMode::File, Some(PathBuf::from(file)))
+        } else if parser.is_set("log") {
+            (DebugConsoleOutputMode::Log, None)
+        } else {
+            return Err(Error::ParseDebugConsoleOutputMissing);
+        };
 
-impl Default for FsConfig {
-    fn default() -> Self {
-        Self {
-            tag: "".to_owned(),
-            socket: PathBuf::new(),
-            num_queues: default_fsconfig_num_queues(),
-            queue_size: default_fsconfig_queue_size(),
-            id: None,
-            pci_segment: 0,
-        }
+        let iobase = parser
+            .get("iobase")
+            .map(|iobase| {
+                let value = if let Some(hex) = iobase.strip_prefix("0x") {
+                    u64::from_str_radix(hex, 16)
+                } else {
+                    iobase.parse::<u64>()
+                };
+                value.map_err(|_| {
+                    Error::ParseDebugConsole(OptionParserError::Conversion(
+                        "iobase".to_owned(),
+                        iobase,
+                    ))
+                })
+            })
+            .transpose()?;
+
+        Ok(DebugConsoleConfig { mode, file, iobase })
     }
-}
 
-impl FsConfig {
-    pub const SYNTAX: &'static str = "virtio-fs parameters \
-    \"tag=<tag_name>,socket=<socket_path>,num_queues=<number_of_queues>,\
-    queue_size=<size_of_each_queue>,id=<device_id>,pci_segment=<segment_id>\"";
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.mode == DebugConsoleOutputMode::File && self.file.is_none() {
+            return Err(ValidationError::DebugConsoleFileMissing);
+        }
 
-    pub fn parse(fs: &str) -> Result<Self> {
-        let mut parser = OptionParser::new();
-        parser
-            .add("tag")
-            .add("queue_size")
-            .add("num_queues")
-            .add("socket")
-            .add("id")
-            .add("pci_segment");
-        parser.parse(fs).map_err(Error::ParseFileSystem)?;
+        if let Some(iobase) = self.iobase {
+            #[cfg(target_arch = "x86_64")]
+            let valid = iobase <= u16::MAX as u64;
+            #[cfg(target_arch = "aarch64")]
+            let valid = iobase != 0;
+            if !valid {
+                return Err(ValidationError::InvalidDebugConsoleIobase(iobase));
+            }
+        }
 
-        let tag = parser.get("tag").ok_or(Error::ParseFsTagMissing)?;
-        let socket = PathBuf::from(parser.get("socket").ok_or(Error::ParseFsSockMissing)?);
+        Ok(())
+    }
+}
 
-        let queue_size = parser
-            .convert("queue_size")
-            .map_err(Error::ParseFileSystem)?
-            .unwrap_or_else(default_fsconfig_queue_size);
-        let num_queues = parser
-            .convert("num_queues")
-            .map_err(Error::ParseFileSystem)?
-            .unwrap_or_else(default_fsconfig_num_queues);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum WatchdogModel {
+    Virtio,
+    Sbsa,
+}
 
-        let id = parser.get("id");
+impl Default for WatchdogModel {
+    fn default() -> Self {
+        WatchdogModel::Virtio
+    }
+}
 
-        let pci_segment = parser
-            .convert("pci_segment")
-            .map_err(Error::ParseFileSystem)?
-            .unwrap_or_default();
+pub enum WatchdogModelParseError {
+    InvalidValue(String),
+}
 
-        Ok(FsConfig {
-            tag,
-            socket,
-            num_queues,
-            queue_size,
-            id,
-            pci_segment,
-        })
-    }
+impl FromStr for WatchdogModel {
+    type Err = WatchdogModelParseError;
 
-    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
-        if self.num_queues > vm_config.cpus.boot_vcpus as usize {
-            return Err(ValidationError::TooManyQueues);
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "virtio" => Ok(WatchdogModel::Virtio),
+            "sbsa" => Ok(WatchdogModel::Sbsa),
+            _ => Err(WatchdogModelParseError::InvalidValue(s.to_owned())),
         }
+    }
+}
 
-        if let Some(platform_config) = vm_config.platform.as_ref() {
-            if self.pci_segment >= platform_config.num_pci_segments {
-                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
-            }
+/// Action taken by the VMM when the watchdog of the guest expires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum WatchdogAction {
+    /// Reboot the VM
+    Reset,
+    /// Shut the VMM down, as if the VM exited
+    Shutdown,
+    /// Run the hook, leaving the VM running
+    Hook,
+}
 
-            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
-                if iommu_segments.contains(&self.pci_segment) {
-                    return Err(ValidationError::IommuNotSupportedOnSegment(
-                        self.pci_segment,
-                    ));
-                }
-            }
-        }
+impl Default for WatchdogAction {
+    fn default() -> Self {
+        WatchdogAction::Reset
+    }
+}
 
-        Ok(())
+pub enum WatchdogActionParseError {
+    InvalidValue(String),
+}
+
+impl FromStr for WatchdogAction {
+    type Err = WatchdogActionParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reset" => Ok(WatchdogAction::Reset),
+            "shutdown" => Ok(WatchdogAction::Shutdown),
+            "hook" => Ok(WatchdogAction::Hook),
+            _ => Err(WatchdogActionParseError::InvalidValue(s.to_owned())),
+        }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct PmemConfig {
-    pub file: PathBuf,
-    #[serde(default)]
-    pub size: Option<u64>,
-    #[serde(default)]
-    pub iommu: bool,
+pub const DEFAULT_WATCHDOG_TIMEOUT: u32 = 20;
+
+fn default_watchdog_timeout() -> u32 {
+    DEFAULT_WATCHDOG_TIMEOUT
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct WatchdogConfig {
     #[serde(default)]
-    pub discard_writes: bool,
+    pub model: WatchdogModel,
+    #[serde(default = "default_watchdog_timeout")]
+    pub timeout: u32,
     #[serde(default)]
-    pub id: Option<String>,
+    pub action: WatchdogAction,
     #[serde(default)]
-    pub pci_segment: u16,
+    pub hook: Option<PathBuf>,
 }
 
-impl PmemConfig {
-    pub const SYNTAX: &'static str = "Persistent memory parameters \
-    \"file=<backing_file_path>,size=<persistent_memory_size>,iommu=on|off,\
-    discard_writes=on|off,id=<device_id>,pci_segment=<segment_id>\"";
-    pub fn parse(pmem: &str) -> Result<Self> {
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            model: WatchdogModel::default(),
+            timeout: DEFAULT_WATCHDOG_TIMEOUT,
+            action: WatchdogAction::default(),
+            hook: None,
+        }
+    }
+}
+
+impl WatchdogConfig {
+    pub const SYNTAX: &'static str = "Enable the watchdog, optionally with \"model=virtio|sbsa,timeout=<timeout_in_seconds>,action=reset|shutdown|hook,\
+    hook=<path_to_executable>\"";
+    pub fn parse(watchdog: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser
-            .add("size")
-            .add("file")
-            .add("iommu")
-            .add("discard_writes")
-            .add("id")
-            .add("pci_segment");
-        parser.parse(pmem).map_err(Error::ParsePersistentMemory)?;
+        parser.add("model").add("timeout").add("action").add("hook");
+        parser.parse(watchdog).map_err(Error::ParseWatchdog)?;
 
-        let file = PathBuf::from(parser.get("file").ok_or(Error::ParsePmemFileMissing)?);
-        let size = parser
-            .convert::<ByteSized>("size")
-            .map_err(Error::ParsePersistentMemory)?
-            .map(|v| v.0);
-        let iommu = parser
-            .convert::<Toggle>("iommu")
-            .map_err(Error::ParsePersistentMemory)?
-            .unwrap_or(Toggle(false))
-            .0;
-        let discard_writes = parser
-            .convert::<Toggle>("discard_writes")
-            .map_err(Error::ParsePersistentMemory)?
-            .unwrap_or(Toggle(false))
-            .0;
-        let id = parser.get("id");
-        let pci_segment = parser
-            .convert("pci_segment")
-            .map_err(Error::ParsePersistentMemory)?
+        let model = parser
+            .convert("model")
+            .map_err(Error::ParseWatchdog)?
+            .unwrap_or_default();
+        let timeout = parser
+            .convert("timeout")
+            .map_err(Error::ParseWatchdog)?
+            .unwrap_or(DEFAULT_WATCHDOG_TIMEOUT);
+        let action = parser
+            .convert("action")
+            .map_err(Error::ParseWatchdog)?
             .unwrap_or_default();
+        let hook = parser.get("hook").map(PathBuf::from);
 
-        Ok(PmemConfig {
-            file,
-            size,
-            iommu,
-            discard_writes,
-            id,
-            pci_segment,
+        Ok(WatchdogConfig {
+            model,
+            timeout,
+            action,
+            hook,
         })
     }
 
-    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
-        if let Some(platform_config) = vm_config.platform.as_ref() {
-            if self.pci_segment >= platform_config.num_pci_segments {
-                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+    pub fn validate(&self) -> ValidationResult<()> {
+        match self.model {
+            // The guest driver of the virtio watchdog pings it at a fixed
+            // interval.
+            WatchdogModel::Virtio => {
+                if self.timeout <= virtio_devices::WATCHDOG_TIMER_INTERVAL as u32 {
+                    return Err(ValidationError::InvalidWatchdogTimeout(self.timeout));
+                }
             }
-
-            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
-                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
-                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+            WatchdogModel::Sbsa => {
+                #[cfg(not(target_arch = "aarch64"))]
+                return Err(ValidationError::SbsaWatchdogUnsupported);
+                #[cfg(target_arch = "aarch64")]
+                if self.timeout == 0 {
+                    return Err(ValidationError::InvalidWatchdogTimeout(self.timeout));
                 }
             }
         }
 
+        if self.action == WatchdogAction::Hook && self.hook.is_none() {
+            return Err(ValidationError::WatchdogHookMissing);
+        }
+
         Ok(())
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
-pub enum ConsoleOutputMode {
-    Off,
-    Pty,
-    Tty,
-    File,
-    Null,
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct AcpiOemTableIdConfig {
+    pub signature: String,
+    pub oem_table_id: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
-pub struct ConsoleConfig {
-    #[serde(default = "default_consoleconfig_file")]
-    pub file: Option<PathBuf>,
-    pub mode: ConsoleOutputMode,
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct AcpiConfig {
     #[serde(default)]
-    pub iommu: bool,
-}
-
-fn default_consoleconfig_file() -> Option<PathBuf> {
-    None
+    pub ssdt: Vec<PathBuf>,
+    #[serde(default)]
+    pub oem_table_ids: Vec<AcpiOemTableIdConfig>,
+    /// Give the ACPI tables to a kernel booted without UEFI firmware,
+    /// through its command line.
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub direct_boot: bool,
 }
 
-impl ConsoleConfig {
-    pub fn parse(console: &str) -> Result<Self> {
+impl AcpiConfig {
+    pub const SYNTAX: &'static str = "Additional ACPI tables and overrides \
+    \"ssdt=<list_of_ssdt_aml_files>,oem_table_ids=<list_of_signature@oem_table_id>,\
+    direct_boot=on|off\" \
+    e.g. ssdt=[/path/to/a.aml,/path/to/b.aml],oem_table_ids=[FACP@MYFACP,DSDT@MYDSDT]";
+    pub fn parse(acpi: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser
-            .add_valueless("off")
-            .add_valueless("pty")
-            .add_valueless("tty")
-            .add_valueless("null")
-            .add("file")
-            .add("iommu");
-        parser.parse(console).map_err(Error::ParseConsole)?;
+        parser.add("ssdt").add("oem_table_ids");
+        #[cfg(target_arch = "aarch64")]
+        parser.add("direct_boot");
+        parser.parse(acpi).map_err(Error::ParseAcpi)?;
+
+        let ssdt = parser
+            .convert::<StringList>("ssdt")
+            .map_err(Error::ParseAcpi)?
+            .unwrap_or_default()
+            .0
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let oem_table_ids = parser
+            .convert::<Tuple<String, String>>("oem_table_ids")
+            .map_err(Error::ParseAcpi)?
+            .map(|v| v.0)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(signature, oem_table_id)| AcpiOemTableIdConfig {
+                signature,
+                oem_table_id,
+            })
+            .collect();
+        #[cfg(target_arch = "aarch64")]
+        let direct_boot = parser
+            .convert::<Toggle>("direct_boot")
+            .map_err(Error::ParseAcpi)?
+            .unwrap_or(Toggle(false))
+            .0;
 
-        let mut file: Option<PathBuf> = default_consoleconfig_file();
-        let mut mode: ConsoleOutputMode = ConsoleOutputMode::Off;
+        Ok(AcpiConfig {
+            ssdt,
+            oem_table_ids,
+            #[cfg(target_arch = "aarch64")]
+            direct_boot,
+        })
+    }
 
-        if parser.is_set("off") {
-        } else if parser.is_set("pty") {
-            mode = ConsoleOutputMode::Pty
-        } else if parser.is_set("tty") {
-            mode = ConsoleOutputMode::Tty
-        } else if parser.is_set("null") {
-            mode = ConsoleOutputMode::Null
-        } else if parser.is_set("file") {
-            mode = ConsoleOutputMode::File;
-            file =
-                Some(PathBuf::from(parser.get("file").ok_or(
-                    Error::Validation(ValidationError::ConsoleFileMissing),
-                )?));
-        } else {
-            return Err(Error::ParseConsoleInvalidModeGiven);
+    pub fn validate(&self) -> ValidationResult<()> {
+        for override_config in self.oem_table_ids.iter() {
+            if override_config.signature.len() != 4 || !override_config.signature.is_ascii() {
+                return Err(ValidationError::InvalidAcpiTableSignature(
+                    override_config.signature.clone(),
+                ));
+            }
+            if override_config.oem_table_id.len() > 8 || !override_config.oem_table_id.is_ascii() {
+                return Err(ValidationError::InvalidAcpiOemTableId(
+                    override_config.oem_table_id.clone(),
+                ));
+            }
         }
-        let iommu = parser
-            .convert::<Toggle>("iommu")
-            .map_err(Error::ParseConsole)?
-            .unwrap_or(Toggle(false))
-            .0;
 
-        Ok(Self { file, mode, iommu })
+        Ok(())
     }
+}
 
-    pub fn default_serial() -> Self {
-        ConsoleConfig {
-            file: None,
-            mode: ConsoleOutputMode::Null,
-            iommu: false,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum SeccompLevel {
+    Trap,
+    Log,
+    Allow,
+}
+
+impl SeccompLevel {
+    /// Rank of the level, the stricter levels ranking higher.
+    pub fn strictness(&self) -> u8 {
+        match self {
+            SeccompLevel::Allow => 0,
+            SeccompLevel::Log => 1,
+            SeccompLevel::Trap => 2,
         }
     }
+}
 
-    pub fn default_console() -> Self {
-        ConsoleConfig {
-            file: None,
-            mode: ConsoleOutputMode::Tty,
-            iommu: false,
+pub enum SeccompLevelParseError {
+    InvalidValue(String),
+}
+
+impl FromStr for SeccompLevel {
+    type Err = SeccompLevelParseError;
+
+    // Same values as the --seccomp option of the VMM.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "true" => Ok(SeccompLevel::Trap),
+            "false" => Ok(SeccompLevel::Allow),
+            "log" => Ok(SeccompLevel::Log),
+            _ => Err(SeccompLevelParseError::InvalidValue(s.to_owned())),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct DeviceConfig {
-    pub path: PathBuf,
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct SecurityConfig {
     #[serde(default)]
-    pub iommu: bool,
+    pub seccomp: Option<SeccompLevel>,
     #[serde(default)]
-    pub id: Option<String>,
+    pub require_iommu: bool,
     #[serde(default)]
-    pub pci_segment: u16,
+    pub seccomp_policy: Option<PathBuf>,
 }
 
-impl DeviceConfig {
-    pub const SYNTAX: &'static str =
-        "Direct device assignment parameters \"path=<device_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>\"";
-    pub fn parse(device: &str) -> Result<Self> {
+impl SecurityConfig {
+    pub const SYNTAX: &'static str = "Security settings of the VM \
+    \"seccomp=true|false|log,require_iommu=on|off,seccomp_policy=<policy_file>\"";
+    pub fn parse(security: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("path").add("id").add("iommu").add("pci_segment");
-        parser.parse(device).map_err(Error::ParseDevice)?;
-
-        let path = parser
-            .get("path")
-            .map(PathBuf::from)
-            .ok_or(Error::ParseDevicePathMissing)?;
-        let iommu = parser
-            .convert::<Toggle>("iommu")
-            .map_err(Error::ParseDevice)?
+        parser
+            .add("seccomp")
+            .add("require_iommu")
+            .add("seccomp_policy");
+        parser.parse(security).map_err(Error::ParseSecurity)?;
+
+        let seccomp = parser
+            .convert::<SeccompLevel>("seccomp")
+            .map_err(Error::ParseSecurity)?;
+        let require_iommu = parser
+            .convert::<Toggle>("require_iommu")
+            .map_err(Error::ParseSecurity)?
             .unwrap_or(Toggle(false))
             .0;
-        let id = parser.get("id");
-        let pci_segment = parser
-            .convert::<u16>("pci_segment")
-            .map_err(Error::ParseDevice)?
-            .unwrap_or_default();
+        let seccomp_policy = parser.get("seccomp_policy").map(PathBuf::from);
 
-        Ok(DeviceConfig {
-            path,
-            iommu,
-            id,
-            pci_segment,
+        Ok(SecurityConfig {
+            seccomp,
+            require_iommu,
+            seccomp_policy,
         })
     }
+}
 
-    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
-        if let Some(platform_config) = vm_config.platform.as_ref() {
-            if self.pci_segment >= platform_config.num_pci_segments {
-                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
-            }
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct DeterministicConfig {
+    pub seed: u64,
+}
 
-            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
-                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
-                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
-                }
-            }
-        }
+impl DeterministicConfig {
+    pub const SYNTAX: &'static str = "Deterministic execution (experimental), making the sources \
+    of nondeterminism controlled by the VMM reproducible \"seed=<random_seed>\"";
+    pub fn parse(deterministic: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("seed");
+        parser
+            .parse(deterministic)
+            .map_err(Error::ParseDeterministic)?;
 
-        Ok(())
+        let seed = parser
+            .convert("seed")
+            .map_err(Error::ParseDeterministic)?
+            .ok_or(Error::ParseDeterministicSeedMissing)?;
+
+        Ok(DeterministicConfig { seed })
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct UserDeviceConfig {
-    pub socket: PathBuf,
+pub const DEFAULT_SNAPSHOT_SCHEDULE_RETENTION: u32 = 3;
+
+fn default_snapshot_schedule_retention() -> u32 {
+    DEFAULT_SNAPSHOT_SCHEDULE_RETENTION
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct SnapshotScheduleConfig {
+    /// Directory the snapshots are stored in, one subdirectory per snapshot.
+    pub destination: PathBuf,
+    /// Interval in seconds between two snapshots.
+    pub interval: u64,
+    /// Number of snapshots kept, the oldest ones being removed.
+    #[serde(default = "default_snapshot_schedule_retention")]
+    pub retention: u32,
+    /// Amount of guest memory which must have been written since the last
+    /// snapshot for a new one to be taken, or 0 to always take it.
     #[serde(default)]
-    pub id: Option<String>,
+    pub dirty_threshold: u64,
     #[serde(default)]
-    pub pci_segment: u16,
+    pub format: SnapshotFormat,
+    /// Only store the guest memory written since the previous snapshot,
+    /// a full snapshot starting a new chain every `retention` snapshots.
+    #[serde(default)]
+    pub incremental: bool,
 }
 
-impl UserDeviceConfig {
-    pub const SYNTAX: &'static str =
-        "Userspace device socket=<socket_path>,id=<device_id>,pci_segment=<segment_id>\"";
-    pub fn parse(user_device: &str) -> Result<Self> {
+impl SnapshotScheduleConfig {
+    pub const SYNTAX: &'static str = "Periodic snapshots of the VM \
+    \"destination=<directory>,interval=<seconds>,retention=<count>,\
+    dirty_threshold=<dirty_memory_size>,format=raw|zstd,incremental=on|off\"";
+    pub fn parse(snapshot_schedule: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("socket").add("id").add("pci_segment");
-        parser.parse(user_device).map_err(Error::ParseUserDevice)?;
+        parser
+            .add("destination")
+            .add("interval")
+            .add("retention")
+            .add("dirty_threshold")
+            .add("format")
+            .add("incremental");
+        parser
+            .parse(snapshot_schedule)
+            .map_err(Error::ParseSnapshotSchedule)?;
 
-        let socket = parser
-            .get("socket")
+        let destination = parser
+            .get("destination")
             .map(PathBuf::from)
-            .ok_or(Error::ParseUserDeviceSocketMissing)?;
-        let id = parser.get("id");
-        let pci_segment = parser
-            .convert::<u16>("pci_segment")
-            .map_err(Error::ParseUserDevice)?
+            .ok_or(Error::ParseSnapshotScheduleDestinationMissing)?;
+        let interval = parser
+            .convert("interval")
+            .map_err(Error::ParseSnapshotSchedule)?
+            .ok_or(Error::ParseSnapshotScheduleIntervalMissing)?;
+        let retention = parser
+            .convert("retention")
+            .map_err(Error::ParseSnapshotSchedule)?
+            .unwrap_or(DEFAULT_SNAPSHOT_SCHEDULE_RETENTION);
+        let dirty_threshold = parser
+            .convert::<ByteSized>("dirty_threshold")
+            .map_err(Error::ParseSnapshotSchedule)?
+            .map(|v| v.0)
+            .unwrap_or(0);
+        let format = parser
+            .convert("format")
+            .map_err(Error::ParseSnapshotSchedule)?
             .unwrap_or_default();
+        let incremental = parser
+            .convert::<Toggle>("incremental")
+            .map_err(Error::ParseSnapshotSchedule)?
+            .unwrap_or(Toggle(false))
+            .0;
 
-        Ok(UserDeviceConfig {
-            socket,
-            id,
-            pci_segment,
+        Ok(SnapshotScheduleConfig {
+            destination,
+            interval,
+            retention,
+            dirty_threshold,
+            format,
+            incremental,
         })
     }
 
-    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
-        if let Some(platform_config) = vm_config.platform.as_ref() {
-            if self.pci_segment >= platform_config.num_pci_segments {
-                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
-            }
-
-            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
-                if iommu_segments.contains(&self.pci_segment) {
-                    return Err(ValidationError::IommuNotSupportedOnSegment(
-                        self.pci_segment,
-                    ));
-                }
-            }
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.interval == 0 {
+            return Err(ValidationError::InvalidSnapshotScheduleInterval);
+        }
+        if self.retention == 0 {
+            return Err(ValidationError::InvalidSnapshotScheduleRetention);
         }
 
         Ok(())
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct VdpaConfig {
-    pub path: PathBuf,
-    #[serde(default = "default_vdpaconfig_num_queues")]
-    pub num_queues: usize,
-    #[serde(default)]
-    pub iommu: bool,
+/// Action taken by the VMM when the lease of a VM in maintenance expires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum MaintenanceExpiryAction {
+    /// Resume the VM, unless it was already paused when entering maintenance
+    Resume,
+    /// Shut the VMM down, as if the VM exited
+    Shutdown,
+}
+
+impl Default for MaintenanceExpiryAction {
+    fn default() -> Self {
+        MaintenanceExpiryAction::Resume
+    }
+}
+
+pub enum MaintenanceExpiryActionParseError {
+    InvalidValue(String),
+}
+
+impl FromStr for MaintenanceExpiryAction {
+    type Err = MaintenanceExpiryActionParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "resume" => Ok(MaintenanceExpiryAction::Resume),
+            "shutdown" => Ok(MaintenanceExpiryAction::Shutdown),
+            _ => Err(MaintenanceExpiryActionParseError::InvalidValue(
+                s.to_owned(),
+            )),
+        }
+    }
+}
+
+pub const DEFAULT_MAINTENANCE_LEASE: u64 = 300;
+// A week, keeping the expiration time far from overflowing.
+pub const MAX_MAINTENANCE_LEASE: u64 = 7 * 24 * 3600;
+
+fn default_maintenance_lease() -> u64 {
+    DEFAULT_MAINTENANCE_LEASE
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct MaintenanceConfig {
+    /// Seconds the VM stays paused in maintenance without the lease being
+    /// renewed.
+    #[serde(default = "default_maintenance_lease")]
+    pub lease: u64,
     #[serde(default)]
-    pub id: Option<String>,
+    pub on_expiry: MaintenanceExpiryAction,
+    /// File the checkpoint header of the VM is kept in while in maintenance.
     #[serde(default)]
-    pub pci_segment: u16,
+    pub checkpoint: Option<PathBuf>,
 }
 
-fn default_vdpaconfig_num_queues() -> usize {
-    1
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            lease: DEFAULT_MAINTENANCE_LEASE,
+            on_expiry: MaintenanceExpiryAction::default(),
+            checkpoint: None,
+        }
+    }
 }
 
-impl VdpaConfig {
-    pub const SYNTAX: &'static str = "vDPA device \
-        \"path=<device_path>,num_queues=<number_of_queues>,iommu=on|off,\
-        id=<device_id>,pci_segment=<segment_id>\"";
-    pub fn parse(vdpa: &str) -> Result<Self> {
+impl MaintenanceConfig {
+    pub const SYNTAX: &'static str = "Host maintenance, entered on SIGUSR1 or through the API \
+    \"lease=<seconds>,on_expiry=resume|shutdown,checkpoint=<checkpoint_header_path>\"";
+    pub fn parse(maintenance: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser
-            .add("path")
-            .add("num_queues")
-            .add("iommu")
-            .add("id")
-            .add("pci_segment");
-        parser.parse(vdpa).map_err(Error::ParseVdpa)?;
-
-        let path = parser
-            .get("path")
-            .map(PathBuf::from)
-            .ok_or(Error::ParseVdpaPathMissing)?;
-        let num_queues = parser
-            .convert("num_queues")
-            .map_err(Error::ParseVdpa)?
-            .unwrap_or_else(default_vdpaconfig_num_queues);
-        let iommu = parser
-            .convert::<Toggle>("iommu")
-            .map_err(Error::ParseVdpa)?
-            .unwrap_or(Toggle(false))
-            .0;
-        let id = parser.get("id");
-        let pci_segment = parser
-            .convert("pci_segment")
-            .map_err(Error::ParseVdpa)?
+        parser.add("lease").add("on_expiry").add("checkpoint");
+        parser.parse(maintenance).map_err(Error::ParseMaintenance)?;
+
+        let lease = parser
+            .convert("lease")
+            .map_err(Error::ParseMaintenance)?
+            .unwrap_or(DEFAULT_MAINTENANCE_LEASE);
+        let on_expiry = parser
+            .convert("on_expiry")
+            .map_err(Error::ParseMaintenance)?
             .unwrap_or_default();
+        let checkpoint = parser.get("checkpoint").map(PathBuf::from);
 
-        Ok(VdpaConfig {
-            path,
-            num_queues,
-            iommu,
-            id,
-            pci_segment,
+        Ok(MaintenanceConfig {
+            lease,
+            on_expiry,
+            checkpoint,
         })
     }
 
-    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
-        if let Some(platform_config) = vm_config.platform.as_ref() {
-            if self.pci_segment >= platform_config.num_pci_segments {
-                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
-            }
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.lease == 0 || self.lease > MAX_MAINTENANCE_LEASE {
+            return Err(ValidationError::InvalidMaintenanceLease);
+        }
 
-            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
-                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
-                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
-                }
+        // The header is written with the privileges of the VMM, the path
+        // possibly coming from the API.
+        if let Some(checkpoint) = &self.checkpoint {
+            if !checkpoint.is_absolute()
+                || checkpoint.file_name().is_none()
+                || checkpoint.components().any(|c| c == Component::ParentDir)
+            {
+                return Err(ValidationError::InvalidMaintenanceCheckpoint(
+                    checkpoint.clone(),
+                ));
             }
         }
 
@@ -2012,815 +3291,4002 @@ impl VdpaConfig {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct VsockConfig {
-    pub cid: u64,
-    pub socket: PathBuf,
-    #[serde(default)]
-    pub iommu: bool,
+// Feature bits independent of the device type, which can be given by name.
+const VIRTIO_FEATURE_NAMES: [(&str, u64); 11] = [
+    ("notify_on_empty", 24),
+    ("any_layout", 27),
+    ("indirect_desc", 28),
+    ("event_idx", 29),
+    ("version_1", 32),
+    ("access_platform", 33),
+    ("ring_packed", 34),
+    ("in_order", 35),
+    ("order_platform", 36),
+    ("sr_iov", 37),
+    ("notification_data", 38),
+];
+
+const VIRTIO_F_VERSION_1: u64 = 32;
+
+// Converts a list of feature bits, given by name or number, into a mask.
+fn parse_virtio_features(option: &str, features: &StringList) -> Result<u64> {
+    let mut mask = 0;
+    for feature in features.0.iter() {
+        let bit = VIRTIO_FEATURE_NAMES
+            .iter()
+            .find(|(name, _)| name == feature)
+            .map(|(_, bit)| *bit)
+            .or_else(|| feature.parse::<u64>().ok().filter(|bit| *bit < 64))
+            .ok_or_else(|| {
+                Error::ParseVirtioFeatures(OptionParserError::Conversion(
+                    option.to_owned(),
+                    feature.to_owned(),
+                ))
+            })?;
+        mask |= 1 << bit;
+    }
+
+    Ok(mask)
+}
+
+/// Feature bits of a virtio device hidden from the guest driver, to work
+/// around drivers mishandling some features, or required from the device.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct VirtioFeaturesConfig {
+    /// Identifier of the device, e.g. `_disk0` for the first disk without an
+    /// identifier.
+    pub id: String,
+    /// Mask of the feature bits hidden from the driver.
     #[serde(default)]
-    pub id: Option<String>,
+    pub disable: u64,
+    /// Mask of the feature bits the device must offer to the driver.
     #[serde(default)]
-    pub pci_segment: u16,
+    pub enable: u64,
 }
 
-impl VsockConfig {
-    pub const SYNTAX: &'static str = "Virtio VSOCK parameters \
-        \"cid=<context_id>,socket=<socket_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>\"";
-    pub fn parse(vsock: &str) -> Result<Self> {
+impl VirtioFeaturesConfig {
+    pub const SYNTAX: &'static str = "Virtio features override \
+         \"id=<device_id>,disable=<list_of_features>,enable=<list_of_features>\", \
+         the features being given by bit number or by name \
+         (indirect_desc, event_idx, any_layout, ...)";
+    pub fn parse(virtio_features: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
+        parser.add("id").add("disable").add("enable");
         parser
-            .add("socket")
-            .add("cid")
-            .add("iommu")
-            .add("id")
-            .add("pci_segment");
-        parser.parse(vsock).map_err(Error::ParseVsock)?;
-
-        let socket = parser
-            .get("socket")
-            .map(PathBuf::from)
-            .ok_or(Error::ParseVsockSockMissing)?;
-        let iommu = parser
-            .convert::<Toggle>("iommu")
-            .map_err(Error::ParseVsock)?
-            .unwrap_or(Toggle(false))
-            .0;
-        let cid = parser
-            .convert("cid")
-            .map_err(Error::ParseVsock)?
-            .ok_or(Error::ParseVsockCidMissing)?;
-        let id = parser.get("id");
-        let pci_segment = parser
-            .convert("pci_segment")
-            .map_err(Error::ParseVsock)?
-            .unwrap_or_default();
+            .parse(virtio_features)
+            .map_err(Error::ParseVirtioFeatures)?;
+
+        let id = parser
+            .get("id")
+            .ok_or(Error::ParseVirtioFeaturesIdMissing)?;
+        let disable = parser
+            .convert::<StringList>("disable")
+            .map_err(Error::ParseVirtioFeatures)?
+            .map(|features| parse_virtio_features("disable", &features))
+            .transpose()?
+            .unwrap_or(0);
+        let enable = parser
+            .convert::<StringList>("enable")
+            .map_err(Error::ParseVirtioFeatures)?
+            .map(|features| parse_virtio_features("enable", &features))
+            .transpose()?
+            .unwrap_or(0);
 
-        Ok(VsockConfig {
-            cid,
-            socket,
-            iommu,
+        Ok(VirtioFeaturesConfig {
             id,
-            pci_segment,
+            disable,
+            enable,
         })
     }
 
-    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
-        if let Some(platform_config) = vm_config.platform.as_ref() {
-            if self.pci_segment >= platform_config.num_pci_segments {
-                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
-            }
+    pub fn validate(&self) -> ValidationResult<()> {
+        if self.disable & self.enable != 0 {
+            return Err(ValidationError::VirtioFeaturesConflict(
+                self.id.clone(),
+                self.disable & self.enable,
+            ));
+        }
 
-            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
-                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
-                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
-                }
-            }
+        if self.disable & (1 << VIRTIO_F_VERSION_1) != 0 {
+            return Err(ValidationError::VirtioFeaturesVersion1(self.id.clone()));
         }
 
         Ok(())
     }
 }
 
-#[cfg(feature = "tdx")]
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct TdxConfig {
-    pub firmware: PathBuf,
-}
+/// Interrupts the devices can be given: the IOAPIC pins from 5 on x86_64,
+/// the SPIs on AArch64.
+#[cfg(target_arch = "x86_64")]
+pub const DEVICE_IRQS: std::ops::Range<u32> = 5..24;
+#[cfg(target_arch = "aarch64")]
+pub const DEVICE_IRQS: std::ops::Range<u32> = arch::IRQ_BASE..arch::layout::IRQ_NUM;
 
-#[cfg(feature = "tdx")]
-impl TdxConfig {
-    pub fn parse(tdx: &str) -> Result<Self> {
-        let mut parser = OptionParser::new();
-        parser.add("firmware");
-        parser.parse(tdx).map_err(Error::ParseTdx)?;
-        let firmware = parser
-            .get("firmware")
-            .map(PathBuf::from)
-            .ok_or(Error::FirmwarePathMissing)?;
-        Ok(TdxConfig { firmware })
-    }
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct IrqPinConfig {
+    /// Identifier of the device, as found in the device tree of the VM.
+    pub id: String,
+    pub irq: u32,
 }
 
-#[cfg(target_arch = "x86_64")]
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct SgxEpcConfig {
-    pub id: String,
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct IrqConfig {
+    /// IRQs given to the devices instead of the next available ones.
     #[serde(default)]
-    pub size: u64,
+    pub pins: Vec<IrqPinConfig>,
+    /// IRQs never given to the devices, left to the passthrough ones.
     #[serde(default)]
-    pub prefault: bool,
+    pub reserved: Vec<u32>,
 }
 
-#[cfg(target_arch = "x86_64")]
-impl SgxEpcConfig {
-    pub const SYNTAX: &'static str = "SGX EPC parameters \
-        \"id=<epc_section_identifier>,size=<epc_section_size>,prefault=on|off\"";
-    pub fn parse(sgx_epc: &str) -> Result<Self> {
+impl IrqConfig {
+    pub const SYNTAX: &'static str = "Static IRQ layout \
+    \"pins=<list_of_device_id@irq>,reserved=<list_of_irqs>\" \
+    e.g. pins=[__serial@40,_net0@41],reserved=[48-55]";
+    pub fn parse(irqs: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("id").add("size").add("prefault");
-        parser.parse(sgx_epc).map_err(Error::ParseSgxEpc)?;
+        parser.add("pins").add("reserved");
+        parser.parse(irqs).map_err(Error::ParseIrqs)?;
 
-        let id = parser.get("id").ok_or(Error::ParseSgxEpcIdMissing)?;
-        let size = parser
-            .convert::<ByteSized>("size")
-            .map_err(Error::ParseSgxEpc)?
-            .unwrap_or(ByteSized(0))
-            .0;
-        let prefault = parser
-            .convert::<Toggle>("prefault")
-            .map_err(Error::ParseSgxEpc)?
-            .unwrap_or(Toggle(false))
-            .0;
+        let pins = parser
+            .convert::<Tuple<String, u64>>("pins")
+            .map_err(Error::ParseIrqs)?
+            .map(|v| v.0)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, irq)| IrqPinConfig {
+                id,
+                irq: irq as u32,
+            })
+            .collect();
+        let reserved = parser
+            .convert::<IntegerList>("reserved")
+            .map_err(Error::ParseIrqs)?
+            .map(|v| v.0.iter().map(|irq| *irq as u32).collect())
+            .unwrap_or_default();
 
-        Ok(SgxEpcConfig { id, size, prefault })
+        Ok(IrqConfig { pins, reserved })
     }
-}
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct NumaDistance {
-    #[serde(default)]
-    pub destination: u32,
-    #[serde(default)]
-    pub distance: u8,
-}
+    pub fn validate(&self) -> ValidationResult<()> {
+        let mut ids = BTreeSet::new();
+        let mut irqs = BTreeSet::new();
+        for pin in self.pins.iter() {
+            if !ids.insert(&pin.id) {
+                return Err(ValidationError::DuplicateIrqPin(pin.id.clone()));
+            }
+        }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct NumaConfig {
-    #[serde(default)]
-    pub guest_numa_id: u32,
-    #[serde(default)]
-    pub cpus: Option<Vec<u8>>,
-    #[serde(default)]
-    pub distances: Option<Vec<NumaDistance>>,
+        for irq in self
+            .pins
+            .iter()
+            .map(|pin| pin.irq)
+            .chain(self.reserved.iter().copied())
+        {
+            if !DEVICE_IRQS.contains(&irq) {
+                return Err(ValidationError::InvalidIrq(irq));
+            }
+            if !irqs.insert(irq) {
+                return Err(ValidationError::DuplicateIrq(irq));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct DoorbellConfig {
+    /// Pair of eventfds for each channel, the first one signaled when the
+    /// guest rings the channel, the second one notifying the guest.
+    pub fds: Vec<i32>,
     #[serde(default)]
-    pub memory_zones: Option<Vec<String>>,
-    #[cfg(target_arch = "x86_64")]
+    pub id: Option<String>,
+}
+
+impl DoorbellConfig {
+    pub const SYNTAX: &'static str = "Doorbell device \
+    \"fds=<list_of_eventfd_pairs>,id=<device_id>\", with a pair of eventfds for each \
+    channel, signaled when the guest rings the channel and notifying the guest";
+    pub fn parse(doorbell: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("fds").add("id");
+        parser.parse(doorbell).map_err(Error::ParseDoorbell)?;
+
+        let fds = parser
+            .convert::<IntegerList>("fds")
+            .map_err(Error::ParseDoorbell)?
+            .map(|v| v.0.iter().map(|fd| *fd as i32).collect())
+            .ok_or(Error::ParseDoorbellFdsMissing)?;
+        let id = parser.get("id");
+
+        Ok(DoorbellConfig { fds, id })
+    }
+
+    pub fn validate(&self) -> ValidationResult<()> {
+        // The device is only advertised through the device tree.
+        if cfg!(not(target_arch = "aarch64")) {
+            return Err(ValidationError::DoorbellUnsupported);
+        }
+
+        let fds = self.fds.len();
+        if fds == 0 || fds % 2 != 0 || fds > 2 * devices::legacy::DOORBELL_MAX_CHANNELS {
+            return Err(ValidationError::InvalidDoorbellChannels(fds));
+        }
+
+        if self.fds.iter().any(|fd| *fd <= 2) {
+            return Err(ValidationError::DoorbellReservedFd);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PartitionServicesConfig {
+    /// Identifier of the partition reported to the guest.
     #[serde(default)]
-    pub sgx_epc_sections: Option<Vec<String>>,
+    pub partition_id: u64,
 }
 
-impl NumaConfig {
-    pub const SYNTAX: &'static str = "Settings related to a given NUMA node \
-        \"guest_numa_id=<node_id>,cpus=<cpus_id>,distances=<list_of_distances_to_destination_nodes>,\
-        memory_zones=<list_of_memory_zones>,sgx_epc_sections=<list_of_sgx_epc_sections>\"";
-    pub fn parse(numa: &str) -> Result<Self> {
+impl PartitionServicesConfig {
+    pub const SYNTAX: &'static str = "Partition services mailbox \
+    \"partition_id=<partition_id>\"";
+    pub fn parse(partition_services: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
+        parser.add("partition_id");
         parser
-            .add("guest_numa_id")
-            .add("cpus")
-            .add("distances")
-            .add("memory_zones")
-            .add("sgx_epc_sections");
-        parser.parse(numa).map_err(Error::ParseNuma)?;
+            .parse(partition_services)
+            .map_err(Error::ParsePartitionServices)?;
 
-        let guest_numa_id = parser
-            .convert::<u32>("guest_numa_id")
-            .map_err(Error::ParseNuma)?
-            .unwrap_or(0);
-        let cpus = parser
-            .convert::<IntegerList>("cpus")
-            .map_err(Error::ParseNuma)?
-            .map(|v| v.0.iter().map(|e| *e as u8).collect());
-        let distances = parser
-            .convert::<Tuple<u64, u64>>("distances")
-            .map_err(Error::ParseNuma)?
-            .map(|v| {
-                v.0.iter()
-                    .map(|(e1, e2)| NumaDistance {
-                        destination: *e1 as u32,
-                        distance: *e2 as u8,
-                    })
-                    .collect()
-            });
-        let memory_zones = parser
-            .convert::<StringList>("memory_zones")
-            .map_err(Error::ParseNuma)?
-            .map(|v| v.0);
-        #[cfg(target_arch = "x86_64")]
-        let sgx_epc_sections = parser
-            .convert::<StringList>("sgx_epc_sections")
-            .map_err(Error::ParseNuma)?
-            .map(|v| v.0);
+        let partition_id = parser
+            .convert("partition_id")
+            .map_err(Error::ParsePartitionServices)?
+            .unwrap_or_default();
 
-        Ok(NumaConfig {
-            guest_numa_id,
-            cpus,
-            distances,
-            memory_zones,
-            #[cfg(target_arch = "x86_64")]
-            sgx_epc_sections,
-        })
+        Ok(PartitionServicesConfig { partition_id })
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
-pub struct RestoreConfig {
-    pub source_url: PathBuf,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PriorityClass {
+    Realtime,
+    Latency,
+    Throughput,
+    Background,
+}
+
+#[derive(Debug)]
+pub enum ParsePriorityClassError {
+    InvalidValue(String),
+}
+
+impl FromStr for PriorityClass {
+    type Err = ParsePriorityClassError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "realtime" => Ok(PriorityClass::Realtime),
+            "latency" => Ok(PriorityClass::Latency),
+            "throughput" => Ok(PriorityClass::Throughput),
+            "background" => Ok(PriorityClass::Background),
+            _ => Err(ParsePriorityClassError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PriorityConfig {
+    /// Class selecting the host tuning profile of the VM.
+    pub class: PriorityClass,
+}
+
+impl PriorityConfig {
+    pub const SYNTAX: &'static str = "Priority class of the VM, bundling the host tuning of \
+    its vCPUs, memory and device threads \"class=realtime|latency|throughput|background\"";
+    pub fn parse(priority: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("class");
+        parser.parse(priority).map_err(Error::ParsePriority)?;
+
+        let class = parser
+            .convert("class")
+            .map_err(Error::ParsePriority)?
+            .ok_or(Error::ParsePriorityClassMissing)?;
+
+        Ok(PriorityConfig { class })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct BalloonConfig {
+    pub size: u64,
+    /// Option to deflate the balloon in case the guest is out of memory.
     #[serde(default)]
-    pub prefault: bool,
+    pub deflate_on_oom: bool,
+    /// Option to enable free page reporting from the guest.
+    #[serde(default)]
+    pub free_page_reporting: bool,
+    /// Interval in seconds between two memory statistics requests to the
+    /// guest, or 0 to disable the statistics.
+    #[serde(default)]
+    pub stats_polling_interval: u64,
 }
 
-impl RestoreConfig {
-    pub const SYNTAX: &'static str = "Restore from a VM snapshot. \
-        \nRestore parameters \"source_url=<source_url>,prefault=on|off\" \
-        \n`source_url` should be a valid URL (e.g file:///foo/bar or tcp://192.168.1.10/foo) \
-        \n`prefault` brings memory pages in when enabled (disabled by default)";
-    pub fn parse(restore: &str) -> Result<Self> {
+impl BalloonConfig {
+    pub const SYNTAX: &'static str =
+        "Balloon parameters \"size=<balloon_size>,deflate_on_oom=on|off,\
+        free_page_reporting=on|off,stats_polling_interval=<seconds>\"";
+
+    pub fn parse(balloon: &str) -> Result<Self> {
         let mut parser = OptionParser::new();
-        parser.add("source_url").add("prefault");
-        parser.parse(restore).map_err(Error::ParseRestore)?;
+        parser.add("size");
+        parser.add("deflate_on_oom");
+        parser.add("free_page_reporting");
+        parser.add("stats_polling_interval");
+        parser.parse(balloon).map_err(Error::ParseBalloon)?;
 
-        let source_url = parser
-            .get("source_url")
-            .map(PathBuf::from)
-            .ok_or(Error::ParseRestoreSourceUrlMissing)?;
-        let prefault = parser
-            .convert::<Toggle>("prefault")
-            .map_err(Error::ParseRestore)?
+        let size = parser
+            .convert::<ByteSized>("size")
+            .map_err(Error::ParseBalloon)?
+            .map(|v| v.0)
+            .unwrap_or(0);
+
+        let deflate_on_oom = parser
+            .convert::<Toggle>("deflate_on_oom")
+            .map_err(Error::ParseBalloon)?
             .unwrap_or(Toggle(false))
             .0;
 
-        Ok(RestoreConfig {
-            source_url,
-            prefault,
+        let free_page_reporting = parser
+            .convert::<Toggle>("free_page_reporting")
+            .map_err(Error::ParseBalloon)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        let stats_polling_interval = parser
+            .convert("stats_polling_interval")
+            .map_err(Error::ParseBalloon)?
+            .unwrap_or(0);
+
+        Ok(BalloonConfig {
+            size,
+            deflate_on_oom,
+            free_page_reporting,
+            stats_polling_interval,
         })
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
-pub struct VmConfig {
-    #[serde(default)]
-    pub cpus: CpusConfig,
-    #[serde(default)]
-    pub memory: MemoryConfig,
-    pub kernel: Option<KernelConfig>,
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct FsConfig {
+    pub tag: String,
+    pub socket: PathBuf,
+    #[serde(default = "default_fsconfig_num_queues")]
+    pub num_queues: usize,
+    #[serde(default = "default_fsconfig_queue_size")]
+    pub queue_size: u16,
     #[serde(default)]
-    pub initramfs: Option<InitramfsConfig>,
+    pub id: Option<String>,
     #[serde(default)]
-    pub cmdline: CmdlineConfig,
-    pub disks: Option<Vec<DiskConfig>>,
-    pub net: Option<Vec<NetConfig>>,
+    pub pci_segment: u16,
     #[serde(default)]
-    pub rng: RngConfig,
-    pub balloon: Option<BalloonConfig>,
-    pub fs: Option<Vec<FsConfig>>,
-    pub pmem: Option<Vec<PmemConfig>>,
-    #[serde(default = "ConsoleConfig::default_serial")]
-    pub serial: ConsoleConfig,
-    #[serde(default = "ConsoleConfig::default_console")]
-    pub console: ConsoleConfig,
-    pub devices: Option<Vec<DeviceConfig>>,
-    pub user_devices: Option<Vec<UserDeviceConfig>>,
-    pub vdpa: Option<Vec<VdpaConfig>>,
-    pub vsock: Option<VsockConfig>,
+    pub optional: bool,
     #[serde(default)]
-    pub iommu: bool,
-    #[cfg(target_arch = "x86_64")]
-    pub sgx_epc: Option<Vec<SgxEpcConfig>>,
-    pub numa: Option<Vec<NumaConfig>>,
+    pub dax: bool,
+    #[serde(default = "default_fsconfig_cache_size")]
+    pub cache_size: u64,
     #[serde(default)]
-    pub watchdog: bool,
-    #[cfg(feature = "tdx")]
-    pub tdx: Option<TdxConfig>,
-    #[cfg(feature = "gdb")]
-    pub gdb: bool,
-    pub platform: Option<PlatformConfig>,
+    pub transport: VirtioTransportType,
 }
 
-impl VmConfig {
-    fn validate_identifier(
-        id_list: &mut BTreeSet<String>,
-        id: &Option<String>,
-    ) -> ValidationResult<()> {
-        if let Some(id) = id.as_ref() {
-            if id.starts_with("__") {
-                return Err(ValidationError::InvalidIdentifier(id.clone()));
-            }
-
-            if !id_list.insert(id.clone()) {
-                return Err(ValidationError::IdentifierNotUnique(id.clone()));
-            }
-        }
-
-        Ok(())
-    }
+fn default_fsconfig_num_queues() -> usize {
+    1
+}
 
-    // Also enables virtio-iommu if the config needs it
-    // Returns the list of unique identifiers provided through the
-    // configuration.
-    pub fn validate(&mut self) -> ValidationResult<BTreeSet<String>> {
-        let mut id_list = BTreeSet::new();
+fn default_fsconfig_queue_size() -> u16 {
+    1024
+}
 
-        #[cfg(not(feature = "tdx"))]
-        self.kernel.as_ref().ok_or(ValidationError::KernelMissing)?;
+fn default_fsconfig_cache_size() -> u64 {
+    0x0002_0000_0000
+}
 
-        #[cfg(feature = "tdx")]
-        {
-            let tdx_enabled = self.tdx.is_some();
-            if !tdx_enabled && self.kernel.is_none() {
-                return Err(ValidationError::KernelMissing);
-            }
-            if tdx_enabled && (self.cpus.max_vcpus != self.cpus.boot_vcpus) {
-                return Err(ValidationError::TdxNoCpuHotplug);
-            }
+impl Default for FsConfig {
+    fn default() -> Self {
+        Self {
+            tag: "".to_owned(),
+            socket: PathBuf::new(),
+            num_queues: default_fsconfig_num_queues(),
+            queue_size: default_fsconfig_queue_size(),
+            id: None,
+            pci_segment: 0,
+            optional: false,
+            dax: false,
+            cache_size: default_fsconfig_cache_size(),
+            transport: VirtioTransportType::Pci,
         }
+    }
+}
 
-        if self.console.mode == ConsoleOutputMode::Tty && self.serial.mode == ConsoleOutputMode::Tty
-        {
-            return Err(ValidationError::DoubleTtyMode);
-        }
+impl FsConfig {
+    pub const SYNTAX: &'static str = "virtio-fs parameters \
+    \"tag=<tag_name>,socket=<socket_path>,num_queues=<number_of_queues>,\
+    queue_size=<size_of_each_queue>,id=<device_id>,pci_segment=<segment_id>,optional=on|off,\
+    dax=on|off,cache_size=<DAX cache size: default 8Gib>,transport=pci|mmio\"";
 
-        if self.console.mode == ConsoleOutputMode::File && self.console.file.is_none() {
-            return Err(ValidationError::ConsoleFileMissing);
-        }
+    pub fn parse(fs: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("tag")
+            .add("queue_size")
+            .add("num_queues")
+            .add("socket")
+            .add("id")
+            .add("pci_segment")
+            .add("optional")
+            .add("dax")
+            .add("cache_size")
+            .add("transport");
+        parser.parse(fs).map_err(Error::ParseFileSystem)?;
 
-        if self.serial.mode == ConsoleOutputMode::File && self.serial.file.is_none() {
-            return Err(ValidationError::ConsoleFileMissing);
-        }
+        let tag = parser.get("tag").ok_or(Error::ParseFsTagMissing)?;
+        let socket = PathBuf::from(parser.get("socket").ok_or(Error::ParseFsSockMissing)?);
 
-        if self.cpus.max_vcpus < self.cpus.boot_vcpus {
-            return Err(ValidationError::CpusMaxLowerThanBoot);
-        }
+        let queue_size = parser
+            .convert("queue_size")
+            .map_err(Error::ParseFileSystem)?
+            .unwrap_or_else(default_fsconfig_queue_size);
+        let num_queues = parser
+            .convert("num_queues")
+            .map_err(Error::ParseFileSystem)?
+            .unwrap_or_else(default_fsconfig_num_queues);
 
-        if let Some(disks) = &self.disks {
-            for disk in disks {
-                if disk.vhost_socket.as_ref().and(disk.path.as_ref()).is_some() {
-                    return Err(ValidationError::DiskSocketAndPath);
-                }
-                if disk.vhost_user && !self.memory.shared {
-                    return Err(ValidationError::VhostUserRequiresSharedMemory);
-                }
-                if disk.vhost_user && disk.vhost_socket.is_none() {
-                    return Err(ValidationError::VhostUserMissingSocket);
-                }
-                disk.validate(self)?;
-                self.iommu |= disk.iommu;
+        let id = parser.get("id");
 
-                Self::validate_identifier(&mut id_list, &disk.id)?;
-            }
-        }
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParseFileSystem)?
+            .unwrap_or_default();
 
-        if let Some(nets) = &self.net {
-            for net in nets {
-                if net.vhost_user && !self.memory.shared {
-                    return Err(ValidationError::VhostUserRequiresSharedMemory);
-                }
-                net.validate(self)?;
-                self.iommu |= net.iommu;
+        let optional = parser
+            .convert::<Toggle>("optional")
+            .map_err(Error::ParseFileSystem)?
+            .unwrap_or(Toggle(false))
+            .0;
 
-                Self::validate_identifier(&mut id_list, &net.id)?;
-            }
-        }
+        let dax = parser
+            .convert::<Toggle>("dax")
+            .map_err(Error::ParseFileSystem)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let cache_size = parser
+            .convert::<ByteSized>("cache_size")
+            .map_err(Error::ParseFileSystem)?
+            .map(|v| v.0)
+            .unwrap_or_else(default_fsconfig_cache_size);
 
-        if let Some(fses) = &self.fs {
-            if !fses.is_empty() && !self.memory.shared {
-                return Err(ValidationError::VhostUserRequiresSharedMemory);
-            }
-            for fs in fses {
-                fs.validate(self)?;
+        let transport = parser
+            .convert("transport")
+            .map_err(Error::ParseFileSystem)?
+            .unwrap_or_default();
 
-                Self::validate_identifier(&mut id_list, &fs.id)?;
-            }
-        }
+        Ok(FsConfig {
+            tag,
+            socket,
+            num_queues,
+            queue_size,
+            id,
+            pci_segment,
+            optional,
+            dax,
+            cache_size,
+            transport,
+        })
+    }
 
-        if let Some(pmems) = &self.pmem {
-            for pmem in pmems {
-                pmem.validate(self)?;
-                self.iommu |= pmem.iommu;
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.num_queues > vm_config.cpus.boot_vcpus as usize {
+            return Err(ValidationError::TooManyQueues);
+        }
 
-                Self::validate_identifier(&mut id_list, &pmem.id)?;
-            }
+        // The DAX cache is mapped with huge pages when possible.
+        if self.dax && (self.cache_size == 0 || self.cache_size % 0x20_0000 != 0) {
+            return Err(ValidationError::InvalidFsCacheSize(self.cache_size));
         }
 
-        self.iommu |= self.rng.iommu;
-        self.iommu |= self.console.iommu;
+        if self.transport == VirtioTransportType::Mmio {
+            #[cfg(not(target_arch = "aarch64"))]
+            return Err(ValidationError::VirtioMmioUnsupported);
 
-        if let Some(t) = &self.cpus.topology {
-            if t.threads_per_core == 0
-                || t.cores_per_die == 0
-                || t.dies_per_package == 0
-                || t.packages == 0
+            #[cfg(target_arch = "aarch64")]
             {
-                return Err(ValidationError::CpuTopologyZeroPart);
+                if self.pci_segment != 0 {
+                    return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+                }
+                return Ok(());
             }
+        }
 
-            // The setting of dies doesen't apply on AArch64.
-            // Only '1' value is accepted, so its impact on the vcpu topology
-            // setting can be ignored.
-            #[cfg(target_arch = "aarch64")]
-            if t.dies_per_package != 1 {
-                return Err(ValidationError::CpuTopologyDiesPerPackage);
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
             }
 
-            let total = t.threads_per_core * t.cores_per_die * t.dies_per_package * t.packages;
-            if total != self.cpus.max_vcpus {
-                return Err(ValidationError::CpuTopologyCount);
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) {
+                    return Err(ValidationError::IommuNotSupportedOnSegment(
+                        self.pci_segment,
+                    ));
+                }
             }
         }
 
-        if let Some(hugepage_size) = &self.memory.hugepage_size {
-            if !self.memory.hugepages {
-                return Err(ValidationError::HugePageSizeWithoutHugePages);
-            }
-            if !hugepage_size.is_power_of_two() {
-                return Err(ValidationError::InvalidHugePageSize(*hugepage_size));
-            }
-        }
+        Ok(())
+    }
+}
 
-        if let Some(user_devices) = &self.user_devices {
-            if !user_devices.is_empty() && !self.memory.shared {
-                return Err(ValidationError::UserDevicesRequireSharedMemory);
-            }
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct PmemConfig {
+    pub file: PathBuf,
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default)]
+    pub discard_writes: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+    #[serde(default)]
+    pub optional: bool,
+    #[serde(default)]
+    pub transport: VirtioTransportType,
+}
 
-            for user_device in user_devices {
-                user_device.validate(self)?;
+impl PmemConfig {
+    pub const SYNTAX: &'static str = "Persistent memory parameters \
+    \"file=<backing_file_path>,size=<persistent_memory_size>,iommu=on|off,\
+    discard_writes=on|off,id=<device_id>,pci_segment=<segment_id>,optional=on|off,\
+    transport=pci|mmio\"";
+    pub fn parse(pmem: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("size")
+            .add("file")
+            .add("iommu")
+            .add("discard_writes")
+            .add("id")
+            .add("pci_segment")
+            .add("optional")
+            .add("transport");
+        parser.parse(pmem).map_err(Error::ParsePersistentMemory)?;
 
-                Self::validate_identifier(&mut id_list, &user_device.id)?;
-            }
-        }
+        let file = PathBuf::from(parser.get("file").ok_or(Error::ParsePmemFileMissing)?);
+        let size = parser
+            .convert::<ByteSized>("size")
+            .map_err(Error::ParsePersistentMemory)?
+            .map(|v| v.0);
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParsePersistentMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let discard_writes = parser
+            .convert::<Toggle>("discard_writes")
+            .map_err(Error::ParsePersistentMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParsePersistentMemory)?
+            .unwrap_or_default();
 
-        if let Some(vdpa_devices) = &self.vdpa {
-            for vdpa_device in vdpa_devices {
-                vdpa_device.validate(self)?;
-                self.iommu |= vdpa_device.iommu;
+        let optional = parser
+            .convert::<Toggle>("optional")
+            .map_err(Error::ParsePersistentMemory)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let transport = parser
+            .convert("transport")
+            .map_err(Error::ParsePersistentMemory)?
+            .unwrap_or_default();
 
-                Self::validate_identifier(&mut id_list, &vdpa_device.id)?;
-            }
-        }
+        Ok(PmemConfig {
+            file,
+            size,
+            iommu,
+            discard_writes,
+            id,
+            pci_segment,
+            optional,
+            transport,
+        })
+    }
 
-        if let Some(balloon) = &self.balloon {
-            let mut ram_size = self.memory.size;
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.transport == VirtioTransportType::Mmio {
+            // The virtio-mmio devices are only described through the device
+            // tree.
+            #[cfg(not(target_arch = "aarch64"))]
+            return Err(ValidationError::VirtioMmioUnsupported);
 
-            if let Some(zones) = &self.memory.zones {
-                for zone in zones {
-                    ram_size += zone.size;
+            #[cfg(target_arch = "aarch64")]
+            {
+                if self.iommu {
+                    return Err(ValidationError::VirtioMmioIommu);
                 }
-            }
-
-            if balloon.size >= ram_size {
-                return Err(ValidationError::BalloonLargerThanRam(
-                    balloon.size,
-                    ram_size,
-                ));
+                if self.pci_segment != 0 {
+                    return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+                }
+                return Ok(());
             }
         }
 
-        if let Some(devices) = &self.devices {
-            for device in devices {
-                device.validate(self)?;
-                self.iommu |= device.iommu;
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
 
-                Self::validate_identifier(&mut id_list, &device.id)?;
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+                }
             }
         }
 
-        if let Some(vsock) = &self.vsock {
-            vsock.validate(self)?;
-            self.iommu |= vsock.iommu;
+        Ok(())
+    }
+}
 
-            Self::validate_identifier(&mut id_list, &vsock.id)?;
-        }
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PstoreConfig {
+    pub file: PathBuf,
+    pub size: u64,
+}
 
-        if let Some(numa) = &self.numa {
-            let mut used_numa_node_memory_zones = HashMap::new();
-            for numa_node in numa.iter() {
-                for memory_zone in numa_node.memory_zones.clone().unwrap().iter() {
-                    if !used_numa_node_memory_zones.contains_key(memory_zone) {
-                        used_numa_node_memory_zones
-                            .insert(memory_zone.to_string(), numa_node.guest_numa_id);
-                    } else {
-                        return Err(ValidationError::MemoryZoneReused(
-                            memory_zone.to_string(),
-                            *used_numa_node_memory_zones.get(memory_zone).unwrap(),
-                            numa_node.guest_numa_id,
-                        ));
-                    }
+impl PstoreConfig {
+    pub const SYNTAX: &'static str = "Pstore (ramoops) region parameters \
+    \"file=<backing_file_path>,size=<region_size>\"";
+    pub fn parse(pstore: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("file").add("size");
+        parser.parse(pstore).map_err(Error::ParsePstore)?;
+
+        let file = PathBuf::from(parser.get("file").ok_or(Error::ParsePstoreFileMissing)?);
+        let size = parser
+            .convert::<ByteSized>("size")
+            .map_err(Error::ParsePstore)?
+            .unwrap_or(ByteSized(DEFAULT_PSTORE_SIZE))
+            .0;
+
+        Ok(PstoreConfig { file, size })
+    }
+
+    pub fn validate(&self) -> ValidationResult<()> {
+        // The region holds at least one dmesg record and the console record,
+        // each of them being 64KiB.
+        if self.size < 2 * PSTORE_RECORD_SIZE || self.size % PSTORE_RECORD_SIZE != 0 {
+            return Err(ValidationError::InvalidPstoreSize(self.size));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct CrashKernelConfig {
+    pub size: u64,
+}
+
+impl CrashKernelConfig {
+    pub const SYNTAX: &'static str = "Memory reserved for the guest kdump kernel \
+    \"size=<region_size>\"";
+    pub fn parse(crashkernel: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("size");
+        parser.parse(crashkernel).map_err(Error::ParseCrashKernel)?;
+
+        let size = parser
+            .convert::<ByteSized>("size")
+            .map_err(Error::ParseCrashKernel)?
+            .ok_or(Error::ParseCrashKernelSizeMissing)?
+            .0;
+
+        Ok(CrashKernelConfig { size })
+    }
+
+    pub fn validate(&self, memory: &MemoryConfig) -> ValidationResult<()> {
+        // The region is reserved by the guest kernel out of the RAM available
+        // at boot time, hotplugged memory can't be used for it.
+        let boot_ram = memory.size
+            + memory
+                .zones
+                .as_ref()
+                .map(|zones| zones.iter().map(|z| z.size).sum())
+                .unwrap_or(0);
+
+        if self.size == 0 || self.size % (1 << 20) != 0 || self.size >= boot_ram {
+            return Err(ValidationError::InvalidCrashKernelSize(self.size));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum ConsoleOutputMode {
+    Off,
+    Pty,
+    Tty,
+    File,
+    Null,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct ConsoleConfig {
+    #[serde(default = "default_consoleconfig_file")]
+    pub file: Option<PathBuf>,
+    pub mode: ConsoleOutputMode,
+    #[serde(default)]
+    pub iommu: bool,
+}
+
+fn default_consoleconfig_file() -> Option<PathBuf> {
+    None
+}
+
+impl ConsoleConfig {
+    pub fn parse(console: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add_valueless("off")
+            .add_valueless("pty")
+            .add_valueless("tty")
+            .add_valueless("null")
+            .add("file")
+            .add("iommu");
+        parser.parse(console).map_err(Error::ParseConsole)?;
+
+        let mut file: Option<PathBuf> = default_consoleconfig_file();
+        let mut mode: ConsoleOutputMode = ConsoleOutputMode::Off;
+
+        if parser.is_set("off") {
+        } else if parser.is_set("pty") {
+            mode = ConsoleOutputMode::Pty
+        } else if parser.is_set("tty") {
+            mode = ConsoleOutputMode::Tty
+        } else if parser.is_set("null") {
+            mode = ConsoleOutputMode::Null
+        } else if parser.is_set("file") {
+            mode = ConsoleOutputMode::File;
+            file =
+                Some(PathBuf::from(parser.get("file").ok_or(
+                    Error::Validation(ValidationError::ConsoleFileMissing),
+                )?));
+        } else {
+            return Err(Error::ParseConsoleInvalidModeGiven);
+        }
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseConsole)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(Self { file, mode, iommu })
+    }
+
+    pub fn default_serial() -> Self {
+        ConsoleConfig {
+            file: None,
+            mode: ConsoleOutputMode::Null,
+            iommu: false,
+        }
+    }
+
+    pub fn default_console() -> Self {
+        ConsoleConfig {
+            file: None,
+            mode: ConsoleOutputMode::Tty,
+            iommu: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct DeviceConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+    #[serde(default)]
+    pub multifunction_of: Option<String>,
+    #[serde(default)]
+    pub function: Option<u8>,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl DeviceConfig {
+    pub const SYNTAX: &'static str =
+        "Direct device assignment parameters \"path=<device_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>,\
+        multifunction_of=<device_id>,function=<function_number>,optional=on|off\" \
+        \n`multifunction_of` and `function` place the device as the given function (1 to 7) of \
+        the PCI slot of another passthrough device";
+    pub fn parse(device: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("path")
+            .add("id")
+            .add("iommu")
+            .add("pci_segment")
+            .add("multifunction_of")
+            .add("function")
+            .add("optional");
+        parser.parse(device).map_err(Error::ParseDevice)?;
+
+        let path = parser
+            .get("path")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseDevicePathMissing)?;
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseDevice)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert::<u16>("pci_segment")
+            .map_err(Error::ParseDevice)?
+            .unwrap_or_default();
+        let multifunction_of = parser.get("multifunction_of");
+        let function = parser
+            .convert::<u8>("function")
+            .map_err(Error::ParseDevice)?;
+
+        let optional = parser
+            .convert::<Toggle>("optional")
+            .map_err(Error::ParseDevice)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(DeviceConfig {
+            path,
+            iommu,
+            id,
+            pci_segment,
+            multifunction_of,
+            function,
+            optional,
+        })
+    }
+
+    fn validate_multifunction(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        let (parent_id, function) = match (&self.multifunction_of, self.function) {
+            (None, None) => return Ok(()),
+            (Some(parent_id), Some(function)) => (parent_id, function),
+            _ => return Err(ValidationError::MultifunctionIncomplete),
+        };
+
+        if !(1..8).contains(&function) {
+            return Err(ValidationError::InvalidPciFunction(function));
+        }
+
+        let devices = vm_config.devices.as_deref().unwrap_or_default();
+        let parent_valid = devices.iter().any(|d| {
+            d.id.as_ref() == Some(parent_id)
+                && d.multifunction_of.is_none()
+                && d.pci_segment == self.pci_segment
+        });
+        if !parent_valid {
+            return Err(ValidationError::InvalidMultifunctionDevice(
+                parent_id.clone(),
+            ));
+        }
+
+        let function_users = devices
+            .iter()
+            .filter(|d| {
+                d.multifunction_of.as_ref() == Some(parent_id) && d.function == Some(function)
+            })
+            .count();
+        if function_users > 1 {
+            return Err(ValidationError::PciFunctionInUse(
+                parent_id.clone(),
+                function,
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        self.validate_multifunction(vm_config)?;
+
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
+
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct UserDeviceConfig {
+    pub socket: PathBuf,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl UserDeviceConfig {
+    pub const SYNTAX: &'static str =
+        "Userspace device socket=<socket_path>,id=<device_id>,pci_segment=<segment_id>,\
+        optional=on|off\"";
+    pub fn parse(user_device: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("socket")
+            .add("id")
+            .add("pci_segment")
+            .add("optional");
+        parser.parse(user_device).map_err(Error::ParseUserDevice)?;
+
+        let socket = parser
+            .get("socket")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseUserDeviceSocketMissing)?;
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert::<u16>("pci_segment")
+            .map_err(Error::ParseUserDevice)?
+            .unwrap_or_default();
+
+        let optional = parser
+            .convert::<Toggle>("optional")
+            .map_err(Error::ParseUserDevice)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(UserDeviceConfig {
+            socket,
+            id,
+            pci_segment,
+            optional,
+        })
+    }
+
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
+
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) {
+                    return Err(ValidationError::IommuNotSupportedOnSegment(
+                        self.pci_segment,
+                    ));
                 }
             }
-        }
-
-        if let Some(zones) = &self.memory.zones {
-            for zone in zones.iter() {
-                let id = zone.id.clone();
-                Self::validate_identifier(&mut id_list, &Some(id))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct VdpaConfig {
+    pub path: PathBuf,
+    #[serde(default = "default_vdpaconfig_num_queues")]
+    pub num_queues: usize,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+fn default_vdpaconfig_num_queues() -> usize {
+    1
+}
+
+impl VdpaConfig {
+    pub const SYNTAX: &'static str = "vDPA device \
+        \"path=<device_path>,num_queues=<number_of_queues>,iommu=on|off,\
+        id=<device_id>,pci_segment=<segment_id>,optional=on|off\"";
+    pub fn parse(vdpa: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("path")
+            .add("num_queues")
+            .add("iommu")
+            .add("id")
+            .add("pci_segment")
+            .add("optional");
+        parser.parse(vdpa).map_err(Error::ParseVdpa)?;
+
+        let path = parser
+            .get("path")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseVdpaPathMissing)?;
+        let num_queues = parser
+            .convert("num_queues")
+            .map_err(Error::ParseVdpa)?
+            .unwrap_or_else(default_vdpaconfig_num_queues);
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseVdpa)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParseVdpa)?
+            .unwrap_or_default();
+
+        let optional = parser
+            .convert::<Toggle>("optional")
+            .map_err(Error::ParseVdpa)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(VdpaConfig {
+            path,
+            num_queues,
+            iommu,
+            id,
+            pci_segment,
+            optional,
+        })
+    }
+
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
+
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum VirtioTransportType {
+    Pci,
+    Mmio,
+}
+
+impl Default for VirtioTransportType {
+    fn default() -> Self {
+        VirtioTransportType::Pci
+    }
+}
+
+pub enum VirtioTransportTypeParseError {
+    InvalidValue(String),
+}
+
+impl FromStr for VirtioTransportType {
+    type Err = VirtioTransportTypeParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pci" => Ok(VirtioTransportType::Pci),
+            "mmio" => Ok(VirtioTransportType::Mmio),
+            _ => Err(VirtioTransportTypeParseError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct VsockConfig {
+    pub cid: u64,
+    pub socket: PathBuf,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+    #[serde(default)]
+    pub transport: VirtioTransportType,
+}
+
+impl VsockConfig {
+    pub const SYNTAX: &'static str = "Virtio VSOCK parameters \
+        \"cid=<context_id>,socket=<socket_path>,iommu=on|off,id=<device_id>,pci_segment=<segment_id>,transport=pci|mmio\"";
+    pub fn parse(vsock: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("socket")
+            .add("cid")
+            .add("iommu")
+            .add("id")
+            .add("pci_segment")
+            .add("transport");
+        parser.parse(vsock).map_err(Error::ParseVsock)?;
+
+        let socket = parser
+            .get("socket")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseVsockSockMissing)?;
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseVsock)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let cid = parser
+            .convert("cid")
+            .map_err(Error::ParseVsock)?
+            .ok_or(Error::ParseVsockCidMissing)?;
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParseVsock)?
+            .unwrap_or_default();
+        let transport = parser
+            .convert("transport")
+            .map_err(Error::ParseVsock)?
+            .unwrap_or_default();
+
+        Ok(VsockConfig {
+            cid,
+            socket,
+            iommu,
+            id,
+            pci_segment,
+            transport,
+        })
+    }
+
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.transport == VirtioTransportType::Mmio {
+            // The virtio-mmio devices are only described through the device
+            // tree.
+            #[cfg(not(target_arch = "aarch64"))]
+            return Err(ValidationError::VirtioMmioUnsupported);
+
+            #[cfg(target_arch = "aarch64")]
+            {
+                if self.iommu {
+                    return Err(ValidationError::VirtioMmioIommu);
+                }
+                if self.pci_segment != 0 {
+                    return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+                }
+                return Ok(());
+            }
+        }
+
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
+
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GpuBackend {
+    /// Frames are copied to the viewers connected to the socket.
+    Socket,
+    /// Frames are shared with the host compositor through a dmabuf.
+    Dmabuf,
+}
+
+impl Default for GpuBackend {
+    fn default() -> Self {
+        GpuBackend::Socket
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseGpuBackendError {
+    InvalidValue(String),
+}
+
+impl fmt::Display for ParseGpuBackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseGpuBackendError::InvalidValue(s) => {
+                write!(f, "Invalid GPU backend: {} (expected socket or dmabuf)", s)
+            }
+        }
+    }
+}
+
+impl FromStr for GpuBackend {
+    type Err = ParseGpuBackendError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "socket" => Ok(GpuBackend::Socket),
+            "dmabuf" => Ok(GpuBackend::Dmabuf),
+            _ => Err(ParseGpuBackendError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+pub const DEFAULT_GPU_WIDTH: u32 = 1280;
+pub const DEFAULT_GPU_HEIGHT: u32 = 800;
+pub const MAX_GPU_RESOLUTION: u32 = 4096;
+
+fn default_gpu_width() -> u32 {
+    DEFAULT_GPU_WIDTH
+}
+
+fn default_gpu_height() -> u32 {
+    DEFAULT_GPU_HEIGHT
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct GpuConfig {
+    /// Socket the viewers of the display connect to.
+    pub socket: PathBuf,
+    #[serde(default)]
+    pub backend: GpuBackend,
+    #[serde(default = "default_gpu_width")]
+    pub width: u32,
+    #[serde(default = "default_gpu_height")]
+    pub height: u32,
+    #[serde(default)]
+    pub iommu: bool,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub pci_segment: u16,
+    #[serde(default)]
+    pub transport: VirtioTransportType,
+}
+
+impl Default for GpuConfig {
+    fn default() -> Self {
+        GpuConfig {
+            socket: PathBuf::new(),
+            backend: GpuBackend::default(),
+            width: DEFAULT_GPU_WIDTH,
+            height: DEFAULT_GPU_HEIGHT,
+            iommu: false,
+            id: None,
+            pci_segment: 0,
+            transport: VirtioTransportType::default(),
+        }
+    }
+}
+
+impl GpuConfig {
+    pub const SYNTAX: &'static str = "Virtio GPU parameters \
+        \"socket=<socket_path>,backend=socket|dmabuf,width=<display_width>,height=<display_height>,\
+        iommu=on|off,id=<device_id>,pci_segment=<segment_id>,transport=pci|mmio\"";
+    pub fn parse(gpu: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("socket")
+            .add("backend")
+            .add("width")
+            .add("height")
+            .add("iommu")
+            .add("id")
+            .add("pci_segment")
+            .add("transport");
+        parser.parse(gpu).map_err(Error::ParseGpu)?;
+
+        let socket = parser
+            .get("socket")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseGpuSocketMissing)?;
+        let backend = parser
+            .convert("backend")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or_default();
+        let width = parser
+            .convert("width")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or(DEFAULT_GPU_WIDTH);
+        let height = parser
+            .convert("height")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or(DEFAULT_GPU_HEIGHT);
+        let iommu = parser
+            .convert::<Toggle>("iommu")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let id = parser.get("id");
+        let pci_segment = parser
+            .convert("pci_segment")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or_default();
+        let transport = parser
+            .convert("transport")
+            .map_err(Error::ParseGpu)?
+            .unwrap_or_default();
+
+        Ok(GpuConfig {
+            socket,
+            backend,
+            width,
+            height,
+            iommu,
+            id,
+            pci_segment,
+            transport,
+        })
+    }
+
+    pub fn validate(&self, vm_config: &VmConfig) -> ValidationResult<()> {
+        if self.width == 0
+            || self.height == 0
+            || self.width > MAX_GPU_RESOLUTION
+            || self.height > MAX_GPU_RESOLUTION
+        {
+            return Err(ValidationError::InvalidGpuResolution(
+                self.width,
+                self.height,
+            ));
+        }
+
+        if self.transport == VirtioTransportType::Mmio {
+            // The virtio-mmio devices are only described through the device
+            // tree.
+            #[cfg(not(target_arch = "aarch64"))]
+            return Err(ValidationError::VirtioMmioUnsupported);
+
+            #[cfg(target_arch = "aarch64")]
+            {
+                if self.iommu {
+                    return Err(ValidationError::VirtioMmioIommu);
+                }
+                if self.pci_segment != 0 {
+                    return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+                }
+                return Ok(());
+            }
+        }
+
+        if let Some(platform_config) = vm_config.platform.as_ref() {
+            if self.pci_segment >= platform_config.num_pci_segments {
+                return Err(ValidationError::InvalidPciSegment(self.pci_segment));
+            }
+
+            if let Some(iommu_segments) = platform_config.iommu_segments.as_ref() {
+                if iommu_segments.contains(&self.pci_segment) && !self.iommu {
+                    return Err(ValidationError::OnIommuSegment(self.pci_segment));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tdx")]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct TdxConfig {
+    pub firmware: PathBuf,
+}
+
+#[cfg(feature = "tdx")]
+impl TdxConfig {
+    pub fn parse(tdx: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("firmware");
+        parser.parse(tdx).map_err(Error::ParseTdx)?;
+        let firmware = parser
+            .get("firmware")
+            .map(PathBuf::from)
+            .ok_or(Error::FirmwarePathMissing)?;
+        Ok(TdxConfig { firmware })
+    }
+}
+
+#[cfg(feature = "sev_snp")]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct SevSnpConfig {
+    pub firmware: PathBuf,
+    #[serde(default = "default_sev_snp_policy")]
+    pub policy: u64,
+}
+
+// Guest policy allowing SMT, without debugging nor migration agent.
+#[cfg(feature = "sev_snp")]
+fn default_sev_snp_policy() -> u64 {
+    1 << 16
+}
+
+#[cfg(feature = "sev_snp")]
+impl SevSnpConfig {
+    pub const SYNTAX: &'static str = "SEV-SNP Support: firmware=<ovmf path>,policy=<guest_policy>";
+
+    pub fn parse(sev_snp: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("firmware").add("policy");
+        parser.parse(sev_snp).map_err(Error::ParseSevSnp)?;
+        let firmware = parser
+            .get("firmware")
+            .map(PathBuf::from)
+            .ok_or(Error::SevSnpFirmwarePathMissing)?;
+        let policy = parser
+            .convert("policy")
+            .map_err(Error::ParseSevSnp)?
+            .unwrap_or_else(default_sev_snp_policy);
+        Ok(SevSnpConfig { firmware, policy })
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct SgxEpcConfig {
+    pub id: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub prefault: bool,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl SgxEpcConfig {
+    pub const SYNTAX: &'static str = "SGX EPC parameters \
+        \"id=<epc_section_identifier>,size=<epc_section_size>,prefault=on|off\"";
+    pub fn parse(sgx_epc: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser.add("id").add("size").add("prefault");
+        parser.parse(sgx_epc).map_err(Error::ParseSgxEpc)?;
+
+        let id = parser.get("id").ok_or(Error::ParseSgxEpcIdMissing)?;
+        let size = parser
+            .convert::<ByteSized>("size")
+            .map_err(Error::ParseSgxEpc)?
+            .unwrap_or(ByteSized(0))
+            .0;
+        let prefault = parser
+            .convert::<Toggle>("prefault")
+            .map_err(Error::ParseSgxEpc)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(SgxEpcConfig { id, size, prefault })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct NumaDistance {
+    #[serde(default)]
+    pub destination: u32,
+    #[serde(default)]
+    pub distance: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct NumaConfig {
+    #[serde(default)]
+    pub guest_numa_id: u32,
+    #[serde(default)]
+    pub cpus: Option<Vec<u8>>,
+    #[serde(default)]
+    pub distances: Option<Vec<NumaDistance>>,
+    #[serde(default)]
+    pub memory_zones: Option<Vec<String>>,
+    #[cfg(target_arch = "x86_64")]
+    #[serde(default)]
+    pub sgx_epc_sections: Option<Vec<String>>,
+    #[serde(default)]
+    pub pmem: Option<Vec<String>>,
+    #[serde(default)]
+    pub memory_hotplug: bool,
+}
+
+impl NumaConfig {
+    pub const SYNTAX: &'static str = "Settings related to a given NUMA node \
+        \"guest_numa_id=<node_id>,cpus=<cpus_id>,distances=<list_of_distances_to_destination_nodes>,\
+        memory_zones=<list_of_memory_zones>,sgx_epc_sections=<list_of_sgx_epc_sections>,\
+        pmem=<list_of_pmem_devices>,memory_hotplug=on|off\"";
+    pub fn parse(numa: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("guest_numa_id")
+            .add("cpus")
+            .add("distances")
+            .add("memory_zones")
+            .add("sgx_epc_sections")
+            .add("pmem")
+            .add("memory_hotplug");
+        parser.parse(numa).map_err(Error::ParseNuma)?;
+
+        let guest_numa_id = parser
+            .convert::<u32>("guest_numa_id")
+            .map_err(Error::ParseNuma)?
+            .unwrap_or(0);
+        let cpus = parser
+            .convert::<IntegerList>("cpus")
+            .map_err(Error::ParseNuma)?
+            .map(|v| v.0.iter().map(|e| *e as u8).collect());
+        let distances = parser
+            .convert::<Tuple<u64, u64>>("distances")
+            .map_err(Error::ParseNuma)?
+            .map(|v| {
+                v.0.iter()
+                    .map(|(e1, e2)| NumaDistance {
+                        destination: *e1 as u32,
+                        distance: *e2 as u8,
+                    })
+                    .collect()
+            });
+        let memory_zones = parser
+            .convert::<StringList>("memory_zones")
+            .map_err(Error::ParseNuma)?
+            .map(|v| v.0);
+        #[cfg(target_arch = "x86_64")]
+        let sgx_epc_sections = parser
+            .convert::<StringList>("sgx_epc_sections")
+            .map_err(Error::ParseNuma)?
+            .map(|v| v.0);
+        let pmem = parser
+            .convert::<StringList>("pmem")
+            .map_err(Error::ParseNuma)?
+            .map(|v| v.0);
+        let memory_hotplug = parser
+            .convert::<Toggle>("memory_hotplug")
+            .map_err(Error::ParseNuma)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(NumaConfig {
+            guest_numa_id,
+            cpus,
+            distances,
+            memory_zones,
+            #[cfg(target_arch = "x86_64")]
+            sgx_epc_sections,
+            pmem,
+            memory_hotplug,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    /// Guest memory is stored as-is.
+    Raw,
+    /// Guest memory is stored as one zstd frame per memory range.
+    Zstd,
+}
+
+impl Default for SnapshotFormat {
+    fn default() -> Self {
+        SnapshotFormat::Raw
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseSnapshotFormatError {
+    InvalidValue(String),
+}
+
+impl fmt::Display for ParseSnapshotFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseSnapshotFormatError::InvalidValue(s) => {
+                write!(f, "Invalid snapshot format: {} (expected raw or zstd)", s)
+            }
+        }
+    }
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = ParseSnapshotFormatError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "raw" => Ok(SnapshotFormat::Raw),
+            "zstd" => Ok(SnapshotFormat::Zstd),
+            _ => Err(ParseSnapshotFormatError::InvalidValue(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, JsonSchema)]
+pub struct RestoreConfig {
+    pub source_url: PathBuf,
+    #[serde(default)]
+    pub prefault: bool,
+    #[serde(default)]
+    pub no_verify: bool,
+    #[serde(default)]
+    pub fresh_devices: Option<Vec<String>>,
+    #[serde(default)]
+    pub on_demand: bool,
+}
+
+impl RestoreConfig {
+    pub const SYNTAX: &'static str = "Restore from a VM snapshot. \
+        \nRestore parameters \"source_url=<source_url>,prefault=on|off,no_verify=on|off,\
+        fresh_devices=<list_of_device_ids>,on_demand=on|off\" \
+        \n`source_url` should be a valid URL (e.g file:///foo/bar or tcp://192.168.1.10/foo) \
+        \n`prefault` brings memory pages in when enabled (disabled by default) \
+        \n`no_verify` skips the verification of the snapshot state checksums and memory \
+        manifest when enabled (disabled by default) \
+        \n`fresh_devices` lists the devices which are not restored from the snapshot but \
+        left in their initial state (e.g. fresh_devices=[_net2,_disk0]) \
+        \n`on_demand` reads the memory pages from the snapshot when first accessed by the \
+        guest rather than before resuming it, which requires `no_verify` (disabled by default)";
+    pub fn parse(restore: &str) -> Result<Self> {
+        let mut parser = OptionParser::new();
+        parser
+            .add("source_url")
+            .add("prefault")
+            .add("no_verify")
+            .add("fresh_devices")
+            .add("on_demand");
+        parser.parse(restore).map_err(Error::ParseRestore)?;
+
+        let source_url = parser
+            .get("source_url")
+            .map(PathBuf::from)
+            .ok_or(Error::ParseRestoreSourceUrlMissing)?;
+        let prefault = parser
+            .convert::<Toggle>("prefault")
+            .map_err(Error::ParseRestore)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let no_verify = parser
+            .convert::<Toggle>("no_verify")
+            .map_err(Error::ParseRestore)?
+            .unwrap_or(Toggle(false))
+            .0;
+        let fresh_devices = parser
+            .convert::<StringList>("fresh_devices")
+            .map_err(Error::ParseRestore)?
+            .map(|v| v.0);
+        let on_demand = parser
+            .convert::<Toggle>("on_demand")
+            .map_err(Error::ParseRestore)?
+            .unwrap_or(Toggle(false))
+            .0;
+
+        Ok(RestoreConfig {
+            source_url,
+            prefault,
+            no_verify,
+            fresh_devices,
+            on_demand,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct VmConfig {
+    #[serde(default)]
+    pub cpus: CpusConfig,
+    #[serde(default)]
+    pub memory: MemoryConfig,
+    pub kernel: Option<KernelConfig>,
+    #[serde(default)]
+    pub initramfs: Option<InitramfsConfig>,
+    #[serde(default)]
+    pub payload_blobs: Option<Vec<PayloadBlobConfig>>,
+    #[serde(default)]
+    pub cmdline: CmdlineConfig,
+    pub disks: Option<Vec<DiskConfig>>,
+    pub net: Option<Vec<NetConfig>>,
+    #[serde(default)]
+    pub rng: RngConfig,
+    pub balloon: Option<BalloonConfig>,
+    pub fs: Option<Vec<FsConfig>>,
+    pub pmem: Option<Vec<PmemConfig>>,
+    #[serde(default = "ConsoleConfig::default_serial")]
+    pub serial: ConsoleConfig,
+    #[serde(default = "ConsoleConfig::default_console")]
+    pub console: ConsoleConfig,
+    pub devices: Option<Vec<DeviceConfig>>,
+    pub user_devices: Option<Vec<UserDeviceConfig>>,
+    pub vdpa: Option<Vec<VdpaConfig>>,
+    pub vsock: Option<VsockConfig>,
+    #[serde(default)]
+    pub iommu: bool,
+    #[cfg(target_arch = "x86_64")]
+    pub sgx_epc: Option<Vec<SgxEpcConfig>>,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub fdt_overlays: Option<Vec<FdtOverlayConfig>>,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    pub fdt: Option<FdtConfig>,
+    pub numa: Option<Vec<NumaConfig>>,
+    #[serde(default)]
+    pub watchdog: bool,
+    #[serde(default)]
+    pub watchdog_config: Option<WatchdogConfig>,
+    #[serde(default)]
+    pub pvpanic: bool,
+    #[serde(default)]
+    pub boot_paused: bool,
+    #[cfg(feature = "tdx")]
+    pub tdx: Option<TdxConfig>,
+    #[cfg(feature = "sev_snp")]
+    #[serde(default)]
+    pub sev_snp: Option<SevSnpConfig>,
+    #[cfg(feature = "gdb")]
+    pub gdb: bool,
+    pub platform: Option<PlatformConfig>,
+    #[serde(default)]
+    pub pstore: Option<PstoreConfig>,
+    #[serde(default)]
+    pub crashkernel: Option<CrashKernelConfig>,
+    #[serde(default)]
+    pub ptp: Option<PtpConfig>,
+    #[serde(default)]
+    pub debug_console: Option<DebugConsoleConfig>,
+    #[serde(default)]
+    pub acpi: Option<AcpiConfig>,
+    #[serde(default)]
+    pub security: Option<SecurityConfig>,
+    #[serde(default)]
+    pub deterministic: Option<DeterministicConfig>,
+    #[serde(default)]
+    pub snapshot_schedule: Option<SnapshotScheduleConfig>,
+    #[serde(default)]
+    pub irqs: Option<IrqConfig>,
+    #[serde(default)]
+    pub doorbells: Option<Vec<DoorbellConfig>>,
+    #[serde(default)]
+    pub partition_services: Option<PartitionServicesConfig>,
+    #[serde(default)]
+    pub priority: Option<PriorityConfig>,
+    #[serde(default)]
+    pub gpu: Option<GpuConfig>,
+    #[serde(default)]
+    pub maintenance: Option<MaintenanceConfig>,
+    #[serde(default)]
+    pub virtio_features: Option<Vec<VirtioFeaturesConfig>>,
+}
+
+impl VmConfig {
+    fn validate_identifier(
+        id_list: &mut BTreeSet<String>,
+        id: &Option<String>,
+    ) -> ValidationResult<()> {
+        if let Some(id) = id.as_ref() {
+            if id.starts_with("__") {
+                return Err(ValidationError::InvalidIdentifier(id.clone()));
+            }
+
+            if !id_list.insert(id.clone()) {
+                return Err(ValidationError::IdentifierNotUnique(id.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Also enables virtio-iommu if the config needs it
+    // Returns the list of unique identifiers provided through the
+    // configuration.
+    pub fn validate(&mut self) -> ValidationResult<BTreeSet<String>> {
+        let mut id_list = BTreeSet::new();
+
+        #[cfg(feature = "tdx")]
+        let tdx_enabled = self.tdx.is_some();
+        #[cfg(not(feature = "tdx"))]
+        let tdx_enabled = false;
+        #[cfg(feature = "sev_snp")]
+        let sev_snp_enabled = self.sev_snp.is_some();
+        #[cfg(not(feature = "sev_snp"))]
+        let sev_snp_enabled = false;
+
+        // The firmware of a confidential VM is loaded in place of the kernel.
+        if !tdx_enabled && !sev_snp_enabled && self.kernel.is_none() {
+            return Err(ValidationError::KernelMissing);
+        }
+
+        #[cfg(feature = "tdx")]
+        if tdx_enabled && (self.cpus.max_vcpus != self.cpus.boot_vcpus) {
+            return Err(ValidationError::TdxNoCpuHotplug);
+        }
+
+        #[cfg(feature = "sev_snp")]
+        if sev_snp_enabled {
+            #[cfg(feature = "tdx")]
+            if tdx_enabled {
+                return Err(ValidationError::SevSnpWithTdx);
+            }
+            if self.cpus.max_vcpus != self.cpus.boot_vcpus {
+                return Err(ValidationError::SevSnpNoCpuHotplug);
+            }
+            if self.memory.hotplug_size.is_some()
+                || self
+                    .memory
+                    .zones
+                    .iter()
+                    .flatten()
+                    .any(|zone| zone.hotplug_size.is_some())
+            {
+                return Err(ValidationError::SevSnpNoMemoryHotplug);
+            }
+        }
+
+        if self.console.mode == ConsoleOutputMode::Tty && self.serial.mode == ConsoleOutputMode::Tty
+        {
+            return Err(ValidationError::DoubleTtyMode);
+        }
+
+        if self.console.mode == ConsoleOutputMode::File && self.console.file.is_none() {
+            return Err(ValidationError::ConsoleFileMissing);
+        }
+
+        if self.serial.mode == ConsoleOutputMode::File && self.serial.file.is_none() {
+            return Err(ValidationError::ConsoleFileMissing);
+        }
+
+        if self.cpus.max_vcpus < self.cpus.boot_vcpus {
+            return Err(ValidationError::CpusMaxLowerThanBoot);
+        }
+
+        if let Some(weight) = self.cpus.weight {
+            if !(1..=10000).contains(&weight) {
+                return Err(ValidationError::InvalidCpuWeight(weight));
+            }
+        }
+
+        if self.cpus.quota == Some(0) {
+            return Err(ValidationError::InvalidCpuQuota);
+        }
+
+        if let Some(disks) = &self.disks {
+            for disk in disks {
+                if disk.vhost_socket.as_ref().and(disk.path.as_ref()).is_some() {
+                    return Err(ValidationError::DiskSocketAndPath);
+                }
+                if disk.vhost_user && !self.memory.shared {
+                    return Err(ValidationError::VhostUserRequiresSharedMemory);
+                }
+                if disk.vhost_user && disk.vhost_socket.is_none() {
+                    return Err(ValidationError::VhostUserMissingSocket);
+                }
+                disk.validate(self)?;
+                self.iommu |= disk.iommu;
+
+                Self::validate_identifier(&mut id_list, &disk.id)?;
+            }
+        }
+
+        if let Some(nets) = &self.net {
+            for net in nets {
+                if net.vhost_user && !self.memory.shared {
+                    return Err(ValidationError::VhostUserRequiresSharedMemory);
+                }
+                net.validate(self)?;
+                self.iommu |= net.iommu;
+
+                Self::validate_identifier(&mut id_list, &net.id)?;
+            }
+        }
+
+        if let Some(fses) = &self.fs {
+            if !fses.is_empty() && !self.memory.shared {
+                return Err(ValidationError::VhostUserRequiresSharedMemory);
+            }
+            for fs in fses {
+                fs.validate(self)?;
+
+                Self::validate_identifier(&mut id_list, &fs.id)?;
+            }
+        }
+
+        if let Some(pmems) = &self.pmem {
+            for pmem in pmems {
+                pmem.validate(self)?;
+                self.iommu |= pmem.iommu;
+
+                Self::validate_identifier(&mut id_list, &pmem.id)?;
+            }
+        }
+
+        self.iommu |= self.rng.iommu;
+        self.iommu |= self.console.iommu;
+        if let Some(ptp) = &self.ptp {
+            self.iommu |= ptp.iommu;
+        }
+
+        if let Some(debug_console) = &self.debug_console {
+            debug_console.validate()?;
+        }
+
+        if let Some(watchdog_config) = &self.watchdog_config {
+            if !self.watchdog {
+                return Err(ValidationError::WatchdogConfigWithoutWatchdog);
+            }
+            watchdog_config.validate()?;
+        }
+
+        if let Some(acpi) = &self.acpi {
+            acpi.validate()?;
+        }
+
+        if let Some(t) = &self.cpus.topology {
+            if t.threads_per_core == 0
+                || t.cores_per_die == 0
+                || t.dies_per_package == 0
+                || t.packages == 0
+            {
+                return Err(ValidationError::CpuTopologyZeroPart);
+            }
+
+            let total = t.threads_per_core * t.cores_per_die * t.dies_per_package * t.packages;
+            if total != self.cpus.max_vcpus {
+                return Err(ValidationError::CpuTopologyCount);
+            }
+        }
+
+        if let Some(hugepage_size) = &self.memory.hugepage_size {
+            if !self.memory.hugepages {
+                return Err(ValidationError::HugePageSizeWithoutHugePages);
+            }
+            if !hugepage_size.is_power_of_two() {
+                return Err(ValidationError::InvalidHugePageSize(*hugepage_size));
+            }
+        }
+
+        if self.memory.hugepage_pool_limit.is_some() && !self.memory.hugepage_pool {
+            return Err(ValidationError::HugepagePoolLimitWithoutHugepagePool);
+        }
+
+        // The memory zones replace the memory options describing the RAM.
+        if self.memory.sealed && self.memory.zones.is_some() {
+            return Err(ValidationError::MemorySealedWithZones);
+        }
+        if self.memory.sealed && !self.memory.shared {
+            return Err(ValidationError::MemorySealedNotShared);
+        }
+
+        // The virtio-mmio devices are only described through the device tree.
+        #[cfg(not(target_arch = "aarch64"))]
+        if self.memory.virtio_mem_transport == VirtioTransportType::Mmio {
+            return Err(ValidationError::VirtioMmioUnsupported);
+        }
+        if self.memory.virtio_mem_transport == VirtioTransportType::Mmio
+            && self.memory.hotplug_method != HotplugMethod::VirtioMem
+        {
+            return Err(ValidationError::VirtioMemTransportWithoutVirtioMem);
+        }
+
+        if self.memory.hugepage_pool
+            && !self.memory.hugepages
+            && !self
+                .memory
+                .zones
+                .iter()
+                .flatten()
+                .any(|zone| zone.hugepages)
+        {
+            return Err(ValidationError::HugepagePoolWithoutHugePages);
+        }
+
+        if let Some(user_devices) = &self.user_devices {
+            if !user_devices.is_empty() && !self.memory.shared {
+                return Err(ValidationError::UserDevicesRequireSharedMemory);
+            }
+
+            for user_device in user_devices {
+                user_device.validate(self)?;
+
+                Self::validate_identifier(&mut id_list, &user_device.id)?;
+            }
+        }
+
+        if let Some(vdpa_devices) = &self.vdpa {
+            for vdpa_device in vdpa_devices {
+                vdpa_device.validate(self)?;
+                self.iommu |= vdpa_device.iommu;
+
+                Self::validate_identifier(&mut id_list, &vdpa_device.id)?;
+            }
+        }
+
+        if let Some(balloon) = &self.balloon {
+            let mut ram_size = self.memory.size;
+
+            if let Some(zones) = &self.memory.zones {
+                for zone in zones {
+                    ram_size += zone.size;
+                }
+            }
+
+            if balloon.size >= ram_size {
+                return Err(ValidationError::BalloonLargerThanRam(
+                    balloon.size,
+                    ram_size,
+                ));
+            }
+        }
+
+        if let Some(devices) = &self.devices {
+            for device in devices {
+                device.validate(self)?;
+                self.iommu |= device.iommu;
+
+                Self::validate_identifier(&mut id_list, &device.id)?;
+            }
+        }
+
+        if let Some(vsock) = &self.vsock {
+            vsock.validate(self)?;
+            self.iommu |= vsock.iommu;
+
+            Self::validate_identifier(&mut id_list, &vsock.id)?;
+        }
+
+        if let Some(gpu) = &self.gpu {
+            gpu.validate(self)?;
+            self.iommu |= gpu.iommu;
+
+            Self::validate_identifier(&mut id_list, &gpu.id)?;
+        }
+
+        if let Some(numa) = &self.numa {
+            let mut used_numa_node_memory_zones = HashMap::new();
+            let mut used_numa_node_pmems = HashMap::new();
+            let mut memory_hotplug_numa_node = None;
+            for numa_node in numa.iter() {
+                for memory_zone in numa_node.memory_zones.iter().flatten() {
+                    if !used_numa_node_memory_zones.contains_key(memory_zone) {
+                        used_numa_node_memory_zones
+                            .insert(memory_zone.to_string(), numa_node.guest_numa_id);
+                    } else {
+                        return Err(ValidationError::MemoryZoneReused(
+                            memory_zone.to_string(),
+                            *used_numa_node_memory_zones.get(memory_zone).unwrap(),
+                            numa_node.guest_numa_id,
+                        ));
+                    }
+                }
+
+                for pmem in numa_node.pmem.iter().flatten() {
+                    if !self
+                        .pmem
+                        .iter()
+                        .flatten()
+                        .any(|pmem_cfg| pmem_cfg.id.as_ref() == Some(pmem))
+                    {
+                        return Err(ValidationError::InvalidNumaPmem(pmem.to_string()));
+                    }
+
+                    if let Some(numa_id) =
+                        used_numa_node_pmems.insert(pmem.to_string(), numa_node.guest_numa_id)
+                    {
+                        return Err(ValidationError::PmemReused(
+                            pmem.to_string(),
+                            numa_id,
+                            numa_node.guest_numa_id,
+                        ));
+                    }
+                }
+
+                if numa_node.memory_hotplug {
+                    if self.memory.hotplug_method != HotplugMethod::Acpi
+                        || self.memory.hotplug_size.is_none()
+                    {
+                        return Err(ValidationError::NumaMemoryHotplugDisabled);
+                    }
+
+                    if let Some(numa_id) = memory_hotplug_numa_node.replace(numa_node.guest_numa_id)
+                    {
+                        return Err(ValidationError::NumaMemoryHotplugReused(
+                            numa_id,
+                            numa_node.guest_numa_id,
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(zones) = &self.memory.zones {
+            for zone in zones.iter() {
+                if zone.template {
+                    if zone.file.as_ref().map_or(true, |file| file.is_dir()) {
+                        return Err(ValidationError::MemoryZoneTemplateFileMissing(
+                            zone.id.clone(),
+                        ));
+                    }
+                    if zone.shared || zone.hugepages || zone.hotplug_size.is_some() {
+                        return Err(ValidationError::MemoryZoneTemplateIncompatible(
+                            zone.id.clone(),
+                        ));
+                    }
+                }
+                // Only the memory created by the VMM can be sealed, and the
+                // seals only matter to the memory shared by file descriptor.
+                if zone.sealed && zone.file.is_some() {
+                    return Err(ValidationError::MemoryZoneSealedWithFile(zone.id.clone()));
+                }
+                if zone.sealed && !zone.shared {
+                    return Err(ValidationError::MemoryZoneSealedNotShared(zone.id.clone()));
+                }
+
+                let id = zone.id.clone();
+                Self::validate_identifier(&mut id_list, &Some(id))?;
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(sgx_epcs) = &self.sgx_epc {
+            for sgx_epc in sgx_epcs.iter() {
+                let id = sgx_epc.id.clone();
+                Self::validate_identifier(&mut id_list, &Some(id))?;
+            }
+        }
+
+        if let Some(payload_blobs) = &self.payload_blobs {
+            if payload_blobs.len() > MAX_PAYLOAD_BLOBS {
+                return Err(ValidationError::TooManyPayloadBlobs(payload_blobs.len()));
+            }
+            for payload_blob in payload_blobs.iter() {
+                payload_blob.validate()?;
+
+                Self::validate_identifier(&mut id_list, &payload_blob.id)?;
+            }
+        }
+
+        if let Some(doorbells) = &self.doorbells {
+            for doorbell in doorbells.iter() {
+                doorbell.validate()?;
+
+                Self::validate_identifier(&mut id_list, &doorbell.id)?;
+            }
+        }
+
+        self.platform.as_ref().map(|p| p.validate()).transpose()?;
+        self.pstore.as_ref().map(|p| p.validate()).transpose()?;
+        self.crashkernel
+            .as_ref()
+            .map(|c| c.validate(&self.memory))
+            .transpose()?;
+        self.iommu |= self
+            .platform
+            .as_ref()
+            .map(|p| p.iommu_segments.is_some())
+            .unwrap_or_default();
+
+        if self.security.as_ref().map_or(false, |s| s.require_iommu) {
+            self.validate_iommu_required()?;
+        }
+
+        if self.deterministic.is_some() {
+            self.validate_deterministic()?;
+        }
+
+        if let Some(snapshot_schedule) = &self.snapshot_schedule {
+            snapshot_schedule.validate()?;
+        }
+
+        if let Some(maintenance) = &self.maintenance {
+            maintenance.validate()?;
+        }
+
+        let mut virtio_features_ids = BTreeSet::new();
+        for virtio_features in self.virtio_features.iter().flatten() {
+            virtio_features.validate()?;
+            if !virtio_features_ids.insert(&virtio_features.id) {
+                return Err(ValidationError::DuplicateVirtioFeatures(
+                    virtio_features.id.clone(),
+                ));
+            }
+            // The identifiers starting with an underscore are given by the
+            // VMM when the devices are created.
+            if !virtio_features.id.starts_with('_') && !id_list.contains(&virtio_features.id) {
+                return Err(ValidationError::UnknownVirtioFeaturesDevice(
+                    virtio_features.id.clone(),
+                ));
+            }
+        }
+
+        if let Some(irqs) = &self.irqs {
+            irqs.validate()?;
+            self.validate_irq_pins(irqs, &id_list)?;
+        }
+
+        Ok(id_list)
+    }
+
+    // Checks the IRQs are pinned to devices given a legacy IRQ by the VMM.
+    // The identifiers starting with an underscore are given by the VMM when
+    // the devices are created, which checks them then.
+    fn validate_irq_pins(
+        &self,
+        irqs: &IrqConfig,
+        id_list: &BTreeSet<String>,
+    ) -> ValidationResult<()> {
+        let mut pinnable_ids = BTreeSet::new();
+        let virtio_devices = self
+            .net
+            .iter()
+            .flatten()
+            .map(|n| (&n.id, n.transport))
+            .chain(self.fs.iter().flatten().map(|f| (&f.id, f.transport)))
+            .chain(self.pmem.iter().flatten().map(|p| (&p.id, p.transport)))
+            .chain(self.vsock.iter().map(|v| (&v.id, v.transport)))
+            .chain(self.gpu.iter().map(|g| (&g.id, g.transport)));
+        for (id, transport) in virtio_devices {
+            if let (Some(id), VirtioTransportType::Mmio) = (id, transport) {
+                pinnable_ids.insert(id);
+            }
+        }
+        if self.memory.virtio_mem_transport == VirtioTransportType::Mmio {
+            for zone in self.memory.zones.iter().flatten() {
+                if zone.hotplug_size.is_some() {
+                    pinnable_ids.insert(&zone.id);
+                }
+            }
+        }
+        pinnable_ids.extend(
+            self.doorbells
+                .iter()
+                .flatten()
+                .filter_map(|d| d.id.as_ref()),
+        );
+
+        for pin in irqs.pins.iter() {
+            #[cfg(target_arch = "x86_64")]
+            if pin.id == "__serial" {
+                return Err(ValidationError::IrqPinSerial);
+            }
+            if pin.id.starts_with('_') || pinnable_ids.contains(&pin.id) {
+                continue;
+            }
+            if id_list.contains(&pin.id) {
+                return Err(ValidationError::IrqPinUnsupportedDevice(pin.id.clone()));
+            }
+            return Err(ValidationError::UnknownIrqPinDevice(pin.id.clone()));
+        }
+
+        Ok(())
+    }
+
+    // Checks the VMM can make the execution reproducible: the TSC frequency
+    // must not depend on the host, and the requests of each device must be
+    // processed in order by a single queue.
+    fn validate_deterministic(&self) -> ValidationResult<()> {
+        #[cfg(target_arch = "x86_64")]
+        if self.cpus.tsc_khz.is_none() {
+            return Err(ValidationError::DeterministicTscKhzMissing);
+        }
+
+        fn device_name(kind: &str, id: &Option<String>) -> String {
+            match id {
+                Some(id) => format!("{} ({})", kind, id),
+                None => kind.to_owned(),
+            }
+        }
+
+        if let Some(disks) = &self.disks {
+            if let Some(disk) = disks.iter().find(|d| d.num_queues > 1) {
+                return Err(ValidationError::DeterministicMultiQueue(device_name(
+                    "disk", &disk.id,
+                )));
+            }
+        }
+
+        if let Some(nets) = &self.net {
+            if let Some(net) = nets.iter().find(|n| n.num_queues > 2) {
+                return Err(ValidationError::DeterministicMultiQueue(device_name(
+                    "net", &net.id,
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Checks every device which can be placed behind the virtual IOMMU is,
+    // and that no device without IOMMU support is used.
+    fn validate_iommu_required(&self) -> ValidationResult<()> {
+        fn device_name(kind: &str, id: &Option<String>) -> String {
+            match id {
+                Some(id) => format!("{} ({})", kind, id),
+                None => kind.to_owned(),
+            }
+        }
+
+        for disk in self.disks.iter().flatten() {
+            if !disk.iommu {
+                return Err(ValidationError::IommuRequired(device_name(
+                    "disk", &disk.id,
+                )));
+            }
+        }
+        for net in self.net.iter().flatten() {
+            if !net.iommu {
+                return Err(ValidationError::IommuRequired(device_name("net", &net.id)));
+            }
+        }
+        for pmem in self.pmem.iter().flatten() {
+            if !pmem.iommu {
+                return Err(ValidationError::IommuRequired(device_name(
+                    "pmem", &pmem.id,
+                )));
+            }
+        }
+        for vdpa in self.vdpa.iter().flatten() {
+            if !vdpa.iommu {
+                return Err(ValidationError::IommuRequired(device_name(
+                    "vdpa", &vdpa.id,
+                )));
+            }
+        }
+        for device in self.devices.iter().flatten() {
+            if !device.iommu {
+                return Err(ValidationError::IommuRequired(device_name(
+                    "device", &device.id,
+                )));
+            }
+        }
+        if let Some(vsock) = &self.vsock {
+            if !vsock.iommu {
+                return Err(ValidationError::IommuRequired(device_name(
+                    "vsock", &vsock.id,
+                )));
+            }
+        }
+        if !self.rng.iommu {
+            return Err(ValidationError::IommuRequired("rng".to_owned()));
+        }
+        if self.console.mode != ConsoleOutputMode::Off && !self.console.iommu {
+            return Err(ValidationError::IommuRequired("console".to_owned()));
+        }
+        if let Some(ptp) = &self.ptp {
+            if !ptp.iommu {
+                return Err(ValidationError::IommuRequired("ptp".to_owned()));
+            }
+        }
+        if let Some(gpu) = &self.gpu {
+            if !gpu.iommu {
+                return Err(ValidationError::IommuRequired(device_name("gpu", &gpu.id)));
+            }
+        }
+        // Neither virtio-fs nor vfio-user devices can be attached to the
+        // virtual IOMMU.
+        if let Some(fs) = self.fs.as_ref().and_then(|fs| fs.first()) {
+            return Err(ValidationError::IommuRequired(device_name("fs", &fs.id)));
+        }
+        if let Some(user_device) = self.user_devices.as_ref().and_then(|d| d.first()) {
+            return Err(ValidationError::IommuRequired(device_name(
+                "user device",
+                &user_device.id,
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn parse(vm_params: VmParams) -> Result<Self> {
+        let mut disks: Option<Vec<DiskConfig>> = None;
+        if let Some(disk_list) = &vm_params.disks {
+            let mut disk_config_list = Vec::new();
+            for item in disk_list.iter() {
+                let disk_config = DiskConfig::parse(item)?;
+                disk_config_list.push(disk_config);
+            }
+            disks = Some(disk_config_list);
+        }
+
+        let mut net: Option<Vec<NetConfig>> = None;
+        if let Some(net_list) = &vm_params.net {
+            let mut net_config_list = Vec::new();
+            for item in net_list.iter() {
+                let net_config = NetConfig::parse(item)?;
+                net_config_list.push(net_config);
+            }
+            net = Some(net_config_list);
+        }
+
+        let rng = RngConfig::parse(vm_params.rng)?;
+
+        let mut balloon: Option<BalloonConfig> = None;
+        if let Some(balloon_params) = &vm_params.balloon {
+            balloon = Some(BalloonConfig::parse(balloon_params)?);
+        }
+
+        let mut fs: Option<Vec<FsConfig>> = None;
+        if let Some(fs_list) = &vm_params.fs {
+            let mut fs_config_list = Vec::new();
+            for item in fs_list.iter() {
+                fs_config_list.push(FsConfig::parse(item)?);
+            }
+            fs = Some(fs_config_list);
+        }
+
+        let mut pmem: Option<Vec<PmemConfig>> = None;
+        if let Some(pmem_list) = &vm_params.pmem {
+            let mut pmem_config_list = Vec::new();
+            for item in pmem_list.iter() {
+                let pmem_config = PmemConfig::parse(item)?;
+                pmem_config_list.push(pmem_config);
+            }
+            pmem = Some(pmem_config_list);
+        }
+
+        let console = ConsoleConfig::parse(vm_params.console)?;
+        let serial = ConsoleConfig::parse(vm_params.serial)?;
+
+        let mut devices: Option<Vec<DeviceConfig>> = None;
+        if let Some(device_list) = &vm_params.devices {
+            let mut device_config_list = Vec::new();
+            for item in device_list.iter() {
+                let device_config = DeviceConfig::parse(item)?;
+                device_config_list.push(device_config);
+            }
+            devices = Some(device_config_list);
+        }
+
+        let mut user_devices: Option<Vec<UserDeviceConfig>> = None;
+        if let Some(user_device_list) = &vm_params.user_devices {
+            let mut user_device_config_list = Vec::new();
+            for item in user_device_list.iter() {
+                let user_device_config = UserDeviceConfig::parse(item)?;
+                user_device_config_list.push(user_device_config);
+            }
+            user_devices = Some(user_device_config_list);
+        }
+
+        let mut vdpa: Option<Vec<VdpaConfig>> = None;
+        if let Some(vdpa_list) = &vm_params.vdpa {
+            let mut vdpa_config_list = Vec::new();
+            for item in vdpa_list.iter() {
+                let vdpa_config = VdpaConfig::parse(item)?;
+                vdpa_config_list.push(vdpa_config);
+            }
+            vdpa = Some(vdpa_config_list);
+        }
+
+        let mut vsock: Option<VsockConfig> = None;
+        if let Some(vs) = &vm_params.vsock {
+            let vsock_config = VsockConfig::parse(vs)?;
+            vsock = Some(vsock_config);
+        }
+
+        let platform = vm_params.platform.map(PlatformConfig::parse).transpose()?;
+        let pstore = vm_params.pstore.map(PstoreConfig::parse).transpose()?;
+        let crashkernel = vm_params
+            .crashkernel
+            .map(CrashKernelConfig::parse)
+            .transpose()?;
+        let ptp = vm_params.ptp.map(PtpConfig::parse).transpose()?;
+        let debug_console = vm_params
+            .debug_console
+            .map(DebugConsoleConfig::parse)
+            .transpose()?;
+        let acpi = vm_params.acpi.map(AcpiConfig::parse).transpose()?;
+        let security = vm_params.security.map(SecurityConfig::parse).transpose()?;
+        let deterministic = vm_params
+            .deterministic
+            .map(DeterministicConfig::parse)
+            .transpose()?;
+        let snapshot_schedule = vm_params
+            .snapshot_schedule
+            .map(SnapshotScheduleConfig::parse)
+            .transpose()?;
+        let irqs = vm_params.irqs.map(IrqConfig::parse).transpose()?;
+        let partition_services = vm_params
+            .partition_services
+            .map(PartitionServicesConfig::parse)
+            .transpose()?;
+        let priority = vm_params.priority.map(PriorityConfig::parse).transpose()?;
+        let gpu = vm_params.gpu.map(GpuConfig::parse).transpose()?;
+        let maintenance = vm_params
+            .maintenance
+            .map(MaintenanceConfig::parse)
+            .transpose()?;
+
+        let mut virtio_features: Option<Vec<VirtioFeaturesConfig>> = None;
+        if let Some(virtio_features_list) = &vm_params.virtio_features {
+            let mut virtio_features_config_list = Vec::new();
+            for item in virtio_features_list.iter() {
+                let virtio_features_config = VirtioFeaturesConfig::parse(item)?;
+                virtio_features_config_list.push(virtio_features_config);
+            }
+            virtio_features = Some(virtio_features_config_list);
+        }
+
+        let mut doorbells: Option<Vec<DoorbellConfig>> = None;
+        if let Some(doorbell_list) = &vm_params.doorbells {
+            let mut doorbell_config_list = Vec::new();
+            for item in doorbell_list.iter() {
+                let doorbell_config = DoorbellConfig::parse(item)?;
+                doorbell_config_list.push(doorbell_config);
+            }
+            doorbells = Some(doorbell_config_list);
+        }
+        let watchdog_config = vm_params
+            .watchdog_config
+            .map(WatchdogConfig::parse)
+            .transpose()?;
+
+        #[cfg(target_arch = "x86_64")]
+        let mut sgx_epc: Option<Vec<SgxEpcConfig>> = None;
+        #[cfg(target_arch = "x86_64")]
+        {
+            if let Some(sgx_epc_list) = &vm_params.sgx_epc {
+                let mut sgx_epc_config_list = Vec::new();
+                for item in sgx_epc_list.iter() {
+                    let sgx_epc_config = SgxEpcConfig::parse(item)?;
+                    sgx_epc_config_list.push(sgx_epc_config);
+                }
+                sgx_epc = Some(sgx_epc_config_list);
+            }
+        }
+
+        let mut numa: Option<Vec<NumaConfig>> = None;
+        if let Some(numa_list) = &vm_params.numa {
+            let mut numa_config_list = Vec::new();
+            for item in numa_list.iter() {
+                let numa_config = NumaConfig::parse(item)?;
+                numa_config_list.push(numa_config);
+            }
+            numa = Some(numa_config_list);
+        }
+
+        let mut kernel: Option<KernelConfig> = None;
+        if let Some(k) = vm_params.kernel {
+            kernel = Some(KernelConfig {
+                path: PathBuf::from(k),
+            });
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        let fdt_overlays = vm_params.fdt_overlays.as_ref().map(|overlays| {
+            overlays
+                .iter()
+                .map(|path| FdtOverlayConfig {
+                    path: PathBuf::from(path),
+                })
+                .collect()
+        });
+        #[cfg(target_arch = "aarch64")]
+        let fdt = vm_params.fdt.map(FdtConfig::parse).transpose()?;
+
+        let initramfs = vm_params
+            .initramfs
+            .as_ref()
+            .and_then(|paths| paths.split_first())
+            .map(|(path, extra_paths)| InitramfsConfig {
+                path: PathBuf::from(path),
+                extra_paths: extra_paths.iter().map(PathBuf::from).collect(),
+            });
+
+        let mut payload_blobs: Option<Vec<PayloadBlobConfig>> = None;
+        if let Some(payload_blob_list) = &vm_params.payload_blobs {
+            let mut payload_blob_config_list = Vec::new();
+            for item in payload_blob_list.iter() {
+                let payload_blob_config = PayloadBlobConfig::parse(item)?;
+                payload_blob_config_list.push(payload_blob_config);
+            }
+            payload_blobs = Some(payload_blob_config_list);
+        }
+
+        #[cfg(feature = "tdx")]
+        let tdx = vm_params.tdx.map(TdxConfig::parse).transpose()?;
+
+        #[cfg(feature = "sev_snp")]
+        let sev_snp = vm_params.sev_snp.map(SevSnpConfig::parse).transpose()?;
+
+        #[cfg(feature = "gdb")]
+        let gdb = vm_params.gdb;
+
+        let mut config = VmConfig {
+            cpus: CpusConfig::parse(vm_params.cpus)?,
+            memory: MemoryConfig::parse(vm_params.memory, vm_params.memory_zones)?,
+            kernel,
+            initramfs,
+            payload_blobs,
+            cmdline: CmdlineConfig::parse(vm_params.cmdline)?,
+            disks,
+            net,
+            rng,
+            balloon,
+            fs,
+            pmem,
+            serial,
+            console,
+            devices,
+            user_devices,
+            vdpa,
+            vsock,
+            iommu: false, // updated in VmConfig::validate()
+            #[cfg(target_arch = "x86_64")]
+            sgx_epc,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays,
+            #[cfg(target_arch = "aarch64")]
+            fdt,
+            numa,
+            watchdog: vm_params.watchdog,
+            watchdog_config,
+            pvpanic: vm_params.pvpanic,
+            boot_paused: vm_params.boot_paused,
+            #[cfg(feature = "tdx")]
+            tdx,
+            #[cfg(feature = "sev_snp")]
+            sev_snp,
+            #[cfg(feature = "gdb")]
+            gdb,
+            platform,
+            pstore,
+            crashkernel,
+            ptp,
+            debug_console,
+            acpi,
+            security,
+            deterministic,
+            snapshot_schedule,
+            irqs,
+            doorbells,
+            partition_services,
+            priority,
+            gpu,
+            maintenance,
+            virtio_features,
+        };
+        config.validate().map_err(Error::Validation)?;
+        Ok(config)
+    }
+
+    /// Host tuning profile of the priority class, if set.
+    pub fn priority_profile(&self) -> Option<PriorityProfile> {
+        self.priority
+            .as_ref()
+            .map(|priority| PriorityProfile::from(priority.class))
+    }
+
+    /// Memory configuration the guest RAM is created from, with the
+    /// hugepages and prefaulting of the priority class applied.
+    pub fn tuned_memory_config(&self) -> MemoryConfig {
+        let mut memory = self.memory.clone();
+        if let Some(mut profile) = self.priority_profile() {
+            // Unless the hugepage pool is grown for the VM, the guest RAM is
+            // only backed by hugepages if the host has enough of them free,
+            // and by regular pages otherwise.
+            if profile.hugepages && !memory.hugepage_pool {
+                let needed = profile.hugepages_needed(&memory);
+                let free = hugepages::free_default_hugepages().unwrap_or(0);
+                if free < needed {
+                    warn!(
+                        "Only {} bytes of free hugepages for the {} bytes of guest RAM, \
+                        backing it with regular pages",
+                        free, needed
+                    );
+                    profile.hugepages = false;
+                }
+            }
+            profile.apply_to_memory(&mut memory);
+        }
+        memory
+    }
+
+    /// Model of the watchdog device, if enabled.
+    pub fn watchdog_model(&self) -> Option<WatchdogModel> {
+        if !self.watchdog {
+            return None;
+        }
+        Some(
+            self.watchdog_config
+                .as_ref()
+                .map(|watchdog_config| watchdog_config.model)
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Rate limiting of the disk or network device, if there is such a
+    /// device rate limited by the VMM, which excludes the vhost-user ones.
+    pub fn rate_limiter_config_mut(&mut self, id: &str) -> Option<&mut Option<RateLimiterConfig>> {
+        if let Some(disk) = self
+            .disks
+            .iter_mut()
+            .flatten()
+            .find(|disk| disk.id.as_deref() == Some(id))
+        {
+            return if disk.vhost_user {
+                None
+            } else {
+                Some(&mut disk.rate_limiter_config)
+            };
+        }
+
+        self.net
+            .iter_mut()
+            .flatten()
+            .find(|net| net.id.as_deref() == Some(id))
+            .filter(|net| !net.vhost_user)
+            .map(|net| &mut net.rate_limiter_config)
+    }
+
+    /// Interrupt coalescing of the disk or network device `id`, if it isn't a
+    /// vhost-user one.
+    pub fn interrupt_coalescing_mut(
+        &mut self,
+        id: &str,
+    ) -> Option<&mut Option<InterruptCoalescingConfig>> {
+        if let Some(disk) = self
+            .disks
+            .iter_mut()
+            .flatten()
+            .find(|disk| disk.id.as_deref() == Some(id))
+        {
+            return if disk.vhost_user {
+                None
+            } else {
+                Some(&mut disk.interrupt_coalescing)
+            };
+        }
+
+        self.net
+            .iter_mut()
+            .flatten()
+            .find(|net| net.id.as_deref() == Some(id))
+            .filter(|net| !net.vhost_user)
+            .map(|net| &mut net.interrupt_coalescing)
+    }
+
+    /// Size of the queues of the disk or network device `id`. The size of
+    /// the queues of the vhost-user devices is bounded by their backend, and
+    /// can't be changed.
+    pub fn queue_size_mut(&mut self, id: &str) -> Option<&mut u16> {
+        if let Some(disk) = self
+            .disks
+            .iter_mut()
+            .flatten()
+            .find(|disk| disk.id.as_deref() == Some(id))
+        {
+            if disk.vhost_user {
+                return None;
+            }
+            return Some(&mut disk.queue_size);
+        }
+
+        self.net
+            .iter_mut()
+            .flatten()
+            .find(|net| net.id.as_deref() == Some(id))
+            .filter(|net| !net.vhost_user)
+            .map(|net| &mut net.queue_size)
+    }
+}
+
+/// JSON Schema describing the `VmConfig` accepted by this build, including
+/// the fields of the features and architecture it was built for.
+pub fn vm_config_schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(VmConfig)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_option_parser() {
+        let mut parser = OptionParser::new();
+        parser
+            .add("size")
+            .add("mergeable")
+            .add("hotplug_method")
+            .add("hotplug_size");
+
+        assert!(parser.parse("size=128M,hanging_param").is_err());
+        assert!(parser.parse("size=128M,too_many_equals=foo=bar").is_err());
+        assert!(parser.parse("size=128M,file=/dev/shm").is_err());
+        assert!(parser.parse("size=128M").is_ok());
+
+        assert_eq!(parser.get("size"), Some("128M".to_owned()));
+        assert!(!parser.is_set("mergeable"));
+        assert!(parser.is_set("size"));
+    }
+
+    #[test]
+    fn test_cpu_parsing() -> Result<()> {
+        assert_eq!(CpusConfig::parse("")?, CpusConfig::default());
+
+        assert_eq!(
+            CpusConfig::parse("boot=1")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=1,max=2")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=8,topology=2:2:1:2")?,
+            CpusConfig {
+                boot_vcpus: 8,
+                max_vcpus: 8,
+                topology: Some(CpuTopology {
+                    threads_per_core: 2,
+                    cores_per_die: 2,
+                    dies_per_package: 1,
+                    packages: 2
+                }),
+                ..Default::default()
+            }
+        );
+
+        assert!(CpusConfig::parse("boot=8,topology=2:2:1").is_err());
+        assert!(CpusConfig::parse("boot=8,topology=2:2:1:x").is_err());
+        assert_eq!(
+            CpusConfig::parse("boot=1,kvm_hyperv=on")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                kvm_hyperv: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=1,nested=on")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                nested: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            CpusConfig::parse("boot=2,weight=50,quota=150")?,
+            CpusConfig {
+                boot_vcpus: 2,
+                max_vcpus: 2,
+                weight: Some(50),
+                quota: Some(150),
+                ..Default::default()
+            }
+        );
+        assert!(CpusConfig::parse("weight=x").is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("boot=1,tsc_khz=2000000")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                tsc_khz: Some(2_000_000),
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            CpusConfig::parse("boot=1,baseline=cascadelake-server")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                baseline: Some(CpuModel::CascadelakeServer),
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            CpusConfig::parse("boot=1,baseline=neoverse-n1")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                baseline: Some(CpuModel::NeoverseN1),
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            CpusConfig::parse("boot=1,pmu=off")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                pmu: false,
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert!(
+            serde_json::from_str::<CpusConfig>(r#"{"boot_vcpus": 1, "max_vcpus": 1}"#)
+                .unwrap()
+                .pmu
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            CpusConfig::parse("boot=1,freeze_counter=off")?,
+            CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
+                freeze_counter: false,
+                ..Default::default()
+            }
+        );
+        #[cfg(target_arch = "aarch64")]
+        assert!(
+            serde_json::from_str::<CpusConfig>(r#"{"boot_vcpus": 1, "max_vcpus": 1}"#)
+                .unwrap()
+                .freeze_counter
+        );
+        assert!(CpusConfig::parse("baseline=pentium").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mem_parsing() -> Result<()> {
+        assert_eq!(MemoryConfig::parse("", None)?, MemoryConfig::default());
+        // Default string
+        assert_eq!(
+            MemoryConfig::parse("size=512M", None)?,
+            MemoryConfig::default()
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=512M,mergeable=on", None)?,
+            MemoryConfig {
+                size: 512 << 20,
+                mergeable: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("mergeable=on", None)?,
+            MemoryConfig {
+                mergeable: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("size=1G,mergeable=off", None)?,
+            MemoryConfig {
+                size: 1 << 30,
+                mergeable: false,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("hotplug_method=acpi", None)?,
+            MemoryConfig {
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("hotplug_method=acpi,hotplug_size=512M", None)?,
+            MemoryConfig {
+                hotplug_size: Some(512 << 20),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("hotplug_method=virtio-mem,hotplug_size=512M", None)?,
+            MemoryConfig {
+                hotplug_size: Some(512 << 20),
+                hotplug_method: HotplugMethod::VirtioMem,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse(
+                "hotplug_method=virtio-mem,hotplug_size=512M,virtio_mem_transport=mmio",
+                None
+            )?,
+            MemoryConfig {
+                hotplug_size: Some(512 << 20),
+                hotplug_method: HotplugMethod::VirtioMem,
+                virtio_mem_transport: VirtioTransportType::Mmio,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("hugepages=on,size=1G,hugepage_size=2M", None)?,
+            MemoryConfig {
+                hugepage_size: Some(2 << 20),
+                size: 1 << 30,
+                hugepages: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("hugepages=on,hugepage_pool=on,hugepage_pool_limit=4G", None)?,
+            MemoryConfig {
+                hugepages: true,
+                hugepage_pool: true,
+                hugepage_pool_limit: Some(4 << 30),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse("shared=on,hugepages=on,sealed=on", None)?,
+            MemoryConfig {
+                shared: true,
+                hugepages: true,
+                sealed: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            MemoryConfig::parse(
+                "size=0",
+                Some(vec!["id=mem0,size=1G,file=/path/to/template,template=on"])
+            )?,
+            MemoryConfig {
+                size: 0,
+                zones: Some(vec![MemoryZoneConfig {
+                    id: "mem0".to_owned(),
+                    size: 1 << 30,
+                    file: Some(PathBuf::from("/path/to/template")),
+                    shared: false,
+                    hugepages: false,
+                    hugepage_size: None,
+                    host_numa_node: None,
+                    hotplug_size: None,
+                    hotplugged_size: None,
+                    prefault: false,
+                    template: true,
+                    sealed: false,
+                }]),
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_parsing() -> Result<()> {
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,id=mydisk0")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                id: Some("mydisk0".to_owned()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("vhost_user=true,socket=/tmp/sock")?,
+            DiskConfig {
+                vhost_socket: Some(String::from("/tmp/sock")),
+                vhost_user: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,iommu=on")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                iommu: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,iommu=on,queue_size=256")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                iommu: true,
+                queue_size: 256,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,iommu=on,queue_size=256,num_queues=4")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                iommu: true,
+                queue_size: 256,
+                num_queues: 4,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,direct=on")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                direct: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,optional=on")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                optional: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,poll_queue=false")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                poll_queue: false,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,poll_queue=true")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                poll_queue: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,io_engine=io_uring")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                io_engine: Some(DiskIoEngine::IoUring),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,io_engine=sync")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                io_engine: Some(DiskIoEngine::Sync),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,io_engine=threads")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                io_engine: Some(DiskIoEngine::Threads),
+                ..Default::default()
+            }
+        );
+        assert!(DiskConfig::parse("path=/path/to_file,io_engine=aio").is_err());
+        assert_eq!(
+            DiskConfig::parse(
+                "path=/path/to_file,bw_size=1000,bw_refill_time=100,ops=500,ops_one_time_burst=50"
+            )?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                rate_limiter_config: Some(RateLimiterConfig {
+                    bandwidth: Some(TokenBucketConfig {
+                        size: 1000,
+                        one_time_burst: Some(0),
+                        refill_time: 100,
+                    }),
+                    ops: Some(TokenBucketConfig {
+                        size: 500,
+                        one_time_burst: Some(50),
+                        refill_time: 1000,
+                    }),
+                }),
+                ..Default::default()
+            }
+        );
+        assert!(DiskConfig::parse("path=/path/to_file,bw=1000,bw_size=1000").is_err());
+        assert_eq!(
+            DiskConfig::parse("path=/path/to_file,coalesce_events=32,coalesce_usecs=50")?,
+            DiskConfig {
+                path: Some(PathBuf::from("/path/to_file")),
+                interrupt_coalescing: Some(InterruptCoalescingConfig {
+                    max_events: 32,
+                    usecs: 50,
+                }),
+                ..Default::default()
+            }
+        );
+        assert!(DiskConfig::parse("path=/path/to_file,coalesce_events=32").is_err());
+        assert!(DiskConfig::parse("path=/path/to_file,coalesce_usecs=0").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_net_parsing() -> Result<()> {
+        // mac address is random
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,id=mynet0")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                id: Some("mynet0".to_owned()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse(
+                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,tap=tap0,ip=192.168.100.1,mask=255.255.255.128"
+            )?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                tap: Some("tap0".to_owned()),
+                ip: "192.168.100.1".parse().unwrap(),
+                mask: "255.255.255.128".parse().unwrap(),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse(
+                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,vhost_user=true,socket=/tmp/sock"
+            )?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                vhost_user: true,
+                vhost_socket: Some("/tmp/sock".to_owned()),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,num_queues=4,queue_size=1024,iommu=on")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+                num_queues: 4,
+                queue_size: 1024,
+                iommu: true,
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,fd=[3,7],num_queues=4")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                fds: Some(vec![3, 7]),
+                num_queues: 4,
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            NetConfig::parse(
+                "mac=de:ad:be:ef:12:34,vhost_user=on,socket=/tmp/sock,transport=mmio"
+            )?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                vhost_user: true,
+                vhost_socket: Some("/tmp/sock".to_owned()),
+                transport: VirtioTransportType::Mmio,
+                ..Default::default()
+            }
+        );
+        assert!(NetConfig::parse("transport=ccw").is_err());
+
+        assert_eq!(
+            NetConfig::parse("mac=de:ad:be:ef:12:34,tap=tap0,num_queues=8,vhost_net=on")?,
+            NetConfig {
+                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
+                tap: Some("tap0".to_owned()),
+                num_queues: 8,
+                vhost_net: true,
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_numa_parsing() -> Result<()> {
+        assert_eq!(
+            NumaConfig::parse(
+                "guest_numa_id=1,cpus=[2,3],memory_zones=mem1,pmem=[pmem0,pmem1],memory_hotplug=on"
+            )?,
+            NumaConfig {
+                guest_numa_id: 1,
+                cpus: Some(vec![2, 3]),
+                memory_zones: Some(vec!["mem1".to_owned()]),
+                pmem: Some(vec!["pmem0".to_owned(), "pmem1".to_owned()]),
+                memory_hotplug: true,
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rng() -> Result<()> {
+        assert_eq!(RngConfig::parse("")?, RngConfig::default());
+        assert_eq!(
+            RngConfig::parse("src=/dev/random")?,
+            RngConfig {
+                src: PathBuf::from("/dev/random"),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            RngConfig::parse("src=/dev/random,iommu=on")?,
+            RngConfig {
+                src: PathBuf::from("/dev/random"),
+                iommu: true,
+            }
+        );
+        assert_eq!(
+            RngConfig::parse("iommu=on")?,
+            RngConfig {
+                iommu: true,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_fs() -> Result<()> {
+        // "tag" and "socket" must be supplied
+        assert!(FsConfig::parse("").is_err());
+        assert!(FsConfig::parse("tag=mytag").is_err());
+        assert!(FsConfig::parse("socket=/tmp/sock").is_err());
+        assert_eq!(
+            FsConfig::parse("tag=mytag,socket=/tmp/sock")?,
+            FsConfig {
+                socket: PathBuf::from("/tmp/sock"),
+                tag: "mytag".to_owned(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            FsConfig::parse("tag=mytag,socket=/tmp/sock")?,
+            FsConfig {
+                socket: PathBuf::from("/tmp/sock"),
+                tag: "mytag".to_owned(),
+                ..Default::default()
             }
-        }
-
-        #[cfg(target_arch = "x86_64")]
-        if let Some(sgx_epcs) = &self.sgx_epc {
-            for sgx_epc in sgx_epcs.iter() {
-                let id = sgx_epc.id.clone();
-                Self::validate_identifier(&mut id_list, &Some(id))?;
+        );
+        assert_eq!(
+            FsConfig::parse("tag=mytag,socket=/tmp/sock,num_queues=4,queue_size=1024")?,
+            FsConfig {
+                socket: PathBuf::from("/tmp/sock"),
+                tag: "mytag".to_owned(),
+                num_queues: 4,
+                queue_size: 1024,
+                ..Default::default()
             }
-        }
-
-        self.platform.as_ref().map(|p| p.validate()).transpose()?;
-        self.iommu |= self
-            .platform
-            .as_ref()
-            .map(|p| p.iommu_segments.is_some())
-            .unwrap_or_default();
+        );
+        assert_eq!(
+            FsConfig::parse("tag=mytag,socket=/tmp/sock,dax=on,cache_size=1G,transport=mmio")?,
+            FsConfig {
+                socket: PathBuf::from("/tmp/sock"),
+                tag: "mytag".to_owned(),
+                dax: true,
+                cache_size: 1 << 30,
+                transport: VirtioTransportType::Mmio,
+                ..Default::default()
+            }
+        );
+        assert!(FsConfig::parse("tag=mytag,socket=/tmp/sock,transport=ccw").is_err());
 
-        Ok(id_list)
+        Ok(())
     }
 
-    pub fn parse(vm_params: VmParams) -> Result<Self> {
-        let mut disks: Option<Vec<DiskConfig>> = None;
-        if let Some(disk_list) = &vm_params.disks {
-            let mut disk_config_list = Vec::new();
-            for item in disk_list.iter() {
-                let disk_config = DiskConfig::parse(item)?;
-                disk_config_list.push(disk_config);
+    #[test]
+    fn test_pmem_parsing() -> Result<()> {
+        // Must always give a file and size
+        assert!(PmemConfig::parse("").is_err());
+        assert!(PmemConfig::parse("size=128M").is_err());
+        assert_eq!(
+            PmemConfig::parse("file=/tmp/pmem,size=128M")?,
+            PmemConfig {
+                file: PathBuf::from("/tmp/pmem"),
+                size: Some(128 << 20),
+                ..Default::default()
             }
-            disks = Some(disk_config_list);
-        }
-
-        let mut net: Option<Vec<NetConfig>> = None;
-        if let Some(net_list) = &vm_params.net {
-            let mut net_config_list = Vec::new();
-            for item in net_list.iter() {
-                let net_config = NetConfig::parse(item)?;
-                net_config_list.push(net_config);
+        );
+        assert_eq!(
+            PmemConfig::parse("file=/tmp/pmem,size=128M,id=mypmem0")?,
+            PmemConfig {
+                file: PathBuf::from("/tmp/pmem"),
+                size: Some(128 << 20),
+                id: Some("mypmem0".to_owned()),
+                ..Default::default()
             }
-            net = Some(net_config_list);
-        }
-
-        let rng = RngConfig::parse(vm_params.rng)?;
+        );
+        assert_eq!(
+            PmemConfig::parse("file=/tmp/pmem,size=128M,iommu=on,discard_writes=on")?,
+            PmemConfig {
+                file: PathBuf::from("/tmp/pmem"),
+                size: Some(128 << 20),
+                discard_writes: true,
+                iommu: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PmemConfig::parse("file=/tmp/pmem,transport=mmio")?,
+            PmemConfig {
+                file: PathBuf::from("/tmp/pmem"),
+                transport: VirtioTransportType::Mmio,
+                ..Default::default()
+            }
+        );
 
-        let mut balloon: Option<BalloonConfig> = None;
-        if let Some(balloon_params) = &vm_params.balloon {
-            balloon = Some(BalloonConfig::parse(balloon_params)?);
-        }
+        Ok(())
+    }
 
-        let mut fs: Option<Vec<FsConfig>> = None;
-        if let Some(fs_list) = &vm_params.fs {
-            let mut fs_config_list = Vec::new();
-            for item in fs_list.iter() {
-                fs_config_list.push(FsConfig::parse(item)?);
+    #[test]
+    fn test_pstore_parsing() -> Result<()> {
+        // Must always give a file
+        assert!(PstoreConfig::parse("").is_err());
+        assert!(PstoreConfig::parse("size=1M").is_err());
+        assert_eq!(
+            PstoreConfig::parse("file=/tmp/pstore")?,
+            PstoreConfig {
+                file: PathBuf::from("/tmp/pstore"),
+                size: DEFAULT_PSTORE_SIZE,
             }
-            fs = Some(fs_config_list);
-        }
+        );
+        assert_eq!(
+            PstoreConfig::parse("file=/tmp/pstore,size=256K")?,
+            PstoreConfig {
+                file: PathBuf::from("/tmp/pstore"),
+                size: 256 << 10,
+            }
+        );
 
-        let mut pmem: Option<Vec<PmemConfig>> = None;
-        if let Some(pmem_list) = &vm_params.pmem {
-            let mut pmem_config_list = Vec::new();
-            for item in pmem_list.iter() {
-                let pmem_config = PmemConfig::parse(item)?;
-                pmem_config_list.push(pmem_config);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ptp_parsing() -> Result<()> {
+        // Must always give a path
+        assert!(PtpConfig::parse("").is_err());
+        assert!(PtpConfig::parse("iommu=on").is_err());
+        assert_eq!(
+            PtpConfig::parse("path=/dev/ptp0")?,
+            PtpConfig {
+                path: PathBuf::from("/dev/ptp0"),
+                iommu: false,
             }
-            pmem = Some(pmem_config_list);
-        }
+        );
+        assert_eq!(
+            PtpConfig::parse("path=/dev/ptp0,iommu=on")?,
+            PtpConfig {
+                path: PathBuf::from("/dev/ptp0"),
+                iommu: true,
+            }
+        );
 
-        let console = ConsoleConfig::parse(vm_params.console)?;
-        let serial = ConsoleConfig::parse(vm_params.serial)?;
+        Ok(())
+    }
 
-        let mut devices: Option<Vec<DeviceConfig>> = None;
-        if let Some(device_list) = &vm_params.devices {
-            let mut device_config_list = Vec::new();
-            for item in device_list.iter() {
-                let device_config = DeviceConfig::parse(item)?;
-                device_config_list.push(device_config);
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn test_fdt_parsing() -> Result<()> {
+        // Must always give a base
+        assert!(FdtConfig::parse("").is_err());
+        assert_eq!(
+            FdtConfig::parse("base=/tmp/board.dtb")?,
+            FdtConfig {
+                base: PathBuf::from("/tmp/board.dtb"),
+                patch_memory: false,
+                patch_virtio: false,
+                edits: Vec::new(),
             }
-            devices = Some(device_config_list);
-        }
+        );
+        assert_eq!(
+            FdtConfig::parse("base=/tmp/board.dtb,patch_memory=on,patch_virtio=on")?,
+            FdtConfig {
+                base: PathBuf::from("/tmp/board.dtb"),
+                patch_memory: true,
+                patch_virtio: true,
+                edits: Vec::new(),
+            }
+        );
+        assert!(FdtConfig::parse("base=/tmp/board.dtb,patch_memory=yes").is_err());
 
-        let mut user_devices: Option<Vec<UserDeviceConfig>> = None;
-        if let Some(user_device_list) = &vm_params.user_devices {
-            let mut user_device_config_list = Vec::new();
-            for item in user_device_list.iter() {
-                let user_device_config = UserDeviceConfig::parse(item)?;
-                user_device_config_list.push(user_device_config);
+        Ok(())
+    }
+
+    #[test]
+    fn test_acpi_parsing() -> Result<()> {
+        assert_eq!(AcpiConfig::parse("")?, AcpiConfig::default());
+        assert_eq!(
+            AcpiConfig::parse("ssdt=[/tmp/a.aml,/tmp/b.aml]")?,
+            AcpiConfig {
+                ssdt: vec![PathBuf::from("/tmp/a.aml"), PathBuf::from("/tmp/b.aml")],
+                oem_table_ids: Vec::new(),
+                #[cfg(target_arch = "aarch64")]
+                direct_boot: false,
             }
-            user_devices = Some(user_device_config_list);
-        }
+        );
+        assert_eq!(
+            AcpiConfig::parse("ssdt=/tmp/a.aml,oem_table_ids=[FACP@MYFACP,DSDT@MYDSDT]")?,
+            AcpiConfig {
+                ssdt: vec![PathBuf::from("/tmp/a.aml")],
+                oem_table_ids: vec![
+                    AcpiOemTableIdConfig {
+                        signature: "FACP".to_owned(),
+                        oem_table_id: "MYFACP".to_owned(),
+                    },
+                    AcpiOemTableIdConfig {
+                        signature: "DSDT".to_owned(),
+                        oem_table_id: "MYDSDT".to_owned(),
+                    },
+                ],
+                #[cfg(target_arch = "aarch64")]
+                direct_boot: false,
+            }
+        );
+        assert!(AcpiConfig::parse("oem_table_ids=[FACP]").is_err());
+        #[cfg(target_arch = "aarch64")]
+        assert!(AcpiConfig::parse("direct_boot=on")?.direct_boot);
 
-        let mut vdpa: Option<Vec<VdpaConfig>> = None;
-        if let Some(vdpa_list) = &vm_params.vdpa {
-            let mut vdpa_config_list = Vec::new();
-            for item in vdpa_list.iter() {
-                let vdpa_config = VdpaConfig::parse(item)?;
-                vdpa_config_list.push(vdpa_config);
+        Ok(())
+    }
+
+    #[test]
+    fn test_security_parsing() -> Result<()> {
+        assert_eq!(SecurityConfig::parse("")?, SecurityConfig::default());
+        assert_eq!(
+            SecurityConfig::parse("seccomp=log")?,
+            SecurityConfig {
+                seccomp: Some(SeccompLevel::Log),
+                require_iommu: false,
+                seccomp_policy: None,
             }
-            vdpa = Some(vdpa_config_list);
-        }
+        );
+        assert_eq!(
+            SecurityConfig::parse("seccomp=true,require_iommu=on")?,
+            SecurityConfig {
+                seccomp: Some(SeccompLevel::Trap),
+                require_iommu: true,
+                seccomp_policy: None,
+            }
+        );
+        assert_eq!(
+            SecurityConfig::parse("seccomp_policy=/tmp/policy.json")?,
+            SecurityConfig {
+                seccomp: None,
+                require_iommu: false,
+                seccomp_policy: Some(PathBuf::from("/tmp/policy.json")),
+            }
+        );
+        assert!(SecurityConfig::parse("seccomp=strict").is_err());
+        assert!(SeccompLevel::Allow.strictness() < SeccompLevel::Log.strictness());
+        assert!(SeccompLevel::Log.strictness() < SeccompLevel::Trap.strictness());
 
-        let mut vsock: Option<VsockConfig> = None;
-        if let Some(vs) = &vm_params.vsock {
-            let vsock_config = VsockConfig::parse(vs)?;
-            vsock = Some(vsock_config);
-        }
+        Ok(())
+    }
 
-        let platform = vm_params.platform.map(PlatformConfig::parse).transpose()?;
+    #[test]
+    fn test_deterministic_parsing() -> Result<()> {
+        assert_eq!(
+            DeterministicConfig::parse("seed=42")?,
+            DeterministicConfig { seed: 42 }
+        );
+        assert!(DeterministicConfig::parse("").is_err());
+        assert!(DeterministicConfig::parse("seed=foo").is_err());
 
+        Ok(())
+    }
+
+    #[test]
+    fn test_vm_config_schema() {
+        let schema = serde_json::to_value(vm_config_schema()).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        assert!(properties.contains_key("cpus"));
+        assert!(properties.contains_key("priority"));
+        #[cfg(target_arch = "aarch64")]
+        assert!(properties.contains_key("fdt"));
         #[cfg(target_arch = "x86_64")]
-        let mut sgx_epc: Option<Vec<SgxEpcConfig>> = None;
-        #[cfg(target_arch = "x86_64")]
-        {
-            if let Some(sgx_epc_list) = &vm_params.sgx_epc {
-                let mut sgx_epc_config_list = Vec::new();
-                for item in sgx_epc_list.iter() {
-                    let sgx_epc_config = SgxEpcConfig::parse(item)?;
-                    sgx_epc_config_list.push(sgx_epc_config);
-                }
-                sgx_epc = Some(sgx_epc_config_list);
-            }
-        }
+        assert!(properties.contains_key("sgx_epc"));
+        assert!(schema["definitions"]["NetConfig"].is_object());
+    }
 
-        let mut numa: Option<Vec<NumaConfig>> = None;
-        if let Some(numa_list) = &vm_params.numa {
-            let mut numa_config_list = Vec::new();
-            for item in numa_list.iter() {
-                let numa_config = NumaConfig::parse(item)?;
-                numa_config_list.push(numa_config);
+    #[test]
+    fn test_priority_parsing() -> Result<()> {
+        assert_eq!(
+            PriorityConfig::parse("class=realtime")?,
+            PriorityConfig {
+                class: PriorityClass::Realtime
             }
-            numa = Some(numa_config_list);
-        }
-
-        let mut kernel: Option<KernelConfig> = None;
-        if let Some(k) = vm_params.kernel {
-            kernel = Some(KernelConfig {
-                path: PathBuf::from(k),
-            });
-        }
-
-        let mut initramfs: Option<InitramfsConfig> = None;
-        if let Some(k) = vm_params.initramfs {
-            initramfs = Some(InitramfsConfig {
-                path: PathBuf::from(k),
-            });
-        }
+        );
+        assert_eq!(
+            PriorityConfig::parse("class=Background")?,
+            PriorityConfig {
+                class: PriorityClass::Background
+            }
+        );
+        assert!(PriorityConfig::parse("").is_err());
+        assert!(PriorityConfig::parse("class=urgent").is_err());
 
-        #[cfg(feature = "tdx")]
-        let tdx = vm_params.tdx.map(TdxConfig::parse).transpose()?;
+        Ok(())
+    }
 
-        #[cfg(feature = "gdb")]
-        let gdb = vm_params.gdb;
+    #[test]
+    fn test_irqs_parsing() -> Result<()> {
+        assert_eq!(
+            IrqConfig::parse("pins=[__serial@40,_net0@41],reserved=[48-50,60]")?,
+            IrqConfig {
+                pins: vec![
+                    IrqPinConfig {
+                        id: "__serial".to_owned(),
+                        irq: 40,
+                    },
+                    IrqPinConfig {
+                        id: "_net0".to_owned(),
+                        irq: 41,
+                    },
+                ],
+                reserved: vec![48, 49, 50, 60],
+            }
+        );
+        assert_eq!(IrqConfig::parse("")?, IrqConfig::default());
+        assert!(IrqConfig::parse("pins=[__serial]").is_err());
+        assert!(IrqConfig::parse("reserved=[foo]").is_err());
 
-        let mut config = VmConfig {
-            cpus: CpusConfig::parse(vm_params.cpus)?,
-            memory: MemoryConfig::parse(vm_params.memory, vm_params.memory_zones)?,
-            kernel,
-            initramfs,
-            cmdline: CmdlineConfig::parse(vm_params.cmdline)?,
-            disks,
-            net,
-            rng,
-            balloon,
-            fs,
-            pmem,
-            serial,
-            console,
-            devices,
-            user_devices,
-            vdpa,
-            vsock,
-            iommu: false, // updated in VmConfig::validate()
-            #[cfg(target_arch = "x86_64")]
-            sgx_epc,
-            numa,
-            watchdog: vm_params.watchdog,
-            #[cfg(feature = "tdx")]
-            tdx,
-            #[cfg(feature = "gdb")]
-            gdb,
-            platform,
-        };
-        config.validate().map_err(Error::Validation)?;
-        Ok(config)
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_doorbell_parsing() -> Result<()> {
+        assert_eq!(
+            DoorbellConfig::parse("fds=[3,4,5,6],id=db0")?,
+            DoorbellConfig {
+                fds: vec![3, 4, 5, 6],
+                id: Some("db0".to_owned()),
+            }
+        );
+        assert!(DoorbellConfig::parse("id=db0").is_err());
+        assert!(DoorbellConfig::parse("fds=[foo]").is_err());
+
+        Ok(())
+    }
 
     #[test]
-    fn test_option_parser() {
-        let mut parser = OptionParser::new();
-        parser
-            .add("size")
-            .add("mergeable")
-            .add("hotplug_method")
-            .add("hotplug_size");
+    fn test_partition_services_parsing() -> Result<()> {
+        assert_eq!(
+            PartitionServicesConfig::parse("partition_id=42")?,
+            PartitionServicesConfig { partition_id: 42 }
+        );
+        assert_eq!(
+            PartitionServicesConfig::parse("")?,
+            PartitionServicesConfig { partition_id: 0 }
+        );
+        assert!(PartitionServicesConfig::parse("partition_id=foo").is_err());
 
-        assert!(parser.parse("size=128M,hanging_param").is_err());
-        assert!(parser.parse("size=128M,too_many_equals=foo=bar").is_err());
-        assert!(parser.parse("size=128M,file=/dev/shm").is_err());
-        assert!(parser.parse("size=128M").is_ok());
+        Ok(())
+    }
 
-        assert_eq!(parser.get("size"), Some("128M".to_owned()));
-        assert!(!parser.is_set("mergeable"));
-        assert!(parser.is_set("size"));
+    #[test]
+    fn test_snapshot_schedule_parsing() -> Result<()> {
+        assert_eq!(
+            SnapshotScheduleConfig::parse("destination=/var/snapshots,interval=3600")?,
+            SnapshotScheduleConfig {
+                destination: PathBuf::from("/var/snapshots"),
+                interval: 3600,
+                retention: DEFAULT_SNAPSHOT_SCHEDULE_RETENTION,
+                dirty_threshold: 0,
+                format: SnapshotFormat::Raw,
+                incremental: false,
+            }
+        );
+        assert_eq!(
+            SnapshotScheduleConfig::parse(
+                "destination=/var/snapshots,interval=60,retention=10,dirty_threshold=64M,format=zstd,incremental=on"
+            )?,
+            SnapshotScheduleConfig {
+                destination: PathBuf::from("/var/snapshots"),
+                interval: 60,
+                retention: 10,
+                dirty_threshold: 64 << 20,
+                format: SnapshotFormat::Zstd,
+                incremental: true,
+            }
+        );
+        assert!(SnapshotScheduleConfig::parse("interval=60").is_err());
+        assert!(SnapshotScheduleConfig::parse("destination=/var/snapshots").is_err());
+        assert!(SnapshotScheduleConfig::parse("destination=/var/snapshots,interval=1m").is_err());
+
+        Ok(())
     }
 
     #[test]
-    fn test_cpu_parsing() -> Result<()> {
-        assert_eq!(CpusConfig::parse("")?, CpusConfig::default());
+    fn test_maintenance_parsing() -> Result<()> {
+        assert_eq!(MaintenanceConfig::parse("")?, MaintenanceConfig::default());
+        assert_eq!(
+            MaintenanceConfig::parse(
+                "lease=60,on_expiry=shutdown,checkpoint=/run/ch/maintenance.json"
+            )?,
+            MaintenanceConfig {
+                lease: 60,
+                on_expiry: MaintenanceExpiryAction::Shutdown,
+                checkpoint: Some(PathBuf::from("/run/ch/maintenance.json")),
+            }
+        );
+        assert!(MaintenanceConfig::parse("on_expiry=reset").is_err());
+        assert!(MaintenanceConfig::parse("lease=1m").is_err());
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_virtio_features_parsing() -> Result<()> {
+        assert!(VirtioFeaturesConfig::parse("disable=[28]").is_err());
         assert_eq!(
-            CpusConfig::parse("boot=1")?,
-            CpusConfig {
-                boot_vcpus: 1,
-                max_vcpus: 1,
+            VirtioFeaturesConfig::parse("id=_disk0")?,
+            VirtioFeaturesConfig {
+                id: "_disk0".to_owned(),
                 ..Default::default()
             }
         );
         assert_eq!(
-            CpusConfig::parse("boot=1,max=2")?,
-            CpusConfig {
-                boot_vcpus: 1,
-                max_vcpus: 2,
-                ..Default::default()
+            VirtioFeaturesConfig::parse("id=_net0,disable=[indirect_desc,event_idx],enable=27")?,
+            VirtioFeaturesConfig {
+                id: "_net0".to_owned(),
+                disable: (1 << 28) | (1 << 29),
+                enable: 1 << 27,
             }
         );
+        assert!(VirtioFeaturesConfig::parse("id=_net0,disable=[64]").is_err());
+        assert!(VirtioFeaturesConfig::parse("id=_net0,enable=[unknown]").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_console_parsing() -> Result<()> {
+        // Must always give an output
+        assert!(DebugConsoleConfig::parse("").is_err());
+        assert!(DebugConsoleConfig::parse("iobase=0xe9").is_err());
+        assert!(DebugConsoleConfig::parse("log,iobase=e9").is_err());
         assert_eq!(
-            CpusConfig::parse("boot=8,topology=2:2:1:2")?,
-            CpusConfig {
-                boot_vcpus: 8,
-                max_vcpus: 8,
-                topology: Some(CpuTopology {
-                    threads_per_core: 2,
-                    cores_per_die: 2,
-                    dies_per_package: 1,
-                    packages: 2
-                }),
-                ..Default::default()
+            DebugConsoleConfig::parse("file=/tmp/debugcon")?,
+            DebugConsoleConfig {
+                mode: DebugConsoleOutputMode::File,
+                file: Some(PathBuf::from("/tmp/debugcon")),
+                iobase: None,
             }
         );
+        assert_eq!(
+            DebugConsoleConfig::parse("log,iobase=0x402")?,
+            DebugConsoleConfig {
+                mode: DebugConsoleOutputMode::Log,
+                file: None,
+                iobase: Some(0x402),
+            }
+        );
+        assert_eq!(
+            DebugConsoleConfig::parse("log,iobase=233")?.iobase,
+            Some(0xe9)
+        );
 
-        assert!(CpusConfig::parse("boot=8,topology=2:2:1").is_err());
-        assert!(CpusConfig::parse("boot=8,topology=2:2:1:x").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_crashkernel_parsing() -> Result<()> {
+        // Must always give a size
+        assert!(CrashKernelConfig::parse("").is_err());
         assert_eq!(
-            CpusConfig::parse("boot=1,kvm_hyperv=on")?,
-            CpusConfig {
-                boot_vcpus: 1,
-                max_vcpus: 1,
-                kvm_hyperv: true,
+            CrashKernelConfig::parse("size=256M")?,
+            CrashKernelConfig { size: 256 << 20 }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_payload_blob_parsing() -> Result<()> {
+        // Must always give a path
+        assert!(PayloadBlobConfig::parse("address=0x1000").is_err());
+        assert!(PayloadBlobConfig::parse("path=/path/to/blob,address=foo").is_err());
+        assert_eq!(
+            PayloadBlobConfig::parse("path=/path/to/blob")?,
+            PayloadBlobConfig {
+                path: PathBuf::from("/path/to/blob"),
                 ..Default::default()
             }
         );
+        assert_eq!(
+            PayloadBlobConfig::parse("path=/path/to/blob,address=0x8000000,id=fw0")?,
+            PayloadBlobConfig {
+                path: PathBuf::from("/path/to/blob"),
+                address: Some(0x800_0000),
+                id: Some("fw0".to_owned()),
+            }
+        );
+        assert_eq!(
+            PayloadBlobConfig::parse("path=/path/to/blob,address=4096")?.address,
+            Some(0x1000)
+        );
+
         Ok(())
     }
 
     #[test]
-    fn test_mem_parsing() -> Result<()> {
-        assert_eq!(MemoryConfig::parse("", None)?, MemoryConfig::default());
-        // Default string
+    fn test_console_parsing() -> Result<()> {
+        assert!(ConsoleConfig::parse("").is_err());
+        assert!(ConsoleConfig::parse("badmode").is_err());
         assert_eq!(
-            MemoryConfig::parse("size=512M", None)?,
-            MemoryConfig::default()
+            ConsoleConfig::parse("off")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Off,
+                iommu: false,
+                file: None,
+            }
         );
         assert_eq!(
-            MemoryConfig::parse("size=512M,mergeable=on", None)?,
-            MemoryConfig {
-                size: 512 << 20,
-                mergeable: true,
-                ..Default::default()
+            ConsoleConfig::parse("pty")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Pty,
+                iommu: false,
+                file: None,
             }
         );
         assert_eq!(
-            MemoryConfig::parse("mergeable=on", None)?,
-            MemoryConfig {
-                mergeable: true,
+            ConsoleConfig::parse("tty")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Tty,
+                iommu: false,
+                file: None,
+            }
+        );
+        assert_eq!(
+            ConsoleConfig::parse("null")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Null,
+                iommu: false,
+                file: None,
+            }
+        );
+        assert_eq!(
+            ConsoleConfig::parse("file=/tmp/console")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::File,
+                iommu: false,
+                file: Some(PathBuf::from("/tmp/console"))
+            }
+        );
+        assert_eq!(
+            ConsoleConfig::parse("null,iommu=on")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::Null,
+                iommu: true,
+                file: None,
+            }
+        );
+        assert_eq!(
+            ConsoleConfig::parse("file=/tmp/console,iommu=on")?,
+            ConsoleConfig {
+                mode: ConsoleOutputMode::File,
+                iommu: true,
+                file: Some(PathBuf::from("/tmp/console"))
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_device_parsing() -> Result<()> {
+        // Device must have a path provided
+        assert!(DeviceConfig::parse("").is_err());
+        assert_eq!(
+            DeviceConfig::parse("path=/path/to/device")?,
+            DeviceConfig {
+                path: PathBuf::from("/path/to/device"),
+                id: None,
+                iommu: false,
                 ..Default::default()
             }
         );
+
         assert_eq!(
-            MemoryConfig::parse("size=1G,mergeable=off", None)?,
-            MemoryConfig {
-                size: 1 << 30,
-                mergeable: false,
+            DeviceConfig::parse("path=/path/to/device,iommu=on")?,
+            DeviceConfig {
+                path: PathBuf::from("/path/to/device"),
+                id: None,
+                iommu: true,
                 ..Default::default()
             }
         );
+
         assert_eq!(
-            MemoryConfig::parse("hotplug_method=acpi", None)?,
-            MemoryConfig {
+            DeviceConfig::parse("path=/path/to/device,iommu=on,id=mydevice0")?,
+            DeviceConfig {
+                path: PathBuf::from("/path/to/device"),
+                id: Some("mydevice0".to_owned()),
+                iommu: true,
                 ..Default::default()
             }
         );
+
         assert_eq!(
-            MemoryConfig::parse("hotplug_method=acpi,hotplug_size=512M", None)?,
-            MemoryConfig {
-                hotplug_size: Some(512 << 20),
+            DeviceConfig::parse("path=/path/to/device,multifunction_of=mydevice0,function=1")?,
+            DeviceConfig {
+                path: PathBuf::from("/path/to/device"),
+                multifunction_of: Some("mydevice0".to_owned()),
+                function: Some(1),
                 ..Default::default()
             }
         );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vdpa_parsing() -> Result<()> {
+        // path is required
+        assert!(VdpaConfig::parse("").is_err());
         assert_eq!(
-            MemoryConfig::parse("hotplug_method=virtio-mem,hotplug_size=512M", None)?,
-            MemoryConfig {
-                hotplug_size: Some(512 << 20),
-                hotplug_method: HotplugMethod::VirtioMem,
+            VdpaConfig::parse("path=/dev/vhost-vdpa")?,
+            VdpaConfig {
+                path: PathBuf::from("/dev/vhost-vdpa"),
+                num_queues: 1,
+                id: None,
                 ..Default::default()
             }
         );
         assert_eq!(
-            MemoryConfig::parse("hugepages=on,size=1G,hugepage_size=2M", None)?,
-            MemoryConfig {
-                hugepage_size: Some(2 << 20),
-                size: 1 << 30,
-                hugepages: true,
+            VdpaConfig::parse("path=/dev/vhost-vdpa,num_queues=2,id=my_vdpa")?,
+            VdpaConfig {
+                path: PathBuf::from("/dev/vhost-vdpa"),
+                num_queues: 2,
+                id: Some("my_vdpa".to_owned()),
                 ..Default::default()
             }
         );
@@ -2828,676 +7294,1028 @@ mod tests {
     }
 
     #[test]
-    fn test_disk_parsing() -> Result<()> {
+    fn test_balloon_parsing() -> Result<()> {
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                ..Default::default()
+            BalloonConfig::parse("size=1G")?,
+            BalloonConfig {
+                size: 1 << 30,
+                deflate_on_oom: false,
+                free_page_reporting: false,
+                stats_polling_interval: 0,
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,id=mydisk0")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                id: Some("mydisk0".to_owned()),
-                ..Default::default()
+            BalloonConfig::parse("size=0,stats_polling_interval=5")?,
+            BalloonConfig {
+                size: 0,
+                deflate_on_oom: false,
+                free_page_reporting: false,
+                stats_polling_interval: 5,
             }
         );
+        assert!(BalloonConfig::parse("size=0,stats_polling_interval=foo").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_vsock_parsing() -> Result<()> {
+        // socket and cid is required
+        assert!(VsockConfig::parse("").is_err());
         assert_eq!(
-            DiskConfig::parse("vhost_user=true,socket=/tmp/sock")?,
-            DiskConfig {
-                vhost_socket: Some(String::from("/tmp/sock")),
-                vhost_user: true,
+            VsockConfig::parse("socket=/tmp/sock,cid=1")?,
+            VsockConfig {
+                cid: 1,
+                socket: PathBuf::from("/tmp/sock"),
+                iommu: false,
+                id: None,
                 ..Default::default()
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,iommu=on")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
+            VsockConfig::parse("socket=/tmp/sock,cid=1,iommu=on")?,
+            VsockConfig {
+                cid: 1,
+                socket: PathBuf::from("/tmp/sock"),
                 iommu: true,
+                id: None,
                 ..Default::default()
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,iommu=on,queue_size=256")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                iommu: true,
-                queue_size: 256,
+            VsockConfig::parse("socket=/tmp/sock,cid=1,transport=mmio")?,
+            VsockConfig {
+                cid: 1,
+                socket: PathBuf::from("/tmp/sock"),
+                transport: VirtioTransportType::Mmio,
                 ..Default::default()
             }
         );
+        assert!(VsockConfig::parse("socket=/tmp/sock,cid=1,transport=ccw").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gpu_parsing() -> Result<()> {
+        // socket is required
+        assert!(GpuConfig::parse("").is_err());
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,iommu=on,queue_size=256,num_queues=4")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                iommu: true,
-                queue_size: 256,
-                num_queues: 4,
+            GpuConfig::parse("socket=/tmp/gpu")?,
+            GpuConfig {
+                socket: PathBuf::from("/tmp/gpu"),
                 ..Default::default()
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,direct=on")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                direct: true,
+            GpuConfig::parse("socket=/tmp/gpu,backend=dmabuf,width=1920,height=1080,id=gpu0")?,
+            GpuConfig {
+                socket: PathBuf::from("/tmp/gpu"),
+                backend: GpuBackend::Dmabuf,
+                width: 1920,
+                height: 1080,
+                id: Some("gpu0".to_owned()),
                 ..Default::default()
             }
         );
+        assert!(GpuConfig::parse("socket=/tmp/gpu,backend=virgl").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_watchdog_parsing() -> Result<()> {
+        assert_eq!(WatchdogConfig::parse("")?, WatchdogConfig::default());
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                ..Default::default()
+            WatchdogConfig::parse("model=sbsa,timeout=30,action=hook,hook=/usr/bin/notify")?,
+            WatchdogConfig {
+                model: WatchdogModel::Sbsa,
+                timeout: 30,
+                action: WatchdogAction::Hook,
+                hook: Some(PathBuf::from("/usr/bin/notify")),
             }
         );
+        assert!(WatchdogConfig::parse("model=i6300esb").is_err());
+        assert!(WatchdogConfig::parse("action=poweroff").is_err());
+
+        // The virtio watchdog is pinged every 15 seconds.
+        assert!(WatchdogConfig::parse("timeout=15")?.validate().is_err());
+        assert!(WatchdogConfig::parse("timeout=16")?.validate().is_ok());
+        assert!(WatchdogConfig::parse("action=hook")?.validate().is_err());
+        #[cfg(target_arch = "aarch64")]
+        assert!(WatchdogConfig::parse("model=sbsa,timeout=5")?
+            .validate()
+            .is_ok());
+        #[cfg(not(target_arch = "aarch64"))]
+        assert!(WatchdogConfig::parse("model=sbsa")?.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_parsing() -> Result<()> {
+        // source_url is required
+        assert!(RestoreConfig::parse("prefault=on").is_err());
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
+            RestoreConfig::parse("source_url=/path/to/snapshot")?,
+            RestoreConfig {
+                source_url: PathBuf::from("/path/to/snapshot"),
                 ..Default::default()
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,poll_queue=false")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                poll_queue: false,
+            RestoreConfig::parse(
+                "source_url=/path/to/snapshot,no_verify=on,fresh_devices=[_net2,_disk0]"
+            )?,
+            RestoreConfig {
+                source_url: PathBuf::from("/path/to/snapshot"),
+                no_verify: true,
+                fresh_devices: Some(vec!["_net2".to_owned(), "_disk0".to_owned()]),
                 ..Default::default()
             }
         );
         assert_eq!(
-            DiskConfig::parse("path=/path/to_file,poll_queue=true")?,
-            DiskConfig {
-                path: Some(PathBuf::from("/path/to_file")),
-                poll_queue: true,
+            RestoreConfig::parse("source_url=/path/to/snapshot,no_verify=on,on_demand=on")?,
+            RestoreConfig {
+                source_url: PathBuf::from("/path/to/snapshot"),
+                no_verify: true,
+                on_demand: true,
                 ..Default::default()
             }
         );
-
         Ok(())
     }
 
     #[test]
-    fn test_net_parsing() -> Result<()> {
-        // mac address is random
+    fn test_snapshot_format_parsing() {
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
+            SnapshotFormat::from_str("raw").unwrap(),
+            SnapshotFormat::Raw
+        );
+        assert_eq!(
+            SnapshotFormat::from_str("ZSTD").unwrap(),
+            SnapshotFormat::Zstd
+        );
+        assert!(SnapshotFormat::from_str("gzip").is_err());
+        assert_eq!(SnapshotFormat::default(), SnapshotFormat::Raw);
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let mut valid_config = VmConfig {
+            cpus: CpusConfig {
+                boot_vcpus: 1,
+                max_vcpus: 1,
                 ..Default::default()
-            }
+            },
+            memory: MemoryConfig {
+                size: 536_870_912,
+                mergeable: false,
+                hotplug_method: HotplugMethod::Acpi,
+                hotplug_size: None,
+                hotplugged_size: None,
+                shared: false,
+                hugepages: false,
+                hugepage_size: None,
+                prefault: false,
+                zones: None,
+                hugepage_pool: false,
+                hugepage_pool_limit: None,
+                virtio_mem_transport: VirtioTransportType::Pci,
+                sealed: false,
+            },
+            kernel: Some(KernelConfig {
+                path: PathBuf::from("/path/to/kernel"),
+            }),
+            initramfs: None,
+            payload_blobs: None,
+            cmdline: CmdlineConfig {
+                args: String::from(""),
+            },
+            disks: None,
+            net: None,
+            rng: RngConfig {
+                src: PathBuf::from("/dev/urandom"),
+                iommu: false,
+            },
+            balloon: None,
+            fs: None,
+            pmem: None,
+            serial: ConsoleConfig {
+                file: None,
+                mode: ConsoleOutputMode::Null,
+                iommu: false,
+            },
+            console: ConsoleConfig {
+                file: None,
+                mode: ConsoleOutputMode::Tty,
+                iommu: false,
+            },
+            devices: None,
+            user_devices: None,
+            vdpa: None,
+            vsock: None,
+            iommu: false,
+            #[cfg(target_arch = "x86_64")]
+            sgx_epc: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt: None,
+            numa: None,
+            watchdog: false,
+            watchdog_config: None,
+            pvpanic: false,
+            boot_paused: false,
+            #[cfg(feature = "tdx")]
+            tdx: None,
+            #[cfg(feature = "sev_snp")]
+            sev_snp: None,
+            #[cfg(feature = "gdb")]
+            gdb: false,
+            platform: None,
+            pstore: None,
+            crashkernel: None,
+            ptp: None,
+            debug_console: None,
+            acpi: None,
+            security: None,
+            deterministic: None,
+            snapshot_schedule: None,
+            irqs: None,
+            doorbells: None,
+            partition_services: None,
+            priority: None,
+            gpu: None,
+            maintenance: None,
+            virtio_features: None,
+        };
+
+        assert!(valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial.mode = ConsoleOutputMode::Tty;
+        invalid_config.console.mode = ConsoleOutputMode::Tty;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DoubleTtyMode)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.kernel = None;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::KernelMissing)
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.serial.mode = ConsoleOutputMode::File;
+        invalid_config.serial.file = None;
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,id=mynet0")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                id: Some("mynet0".to_owned()),
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::ConsoleFileMissing)
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.max_vcpus = 16;
+        invalid_config.cpus.boot_vcpus = 32;
         assert_eq!(
-            NetConfig::parse(
-                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,tap=tap0,ip=192.168.100.1,mask=255.255.255.128"
-            )?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                tap: Some("tap0".to_owned()),
-                ip: "192.168.100.1".parse().unwrap(),
-                mask: "255.255.255.128".parse().unwrap(),
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::CpusMaxLowerThanBoot)
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.weight = Some(10001);
         assert_eq!(
-            NetConfig::parse(
-                "mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,vhost_user=true,socket=/tmp/sock"
-            )?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                vhost_user: true,
-                vhost_socket: Some("/tmp/sock".to_owned()),
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::InvalidCpuWeight(10001))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.quota = Some(0);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidCpuQuota)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.cpus.max_vcpus = 16;
+        invalid_config.cpus.boot_vcpus = 16;
+        invalid_config.cpus.topology = Some(CpuTopology {
+            threads_per_core: 2,
+            cores_per_die: 8,
+            dies_per_package: 1,
+            packages: 2,
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::CpuTopologyCount)
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            path: Some(PathBuf::from("/path/to/image")),
+            ..Default::default()
+        }]);
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,host_mac=12:34:de:ad:be:ef,num_queues=4,queue_size=1024,iommu=on")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                host_mac: Some(MacAddr::parse_str("12:34:de:ad:be:ef").unwrap()),
-                num_queues: 4,
-                queue_size: 1024,
-                iommu: true,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::DiskSocketAndPath)
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            ..Default::default()
+        }]);
         assert_eq!(
-            NetConfig::parse("mac=de:ad:be:ef:12:34,fd=[3,7],num_queues=4")?,
-            NetConfig {
-                mac: MacAddr::parse_str("de:ad:be:ef:12:34").unwrap(),
-                fds: Some(vec![3, 7]),
-                num_queues: 4,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserMissingSocket)
         );
 
-        Ok(())
-    }
-
-    #[test]
-    fn test_parse_rng() -> Result<()> {
-        assert_eq!(RngConfig::parse("")?, RngConfig::default());
-        assert_eq!(
-            RngConfig::parse("src=/dev/random")?,
-            RngConfig {
-                src: PathBuf::from("/dev/random"),
-                ..Default::default()
-            }
-        );
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            ..Default::default()
+        }]);
         assert_eq!(
-            RngConfig::parse("src=/dev/random,iommu=on")?,
-            RngConfig {
-                src: PathBuf::from("/dev/random"),
-                iommu: true,
-            }
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserRequiresSharedMemory)
         );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            ..Default::default()
+        }]);
+        still_valid_config.memory.shared = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.disks = Some(vec![DiskConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            io_engine: Some(DiskIoEngine::Sync),
+            ..Default::default()
+        }]);
+        invalid_config.memory.shared = true;
         assert_eq!(
-            RngConfig::parse("iommu=on")?,
-            RngConfig {
-                iommu: true,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::DiskIoEngineVhostUser)
         );
-        Ok(())
-    }
 
-    #[test]
-    fn test_parse_fs() -> Result<()> {
-        // "tag" and "socket" must be supplied
-        assert!(FsConfig::parse("").is_err());
-        assert!(FsConfig::parse("tag=mytag").is_err());
-        assert!(FsConfig::parse("socket=/tmp/sock").is_err());
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vhost_user: true,
+            ..Default::default()
+        }]);
         assert_eq!(
-            FsConfig::parse("tag=mytag,socket=/tmp/sock")?,
-            FsConfig {
-                socket: PathBuf::from("/tmp/sock"),
-                tag: "mytag".to_owned(),
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserRequiresSharedMemory)
         );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            vhost_user: true,
+            vhost_socket: Some("/path/to/sock".to_owned()),
+            ..Default::default()
+        }]);
+        still_valid_config.memory.shared = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            fds: Some(vec![0]),
+            ..Default::default()
+        }]);
         assert_eq!(
-            FsConfig::parse("tag=mytag,socket=/tmp/sock")?,
-            FsConfig {
-                socket: PathBuf::from("/tmp/sock"),
-                tag: "mytag".to_owned(),
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::VnetReservedFd)
         );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            vhost_net: true,
+            interrupt_coalescing: Some(InterruptCoalescingConfig {
+                max_events: 32,
+                usecs: 100,
+            }),
+            ..Default::default()
+        }]);
         assert_eq!(
-            FsConfig::parse("tag=mytag,socket=/tmp/sock,num_queues=4,queue_size=1024")?,
-            FsConfig {
-                socket: PathBuf::from("/tmp/sock"),
-                tag: "mytag".to_owned(),
-                num_queues: 4,
-                queue_size: 1024,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::VhostNetRateLimiterOrCoalescing)
         );
 
-        Ok(())
-    }
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.net = Some(vec![NetConfig {
+            vhost_net: true,
+            ..Default::default()
+        }]);
+        assert!(still_valid_config.validate().is_ok());
 
-    #[test]
-    fn test_pmem_parsing() -> Result<()> {
-        // Must always give a file and size
-        assert!(PmemConfig::parse("").is_err());
-        assert!(PmemConfig::parse("size=128M").is_err());
-        assert_eq!(
-            PmemConfig::parse("file=/tmp/pmem,size=128M")?,
-            PmemConfig {
-                file: PathBuf::from("/tmp/pmem"),
-                size: Some(128 << 20),
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory.hotplug_size = Some(1 << 30);
+        still_valid_config.pmem = Some(vec![PmemConfig {
+            id: Some("pmem0".to_owned()),
+            ..Default::default()
+        }]);
+        still_valid_config.numa = Some(vec![
+            NumaConfig {
+                guest_numa_id: 0,
                 ..Default::default()
-            }
-        );
-        assert_eq!(
-            PmemConfig::parse("file=/tmp/pmem,size=128M,id=mypmem0")?,
-            PmemConfig {
-                file: PathBuf::from("/tmp/pmem"),
-                size: Some(128 << 20),
-                id: Some("mypmem0".to_owned()),
+            },
+            NumaConfig {
+                guest_numa_id: 1,
+                pmem: Some(vec!["pmem0".to_owned()]),
+                memory_hotplug: true,
                 ..Default::default()
-            }
-        );
+            },
+        ]);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.numa.as_mut().unwrap()[0].pmem = Some(vec!["pmem0".to_owned()]);
         assert_eq!(
-            PmemConfig::parse("file=/tmp/pmem,size=128M,iommu=on,discard_writes=on")?,
-            PmemConfig {
-                file: PathBuf::from("/tmp/pmem"),
-                size: Some(128 << 20),
-                discard_writes: true,
-                iommu: true,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::PmemReused("pmem0".to_owned(), 0, 1))
         );
 
-        Ok(())
-    }
-
-    #[test]
-    fn test_console_parsing() -> Result<()> {
-        assert!(ConsoleConfig::parse("").is_err());
-        assert!(ConsoleConfig::parse("badmode").is_err());
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.numa.as_mut().unwrap()[1].pmem = Some(vec!["pmem1".to_owned()]);
         assert_eq!(
-            ConsoleConfig::parse("off")?,
-            ConsoleConfig {
-                mode: ConsoleOutputMode::Off,
-                iommu: false,
-                file: None,
-            }
+            invalid_config.validate(),
+            Err(ValidationError::InvalidNumaPmem("pmem1".to_owned()))
         );
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.numa.as_mut().unwrap()[0].memory_hotplug = true;
         assert_eq!(
-            ConsoleConfig::parse("pty")?,
-            ConsoleConfig {
-                mode: ConsoleOutputMode::Pty,
-                iommu: false,
-                file: None,
-            }
+            invalid_config.validate(),
+            Err(ValidationError::NumaMemoryHotplugReused(0, 1))
         );
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.memory.hotplug_method = HotplugMethod::VirtioMem;
         assert_eq!(
-            ConsoleConfig::parse("tty")?,
-            ConsoleConfig {
-                mode: ConsoleOutputMode::Tty,
-                iommu: false,
-                file: None,
-            }
+            invalid_config.validate(),
+            Err(ValidationError::NumaMemoryHotplugDisabled)
         );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.fs = Some(vec![FsConfig {
+            ..Default::default()
+        }]);
         assert_eq!(
-            ConsoleConfig::parse("null")?,
-            ConsoleConfig {
-                mode: ConsoleOutputMode::Null,
-                iommu: false,
-                file: None,
-            }
+            invalid_config.validate(),
+            Err(ValidationError::VhostUserRequiresSharedMemory)
         );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory.shared = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory.hugepages = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory.hugepages = true;
+        still_valid_config.memory.hugepage_size = Some(2 << 20);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.hugepages = false;
+        invalid_config.memory.hugepage_size = Some(2 << 20);
         assert_eq!(
-            ConsoleConfig::parse("file=/tmp/console")?,
-            ConsoleConfig {
-                mode: ConsoleOutputMode::File,
-                iommu: false,
-                file: Some(PathBuf::from("/tmp/console"))
-            }
+            invalid_config.validate(),
+            Err(ValidationError::HugePageSizeWithoutHugePages)
         );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.hugepages = true;
+        invalid_config.memory.hugepage_size = Some(3 << 20);
         assert_eq!(
-            ConsoleConfig::parse("null,iommu=on")?,
-            ConsoleConfig {
-                mode: ConsoleOutputMode::Null,
-                iommu: true,
-                file: None,
-            }
+            invalid_config.validate(),
+            Err(ValidationError::InvalidHugePageSize(3 << 20))
         );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.memory.hugepages = true;
+        still_valid_config.memory.hugepage_pool = true;
+        still_valid_config.memory.hugepage_pool_limit = Some(1 << 30);
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.hugepage_pool = true;
         assert_eq!(
-            ConsoleConfig::parse("file=/tmp/console,iommu=on")?,
-            ConsoleConfig {
-                mode: ConsoleOutputMode::File,
-                iommu: true,
-                file: Some(PathBuf::from("/tmp/console"))
-            }
+            invalid_config.validate(),
+            Err(ValidationError::HugepagePoolWithoutHugePages)
         );
-        Ok(())
-    }
 
-    #[test]
-    fn test_device_parsing() -> Result<()> {
-        // Device must have a path provided
-        assert!(DeviceConfig::parse("").is_err());
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.hugepages = true;
+        invalid_config.memory.hugepage_pool_limit = Some(1 << 30);
         assert_eq!(
-            DeviceConfig::parse("path=/path/to/device")?,
-            DeviceConfig {
-                path: PathBuf::from("/path/to/device"),
-                id: None,
-                iommu: false,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::HugepagePoolLimitWithoutHugepagePool)
         );
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.platform = Some(PlatformConfig {
+            num_pci_segments: 16,
+            ..Default::default()
+        });
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.platform = Some(PlatformConfig {
+            num_pci_segments: 17,
+            ..Default::default()
+        });
         assert_eq!(
-            DeviceConfig::parse("path=/path/to/device,iommu=on")?,
-            DeviceConfig {
-                path: PathBuf::from("/path/to/device"),
-                id: None,
-                iommu: true,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::InvalidNumPciSegments(17))
         );
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.platform = Some(PlatformConfig {
+            num_pci_segments: 16,
+            iommu_segments: Some(vec![1, 2, 3]),
+            ..Default::default()
+        });
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.platform = Some(PlatformConfig {
+            num_pci_segments: 16,
+            iommu_segments: Some(vec![17, 18]),
+            ..Default::default()
+        });
         assert_eq!(
-            DeviceConfig::parse("path=/path/to/device,iommu=on,id=mydevice0")?,
-            DeviceConfig {
-                path: PathBuf::from("/path/to/device"),
-                id: Some("mydevice0".to_owned()),
-                iommu: true,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::InvalidPciSegment(17))
         );
 
-        Ok(())
-    }
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.platform = Some(PlatformConfig {
+            uuid: Some("4e0ba8d2-6a8c-4f11-9d7e-1c2b3a4d5e6f".to_owned()),
+            ..Default::default()
+        });
+        assert!(still_valid_config.validate().is_ok());
 
-    #[test]
-    fn test_vdpa_parsing() -> Result<()> {
-        // path is required
-        assert!(VdpaConfig::parse("").is_err());
+        let mut invalid_config = valid_config.clone();
+        invalid_config.platform = Some(PlatformConfig {
+            uuid: Some("not-a-uuid".to_owned()),
+            ..Default::default()
+        });
         assert_eq!(
-            VdpaConfig::parse("path=/dev/vhost-vdpa")?,
-            VdpaConfig {
-                path: PathBuf::from("/dev/vhost-vdpa"),
-                num_queues: 1,
-                id: None,
+            invalid_config.validate(),
+            Err(ValidationError::InvalidUuid("not-a-uuid".to_owned()))
+        );
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.platform = Some(PlatformConfig {
+                mmio_hotplug_slots: 17,
                 ..Default::default()
-            }
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidMmioHotplugSlots(17))
+            );
+        }
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.acpi = Some(AcpiConfig {
+            oem_table_ids: vec![AcpiOemTableIdConfig {
+                signature: "FAC".to_owned(),
+                oem_table_id: "MYFACP".to_owned(),
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidAcpiTableSignature("FAC".to_owned()))
         );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.acpi = Some(AcpiConfig {
+            oem_table_ids: vec![AcpiOemTableIdConfig {
+                signature: "FACP".to_owned(),
+                oem_table_id: "TOOLONGID".to_owned(),
+            }],
+            ..Default::default()
+        });
         assert_eq!(
-            VdpaConfig::parse("path=/dev/vhost-vdpa,num_queues=2,id=my_vdpa")?,
-            VdpaConfig {
-                path: PathBuf::from("/dev/vhost-vdpa"),
-                num_queues: 2,
-                id: Some("my_vdpa".to_owned()),
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::InvalidAcpiOemTableId(
+                "TOOLONGID".to_owned()
+            ))
         );
-        Ok(())
-    }
 
-    #[test]
-    fn test_vsock_parsing() -> Result<()> {
-        // socket and cid is required
-        assert!(VsockConfig::parse("").is_err());
+        let mut invalid_config = valid_config.clone();
+        invalid_config.security = Some(SecurityConfig {
+            require_iommu: true,
+            ..Default::default()
+        });
         assert_eq!(
-            VsockConfig::parse("socket=/tmp/sock,cid=1")?,
-            VsockConfig {
-                cid: 1,
-                socket: PathBuf::from("/tmp/sock"),
-                iommu: false,
-                id: None,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::IommuRequired("rng".to_owned()))
         );
+
+        let mut still_valid_config = invalid_config.clone();
+        still_valid_config.rng.iommu = true;
+        still_valid_config.console.iommu = true;
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.fs = Some(vec![FsConfig {
+            id: Some("myfs0".to_owned()),
+            ..Default::default()
+        }]);
         assert_eq!(
-            VsockConfig::parse("socket=/tmp/sock,cid=1,iommu=on")?,
-            VsockConfig {
-                cid: 1,
-                socket: PathBuf::from("/tmp/sock"),
-                iommu: true,
-                id: None,
-                ..Default::default()
-            }
+            invalid_config.validate(),
+            Err(ValidationError::IommuRequired("fs (myfs0)".to_owned()))
         );
-        Ok(())
-    }
 
-    #[test]
-    fn test_config_validation() {
-        let mut valid_config = VmConfig {
-            cpus: CpusConfig {
-                boot_vcpus: 1,
-                max_vcpus: 1,
-                ..Default::default()
-            },
-            memory: MemoryConfig {
-                size: 536_870_912,
-                mergeable: false,
-                hotplug_method: HotplugMethod::Acpi,
-                hotplug_size: None,
-                hotplugged_size: None,
-                shared: false,
-                hugepages: false,
-                hugepage_size: None,
-                prefault: false,
-                zones: None,
-            },
-            kernel: Some(KernelConfig {
-                path: PathBuf::from("/path/to/kernel"),
-            }),
-            initramfs: None,
-            cmdline: CmdlineConfig {
-                args: String::from(""),
-            },
-            disks: None,
-            net: None,
-            rng: RngConfig {
-                src: PathBuf::from("/dev/urandom"),
-                iommu: false,
-            },
-            balloon: None,
-            fs: None,
-            pmem: None,
-            serial: ConsoleConfig {
-                file: None,
-                mode: ConsoleOutputMode::Null,
-                iommu: false,
-            },
-            console: ConsoleConfig {
-                file: None,
-                mode: ConsoleOutputMode::Tty,
-                iommu: false,
-            },
-            devices: None,
-            user_devices: None,
-            vdpa: None,
-            vsock: None,
-            iommu: false,
-            #[cfg(target_arch = "x86_64")]
-            sgx_epc: None,
-            numa: None,
-            watchdog: false,
-            #[cfg(feature = "tdx")]
-            tdx: None,
-            #[cfg(feature = "gdb")]
-            gdb: false,
-            platform: None,
-        };
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.deterministic = Some(DeterministicConfig { seed: 1 });
+        #[cfg(target_arch = "x86_64")]
+        {
+            still_valid_config.cpus.tsc_khz = Some(2_000_000);
+        }
+        assert!(still_valid_config.validate().is_ok());
 
-        assert!(valid_config.validate().is_ok());
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory =
+            MemoryConfig::parse("size=0", Some(vec!["id=mem0,size=1G,template=on"])).unwrap();
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemoryZoneTemplateFileMissing(
+                "mem0".to_owned()
+            ))
+        );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.serial.mode = ConsoleOutputMode::Tty;
-        invalid_config.console.mode = ConsoleOutputMode::Tty;
+        invalid_config.memory = MemoryConfig::parse(
+            "size=0",
+            Some(vec![
+                "id=mem0,size=1G,file=/path/to/template,template=on,shared=on",
+            ]),
+        )
+        .unwrap();
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::DoubleTtyMode)
+            Err(ValidationError::MemoryZoneTemplateIncompatible(
+                "mem0".to_owned()
+            ))
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.kernel = None;
+        invalid_config.memory = MemoryConfig::parse(
+            "size=0",
+            Some(vec!["id=mem0,size=1G,file=/dev/shm/mem0,sealed=on"]),
+        )
+        .unwrap();
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::KernelMissing)
+            Err(ValidationError::MemoryZoneSealedWithFile("mem0".to_owned()))
+        );
+        invalid_config.memory =
+            MemoryConfig::parse("size=0", Some(vec!["id=mem0,size=1G,sealed=on"])).unwrap();
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemoryZoneSealedNotShared(
+                "mem0".to_owned()
+            ))
         );
+        invalid_config.memory = MemoryConfig::parse(
+            "size=0,shared=on,sealed=on",
+            Some(vec!["id=mem0,size=1G,shared=on,sealed=on"]),
+        )
+        .unwrap();
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemorySealedWithZones)
+        );
+        invalid_config.memory = MemoryConfig::parse("size=1G,sealed=on", None).unwrap();
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MemorySealedNotShared)
+        );
+        invalid_config.memory = MemoryConfig::parse("size=1G,shared=on,sealed=on", None).unwrap();
+        assert!(invalid_config.validate().is_ok());
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.serial.mode = ConsoleOutputMode::File;
-        invalid_config.serial.file = None;
+        invalid_config.snapshot_schedule =
+            Some(SnapshotScheduleConfig::parse("destination=/tmp,interval=0").unwrap());
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::ConsoleFileMissing)
+            Err(ValidationError::InvalidSnapshotScheduleInterval)
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.cpus.max_vcpus = 16;
-        invalid_config.cpus.boot_vcpus = 32;
+        invalid_config.maintenance = Some(MaintenanceConfig::parse("lease=0").unwrap());
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidMaintenanceLease)
+        );
+        invalid_config.maintenance = Some(MaintenanceConfig {
+            lease: u64::MAX,
+            ..Default::default()
+        });
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::CpusMaxLowerThanBoot)
+            Err(ValidationError::InvalidMaintenanceLease)
         );
-
-        let mut invalid_config = valid_config.clone();
-        invalid_config.cpus.max_vcpus = 16;
-        invalid_config.cpus.boot_vcpus = 16;
-        invalid_config.cpus.topology = Some(CpuTopology {
-            threads_per_core: 2,
-            cores_per_die: 8,
-            dies_per_package: 1,
-            packages: 2,
-        });
+        invalid_config.maintenance =
+            Some(MaintenanceConfig::parse("checkpoint=/run/ch/../../etc/passwd").unwrap());
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::CpuTopologyCount)
+            Err(ValidationError::InvalidMaintenanceCheckpoint(
+                PathBuf::from("/run/ch/../../etc/passwd")
+            ))
+        );
+        invalid_config.maintenance =
+            Some(MaintenanceConfig::parse("checkpoint=vm0.maintenance").unwrap());
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidMaintenanceCheckpoint(
+                PathBuf::from("vm0.maintenance")
+            ))
         );
 
         let mut invalid_config = valid_config.clone();
         invalid_config.disks = Some(vec![DiskConfig {
-            vhost_socket: Some("/path/to/sock".to_owned()),
             path: Some(PathBuf::from("/path/to/image")),
+            interrupt_coalescing: Some(InterruptCoalescingConfig {
+                max_events: 32,
+                usecs: 0,
+            }),
             ..Default::default()
         }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::DiskSocketAndPath)
+            Err(ValidationError::InvalidInterruptCoalescingUsecs)
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.memory.shared = true;
-        invalid_config.disks = Some(vec![DiskConfig {
-            vhost_user: true,
+        invalid_config.net = Some(vec![NetConfig {
+            interrupt_coalescing: Some(InterruptCoalescingConfig {
+                max_events: 0,
+                usecs: 0,
+            }),
             ..Default::default()
         }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::VhostUserMissingSocket)
+            Err(ValidationError::InvalidInterruptCoalescingUsecs)
         );
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.disks = Some(vec![DiskConfig {
-            vhost_user: true,
-            vhost_socket: Some("/path/to/sock".to_owned()),
-            ..Default::default()
-        }]);
+        invalid_config.virtio_features = Some(vec![
+            VirtioFeaturesConfig::parse("id=_disk0,disable=[28,29]").unwrap(),
+            VirtioFeaturesConfig::parse("id=_disk0,enable=[27]").unwrap(),
+        ]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::VhostUserRequiresSharedMemory)
+            Err(ValidationError::DuplicateVirtioFeatures(
+                "_disk0".to_owned()
+            ))
         );
-
-        let mut still_valid_config = valid_config.clone();
-        still_valid_config.disks = Some(vec![DiskConfig {
-            vhost_user: true,
-            vhost_socket: Some("/path/to/sock".to_owned()),
+        invalid_config.virtio_features = Some(vec![VirtioFeaturesConfig::parse(
+            "id=_disk0,disable=[28,29],enable=[29]",
+        )
+        .unwrap()]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VirtioFeaturesConflict(
+                "_disk0".to_owned(),
+                1 << 29
+            ))
+        );
+        invalid_config.virtio_features = Some(vec![VirtioFeaturesConfig::parse(
+            "id=_disk0,disable=[version_1]",
+        )
+        .unwrap()]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VirtioFeaturesVersion1("_disk0".to_owned()))
+        );
+        invalid_config.virtio_features =
+            Some(vec![
+                VirtioFeaturesConfig::parse("id=disk1,disable=[28]").unwrap()
+            ]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::UnknownVirtioFeaturesDevice(
+                "disk1".to_owned()
+            ))
+        );
+        invalid_config.disks = Some(vec![DiskConfig {
+            path: Some(PathBuf::from("/path/to/image")),
+            id: Some("disk1".to_owned()),
             ..Default::default()
         }]);
-        still_valid_config.memory.shared = true;
-        assert!(still_valid_config.validate().is_ok());
+        assert!(invalid_config.validate().is_ok());
 
+        let irq = DEVICE_IRQS.start;
         let mut invalid_config = valid_config.clone();
-        invalid_config.net = Some(vec![NetConfig {
-            vhost_user: true,
+        invalid_config.irqs = Some(IrqConfig {
+            reserved: vec![DEVICE_IRQS.end],
             ..Default::default()
-        }]);
+        });
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::VhostUserRequiresSharedMemory)
+            Err(ValidationError::InvalidIrq(DEVICE_IRQS.end))
         );
-
-        let mut still_valid_config = valid_config.clone();
-        still_valid_config.net = Some(vec![NetConfig {
-            vhost_user: true,
-            vhost_socket: Some("/path/to/sock".to_owned()),
+        invalid_config.irqs = Some(IrqConfig {
+            pins: vec![IrqPinConfig {
+                id: "__serial".to_owned(),
+                irq,
+            }],
+            reserved: vec![irq],
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DuplicateIrq(irq))
+        );
+        invalid_config.irqs = Some(IrqConfig {
+            pins: vec![
+                IrqPinConfig {
+                    id: "__serial".to_owned(),
+                    irq,
+                },
+                IrqPinConfig {
+                    id: "__serial".to_owned(),
+                    irq: irq + 1,
+                },
+            ],
             ..Default::default()
-        }]);
-        still_valid_config.memory.shared = true;
-        assert!(still_valid_config.validate().is_ok());
-
-        let mut invalid_config = valid_config.clone();
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::DuplicateIrqPin("__serial".to_owned()))
+        );
+        invalid_config.irqs = Some(IrqConfig {
+            pins: vec![IrqPinConfig {
+                id: "net1".to_owned(),
+                irq,
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::UnknownIrqPinDevice("net1".to_owned()))
+        );
         invalid_config.net = Some(vec![NetConfig {
-            fds: Some(vec![0]),
+            id: Some("net1".to_owned()),
             ..Default::default()
         }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::VnetReservedFd)
+            Err(ValidationError::IrqPinUnsupportedDevice("net1".to_owned()))
+        );
+        #[cfg(target_arch = "aarch64")]
+        {
+            invalid_config.net.as_mut().unwrap()[0].transport = VirtioTransportType::Mmio;
+            assert!(invalid_config.validate().is_ok());
+        }
+        invalid_config.irqs = Some(IrqConfig {
+            pins: vec![IrqPinConfig {
+                id: "__serial".to_owned(),
+                irq,
+            }],
+            ..Default::default()
+        });
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::IrqPinSerial)
         );
+        #[cfg(target_arch = "aarch64")]
+        assert!(invalid_config.validate().is_ok());
 
         let mut invalid_config = valid_config.clone();
-        invalid_config.fs = Some(vec![FsConfig {
-            ..Default::default()
+        invalid_config.doorbells = Some(vec![DoorbellConfig {
+            fds: vec![3, 4, 5],
+            id: None,
         }]);
+        #[cfg(target_arch = "aarch64")]
+        {
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidDoorbellChannels(3))
+            );
+            invalid_config.doorbells = Some(vec![DoorbellConfig {
+                fds: vec![2, 3],
+                id: None,
+            }]);
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::DoorbellReservedFd)
+            );
+            let mut still_valid_config = valid_config.clone();
+            still_valid_config.doorbells = Some(vec![DoorbellConfig {
+                fds: vec![3, 4],
+                id: None,
+            }]);
+            assert!(still_valid_config.validate().is_ok());
+        }
+        #[cfg(target_arch = "x86_64")]
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::VhostUserRequiresSharedMemory)
+            Err(ValidationError::DoorbellUnsupported)
         );
 
-        let mut still_valid_config = valid_config.clone();
-        still_valid_config.memory.shared = true;
-        assert!(still_valid_config.validate().is_ok());
-
-        let mut still_valid_config = valid_config.clone();
-        still_valid_config.memory.hugepages = true;
-        assert!(still_valid_config.validate().is_ok());
-
-        let mut still_valid_config = valid_config.clone();
-        still_valid_config.memory.hugepages = true;
-        still_valid_config.memory.hugepage_size = Some(2 << 20);
-        assert!(still_valid_config.validate().is_ok());
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = still_valid_config.clone();
+            invalid_config.cpus.tsc_khz = None;
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::DeterministicTscKhzMissing)
+            );
+        }
 
-        let mut invalid_config = valid_config.clone();
-        invalid_config.memory.hugepages = false;
-        invalid_config.memory.hugepage_size = Some(2 << 20);
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.cpus.boot_vcpus = 2;
+        invalid_config.cpus.max_vcpus = 2;
+        invalid_config.disks = Some(vec![DiskConfig {
+            id: Some("mydisk0".to_owned()),
+            num_queues: 2,
+            ..Default::default()
+        }]);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::HugePageSizeWithoutHugePages)
+            Err(ValidationError::DeterministicMultiQueue(
+                "disk (mydisk0)".to_owned()
+            ))
         );
 
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.debug_console = Some(DebugConsoleConfig {
+                mode: DebugConsoleOutputMode::Log,
+                file: None,
+                iobase: Some(0x1_0000),
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::InvalidDebugConsoleIobase(0x1_0000))
+            );
+        }
+
         let mut invalid_config = valid_config.clone();
-        invalid_config.memory.hugepages = true;
-        invalid_config.memory.hugepage_size = Some(3 << 20);
+        invalid_config.debug_console = Some(DebugConsoleConfig {
+            mode: DebugConsoleOutputMode::File,
+            file: None,
+            iobase: None,
+        });
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::InvalidHugePageSize(3 << 20))
+            Err(ValidationError::DebugConsoleFileMissing)
         );
 
         let mut still_valid_config = valid_config.clone();
-        still_valid_config.platform = Some(PlatformConfig {
-            num_pci_segments: 16,
-            ..Default::default()
-        });
+        still_valid_config.devices = Some(vec![
+            DeviceConfig {
+                path: "/device1".into(),
+                id: Some("gpu0".to_owned()),
+                ..Default::default()
+            },
+            DeviceConfig {
+                path: "/device2".into(),
+                multifunction_of: Some("gpu0".to_owned()),
+                function: Some(1),
+                ..Default::default()
+            },
+        ]);
         assert!(still_valid_config.validate().is_ok());
 
-        let mut invalid_config = valid_config.clone();
-        invalid_config.platform = Some(PlatformConfig {
-            num_pci_segments: 17,
-            ..Default::default()
-        });
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.devices.as_mut().unwrap()[1].function = Some(8);
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::InvalidNumPciSegments(17))
+            Err(ValidationError::InvalidPciFunction(8))
         );
 
-        let mut still_valid_config = valid_config.clone();
-        still_valid_config.platform = Some(PlatformConfig {
-            num_pci_segments: 16,
-            iommu_segments: Some(vec![1, 2, 3]),
-            ..Default::default()
-        });
-        assert!(still_valid_config.validate().is_ok());
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.devices.as_mut().unwrap()[1].function = None;
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::MultifunctionIncomplete)
+        );
 
-        let mut invalid_config = valid_config.clone();
-        invalid_config.platform = Some(PlatformConfig {
-            num_pci_segments: 16,
-            iommu_segments: Some(vec![17, 18]),
-            ..Default::default()
-        });
+        let mut invalid_config = still_valid_config.clone();
+        invalid_config.devices.as_mut().unwrap()[1].multifunction_of = Some("gpu1".to_owned());
         assert_eq!(
             invalid_config.validate(),
-            Err(ValidationError::InvalidPciSegment(17))
+            Err(ValidationError::InvalidMultifunctionDevice(
+                "gpu1".to_owned()
+            ))
         );
 
         let mut still_valid_config = valid_config.clone();
@@ -3645,6 +8463,106 @@ mod tests {
             Err(ValidationError::OnIommuSegment(1))
         );
 
+        let mut invalid_config = valid_config.clone();
+        invalid_config.gpu = Some(GpuConfig {
+            width: 0,
+            ..Default::default()
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidGpuResolution(0, DEFAULT_GPU_HEIGHT))
+        );
+
+        let mut mmio_config = valid_config.clone();
+        mmio_config.vsock = Some(VsockConfig {
+            transport: VirtioTransportType::Mmio,
+            ..Default::default()
+        });
+        #[cfg(target_arch = "aarch64")]
+        assert!(mmio_config.validate().is_ok());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            mmio_config.validate(),
+            Err(ValidationError::VirtioMmioUnsupported)
+        );
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let mut invalid_config = valid_config.clone();
+            invalid_config.vsock = Some(VsockConfig {
+                iommu: true,
+                transport: VirtioTransportType::Mmio,
+                ..Default::default()
+            });
+            assert_eq!(
+                invalid_config.validate(),
+                Err(ValidationError::VirtioMmioIommu)
+            );
+        }
+
+        let mut mmio_config = valid_config.clone();
+        mmio_config.memory.hotplug_method = HotplugMethod::VirtioMem;
+        mmio_config.memory.hotplug_size = Some(1 << 30);
+        mmio_config.memory.virtio_mem_transport = VirtioTransportType::Mmio;
+        #[cfg(target_arch = "aarch64")]
+        assert!(mmio_config.validate().is_ok());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            mmio_config.validate(),
+            Err(ValidationError::VirtioMmioUnsupported)
+        );
+        #[cfg(target_arch = "aarch64")]
+        {
+            mmio_config.memory.hotplug_method = HotplugMethod::Acpi;
+            assert_eq!(
+                mmio_config.validate(),
+                Err(ValidationError::VirtioMemTransportWithoutVirtioMem)
+            );
+        }
+
+        let mut mmio_config = valid_config.clone();
+        mmio_config.memory.shared = true;
+        mmio_config.fs = Some(vec![FsConfig {
+            dax: true,
+            transport: VirtioTransportType::Mmio,
+            ..Default::default()
+        }]);
+        #[cfg(target_arch = "aarch64")]
+        assert!(mmio_config.validate().is_ok());
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            mmio_config.validate(),
+            Err(ValidationError::VirtioMmioUnsupported)
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.memory.shared = true;
+        invalid_config.fs = Some(vec![FsConfig {
+            dax: true,
+            cache_size: 3 << 20,
+            ..Default::default()
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidFsCacheSize(3 << 20))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.net = Some(vec![NetConfig {
+            transport: VirtioTransportType::Mmio,
+            ..Default::default()
+        }]);
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VirtioMmioNetVhostUserOnly)
+        );
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::VirtioMmioUnsupported)
+        );
+
         let mut invalid_config = valid_config.clone();
         invalid_config.memory.shared = true;
         invalid_config.platform = Some(PlatformConfig {
@@ -3676,6 +8594,80 @@ mod tests {
             Err(ValidationError::OnIommuSegment(1))
         );
 
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.pstore = Some(PstoreConfig {
+            file: PathBuf::from("/tmp/pstore"),
+            size: 128 << 10,
+        });
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.pstore = Some(PstoreConfig {
+            file: PathBuf::from("/tmp/pstore"),
+            size: 64 << 10,
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidPstoreSize(64 << 10))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.pstore = Some(PstoreConfig {
+            file: PathBuf::from("/tmp/pstore"),
+            size: (128 << 10) + 4096,
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidPstoreSize((128 << 10) + 4096))
+        );
+
+        let mut still_valid_config = valid_config.clone();
+        still_valid_config.crashkernel = Some(CrashKernelConfig { size: 128 << 20 });
+        assert!(still_valid_config.validate().is_ok());
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.crashkernel = Some(CrashKernelConfig { size: 0 });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidCrashKernelSize(0))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.crashkernel = Some(CrashKernelConfig {
+            size: invalid_config.memory.size,
+        });
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidCrashKernelSize(
+                invalid_config.memory.size
+            ))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.payload_blobs = Some(vec![PayloadBlobConfig {
+            path: PathBuf::from("/path/to/blob"),
+            address: Some(0x1234),
+            id: None,
+        }]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::InvalidPayloadBlobAddress(0x1234))
+        );
+
+        let mut invalid_config = valid_config.clone();
+        invalid_config.payload_blobs = Some(vec![
+            PayloadBlobConfig {
+                path: PathBuf::from("/path/to/blob"),
+                address: None,
+                id: None,
+            };
+            MAX_PAYLOAD_BLOBS + 1
+        ]);
+        assert_eq!(
+            invalid_config.validate(),
+            Err(ValidationError::TooManyPayloadBlobs(MAX_PAYLOAD_BLOBS + 1))
+        );
+
         let mut invalid_config = valid_config;
         invalid_config.memory.shared = true;
         invalid_config.platform = Some(PlatformConfig {
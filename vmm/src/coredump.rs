@@ -3,14 +3,28 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use crate::api::VmCoredumpData;
+use crate::GuestMemoryMmap;
 #[cfg(target_arch = "x86_64")]
 use hypervisor::kvm::kvm_bindings::kvm_dtable as DTableRegister;
 #[cfg(target_arch = "x86_64")]
 use hypervisor::x86_64::SegmentRegister;
 use linux_loader::elf;
 use std::fs::File;
-use std::io::Write;
-use vm_memory::ByteValued;
+use std::io::{Seek, SeekFrom, Write};
+use vm_memory::{ByteValued, Bytes, GuestAddress};
+use vm_migration::protocol::MemoryRange;
+
+/// Granularity of the zero pages left as holes in a sparse dump.
+const COREDUMP_PAGE_SIZE: usize = 4096;
+/// Guest memory read at once while dumping it.
+const COREDUMP_CHUNK_SIZE: usize = 1 << 20;
+/// Free ranges shorter than this are kept when excluding the free pages, so
+/// that the number of program headers stays bounded.
+pub const COREDUMP_MIN_EXCLUDED_SIZE: u64 = 2 << 20;
+/// Maximum number of load segments the guest memory is split into when
+/// excluding the free pages.
+pub const COREDUMP_MAX_LOAD_SEGMENTS: u64 = 32768;
 
 #[derive(Clone)]
 pub struct CoredumpMemoryRegion {
@@ -23,6 +37,27 @@ pub struct CoredumpMemoryRegions {
     pub ram_maps: std::collections::BTreeMap<u64, CoredumpMemoryRegion>,
 }
 
+impl CoredumpMemoryRegions {
+    /// Lay the memory ranges out one after the other in the dump, from
+    /// `mem_offset`.
+    pub fn new(ranges: &[MemoryRange], mem_offset: u64) -> Self {
+        let mut mem_offset_in_elf = mem_offset;
+        let mut ram_maps = std::collections::BTreeMap::new();
+        for range in ranges.iter() {
+            ram_maps.insert(
+                range.gpa,
+                CoredumpMemoryRegion {
+                    mem_offset_in_elf,
+                    mem_size: range.length,
+                },
+            );
+            mem_offset_in_elf += range.length;
+        }
+
+        CoredumpMemoryRegions { ram_maps }
+    }
+}
+
 /// Platform information
 #[derive(Default)]
 pub struct DumpState {
@@ -32,6 +67,10 @@ pub struct DumpState {
     pub mem_offset: u64,
     pub mem_info: Option<CoredumpMemoryRegions>,
     pub file: Option<File>,
+    /// Leave holes in the file for the zero pages.
+    pub sparse: bool,
+    /// File the dump is written to compressed with zstd once complete.
+    pub destination: Option<File>,
 }
 
 #[derive(Debug)]
@@ -43,12 +82,107 @@ pub enum GuestDebuggableError {
 pub trait GuestDebuggable: vm_migration::Pausable {
     fn coredump(
         &mut self,
-        _destination_url: &str,
+        _coredump_data: &VmCoredumpData,
     ) -> std::result::Result<(), GuestDebuggableError> {
         Ok(())
     }
 }
 
+fn is_zero_page(page: &[u8]) -> bool {
+    page.iter().all(|b| *b == 0)
+}
+
+/// Split the sorted ranges of guest memory around the sorted `excluded`
+/// ranges of at least `min_size` bytes.
+pub fn exclude_memory_ranges(
+    ranges: &[MemoryRange],
+    excluded: &[MemoryRange],
+    min_size: u64,
+) -> Vec<MemoryRange> {
+    let mut filtered_ranges = Vec::new();
+
+    for range in ranges.iter() {
+        let end = range.gpa + range.length;
+        let mut kept_start = range.gpa;
+        for free in excluded.iter().filter(|r| r.length >= min_size) {
+            let free_start = std::cmp::max(free.gpa, kept_start);
+            let free_end = std::cmp::min(free.gpa + free.length, end);
+            if free_start >= free_end {
+                continue;
+            }
+            if free_start > kept_start {
+                filtered_ranges.push(MemoryRange {
+                    gpa: kept_start,
+                    length: free_start - kept_start,
+                });
+            }
+            kept_start = free_end;
+        }
+        if end > kept_start {
+            filtered_ranges.push(MemoryRange {
+                gpa: kept_start,
+                length: end - kept_start,
+            });
+        }
+    }
+
+    filtered_ranges
+}
+
+/// Write `length` bytes of guest memory from `gpa` at the current position
+/// of the file, seeking over the zero pages rather than writing them when
+/// `sparse` is set. Returns the number of bytes written.
+pub fn write_guest_memory<W: Write + Seek>(
+    guest_memory: &GuestMemoryMmap,
+    file: &mut W,
+    gpa: u64,
+    length: u64,
+    sparse: bool,
+) -> std::result::Result<u64, GuestDebuggableError> {
+    let mut buf = vec![0u8; COREDUMP_CHUNK_SIZE];
+    let mut written = 0;
+    let mut offset = 0;
+
+    while offset < length {
+        let len = std::cmp::min(COREDUMP_CHUNK_SIZE as u64, length - offset) as usize;
+        guest_memory
+            .read_slice(&mut buf[..len], GuestAddress(gpa + offset))
+            .map_err(|e| GuestDebuggableError::Coredump(e.into()))?;
+
+        if sparse {
+            // Consecutive non zero pages are written at once.
+            let mut data_start = None;
+            for (i, page) in buf[..len].chunks(COREDUMP_PAGE_SIZE).enumerate() {
+                let page_offset = i * COREDUMP_PAGE_SIZE;
+                if !is_zero_page(page) {
+                    data_start.get_or_insert(page_offset);
+                    continue;
+                }
+                if let Some(start) = data_start.take() {
+                    file.write_all(&buf[start..page_offset])
+                        .map_err(GuestDebuggableError::CoredumpFile)?;
+                    written += (page_offset - start) as u64;
+                }
+                file.seek(SeekFrom::Current(page.len() as i64))
+                    .map_err(GuestDebuggableError::CoredumpFile)?;
+            }
+            if let Some(start) = data_start {
+                file.write_all(&buf[start..len])
+                    .map_err(GuestDebuggableError::CoredumpFile)?;
+                written += (len - start) as u64;
+            }
+        } else {
+            file.write_all(&buf[..len])
+                .map_err(GuestDebuggableError::CoredumpFile)?;
+            written += len as u64;
+        }
+
+        offset += len as u64;
+    }
+
+    Ok(written)
+}
+
 #[macro_export]
 macro_rules! div_round_up {
     ($n:expr,$d:expr) => {
@@ -346,3 +480,31 @@ pub trait CpuElf64Writable {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclude_memory_ranges() {
+        let page = COREDUMP_PAGE_SIZE as u64;
+        let range = |gpa: u64, length: u64| MemoryRange {
+            gpa: gpa * page,
+            length: length * page,
+        };
+        let ranges = [range(0, 64), range(128, 16)];
+
+        // Only the free ranges of at least 8 pages are left out, and a free
+        // range covering a whole memory range drops it entirely.
+        let excluded = [range(4, 2), range(10, 8), range(60, 72), range(140, 8)];
+        assert_eq!(
+            exclude_memory_ranges(&ranges, &excluded, 8 * page),
+            vec![range(0, 10), range(18, 42), range(132, 8)]
+        );
+        assert_eq!(
+            exclude_memory_ranges(&ranges, &[range(0, 64), range(128, 16)], page),
+            vec![]
+        );
+        assert_eq!(exclude_memory_ranges(&ranges, &[], page), ranges.to_vec());
+    }
+}
@@ -11,6 +11,7 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+use crate::cgroup::VcpusCgroup;
 use crate::config::CpusConfig;
 #[cfg(feature = "guest_debug")]
 use crate::coredump::{
@@ -22,6 +23,7 @@ use crate::device_manager::DeviceManager;
 #[cfg(feature = "gdb")]
 use crate::gdb::{get_raw_tid, Debuggable, DebuggableError};
 use crate::memory_manager::MemoryManager;
+use crate::priority::{self, HostCpuClaim, PriorityProfile, SchedPolicy};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
 #[cfg(target_arch = "x86_64")]
 use crate::vm::physical_bits;
@@ -31,6 +33,8 @@ use acpi_tables::{aml, aml::Aml, sdt::Sdt};
 use anyhow::anyhow;
 use arch::EntryPoint;
 use arch::NumaNodes;
+#[cfg(target_arch = "aarch64")]
+use devices::gic::Gic;
 use devices::interrupt_controller::InterruptController;
 #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
 use gdbstub_arch::x86::reg::{X86SegmentRegs, X86_64CoreRegs};
@@ -46,20 +50,23 @@ use hypervisor::x86_64::CpuId;
 use hypervisor::x86_64::{MsrEntries, MsrEntry};
 #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
 use hypervisor::x86_64::{SpecialRegisters, StandardRegisters};
-use hypervisor::{CpuState, HypervisorCpuError, VmExit, VmOps};
+use hypervisor::{CpuState, HypervisorCpuError, HypervisorVmError, VmExit, VmOps};
 use libc::{c_void, siginfo_t};
 #[cfg(feature = "guest_debug")]
 use linux_loader::elf::Elf64_Nhdr;
 use seccompiler::{apply_filter, SeccompAction};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 #[cfg(feature = "guest_debug")]
 use std::io::Write;
 #[cfg(feature = "guest_debug")]
 use std::mem::size_of;
+use std::num::Wrapping;
 use std::os::unix::thread::JoinHandleExt;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
-use std::{cmp, io, result, thread};
+use std::time::{Duration, Instant};
+use std::{cmp, fs, io, result, thread};
 use thiserror::Error;
 use vm_device::BusDevice;
 #[cfg(feature = "guest_debug")]
@@ -76,6 +83,20 @@ use vmm_sys_util::signal::{register_signal_handler, SIGRTMIN};
 
 pub const CPU_MANAGER_ACPI_SIZE: usize = 0xc;
 
+// Highest share of the vCPU time that can be taken away by throttling. The
+// vCPUs always keep making some progress, and the time they spend sleeping
+// between two runs, which delays a pause request, remains bounded.
+pub const MAX_VCPU_THROTTLE_PERCENTAGE: u8 = 90;
+
+// Period of the timer kicking a throttled vCPU out of the guest, so that a
+// guest which never exits to the VMM gets throttled as well.
+const VCPU_THROTTLE_PERIOD: Duration = Duration::from_millis(10);
+
+// vCPU feature giving the guest the EL2, from the Linux UAPI, which the
+// kvm-bindings don't provide yet.
+#[cfg(target_arch = "aarch64")]
+const KVM_ARM_VCPU_HAS_EL2: u32 = 7;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Error creating vCPU: {0}")]
@@ -125,14 +146,63 @@ pub enum Error {
     #[error("Failed to allocate MMIO address for CpuManager")]
     AllocateMmmioAddress,
 
+    #[error("Error setting up the vCPUs cgroup: {0}")]
+    VcpusCgroup(#[source] io::Error),
+
+    #[error("Error reading the host CPUs the vCPUs can be pinned to: {0}")]
+    AllowedHostCpus(#[source] io::Error),
+
+    #[error("Error claiming the host CPUs the vCPUs are pinned to: {0}")]
+    ClaimHostCpus(#[source] io::Error),
+
+    #[error("Pinning {0} vCPUs requires as many host CPUs, only {1} are free")]
+    NotEnoughHostCpus(u8, usize),
+
+    #[error("Error applying the scheduling policy {0:?} to the vCPUs: {1}")]
+    VcpuPolicy(SchedPolicy, #[source] io::Error),
+
+    #[error("Invalid vCPU throttling percentage: {0}")]
+    InvalidThrottlePercentage(u8),
+
+    #[error("vCPU {0} is not present")]
+    VcpuNotPresent(u8),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error injecting NMI: {0}")]
+    InjectNmi(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(target_arch = "aarch64")]
+    #[error("Invalid SGI: {0}")]
+    InvalidSgi(u8),
+
+    #[cfg(target_arch = "aarch64")]
+    #[error("Error injecting SGI: {0:?}")]
+    InjectSgi(devices::interrupt_controller::Error),
+
+    #[cfg(target_arch = "aarch64")]
+    #[error("Error pausing the vCPUs: {0}")]
+    PauseVcpus(#[source] MigratableError),
+
+    #[cfg(target_arch = "x86_64")]
+    #[error("Error setting the TSC frequency: {0}")]
+    SetTscKhz(#[source] hypervisor::HypervisorCpuError),
+
     #[cfg(feature = "tdx")]
     #[error("Error initializing TDX: {0}")]
     InitializeTdx(#[source] hypervisor::HypervisorCpuError),
 
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    #[error("Error setting vCPU MP state: {0}")]
+    SetMpState(#[source] hypervisor::HypervisorCpuError),
+
     #[cfg(target_arch = "aarch64")]
     #[error("Error initializing PMU: {0}")]
     InitPmu(#[source] hypervisor::HypervisorCpuError),
 
+    #[cfg(target_arch = "aarch64")]
+    #[error("PMU is not supported by the host")]
+    PmuUnsupported,
+
     #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
     #[error("Error during CPU debug: {0}")]
     CpuDebug(#[source] hypervisor::HypervisorCpuError),
@@ -313,11 +383,14 @@ impl Vcpu {
         #[cfg(target_arch = "x86_64")] vm_memory: &GuestMemoryAtomic<GuestMemoryMmap>,
         #[cfg(target_arch = "x86_64")] cpuid: CpuId,
         #[cfg(target_arch = "x86_64")] kvm_hyperv: bool,
+        #[cfg(target_arch = "aarch64")] cpu_model: Option<arch::CpuModel>,
+        #[cfg(target_arch = "aarch64")] pmu: bool,
+        #[cfg(target_arch = "aarch64")] nested: bool,
     ) -> Result<()> {
         #[cfg(target_arch = "aarch64")]
         {
-            self.init(vm)?;
-            self.mpidr = arch::configure_vcpu(&self.vcpu, self.id, kernel_entry_point)
+            self.init(vm, pmu, nested)?;
+            self.mpidr = arch::configure_vcpu(&self.vcpu, self.id, kernel_entry_point, cpu_model)
                 .map_err(Error::VcpuConfiguration)?;
         }
         info!("Configuring vCPU: cpu_id = {}", self.id);
@@ -349,7 +422,7 @@ impl Vcpu {
 
     /// Initializes an aarch64 specific vcpu for booting Linux.
     #[cfg(target_arch = "aarch64")]
-    pub fn init(&self, vm: &Arc<dyn hypervisor::Vm>) -> Result<()> {
+    pub fn init(&self, vm: &Arc<dyn hypervisor::Vm>, pmu: bool, nested: bool) -> Result<()> {
         let mut kvi: kvm_bindings::kvm_vcpu_init = kvm_bindings::kvm_vcpu_init::default();
 
         // This reads back the kernel's preferred target type.
@@ -357,7 +430,19 @@ impl Vcpu {
             .map_err(Error::VcpuArmPreferredTarget)?;
         // We already checked that the capability is supported.
         kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_PSCI_0_2;
-        kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_PMU_V3;
+        if pmu {
+            #[cfg(feature = "kvm")]
+            if !vm.check_extension(hypervisor::kvm::Cap::ArmPmuV3) {
+                return Err(Error::PmuUnsupported);
+            }
+            kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_PMU_V3;
+        }
+        // The vCPUs are reset at EL2, where the guest kernel is entered, and
+        // the init fails if the host doesn't support the nested
+        // virtualization.
+        if nested {
+            kvi.features[0] |= 1 << KVM_ARM_VCPU_HAS_EL2;
+        }
         // Non-boot cpus are powered off initially.
         if self.id > 0 {
             kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_POWER_OFF;
@@ -375,6 +460,7 @@ impl Vcpu {
 }
 
 const VCPU_SNAPSHOT_ID: &str = "vcpu";
+const GUEST_COUNTER_SNAPSHOT_ID: &str = "guest-counter";
 impl Pausable for Vcpu {}
 impl Snapshottable for Vcpu {
     fn id(&self) -> String {
@@ -423,6 +509,7 @@ pub struct CpuManager {
     vm: Arc<dyn hypervisor::Vm>,
     vcpus_kill_signalled: Arc<AtomicBool>,
     vcpus_pause_signalled: Arc<AtomicBool>,
+    vcpus_throttle: Arc<AtomicU8>,
     exit_evt: EventFd,
     #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
     reset_evt: EventFd,
@@ -433,11 +520,19 @@ pub struct CpuManager {
     vcpus: Vec<Arc<Mutex<Vcpu>>>,
     seccomp_action: SeccompAction,
     vm_ops: Arc<dyn VmOps>,
-    #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
     acpi_address: Option<GuestAddress>,
     proximity_domain_per_cpu: BTreeMap<u8, u32>,
     affinity: BTreeMap<u8, Vec<u8>>,
+    vcpus_cgroup: Option<Arc<VcpusCgroup>>,
     dynamic: bool,
+    deterministic: bool,
+    // Counter of the guest when its vCPUs were paused, set back on all of
+    // them when resumed so that it doesn't count the time spent paused.
+    paused_counter: Option<u64>,
+    vcpu_policy: Option<SchedPolicy>,
+    // Host CPUs the vCPUs are pinned to by the priority class, claimed for
+    // as long as the vCPUs run.
+    pinned_host_cpus: Vec<HostCpuClaim>,
 }
 
 const CPU_ENABLE_FLAG: usize = 0;
@@ -523,6 +618,220 @@ impl BusDevice for CpuManager {
     }
 }
 
+// Runtime statistics of a vCPU, updated from the vCPU thread.
+#[derive(Default)]
+struct VcpuCounters {
+    // Thread id of the vCPU thread, used to retrieve its scheduling
+    // statistics from procfs.
+    tid: AtomicU64,
+    exits: AtomicU64,
+    ignored_exits: AtomicU64,
+    mmio_exits: AtomicU64,
+    #[cfg(target_arch = "x86_64")]
+    pio_exits: AtomicU64,
+    run_time_us: AtomicU64,
+    // CPU time consumed by the vCPU thread running the guest, unlike the
+    // run time which includes the time the vCPU spent halted.
+    cpu_time_ns: AtomicU64,
+    throttle_time_us: AtomicU64,
+}
+
+impl VcpuCounters {
+    // Time the vCPU thread spent runnable but waiting for a host CPU, which
+    // is the time stolen from the guest.
+    fn steal_time_us(&self) -> Option<u64> {
+        let schedstat = fs::read_to_string(format!(
+            "/proc/self/task/{}/schedstat",
+            self.tid.load(Ordering::Acquire)
+        ))
+        .ok()?;
+        let wait_time_ns: u64 = schedstat.split_whitespace().nth(1)?.parse().ok()?;
+        Some(wait_time_ns / 1000)
+    }
+
+    fn counters(&self) -> HashMap<&'static str, Wrapping<u64>> {
+        let mut counters = HashMap::new();
+
+        let exits = self.exits.load(Ordering::Acquire);
+        let mmio_exits = self.mmio_exits.load(Ordering::Acquire);
+        #[cfg(target_arch = "x86_64")]
+        let pio_exits = self.pio_exits.load(Ordering::Acquire);
+        #[cfg(target_arch = "aarch64")]
+        let pio_exits = 0;
+        // MMIO and PIO exits are handled from the hypervisor crate, which
+        // returns to the vCPU loop the same way it does when KVM_RUN has been
+        // interrupted by a signal.
+        let interrupt_exits = self
+            .ignored_exits
+            .load(Ordering::Acquire)
+            .saturating_sub(mmio_exits + pio_exits);
+
+        counters.insert("exits", Wrapping(exits));
+        counters.insert("mmio_exits", Wrapping(mmio_exits));
+        #[cfg(target_arch = "x86_64")]
+        counters.insert("pio_exits", Wrapping(pio_exits));
+        counters.insert("interrupt_exits", Wrapping(interrupt_exits));
+        counters.insert(
+            "run_time_us",
+            Wrapping(self.run_time_us.load(Ordering::Acquire)),
+        );
+        counters.insert(
+            "cpu_time_ns",
+            Wrapping(self.cpu_time_ns.load(Ordering::Acquire)),
+        );
+        counters.insert(
+            "throttle_time_us",
+            Wrapping(self.throttle_time_us.load(Ordering::Acquire)),
+        );
+        if let Some(steal_time_us) = self.steal_time_us() {
+            counters.insert("steal_time_us", Wrapping(steal_time_us));
+        }
+
+        counters
+    }
+}
+
+// Exit handlers of a single vCPU, accounting for the exits before forwarding
+// them to the handlers shared by all vCPUs.
+struct VcpuVmOps {
+    vm_ops: Arc<dyn VmOps>,
+    counters: Arc<VcpuCounters>,
+}
+
+impl VmOps for VcpuVmOps {
+    fn guest_mem_write(&self, gpa: u64, buf: &[u8]) -> result::Result<usize, HypervisorVmError> {
+        self.vm_ops.guest_mem_write(gpa, buf)
+    }
+
+    fn guest_mem_read(&self, gpa: u64, buf: &mut [u8]) -> result::Result<usize, HypervisorVmError> {
+        self.vm_ops.guest_mem_read(gpa, buf)
+    }
+
+    fn mmio_read(&self, gpa: u64, data: &mut [u8]) -> result::Result<(), HypervisorVmError> {
+        self.counters.mmio_exits.fetch_add(1, Ordering::AcqRel);
+        self.vm_ops.mmio_read(gpa, data)
+    }
+
+    fn mmio_write(&self, gpa: u64, data: &[u8]) -> result::Result<(), HypervisorVmError> {
+        self.counters.mmio_exits.fetch_add(1, Ordering::AcqRel);
+        self.vm_ops.mmio_write(gpa, data)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pio_read(&self, port: u64, data: &mut [u8]) -> result::Result<(), HypervisorVmError> {
+        self.counters.pio_exits.fetch_add(1, Ordering::AcqRel);
+        self.vm_ops.pio_read(port, data)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pio_write(&self, port: u64, data: &[u8]) -> result::Result<(), HypervisorVmError> {
+        self.counters.pio_exits.fetch_add(1, Ordering::AcqRel);
+        self.vm_ops.pio_write(port, data)
+    }
+}
+
+// Timer sending SIGRTMIN to the vCPU thread that created it, which
+// interrupts KVM_RUN the same way a pause request does.
+struct VcpuThrottleTimer {
+    timer: libc::timer_t,
+    armed: bool,
+}
+
+impl VcpuThrottleTimer {
+    fn new() -> io::Result<Self> {
+        let mut sigevent: libc::sigevent = unsafe { std::mem::zeroed() };
+        sigevent.sigev_notify = libc::SIGEV_THREAD_ID;
+        sigevent.sigev_signo = SIGRTMIN();
+        sigevent.sigev_notify_thread_id = unsafe { libc::syscall(libc::SYS_gettid) } as i32;
+
+        let mut timer: libc::timer_t = std::ptr::null_mut();
+        let ret = unsafe { libc::timer_create(libc::CLOCK_MONOTONIC, &mut sigevent, &mut timer) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(VcpuThrottleTimer {
+            timer,
+            armed: false,
+        })
+    }
+
+    fn arm(&mut self, armed: bool) -> io::Result<()> {
+        if armed == self.armed {
+            return Ok(());
+        }
+
+        let period = if armed {
+            libc::timespec {
+                tv_sec: 0,
+                tv_nsec: VCPU_THROTTLE_PERIOD.as_nanos() as _,
+            }
+        } else {
+            libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            }
+        };
+        let spec = libc::itimerspec {
+            it_interval: period,
+            it_value: period,
+        };
+        let ret = unsafe { libc::timer_settime(self.timer, 0, &spec, std::ptr::null_mut()) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.armed = armed;
+        Ok(())
+    }
+}
+
+impl Drop for VcpuThrottleTimer {
+    fn drop(&mut self) {
+        unsafe { libc::timer_delete(self.timer) };
+    }
+}
+
+// Counter of the guest, read from its first vCPU as all of them count from
+// the same value.
+fn guest_counter(vcpus: &[Arc<Mutex<Vcpu>>]) -> std::result::Result<Option<u64>, MigratableError> {
+    match vcpus.first() {
+        Some(vcpu) => vcpu.lock().unwrap().vcpu.guest_counter().map_err(|e| {
+            MigratableError::Pause(anyhow!("Could not read the guest counter {:?}", e))
+        }),
+        None => Ok(None),
+    }
+}
+
+// Sets the counter of the guest on all its vCPUs, for them to keep counting
+// in sync from the given value.
+fn set_guest_counter(
+    vcpus: &[Arc<Mutex<Vcpu>>],
+    counter: u64,
+) -> std::result::Result<(), MigratableError> {
+    for vcpu in vcpus.iter() {
+        vcpu.lock()
+            .unwrap()
+            .vcpu
+            .set_guest_counter(counter)
+            .map_err(|e| {
+                MigratableError::Resume(anyhow!("Could not set the guest counter {:?}", e))
+            })?;
+    }
+
+    Ok(())
+}
+
+// CPU time consumed by the calling thread.
+fn thread_cpu_time() -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
 #[derive(Default)]
 struct VcpuState {
     inserting: bool,
@@ -530,6 +839,7 @@ struct VcpuState {
     handle: Option<thread::JoinHandle<()>>,
     kill: Arc<AtomicBool>,
     vcpu_run_interrupted: Arc<AtomicBool>,
+    counters: Arc<VcpuCounters>,
 }
 
 impl VcpuState {
@@ -554,6 +864,15 @@ impl VcpuState {
         }
     }
 
+    // Interrupt KVM_RUN once, without waiting for the vCPU to notice.
+    fn kick_thread(&self) {
+        if let Some(handle) = self.handle.as_ref() {
+            unsafe {
+                libc::pthread_kill(handle.as_pthread_t() as _, SIGRTMIN());
+            }
+        }
+    }
+
     fn join_thread(&mut self) -> Result<()> {
         if let Some(handle) = self.handle.take() {
             handle.join().map_err(Error::ThreadCleanup)?
@@ -569,6 +888,101 @@ impl VcpuState {
     }
 }
 
+#[cfg(target_arch = "aarch64")]
+fn create_pptt(vcpu_topology: (u8, u8, u8, u8), boot_vcpus: u8) -> Sdt {
+    let pptt_start = 0;
+    let mut cpus = 0;
+    let mut uid = 0;
+    let (threads_per_core, cores_per_die, dies_per_package, packages) = vcpu_topology;
+
+    let mut pptt = Sdt::new(*b"PPTT", 36, 2, *b"CLOUDH", *b"CHPPTT  ", 1);
+
+    for cluster_idx in 0..packages {
+        if cpus < boot_vcpus as usize {
+            let cluster_offset = pptt.len() - pptt_start;
+            let cluster_hierarchy_node = ProcessorHierarchyNode {
+                r#type: 0,
+                length: 20,
+                reserved: 0,
+                flags: 0x2,
+                parent: 0,
+                acpi_processor_id: cluster_idx as u32,
+                num_private_resources: 0,
+            };
+            pptt.append(cluster_hierarchy_node);
+
+            for die_idx in 0..dies_per_package {
+                // A die is only described when there are several of them
+                // in a package, its cores belonging to the package
+                // otherwise.
+                let die_offset = if dies_per_package > 1 {
+                    let die_offset = pptt.len() - pptt_start;
+                    let die_hierarchy_node = ProcessorHierarchyNode {
+                        r#type: 0,
+                        length: 20,
+                        reserved: 0,
+                        flags: 0x2,
+                        parent: cluster_offset as u32,
+                        acpi_processor_id: die_idx as u32,
+                        num_private_resources: 0,
+                    };
+                    pptt.append(die_hierarchy_node);
+                    die_offset
+                } else {
+                    cluster_offset
+                };
+
+                for core_idx in 0..cores_per_die {
+                    let core_offset = pptt.len() - pptt_start;
+
+                    if threads_per_core > 1 {
+                        let core_hierarchy_node = ProcessorHierarchyNode {
+                            r#type: 0,
+                            length: 20,
+                            reserved: 0,
+                            flags: 0x2,
+                            parent: die_offset as u32,
+                            acpi_processor_id: core_idx as u32,
+                            num_private_resources: 0,
+                        };
+                        pptt.append(core_hierarchy_node);
+
+                        for _thread_idx in 0..threads_per_core {
+                            let thread_hierarchy_node = ProcessorHierarchyNode {
+                                r#type: 0,
+                                length: 20,
+                                reserved: 0,
+                                flags: 0xE,
+                                parent: core_offset as u32,
+                                acpi_processor_id: uid as u32,
+                                num_private_resources: 0,
+                            };
+                            pptt.append(thread_hierarchy_node);
+                            uid += 1;
+                        }
+                    } else {
+                        let thread_hierarchy_node = ProcessorHierarchyNode {
+                            r#type: 0,
+                            length: 20,
+                            reserved: 0,
+                            flags: 0xA,
+                            parent: die_offset as u32,
+                            acpi_processor_id: uid as u32,
+                            num_private_resources: 0,
+                        };
+                        pptt.append(thread_hierarchy_node);
+                        uid += 1;
+                    }
+                }
+            }
+            cpus += (cores_per_die * threads_per_core * dies_per_package) as usize;
+        }
+    }
+
+    pptt.update_checksum();
+    pptt
+}
+
 impl CpuManager {
     #[allow(unused_variables)]
     #[allow(clippy::too_many_arguments)]
@@ -585,6 +999,9 @@ impl CpuManager {
         vm_ops: Arc<dyn VmOps>,
         #[cfg(feature = "tdx")] tdx_enabled: bool,
         numa_nodes: &NumaNodes,
+        #[cfg(target_arch = "x86_64")] hypervisor_info: Option<&arch::HypervisorInfo>,
+        deterministic: bool,
+        priority: Option<PriorityProfile>,
     ) -> Result<Arc<Mutex<CpuManager>>> {
         let guest_memory = memory_manager.lock().unwrap().guest_memory();
         let mut vcpu_states = Vec::with_capacity(usize::from(config.max_vcpus));
@@ -600,7 +1017,7 @@ impl CpuManager {
         #[cfg(target_arch = "x86_64")]
         let cpuid = {
             let phys_bits = physical_bits(config.max_phys_bits);
-            arch::generate_common_cpuid(
+            let mut cpuid = arch::generate_common_cpuid(
                 hypervisor,
                 config
                     .topology
@@ -609,10 +1026,18 @@ impl CpuManager {
                 sgx_epc_sections,
                 phys_bits,
                 config.kvm_hyperv,
+                config.nested,
+                config.baseline,
+                config.tsc_khz,
                 #[cfg(feature = "tdx")]
                 tdx_enabled,
             )
-            .map_err(Error::CommonCpuId)?
+            .map_err(Error::CommonCpuId)?;
+            if let Some(info) = hypervisor_info {
+                arch::x86_64::update_cpuid_hypervisor_info(&mut cpuid, info)
+                    .map_err(Error::CommonCpuId)?;
+            }
+            cpuid
         };
         #[cfg(all(feature = "amx", target_arch = "x86_64"))]
         if config.features.amx {
@@ -660,15 +1085,53 @@ impl CpuManager {
         .into_iter()
         .collect();
 
+        // Refuse a scheduling policy the vCPU threads would fail to apply
+        // rather than failing them once started.
+        if let Some(profile) = priority.as_ref() {
+            profile
+                .vcpu_policy
+                .check()
+                .map_err(|e| Error::VcpuPolicy(profile.vcpu_policy, e))?;
+        }
+
+        let mut pinned_host_cpus = Vec::new();
         let affinity = if let Some(cpu_affinity) = config.affinity.as_ref() {
             cpu_affinity
                 .iter()
                 .map(|a| (a.vcpu, a.host_cpus.clone()))
                 .collect()
+        } else if priority.map_or(false, |p| p.pin_vcpus) {
+            // Give each vCPU a host CPU of its own, among the ones the VMM
+            // is allowed to run on and not claimed by the other VMs.
+            let host_cpus = priority::allowed_host_cpus().map_err(Error::AllowedHostCpus)?;
+            pinned_host_cpus = priority::claim_host_cpus(
+                Path::new(priority::PINNED_CPUS_DIR),
+                &host_cpus,
+                usize::from(config.max_vcpus),
+            )
+            .map_err(Error::ClaimHostCpus)?;
+            if pinned_host_cpus.len() < usize::from(config.max_vcpus) {
+                return Err(Error::NotEnoughHostCpus(
+                    config.max_vcpus,
+                    pinned_host_cpus.len(),
+                ));
+            }
+            (0..config.max_vcpus)
+                .zip(pinned_host_cpus.iter())
+                .map(|(vcpu, claim)| (vcpu, vec![claim.cpu()]))
+                .collect()
         } else {
             BTreeMap::new()
         };
 
+        let vcpus_cgroup = if config.weight.is_some() || config.quota.is_some() {
+            Some(Arc::new(
+                VcpusCgroup::new(config.weight, config.quota).map_err(Error::VcpusCgroup)?,
+            ))
+        } else {
+            None
+        };
+
         #[cfg(feature = "tdx")]
         let dynamic = !tdx_enabled;
         #[cfg(not(feature = "tdx"))]
@@ -696,6 +1159,7 @@ impl CpuManager {
             vm,
             vcpus_kill_signalled: Arc::new(AtomicBool::new(false)),
             vcpus_pause_signalled: Arc::new(AtomicBool::new(false)),
+            vcpus_throttle: Arc::new(AtomicU8::new(0)),
             vcpu_states,
             exit_evt,
             reset_evt,
@@ -708,7 +1172,12 @@ impl CpuManager {
             acpi_address,
             proximity_domain_per_cpu,
             affinity,
+            vcpus_cgroup,
             dynamic,
+            deterministic,
+            paused_counter: None,
+            vcpu_policy: priority.map(|p| p.vcpu_policy),
+            pinned_host_cpus,
         }));
 
         if let Some(acpi_address) = acpi_address {
@@ -733,15 +1202,24 @@ impl CpuManager {
     ) -> Result<()> {
         info!("Creating vCPU: cpu_id = {}", cpu_id);
 
-        let mut vcpu = Vcpu::new(cpu_id, &self.vm, Some(self.vm_ops.clone()))?;
+        let vm_ops: Arc<dyn VmOps> = Arc::new(VcpuVmOps {
+            vm_ops: self.vm_ops.clone(),
+            counters: self.vcpu_states[usize::from(cpu_id)].counters.clone(),
+        });
+        let mut vcpu = Vcpu::new(cpu_id, &self.vm, Some(vm_ops))?;
 
         if let Some(snapshot) = snapshot {
             // AArch64 vCPUs should be initialized after created.
             #[cfg(target_arch = "aarch64")]
-            vcpu.init(&self.vm)?;
+            vcpu.init(&self.vm, self.config.pmu, self.config.nested)?;
 
             vcpu.restore(snapshot).expect("Failed to restore vCPU");
         } else {
+            #[cfg(target_arch = "x86_64")]
+            if let Some(tsc_khz) = self.config.tsc_khz {
+                vcpu.vcpu.set_tsc_khz(tsc_khz).map_err(Error::SetTscKhz)?;
+            }
+
             #[cfg(target_arch = "x86_64")]
             vcpu.configure(
                 entry_point,
@@ -751,9 +1229,15 @@ impl CpuManager {
             )
             .expect("Failed to configure vCPU");
 
+            // The CPU model may not be supported by the host.
             #[cfg(target_arch = "aarch64")]
-            vcpu.configure(&self.vm, entry_point)
-                .expect("Failed to configure vCPU");
+            vcpu.configure(
+                &self.vm,
+                entry_point,
+                self.config.baseline,
+                self.config.pmu,
+                self.config.nested,
+            )?;
         }
 
         // Adding vCPU to the CpuManager's vCPU list.
@@ -785,8 +1269,14 @@ impl CpuManager {
         Ok(())
     }
 
+    /// Initializes the PMU of the vCPUs when it is enabled, returning
+    /// whether it is exposed to the guest.
     #[cfg(target_arch = "aarch64")]
     pub fn init_pmu(&self, irq: u32) -> Result<bool> {
+        if !self.config.pmu {
+            return Ok(false);
+        }
+
         let cpu_attr = kvm_bindings::kvm_device_attr {
             group: kvm_bindings::KVM_ARM_VCPU_PMU_V3_CTRL,
             attr: u64::from(kvm_bindings::KVM_ARM_VCPU_PMU_V3_INIT),
@@ -803,28 +1293,15 @@ impl CpuManager {
                 flags: 0,
             };
 
-            // Check if PMU attr is available, if not, log the information.
-            if cpu.lock().unwrap().vcpu.has_vcpu_attr(&cpu_attr).is_ok() {
-                // Set irq for PMU
-                cpu.lock()
-                    .unwrap()
-                    .vcpu
-                    .set_vcpu_attr(&cpu_attr_irq)
-                    .map_err(Error::InitPmu)?;
-
-                // Init PMU
-                cpu.lock()
-                    .unwrap()
-                    .vcpu
-                    .set_vcpu_attr(&cpu_attr)
-                    .map_err(Error::InitPmu)?;
-            } else {
-                debug!(
-                    "PMU attribute is not supported in vCPU{}, skip PMU init!",
-                    cpu.lock().unwrap().id
-                );
-                return Ok(false);
-            }
+            let cpu = cpu.lock().unwrap();
+            // The PMU was requested, so the vCPUs were created with it.
+            cpu.vcpu.has_vcpu_attr(&cpu_attr).map_err(Error::InitPmu)?;
+            // Set irq for PMU
+            cpu.vcpu
+                .set_vcpu_attr(&cpu_attr_irq)
+                .map_err(Error::InitPmu)?;
+            // Init PMU
+            cpu.vcpu.set_vcpu_attr(&cpu_attr).map_err(Error::InitPmu)?;
         }
 
         Ok(true)
@@ -844,12 +1321,14 @@ impl CpuManager {
         let panic_exit_evt = self.exit_evt.try_clone().unwrap();
         let vcpu_kill_signalled = self.vcpus_kill_signalled.clone();
         let vcpu_pause_signalled = self.vcpus_pause_signalled.clone();
+        let vcpu_throttle = self.vcpus_throttle.clone();
 
         let vcpu_kill = self.vcpu_states[usize::from(vcpu_id)].kill.clone();
         let vcpu_run_interrupted = self.vcpu_states[usize::from(vcpu_id)]
             .vcpu_run_interrupted
             .clone();
         let panic_vcpu_run_interrupted = vcpu_run_interrupted.clone();
+        let vcpu_counters = self.vcpu_states[usize::from(vcpu_id)].counters.clone();
 
         // Prepare the CPU set the current vCPU is expected to run onto.
         let cpuset = self.affinity.get(&vcpu_id).map(|host_cpus| {
@@ -860,6 +1339,8 @@ impl CpuManager {
             }
             cpuset
         });
+        let vcpus_cgroup = self.vcpus_cgroup.clone();
+        let vcpu_policy = self.vcpu_policy;
 
         // Retrieve seccomp filter for vcpu thread
         let vcpu_seccomp_filter = get_seccomp_filter(&self.seccomp_action, Thread::Vcpu)
@@ -868,12 +1349,18 @@ impl CpuManager {
         #[cfg(target_arch = "x86_64")]
         let interrupt_controller_clone = self.interrupt_controller.as_ref().cloned();
 
+        #[cfg(feature = "sev_snp")]
+        let vm = self.vm.clone();
+
         info!("Starting vCPU: cpu_id = {}", vcpu_id);
 
         let handle = Some(
             thread::Builder::new()
                 .name(format!("vcpu{}", vcpu_id))
                 .spawn(move || {
+                    let tid = unsafe { libc::syscall(libc::SYS_gettid) } as u64;
+                    vcpu_counters.tid.store(tid, Ordering::Release);
+
                     // Schedule the thread to run on the expected CPU set
                     if let Some(cpuset) = cpuset.as_ref() {
                         let ret = unsafe {
@@ -894,6 +1381,51 @@ impl CpuManager {
                         }
                     }
 
+                    // Apply the CPU weight and quota to the vCPU. On failure,
+                    // the VM is shut down once all the vCPU threads are ready,
+                    // as the VMM thread waits for this one as well.
+                    if let Some(vcpus_cgroup) = vcpus_cgroup.as_ref() {
+                        if let Err(e) = vcpus_cgroup.add_thread(tid) {
+                            error!(
+                                "Failed moving the vCPU {} to the vCPUs cgroup: {}",
+                                vcpu_id, e
+                            );
+                            exit_evt.write(1).ok();
+                            vcpu_thread_barrier.wait();
+                            return;
+                        }
+                    }
+
+                    // Apply the scheduling policy of the priority class,
+                    // checked when the CPU manager was created. Should it
+                    // still fail, the VM is shut down once all the vCPU
+                    // threads are ready, rather than leaving them waiting
+                    // for this one.
+                    if let Some(vcpu_policy) = vcpu_policy.as_ref() {
+                        if let Err(e) = vcpu_policy.apply() {
+                            error!(
+                                "Failed applying the scheduling policy {:?} to the vCPU {}: {}",
+                                vcpu_policy, vcpu_id, e
+                            );
+                            exit_evt.write(1).ok();
+                            vcpu_thread_barrier.wait();
+                            return;
+                        }
+                    }
+
+                    let mut throttle_timer = match VcpuThrottleTimer::new() {
+                        Ok(timer) => timer,
+                        Err(e) => {
+                            error!(
+                                "Failed creating the vCPU {} throttling timer: {}",
+                                vcpu_id, e
+                            );
+                            exit_evt.write(1).ok();
+                            vcpu_thread_barrier.wait();
+                            return;
+                        }
+                    };
+
                     // Apply seccomp filter for vcpu thread.
                     if !vcpu_seccomp_filter.is_empty() {
                         if let Err(e) =
@@ -950,6 +1482,12 @@ impl CpuManager {
                                     vcpu.lock().as_ref().unwrap().vcpu.set_immediate_exit(false);
                                 }
 
+                                // Don't let the throttling timer wake up the
+                                // parked thread.
+                                if let Err(e) = throttle_timer.arm(false) {
+                                    error!("Failed disarming the throttling timer: {}", e);
+                                }
+
                                 vcpu_run_interrupted.store(true, Ordering::SeqCst);
                                 while vcpu_pause_signalled.load(Ordering::SeqCst) {
                                     thread::park();
@@ -965,12 +1503,31 @@ impl CpuManager {
                                 break;
                             }
 
-                            #[cfg(feature = "tdx")]
+                            #[cfg(any(feature = "tdx", feature = "sev_snp"))]
                             let mut vcpu = vcpu.lock().unwrap();
-                            #[cfg(not(feature = "tdx"))]
+                            #[cfg(not(any(feature = "tdx", feature = "sev_snp")))]
                             let vcpu = vcpu.lock().unwrap();
+
+                            let throttle = vcpu_throttle.load(Ordering::SeqCst);
+                            if let Err(e) = throttle_timer.arm(throttle != 0) {
+                                error!("Failed arming the throttling timer: {}", e);
+                            }
+
+                            let run_start = Instant::now();
+                            let cpu_time_start = thread_cpu_time();
+                            let exit = vcpu.run();
+                            let cpu_time = thread_cpu_time().saturating_sub(cpu_time_start);
+                            vcpu_counters.run_time_us.fetch_add(
+                                run_start.elapsed().as_micros() as u64,
+                                Ordering::AcqRel,
+                            );
+                            vcpu_counters
+                                .cpu_time_ns
+                                .fetch_add(cpu_time.as_nanos() as u64, Ordering::AcqRel);
+                            vcpu_counters.exits.fetch_add(1, Ordering::AcqRel);
+
                             // vcpu.run() returns false on a triple-fault so trigger a reset
-                            match vcpu.run() {
+                            match exit {
                                 Ok(run) => match run {
                                     #[cfg(all(target_arch = "x86_64", feature = "kvm"))]
                                     VmExit::Debug => {
@@ -993,7 +1550,11 @@ impl CpuManager {
                                                 .end_of_interrupt(vector);
                                         }
                                     }
-                                    VmExit::Ignore => {}
+                                    VmExit::Ignore => {
+                                        vcpu_counters
+                                            .ignored_exits
+                                            .fetch_add(1, Ordering::AcqRel);
+                                    }
                                     VmExit::Hyperv => {}
                                     VmExit::Reset => {
                                         info!("VmExit::Reset");
@@ -1027,6 +1588,42 @@ impl CpuManager {
                                             unreachable!("Couldn't get a mutable reference from Arc<dyn Vcpu> as there are multiple instances");
                                         }
                                     }
+                                    // The guest converts memory between
+                                    // private and shared, either explicitly
+                                    // or by accessing it.
+                                    #[cfg(feature = "sev_snp")]
+                                    VmExit::SevSnp => {
+                                        if let Some(vcpu) = Arc::get_mut(&mut vcpu.vcpu) {
+                                            let conversion = match vcpu.get_sev_snp_conversion() {
+                                                Ok(conversion) => conversion,
+                                                Err(e) => {
+                                                    error!("VCPU generated error: {:?}", Error::VcpuRun(e.into()));
+                                                    break;
+                                                }
+                                            };
+                                            let result = vm.sev_snp_set_memory_private(
+                                                conversion.gpa,
+                                                conversion.size,
+                                                conversion.private,
+                                            );
+                                            // An access whose page can't be
+                                            // converted would fault again,
+                                            // while the guest is told about
+                                            // a failed hypercall.
+                                            if let Err(e) = result {
+                                                if conversion.implicit {
+                                                    error!("Failed SEV-SNP memory conversion {:x?}: {}", conversion, e);
+                                                    break;
+                                                }
+                                                debug!("Failed SEV-SNP memory conversion {:x?}: {}", conversion, e);
+                                                vcpu.set_sev_snp_conversion_result(false);
+                                            } else {
+                                                vcpu.set_sev_snp_conversion_result(true);
+                                            }
+                                        } else {
+                                            unreachable!("Couldn't get a mutable reference from Arc<dyn Vcpu> as there are multiple instances");
+                                        }
+                                    }
                                     _ => {
                                         error!(
                                             "VCPU generated error: {:?}",
@@ -1049,6 +1646,23 @@ impl CpuManager {
                                 vcpu_run_interrupted.store(true, Ordering::SeqCst);
                                 break;
                             }
+
+                            // Sleep long enough for the CPU time consumed by
+                            // the last run to amount to the unthrottled share
+                            // of the vCPU time. A halted vCPU doesn't consume
+                            // any, and isn't delayed. The vCPU is unlocked
+                            // first, not to hold off the operations on it,
+                            // such as pausing or snapshotting the VM.
+                            drop(vcpu);
+                            if throttle != 0 {
+                                let throttle_time = cpu_time * u32::from(throttle)
+                                    / u32::from(100 - throttle);
+                                thread::sleep(throttle_time);
+                                vcpu_counters.throttle_time_us.fetch_add(
+                                    throttle_time.as_micros() as u64,
+                                    Ordering::AcqRel,
+                                );
+                            }
                         }
                     })
                     .or_else(|_| {
@@ -1118,7 +1732,31 @@ impl CpuManager {
         Ok(())
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn synchronize_tsc_offsets(&self) -> std::result::Result<(), HypervisorCpuError> {
+        let offset = match self.vcpus.first() {
+            Some(vcpu) => vcpu.lock().unwrap().vcpu.tsc_offset()?,
+            None => None,
+        };
+
+        if let Some(offset) = offset {
+            for vcpu in self.vcpus.iter().skip(1) {
+                vcpu.lock().unwrap().vcpu.set_tsc_offset(offset)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn create_boot_vcpus(&mut self, entry_point: Option<EntryPoint>) -> Result<()> {
+        // KVM doesn't allow creating vCPUs once the vGIC is initialized, so
+        // the vCPUs which can be hot-added are created at boot as well. They
+        // stay powered off, without any thread running them, until added.
+        #[cfg(target_arch = "aarch64")]
+        if self.dynamic {
+            return self.create_vcpus(self.max_vcpus(), entry_point);
+        }
+
         self.create_vcpus(self.boot_vcpus(), entry_point)
     }
 
@@ -1127,8 +1765,24 @@ impl CpuManager {
         self.activate_vcpus(self.boot_vcpus(), false)
     }
 
+    // Starts the vCPUs the VM is booting with in the "paused" state, so that
+    // they wait for the VM to be resumed before running any guest code.
+    pub fn start_boot_vcpus_paused(&mut self) -> Result<()> {
+        self.vcpus_pause_signalled.store(true, Ordering::SeqCst);
+        self.start_boot_vcpus()
+    }
+
     pub fn start_restored_vcpus(&mut self) -> Result<()> {
         let vcpu_numbers = self.vcpus.len() as u8;
+        // On AArch64, all the vCPUs which can be hot-added are created, but
+        // the ones removed from the VM, or not hot-added yet, are restored
+        // without being started.
+        #[cfg(target_arch = "aarch64")]
+        let vcpu_numbers = if self.dynamic {
+            vcpu_numbers.min(self.boot_vcpus())
+        } else {
+            vcpu_numbers
+        };
         let vcpu_thread_barrier = Arc::new(Barrier::new((vcpu_numbers + 1) as usize));
         // Restore the vCPUs in "paused" state.
         self.vcpus_pause_signalled.store(true, Ordering::SeqCst);
@@ -1169,6 +1823,69 @@ impl CpuManager {
         }
     }
 
+    /// Takes the given percentage of the CPU time away from every vCPU, by
+    /// making the vCPU threads sleep between two runs. A percentage of 0
+    /// lifts the throttling.
+    pub fn throttle_vcpus(&mut self, percentage: u8) -> Result<()> {
+        if percentage > MAX_VCPU_THROTTLE_PERCENTAGE {
+            return Err(Error::InvalidThrottlePercentage(percentage));
+        }
+
+        self.vcpus_throttle.store(percentage, Ordering::SeqCst);
+
+        // Get the vCPUs out of the guest so that they arm or disarm their
+        // throttling timer.
+        for state in self.vcpu_states.iter() {
+            state.kick_thread();
+        }
+
+        Ok(())
+    }
+
+    fn check_vcpu_present(&self, cpu_id: u8) -> Result<()> {
+        match self.vcpu_states.get(usize::from(cpu_id)) {
+            Some(state) if state.active() => Ok(()),
+            _ => Err(Error::VcpuNotPresent(cpu_id)),
+        }
+    }
+
+    /// Injects an NMI into the given vCPU, the APIC ID of a vCPU being its
+    /// identifier.
+    #[cfg(target_arch = "x86_64")]
+    pub fn inject_nmi(&mut self, cpu_id: u8) -> Result<()> {
+        self.check_vcpu_present(cpu_id)?;
+        self.vm
+            .inject_nmi(u32::from(cpu_id))
+            .map_err(Error::InjectNmi)
+    }
+
+    /// Sets the given SGI pending on the given vCPU. The redistributor can
+    /// only be accessed while no vCPU runs, so the vCPUs are paused for the
+    /// injection unless they already are.
+    #[cfg(target_arch = "aarch64")]
+    pub fn inject_sgi(&mut self, cpu_id: u8, sgi: u8, gic: &Arc<Mutex<Gic>>) -> Result<()> {
+        if sgi >= 16 {
+            return Err(Error::InvalidSgi(sgi));
+        }
+        self.check_vcpu_present(cpu_id)?;
+
+        let mpidr = self.vcpus[usize::from(cpu_id)].lock().unwrap().get_mpidr();
+        let paused = self.vcpus_paused();
+        if !paused {
+            self.pause().map_err(Error::PauseVcpus)?;
+        }
+        let result = gic
+            .lock()
+            .unwrap()
+            .inject_sgi(mpidr, sgi)
+            .map_err(Error::InjectSgi);
+        if !paused {
+            self.resume().map_err(Error::PauseVcpus)?;
+        }
+
+        result
+    }
+
     pub fn shutdown(&mut self) -> Result<()> {
         // Tell the vCPUs to stop themselves next time they go through the loop
         self.vcpus_kill_signalled.store(true, Ordering::SeqCst);
@@ -1193,6 +1910,76 @@ impl CpuManager {
             state.join_thread()?;
         }
 
+        // The host CPUs the vCPUs were pinned to are free for other VMs.
+        self.pinned_host_cpus.clear();
+
+        Ok(())
+    }
+
+    /// Stops all the vCPU threads, the same way shutdown() does, but keeps
+    /// the vCPUs around so that they can be configured and started again.
+    pub fn stop_vcpus(&mut self) -> Result<()> {
+        self.vcpus_kill_signalled.store(true, Ordering::SeqCst);
+        self.vcpus_pause_signalled.store(false, Ordering::SeqCst);
+
+        for state in self.vcpu_states.iter() {
+            state.unpark_thread();
+        }
+
+        for state in self.vcpu_states.iter() {
+            state.signal_thread();
+        }
+
+        for state in self.vcpu_states.iter_mut() {
+            state.join_thread()?;
+            state.inserting = false;
+            state.removing = false;
+        }
+
+        self.vcpus_kill_signalled.store(false, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Sets the number of vCPUs the VM boots with, which follows the vCPUs
+    /// added or removed since the VM was created, so that an in-place
+    /// reboot keeps them the same way re-creating the VM does.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_boot_vcpus(&mut self, boot_vcpus: u8) -> Result<()> {
+        self.create_vcpus(boot_vcpus, None)?;
+        self.config.boot_vcpus = boot_vcpus;
+
+        Ok(())
+    }
+
+    /// Configures the stopped boot vCPUs again so that they start from the
+    /// given entry point. The vCPUs can't be re-created as the hypervisor
+    /// doesn't allow it, hence this is what an in-place reboot relies on.
+    #[cfg(target_arch = "x86_64")]
+    pub fn reset_boot_vcpus(&mut self, entry_point: Option<EntryPoint>) -> Result<()> {
+        for vcpu in self.vcpus.iter().take(usize::from(self.boot_vcpus())) {
+            let mut vcpu = vcpu.lock().unwrap();
+            vcpu.configure(
+                entry_point,
+                &self.vm_memory,
+                self.cpuid.clone(),
+                self.config.kvm_hyperv,
+            )?;
+
+            // Only the BSP is runnable, the APs are waiting for the guest
+            // to bring them up through INIT-SIPI.
+            #[cfg(feature = "kvm")]
+            vcpu.vcpu
+                .set_mp_state(hypervisor::MpState {
+                    mp_state: if vcpu.id == 0 {
+                        hypervisor::kvm::kvm_bindings::KVM_MP_STATE_RUNNABLE
+                    } else {
+                        hypervisor::kvm::kvm_bindings::KVM_MP_STATE_UNINITIALIZED
+                    },
+                })
+                .map_err(Error::SetMpState)?;
+        }
+
         Ok(())
     }
 
@@ -1216,6 +2003,18 @@ impl CpuManager {
         self.config.max_vcpus
     }
 
+    pub fn counters(&self) -> HashMap<String, HashMap<&'static str, Wrapping<u64>>> {
+        let mut counters = HashMap::new();
+
+        for (cpu_id, state) in self.vcpu_states.iter().enumerate() {
+            if state.active() {
+                counters.insert(format!("__vcpu{}", cpu_id), state.counters.counters());
+            }
+        }
+
+        counters
+    }
+
     #[cfg(target_arch = "x86_64")]
     pub fn common_cpuid(&self) -> CpuId {
         self.cpuid.clone()
@@ -1227,10 +2026,12 @@ impl CpuManager {
             .fold(0, |acc, state| acc + state.active() as u8)
     }
 
+    // MPIDRs of the boot vCPUs, described through the device tree.
     #[cfg(target_arch = "aarch64")]
     pub fn get_mpidrs(&self) -> Vec<u64> {
         self.vcpus
             .iter()
+            .take(usize::from(self.boot_vcpus()))
             .map(|cpu| cpu.lock().unwrap().get_mpidr())
             .collect()
     }
@@ -1244,11 +2045,15 @@ impl CpuManager {
     }
 
     #[cfg(target_arch = "aarch64")]
-    pub fn get_vcpu_topology(&self) -> Option<(u8, u8, u8)> {
-        self.config
-            .topology
-            .clone()
-            .map(|t| (t.threads_per_core, t.cores_per_die, t.packages))
+    pub fn get_vcpu_topology(&self) -> Option<(u8, u8, u8, u8)> {
+        self.config.topology.clone().map(|t| {
+            (
+                t.threads_per_core,
+                t.cores_per_die,
+                t.dies_per_package,
+                t.packages,
+            )
+        })
     }
 
     pub fn create_madt(&self) -> Sdt {
@@ -1303,7 +2108,7 @@ impl CpuManager {
              */
 
             // See section 5.2.12.14 GIC CPU Interface (GICC) Structure in ACPI spec.
-            for cpu in 0..self.config.boot_vcpus {
+            for cpu in 0..self.config.max_vcpus {
                 let vcpu = &self.vcpus[cpu as usize];
                 let mpidr = vcpu.lock().unwrap().get_mpidr();
                 /* ARMv8 MPIDR format:
@@ -1321,7 +2126,11 @@ impl CpuManager {
                     reserved0: 0,
                     cpu_interface_number: cpu as u32,
                     uid: cpu as u32,
-                    flags: 1,
+                    flags: if cpu < self.config.boot_vcpus {
+                        1 << MADT_CPU_ENABLE_FLAG
+                    } else {
+                        1 << MADT_GICC_ONLINE_CAPABLE_FLAG
+                    },
                     parking_version: 0,
                     performance_interrupt: 0,
                     parked_address: 0,
@@ -1353,7 +2162,7 @@ impl CpuManager {
             madt.append(gicd);
 
             // See 5.2.12.17 GIC Redistributor (GICR) Structure in ACPI spec.
-            let gicr_size: u32 = 0x0001_0000 * 2 * (self.config.boot_vcpus as u32);
+            let gicr_size: u32 = 0x0001_0000 * 2 * (self.config.max_vcpus as u32);
             let gicr_base: u64 =
                 arch::layout::MAPPED_IO_START.raw_value() - 0x0001_0000 - gicr_size as u64;
             let gicr = GicR {
@@ -1384,79 +2193,14 @@ impl CpuManager {
 
     #[cfg(target_arch = "aarch64")]
     pub fn create_pptt(&self) -> Sdt {
-        let pptt_start = 0;
-        let mut cpus = 0;
-        let mut uid = 0;
         // If topology is not specified, the default setting is:
-        // 1 package, multiple cores, 1 thread per core
+        // 1 package, 1 die, multiple cores, 1 thread per core
         // This is also the behavior when PPTT is missing.
-        let (threads_per_core, cores_per_package, packages) =
-            self.get_vcpu_topology().unwrap_or((1, self.max_vcpus(), 1));
-
-        let mut pptt = Sdt::new(*b"PPTT", 36, 2, *b"CLOUDH", *b"CHPPTT  ", 1);
-
-        for cluster_idx in 0..packages {
-            if cpus < self.config.boot_vcpus as usize {
-                let cluster_offset = pptt.len() - pptt_start;
-                let cluster_hierarchy_node = ProcessorHierarchyNode {
-                    r#type: 0,
-                    length: 20,
-                    reserved: 0,
-                    flags: 0x2,
-                    parent: 0,
-                    acpi_processor_id: cluster_idx as u32,
-                    num_private_resources: 0,
-                };
-                pptt.append(cluster_hierarchy_node);
-
-                for core_idx in 0..cores_per_package {
-                    let core_offset = pptt.len() - pptt_start;
-
-                    if threads_per_core > 1 {
-                        let core_hierarchy_node = ProcessorHierarchyNode {
-                            r#type: 0,
-                            length: 20,
-                            reserved: 0,
-                            flags: 0x2,
-                            parent: cluster_offset as u32,
-                            acpi_processor_id: core_idx as u32,
-                            num_private_resources: 0,
-                        };
-                        pptt.append(core_hierarchy_node);
-
-                        for _thread_idx in 0..threads_per_core {
-                            let thread_hierarchy_node = ProcessorHierarchyNode {
-                                r#type: 0,
-                                length: 20,
-                                reserved: 0,
-                                flags: 0xE,
-                                parent: core_offset as u32,
-                                acpi_processor_id: uid as u32,
-                                num_private_resources: 0,
-                            };
-                            pptt.append(thread_hierarchy_node);
-                            uid += 1;
-                        }
-                    } else {
-                        let thread_hierarchy_node = ProcessorHierarchyNode {
-                            r#type: 0,
-                            length: 20,
-                            reserved: 0,
-                            flags: 0xA,
-                            parent: cluster_offset as u32,
-                            acpi_processor_id: uid as u32,
-                            num_private_resources: 0,
-                        };
-                        pptt.append(thread_hierarchy_node);
-                        uid += 1;
-                    }
-                }
-                cpus += (cores_per_package * threads_per_core) as usize;
-            }
-        }
-
-        pptt.update_checksum();
-        pptt
+        create_pptt(
+            self.get_vcpu_topology()
+                .unwrap_or((1, self.max_vcpus(), 1, 1)),
+            self.config.boot_vcpus,
+        )
     }
 
     #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
@@ -1513,6 +2257,19 @@ impl CpuManager {
     pub fn vcpus_paused(&self) -> bool {
         self.vcpus_pause_signalled.load(Ordering::SeqCst)
     }
+
+    // Whether the counter of the guest stops while the VM is paused. On
+    // AArch64 there is no KVM clock to steer, so the virtual counter is
+    // frozen by default (KVM adjusting CNTVOFF_EL2 when it is set back) to
+    // keep the guest monotonic time from jumping over the pause.
+    fn freezes_guest_counter(&self) -> bool {
+        #[cfg(target_arch = "aarch64")]
+        if self.config.freeze_counter {
+            return true;
+        }
+
+        self.deterministic
+    }
 }
 
 struct Cpu {
@@ -1521,9 +2278,13 @@ struct Cpu {
     dynamic: bool,
 }
 
-#[cfg(target_arch = "x86_64")]
 const MADT_CPU_ENABLE_FLAG: usize = 0;
 
+// The vCPUs which can be hot-added are described as online capable rather
+// than enabled.
+#[cfg(target_arch = "aarch64")]
+const MADT_GICC_ONLINE_CAPABLE_FLAG: usize = 3;
+
 impl Cpu {
     #[cfg(target_arch = "x86_64")]
     fn generate_mat(&self) -> Vec<u8> {
@@ -1554,7 +2315,6 @@ impl Aml for Cpu {
                 vec![
                     &aml::Name::new("_HID".into(), &"ACPI0007"),
                     &aml::Name::new("_UID".into(), &self.cpu_id),
-                    // Currently, AArch64 cannot support following fields.
                     /*
                     _STA return value:
                     Bit [0] – Set if the device is present.
@@ -1564,7 +2324,6 @@ impl Aml for Cpu {
                     Bit [4] – Set if the battery is present.
                     Bits [31:5] – Reserved (must be cleared).
                     */
-                    #[cfg(target_arch = "x86_64")]
                     &aml::Method::new(
                         "_STA".into(),
                         0,
@@ -1587,7 +2346,6 @@ impl Aml for Cpu {
                     #[cfg(target_arch = "x86_64")]
                     &aml::Name::new("_MAT".into(), &aml::Buffer::new(mat_data)),
                     // Trigger CPU ejection
-                    #[cfg(target_arch = "x86_64")]
                     &aml::Method::new(
                         "_EJ0".into(),
                         1,
@@ -1645,6 +2403,13 @@ impl Aml for CpuNotify {
     }
 }
 
+// _STA value of the vCPUs which aren't enabled. On AArch64 they remain
+// present, as the guest can only enable the CPUs it found at boot.
+#[cfg(target_arch = "x86_64")]
+const CPU_DISABLED_STATUS: u8 = 0;
+#[cfg(target_arch = "aarch64")]
+const CPU_DISABLED_STATUS: u8 = 0xd;
+
 struct CpuMethods {
     max_vcpus: u8,
     dynamic: bool,
@@ -1663,7 +2428,7 @@ impl Aml for CpuMethods {
                     &aml::Acquire::new("\\_SB_.PRES.CPLK".into(), 0xffff),
                     // Write CPU number (in first argument) to I/O port via field
                     &aml::Store::new(&aml::Path::new("\\_SB_.PRES.CSEL"), &aml::Arg(0)),
-                    &aml::Store::new(&aml::Local(0), &aml::ZERO),
+                    &aml::Store::new(&aml::Local(0), &CPU_DISABLED_STATUS),
                     // Check if CPEN bit is set, if so make the local variable 0xf (see _STA for details of meaning)
                     &aml::If::new(
                         &aml::Equal::new(&aml::Path::new("\\_SB_.PRES.CPEN"), &aml::ONE),
@@ -1671,7 +2436,7 @@ impl Aml for CpuMethods {
                     ),
                     // Release lock
                     &aml::Release::new("\\_SB_.PRES.CPLK".into()),
-                    // Return 0 or 0xf
+                    // Return 0xf or the disabled status
                     &aml::Return::new(&aml::Local(0)),
                 ],
             )
@@ -1765,7 +2530,6 @@ impl Aml for CpuMethods {
 
 impl Aml for CpuManager {
     fn append_aml_bytes(&self, bytes: &mut Vec<u8>) {
-        #[cfg(target_arch = "x86_64")]
         if let Some(acpi_address) = self.acpi_address {
             // CPU hotplug controller
             aml::Device::new(
@@ -1876,10 +2640,20 @@ impl Pausable for CpuManager {
             }
         }
 
+        // Freeze the counter of the guest, so that the guest doesn't see how
+        // long it was paused for.
+        if self.freezes_guest_counter() && self.paused_counter.is_none() {
+            self.paused_counter = guest_counter(&self.vcpus)?;
+        }
+
         Ok(())
     }
 
     fn resume(&mut self) -> std::result::Result<(), MigratableError> {
+        if let Some(counter) = self.paused_counter.take() {
+            set_guest_counter(&self.vcpus, counter)?;
+        }
+
         for vcpu in self.vcpus.iter() {
             vcpu.lock().unwrap().resume()?;
         }
@@ -1912,16 +2686,40 @@ impl Snapshottable for CpuManager {
             cpu_manager_snapshot.add_snapshot(cpu_snapshot);
         }
 
+        // The counter the guest was paused with, for the restored vCPUs to
+        // start counting from the same value.
+        if let Some(counter) = self.paused_counter {
+            cpu_manager_snapshot.add_data_section(SnapshotDataSection::new_from_state(
+                GUEST_COUNTER_SNAPSHOT_ID,
+                &counter,
+            )?);
+        }
+
         Ok(cpu_manager_snapshot)
     }
 
     fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
-        for (cpu_id, snapshot) in snapshot.snapshots.iter() {
+        if self.freezes_guest_counter()
+            && snapshot
+                .snapshot_data
+                .contains_key(&format!("{}-section", GUEST_COUNTER_SNAPSHOT_ID))
+        {
+            self.paused_counter = Some(snapshot.to_state(GUEST_COUNTER_SNAPSHOT_ID)?);
+        }
+
+        for (cpu_id, snapshot) in snapshot.snapshots {
             info!("Restoring VCPU {}", cpu_id);
-            self.create_vcpu(cpu_id.parse::<u8>().unwrap(), None, Some(*snapshot.clone()))
+            self.create_vcpu(cpu_id.parse::<u8>().unwrap(), None, Some(*snapshot))
                 .map_err(|e| MigratableError::Restore(anyhow!("Could not create vCPU {:?}", e)))?;
         }
 
+        // Each vCPU got its TSC restored at a slightly different time, so
+        // give them all the offset of the first one to keep their TSCs in
+        // sync.
+        #[cfg(target_arch = "x86_64")]
+        self.synchronize_tsc_offsets()
+            .map_err(|e| MigratableError::Restore(anyhow!("Could not sync the TSCs {:?}", e)))?;
+
         Ok(())
     }
 }
@@ -2361,6 +3159,32 @@ mod tests {
         assert_eq!(lint1_mode_expected, lint1_mode_actual);
     }
 
+    #[test]
+    fn test_guest_counter() {
+        use super::{guest_counter, set_guest_counter, Vcpu};
+        use std::sync::{Arc, Mutex};
+
+        let hv = hypervisor::new().unwrap();
+        let vm = hv.create_vm().expect("new VM fd creation failed");
+        assert_eq!(guest_counter(&[]).unwrap(), None);
+
+        let vcpus: Vec<_> = (0..2)
+            .map(|id| Arc::new(Mutex::new(Vcpu::new(id, &vm, None).unwrap())))
+            .collect();
+
+        // All the vCPUs keep counting from the counter the guest was paused
+        // with, whatever the time spent since.
+        let paused_counter = 1 << 40;
+        set_guest_counter(&vcpus, paused_counter).unwrap();
+        for vcpu in vcpus.iter() {
+            let counter = vcpu.lock().unwrap().vcpu.guest_counter().unwrap().unwrap();
+            assert!(counter >= paused_counter && counter < paused_counter + (1 << 36));
+        }
+
+        let counter = guest_counter(&vcpus).unwrap().unwrap();
+        assert!(counter >= paused_counter && counter < paused_counter + (1 << 36));
+    }
+
     #[test]
     fn test_setup_fpu() {
         let hv = hypervisor::new().unwrap();
@@ -2436,6 +3260,7 @@ mod tests {
 #[cfg(target_arch = "aarch64")]
 #[cfg(test)]
 mod tests {
+    use super::create_pptt;
     use arch::layout;
     use hypervisor::kvm::aarch64::{is_system_register, MPIDR_EL1};
     use hypervisor::kvm::kvm_bindings::{
@@ -2445,6 +3270,54 @@ mod tests {
     use hypervisor::{arm64_core_reg_id, offset__of};
     use std::mem;
 
+    // Returns the flags, parent and ACPI processor ID of the processor
+    // hierarchy nodes of the PPTT, along with their offset.
+    fn pptt_nodes(topology: (u8, u8, u8, u8), boot_vcpus: u8) -> Vec<(usize, u32, u32, u32)> {
+        let pptt = create_pptt(topology, boot_vcpus);
+        let data = pptt.as_slice();
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        (36..data.len())
+            .step_by(20)
+            .map(|offset| {
+                (
+                    offset,
+                    u32_at(offset + 4),
+                    u32_at(offset + 8),
+                    u32_at(offset + 12),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_create_pptt() {
+        // A single die isn't described, the cores belonging to the package.
+        assert_eq!(
+            pptt_nodes((1, 2, 1, 1), 2),
+            vec![(36, 0x2, 0, 0), (56, 0xa, 36, 0), (76, 0xa, 36, 1)]
+        );
+
+        // The dies sit between the packages and the cores.
+        assert_eq!(
+            pptt_nodes((2, 1, 2, 1), 4),
+            vec![
+                (36, 0x2, 0, 0),
+                (56, 0x2, 36, 0),
+                (76, 0x2, 56, 0),
+                (96, 0xe, 76, 0),
+                (116, 0xe, 76, 1),
+                (136, 0x2, 36, 1),
+                (156, 0x2, 136, 0),
+                (176, 0xe, 156, 2),
+                (196, 0xe, 156, 3),
+            ]
+        );
+
+        // The packages without any booted vCPU aren't described.
+        assert_eq!(pptt_nodes((1, 1, 2, 2), 2).len(), 5);
+    }
+
     #[test]
     fn test_setup_regs() {
         let hv = hypervisor::new().unwrap();
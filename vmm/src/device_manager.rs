@@ -9,9 +9,16 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+#[cfg(feature = "fault_injection")]
+use crate::api::VmInjectFaultData;
+#[cfg(target_arch = "aarch64")]
+use crate::config::DoorbellConfig;
+#[cfg(target_arch = "aarch64")]
+use crate::config::DEFAULT_WATCHDOG_TIMEOUT;
 use crate::config::{
-    ConsoleOutputMode, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, UserDeviceConfig,
-    VdpaConfig, VhostMode, VmConfig, VsockConfig,
+    ConsoleOutputMode, DebugConsoleOutputMode, DeviceConfig, DiskConfig, DiskIoEngine, FsConfig,
+    GpuBackend, GpuConfig, NetConfig, PmemConfig, UserDeviceConfig, VdpaConfig, VhostMode,
+    VirtioTransportType, VmConfig, VsockConfig, WatchdogModel,
 };
 use crate::device_tree::{DeviceNode, DeviceTree};
 use crate::interrupt::LegacyUserspaceInterruptManager;
@@ -19,6 +26,7 @@ use crate::interrupt::MsiInterruptManager;
 use crate::memory_manager::{Error as MemoryManagerError, MemoryManager, MEMORY_MANAGER_ACPI_SIZE};
 use crate::pci_segment::PciSegment;
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::security::DeviceIommuStatus;
 use crate::serial_manager::{Error as SerialManagerError, SerialManager};
 use crate::sigwinch_listener::start_sigwinch_listener;
 #[cfg(target_arch = "aarch64")]
@@ -37,7 +45,8 @@ use arch::{DeviceType, MmioDeviceInfo};
 use block_util::{
     async_io::DiskFile, block_io_uring_is_supported, detect_image_type,
     fixed_vhd_async::FixedVhdDiskAsync, fixed_vhd_sync::FixedVhdDiskSync, qcow_sync::QcowDiskSync,
-    raw_async::RawFileDisk, raw_sync::RawFileDiskSync, vhdx_sync::VhdxDiskSync, ImageType,
+    raw_async::RawFileDisk, raw_sync::RawFileDiskSync, raw_threads::RawFileDiskThreads,
+    vhdx_sync::VhdxDiskSync, ImageType,
 };
 #[cfg(target_arch = "aarch64")]
 use devices::gic;
@@ -50,10 +59,12 @@ use devices::legacy::Serial;
 use devices::{
     interrupt_controller, interrupt_controller::InterruptController, AcpiNotificationFlags,
 };
+#[cfg(target_arch = "aarch64")]
+use hypervisor::DataMatch;
 use hypervisor::{DeviceFd, HypervisorVmError, IoEventAddress};
 use libc::{
-    cfmakeraw, isatty, tcgetattr, tcsetattr, termios, MAP_NORESERVE, MAP_PRIVATE, MAP_SHARED,
-    O_TMPFILE, PROT_READ, PROT_WRITE, TCSANOW,
+    cfmakeraw, isatty, tcgetattr, tcsetattr, termios, MAP_ANONYMOUS, MAP_NORESERVE, MAP_PRIVATE,
+    MAP_SHARED, O_TMPFILE, PROT_NONE, PROT_READ, PROT_WRITE, TCSANOW,
 };
 #[cfg(target_arch = "x86_64")]
 use pci::PciConfigIo;
@@ -74,13 +85,21 @@ use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path::PathBuf;
 use std::result;
 use std::sync::{Arc, Mutex};
+#[cfg(feature = "fault_injection")]
+use std::time::Duration;
 use std::time::Instant;
 use vfio_ioctls::{VfioContainer, VfioDevice};
 use virtio_devices::transport::VirtioTransport;
-use virtio_devices::transport::{VirtioPciDevice, VirtioPciDeviceActivator};
+use virtio_devices::transport::{
+    FeaturesOverride, QueueInfo, VirtioDeviceActivator, VirtioPciDevice,
+};
+#[cfg(target_arch = "aarch64")]
+use virtio_devices::transport::{VirtioMmioDevice, MMIO_MAGIC_VALUE};
 use virtio_devices::vhost_user::VhostUserConfig;
 use virtio_devices::{
-    AccessPlatformMapping, ActivateError, VdpaDmaMapping, VirtioMemMappingSource,
+    AccessPlatformMapping, ActivateError, InterruptCoalescingConfig, RateLimiterConfig,
+    VdpaDmaMapping, VirtioDevice, VirtioMemMappingSource, VirtioSharedMemory,
+    VirtioSharedMemoryList,
 };
 use virtio_devices::{Endpoint, IommuMapping};
 use vm_allocator::{AddressAllocator, SystemAllocator};
@@ -101,6 +120,8 @@ use vm_migration::{
     protocol::MemoryRangeTable, Migratable, MigratableError, Pausable, Snapshot,
     SnapshotDataSection, Snapshottable, Transportable,
 };
+#[cfg(feature = "fault_injection")]
+use vm_virtio::fault_injection::Faults;
 use vm_virtio::AccessPlatform;
 use vm_virtio::VirtioDeviceType;
 use vmm_sys_util::eventfd::EventFd;
@@ -108,13 +129,22 @@ use vmm_sys_util::eventfd::EventFd;
 #[cfg(target_arch = "aarch64")]
 const MMIO_LEN: u64 = 0x1000;
 
+// Alignment of the shared memory windows, for them to be backed by hugepages.
+const MMIO_SHM_ALIGNMENT: u64 = 0x0020_0000;
+
 // Singleton devices / devices the user cannot name
 #[cfg(target_arch = "x86_64")]
 const IOAPIC_DEVICE_NAME: &str = "__ioapic";
 const SERIAL_DEVICE_NAME: &str = "__serial";
 #[cfg(target_arch = "aarch64")]
 const GPIO_DEVICE_NAME: &str = "__gpio";
+#[cfg(target_arch = "aarch64")]
+const RTC_DEVICE_NAME: &str = "__rtc";
+#[cfg(target_arch = "aarch64")]
+const DT_HOTPLUG_DEVICE_NAME: &str = "__dt_hotplug";
+const GED_DEVICE_NAME: &str = "__ged";
 const RNG_DEVICE_NAME: &str = "__rng";
+const PTP_DEVICE_NAME: &str = "__ptp";
 const IOMMU_DEVICE_NAME: &str = "__iommu";
 const BALLOON_DEVICE_NAME: &str = "__balloon";
 const CONSOLE_DEVICE_NAME: &str = "__console";
@@ -127,10 +157,32 @@ const NET_DEVICE_NAME_PREFIX: &str = "_net";
 const PMEM_DEVICE_NAME_PREFIX: &str = "_pmem";
 const VDPA_DEVICE_NAME_PREFIX: &str = "_vdpa";
 const VSOCK_DEVICE_NAME_PREFIX: &str = "_vsock";
+const GPU_DEVICE_NAME_PREFIX: &str = "_gpu";
 const WATCHDOG_DEVICE_NAME: &str = "__watchdog";
 const VFIO_DEVICE_NAME_PREFIX: &str = "_vfio";
 const VFIO_USER_DEVICE_NAME_PREFIX: &str = "_vfio_user";
 const VIRTIO_PCI_DEVICE_NAME_PREFIX: &str = "_virtio-pci";
+#[cfg(target_arch = "aarch64")]
+const VIRTIO_MMIO_DEVICE_NAME_PREFIX: &str = "_virtio-mmio";
+// ACPI device describing the virtio-mmio vsock device, notified through the
+// GED when it is hot-added.
+#[cfg(target_arch = "aarch64")]
+const VSOCK_MMIO_ACPI_DEVICE: &str = "_SB_.VSCK";
+#[cfg(target_arch = "aarch64")]
+const DOORBELL_DEVICE_NAME_PREFIX: &str = "_doorbell";
+
+// Default I/O port of the pvpanic device, the guest finds it through ACPI.
+#[cfg(target_arch = "x86_64")]
+const PVPANIC_IO_PORT: u64 = 0x505;
+
+// I/O port of the partition services mailbox, known to the guest from the
+// header describing the mailbox.
+#[cfg(target_arch = "x86_64")]
+const PARTITION_SERVICES_IO_PORT: u64 = 0x540;
+
+// Default I/O port of the debug console, matching the one from QEMU.
+#[cfg(target_arch = "x86_64")]
+const DEBUG_CONSOLE_IO_PORT: u64 = 0xe9;
 
 /// Errors associated with device manager
 #[derive(Debug)]
@@ -156,6 +208,15 @@ pub enum DeviceManagerError {
     /// Cannot create virtio-rng device
     CreateVirtioRng(io::Error),
 
+    /// Cannot create virtio clock device for the PTP clock
+    CreateVirtioPtp(io::Error),
+
+    /// Cannot create the display backend of the virtio-gpu device
+    CreateGpuDisplay(io::Error),
+
+    /// Cannot create virtio-gpu device
+    CreateVirtioGpu(io::Error),
+
     /// Cannot create virtio-fs device
     CreateVirtioFs(virtio_devices::vhost_user::Error),
 
@@ -192,17 +253,44 @@ pub enum DeviceManagerError {
     /// Cannot create virtio-watchdog device
     CreateVirtioWatchdog(io::Error),
 
+    /// Cannot create the SBSA watchdog device
+    #[cfg(target_arch = "aarch64")]
+    CreateSbsaWatchdog(io::Error),
+
+    /// Cannot duplicate the file descriptor of a doorbell channel
+    #[cfg(target_arch = "aarch64")]
+    DupDoorbellFd(io::Error),
+
+    /// Cannot create a doorbell device
+    #[cfg(target_arch = "aarch64")]
+    CreateDoorbell(io::Error),
+
+    /// File descriptor of a doorbell channel which isn't an eventfd
+    #[cfg(target_arch = "aarch64")]
+    DoorbellFdNotEventFd(i32),
+
     /// Failed to parse disk image format
     DetectImageType(io::Error),
 
+    /// The io_uring I/O engine was selected but the host doesn't support it
+    IoUringNotSupported,
+
+    /// The io_uring I/O engine was selected for a disk image format which
+    /// only has a synchronous backend
+    IoUringNotSupportedForImage,
+
+    /// The threads I/O engine was selected for a disk image format other
+    /// than RAW
+    IoThreadsNotSupportedForImage,
+
     /// Cannot open qcow disk path
     QcowDeviceCreate(qcow::Error),
 
     /// Cannot create serial manager
     CreateSerialManager(SerialManagerError),
 
-    /// Cannot spawn the serial manager thread
-    SpawnSerialManager(SerialManagerError),
+    /// Cannot poll the events of a device from the control loop
+    DeviceEvents(io::Error),
 
     /// Cannot open tap interface
     OpenTap(net_util::TapError),
@@ -246,6 +334,9 @@ pub enum DeviceManagerError {
     /// Error creating serial output file
     SerialOutputFileOpen(io::Error),
 
+    /// Error creating debug console output file
+    DebugConsoleOutputFileOpen(io::Error),
+
     /// Error creating console output file
     ConsoleOutputFileOpen(io::Error),
 
@@ -405,6 +496,12 @@ pub enum DeviceManagerError {
     /// Missing virtual IOMMU device
     MissingVirtualIommu,
 
+    /// Missing device sharing its PCI slot with another function
+    MissingMultifunctionDevice(String),
+
+    /// Removing a function of a multi-function device is not supported
+    MultifunctionRemovalNotAllowed,
+
     /// Failed to do power button notification
     PowerButtonNotification(io::Error),
 
@@ -463,22 +560,91 @@ pub enum DeviceManagerError {
     /// Cannot hotplug device behind vIOMMU
     InvalidIommuHotplug,
 
+    /// Cannot hotplug device on the virtio-mmio transport
+    VirtioMmioHotplug,
+
+    /// Cannot allocate MMIO address range for a virtio-mmio device
+    #[cfg(target_arch = "aarch64")]
+    VirtioMmioAllocateAddress,
+
+    /// Missing resources for a virtio-mmio device being restored
+    #[cfg(target_arch = "aarch64")]
+    MissingVirtioMmioResources,
+
+    /// Cannot hotplug virtio-fs device with DAX on the virtio-mmio transport
+    #[cfg(target_arch = "aarch64")]
+    VirtioMmioFsDaxHotplug,
+
+    /// No virtio-mmio slot left for a hot-added device
+    #[cfg(target_arch = "aarch64")]
+    NoMmioHotplugSlot,
+
+    /// Cannot describe a hot-added virtio-mmio device to a guest booted with ACPI
+    #[cfg(target_arch = "aarch64")]
+    MmioHotplugAcpiGuest,
+
+    /// Cannot create the device tree overlay describing a hot-added device
+    #[cfg(target_arch = "aarch64")]
+    CreateDtHotplugOverlay(arch::aarch64::fdt::Error),
+
+    /// Cannot notify the guest of a hot-added device through the device tree
+    #[cfg(target_arch = "aarch64")]
+    DtHotplugNotification(io::Error),
+
     /// Failed to create UEFI flash
     CreateUefiFlash(HypervisorVmError),
 
     /// Invalid identifier as it is not unique.
     IdentifierNotUnique(String),
 
+    /// IRQ pinned for a device not given a legacy IRQ
+    UnusedIrqPin(String),
+
     /// Invalid identifier
     InvalidIdentifier(String),
 
     /// Error activating virtio device
     VirtioActivate(ActivateError),
+
+    /// Virtio device doesn't support being reset
+    VirtioDeviceReset(String),
+
+    /// Failed quiescing a virtio device
+    VirtioDeviceQuiesce(MigratableError),
+
+    /// Failed changing the rate limiting of a virtio device
+    SetRateLimiter(virtio_devices::Error),
+
+    /// Failed changing the interrupt coalescing of a virtio device
+    SetInterruptCoalescing(virtio_devices::Error),
+
+    /// Failed changing the size of the queues of a virtio device
+    SetQueueSize(virtio_devices::Error),
+
+    /// Virtio features enabled through the configuration that the device
+    /// doesn't implement
+    UnsupportedVirtioFeatures(String, u64),
+
+    /// Device faults requested without a device identifier, or VMM wide
+    /// faults requested for a device.
+    #[cfg(feature = "fault_injection")]
+    InvalidFaultInjection,
+
+    /// Hotplug failure injected through the fault injection API
+    #[cfg(feature = "fault_injection")]
+    InjectedHotplugFailure,
 }
 pub type DeviceManagerResult<T> = result::Result<T, DeviceManagerError>;
 
 const DEVICE_MANAGER_ACPI_SIZE: usize = 0x10;
 
+// Tokens of the device events handled by the control loop of the VMM, the
+// doorbells being identified by their index from DOORBELL_EVENT.
+const SERIAL_MANAGER_EVENT: u64 = 0;
+#[cfg(target_arch = "aarch64")]
+const DOORBELL_EVENT: u64 = 1;
+const DEVICE_EVENTS_LEN: usize = 16;
+
 const TIOCSPTLCK: libc::c_int = 0x4004_5431;
 const TIOCGTPEER: libc::c_int = 0x5441;
 
@@ -568,33 +734,24 @@ impl DeviceRelocation for AddressManager {
     ) -> std::result::Result<(), std::io::Error> {
         match region_type {
             PciBarRegionType::IoRegion => {
-                #[cfg(target_arch = "x86_64")]
-                {
-                    // Update system allocator
-                    self.allocator
-                        .lock()
-                        .unwrap()
-                        .free_io_addresses(GuestAddress(old_base), len as GuestUsize);
+                // Update system allocator
+                self.allocator
+                    .lock()
+                    .unwrap()
+                    .free_io_addresses(GuestAddress(old_base), len as GuestUsize);
 
-                    self.allocator
-                        .lock()
-                        .unwrap()
-                        .allocate_io_addresses(
-                            Some(GuestAddress(new_base)),
-                            len as GuestUsize,
-                            None,
-                        )
-                        .ok_or_else(|| {
-                            io::Error::new(io::ErrorKind::Other, "failed allocating new IO range")
-                        })?;
+                self.allocator
+                    .lock()
+                    .unwrap()
+                    .allocate_io_addresses(Some(GuestAddress(new_base)), len as GuestUsize, None)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::Other, "failed allocating new IO range")
+                    })?;
 
-                    // Update PIO bus
-                    self.io_bus
-                        .update_range(old_base, len, new_base, len)
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-                }
-                #[cfg(target_arch = "aarch64")]
-                error!("I/O region is not supported");
+                // Update PIO bus
+                self.io_bus
+                    .update_range(old_base, len, new_base, len)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
             }
             PciBarRegionType::Memory32BitRegion | PciBarRegionType::Memory64BitRegion => {
                 // Update system allocator
@@ -767,10 +924,21 @@ impl DeviceRelocation for AddressManager {
     }
 }
 
+/// Queues of a virtio device, as reported by vm.queues.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeviceQueues {
+    pub id: String,
+    pub activated: bool,
+    pub queues: Vec<QueueInfo>,
+}
+
 #[derive(Serialize, Deserialize)]
 struct DeviceManagerState {
     device_tree: DeviceTree,
     device_id_cnt: Wrapping<usize>,
+    #[cfg(target_arch = "aarch64")]
+    #[serde(default)]
+    acpi_guest: bool,
 }
 
 #[derive(Debug)]
@@ -822,6 +990,17 @@ pub struct DeviceManager {
     // Serial Manager
     serial_manager: Option<Arc<SerialManager>>,
 
+    // Epoll context of the events of the devices handled by the control loop
+    // of the VMM, rather than by a thread of each device.
+    device_events: File,
+
+    #[cfg(target_arch = "aarch64")]
+    doorbells: Vec<Arc<Mutex<devices::legacy::Doorbell>>>,
+
+    // Allocator of the shared memory windows of the virtio-mmio devices
+    #[cfg(target_arch = "aarch64")]
+    mmio_shm_allocator: Option<Arc<Mutex<AddressAllocator>>>,
+
     // pty foreground status,
     console_resize_pipe: Option<Arc<File>>,
 
@@ -891,6 +1070,8 @@ pub struct DeviceManager {
     // Exit event
     exit_evt: EventFd,
     reset_evt: EventFd,
+    panic_evt: EventFd,
+    watchdog_evt: EventFd,
 
     #[cfg(target_arch = "aarch64")]
     id_to_dev_info: HashMap<(DeviceType, String), MmioDeviceInfo>,
@@ -923,23 +1104,70 @@ pub struct DeviceManager {
     // Flash device for UEFI on AArch64
     uefi_flash: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
 
+    // Guest panic notification device
+    pvpanic_device: Option<Arc<Mutex<devices::legacy::PvPanic>>>,
+
+    #[cfg(target_arch = "x86_64")]
+    // Legacy devices brought back to their initial state on reboot
+    serial_device: Option<Arc<Mutex<Serial>>>,
+    #[cfg(target_arch = "x86_64")]
+    cmos_device: Option<Arc<Mutex<devices::legacy::Cmos>>>,
+
     // Flag to force setting the iommu on virtio devices
     force_iommu: bool,
 
     // Helps identify if the VM is currently being restored
     restoring: bool,
 
+    // Devices which must not be restored from the snapshot, but left in the
+    // state they were created with.
+    fresh_devices: Vec<String>,
+
     // io_uring availability if detected
     io_uring_supported: Option<bool>,
 
     // List of unique identifiers provided at boot through the configuration.
     boot_id_list: BTreeSet<String>,
 
+    // Identifiers of the devices given their pinned IRQ
+    used_irq_pins: Mutex<BTreeSet<String>>,
+
     // Start time of the VM
     timestamp: Instant,
 
     // Pending activations
-    pending_activations: Arc<Mutex<Vec<VirtioPciDeviceActivator>>>,
+    pending_activations: Arc<Mutex<Vec<VirtioDeviceActivator>>>,
+
+    // Faults injected into the virtio devices, indexed by device identifier
+    #[cfg(feature = "fault_injection")]
+    faults: HashMap<String, Arc<Faults>>,
+
+    // Whether the device hotplug requests must fail
+    #[cfg(feature = "fault_injection")]
+    hotplug_failure: bool,
+
+    #[cfg(target_arch = "aarch64")]
+    // Devices exposed through the virtio-mmio transport
+    virtio_mmio_devices: Vec<Arc<Mutex<VirtioMmioDevice>>>,
+
+    #[cfg(target_arch = "aarch64")]
+    // Resources of the virtio-mmio vsock device, reserved at boot so that
+    // the device can be described through ACPI even when hot-added.
+    vsock_mmio_slot: Option<MmioDeviceInfo>,
+
+    #[cfg(target_arch = "aarch64")]
+    // Resources reserved at boot for the virtio-mmio devices hot-added to a
+    // guest booted without ACPI, which is notified through the device tree
+    // hotplug device.
+    mmio_hotplug_slots: Vec<MmioDeviceInfo>,
+
+    #[cfg(target_arch = "aarch64")]
+    dt_hotplug_device: Option<Arc<Mutex<devices::legacy::DtHotplug>>>,
+
+    // Whether the guest booted with ACPI, hence ignores the device tree
+    // overlays describing the hot-added virtio-mmio devices.
+    #[cfg(target_arch = "aarch64")]
+    acpi_guest: bool,
 }
 
 impl DeviceManager {
@@ -950,6 +1178,8 @@ impl DeviceManager {
         memory_manager: Arc<Mutex<MemoryManager>>,
         exit_evt: &EventFd,
         reset_evt: &EventFd,
+        panic_evt: &EventFd,
+        watchdog_evt: &EventFd,
         seccomp_action: SeccompAction,
         numa_nodes: NumaNodes,
         activate_evt: &EventFd,
@@ -970,6 +1200,29 @@ impl DeviceManager {
         let start_of_device_area = memory_manager.lock().unwrap().start_of_device_area().0;
         let end_of_device_area = memory_manager.lock().unwrap().end_of_device_area().0;
 
+        // The shared memory windows of the virtio-mmio devices are taken
+        // from the end of the device area, out of the ranges of the PCI
+        // segments that the guest assigns to the PCI devices.
+        #[cfg(target_arch = "aarch64")]
+        let (mmio_shm_allocator, end_of_device_area) = {
+            let mmio_shm_size = Self::mmio_shm_size(&config.lock().unwrap());
+            if mmio_shm_size == 0 {
+                (None, end_of_device_area)
+            } else {
+                let mmio_shm_start = (end_of_device_area + 1)
+                    .checked_sub(mmio_shm_size)
+                    .filter(|start| *start > start_of_device_area)
+                    .ok_or(DeviceManagerError::FsRangeAllocation)?
+                    & !(MMIO_SHM_ALIGNMENT - 1);
+                let allocator = AddressAllocator::new(
+                    GuestAddress(mmio_shm_start),
+                    end_of_device_area + 1 - mmio_shm_start,
+                )
+                .ok_or(DeviceManagerError::FsRangeAllocation)?;
+                (Some(Arc::new(Mutex::new(allocator))), mmio_shm_start - 1)
+            }
+        };
+
         // Start each PCI segment range on a 4GiB boundary
         let pci_segment_size = (end_of_device_area - start_of_device_area + 1)
             / ((4 << 30) * num_pci_segments as u64)
@@ -1006,6 +1259,20 @@ impl DeviceManager {
                 vm,
             ));
 
+        // The pinned and reserved IRQs are taken out of the allocator before
+        // any device is given one.
+        if let Some(irqs) = &config.lock().unwrap().irqs {
+            let mut allocator = address_manager.allocator.lock().unwrap();
+            for irq in irqs
+                .pins
+                .iter()
+                .map(|pin| pin.irq)
+                .chain(irqs.reserved.iter().copied())
+            {
+                allocator.reserve_irq(irq);
+            }
+        }
+
         let acpi_address = address_manager
             .allocator
             .lock()
@@ -1034,6 +1301,12 @@ impl DeviceManager {
             )?);
         }
 
+        // SAFETY: epoll_create1() returns a new file descriptor, owned by
+        // the file.
+        let device_events = unsafe {
+            File::from_raw_fd(epoll::create(true).map_err(DeviceManagerError::DeviceEvents)?)
+        };
+
         let device_manager = DeviceManager {
             address_manager: Arc::clone(&address_manager),
             console: Arc::new(Console::default()),
@@ -1057,6 +1330,10 @@ impl DeviceManager {
             device_tree,
             exit_evt: exit_evt.try_clone().map_err(DeviceManagerError::EventFd)?,
             reset_evt: reset_evt.try_clone().map_err(DeviceManagerError::EventFd)?,
+            panic_evt: panic_evt.try_clone().map_err(DeviceManagerError::EventFd)?,
+            watchdog_evt: watchdog_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?,
             #[cfg(target_arch = "aarch64")]
             id_to_dev_info: HashMap::new(),
             seccomp_action,
@@ -1069,6 +1346,11 @@ impl DeviceManager {
             selected_segment: 0,
             serial_pty: None,
             serial_manager: None,
+            device_events,
+            #[cfg(target_arch = "aarch64")]
+            doorbells: Vec::new(),
+            #[cfg(target_arch = "aarch64")]
+            mmio_shm_allocator,
             console_pty: None,
             console_resize_pipe: None,
             virtio_mem_devices: Vec::new(),
@@ -1076,12 +1358,33 @@ impl DeviceManager {
             gpio_device: None,
             #[cfg(target_arch = "aarch64")]
             uefi_flash: None,
+            pvpanic_device: None,
+            #[cfg(target_arch = "x86_64")]
+            serial_device: None,
+            #[cfg(target_arch = "x86_64")]
+            cmos_device: None,
             force_iommu,
             restoring,
+            fresh_devices: Vec::new(),
             io_uring_supported: None,
             boot_id_list,
+            used_irq_pins: Mutex::new(BTreeSet::new()),
             timestamp,
             pending_activations: Arc::new(Mutex::new(Vec::default())),
+            #[cfg(feature = "fault_injection")]
+            faults: HashMap::new(),
+            #[cfg(feature = "fault_injection")]
+            hotplug_failure: false,
+            #[cfg(target_arch = "aarch64")]
+            virtio_mmio_devices: Vec::new(),
+            #[cfg(target_arch = "aarch64")]
+            vsock_mmio_slot: None,
+            #[cfg(target_arch = "aarch64")]
+            mmio_hotplug_slots: Vec::new(),
+            #[cfg(target_arch = "aarch64")]
+            dt_hotplug_device: None,
+            #[cfg(target_arch = "aarch64")]
+            acpi_guest: false,
         };
 
         let device_manager = Arc::new(Mutex::new(device_manager));
@@ -1098,6 +1401,50 @@ impl DeviceManager {
         Ok(device_manager)
     }
 
+    // IRQ pinned to the device by the configuration, if any.
+    fn pinned_irq(&self, id: &str) -> Option<u32> {
+        let irq = self
+            .config
+            .lock()
+            .unwrap()
+            .irqs
+            .as_ref()?
+            .pins
+            .iter()
+            .find(|pin| pin.id == id)
+            .map(|pin| pin.irq)?;
+        self.used_irq_pins.lock().unwrap().insert(id.to_owned());
+        Some(irq)
+    }
+
+    // The identifiers of the pins are only checked against the devices
+    // created with a legacy IRQ, as the VMM generates some of them.
+    fn check_irq_pins(&self) -> DeviceManagerResult<()> {
+        let used_irq_pins = self.used_irq_pins.lock().unwrap();
+        if let Some(irqs) = &self.config.lock().unwrap().irqs {
+            if let Some(pin) = irqs.pins.iter().find(|p| !used_irq_pins.contains(&p.id)) {
+                return Err(DeviceManagerError::UnusedIrqPin(pin.id.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    // IRQ of a platform device, either pinned by the configuration or the
+    // next available one.
+    fn allocate_irq(&self, id: &str) -> DeviceManagerResult<u32> {
+        if let Some(irq) = self.pinned_irq(id) {
+            return Ok(irq);
+        }
+
+        self.address_manager
+            .allocator
+            .lock()
+            .unwrap()
+            .allocate_irq()
+            .ok_or(DeviceManagerError::AllocateIrq)
+    }
+
     pub fn serial_pty(&self) -> Option<PtyPair> {
         self.serial_pty
             .as_ref()
@@ -1155,6 +1502,27 @@ impl DeviceManager {
         #[cfg(target_arch = "aarch64")]
         self.add_legacy_devices(&legacy_interrupt_manager)?;
 
+        if self.config.lock().unwrap().pvpanic {
+            self.add_pvpanic_device()?;
+        }
+
+        self.add_partition_services_device()?;
+
+        #[cfg(target_arch = "aarch64")]
+        if self.config.lock().unwrap().watchdog_model() == Some(WatchdogModel::Sbsa) {
+            self.add_sbsa_watchdog_device(&legacy_interrupt_manager)?;
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        self.add_doorbell_devices(&legacy_interrupt_manager)?;
+
+        #[cfg(target_arch = "aarch64")]
+        if self.mmio_hotplug_slots_count() > 0 {
+            self.add_dt_hotplug_device(&legacy_interrupt_manager)?;
+        }
+
+        self.add_debug_console_device()?;
+
         {
             self.ged_notification_device = self.add_acpi_devices(
                 &legacy_interrupt_manager,
@@ -1181,21 +1549,46 @@ impl DeviceManager {
 
         self.add_pci_devices(virtio_devices.clone())?;
 
+        #[cfg(target_arch = "aarch64")]
+        virtio_devices.append(&mut self.add_virtio_mmio_devices()?);
+
         self.virtio_devices = virtio_devices;
 
-        Ok(())
+        self.check_irq_pins()
     }
 
     fn state(&self) -> DeviceManagerState {
         DeviceManagerState {
             device_tree: self.device_tree.lock().unwrap().clone(),
             device_id_cnt: self.device_id_cnt,
+            #[cfg(target_arch = "aarch64")]
+            acpi_guest: self.acpi_guest,
         }
     }
 
     fn set_state(&mut self, state: &DeviceManagerState) {
         *self.device_tree.lock().unwrap() = state.device_tree.clone();
         self.device_id_cnt = state.device_id_cnt;
+        #[cfg(target_arch = "aarch64")]
+        {
+            self.acpi_guest = state.acpi_guest;
+        }
+    }
+
+    /// Records whether the guest booted with ACPI, in which case it can't
+    /// find the virtio-mmio devices hot-added through the device tree.
+    #[cfg(target_arch = "aarch64")]
+    pub fn set_acpi_guest(&mut self, acpi_guest: bool) {
+        self.acpi_guest = acpi_guest;
+    }
+
+    /// Retrieve the device tree stored in a DeviceManager snapshot, without
+    /// creating any device.
+    pub fn device_tree_from_snapshot(
+        snapshot: &Snapshot,
+    ) -> std::result::Result<DeviceTree, MigratableError> {
+        let state: DeviceManagerState = snapshot.to_state(DEVICE_MANAGER_SNAPSHOT_ID)?;
+        Ok(state.device_tree)
     }
 
     fn get_msi_iova_space(&mut self) -> (u64, u64) {
@@ -1307,6 +1700,12 @@ impl DeviceManager {
                     .push(Arc::clone(pci_config_io) as Arc<Mutex<dyn BusDevice>>);
             }
 
+            #[cfg(target_arch = "aarch64")]
+            if let Some(pci_io_window) = segment.pci_io_window.as_ref() {
+                self.bus_devices
+                    .push(Arc::clone(pci_io_window) as Arc<Mutex<dyn BusDevice>>);
+            }
+
             self.bus_devices
                 .push(Arc::clone(&segment.pci_config_mmio) as Arc<Mutex<dyn BusDevice>>);
         }
@@ -1320,7 +1719,7 @@ impl DeviceManager {
     ) -> DeviceManagerResult<Arc<Mutex<dyn InterruptController>>> {
         let interrupt_controller: Arc<Mutex<gic::Gic>> = Arc::new(Mutex::new(
             gic::Gic::new(
-                self.config.lock().unwrap().cpus.boot_vcpus,
+                self.config.lock().unwrap().cpus.max_vcpus,
                 Arc::clone(&self.msi_interrupt_manager),
             )
             .map_err(DeviceManagerError::CreateInterruptController)?,
@@ -1406,13 +1805,7 @@ impl DeviceManager {
                 .map_err(DeviceManagerError::BusError)?;
         }
 
-        let ged_irq = self
-            .address_manager
-            .allocator
-            .lock()
-            .unwrap()
-            .allocate_irq()
-            .unwrap();
+        let ged_irq = self.allocate_irq(GED_DEVICE_NAME)?;
         let interrupt_group = interrupt_manager
             .create_group(LegacyIrqGroupConfig {
                 irq: ged_irq as InterruptIndex,
@@ -1507,8 +1900,10 @@ impl DeviceManager {
 
             self.address_manager
                 .io_bus
-                .insert(cmos, 0x70, 0x2)
+                .insert(cmos.clone(), 0x70, 0x2)
                 .map_err(DeviceManagerError::BusError)?;
+
+            self.cmos_device = Some(cmos);
         }
         #[cfg(feature = "fwdebug")]
         {
@@ -1541,13 +1936,7 @@ impl DeviceManager {
         interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
     ) -> DeviceManagerResult<()> {
         // Add a RTC device
-        let rtc_irq = self
-            .address_manager
-            .allocator
-            .lock()
-            .unwrap()
-            .allocate_irq()
-            .unwrap();
+        let rtc_irq = self.allocate_irq(RTC_DEVICE_NAME)?;
 
         let interrupt_group = interrupt_manager
             .create_group(LegacyIrqGroupConfig {
@@ -1578,13 +1967,7 @@ impl DeviceManager {
 
         // Add a GPIO device
         let id = String::from(GPIO_DEVICE_NAME);
-        let gpio_irq = self
-            .address_manager
-            .allocator
-            .lock()
-            .unwrap()
-            .allocate_irq()
-            .unwrap();
+        let gpio_irq = self.allocate_irq(&id)?;
 
         let interrupt_group = interrupt_manager
             .create_group(LegacyIrqGroupConfig {
@@ -1658,70 +2041,590 @@ impl DeviceManager {
         Ok(())
     }
 
-    #[cfg(target_arch = "x86_64")]
-    fn add_serial_device(
-        &mut self,
-        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
-        serial_writer: Option<Box<dyn io::Write + Send>>,
-    ) -> DeviceManagerResult<Arc<Mutex<Serial>>> {
-        // Serial is tied to IRQ #4
-        let serial_irq = 4;
-
-        let id = String::from(SERIAL_DEVICE_NAME);
-
-        let interrupt_group = interrupt_manager
-            .create_group(LegacyIrqGroupConfig {
-                irq: serial_irq as InterruptIndex,
-            })
-            .map_err(DeviceManagerError::CreateInterruptGroup)?;
-
-        let serial = Arc::new(Mutex::new(Serial::new(
-            id.clone(),
-            interrupt_group,
-            serial_writer,
+    fn add_pvpanic_device(&mut self) -> DeviceManagerResult<()> {
+        let pvpanic_device = Arc::new(Mutex::new(devices::legacy::PvPanic::new(
+            self.panic_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?,
         )));
 
         self.bus_devices
-            .push(Arc::clone(&serial) as Arc<Mutex<dyn BusDevice>>);
+            .push(Arc::clone(&pvpanic_device) as Arc<Mutex<dyn BusDevice>>);
 
-        self.address_manager
-            .allocator
-            .lock()
-            .unwrap()
-            .allocate_io_addresses(Some(GuestAddress(0x3f8)), 0x8, None)
-            .ok_or(DeviceManagerError::AllocateIoPort)?;
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate_io_addresses(Some(GuestAddress(PVPANIC_IO_PORT)), 0x1, None)
+                .ok_or(DeviceManagerError::AllocateIoPort)?;
 
-        self.address_manager
-            .io_bus
-            .insert(serial.clone(), 0x3f8, 0x8)
-            .map_err(DeviceManagerError::BusError)?;
+            self.address_manager
+                .io_bus
+                .insert(pvpanic_device.clone(), PVPANIC_IO_PORT, 0x1)
+                .map_err(DeviceManagerError::BusError)?;
+        }
 
-        // Fill the device tree with a new node. In case of restore, we
-        // know there is nothing to do, so we can simply override the
-        // existing entry.
-        self.device_tree
-            .lock()
-            .unwrap()
-            .insert(id.clone(), device_node!(id, serial));
+        #[cfg(target_arch = "aarch64")]
+        {
+            let addr = arch::layout::LEGACY_PVPANIC_MAPPED_IO_START;
 
-        Ok(serial)
-    }
+            self.address_manager
+                .mmio_bus
+                .insert(pvpanic_device.clone(), addr.0, MMIO_LEN)
+                .map_err(DeviceManagerError::BusError)?;
+
+            // The device doesn't use any interrupt.
+            self.id_to_dev_info.insert(
+                (DeviceType::PvPanic, "pvpanic".to_string()),
+                MmioDeviceInfo {
+                    addr: addr.0,
+                    len: MMIO_LEN,
+                    irq: 0,
+                },
+            );
+        }
+
+        self.pvpanic_device = Some(pvpanic_device);
+
+        Ok(())
+    }
+
+    fn add_partition_services_device(&mut self) -> DeviceManagerResult<()> {
+        let partition_services_config = match self.config.lock().unwrap().partition_services.clone()
+        {
+            Some(partition_services_config) => partition_services_config,
+            None => return Ok(()),
+        };
+
+        let partition_services = Arc::new(Mutex::new(devices::legacy::PartitionServices::new(
+            partition_services_config.partition_id,
+            self.reset_evt
+                .try_clone()
+                .map_err(DeviceManagerError::EventFd)?,
+        )));
+
+        self.bus_devices
+            .push(Arc::clone(&partition_services) as Arc<Mutex<dyn BusDevice>>);
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate_io_addresses(
+                    Some(GuestAddress(PARTITION_SERVICES_IO_PORT)),
+                    devices::legacy::PARTITION_SERVICES_SIZE,
+                    None,
+                )
+                .ok_or(DeviceManagerError::AllocateIoPort)?;
+
+            self.address_manager
+                .io_bus
+                .insert(
+                    partition_services,
+                    PARTITION_SERVICES_IO_PORT,
+                    devices::legacy::PARTITION_SERVICES_SIZE,
+                )
+                .map_err(DeviceManagerError::BusError)?;
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let addr = arch::layout::LEGACY_PARTITION_SERVICES_MAPPED_IO_START;
+
+            self.address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate_legacy_mmio_addresses(Some(addr), MMIO_LEN, None)
+                .ok_or(DeviceManagerError::AllocateMmioAddress)?;
+
+            self.address_manager
+                .mmio_bus
+                .insert(partition_services, addr.0, MMIO_LEN)
+                .map_err(DeviceManagerError::BusError)?;
+
+            // The device doesn't use any interrupt.
+            self.id_to_dev_info.insert(
+                (
+                    DeviceType::PartitionServices,
+                    "partition-services".to_string(),
+                ),
+                MmioDeviceInfo {
+                    addr: addr.0,
+                    len: MMIO_LEN,
+                    irq: 0,
+                },
+            );
+        }
+
+        Ok(())
+    }
 
     #[cfg(target_arch = "aarch64")]
+    fn add_sbsa_watchdog_device(
+        &mut self,
+        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
+    ) -> DeviceManagerResult<()> {
+        let id = String::from(WATCHDOG_DEVICE_NAME);
+        info!("Creating SBSA watchdog device: id = {}", id);
+
+        let (timeout, timeout_set) = {
+            let config = self.config.lock().unwrap();
+            (
+                config
+                    .watchdog_config
+                    .as_ref()
+                    .map(|watchdog_config| watchdog_config.timeout)
+                    .unwrap_or(DEFAULT_WATCHDOG_TIMEOUT),
+                config
+                    .cmdline
+                    .args
+                    .split_whitespace()
+                    .any(|arg| arg.starts_with("sbsa_gwdt.timeout=")),
+            )
+        };
+
+        // The control frame is followed by the refresh frame.
+        let len = 2 * devices::legacy::SBSA_GWDT_FRAME_SIZE;
+        let addr = self
+            .address_manager
+            .allocator
+            .lock()
+            .unwrap()
+            .allocate_platform_mmio_addresses(
+                None,
+                len,
+                Some(devices::legacy::SBSA_GWDT_FRAME_SIZE),
+            )
+            .ok_or(DeviceManagerError::AllocateMmioAddress)?;
+
+        let irq = self.allocate_irq(&id)?;
+
+        let interrupt_group = interrupt_manager
+            .create_group(LegacyIrqGroupConfig {
+                irq: irq as InterruptIndex,
+            })
+            .map_err(DeviceManagerError::CreateInterruptGroup)?;
+
+        let watchdog_device = Arc::new(Mutex::new(
+            devices::legacy::SbsaWatchdog::new(
+                id.clone(),
+                interrupt_group,
+                self.watchdog_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
+                self.timestamp,
+            )
+            .map_err(DeviceManagerError::CreateSbsaWatchdog)?,
+        ));
+
+        self.bus_devices
+            .push(Arc::clone(&watchdog_device) as Arc<Mutex<dyn BusDevice>>);
+
+        self.address_manager
+            .mmio_bus
+            .insert(watchdog_device.clone(), addr.0, len)
+            .map_err(DeviceManagerError::BusError)?;
+
+        self.id_to_dev_info.insert(
+            (DeviceType::Watchdog, "watchdog".to_string()),
+            MmioDeviceInfo {
+                addr: addr.0,
+                len,
+                irq,
+            },
+        );
+
+        // The guest driver programs the watchdog, so that it expires once
+        // the timeout elapsed without being refreshed, unless the timeout is
+        // already set on the command line.
+        if !timeout_set {
+            self.cmdline_additions
+                .push(format!("sbsa_gwdt.timeout={}", timeout));
+        }
+
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, watchdog_device));
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn add_doorbell_devices(
+        &mut self,
+        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
+    ) -> DeviceManagerResult<()> {
+        let mut doorbells = self.config.lock().unwrap().doorbells.clone();
+        if let Some(doorbell_list_cfg) = &mut doorbells {
+            for doorbell_cfg in doorbell_list_cfg.iter_mut() {
+                self.add_doorbell_device(interrupt_manager, doorbell_cfg)?;
+            }
+        }
+        self.config.lock().unwrap().doorbells = doorbells;
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn add_doorbell_device(
+        &mut self,
+        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
+        doorbell_cfg: &mut DoorbellConfig,
+    ) -> DeviceManagerResult<()> {
+        let id = if let Some(id) = &doorbell_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(DOORBELL_DEVICE_NAME_PREFIX)?;
+            doorbell_cfg.id = Some(id.clone());
+            id
+        };
+        info!("Creating doorbell device: {:?}", doorbell_cfg);
+
+        // The file descriptors are duplicated, so that they remain valid for
+        // the device created again when the VM is rebooted.
+        let mut eventfds = Vec::new();
+        for fd in doorbell_cfg.fds.iter() {
+            // The guest rings the channels by writing the eventfds, which
+            // must not be any other file of the VMM.
+            if !is_eventfd(*fd) {
+                return Err(DeviceManagerError::DoorbellFdNotEventFd(*fd));
+            }
+            // SAFETY: FFI call, the returned file descriptor is checked.
+            let fd = unsafe { libc::dup(*fd) };
+            if fd < 0 {
+                return Err(DeviceManagerError::DupDoorbellFd(io::Error::last_os_error()));
+            }
+            // SAFETY: fd is a valid file descriptor, owned by the eventfd.
+            eventfds.push(unsafe { EventFd::from_raw_fd(fd) });
+        }
+        let mut eventfds = eventfds.into_iter();
+        let mut channels = Vec::new();
+        while let (Some(ring), Some(notify)) = (eventfds.next(), eventfds.next()) {
+            channels.push(devices::legacy::DoorbellChannel { ring, notify });
+        }
+
+        let addr = self
+            .address_manager
+            .allocator
+            .lock()
+            .unwrap()
+            .allocate_platform_mmio_addresses(None, devices::legacy::DOORBELL_SIZE, None)
+            .ok_or(DeviceManagerError::AllocateMmioAddress)?;
+
+        let irq = self.allocate_irq(&id)?;
+
+        let interrupt_group = interrupt_manager
+            .create_group(LegacyIrqGroupConfig {
+                irq: irq as InterruptIndex,
+            })
+            .map_err(DeviceManagerError::CreateInterruptGroup)?;
+
+        let doorbell_device = Arc::new(Mutex::new(
+            devices::legacy::Doorbell::new(id.clone(), channels, interrupt_group)
+                .map_err(DeviceManagerError::CreateDoorbell)?,
+        ));
+
+        self.add_device_event(
+            doorbell_device.lock().unwrap().as_raw_fd(),
+            DOORBELL_EVENT + self.doorbells.len() as u64,
+        )?;
+        self.doorbells.push(doorbell_device.clone());
+
+        self.bus_devices
+            .push(Arc::clone(&doorbell_device) as Arc<Mutex<dyn BusDevice>>);
+
+        self.address_manager
+            .mmio_bus
+            .insert(
+                doorbell_device.clone(),
+                addr.0,
+                devices::legacy::DOORBELL_SIZE,
+            )
+            .map_err(DeviceManagerError::BusError)?;
+
+        self.id_to_dev_info.insert(
+            (DeviceType::Doorbell, id.clone()),
+            MmioDeviceInfo {
+                addr: addr.0,
+                len: devices::legacy::DOORBELL_SIZE,
+                irq,
+            },
+        );
+
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, doorbell_device));
+
+        Ok(())
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn mmio_hotplug_slots_count(&self) -> u8 {
+        self.config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .map_or(0, |p| p.mmio_hotplug_slots)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn add_dt_hotplug_device(
+        &mut self,
+        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
+    ) -> DeviceManagerResult<()> {
+        let id = String::from(DT_HOTPLUG_DEVICE_NAME);
+        info!("Creating device tree hotplug device: id = {}", id);
+
+        let addr = arch::layout::LEGACY_DT_HOTPLUG_MAPPED_IO_START;
+        let irq = self.allocate_irq(&id)?;
+
+        let interrupt_group = interrupt_manager
+            .create_group(LegacyIrqGroupConfig {
+                irq: irq as InterruptIndex,
+            })
+            .map_err(DeviceManagerError::CreateInterruptGroup)?;
+
+        let dt_hotplug_device = Arc::new(Mutex::new(devices::legacy::DtHotplug::new(
+            id.clone(),
+            interrupt_group,
+        )));
+
+        self.bus_devices
+            .push(Arc::clone(&dt_hotplug_device) as Arc<Mutex<dyn BusDevice>>);
+
+        self.address_manager
+            .mmio_bus
+            .insert(
+                dt_hotplug_device.clone(),
+                addr.0,
+                devices::legacy::DT_HOTPLUG_SIZE,
+            )
+            .map_err(DeviceManagerError::BusError)?;
+
+        self.id_to_dev_info.insert(
+            (DeviceType::DtHotplug, id.clone()),
+            MmioDeviceInfo {
+                addr: addr.0,
+                len: devices::legacy::DT_HOTPLUG_SIZE,
+                irq,
+            },
+        );
+
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, dt_hotplug_device));
+
+        self.dt_hotplug_device = Some(dt_hotplug_device);
+
+        Ok(())
+    }
+
+    fn add_debug_console_device(&mut self) -> DeviceManagerResult<()> {
+        let debug_console_config = match self.config.lock().unwrap().debug_console.clone() {
+            Some(debug_console_config) => debug_console_config,
+            None => return Ok(()),
+        };
+
+        let out = match debug_console_config.mode {
+            DebugConsoleOutputMode::File => devices::legacy::DebugConsoleOutput::Writer(Box::new(
+                File::create(debug_console_config.file.as_ref().unwrap())
+                    .map_err(DeviceManagerError::DebugConsoleOutputFileOpen)?,
+            )),
+            DebugConsoleOutputMode::Log => devices::legacy::DebugConsoleOutput::Event,
+        };
+
+        let debug_console = Arc::new(Mutex::new(devices::legacy::DebugConsole::new(out)));
+
+        self.bus_devices
+            .push(Arc::clone(&debug_console) as Arc<Mutex<dyn BusDevice>>);
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let port = debug_console_config.iobase.unwrap_or(DEBUG_CONSOLE_IO_PORT);
+
+            self.address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate_io_addresses(Some(GuestAddress(port)), 0x1, None)
+                .ok_or(DeviceManagerError::AllocateIoPort)?;
+
+            self.address_manager
+                .io_bus
+                .insert(debug_console, port, 0x1)
+                .map_err(DeviceManagerError::BusError)?;
+        }
+
+        // The MMIO address, given or not, is taken from the platform MMIO
+        // space, the register having no alignment requirement.
+        #[cfg(target_arch = "aarch64")]
+        {
+            let addr = self
+                .address_manager
+                .allocator
+                .lock()
+                .unwrap()
+                .allocate_platform_mmio_addresses(
+                    debug_console_config.iobase.map(GuestAddress),
+                    0x1,
+                    Some(0x1),
+                )
+                .ok_or(DeviceManagerError::AllocateMmioAddress)?;
+
+            self.address_manager
+                .mmio_bus
+                .insert(debug_console, addr.0, 0x1)
+                .map_err(DeviceManagerError::BusError)?;
+
+            // The device doesn't use any interrupt.
+            self.id_to_dev_info.insert(
+                (DeviceType::DebugConsole, "debug_console".to_string()),
+                MmioDeviceInfo {
+                    addr: addr.0,
+                    len: 0x1,
+                    irq: 0,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn add_device_event(&self, fd: RawFd, token: u64) -> DeviceManagerResult<()> {
+        epoll::ctl(
+            self.device_events.as_raw_fd(),
+            epoll::ControlOptions::EPOLL_CTL_ADD,
+            fd,
+            epoll::Event::new(epoll::Events::EPOLLIN, token),
+        )
+        .map_err(DeviceManagerError::DeviceEvents)
+    }
+
+    /// Epoll file descriptor of the device events, readable when some of
+    /// them are to be handled through `handle_device_events()`.
+    pub fn device_events_fd(&self) -> RawFd {
+        self.device_events.as_raw_fd()
+    }
+
+    /// Handles the events of the serial manager and of the doorbells.
+    pub fn handle_device_events(&self) {
+        let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); DEVICE_EVENTS_LEN];
+        let num_events = match epoll::wait(self.device_events.as_raw_fd(), 0, &mut events[..]) {
+            Ok(num_events) => num_events,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::Interrupted {
+                    error!("Error waiting for the device events: {}", e);
+                }
+                return;
+            }
+        };
+
+        for event in events.iter().take(num_events) {
+            match event.data {
+                SERIAL_MANAGER_EVENT => {
+                    if let Some(serial_manager) = &self.serial_manager {
+                        if let Err(e) = serial_manager.handle_events() {
+                            // The console input is no longer handled, as
+                            // when the serial manager had its own thread.
+                            error!("Error handling the serial console events: {}", e);
+                            epoll::ctl(
+                                self.device_events.as_raw_fd(),
+                                epoll::ControlOptions::EPOLL_CTL_DEL,
+                                serial_manager.as_raw_fd(),
+                                epoll::Event::new(epoll::Events::empty(), 0),
+                            )
+                            .ok();
+                        }
+                    }
+                }
+                #[cfg(target_arch = "aarch64")]
+                token if token >= DOORBELL_EVENT => {
+                    if let Some(doorbell) = self.doorbells.get((token - DOORBELL_EVENT) as usize) {
+                        doorbell.lock().unwrap().process_notifications();
+                    }
+                }
+                token => warn!("Unknown device event: {}", token),
+            }
+        }
+    }
+
+    /// Events reported by the guest through the pvpanic device.
+    pub fn guest_panic_events(&self) -> u8 {
+        self.pvpanic_device
+            .as_ref()
+            .map(|pvpanic| pvpanic.lock().unwrap().events())
+            .unwrap_or(0)
+    }
+
+    #[cfg(target_arch = "x86_64")]
     fn add_serial_device(
         &mut self,
         interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
         serial_writer: Option<Box<dyn io::Write + Send>>,
-    ) -> DeviceManagerResult<Arc<Mutex<Pl011>>> {
+    ) -> DeviceManagerResult<Arc<Mutex<Serial>>> {
+        // Serial is tied to IRQ #4
+        let serial_irq = 4;
+
         let id = String::from(SERIAL_DEVICE_NAME);
 
-        let serial_irq = self
-            .address_manager
+        let interrupt_group = interrupt_manager
+            .create_group(LegacyIrqGroupConfig {
+                irq: serial_irq as InterruptIndex,
+            })
+            .map_err(DeviceManagerError::CreateInterruptGroup)?;
+
+        let serial = Arc::new(Mutex::new(Serial::new(
+            id.clone(),
+            interrupt_group,
+            serial_writer,
+        )));
+
+        self.bus_devices
+            .push(Arc::clone(&serial) as Arc<Mutex<dyn BusDevice>>);
+
+        self.address_manager
             .allocator
             .lock()
             .unwrap()
-            .allocate_irq()
-            .unwrap();
+            .allocate_io_addresses(Some(GuestAddress(0x3f8)), 0x8, None)
+            .ok_or(DeviceManagerError::AllocateIoPort)?;
+
+        self.address_manager
+            .io_bus
+            .insert(serial.clone(), 0x3f8, 0x8)
+            .map_err(DeviceManagerError::BusError)?;
+
+        // Fill the device tree with a new node. In case of restore, we
+        // know there is nothing to do, so we can simply override the
+        // existing entry.
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, serial));
+
+        self.serial_device = Some(serial.clone());
+
+        Ok(serial)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn add_serial_device(
+        &mut self,
+        interrupt_manager: &Arc<dyn InterruptManager<GroupConfig = LegacyIrqGroupConfig>>,
+        serial_writer: Option<Box<dyn io::Write + Send>>,
+    ) -> DeviceManagerResult<Arc<Mutex<Pl011>>> {
+        let id = String::from(SERIAL_DEVICE_NAME);
+
+        let serial_irq = self.allocate_irq(&id)?;
 
         let interrupt_group = interrupt_manager
             .create_group(LegacyIrqGroupConfig {
@@ -1962,14 +2865,8 @@ impl DeviceManager {
                     let serial_manager =
                         SerialManager::new(serial, self.serial_pty.clone(), serial_config.mode)
                             .map_err(DeviceManagerError::CreateSerialManager)?;
-                    if let Some(mut serial_manager) = serial_manager {
-                        serial_manager
-                            .start_thread(
-                                self.exit_evt
-                                    .try_clone()
-                                    .map_err(DeviceManagerError::EventFd)?,
-                            )
-                            .map_err(DeviceManagerError::SpawnSerialManager)?;
+                    if let Some(serial_manager) = serial_manager {
+                        self.add_device_event(serial_manager.as_raw_fd(), SERIAL_MANAGER_EVENT)?;
                         Some(Arc::new(serial_manager))
                     } else {
                         None
@@ -2002,7 +2899,11 @@ impl DeviceManager {
         // Add virtio-vsock if required
         devices.append(&mut self.make_virtio_vsock_devices()?);
 
-        devices.append(&mut self.make_virtio_mem_devices()?);
+        // The virtio-mem devices on the virtio-mmio transport are created
+        // separately.
+        if self.config.lock().unwrap().memory.virtio_mem_transport == VirtioTransportType::Pci {
+            devices.append(&mut self.make_virtio_mem_devices()?);
+        }
 
         // Add virtio-balloon if required
         devices.append(&mut self.make_virtio_balloon_devices()?);
@@ -2010,12 +2911,46 @@ impl DeviceManager {
         // Add virtio-watchdog device
         devices.append(&mut self.make_virtio_watchdog_devices()?);
 
+        // Add virtio clock device for the PTP clock if required
+        devices.append(&mut self.make_virtio_ptp_devices()?);
+
+        // Add virtio-gpu if required
+        devices.append(&mut self.make_virtio_gpu_devices()?);
+
         // Add vDPA devices if required
         devices.append(&mut self.make_vdpa_devices()?);
 
         Ok(devices)
     }
 
+    // Optional devices which can't be created when the VM boots are left out
+    // of the VM, and of its configuration, instead of failing the boot. They
+    // must be restored though, as their state is part of the snapshot.
+    fn optional_device<T>(
+        &self,
+        result: DeviceManagerResult<T>,
+        optional: bool,
+        id: &Option<String>,
+    ) -> DeviceManagerResult<Option<T>> {
+        match result {
+            Ok(device) => Ok(Some(device)),
+            Err(e) if optional && !self.restoring => {
+                let id = id.as_deref().unwrap_or_default();
+                warn!("Skipping optional device {}: {:?}", id, e);
+                event!(
+                    "vm",
+                    "device-skipped",
+                    "id",
+                    id,
+                    "error",
+                    format!("{:?}", e)
+                );
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     // Cache whether io_uring is supported to avoid probing for very block device
     fn io_uring_is_supported(&mut self) -> bool {
         if let Some(supported) = self.io_uring_supported {
@@ -2090,11 +3025,37 @@ impl DeviceManager {
             let image_type =
                 detect_image_type(&mut file).map_err(DeviceManagerError::DetectImageType)?;
 
+            // Use asynchronous backend relying on io_uring if the syscalls
+            // are supported, unless another engine was selected.
+            let io_engine = match disk_cfg.io_engine {
+                Some(DiskIoEngine::IoUring) => {
+                    if !self.io_uring_is_supported() {
+                        return Err(DeviceManagerError::IoUringNotSupported);
+                    }
+                    if matches!(image_type, ImageType::Qcow2 | ImageType::Vhdx) {
+                        return Err(DeviceManagerError::IoUringNotSupportedForImage);
+                    }
+                    DiskIoEngine::IoUring
+                }
+                Some(DiskIoEngine::Threads) => {
+                    if !matches!(image_type, ImageType::Raw) {
+                        return Err(DeviceManagerError::IoThreadsNotSupportedForImage);
+                    }
+                    DiskIoEngine::Threads
+                }
+                Some(DiskIoEngine::Sync) => DiskIoEngine::Sync,
+                None => {
+                    if self.io_uring_is_supported() && !disk_cfg.disable_io_uring {
+                        DiskIoEngine::IoUring
+                    } else {
+                        DiskIoEngine::Sync
+                    }
+                }
+            };
+
             let image = match image_type {
                 ImageType::FixedVhd => {
-                    // Use asynchronous backend relying on io_uring if the
-                    // syscalls are supported.
-                    if self.io_uring_is_supported() && !disk_cfg.disable_io_uring {
+                    if io_engine == DiskIoEngine::IoUring {
                         info!("Using asynchronous fixed VHD disk file (io_uring)");
                         Box::new(
                             FixedVhdDiskAsync::new(file)
@@ -2108,17 +3069,20 @@ impl DeviceManager {
                         ) as Box<dyn DiskFile>
                     }
                 }
-                ImageType::Raw => {
-                    // Use asynchronous backend relying on io_uring if the
-                    // syscalls are supported.
-                    if self.io_uring_is_supported() && !disk_cfg.disable_io_uring {
+                ImageType::Raw => match io_engine {
+                    DiskIoEngine::IoUring => {
                         info!("Using asynchronous RAW disk file (io_uring)");
                         Box::new(RawFileDisk::new(file)) as Box<dyn DiskFile>
-                    } else {
+                    }
+                    DiskIoEngine::Threads => {
+                        info!("Using asynchronous RAW disk file (threads)");
+                        Box::new(RawFileDiskThreads::new(file)) as Box<dyn DiskFile>
+                    }
+                    DiskIoEngine::Sync => {
                         info!("Using synchronous RAW disk file");
                         Box::new(RawFileDiskSync::new(file)) as Box<dyn DiskFile>
                     }
-                }
+                },
                 ImageType::Qcow2 => {
                     info!("Using synchronous QCOW disk file");
                     Box::new(
@@ -2156,6 +3120,11 @@ impl DeviceManager {
                 )
                 .map_err(DeviceManagerError::CreateVirtioBlock)?,
             ));
+            virtio_block
+                .lock()
+                .unwrap()
+                .set_interrupt_coalescing_config(disk_cfg.interrupt_coalescing)
+                .map_err(DeviceManagerError::SetInterruptCoalescing)?;
 
             (
                 Arc::clone(&virtio_block) as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
@@ -2185,9 +3154,15 @@ impl DeviceManager {
 
         let mut block_devices = self.config.lock().unwrap().disks.clone();
         if let Some(disk_list_cfg) = &mut block_devices {
+            let mut skipped = Vec::new();
             for disk_cfg in disk_list_cfg.iter_mut() {
-                devices.push(self.make_virtio_block_device(disk_cfg)?);
+                let device = self.make_virtio_block_device(disk_cfg);
+                match self.optional_device(device, disk_cfg.optional, &disk_cfg.id)? {
+                    Some(device) => devices.push(device),
+                    None => skipped.push(disk_cfg.id.clone()),
+                }
             }
+            disk_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
         }
         self.config.lock().unwrap().disks = block_devices;
 
@@ -2300,6 +3275,18 @@ impl DeviceManager {
                     .map_err(DeviceManagerError::CreateVirtioNet)?,
                 ))
             };
+            virtio_net
+                .lock()
+                .unwrap()
+                .set_interrupt_coalescing_config(net_cfg.interrupt_coalescing)
+                .map_err(DeviceManagerError::SetInterruptCoalescing)?;
+            if net_cfg.vhost_net {
+                virtio_net
+                    .lock()
+                    .unwrap()
+                    .enable_vhost_net(self.memory_manager.lock().unwrap().guest_memory())
+                    .map_err(DeviceManagerError::CreateVirtioNet)?;
+            }
 
             (
                 Arc::clone(&virtio_net) as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
@@ -2329,9 +3316,19 @@ impl DeviceManager {
         let mut devices = Vec::new();
         let mut net_devices = self.config.lock().unwrap().net.clone();
         if let Some(net_list_cfg) = &mut net_devices {
+            let mut skipped = Vec::new();
             for net_cfg in net_list_cfg.iter_mut() {
-                devices.push(self.make_virtio_net_device(net_cfg)?);
+                // The devices on the virtio-mmio transport are created separately.
+                if net_cfg.transport == VirtioTransportType::Mmio {
+                    continue;
+                }
+                let device = self.make_virtio_net_device(net_cfg);
+                match self.optional_device(device, net_cfg.optional, &net_cfg.id)? {
+                    Some(device) => devices.push(device),
+                    None => skipped.push(net_cfg.id.clone()),
+                }
             }
+            net_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
         }
         self.config.lock().unwrap().net = net_devices;
 
@@ -2352,6 +3349,12 @@ impl DeviceManager {
                     id.clone(),
                     rng_path,
                     self.force_iommu | rng_config.iommu,
+                    self.config
+                        .lock()
+                        .unwrap()
+                        .deterministic
+                        .as_ref()
+                        .map(|d| d.seed),
                     self.seccomp_action.clone(),
                     self.exit_evt
                         .try_clone()
@@ -2397,6 +3400,12 @@ impl DeviceManager {
         let mut node = device_node!(id);
 
         if let Some(fs_socket) = fs_cfg.socket.to_str() {
+            let cache = if fs_cfg.dax {
+                Some(self.make_virtio_fs_cache(&id, fs_cfg, &mut node)?)
+            } else {
+                None
+            };
+
             let virtio_fs_device = Arc::new(Mutex::new(
                 virtio_devices::vhost_user::Fs::new(
                     id.clone(),
@@ -2404,7 +3413,7 @@ impl DeviceManager {
                     &fs_cfg.tag,
                     fs_cfg.num_queues,
                     fs_cfg.queue_size,
-                    None,
+                    cache,
                     self.seccomp_action.clone(),
                     self.restoring,
                     self.exit_evt
@@ -2432,14 +3441,123 @@ impl DeviceManager {
         }
     }
 
+    // Size of the area holding the DAX windows of the virtio-fs devices on
+    // the virtio-mmio transport.
+    #[cfg(target_arch = "aarch64")]
+    fn mmio_shm_size(config: &VmConfig) -> u64 {
+        config
+            .fs
+            .iter()
+            .flatten()
+            .filter(|fs_cfg| fs_cfg.dax && fs_cfg.transport == VirtioTransportType::Mmio)
+            .map(|fs_cfg| (fs_cfg.cache_size + MMIO_SHM_ALIGNMENT - 1) & !(MMIO_SHM_ALIGNMENT - 1))
+            .sum()
+    }
+
+    // Creates the DAX cache of a virtio-fs device, the window of the guest
+    // address space the files are mapped into by the backend. It is reported
+    // to the guest as the shared memory region of the device, through a
+    // dedicated BAR or the shared memory registers of the transport.
+    fn make_virtio_fs_cache(
+        &mut self,
+        id: &str,
+        fs_cfg: &FsConfig,
+        node: &mut DeviceNode,
+    ) -> DeviceManagerResult<(VirtioSharedMemoryList, MmapRegion)> {
+        // Look for the id in the device tree. If it can be found, that means
+        // the device is being restored, otherwise it's created from scratch.
+        let cache_base = self.device_tree.lock().unwrap().get(id).and_then(|node| {
+            node.resources.iter().find_map(|resource| match resource {
+                Resource::MmioAddressRange { base, .. } => Some(*base),
+                _ => None,
+            })
+        });
+
+        #[cfg(target_arch = "aarch64")]
+        let allocator = if fs_cfg.transport == VirtioTransportType::Mmio {
+            self.mmio_shm_allocator
+                .clone()
+                .ok_or(DeviceManagerError::FsRangeAllocation)?
+        } else {
+            self.pci_segments[fs_cfg.pci_segment as usize]
+                .allocator
+                .clone()
+        };
+        #[cfg(not(target_arch = "aarch64"))]
+        let allocator = self.pci_segments[fs_cfg.pci_segment as usize]
+            .allocator
+            .clone();
+
+        // The memory needs to be 2MiB aligned in order to support
+        // hugepages.
+        let cache_size = fs_cfg.cache_size;
+        let cache_base = allocator
+            .lock()
+            .unwrap()
+            .allocate(
+                cache_base.map(GuestAddress),
+                cache_size as GuestUsize,
+                Some(MMIO_SHM_ALIGNMENT),
+            )
+            .ok_or(DeviceManagerError::FsRangeAllocation)?
+            .raw_value();
+
+        // The pages are only backed once the backend maps files into the
+        // window.
+        let mmap_region = MmapRegion::build(
+            None,
+            cache_size as usize,
+            PROT_NONE,
+            MAP_ANONYMOUS | MAP_PRIVATE | MAP_NORESERVE,
+        )
+        .map_err(DeviceManagerError::NewMmapRegion)?;
+        let host_addr = mmap_region.as_ptr() as u64;
+
+        let mem_slot = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .create_userspace_mapping(cache_base, cache_size, host_addr, false, false, false)
+            .map_err(DeviceManagerError::MemoryManager)?;
+
+        node.resources.push(Resource::MmioAddressRange {
+            base: cache_base,
+            size: cache_size,
+        });
+
+        Ok((
+            VirtioSharedMemoryList {
+                host_addr,
+                mem_slot,
+                addr: GuestAddress(cache_base),
+                len: cache_size as GuestUsize,
+                region_list: vec![VirtioSharedMemory {
+                    offset: 0,
+                    len: cache_size,
+                }],
+            },
+            mmap_region,
+        ))
+    }
+
     fn make_virtio_fs_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
         let mut devices = Vec::new();
 
         let mut fs_devices = self.config.lock().unwrap().fs.clone();
         if let Some(fs_list_cfg) = &mut fs_devices {
+            let mut skipped = Vec::new();
             for fs_cfg in fs_list_cfg.iter_mut() {
-                devices.push(self.make_virtio_fs_device(fs_cfg)?);
+                // The devices on the virtio-mmio transport are created separately.
+                if fs_cfg.transport == VirtioTransportType::Mmio {
+                    continue;
+                }
+                let device = self.make_virtio_fs_device(fs_cfg);
+                match self.optional_device(device, fs_cfg.optional, &fs_cfg.id)? {
+                    Some(device) => devices.push(device),
+                    None => skipped.push(fs_cfg.id.clone()),
+                }
             }
+            fs_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
         }
         self.config.lock().unwrap().fs = fs_devices;
 
@@ -2621,9 +3739,19 @@ impl DeviceManager {
         // Add virtio-pmem if required
         let mut pmem_devices = self.config.lock().unwrap().pmem.clone();
         if let Some(pmem_list_cfg) = &mut pmem_devices {
+            let mut skipped = Vec::new();
             for pmem_cfg in pmem_list_cfg.iter_mut() {
-                devices.push(self.make_virtio_pmem_device(pmem_cfg)?);
+                // The devices on the virtio-mmio transport are created separately.
+                if pmem_cfg.transport == VirtioTransportType::Mmio {
+                    continue;
+                }
+                let device = self.make_virtio_pmem_device(pmem_cfg);
+                match self.optional_device(device, pmem_cfg.optional, &pmem_cfg.id)? {
+                    Some(device) => devices.push(device),
+                    None => skipped.push(pmem_cfg.id.clone()),
+                }
             }
+            pmem_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
         }
         self.config.lock().unwrap().pmem = pmem_devices;
 
@@ -2690,7 +3818,10 @@ impl DeviceManager {
 
         let mut vsock = self.config.lock().unwrap().vsock.clone();
         if let Some(ref mut vsock_cfg) = &mut vsock {
-            devices.push(self.make_virtio_vsock_device(vsock_cfg)?);
+            // The devices on the virtio-mmio transport are created separately.
+            if vsock_cfg.transport == VirtioTransportType::Pci {
+                devices.push(self.make_virtio_vsock_device(vsock_cfg)?);
+            }
         }
         self.config.lock().unwrap().vsock = vsock;
 
@@ -2766,6 +3897,7 @@ impl DeviceManager {
                     balloon_config.size,
                     balloon_config.deflate_on_oom,
                     balloon_config.free_page_reporting,
+                    balloon_config.stats_polling_interval,
                     self.seccomp_action.clone(),
                     self.exit_evt
                         .try_clone()
@@ -2777,9 +3909,92 @@ impl DeviceManager {
             self.balloon = Some(virtio_balloon_device.clone());
 
             devices.push(MetaVirtioDevice {
-                virtio_device: Arc::clone(&virtio_balloon_device)
+                virtio_device: Arc::clone(&virtio_balloon_device)
+                    as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+                iommu: false,
+                id: id.clone(),
+                pci_segment: 0,
+                dma_handler: None,
+            });
+
+            self.device_tree
+                .lock()
+                .unwrap()
+                .insert(id.clone(), device_node!(id, virtio_balloon_device));
+        }
+
+        Ok(devices)
+    }
+
+    fn make_virtio_watchdog_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let watchdog_config = {
+            let config = self.config.lock().unwrap();
+            if config.watchdog_model() != Some(WatchdogModel::Virtio) {
+                return Ok(devices);
+            }
+            config.watchdog_config.clone().unwrap_or_default()
+        };
+
+        let id = String::from(WATCHDOG_DEVICE_NAME);
+        info!("Creating virtio-watchdog device: id = {}", id);
+
+        let virtio_watchdog_device = Arc::new(Mutex::new(
+            virtio_devices::Watchdog::new(
+                id.clone(),
+                self.watchdog_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
+                watchdog_config.timeout as u64,
+                self.seccomp_action.clone(),
+                self.exit_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
+            )
+            .map_err(DeviceManagerError::CreateVirtioWatchdog)?,
+        ));
+        devices.push(MetaVirtioDevice {
+            virtio_device: Arc::clone(&virtio_watchdog_device)
+                as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+            iommu: false,
+            id: id.clone(),
+            pci_segment: 0,
+            dma_handler: None,
+        });
+
+        self.device_tree
+            .lock()
+            .unwrap()
+            .insert(id.clone(), device_node!(id, virtio_watchdog_device));
+
+        Ok(devices)
+    }
+
+    fn make_virtio_ptp_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let ptp_config = self.config.lock().unwrap().ptp.clone();
+        if let Some(ptp_config) = ptp_config {
+            info!("Creating virtio clock device: {:?}", ptp_config);
+            let id = String::from(PTP_DEVICE_NAME);
+
+            let virtio_ptp_device = Arc::new(Mutex::new(
+                virtio_devices::Ptp::new(
+                    id.clone(),
+                    &ptp_config.path,
+                    self.force_iommu | ptp_config.iommu,
+                    self.seccomp_action.clone(),
+                    self.exit_evt
+                        .try_clone()
+                        .map_err(DeviceManagerError::EventFd)?,
+                )
+                .map_err(DeviceManagerError::CreateVirtioPtp)?,
+            ));
+            devices.push(MetaVirtioDevice {
+                virtio_device: Arc::clone(&virtio_ptp_device)
                     as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
-                iommu: false,
+                iommu: ptp_config.iommu,
                 id: id.clone(),
                 pci_segment: 0,
                 dma_handler: None,
@@ -2788,46 +4003,75 @@ impl DeviceManager {
             self.device_tree
                 .lock()
                 .unwrap()
-                .insert(id.clone(), device_node!(id, virtio_balloon_device));
+                .insert(id.clone(), device_node!(id, virtio_ptp_device));
         }
 
         Ok(devices)
     }
 
-    fn make_virtio_watchdog_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
-        let mut devices = Vec::new();
+    fn make_virtio_gpu_device(
+        &mut self,
+        gpu_cfg: &mut GpuConfig,
+    ) -> DeviceManagerResult<MetaVirtioDevice> {
+        let id = if let Some(id) = &gpu_cfg.id {
+            id.clone()
+        } else {
+            let id = self.next_device_name(GPU_DEVICE_NAME_PREFIX)?;
+            gpu_cfg.id = Some(id.clone());
+            id
+        };
 
-        if !self.config.lock().unwrap().watchdog {
-            return Ok(devices);
-        }
+        info!("Creating virtio-gpu device: {:?}", gpu_cfg);
 
-        let id = String::from(WATCHDOG_DEVICE_NAME);
-        info!("Creating virtio-watchdog device: id = {}", id);
+        let display: Box<dyn virtio_devices::gpu::DisplayBackend> = match gpu_cfg.backend {
+            GpuBackend::Socket => Box::new(virtio_devices::gpu::SocketDisplay::new()),
+            GpuBackend::Dmabuf => Box::new(
+                virtio_devices::gpu::DmabufDisplay::new()
+                    .map_err(DeviceManagerError::CreateGpuDisplay)?,
+            ),
+        };
 
-        let virtio_watchdog_device = Arc::new(Mutex::new(
-            virtio_devices::Watchdog::new(
+        let gpu_device = Arc::new(Mutex::new(
+            virtio_devices::Gpu::new(
                 id.clone(),
-                self.reset_evt.try_clone().unwrap(),
+                gpu_cfg.width,
+                gpu_cfg.height,
+                &gpu_cfg.socket,
+                display,
+                self.force_iommu | gpu_cfg.iommu,
                 self.seccomp_action.clone(),
                 self.exit_evt
                     .try_clone()
                     .map_err(DeviceManagerError::EventFd)?,
             )
-            .map_err(DeviceManagerError::CreateVirtioWatchdog)?,
+            .map_err(DeviceManagerError::CreateVirtioGpu)?,
         ));
-        devices.push(MetaVirtioDevice {
-            virtio_device: Arc::clone(&virtio_watchdog_device)
-                as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
-            iommu: false,
-            id: id.clone(),
-            pci_segment: 0,
-            dma_handler: None,
-        });
 
         self.device_tree
             .lock()
             .unwrap()
-            .insert(id.clone(), device_node!(id, virtio_watchdog_device));
+            .insert(id.clone(), device_node!(id, gpu_device));
+
+        Ok(MetaVirtioDevice {
+            virtio_device: Arc::clone(&gpu_device) as Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+            iommu: gpu_cfg.iommu,
+            id,
+            pci_segment: gpu_cfg.pci_segment,
+            dma_handler: None,
+        })
+    }
+
+    fn make_virtio_gpu_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let mut gpu = self.config.lock().unwrap().gpu.clone();
+        if let Some(ref mut gpu_cfg) = &mut gpu {
+            // The devices on the virtio-mmio transport are created separately.
+            if gpu_cfg.transport == VirtioTransportType::Pci {
+                devices.push(self.make_virtio_gpu_device(gpu_cfg)?);
+            }
+        }
+        self.config.lock().unwrap().gpu = gpu;
 
         Ok(devices)
     }
@@ -2886,9 +4130,15 @@ impl DeviceManager {
         // Add vdpa if required
         let mut vdpa_devices = self.config.lock().unwrap().vdpa.clone();
         if let Some(vdpa_list_cfg) = &mut vdpa_devices {
+            let mut skipped = Vec::new();
             for vdpa_cfg in vdpa_list_cfg.iter_mut() {
-                devices.push(self.make_vdpa_device(vdpa_cfg)?);
+                let device = self.make_vdpa_device(vdpa_cfg);
+                match self.optional_device(device, vdpa_cfg.optional, &vdpa_cfg.id)? {
+                    Some(device) => devices.push(device),
+                    None => skipped.push(vdpa_cfg.id.clone()),
+                }
             }
+            vdpa_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
         }
         self.config.lock().unwrap().vdpa = vdpa_devices;
 
@@ -2981,8 +4231,29 @@ impl DeviceManager {
             id
         };
 
+        let in_device_tree = self.device_tree.lock().unwrap().contains_key(&vfio_name);
         let (pci_segment_id, pci_device_bdf, resources) =
-            self.pci_resources(&vfio_name, device_cfg.pci_segment)?;
+            match (&device_cfg.multifunction_of, device_cfg.function) {
+                (Some(parent_id), Some(function)) if !in_device_tree => {
+                    // The device is placed in the slot of the device it is a
+                    // function of, which is not allocated again.
+                    let parent_bdf = self
+                        .device_tree
+                        .lock()
+                        .unwrap()
+                        .get(parent_id)
+                        .and_then(|node| node.pci_bdf)
+                        .ok_or_else(|| {
+                            DeviceManagerError::MissingMultifunctionDevice(parent_id.clone())
+                        })?;
+                    (
+                        parent_bdf.segment(),
+                        PciBdf::new(parent_bdf.segment(), 0, parent_bdf.device(), function),
+                        None,
+                    )
+                }
+                _ => self.pci_resources(&vfio_name, device_cfg.pci_segment)?,
+            };
 
         let mut needs_dma_mapping = false;
 
@@ -3151,7 +4422,7 @@ impl DeviceManager {
             .unwrap();
 
         pci_bus
-            .add_device(bdf.device() as u32, pci_device)
+            .add_function(bdf.device() as u32, bdf.function() as u32, pci_device)
             .map_err(DeviceManagerError::AddPciDevice)?;
 
         self.bus_devices.push(Arc::clone(&bus_device));
@@ -3159,7 +4430,6 @@ impl DeviceManager {
         pci_bus
             .register_mapping(
                 bus_device,
-                #[cfg(target_arch = "x86_64")]
                 self.address_manager.io_bus.as_ref(),
                 self.address_manager.mmio_bus.as_ref(),
                 bars.clone(),
@@ -3185,12 +4455,26 @@ impl DeviceManager {
         let mut devices = self.config.lock().unwrap().devices.clone();
 
         if let Some(device_list_cfg) = &mut devices {
-            for device_cfg in device_list_cfg.iter_mut() {
-                let (device_id, _) = self.add_passthrough_device(device_cfg)?;
-                if device_cfg.iommu && self.iommu_device.is_some() {
-                    iommu_attached_device_ids.push(device_id);
+            let mut skipped = Vec::new();
+            // Functions sharing the PCI slot of another device can only be
+            // added once that device is.
+            for functions in [false, true] {
+                for device_cfg in device_list_cfg
+                    .iter_mut()
+                    .filter(|d| d.multifunction_of.is_some() == functions)
+                {
+                    let device = self.add_passthrough_device(device_cfg);
+                    match self.optional_device(device, device_cfg.optional, &device_cfg.id)? {
+                        Some((device_id, _)) => {
+                            if device_cfg.iommu && self.iommu_device.is_some() {
+                                iommu_attached_device_ids.push(device_id);
+                            }
+                        }
+                        None => skipped.push(device_cfg.id.clone()),
+                    }
                 }
             }
+            device_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
         }
 
         // Update the list of devices
@@ -3310,9 +4594,17 @@ impl DeviceManager {
         let mut user_devices = self.config.lock().unwrap().user_devices.clone();
 
         if let Some(device_list_cfg) = &mut user_devices {
+            let mut skipped = Vec::new();
             for device_cfg in device_list_cfg.iter_mut() {
-                let (_device_id, _id) = self.add_vfio_user_device(device_cfg)?;
+                let device = self.add_vfio_user_device(device_cfg);
+                if self
+                    .optional_device(device, device_cfg.optional, &device_cfg.id)?
+                    .is_none()
+                {
+                    skipped.push(device_cfg.id.clone());
+                }
             }
+            device_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
         }
 
         // Update the list of devices
@@ -3405,7 +4697,22 @@ impl DeviceManager {
             }
         }
 
+        let use_ioeventfd = self
+            .config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .map(|pc| pc.ioeventfd)
+            .unwrap_or(true);
+
         let device_type = virtio_device.lock().unwrap().device_type();
+        let features_override =
+            self.features_override(&virtio_device_id, virtio_device.lock().unwrap().features())?;
+        virtio_device
+            .lock()
+            .unwrap()
+            .set_thread_nice(self.thread_nice());
         let virtio_pci_device = Arc::new(Mutex::new(
             VirtioPciDevice::new(
                 id.clone(),
@@ -3425,9 +4732,27 @@ impl DeviceManager {
                 pci_segment_id > 0 || device_type != VirtioDeviceType::Block as u32,
                 dma_handler,
                 self.pending_activations.clone(),
+                use_ioeventfd,
             )
             .map_err(DeviceManagerError::VirtioDevice)?,
         ));
+        virtio_pci_device
+            .lock()
+            .unwrap()
+            .set_features_override(features_override);
+
+        #[cfg(feature = "fault_injection")]
+        {
+            let faults = Arc::new(Faults::default());
+            let mut virtio_pci_device = virtio_pci_device.lock().unwrap();
+            virtio_pci_device.set_faults(faults.clone());
+            virtio_pci_device
+                .virtio_device()
+                .lock()
+                .unwrap()
+                .set_faults(faults.clone());
+            self.faults.insert(virtio_device_id.clone(), faults);
+        }
 
         let new_resources = self.add_pci_device(
             virtio_pci_device.clone(),
@@ -3456,6 +4781,352 @@ impl DeviceManager {
         Ok(pci_device_bdf)
     }
 
+    // Creates the devices configured to use the virtio-mmio transport, which
+    // are described to the guest through the device tree. The resources of
+    // the vsock device are reserved even if it isn't configured, so that it
+    // can be hot-added and described through ACPI.
+    #[cfg(target_arch = "aarch64")]
+    fn add_virtio_mmio_devices(&mut self) -> DeviceManagerResult<Vec<MetaVirtioDevice>> {
+        let mut devices = Vec::new();
+
+        let mut net_devices = self.config.lock().unwrap().net.clone();
+        if let Some(net_list_cfg) = &mut net_devices {
+            let mut skipped = Vec::new();
+            for net_cfg in net_list_cfg.iter_mut() {
+                if net_cfg.transport != VirtioTransportType::Mmio {
+                    continue;
+                }
+                let device = self.make_virtio_net_device(net_cfg);
+                match self.optional_device(device, net_cfg.optional, &net_cfg.id)? {
+                    Some(handle) => {
+                        self.add_virtio_mmio_device(
+                            handle.virtio_device.clone(),
+                            handle.id.clone(),
+                            None,
+                        )?;
+                        devices.push(handle);
+                    }
+                    None => skipped.push(net_cfg.id.clone()),
+                }
+            }
+            net_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
+        }
+        self.config.lock().unwrap().net = net_devices;
+
+        let mut vsock = self.config.lock().unwrap().vsock.clone();
+        if let Some(ref mut vsock_cfg) = &mut vsock {
+            if vsock_cfg.transport == VirtioTransportType::Mmio {
+                let handle = self.make_virtio_vsock_device(vsock_cfg)?;
+                self.vsock_mmio_slot = Some(self.add_virtio_mmio_device(
+                    handle.virtio_device.clone(),
+                    handle.id.clone(),
+                    None,
+                )?);
+                devices.push(handle);
+            }
+        }
+        self.config.lock().unwrap().vsock = vsock;
+
+        let mut gpu = self.config.lock().unwrap().gpu.clone();
+        if let Some(ref mut gpu_cfg) = &mut gpu {
+            if gpu_cfg.transport == VirtioTransportType::Mmio {
+                let handle = self.make_virtio_gpu_device(gpu_cfg)?;
+                self.add_virtio_mmio_device(handle.virtio_device.clone(), handle.id.clone(), None)?;
+                devices.push(handle);
+            }
+        }
+        self.config.lock().unwrap().gpu = gpu;
+
+        let mut fs_devices = self.config.lock().unwrap().fs.clone();
+        if let Some(fs_list_cfg) = &mut fs_devices {
+            let mut skipped = Vec::new();
+            for fs_cfg in fs_list_cfg.iter_mut() {
+                if fs_cfg.transport != VirtioTransportType::Mmio {
+                    continue;
+                }
+                let device = self.make_virtio_fs_device(fs_cfg);
+                match self.optional_device(device, fs_cfg.optional, &fs_cfg.id)? {
+                    Some(handle) => {
+                        self.add_virtio_mmio_device(
+                            handle.virtio_device.clone(),
+                            handle.id.clone(),
+                            None,
+                        )?;
+                        devices.push(handle);
+                    }
+                    None => skipped.push(fs_cfg.id.clone()),
+                }
+            }
+            fs_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
+        }
+        self.config.lock().unwrap().fs = fs_devices;
+
+        let mut pmem_devices = self.config.lock().unwrap().pmem.clone();
+        if let Some(pmem_list_cfg) = &mut pmem_devices {
+            let mut skipped = Vec::new();
+            for pmem_cfg in pmem_list_cfg.iter_mut() {
+                if pmem_cfg.transport != VirtioTransportType::Mmio {
+                    continue;
+                }
+                let device = self.make_virtio_pmem_device(pmem_cfg);
+                match self.optional_device(device, pmem_cfg.optional, &pmem_cfg.id)? {
+                    Some(handle) => {
+                        self.add_virtio_mmio_device(
+                            handle.virtio_device.clone(),
+                            handle.id.clone(),
+                            None,
+                        )?;
+                        devices.push(handle);
+                    }
+                    None => skipped.push(pmem_cfg.id.clone()),
+                }
+            }
+            pmem_list_cfg.retain(|cfg| !skipped.contains(&cfg.id));
+        }
+        self.config.lock().unwrap().pmem = pmem_devices;
+
+        if self.config.lock().unwrap().memory.virtio_mem_transport == VirtioTransportType::Mmio {
+            for handle in self.make_virtio_mem_devices()? {
+                self.add_virtio_mmio_device(handle.virtio_device.clone(), handle.id.clone(), None)?;
+                devices.push(handle);
+            }
+        }
+
+        if self.vsock_mmio_slot.is_none() {
+            let mut allocator = self.address_manager.allocator.lock().unwrap();
+            let addr = allocator
+                .allocate_platform_mmio_addresses(None, MMIO_LEN, Some(MMIO_LEN))
+                .ok_or(DeviceManagerError::VirtioMmioAllocateAddress)?
+                .0;
+            let irq = allocator
+                .allocate_irq()
+                .ok_or(DeviceManagerError::AllocateIrq)?;
+            self.vsock_mmio_slot = Some(MmioDeviceInfo {
+                addr,
+                len: MMIO_LEN,
+                irq,
+            });
+        }
+
+        if let Some(ged) = &self.ged_notification_device {
+            ged.lock()
+                .unwrap()
+                .add_mmio_hotplug_device(format!("\\{}", VSOCK_MMIO_ACPI_DEVICE));
+        }
+
+        for _ in 0..self.mmio_hotplug_slots_count() {
+            let mut allocator = self.address_manager.allocator.lock().unwrap();
+            let addr = allocator
+                .allocate_platform_mmio_addresses(None, MMIO_LEN, Some(MMIO_LEN))
+                .ok_or(DeviceManagerError::VirtioMmioAllocateAddress)?
+                .0;
+            let irq = allocator
+                .allocate_irq()
+                .ok_or(DeviceManagerError::AllocateIrq)?;
+            self.mmio_hotplug_slots.push(MmioDeviceInfo {
+                addr,
+                len: MMIO_LEN,
+                irq,
+            });
+        }
+
+        Ok(devices)
+    }
+
+    // Adds a virtio-mmio device, either on the resources found in the device
+    // tree when restoring, on the given reserved ones, or on newly allocated
+    // ones.
+    #[cfg(target_arch = "aarch64")]
+    fn add_virtio_mmio_device(
+        &mut self,
+        virtio_device: Arc<Mutex<dyn virtio_devices::VirtioDevice>>,
+        virtio_device_id: String,
+        reserved: Option<MmioDeviceInfo>,
+    ) -> DeviceManagerResult<MmioDeviceInfo> {
+        let id = format!("{}-{}", VIRTIO_MMIO_DEVICE_NAME_PREFIX, virtio_device_id);
+
+        // Add the new virtio-mmio node to the device tree.
+        let mut node = device_node!(id);
+        node.children = vec![virtio_device_id.clone()];
+
+        // Look for the id in the device tree. If it can be found, that means
+        // the device is being restored, otherwise it's created from scratch.
+        let resources = if let Some(node) = self.device_tree.lock().unwrap().get(&id) {
+            info!("Restoring virtio-mmio {} resources", id);
+
+            let mut addr = None;
+            let mut irq = None;
+            for resource in node.resources.iter() {
+                match resource {
+                    Resource::MmioAddressRange { base, .. } => addr = Some(*base),
+                    Resource::LegacyIrq(i) => irq = Some(*i),
+                    _ => error!("Unexpected resource {:?} for {}", resource, id),
+                }
+            }
+
+            Some(
+                addr.zip(irq)
+                    .ok_or(DeviceManagerError::MissingVirtioMmioResources)?,
+            )
+        } else {
+            None
+        };
+
+        let pinned_irq = self.pinned_irq(&virtio_device_id);
+        let (addr, irq) = {
+            let mut allocator = self.address_manager.allocator.lock().unwrap();
+            match (resources, reserved) {
+                (Some((addr, irq)), _) => {
+                    allocator
+                        .allocate_platform_mmio_addresses(
+                            Some(GuestAddress(addr)),
+                            MMIO_LEN,
+                            Some(MMIO_LEN),
+                        )
+                        .ok_or(DeviceManagerError::VirtioMmioAllocateAddress)?;
+                    (addr, irq)
+                }
+                (None, Some(info)) => (info.addr, info.irq),
+                (None, None) => {
+                    let addr = allocator
+                        .allocate_platform_mmio_addresses(None, MMIO_LEN, Some(MMIO_LEN))
+                        .ok_or(DeviceManagerError::VirtioMmioAllocateAddress)?
+                        .0;
+                    let irq = match pinned_irq {
+                        Some(irq) => irq,
+                        None => allocator
+                            .allocate_irq()
+                            .ok_or(DeviceManagerError::AllocateIrq)?,
+                    };
+                    (addr, irq)
+                }
+            }
+        };
+
+        // Update the existing virtio node by setting the parent.
+        if let Some(node) = self.device_tree.lock().unwrap().get_mut(&virtio_device_id) {
+            node.parent = Some(id.clone());
+        } else {
+            return Err(DeviceManagerError::MissingNode);
+        }
+
+        let interrupt_group = self
+            .legacy_interrupt_manager
+            .as_ref()
+            .ok_or(DeviceManagerError::NoLegacyInterruptManager)?
+            .create_group(LegacyIrqGroupConfig {
+                irq: irq as InterruptIndex,
+            })
+            .map_err(DeviceManagerError::CreateInterruptGroup)?;
+
+        let use_ioeventfd = self
+            .config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .map(|pc| pc.ioeventfd)
+            .unwrap_or(true);
+
+        let memory = self.memory_manager.lock().unwrap().guest_memory();
+        let device_type = virtio_device.lock().unwrap().device_type();
+        let shm_regions = virtio_device.lock().unwrap().get_shm_regions();
+        let features_override =
+            self.features_override(&virtio_device_id, virtio_device.lock().unwrap().features())?;
+        virtio_device
+            .lock()
+            .unwrap()
+            .set_thread_nice(self.thread_nice());
+        let virtio_mmio_device = Arc::new(Mutex::new(
+            VirtioMmioDevice::new(
+                id.clone(),
+                memory,
+                virtio_device,
+                interrupt_group,
+                self.activate_evt
+                    .try_clone()
+                    .map_err(DeviceManagerError::EventFd)?,
+                self.pending_activations.clone(),
+                use_ioeventfd,
+            )
+            .map_err(DeviceManagerError::VirtioDevice)?,
+        ));
+        virtio_mmio_device
+            .lock()
+            .unwrap()
+            .set_features_override(features_override);
+
+        #[cfg(feature = "fault_injection")]
+        {
+            let faults = Arc::new(Faults::default());
+            let mut virtio_mmio_device = virtio_mmio_device.lock().unwrap();
+            virtio_mmio_device.set_faults(faults.clone());
+            virtio_mmio_device
+                .virtio_device()
+                .lock()
+                .unwrap()
+                .set_faults(faults.clone());
+            self.faults.insert(virtio_device_id.clone(), faults);
+        }
+
+        self.address_manager
+            .mmio_bus
+            .insert(virtio_mmio_device.clone(), addr, MMIO_LEN)
+            .map_err(DeviceManagerError::BusError)?;
+
+        for (event, addr, queue_index) in virtio_mmio_device.lock().unwrap().ioeventfds(addr) {
+            self.address_manager
+                .vm
+                .register_ioevent(
+                    event,
+                    &IoEventAddress::Mmio(addr),
+                    Some(DataMatch::DataMatch32(queue_index)),
+                )
+                .map_err(|e| DeviceManagerError::RegisterIoevent(e.into()))?;
+        }
+
+        self.bus_devices
+            .push(Arc::clone(&virtio_mmio_device) as Arc<Mutex<dyn BusDevice>>);
+
+        if let Some(shm_regions) = shm_regions {
+            self.id_to_dev_info.insert(
+                (DeviceType::SharedMemory, virtio_device_id.clone()),
+                MmioDeviceInfo {
+                    addr: shm_regions.addr.raw_value(),
+                    len: shm_regions.len,
+                    irq: 0,
+                },
+            );
+        }
+
+        self.id_to_dev_info.insert(
+            (DeviceType::Virtio(device_type), virtio_device_id),
+            MmioDeviceInfo {
+                addr,
+                len: MMIO_LEN,
+                irq,
+            },
+        );
+
+        node.resources = vec![
+            Resource::MmioAddressRange {
+                base: addr,
+                size: MMIO_LEN,
+            },
+            Resource::LegacyIrq(irq),
+        ];
+        node.migratable = Some(Arc::clone(&virtio_mmio_device) as Arc<Mutex<dyn Migratable>>);
+        self.device_tree.lock().unwrap().insert(id, node);
+
+        self.virtio_mmio_devices.push(virtio_mmio_device);
+
+        Ok(MmioDeviceInfo {
+            addr,
+            len: MMIO_LEN,
+            irq,
+        })
+    }
+
     fn pci_resources(
         &self,
         id: &str,
@@ -3471,12 +5142,15 @@ impl DeviceManager {
                     .ok_or(DeviceManagerError::MissingDeviceNodePciBdf)?;
                 let pci_segment_id = pci_device_bdf.segment();
 
-                self.pci_segments[pci_segment_id as usize]
-                    .pci_bus
-                    .lock()
-                    .unwrap()
-                    .get_device_id(pci_device_bdf.device() as usize)
-                    .map_err(DeviceManagerError::GetPciDeviceId)?;
+                // Functions other than 0 share the slot of another device.
+                if pci_device_bdf.function() == 0 {
+                    self.pci_segments[pci_segment_id as usize]
+                        .pci_bus
+                        .lock()
+                        .unwrap()
+                        .get_device_id(pci_device_bdf.device() as usize)
+                        .map_err(DeviceManagerError::GetPciDeviceId)?;
+                }
 
                 (pci_segment_id, pci_device_bdf, Some(node.resources.clone()))
             } else {
@@ -3488,7 +5162,6 @@ impl DeviceManager {
         )
     }
 
-    #[cfg(target_arch = "x86_64")]
     pub fn io_bus(&self) -> &Arc<Bus> {
         &self.address_manager.io_bus
     }
@@ -3575,15 +5248,106 @@ impl DeviceManager {
             }
         }
 
-        Ok(())
-    }
+        Ok(())
+    }
+
+    pub fn activate_virtio_devices(&self) -> DeviceManagerResult<()> {
+        for mut activator in self.pending_activations.lock().unwrap().drain(..) {
+            activator
+                .activate()
+                .map_err(DeviceManagerError::VirtioActivate)?;
+        }
+        Ok(())
+    }
+
+    /// Guest address range of the memory of a virtio-pmem device.
+    pub fn pmem_range(&self, id: &str) -> Option<(GuestAddress, u64)> {
+        self.device_tree
+            .lock()
+            .unwrap()
+            .get(id)?
+            .resources
+            .iter()
+            .find_map(|resource| match resource {
+                Resource::MmioAddressRange { base, size } => Some((GuestAddress(*base), *size)),
+                _ => None,
+            })
+    }
+
+    /// Returns true if some devices can't be brought back to their initial
+    /// state without being re-created, which is the case of devices passed
+    /// through with VFIO or vfio-user.
+    pub fn has_passthrough_devices(&self) -> bool {
+        self.device_tree.lock().unwrap().iter().any(|(_, node)| {
+            matches!(
+                node.pci_device_handle,
+                Some(PciDeviceHandle::Vfio(_)) | Some(PciDeviceHandle::VfioUser(_))
+            )
+        })
+    }
+
+    /// Brings the devices back to their initial state, as part of an
+    /// in-place reboot of the VM. This is performed in two passes:
+    /// - every activated virtio device is quiesced first, so that none of
+    ///   them keeps processing its queues while the others are being reset,
+    /// - the devices are then reset in reverse breadth-first order, meaning
+    ///   children are reset before their parent. The virtio transports are
+    ///   reprogrammed as part of this, with MSI-X disabled.
+    ///
+    /// The legacy devices holding a state, the serial port and the CMOS, are
+    /// reset as well. In-place reboots being limited to x86_64, the devices
+    /// on the virtio-mmio transport are never reset this way.
+    pub fn reset_devices(&self) -> DeviceManagerResult<()> {
+        let device_tree = self.device_tree.lock().unwrap();
+
+        for node in device_tree.breadth_first_traversal().rev() {
+            if let Some(PciDeviceHandle::Virtio(virtio_pci_device)) = &node.pci_device_handle {
+                if !virtio_pci_device.lock().unwrap().is_activated() {
+                    continue;
+                }
+
+                // The virtio device is the child of its transport node.
+                for child in node.children.iter() {
+                    if let Some(migratable) = device_tree
+                        .get(child)
+                        .and_then(|child_node| child_node.migratable.as_ref())
+                    {
+                        info!("Quiescing {}", child);
+                        migratable
+                            .lock()
+                            .unwrap()
+                            .pause()
+                            .map_err(DeviceManagerError::VirtioDeviceQuiesce)?;
+                    }
+                }
+            }
+        }
+
+        for node in device_tree.breadth_first_traversal().rev() {
+            if let Some(PciDeviceHandle::Virtio(virtio_pci_device)) = &node.pci_device_handle {
+                info!("Resetting {}", node.id);
+                if !virtio_pci_device.lock().unwrap().reset() {
+                    return Err(DeviceManagerError::VirtioDeviceReset(node.id.clone()));
+                }
+            }
+        }
 
-    pub fn activate_virtio_devices(&self) -> DeviceManagerResult<()> {
-        for mut activator in self.pending_activations.lock().unwrap().drain(..) {
-            activator
-                .activate()
-                .map_err(DeviceManagerError::VirtioActivate)?;
+        // Panics reported by the guest only relate to its previous boot.
+        if let Some(pvpanic_device) = &self.pvpanic_device {
+            pvpanic_device.lock().unwrap().reset();
+        }
+
+        // The i8042 device doesn't hold any state.
+        #[cfg(target_arch = "x86_64")]
+        {
+            if let Some(serial_device) = &self.serial_device {
+                serial_device.lock().unwrap().reset();
+            }
+            if let Some(cmos_device) = &self.cmos_device {
+                cmos_device.lock().unwrap().reset();
+            }
         }
+
         Ok(())
     }
 
@@ -3607,6 +5371,9 @@ impl DeviceManager {
     ) -> DeviceManagerResult<PciDeviceInfo> {
         self.validate_identifier(&device_cfg.id)?;
 
+        #[cfg(feature = "fault_injection")]
+        self.check_hotplug_fault()?;
+
         if device_cfg.iommu && !self.is_iommu_segment(device_cfg.pci_segment) {
             return Err(DeviceManagerError::InvalidIommuHotplug);
         }
@@ -3628,6 +5395,9 @@ impl DeviceManager {
     ) -> DeviceManagerResult<PciDeviceInfo> {
         self.validate_identifier(&device_cfg.id)?;
 
+        #[cfg(feature = "fault_injection")]
+        self.check_hotplug_fault()?;
+
         let (bdf, device_name) = self.add_vfio_user_device(device_cfg)?;
 
         // Update the PCIU bitmap
@@ -3666,6 +5436,18 @@ impl DeviceManager {
             .ok_or(DeviceManagerError::MissingDeviceNodePciBdf)?;
         let pci_segment_id = pci_device_bdf.segment();
 
+        // Hot-unplug happens for the whole slot, which would leave the other
+        // functions behind.
+        if pci_device_bdf.function() != 0
+            || self.pci_segments[pci_segment_id as usize]
+                .pci_bus
+                .lock()
+                .unwrap()
+                .is_multifunction(pci_device_bdf.device() as u32)
+        {
+            return Err(DeviceManagerError::MultifunctionRemovalNotAllowed);
+        }
+
         let pci_device_handle = pci_device_node
             .pci_device_handle
             .as_ref()
@@ -3734,6 +5516,9 @@ impl DeviceManager {
                 let child_id = &pci_device_node.children[0];
                 id = child_id.clone();
             }
+
+            #[cfg(feature = "fault_injection")]
+            self.faults.remove(&id);
         }
         for child in pci_device_node.children.iter() {
             device_tree.remove(child);
@@ -3837,7 +5622,6 @@ impl DeviceManager {
             .remove_by_device(&pci_device)
             .map_err(DeviceManagerError::RemoveDeviceFromPciBus)?;
 
-        #[cfg(target_arch = "x86_64")]
         // Remove the device from the IO bus
         self.io_bus()
             .remove_by_device(&bus_device)
@@ -3939,6 +5723,9 @@ impl DeviceManager {
     pub fn add_disk(&mut self, disk_cfg: &mut DiskConfig) -> DeviceManagerResult<PciDeviceInfo> {
         self.validate_identifier(&disk_cfg.id)?;
 
+        #[cfg(feature = "fault_injection")]
+        self.check_hotplug_fault()?;
+
         if disk_cfg.iommu && !self.is_iommu_segment(disk_cfg.pci_segment) {
             return Err(DeviceManagerError::InvalidIommuHotplug);
         }
@@ -3947,27 +5734,78 @@ impl DeviceManager {
         self.hotplug_virtio_pci_device(device)
     }
 
-    pub fn add_fs(&mut self, fs_cfg: &mut FsConfig) -> DeviceManagerResult<PciDeviceInfo> {
+    // Hot-adds the virtio-fs device, returning the PCI information of the
+    // device unless it uses the virtio-mmio transport.
+    pub fn add_fs(&mut self, fs_cfg: &mut FsConfig) -> DeviceManagerResult<Option<PciDeviceInfo>> {
         self.validate_identifier(&fs_cfg.id)?;
 
+        #[cfg(feature = "fault_injection")]
+        self.check_hotplug_fault()?;
+
+        if fs_cfg.transport == VirtioTransportType::Mmio {
+            #[cfg(target_arch = "aarch64")]
+            {
+                self.check_mmio_hotplug()?;
+                // The windows are reserved at boot, out of the ranges of
+                // the PCI segments.
+                if fs_cfg.dax {
+                    return Err(DeviceManagerError::VirtioMmioFsDaxHotplug);
+                }
+                let device = self.make_virtio_fs_device(fs_cfg)?;
+                self.hotplug_virtio_mmio_device(device)?;
+                return Ok(None);
+            }
+            #[cfg(not(target_arch = "aarch64"))]
+            return Err(DeviceManagerError::VirtioMmioHotplug);
+        }
+
         let device = self.make_virtio_fs_device(fs_cfg)?;
-        self.hotplug_virtio_pci_device(device)
+        self.hotplug_virtio_pci_device(device).map(Some)
     }
 
-    pub fn add_pmem(&mut self, pmem_cfg: &mut PmemConfig) -> DeviceManagerResult<PciDeviceInfo> {
+    // Hot-adds the virtio-pmem device, returning the PCI information of the
+    // device unless it uses the virtio-mmio transport.
+    pub fn add_pmem(
+        &mut self,
+        pmem_cfg: &mut PmemConfig,
+    ) -> DeviceManagerResult<Option<PciDeviceInfo>> {
         self.validate_identifier(&pmem_cfg.id)?;
 
+        #[cfg(feature = "fault_injection")]
+        self.check_hotplug_fault()?;
+
+        if pmem_cfg.transport == VirtioTransportType::Mmio {
+            #[cfg(target_arch = "aarch64")]
+            {
+                self.check_mmio_hotplug()?;
+                let device = self.make_virtio_pmem_device(pmem_cfg)?;
+                self.hotplug_virtio_mmio_device(device)?;
+                return Ok(None);
+            }
+            #[cfg(not(target_arch = "aarch64"))]
+            return Err(DeviceManagerError::VirtioMmioHotplug);
+        }
+
         if pmem_cfg.iommu && !self.is_iommu_segment(pmem_cfg.pci_segment) {
             return Err(DeviceManagerError::InvalidIommuHotplug);
         }
 
         let device = self.make_virtio_pmem_device(pmem_cfg)?;
-        self.hotplug_virtio_pci_device(device)
+        self.hotplug_virtio_pci_device(device).map(Some)
     }
 
     pub fn add_net(&mut self, net_cfg: &mut NetConfig) -> DeviceManagerResult<PciDeviceInfo> {
         self.validate_identifier(&net_cfg.id)?;
 
+        #[cfg(feature = "fault_injection")]
+        self.check_hotplug_fault()?;
+
+        // The guest only finds the virtio-mmio devices in the device tree
+        // it booted with.
+        if net_cfg.transport == VirtioTransportType::Mmio {
+            return Err(DeviceManagerError::VirtioMmioHotplug);
+        }
+
         if net_cfg.iommu && !self.is_iommu_segment(net_cfg.pci_segment) {
             return Err(DeviceManagerError::InvalidIommuHotplug);
         }
@@ -3979,6 +5817,9 @@ impl DeviceManager {
     pub fn add_vdpa(&mut self, vdpa_cfg: &mut VdpaConfig) -> DeviceManagerResult<PciDeviceInfo> {
         self.validate_identifier(&vdpa_cfg.id)?;
 
+        #[cfg(feature = "fault_injection")]
+        self.check_hotplug_fault()?;
+
         if vdpa_cfg.iommu && !self.is_iommu_segment(vdpa_cfg.pci_segment) {
             return Err(DeviceManagerError::InvalidIommuHotplug);
         }
@@ -3987,15 +5828,97 @@ impl DeviceManager {
         self.hotplug_virtio_pci_device(device)
     }
 
-    pub fn add_vsock(&mut self, vsock_cfg: &mut VsockConfig) -> DeviceManagerResult<PciDeviceInfo> {
+    // Hot-adds the vsock device, returning the PCI information of the device
+    // unless it uses the virtio-mmio transport.
+    pub fn add_vsock(
+        &mut self,
+        vsock_cfg: &mut VsockConfig,
+    ) -> DeviceManagerResult<Option<PciDeviceInfo>> {
         self.validate_identifier(&vsock_cfg.id)?;
 
+        #[cfg(feature = "fault_injection")]
+        self.check_hotplug_fault()?;
+
+        if vsock_cfg.transport == VirtioTransportType::Mmio {
+            #[cfg(target_arch = "aarch64")]
+            {
+                self.hotplug_virtio_mmio_vsock_device(vsock_cfg)?;
+                return Ok(None);
+            }
+            #[cfg(not(target_arch = "aarch64"))]
+            return Err(DeviceManagerError::VirtioMmioHotplug);
+        }
+
         if vsock_cfg.iommu && !self.is_iommu_segment(vsock_cfg.pci_segment) {
             return Err(DeviceManagerError::InvalidIommuHotplug);
         }
 
         let device = self.make_virtio_vsock_device(vsock_cfg)?;
-        self.hotplug_virtio_pci_device(device)
+        self.hotplug_virtio_pci_device(device).map(Some)
+    }
+
+    // The vsock device is added on the resources reserved at boot, which the
+    // guest finds through the ACPI device describing them once notified.
+    #[cfg(target_arch = "aarch64")]
+    fn hotplug_virtio_mmio_vsock_device(
+        &mut self,
+        vsock_cfg: &mut VsockConfig,
+    ) -> DeviceManagerResult<()> {
+        let slot = self
+            .vsock_mmio_slot
+            .clone()
+            .ok_or(DeviceManagerError::VirtioMmioHotplug)?;
+
+        let handle = self.make_virtio_vsock_device(vsock_cfg)?;
+        self.virtio_devices.push(handle.clone());
+        self.add_virtio_mmio_device(handle.virtio_device, handle.id, Some(slot))?;
+
+        Ok(())
+    }
+
+    // Checks a virtio-mmio device can be hot-added before creating it.
+    #[cfg(target_arch = "aarch64")]
+    fn check_mmio_hotplug(&self) -> DeviceManagerResult<()> {
+        if self.acpi_guest {
+            return Err(DeviceManagerError::MmioHotplugAcpiGuest);
+        }
+        // The slots are only reserved along with the device tree hotplug
+        // device.
+        if self.dt_hotplug_device.is_none() || self.mmio_hotplug_slots.is_empty() {
+            return Err(DeviceManagerError::NoMmioHotplugSlot);
+        }
+
+        Ok(())
+    }
+
+    // The device is added on one of the slots reserved at boot, and
+    // described to the guest through a device tree overlay. The slot is
+    // only consumed once the device is on the bus.
+    #[cfg(target_arch = "aarch64")]
+    fn hotplug_virtio_mmio_device(&mut self, handle: MetaVirtioDevice) -> DeviceManagerResult<()> {
+        self.check_mmio_hotplug()?;
+        let dt_hotplug_device = self.dt_hotplug_device.clone().unwrap();
+        let slot = self.mmio_hotplug_slots[0].clone();
+
+        let info = self.add_virtio_mmio_device(
+            handle.virtio_device.clone(),
+            handle.id.clone(),
+            Some(slot),
+        )?;
+        self.mmio_hotplug_slots.remove(0);
+        self.virtio_devices.push(handle.clone());
+
+        let shm_info = self
+            .id_to_dev_info
+            .get(&(DeviceType::SharedMemory, handle.id));
+        let overlay = arch::aarch64::fdt::create_virtio_overlay(&info, shm_info)
+            .map_err(DeviceManagerError::CreateDtHotplugOverlay)?;
+
+        dt_hotplug_device
+            .lock()
+            .unwrap()
+            .notify(overlay)
+            .map_err(DeviceManagerError::DtHotplugNotification)
     }
 
     pub fn counters(&self) -> HashMap<String, HashMap<&'static str, Wrapping<u64>>> {
@@ -4011,6 +5934,238 @@ impl DeviceManager {
         counters
     }
 
+    /// Change the rate limiting of a virtio device, right away for the
+    /// device threads when it is activated.
+    pub fn set_rate_limiter(
+        &mut self,
+        id: &str,
+        rate_limiter_config: Option<RateLimiterConfig>,
+    ) -> DeviceManagerResult<()> {
+        let handle = self
+            .virtio_devices
+            .iter()
+            .find(|handle| handle.id == id)
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.to_owned()))?;
+
+        handle
+            .virtio_device
+            .lock()
+            .unwrap()
+            .set_rate_limiter_config(rate_limiter_config)
+            .map_err(DeviceManagerError::SetRateLimiter)
+    }
+
+    /// Change the interrupt coalescing of a virtio device, applying to the
+    /// next used buffers of its queues.
+    pub fn set_interrupt_coalescing(
+        &mut self,
+        id: &str,
+        interrupt_coalescing: Option<InterruptCoalescingConfig>,
+    ) -> DeviceManagerResult<()> {
+        let handle = self
+            .virtio_devices
+            .iter()
+            .find(|handle| handle.id == id)
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.to_owned()))?;
+
+        handle
+            .virtio_device
+            .lock()
+            .unwrap()
+            .set_interrupt_coalescing_config(interrupt_coalescing)
+            .map_err(DeviceManagerError::SetInterruptCoalescing)
+    }
+
+    /// Returns the depth of the queues of every activated virtio device,
+    /// indexed by the identifier of the device.
+    pub fn queue_depths(&self) -> HashMap<String, Vec<u16>> {
+        let mut queue_depths = HashMap::new();
+        let device_tree = self.device_tree.lock().unwrap();
+
+        for node in device_tree.pci_devices() {
+            if let Some(PciDeviceHandle::Virtio(virtio_pci_device)) = &node.pci_device_handle {
+                let virtio_pci_device = virtio_pci_device.lock().unwrap();
+                if !virtio_pci_device.is_activated() {
+                    continue;
+                }
+
+                // The virtio device is the child of its transport node.
+                if let Some(id) = node.children.first() {
+                    queue_depths.insert(id.clone(), virtio_pci_device.queue_depths());
+                }
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        for virtio_mmio_device in self.virtio_mmio_devices.iter() {
+            let virtio_mmio_device = virtio_mmio_device.lock().unwrap();
+            if !virtio_mmio_device.is_activated() {
+                continue;
+            }
+
+            if let Some(id) = device_tree
+                .get(&virtio_mmio_device.id())
+                .and_then(|node| node.children.first())
+            {
+                queue_depths.insert(id.clone(), virtio_mmio_device.queue_depths());
+            }
+        }
+
+        queue_depths
+    }
+
+    // Nice value of the threads of the virtio devices, set by the priority
+    // class of the VM, or inherited from the VMM.
+    fn thread_nice(&self) -> Option<i32> {
+        self.config
+            .lock()
+            .unwrap()
+            .priority_profile()
+            .and_then(|profile| profile.io_nice)
+    }
+
+    // Feature bits of the virtio device `id` hidden from the driver, or
+    // required from the device, through the configuration of the VM.
+    fn features_override(&self, id: &str, features: u64) -> DeviceManagerResult<FeaturesOverride> {
+        let features_override = self
+            .config
+            .lock()
+            .unwrap()
+            .virtio_features
+            .iter()
+            .flatten()
+            .find(|virtio_features| virtio_features.id == id)
+            .map(|virtio_features| FeaturesOverride {
+                disable: virtio_features.disable,
+                enable: virtio_features.enable,
+            })
+            .unwrap_or_default();
+
+        let unsupported = features_override.unsupported(features);
+        if unsupported != 0 {
+            return Err(DeviceManagerError::UnsupportedVirtioFeatures(
+                id.to_owned(),
+                unsupported,
+            ));
+        }
+
+        if features_override != FeaturesOverride::default() {
+            info!(
+                "{}: Overriding virtio features, disabled 0x{:x}, enabled 0x{:x}",
+                id, features_override.disable, features_override.enable
+            );
+        }
+
+        Ok(features_override)
+    }
+
+    /// Returns the queues of every virtio device, sorted by the identifier
+    /// of the device.
+    pub fn queues(&self) -> Vec<DeviceQueues> {
+        let mut devices = Vec::new();
+        let device_tree = self.device_tree.lock().unwrap();
+
+        for node in device_tree.pci_devices() {
+            if let Some(PciDeviceHandle::Virtio(virtio_pci_device)) = &node.pci_device_handle {
+                let virtio_pci_device = virtio_pci_device.lock().unwrap();
+                // The virtio device is the child of its transport node.
+                if let Some(id) = node.children.first() {
+                    devices.push(DeviceQueues {
+                        id: id.clone(),
+                        activated: virtio_pci_device.is_activated(),
+                        queues: virtio_pci_device.queues_info(),
+                    });
+                }
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        for virtio_mmio_device in self.virtio_mmio_devices.iter() {
+            let virtio_mmio_device = virtio_mmio_device.lock().unwrap();
+            if let Some(id) = device_tree
+                .get(&virtio_mmio_device.id())
+                .and_then(|node| node.children.first())
+            {
+                devices.push(DeviceQueues {
+                    id: id.clone(),
+                    activated: virtio_mmio_device.is_activated(),
+                    queues: virtio_mmio_device.queues_info(),
+                });
+            }
+        }
+
+        devices.sort_by(|a, b| a.id.cmp(&b.id));
+        devices
+    }
+
+    /// Change the maximum size of the queues of a virtio device, which is
+    /// only possible until its driver starts initializing it.
+    pub fn set_queue_size(&mut self, id: &str, queue_size: u16) -> DeviceManagerResult<()> {
+        let device_tree = self.device_tree.lock().unwrap();
+
+        for node in device_tree.pci_devices() {
+            if let Some(PciDeviceHandle::Virtio(virtio_pci_device)) = &node.pci_device_handle {
+                if node.children.first().map(String::as_str) == Some(id) {
+                    return virtio_pci_device
+                        .lock()
+                        .unwrap()
+                        .set_queue_max_size(queue_size)
+                        .map_err(DeviceManagerError::SetQueueSize);
+                }
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        for virtio_mmio_device in self.virtio_mmio_devices.iter() {
+            let mut virtio_mmio_device = virtio_mmio_device.lock().unwrap();
+            if device_tree
+                .get(&virtio_mmio_device.id())
+                .and_then(|node| node.children.first())
+                .map(String::as_str)
+                == Some(id)
+            {
+                return virtio_mmio_device
+                    .set_queue_max_size(queue_size)
+                    .map_err(DeviceManagerError::SetQueueSize);
+            }
+        }
+
+        Err(DeviceManagerError::UnknownDeviceId(id.to_owned()))
+    }
+
+    pub fn iommu_status(&self) -> Vec<DeviceIommuStatus> {
+        let mut devices = Vec::new();
+        let device_tree = self.device_tree.lock().unwrap();
+
+        for node in device_tree.pci_devices() {
+            let bdf = match node.pci_bdf {
+                Some(bdf) => bdf,
+                None => continue,
+            };
+
+            let iommu = match &self.iommu_attached_devices {
+                // The virtual IOMMU isn't placed behind itself.
+                Some((iommu_bdf, _)) if *iommu_bdf == bdf => continue,
+                Some((_, attached_devices)) => attached_devices.contains(&bdf),
+                None => false,
+            };
+
+            // A virtio device is the child of its transport node.
+            let id = match &node.pci_device_handle {
+                Some(PciDeviceHandle::Virtio(_)) => node.children.first().unwrap_or(&node.id),
+                _ => &node.id,
+            };
+
+            devices.push(DeviceIommuStatus {
+                id: id.clone(),
+                bdf: bdf.to_string(),
+                iommu,
+            });
+        }
+
+        devices
+    }
+
     pub fn resize_balloon(&mut self, size: u64) -> DeviceManagerResult<()> {
         if let Some(balloon) = &self.balloon {
             return balloon
@@ -4032,14 +6187,57 @@ impl DeviceManager {
         0
     }
 
+    #[cfg(feature = "guest_debug")]
+    pub fn balloon_inflated_ranges(&self) -> Vec<vm_migration::protocol::MemoryRange> {
+        if let Some(balloon) = &self.balloon {
+            return balloon.lock().unwrap().inflated_ranges();
+        }
+
+        Vec::new()
+    }
+
     pub fn device_tree(&self) -> Arc<Mutex<DeviceTree>> {
         self.device_tree.clone()
     }
 
+    pub fn set_fresh_devices(&mut self, fresh_devices: Vec<String>) {
+        self.fresh_devices = fresh_devices;
+    }
+
+    // Identify the nodes which must be skipped when restoring the devices.
+    // Only virtio devices are supported, and their transport is skipped as
+    // well since its state can't be restored without the device state.
+    fn fresh_device_nodes(&self) -> std::result::Result<BTreeSet<String>, MigratableError> {
+        let device_tree = self.device_tree.lock().unwrap();
+        let mut nodes = BTreeSet::new();
+
+        for id in self.fresh_devices.iter() {
+            let node = device_tree
+                .get(id)
+                .ok_or_else(|| MigratableError::Restore(anyhow!("Unknown fresh device {}", id)))?;
+            match &node.parent {
+                Some(parent) if parent.starts_with(VIRTIO_PCI_DEVICE_NAME_PREFIX) => {
+                    nodes.insert(parent.clone());
+                    nodes.insert(id.clone());
+                }
+                _ => {
+                    return Err(MigratableError::Restore(anyhow!(
+                        "Only virtio devices can be left unrestored: {}",
+                        id
+                    )))
+                }
+            }
+        }
+
+        Ok(nodes)
+    }
+
     pub fn restore_devices(
         &mut self,
-        snapshot: Snapshot,
+        mut snapshot: Snapshot,
     ) -> std::result::Result<(), MigratableError> {
+        let fresh_nodes = self.fresh_device_nodes()?;
+
         // Finally, restore all devices associated with the DeviceManager.
         // It's important to restore devices in the right order, that's why
         // the device tree is the right way to ensure we restore a child before
@@ -4051,12 +6249,17 @@ impl DeviceManager {
             .breadth_first_traversal()
             .rev()
         {
+            if fresh_nodes.contains(&node.id) {
+                info!("Not restoring {}, keeping it in its initial state", node.id);
+                continue;
+            }
+
             // Restore the node
             if let Some(migratable) = &node.migratable {
                 info!("Restoring {} from DeviceManager", node.id);
-                if let Some(snapshot) = snapshot.snapshots.get(&node.id) {
+                if let Some(snapshot) = snapshot.take_snapshot(&node.id) {
                     migratable.lock().unwrap().pause()?;
-                    migratable.lock().unwrap().restore(*snapshot.clone())?;
+                    migratable.lock().unwrap().restore(snapshot)?;
                 } else {
                     return Err(MigratableError::Restore(anyhow!(
                         "Missing device {}",
@@ -4118,6 +6321,53 @@ impl DeviceManager {
         self.uefi_flash.as_ref().unwrap().clone()
     }
 
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_fault(&mut self, fault_data: &VmInjectFaultData) -> DeviceManagerResult<()> {
+        let id = match &fault_data.id {
+            Some(id) => id,
+            None => {
+                if !fault_data.disk_io_errors.is_empty()
+                    || fault_data.net_drop_interval != 0
+                    || fault_data.interrupt_delay_us != 0
+                {
+                    return Err(DeviceManagerError::InvalidFaultInjection);
+                }
+
+                self.hotplug_failure = fault_data.hotplug_failure;
+                return Ok(());
+            }
+        };
+
+        if fault_data.hotplug_failure {
+            return Err(DeviceManagerError::InvalidFaultInjection);
+        }
+
+        let faults = self
+            .faults
+            .get(id)
+            .ok_or_else(|| DeviceManagerError::UnknownDeviceId(id.clone()))?;
+        faults.set_disk_io_errors(
+            fault_data
+                .disk_io_errors
+                .iter()
+                .map(|(first, last)| *first..=*last)
+                .collect(),
+        );
+        faults.set_net_drop_interval(fault_data.net_drop_interval);
+        faults.set_interrupt_delay(Duration::from_micros(fault_data.interrupt_delay_us));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "fault_injection")]
+    fn check_hotplug_fault(&self) -> DeviceManagerResult<()> {
+        if self.hotplug_failure {
+            return Err(DeviceManagerError::InjectedHotplugFailure);
+        }
+
+        Ok(())
+    }
+
     fn validate_identifier(&self, id: &Option<String>) -> DeviceManagerResult<()> {
         if let Some(id) = id {
             if id.starts_with("__") {
@@ -4133,6 +6383,19 @@ impl DeviceManager {
     }
 }
 
+// Whether the file descriptor is an eventfd, whose entry in fdinfo reports
+// the counter.
+#[cfg(target_arch = "aarch64")]
+fn is_eventfd(fd: i32) -> bool {
+    std::fs::read_to_string(format!("/proc/self/fdinfo/{}", fd))
+        .map(|fdinfo| {
+            fdinfo
+                .lines()
+                .any(|line| line.starts_with("eventfd-count:"))
+        })
+        .unwrap_or(false)
+}
+
 fn numa_node_id_from_memory_zone_id(numa_nodes: &NumaNodes, memory_zone_id: &str) -> Option<u32> {
     for (numa_node_id, numa_node) in numa_nodes.iter() {
         if numa_node.memory_zones.contains(&memory_zone_id.to_owned()) {
@@ -4293,6 +6556,74 @@ impl Aml for DeviceManager {
             .append_aml_bytes(bytes);
         }
 
+        // The guest pvpanic driver looks for this device, on x86_64 only
+        // as the MMIO variant is described through the device tree.
+        #[cfg(target_arch = "x86_64")]
+        if self.pvpanic_device.is_some() {
+            aml::Device::new(
+                "_SB_.PEVT".into(),
+                vec![
+                    &aml::Name::new("_HID".into(), &"QEMU0001"),
+                    &aml::Name::new("_STA".into(), &0xfu8),
+                    &aml::Name::new(
+                        "_CRS".into(),
+                        &aml::ResourceTemplate::new(vec![&aml::Io::new(
+                            PVPANIC_IO_PORT as u16,
+                            PVPANIC_IO_PORT as u16,
+                            1,
+                            1,
+                        )]),
+                    ),
+                ],
+            )
+            .append_aml_bytes(bytes);
+        }
+
+        // The virtio-mmio vsock device, present once the virtio magic value
+        // can be read from its registers, which allows it to be hot-added.
+        #[cfg(target_arch = "aarch64")]
+        if let Some(slot) = &self.vsock_mmio_slot {
+            aml::Device::new(
+                VSOCK_MMIO_ACPI_DEVICE.into(),
+                vec![
+                    &aml::Name::new("_HID".into(), &"LNRO0005"),
+                    &aml::Name::new("_UID".into(), &aml::ZERO),
+                    &aml::Name::new(
+                        "_CRS".into(),
+                        &aml::ResourceTemplate::new(vec![
+                            &aml::Memory32Fixed::new(true, slot.addr as u32, slot.len as u32),
+                            &aml::Interrupt::new(true, true, false, false, slot.irq),
+                        ]),
+                    ),
+                    &aml::OpRegion::new(
+                        "VSST".into(),
+                        aml::OpRegionSpace::SystemMemory,
+                        slot.addr as usize,
+                        4,
+                    ),
+                    &aml::Field::new(
+                        "VSST".into(),
+                        aml::FieldAccessType::DWord,
+                        aml::FieldUpdateRule::Preserve,
+                        vec![aml::FieldEntry::Named(*b"MAGC", 32)],
+                    ),
+                    &aml::Method::new(
+                        "_STA".into(),
+                        0,
+                        false,
+                        vec![
+                            &aml::If::new(
+                                &aml::Equal::new(&aml::Path::new("MAGC"), &MMIO_MAGIC_VALUE),
+                                vec![&aml::Return::new(&0xfu8)],
+                            ),
+                            &aml::Return::new(&aml::ZERO),
+                        ],
+                    ),
+                ],
+            )
+            .append_aml_bytes(bytes);
+        }
+
         aml::Name::new("_S5_".into(), &aml::Package::new(vec![&5u8])).append_aml_bytes(bytes);
 
         aml::Device::new(
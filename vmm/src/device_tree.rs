@@ -37,6 +37,31 @@ impl DeviceNode {
     }
 }
 
+/// Description of a device node reported through the API, the migratable
+/// device it holds being reduced to whether there is one.
+#[derive(Clone, Serialize)]
+pub struct DeviceNodeInfo {
+    pub id: String,
+    pub parent: Option<String>,
+    pub children: Vec<String>,
+    pub pci_bdf: Option<PciBdf>,
+    pub resources: Vec<Resource>,
+    pub migratable: bool,
+}
+
+impl From<&DeviceNode> for DeviceNodeInfo {
+    fn from(node: &DeviceNode) -> Self {
+        DeviceNodeInfo {
+            id: node.id.clone(),
+            parent: node.parent.clone(),
+            children: node.children.clone(),
+            pci_bdf: node.pci_bdf,
+            resources: node.resources.clone(),
+            migratable: node.migratable.is_some(),
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! device_node {
     ($id:ident) => {
@@ -85,6 +110,13 @@ impl DeviceTree {
             .collect()
     }
 
+    /// Nodes of the tree sorted by identifier, so that the output is stable.
+    pub fn info(&self) -> Vec<DeviceNodeInfo> {
+        let mut nodes: Vec<DeviceNodeInfo> = self.0.values().map(DeviceNodeInfo::from).collect();
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        nodes
+    }
+
     pub fn remove_node_by_pci_bdf(&mut self, pci_bdf: PciBdf) -> Option<DeviceNode> {
         let mut id = None;
         for (k, v) in self.0.iter() {
@@ -218,6 +250,12 @@ mod tests {
             }
         }
 
+        // Check info()
+        let info = device_tree.info();
+        let ids: Vec<&str> = info.iter().map(|node| node.id.as_str()).collect();
+        assert_eq!(ids, vec!["disk0", "net0", "rng0"]);
+        assert!(info.iter().all(|node| !node.migratable));
+
         // Check breadth_first_traversal() based on the following hierarchy
         //
         // 0
@@ -0,0 +1,101 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Socket the VM lifecycle events are streamed on. The clients connecting to
+//! it are handed over to the event monitor, which writes them the events of
+//! the `vm` source as they happen.
+
+use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::{Error, Result};
+use seccompiler::{apply_filter, SeccompAction};
+use std::io;
+use std::os::unix::net::UnixListener;
+use std::panic::AssertUnwindSafe;
+use std::thread;
+use std::time::Duration;
+use vmm_sys_util::eventfd::EventFd;
+
+// Time waited before accepting the next client when running out of file
+// descriptors or memory, which wouldn't be available right away.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Returns how long to wait before accepting the next client, after failing
+// to accept one.
+fn accept_backoff(e: &io::Error) -> Option<Duration> {
+    match e.raw_os_error() {
+        Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOBUFS) | Some(libc::ENOMEM) => {
+            Some(ACCEPT_BACKOFF)
+        }
+        _ => None,
+    }
+}
+
+pub fn start_event_stream_thread(
+    listener: UnixListener,
+    seccomp_action: &SeccompAction,
+    exit_evt: EventFd,
+) -> Result<thread::JoinHandle<Result<()>>> {
+    // Retrieve seccomp filter for event stream thread
+    let event_stream_seccomp_filter = get_seccomp_filter(seccomp_action, Thread::EventStream)
+        .map_err(Error::CreateSeccompFilter)?;
+
+    thread::Builder::new()
+        .name("event-stream".to_string())
+        .spawn(move || {
+            // Apply seccomp filter for event stream thread.
+            if !event_stream_seccomp_filter.is_empty() {
+                apply_filter(&event_stream_seccomp_filter)
+                    .map_err(Error::ApplySeccompFilter)
+                    .map_err(|e| {
+                        error!("Error applying seccomp filter: {:?}", e);
+                        exit_evt.write(1).ok();
+                        e
+                    })?;
+            }
+
+            std::panic::catch_unwind(AssertUnwindSafe(move || loop {
+                if let Err(e) = listener
+                    .accept()
+                    .and_then(|(stream, _)| event_monitor::add_subscriber(stream))
+                {
+                    warn!("Error accepting an event stream client: {}", e);
+                    if let Some(backoff) = accept_backoff(&e) {
+                        thread::sleep(backoff);
+                    }
+                }
+            }))
+            .map_err(|_| {
+                error!("event-stream thread panicked");
+                exit_evt.write(1).ok()
+            })
+            .ok();
+
+            Ok(())
+        })
+        .map_err(Error::EventStreamThreadSpawn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_backoff() {
+        for errno in [libc::EMFILE, libc::ENFILE, libc::ENOBUFS, libc::ENOMEM] {
+            assert_eq!(
+                accept_backoff(&io::Error::from_raw_os_error(errno)),
+                Some(ACCEPT_BACKOFF)
+            );
+        }
+        assert_eq!(
+            accept_backoff(&io::Error::from_raw_os_error(libc::ECONNABORTED)),
+            None
+        );
+        assert_eq!(
+            accept_backoff(&io::Error::new(io::ErrorKind::Other, "error")),
+            None
+        );
+    }
+}
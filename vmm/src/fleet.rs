@@ -0,0 +1,631 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Fleet registry, through which the VMM processes of a host report to a
+//! single aggregation point. Each VMM started with `--fleet` sends a
+//! heartbeat to the registry socket at every interval, describing itself and
+//! the status of its VM. One of the VMMs serves the registry, keeping the
+//! members heard of recently, and answering the list requests of the
+//! operators, which find the API socket of a VM there.
+//!
+//! Every request and its response is one JSON line, on a connection of its
+//! own, so that the members don't depend on the registry being up, or on the
+//! order the VMMs are started in.
+//!
+//! The registry only serves the processes running as the same user as its
+//! VMM, and takes the process ID of a member from the credentials of its
+//! connection, rather than from its heartbeat.
+
+use crate::api::{vm_counters, vm_info, ApiRequest};
+use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::vm::VmState;
+use crate::{Error, Result};
+use seccompiler::{apply_filter, SeccompAction};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem::size_of;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use vmm_sys_util::eventfd::EventFd;
+
+/// Default interval between the heartbeats, in seconds.
+pub const DEFAULT_FLEET_INTERVAL: u64 = 10;
+/// Longest interval between the heartbeats, in seconds.
+pub const MAX_FLEET_INTERVAL: u64 = 3600;
+
+// Heartbeats a member can miss before being dropped from the registry.
+const MISSED_HEARTBEATS: u64 = 3;
+
+// Limit on the size of a request, the heartbeats carrying the counters of
+// every device of the VM.
+const MAX_REQUEST_SIZE: u64 = 64 << 10;
+
+// Limit on the number of members kept by the registry.
+const MAX_MEMBERS: usize = 256;
+
+// Limit on the number of connections served at once, each one by a thread
+// of its own.
+const MAX_CONNECTIONS: usize = 16;
+
+// Time given to the peer to send its request, or its response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Time waited before accepting connections again after an error, which
+// would otherwise repeat right away when running out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Membership of the VMM in a fleet.
+pub struct FleetConfig {
+    /// Socket of the registry.
+    pub socket: PathBuf,
+    /// Name of the VMM in the fleet, unique among the members.
+    pub name: String,
+    /// Seconds between the heartbeats.
+    pub interval: u64,
+    /// Registry served by the VMM, bound to `socket`.
+    pub registry: Option<UnixListener>,
+}
+
+/// Status of the VM of a member, as of its last heartbeat.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct FleetStatus {
+    /// State of the VM, none until the VM is created.
+    pub state: Option<VmState>,
+    pub memory_actual_size: u64,
+    pub uptime_ms: u64,
+    pub guest_panicked: bool,
+    /// Counters of the devices and vCPUs, as reported by vm.counters.
+    pub counters: HashMap<String, HashMap<String, u64>>,
+}
+
+/// Description of a member, sent along with each heartbeat.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct FleetMember {
+    pub name: String,
+    pub pid: u32,
+    pub vmm_version: String,
+    /// Path of the API socket, none when the API is served on a file
+    /// descriptor.
+    pub api_socket: Option<PathBuf>,
+    /// Seconds between the heartbeats of the member.
+    pub interval: u64,
+    pub status: FleetStatus,
+}
+
+/// Member as known by the registry.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct FleetEntry {
+    #[serde(flatten)]
+    pub member: FleetMember,
+    /// Time of the last heartbeat, in seconds since the UNIX epoch.
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FleetRequest {
+    Heartbeat(FleetMember),
+    List,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FleetResponse {
+    Registered,
+    Members(Vec<FleetEntry>),
+    Error(String),
+}
+
+/// Members heard of by the registry, dropped once they missed enough
+/// heartbeats.
+#[derive(Default)]
+pub struct FleetRegistry {
+    members: BTreeMap<String, FleetEntry>,
+}
+
+impl FleetRegistry {
+    fn expire(&mut self, now: u64) {
+        self.members.retain(|_, entry| {
+            entry
+                .member
+                .interval
+                .checked_mul(MISSED_HEARTBEATS)
+                .and_then(|timeout| entry.last_seen.checked_add(timeout))
+                .map_or(true, |deadline| deadline >= now)
+        });
+    }
+
+    /// Records the heartbeat of a member. The name of a member still alive
+    /// can't be taken over by another process.
+    pub fn heartbeat(&mut self, member: FleetMember, now: u64) -> FleetResponse {
+        if member.interval == 0 || member.interval > MAX_FLEET_INTERVAL {
+            return FleetResponse::Error(format!(
+                "interval must be between 1 and {} seconds",
+                MAX_FLEET_INTERVAL
+            ));
+        }
+
+        self.expire(now);
+        match self.members.get(&member.name) {
+            Some(entry) if entry.member.pid != member.pid => {
+                return FleetResponse::Error(format!(
+                    "name {} already taken by process {}",
+                    member.name, entry.member.pid
+                ));
+            }
+            Some(_) => {}
+            None if self.members.len() >= MAX_MEMBERS => {
+                return FleetResponse::Error(format!(
+                    "the registry is full, with {} members",
+                    MAX_MEMBERS
+                ));
+            }
+            None => {}
+        }
+
+        self.members.insert(
+            member.name.clone(),
+            FleetEntry {
+                member,
+                last_seen: now,
+            },
+        );
+        FleetResponse::Registered
+    }
+
+    /// Members alive, sorted by name.
+    pub fn list(&mut self, now: u64) -> Vec<FleetEntry> {
+        self.expire(now);
+        self.members.values().cloned().collect()
+    }
+
+    fn handle(&mut self, request: FleetRequest, peer: &libc::ucred) -> FleetResponse {
+        match request {
+            FleetRequest::Heartbeat(mut member) => {
+                // The API socket the operators are sent to must belong to
+                // the member.
+                if let Some(api_socket) = &member.api_socket {
+                    match fs::metadata(api_socket) {
+                        Ok(metadata) if metadata.uid() == peer.uid => {}
+                        _ => {
+                            return FleetResponse::Error(format!(
+                                "API socket {:?} not owned by the member",
+                                api_socket
+                            ))
+                        }
+                    }
+                }
+                member.pid = peer.pid as u32;
+                self.heartbeat(member, now())
+            }
+            FleetRequest::List => FleetResponse::Members(self.list(now())),
+        }
+    }
+}
+
+// Credentials of the process at the other end of the connection.
+fn peer_credentials(stream: &UnixStream) -> io::Result<libc::ucred> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: FFI call with a valid socket, and a buffer of the given size.
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(cred)
+}
+
+fn serve_connection(registry: &Mutex<FleetRegistry>, stream: &UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let peer = peer_credentials(stream)?;
+    // SAFETY: FFI call without arguments.
+    let uid = unsafe { libc::geteuid() };
+    if peer.uid != uid {
+        return write_message(
+            stream,
+            &FleetResponse::Error(format!("peer not running as user {}", uid)),
+        );
+    }
+
+    // The registry is only locked once the request is read, so that a peer
+    // which doesn't send it only holds its own connection.
+    let response = match read_message::<FleetRequest>(stream) {
+        Ok(request) => registry.lock().unwrap().handle(request, &peer),
+        Err(e) => FleetResponse::Error(e.to_string()),
+    };
+    write_message(stream, &response)
+}
+
+// Serves each connection from a thread of its own, up to MAX_CONNECTIONS at
+// once, the connections beyond being closed right away.
+fn serve_registry(listener: UnixListener) {
+    let registry = Arc::new(Mutex::new(FleetRegistry::default()));
+    let connections = Arc::new(AtomicUsize::new(0));
+    loop {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Error accepting a fleet registry connection: {}", e);
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            debug!("Too many fleet registry connections, closing a new one");
+            continue;
+        }
+
+        let registry = registry.clone();
+        let thread_connections = connections.clone();
+        if let Err(e) = thread::Builder::new()
+            .name("fleet-connection".to_owned())
+            .spawn(move || {
+                if let Err(e) = serve_connection(&registry, &stream) {
+                    debug!("Error serving a fleet registry connection: {}", e);
+                }
+                thread_connections.fetch_sub(1, Ordering::SeqCst);
+            })
+        {
+            warn!("Error spawning a fleet registry connection thread: {}", e);
+            connections.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn read_message<T: for<'de> Deserialize<'de>>(stream: &UnixStream) -> io::Result<T> {
+    let mut line = String::new();
+    BufReader::new(io::Read::take(stream, MAX_REQUEST_SIZE)).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(io::Error::from)
+}
+
+fn write_message<T: Serialize>(mut stream: &UnixStream, message: &T) -> io::Result<()> {
+    let mut data = serde_json::to_vec(message).map_err(io::Error::from)?;
+    data.push(b'\n');
+    stream.write_all(&data)
+}
+
+/// Sends a request to the registry listening on `socket`.
+pub fn request(socket: &Path, request: &FleetRequest) -> io::Result<FleetResponse> {
+    let stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    write_message(&stream, request)?;
+    read_message(&stream)
+}
+
+/// Lists the members of the registry listening on `socket`.
+pub fn list_members(socket: &Path) -> io::Result<Vec<FleetEntry>> {
+    match request(socket, &FleetRequest::List)? {
+        FleetResponse::Members(members) => Ok(members),
+        FleetResponse::Error(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+        FleetResponse::Registered => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected response from the fleet registry",
+        )),
+    }
+}
+
+// Status of the VM, through the API as any other client would get it.
+fn vm_status(api_evt: &EventFd, api_sender: &Sender<ApiRequest>) -> io::Result<FleetStatus> {
+    let info = match vm_info(api_evt.try_clone()?, api_sender.clone()) {
+        Ok(info) => info,
+        // No VM created yet.
+        Err(_) => return Ok(FleetStatus::default()),
+    };
+    let counters = vm_counters(api_evt.try_clone()?, api_sender.clone())
+        .ok()
+        .flatten()
+        .and_then(|body| serde_json::from_slice(body.raw()).ok())
+        .unwrap_or_default();
+
+    Ok(FleetStatus {
+        state: Some(info.state),
+        memory_actual_size: info.memory_actual_size,
+        uptime_ms: info.uptime_ms,
+        guest_panicked: info.guest_panicked,
+        counters,
+    })
+}
+
+fn run_member(
+    socket: &Path,
+    mut member: FleetMember,
+    api_evt: &EventFd,
+    api_sender: &Sender<ApiRequest>,
+) {
+    let mut registered = None;
+    loop {
+        let result = vm_status(api_evt, api_sender)
+            .and_then(|status| {
+                member.status = status;
+                request(socket, &FleetRequest::Heartbeat(member.clone()))
+            })
+            .and_then(|response| match response {
+                FleetResponse::Registered => Ok(()),
+                FleetResponse::Error(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+                FleetResponse::Members(_) => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unexpected response from the fleet registry",
+                )),
+            });
+
+        // Only the changes are logged, the registry being down or the name
+        // being taken lasting for many heartbeats.
+        match result {
+            Ok(()) if registered != Some(true) => {
+                info!("Registered with the fleet registry as {}", member.name);
+                registered = Some(true);
+            }
+            Err(e) if registered != Some(false) => {
+                warn!("Error sending heartbeat to the fleet registry: {}", e);
+                registered = Some(false);
+            }
+            _ => {}
+        }
+
+        thread::sleep(Duration::from_secs(member.interval));
+    }
+}
+
+fn spawn_fleet_thread<F>(
+    name: &str,
+    seccomp_action: &SeccompAction,
+    exit_evt: EventFd,
+    f: F,
+) -> Result<thread::JoinHandle<Result<()>>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    // Retrieve seccomp filter for fleet thread
+    let fleet_seccomp_filter =
+        get_seccomp_filter(seccomp_action, Thread::Fleet).map_err(Error::CreateSeccompFilter)?;
+    let thread_name = name.to_owned();
+
+    thread::Builder::new()
+        .name(thread_name.clone())
+        .spawn(move || {
+            // Apply seccomp filter for fleet thread.
+            if !fleet_seccomp_filter.is_empty() {
+                apply_filter(&fleet_seccomp_filter)
+                    .map_err(Error::ApplySeccompFilter)
+                    .map_err(|e| {
+                        error!("Error applying seccomp filter: {:?}", e);
+                        exit_evt.write(1).ok();
+                        e
+                    })?;
+            }
+
+            std::panic::catch_unwind(AssertUnwindSafe(f))
+                .map_err(|_| {
+                    error!("{} thread panicked", thread_name);
+                    exit_evt.write(1).ok()
+                })
+                .ok();
+
+            Ok(())
+        })
+        .map_err(Error::FleetThreadSpawn)
+}
+
+pub fn start_fleet_threads(
+    config: FleetConfig,
+    vmm_version: String,
+    api_socket: Option<PathBuf>,
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    seccomp_action: &SeccompAction,
+    exit_evt: EventFd,
+) -> Result<()> {
+    if let Some(listener) = config.registry {
+        spawn_fleet_thread(
+            "fleet-registry",
+            seccomp_action,
+            exit_evt.try_clone().map_err(Error::EventFdClone)?,
+            move || serve_registry(listener),
+        )?;
+    }
+
+    // The members may run in other directories than the one of the
+    // operator.
+    let api_socket = api_socket.map(|path| match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path,
+    });
+    let member = FleetMember {
+        name: config.name,
+        pid: std::process::id(),
+        vmm_version,
+        api_socket,
+        interval: config.interval,
+        status: FleetStatus::default(),
+    };
+    let socket = config.socket;
+    spawn_fleet_thread("fleet", seccomp_action, exit_evt, move || {
+        run_member(&socket, member, &api_evt, &api_sender)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, pid: u32) -> FleetMember {
+        FleetMember {
+            name: name.to_owned(),
+            pid,
+            vmm_version: "v1".to_owned(),
+            api_socket: Some(PathBuf::from(format!("/run/{}.sock", name))),
+            interval: 10,
+            status: FleetStatus::default(),
+        }
+    }
+
+    #[test]
+    fn test_fleet_registry() {
+        let mut registry = FleetRegistry::default();
+        assert_eq!(
+            registry.heartbeat(member("vm1", 1), 100),
+            FleetResponse::Registered
+        );
+        assert_eq!(
+            registry.heartbeat(member("vm0", 2), 110),
+            FleetResponse::Registered
+        );
+
+        // The name of a member alive can't be taken, until it expires.
+        assert!(matches!(
+            registry.heartbeat(member("vm1", 3), 120),
+            FleetResponse::Error(_)
+        ));
+        let members = registry.list(120);
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].member.name, "vm0");
+        assert_eq!(members[1].member.pid, 1);
+
+        let members = registry.list(131);
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].member.name, "vm0");
+        assert_eq!(
+            registry.heartbeat(member("vm1", 3), 131),
+            FleetResponse::Registered
+        );
+        assert_eq!(registry.list(131)[1].last_seen, 131);
+
+        // The interval is bounded, so that the expiration can't overflow.
+        let mut slow = member("vm2", 4);
+        slow.interval = u64::MAX;
+        assert!(matches!(
+            registry.heartbeat(slow, 131),
+            FleetResponse::Error(_)
+        ));
+        let mut slow = member("vm2", 4);
+        slow.interval = MAX_FLEET_INTERVAL;
+        assert_eq!(
+            registry.heartbeat(slow, u64::MAX),
+            FleetResponse::Registered
+        );
+        assert_eq!(registry.list(u64::MAX).len(), 1);
+    }
+
+    #[test]
+    fn test_fleet_registry_full() {
+        let mut registry = FleetRegistry::default();
+        for i in 0..MAX_MEMBERS {
+            assert_eq!(
+                registry.heartbeat(member(&format!("vm{}", i), i as u32), 100),
+                FleetResponse::Registered
+            );
+        }
+        assert!(matches!(
+            registry.heartbeat(member("extra", 0), 100),
+            FleetResponse::Error(_)
+        ));
+        // The members already known keep sending their heartbeats.
+        assert_eq!(
+            registry.heartbeat(member("vm0", 0), 101),
+            FleetResponse::Registered
+        );
+    }
+
+    #[test]
+    fn test_fleet_registry_peer() {
+        let directory = vmm_sys_util::tempdir::TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let api_socket = directory.as_path().join("api.sock");
+        let _listener = UnixListener::bind(&api_socket).unwrap();
+        // SAFETY: FFI calls without arguments.
+        let peer = unsafe {
+            libc::ucred {
+                pid: 42,
+                uid: libc::geteuid(),
+                gid: libc::getegid(),
+            }
+        };
+
+        // The process ID comes from the credentials of the peer.
+        let registry = Mutex::new(FleetRegistry::default());
+        let mut heartbeat = member("vm0", 1);
+        heartbeat.api_socket = Some(api_socket);
+        assert_eq!(
+            registry
+                .lock()
+                .unwrap()
+                .handle(FleetRequest::Heartbeat(heartbeat.clone()), &peer),
+            FleetResponse::Registered
+        );
+        assert_eq!(registry.lock().unwrap().list(now())[0].member.pid, 42);
+
+        // The API socket must belong to the user of the peer.
+        let other = libc::ucred {
+            uid: peer.uid + 1,
+            ..peer
+        };
+        assert!(matches!(
+            registry
+                .lock()
+                .unwrap()
+                .handle(FleetRequest::Heartbeat(heartbeat), &other),
+            FleetResponse::Error(_)
+        ));
+
+        // A peer running as the same user is served through the socket.
+        let (client, server) = UnixStream::pair().unwrap();
+        write_message(&client, &FleetRequest::List).unwrap();
+        serve_connection(&registry, &server).unwrap();
+        assert!(matches!(
+            read_message::<FleetResponse>(&client).unwrap(),
+            FleetResponse::Members(members) if members.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_fleet_request() {
+        assert_eq!(
+            serde_json::to_string(&FleetRequest::List).unwrap(),
+            "\"list\""
+        );
+        let heartbeat = FleetRequest::Heartbeat(member("vm0", 1));
+        assert_eq!(
+            serde_json::from_str::<FleetRequest>(&serde_json::to_string(&heartbeat).unwrap())
+                .unwrap(),
+            heartbeat
+        );
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! The hooks of the VMM, such as the one of the watchdog, are run by a helper
+//! process created before any seccomp filter is applied. This way the VMM
+//! threads are never allowed to execute programs, and the hooks don't
+//! inherit their seccomp filters.
+
+use crate::clone3::{clone3, clone_args, CLONE_CLEAR_SIGHAND};
+use crate::sigwinch_listener::unblock_all_signals;
+use libc::{pipe2, O_CLOEXEC};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem::size_of;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
+use std::thread;
+
+pub struct HookRunner {
+    tx: File,
+}
+
+impl HookRunner {
+    /// Start the helper process, which exits once the VMM closes its end of
+    /// the pipe.
+    pub fn new() -> io::Result<Self> {
+        let mut pipe = [-1; 2];
+        // SAFETY: FFI call with a valid array of two file descriptors.
+        if unsafe { pipe2(pipe.as_mut_ptr(), O_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the file descriptors were just created, and are owned by
+        // nothing else.
+        let rx = unsafe { File::from_raw_fd(pipe[0]) };
+        // SAFETY: same as above.
+        let tx = unsafe { File::from_raw_fd(pipe[1]) };
+
+        let mut args = clone_args::default();
+        args.flags |= CLONE_CLEAR_SIGHAND;
+
+        // SAFETY: the arguments are valid, and the child only runs
+        // hook_runner_main() before exiting.
+        match unsafe { clone3(&mut args, size_of::<clone_args>()) } {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {
+                drop(tx);
+                hook_runner_main(rx);
+            }
+            _ => (),
+        }
+
+        drop(rx);
+
+        Ok(HookRunner { tx })
+    }
+
+    /// Have the helper process run the hook, without waiting for it.
+    pub fn run(&self, hook: &Path) -> io::Result<()> {
+        let mut request = hook.as_os_str().as_bytes().to_vec();
+        if request.contains(&b'\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the path of the hook contains a newline",
+            ));
+        }
+        request.push(b'\n');
+
+        (&self.tx).write_all(&request)
+    }
+}
+
+fn hook_runner_main(rx: File) -> ! {
+    // The signals handled by the VMM are blocked, and would be for the hooks
+    // as well.
+    if let Err(e) = unblock_all_signals() {
+        error!("Error unblocking the signals of the hook runner: {}", e);
+        exit(1);
+    }
+
+    for request in BufReader::new(rx).split(b'\n') {
+        match request {
+            Ok(hook) => run_hook(PathBuf::from(OsString::from_vec(hook))),
+            Err(e) => {
+                error!("Error reading the hook to run: {}", e);
+                exit(1);
+            }
+        }
+    }
+
+    exit(0);
+}
+
+fn run_hook(hook: PathBuf) {
+    let mut child = match Command::new(&hook).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Error running the hook {:?}: {}", hook, e);
+            return;
+        }
+    };
+
+    if let Err(e) = thread::Builder::new()
+        .name("hook".to_owned())
+        .spawn(move || match child.wait() {
+            Ok(status) if !status.success() => warn!("Hook {:?} failed: {}", hook, status),
+            Ok(_) => {}
+            Err(e) => error!("Error waiting for the hook {:?}: {}", hook, e),
+        })
+    {
+        error!("Error spawning the hook thread: {}", e);
+    }
+}
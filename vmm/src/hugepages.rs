@@ -0,0 +1,366 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Management of the host hugepage pool backing the guest memory. The pool
+//! is grown by the hugepages the VM needs when its memory is created, and
+//! shrunk back once the VM is shut down, so that the hugepages don't have to
+//! be reserved at boot time for the peak usage of the host.
+//!
+//! The pool is grown by the number of hugepages needed regardless of the
+//! free hugepages it already has, which are left to the static reservations
+//! and to the other VMMs.
+
+use crate::config::MemoryZoneConfig;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const SYSFS: &str = "/sys";
+const MEMINFO: &str = "/proc/meminfo";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Error reading the default hugepage size: {0}")]
+    DefaultHugepageSize(#[source] io::Error),
+
+    #[error("Error accessing the hugepage pool {0}: {1}")]
+    Pool(PathBuf, #[source] io::Error),
+
+    #[error("Growing the hugepage pool to {0} bytes would exceed its limit of {1} bytes")]
+    LimitExceeded(u64, u64),
+
+    #[error("Growing the hugepage pool by {0} hugepages of {1} bytes overflows")]
+    Overflow(u64, u64),
+
+    #[error("Only {allocated} of the {requested} hugepages could be allocated")]
+    Insufficient { requested: u64, allocated: u64 },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// Size of the default hugepages, from the "Hugepagesize" of /proc/meminfo.
+fn parse_default_hugepage_size(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|l| l.strip_prefix("Hugepagesize:"))
+        .and_then(|s| s.trim().strip_suffix("kB"))
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .map(|kb| kb << 10)
+}
+
+fn default_hugepage_size() -> Result<u64> {
+    let meminfo = fs::read_to_string(MEMINFO).map_err(Error::DefaultHugepageSize)?;
+    parse_default_hugepage_size(&meminfo).ok_or_else(|| {
+        Error::DefaultHugepageSize(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no Hugepagesize in /proc/meminfo",
+        ))
+    })
+}
+
+// Free hugepages of the default size, in bytes, from the "HugePages_Free"
+// and "Hugepagesize" of /proc/meminfo.
+fn parse_free_hugepages(meminfo: &str) -> Option<u64> {
+    let free = meminfo
+        .lines()
+        .find_map(|l| l.strip_prefix("HugePages_Free:"))
+        .and_then(|s| s.trim().parse::<u64>().ok())?;
+    parse_default_hugepage_size(meminfo).map(|size| free * size)
+}
+
+/// Bytes of free hugepages of the default size on the host.
+pub fn free_default_hugepages() -> Result<u64> {
+    let meminfo = fs::read_to_string(MEMINFO).map_err(Error::DefaultHugepageSize)?;
+    parse_free_hugepages(&meminfo).ok_or_else(|| {
+        Error::DefaultHugepageSize(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no HugePages_Free in /proc/meminfo",
+        ))
+    })
+}
+
+fn read_pages(path: &Path) -> Result<u64> {
+    fs::read_to_string(path)
+        .and_then(|s| {
+            s.trim()
+                .parse::<u64>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+        .map_err(|e| Error::Pool(path.to_owned(), e))
+}
+
+fn write_pages(path: &Path, pages: u64) -> Result<()> {
+    fs::write(path, pages.to_string()).map_err(|e| Error::Pool(path.to_owned(), e))
+}
+
+// Exclusive lock on the pool of a hugepage size, serializing its updates
+// with the other VMMs, released when dropped.
+struct PoolLock(File);
+
+impl PoolLock {
+    fn new(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| Error::Pool(path.to_owned(), e))?;
+        // SAFETY: the file descriptor is valid for the lifetime of the file.
+        let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) };
+        if ret < 0 {
+            return Err(Error::Pool(path.to_owned(), io::Error::last_os_error()));
+        }
+        Ok(PoolLock(file))
+    }
+}
+
+// Hugepages added to the pool of a hugepage size, on a host NUMA node or
+// on any of them.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+struct PoolId {
+    hugepage_size: u64,
+    host_numa_node: Option<u32>,
+}
+
+// Hugepages added for a memory zone, and the guest memory they back.
+struct ZoneReservation {
+    pool: PoolId,
+    size: u64,
+}
+
+/// Hugepages added to the host pools for the guest memory, given back when
+/// released or dropped.
+pub struct HugepagePool {
+    sysfs: PathBuf,
+    limit: Option<u64>,
+    // Hugepages added to each pool.
+    reserved: HashMap<PoolId, u64>,
+    zones: HashMap<String, ZoneReservation>,
+}
+
+impl HugepagePool {
+    /// Pool which can't grow beyond `limit` bytes, for each hugepage size.
+    pub fn new(limit: Option<u64>) -> Self {
+        Self::with_sysfs(Path::new(SYSFS), limit)
+    }
+
+    fn with_sysfs(sysfs: &Path, limit: Option<u64>) -> Self {
+        HugepagePool {
+            sysfs: sysfs.to_owned(),
+            limit,
+            reserved: HashMap::new(),
+            zones: HashMap::new(),
+        }
+    }
+
+    fn hugepages_dir(hugepage_size: u64) -> String {
+        format!("hugepages/hugepages-{}kB/nr_hugepages", hugepage_size >> 10)
+    }
+
+    fn global_path(&self, hugepage_size: u64) -> PathBuf {
+        self.sysfs
+            .join("kernel/mm")
+            .join(Self::hugepages_dir(hugepage_size))
+    }
+
+    fn path(&self, id: &PoolId) -> PathBuf {
+        match id.host_numa_node {
+            Some(node) => self
+                .sysfs
+                .join(format!("devices/system/node/node{}", node))
+                .join(Self::hugepages_dir(id.hugepage_size)),
+            None => self.global_path(id.hugepage_size),
+        }
+    }
+
+    fn grow(&mut self, id: PoolId, pages: u64) -> Result<()> {
+        let global_path = self.global_path(id.hugepage_size);
+        let path = self.path(&id);
+
+        let _lock = PoolLock::new(&global_path)?;
+
+        let overflow = || Error::Overflow(pages, id.hugepage_size);
+
+        if let Some(limit) = self.limit {
+            let total = read_pages(&global_path)?
+                .checked_add(pages)
+                .and_then(|total| total.checked_mul(id.hugepage_size))
+                .ok_or_else(overflow)?;
+            if total > limit {
+                return Err(Error::LimitExceeded(total, limit));
+            }
+        }
+
+        // The kernel allocates as many hugepages as it can, which can be
+        // fewer than requested when the host memory is fragmented.
+        let current = read_pages(&path)?;
+        write_pages(&path, current.checked_add(pages).ok_or_else(overflow)?)?;
+        let allocated = read_pages(&path)?.saturating_sub(current);
+        if allocated < pages {
+            write_pages(&path, current)?;
+            return Err(Error::Insufficient {
+                requested: pages,
+                allocated,
+            });
+        }
+
+        info!(
+            "Added {} hugepages of {} bytes to {}",
+            pages,
+            id.hugepage_size,
+            path.display()
+        );
+        *self.reserved.entry(id).or_default() += pages;
+
+        Ok(())
+    }
+
+    /// Grow the pool by the hugepages backing the memory of the zone plugged
+    /// in when the VM is created.
+    pub fn add_zone(&mut self, zone: &MemoryZoneConfig) -> Result<()> {
+        let hugepage_size = match zone.hugepage_size {
+            Some(hugepage_size) => hugepage_size,
+            None => default_hugepage_size()?,
+        };
+        self.zones.insert(
+            zone.id.clone(),
+            ZoneReservation {
+                pool: PoolId {
+                    hugepage_size,
+                    host_numa_node: zone.host_numa_node,
+                },
+                size: 0,
+            },
+        );
+
+        self.resize_zone(&zone.id, zone.size + zone.hotplugged_size.unwrap_or(0))
+    }
+
+    /// Grow the pool so that it holds the hugepages backing `size` bytes of
+    /// the memory zone. The hugepages are kept until released when the zone
+    /// shrinks, and the zones not added to the pool are ignored.
+    pub fn resize_zone(&mut self, id: &str, size: u64) -> Result<()> {
+        let (pool, reserved_size) = match self.zones.get(id) {
+            Some(zone) if size > zone.size => (zone.pool, zone.size),
+            _ => return Ok(()),
+        };
+
+        let pages = (size - reserved_size + pool.hugepage_size - 1) / pool.hugepage_size;
+        self.grow(pool, pages)?;
+
+        if let Some(zone) = self.zones.get_mut(id) {
+            zone.size += pages * pool.hugepage_size;
+        }
+
+        Ok(())
+    }
+
+    /// Shrink the pool back by the hugepages it was grown by. The hugepages
+    /// still mapped are freed by the kernel once they are unmapped.
+    pub fn release(&mut self) {
+        self.zones.clear();
+        for (id, pages) in self.reserved.drain().collect::<Vec<_>>() {
+            let path = self.path(&id);
+            let result = PoolLock::new(&self.global_path(id.hugepage_size)).and_then(|_lock| {
+                let current = read_pages(&path)?;
+                write_pages(&path, current.saturating_sub(pages))
+            });
+            match result {
+                Ok(()) => info!(
+                    "Removed {} hugepages of {} bytes from {}",
+                    pages,
+                    id.hugepage_size,
+                    path.display()
+                ),
+                Err(e) => error!("Failed shrinking the hugepage pool: {}", e),
+            }
+        }
+    }
+}
+
+impl Drop for HugepagePool {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_parse_default_hugepage_size() {
+        let meminfo = "MemTotal:       16315436 kB\n\
+                       HugePages_Total:       8\n\
+                       HugePages_Free:        3\n\
+                       Hugepagesize:       2048 kB\n";
+        assert_eq!(parse_default_hugepage_size(meminfo), Some(2 << 20));
+        assert_eq!(parse_default_hugepage_size("MemTotal: 1 kB\n"), None);
+        assert_eq!(parse_free_hugepages(meminfo), Some(3 * (2 << 20)));
+        assert_eq!(parse_free_hugepages("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn test_hugepage_pool() {
+        let sysfs = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let pool_path = sysfs
+            .as_path()
+            .join("kernel/mm/hugepages/hugepages-2048kB/nr_hugepages");
+        let node_path = sysfs
+            .as_path()
+            .join("devices/system/node/node1/hugepages/hugepages-2048kB/nr_hugepages");
+        for path in [&pool_path, &node_path] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "4\n").unwrap();
+        }
+
+        let zone = |id: &str, size, host_numa_node| MemoryZoneConfig {
+            id: id.to_owned(),
+            size,
+            file: None,
+            shared: false,
+            hugepages: true,
+            hugepage_size: Some(2 << 20),
+            host_numa_node,
+            hotplug_size: None,
+            hotplugged_size: None,
+            prefault: false,
+            template: false,
+            sealed: false,
+        };
+
+        let mut pool = HugepagePool::with_sysfs(sysfs.as_path(), Some(32 << 20));
+        pool.add_zone(&zone("mem0", 7 << 20, None)).unwrap();
+        assert_eq!(read_pages(&pool_path).unwrap(), 8);
+        pool.add_zone(&zone("mem1", 4 << 20, Some(1))).unwrap();
+        assert_eq!(read_pages(&node_path).unwrap(), 6);
+
+        // Only the growth of a memory zone is added to the pool.
+        pool.resize_zone("mem0", 10 << 20).unwrap();
+        assert_eq!(read_pages(&pool_path).unwrap(), 9);
+        pool.resize_zone("mem0", 2 << 20).unwrap();
+        assert_eq!(read_pages(&pool_path).unwrap(), 9);
+        pool.resize_zone("mem2", 2 << 20).unwrap();
+        assert_eq!(read_pages(&pool_path).unwrap(), 9);
+
+        // The pool can't grow beyond its limit.
+        assert!(matches!(
+            pool.resize_zone("mem0", 30 << 20),
+            Err(Error::LimitExceeded(_, _))
+        ));
+        assert_eq!(read_pages(&pool_path).unwrap(), 9);
+
+        // Nor can its size overflow.
+        write_pages(&pool_path, u64::MAX / 2).unwrap();
+        assert!(matches!(
+            pool.resize_zone("mem0", 12 << 20),
+            Err(Error::Overflow(1, _))
+        ));
+        write_pages(&pool_path, 9).unwrap();
+
+        drop(pool);
+        assert_eq!(read_pages(&pool_path).unwrap(), 4);
+        assert_eq!(read_pages(&node_path).unwrap(), 4);
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Identification of the VMM exposed to the guest, through CPUID leaves on
+//! x86_64 and a device tree node on AArch64, so that the guest software can
+//! find the version of the VMM and the paravirtual features enabled for the
+//! VM without probing the devices.
+
+use crate::config::VmConfig;
+use crate::swiotlb;
+use arch::{
+    HypervisorInfo, HYPERVISOR_INFO_FEATURE_BALLOON, HYPERVISOR_INFO_FEATURE_CONFIDENTIAL,
+    HYPERVISOR_INFO_FEATURE_CPU_HOTPLUG, HYPERVISOR_INFO_FEATURE_HYPERV,
+    HYPERVISOR_INFO_FEATURE_IOMMU, HYPERVISOR_INFO_FEATURE_MEMORY_HOTPLUG,
+    HYPERVISOR_INFO_FEATURE_PVPANIC, HYPERVISOR_INFO_FEATURE_WATCHDOG,
+};
+
+// Major, minor and patch numbers of the VMM version, which is either a
+// release tag ("v24.0") or the output of git describe ("v24.0-12-gabcdef").
+// The missing or invalid numbers are reported as 0.
+fn parse_version(version: &str) -> [u32; 3] {
+    let mut numbers = [0; 3];
+    let version = version.trim_start_matches('v');
+    let end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    for (number, part) in numbers.iter_mut().zip(version[..end].split('.')) {
+        *number = part.parse().unwrap_or(0);
+    }
+    numbers
+}
+
+fn features(config: &VmConfig) -> u32 {
+    let mut features = 0;
+
+    if config.cpus.kvm_hyperv {
+        features |= HYPERVISOR_INFO_FEATURE_HYPERV;
+    }
+    if config.cpus.max_vcpus > config.cpus.boot_vcpus {
+        features |= HYPERVISOR_INFO_FEATURE_CPU_HOTPLUG;
+    }
+    if config.memory.hotplug_size.is_some()
+        || config
+            .memory
+            .zones
+            .iter()
+            .flatten()
+            .any(|zone| zone.hotplug_size.is_some())
+    {
+        features |= HYPERVISOR_INFO_FEATURE_MEMORY_HOTPLUG;
+    }
+    if config.balloon.is_some() {
+        features |= HYPERVISOR_INFO_FEATURE_BALLOON;
+    }
+    if config.pvpanic {
+        features |= HYPERVISOR_INFO_FEATURE_PVPANIC;
+    }
+    if config.watchdog {
+        features |= HYPERVISOR_INFO_FEATURE_WATCHDOG;
+    }
+    if config.iommu
+        || config
+            .platform
+            .as_ref()
+            .map(|platform| platform.iommu_segments.is_some())
+            .unwrap_or(false)
+    {
+        features |= HYPERVISOR_INFO_FEATURE_IOMMU;
+    }
+    if swiotlb::required(config) {
+        features |= HYPERVISOR_INFO_FEATURE_CONFIDENTIAL;
+    }
+
+    features
+}
+
+/// Identification of the VMM exposed to the guest, if enabled through the
+/// platform configuration.
+pub fn hypervisor_info(config: &VmConfig, vmm_version: &str) -> Option<HypervisorInfo> {
+    let platform = config.platform.as_ref()?;
+    if !platform.hypervisor_info {
+        return None;
+    }
+
+    Some(HypervisorInfo {
+        version: parse_version(vmm_version),
+        features: features(config),
+        uuid: platform
+            .uuid
+            .as_ref()
+            .and_then(|uuid| uuid::Uuid::parse_str(uuid).ok()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("v24.0"), [24, 0, 0]);
+        assert_eq!(parse_version("v24.1.2"), [24, 1, 2]);
+        assert_eq!(parse_version("v24.0-12-gabcdef01"), [24, 0, 0]);
+        assert_eq!(parse_version("v24.0-dirty"), [24, 0, 0]);
+        assert_eq!(parse_version("unknown"), [0, 0, 0]);
+        assert_eq!(parse_version(""), [0, 0, 0]);
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Compressed kernel images
+//!
+//! The aarch64 kernels are commonly shipped compressed by the distributions,
+//! either as a plain gzip or zstd stream (`Image.gz`, `Image.zst`), or as an
+//! EFI zboot image (`vmlinuz.efi`), a small EFI application carrying the
+//! compressed `Image` as its payload. The kernel image is decompressed in
+//! memory before being loaded, the loaders only handling the raw `Image`.
+
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom};
+use thiserror::Error;
+
+/// Largest decompressed kernel image accepted, so that a corrupted or
+/// malicious image can't make the VMM allocate an unbounded buffer.
+pub const MAX_KERNEL_IMAGE_SIZE: u64 = 512 << 20;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+// The EFI zboot header follows the "MZ" signature of the PE image with the
+// "zimg" magic, the offset and size of the payload, and the name of the
+// compression algorithm as a NUL terminated string.
+const ZBOOT_MZ_MAGIC: &[u8] = b"MZ";
+const ZBOOT_MAGIC: &[u8] = b"zimg";
+const ZBOOT_MAGIC_OFFSET: usize = 4;
+const ZBOOT_PAYLOAD_OFFSET: usize = 8;
+const ZBOOT_PAYLOAD_SIZE: usize = 12;
+const ZBOOT_COMPRESSION: usize = 24;
+const ZBOOT_HEADER_SIZE: usize = 56;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Cannot read the kernel image: {0}")]
+    Read(#[source] io::Error),
+
+    #[error("Cannot decompress the {0} kernel image: {1}")]
+    Decompress(&'static str, #[source] io::Error),
+
+    #[error(
+        "Decompressed kernel image larger than {} bytes",
+        MAX_KERNEL_IMAGE_SIZE
+    )]
+    TooLarge,
+
+    #[error("EFI zboot payload out of the bounds of the kernel image")]
+    InvalidZbootPayload,
+
+    #[error("Unsupported EFI zboot compression: {0}")]
+    UnsupportedZbootCompression(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn from_magic(header: &[u8]) -> Option<Self> {
+        if header.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    fn decompress<R: Read>(&self, compressed: R) -> Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => self.read_image(flate2::read::GzDecoder::new(compressed)),
+            Compression::Zstd => self.read_image(
+                zstd::stream::read::Decoder::new(compressed)
+                    .map_err(|e| Error::Decompress(self.name(), e))?,
+            ),
+        }
+    }
+
+    fn read_image<R: Read>(&self, decoder: R) -> Result<Vec<u8>> {
+        // Read one byte past the limit to tell an image of exactly the
+        // maximum size from a larger one.
+        let mut image = Vec::new();
+        decoder
+            .take(MAX_KERNEL_IMAGE_SIZE + 1)
+            .read_to_end(&mut image)
+            .map_err(|e| Error::Decompress(self.name(), e))?;
+        if image.len() as u64 > MAX_KERNEL_IMAGE_SIZE {
+            return Err(Error::TooLarge);
+        }
+
+        Ok(image)
+    }
+}
+
+fn read_u32(header: &[u8], offset: usize) -> u64 {
+    u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap()).into()
+}
+
+/// Decompress the kernel image if it is compressed, returning `None` for an
+/// uncompressed image. The image is read from its start, and left at an
+/// unspecified position.
+pub fn decompress<F: Read + Seek>(kernel: &mut F) -> Result<Option<Vec<u8>>> {
+    let size = kernel.seek(SeekFrom::End(0)).map_err(Error::Read)?;
+    kernel.seek(SeekFrom::Start(0)).map_err(Error::Read)?;
+
+    let mut header = vec![0u8; (ZBOOT_HEADER_SIZE as u64).min(size) as usize];
+    kernel.read_exact(&mut header).map_err(Error::Read)?;
+
+    if let Some(compression) = Compression::from_magic(&header) {
+        info!("Decompressing the {} kernel image", compression.name());
+        kernel.seek(SeekFrom::Start(0)).map_err(Error::Read)?;
+        return compression.decompress(kernel).map(Some);
+    }
+
+    if header.len() == ZBOOT_HEADER_SIZE
+        && header.starts_with(ZBOOT_MZ_MAGIC)
+        && &header[ZBOOT_MAGIC_OFFSET..ZBOOT_MAGIC_OFFSET + ZBOOT_MAGIC.len()] == ZBOOT_MAGIC
+    {
+        let name = &header[ZBOOT_COMPRESSION..];
+        let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())];
+        let name = String::from_utf8_lossy(name);
+        let compression = match name.as_ref() {
+            "gzip" => Compression::Gzip,
+            "zstd" => Compression::Zstd,
+            _ => return Err(Error::UnsupportedZbootCompression(name.into_owned())),
+        };
+
+        let payload_offset = read_u32(&header, ZBOOT_PAYLOAD_OFFSET);
+        let payload_size = read_u32(&header, ZBOOT_PAYLOAD_SIZE);
+        if payload_offset + payload_size > size {
+            return Err(Error::InvalidZbootPayload);
+        }
+
+        info!(
+            "Decompressing the {} payload of the EFI zboot kernel image",
+            compression.name()
+        );
+        kernel
+            .seek(SeekFrom::Start(payload_offset))
+            .map_err(Error::Read)?;
+        return compression.decompress(kernel.take(payload_size)).map(Some);
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zboot(compression: &str, payload: &[u8]) -> Vec<u8> {
+        let mut image = vec![0u8; 0x100];
+        image[..2].copy_from_slice(ZBOOT_MZ_MAGIC);
+        image[ZBOOT_MAGIC_OFFSET..ZBOOT_MAGIC_OFFSET + 4].copy_from_slice(ZBOOT_MAGIC);
+        image[ZBOOT_PAYLOAD_OFFSET..ZBOOT_PAYLOAD_OFFSET + 4]
+            .copy_from_slice(&0x100u32.to_le_bytes());
+        image[ZBOOT_PAYLOAD_SIZE..ZBOOT_PAYLOAD_SIZE + 4]
+            .copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        image[ZBOOT_COMPRESSION..ZBOOT_COMPRESSION + compression.len()]
+            .copy_from_slice(compression.as_bytes());
+        image.extend_from_slice(payload);
+        // Trailing data of the PE image, after the payload.
+        image.extend_from_slice(&[0xff; 0x40]);
+        image
+    }
+
+    #[test]
+    fn test_decompress_kernel_image() {
+        let kernel = b"uncompressed arm64 kernel image".repeat(64);
+
+        assert!(decompress(&mut Cursor::new(&kernel)).unwrap().is_none());
+        assert!(decompress(&mut Cursor::new(b"MZ")).unwrap().is_none());
+
+        let compressed = gzip(&kernel);
+        assert_eq!(
+            decompress(&mut Cursor::new(&compressed)).unwrap().unwrap(),
+            kernel
+        );
+
+        let compressed = zstd::stream::encode_all(kernel.as_slice(), 0).unwrap();
+        assert_eq!(
+            decompress(&mut Cursor::new(&compressed)).unwrap().unwrap(),
+            kernel
+        );
+
+        let image = zboot("gzip", &gzip(&kernel));
+        assert_eq!(
+            decompress(&mut Cursor::new(&image)).unwrap().unwrap(),
+            kernel
+        );
+
+        let image = zboot("lzma", &[0; 16]);
+        assert!(matches!(
+            decompress(&mut Cursor::new(&image)),
+            Err(Error::UnsupportedZbootCompression(name)) if name == "lzma"
+        ));
+
+        let mut image = zboot("gzip", &gzip(&kernel));
+        image.truncate(0x120);
+        assert!(matches!(
+            decompress(&mut Cursor::new(&image)),
+            Err(Error::InvalidZbootPayload)
+        ));
+
+        let mut compressed = gzip(&kernel);
+        compressed.truncate(compressed.len() / 2);
+        assert!(matches!(
+            decompress(&mut Cursor::new(&compressed)),
+            Err(Error::Decompress("gzip", _))
+        ));
+    }
+}
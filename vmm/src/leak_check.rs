@@ -0,0 +1,246 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Detection of the resources leaked by a VM once it is shut down, for hosts
+//! cycling many VMs from the same VMM process. The file descriptors and the
+//! threads of the process are compared with the ones it had before the VM
+//! was created, and the guest memory must not be mapped anymore.
+//!
+//! The KVM memory slots and irqfds are released along with the VM and
+//! eventfd file descriptors, hence reported as leaked file descriptors.
+//! Sockets are ignored, as the API connection requesting the shutdown is
+//! opened while the VM is running.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+// The threads of the virtio devices are signaled to exit when the devices
+// are dropped, but aren't joined. Leave them some time before reporting them.
+const LEAK_CHECK_RETRIES: u32 = 20;
+const LEAK_CHECK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeakCheck {
+    /// No verification.
+    Off,
+    /// The leaks are logged.
+    Log,
+    /// The leaks are logged, then the VMM panics.
+    Assert,
+}
+
+impl FromStr for LeakCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(LeakCheck::Off),
+            "log" => Ok(LeakCheck::Log),
+            "assert" => Ok(LeakCheck::Assert),
+            _ => Err(format!("Invalid leak check mode: {}", s)),
+        }
+    }
+}
+
+/// File descriptors and threads of the process.
+#[derive(Debug, Default)]
+pub struct Resources {
+    fds: BTreeMap<RawFd, String>,
+    threads: BTreeMap<u32, String>,
+}
+
+impl Resources {
+    pub fn capture() -> io::Result<Self> {
+        let mut fds = BTreeMap::new();
+        // Listed before being resolved, so that the file descriptor reading
+        // the directory is closed.
+        let entries = fs::read_dir("/proc/self/fd")?.collect::<io::Result<Vec<_>>>()?;
+        for entry in entries {
+            let fd = match entry.file_name().to_str().and_then(|fd| fd.parse().ok()) {
+                Some(fd) => fd,
+                None => continue,
+            };
+            if let Ok(target) = fs::read_link(entry.path()) {
+                fds.insert(fd, target.to_string_lossy().into_owned());
+            }
+        }
+
+        let mut threads = BTreeMap::new();
+        for entry in fs::read_dir("/proc/self/task")? {
+            let entry = entry?;
+            let tid = match entry.file_name().to_str().and_then(|tid| tid.parse().ok()) {
+                Some(tid) => tid,
+                None => continue,
+            };
+            // The thread may have exited in between.
+            if let Ok(name) = fs::read_to_string(entry.path().join("comm")) {
+                threads.insert(tid, name.trim_end().to_string());
+            }
+        }
+
+        Ok(Resources { fds, threads })
+    }
+}
+
+/// Resources of a VM which are still held after its shutdown.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Leaks {
+    fds: Vec<(RawFd, String)>,
+    mappings: Vec<(u64, u64)>,
+    threads: Vec<(u32, String)>,
+}
+
+impl Leaks {
+    fn new(before: &Resources, after: &Resources, maps: &str, mappings: &[(u64, u64)]) -> Self {
+        let fds = after
+            .fds
+            .iter()
+            .filter(|(fd, target)| {
+                !target.starts_with("socket:") && before.fds.get(fd) != Some(target)
+            })
+            .map(|(fd, target)| (*fd, target.clone()))
+            .collect();
+
+        let threads = after
+            .threads
+            .iter()
+            .filter(|(tid, _)| !before.threads.contains_key(tid))
+            .map(|(tid, name)| (*tid, name.clone()))
+            .collect();
+
+        // The address range is the first field of each line of the maps.
+        let mapped: Vec<(u64, u64)> = maps
+            .lines()
+            .filter_map(|line| {
+                let (start, end) = line.split_whitespace().next()?.split_once('-')?;
+                Some((
+                    u64::from_str_radix(start, 16).ok()?,
+                    u64::from_str_radix(end, 16).ok()?,
+                ))
+            })
+            .collect();
+        let mappings = mappings
+            .iter()
+            .filter(|(addr, size)| {
+                mapped
+                    .iter()
+                    .any(|(start, end)| *start < addr + size && *addr < *end)
+            })
+            .copied()
+            .collect();
+
+        Leaks {
+            fds,
+            mappings,
+            threads,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty() && self.mappings.is_empty() && self.threads.is_empty()
+    }
+}
+
+impl fmt::Display for Leaks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut leaks = Vec::new();
+        if !self.fds.is_empty() {
+            let fds: Vec<String> = self
+                .fds
+                .iter()
+                .map(|(fd, target)| format!("{} ({})", fd, target))
+                .collect();
+            leaks.push(format!("file descriptors: {}", fds.join(", ")));
+        }
+        if !self.mappings.is_empty() {
+            let mappings: Vec<String> = self
+                .mappings
+                .iter()
+                .map(|(addr, size)| format!("0x{:x}-0x{:x}", addr, addr + size))
+                .collect();
+            leaks.push(format!("guest memory mappings: {}", mappings.join(", ")));
+        }
+        if !self.threads.is_empty() {
+            let threads: Vec<String> = self
+                .threads
+                .iter()
+                .map(|(tid, name)| format!("{} ({})", tid, name))
+                .collect();
+            leaks.push(format!("threads: {}", threads.join(", ")));
+        }
+        write!(f, "{}", leaks.join("; "))
+    }
+}
+
+/// Resources held by the process compared to the ones from before the VM
+/// was created, along with the mappings of its guest memory still present.
+pub fn check(before: &Resources, guest_memory_mappings: &[(u64, u64)]) -> io::Result<Leaks> {
+    let mut retries = LEAK_CHECK_RETRIES;
+    loop {
+        let after = Resources::capture()?;
+        let maps = fs::read_to_string("/proc/self/maps")?;
+        let leaks = Leaks::new(before, &after, &maps, guest_memory_mappings);
+        if leaks.is_empty() || retries == 0 {
+            return Ok(leaks);
+        }
+        retries -= 1;
+        thread::sleep(LEAK_CHECK_RETRY_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leaks() {
+        let before = Resources {
+            fds: BTreeMap::from([
+                (0, "/dev/null".to_string()),
+                (3, "anon_inode:[eventfd]".to_string()),
+            ]),
+            threads: BTreeMap::from([(100, "cloud-hyperviso".to_string())]),
+        };
+        let after = Resources {
+            fds: BTreeMap::from([
+                (0, "/dev/null".to_string()),
+                (3, "anon_inode:[eventfd]".to_string()),
+                (4, "anon_inode:kvm-vm".to_string()),
+                (5, "socket:[1234]".to_string()),
+            ]),
+            threads: BTreeMap::from([
+                (100, "cloud-hyperviso".to_string()),
+                (104, "_disk0_q0".to_string()),
+            ]),
+        };
+        let maps = "7f0000000000-7f0040000000 rw-p 00000000 00:00 0 \n\
+                    7fff00000000-7fff00021000 rw-p 00000000 00:00 0 [stack]\n";
+        let mappings = [(0x7f00_0000_0000, 0x4000_0000), (0x1000_0000, 0x1000)];
+
+        let leaks = Leaks::new(&before, &after, maps, &mappings);
+        assert_eq!(
+            leaks,
+            Leaks {
+                fds: vec![(4, "anon_inode:kvm-vm".to_string())],
+                mappings: vec![(0x7f00_0000_0000, 0x4000_0000)],
+                threads: vec![(104, "_disk0_q0".to_string())],
+            }
+        );
+        assert_eq!(
+            leaks.to_string(),
+            "file descriptors: 4 (anon_inode:kvm-vm); \
+             guest memory mappings: 0x7f0000000000-0x7f0040000000; \
+             threads: 104 (_disk0_q0)"
+        );
+
+        assert!(Leaks::new(&before, &before, "", &mappings).is_empty());
+    }
+}
@@ -10,23 +10,36 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
+use crate::accounting::Accounting;
+#[cfg(feature = "guest_debug")]
+use crate::api::VmCoredumpData;
+#[cfg(feature = "fault_injection")]
+use crate::api::VmInjectFaultData;
 use crate::api::{
-    ApiError, ApiRequest, ApiResponse, ApiResponsePayload, VmInfo, VmReceiveMigrationData,
-    VmSendMigrationData, VmmPingResponse,
+    ApiError, ApiRequest, ApiResponse, ApiResponsePayload, VmInfo, VmInjectNmiData,
+    VmMaintenanceData, VmReceiveMigrationData, VmSendMigrationData, VmSetInterruptCoalescingData,
+    VmSetQueueSizeData, VmSetRateLimiterData, VmSnapshotConfig, VmmPingResponse,
 };
 use crate::config::{
-    add_to_config, DeviceConfig, DiskConfig, FsConfig, NetConfig, PmemConfig, RestoreConfig,
-    UserDeviceConfig, VdpaConfig, VmConfig, VsockConfig,
+    add_to_config, DeviceConfig, DiskConfig, FsConfig, MaintenanceExpiryAction, NetConfig,
+    PmemConfig, RestoreConfig, SnapshotScheduleConfig, UserDeviceConfig, VdpaConfig, VmConfig,
+    VsockConfig, WatchdogAction,
 };
 #[cfg(feature = "guest_debug")]
 use crate::coredump::GuestDebuggable;
+use crate::hook_runner::HookRunner;
+use crate::leak_check::LeakCheck;
+use crate::maintenance::{Maintenance, MaintenanceCheckpoint};
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
 use crate::migration::get_vm_snapshot;
-use crate::migration::{recv_vm_config, recv_vm_state};
+use crate::migration::{recv_vm_config, recv_vm_state, snapshot_info, SnapshotInfo};
 use crate::seccomp_filters::{get_seccomp_filter, Thread};
-use crate::vm::{Error as VmError, Vm, VmState};
+use crate::signal_fd::SignalFd;
+use crate::snapshot_scheduler::{create_snapshot_directory, prune, SnapshotScheduler};
+use crate::state_history::{StateHistory, VmStateReason};
+use crate::vm::{Error as VmError, Vm, VmState, HANDLED_SIGNALS};
 use anyhow::anyhow;
-use libc::EFD_NONBLOCK;
+use libc::{EFD_NONBLOCK, SIGINT, SIGTERM, SIGUSR1};
 use memory_manager::MemoryManagerSnapshotData;
 use pci::PciBdf;
 use seccompiler::{apply_filter, SeccompAction};
@@ -39,7 +52,7 @@ use std::io::{Read, Write};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::UnixListener;
 use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{Receiver, RecvError, SendError, Sender};
 use std::sync::{Arc, Mutex};
 use std::{result, thread};
@@ -50,8 +63,11 @@ use vm_migration::{MigratableError, Pausable, Snapshot, Snapshottable, Transport
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::sock_ctrl_msg::ScmSocket;
 
+pub mod accounting;
 mod acpi;
 pub mod api;
+mod boot_phase;
+mod cgroup;
 mod clone3;
 pub mod config;
 #[cfg(feature = "guest_debug")]
@@ -59,16 +75,33 @@ mod coredump;
 pub mod cpu;
 pub mod device_manager;
 pub mod device_tree;
+mod event_stream;
+pub mod fleet;
 #[cfg(feature = "gdb")]
 mod gdb;
+mod hook_runner;
+mod hugepages;
+mod hypervisor_info;
 pub mod interrupt;
+#[cfg(target_arch = "aarch64")]
+mod kernel_image;
+pub mod leak_check;
+pub mod maintenance;
 pub mod memory_manager;
+pub mod metrics;
 pub mod migration;
 mod pci_segment;
+mod priority;
+mod pstore;
 pub mod seccomp_filters;
+pub mod security;
 mod serial_buffer;
 mod serial_manager;
+mod signal_fd;
 mod sigwinch_listener;
+mod snapshot_scheduler;
+pub mod state_history;
+mod swiotlb;
 pub mod vm;
 
 type GuestMemoryMmap = vm_memory::GuestMemoryMmap<AtomicBitmap>;
@@ -98,6 +131,34 @@ pub enum Error {
     #[error("Error creating EventFd: {0}")]
     EventFdCreate(#[source] io::Error),
 
+    /// Cannot create the timer of the scheduled snapshots.
+    #[error("Error creating the snapshot schedule timer: {0}")]
+    SnapshotTimerCreate(#[source] io::Error),
+
+    /// Cannot read the timer of the scheduled snapshots.
+    #[error("Error reading the snapshot schedule timer: {0}")]
+    SnapshotTimerRead(#[source] io::Error),
+
+    /// Cannot create the timer of the maintenance lease.
+    #[error("Error creating the maintenance lease timer: {0}")]
+    MaintenanceTimerCreate(#[source] io::Error),
+
+    /// Cannot read the timer of the maintenance lease.
+    #[error("Error reading the maintenance lease timer: {0}")]
+    MaintenanceTimerRead(#[source] io::Error),
+
+    /// Cannot create the signalfd of the handled signals.
+    #[error("Error creating the signalfd: {0}")]
+    SignalFdCreate(#[source] io::Error),
+
+    /// Cannot start the process running the hooks.
+    #[error("Error starting the hook runner: {0}")]
+    HookRunnerStart(#[source] io::Error),
+
+    /// Cannot read from the signalfd of the handled signals.
+    #[error("Error reading from the signalfd: {0}")]
+    SignalFdRead(#[source] io::Error),
+
     /// Cannot read from EventFd.
     #[error("Error reading from EventFd: {0}")]
     EventFdRead(#[source] io::Error),
@@ -110,6 +171,18 @@ pub enum Error {
     #[error("Error spawning HTTP thread: {0}")]
     HttpThreadSpawn(#[source] io::Error),
 
+    /// Cannot create metrics thread
+    #[error("Error spawning metrics thread: {0}")]
+    MetricsThreadSpawn(#[source] io::Error),
+
+    /// Cannot create event stream thread
+    #[error("Error spawning event stream thread: {0}")]
+    EventStreamThreadSpawn(#[source] io::Error),
+
+    /// Cannot create fleet thread
+    #[error("Error spawning fleet thread: {0}")]
+    FleetThreadSpawn(#[source] io::Error),
+
     /// Cannot handle the VM STDIN stream
     #[error("Error handling VM stdin: {0:?}")]
     Stdin(VmError),
@@ -174,6 +247,12 @@ pub enum EpollDispatch {
     Api = 2,
     ActivateVirtioDevices = 3,
     Debug = 4,
+    GuestPanic = 5,
+    Watchdog = 6,
+    SnapshotSchedule = 7,
+    Signal = 8,
+    Maintenance = 9,
+    DeviceEvents = 10,
     Unknown,
 }
 
@@ -186,6 +265,12 @@ impl From<u64> for EpollDispatch {
             2 => Api,
             3 => ActivateVirtioDevices,
             4 => Debug,
+            5 => GuestPanic,
+            6 => Watchdog,
+            7 => SnapshotSchedule,
+            8 => Signal,
+            9 => Maintenance,
+            10 => DeviceEvents,
             _ => Unknown,
         }
     }
@@ -253,6 +338,9 @@ pub fn start_vmm_thread(
     vmm_version: String,
     http_path: &Option<String>,
     http_fd: Option<RawFd>,
+    metrics_listener: Option<metrics::MetricsListener>,
+    event_stream_listener: Option<UnixListener>,
+    fleet_config: Option<fleet::FleetConfig>,
     api_event: EventFd,
     api_sender: Sender<ApiRequest>,
     api_receiver: Receiver<ApiRequest>,
@@ -261,6 +349,7 @@ pub fn start_vmm_thread(
     #[cfg(feature = "gdb")] vm_debug_event: EventFd,
     seccomp_action: &SeccompAction,
     hypervisor: Arc<dyn hypervisor::Hypervisor>,
+    leak_check: LeakCheck,
 ) -> Result<thread::JoinHandle<Result<()>>> {
     #[cfg(feature = "gdb")]
     let (gdb_sender, gdb_receiver) = std::sync::mpsc::channel();
@@ -270,6 +359,8 @@ pub fn start_vmm_thread(
     let gdb_vm_debug_event = vm_debug_event.try_clone().map_err(Error::EventFdClone)?;
 
     let http_api_event = api_event.try_clone().map_err(Error::EventFdClone)?;
+    let metrics_api_event = api_event.try_clone().map_err(Error::EventFdClone)?;
+    let fleet_api_event = api_event.try_clone().map_err(Error::EventFdClone)?;
 
     // Retrieve seccomp filter
     let vmm_seccomp_filter =
@@ -277,8 +368,12 @@ pub fn start_vmm_thread(
 
     let vmm_seccomp_action = seccomp_action.clone();
     let exit_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+    // Created before the seccomp filter of the VMM thread is applied.
+    let signal_fd = SignalFd::new(&HANDLED_SIGNALS).map_err(Error::SignalFdCreate)?;
+    let hook_runner = HookRunner::new().map_err(Error::HookRunnerStart)?;
     let thread = {
         let exit_evt = exit_evt.try_clone().map_err(Error::EventFdClone)?;
+        let vmm_version = vmm_version.clone();
         thread::Builder::new()
             .name("vmm".to_string())
             .spawn(move || {
@@ -288,7 +383,7 @@ pub fn start_vmm_thread(
                 }
 
                 let mut vmm = Vmm::new(
-                    vmm_version.to_string(),
+                    vmm_version,
                     api_event,
                     #[cfg(feature = "gdb")]
                     debug_event,
@@ -297,6 +392,9 @@ pub fn start_vmm_thread(
                     vmm_seccomp_action,
                     hypervisor,
                     exit_evt,
+                    signal_fd,
+                    Some(hook_runner),
+                    leak_check,
                 )?;
 
                 vmm.control_loop(
@@ -309,6 +407,36 @@ pub fn start_vmm_thread(
     };
 
     // The VMM thread is started, we can start serving HTTP requests
+    if let Some(metrics_listener) = metrics_listener {
+        metrics::start_metrics_thread(
+            metrics_listener,
+            metrics_api_event,
+            api_sender.clone(),
+            seccomp_action,
+            exit_evt.try_clone().map_err(Error::EventFdClone)?,
+        )?;
+    }
+
+    if let Some(event_stream_listener) = event_stream_listener {
+        event_stream::start_event_stream_thread(
+            event_stream_listener,
+            seccomp_action,
+            exit_evt.try_clone().map_err(Error::EventFdClone)?,
+        )?;
+    }
+
+    if let Some(fleet_config) = fleet_config {
+        fleet::start_fleet_threads(
+            fleet_config,
+            vmm_version,
+            http_path.as_ref().map(PathBuf::from),
+            fleet_api_event,
+            api_sender.clone(),
+            seccomp_action,
+            exit_evt.try_clone().map_err(Error::EventFdClone)?,
+        )?;
+    }
+
     if let Some(http_path) = http_path {
         api::start_http_path_thread(
             http_path,
@@ -339,6 +467,24 @@ pub fn start_vmm_thread(
     Ok(thread)
 }
 
+// The file descriptors of the doorbell channels, given on the command line of
+// the VMM, would refer to any other file in the restored or migrated VM.
+fn has_doorbells(vm_config: &VmConfig) -> bool {
+    vm_config
+        .doorbells
+        .as_ref()
+        .map_or(false, |doorbells| !doorbells.is_empty())
+}
+
+// The watchdog hook is run by a helper process without seccomp filter, so it
+// is only trusted from the command line of the VMM.
+fn has_watchdog_hook(vm_config: &VmConfig) -> bool {
+    vm_config
+        .watchdog_config
+        .as_ref()
+        .map_or(false, |watchdog| watchdog.hook.is_some())
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 struct VmMigrationConfig {
     vm_config: Arc<Mutex<VmConfig>>,
@@ -351,6 +497,8 @@ pub struct Vmm {
     epoll: EpollContext,
     exit_evt: EventFd,
     reset_evt: EventFd,
+    panic_evt: EventFd,
+    watchdog_evt: EventFd,
     api_evt: EventFd,
     #[cfg(feature = "gdb")]
     debug_evt: EventFd,
@@ -362,6 +510,14 @@ pub struct Vmm {
     seccomp_action: SeccompAction,
     hypervisor: Arc<dyn hypervisor::Hypervisor>,
     activate_evt: EventFd,
+    leak_check: LeakCheck,
+    vm_resources: Option<leak_check::Resources>,
+    state_history: StateHistory,
+    accounting: Accounting,
+    snapshot_scheduler: SnapshotScheduler,
+    maintenance: Maintenance,
+    signal_fd: SignalFd,
+    hook_runner: Option<HookRunner>,
 }
 
 impl Vmm {
@@ -373,10 +529,17 @@ impl Vmm {
         seccomp_action: SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
         exit_evt: EventFd,
+        signal_fd: SignalFd,
+        hook_runner: Option<HookRunner>,
+        leak_check: LeakCheck,
     ) -> Result<Self> {
         let mut epoll = EpollContext::new().map_err(Error::Epoll)?;
         let reset_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        let panic_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        let watchdog_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
         let activate_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFdCreate)?;
+        let snapshot_scheduler = SnapshotScheduler::new().map_err(Error::SnapshotTimerCreate)?;
+        let maintenance = Maintenance::new().map_err(Error::MaintenanceTimerCreate)?;
 
         epoll
             .add_event(&exit_evt, EpollDispatch::Exit)
@@ -386,6 +549,14 @@ impl Vmm {
             .add_event(&reset_evt, EpollDispatch::Reset)
             .map_err(Error::Epoll)?;
 
+        epoll
+            .add_event(&panic_evt, EpollDispatch::GuestPanic)
+            .map_err(Error::Epoll)?;
+
+        epoll
+            .add_event(&watchdog_evt, EpollDispatch::Watchdog)
+            .map_err(Error::Epoll)?;
+
         epoll
             .add_event(&activate_evt, EpollDispatch::ActivateVirtioDevices)
             .map_err(Error::Epoll)?;
@@ -394,6 +565,18 @@ impl Vmm {
             .add_event(&api_evt, EpollDispatch::Api)
             .map_err(Error::Epoll)?;
 
+        epoll
+            .add_event(&snapshot_scheduler, EpollDispatch::SnapshotSchedule)
+            .map_err(Error::Epoll)?;
+
+        epoll
+            .add_event(&maintenance, EpollDispatch::Maintenance)
+            .map_err(Error::Epoll)?;
+
+        epoll
+            .add_event(&signal_fd, EpollDispatch::Signal)
+            .map_err(Error::Epoll)?;
+
         #[cfg(feature = "gdb")]
         epoll
             .add_event(&debug_evt, EpollDispatch::Debug)
@@ -403,6 +586,8 @@ impl Vmm {
             epoll,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             api_evt,
             #[cfg(feature = "gdb")]
             debug_evt,
@@ -414,14 +599,260 @@ impl Vmm {
             seccomp_action,
             hypervisor,
             activate_evt,
+            leak_check,
+            vm_resources: None,
+            state_history: StateHistory::default(),
+            accounting: Accounting::default(),
+            snapshot_scheduler,
+            maintenance,
+            signal_fd,
+            hook_runner,
         })
     }
 
+    fn run_hook(&self, hook: &Path) {
+        let result = match &self.hook_runner {
+            Some(hook_runner) => hook_runner.run(hook),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no hook runner was started",
+            )),
+        };
+        if let Err(e) = result {
+            error!("Error running the hook {:?}: {}", hook, e);
+        }
+    }
+
+    // Lists the resources of the process before the VM is created, to find
+    // the ones it leaks once shut down.
+    fn capture_resources(&mut self) {
+        if self.leak_check != LeakCheck::Off {
+            self.vm_resources = leak_check::Resources::capture()
+                .map_err(|e| warn!("Error listing the resources of the VMM: {}", e))
+                .ok();
+        }
+    }
+
+    fn check_leaks(&mut self, guest_memory_mappings: &[(u64, u64)]) {
+        let resources = match self.vm_resources.take() {
+            Some(resources) => resources,
+            None => return,
+        };
+
+        match leak_check::check(&resources, guest_memory_mappings) {
+            Ok(leaks) if leaks.is_empty() => info!("No resource leaked by the VM"),
+            Ok(leaks) => {
+                error!("Resources leaked by the VM: {}", leaks);
+                if self.leak_check == LeakCheck::Assert {
+                    panic!("Resources leaked by the VM: {}", leaks);
+                }
+            }
+            Err(e) => warn!("Error checking the resources leaked by the VM: {}", e),
+        }
+    }
+
+    // Arms the timer of the scheduled snapshots, if any, and tracks the guest
+    // memory written in between when the snapshots depend on it. Called each
+    // time a VM is started, as the dirty pages logging is lost when the VM is
+    // re-created.
+    fn start_snapshot_schedule(&mut self) {
+        let snapshot_schedule = match self
+            .vm_config
+            .as_ref()
+            .and_then(|config| config.lock().unwrap().snapshot_schedule.clone())
+        {
+            Some(snapshot_schedule) => snapshot_schedule,
+            None => return,
+        };
+
+        if snapshot_schedule.dirty_threshold > 0 {
+            if let Some(ref mut vm) = self.vm {
+                if let Err(e) = vm.start_scheduled_dirty_log() {
+                    warn!(
+                        "Error tracking the guest memory written between scheduled snapshots: {:?}",
+                        e
+                    );
+                }
+            }
+        }
+
+        if let Err(e) = self.snapshot_scheduler.start(snapshot_schedule.interval) {
+            error!("Error starting the scheduled snapshots: {}", e);
+        }
+    }
+
+    fn stop_snapshot_schedule(&mut self) {
+        if let Err(e) = self.snapshot_scheduler.stop() {
+            error!("Error stopping the scheduled snapshots: {}", e);
+        }
+    }
+
+    fn vm_scheduled_snapshot(&mut self) {
+        let snapshot_schedule = match self
+            .vm_config
+            .as_ref()
+            .and_then(|config| config.lock().unwrap().snapshot_schedule.clone())
+        {
+            Some(snapshot_schedule) => snapshot_schedule,
+            None => return,
+        };
+        let vm = match self.vm {
+            Some(ref mut vm) => vm,
+            None => return,
+        };
+
+        // A paused VM is left as is, the next snapshot being taken on the
+        // first expiration of the timer once resumed.
+        if !matches!(vm.get_state(), Ok(VmState::Running)) {
+            event!("vm", "scheduled-snapshot-skipped", "reason", "not running");
+            return;
+        }
+
+        let track_dirty = snapshot_schedule.dirty_threshold > 0;
+        if track_dirty {
+            match vm.scheduled_dirty() {
+                Ok(dirty) => {
+                    if dirty < snapshot_schedule.dirty_threshold {
+                        info!(
+                            "Skipping the scheduled snapshot: {} bytes written since the last one",
+                            dirty
+                        );
+                        event!(
+                            "vm",
+                            "scheduled-snapshot-skipped",
+                            "reason",
+                            "dirty threshold",
+                            "dirty",
+                            dirty.to_string()
+                        );
+                        return;
+                    }
+                }
+                Err(e) => warn!(
+                    "Error reading the guest memory written since the last scheduled snapshot: {:?}",
+                    e
+                ),
+            }
+        }
+
+        let directory = match create_snapshot_directory(&snapshot_schedule.destination) {
+            Ok(directory) => directory,
+            Err(e) => {
+                error!("Error creating the scheduled snapshot directory: {}", e);
+                event!(
+                    "vm",
+                    "scheduled-snapshot-failed",
+                    "error",
+                    format!("{:?}", e)
+                );
+                return;
+            }
+        };
+        let restart_chain = snapshot_schedule.incremental
+            && self
+                .snapshot_scheduler
+                .restart_chain(snapshot_schedule.retention);
+        match Self::scheduled_snapshot(
+            vm,
+            &directory,
+            &snapshot_schedule,
+            restart_chain,
+            track_dirty,
+        ) {
+            Ok(()) => {
+                if snapshot_schedule.incremental {
+                    self.snapshot_scheduler.add_to_chain(restart_chain);
+                }
+                info!("Scheduled snapshot taken: {}", directory.display());
+                event!(
+                    "vm",
+                    "scheduled-snapshot-taken",
+                    "destination",
+                    directory.display().to_string()
+                );
+            }
+            Err(e) => {
+                error!("Error taking the scheduled snapshot: {:?}", e);
+                event!(
+                    "vm",
+                    "scheduled-snapshot-failed",
+                    "error",
+                    format!("{:?}", e)
+                );
+                // The directory was created for this snapshot only.
+                std::fs::remove_dir_all(&directory).ok();
+                return;
+            }
+        }
+
+        match prune(&snapshot_schedule.destination, snapshot_schedule.retention) {
+            Ok(removed) => {
+                for path in removed {
+                    info!("Scheduled snapshot removed: {}", path.display());
+                    event!(
+                        "vm",
+                        "scheduled-snapshot-removed",
+                        "destination",
+                        path.display().to_string()
+                    );
+                }
+            }
+            Err(e) => warn!("Error removing the oldest scheduled snapshots: {}", e),
+        }
+    }
+
+    // The VM is paused for the time of the snapshot, and resumed whatever its
+    // outcome.
+    fn scheduled_snapshot(
+        vm: &mut Vm,
+        directory: &Path,
+        snapshot_schedule: &SnapshotScheduleConfig,
+        restart_chain: bool,
+        track_dirty: bool,
+    ) -> result::Result<(), MigratableError> {
+        vm.pause()?;
+        vm.set_snapshot_format(snapshot_schedule.format);
+        vm.set_snapshot_incremental(snapshot_schedule.incremental);
+        let url = format!("file://{}", directory.display());
+        // A new chain of incremental snapshots is started once the current
+        // one holds as many snapshots as kept, so that the oldest chain can
+        // be removed.
+        let result = if restart_chain {
+            vm.end_snapshot_chain()
+        } else {
+            Ok(())
+        }
+        .and_then(|_| vm.snapshot())
+        .and_then(|snapshot| vm.send(&snapshot, &url));
+        if track_dirty && result.is_ok() {
+            if let Err(e) = vm.reset_scheduled_dirty() {
+                warn!("Error resetting the guest memory written: {:?}", e);
+            }
+        }
+        vm.resume()?;
+
+        result
+    }
+
     fn vm_create(&mut self, config: Arc<Mutex<VmConfig>>) -> result::Result<(), VmError> {
         // We only store the passed VM config.
         // The VM will be created when being asked to boot it.
         if self.vm_config.is_none() {
+            // The UUID identifies the VM for its whole lifetime, including
+            // across reboots and snapshot/restore, hence it is stored in the
+            // platform configuration when not provided by the user. The VMs
+            // configured without any platform are left without an identity,
+            // their configuration staying the one given.
+            if let Some(platform) = config.lock().unwrap().platform.as_mut() {
+                platform
+                    .uuid
+                    .get_or_insert_with(|| uuid::Uuid::new_v4().to_string());
+            }
             self.vm_config = Some(config);
+            self.state_history.clear();
+            self.accounting.clear();
+            self.state_history
+                .record(VmState::Created, VmStateReason::Api);
             Ok(())
         } else {
             Err(VmError::VmAlreadyCreated)
@@ -436,8 +867,15 @@ impl Vmm {
 
         // Create a new VM if we don't have one yet.
         if self.vm.is_none() {
+            self.capture_resources();
+
             let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
             let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
+            let panic_evt = self.panic_evt.try_clone().map_err(VmError::EventFdClone)?;
+            let watchdog_evt = self
+                .watchdog_evt
+                .try_clone()
+                .map_err(VmError::EventFdClone)?;
             #[cfg(feature = "gdb")]
             let vm_debug_evt = self
                 .vm_debug_evt
@@ -453,6 +891,8 @@ impl Vmm {
                     Arc::clone(vm_config),
                     exit_evt,
                     reset_evt,
+                    panic_evt,
+                    watchdog_evt,
                     #[cfg(feature = "gdb")]
                     vm_debug_evt,
                     &self.seccomp_action,
@@ -461,42 +901,75 @@ impl Vmm {
                     None,
                     None,
                     None,
+                    &self.version,
                 )?;
 
+                self.add_device_events(&vm)?;
                 self.vm = Some(vm);
             }
         }
 
         // Now we can boot the VM.
+        let boot_paused = self
+            .vm_config
+            .as_ref()
+            .map_or(false, |config| config.lock().unwrap().boot_paused);
         if let Some(ref mut vm) = self.vm {
-            vm.boot()
+            if boot_paused {
+                vm.boot_paused()?;
+                self.state_history
+                    .record(VmState::Paused, VmStateReason::Api);
+            } else {
+                vm.boot()?;
+                self.state_history
+                    .record(VmState::Running, VmStateReason::Api);
+            }
+            self.start_snapshot_schedule();
+            Ok(())
         } else {
             Err(VmError::VmNotCreated)
         }
     }
 
     fn vm_pause(&mut self) -> result::Result<(), VmError> {
+        if self.maintenance.checkpoint().is_some() {
+            return Err(VmError::VmInMaintenance);
+        }
+
         if let Some(ref mut vm) = self.vm {
-            vm.pause().map_err(VmError::Pause)
+            vm.pause().map_err(VmError::Pause)?;
+            self.state_history
+                .record(VmState::Paused, VmStateReason::Api);
+            Ok(())
         } else {
             Err(VmError::VmNotRunning)
         }
     }
 
     fn vm_resume(&mut self) -> result::Result<(), VmError> {
+        // The VM is resumed by ending the maintenance instead.
+        if self.maintenance.checkpoint().is_some() {
+            return Err(VmError::VmInMaintenance);
+        }
+
         if let Some(ref mut vm) = self.vm {
-            vm.resume().map_err(VmError::Resume)
+            vm.resume().map_err(VmError::Resume)?;
+            self.state_history
+                .record(VmState::Running, VmStateReason::Api);
+            Ok(())
         } else {
             Err(VmError::VmNotRunning)
         }
     }
 
-    fn vm_snapshot(&mut self, destination_url: &str) -> result::Result<(), VmError> {
+    fn vm_snapshot(&mut self, snapshot_cfg: &VmSnapshotConfig) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
+            vm.set_snapshot_format(snapshot_cfg.format);
+            vm.set_snapshot_incremental(snapshot_cfg.incremental);
             vm.snapshot()
                 .map_err(VmError::Snapshot)
                 .and_then(|snapshot| {
-                    vm.send(&snapshot, destination_url)
+                    vm.send(&snapshot, &snapshot_cfg.destination_url)
                         .map_err(VmError::SnapshotSend)
                 })
         } else {
@@ -504,11 +977,23 @@ impl Vmm {
         }
     }
 
+    fn vm_memory_template(&self, destination_url: &str) -> result::Result<(), VmError> {
+        if let Some(ref vm) = self.vm {
+            vm.memory_template(destination_url)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_restore(&mut self, restore_cfg: RestoreConfig) -> result::Result<(), VmError> {
         if self.vm.is_some() || self.vm_config.is_some() {
             return Err(VmError::VmAlreadyCreated);
         }
 
+        self.capture_resources();
+        self.state_history.clear();
+        self.accounting.clear();
+
         let source_url = restore_cfg.source_url.as_path().to_str();
         if source_url.is_none() {
             return Err(VmError::InvalidRestoreSourceUrl);
@@ -519,7 +1004,18 @@ impl Vmm {
         let vm_config = Arc::new(Mutex::new(
             recv_vm_config(source_url).map_err(VmError::Restore)?,
         ));
+        if has_doorbells(&vm_config.lock().unwrap()) {
+            return Err(VmError::DoorbellRestore);
+        }
+        if has_watchdog_hook(&vm_config.lock().unwrap()) {
+            return Err(VmError::WatchdogHookRestore);
+        }
         let snapshot = recv_vm_state(source_url).map_err(VmError::Restore)?;
+        if restore_cfg.no_verify {
+            warn!("Skipping snapshot checksum verification");
+        } else {
+            snapshot.verify().map_err(VmError::Restore)?;
+        }
         #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
         let vm_snapshot = get_vm_snapshot(&snapshot).map_err(VmError::Restore)?;
 
@@ -531,6 +1027,11 @@ impl Vmm {
 
         let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
         let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let panic_evt = self.panic_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let watchdog_evt = self
+            .watchdog_evt
+            .try_clone()
+            .map_err(VmError::EventFdClone)?;
         #[cfg(feature = "gdb")]
         let debug_evt = self
             .vm_debug_evt
@@ -546,42 +1047,109 @@ impl Vmm {
             vm_config,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "gdb")]
             debug_evt,
             Some(source_url),
             restore_cfg.prefault,
+            !restore_cfg.no_verify,
+            restore_cfg.on_demand,
             &self.seccomp_action,
             self.hypervisor.clone(),
             activate_evt,
+            &self.version,
         )?;
+        self.add_device_events(&vm)?;
         self.vm = Some(vm);
 
         // Now we can restore the rest of the VM.
         if let Some(ref mut vm) = self.vm {
-            vm.restore(snapshot).map_err(VmError::Restore)
+            if let Some(fresh_devices) = restore_cfg.fresh_devices {
+                vm.set_fresh_devices(fresh_devices);
+            }
+            vm.restore(snapshot).map_err(VmError::Restore)?;
+            self.state_history
+                .record(VmState::Paused, VmStateReason::Api);
+            self.start_snapshot_schedule();
+            Ok(())
         } else {
             Err(VmError::VmNotCreated)
         }
     }
 
     #[cfg(feature = "guest_debug")]
-    fn vm_coredump(&mut self, destination_url: &str) -> result::Result<(), VmError> {
+    fn vm_coredump(&mut self, coredump_data: &VmCoredumpData) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
-            vm.coredump(destination_url).map_err(VmError::Coredump)
+            // A running VM is paused for the time of the dump, so that the
+            // guest memory and the vCPU registers are consistent, and it is
+            // resumed afterwards whatever the outcome of the dump.
+            let pause = vm.get_state()? == VmState::Running;
+            if pause {
+                vm.pause().map_err(VmError::Pause)?;
+            }
+
+            let result = vm.coredump(coredump_data).map_err(VmError::Coredump);
+
+            if pause {
+                vm.resume().map_err(VmError::Resume)?;
+            }
+
+            result
         } else {
             Err(VmError::VmNotRunning)
         }
     }
 
-    fn vm_shutdown(&mut self) -> result::Result<(), VmError> {
-        if let Some(ref mut vm) = self.vm.take() {
-            vm.shutdown()
+    fn vm_shutdown(&mut self, reason: VmStateReason) -> result::Result<(), VmError> {
+        if let Some(mut vm) = self.vm.take() {
+            let guest_memory_mappings = vm.guest_memory_mappings();
+            Self::update_accounting(&mut self.accounting, &vm);
+            self.accounting.remove_vm();
+            vm.shutdown()?;
+            drop(vm);
+            self.state_history.record(VmState::Shutdown, reason);
+            self.stop_snapshot_schedule();
+            self.maintenance.leave();
+            self.check_leaks(&guest_memory_mappings);
+            Ok(())
         } else {
             Err(VmError::VmNotRunning)
         }
     }
 
-    fn vm_reboot(&mut self) -> result::Result<(), VmError> {
+    fn vm_reboot(&mut self, reason: VmStateReason) -> result::Result<(), VmError> {
+        // The rebooted VM is running, hence no longer in maintenance.
+        self.maintenance.leave();
+
+        // Reboot the VM in place whenever possible, otherwise fall back to
+        // re-creating it.
+        #[cfg(target_arch = "x86_64")]
+        if let Some(ref mut vm) = self.vm {
+            Self::update_accounting(&mut self.accounting, vm);
+            match vm.reboot() {
+                Ok(()) => {
+                    // Same as below, ignore the i8042 reset that may follow
+                    // the ACPI reset.
+                    if self.reset_evt.read().is_ok() {
+                        warn!("Spurious second reset event received. Ignoring.");
+                    }
+                    self.state_history.record(VmState::Shutdown, reason);
+                    self.state_history.record(VmState::Running, reason);
+                    self.start_snapshot_schedule();
+                    return Ok(());
+                }
+                Err(VmError::InPlaceRebootNotSupported) => {
+                    info!("Re-creating the VM to reboot it");
+                }
+                // The VM is left half reset, re-creating it is the only way
+                // to get it back into a usable state.
+                Err(e) => {
+                    error!("Failed to reboot the VM in place, re-creating it: {:?}", e);
+                }
+            }
+        }
+
         // First we stop the current VM
         let (config, serial_pty, console_pty, console_resize_pipe) =
             if let Some(mut vm) = self.vm.take() {
@@ -592,7 +1160,10 @@ impl Vmm {
                     .console_resize_pipe()
                     .as_ref()
                     .map(|pipe| pipe.try_clone().unwrap());
+                Self::update_accounting(&mut self.accounting, &vm);
+                self.accounting.remove_vm();
                 vm.shutdown()?;
+                self.state_history.record(VmState::Shutdown, reason);
                 (config, serial_pty, console_pty, console_resize_pipe)
             } else {
                 return Err(VmError::VmNotCreated);
@@ -600,6 +1171,11 @@ impl Vmm {
 
         let exit_evt = self.exit_evt.try_clone().map_err(VmError::EventFdClone)?;
         let reset_evt = self.reset_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let panic_evt = self.panic_evt.try_clone().map_err(VmError::EventFdClone)?;
+        let watchdog_evt = self
+            .watchdog_evt
+            .try_clone()
+            .map_err(VmError::EventFdClone)?;
         #[cfg(feature = "gdb")]
         let debug_evt = self
             .vm_debug_evt
@@ -622,6 +1198,8 @@ impl Vmm {
             config,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "gdb")]
             debug_evt,
             &self.seccomp_action,
@@ -630,12 +1208,16 @@ impl Vmm {
             serial_pty,
             console_pty,
             console_resize_pipe,
+            &self.version,
         )?;
 
         // And we boot it
         vm.boot()?;
+        self.state_history.record(VmState::Running, reason);
 
+        self.add_device_events(&vm)?;
         self.vm = Some(vm);
+        self.start_snapshot_schedule();
 
         Ok(())
     }
@@ -657,11 +1239,21 @@ impl Vmm {
 
                 let device_tree = self.vm.as_ref().map(|vm| vm.device_tree());
 
+                let guest_panicked = self
+                    .vm
+                    .as_ref()
+                    .map(|vm| vm.guest_panic_events() != 0)
+                    .unwrap_or(false);
+
                 Ok(VmInfo {
                     config,
                     state,
                     memory_actual_size,
                     device_tree,
+                    guest_panicked,
+                    uptime_ms: self.state_history.uptime().as_millis() as u64,
+                    state_history: self.state_history.transitions(),
+                    maintenance: self.maintenance.checkpoint().cloned(),
                 })
             }
             None => Err(VmError::VmNotCreated),
@@ -674,14 +1266,30 @@ impl Vmm {
         }
     }
 
-    fn vm_delete(&mut self) -> result::Result<(), VmError> {
+    fn vmm_snapshot_info(&self, source_url: &str) -> result::Result<SnapshotInfo, VmError> {
+        snapshot_info(source_url).map_err(|e| {
+            error!("Error when inspecting the snapshot: {:?}", e);
+            VmError::SnapshotInfo(e)
+        })
+    }
+
+    // The device events of the VM are handled by the control loop. Their
+    // epoll file descriptor is removed from the one of the VMM once closed
+    // along with the VM.
+    fn add_device_events(&mut self, vm: &Vm) -> result::Result<(), VmError> {
+        self.epoll
+            .add_event(&vm.device_events_fd(), EpollDispatch::DeviceEvents)
+            .map_err(VmError::DeviceEvents)
+    }
+
+    fn vm_delete(&mut self, reason: VmStateReason) -> result::Result<(), VmError> {
         if self.vm_config.is_none() {
             return Ok(());
         }
 
         // If a VM is booted, we first try to shut it down.
         if self.vm.is_some() {
-            self.vm_shutdown()?;
+            self.vm_shutdown(reason)?;
         }
 
         self.vm_config = None;
@@ -691,8 +1299,8 @@ impl Vmm {
         Ok(())
     }
 
-    fn vmm_shutdown(&mut self) -> result::Result<(), VmError> {
-        self.vm_delete()?;
+    fn vmm_shutdown(&mut self, reason: VmStateReason) -> result::Result<(), VmError> {
+        self.vm_delete(reason)?;
         event!("vmm", "shutdown");
         Ok(())
     }
@@ -757,6 +1365,122 @@ impl Vmm {
         }
     }
 
+    fn vm_throttle_vcpus(&mut self, percentage: u8) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            vm.throttle_vcpus(percentage).map_err(|e| {
+                error!("Error when throttling the vCPUs: {:?}", e);
+                e
+            })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_set_rate_limiter(
+        &mut self,
+        rate_limiter_data: &VmSetRateLimiterData,
+    ) -> result::Result<(), VmError> {
+        self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        if let Some(ref mut vm) = self.vm {
+            vm.set_rate_limiter(&rate_limiter_data.id, rate_limiter_data.rate_limiter_config)
+                .map_err(|e| {
+                    error!("Error when setting the rate limiter: {:?}", e);
+                    e
+                })
+        } else {
+            // Update VmConfig by setting the new rate limiting.
+            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
+            let rate_limiter_config = config
+                .rate_limiter_config_mut(&rate_limiter_data.id)
+                .ok_or_else(|| VmError::UnknownRateLimitedDevice(rate_limiter_data.id.clone()))?;
+            *rate_limiter_config = rate_limiter_data.rate_limiter_config;
+            Ok(())
+        }
+    }
+
+    fn vm_set_interrupt_coalescing(
+        &mut self,
+        interrupt_coalescing_data: &VmSetInterruptCoalescingData,
+    ) -> result::Result<(), VmError> {
+        self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        if matches!(interrupt_coalescing_data.interrupt_coalescing, Some(c) if c.usecs == 0) {
+            return Err(VmError::InvalidInterruptCoalescingUsecs);
+        }
+
+        if let Some(ref mut vm) = self.vm {
+            vm.set_interrupt_coalescing(
+                &interrupt_coalescing_data.id,
+                interrupt_coalescing_data.interrupt_coalescing,
+            )
+            .map_err(|e| {
+                error!("Error when setting the interrupt coalescing: {:?}", e);
+                e
+            })
+        } else {
+            // Update VmConfig by setting the new interrupt coalescing.
+            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
+            let interrupt_coalescing = config
+                .interrupt_coalescing_mut(&interrupt_coalescing_data.id)
+                .ok_or_else(|| {
+                    VmError::UnknownInterruptCoalescingDevice(interrupt_coalescing_data.id.clone())
+                })?;
+            *interrupt_coalescing = interrupt_coalescing_data.interrupt_coalescing;
+            Ok(())
+        }
+    }
+
+    fn vm_set_queue_size(
+        &mut self,
+        queue_size_data: &VmSetQueueSizeData,
+    ) -> result::Result<(), VmError> {
+        self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        if !queue_size_data.queue_size.is_power_of_two() {
+            return Err(VmError::InvalidQueueSize(queue_size_data.queue_size));
+        }
+
+        if let Some(ref mut vm) = self.vm {
+            vm.set_queue_size(&queue_size_data.id, queue_size_data.queue_size)
+                .map_err(|e| {
+                    error!("Error when setting the queue size: {:?}", e);
+                    e
+                })
+        } else {
+            // Update VmConfig by setting the new queue size.
+            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
+            let queue_size = config
+                .queue_size_mut(&queue_size_data.id)
+                .ok_or_else(|| VmError::UnknownQueueSizeDevice(queue_size_data.id.clone()))?;
+            *queue_size = queue_size_data.queue_size;
+            Ok(())
+        }
+    }
+
+    fn vm_inject_nmi(&mut self, nmi_data: &VmInjectNmiData) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            vm.inject_nmi(nmi_data).map_err(|e| {
+                error!("Error when injecting the NMI: {:?}", e);
+                e
+            })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    #[cfg(feature = "fault_injection")]
+    fn vm_inject_fault(&mut self, fault_data: &VmInjectFaultData) -> result::Result<(), VmError> {
+        if let Some(ref mut vm) = self.vm {
+            vm.inject_fault(fault_data).map_err(|e| {
+                error!("Error when injecting faults: {:?}", e);
+                e
+            })
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
     fn vm_add_device(
         &mut self,
         device_cfg: DeviceConfig,
@@ -817,10 +1541,12 @@ impl Vmm {
 
     fn vm_remove_device(&mut self, id: String) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
-            if let Err(e) = vm.remove_device(id) {
+            Self::update_accounting(&mut self.accounting, vm);
+            if let Err(e) = vm.remove_device(id.clone()) {
                 error!("Error when removing new device to the VM: {:?}", e);
                 Err(e)
             } else {
+                self.accounting.remove_device(&id);
                 Ok(())
             }
         } else {
@@ -869,9 +1595,10 @@ impl Vmm {
                 error!("Error when adding new fs to the VM: {:?}", e);
                 e
             })?;
-            serde_json::to_vec(&info)
-                .map(Some)
-                .map_err(VmError::SerializeJson)
+            // Devices added on the virtio-mmio transport have no PCI
+            // information to report.
+            info.map(|info| serde_json::to_vec(&info).map_err(VmError::SerializeJson))
+                .transpose()
         } else {
             // Update VmConfig by adding the new device.
             let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
@@ -895,9 +1622,10 @@ impl Vmm {
                 error!("Error when adding new pmem device to the VM: {:?}", e);
                 e
             })?;
-            serde_json::to_vec(&info)
-                .map(Some)
-                .map_err(VmError::SerializeJson)
+            // Devices added on the virtio-mmio transport have no PCI
+            // information to report.
+            info.map(|info| serde_json::to_vec(&info).map_err(VmError::SerializeJson))
+                .transpose()
         } else {
             // Update VmConfig by adding the new device.
             let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
@@ -978,24 +1706,95 @@ impl Vmm {
                 error!("Error when adding new vsock device to the VM: {:?}", e);
                 e
             })?;
+            // Devices added on the virtio-mmio transport have no PCI
+            // information to report.
+            info.map(|info| serde_json::to_vec(&info).map_err(VmError::SerializeJson))
+                .transpose()
+        } else {
+            // Update VmConfig by adding the new device.
+            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
+            config.vsock = Some(vsock_cfg);
+            Ok(None)
+        }
+    }
+
+    fn vm_counters(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref mut vm) = self.vm {
+            let info = vm.counters().map_err(|e| {
+                error!("Error when getting counters from the VM: {:?}", e);
+                e
+            })?;
             serde_json::to_vec(&info)
                 .map(Some)
                 .map_err(VmError::SerializeJson)
         } else {
-            // Update VmConfig by adding the new device.
-            let mut config = self.vm_config.as_ref().unwrap().lock().unwrap();
-            config.vsock = Some(vsock_cfg);
-            Ok(None)
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn update_accounting(accounting: &mut Accounting, vm: &Vm) {
+        match vm.counters() {
+            Ok(counters) => accounting.update(&counters),
+            Err(e) => warn!("Error when getting counters from the VM: {:?}", e),
+        }
+    }
+
+    fn vm_accounting(&mut self, reset: bool) -> result::Result<Option<Vec<u8>>, VmError> {
+        self.vm_config.as_ref().ok_or(VmError::VmNotCreated)?;
+
+        if let Some(ref vm) = self.vm {
+            Self::update_accounting(&mut self.accounting, vm);
+        }
+
+        let usage = if reset {
+            self.accounting.reset()
+        } else {
+            self.accounting.usage()
+        };
+        serde_json::to_vec(&usage)
+            .map(Some)
+            .map_err(VmError::SerializeJson)
+    }
+
+    fn vm_metrics(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref mut vm) = self.vm {
+            let metrics = vm.metrics().map_err(|e| {
+                error!("Error when getting metrics from the VM: {:?}", e);
+                e
+            })?;
+            Ok(Some(metrics.to_prometheus().into_bytes()))
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_security_status(&self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref vm) = self.vm {
+            let status = vm.security_status(&self.seccomp_action).map_err(|e| {
+                error!("Error when getting the security status of the VM: {:?}", e);
+                e
+            })?;
+            serde_json::to_vec(&status)
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
+        }
+    }
+
+    fn vm_device_tree(&self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref vm) = self.vm {
+            serde_json::to_vec(&vm.device_tree_info())
+                .map(Some)
+                .map_err(VmError::SerializeJson)
+        } else {
+            Err(VmError::VmNotRunning)
         }
     }
 
-    fn vm_counters(&mut self) -> result::Result<Option<Vec<u8>>, VmError> {
-        if let Some(ref mut vm) = self.vm {
-            let info = vm.counters().map_err(|e| {
-                error!("Error when getting counters from the VM: {:?}", e);
-                e
-            })?;
-            serde_json::to_vec(&info)
+    fn vm_queues(&self) -> result::Result<Option<Vec<u8>>, VmError> {
+        if let Some(ref vm) = self.vm {
+            serde_json::to_vec(&vm.queues())
                 .map(Some)
                 .map_err(VmError::SerializeJson)
         } else {
@@ -1003,6 +1802,26 @@ impl Vmm {
         }
     }
 
+    fn vm_pstore(&self) -> result::Result<Option<Vec<u8>>, VmError> {
+        // The records are read from the backing file, so that they can be
+        // retrieved even after the guest crashed and the VM was shut down.
+        let pstore_config = self
+            .vm_config
+            .as_ref()
+            .ok_or(VmError::VmNotCreated)?
+            .lock()
+            .unwrap()
+            .pstore
+            .clone()
+            .ok_or(VmError::PstoreNotConfigured)?;
+
+        let records = pstore::read_records(&pstore_config.file, pstore_config.size)
+            .map_err(VmError::PstoreRead)?;
+        serde_json::to_vec(&records)
+            .map(Some)
+            .map_err(VmError::SerializeJson)
+    }
+
     fn vm_power_button(&mut self) -> result::Result<(), VmError> {
         if let Some(ref mut vm) = self.vm {
             vm.power_button()
@@ -1011,6 +1830,136 @@ impl Vmm {
         }
     }
 
+    // Pauses the VM, if running, for as long as the lease is renewed. The
+    // settings not requested are the ones of the VM configuration.
+    fn vm_enter_maintenance(
+        &mut self,
+        maintenance_data: &VmMaintenanceData,
+    ) -> result::Result<(), VmError> {
+        if self.maintenance.checkpoint().is_some() {
+            return Err(VmError::VmInMaintenance);
+        }
+
+        let (mut maintenance_config, uuid) = match self.vm_config {
+            Some(ref config) => {
+                let config = config.lock().unwrap();
+                (
+                    config.maintenance.clone().unwrap_or_default(),
+                    config.platform.as_ref().and_then(|p| p.uuid.clone()),
+                )
+            }
+            None => return Err(VmError::VmNotCreated),
+        };
+        if let Some(lease) = maintenance_data.lease {
+            maintenance_config.lease = lease;
+        }
+        if let Some(on_expiry) = maintenance_data.on_expiry {
+            maintenance_config.on_expiry = on_expiry;
+        }
+        if let Some(checkpoint) = &maintenance_data.checkpoint {
+            maintenance_config.checkpoint = Some(checkpoint.clone());
+        }
+        maintenance_config
+            .validate()
+            .map_err(VmError::ConfigValidation)?;
+
+        let vm = self.vm.as_mut().ok_or(VmError::VmNotRunning)?;
+        let state = vm.get_state()?;
+        match state {
+            VmState::Running => {
+                vm.pause().map_err(VmError::Pause)?;
+                self.state_history
+                    .record(VmState::Paused, VmStateReason::Maintenance);
+            }
+            VmState::Paused => {}
+            _ => return Err(VmError::VmNotRunning),
+        }
+
+        let checkpoint = MaintenanceCheckpoint::new(
+            uuid,
+            self.version.clone(),
+            state,
+            maintenance_config.lease,
+            maintenance_config.on_expiry,
+        );
+        if let Err(e) = self
+            .maintenance
+            .enter(checkpoint, maintenance_config.checkpoint)
+        {
+            // The VM is never left paused without a lease.
+            if state == VmState::Running {
+                vm.resume().map_err(VmError::Resume)?;
+                self.state_history
+                    .record(VmState::Running, VmStateReason::Maintenance);
+            }
+            return Err(VmError::Maintenance(e));
+        }
+
+        info!(
+            "VM in maintenance: lease = {}s, on_expiry = {:?}",
+            maintenance_config.lease, maintenance_config.on_expiry
+        );
+        event!(
+            "vm",
+            "maintenance-entered",
+            "lease",
+            maintenance_config.lease.to_string()
+        );
+        Ok(())
+    }
+
+    fn vm_renew_maintenance(&mut self) -> result::Result<(), VmError> {
+        if self.maintenance.checkpoint().is_none() {
+            return Err(VmError::VmNotInMaintenance);
+        }
+
+        let checkpoint = self.maintenance.renew().map_err(VmError::Maintenance)?;
+        event!(
+            "vm",
+            "maintenance-renewed",
+            "expires_at",
+            checkpoint.expires_at.to_string()
+        );
+        Ok(())
+    }
+
+    // Resumes the VM, unless it was already paused when entering maintenance.
+    fn vm_end_maintenance(&mut self) -> result::Result<(), VmError> {
+        let checkpoint = self
+            .maintenance
+            .leave()
+            .ok_or(VmError::VmNotInMaintenance)?;
+        info!("VM out of maintenance");
+        event!("vm", "maintenance-ended");
+
+        if checkpoint.state == VmState::Running {
+            if let Some(ref mut vm) = self.vm {
+                vm.resume().map_err(VmError::Resume)?;
+                self.state_history
+                    .record(VmState::Running, VmStateReason::Maintenance);
+            }
+        }
+
+        Ok(())
+    }
+
+    // The maintenance signal of the host is ignored by the VMs without
+    // maintenance settings, for which the host didn't plan any.
+    fn vm_maintenance_signal(&mut self) {
+        let configured = self
+            .vm_config
+            .as_ref()
+            .map_or(false, |config| config.lock().unwrap().maintenance.is_some());
+        if !configured {
+            warn!("Ignoring the maintenance signal: the VM has no maintenance settings");
+            return;
+        }
+
+        if let Err(e) = self.vm_enter_maintenance(&VmMaintenanceData::default()) {
+            error!("Error entering maintenance on signal: {:?}", e);
+        }
+    }
+
     fn vm_receive_config<T>(
         &mut self,
         req: &Request,
@@ -1038,12 +1987,34 @@ impl Vmm {
             &vm_migration_config.common_cpuid,
         )?;
 
+        if has_doorbells(&vm_migration_config.vm_config.lock().unwrap()) {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "The doorbell devices can't be migrated"
+            )));
+        }
+
+        if has_watchdog_hook(&vm_migration_config.vm_config.lock().unwrap()) {
+            return Err(MigratableError::MigrateReceive(anyhow!(
+                "The watchdog hook can't be migrated"
+            )));
+        }
+
+        self.capture_resources();
+        self.state_history.clear();
+        self.accounting.clear();
+
         let exit_evt = self.exit_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning exit EventFd: {}", e))
         })?;
         let reset_evt = self.reset_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning reset EventFd: {}", e))
         })?;
+        let panic_evt = self.panic_evt.try_clone().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error cloning panic EventFd: {}", e))
+        })?;
+        let watchdog_evt = self.watchdog_evt.try_clone().map_err(|e| {
+            MigratableError::MigrateReceive(anyhow!("Error cloning watchdog EventFd: {}", e))
+        })?;
         #[cfg(feature = "gdb")]
         let debug_evt = self.vm_debug_evt.try_clone().map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error cloning debug EventFd: {}", e))
@@ -1057,6 +2028,8 @@ impl Vmm {
             self.vm_config.clone().unwrap(),
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "gdb")]
             debug_evt,
             &self.seccomp_action,
@@ -1064,6 +2037,7 @@ impl Vmm {
             activate_evt,
             &vm_migration_config.memory_manager_data,
             existing_memory_files,
+            &self.version,
         )
         .map_err(|e| {
             MigratableError::MigrateReceive(anyhow!("Error creating VM from snapshot: {:?}", e))
@@ -1098,6 +2072,10 @@ impl Vmm {
             Response::error().write_to(socket).ok();
             e
         })?;
+        self.add_device_events(&vm).map_err(|e| {
+            Response::error().write_to(socket).ok();
+            MigratableError::MigrateReceive(anyhow!("Error polling the device events: {}", e))
+        })?;
         self.vm = Some(vm);
 
         Response::ok().write_to(socket)?;
@@ -1243,6 +2221,9 @@ impl Vmm {
                     info!("Complete Command Received");
                     if let Some(ref mut vm) = self.vm.as_mut() {
                         vm.resume()?;
+                        self.state_history
+                            .record(VmState::Running, VmStateReason::Migration);
+                        self.start_snapshot_schedule();
                         Response::ok().write_to(&mut socket)?;
                     } else {
                         warn!("VM not created yet");
@@ -1333,6 +2314,9 @@ impl Vmm {
                 None,
                 phys_bits,
                 vm_config.lock().unwrap().cpus.kvm_hyperv,
+                vm_config.lock().unwrap().cpus.nested,
+                vm_config.lock().unwrap().cpus.baseline,
+                vm_config.lock().unwrap().cpus.tsc_khz,
                 #[cfg(feature = "tdx")]
                 tdx_enabled,
             )
@@ -1470,6 +2454,12 @@ impl Vmm {
             )));
         }
 
+        if self.vm_config.as_ref().unwrap().lock().unwrap().cpus.nested {
+            return Err(MigratableError::MigrateSend(anyhow!(
+                "Migration not possible with nested virtualization enabled"
+            )));
+        }
+
         if let Some(vm) = self.vm.as_mut() {
             Self::send_migration(
                 vm,
@@ -1526,6 +2516,9 @@ impl Vmm {
                 None,
                 phys_bits,
                 vm_config.cpus.kvm_hyperv,
+                vm_config.cpus.nested,
+                vm_config.cpus.baseline,
+                vm_config.cpus.tsc_khz,
                 #[cfg(feature = "tdx")]
                 tdx_enabled,
             )
@@ -1580,7 +2573,8 @@ impl Vmm {
                         info!("VM exit event");
                         // Consume the event.
                         self.exit_evt.read().map_err(Error::EventFdRead)?;
-                        self.vmm_shutdown().map_err(Error::VmmShutdown)?;
+                        self.vmm_shutdown(VmStateReason::GuestShutdown)
+                            .map_err(Error::VmmShutdown)?;
 
                         break 'outer;
                     }
@@ -1588,7 +2582,114 @@ impl Vmm {
                         info!("VM reset event");
                         // Consume the event.
                         self.reset_evt.read().map_err(Error::EventFdRead)?;
-                        self.vm_reboot().map_err(Error::VmReboot)?;
+                        self.vm_reboot(VmStateReason::GuestReset)
+                            .map_err(Error::VmReboot)?;
+                    }
+                    EpollDispatch::GuestPanic => {
+                        // Consume the event.
+                        self.panic_evt.read().map_err(Error::EventFdRead)?;
+                        if let Some(ref vm) = self.vm {
+                            let crash_loaded = vm.guest_panic_events()
+                                & devices::legacy::PVPANIC_CRASH_LOADED
+                                != 0;
+                            warn!("VM guest panic event: crash_loaded = {}", crash_loaded);
+                            event!("vm", "panicked", "crash_loaded", crash_loaded.to_string());
+                        }
+                    }
+                    EpollDispatch::Watchdog => {
+                        // Consume the event.
+                        self.watchdog_evt.read().map_err(Error::EventFdRead)?;
+                        let watchdog_config = self
+                            .vm_config
+                            .as_ref()
+                            .and_then(|config| config.lock().unwrap().watchdog_config.clone())
+                            .unwrap_or_default();
+                        let action = format!("{:?}", watchdog_config.action).to_lowercase();
+                        warn!("VM watchdog event: action = {}", action);
+                        event!("vm", "watchdog", "action", action);
+                        match watchdog_config.action {
+                            WatchdogAction::Reset => {
+                                self.vm_reboot(VmStateReason::Watchdog)
+                                    .map_err(Error::VmReboot)?;
+                            }
+                            WatchdogAction::Shutdown => {
+                                self.vmm_shutdown(VmStateReason::Watchdog)
+                                    .map_err(Error::VmmShutdown)?;
+
+                                break 'outer;
+                            }
+                            WatchdogAction::Hook => {
+                                if let Some(hook) = watchdog_config.hook {
+                                    self.run_hook(&hook);
+                                }
+                            }
+                        }
+                    }
+                    EpollDispatch::SnapshotSchedule => {
+                        if self
+                            .snapshot_scheduler
+                            .expired()
+                            .map_err(Error::SnapshotTimerRead)?
+                        {
+                            self.vm_scheduled_snapshot();
+                        }
+                    }
+                    EpollDispatch::Maintenance => {
+                        if self
+                            .maintenance
+                            .expired()
+                            .map_err(Error::MaintenanceTimerRead)?
+                        {
+                            let on_expiry = self
+                                .maintenance
+                                .checkpoint()
+                                .map(|checkpoint| checkpoint.on_expiry)
+                                .unwrap_or_default();
+                            let action = format!("{:?}", on_expiry).to_lowercase();
+                            warn!("VM maintenance lease expired: action = {}", action);
+                            event!("vm", "maintenance-expired", "action", action);
+                            match on_expiry {
+                                MaintenanceExpiryAction::Resume => {
+                                    if let Err(e) = self.vm_end_maintenance() {
+                                        error!(
+                                            "Error resuming the VM after its maintenance: {:?}",
+                                            e
+                                        );
+                                    }
+                                }
+                                MaintenanceExpiryAction::Shutdown => {
+                                    self.vmm_shutdown(VmStateReason::Maintenance)
+                                        .map_err(Error::VmmShutdown)?;
+
+                                    break 'outer;
+                                }
+                            }
+                        }
+                    }
+                    EpollDispatch::Signal => {
+                        while let Some(signal) =
+                            self.signal_fd.read().map_err(Error::SignalFdRead)?
+                        {
+                            if let Some(ref vm) = self.vm {
+                                vm.handle_signal(signal);
+                            }
+                            if signal == SIGUSR1 {
+                                info!("VM maintenance requested by signal");
+                                self.vm_maintenance_signal();
+                            }
+                            if signal == SIGTERM || signal == SIGINT {
+                                info!("VMM terminated by signal {}", signal);
+                                self.vmm_shutdown(VmStateReason::Signal)
+                                    .map_err(Error::VmmShutdown)?;
+
+                                break 'outer;
+                            }
+                        }
+                    }
+                    EpollDispatch::DeviceEvents => {
+                        if let Some(ref vm) = self.vm {
+                            vm.handle_device_events();
+                        }
                     }
                     EpollDispatch::ActivateVirtioDevices => {
                         if let Some(ref vm) = self.vm {
@@ -1620,7 +2721,7 @@ impl Vmm {
                             }
                             ApiRequest::VmDelete(sender) => {
                                 let response = self
-                                    .vm_delete()
+                                    .vm_delete(VmStateReason::Api)
                                     .map_err(ApiError::VmDelete)
                                     .map(|_| ApiResponsePayload::Empty);
 
@@ -1636,7 +2737,7 @@ impl Vmm {
                             }
                             ApiRequest::VmShutdown(sender) => {
                                 let response = self
-                                    .vm_shutdown()
+                                    .vm_shutdown(VmStateReason::Api)
                                     .map_err(ApiError::VmShutdown)
                                     .map(|_| ApiResponsePayload::Empty);
 
@@ -1644,7 +2745,7 @@ impl Vmm {
                             }
                             ApiRequest::VmReboot(sender) => {
                                 let response = self
-                                    .vm_reboot()
+                                    .vm_reboot(VmStateReason::Api)
                                     .map_err(ApiError::VmReboot)
                                     .map(|_| ApiResponsePayload::Empty);
 
@@ -1681,12 +2782,28 @@ impl Vmm {
                             }
                             ApiRequest::VmSnapshot(snapshot_data, sender) => {
                                 let response = self
-                                    .vm_snapshot(&snapshot_data.destination_url)
+                                    .vm_snapshot(&snapshot_data)
                                     .map_err(ApiError::VmSnapshot)
                                     .map(|_| ApiResponsePayload::Empty);
 
                                 sender.send(response).map_err(Error::ApiResponseSend)?;
                             }
+                            ApiRequest::VmMemoryTemplate(template_data, sender) => {
+                                let response = self
+                                    .vm_memory_template(&template_data.destination_url)
+                                    .map_err(ApiError::VmMemoryTemplate)
+                                    .map(|_| ApiResponsePayload::Empty);
+
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmmSnapshotInfo(info_data, sender) => {
+                                let response = self
+                                    .vmm_snapshot_info(&info_data.source_url)
+                                    .map_err(ApiError::VmmSnapshotInfo)
+                                    .map(ApiResponsePayload::VmmSnapshotInfo);
+
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
                             ApiRequest::VmRestore(restore_data, sender) => {
                                 let response = self
                                     .vm_restore(restore_data.as_ref().clone())
@@ -1698,7 +2815,7 @@ impl Vmm {
                             #[cfg(feature = "guest_debug")]
                             ApiRequest::VmCoredump(coredump_data, sender) => {
                                 let response = self
-                                    .vm_coredump(&coredump_data.destination_url)
+                                    .vm_coredump(&coredump_data)
                                     .map_err(ApiError::VmCoredump)
                                     .map(|_| ApiResponsePayload::Empty);
 
@@ -1706,7 +2823,7 @@ impl Vmm {
                             }
                             ApiRequest::VmmShutdown(sender) => {
                                 let response = self
-                                    .vmm_shutdown()
+                                    .vmm_shutdown(VmStateReason::Api)
                                     .map_err(ApiError::VmmShutdown)
                                     .map(|_| ApiResponsePayload::Empty);
 
@@ -1735,6 +2852,52 @@ impl Vmm {
                                     .map(|_| ApiResponsePayload::Empty);
                                 sender.send(response).map_err(Error::ApiResponseSend)?;
                             }
+                            ApiRequest::VmThrottleVcpus(throttle_vcpus_data, sender) => {
+                                let response = self
+                                    .vm_throttle_vcpus(throttle_vcpus_data.percentage)
+                                    .map_err(ApiError::VmThrottleVcpus)
+                                    .map(|_| ApiResponsePayload::Empty);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmSetRateLimiter(rate_limiter_data, sender) => {
+                                let response = self
+                                    .vm_set_rate_limiter(rate_limiter_data.as_ref())
+                                    .map_err(ApiError::VmSetRateLimiter)
+                                    .map(|_| ApiResponsePayload::Empty);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmSetInterruptCoalescing(
+                                interrupt_coalescing_data,
+                                sender,
+                            ) => {
+                                let response = self
+                                    .vm_set_interrupt_coalescing(interrupt_coalescing_data.as_ref())
+                                    .map_err(ApiError::VmSetInterruptCoalescing)
+                                    .map(|_| ApiResponsePayload::Empty);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmSetQueueSize(queue_size_data, sender) => {
+                                let response = self
+                                    .vm_set_queue_size(queue_size_data.as_ref())
+                                    .map_err(ApiError::VmSetQueueSize)
+                                    .map(|_| ApiResponsePayload::Empty);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmInjectNmi(nmi_data, sender) => {
+                                let response = self
+                                    .vm_inject_nmi(nmi_data.as_ref())
+                                    .map_err(ApiError::VmInjectNmi)
+                                    .map(|_| ApiResponsePayload::Empty);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            #[cfg(feature = "fault_injection")]
+                            ApiRequest::VmInjectFault(fault_data, sender) => {
+                                let response = self
+                                    .vm_inject_fault(fault_data.as_ref())
+                                    .map_err(ApiError::VmInjectFault)
+                                    .map(|_| ApiResponsePayload::Empty);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
                             ApiRequest::VmAddDevice(add_device_data, sender) => {
                                 let response = self
                                     .vm_add_device(add_device_data.as_ref().clone())
@@ -1805,6 +2968,48 @@ impl Vmm {
                                     .map(ApiResponsePayload::VmAction);
                                 sender.send(response).map_err(Error::ApiResponseSend)?;
                             }
+                            ApiRequest::VmAccounting(accounting_data, sender) => {
+                                let response = self
+                                    .vm_accounting(accounting_data.reset)
+                                    .map_err(ApiError::VmInfo)
+                                    .map(ApiResponsePayload::VmAction);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmMetrics(sender) => {
+                                let response = self
+                                    .vm_metrics()
+                                    .map_err(ApiError::VmInfo)
+                                    .map(ApiResponsePayload::VmAction);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmPstore(sender) => {
+                                let response = self
+                                    .vm_pstore()
+                                    .map_err(ApiError::VmPstore)
+                                    .map(ApiResponsePayload::VmAction);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmSecurityStatus(sender) => {
+                                let response = self
+                                    .vm_security_status()
+                                    .map_err(ApiError::VmSecurityStatus)
+                                    .map(ApiResponsePayload::VmAction);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmDeviceTree(sender) => {
+                                let response = self
+                                    .vm_device_tree()
+                                    .map_err(ApiError::VmDeviceTree)
+                                    .map(ApiResponsePayload::VmAction);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmQueues(sender) => {
+                                let response = self
+                                    .vm_queues()
+                                    .map_err(ApiError::VmQueues)
+                                    .map(ApiResponsePayload::VmAction);
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
                             ApiRequest::VmReceiveMigration(receive_migration_data, sender) => {
                                 let response = self
                                     .vm_receive_migration(receive_migration_data.as_ref().clone())
@@ -1827,6 +3032,30 @@ impl Vmm {
 
                                 sender.send(response).map_err(Error::ApiResponseSend)?;
                             }
+                            ApiRequest::VmEnterMaintenance(maintenance_data, sender) => {
+                                let response = self
+                                    .vm_enter_maintenance(maintenance_data.as_ref())
+                                    .map_err(ApiError::VmEnterMaintenance)
+                                    .map(|_| ApiResponsePayload::Empty);
+
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmRenewMaintenance(sender) => {
+                                let response = self
+                                    .vm_renew_maintenance()
+                                    .map_err(ApiError::VmRenewMaintenance)
+                                    .map(|_| ApiResponsePayload::Empty);
+
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
+                            ApiRequest::VmEndMaintenance(sender) => {
+                                let response = self
+                                    .vm_end_maintenance()
+                                    .map_err(ApiError::VmEndMaintenance)
+                                    .map(|_| ApiResponsePayload::Empty);
+
+                                sender.send(response).map_err(Error::ApiResponseSend)?;
+                            }
                         }
                     }
                     #[cfg(feature = "gdb")]
@@ -1868,8 +3097,10 @@ mod unit_tests {
     use super::*;
     use config::{
         CmdlineConfig, ConsoleConfig, ConsoleOutputMode, CpusConfig, HotplugMethod, KernelConfig,
-        MemoryConfig, RngConfig, VmConfig,
+        MemoryConfig, PlatformConfig, RngConfig, VirtioTransportType, VmConfig,
     };
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    use vmm_sys_util::{tempdir::TempDir, tempfile::TempFile};
 
     fn create_dummy_vmm() -> Vmm {
         Vmm::new(
@@ -1880,12 +3111,27 @@ mod unit_tests {
             #[cfg(feature = "gdb")]
             EventFd::new(EFD_NONBLOCK).unwrap(),
             SeccompAction::Allow,
-            hypervisor::new().unwrap(),
+            create_dummy_hypervisor(),
             EventFd::new(EFD_NONBLOCK).unwrap(),
+            SignalFd::new(&HANDLED_SIGNALS).unwrap(),
+            None,
+            LeakCheck::Off,
         )
         .unwrap()
     }
 
+    // The VMs are run on the mock hypervisor wherever it is available, so
+    // that the tests don't need /dev/kvm.
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    fn create_dummy_hypervisor() -> Arc<dyn hypervisor::Hypervisor> {
+        Arc::new(hypervisor::mock::MockHypervisor::new())
+    }
+
+    #[cfg(not(all(feature = "kvm", target_arch = "x86_64")))]
+    fn create_dummy_hypervisor() -> Arc<dyn hypervisor::Hypervisor> {
+        hypervisor::new().unwrap()
+    }
+
     fn create_dummy_vm_config() -> Arc<Mutex<VmConfig>> {
         Arc::new(Mutex::new(VmConfig {
             cpus: CpusConfig {
@@ -1893,9 +3139,19 @@ mod unit_tests {
                 max_vcpus: 1,
                 topology: None,
                 kvm_hyperv: false,
+                nested: false,
                 max_phys_bits: 46,
                 affinity: None,
                 features: config::CpuFeatures::default(),
+                weight: None,
+                quota: None,
+                #[cfg(target_arch = "x86_64")]
+                tsc_khz: None,
+                baseline: None,
+                #[cfg(target_arch = "aarch64")]
+                pmu: true,
+                #[cfg(target_arch = "aarch64")]
+                freeze_counter: true,
             },
             memory: MemoryConfig {
                 size: 536_870_912,
@@ -1908,11 +3164,16 @@ mod unit_tests {
                 hugepage_size: None,
                 prefault: false,
                 zones: None,
+                hugepage_pool: false,
+                hugepage_pool_limit: None,
+                virtio_mem_transport: VirtioTransportType::Pci,
+                sealed: false,
             },
             kernel: Some(KernelConfig {
                 path: PathBuf::from("/path/to/kernel"),
             }),
             initramfs: None,
+            payload_blobs: None,
             cmdline: CmdlineConfig {
                 args: String::from(""),
             },
@@ -1942,16 +3203,63 @@ mod unit_tests {
             iommu: false,
             #[cfg(target_arch = "x86_64")]
             sgx_epc: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt_overlays: None,
+            #[cfg(target_arch = "aarch64")]
+            fdt: None,
             numa: None,
             watchdog: false,
+            watchdog_config: None,
+            pvpanic: false,
+            boot_paused: false,
             #[cfg(feature = "tdx")]
             tdx: None,
+            #[cfg(feature = "sev_snp")]
+            sev_snp: None,
             #[cfg(feature = "gdb")]
             gdb: false,
             platform: None,
+            pstore: None,
+            crashkernel: None,
+            ptp: None,
+            debug_console: None,
+            acpi: None,
+            security: None,
+            deterministic: None,
+            snapshot_schedule: None,
+            irqs: None,
+            doorbells: None,
+            partition_services: None,
+            priority: None,
+            gpu: None,
+            maintenance: None,
+            virtio_features: None,
         }))
     }
 
+    // Configuration of a VM which can be booted on the mock hypervisor, from
+    // a raw firmware, without any console attached to the test.
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    fn create_bootable_vm_config(firmware: &Path) -> Arc<Mutex<VmConfig>> {
+        let config = create_dummy_vm_config();
+        {
+            let mut config = config.lock().unwrap();
+            config.memory.size = 128 << 20;
+            config.kernel = Some(KernelConfig {
+                path: firmware.to_owned(),
+            });
+            config.console.mode = ConsoleOutputMode::Off;
+        }
+        config
+    }
+
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    fn create_dummy_firmware() -> TempFile {
+        let firmware = TempFile::new().unwrap();
+        firmware.as_file().write_all(&[0xf4; 4096]).unwrap();
+        firmware
+    }
+
     #[test]
     fn test_vmm_vm_create() {
         let mut vmm = create_dummy_vmm();
@@ -1964,6 +3272,30 @@ mod unit_tests {
         ));
     }
 
+    #[test]
+    fn test_vmm_vm_create_uuid() {
+        // The configuration without any platform is kept as is.
+        let mut vmm = create_dummy_vmm();
+        let config = create_dummy_vm_config();
+        vmm.vm_create(config.clone()).unwrap();
+        assert!(config.lock().unwrap().platform.is_none());
+
+        // A UUID is generated for the platform when none is given.
+        let mut vmm = create_dummy_vmm();
+        let config = create_dummy_vm_config();
+        config.lock().unwrap().platform = Some(PlatformConfig::default());
+        vmm.vm_create(config.clone()).unwrap();
+        let uuid = config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .unwrap()
+            .uuid
+            .clone();
+        assert!(uuid::Uuid::parse_str(&uuid.unwrap()).is_ok());
+    }
+
     #[test]
     fn test_vmm_vm_cold_add_device() {
         let mut vmm = create_dummy_vmm();
@@ -2332,4 +3664,103 @@ mod unit_tests {
             vsock_config
         );
     }
+
+    #[test]
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    fn test_vmm_vm_boot() {
+        let firmware = create_dummy_firmware();
+        let mut vmm = create_dummy_vmm();
+
+        vmm.vm_create(create_bootable_vm_config(firmware.as_path()))
+            .unwrap();
+        vmm.vm_boot().unwrap();
+        assert_eq!(
+            vmm.vm.as_ref().unwrap().get_state().unwrap(),
+            VmState::Running
+        );
+
+        vmm.vm_pause().unwrap();
+        assert_eq!(
+            vmm.vm.as_ref().unwrap().get_state().unwrap(),
+            VmState::Paused
+        );
+        vmm.vm_resume().unwrap();
+        assert_eq!(
+            vmm.vm.as_ref().unwrap().get_state().unwrap(),
+            VmState::Running
+        );
+
+        vmm.vm_shutdown(VmStateReason::Signal).unwrap();
+        assert!(vmm.vm.is_none());
+
+        let transition = vmm.state_history.transitions().pop().unwrap();
+        assert_eq!(transition.state, VmState::Shutdown);
+        assert_eq!(transition.reason, VmStateReason::Signal);
+    }
+
+    #[test]
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    fn test_vmm_vm_hotplug_disk() {
+        let firmware = create_dummy_firmware();
+        let disk = TempFile::new().unwrap();
+        disk.as_file().set_len(1 << 20).unwrap();
+        let mut vmm = create_dummy_vmm();
+
+        vmm.vm_create(create_bootable_vm_config(firmware.as_path()))
+            .unwrap();
+        vmm.vm_boot().unwrap();
+
+        let disk_config =
+            DiskConfig::parse(&format!("path={},id=disk0", disk.as_path().display())).unwrap();
+        let info = vmm.vm_add_disk(disk_config).unwrap();
+        assert!(info.is_some());
+        assert_eq!(
+            vmm.vm_config
+                .as_ref()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .disks
+                .as_ref()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        vmm.vm_remove_device("disk0".to_string()).unwrap();
+        vmm.vm_shutdown(VmStateReason::Api).unwrap();
+    }
+
+    #[test]
+    #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
+    fn test_vmm_vm_snapshot_restore() {
+        let firmware = create_dummy_firmware();
+        let snapshot_dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let snapshot_url = format!("file://{}", snapshot_dir.as_path().display());
+        let mut vmm = create_dummy_vmm();
+
+        vmm.vm_create(create_bootable_vm_config(firmware.as_path()))
+            .unwrap();
+        vmm.vm_boot().unwrap();
+        vmm.vm_pause().unwrap();
+        vmm.vm_snapshot(&VmSnapshotConfig {
+            destination_url: snapshot_url.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        vmm.vm_shutdown(VmStateReason::Api).unwrap();
+
+        let mut vmm = create_dummy_vmm();
+        vmm.vm_restore(RestoreConfig {
+            source_url: PathBuf::from(snapshot_url),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            vmm.vm.as_ref().unwrap().get_state().unwrap(),
+            VmState::Paused
+        );
+        vmm.vm_resume().unwrap();
+        vmm.vm_shutdown(VmStateReason::Api).unwrap();
+    }
 }
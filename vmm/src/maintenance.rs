@@ -0,0 +1,301 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Maintenance of the host, during which the VM is kept paused for as long
+//! as the orchestrator renews its lease. The VM is resumed, or the VMM shut
+//! down, once the lease expires without being renewed, so that a VM is never
+//! left paused by an orchestrator which went away.
+//!
+//! While in maintenance, a checkpoint header describing the paused VM and its
+//! lease is kept in a file, for the orchestrator to find the VMs left in
+//! maintenance after its own restart.
+
+use crate::config::MaintenanceExpiryAction;
+use crate::vm::VmState;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use vmm_sys_util::timerfd::TimerFd;
+
+const MAINTENANCE_CHECKPOINT_VERSION: u32 = 1;
+
+/// Header of the checkpoint of a VM in maintenance, also reported by
+/// vm.info. The times are in seconds since the UNIX epoch.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct MaintenanceCheckpoint {
+    pub version: u32,
+    pub uuid: Option<String>,
+    pub vmm_version: String,
+    pub pid: u32,
+    /// State of the VM when entering maintenance, restored when resuming it.
+    pub state: VmState,
+    /// Seconds the VM stays in maintenance without the lease being renewed.
+    pub lease: u64,
+    pub on_expiry: MaintenanceExpiryAction,
+    pub entered_at: u64,
+    pub renewed_at: u64,
+    pub expires_at: u64,
+    pub renewals: u64,
+}
+
+impl MaintenanceCheckpoint {
+    pub fn new(
+        uuid: Option<String>,
+        vmm_version: String,
+        state: VmState,
+        lease: u64,
+        on_expiry: MaintenanceExpiryAction,
+    ) -> Self {
+        let now = now();
+        MaintenanceCheckpoint {
+            version: MAINTENANCE_CHECKPOINT_VERSION,
+            uuid,
+            vmm_version,
+            pid: std::process::id(),
+            state,
+            lease,
+            on_expiry,
+            entered_at: now,
+            renewed_at: now,
+            expires_at: now + lease,
+            renewals: 0,
+        }
+    }
+}
+
+struct ActiveMaintenance {
+    checkpoint: MaintenanceCheckpoint,
+    path: Option<PathBuf>,
+}
+
+pub struct Maintenance {
+    timer: TimerFd,
+    active: Option<ActiveMaintenance>,
+}
+
+impl Maintenance {
+    pub fn new() -> io::Result<Self> {
+        let timer = TimerFd::new()?;
+        // The lease may be renewed between its expiration being reported and
+        // the timer being read, in which case reading it must not block.
+        // SAFETY: FFI call with a valid file descriptor.
+        if unsafe { libc::fcntl(timer.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Maintenance {
+            timer,
+            active: None,
+        })
+    }
+
+    pub fn checkpoint(&self) -> Option<&MaintenanceCheckpoint> {
+        self.active.as_ref().map(|active| &active.checkpoint)
+    }
+
+    /// Start the lease, writing the checkpoint header if a path is given.
+    /// The header is never written over an existing file, the ones left by
+    /// a previous maintenance having to be removed first.
+    pub fn enter(
+        &mut self,
+        checkpoint: MaintenanceCheckpoint,
+        path: Option<PathBuf>,
+    ) -> io::Result<()> {
+        if let Some(path) = &path {
+            write_checkpoint(path, &checkpoint, false)?;
+        }
+        if let Err(e) = self
+            .timer
+            .reset(Duration::from_secs(checkpoint.lease), None)
+        {
+            if let Some(path) = &path {
+                fs::remove_file(path).ok();
+            }
+            return Err(e);
+        }
+
+        self.active = Some(ActiveMaintenance { checkpoint, path });
+        Ok(())
+    }
+
+    /// Extend the lease by its duration from now.
+    pub fn renew(&mut self) -> io::Result<&MaintenanceCheckpoint> {
+        let active = match self.active.as_mut() {
+            Some(active) => active,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "the VM is not in maintenance",
+                ))
+            }
+        };
+
+        let checkpoint = &mut active.checkpoint;
+        self.timer
+            .reset(Duration::from_secs(checkpoint.lease), None)?;
+        checkpoint.renewed_at = now();
+        checkpoint.expires_at = checkpoint.renewed_at + checkpoint.lease;
+        checkpoint.renewals += 1;
+        // The lease is renewed whether or not the header could be updated, the
+        // header only being informative.
+        if let Some(path) = &active.path {
+            if let Err(e) = write_checkpoint(path, checkpoint, true) {
+                warn!("Error updating the maintenance checkpoint header: {}", e);
+            }
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// Stop the lease and remove the checkpoint header, returning the
+    /// header of the maintenance left, if any.
+    pub fn leave(&mut self) -> Option<MaintenanceCheckpoint> {
+        let active = self.active.take()?;
+        if let Err(e) = self.timer.clear() {
+            error!("Error stopping the maintenance lease: {}", e);
+        }
+        if let Some(path) = &active.path {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Error removing the maintenance checkpoint header: {}", e);
+            }
+        }
+
+        Some(active.checkpoint)
+    }
+
+    /// Consume the expiration of the lease, if it still expired.
+    pub fn expired(&mut self) -> io::Result<bool> {
+        match self.timer.wait() {
+            Ok(_) => Ok(self.active.is_some()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl AsRawFd for Maintenance {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// The header is written to a new file next to its destination, and either
+// renamed over the header it replaces or linked to a destination which
+// mustn't exist yet, so that the orchestrator never reads a partial one and
+// no other file is ever overwritten.
+fn write_checkpoint(
+    path: &Path,
+    checkpoint: &MaintenanceCheckpoint,
+    replace: bool,
+) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let data = serde_json::to_vec(checkpoint).map_err(io::Error::from)?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
+    let result = file.write_all(&data).and_then(|_| {
+        if replace {
+            fs::rename(&tmp_path, path)
+        } else {
+            fs::hard_link(&tmp_path, path)
+        }
+    });
+    if !replace || result.is_err() {
+        fs::remove_file(&tmp_path).ok();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_maintenance() {
+        let directory = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let path = directory.as_path().join("maintenance.json");
+        let mut maintenance = Maintenance::new().unwrap();
+        assert!(maintenance.checkpoint().is_none());
+        assert!(maintenance.renew().is_err());
+        assert!(!maintenance.expired().unwrap());
+
+        let checkpoint = MaintenanceCheckpoint::new(
+            Some("uuid".to_owned()),
+            "v1".to_owned(),
+            VmState::Running,
+            60,
+            MaintenanceExpiryAction::Resume,
+        );
+        assert_eq!(checkpoint.expires_at, checkpoint.entered_at + 60);
+        maintenance
+            .enter(checkpoint.clone(), Some(path.clone()))
+            .unwrap();
+        let written: MaintenanceCheckpoint =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written, checkpoint);
+
+        let renewed = maintenance.renew().unwrap().clone();
+        assert_eq!(renewed.renewals, 1);
+        assert_eq!(renewed.expires_at, renewed.renewed_at + 60);
+        let written: MaintenanceCheckpoint =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written, renewed);
+        assert!(!maintenance.expired().unwrap());
+
+        assert_eq!(maintenance.leave(), Some(renewed));
+        assert!(!path.exists());
+        assert!(maintenance.checkpoint().is_none());
+        assert!(maintenance.leave().is_none());
+
+        // An existing file is never overwritten.
+        fs::write(&path, b"").unwrap();
+        assert!(maintenance.enter(checkpoint, Some(path.clone())).is_err());
+        assert!(fs::read(&path).unwrap().is_empty());
+        assert!(maintenance.checkpoint().is_none());
+    }
+
+    #[test]
+    fn test_maintenance_expiry() {
+        let mut maintenance = Maintenance::new().unwrap();
+        let checkpoint = MaintenanceCheckpoint::new(
+            None,
+            "v1".to_owned(),
+            VmState::Paused,
+            1,
+            MaintenanceExpiryAction::Shutdown,
+        );
+        maintenance.enter(checkpoint.clone(), None).unwrap();
+        assert!(!maintenance.expired().unwrap());
+
+        let mut expired = false;
+        for _ in 0..30 {
+            std::thread::sleep(Duration::from_millis(100));
+            if maintenance.expired().unwrap() {
+                expired = true;
+                break;
+            }
+        }
+        assert!(expired);
+        // The expiration is only reported once.
+        assert!(!maintenance.expired().unwrap());
+        assert_eq!(maintenance.leave(), Some(checkpoint));
+    }
+}
@@ -4,12 +4,17 @@
 //
 #[cfg(target_arch = "x86_64")]
 use crate::config::SgxEpcConfig;
-use crate::config::{HotplugMethod, MemoryConfig, MemoryZoneConfig};
+use crate::config::{HotplugMethod, MemoryConfig, MemoryZoneConfig, PstoreConfig, SnapshotFormat};
 #[cfg(feature = "guest_debug")]
-use crate::coredump::{CoredumpMemoryRegion, CoredumpMemoryRegions};
-#[cfg(feature = "guest_debug")]
-use crate::coredump::{DumpState, GuestDebuggableError};
-use crate::migration::url_to_path;
+use crate::coredump::{
+    exclude_memory_ranges, write_guest_memory, DumpState, GuestDebuggableError,
+    COREDUMP_MAX_LOAD_SEGMENTS, COREDUMP_MIN_EXCLUDED_SIZE,
+};
+use crate::hugepages::{self, HugepagePool};
+use crate::migration::{
+    recv_memory_manifest, url_to_path, MemoryManifest, MemoryRangeManifest, Sha256Stream,
+    SNAPSHOT_MEMORY_MANIFEST_FILE,
+};
 use crate::MEMORY_MANAGER_SNAPSHOT_ID;
 use crate::{GuestMemoryMmap, GuestRegionMmap};
 use acpi_tables::{aml, aml::Aml};
@@ -19,19 +24,16 @@ use arch::x86_64::{SgxEpcRegion, SgxEpcSection};
 use arch::{layout, RegionType};
 #[cfg(target_arch = "x86_64")]
 use devices::ioapic;
-#[cfg(target_arch = "x86_64")]
 use libc::{MAP_NORESERVE, MAP_POPULATE, MAP_SHARED, PROT_READ, PROT_WRITE};
 use serde::{Deserialize, Serialize};
-#[cfg(feature = "guest_debug")]
-use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::ffi;
 use std::fs::{File, OpenOptions};
-use std::io;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::ops::Deref;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::{Arc, Barrier, Mutex};
 use versionize::{VersionMap, Versionize, VersionizeResult};
@@ -58,6 +60,8 @@ const DEFAULT_MEMORY_ZONE: &str = "mem0";
 
 const SNAPSHOT_FILENAME: &str = "memory-ranges";
 
+const PSTORE_REGION_ALIGNMENT: u64 = 0x20_0000;
+
 #[cfg(target_arch = "x86_64")]
 const X86_64_IRQ_BASE: u32 = 5;
 
@@ -119,6 +123,7 @@ impl VirtioMemZone {
 pub struct MemoryZone {
     regions: Vec<Arc<GuestRegionMmap>>,
     virtio_mem_zone: Option<VirtioMemZone>,
+    template: bool,
 }
 
 impl MemoryZone {
@@ -149,6 +154,27 @@ struct ArchMemRegion {
     r_type: RegionType,
 }
 
+struct PstoreRegion {
+    start: GuestAddress,
+    size: u64,
+    // Keeps the backing file mapped for as long as the guest can access it.
+    _mmap_region: MmapRegion,
+}
+
+// Snapshot the next incremental snapshot is based on.
+struct SnapshotBase {
+    // Canonical directory of the base snapshot, unset until it has been
+    // written successfully.
+    directory: Option<PathBuf>,
+    // Memory ranges of the base snapshot, the guest memory layout must be
+    // the same for the pages dirtied since to be enough.
+    memory_ranges: MemoryRangeTable,
+    // Pages dirtied since the base snapshot, accumulated over all the reads
+    // of the dirty log of the VM, e.g. by the snapshot scheduler or a live
+    // migration.
+    dirty: MemoryRangeTable,
+}
+
 pub struct MemoryManager {
     boot_guest_memory: GuestMemoryMmap,
     guest_memory: GuestMemoryAtomic<GuestMemoryMmap>,
@@ -169,10 +195,18 @@ pub struct MemoryManager {
     hugepages: bool,
     hugepage_size: Option<u64>,
     prefault: bool,
+    sealed: bool,
+    hugepage_pool: Option<HugepagePool>,
     #[cfg(target_arch = "x86_64")]
     sgx_epc_region: Option<SgxEpcRegion>,
+    pstore_region: Option<PstoreRegion>,
     user_provided_zones: bool,
     snapshot_memory_ranges: MemoryRangeTable,
+    snapshot_format: SnapshotFormat,
+    snapshot_incremental: bool,
+    snapshot_base: Option<SnapshotBase>,
+    // Base of the snapshot being taken, recorded in its memory manifest.
+    snapshot_parent: Option<PathBuf>,
     memory_zones: MemoryZones,
     log_dirty: bool, // Enable dirty logging for created RAM regions
     arch_mem_regions: Vec<ArchMemRegion>,
@@ -195,6 +229,9 @@ pub enum Error {
     /// Failed to set shared file length.
     SharedFileSetLen(io::Error),
 
+    /// Failed to seal the memory file.
+    SealMemoryFile(io::Error),
+
     /// Mmap backed guest memory error
     GuestMemory(MmapError),
 
@@ -262,7 +299,6 @@ pub enum Error {
     SgxEnableProvisioning(hypervisor::HypervisorVmError),
 
     /// Failed creating a new MmapRegion instance.
-    #[cfg(target_arch = "x86_64")]
     NewMmapRegion(vm_memory::mmap::MmapRegionError),
 
     /// No memory zones found.
@@ -312,8 +348,54 @@ pub enum Error {
     // Error copying snapshot into region
     SnapshotCopy(GuestMemoryError),
 
+    /// Error reading the snapshot file
+    SnapshotRead(io::Error),
+
+    /// The snapshot memory manifest doesn't match the saved memory ranges
+    SnapshotManifestMismatch,
+
+    /// The content of a snapshot memory range doesn't match its digest
+    SnapshotChecksumMismatch(u64),
+
+    /// Error mapping a snapshot memory range into the guest memory
+    SnapshotMap(io::Error),
+
+    /// Restoring the memory on demand requires an uncompressed snapshot
+    SnapshotOnDemandCompressed,
+
+    /// Restoring the memory on demand requires a private guest memory
+    SnapshotOnDemandShared,
+
+    /// The memory restored on demand can't be verified
+    SnapshotOnDemandVerify,
+
+    /// Snapshot memory range not page aligned or spanning multiple regions
+    SnapshotOnDemandRange(u64),
+
+    /// Restoring the memory on demand isn't supported from an incremental
+    /// snapshot
+    SnapshotOnDemandIncremental,
+
+    /// Missing or looping base snapshot of an incremental snapshot
+    SnapshotBase(PathBuf),
+
+    /// The template file is smaller than the memory zone
+    InvalidTemplateFileSize(PathBuf),
+
+    /// Error writing the memory templates
+    MemoryTemplate(MigratableError),
+
     /// Failed to allocate MMIO address
     AllocateMmioAddress,
+
+    /// Failed opening the pstore backing file
+    PstoreFileOpen(io::Error),
+
+    /// Failed setting the pstore backing file length
+    PstoreFileSetLen(io::Error),
+
+    /// Failed growing the host hugepage pool
+    HugepagePool(hugepages::Error),
 }
 
 const ENABLE_FLAG: usize = 0;
@@ -438,7 +520,13 @@ impl MemoryManager {
         let mut memory_zones = HashMap::new();
 
         // Add zone id to the list of memory zones.
-        memory_zones.insert(zone.id.clone(), MemoryZone::default());
+        memory_zones.insert(
+            zone.id.clone(),
+            MemoryZone {
+                template: zone.template,
+                ..Default::default()
+            },
+        );
 
         for ram_region in ram_regions.iter() {
             let mut ram_region_offset = 0;
@@ -485,6 +573,8 @@ impl MemoryManager {
                     zone.hugepages,
                     zone.hugepage_size,
                     zone.host_numa_node,
+                    zone.template,
+                    zone.sealed,
                     None,
                 )?;
 
@@ -516,7 +606,13 @@ impl MemoryManager {
                         );
                         return Err(Error::DuplicateZoneId);
                     }
-                    memory_zones.insert(zone.id.clone(), MemoryZone::default());
+                    memory_zones.insert(
+                        zone.id.clone(),
+                        MemoryZone {
+                            template: zone.template,
+                            ..Default::default()
+                        },
+                    );
                 }
 
                 if ram_region_consumed {
@@ -543,7 +639,13 @@ impl MemoryManager {
         let mut memory_zones = HashMap::new();
 
         for zone_config in zones_config {
-            memory_zones.insert(zone_config.id.clone(), MemoryZone::default());
+            memory_zones.insert(
+                zone_config.id.clone(),
+                MemoryZone {
+                    template: zone_config.template,
+                    ..Default::default()
+                },
+            );
         }
 
         for guest_ram_mapping in guest_ram_mappings {
@@ -562,6 +664,8 @@ impl MemoryManager {
                         zone_config.hugepages,
                         zone_config.hugepage_size,
                         zone_config.host_numa_node,
+                        zone_config.template,
+                        zone_config.sealed,
                         existing_memory_files.remove(&guest_ram_mapping.slot),
                     )?;
                     memory_regions.push(Arc::clone(&region));
@@ -590,10 +694,197 @@ impl MemoryManager {
         Ok((memory_regions, memory_zones))
     }
 
+    fn read_saved_range<R: Read>(
+        guest_memory: &GuestMemoryMmap,
+        range: &MemoryRange,
+        reader: &mut R,
+        template: bool,
+    ) -> Result<(), Error> {
+        if template {
+            return Self::merge_saved_range(guest_memory, range, reader);
+        }
+
+        let mut offset: u64 = 0;
+        // Here we are manually handling the retry in case we can't write
+        // the whole region at once because we can't use the implementation
+        // from vm-memory::GuestMemory of read_exact_from() as it is not
+        // following the correct behavior. For more info about this issue
+        // see: https://github.com/rust-vmm/vm-memory/issues/174
+        loop {
+            let bytes_read = guest_memory
+                .read_from(
+                    GuestAddress(range.gpa + offset),
+                    reader,
+                    (range.length - offset) as usize,
+                )
+                .map_err(Error::SnapshotCopy)?;
+            if bytes_read == 0 {
+                // The snapshot file is shorter than the saved regions
+                return Err(Error::SnapshotCopy(GuestMemoryError::PartialBuffer {
+                    expected: range.length as usize,
+                    completed: offset as usize,
+                }));
+            }
+            offset += bytes_read as u64;
+
+            if offset == range.length {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Only writes the pages differing from the current content of the guest
+    // memory, so that the pages of a template zone left unmodified since the
+    // template was produced keep being shared with the template file.
+    fn merge_saved_range<R: Read>(
+        guest_memory: &GuestMemoryMmap,
+        range: &MemoryRange,
+        reader: &mut R,
+    ) -> Result<(), Error> {
+        let page_size = arch::PAGE_SIZE as u64;
+        let mut saved = vec![0u8; page_size as usize];
+        let mut current = vec![0u8; page_size as usize];
+        let mut offset: u64 = 0;
+        while offset < range.length {
+            let len = std::cmp::min(page_size, range.length - offset) as usize;
+            let addr = GuestAddress(range.gpa + offset);
+            reader
+                .read_exact(&mut saved[..len])
+                .map_err(Error::SnapshotRead)?;
+            guest_memory
+                .read_slice(&mut current[..len], addr)
+                .map_err(Error::SnapshotCopy)?;
+            if saved[..len] != current[..len] {
+                guest_memory
+                    .write_slice(&saved[..len], addr)
+                    .map_err(Error::SnapshotCopy)?;
+            }
+            offset += len as u64;
+        }
+
+        Ok(())
+    }
+
+    // Whether the range belongs to the boot memory of a template zone.
+    fn is_template_range(&self, range: &MemoryRange) -> bool {
+        self.memory_zones
+            .values()
+            .filter(|zone| zone.template)
+            .flat_map(|zone| zone.regions.iter())
+            .any(|region| {
+                let start = region.start_addr().raw_value();
+                range.gpa >= start && range.gpa + range.length <= start + region.len()
+            })
+    }
+
+    fn write_snapshot_range<W: Write>(
+        guest_memory: &GuestMemoryMmap,
+        range: &MemoryRange,
+        writer: &mut W,
+    ) -> result::Result<(), MigratableError> {
+        let mut offset: u64 = 0;
+        // Here we are manually handling the retry in case we can't read
+        // the whole region at once because we can't use the implementation
+        // from vm-memory::GuestMemory of write_all_to() as it is not
+        // following the correct behavior. For more info about this issue
+        // see: https://github.com/rust-vmm/vm-memory/issues/174
+        loop {
+            let bytes_written = guest_memory
+                .write_to(
+                    GuestAddress(range.gpa + offset),
+                    writer,
+                    (range.length - offset) as usize,
+                )
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+            offset += bytes_written as u64;
+
+            if offset == range.length {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes the ranges of the guest memory to the snapshot memory file of
+    // `directory`, along with the manifest describing them.
+    fn write_saved_regions(
+        guest_memory: &GuestMemoryMmap,
+        directory: &Path,
+        ranges: &MemoryRangeTable,
+        format: SnapshotFormat,
+        base: Option<PathBuf>,
+    ) -> result::Result<(), MigratableError> {
+        // Create the snapshot file for the entire memory
+        let mut memory_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(directory.join(SNAPSHOT_FILENAME))
+            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+        let mut manifest_ranges = Vec::new();
+        for range in ranges.regions() {
+            let start = memory_file
+                .stream_position()
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+            let sha256 = match format {
+                SnapshotFormat::Raw => {
+                    let mut writer = Sha256Stream::new(&mut memory_file);
+                    Self::write_snapshot_range(guest_memory, range, &mut writer)?;
+                    writer.finalize().1
+                }
+                SnapshotFormat::Zstd => {
+                    let encoder = zstd::stream::write::Encoder::new(&mut memory_file, 0)
+                        .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+                    let mut writer = Sha256Stream::new(encoder);
+                    Self::write_snapshot_range(guest_memory, range, &mut writer)?;
+                    let (encoder, sha256) = writer.finalize();
+                    encoder
+                        .finish()
+                        .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+                    sha256
+                }
+            };
+
+            let end = memory_file
+                .stream_position()
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+            manifest_ranges.push(MemoryRangeManifest {
+                gpa: range.gpa,
+                length: range.length,
+                stored_length: end - start,
+                sha256,
+            });
+        }
+
+        let manifest_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(directory.join(SNAPSHOT_MEMORY_MANIFEST_FILE))
+            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+
+        serde_json::to_writer(
+            manifest_file,
+            &MemoryManifest {
+                format,
+                ranges: manifest_ranges,
+                base,
+            },
+        )
+        .map_err(|e| MigratableError::MigrateSend(e.into()))
+    }
+
     fn fill_saved_regions(
         &mut self,
         file_path: PathBuf,
         saved_regions: MemoryRangeTable,
+        manifest: Option<MemoryManifest>,
+        verify: bool,
     ) -> Result<(), Error> {
         if saved_regions.is_empty() {
             return Ok(());
@@ -606,27 +897,210 @@ impl MemoryManager {
             .map_err(Error::SnapshotOpen)?;
 
         let guest_memory = self.guest_memory.memory();
-        for range in saved_regions.regions() {
-            let mut offset: u64 = 0;
-            // Here we are manually handling the retry in case we can't write
-            // the whole region at once because we can't use the implementation
-            // from vm-memory::GuestMemory of read_exact_from() as it is not
-            // following the correct behavior. For more info about this issue
-            // see: https://github.com/rust-vmm/vm-memory/issues/174
-            loop {
-                let bytes_read = guest_memory
-                    .read_from(
-                        GuestAddress(range.gpa + offset),
-                        &mut memory_file,
-                        (range.length - offset) as usize,
-                    )
-                    .map_err(Error::SnapshotCopy)?;
-                offset += bytes_read as u64;
 
-                if offset == range.length {
-                    break;
+        let manifest = if let Some(manifest) = manifest {
+            manifest
+        } else {
+            // No manifest, the memory ranges are stored as-is one after
+            // the other.
+            for range in saved_regions.regions() {
+                let template = self.is_template_range(range);
+                Self::read_saved_range(&guest_memory, range, &mut memory_file, template)?;
+            }
+            return Ok(());
+        };
+
+        Self::read_saved_regions(
+            &guest_memory,
+            &mut memory_file,
+            &saved_regions,
+            &manifest,
+            verify,
+            |range| self.is_template_range(range),
+        )
+    }
+
+    // Reads the saved ranges described by the manifest from the snapshot
+    // memory file into the guest memory.
+    fn read_saved_regions<F: Fn(&MemoryRange) -> bool>(
+        guest_memory: &GuestMemoryMmap,
+        memory_file: &mut File,
+        saved_regions: &MemoryRangeTable,
+        manifest: &MemoryManifest,
+        verify: bool,
+        is_template_range: F,
+    ) -> Result<(), Error> {
+        if manifest.ranges.len() != saved_regions.regions().len()
+            || manifest
+                .ranges
+                .iter()
+                .zip(saved_regions.regions())
+                .any(|(m, r)| m.gpa != r.gpa || m.length != r.length)
+        {
+            return Err(Error::SnapshotManifestMismatch);
+        }
+
+        let mut file_offset: u64 = 0;
+        for (range, entry) in saved_regions.regions().iter().zip(manifest.ranges.iter()) {
+            memory_file
+                .seek(SeekFrom::Start(file_offset))
+                .map_err(Error::SnapshotRead)?;
+            let stored_range = (&mut *memory_file).take(entry.stored_length);
+            let template = is_template_range(range);
+
+            let digest = match manifest.format {
+                SnapshotFormat::Raw => {
+                    let mut reader = Sha256Stream::new(stored_range);
+                    Self::read_saved_range(guest_memory, range, &mut reader, template)?;
+                    reader.finalize().1
+                }
+                SnapshotFormat::Zstd => {
+                    let decoder = zstd::stream::read::Decoder::new(stored_range)
+                        .map_err(Error::SnapshotRead)?;
+                    let mut reader = Sha256Stream::new(decoder);
+                    Self::read_saved_range(guest_memory, range, &mut reader, template)?;
+                    reader.finalize().1
                 }
+            };
+
+            if verify && digest != entry.sha256 {
+                return Err(Error::SnapshotChecksumMismatch(range.gpa));
             }
+
+            file_offset += entry.stored_length;
+        }
+
+        Ok(())
+    }
+
+    // Maps the saved ranges from the snapshot file over the guest memory, so
+    // that the pages are read from the file when first accessed rather than
+    // copied upfront. The mappings are private: the pages written by the
+    // guest are copied, leaving the snapshot untouched. The file can't be
+    // modified while the VM is running though.
+    fn map_saved_regions(
+        &mut self,
+        config: &MemoryConfig,
+        file_path: PathBuf,
+        saved_regions: MemoryRangeTable,
+        manifest: Option<MemoryManifest>,
+        prefault: bool,
+    ) -> Result<(), Error> {
+        if saved_regions.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(manifest) = manifest {
+            if manifest.format != SnapshotFormat::Raw {
+                return Err(Error::SnapshotOnDemandCompressed);
+            }
+            if manifest.ranges.len() != saved_regions.regions().len()
+                || manifest
+                    .ranges
+                    .iter()
+                    .zip(saved_regions.regions())
+                    .any(|(m, r)| m.gpa != r.gpa || m.length != r.length)
+            {
+                return Err(Error::SnapshotManifestMismatch);
+            }
+        }
+
+        let memory_file = OpenOptions::new()
+            .read(true)
+            .open(file_path)
+            .map_err(Error::SnapshotOpen)?;
+
+        // The host NUMA node the memory zone of each range is bound to.
+        let host_numa_node = |gpa: u64| {
+            let zone_id = &self
+                .guest_ram_mappings
+                .iter()
+                .find(|m| gpa >= m.gpa && gpa < m.gpa + m.size)?
+                .zone_id;
+            config
+                .zones
+                .iter()
+                .flatten()
+                .find(|zone| zone.id == *zone_id)?
+                .host_numa_node
+        };
+
+        Self::map_saved_ranges(
+            &self.guest_memory.memory(),
+            &memory_file,
+            &saved_regions,
+            prefault,
+            self.mergeable,
+            host_numa_node,
+        )
+    }
+
+    // Maps the saved ranges of the memory file over the guest memory. The
+    // NUMA policy and the KSM advice only apply to the mappings they were set
+    // on, and are thus set again on the new mappings.
+    fn map_saved_ranges(
+        guest_memory: &GuestMemoryMmap,
+        memory_file: &File,
+        saved_regions: &MemoryRangeTable,
+        prefault: bool,
+        mergeable: bool,
+        host_numa_node: impl Fn(u64) -> Option<u32>,
+    ) -> Result<(), Error> {
+        let file_size = memory_file.metadata().map_err(Error::SnapshotRead)?.len();
+
+        let page_size = arch::PAGE_SIZE as u64;
+        let mut flags = libc::MAP_PRIVATE | libc::MAP_FIXED | MAP_NORESERVE;
+        if prefault {
+            flags |= MAP_POPULATE;
+        }
+
+        // The ranges are stored one after the other.
+        let mut file_offset: u64 = 0;
+        for range in saved_regions.regions() {
+            // Accessing a page beyond the end of the file would kill the VMM.
+            if file_offset + range.length > file_size {
+                return Err(Error::SnapshotCopy(GuestMemoryError::PartialBuffer {
+                    expected: range.length as usize,
+                    completed: file_size.saturating_sub(file_offset) as usize,
+                }));
+            }
+
+            let region = guest_memory
+                .find_region(GuestAddress(range.gpa))
+                .ok_or(Error::SnapshotOnDemandRange(range.gpa))?;
+            let region_offset = range.gpa - region.start_addr().raw_value();
+            if region_offset + range.length > region.len()
+                || range.gpa % page_size != 0
+                || range.length % page_size != 0
+                || file_offset % page_size != 0
+            {
+                return Err(Error::SnapshotOnDemandRange(range.gpa));
+            }
+
+            // SAFETY: the range is within the mapping of the guest memory
+            // region, which is replaced by the content of the file.
+            let addr = unsafe {
+                libc::mmap(
+                    region.as_ptr().add(region_offset as usize) as *mut libc::c_void,
+                    range.length as usize,
+                    PROT_READ | PROT_WRITE,
+                    flags,
+                    memory_file.as_raw_fd(),
+                    file_offset as libc::off_t,
+                )
+            };
+            if addr == libc::MAP_FAILED {
+                return Err(Error::SnapshotMap(io::Error::last_os_error()));
+            }
+
+            if let Some(node) = host_numa_node(range.gpa) {
+                Self::set_numa_policy(addr as *mut u8, range.length, node)?;
+            }
+            if mergeable {
+                Self::mark_mergeable(addr as u64, range.length);
+            }
+
+            file_offset += range.length;
         }
 
         Ok(())
@@ -690,6 +1164,8 @@ impl MemoryManager {
                 hotplug_size: config.hotplug_size,
                 hotplugged_size: config.hotplugged_size,
                 prefault: config.prefault,
+                template: false,
+                sealed: config.sealed,
             }];
 
             Ok((config.size, zones, allow_mem_hotplug))
@@ -835,6 +1311,7 @@ impl MemoryManager {
         restore_data: Option<&MemoryManagerSnapshotData>,
         existing_memory_files: Option<HashMap<u32, File>>,
         #[cfg(target_arch = "x86_64")] sgx_epc_config: Option<Vec<SgxEpcConfig>>,
+        pstore_config: Option<PstoreConfig>,
     ) -> Result<Arc<Mutex<MemoryManager>>, Error> {
         let user_provided_zones = config.size == 0;
 
@@ -850,6 +1327,18 @@ impl MemoryManager {
         let (ram_size, zones, allow_mem_hotplug) =
             Self::validate_memory_config(config, user_provided_zones)?;
 
+        // The hugepages are added to the pool before the guest memory is
+        // mapped, for the memory plugged in when the VM is created.
+        let hugepage_pool = if config.hugepage_pool {
+            let mut hugepage_pool = HugepagePool::new(config.hugepage_pool_limit);
+            for zone in zones.iter().filter(|zone| zone.hugepages) {
+                hugepage_pool.add_zone(zone).map_err(Error::HugepagePool)?;
+            }
+            Some(hugepage_pool)
+        } else {
+            None
+        };
+
         let (
             start_of_device_area,
             boot_ram,
@@ -953,6 +1442,8 @@ impl MemoryManager {
                                 zone.hugepages,
                                 zone.hugepage_size,
                                 zone.host_numa_node,
+                                false,
+                                zone.sealed,
                                 None,
                             )?;
 
@@ -1001,17 +1492,26 @@ impl MemoryManager {
 
         let guest_memory = GuestMemoryAtomic::new(guest_memory);
 
-        // Both MMIO and PIO address spaces start at address 0.
+        // Both MMIO and PIO address spaces start at address 0. On AArch64,
+        // the PIO address space is the one of the PCI I/O window.
         let allocator = Arc::new(Mutex::new(
             SystemAllocator::new(
                 #[cfg(target_arch = "x86_64")]
                 {
                     GuestAddress(0)
                 },
+                #[cfg(target_arch = "aarch64")]
+                {
+                    layout::PCI_IO_PORT_START
+                },
                 #[cfg(target_arch = "x86_64")]
                 {
                     1 << 16
                 },
+                #[cfg(target_arch = "aarch64")]
+                {
+                    layout::PCI_IO_PORT_SIZE
+                },
                 start_of_platform_device_area,
                 PLATFORM_DEVICE_AREA_SIZE,
                 layout::MEM_32BIT_DEVICES_START,
@@ -1021,6 +1521,10 @@ impl MemoryManager {
                     X86_64_IRQ_BASE,
                     ioapic::NUM_IOAPIC_PINS as u32 - X86_64_IRQ_BASE,
                 )],
+                #[cfg(target_arch = "aarch64")]
+                layout::MAPPED_IO_START,
+                #[cfg(target_arch = "aarch64")]
+                layout::LEGACY_MAPPED_IO_SIZE,
             )
             .ok_or(Error::CreateSystemAllocator)?,
         ));
@@ -1070,10 +1574,17 @@ impl MemoryManager {
             hugepages: config.hugepages,
             hugepage_size: config.hugepage_size,
             prefault: config.prefault,
+            sealed: config.sealed,
+            hugepage_pool,
             #[cfg(target_arch = "x86_64")]
             sgx_epc_region: None,
+            pstore_region: None,
             user_provided_zones,
             snapshot_memory_ranges: MemoryRangeTable::default(),
+            snapshot_format: SnapshotFormat::default(),
+            snapshot_incremental: false,
+            snapshot_base: None,
+            snapshot_parent: None,
             memory_zones,
             guest_ram_mappings: Vec::new(),
             acpi_address,
@@ -1089,6 +1600,10 @@ impl MemoryManager {
             memory_manager.setup_sgx(sgx_epc_config)?;
         }
 
+        if let Some(pstore_config) = pstore_config {
+            memory_manager.setup_pstore(&pstore_config, restore_data.is_some())?;
+        }
+
         Ok(Arc::new(Mutex::new(memory_manager)))
     }
 
@@ -1098,20 +1613,48 @@ impl MemoryManager {
         config: &MemoryConfig,
         source_url: Option<&str>,
         prefault: bool,
+        verify: bool,
+        on_demand: bool,
         phys_bits: u8,
+        pstore_config: Option<PstoreConfig>,
     ) -> Result<Arc<Mutex<MemoryManager>>, Error> {
         if let Some(source_url) = source_url {
             let mut memory_file_path = url_to_path(source_url).map_err(Error::Restore)?;
             memory_file_path.push(String::from(SNAPSHOT_FILENAME));
+            let manifest = recv_memory_manifest(source_url).map_err(Error::Restore)?;
+            let source_directory = url_to_path(source_url).map_err(Error::Restore)?;
+            let bases = Self::snapshot_bases(&source_directory, manifest.as_ref())?;
 
             let mem_snapshot: MemoryManagerSnapshotData = snapshot
                 .to_versioned_state(MEMORY_MANAGER_SNAPSHOT_ID)
                 .map_err(Error::Restore)?;
 
+            if on_demand {
+                // The pages of the snapshot are read from the file, which can
+                // only be mapped over a private anonymous memory.
+                if config.shared
+                    || config.hugepages
+                    || config
+                        .zones
+                        .iter()
+                        .flatten()
+                        .any(|zone| zone.shared || zone.hugepages || zone.file.is_some())
+                {
+                    return Err(Error::SnapshotOnDemandShared);
+                }
+                if verify {
+                    return Err(Error::SnapshotOnDemandVerify);
+                }
+                if !bases.is_empty() {
+                    return Err(Error::SnapshotOnDemandIncremental);
+                }
+            }
+
             let mm = MemoryManager::new(
                 vm,
                 config,
-                Some(prefault),
+                // The guest memory is populated from the snapshot file.
+                Some(prefault && !on_demand),
                 phys_bits,
                 #[cfg(feature = "tdx")]
                 false,
@@ -1119,11 +1662,46 @@ impl MemoryManager {
                 None,
                 #[cfg(target_arch = "x86_64")]
                 None,
+                pstore_config,
             )?;
 
-            mm.lock()
-                .unwrap()
-                .fill_saved_regions(memory_file_path, mem_snapshot.memory_ranges)?;
+            if on_demand {
+                mm.lock().unwrap().map_saved_regions(
+                    config,
+                    memory_file_path,
+                    mem_snapshot.memory_ranges,
+                    manifest,
+                    prefault,
+                )?;
+            } else {
+                let mut mm_locked = mm.lock().unwrap();
+
+                // The chain of snapshots is restored from the oldest one,
+                // each snapshot overwriting the pages dirtied since its base.
+                for (base_directory, base_manifest) in bases {
+                    let base_file_path = base_directory.join(SNAPSHOT_FILENAME);
+                    let mut base_ranges = MemoryRangeTable::default();
+                    for range in base_manifest.ranges.iter() {
+                        base_ranges.push(MemoryRange {
+                            gpa: range.gpa,
+                            length: range.length,
+                        });
+                    }
+                    mm_locked.fill_saved_regions(
+                        base_file_path,
+                        base_ranges,
+                        Some(base_manifest),
+                        verify,
+                    )?;
+                }
+
+                mm_locked.fill_saved_regions(
+                    memory_file_path,
+                    mem_snapshot.memory_ranges,
+                    manifest,
+                    verify,
+                )?;
+            }
 
             Ok(mm)
         } else {
@@ -1131,6 +1709,40 @@ impl MemoryManager {
         }
     }
 
+    // Returns the directories of the chain of snapshots an incremental
+    // snapshot stored in `directory` is based on, along with their memory
+    // manifests, from the oldest one.
+    fn snapshot_bases(
+        directory: &Path,
+        manifest: Option<&MemoryManifest>,
+    ) -> Result<Vec<(PathBuf, MemoryManifest)>, Error> {
+        let mut bases: Vec<(PathBuf, MemoryManifest)> = Vec::new();
+        let mut directory = directory.to_path_buf();
+        let mut base = manifest.and_then(|manifest| manifest.base.clone());
+        while let Some(path) = base {
+            // The base is relative to the directory of the snapshot based on
+            // it, so that a chain can be moved as a whole.
+            directory = directory
+                .join(&path)
+                .canonicalize()
+                .map_err(|_| Error::SnapshotBase(path.clone()))?;
+            if bases
+                .iter()
+                .any(|(base_directory, _)| *base_directory == directory)
+            {
+                return Err(Error::SnapshotBase(path));
+            }
+            let base_manifest = recv_memory_manifest(&format!("file://{}", directory.display()))
+                .map_err(Error::Restore)?
+                .ok_or_else(|| Error::SnapshotBase(path.clone()))?;
+            base = base_manifest.base.clone();
+            bases.push((directory.clone(), base_manifest));
+        }
+        bases.reverse();
+
+        Ok(bases)
+    }
+
     fn memfd_create(name: &ffi::CStr, flags: u32) -> Result<RawFd, io::Error> {
         let res = unsafe { libc::syscall(libc::SYS_memfd_create, name.as_ptr(), flags) };
 
@@ -1141,6 +1753,23 @@ impl MemoryManager {
         }
     }
 
+    fn seal_memory_file(f: &File) -> Result<(), io::Error> {
+        // SAFETY: FFI call with a valid file descriptor.
+        let res = unsafe {
+            libc::fcntl(
+                f.as_raw_fd(),
+                libc::F_ADD_SEALS,
+                libc::F_SEAL_GROW | libc::F_SEAL_SHRINK,
+            )
+        };
+
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
     fn mbind(
         addr: *mut u8,
         len: u64,
@@ -1174,10 +1803,28 @@ impl MemoryManager {
         size: usize,
         hugepages: bool,
         hugepage_size: Option<u64>,
+        template: bool,
+        sealed: bool,
     ) -> Result<(File, u64), Error> {
         let (f, f_off) = match backing_file {
             Some(ref file) => {
-                if file.is_dir() {
+                if template {
+                    // The template is shared by many VMs, hence never
+                    // written to: the pages written by the guest are copied
+                    // through the private mapping.
+                    let f = OpenOptions::new()
+                        .read(true)
+                        .open(file)
+                        .map_err(Error::SharedFileCreate)?;
+                    // Accessing a page beyond the end of the file would kill
+                    // the VMM.
+                    let len = f.metadata().map_err(Error::SharedFileCreate)?.len();
+                    if len < file_offset + size as u64 {
+                        return Err(Error::InvalidTemplateFileSize(file.clone()));
+                    }
+
+                    (f, file_offset)
+                } else if file.is_dir() {
                     // Override file offset as it does not apply in this case.
                     info!(
                         "Ignoring file offset since the backing file is a \
@@ -1204,35 +1851,41 @@ impl MemoryManager {
                 }
             }
             None => {
-                let fd = Self::memfd_create(
-                    &ffi::CString::new("ch_ram").unwrap(),
-                    if hugepages {
-                        libc::MFD_HUGETLB
-                            | if let Some(hugepage_size) = hugepage_size {
-                                /*
-                                 * From the Linux kernel:
-                                 * Several system calls take a flag to request "hugetlb" huge pages.
-                                 * Without further specification, these system calls will use the
-                                 * system's default huge page size.  If a system supports multiple
-                                 * huge page sizes, the desired huge page size can be specified in
-                                 * bits [26:31] of the flag arguments.  The value in these 6 bits
-                                 * will encode the log2 of the huge page size.
-                                 */
-
-                                hugepage_size.trailing_zeros() << 26
-                            } else {
-                                // Use the system default huge page size
-                                0
-                            }
-                    } else {
-                        0
-                    },
-                )
-                .map_err(Error::SharedFileCreate)?;
+                let mut flags = if sealed { libc::MFD_ALLOW_SEALING } else { 0 };
+                flags |= if hugepages {
+                    libc::MFD_HUGETLB
+                        | if let Some(hugepage_size) = hugepage_size {
+                            /*
+                             * From the Linux kernel:
+                             * Several system calls take a flag to request "hugetlb" huge pages.
+                             * Without further specification, these system calls will use the
+                             * system's default huge page size.  If a system supports multiple
+                             * huge page sizes, the desired huge page size can be specified in
+                             * bits [26:31] of the flag arguments.  The value in these 6 bits
+                             * will encode the log2 of the huge page size.
+                             */
+
+                            hugepage_size.trailing_zeros() << 26
+                        } else {
+                            // Use the system default huge page size
+                            0
+                        }
+                } else {
+                    0
+                };
+                let fd = Self::memfd_create(&ffi::CString::new("ch_ram").unwrap(), flags)
+                    .map_err(Error::SharedFileCreate)?;
 
                 let f = unsafe { File::from_raw_fd(fd) };
                 f.set_len(size as u64).map_err(Error::SharedFileSetLen)?;
 
+                // Once shared by fd, e.g. with a vhost-user backend, the
+                // file can't be truncated under the mappings of the guest
+                // memory, which would kill the process accessing them.
+                if sealed {
+                    Self::seal_memory_file(&f).map_err(Error::SealMemoryFile)?;
+                }
+
                 (f, 0)
             }
         };
@@ -1251,12 +1904,22 @@ impl MemoryManager {
         hugepages: bool,
         hugepage_size: Option<u64>,
         host_numa_node: Option<u32>,
+        template: bool,
+        sealed: bool,
         existing_memory_file: Option<File>,
     ) -> Result<Arc<GuestRegionMmap>, Error> {
         let (f, f_off) = if let Some(f) = existing_memory_file {
             (f, file_offset)
         } else {
-            Self::open_memory_file(backing_file, file_offset, size, hugepages, hugepage_size)?
+            Self::open_memory_file(
+                backing_file,
+                file_offset,
+                size,
+                hugepages,
+                hugepage_size,
+                template,
+                sealed,
+            )?
         };
 
         let mut mmap_flags = libc::MAP_NORESERVE
@@ -1283,39 +1946,41 @@ impl MemoryManager {
 
         // Apply NUMA policy if needed.
         if let Some(node) = host_numa_node {
-            let addr = region.deref().as_ptr();
-            let len = region.deref().size() as u64;
-            let mode = MPOL_BIND;
-            let mut nodemask: Vec<u64> = Vec::new();
-            let flags = MPOL_MF_STRICT | MPOL_MF_MOVE;
-
-            // Linux is kind of buggy in the way it interprets maxnode as it
-            // will cut off the last node. That's why we have to add 1 to what
-            // we would consider as the proper maxnode value.
-            let maxnode = node as u64 + 1 + 1;
-
-            // Allocate the right size for the vector.
-            nodemask.resize((node as usize / 64) + 1, 0);
-
-            // Fill the global bitmask through the nodemask vector.
-            let idx = (node / 64) as usize;
-            let shift = node % 64;
-            nodemask[idx] |= 1u64 << shift;
-
-            // Policies are enforced by using MPOL_MF_MOVE flag as it will
-            // force the kernel to move all pages that might have been already
-            // allocated to the proper set of NUMA nodes. MPOL_MF_STRICT is
-            // used to throw an error if MPOL_MF_MOVE didn't succeed.
-            // MPOL_BIND is the selected mode as it specifies a strict policy
-            // that restricts memory allocation to the nodes specified in the
-            // nodemask.
-            Self::mbind(addr, len, mode, nodemask, maxnode, flags)
-                .map_err(Error::ApplyNumaPolicy)?;
+            Self::set_numa_policy(region.deref().as_ptr(), region.deref().size() as u64, node)?;
         }
 
         Ok(Arc::new(region))
     }
 
+    // Bind the memory mapped at the given address to a host NUMA node.
+    fn set_numa_policy(addr: *mut u8, len: u64, node: u32) -> Result<(), Error> {
+        let mode = MPOL_BIND;
+        let mut nodemask: Vec<u64> = Vec::new();
+        let flags = MPOL_MF_STRICT | MPOL_MF_MOVE;
+
+        // Linux is kind of buggy in the way it interprets maxnode as it
+        // will cut off the last node. That's why we have to add 1 to what
+        // we would consider as the proper maxnode value.
+        let maxnode = node as u64 + 1 + 1;
+
+        // Allocate the right size for the vector.
+        nodemask.resize((node as usize / 64) + 1, 0);
+
+        // Fill the global bitmask through the nodemask vector.
+        let idx = (node / 64) as usize;
+        let shift = node % 64;
+        nodemask[idx] |= 1u64 << shift;
+
+        // Policies are enforced by using MPOL_MF_MOVE flag as it will
+        // force the kernel to move all pages that might have been already
+        // allocated to the proper set of NUMA nodes. MPOL_MF_STRICT is
+        // used to throw an error if MPOL_MF_MOVE didn't succeed.
+        // MPOL_BIND is the selected mode as it specifies a strict policy
+        // that restricts memory allocation to the nodes specified in the
+        // nodemask.
+        Self::mbind(addr, len, mode, nodemask, maxnode, flags).map_err(Error::ApplyNumaPolicy)
+    }
+
     // Update the GuestMemoryMmap with the new range
     fn add_region(&mut self, region: Arc<GuestRegionMmap>) -> Result<(), Error> {
         let guest_memory = self
@@ -1369,6 +2034,8 @@ impl MemoryManager {
             self.hugepages,
             self.hugepage_size,
             None,
+            false,
+            self.sealed,
             None,
         )?;
 
@@ -1414,6 +2081,12 @@ impl MemoryManager {
             return Err(Error::InsufficientHotplugRam);
         }
 
+        if let Some(hugepage_pool) = self.hugepage_pool.as_mut() {
+            hugepage_pool
+                .resize_zone(DEFAULT_MEMORY_ZONE, self.current_ram + size as u64)
+                .map_err(Error::HugepagePool)?;
+        }
+
         let region = self.add_ram_region(start_addr, size)?;
 
         // Add region to the list of regions associated with the default
@@ -1455,6 +2128,21 @@ impl MemoryManager {
         self.start_of_device_area
     }
 
+    /// Guest address range the memory is hotplugged into through ACPI, if
+    /// the ACPI memory hotplug is enabled.
+    pub fn acpi_hotplug_range(&self) -> Option<(GuestAddress, u64)> {
+        if self.hotplug_method != HotplugMethod::Acpi || self.user_provided_zones {
+            return None;
+        }
+
+        let start = MemoryManager::start_addr(self.boot_guest_memory.last_addr(), true).ok()?;
+        if start >= self.start_of_device_area {
+            return None;
+        }
+
+        Some((start, self.start_of_device_area.0 - start.0))
+    }
+
     pub fn end_of_device_area(&self) -> GuestAddress {
         self.end_of_device_area
     }
@@ -1465,6 +2153,30 @@ impl MemoryManager {
         slot_id
     }
 
+    fn mark_mergeable(userspace_addr: u64, memory_size: u64) {
+        // Safe because the address and size are valid since the
+        // mmap succeeded.
+        let ret = unsafe {
+            libc::madvise(
+                userspace_addr as *mut libc::c_void,
+                memory_size as libc::size_t,
+                libc::MADV_MERGEABLE,
+            )
+        };
+        if ret != 0 {
+            let err = io::Error::last_os_error();
+            // Safe to unwrap because the error is constructed with
+            // last_os_error(), which ensures the output will be Some().
+            let errno = err.raw_os_error().unwrap();
+            if errno == libc::EINVAL {
+                warn!("kernel not configured with CONFIG_KSM");
+            } else {
+                warn!("madvise error: {}", err);
+            }
+            warn!("failed to mark pages as mergeable");
+        }
+    }
+
     pub fn create_userspace_mapping(
         &mut self,
         guest_phys_addr: u64,
@@ -1495,27 +2207,7 @@ impl MemoryManager {
 
         // Mark the pages as mergeable if explicitly asked for.
         if mergeable {
-            // Safe because the address and size are valid since the
-            // mmap succeeded.
-            let ret = unsafe {
-                libc::madvise(
-                    userspace_addr as *mut libc::c_void,
-                    memory_size as libc::size_t,
-                    libc::MADV_MERGEABLE,
-                )
-            };
-            if ret != 0 {
-                let err = io::Error::last_os_error();
-                // Safe to unwrap because the error is constructed with
-                // last_os_error(), which ensures the output will be Some().
-                let errno = err.raw_os_error().unwrap();
-                if errno == libc::EINVAL {
-                    warn!("kernel not configured with CONFIG_KSM");
-                } else {
-                    warn!("madvise error: {}", err);
-                }
-                warn!("failed to mark pages as mergeable");
-            }
+            Self::mark_mergeable(userspace_addr, memory_size);
         }
 
         info!(
@@ -1584,6 +2276,13 @@ impl MemoryManager {
     pub fn virtio_mem_resize(&mut self, id: &str, size: u64) -> Result<(), Error> {
         if let Some(memory_zone) = self.memory_zones.get_mut(id) {
             if let Some(virtio_mem_zone) = &mut memory_zone.virtio_mem_zone {
+                if let Some(hugepage_pool) = self.hugepage_pool.as_mut() {
+                    let boot_size: u64 = memory_zone.regions.iter().map(|r| r.len()).sum();
+                    hugepage_pool
+                        .resize_zone(id, boot_size + size)
+                        .map_err(Error::HugepagePool)?;
+                }
+
                 virtio_mem_zone
                     .resize_handler()
                     .work(size)
@@ -1762,6 +2461,82 @@ impl MemoryManager {
         &self.sgx_epc_region
     }
 
+    pub fn setup_pstore(
+        &mut self,
+        pstore_config: &PstoreConfig,
+        restoring: bool,
+    ) -> Result<(), Error> {
+        let size = pstore_config.size;
+
+        // The pstore region is placed between the RAM and the device area.
+        // When restoring, the start of the device area has been saved with
+        // the region already accounted for.
+        let start = if restoring {
+            let start = GuestAddress(
+                self.start_of_device_area
+                    .0
+                    .checked_sub(size)
+                    .ok_or(Error::GuestAddressOverFlow)?,
+            );
+            self.ram_allocator
+                .allocate(Some(start), size as GuestUsize, None)
+                .ok_or(Error::MemoryRangeAllocation)?;
+            start
+        } else {
+            let start = GuestAddress(
+                ((self.start_of_device_area.0 + PSTORE_REGION_ALIGNMENT - 1)
+                    / PSTORE_REGION_ALIGNMENT)
+                    * PSTORE_REGION_ALIGNMENT,
+            );
+            self.start_of_device_area =
+                start.checked_add(size).ok_or(Error::GuestAddressOverFlow)?;
+            start
+        };
+
+        info!("Pstore region: 0x{:x} (0x{:x})", start.0, size);
+
+        // The backing file is kept across VM instances so that the records
+        // written by the guest survive a crash or a reboot.
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&pstore_config.file)
+            .map_err(Error::PstoreFileOpen)?;
+        if file.metadata().map_err(Error::PstoreFileOpen)?.len() < size {
+            file.set_len(size).map_err(Error::PstoreFileSetLen)?;
+        }
+
+        let mmap_region = MmapRegion::build(
+            Some(FileOffset::new(file, 0)),
+            size as usize,
+            PROT_READ | PROT_WRITE,
+            MAP_NORESERVE | MAP_SHARED,
+        )
+        .map_err(Error::NewMmapRegion)?;
+
+        self.create_userspace_mapping(
+            start.0,
+            size,
+            mmap_region.as_ptr() as u64,
+            false,
+            false,
+            false,
+        )?;
+
+        self.pstore_region = Some(PstoreRegion {
+            start,
+            size,
+            _mmap_region: mmap_region,
+        });
+
+        Ok(())
+    }
+
+    pub fn pstore_region(&self) -> Option<(GuestAddress, u64)> {
+        self.pstore_region.as_ref().map(|r| (r.start, r.size))
+    }
+
     pub fn is_hardlink(f: &File) -> bool {
         let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
         let ret = unsafe { libc::fstat(f.as_raw_fd(), stat.as_mut_ptr()) };
@@ -1773,6 +2548,13 @@ impl MemoryManager {
         unsafe { (*stat.as_ptr()).st_nlink as usize > 0 }
     }
 
+    /// Give the hugepages added for the guest memory back to the host pool.
+    pub fn release_hugepages(&mut self) {
+        if let Some(hugepage_pool) = self.hugepage_pool.as_mut() {
+            hugepage_pool.release();
+        }
+    }
+
     pub fn memory_zones(&self) -> &MemoryZones {
         &self.memory_zones
     }
@@ -1815,9 +2597,101 @@ impl MemoryManager {
             }
         }
 
+        // Memory zones are stored in a HashMap, make sure the ranges (and
+        // therefore the layout of the memory snapshot file) don't depend on
+        // its iteration order.
+        table.sort();
+
         Ok(table)
     }
 
+    /// Write the boot memory of each zone to `<directory>/<zone id>`, laid
+    /// out as expected from the backing file of a template zone.
+    pub fn write_memory_templates(&self, directory: &Path) -> Result<(), Error> {
+        let guest_memory = self.guest_memory.memory();
+        let mut templates: HashMap<&str, File> = HashMap::new();
+
+        // The mappings of each zone are listed in the order the zone is
+        // laid out in its backing file.
+        for mapping in self.guest_ram_mappings.iter().filter(|m| !m.virtio_mem) {
+            if !templates.contains_key(mapping.zone_id.as_str()) {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(directory.join(&mapping.zone_id))
+                    .map_err(|e| Error::MemoryTemplate(MigratableError::MigrateSend(e.into())))?;
+                templates.insert(&mapping.zone_id, file);
+            }
+            let file = templates.get_mut(mapping.zone_id.as_str()).unwrap();
+
+            Self::write_snapshot_range(
+                &guest_memory,
+                &MemoryRange {
+                    gpa: mapping.gpa,
+                    length: mapping.size,
+                },
+                file,
+            )
+            .map_err(Error::MemoryTemplate)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_snapshot_format(&mut self, format: SnapshotFormat) {
+        self.snapshot_format = format;
+    }
+
+    pub fn set_snapshot_incremental(&mut self, incremental: bool) {
+        self.snapshot_incremental = incremental;
+    }
+
+    pub fn snapshot_incremental(&self) -> bool {
+        self.snapshot_incremental
+    }
+
+    /// Whether a chain of incremental snapshots has been started, which
+    /// depends on the dirty pages logging of the VM.
+    pub fn snapshot_chain_started(&self) -> bool {
+        self.snapshot_base.is_some()
+    }
+
+    /// End the chain of incremental snapshots, the next one storing all the
+    /// guest memory again.
+    pub fn end_snapshot_chain(&mut self) {
+        self.snapshot_base = None;
+    }
+
+    /// Account for the pages dirtied since the base snapshot, read from the
+    /// dirty log of the VM.
+    pub fn add_snapshot_dirty(&mut self, table: &MemoryRangeTable) {
+        if let Some(base) = self.snapshot_base.as_mut() {
+            base.dirty.extend(table.clone());
+            base.dirty.coalesce();
+        }
+    }
+
+    /// Make the incremental snapshot successfully sent to `destination_url`
+    /// the base of the next one.
+    pub fn set_snapshot_base(
+        &mut self,
+        destination_url: &str,
+    ) -> result::Result<(), MigratableError> {
+        if !self.snapshot_incremental {
+            return Ok(());
+        }
+
+        if let Some(base) = self.snapshot_base.as_mut() {
+            let directory = url_to_path(destination_url)?
+                .canonicalize()
+                .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+            base.directory = Some(directory);
+            base.dirty = MemoryRangeTable::default();
+        }
+
+        Ok(())
+    }
+
     pub fn snapshot_data(&self) -> MemoryManagerSnapshotData {
         MemoryManagerSnapshotData {
             memory_ranges: self.snapshot_memory_ranges.clone(),
@@ -1853,29 +2727,29 @@ impl MemoryManager {
         self.acpi_address
     }
 
-    pub fn num_guest_ram_mappings(&self) -> u32 {
-        self.guest_ram_mappings.len() as u32
-    }
-
+    /// Ranges of guest memory to dump, split around the sorted `free_ranges`
+    /// the guest doesn't use.
     #[cfg(feature = "guest_debug")]
-    pub fn coredump_memory_regions(&self, mem_offset: u64) -> CoredumpMemoryRegions {
+    pub fn coredump_memory_ranges(&self, free_ranges: &[MemoryRange]) -> Vec<MemoryRange> {
         let mut mapping_sorted_by_gpa = self.guest_ram_mappings.clone();
         mapping_sorted_by_gpa.sort_by_key(|m| m.gpa);
+        let ranges: Vec<MemoryRange> = mapping_sorted_by_gpa
+            .iter()
+            .map(|m| MemoryRange {
+                gpa: m.gpa,
+                length: m.size,
+            })
+            .collect();
+
+        // The free ranges left out are long enough for the number of load
+        // segments to stay bounded whatever the size of the guest.
+        let total_size: u64 = ranges.iter().map(|r| r.length).sum();
+        let min_size = std::cmp::max(
+            COREDUMP_MIN_EXCLUDED_SIZE,
+            total_size / COREDUMP_MAX_LOAD_SEGMENTS,
+        );
 
-        let mut mem_offset_in_elf = mem_offset;
-        let mut ram_maps = BTreeMap::new();
-        for mapping in mapping_sorted_by_gpa.iter() {
-            ram_maps.insert(
-                mapping.gpa,
-                CoredumpMemoryRegion {
-                    mem_offset_in_elf,
-                    mem_size: mapping.size,
-                },
-            );
-            mem_offset_in_elf += mapping.size;
-        }
-
-        CoredumpMemoryRegions { ram_maps }
+        exclude_memory_ranges(&ranges, free_ranges, min_size)
     }
 
     #[cfg(feature = "guest_debug")]
@@ -1883,38 +2757,32 @@ impl MemoryManager {
         &mut self,
         dump_state: &DumpState,
     ) -> std::result::Result<(), GuestDebuggableError> {
-        let snapshot_memory_ranges = self
-            .memory_range_table(false)
-            .map_err(|e| GuestDebuggableError::Coredump(e.into()))?;
-
-        if snapshot_memory_ranges.is_empty() {
-            return Ok(());
-        }
-
+        let mem_info = dump_state.mem_info.as_ref().unwrap();
         let mut coredump_file = dump_state.file.as_ref().unwrap();
 
         let guest_memory = self.guest_memory.memory();
         let mut total_bytes: u64 = 0;
-
-        for range in snapshot_memory_ranges.regions() {
-            let mut offset: u64 = 0;
-            loop {
-                let bytes_written = guest_memory
-                    .write_to(
-                        GuestAddress(range.gpa + offset),
-                        &mut coredump_file,
-                        (range.length - offset) as usize,
-                    )
-                    .map_err(|e| GuestDebuggableError::Coredump(e.into()))?;
-                offset += bytes_written as u64;
-                total_bytes += bytes_written as u64;
-
-                if offset == range.length {
-                    break;
-                }
-            }
+        let mut end_of_dump = dump_state.mem_offset;
+
+        for (gpa, load) in mem_info.ram_maps.iter() {
+            coredump_file
+                .seek(SeekFrom::Start(load.mem_offset_in_elf))
+                .map_err(GuestDebuggableError::CoredumpFile)?;
+            total_bytes += write_guest_memory(
+                &guest_memory,
+                &mut coredump_file,
+                *gpa,
+                load.mem_size,
+                dump_state.sparse,
+            )?;
+            end_of_dump = load.mem_offset_in_elf + load.mem_size;
         }
 
+        // The zero pages at the end of a sparse dump were only seeked over.
+        coredump_file
+            .set_len(end_of_dump)
+            .map_err(GuestDebuggableError::CoredumpFile)?;
+
         debug!("coredump total bytes {}", total_bytes);
         Ok(())
     }
@@ -2262,7 +3130,7 @@ impl Aml for MemoryManager {
 
 impl Pausable for MemoryManager {}
 
-#[derive(Clone, Serialize, Deserialize, Versionize)]
+#[derive(Clone, Default, Serialize, Deserialize, Versionize)]
 pub struct MemoryManagerSnapshotData {
     memory_ranges: MemoryRangeTable,
     guest_ram_mappings: Vec<GuestRamMapping>,
@@ -2296,7 +3164,33 @@ impl Snapshottable for MemoryManager {
         // not. This saves the 'send' step having to go through the same
         // process, and instead it can directly proceed with storing the
         // memory range content for the ranges requiring it.
-        self.snapshot_memory_ranges = memory_ranges;
+        //
+        // The dirty pages logging incremental snapshots depend on is handled
+        // by the VM, as it involves the devices writing to the guest memory
+        // as well.
+        self.snapshot_parent = None;
+        let base = self
+            .snapshot_base
+            .as_ref()
+            .filter(|base| base.directory.is_some() && base.memory_ranges == memory_ranges);
+        self.snapshot_memory_ranges = if !self.snapshot_incremental {
+            memory_ranges
+        } else if let Some(base) = base {
+            // Only the pages dirtied since the base snapshot are stored.
+            // They are kept until the snapshot has been written, in case it
+            // fails.
+            self.snapshot_parent = base.directory.clone();
+            base.dirty.intersection(&memory_ranges)
+        } else {
+            // First snapshot of a chain, or the guest memory layout changed
+            // since the base snapshot: all the memory is stored.
+            self.snapshot_base = Some(SnapshotBase {
+                directory: None,
+                memory_ranges: memory_ranges.clone(),
+                dirty: MemoryRangeTable::default(),
+            });
+            memory_ranges
+        };
 
         memory_manager_snapshot.add_data_section(SnapshotDataSection::new_from_versioned_state(
             MEMORY_MANAGER_SNAPSHOT_ID,
@@ -2307,52 +3201,59 @@ impl Snapshottable for MemoryManager {
     }
 }
 
+// Returns the path of `to` relative to the directory `from`, both being
+// canonical paths.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut path = PathBuf::new();
+    for _ in from.components().skip(common) {
+        path.push("..");
+    }
+    for component in to.components().skip(common) {
+        path.push(component);
+    }
+
+    path
+}
+
 impl Transportable for MemoryManager {
     fn send(
         &self,
         _snapshot: &Snapshot,
         destination_url: &str,
     ) -> result::Result<(), MigratableError> {
-        if self.snapshot_memory_ranges.is_empty() {
+        // An incremental snapshot without any page dirtied still needs the
+        // manifest pointing to its base.
+        if self.snapshot_memory_ranges.is_empty() && self.snapshot_parent.is_none() {
             return Ok(());
         }
 
-        let mut memory_file_path = url_to_path(destination_url)?;
-        memory_file_path.push(String::from(SNAPSHOT_FILENAME));
-
-        // Create the snapshot file for the entire memory
-        let mut memory_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create_new(true)
-            .open(memory_file_path)
+        let directory = url_to_path(destination_url)?;
+        // The base is recorded relatively to the snapshot, so that a chain
+        // can be moved as a whole.
+        let base = self
+            .snapshot_parent
+            .as_ref()
+            .map(|parent| {
+                directory
+                    .canonicalize()
+                    .map(|directory| relative_path(&directory, parent))
+            })
+            .transpose()
             .map_err(|e| MigratableError::MigrateSend(e.into()))?;
 
-        let guest_memory = self.guest_memory.memory();
-
-        for range in self.snapshot_memory_ranges.regions() {
-            let mut offset: u64 = 0;
-            // Here we are manually handling the retry in case we can't read
-            // the whole region at once because we can't use the implementation
-            // from vm-memory::GuestMemory of write_all_to() as it is not
-            // following the correct behavior. For more info about this issue
-            // see: https://github.com/rust-vmm/vm-memory/issues/174
-            loop {
-                let bytes_written = guest_memory
-                    .write_to(
-                        GuestAddress(range.gpa + offset),
-                        &mut memory_file,
-                        (range.length - offset) as usize,
-                    )
-                    .map_err(|e| MigratableError::MigrateSend(e.into()))?;
-                offset += bytes_written as u64;
-
-                if offset == range.length {
-                    break;
-                }
-            }
-        }
-        Ok(())
+        Self::write_saved_regions(
+            &self.guest_memory.memory(),
+            &directory,
+            &self.snapshot_memory_ranges,
+            self.snapshot_format,
+            base,
+        )
     }
 }
 
@@ -2426,3 +3327,337 @@ impl Migratable for MemoryManager {
         Ok(table)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(
+                Path::new("/var/snapshots/snapshot-2"),
+                Path::new("/var/snapshots/snapshot-1")
+            ),
+            PathBuf::from("../snapshot-1")
+        );
+        assert_eq!(
+            relative_path(Path::new("/var/snapshots"), Path::new("/srv/base")),
+            PathBuf::from("../../srv/base")
+        );
+    }
+
+    #[test]
+    fn test_map_saved_ranges() {
+        let guest_memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let tmp = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let file_path = tmp.as_path().join(SNAPSHOT_FILENAME);
+        let content: Vec<u8> = (0..3u8).flat_map(|i| [i + 1; 0x1000]).collect();
+        std::fs::write(&file_path, &content).unwrap();
+        let memory_file = File::open(&file_path).unwrap();
+
+        let mut saved_regions = MemoryRangeTable::default();
+        saved_regions.push(MemoryRange {
+            gpa: 0x2000,
+            length: 0x2000,
+        });
+        saved_regions.push(MemoryRange {
+            gpa: 0x8000,
+            length: 0x1000,
+        });
+
+        // The NUMA node is looked up for every range mapped.
+        let numa_lookups = std::cell::RefCell::new(Vec::new());
+        MemoryManager::map_saved_ranges(
+            &guest_memory,
+            &memory_file,
+            &saved_regions,
+            false,
+            true,
+            |gpa| {
+                numa_lookups.borrow_mut().push(gpa);
+                None
+            },
+        )
+        .unwrap();
+        assert_eq!(*numa_lookups.borrow(), [0x2000, 0x8000]);
+
+        let mut data = vec![0u8; 0x2000];
+        guest_memory
+            .read_slice(&mut data, GuestAddress(0x2000))
+            .unwrap();
+        assert!(data == content[..0x2000]);
+        guest_memory
+            .read_slice(&mut data[..0x1000], GuestAddress(0x8000))
+            .unwrap();
+        assert!(data[..0x1000] == content[0x2000..]);
+
+        // The pages written by the guest are private copies.
+        guest_memory
+            .write_slice(&[0xff; 0x1000], GuestAddress(0x2000))
+            .unwrap();
+        assert_eq!(std::fs::read(&file_path).unwrap(), content);
+
+        // Ranges beyond the end of the file or not page aligned are
+        // rejected.
+        let mut saved_regions = MemoryRangeTable::default();
+        saved_regions.push(MemoryRange {
+            gpa: 0x2000,
+            length: 0x4000,
+        });
+        assert!(matches!(
+            MemoryManager::map_saved_ranges(
+                &guest_memory,
+                &memory_file,
+                &saved_regions,
+                false,
+                false,
+                |_| None
+            ),
+            Err(Error::SnapshotCopy(_))
+        ));
+        let mut saved_regions = MemoryRangeTable::default();
+        saved_regions.push(MemoryRange {
+            gpa: 0x2800,
+            length: 0x1000,
+        });
+        assert!(matches!(
+            MemoryManager::map_saved_ranges(
+                &guest_memory,
+                &memory_file,
+                &saved_regions,
+                false,
+                false,
+                |_| None
+            ),
+            Err(Error::SnapshotOnDemandRange(0x2800))
+        ));
+    }
+
+    #[test]
+    fn test_incremental_snapshot_restore() {
+        let regions = [(GuestAddress(0), 0x10000), (GuestAddress(0x100000), 0x4000)];
+        let guest_memory = GuestMemoryMmap::from_ranges(&regions).unwrap();
+        let mut layout = MemoryRangeTable::default();
+        for (addr, length) in regions {
+            layout.push(MemoryRange {
+                gpa: addr.raw_value(),
+                length: length as u64,
+            });
+        }
+
+        let page_size = 0x1000;
+        let write_page = |gpa: u64, value: u8| {
+            guest_memory
+                .write_slice(&[value; 0x1000], GuestAddress(gpa))
+                .unwrap();
+        };
+        for range in layout.regions() {
+            for gpa in (range.gpa..range.gpa + range.length).step_by(page_size) {
+                write_page(gpa, (gpa >> 12) as u8 + 1);
+            }
+        }
+
+        let tmp = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let chain = tmp.as_path().join("chain");
+        let snapshots: Vec<PathBuf> = (0..3)
+            .map(|i| chain.join(format!("snapshot-{}", i)))
+            .collect();
+        for snapshot in snapshots.iter() {
+            std::fs::create_dir_all(snapshot).unwrap();
+        }
+
+        // Full base snapshot, followed by two incremental ones only storing
+        // the pages written in between.
+        MemoryManager::write_saved_regions(
+            &guest_memory,
+            &snapshots[0],
+            &layout,
+            SnapshotFormat::Raw,
+            None,
+        )
+        .unwrap();
+        for (i, pages) in [vec![0x3000, 0x4000, 0x101000], vec![0x4000, 0xf000]]
+            .iter()
+            .enumerate()
+        {
+            let mut dirty = MemoryRangeTable::default();
+            for gpa in pages {
+                write_page(*gpa, 0xa0 + i as u8);
+                dirty.push(MemoryRange {
+                    gpa: *gpa,
+                    length: page_size as u64,
+                });
+            }
+            dirty.coalesce();
+            MemoryManager::write_saved_regions(
+                &guest_memory,
+                &snapshots[i + 1],
+                &dirty.intersection(&layout),
+                SnapshotFormat::Zstd,
+                Some(relative_path(
+                    &snapshots[i + 1].canonicalize().unwrap(),
+                    &snapshots[i].canonicalize().unwrap(),
+                )),
+            )
+            .unwrap();
+        }
+
+        // The chain is still complete once moved as a whole.
+        let moved = tmp.as_path().join("moved");
+        std::fs::rename(&chain, &moved).unwrap();
+        let directory = moved.join("snapshot-2");
+        let manifest = recv_memory_manifest(&format!("file://{}", directory.display()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(manifest.base, Some(PathBuf::from("../snapshot-1")));
+        let mut chain = MemoryManager::snapshot_bases(&directory, Some(&manifest)).unwrap();
+        assert_eq!(chain.len(), 2);
+        chain.push((directory, manifest));
+
+        let restored = GuestMemoryMmap::from_ranges(&regions).unwrap();
+        for (directory, manifest) in chain {
+            let mut saved_regions = MemoryRangeTable::default();
+            for range in manifest.ranges.iter() {
+                saved_regions.push(MemoryRange {
+                    gpa: range.gpa,
+                    length: range.length,
+                });
+            }
+            let mut memory_file = File::open(directory.join(SNAPSHOT_FILENAME)).unwrap();
+            MemoryManager::read_saved_regions(
+                &restored,
+                &mut memory_file,
+                &saved_regions,
+                &manifest,
+                true,
+                |_| false,
+            )
+            .unwrap();
+        }
+
+        for range in layout.regions() {
+            let mut expected = vec![0u8; range.length as usize];
+            let mut actual = vec![0u8; range.length as usize];
+            guest_memory
+                .read_slice(&mut expected, GuestAddress(range.gpa))
+                .unwrap();
+            restored
+                .read_slice(&mut actual, GuestAddress(range.gpa))
+                .unwrap();
+            assert!(expected == actual, "mismatch in range {:x}", range.gpa);
+        }
+
+        // A snapshot based on itself is rejected.
+        let directory = moved.join("snapshot-0");
+        let manifest = MemoryManifest {
+            format: SnapshotFormat::Raw,
+            ranges: Vec::new(),
+            base: Some(PathBuf::from(".")),
+        };
+        std::fs::write(
+            directory.join(SNAPSHOT_MEMORY_MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(
+            MemoryManager::snapshot_bases(&directory, Some(&manifest)),
+            Err(Error::SnapshotBase(_))
+        ));
+    }
+
+    #[test]
+    fn test_zstd_snapshot_round_trip() {
+        let regions = [(GuestAddress(0), 0x10000), (GuestAddress(0x100000), 0x4000)];
+        let guest_memory = GuestMemoryMmap::from_ranges(&regions).unwrap();
+        let mut layout = MemoryRangeTable::default();
+        for (addr, length) in regions {
+            layout.push(MemoryRange {
+                gpa: addr.raw_value(),
+                length: length as u64,
+            });
+            let content: Vec<u8> = (0..length).map(|i| (i / 0x100) as u8).collect();
+            guest_memory.write_slice(&content, addr).unwrap();
+        }
+
+        let tmp = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let directory = tmp.as_path();
+        MemoryManager::write_saved_regions(
+            &guest_memory,
+            directory,
+            &layout,
+            SnapshotFormat::Zstd,
+            None,
+        )
+        .unwrap();
+
+        let mut manifest = recv_memory_manifest(&format!("file://{}", directory.display()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(manifest.format, SnapshotFormat::Zstd);
+        assert_eq!(manifest.ranges.len(), 2);
+        for entry in manifest.ranges.iter() {
+            assert!(entry.stored_length < entry.length);
+        }
+
+        let restore = |manifest: &MemoryManifest, verify: bool| {
+            let restored = GuestMemoryMmap::from_ranges(&regions).unwrap();
+            let mut memory_file = File::open(directory.join(SNAPSHOT_FILENAME)).unwrap();
+            MemoryManager::read_saved_regions(
+                &restored,
+                &mut memory_file,
+                &layout,
+                manifest,
+                verify,
+                |_| false,
+            )
+            .map(|_| restored)
+        };
+
+        let restored = restore(&manifest, true).unwrap();
+        for range in layout.regions() {
+            let mut expected = vec![0u8; range.length as usize];
+            let mut actual = vec![0u8; range.length as usize];
+            guest_memory
+                .read_slice(&mut expected, GuestAddress(range.gpa))
+                .unwrap();
+            restored
+                .read_slice(&mut actual, GuestAddress(range.gpa))
+                .unwrap();
+            assert!(expected == actual, "mismatch in range {:x}", range.gpa);
+        }
+
+        // A range not matching its digest is only accepted when the
+        // verification is disabled.
+        manifest.ranges[1].sha256 = "00".repeat(32);
+        assert!(matches!(
+            restore(&manifest, true),
+            Err(Error::SnapshotChecksumMismatch(0x100000))
+        ));
+        assert!(restore(&manifest, false).is_ok());
+    }
+
+    #[test]
+    fn test_sealed_memory_file() {
+        // SAFETY: FFI call with a valid file descriptor.
+        let seals = |f: &File| unsafe { libc::fcntl(f.as_raw_fd(), libc::F_GET_SEALS) };
+
+        let (f, _) =
+            MemoryManager::open_memory_file(&None, 0, 0x10_0000, false, None, false, true).unwrap();
+        assert_eq!(f.metadata().unwrap().len(), 0x10_0000);
+        assert_eq!(
+            seals(&f) & (libc::F_SEAL_GROW | libc::F_SEAL_SHRINK),
+            libc::F_SEAL_GROW | libc::F_SEAL_SHRINK
+        );
+        assert!(f.set_len(0x20_0000).is_err());
+        assert!(f.set_len(0).is_err());
+
+        // The files which aren't sealed can't be.
+        let (f, _) =
+            MemoryManager::open_memory_file(&None, 0, 0x10_0000, false, None, false, false)
+                .unwrap();
+        assert_eq!(seals(&f), libc::F_SEAL_SEAL);
+        assert!(f.set_len(0x20_0000).is_ok());
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::api::{vm_metrics, ApiRequest};
+use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::{Error, Result};
+use seccompiler::{apply_filter, SeccompAction};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::num::Wrapping;
+use std::os::unix::net::UnixListener;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use vmm_sys_util::eventfd::EventFd;
+
+// Prefix of the name of every metric.
+const METRICS_PREFIX: &str = "cloud_hypervisor";
+
+// Limit on the size of the HTTP request sent by the scraper, headers
+// included.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+// Time given to a client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Socket the metrics are served on.
+pub enum MetricsListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl MetricsListener {
+    // Waits for the next scraper connection and serves it.
+    fn serve_next(&self, api_evt: &EventFd, api_sender: &Sender<ApiRequest>) -> io::Result<()> {
+        match self {
+            MetricsListener::Unix(listener) => {
+                let (mut stream, _) = listener.accept()?;
+                stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+                serve_request(&mut stream, api_evt, api_sender)
+            }
+            MetricsListener::Tcp(listener) => {
+                let (mut stream, _) = listener.accept()?;
+                stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+                serve_request(&mut stream, api_evt, api_sender)
+            }
+        }
+    }
+}
+
+/// Metrics of a VM, as exposed to the scraper.
+pub struct VmMetrics {
+    pub counters: HashMap<String, HashMap<&'static str, Wrapping<u64>>>,
+    pub memory_total_size: u64,
+    pub memory_actual_size: u64,
+    pub queue_depths: HashMap<String, Vec<u16>>,
+}
+
+impl VmMetrics {
+    /// Formats the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut output = String::new();
+
+        // Group the counters by name, so that each metric is described once,
+        // with one sample per device or vCPU.
+        let mut counters: BTreeMap<&str, BTreeMap<&str, u64>> = BTreeMap::new();
+        for (id, device_counters) in self.counters.iter() {
+            for (name, value) in device_counters.iter() {
+                counters
+                    .entry(*name)
+                    .or_default()
+                    .insert(id.as_str(), value.0);
+            }
+        }
+        for (name, samples) in counters.iter() {
+            let _ = writeln!(output, "# TYPE {}_{} counter", METRICS_PREFIX, name);
+            for (id, value) in samples.iter() {
+                let _ = writeln!(
+                    output,
+                    "{}_{}{{id=\"{}\"}} {}",
+                    METRICS_PREFIX,
+                    name,
+                    escape_label_value(id),
+                    value
+                );
+            }
+        }
+
+        let _ = writeln!(output, "# TYPE {}_memory_size_bytes gauge", METRICS_PREFIX);
+        let _ = writeln!(
+            output,
+            "{}_memory_size_bytes {}",
+            METRICS_PREFIX, self.memory_total_size
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE {}_memory_actual_size_bytes gauge",
+            METRICS_PREFIX
+        );
+        let _ = writeln!(
+            output,
+            "{}_memory_actual_size_bytes {}",
+            METRICS_PREFIX, self.memory_actual_size
+        );
+
+        let queue_depths: BTreeMap<&String, &Vec<u16>> = self.queue_depths.iter().collect();
+        let _ = writeln!(output, "# TYPE {}_virtio_queue_depth gauge", METRICS_PREFIX);
+        for (id, depths) in queue_depths.iter() {
+            for (queue, depth) in depths.iter().enumerate() {
+                let _ = writeln!(
+                    output,
+                    "{}_virtio_queue_depth{{id=\"{}\",queue=\"{}\"}} {}",
+                    METRICS_PREFIX,
+                    escape_label_value(id),
+                    queue,
+                    depth
+                );
+            }
+        }
+
+        output
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn read_request<S: Read>(stream: &mut S) -> io::Result<String> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP request too large",
+            ));
+        }
+        let count = stream.read(&mut buf)?;
+        if count == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..count]);
+    }
+
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+fn serve_request<S: Read + Write>(
+    stream: &mut S,
+    api_evt: &EventFd,
+    api_sender: &Sender<ApiRequest>,
+) -> io::Result<()> {
+    let request = read_request(stream)?;
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            match vm_metrics(api_evt.try_clone()?, api_sender.clone()) {
+                Ok(body) => ("200 OK", body.map(|b| b.raw().to_vec()).unwrap_or_default()),
+                Err(e) => ("503 Service Unavailable", format!("{:?}\n", e).into_bytes()),
+            }
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", Vec::new()),
+        _ => ("405 Method Not Allowed", Vec::new()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nServer: Cloud Hypervisor Metrics\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+pub fn start_metrics_thread(
+    listener: MetricsListener,
+    api_evt: EventFd,
+    api_sender: Sender<ApiRequest>,
+    seccomp_action: &SeccompAction,
+    exit_evt: EventFd,
+) -> Result<thread::JoinHandle<Result<()>>> {
+    // Retrieve seccomp filter for metrics thread
+    let metrics_seccomp_filter =
+        get_seccomp_filter(seccomp_action, Thread::Metrics).map_err(Error::CreateSeccompFilter)?;
+
+    thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            // Apply seccomp filter for metrics thread.
+            if !metrics_seccomp_filter.is_empty() {
+                apply_filter(&metrics_seccomp_filter)
+                    .map_err(Error::ApplySeccompFilter)
+                    .map_err(|e| {
+                        error!("Error applying seccomp filter: {:?}", e);
+                        exit_evt.write(1).ok();
+                        e
+                    })?;
+            }
+
+            std::panic::catch_unwind(AssertUnwindSafe(move || loop {
+                if let Err(e) = listener.serve_next(&api_evt, &api_sender) {
+                    warn!("Error serving metrics: {}", e);
+                }
+            }))
+            .map_err(|_| {
+                error!("metrics thread panicked");
+                exit_evt.write(1).ok()
+            })
+            .ok();
+
+            Ok(())
+        })
+        .map_err(Error::MetricsThreadSpawn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_to_prometheus() {
+        let mut counters = HashMap::new();
+        let mut disk_counters = HashMap::new();
+        disk_counters.insert("read_bytes", Wrapping(4096));
+        disk_counters.insert("write_ops", Wrapping(2));
+        counters.insert("_disk0".to_owned(), disk_counters);
+        let mut net_counters = HashMap::new();
+        net_counters.insert("read_bytes", Wrapping(1500));
+        counters.insert("_net1".to_owned(), net_counters);
+
+        let mut queue_depths = HashMap::new();
+        queue_depths.insert("_disk0".to_owned(), vec![3]);
+
+        let metrics = VmMetrics {
+            counters,
+            memory_total_size: 1 << 30,
+            memory_actual_size: 1 << 29,
+            queue_depths,
+        };
+
+        assert_eq!(
+            metrics.to_prometheus(),
+            "# TYPE cloud_hypervisor_read_bytes counter\n\
+             cloud_hypervisor_read_bytes{id=\"_disk0\"} 4096\n\
+             cloud_hypervisor_read_bytes{id=\"_net1\"} 1500\n\
+             # TYPE cloud_hypervisor_write_ops counter\n\
+             cloud_hypervisor_write_ops{id=\"_disk0\"} 2\n\
+             # TYPE cloud_hypervisor_memory_size_bytes gauge\n\
+             cloud_hypervisor_memory_size_bytes 1073741824\n\
+             # TYPE cloud_hypervisor_memory_actual_size_bytes gauge\n\
+             cloud_hypervisor_memory_actual_size_bytes 536870912\n\
+             # TYPE cloud_hypervisor_virtio_queue_depth gauge\n\
+             cloud_hypervisor_virtio_queue_depth{id=\"_disk0\",queue=\"0\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape_label_value("disk0"), "disk0");
+        assert_eq!(escape_label_value("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
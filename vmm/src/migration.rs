@@ -5,17 +5,100 @@
 #[cfg(feature = "guest_debug")]
 use crate::coredump::GuestDebuggableError;
 use crate::{
-    config::VmConfig,
+    config::{SnapshotFormat, VmConfig},
+    device_manager::DeviceManager,
+    device_tree::DeviceTree,
+    memory_manager::MemoryManagerSnapshotData,
     vm::{VmSnapshot, VM_SNAPSHOT_ID},
+    DEVICE_MANAGER_SNAPSHOT_ID, MEMORY_MANAGER_SNAPSHOT_ID,
 };
 use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader, Read, Write};
 use std::path::PathBuf;
 use vm_migration::{MigratableError, Snapshot};
 
 pub const SNAPSHOT_STATE_FILE: &str = "state.json";
+pub const SNAPSHOT_STATE_DATA_FILE: &str = "state.bin";
 pub const SNAPSHOT_CONFIG_FILE: &str = "config.json";
+pub const SNAPSHOT_MEMORY_MANIFEST_FILE: &str = "memory-manifest.json";
+
+/// Description of a memory range stored in the snapshot memory file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MemoryRangeManifest {
+    pub gpa: u64,
+    pub length: u64,
+    /// Number of bytes the range takes in the memory file.
+    pub stored_length: u64,
+    /// SHA-256 digest of the range content, hex encoded.
+    pub sha256: String,
+}
+
+/// Describes how the guest memory is laid out in the snapshot memory file.
+/// Snapshots taken before the manifest was introduced don't have one, and
+/// their memory ranges are stored as-is.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MemoryManifest {
+    pub format: SnapshotFormat,
+    pub ranges: Vec<MemoryRangeManifest>,
+    /// Directory of the snapshot an incremental snapshot is based on,
+    /// relative to the directory of the incremental snapshot. Only the
+    /// ranges dirtied since the base snapshot are stored, the others being
+    /// read from the chain of base snapshots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<PathBuf>,
+}
+
+/// Stream wrapper computing the SHA-256 digest of the data read from or
+/// written to the inner stream.
+pub struct Sha256Stream<T> {
+    inner: T,
+    hasher: Sha256,
+}
+
+impl<T> Sha256Stream<T> {
+    pub fn new(inner: T) -> Self {
+        Sha256Stream {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Returns the inner stream along with the hex encoded digest.
+    pub fn finalize(self) -> (T, String) {
+        let digest = self
+            .hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        (self.inner, digest)
+    }
+}
+
+impl<T: Read> Read for Sha256Stream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.hasher.update(&buf[..count]);
+        Ok(count)
+    }
+}
+
+impl<T: Write> Write for Sha256Stream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.hasher.update(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
 
 pub fn url_to_path(url: &str) -> std::result::Result<PathBuf, MigratableError> {
     let path: PathBuf = url
@@ -61,13 +144,42 @@ pub fn recv_vm_config(source_url: &str) -> std::result::Result<VmConfig, Migrata
 pub fn recv_vm_state(source_url: &str) -> std::result::Result<Snapshot, MigratableError> {
     let mut vm_state_path = url_to_path(source_url)?;
 
+    let mut vm_state_data_path = vm_state_path.clone();
     vm_state_path.push(SNAPSHOT_STATE_FILE);
+    vm_state_data_path.push(SNAPSHOT_STATE_DATA_FILE);
 
     // Try opening the snapshot file
     let vm_state_file =
         File::open(vm_state_path).map_err(|e| MigratableError::MigrateSend(e.into()))?;
     let vm_state_reader = BufReader::new(vm_state_file);
-    serde_json::from_reader(vm_state_reader).map_err(|e| MigratableError::MigrateReceive(e.into()))
+
+    // The data of the sections is streamed from the data file as they are
+    // restored. The snapshots taken before it was introduced embed it in
+    // their state.
+    match File::open(vm_state_data_path) {
+        Ok(vm_state_data_file) => Snapshot::read_state(vm_state_reader, vm_state_data_file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => serde_json::from_reader(vm_state_reader)
+            .map_err(|e| MigratableError::MigrateReceive(e.into())),
+        Err(e) => Err(MigratableError::MigrateReceive(e.into())),
+    }
+}
+
+pub fn recv_memory_manifest(
+    source_url: &str,
+) -> std::result::Result<Option<MemoryManifest>, MigratableError> {
+    let mut manifest_path = url_to_path(source_url)?;
+
+    manifest_path.push(SNAPSHOT_MEMORY_MANIFEST_FILE);
+
+    let manifest_file = match File::open(manifest_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(MigratableError::MigrateReceive(e.into())),
+    };
+    let manifest_reader = BufReader::new(manifest_file);
+    serde_json::from_reader(manifest_reader)
+        .map(Some)
+        .map_err(|e| MigratableError::MigrateReceive(e.into()))
 }
 
 pub fn get_vm_snapshot(snapshot: &Snapshot) -> std::result::Result<VmSnapshot, MigratableError> {
@@ -75,7 +187,7 @@ pub fn get_vm_snapshot(snapshot: &Snapshot) -> std::result::Result<VmSnapshot, M
         .snapshot_data
         .get(&format!("{}-section", VM_SNAPSHOT_ID))
     {
-        return serde_json::from_slice(&vm_section.snapshot).map_err(|e| {
+        return vm_section.to_state().map_err(|e| {
             MigratableError::Restore(anyhow!("Could not deserialize VM snapshot {}", e))
         });
     }
@@ -84,3 +196,161 @@ pub fn get_vm_snapshot(snapshot: &Snapshot) -> std::result::Result<VmSnapshot, M
         "Could not find VM config snapshot section"
     )))
 }
+
+/// Description of a single snapshot data section.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SnapshotSectionInfo {
+    /// Size of the serialized section, in bytes.
+    pub size: u64,
+    /// VMM version the section was serialized with, for versioned sections.
+    pub version: Option<String>,
+    /// Whether the section matches its recorded checksum, false for the
+    /// sections written without one.
+    pub verified: bool,
+}
+
+/// Read-only description of a snapshot, as stored on disk.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SnapshotInfo {
+    pub config: VmConfig,
+    pub device_tree: DeviceTree,
+    pub memory: MemoryManagerSnapshotData,
+    /// All the data sections, indexed by their path in the snapshot tree.
+    pub sections: BTreeMap<String, SnapshotSectionInfo>,
+}
+
+fn collect_sections(
+    snapshot: &Snapshot,
+    prefix: &str,
+    sections: &mut BTreeMap<String, SnapshotSectionInfo>,
+) {
+    let path = format!("{}/{}", prefix, snapshot.id);
+
+    for (id, section) in snapshot.snapshot_data.iter() {
+        sections.insert(
+            format!("{}/{}", path, id),
+            SnapshotSectionInfo {
+                size: section.len(),
+                version: section.version_string(),
+                verified: section.checksum.is_some() && section.verify().is_ok(),
+            },
+        );
+    }
+
+    for child in snapshot.snapshots.values() {
+        collect_sections(child, &path, sections);
+    }
+}
+
+/// Inspect the snapshot found at the given URL, without creating any VM
+/// from it.
+pub fn snapshot_info(source_url: &str) -> std::result::Result<SnapshotInfo, MigratableError> {
+    let config = recv_vm_config(source_url)?;
+    let snapshot = recv_vm_state(source_url)?;
+
+    let device_tree = DeviceManager::device_tree_from_snapshot(
+        snapshot
+            .snapshots
+            .get(DEVICE_MANAGER_SNAPSHOT_ID)
+            .ok_or_else(|| MigratableError::Restore(anyhow!("Missing DeviceManager snapshot")))?,
+    )?;
+
+    let memory = snapshot
+        .snapshots
+        .get(MEMORY_MANAGER_SNAPSHOT_ID)
+        .ok_or_else(|| MigratableError::Restore(anyhow!("Missing MemoryManager snapshot")))?
+        .to_versioned_state(MEMORY_MANAGER_SNAPSHOT_ID)?;
+
+    let mut sections = BTreeMap::new();
+    collect_sections(&snapshot, "", &mut sections);
+
+    Ok(SnapshotInfo {
+        config,
+        device_tree,
+        memory,
+        sections,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use vm_migration::SnapshotDataSection;
+    use vmm_sys_util::tempdir::TempDir;
+
+    // Write a snapshot holding the state of the device and memory managers,
+    // along with a section without checksum and a corrupted one.
+    fn write_snapshot(directory: &Path) {
+        #[allow(unused_mut)]
+        let mut config = serde_json::json!({
+            "cpus": { "boot_vcpus": 2, "max_vcpus": 4 },
+        });
+        #[cfg(feature = "gdb")]
+        {
+            config["gdb"] = false.into();
+        }
+        let config_file = File::create(directory.join(SNAPSHOT_CONFIG_FILE)).unwrap();
+        serde_json::to_writer(config_file, &config).unwrap();
+
+        let mut snapshot = Snapshot::new(VM_SNAPSHOT_ID);
+        snapshot.add_snapshot(
+            Snapshot::new_from_state(
+                DEVICE_MANAGER_SNAPSHOT_ID,
+                &serde_json::json!({ "device_tree": {}, "device_id_cnt": 0 }),
+            )
+            .unwrap(),
+        );
+        snapshot.add_snapshot(
+            Snapshot::new_from_versioned_state(
+                MEMORY_MANAGER_SNAPSHOT_ID,
+                &MemoryManagerSnapshotData::default(),
+            )
+            .unwrap(),
+        );
+
+        let mut unchecked = SnapshotDataSection::new("unchecked", vec![0xaa; 3]);
+        unchecked.checksum = None;
+        snapshot.add_data_section(unchecked);
+        let mut corrupted = SnapshotDataSection::new("corrupted", vec![0xaa; 16]);
+        corrupted.snapshot.push(0);
+        snapshot.add_data_section(corrupted);
+
+        let state_file = File::create(directory.join(SNAPSHOT_STATE_FILE)).unwrap();
+        let mut state_data_file = File::create(directory.join(SNAPSHOT_STATE_DATA_FILE)).unwrap();
+        snapshot
+            .write_state(state_file, &mut state_data_file)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_info() {
+        let directory = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        write_snapshot(directory.as_path());
+
+        let info = snapshot_info(&format!("file://{}", directory.as_path().display())).unwrap();
+        assert_eq!(info.config.cpus.boot_vcpus, 2);
+        assert_eq!(info.config.cpus.max_vcpus, 4);
+
+        let sections: Vec<(&str, Option<&str>, bool)> = info
+            .sections
+            .iter()
+            .map(|(path, section)| (path.as_str(), section.version.as_deref(), section.verified))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                ("/vm/corrupted-section", None, false),
+                ("/vm/device-manager/device-manager-section", None, true),
+                (
+                    "/vm/memory-manager/memory-manager-section",
+                    Some("24.0"),
+                    true
+                ),
+                ("/vm/unchecked-section", None, false),
+            ]
+        );
+        assert_eq!(info.sections["/vm/corrupted-section"].size, 17);
+        assert_eq!(info.sections["/vm/unchecked-section"].size, 3);
+    }
+}
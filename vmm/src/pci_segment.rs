@@ -12,6 +12,8 @@
 use crate::device_manager::{AddressManager, DeviceManagerError, DeviceManagerResult};
 use acpi_tables::aml::{self, Aml};
 use arch::layout;
+#[cfg(target_arch = "aarch64")]
+use pci::PciIoWindow;
 use pci::{DeviceRelocation, PciBdf, PciBus, PciConfigMmio, PciRoot};
 #[cfg(target_arch = "x86_64")]
 use pci::{PciConfigIo, PCI_CONFIG_IO_PORT, PCI_CONFIG_IO_PORT_SIZE};
@@ -28,6 +30,8 @@ pub(crate) struct PciSegment {
 
     #[cfg(target_arch = "x86_64")]
     pub(crate) pci_config_io: Option<Arc<Mutex<PciConfigIo>>>,
+    #[cfg(target_arch = "aarch64")]
+    pub(crate) pci_io_window: Option<Arc<Mutex<PciIoWindow>>>,
 
     // Bitmap of PCI devices to hotplug.
     pub(crate) pci_devices_up: u32,
@@ -81,6 +85,8 @@ impl PciSegment {
             pci_devices_down: 0,
             #[cfg(target_arch = "x86_64")]
             pci_config_io: None,
+            #[cfg(target_arch = "aarch64")]
+            pci_io_window: None,
             allocator,
             start_of_device_area,
             end_of_device_area,
@@ -123,7 +129,26 @@ impl PciSegment {
         allocator: Arc<Mutex<AddressAllocator>>,
         pci_irq_slots: &[u8; 32],
     ) -> DeviceManagerResult<PciSegment> {
-        Self::new(0, address_manager, allocator, pci_irq_slots)
+        let mut segment = Self::new(0, address_manager, allocator, pci_irq_slots)?;
+
+        // The I/O BARs of the devices are reached through the PCI I/O window
+        // of the default segment.
+        let pci_io_window = Arc::new(Mutex::new(PciIoWindow::new(Arc::clone(
+            &address_manager.io_bus,
+        ))));
+
+        address_manager
+            .mmio_bus
+            .insert(
+                pci_io_window.clone(),
+                layout::MEM_PCI_IO_START.0,
+                layout::MEM_PCI_IO_SIZE,
+            )
+            .map_err(DeviceManagerError::BusError)?;
+
+        segment.pci_io_window = Some(pci_io_window);
+
+        Ok(segment)
     }
 
     pub(crate) fn next_device_bdf(&self) -> DeviceManagerResult<PciBdf> {
@@ -322,16 +347,18 @@ impl Aml for PciSegment {
         pci_dsdt_inner_data.push(&adr);
         let seg = aml::Name::new("_SEG".into(), &self.id);
         pci_dsdt_inner_data.push(&seg);
-        let uid = aml::Name::new("_UID".into(), &aml::ZERO);
+        // Each host bridge is a distinct device, identified by its segment.
+        let uid = aml::Name::new("_UID".into(), &self.id);
         pci_dsdt_inner_data.push(&uid);
         let cca = aml::Name::new("_CCA".into(), &aml::ONE);
         pci_dsdt_inner_data.push(&cca);
         let supp = aml::Name::new("SUPP".into(), &aml::ZERO);
         pci_dsdt_inner_data.push(&supp);
 
-        // Since Cloud Hypervisor supports only one PCI bus, it can be tied
-        // to the NUMA node 0. It's up to the user to organize the NUMA nodes
-        // so that the PCI bus relates to the expected vCPUs and guest RAM.
+        // Since Cloud Hypervisor supports only one PCI bus per segment, the
+        // segments can be tied to the NUMA node 0. It's up to the user to
+        // organize the NUMA nodes so that the PCI buses relate to the expected
+        // vCPUs and guest RAM.
         let proximity_domain = 0u32;
         let pxm_return = aml::Return::new(&proximity_domain);
         let pxm = aml::Method::new("_PXM".into(), 0, false, vec![&pxm_return]);
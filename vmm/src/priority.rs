@@ -0,0 +1,321 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Host tuning profiles bundled by the priority classes of the VMs. A class
+//! sets the scheduling of the vCPU and device threads, the pinning of the
+//! vCPUs, the halt polling of the hypervisor and the backing of the guest
+//! memory together, so that they are consistent with each other.
+//!
+//! The settings explicitly provided by the user, such as the vCPU affinity,
+//! take precedence over the ones of the profile.
+
+use crate::config::{MemoryConfig, PriorityClass};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::thread;
+
+/// Directory of the lock files through which the VMMs of the host claim the
+/// host CPUs they pin vCPUs to, so that VMs don't share them.
+pub const PINNED_CPUS_DIR: &str = "/run/cloud-hypervisor/pinned-cpus";
+
+/// Scheduling policy of a thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// Real-time, first in first out, with the given priority.
+    Fifo(i32),
+    /// Default time sharing, with the given nice value.
+    Other(i32),
+    /// Time sharing for CPU bound batch jobs.
+    Batch,
+    /// Only runs when the CPU would be idle otherwise.
+    Idle,
+}
+
+impl SchedPolicy {
+    /// Applies the policy to the calling thread.
+    pub fn apply(&self) -> io::Result<()> {
+        let (policy, priority, nice) = match *self {
+            SchedPolicy::Fifo(priority) => (libc::SCHED_FIFO, priority, None),
+            SchedPolicy::Other(nice) => (libc::SCHED_OTHER, 0, Some(nice)),
+            SchedPolicy::Batch => (libc::SCHED_BATCH, 0, None),
+            SchedPolicy::Idle => (libc::SCHED_IDLE, 0, None),
+        };
+
+        let param = libc::sched_param {
+            sched_priority: priority,
+        };
+        // SAFETY: FFI call with valid arguments, the PID 0 referring to the
+        // calling thread.
+        if unsafe { libc::sched_setscheduler(0, policy, &param) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if let Some(nice) = nice {
+            // SAFETY: FFI call with valid arguments, the thread ID being the
+            // one of the calling thread.
+            let ret = unsafe {
+                let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+                libc::setpriority(libc::PRIO_PROCESS, tid, nice)
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the policy can be applied, lacking the `CAP_SYS_NICE`
+    /// capability or a suitable rlimit otherwise, by applying it to a short
+    /// lived thread.
+    pub fn check(&self) -> io::Result<()> {
+        let policy = *self;
+        thread::Builder::new()
+            .name("sched_check".to_string())
+            .spawn(move || policy.apply())?
+            .join()
+            .unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "scheduling policy check panicked",
+                ))
+            })
+    }
+}
+
+/// Host tuning applied to a VM of a given priority class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriorityProfile {
+    /// Pin each vCPU to its own host CPU, unless an affinity is provided.
+    pub pin_vcpus: bool,
+    /// Scheduling policy of the vCPU threads.
+    pub vcpu_policy: SchedPolicy,
+    /// Nice value of the device threads, inherited from the VMM if `None`.
+    pub io_nice: Option<i32>,
+    /// Time a halted vCPU polls for a wake up, host default if `None`.
+    pub halt_poll_ns: Option<u32>,
+    /// Back the guest RAM with hugepages.
+    pub hugepages: bool,
+    /// Populate the guest RAM when the VM is created.
+    pub prefault: bool,
+}
+
+impl From<PriorityClass> for PriorityProfile {
+    fn from(class: PriorityClass) -> Self {
+        match class {
+            PriorityClass::Realtime => PriorityProfile {
+                pin_vcpus: true,
+                vcpu_policy: SchedPolicy::Fifo(10),
+                io_nice: Some(-10),
+                halt_poll_ns: Some(500_000),
+                hugepages: true,
+                prefault: true,
+            },
+            PriorityClass::Latency => PriorityProfile {
+                pin_vcpus: true,
+                vcpu_policy: SchedPolicy::Other(-5),
+                io_nice: Some(-5),
+                halt_poll_ns: Some(200_000),
+                hugepages: false,
+                prefault: true,
+            },
+            PriorityClass::Throughput => PriorityProfile {
+                pin_vcpus: false,
+                vcpu_policy: SchedPolicy::Batch,
+                io_nice: None,
+                halt_poll_ns: None,
+                hugepages: true,
+                prefault: false,
+            },
+            PriorityClass::Background => PriorityProfile {
+                pin_vcpus: false,
+                vcpu_policy: SchedPolicy::Idle,
+                io_nice: Some(10),
+                halt_poll_ns: Some(0),
+                hugepages: false,
+                prefault: false,
+            },
+        }
+    }
+}
+
+impl PriorityProfile {
+    /// Bytes of guest RAM the profile backs with hugepages, not counting the
+    /// memory zones already backed by them.
+    pub fn hugepages_needed(&self, memory: &MemoryConfig) -> u64 {
+        if !self.hugepages {
+            return 0;
+        }
+        let ram = if memory.hugepages { 0 } else { memory.size };
+        ram + memory
+            .zones
+            .iter()
+            .flatten()
+            .filter(|zone| zone.file.is_none() && !zone.template && !zone.hugepages)
+            .map(|zone| zone.size)
+            .sum::<u64>()
+    }
+
+    /// Enables the hugepages and the prefaulting of the profile on the guest
+    /// RAM. The memory zones backed by a file are left untouched.
+    pub fn apply_to_memory(&self, memory: &mut MemoryConfig) {
+        memory.hugepages |= self.hugepages;
+        memory.prefault |= self.prefault;
+        for zone in memory
+            .zones
+            .iter_mut()
+            .flatten()
+            .filter(|zone| zone.file.is_none() && !zone.template)
+        {
+            zone.hugepages |= self.hugepages;
+            zone.prefault |= self.prefault;
+        }
+    }
+}
+
+/// Returns the host CPUs the VMM is allowed to run on, in ascending order.
+pub fn allowed_host_cpus() -> io::Result<Vec<u8>> {
+    // SAFETY: zero-initialized value of a plain data type.
+    let mut cpuset: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    // SAFETY: FFI call with valid arguments, the PID 0 referring to the
+    // calling thread.
+    let ret =
+        unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut cpuset) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((0..=u8::MAX)
+        // SAFETY: the CPU index is within the bounds of the set.
+        .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu as usize, &cpuset) })
+        .collect())
+}
+
+/// Host CPU claimed for the pinning of a vCPU, until dropped.
+pub struct HostCpuClaim {
+    cpu: u8,
+    // Held locked for as long as the host CPU is claimed.
+    _lock: File,
+}
+
+impl HostCpuClaim {
+    pub fn cpu(&self) -> u8 {
+        self.cpu
+    }
+}
+
+/// Claims up to `count` of the `host_cpus`, in order, skipping the ones
+/// already claimed through the lock files of `dir` by the other VMs. Less
+/// than `count` host CPUs are returned if not enough of them are free.
+pub fn claim_host_cpus(
+    dir: &Path,
+    host_cpus: &[u8],
+    count: usize,
+) -> io::Result<Vec<HostCpuClaim>> {
+    fs::create_dir_all(dir)?;
+
+    let mut claims = Vec::with_capacity(count);
+    for cpu in host_cpus {
+        if claims.len() == count {
+            break;
+        }
+
+        let lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(dir.join(format!("cpu{}", cpu)))?;
+        // SAFETY: the file descriptor is valid for the lifetime of the file.
+        let ret = unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if ret == 0 {
+            claims.push(HostCpuClaim {
+                cpu: *cpu,
+                _lock: lock,
+            });
+        } else {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MemoryZoneConfig;
+    use std::path::PathBuf;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_apply_to_memory() {
+        let zone = MemoryZoneConfig {
+            id: String::from("mem0"),
+            size: 1 << 30,
+            file: None,
+            shared: false,
+            hugepages: false,
+            hugepage_size: None,
+            host_numa_node: None,
+            hotplug_size: None,
+            hotplugged_size: None,
+            prefault: false,
+            template: false,
+            sealed: false,
+        };
+        let mut memory = MemoryConfig {
+            size: 0,
+            zones: Some(vec![
+                zone.clone(),
+                MemoryZoneConfig {
+                    id: String::from("mem1"),
+                    file: Some(PathBuf::from("/dev/shm/mem1")),
+                    ..zone
+                },
+            ]),
+            ..Default::default()
+        };
+
+        let realtime = PriorityProfile::from(PriorityClass::Realtime);
+        assert_eq!(realtime.hugepages_needed(&memory), 1 << 30);
+        assert_eq!(
+            PriorityProfile::from(PriorityClass::Latency).hugepages_needed(&memory),
+            0
+        );
+
+        realtime.apply_to_memory(&mut memory);
+        assert_eq!(realtime.hugepages_needed(&memory), 0);
+        let zones = memory.zones.as_ref().unwrap();
+        assert!(zones[0].hugepages && zones[0].prefault);
+        assert!(!zones[1].hugepages && !zones[1].prefault);
+
+        // A profile never turns off what the user enabled.
+        PriorityProfile::from(PriorityClass::Background).apply_to_memory(&mut memory);
+        assert!(memory.zones.unwrap()[0].hugepages);
+    }
+
+    #[test]
+    fn test_claim_host_cpus() {
+        let dir = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let host_cpus = [0, 2, 3, 5];
+
+        let first = claim_host_cpus(dir.as_path(), &host_cpus, 2).unwrap();
+        assert_eq!(first.iter().map(|c| c.cpu()).collect::<Vec<_>>(), [0, 2]);
+
+        // Another VM gets the host CPUs left.
+        let second = claim_host_cpus(dir.as_path(), &host_cpus, 3).unwrap();
+        assert_eq!(second.iter().map(|c| c.cpu()).collect::<Vec<_>>(), [3, 5]);
+
+        // The host CPUs are free again once released.
+        drop(first);
+        let third = claim_host_cpus(dir.as_path(), &host_cpus, 4).unwrap();
+        assert_eq!(third.iter().map(|c| c.cpu()).collect::<Vec<_>>(), [0, 2]);
+    }
+}
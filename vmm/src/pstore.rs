@@ -0,0 +1,123 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::io;
+use std::path::Path;
+use vm_memory::GuestAddress;
+
+// Size of each dmesg record, and of the console record. The pstore region
+// is split into dmesg records, followed by a single console record.
+pub const PSTORE_RECORD_SIZE: u64 = 0x10000;
+
+// Every ramoops zone starts with a persistent_ram_buffer header made of the
+// signature, the start offset and the size of the data stored in the zone.
+const PERSISTENT_RAM_SIG: u32 = 0x4347_4244;
+const PERSISTENT_RAM_HEADER_SIZE: usize = 12;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct PstoreRecords {
+    pub dmesg: Vec<String>,
+    pub console: Option<String>,
+}
+
+// Kernel command line parameters describing the pstore region to the guest
+// ramoops driver.
+pub fn cmdline(start: GuestAddress, size: u64) -> String {
+    format!(
+        "ramoops.mem_address=0x{:x} ramoops.mem_size=0x{:x} ramoops.record_size=0x{:x} \
+        ramoops.console_size=0x{:x} ramoops.ecc=0",
+        start.0, size, PSTORE_RECORD_SIZE, PSTORE_RECORD_SIZE
+    )
+}
+
+fn zone_content(zone: &[u8]) -> Option<String> {
+    if zone.len() < PERSISTENT_RAM_HEADER_SIZE {
+        return None;
+    }
+
+    let sig = u32::from_le_bytes(zone[0..4].try_into().unwrap());
+    let start = u32::from_le_bytes(zone[4..8].try_into().unwrap()) as usize;
+    let size = u32::from_le_bytes(zone[8..12].try_into().unwrap()) as usize;
+    let data = &zone[PERSISTENT_RAM_HEADER_SIZE..];
+
+    if sig != PERSISTENT_RAM_SIG || size == 0 || size > data.len() || start > size {
+        return None;
+    }
+
+    // Once the zone has wrapped around, the oldest data starts right after
+    // the current write position.
+    let mut content = data[start..size].to_vec();
+    content.extend_from_slice(&data[..start]);
+
+    Some(String::from_utf8_lossy(&content).into_owned())
+}
+
+pub fn parse_records(region: &[u8]) -> PstoreRecords {
+    let record_size = PSTORE_RECORD_SIZE as usize;
+    let (dmesg_zones, console_zone) = region.split_at(region.len().saturating_sub(record_size));
+
+    PstoreRecords {
+        dmesg: dmesg_zones
+            .chunks_exact(record_size)
+            .filter_map(zone_content)
+            .collect(),
+        console: zone_content(console_zone),
+    }
+}
+
+// Read the records straight from the backing file, which is shared with the
+// guest mapping. This works whether the VM is running or not.
+pub fn read_records(path: &Path, size: u64) -> io::Result<PstoreRecords> {
+    let mut region = std::fs::read(path)?;
+    region.resize(size as usize, 0);
+
+    Ok(parse_records(&region))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_zone(region: &mut [u8], offset: usize, start: u32, data: &[u8]) {
+        region[offset..offset + 4].copy_from_slice(&PERSISTENT_RAM_SIG.to_le_bytes());
+        region[offset + 4..offset + 8].copy_from_slice(&start.to_le_bytes());
+        region[offset + 8..offset + 12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        let data_offset = offset + PERSISTENT_RAM_HEADER_SIZE;
+        region[data_offset..data_offset + data.len()].copy_from_slice(data);
+    }
+
+    #[test]
+    fn test_parse_records() {
+        let record_size = PSTORE_RECORD_SIZE as usize;
+        let mut region = vec![0u8; 4 * record_size];
+
+        // Empty region
+        assert_eq!(parse_records(&region), PstoreRecords::default());
+
+        write_zone(&mut region, 0, 10, b"Oops: 0000");
+        write_zone(&mut region, 2 * record_size, 11, b"bootconsole");
+        // The console zone wrapped around
+        write_zone(&mut region, 3 * record_size, 7, b"world!\nhello, ");
+
+        assert_eq!(
+            parse_records(&region),
+            PstoreRecords {
+                dmesg: vec!["Oops: 0000".to_owned(), "bootconsole".to_owned()],
+                console: Some("hello, world!\n".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_cmdline() {
+        assert_eq!(
+            cmdline(GuestAddress(0x1_0020_0000), 0x10_0000),
+            "ramoops.mem_address=0x100200000 ramoops.mem_size=0x100000 \
+            ramoops.record_size=0x10000 ramoops.console_size=0x10000 ramoops.ecc=0"
+        );
+    }
+}
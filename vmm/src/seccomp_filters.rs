@@ -12,12 +12,29 @@ use std::convert::TryInto;
 
 pub enum Thread {
     Api,
-    SignalHandler,
+    EventStream,
+    Fleet,
+    Metrics,
     Vcpu,
     Vmm,
     PtyForeground,
 }
 
+impl Thread {
+    /// Name of the thread type, as used by the seccomp policy file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Thread::Api => "api",
+            Thread::EventStream => "event-stream",
+            Thread::Fleet => "fleet",
+            Thread::Metrics => "metrics",
+            Thread::Vcpu => "vcpu",
+            Thread::Vmm => "vmm",
+            Thread::PtyForeground => "pty-foreground",
+        }
+    }
+}
+
 /// Shorthand for chaining `SeccompCondition`s with the `and` operator  in a `SeccompRule`.
 /// The rule will take the `Allow` action if _all_ the conditions are true.
 ///
@@ -89,6 +106,7 @@ const VFIO_DEVICE_IOEVENTFD: u64 = 0x3b74;
 const VHOST_GET_FEATURES: u64 = 0x8008af00;
 const VHOST_SET_FEATURES: u64 = 0x4008af00;
 const VHOST_SET_OWNER: u64 = 0xaf01;
+const VHOST_SET_MEM_TABLE: u64 = 0x4008af03;
 const VHOST_SET_VRING_NUM: u64 = 0x4008af10;
 const VHOST_SET_VRING_ADDR: u64 = 0x4028af11;
 const VHOST_SET_VRING_BASE: u64 = 0x4008af12;
@@ -96,6 +114,7 @@ const VHOST_SET_VRING_KICK: u64 = 0x4008af20;
 const VHOST_SET_VRING_CALL: u64 = 0x4008af21;
 const VHOST_SET_BACKEND_FEATURES: u64 = 0x4008af25;
 const VHOST_GET_BACKEND_FEATURES: u64 = 0x8008af26;
+const VHOST_NET_SET_BACKEND: u64 = 0x4008af30;
 const VHOST_VDPA_GET_DEVICE_ID: u64 = 0x8004af70;
 const VHOST_VDPA_GET_STATUS: u64 = 0x8001af71;
 const VHOST_VDPA_SET_STATUS: u64 = 0x4001af72;
@@ -125,6 +144,7 @@ mod kvm {
     pub const KVM_IRQFD: u64 = 0x4020_ae76;
     pub const KVM_IOEVENTFD: u64 = 0x4040_ae79;
     pub const KVM_SET_VCPU_EVENTS: u64 = 0x4040_aea0;
+    pub const KVM_SIGNAL_MSI: u64 = 0x4020_aea5;
     pub const KVM_ENABLE_CAP: u64 = 0x4068_aea3;
     pub const KVM_SET_REGS: u64 = 0x4090_ae82;
     pub const KVM_GET_MP_STATE: u64 = 0x8004_ae98;
@@ -137,6 +157,10 @@ mod kvm {
     pub const KVM_CREATE_DEVICE: u64 = 0xc00c_aee0;
     pub const KVM_GET_REG_LIST: u64 = 0xc008_aeb0;
     pub const KVM_MEMORY_ENCRYPT_OP: u64 = 0xc008_aeba;
+    pub const KVM_SET_USER_MEMORY_REGION2: u64 = 0x40a8_ae49;
+    pub const KVM_SET_MEMORY_ATTRIBUTES: u64 = 0x4020_aed2;
+    pub const KVM_CREATE_GUEST_MEMFD: u64 = 0xc040_aed4;
+    pub const KVM_RESET_DIRTY_RINGS: u64 = 0xaec7;
 }
 
 #[cfg(feature = "kvm")]
@@ -156,6 +180,7 @@ mod mshv {
     pub const MSHV_GET_VP_REGISTERS: u64 = 0xc010_b805;
     pub const MSHV_SET_VP_REGISTERS: u64 = 0x4010_b806;
     pub const MSHV_RUN_VP: u64 = 0x8100_b807;
+    pub const MSHV_ASSERT_INTERRUPT: u64 = 0x4018_b809;
     pub const MSHV_GET_VP_STATE: u64 = 0xc028_b80a;
     pub const MSHV_SET_VP_STATE: u64 = 0xc028_b80b;
     pub const MSHV_SET_PARTITION_PROPERTY: u64 = 0x4010_b80c;
@@ -179,6 +204,7 @@ fn create_vmm_ioctl_seccomp_rule_common_mshv() -> Result<Vec<SeccompRule>, Backe
         and![Cond::new(1, ArgLen::Dword, Eq, MSHV_GET_VP_REGISTERS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, MSHV_SET_VP_REGISTERS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, MSHV_RUN_VP)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, MSHV_ASSERT_INTERRUPT)?],
         and![Cond::new(1, ArgLen::Dword, Eq, MSHV_GET_VP_STATE)?],
         and![Cond::new(1, ArgLen::Dword, Eq, MSHV_SET_VP_STATE)?],
         and![Cond::new(
@@ -215,6 +241,9 @@ fn create_vmm_ioctl_seccomp_rule_common_kvm() -> Result<Vec<SeccompRule>, Backen
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_IRQFD)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_RUN)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_MEMORY_ENCRYPT_OP)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_CREATE_GUEST_MEMFD)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_MEMORY_ATTRIBUTES)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_RESET_DIRTY_RINGS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_DEVICE_ATTR,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_HAS_DEVICE_ATTR,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_GSI_ROUTING)?],
@@ -222,7 +251,14 @@ fn create_vmm_ioctl_seccomp_rule_common_kvm() -> Result<Vec<SeccompRule>, Backen
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_ONE_REG)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_REGS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_USER_MEMORY_REGION,)?],
+        and![Cond::new(
+            1,
+            ArgLen::Dword,
+            Eq,
+            KVM_SET_USER_MEMORY_REGION2,
+        )?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_VCPU_EVENTS,)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_SIGNAL_MSI)?],
     ])
 }
 
@@ -285,6 +321,7 @@ fn create_vmm_ioctl_seccomp_rule_common() -> Result<Vec<SeccompRule>, BackendErr
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_GET_FEATURES)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_FEATURES)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_OWNER)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_MEM_TABLE)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_NUM)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_ADDR)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_BASE)?],
@@ -292,6 +329,7 @@ fn create_vmm_ioctl_seccomp_rule_common() -> Result<Vec<SeccompRule>, BackendErr
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_VRING_CALL)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_SET_BACKEND_FEATURES)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_GET_BACKEND_FEATURES)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, VHOST_NET_SET_BACKEND)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_GET_DEVICE_ID)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_GET_STATUS)?],
         and![Cond::new(1, ArgLen::Dword, Eq, VHOST_VDPA_SET_STATUS)?],
@@ -406,32 +444,8 @@ fn create_api_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, BackendError> {
     Ok(or![and![Cond::new(1, ArgLen::Dword, Eq, FIONBIO)?]])
 }
 
-fn create_signal_handler_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, BackendError> {
-    Ok(or![
-        and![Cond::new(1, ArgLen::Dword, Eq, TCGETS)?],
-        and![Cond::new(1, ArgLen::Dword, Eq, TCSETS)?],
-        and![Cond::new(1, ArgLen::Dword, Eq, TIOCGWINSZ)?],
-    ])
-}
-
-fn signal_handler_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
-    Ok(vec![
-        (libc::SYS_brk, vec![]),
-        (libc::SYS_close, vec![]),
-        (libc::SYS_exit, vec![]),
-        (libc::SYS_exit_group, vec![]),
-        (libc::SYS_futex, vec![]),
-        (libc::SYS_ioctl, create_signal_handler_ioctl_seccomp_rule()?),
-        (libc::SYS_madvise, vec![]),
-        (libc::SYS_mmap, vec![]),
-        (libc::SYS_munmap, vec![]),
-        (libc::SYS_recvfrom, vec![]),
-        (libc::SYS_rt_sigprocmask, vec![]),
-        (libc::SYS_rt_sigreturn, vec![]),
-        (libc::SYS_sendto, vec![]),
-        (libc::SYS_sigaltstack, vec![]),
-        (libc::SYS_write, vec![]),
-    ])
+fn create_event_stream_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, BackendError> {
+    Ok(or![and![Cond::new(1, ArgLen::Dword, Eq, FIONBIO)?]])
 }
 
 fn create_pty_foreground_ioctl_seccomp_rule() -> Result<Vec<SeccompRule>, BackendError> {
@@ -490,6 +504,7 @@ fn vmm_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         (libc::SYS_fallocate, vec![]),
         (libc::SYS_fcntl, vec![]),
         (libc::SYS_fdatasync, vec![]),
+        (libc::SYS_flock, vec![]),
         (libc::SYS_fstat, vec![]),
         (libc::SYS_fsync, vec![]),
         (libc::SYS_ftruncate, vec![]),
@@ -498,6 +513,7 @@ fn vmm_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         #[cfg(target_arch = "aarch64")]
         (libc::SYS_newfstatat, vec![]),
         (libc::SYS_futex, vec![]),
+        (libc::SYS_getdents64, vec![]),
         (libc::SYS_getpgid, vec![]),
         #[cfg(target_arch = "x86_64")]
         (libc::SYS_getpgrp, vec![]),
@@ -516,6 +532,9 @@ fn vmm_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         (libc::SYS_madvise, vec![]),
         (libc::SYS_mbind, vec![]),
         (libc::SYS_memfd_create, vec![]),
+        #[cfg(target_arch = "x86_64")]
+        (libc::SYS_mkdir, vec![]),
+        (libc::SYS_mkdirat, vec![]),
         (libc::SYS_mmap, vec![]),
         (libc::SYS_mprotect, vec![]),
         (libc::SYS_mremap, vec![]),
@@ -544,6 +563,10 @@ fn vmm_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         (libc::SYS_readlinkat, vec![]),
         (libc::SYS_recvfrom, vec![]),
         (libc::SYS_recvmsg, vec![]),
+        #[cfg(target_arch = "x86_64")]
+        (libc::SYS_rename, vec![]),
+        #[cfg(target_arch = "aarch64")]
+        (libc::SYS_renameat, vec![]),
         (libc::SYS_restart_syscall, vec![]),
         // musl is missing this constant
         // (libc::SYS_rseq, vec![]),
@@ -556,9 +579,13 @@ fn vmm_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         (libc::SYS_rt_sigreturn, vec![]),
         (libc::SYS_sched_getaffinity, vec![]),
         (libc::SYS_sched_setaffinity, vec![]),
+        // Scheduling of the vCPU and device threads, inheriting this filter,
+        // set by the priority class of the VM.
+        (libc::SYS_sched_setscheduler, vec![]),
         (libc::SYS_sendmsg, vec![]),
         (libc::SYS_sendto, vec![]),
         (libc::SYS_set_robust_list, vec![]),
+        (libc::SYS_setpriority, vec![]),
         (libc::SYS_setsid, vec![]),
         (libc::SYS_shutdown, vec![]),
         (libc::SYS_sigaltstack, vec![]),
@@ -600,7 +627,15 @@ fn create_vcpu_ioctl_seccomp_rule_kvm() -> Result<Vec<SeccompRule>, BackendError
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_DEVICE_ATTR,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_GSI_ROUTING,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_USER_MEMORY_REGION,)?],
+        and![Cond::new(
+            1,
+            ArgLen::Dword,
+            Eq,
+            KVM_SET_USER_MEMORY_REGION2,
+        )?],
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_SET_MEMORY_ATTRIBUTES,)?],
         and![Cond::new(1, ArgLen::Dword, Eq, KVM_RUN,)?],
+        and![Cond::new(1, ArgLen::Dword, Eq, KVM_RESET_DIRTY_RINGS,)?],
     ])
 }
 
@@ -688,6 +723,8 @@ fn vcpu_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
         (libc::SYS_shutdown, vec![]),
         (libc::SYS_sigaltstack, vec![]),
         (libc::SYS_tgkill, vec![]),
+        (libc::SYS_timer_delete, vec![]),
+        (libc::SYS_timer_settime, vec![]),
         (libc::SYS_tkill, vec![]),
         #[cfg(target_arch = "x86_64")]
         (libc::SYS_unlink, vec![]),
@@ -727,14 +764,106 @@ fn api_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
     ])
 }
 
+fn metrics_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
+    Ok(vec![
+        (libc::SYS_accept4, vec![]),
+        (libc::SYS_brk, vec![]),
+        (libc::SYS_close, vec![]),
+        (libc::SYS_exit, vec![]),
+        (libc::SYS_fcntl, vec![]),
+        (libc::SYS_futex, vec![]),
+        (libc::SYS_madvise, vec![]),
+        (libc::SYS_mmap, vec![]),
+        (libc::SYS_mprotect, vec![]),
+        (libc::SYS_munmap, vec![]),
+        (libc::SYS_read, vec![]),
+        (libc::SYS_recvfrom, vec![]),
+        (libc::SYS_sendto, vec![]),
+        (libc::SYS_setsockopt, vec![]),
+        (libc::SYS_sigaltstack, vec![]),
+        (libc::SYS_write, vec![]),
+    ])
+}
+
+// The filter containing the white listed syscall rules required by the event
+// stream thread to accept its clients.
+fn event_stream_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
+    Ok(vec![
+        (libc::SYS_accept4, vec![]),
+        (libc::SYS_brk, vec![]),
+        (libc::SYS_clock_nanosleep, vec![]),
+        (libc::SYS_close, vec![]),
+        (libc::SYS_exit, vec![]),
+        (libc::SYS_fcntl, vec![]),
+        (libc::SYS_futex, vec![]),
+        (libc::SYS_ioctl, create_event_stream_ioctl_seccomp_rule()?),
+        (libc::SYS_madvise, vec![]),
+        (libc::SYS_mmap, vec![]),
+        (libc::SYS_mprotect, vec![]),
+        (libc::SYS_munmap, vec![]),
+        (libc::SYS_sigaltstack, vec![]),
+        (libc::SYS_write, vec![]),
+    ])
+}
+
+// The filter containing the white listed syscall rules required by the fleet
+// threads to send the heartbeats and to serve the registry.
+fn fleet_thread_rules() -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
+    Ok(vec![
+        (libc::SYS_accept4, vec![]),
+        (libc::SYS_brk, vec![]),
+        (libc::SYS_clock_gettime, vec![]),
+        (libc::SYS_clock_nanosleep, vec![]),
+        (libc::SYS_clone, vec![]),
+        (libc::SYS_clone3, vec![]),
+        (libc::SYS_close, vec![]),
+        (libc::SYS_connect, vec![]),
+        (libc::SYS_exit, vec![]),
+        (libc::SYS_fcntl, vec![]),
+        (libc::SYS_futex, vec![]),
+        (libc::SYS_geteuid, vec![]),
+        (libc::SYS_getsockopt, vec![]),
+        (libc::SYS_madvise, vec![]),
+        (libc::SYS_mmap, vec![]),
+        (libc::SYS_mprotect, vec![]),
+        (libc::SYS_munmap, vec![]),
+        (libc::SYS_nanosleep, vec![]),
+        (libc::SYS_newfstatat, vec![]),
+        (libc::SYS_prctl, vec![]),
+        (libc::SYS_read, vec![]),
+        (libc::SYS_recvfrom, vec![]),
+        (libc::SYS_restart_syscall, vec![]),
+        (libc::SYS_rt_sigprocmask, vec![]),
+        (libc::SYS_sched_getaffinity, vec![]),
+        (libc::SYS_sendto, vec![]),
+        (libc::SYS_set_robust_list, vec![]),
+        (libc::SYS_setsockopt, vec![]),
+        (libc::SYS_sigaltstack, vec![]),
+        (libc::SYS_socket, vec![]),
+        #[cfg(target_arch = "x86_64")]
+        (libc::SYS_stat, vec![]),
+        (libc::SYS_statx, vec![]),
+        (libc::SYS_write, vec![]),
+    ])
+}
+
 fn get_seccomp_rules(thread_type: Thread) -> Result<Vec<(i64, Vec<SeccompRule>)>, BackendError> {
-    match thread_type {
-        Thread::Api => Ok(api_thread_rules()?),
-        Thread::SignalHandler => Ok(signal_handler_thread_rules()?),
-        Thread::Vcpu => Ok(vcpu_thread_rules()?),
-        Thread::Vmm => Ok(vmm_thread_rules()?),
-        Thread::PtyForeground => Ok(pty_foreground_thread_rules()?),
-    }
+    let thread_name = thread_type.name();
+    let mut rules = match thread_type {
+        Thread::Api => api_thread_rules()?,
+        Thread::EventStream => event_stream_thread_rules()?,
+        Thread::Fleet => fleet_thread_rules()?,
+        Thread::Metrics => metrics_thread_rules()?,
+        Thread::Vcpu => vcpu_thread_rules()?,
+        Thread::Vmm => vmm_thread_rules()?,
+        Thread::PtyForeground => pty_foreground_thread_rules()?,
+    };
+    // Appended last so that they replace the conditional rules of the same
+    // syscalls when collected into the filter.
+    rules.append(&mut virtio_devices::seccomp_filters::extra_syscalls(
+        thread_name,
+    ));
+    Ok(rules)
 }
 
 /// Generate a BPF program based on the seccomp_action value
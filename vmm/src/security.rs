@@ -0,0 +1,274 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use crate::config::SeccompLevel;
+use crate::seccomp_filters::Thread;
+use seccompiler::SeccompAction;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+/// Security mitigations active for a running VM, as reported by the
+/// `vm.security-status` API.
+#[derive(Clone, Debug, Serialize)]
+pub struct SecurityStatus {
+    /// Seccomp level applied to the threads created for the VM.
+    pub seccomp: SeccompLevel,
+    /// Seccomp level applied to the threads of the VMM itself.
+    pub vmm_seccomp: SeccompLevel,
+    /// Whether the configuration requires every device to be placed behind
+    /// the virtual IOMMU.
+    pub require_iommu: bool,
+    pub devices: Vec<DeviceIommuStatus>,
+}
+
+/// IOMMU protection of a PCI device.
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceIommuStatus {
+    pub id: String,
+    pub bdf: String,
+    pub iommu: bool,
+}
+
+/// Errors associated with the seccomp policy file.
+#[derive(Debug, Error)]
+pub enum SeccompPolicyError {
+    #[error("Cannot read seccomp policy file: {0}")]
+    Read(#[source] io::Error),
+
+    #[error("Cannot parse seccomp policy file: {0}")]
+    Parse(#[source] serde_json::Error),
+
+    #[error("Unknown thread type {0:?} in the seccomp policy")]
+    UnknownThread(String),
+
+    #[error("Unknown syscall {1:?} for the thread type {0:?} in the seccomp policy")]
+    UnknownSyscall(String, String),
+}
+
+// Threads of the VMM created for the VM, whose filters can be extended by
+// the seccomp policy. The other ones are created with the VMM, before the
+// policy is known.
+const POLICY_VMM_THREADS: [Thread; 1] = [Thread::Vcpu];
+
+// Syscalls which can be allowed by the seccomp policy.
+const SYSCALLS: &[(&str, i64)] = &[
+    ("accept", libc::SYS_accept),
+    ("accept4", libc::SYS_accept4),
+    #[cfg(target_arch = "x86_64")]
+    ("access", libc::SYS_access),
+    ("bind", libc::SYS_bind),
+    ("brk", libc::SYS_brk),
+    ("capget", libc::SYS_capget),
+    ("capset", libc::SYS_capset),
+    ("chdir", libc::SYS_chdir),
+    ("clock_getres", libc::SYS_clock_getres),
+    ("clock_gettime", libc::SYS_clock_gettime),
+    ("clock_nanosleep", libc::SYS_clock_nanosleep),
+    ("clone", libc::SYS_clone),
+    ("close", libc::SYS_close),
+    ("connect", libc::SYS_connect),
+    ("copy_file_range", libc::SYS_copy_file_range),
+    ("dup", libc::SYS_dup),
+    #[cfg(target_arch = "x86_64")]
+    ("dup2", libc::SYS_dup2),
+    ("dup3", libc::SYS_dup3),
+    ("epoll_create1", libc::SYS_epoll_create1),
+    ("epoll_ctl", libc::SYS_epoll_ctl),
+    ("epoll_pwait", libc::SYS_epoll_pwait),
+    #[cfg(target_arch = "x86_64")]
+    ("epoll_wait", libc::SYS_epoll_wait),
+    ("eventfd2", libc::SYS_eventfd2),
+    ("exit", libc::SYS_exit),
+    ("exit_group", libc::SYS_exit_group),
+    ("faccessat", libc::SYS_faccessat),
+    ("fadvise64", libc::SYS_fadvise64),
+    ("fallocate", libc::SYS_fallocate),
+    ("fchmod", libc::SYS_fchmod),
+    ("fchown", libc::SYS_fchown),
+    ("fcntl", libc::SYS_fcntl),
+    ("fdatasync", libc::SYS_fdatasync),
+    ("flock", libc::SYS_flock),
+    ("fstat", libc::SYS_fstat),
+    ("fstatfs", libc::SYS_fstatfs),
+    ("fsync", libc::SYS_fsync),
+    ("ftruncate", libc::SYS_ftruncate),
+    ("futex", libc::SYS_futex),
+    ("getcwd", libc::SYS_getcwd),
+    ("getdents64", libc::SYS_getdents64),
+    ("getegid", libc::SYS_getegid),
+    ("geteuid", libc::SYS_geteuid),
+    ("getgid", libc::SYS_getgid),
+    ("getpeername", libc::SYS_getpeername),
+    ("getpid", libc::SYS_getpid),
+    ("getppid", libc::SYS_getppid),
+    ("getrandom", libc::SYS_getrandom),
+    ("getrlimit", libc::SYS_getrlimit),
+    ("getsockname", libc::SYS_getsockname),
+    ("getsockopt", libc::SYS_getsockopt),
+    ("gettid", libc::SYS_gettid),
+    ("gettimeofday", libc::SYS_gettimeofday),
+    ("getuid", libc::SYS_getuid),
+    ("io_uring_enter", libc::SYS_io_uring_enter),
+    ("io_uring_register", libc::SYS_io_uring_register),
+    ("io_uring_setup", libc::SYS_io_uring_setup),
+    ("ioctl", libc::SYS_ioctl),
+    ("kill", libc::SYS_kill),
+    ("listen", libc::SYS_listen),
+    ("lseek", libc::SYS_lseek),
+    ("madvise", libc::SYS_madvise),
+    ("mbind", libc::SYS_mbind),
+    ("memfd_create", libc::SYS_memfd_create),
+    ("mincore", libc::SYS_mincore),
+    ("mkdirat", libc::SYS_mkdirat),
+    ("mlock", libc::SYS_mlock),
+    ("mmap", libc::SYS_mmap),
+    ("mprotect", libc::SYS_mprotect),
+    ("mremap", libc::SYS_mremap),
+    ("msync", libc::SYS_msync),
+    ("munlock", libc::SYS_munlock),
+    ("munmap", libc::SYS_munmap),
+    ("nanosleep", libc::SYS_nanosleep),
+    ("newfstatat", libc::SYS_newfstatat),
+    #[cfg(target_arch = "x86_64")]
+    ("open", libc::SYS_open),
+    ("openat", libc::SYS_openat),
+    ("pipe2", libc::SYS_pipe2),
+    #[cfg(target_arch = "x86_64")]
+    ("poll", libc::SYS_poll),
+    ("ppoll", libc::SYS_ppoll),
+    ("prctl", libc::SYS_prctl),
+    ("pread64", libc::SYS_pread64),
+    ("preadv", libc::SYS_preadv),
+    ("prlimit64", libc::SYS_prlimit64),
+    ("pwrite64", libc::SYS_pwrite64),
+    ("pwritev", libc::SYS_pwritev),
+    ("read", libc::SYS_read),
+    ("readlinkat", libc::SYS_readlinkat),
+    ("readv", libc::SYS_readv),
+    ("recvfrom", libc::SYS_recvfrom),
+    ("recvmsg", libc::SYS_recvmsg),
+    ("renameat", libc::SYS_renameat),
+    ("rt_sigaction", libc::SYS_rt_sigaction),
+    ("rt_sigprocmask", libc::SYS_rt_sigprocmask),
+    ("rt_sigreturn", libc::SYS_rt_sigreturn),
+    ("sched_getaffinity", libc::SYS_sched_getaffinity),
+    ("sched_setaffinity", libc::SYS_sched_setaffinity),
+    ("sched_yield", libc::SYS_sched_yield),
+    ("sendmsg", libc::SYS_sendmsg),
+    ("sendto", libc::SYS_sendto),
+    ("set_robust_list", libc::SYS_set_robust_list),
+    ("setsockopt", libc::SYS_setsockopt),
+    ("shutdown", libc::SYS_shutdown),
+    ("sigaltstack", libc::SYS_sigaltstack),
+    ("socket", libc::SYS_socket),
+    ("socketpair", libc::SYS_socketpair),
+    ("statx", libc::SYS_statx),
+    ("tgkill", libc::SYS_tgkill),
+    ("timerfd_create", libc::SYS_timerfd_create),
+    ("timerfd_gettime", libc::SYS_timerfd_gettime),
+    ("timerfd_settime", libc::SYS_timerfd_settime),
+    ("umask", libc::SYS_umask),
+    ("uname", libc::SYS_uname),
+    ("unlinkat", libc::SYS_unlinkat),
+    ("wait4", libc::SYS_wait4),
+    ("write", libc::SYS_write),
+    ("writev", libc::SYS_writev),
+];
+
+pub fn seccomp_action(level: SeccompLevel) -> SeccompAction {
+    match level {
+        SeccompLevel::Trap => SeccompAction::Trap,
+        SeccompLevel::Log => SeccompAction::Log,
+        SeccompLevel::Allow => SeccompAction::Allow,
+    }
+}
+
+pub fn seccomp_level(action: &SeccompAction) -> SeccompLevel {
+    match action {
+        SeccompAction::Allow => SeccompLevel::Allow,
+        SeccompAction::Log => SeccompLevel::Log,
+        // Any other action denies the system calls missing from the filters.
+        _ => SeccompLevel::Trap,
+    }
+}
+
+// Resolves the syscall names of the policy, indexed by thread type, after
+// checking the thread types have filters which can be extended.
+fn parse_seccomp_policy(policy: &str) -> Result<HashMap<String, Vec<i64>>, SeccompPolicyError> {
+    let policy: HashMap<String, Vec<String>> =
+        serde_json::from_str(policy).map_err(SeccompPolicyError::Parse)?;
+
+    let mut extra_syscalls = HashMap::new();
+    for (thread, syscalls) in policy {
+        let known_thread = POLICY_VMM_THREADS
+            .iter()
+            .map(|t| t.name())
+            .chain(
+                virtio_devices::seccomp_filters::Thread::ALL
+                    .iter()
+                    .map(|t| t.name()),
+            )
+            .any(|name| name == thread);
+        if !known_thread {
+            return Err(SeccompPolicyError::UnknownThread(thread));
+        }
+
+        let mut numbers = Vec::new();
+        for syscall in syscalls {
+            match SYSCALLS.iter().find(|(name, _)| *name == syscall) {
+                Some((_, nr)) => numbers.push(*nr),
+                None => return Err(SeccompPolicyError::UnknownSyscall(thread, syscall)),
+            }
+        }
+        extra_syscalls.insert(thread, numbers);
+    }
+
+    Ok(extra_syscalls)
+}
+
+/// Loads the seccomp policy file, a JSON object listing the syscalls to
+/// allow for each thread type on top of the built-in filters, and registers
+/// them for the filters created afterwards.
+pub fn load_seccomp_policy(path: &Path) -> Result<(), SeccompPolicyError> {
+    let policy = fs::read_to_string(path).map_err(SeccompPolicyError::Read)?;
+    let extra_syscalls = parse_seccomp_policy(&policy)?;
+    virtio_devices::seccomp_filters::set_extra_syscalls(extra_syscalls);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_seccomp_policy() {
+        let extra_syscalls = parse_seccomp_policy(
+            r#"{"virtio-vhost-block": ["getrandom", "fadvise64"], "vcpu": []}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            extra_syscalls.get("virtio-vhost-block"),
+            Some(&vec![libc::SYS_getrandom, libc::SYS_fadvise64])
+        );
+        assert_eq!(extra_syscalls.get("vcpu"), Some(&Vec::new()));
+
+        assert!(matches!(
+            parse_seccomp_policy(r#"{"vmm": ["getrandom"]}"#),
+            Err(SeccompPolicyError::UnknownThread(t)) if t == "vmm"
+        ));
+        assert!(matches!(
+            parse_seccomp_policy(r#"{"virtio-block": ["execve"]}"#),
+            Err(SeccompPolicyError::UnknownSyscall(_, s)) if s == "execve"
+        ));
+        assert!(matches!(
+            parse_seccomp_policy(r#"["getrandom"]"#),
+            Err(SeccompPolicyError::Parse(_))
+        ));
+    }
+}
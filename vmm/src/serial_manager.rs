@@ -10,15 +10,12 @@ use crate::serial_buffer::SerialBuffer;
 use devices::legacy::Pl011;
 #[cfg(target_arch = "x86_64")]
 use devices::legacy::Serial;
-use libc::EFD_NONBLOCK;
 use std::fs::File;
 use std::io::Read;
-use std::os::unix::io::{AsRawFd, FromRawFd};
-use std::panic::AssertUnwindSafe;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::sync::{Arc, Mutex};
-use std::{io, result, thread};
+use std::{io, result};
 use thiserror::Error;
-use vmm_sys_util::eventfd::EventFd;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -45,14 +42,6 @@ pub enum Error {
     /// Cannot make the file descriptor non-blocking.
     #[error("Error making input file descriptor non-blocking: {0}")]
     SetNonBlocking(#[source] io::Error),
-
-    /// Cannot create EventFd.
-    #[error("Error creating EventFd: {0}")]
-    EventFd(#[source] io::Error),
-
-    /// Cannot spawn SerialManager thread.
-    #[error("Error spawning SerialManager thread: {0}")]
-    SpawnSerialManager(#[source] io::Error),
 }
 pub type Result<T> = result::Result<T, Error>;
 
@@ -60,7 +49,6 @@ pub type Result<T> = result::Result<T, Error>;
 #[repr(u64)]
 pub enum EpollDispatch {
     File = 0,
-    Kill = 1,
     Unknown,
 }
 
@@ -69,12 +57,15 @@ impl From<u64> for EpollDispatch {
         use EpollDispatch::*;
         match v {
             0 => File,
-            1 => Kill,
             _ => Unknown,
         }
     }
 }
 
+/// Forwards the input of the console to the serial device, and flushes its
+/// buffered output. The events are polled through the epoll file descriptor
+/// of the serial manager, by the control loop of the VMM.
+
 pub struct SerialManager {
     #[cfg(target_arch = "x86_64")]
     serial: Arc<Mutex<Serial>>,
@@ -82,8 +73,6 @@ pub struct SerialManager {
     serial: Arc<Mutex<Pl011>>,
     epoll_file: File,
     in_file: File,
-    kill_evt: EventFd,
-    handle: Option<thread::JoinHandle<()>>,
 }
 
 impl SerialManager {
@@ -129,15 +118,6 @@ impl SerialManager {
         };
 
         let epoll_fd = epoll::create(true).map_err(Error::Epoll)?;
-        let kill_evt = EventFd::new(EFD_NONBLOCK).map_err(Error::EventFd)?;
-
-        epoll::ctl(
-            epoll_fd,
-            epoll::ControlOptions::EPOLL_CTL_ADD,
-            kill_evt.as_raw_fd(),
-            epoll::Event::new(epoll::Events::EPOLLIN, EpollDispatch::Kill as u64),
-        )
-        .map_err(Error::Epoll)?;
 
         epoll::ctl(
             epoll_fd,
@@ -162,109 +142,64 @@ impl SerialManager {
             serial,
             epoll_file,
             in_file,
-            kill_evt,
-            handle: None,
         }))
     }
 
-    pub fn start_thread(&mut self, exit_evt: EventFd) -> Result<()> {
-        // Don't allow this to be run if the handle exists
-        if self.handle.is_some() {
-            warn!("Tried to start multiple SerialManager threads, ignoring");
-            return Ok(());
-        }
-
-        let epoll_fd = self.epoll_file.as_raw_fd();
-        let mut in_file = self.in_file.try_clone().map_err(Error::FileClone)?;
-        let serial = self.serial.clone();
-
-        let thread = thread::Builder::new()
-            .name("serial-manager".to_string())
-            .spawn(move || {
-                std::panic::catch_unwind(AssertUnwindSafe(move || {
-                    // 3 for File, Kill, and Unknown
-                    const EPOLL_EVENTS_LEN: usize = 3;
-
-                    let mut events =
-                        vec![epoll::Event::new(epoll::Events::empty(), 0); EPOLL_EVENTS_LEN];
-
-                    loop {
-                        let num_events = match epoll::wait(epoll_fd, -1, &mut events[..]) {
-                            Ok(res) => res,
-                            Err(e) => {
-                                if e.kind() == io::ErrorKind::Interrupted {
-                                    // It's well defined from the epoll_wait() syscall
-                                    // documentation that the epoll loop can be interrupted
-                                    // before any of the requested events occurred or the
-                                    // timeout expired. In both those cases, epoll_wait()
-                                    // returns an error of type EINTR, but this should not
-                                    // be considered as a regular error. Instead it is more
-                                    // appropriate to retry, by calling into epoll_wait().
-                                    continue;
-                                }
-                                return Err(Error::Epoll(e));
-                            }
-                        };
-
-                        for event in events.iter().take(num_events) {
-                            let dispatch_event: EpollDispatch = event.data.into();
-                            match dispatch_event {
-                                EpollDispatch::Unknown => {
-                                    let event = event.data;
-                                    warn!("Unknown serial manager loop event: {}", event);
-                                }
-                                EpollDispatch::File => {
-                                    if event.events & libc::EPOLLOUT as u32 != 0 {
-                                        serial
-                                            .as_ref()
-                                            .lock()
-                                            .unwrap()
-                                            .flush_output()
-                                            .map_err(Error::FlushOutput)?;
-                                    }
-                                    if event.events & libc::EPOLLIN as u32 != 0 {
-                                        let mut input = [0u8; 64];
-                                        let count =
-                                            in_file.read(&mut input).map_err(Error::ReadInput)?;
+    /// Handles the events of the console once the epoll file descriptor of
+    /// the serial manager is readable.
+    pub fn handle_events(&self) -> Result<()> {
+        // 2 for File and Unknown
+        const EPOLL_EVENTS_LEN: usize = 2;
+
+        let mut events = vec![epoll::Event::new(epoll::Events::empty(), 0); EPOLL_EVENTS_LEN];
+        let num_events = match epoll::wait(self.epoll_file.as_raw_fd(), 0, &mut events[..]) {
+            Ok(res) => res,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(Error::Epoll(e)),
+        };
 
-                                        // Replace "\n" with "\r" to deal with Windows SAC (#1170)
-                                        if count == 1 && input[0] == 0x0a {
-                                            input[0] = 0x0d;
-                                        }
+        for event in events.iter().take(num_events) {
+            let dispatch_event: EpollDispatch = event.data.into();
+            match dispatch_event {
+                EpollDispatch::Unknown => {
+                    let event = event.data;
+                    warn!("Unknown serial manager loop event: {}", event);
+                }
+                EpollDispatch::File => {
+                    if event.events & libc::EPOLLOUT as u32 != 0 {
+                        self.serial
+                            .as_ref()
+                            .lock()
+                            .unwrap()
+                            .flush_output()
+                            .map_err(Error::FlushOutput)?;
+                    }
+                    if event.events & libc::EPOLLIN as u32 != 0 {
+                        let mut input = [0u8; 64];
+                        let count = (&self.in_file).read(&mut input).map_err(Error::ReadInput)?;
 
-                                        serial
-                                            .as_ref()
-                                            .lock()
-                                            .unwrap()
-                                            .queue_input_bytes(&input[..count])
-                                            .map_err(Error::QueueInput)?;
-                                    }
-                                }
-                                EpollDispatch::Kill => {
-                                    info!("KILL event received, stopping epoll loop");
-                                    return Ok(());
-                                }
-                            }
+                        // Replace "\n" with "\r" to deal with Windows SAC (#1170)
+                        if count == 1 && input[0] == 0x0a {
+                            input[0] = 0x0d;
                         }
+
+                        self.serial
+                            .as_ref()
+                            .lock()
+                            .unwrap()
+                            .queue_input_bytes(&input[..count])
+                            .map_err(Error::QueueInput)?;
                     }
-                }))
-                .map_err(|_| {
-                    error!("serial-manager thread panicked");
-                    exit_evt.write(1).ok()
-                })
-                .ok();
-            })
-            .map_err(Error::SpawnSerialManager)?;
-        self.handle = Some(thread);
+                }
+            }
+        }
+
         Ok(())
     }
 }
 
-impl Drop for SerialManager {
-    fn drop(&mut self) {
-        self.kill_evt.write(1).ok();
-        if let Some(handle) = self.handle.take() {
-            handle.join().ok();
-        }
+impl AsRawFd for SerialManager {
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll_file.as_raw_fd()
     }
 }
@@ -0,0 +1,88 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Signals handled by the VMM thread through its epoll loop, rather than by a
+//! dedicated thread per VM. The signals must be blocked in all the threads of
+//! the process, so that they stay pending until read from the signalfd.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+pub struct SignalFd {
+    file: File,
+}
+
+impl SignalFd {
+    pub fn new(signals: &[i32]) -> io::Result<Self> {
+        // SAFETY: the signal set is initialized by sigemptyset() before use.
+        let mut mask: libc::sigset_t = unsafe { mem::zeroed() };
+        // SAFETY: FFI calls with a valid signal set.
+        unsafe {
+            libc::sigemptyset(&mut mask);
+            for signal in signals {
+                if libc::sigaddset(&mut mask, *signal) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+
+        // SAFETY: FFI call with a valid signal set.
+        let fd = unsafe { libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(SignalFd {
+            // SAFETY: the file descriptor was just created and is owned by
+            // the file from now on.
+            file: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    /// Consume the next pending signal, if any.
+    pub fn read(&mut self) -> io::Result<Option<i32>> {
+        let mut info = [0u8; mem::size_of::<libc::signalfd_siginfo>()];
+        match self.file.read(&mut info) {
+            Ok(n) if n == info.len() => {
+                // SAFETY: the buffer holds a whole signalfd_siginfo.
+                let info: libc::signalfd_siginfo =
+                    unsafe { std::ptr::read_unaligned(info.as_ptr() as *const _) };
+                Ok(Some(info.ssi_signo as i32))
+            }
+            Ok(n) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("short read of {} bytes from the signalfd", n),
+            )),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::signal::block_signal;
+
+    #[test]
+    fn test_signal_fd() {
+        block_signal(libc::SIGUSR2).unwrap();
+        let mut signal_fd = SignalFd::new(&[libc::SIGUSR2]).unwrap();
+        assert_eq!(signal_fd.read().unwrap(), None);
+
+        // SAFETY: the signal is blocked, hence stays pending on the thread.
+        assert_eq!(unsafe { libc::raise(libc::SIGUSR2) }, 0);
+        assert_eq!(signal_fd.read().unwrap(), Some(libc::SIGUSR2));
+        assert_eq!(signal_fd.read().unwrap(), None);
+    }
+}
@@ -43,7 +43,7 @@ extern "C" fn sigwinch_handler(_signo: c_int, _info: *mut siginfo_t, _unused: *m
     notify();
 }
 
-fn unblock_all_signals() -> io::Result<()> {
+pub(crate) fn unblock_all_signals() -> io::Result<()> {
     let mut set = MaybeUninit::uninit();
     if unsafe { sigemptyset(set.as_mut_ptr()) } == -1 {
         return Err(io::Error::last_os_error());
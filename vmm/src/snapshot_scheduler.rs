@@ -0,0 +1,253 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Periodic snapshots of the VM, taken by the VMM thread each time the timer
+//! expires. Each snapshot is stored in its own subdirectory of the
+//! destination, named after the time it was taken, and the oldest ones are
+//! removed beyond the retention count.
+//!
+//! When a dirty threshold is set, the guest memory written since the last
+//! snapshot is tracked through the dirty pages logging used by the live
+//! migration, and the snapshot is skipped while the threshold isn't reached.
+//!
+//! Incremental snapshots are chained, each one being based on the previous
+//! one. A full snapshot starts a new chain every retention count, and the
+//! snapshots still needed as a base by the ones kept aren't removed.
+
+use crate::migration::{MemoryManifest, SNAPSHOT_MEMORY_MANIFEST_FILE};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use vmm_sys_util::timerfd::TimerFd;
+
+const SNAPSHOT_DIRECTORY_PREFIX: &str = "snapshot-";
+
+pub struct SnapshotScheduler {
+    timer: TimerFd,
+    // Number of snapshots of the current chain of incremental snapshots.
+    chain_length: u32,
+}
+
+impl SnapshotScheduler {
+    pub fn new() -> io::Result<Self> {
+        let timer = TimerFd::new()?;
+        // The timer may be re-armed or stopped between its expiration being
+        // reported and read, in which case reading it must not block.
+        // SAFETY: FFI call with a valid file descriptor.
+        if unsafe { libc::fcntl(timer.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(SnapshotScheduler {
+            timer,
+            chain_length: 0,
+        })
+    }
+
+    pub fn start(&mut self, interval: u64) -> io::Result<()> {
+        let interval = Duration::from_secs(interval);
+        // The chain is lost along with the VM.
+        self.chain_length = 0;
+        self.timer.reset(interval, Some(interval))
+    }
+
+    pub fn stop(&mut self) -> io::Result<()> {
+        self.timer.clear()
+    }
+
+    /// Consume the expiration of the timer, if it still expired.
+    pub fn expired(&mut self) -> io::Result<bool> {
+        match self.timer.wait() {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the next incremental snapshot must start a new chain, the
+    /// current one holding as many snapshots as kept.
+    pub fn restart_chain(&self, retention: u32) -> bool {
+        self.chain_length >= retention
+    }
+
+    /// Account for an incremental snapshot taken, either starting a new
+    /// chain or added to the current one.
+    pub fn add_to_chain(&mut self, restarted: bool) {
+        self.chain_length = if restarted { 1 } else { self.chain_length + 1 };
+    }
+}
+
+impl AsRawFd for SnapshotScheduler {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+}
+
+/// Create the directory to store a new snapshot in, named after the time it
+/// is taken. The entries already named after the same time are skipped, so
+/// that the directory is never one which wasn't created for this snapshot.
+pub fn create_snapshot_directory(destination: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(destination)?;
+    let mut timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    loop {
+        let directory = destination.join(format!("{}{}", SNAPSHOT_DIRECTORY_PREFIX, timestamp));
+        match fs::create_dir(&directory) {
+            Ok(()) => return Ok(directory),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => timestamp += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Returns the canonical directories of the chain of snapshots the snapshot
+// stored in `directory` is based on.
+fn snapshot_bases(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut bases = Vec::new();
+    let mut directory = directory.canonicalize()?;
+    loop {
+        let manifest: MemoryManifest = match fs::read(directory.join(SNAPSHOT_MEMORY_MANIFEST_FILE))
+        {
+            Ok(manifest) => serde_json::from_slice(&manifest)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e),
+        };
+        let base = match manifest.base {
+            Some(base) => directory.join(base).canonicalize()?,
+            None => break,
+        };
+        if bases.contains(&base) {
+            break;
+        }
+        bases.push(base.clone());
+        directory = base;
+    }
+
+    Ok(bases)
+}
+
+/// Remove the oldest snapshots of the destination beyond the retention
+/// count, unless the snapshots kept are based on them. The other entries of
+/// the destination are left untouched.
+pub fn prune(destination: &Path, retention: u32) -> io::Result<Vec<PathBuf>> {
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(destination)? {
+        let entry = entry?;
+        let timestamp = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix(SNAPSHOT_DIRECTORY_PREFIX))
+            .and_then(|timestamp| timestamp.parse::<u64>().ok());
+        if let Some(timestamp) = timestamp {
+            if entry.file_type()?.is_dir() {
+                snapshots.push((timestamp, entry.path()));
+            }
+        }
+    }
+
+    snapshots.sort();
+    let count = snapshots.len().saturating_sub(retention as usize);
+    let mut bases = HashSet::new();
+    for (_, path) in snapshots[count..].iter() {
+        bases.extend(snapshot_bases(path)?);
+    }
+    let mut removed = Vec::new();
+    for (_, path) in snapshots.drain(..count) {
+        if !bases.contains(&path.canonicalize()?) {
+            removed.push(path);
+        }
+    }
+    for path in removed.iter() {
+        fs::remove_dir_all(path)?;
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempdir::TempDir;
+
+    #[test]
+    fn test_create_snapshot_directory() {
+        let destination = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let path = destination.as_path().join("snapshots");
+        let first = create_snapshot_directory(&path).unwrap();
+        fs::write(first.join("state.json"), b"").unwrap();
+        let second = create_snapshot_directory(&path).unwrap();
+        assert_ne!(first, second);
+        assert!(first.join("state.json").exists());
+        assert_eq!(fs::read_dir(&second).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_prune() {
+        let destination = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let path = destination.as_path();
+        for name in [
+            "snapshot-300",
+            "snapshot-1000",
+            "snapshot-20",
+            "snapshot-foo",
+            "other",
+        ] {
+            fs::create_dir(path.join(name)).unwrap();
+        }
+        fs::write(path.join("snapshot-10"), b"").unwrap();
+
+        assert_eq!(prune(path, 2).unwrap(), vec![path.join("snapshot-20")]);
+        assert!(prune(path, 2).unwrap().is_empty());
+        assert_eq!(prune(path, 1).unwrap(), vec![path.join("snapshot-300")]);
+        for name in ["snapshot-1000", "snapshot-foo", "other", "snapshot-10"] {
+            assert!(path.join(name).exists());
+        }
+    }
+
+    #[test]
+    fn test_prune_chain() {
+        let destination = TempDir::new_with_prefix("/tmp/ch").unwrap();
+        let path = destination.as_path();
+        // Two chains: 10 <- 20 <- 30 and 40 <- 50.
+        for (name, base) in [
+            ("snapshot-10", None),
+            ("snapshot-20", Some("../snapshot-10")),
+            ("snapshot-30", Some("../snapshot-20")),
+            ("snapshot-40", None),
+            ("snapshot-50", Some("../snapshot-40")),
+        ] {
+            fs::create_dir(path.join(name)).unwrap();
+            let manifest = MemoryManifest {
+                format: Default::default(),
+                ranges: Vec::new(),
+                base: base.map(PathBuf::from),
+            };
+            fs::write(
+                path.join(name).join(SNAPSHOT_MEMORY_MANIFEST_FILE),
+                serde_json::to_vec(&manifest).unwrap(),
+            )
+            .unwrap();
+        }
+
+        // snapshot-30 is kept, and so is the chain it is based on.
+        assert!(prune(path, 3).unwrap().is_empty());
+        // Only whole chains are removed.
+        assert_eq!(
+            prune(path, 2).unwrap(),
+            vec![
+                path.join("snapshot-10"),
+                path.join("snapshot-20"),
+                path.join("snapshot-30")
+            ]
+        );
+        assert!(prune(path, 1).unwrap().is_empty());
+        assert!(path.join("snapshot-40").exists());
+    }
+}
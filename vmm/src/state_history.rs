@@ -0,0 +1,155 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! History of the state changes of the VM, kept by the VMM rather than by
+//! the VM so that it survives the reboots re-creating the VM. Along with the
+//! uptime of the VM, it is reported by vm.info to find when and why a VM was
+//! paused, rebooted or shut down.
+
+use crate::vm::VmState;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Number of state changes kept, the oldest ones being dropped first.
+const STATE_HISTORY_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum VmStateReason {
+    /// Request received through the API.
+    Api,
+    /// Reset requested by the guest.
+    GuestReset,
+    /// Shutdown requested by the guest.
+    GuestShutdown,
+    /// SIGTERM or SIGINT received by the VMM.
+    Signal,
+    /// Expiration of the watchdog.
+    Watchdog,
+    /// Completion of an incoming live migration.
+    Migration,
+    /// Host maintenance, or the expiration of its lease.
+    Maintenance,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct VmStateTransition {
+    pub state: VmState,
+    pub reason: VmStateReason,
+    /// Milliseconds since the UNIX epoch.
+    pub timestamp_ms: u64,
+    /// Milliseconds since the VMM was started, from the monotonic clock.
+    pub monotonic_ms: u64,
+}
+
+pub struct StateHistory {
+    start: Instant,
+    booted: Option<Instant>,
+    transitions: VecDeque<VmStateTransition>,
+}
+
+impl Default for StateHistory {
+    fn default() -> Self {
+        StateHistory {
+            start: Instant::now(),
+            booted: None,
+            transitions: VecDeque::new(),
+        }
+    }
+}
+
+impl StateHistory {
+    /// Forget the history of the previous VM.
+    pub fn clear(&mut self) {
+        self.booted = None;
+        self.transitions.clear();
+    }
+
+    pub fn record(&mut self, state: VmState, reason: VmStateReason) {
+        info!("VM state changed to {:?}: reason = {:?}", state, reason);
+
+        let now = Instant::now();
+        match state {
+            VmState::Running if self.booted.is_none() => self.booted = Some(now),
+            VmState::Created | VmState::Shutdown => self.booted = None,
+            _ => {}
+        }
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        if self.transitions.len() == STATE_HISTORY_SIZE {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back(VmStateTransition {
+            state,
+            reason,
+            timestamp_ms,
+            monotonic_ms: now.duration_since(self.start).as_millis() as u64,
+        });
+    }
+
+    /// Time elapsed since the VM started running, paused time included.
+    /// The uptime starts over when the VM is rebooted.
+    pub fn uptime(&self) -> Duration {
+        self.booted
+            .map(|booted| booted.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// State changes, oldest first.
+    pub fn transitions(&self) -> Vec<VmStateTransition> {
+        self.transitions.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_history() {
+        let mut history = StateHistory::default();
+        assert_eq!(history.uptime(), Duration::ZERO);
+
+        history.record(VmState::Created, VmStateReason::Api);
+        assert_eq!(history.uptime(), Duration::ZERO);
+        history.record(VmState::Running, VmStateReason::Api);
+        let booted = history.booted;
+        assert!(booted.is_some());
+
+        // Pausing and resuming doesn't restart the uptime, rebooting does.
+        history.record(VmState::Paused, VmStateReason::Api);
+        history.record(VmState::Running, VmStateReason::Api);
+        assert_eq!(history.booted, booted);
+        history.record(VmState::Shutdown, VmStateReason::Watchdog);
+        assert_eq!(history.uptime(), Duration::ZERO);
+        history.record(VmState::Running, VmStateReason::Watchdog);
+        assert!(history.booted.is_some());
+
+        let transitions = history.transitions();
+        assert_eq!(transitions.len(), 6);
+        assert_eq!(transitions[4].state, VmState::Shutdown);
+        assert_eq!(transitions[4].reason, VmStateReason::Watchdog);
+        assert!(transitions
+            .windows(2)
+            .all(|t| t[0].monotonic_ms <= t[1].monotonic_ms));
+
+        // The oldest state changes are dropped.
+        for _ in 0..STATE_HISTORY_SIZE {
+            history.record(VmState::Paused, VmStateReason::Api);
+        }
+        let transitions = history.transitions();
+        assert_eq!(transitions.len(), STATE_HISTORY_SIZE);
+        assert!(transitions.iter().all(|t| t.state == VmState::Paused));
+
+        history.clear();
+        assert!(history.transitions().is_empty());
+        assert_eq!(history.uptime(), Duration::ZERO);
+    }
+}
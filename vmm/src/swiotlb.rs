@@ -0,0 +1,143 @@
+// Copyright © 2022, Microsoft Corporation
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+//! Sizing of the software IO TLB of confidential guests. Devices can't access
+//! the private memory of a TDX or SEV-SNP guest, so the guest kernel bounces
+//! every DMA through the swiotlb, a shared region which is allocated at boot
+//! and can't grow. Its default size is too small for guests with many or
+//! deep virtqueues, leading to I/O errors once it is exhausted.
+
+use crate::config::VmConfig;
+
+// Size of a slab of the swiotlb, the unit of its size on the kernel command
+// line (IO_TLB_SHIFT).
+const IO_TLB_SLAB_SIZE: u64 = 1 << 11;
+
+// Number of slabs of a segment, the largest contiguous bounce buffer the
+// swiotlb can map (IO_TLB_SEGSIZE).
+const IO_TLB_SEGMENT_SLABS: u64 = 128;
+
+// Default size of the swiotlb, enough for the devices with a few shallow
+// queues (rng, console, vsock, balloon).
+const DEFAULT_SWIOTLB_SIZE: u64 = 64 << 20;
+
+// Data in flight for each entry of the virtqueues. A block or filesystem
+// request can span multiple pages through indirect descriptors, while the
+// network buffers fit in a page.
+const BLOCK_REQUEST_DATA_SIZE: u64 = 128 << 10;
+const NET_BUFFER_DATA_SIZE: u64 = 4 << 10;
+
+// Whether the devices DMA through the swiotlb of the guest.
+#[cfg_attr(
+    not(any(feature = "tdx", feature = "sev_snp")),
+    allow(unused_variables)
+)]
+pub fn required(config: &VmConfig) -> bool {
+    #[cfg(feature = "tdx")]
+    if config.tdx.is_some() {
+        return true;
+    }
+    #[cfg(feature = "sev_snp")]
+    if config.sev_snp.is_some() {
+        return true;
+    }
+    false
+}
+
+// Entries of the virtqueues of the VM, along with the data in flight for
+// each entry.
+fn virtqueue_entries(config: &VmConfig) -> Vec<(u64, u64)> {
+    let mut entries = Vec::new();
+
+    for disk in config.disks.iter().flatten() {
+        entries.push((
+            disk.num_queues as u64 * disk.queue_size as u64,
+            BLOCK_REQUEST_DATA_SIZE,
+        ));
+    }
+    for net in config.net.iter().flatten() {
+        entries.push((
+            net.num_queues as u64 * net.queue_size as u64,
+            NET_BUFFER_DATA_SIZE,
+        ));
+    }
+    // The high priority queue of virtio-fs isn't counted by num_queues.
+    for fs in config.fs.iter().flatten() {
+        entries.push((
+            (fs.num_queues as u64 + 1) * fs.queue_size as u64,
+            BLOCK_REQUEST_DATA_SIZE,
+        ));
+    }
+
+    entries
+}
+
+// Size of the swiotlb needed for the data in flight on the virtqueues,
+// limited to a quarter of the memory and rounded up to a segment.
+fn swiotlb_size(virtqueue_entries: &[(u64, u64)], memory_size: u64) -> u64 {
+    let size = virtqueue_entries
+        .iter()
+        .fold(DEFAULT_SWIOTLB_SIZE, |size, (entries, data_size)| {
+            size + entries * data_size
+        })
+        .min((memory_size / 4).max(DEFAULT_SWIOTLB_SIZE));
+
+    let segment_size = IO_TLB_SEGMENT_SLABS * IO_TLB_SLAB_SIZE;
+    (size + segment_size - 1) / segment_size * segment_size
+}
+
+pub fn size(config: &VmConfig) -> u64 {
+    swiotlb_size(&virtqueue_entries(config), config.memory.size)
+}
+
+// Kernel command line parameter sizing the swiotlb, split into one area per
+// vCPU to avoid contention on its lock. Nothing is added when the swiotlb
+// isn't used, or if it is already set on the command line.
+pub fn cmdline(config: &VmConfig) -> Option<String> {
+    if !required(config)
+        || config
+            .cmdline
+            .args
+            .split_whitespace()
+            .any(|arg| arg.starts_with("swiotlb="))
+    {
+        return None;
+    }
+
+    Some(format!(
+        "swiotlb={},{}",
+        size(config) / IO_TLB_SLAB_SIZE,
+        config.cpus.boot_vcpus
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swiotlb_size() {
+        // A disk with 4 queues of 128 entries and a network device with 8
+        // queues of 256 entries.
+        let entries = [
+            (4 * 128, BLOCK_REQUEST_DATA_SIZE),
+            (8 * 256, NET_BUFFER_DATA_SIZE),
+        ];
+        assert_eq!(
+            swiotlb_size(&entries, 4 << 30),
+            (64 << 20) + (64 << 20) + (8 << 20)
+        );
+
+        // Limited to a quarter of the memory, rounded up to a segment.
+        assert_eq!(
+            swiotlb_size(&entries, (400 << 20) + (4 << 10)),
+            (100 << 20) + (256 << 10)
+        );
+
+        // Never lower than the default size.
+        assert_eq!(swiotlb_size(&entries, 128 << 20), DEFAULT_SWIOTLB_SIZE);
+        assert_eq!(swiotlb_size(&[], 4 << 30), DEFAULT_SWIOTLB_SIZE);
+    }
+}
@@ -11,27 +11,42 @@
 // SPDX-License-Identifier: Apache-2.0 AND BSD-3-Clause
 //
 
+#[cfg(feature = "guest_debug")]
+use crate::api::VmCoredumpData;
+#[cfg(feature = "fault_injection")]
+use crate::api::VmInjectFaultData;
+use crate::api::VmInjectNmiData;
+use crate::boot_phase::BootPhase;
 use crate::config::NumaConfig;
 use crate::config::{
     add_to_config, DeviceConfig, DiskConfig, FsConfig, HotplugMethod, NetConfig, PmemConfig,
-    UserDeviceConfig, ValidationError, VdpaConfig, VmConfig, VsockConfig,
+    SeccompLevel, SnapshotFormat, UserDeviceConfig, ValidationError, VdpaConfig, VmConfig,
+    VsockConfig,
 };
 #[cfg(feature = "guest_debug")]
 use crate::coredump::{
-    CpuElf64Writable, DumpState, Elf64Writable, GuestDebuggable, GuestDebuggableError, NoteDescType,
+    CoredumpMemoryRegions, CpuElf64Writable, DumpState, Elf64Writable, GuestDebuggable,
+    GuestDebuggableError, NoteDescType,
 };
 use crate::cpu;
-use crate::device_manager::{Console, DeviceManager, DeviceManagerError, PtyPair};
-use crate::device_tree::DeviceTree;
+use crate::device_manager::{Console, DeviceManager, DeviceManagerError, DeviceQueues, PtyPair};
+use crate::device_tree::{DeviceNodeInfo, DeviceTree};
 #[cfg(feature = "gdb")]
 use crate::gdb::{Debuggable, DebuggableError, GdbRequestPayload, GdbResponsePayload};
+use crate::hypervisor_info;
 use crate::memory_manager::{
     Error as MemoryManagerError, MemoryManager, MemoryManagerSnapshotData,
 };
+use crate::metrics::VmMetrics;
 #[cfg(feature = "guest_debug")]
 use crate::migration::url_to_file;
-use crate::migration::{get_vm_snapshot, url_to_path, SNAPSHOT_CONFIG_FILE, SNAPSHOT_STATE_FILE};
-use crate::seccomp_filters::{get_seccomp_filter, Thread};
+use crate::migration::{
+    get_vm_snapshot, url_to_path, SNAPSHOT_CONFIG_FILE, SNAPSHOT_STATE_DATA_FILE,
+    SNAPSHOT_STATE_FILE,
+};
+use crate::pstore;
+use crate::security::{self, SecurityStatus};
+use crate::swiotlb;
 use crate::GuestMemoryMmap;
 use crate::{
     PciDeviceInfo, CPU_MANAGER_SNAPSHOT_ID, DEVICE_MANAGER_SNAPSHOT_ID, MEMORY_MANAGER_SNAPSHOT_ID,
@@ -62,19 +77,15 @@ use linux_loader::loader::elf::PvhBootCapability::PvhEntryPresent;
 #[cfg(target_arch = "aarch64")]
 use linux_loader::loader::pe::Error::InvalidImageMagicNumber;
 use linux_loader::loader::KernelLoader;
-use seccompiler::{apply_filter, SeccompAction};
+use seccompiler::SeccompAction;
 use serde::{Deserialize, Serialize};
-use signal_hook::{
-    consts::{SIGINT, SIGTERM, SIGWINCH},
-    iterator::backend::Handle,
-    iterator::Signals,
-};
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1, SIGWINCH};
 use std::cmp;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Write};
+use std::io::{self, BufWriter, Read, Write};
 use std::io::{Seek, SeekFrom};
 #[cfg(feature = "tdx")]
 use std::mem;
@@ -82,27 +93,30 @@ use std::mem;
 use std::mem::size_of;
 use std::num::Wrapping;
 use std::ops::Deref;
+use std::os::unix::io::RawFd;
 use std::os::unix::net::UnixStream;
-use std::panic::AssertUnwindSafe;
 use std::sync::{Arc, Mutex, RwLock};
+#[cfg(target_arch = "x86_64")]
+use std::thread;
 use std::time::Instant;
-use std::{result, str, thread};
+use std::{result, str};
 use thiserror::Error;
+use virtio_devices::{InterruptCoalescingConfig, RateLimiterConfig};
 use vm_device::Bus;
 #[cfg(target_arch = "x86_64")]
 use vm_device::BusDevice;
-#[cfg(target_arch = "x86_64")]
 use vm_memory::Address;
 #[cfg(feature = "tdx")]
-use vm_memory::{ByteValued, GuestMemory, GuestMemoryRegion};
-use vm_memory::{Bytes, GuestAddress, GuestAddressSpace, GuestMemoryAtomic};
+use vm_memory::ByteValued;
+use vm_memory::{
+    Bytes, GuestAddress, GuestAddressSpace, GuestMemory, GuestMemoryAtomic, GuestMemoryRegion,
+};
 use vm_migration::protocol::{Request, Response, Status};
 use vm_migration::{
     protocol::MemoryRangeTable, Migratable, MigratableError, Pausable, Snapshot,
     SnapshotDataSection, Snapshottable, Transportable,
 };
 use vmm_sys_util::eventfd::EventFd;
-use vmm_sys_util::signal::unblock_signal;
 use vmm_sys_util::sock_ctrl_msg::ScmSocket;
 use vmm_sys_util::terminal::Terminal;
 
@@ -122,9 +136,25 @@ pub enum Error {
     #[error("Cannot load the UEFI binary in memory: {0:?}")]
     UefiLoad(arch::aarch64::uefi::Error),
 
+    #[cfg(target_arch = "aarch64")]
+    #[error("Cannot decompress the kernel: {0}")]
+    KernelDecompress(#[source] crate::kernel_image::Error),
+
     #[error("Cannot load the initramfs into memory")]
     InitramfsLoad,
 
+    #[error("Cannot open payload blob file: {0}")]
+    PayloadBlobFile(#[source] io::Error),
+
+    #[error("Cannot load the payload blob {0} into memory")]
+    PayloadBlobLoad(String),
+
+    #[error("The payload blob {0} overlaps with the boot data, the kernel or another payload")]
+    PayloadBlobOverlap(String),
+
+    #[error("The payload blob {0} doesn't fit in the guest RAM")]
+    PayloadBlobOutOfRam(String),
+
     #[error("Cannot load the kernel command line in memory: {0}")]
     LoadCmdLine(#[source] linux_loader::loader::Error),
 
@@ -150,12 +180,6 @@ pub enum Error {
     #[error("Cannot setup terminal in canonical mode.: {0}")]
     SetTerminalCanon(#[source] vmm_sys_util::errno::Error),
 
-    #[error("Cannot spawn a signal handler thread: {0}")]
-    SignalHandlerSpawn(#[source] io::Error),
-
-    #[error("Failed to join on threads: {0:?}")]
-    ThreadCleanup(std::boxed::Box<dyn std::any::Any + std::marker::Send>),
-
     #[error("VM config is missing")]
     VmMissingConfig,
 
@@ -168,6 +192,9 @@ pub enum Error {
     #[error("VM is not running")]
     VmNotRunning,
 
+    #[error("VM is not paused")]
+    VmNotPaused,
+
     #[error("Cannot clone EventFd: {0}")]
     EventFdClone(#[source] io::Error),
 
@@ -210,6 +237,26 @@ pub enum Error {
     #[error("Cannot send VM snapshot: {0}")]
     SnapshotSend(#[source] MigratableError),
 
+    #[error("Cannot inspect VM snapshot: {0}")]
+    SnapshotInfo(#[source] MigratableError),
+
+    #[error("Cannot write the memory templates: {0:?}")]
+    MemoryTemplate(MemoryManagerError),
+
+    #[error("No pstore region is configured")]
+    PstoreNotConfigured,
+
+    #[error("Cannot read pstore records: {0}")]
+    PstoreRead(#[source] io::Error),
+
+    #[cfg(target_arch = "aarch64")]
+    #[error("Cannot read FDT overlay: {0}")]
+    FdtOverlayRead(#[source] io::Error),
+
+    #[cfg(target_arch = "aarch64")]
+    #[error("Cannot read the base DTB: {0}")]
+    FdtBaseRead(#[source] io::Error),
+
     #[error("Invalid restore source URL")]
     InvalidRestoreSourceUrl,
 
@@ -225,12 +272,6 @@ pub enum Error {
     #[error("Invalid NUMA configuration")]
     InvalidNumaConfig,
 
-    #[error("Cannot create seccomp filter: {0}")]
-    CreateSeccompFilter(#[source] seccompiler::Error),
-
-    #[error("Cannot apply seccomp filter: {0}")]
-    ApplySeccompFilter(#[source] seccompiler::Error),
-
     #[error("Failed resizing a memory zone")]
     ResizeZone,
 
@@ -291,6 +332,34 @@ pub enum Error {
     #[error("Invalid TDX payload type")]
     InvalidPayloadType,
 
+    #[cfg(feature = "sev_snp")]
+    #[error("Error performing I/O on SEV-SNP firmware file: {0}")]
+    LoadSevSnpFirmware(#[source] std::io::Error),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error parsing SEV-SNP firmware: {0}")]
+    ParseSevSnpFirmware(#[source] arch::x86_64::sev_snp::SevSnpError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error enabling SEV-SNP VM: {0}")]
+    InitializeSevSnpVm(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error accessing SEV-SNP guest memory: {0}")]
+    SevSnpGuestMemory(#[source] vm_memory::GuestMemoryError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error creating SEV-SNP CPUID page: {0}")]
+    CreateSevSnpCpuidPage(#[source] arch::x86_64::sev_snp::SevSnpError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error adding memory to the SEV-SNP launch measurement: {0}")]
+    LaunchUpdateSevSnp(#[source] hypervisor::HypervisorVmError),
+
+    #[cfg(feature = "sev_snp")]
+    #[error("Error finalizing SEV-SNP VM: {0}")]
+    FinalizeSevSnp(#[source] hypervisor::HypervisorVmError),
+
     #[cfg(feature = "gdb")]
     #[error("Error debugging VM: {0:?}")]
     Debug(DebuggableError),
@@ -306,6 +375,57 @@ pub enum Error {
     #[cfg(feature = "guest_debug")]
     #[error("Error coredumping VM: {0:?}")]
     Coredump(GuestDebuggableError),
+
+    #[error("The VM can't be rebooted in place")]
+    InPlaceRebootNotSupported,
+
+    #[error("Error polling the device events from the VMM: {0}")]
+    DeviceEvents(#[source] io::Error),
+
+    #[error("The doorbell devices can't be restored, their file descriptors aren't part of the snapshot")]
+    DoorbellRestore,
+
+    #[error("The watchdog hook can't be restored, it can only be given on the command line")]
+    WatchdogHookRestore,
+
+    #[error("Cannot load the SSDT tables: {0}")]
+    LoadSsdtTables(#[source] crate::acpi::SsdtError),
+
+    #[error("The seccomp level {0:?} of the VM is less strict than the level {1:?} of the VMM")]
+    SeccompLevelLoosened(SeccompLevel, SeccompLevel),
+
+    #[error("Cannot set the timer slack: {0}")]
+    SetTimerSlack(#[source] io::Error),
+
+    #[error("Cannot set the halt polling time: {0}")]
+    SetHaltPoll(#[source] hypervisor::HypervisorVmError),
+
+    #[error("Invalid seccomp policy: {0}")]
+    SeccompPolicy(#[source] security::SeccompPolicyError),
+
+    #[error("No disk or network device rate limited by the VMM with identifier {0}")]
+    UnknownRateLimitedDevice(String),
+
+    #[error("No disk or network device whose interrupts the VMM coalesces with identifier {0}")]
+    UnknownInterruptCoalescingDevice(String),
+
+    #[error("No disk, network or filesystem device with identifier {0}")]
+    UnknownQueueSizeDevice(String),
+
+    #[error("Queue size {0} is not a power of 2")]
+    InvalidQueueSize(u16),
+
+    #[error("Interrupt coalescing delay must be non-zero")]
+    InvalidInterruptCoalescingUsecs,
+
+    #[error("VM is in maintenance")]
+    VmInMaintenance,
+
+    #[error("VM is not in maintenance")]
+    VmNotInMaintenance,
+
+    #[error("Cannot start the maintenance lease: {0}")]
+    Maintenance(#[source] io::Error),
 }
 pub type Result<T> = result::Result<T, Error>;
 
@@ -455,17 +575,107 @@ pub fn physical_bits(max_phys_bits: u8) -> u8 {
     cmp::min(host_phys_bits, max_phys_bits)
 }
 
-pub const HANDLED_SIGNALS: [i32; 3] = [SIGWINCH, SIGTERM, SIGINT];
+// SIGUSR1 is sent by the host to put the VM in maintenance.
+pub const HANDLED_SIGNALS: [i32; 4] = [SIGWINCH, SIGTERM, SIGINT, SIGUSR1];
+
+// Alignment of the initramfs files concatenated in guest memory.
+const INITRAMFS_ALIGNMENT: usize = 4;
+
+struct PayloadFile {
+    id: String,
+    file: File,
+    address: Option<GuestAddress>,
+}
+
+// Start and size of the guest memory taken by the loaded kernel.
+type KernelRegion = (GuestAddress, u64);
+
+// Guest memory the VMM fills for the kernel to boot: the boot structures, the
+// command line and the ACPI tables below 1MiB on x86_64, the FDT holding the
+// command line followed by the ACPI tables on aarch64.
+#[cfg(target_arch = "x86_64")]
+const BOOT_DATA_REGION: (GuestAddress, u64) =
+    (arch::layout::LOW_RAM_START, arch::layout::HIGH_RAM_START.0);
+#[cfg(target_arch = "aarch64")]
+const BOOT_DATA_REGION: (GuestAddress, u64) = (
+    arch::layout::FDT_START,
+    arch::layout::FDT_MAX_SIZE + arch::layout::ACPI_MAX_SIZE,
+);
+
+// Lays out the initramfs and the payload blobs, given by their identifier,
+// size and fixed address if any. The blobs must fit in the guest RAM without
+// overlapping the boot data, the kernel once loaded, or the other payloads.
+fn layout_payloads(
+    guest_mem: &GuestMemoryMmap,
+    initramfs_size: Option<usize>,
+    payload_blobs: &[(String, usize, Option<GuestAddress>)],
+    kernel_region: Option<KernelRegion>,
+) -> Result<(Option<arch::InitramfsConfig>, Vec<arch::PayloadBlob>)> {
+    let page_aligned = |size: usize| (size + arch::PAGE_SIZE - 1) & !(arch::PAGE_SIZE - 1);
+    // Memory taken below the top of the low memory by the payloads placed
+    // so far.
+    let mut reserved = 0;
+
+    let mut initramfs = None;
+    if let Some(size) = initramfs_size {
+        let address =
+            arch::initramfs_load_addr(guest_mem, size).map_err(|_| Error::InitramfsLoad)?;
+        reserved = page_aligned(size);
+        initramfs = Some(arch::InitramfsConfig {
+            address: GuestAddress(address),
+            size,
+        });
+    }
+
+    let mut layout: Vec<arch::PayloadBlob> = Vec::new();
+    for (id, size, address) in payload_blobs.iter() {
+        let size = *size;
+        let address = match address {
+            Some(address) => *address,
+            None => {
+                reserved += page_aligned(size);
+                arch::initramfs_load_addr(guest_mem, reserved)
+                    .map(GuestAddress)
+                    .map_err(|_| Error::PayloadBlobLoad(id.clone()))?
+            }
+        };
+
+        if !guest_mem.check_range(address, size) {
+            return Err(Error::PayloadBlobOutOfRam(id.clone()));
+        }
+
+        let overlaps = |start: GuestAddress, len: u64| {
+            start.0 < address.0 + size as u64 && address.0 < start.0 + len
+        };
+        if overlaps(BOOT_DATA_REGION.0, BOOT_DATA_REGION.1)
+            || kernel_region.map_or(false, |(start, len)| overlaps(start, len))
+            || initramfs
+                .iter()
+                .any(|i: &arch::InitramfsConfig| overlaps(i.address, i.size as u64))
+            || layout.iter().any(|b| overlaps(b.address, b.size as u64))
+        {
+            return Err(Error::PayloadBlobOverlap(id.clone()));
+        }
+
+        layout.push(arch::PayloadBlob {
+            id: id.clone(),
+            address,
+            size,
+        });
+    }
+
+    Ok((initramfs, layout))
+}
 
 pub struct Vm {
     #[cfg(any(target_arch = "aarch64", feature = "tdx"))]
     kernel: Option<File>,
-    initramfs: Option<File>,
-    threads: Vec<thread::JoinHandle<()>>,
+    // Initramfs files, concatenated in guest memory.
+    initramfs: Vec<File>,
+    payload_blobs: Vec<PayloadFile>,
     device_manager: Arc<Mutex<DeviceManager>>,
     config: Arc<Mutex<VmConfig>>,
     on_tty: bool,
-    signals: Option<Handle>,
     state: RwLock<VmState>,
     cpu_manager: Arc<Mutex<cpu::CpuManager>>,
     memory_manager: Arc<Mutex<MemoryManager>>,
@@ -476,12 +686,20 @@ pub struct Vm {
     saved_clock: Option<hypervisor::ClockData>,
     numa_nodes: NumaNodes,
     seccomp_action: SeccompAction,
-    exit_evt: EventFd,
     #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
     hypervisor: Arc<dyn hypervisor::Hypervisor>,
     stop_on_boot: bool,
     #[cfg(target_arch = "x86_64")]
-    load_kernel_handle: Option<thread::JoinHandle<Result<EntryPoint>>>,
+    load_kernel_handle: Option<thread::JoinHandle<Result<(EntryPoint, KernelRegion)>>>,
+    kernel_region: Option<KernelRegion>,
+    #[cfg(target_arch = "x86_64")]
+    boot_entry_point: Option<EntryPoint>,
+    #[cfg(target_arch = "aarch64")]
+    hypervisor_info: Option<arch::HypervisorInfo>,
+    // Guest memory written since the last scheduled snapshot, in bytes, when
+    // tracked for their dirty threshold. A page written between several
+    // reads of the dirty log is counted once per read.
+    scheduled_dirty: Option<u64>,
 }
 
 impl Vm {
@@ -492,19 +710,33 @@ impl Vm {
         vm: Arc<dyn hypervisor::Vm>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        panic_evt: EventFd,
+        watchdog_evt: EventFd,
         #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
         activate_evt: EventFd,
         restoring: bool,
         timestamp: Instant,
+        vmm_version: &str,
     ) -> Result<Self> {
-        let kernel = config
+        let kernel_path = config
             .lock()
             .unwrap()
             .kernel
             .as_ref()
-            .map(|k| File::open(&k.path))
+            .map(|k| k.path.clone());
+        // The firmware of a SEV-SNP guest is loaded in place of the kernel.
+        #[cfg(feature = "sev_snp")]
+        let kernel_path = config
+            .lock()
+            .unwrap()
+            .sev_snp
+            .as_ref()
+            .map(|s| s.firmware.clone())
+            .or(kernel_path);
+        let kernel = kernel_path
+            .map(File::open)
             .transpose()
             .map_err(Error::KernelFile)?;
 
@@ -523,6 +755,67 @@ impl Vm {
 
         info!("Booting VM from config: {:?}", &config);
 
+        // The seccomp level from the security configuration, if any, applies
+        // to the threads created for the VM instead of the one of the VMM. It
+        // can come from an API client, and may only make the filters stricter.
+        let vm_seccomp = config
+            .lock()
+            .unwrap()
+            .security
+            .as_ref()
+            .and_then(|s| s.seccomp);
+        let seccomp_action = &match vm_seccomp {
+            Some(level) => {
+                let vmm_level = security::seccomp_level(seccomp_action);
+                if level.strictness() < vmm_level.strictness() {
+                    return Err(Error::SeccompLevelLoosened(level, vmm_level));
+                }
+                if level == vmm_level {
+                    seccomp_action.clone()
+                } else {
+                    security::seccomp_action(level)
+                }
+            }
+            None => seccomp_action.clone(),
+        };
+
+        // The syscalls allowed by the seccomp policy must be registered
+        // before any thread of the VM is created.
+        let seccomp_policy = config
+            .lock()
+            .unwrap()
+            .security
+            .as_ref()
+            .and_then(|s| s.seccomp_policy.clone());
+        match seccomp_policy {
+            Some(path) => security::load_seccomp_policy(&path).map_err(Error::SeccompPolicy)?,
+            None => virtio_devices::seccomp_filters::set_extra_syscalls(HashMap::new()),
+        }
+
+        // Reduce the timer slack to its minimum when running deterministically
+        // so that the expiration of the timers isn't coalesced depending on
+        // the load of the host. The vCPU and device threads, created from
+        // this one, inherit it.
+        if config.lock().unwrap().deterministic.is_some() {
+            // SAFETY: FFI call with valid arguments
+            let ret = unsafe { libc::prctl(libc::PR_SET_TIMERSLACK, 1) };
+            if ret < 0 {
+                return Err(Error::SetTimerSlack(io::Error::last_os_error()));
+            }
+        }
+
+        // Let the vCPUs poll before yielding their host CPU for as long as
+        // the priority class asks for.
+        let halt_poll_ns = config
+            .lock()
+            .unwrap()
+            .priority_profile()
+            .and_then(|profile| profile.halt_poll_ns);
+        if let Some(halt_poll_ns) = halt_poll_ns {
+            vm.set_halt_poll_ns(halt_poll_ns)
+                .map_err(Error::SetHaltPoll)?;
+        }
+
         // Create NUMA nodes based on NumaConfig.
         let numa_nodes =
             Self::create_numa_nodes(config.lock().unwrap().numa.clone(), &memory_manager)?;
@@ -543,6 +836,8 @@ impl Vm {
             memory_manager.clone(),
             &exit_evt,
             &reset_evt,
+            &panic_evt,
+            &watchdog_evt,
             seccomp_action.clone(),
             numa_nodes.clone(),
             &activate_evt,
@@ -570,7 +865,9 @@ impl Vm {
             pci_config_io,
         });
 
-        let exit_evt_clone = exit_evt.try_clone().map_err(Error::EventFdClone)?;
+        let hypervisor_info =
+            hypervisor_info::hypervisor_info(&config.lock().unwrap(), vmm_version);
+
         #[cfg(feature = "tdx")]
         let tdx_enabled = config.lock().unwrap().tdx.is_some();
         let cpus_config = { &config.lock().unwrap().cpus.clone() };
@@ -579,7 +876,7 @@ impl Vm {
             &device_manager,
             &memory_manager,
             vm.clone(),
-            exit_evt_clone,
+            exit_evt,
             reset_evt,
             #[cfg(feature = "gdb")]
             vm_debug_evt,
@@ -589,6 +886,10 @@ impl Vm {
             #[cfg(feature = "tdx")]
             tdx_enabled,
             &numa_nodes,
+            #[cfg(target_arch = "x86_64")]
+            hypervisor_info.as_ref(),
+            config.lock().unwrap().deterministic.is_some(),
+            config.lock().unwrap().priority_profile(),
         )
         .map_err(Error::CpuManager)?;
 
@@ -598,20 +899,40 @@ impl Vm {
             .lock()
             .unwrap()
             .initramfs
-            .as_ref()
-            .map(|i| File::open(&i.path))
-            .transpose()
+            .iter()
+            .flat_map(|i| std::iter::once(&i.path).chain(i.extra_paths.iter()))
+            .map(File::open)
+            .collect::<io::Result<Vec<File>>>()
             .map_err(Error::InitramfsFile)?;
 
+        let payload_blobs = config
+            .lock()
+            .unwrap()
+            .payload_blobs
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(i, payload_blob)| -> io::Result<PayloadFile> {
+                Ok(PayloadFile {
+                    id: payload_blob
+                        .id
+                        .clone()
+                        .unwrap_or_else(|| format!("_blob{}", i)),
+                    file: File::open(&payload_blob.path)?,
+                    address: payload_blob.address.map(GuestAddress),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(Error::PayloadBlobFile)?;
+
         Ok(Vm {
             #[cfg(any(target_arch = "aarch64", feature = "tdx"))]
             kernel,
             initramfs,
+            payload_blobs,
             device_manager,
             config,
             on_tty,
-            threads: Vec::with_capacity(1),
-            signals: None,
             state: RwLock::new(VmState::Created),
             cpu_manager,
             memory_manager,
@@ -620,12 +941,17 @@ impl Vm {
             saved_clock: None,
             numa_nodes,
             seccomp_action: seccomp_action.clone(),
-            exit_evt,
             #[cfg(all(feature = "kvm", target_arch = "x86_64"))]
             hypervisor,
             stop_on_boot,
             #[cfg(target_arch = "x86_64")]
             load_kernel_handle,
+            kernel_region: None,
+            #[cfg(target_arch = "x86_64")]
+            boot_entry_point: None,
+            #[cfg(target_arch = "aarch64")]
+            hypervisor_info,
+            scheduled_dirty: None,
         })
     }
 
@@ -665,6 +991,11 @@ impl Vm {
                     node.cpus.extend(cpus);
                 }
 
+                if let Some(pmem_devices) = &config.pmem {
+                    node.pmem_devices.extend(pmem_devices.iter().cloned());
+                }
+                node.memory_hotplug = config.memory_hotplug;
+
                 if let Some(distances) = &config.distances {
                     for distance in distances.iter() {
                         let dest = distance.destination;
@@ -714,6 +1045,8 @@ impl Vm {
         config: Arc<Mutex<VmConfig>>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        panic_evt: EventFd,
+        watchdog_evt: EventFd,
         #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
@@ -721,21 +1054,28 @@ impl Vm {
         serial_pty: Option<PtyPair>,
         console_pty: Option<PtyPair>,
         console_resize_pipe: Option<File>,
+        vmm_version: &str,
     ) -> Result<Self> {
         let timestamp = Instant::now();
 
         #[cfg(feature = "tdx")]
         let tdx_enabled = config.lock().unwrap().tdx.is_some();
         hypervisor.check_required_extensions().unwrap();
-        #[cfg(feature = "tdx")]
-        let vm = hypervisor
-            .create_vm_with_type(if tdx_enabled {
-                2 // KVM_X86_TDX_VM
-            } else {
-                0 // KVM_X86_LEGACY_VM
-            })
-            .unwrap();
-        #[cfg(not(feature = "tdx"))]
+        #[cfg(any(feature = "tdx", feature = "sev_snp"))]
+        let vm = {
+            #[allow(unused_mut)]
+            let mut vm_type = 0; // KVM_X86_LEGACY_VM
+            #[cfg(feature = "tdx")]
+            if tdx_enabled {
+                vm_type = 2; // KVM_X86_TDX_VM
+            }
+            #[cfg(feature = "sev_snp")]
+            if config.lock().unwrap().sev_snp.is_some() {
+                vm_type = hypervisor::kvm::KVM_X86_SNP_VM;
+            }
+            hypervisor.create_vm_with_type(vm_type).unwrap()
+        };
+        #[cfg(not(any(feature = "tdx", feature = "sev_snp")))]
         let vm = hypervisor.create_vm().unwrap();
 
         #[cfg(target_arch = "x86_64")]
@@ -750,10 +1090,14 @@ impl Vm {
 
         #[cfg(target_arch = "x86_64")]
         let sgx_epc_config = config.lock().unwrap().sgx_epc.clone();
+        let pstore_config = config.lock().unwrap().pstore.clone();
+
+        let memory_config = config.lock().unwrap().tuned_memory_config();
 
+        let phase = BootPhase::start("memory-map");
         let memory_manager = MemoryManager::new(
             vm.clone(),
-            &config.lock().unwrap().memory.clone(),
+            &memory_config,
             None,
             phys_bits,
             #[cfg(feature = "tdx")]
@@ -762,8 +1106,10 @@ impl Vm {
             None,
             #[cfg(target_arch = "x86_64")]
             sgx_epc_config,
+            pstore_config,
         )
         .map_err(Error::MemoryManager)?;
+        drop(phase);
 
         let new_vm = Vm::new_from_memory_manager(
             config,
@@ -771,6 +1117,8 @@ impl Vm {
             vm,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "gdb")]
             vm_debug_evt,
             seccomp_action,
@@ -778,10 +1126,12 @@ impl Vm {
             activate_evt,
             false,
             timestamp,
+            vmm_version,
         )?;
 
         // The device manager must create the devices from here as it is part
         // of the regular code path creating everything from scratch.
+        let _phase = BootPhase::start("devices");
         new_vm
             .device_manager
             .lock()
@@ -797,12 +1147,17 @@ impl Vm {
         vm_config: Arc<Mutex<VmConfig>>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        panic_evt: EventFd,
+        watchdog_evt: EventFd,
         #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
         source_url: Option<&str>,
         prefault: bool,
+        verify: bool,
+        on_demand: bool,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
         activate_evt: EventFd,
+        vmm_version: &str,
     ) -> Result<Self> {
         let timestamp = Instant::now();
 
@@ -827,13 +1182,17 @@ impl Vm {
             snapshot.snapshots.get(MEMORY_MANAGER_SNAPSHOT_ID)
         {
             let phys_bits = physical_bits(vm_config.lock().unwrap().cpus.max_phys_bits);
+            let pstore_config = vm_config.lock().unwrap().pstore.clone();
             MemoryManager::new_from_snapshot(
                 memory_manager_snapshot,
                 vm.clone(),
-                &vm_config.lock().unwrap().memory.clone(),
+                &vm_config.lock().unwrap().tuned_memory_config(),
                 source_url,
                 prefault,
+                verify,
+                on_demand,
                 phys_bits,
+                pstore_config,
             )
             .map_err(Error::MemoryManager)?
         } else {
@@ -848,6 +1207,8 @@ impl Vm {
             vm,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "gdb")]
             vm_debug_evt,
             seccomp_action,
@@ -855,6 +1216,7 @@ impl Vm {
             activate_evt,
             true,
             timestamp,
+            vmm_version,
         )
     }
 
@@ -863,12 +1225,15 @@ impl Vm {
         config: Arc<Mutex<VmConfig>>,
         exit_evt: EventFd,
         reset_evt: EventFd,
+        panic_evt: EventFd,
+        watchdog_evt: EventFd,
         #[cfg(feature = "gdb")] vm_debug_evt: EventFd,
         seccomp_action: &SeccompAction,
         hypervisor: Arc<dyn hypervisor::Hypervisor>,
         activate_evt: EventFd,
         memory_manager_data: &MemoryManagerSnapshotData,
         existing_memory_files: Option<HashMap<u32, File>>,
+        vmm_version: &str,
     ) -> Result<Self> {
         let timestamp = Instant::now();
 
@@ -884,10 +1249,11 @@ impl Vm {
         }
 
         let phys_bits = physical_bits(config.lock().unwrap().cpus.max_phys_bits);
+        let pstore_config = config.lock().unwrap().pstore.clone();
 
         let memory_manager = MemoryManager::new(
             vm.clone(),
-            &config.lock().unwrap().memory.clone(),
+            &config.lock().unwrap().tuned_memory_config(),
             None,
             phys_bits,
             #[cfg(feature = "tdx")]
@@ -896,6 +1262,7 @@ impl Vm {
             existing_memory_files,
             #[cfg(target_arch = "x86_64")]
             None,
+            pstore_config,
         )
         .map_err(Error::MemoryManager)?;
 
@@ -905,6 +1272,8 @@ impl Vm {
             vm,
             exit_evt,
             reset_evt,
+            panic_evt,
+            watchdog_evt,
             #[cfg(feature = "gdb")]
             vm_debug_evt,
             seccomp_action,
@@ -912,34 +1281,138 @@ impl Vm {
             activate_evt,
             true,
             timestamp,
+            vmm_version,
         )
     }
 
-    fn load_initramfs(&mut self, guest_mem: &GuestMemoryMmap) -> Result<arch::InitramfsConfig> {
-        let mut initramfs = self.initramfs.as_ref().unwrap();
-        let size: usize = initramfs
-            .seek(SeekFrom::End(0))
-            .map_err(|_| Error::InitramfsLoad)?
-            .try_into()
-            .unwrap();
-        initramfs
-            .seek(SeekFrom::Start(0))
-            .map_err(|_| Error::InitramfsLoad)?;
+    // Offsets and sizes of the initramfs files within the concatenated
+    // initramfs, and its size. Each file is aligned on 4 bytes, as the kernel
+    // expects the concatenated archives to be.
+    fn initramfs_layout(initramfs: &[File]) -> Result<(Vec<(usize, usize)>, usize)> {
+        let mut files = Vec::new();
+        let mut size = 0;
+        for file in initramfs.iter() {
+            let offset = (size + INITRAMFS_ALIGNMENT - 1) & !(INITRAMFS_ALIGNMENT - 1);
+            let len: usize = file
+                .metadata()
+                .map_err(|_| Error::InitramfsLoad)?
+                .len()
+                .try_into()
+                .unwrap();
+            files.push((offset, len));
+            size = offset + len;
+        }
 
-        let address =
-            arch::initramfs_load_addr(guest_mem, size).map_err(|_| Error::InitramfsLoad)?;
-        let address = GuestAddress(address);
+        Ok((files, size))
+    }
+
+    // Guest memory ranges of the initramfs and of the payload blobs. The ones
+    // without an address are stacked below each other, from where the
+    // initramfs alone would be loaded.
+    fn payload_layout(
+        &self,
+        guest_mem: &GuestMemoryMmap,
+    ) -> Result<(Option<arch::InitramfsConfig>, Vec<arch::PayloadBlob>)> {
+        let initramfs_size = if !self.initramfs.is_empty() {
+            Some(Self::initramfs_layout(&self.initramfs)?.1)
+        } else {
+            None
+        };
+
+        let mut payload_blobs = Vec::new();
+        for payload in self.payload_blobs.iter() {
+            let size: usize = payload
+                .file
+                .metadata()
+                .map_err(|_| Error::PayloadBlobLoad(payload.id.clone()))?
+                .len()
+                .try_into()
+                .unwrap();
+            if size == 0 {
+                return Err(Error::PayloadBlobLoad(payload.id.clone()));
+            }
+            payload_blobs.push((payload.id.clone(), size, payload.address));
+        }
+
+        layout_payloads(
+            guest_mem,
+            initramfs_size,
+            &payload_blobs,
+            self.kernel_region,
+        )
+    }
+
+    fn load_payloads(
+        &self,
+        guest_mem: &GuestMemoryMmap,
+    ) -> Result<(Option<arch::InitramfsConfig>, Vec<arch::PayloadBlob>)> {
+        let (initramfs_config, payload_blobs) = self.payload_layout(guest_mem)?;
+
+        if let Some(initramfs) = &initramfs_config {
+            let (files, _) = Self::initramfs_layout(&self.initramfs)?;
+            let mut end = 0;
+            for (file, (offset, len)) in self.initramfs.iter().zip(files) {
+                // The padding is zeroed, as the guest may have used the
+                // memory before a reboot.
+                guest_mem
+                    .write_slice(
+                        &[0u8; INITRAMFS_ALIGNMENT][..offset - end],
+                        initramfs.address.unchecked_add(end as u64),
+                    )
+                    .map_err(|_| Error::InitramfsLoad)?;
+                Self::load_payload(
+                    guest_mem,
+                    file,
+                    initramfs.address.unchecked_add(offset as u64),
+                    len,
+                )
+                .map_err(|_| Error::InitramfsLoad)?;
+                end = offset + len;
+            }
+            info!(
+                "Initramfs loaded: address = 0x{:x}, files = {}",
+                initramfs.address.0,
+                self.initramfs.len()
+            );
+        }
+
+        for (payload, payload_blob) in self.payload_blobs.iter().zip(payload_blobs.iter()) {
+            Self::load_payload(
+                guest_mem,
+                &payload.file,
+                payload_blob.address,
+                payload_blob.size,
+            )
+            .map_err(|_| Error::PayloadBlobLoad(payload_blob.id.clone()))?;
+            info!(
+                "Payload blob {} loaded: address = 0x{:x}, size = 0x{:x}",
+                payload_blob.id, payload_blob.address.0, payload_blob.size
+            );
+        }
 
-        guest_mem
-            .read_from(address, &mut initramfs, size)
-            .map_err(|_| Error::InitramfsLoad)?;
+        Ok((initramfs_config, payload_blobs))
+    }
+
+    fn load_payload(
+        guest_mem: &GuestMemoryMmap,
+        mut file: &File,
+        address: GuestAddress,
+        size: usize,
+    ) -> io::Result<()> {
+        file.seek(SeekFrom::Start(0))?;
+        let read = guest_mem
+            .read_from(address, &mut file, size)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if read != size {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
 
-        info!("Initramfs loaded: address = 0x{:x}", address.0);
-        Ok(arch::InitramfsConfig { address, size })
+        Ok(())
     }
 
     fn generate_cmdline(
         config: &Arc<Mutex<VmConfig>>,
+        memory_manager: &Arc<Mutex<MemoryManager>>,
         #[cfg(target_arch = "aarch64")] device_manager: &Arc<Mutex<DeviceManager>>,
     ) -> Result<Cmdline> {
         let mut cmdline = Cmdline::new(arch::CMDLINE_MAX_SIZE);
@@ -947,6 +1420,28 @@ impl Vm {
             .insert_str(&config.lock().unwrap().cmdline.args)
             .map_err(Error::CmdLineInsertStr)?;
 
+        if let Some((start, size)) = memory_manager.lock().unwrap().pstore_region() {
+            cmdline
+                .insert_str(pstore::cmdline(start, size))
+                .map_err(Error::CmdLineInsertStr)?;
+        }
+
+        // Let the guest kernel reserve the memory its kdump kernel is loaded
+        // into, so that it can be kexec'ed into on a panic.
+        if let Some(crashkernel) = &config.lock().unwrap().crashkernel {
+            cmdline
+                .insert_str(format!("crashkernel={}M", crashkernel.size >> 20))
+                .map_err(Error::CmdLineInsertStr)?;
+        }
+
+        // Confidential guests bounce every DMA through the swiotlb, which
+        // must be large enough for the data in flight on all the queues.
+        if let Some(swiotlb) = swiotlb::cmdline(&config.lock().unwrap()) {
+            cmdline
+                .insert_str(swiotlb)
+                .map_err(Error::CmdLineInsertStr)?;
+        }
+
         #[cfg(target_arch = "aarch64")]
         for entry in device_manager.lock().unwrap().cmdline_additions() {
             cmdline.insert_str(entry).map_err(Error::CmdLineInsertStr)?;
@@ -956,29 +1451,51 @@ impl Vm {
 
     #[cfg(target_arch = "aarch64")]
     fn load_kernel(&mut self) -> Result<EntryPoint> {
+        let mut kernel = self.kernel.as_ref().unwrap();
+        // A compressed kernel is decompressed in memory, and has to be a PE
+        // kernel image once decompressed.
+        let (entry_point, kernel_region) =
+            match crate::kernel_image::decompress(&mut kernel).map_err(Error::KernelDecompress)? {
+                Some(image) => self.load_kernel_image(&mut std::io::Cursor::new(image), false)?,
+                None => self.load_kernel_image(&mut kernel, true)?,
+            };
+        self.kernel_region = kernel_region;
+
+        Ok(entry_point)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn load_kernel_image<F: Read + Seek>(
+        &self,
+        kernel: &mut F,
+        allow_uefi: bool,
+    ) -> Result<(EntryPoint, Option<KernelRegion>)> {
         let guest_memory = self.memory_manager.lock().as_ref().unwrap().guest_memory();
         let mem = guest_memory.memory();
-        let mut kernel = self.kernel.as_ref().unwrap();
         let entry_addr = match linux_loader::loader::pe::PE::load(
             mem.deref(),
             Some(arch::layout::KERNEL_START),
-            &mut kernel,
+            kernel,
             None,
         ) {
             Ok(entry_addr) => entry_addr,
             // Try to load the binary as kernel PE file at first.
             // If failed, retry to load it as UEFI binary.
             // As the UEFI binary is formatless, it must be the last option to try.
-            Err(linux_loader::loader::Error::Pe(InvalidImageMagicNumber)) => {
+            Err(linux_loader::loader::Error::Pe(InvalidImageMagicNumber)) if allow_uefi => {
                 let uefi_flash = self.device_manager.lock().as_ref().unwrap().uefi_flash();
                 let mem = uefi_flash.memory();
-                arch::aarch64::uefi::load_uefi(mem.deref(), arch::layout::UEFI_START, &mut kernel)
+                arch::aarch64::uefi::load_uefi(mem.deref(), arch::layout::UEFI_START, kernel)
                     .map_err(Error::UefiLoad)?;
 
-                // The entry point offset in UEFI image is always 0.
-                return Ok(EntryPoint {
-                    entry_addr: arch::layout::UEFI_START,
-                });
+                // The entry point offset in UEFI image is always 0, and the
+                // firmware is loaded in its flash rather than in the RAM.
+                return Ok((
+                    EntryPoint {
+                        entry_addr: arch::layout::UEFI_START,
+                    },
+                    None,
+                ));
             }
             Err(e) => {
                 return Err(Error::KernelLoad(e));
@@ -986,10 +1503,17 @@ impl Vm {
         };
 
         let entry_point_addr: GuestAddress = entry_addr.kernel_load;
+        let kernel_region = (
+            entry_addr.kernel_load,
+            entry_addr.kernel_end - entry_addr.kernel_load.raw_value(),
+        );
 
-        Ok(EntryPoint {
-            entry_addr: entry_point_addr,
-        })
+        Ok((
+            EntryPoint {
+                entry_addr: entry_point_addr,
+            },
+            Some(kernel_region),
+        ))
     }
 
     #[cfg(target_arch = "x86_64")]
@@ -997,7 +1521,7 @@ impl Vm {
         mut kernel: File,
         cmdline: Cmdline,
         memory_manager: Arc<Mutex<MemoryManager>>,
-    ) -> Result<EntryPoint> {
+    ) -> Result<(EntryPoint, KernelRegion)> {
         use linux_loader::loader::{elf::Error::InvalidElfMagicNumber, Error::Elf};
         info!("Loading kernel");
 
@@ -1050,7 +1574,7 @@ impl Vm {
                         .read_exact_from(load_address, &mut kernel, size as usize)
                         .map_err(Error::FirmwareLoad)?;
 
-                    return Ok(EntryPoint { entry_addr: None });
+                    return Ok((EntryPoint { entry_addr: None }, (load_address, size)));
                 }
                 _ => {
                     return Err(Error::KernelLoad(e));
@@ -1061,12 +1585,20 @@ impl Vm {
         linux_loader::loader::load_cmdline(mem.deref(), arch::layout::CMDLINE_START, &cmdline)
             .map_err(Error::LoadCmdLine)?;
 
+        let kernel_region = (
+            entry_addr.kernel_load,
+            entry_addr.kernel_end - entry_addr.kernel_load.raw_value(),
+        );
+
         if let PvhEntryPresent(entry_addr) = entry_addr.pvh_boot_cap {
             // Use the PVH kernel entry point to boot the guest
             info!("Kernel loaded: entry_addr = 0x{:x}", entry_addr.0);
-            Ok(EntryPoint {
-                entry_addr: Some(entry_addr),
-            })
+            Ok((
+                EntryPoint {
+                    entry_addr: Some(entry_addr),
+                },
+                kernel_region,
+            ))
         } else {
             Err(Error::KernelMissingPvhHeader)
         }
@@ -1077,7 +1609,7 @@ impl Vm {
         kernel: &Option<File>,
         memory_manager: &Arc<Mutex<MemoryManager>>,
         config: &Arc<Mutex<VmConfig>>,
-    ) -> Result<Option<thread::JoinHandle<Result<EntryPoint>>>> {
+    ) -> Result<Option<thread::JoinHandle<Result<(EntryPoint, KernelRegion)>>>> {
         // Kernel with TDX is loaded in a different manner
         #[cfg(feature = "tdx")]
         if config.lock().unwrap().tdx.is_some() {
@@ -1094,7 +1626,7 @@ impl Vm {
                 std::thread::Builder::new()
                     .name("kernel_loader".into())
                     .spawn(move || {
-                        let cmdline = Self::generate_cmdline(&config)?;
+                        let cmdline = Self::generate_cmdline(&config, &memory_manager)?;
                         Self::load_kernel(kernel, cmdline, memory_manager)
                     })
                     .map_err(Error::KernelLoadThreadSpawn)
@@ -1107,10 +1639,7 @@ impl Vm {
         info!("Configuring system");
         let mem = self.memory_manager.lock().unwrap().boot_guest_memory();
 
-        let initramfs_config = match self.initramfs {
-            Some(_) => Some(self.load_initramfs(&mem)?),
-            None => None,
-        };
+        let (initramfs_config, payload_blobs) = self.load_payloads(&mem)?;
 
         let boot_vcpus = self.cpu_manager.lock().unwrap().boot_vcpus();
         let rsdp_addr = Some(rsdp_addr);
@@ -1130,30 +1659,56 @@ impl Vm {
             .as_ref()
             .and_then(|p| p.serial_number.clone());
 
+        let uuid = self
+            .config
+            .lock()
+            .unwrap()
+            .platform
+            .as_ref()
+            .and_then(|p| p.uuid.clone());
+
         arch::configure_system(
             &mem,
             arch::layout::CMDLINE_START,
             &initramfs_config,
+            &payload_blobs,
             boot_vcpus,
             rsdp_addr,
             sgx_epc_region,
             serial_number.as_deref(),
+            uuid.as_deref(),
         )
         .map_err(Error::ConfigureSystem)?;
         Ok(())
     }
 
     #[cfg(target_arch = "aarch64")]
-    fn configure_system(&mut self, _rsdp_addr: GuestAddress) -> Result<()> {
-        let cmdline = Self::generate_cmdline(&self.config, &self.device_manager)?;
+    fn configure_system(&mut self, rsdp_addr: GuestAddress) -> Result<()> {
+        let mut cmdline =
+            Self::generate_cmdline(&self.config, &self.memory_manager, &self.device_manager)?;
+
+        // Without UEFI firmware, the kernel is told where the RSDP is, and
+        // has to use ACPI even though it is given a device tree.
+        let acpi_direct_boot = self
+            .config
+            .lock()
+            .unwrap()
+            .acpi
+            .as_ref()
+            .map_or(false, |acpi| acpi.direct_boot);
+        if acpi_direct_boot {
+            cmdline
+                .insert_str(format!(
+                    "acpi=force acpi_rsdp=0x{:x}",
+                    rsdp_addr.raw_value()
+                ))
+                .map_err(Error::CmdLineInsertStr)?;
+        }
         let vcpu_mpidrs = self.cpu_manager.lock().unwrap().get_mpidrs();
         let vcpu_topology = self.cpu_manager.lock().unwrap().get_vcpu_topology();
         let mem = self.memory_manager.lock().unwrap().boot_guest_memory();
         let mut pci_space_info: Vec<PciSpaceInfo> = Vec::new();
-        let initramfs_config = match self.initramfs {
-            Some(_) => Some(self.load_initramfs(&mem)?),
-            None => None,
-        };
+        let (initramfs_config, payload_blobs) = self.load_payloads(&mem)?;
 
         let device_info = &self
             .device_manager
@@ -1162,6 +1717,14 @@ impl Vm {
             .get_device_info()
             .clone();
 
+        let (virtio_iommu_bdf, iommu_attached_devices) = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .iommu_attached_devices()
+            .clone()
+            .map_or((None, Vec::new()), |(v, devices)| (Some(v), devices));
+
         for pci_segment in self.device_manager.lock().unwrap().pci_segments().iter() {
             let pci_space = PciSpaceInfo {
                 pci_segment_id: pci_segment.id,
@@ -1170,18 +1733,15 @@ impl Vm {
                 pci_device_space_size: pci_segment.end_of_device_area
                     - pci_segment.start_of_device_area
                     + 1,
+                iommu_attached_devices: iommu_attached_devices
+                    .iter()
+                    .filter(|bdf| bdf.segment() == pci_segment.id)
+                    .map(|bdf| (*bdf).into())
+                    .collect(),
             };
             pci_space_info.push(pci_space);
         }
 
-        let virtio_iommu_bdf = self
-            .device_manager
-            .lock()
-            .unwrap()
-            .iommu_attached_devices()
-            .as_ref()
-            .map(|(v, _)| *v);
-
         let vgic = self
             .device_manager
             .lock()
@@ -1192,7 +1752,7 @@ impl Vm {
             .unwrap()
             .create_vgic(
                 &self.memory_manager.lock().as_ref().unwrap().vm,
-                self.cpu_manager.lock().unwrap().boot_vcpus() as u64,
+                self.cpu_manager.lock().unwrap().max_vcpus() as u64,
             )
             .map_err(|_| {
                 Error::ConfigureSystem(arch::Error::PlatformSpecific(
@@ -1212,6 +1772,22 @@ impl Vm {
                 ))
             })?;
 
+        let fdt_overlays = self
+            .config
+            .lock()
+            .unwrap()
+            .fdt_overlays
+            .iter()
+            .flatten()
+            .map(|overlay| std::fs::read(&overlay.path).map_err(Error::FdtOverlayRead))
+            .collect::<Result<Vec<Vec<u8>>>>()?;
+
+        let fdt_config = self.config.lock().unwrap().fdt.clone();
+        let fdt_base = fdt_config
+            .as_ref()
+            .map(|fdt| std::fs::read(&fdt.base).map_err(Error::FdtBaseRead))
+            .transpose()?;
+
         arch::configure_system(
             &mem,
             cmdline.as_str(),
@@ -1219,11 +1795,24 @@ impl Vm {
             vcpu_topology,
             device_info,
             &initramfs_config,
+            &payload_blobs,
             &pci_space_info,
             virtio_iommu_bdf.map(|bdf| bdf.into()),
             &vgic,
             &self.numa_nodes,
             pmu_supported,
+            self.hypervisor_info.as_ref(),
+            acpi_direct_boot,
+            &fdt_overlays,
+            fdt_base
+                .as_deref()
+                .zip(fdt_config.as_ref())
+                .map(|(dtb, fdt)| arch::aarch64::fdt::FdtBase {
+                    dtb,
+                    patch_memory: fdt.patch_memory,
+                    patch_virtio: fdt.patch_virtio,
+                    edits: &fdt.edits,
+                }),
         )
         .map_err(Error::ConfigureSystem)?;
 
@@ -1268,11 +1857,6 @@ impl Vm {
                 .map_err(Error::SetTerminalCanon)?;
         }
 
-        // Trigger the termination of the signal_handler thread
-        if let Some(signals) = self.signals.take() {
-            signals.close();
-        }
-
         // Wake up the DeviceManager threads so they will get terminated cleanly
         self.device_manager
             .lock()
@@ -1286,10 +1870,7 @@ impl Vm {
             .shutdown()
             .map_err(Error::CpuManager)?;
 
-        // Wait for all the threads to finish
-        for thread in self.threads.drain(..) {
-            thread.join().map_err(Error::ThreadCleanup)?
-        }
+        self.memory_manager.lock().unwrap().release_hugepages();
         *state = new_state;
 
         event!("vm", "shutdown");
@@ -1421,49 +2002,173 @@ impl Vm {
         Err(Error::ResizeZone)
     }
 
-    pub fn add_device(&mut self, mut device_cfg: DeviceConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
+    pub fn throttle_vcpus(&mut self, percentage: u8) -> Result<()> {
+        event!(
+            "vm",
+            "throttling_vcpus",
+            "percentage",
+            percentage.to_string()
+        );
+
+        self.cpu_manager
             .lock()
             .unwrap()
-            .add_device(&mut device_cfg)
-            .map_err(Error::DeviceManager)?;
+            .throttle_vcpus(percentage)
+            .map_err(Error::CpuManager)
+    }
 
-        // Update VmConfig by adding the new device. This is important to
-        // ensure the device would be created in case of a reboot.
-        {
-            let mut config = self.config.lock().unwrap();
-            add_to_config(&mut config.devices, device_cfg);
-        }
+    pub fn set_rate_limiter(
+        &mut self,
+        id: &str,
+        rate_limiter_config: Option<RateLimiterConfig>,
+    ) -> Result<()> {
+        event!("vm", "setting_rate_limiter", "id", id);
+
+        let mut config = self.config.lock().unwrap();
+        let device_rate_limiter_config = config
+            .rate_limiter_config_mut(id)
+            .ok_or_else(|| Error::UnknownRateLimitedDevice(id.to_owned()))?;
 
         self.device_manager
             .lock()
             .unwrap()
-            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+            .set_rate_limiter(id, rate_limiter_config)
             .map_err(Error::DeviceManager)?;
 
-        Ok(pci_device_info)
+        // Update VmConfig so that the rate limiting still applies once the
+        // VM is rebooted.
+        *device_rate_limiter_config = rate_limiter_config;
+
+        Ok(())
     }
 
-    pub fn add_user_device(&mut self, mut device_cfg: UserDeviceConfig) -> Result<PciDeviceInfo> {
-        let pci_device_info = self
-            .device_manager
-            .lock()
-            .unwrap()
-            .add_user_device(&mut device_cfg)
-            .map_err(Error::DeviceManager)?;
+    pub fn set_interrupt_coalescing(
+        &mut self,
+        id: &str,
+        interrupt_coalescing: Option<InterruptCoalescingConfig>,
+    ) -> Result<()> {
+        event!("vm", "setting_interrupt_coalescing", "id", id);
 
-        // Update VmConfig by adding the new device. This is important to
-        // ensure the device would be created in case of a reboot.
-        {
-            let mut config = self.config.lock().unwrap();
-            add_to_config(&mut config.user_devices, device_cfg);
-        }
+        let mut config = self.config.lock().unwrap();
+        let device_interrupt_coalescing = config
+            .interrupt_coalescing_mut(id)
+            .ok_or_else(|| Error::UnknownInterruptCoalescingDevice(id.to_owned()))?;
 
         self.device_manager
             .lock()
             .unwrap()
-            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+            .set_interrupt_coalescing(id, interrupt_coalescing)
+            .map_err(Error::DeviceManager)?;
+
+        // Update VmConfig so that the coalescing still applies once the VM
+        // is rebooted.
+        *device_interrupt_coalescing = interrupt_coalescing;
+
+        Ok(())
+    }
+
+    pub fn queues(&self) -> Vec<DeviceQueues> {
+        self.device_manager.lock().unwrap().queues()
+    }
+
+    pub fn set_queue_size(&mut self, id: &str, queue_size: u16) -> Result<()> {
+        event!("vm", "setting_queue_size", "id", id);
+
+        let mut config = self.config.lock().unwrap();
+        let device_queue_size = config
+            .queue_size_mut(id)
+            .ok_or_else(|| Error::UnknownQueueSizeDevice(id.to_owned()))?;
+
+        self.device_manager
+            .lock()
+            .unwrap()
+            .set_queue_size(id, queue_size)
+            .map_err(Error::DeviceManager)?;
+
+        // Update VmConfig so that the queues keep their size once the VM is
+        // rebooted, and the device is created with them when restored.
+        *device_queue_size = queue_size;
+
+        Ok(())
+    }
+
+    pub fn inject_nmi(&mut self, nmi_data: &VmInjectNmiData) -> Result<()> {
+        event!("vm", "injecting_nmi", "cpu_id", nmi_data.cpu_id.to_string());
+
+        #[cfg(target_arch = "x86_64")]
+        let result = self.cpu_manager.lock().unwrap().inject_nmi(nmi_data.cpu_id);
+        #[cfg(target_arch = "aarch64")]
+        let result = {
+            let gic = self
+                .device_manager
+                .lock()
+                .unwrap()
+                .get_interrupt_controller()
+                .unwrap()
+                .clone();
+            self.cpu_manager
+                .lock()
+                .unwrap()
+                .inject_sgi(nmi_data.cpu_id, nmi_data.sgi, &gic)
+        };
+
+        result.map_err(Error::CpuManager)
+    }
+
+    #[cfg(feature = "fault_injection")]
+    pub fn inject_fault(&mut self, fault_data: &VmInjectFaultData) -> Result<()> {
+        event!("vm", "injecting_fault");
+
+        self.device_manager
+            .lock()
+            .unwrap()
+            .inject_fault(fault_data)
+            .map_err(Error::DeviceManager)
+    }
+
+    pub fn add_device(&mut self, mut device_cfg: DeviceConfig) -> Result<PciDeviceInfo> {
+        let pci_device_info = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .add_device(&mut device_cfg)
+            .map_err(Error::DeviceManager)?;
+
+        // Update VmConfig by adding the new device. This is important to
+        // ensure the device would be created in case of a reboot.
+        {
+            let mut config = self.config.lock().unwrap();
+            add_to_config(&mut config.devices, device_cfg);
+        }
+
+        self.device_manager
+            .lock()
+            .unwrap()
+            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+            .map_err(Error::DeviceManager)?;
+
+        Ok(pci_device_info)
+    }
+
+    pub fn add_user_device(&mut self, mut device_cfg: UserDeviceConfig) -> Result<PciDeviceInfo> {
+        let pci_device_info = self
+            .device_manager
+            .lock()
+            .unwrap()
+            .add_user_device(&mut device_cfg)
+            .map_err(Error::DeviceManager)?;
+
+        // Update VmConfig by adding the new device. This is important to
+        // ensure the device would be created in case of a reboot.
+        {
+            let mut config = self.config.lock().unwrap();
+            add_to_config(&mut config.user_devices, device_cfg);
+        }
+
+        self.device_manager
+            .lock()
+            .unwrap()
+            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
             .map_err(Error::DeviceManager)?;
 
         Ok(pci_device_info)
@@ -1554,7 +2259,7 @@ impl Vm {
         Ok(pci_device_info)
     }
 
-    pub fn add_fs(&mut self, mut fs_cfg: FsConfig) -> Result<PciDeviceInfo> {
+    pub fn add_fs(&mut self, mut fs_cfg: FsConfig) -> Result<Option<PciDeviceInfo>> {
         let pci_device_info = self
             .device_manager
             .lock()
@@ -1569,16 +2274,20 @@ impl Vm {
             add_to_config(&mut config.fs, fs_cfg);
         }
 
-        self.device_manager
-            .lock()
-            .unwrap()
-            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
-            .map_err(Error::DeviceManager)?;
+        // A device without PCI information was added on the virtio-mmio
+        // transport, the guest being notified through the device tree.
+        if pci_device_info.is_some() {
+            self.device_manager
+                .lock()
+                .unwrap()
+                .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+                .map_err(Error::DeviceManager)?;
+        }
 
         Ok(pci_device_info)
     }
 
-    pub fn add_pmem(&mut self, mut pmem_cfg: PmemConfig) -> Result<PciDeviceInfo> {
+    pub fn add_pmem(&mut self, mut pmem_cfg: PmemConfig) -> Result<Option<PciDeviceInfo>> {
         let pci_device_info = self
             .device_manager
             .lock()
@@ -1593,11 +2302,15 @@ impl Vm {
             add_to_config(&mut config.pmem, pmem_cfg);
         }
 
-        self.device_manager
-            .lock()
-            .unwrap()
-            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
-            .map_err(Error::DeviceManager)?;
+        // A device without PCI information was added on the virtio-mmio
+        // transport, the guest being notified through the device tree.
+        if pci_device_info.is_some() {
+            self.device_manager
+                .lock()
+                .unwrap()
+                .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+                .map_err(Error::DeviceManager)?;
+        }
 
         Ok(pci_device_info)
     }
@@ -1650,7 +2363,7 @@ impl Vm {
         Ok(pci_device_info)
     }
 
-    pub fn add_vsock(&mut self, mut vsock_cfg: VsockConfig) -> Result<PciDeviceInfo> {
+    pub fn add_vsock(&mut self, mut vsock_cfg: VsockConfig) -> Result<Option<PciDeviceInfo>> {
         let pci_device_info = self
             .device_manager
             .lock()
@@ -1665,47 +2378,76 @@ impl Vm {
             config.vsock = Some(vsock_cfg);
         }
 
+        // A device without PCI information was added on the virtio-mmio
+        // transport.
+        let notification = if pci_device_info.is_some() {
+            AcpiNotificationFlags::PCI_DEVICES_CHANGED
+        } else {
+            AcpiNotificationFlags::MMIO_DEVICES_CHANGED
+        };
         self.device_manager
             .lock()
             .unwrap()
-            .notify_hotplug(AcpiNotificationFlags::PCI_DEVICES_CHANGED)
+            .notify_hotplug(notification)
             .map_err(Error::DeviceManager)?;
 
         Ok(pci_device_info)
     }
 
     pub fn counters(&self) -> Result<HashMap<String, HashMap<&'static str, Wrapping<u64>>>> {
-        Ok(self.device_manager.lock().unwrap().counters())
+        let mut counters = self.device_manager.lock().unwrap().counters();
+        counters.extend(self.cpu_manager.lock().unwrap().counters());
+        Ok(counters)
     }
 
-    fn os_signal_handler(
-        mut signals: Signals,
-        console_input_clone: Arc<Console>,
-        on_tty: bool,
-        exit_evt: &EventFd,
-    ) {
-        for sig in &HANDLED_SIGNALS {
-            unblock_signal(*sig).unwrap();
-        }
+    pub fn metrics(&self) -> Result<VmMetrics> {
+        let memory_total_size = self.config.lock().unwrap().memory.total_size();
+        let memory_actual_size = memory_total_size - self.balloon_size();
 
-        for signal in signals.forever() {
-            match signal {
-                SIGWINCH => {
-                    console_input_clone.update_console_size();
-                }
-                SIGTERM | SIGINT => {
-                    if on_tty {
-                        io::stdin()
-                            .lock()
-                            .set_canon_mode()
-                            .expect("failed to restore terminal mode");
-                    }
-                    if exit_evt.write(1).is_err() {
-                        std::process::exit(1);
+        Ok(VmMetrics {
+            counters: self.counters()?,
+            memory_total_size,
+            memory_actual_size,
+            queue_depths: self.device_manager.lock().unwrap().queue_depths(),
+        })
+    }
+
+    pub fn security_status(&self, vmm_seccomp_action: &SeccompAction) -> Result<SecurityStatus> {
+        let require_iommu = self
+            .config
+            .lock()
+            .unwrap()
+            .security
+            .as_ref()
+            .map_or(false, |s| s.require_iommu);
+
+        Ok(SecurityStatus {
+            seccomp: security::seccomp_level(&self.seccomp_action),
+            vmm_seccomp: security::seccomp_level(vmm_seccomp_action),
+            require_iommu,
+            devices: self.device_manager.lock().unwrap().iommu_status(),
+        })
+    }
+
+    /// Handle a signal received by the VMM, before it shuts the VM down for
+    /// SIGTERM and SIGINT.
+    pub fn handle_signal(&self, signal: i32) {
+        match signal {
+            SIGWINCH => {
+                self.device_manager
+                    .lock()
+                    .unwrap()
+                    .console()
+                    .update_console_size();
+            }
+            SIGTERM | SIGINT => {
+                if self.on_tty {
+                    if let Err(e) = io::stdin().lock().set_canon_mode() {
+                        error!("Failed restoring the terminal mode: {}", e);
                     }
                 }
-                _ => (),
             }
+            _ => (),
         }
     }
 
@@ -1719,6 +2461,127 @@ impl Vm {
         Ok(())
     }
 
+    #[cfg(feature = "sev_snp")]
+    fn init_sev_snp(&mut self) -> Result<()> {
+        let policy = self.config.lock().unwrap().sev_snp.as_ref().unwrap().policy;
+        self.vm
+            .sev_snp_init(policy)
+            .map_err(Error::InitializeSevSnpVm)?;
+        Ok(())
+    }
+
+    // Adds a range of guest memory to the launch measurement.
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_launch_update(
+        &self,
+        address: GuestAddress,
+        size: u64,
+        page_type: hypervisor::kvm::SevSnpPageType,
+    ) -> Result<()> {
+        let host_address = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .guest_memory()
+            .memory()
+            .get_host_address(address)
+            .map_err(Error::SevSnpGuestMemory)?;
+        self.vm
+            .sev_snp_launch_update(host_address as u64, address.raw_value(), size, page_type)
+            .map_err(Error::LaunchUpdateSevSnp)
+    }
+
+    // The ACPI tables are laid out from the RSDP up to the XSDT, the last of
+    // them.
+    #[cfg(feature = "sev_snp")]
+    fn sev_snp_acpi_tables(&self) -> Result<(u64, u64)> {
+        let mem = self.memory_manager.lock().unwrap().guest_memory().memory();
+        let rsdp = arch::layout::RSDP_POINTER;
+        let xsdt: u64 = mem
+            .read_obj(rsdp.unchecked_add(24))
+            .map_err(Error::SevSnpGuestMemory)?;
+        let xsdt_len: u32 = mem
+            .read_obj(GuestAddress(xsdt).unchecked_add(4))
+            .map_err(Error::SevSnpGuestMemory)?;
+        Ok((rsdp.raw_value(), xsdt + xsdt_len as u64 - rsdp.raw_value()))
+    }
+
+    // Encrypts the firmware, the ACPI tables and the sections described by
+    // the SEV metadata of the firmware, then completes the launch. The
+    // vCPUs must be created first, as their state is saved to their VMSA
+    // when the launch completes.
+    #[cfg(feature = "sev_snp")]
+    fn launch_sev_snp(&mut self) -> Result<()> {
+        use arch::x86_64::sev_snp::*;
+        use hypervisor::kvm::SevSnpPageType;
+
+        // The guest kernel and its command line are loaded by the firmware
+        // from the disk image, so the swiotlb size can only be suggested.
+        if let Some(swiotlb) = swiotlb::cmdline(&self.config.lock().unwrap()) {
+            warn!(
+                "Add \"{}\" to the guest kernel command line for the swiotlb to fit the devices",
+                swiotlb
+            );
+        }
+
+        let mut firmware_file = File::open(
+            &self
+                .config
+                .lock()
+                .unwrap()
+                .sev_snp
+                .as_ref()
+                .unwrap()
+                .firmware,
+        )
+        .map_err(Error::LoadSevSnpFirmware)?;
+        let firmware_size = firmware_file
+            .seek(SeekFrom::End(0))
+            .map_err(Error::LoadSevSnpFirmware)?;
+        firmware_file
+            .seek(SeekFrom::Start(0))
+            .map_err(Error::LoadSevSnpFirmware)?;
+        let sections =
+            parse_sev_snp_firmware(&mut firmware_file).map_err(Error::ParseSevSnpFirmware)?;
+
+        // The firmware is loaded right below 4GiB, and the ACPI tables are
+        // part of the low memory.
+        let contents = [
+            ((4 << 30) - firmware_size, firmware_size),
+            self.sev_snp_acpi_tables()?,
+        ];
+        let ranges = launch_ranges(&contents, &sections).map_err(Error::ParseSevSnpFirmware)?;
+
+        for range in ranges {
+            let address = GuestAddress(range.address);
+            let page_type = match range.r#type {
+                SevSnpLaunchType::Normal => SevSnpPageType::Normal,
+                SevSnpLaunchType::Zero => SevSnpPageType::Zero,
+                SevSnpLaunchType::Secrets => SevSnpPageType::Secrets,
+                SevSnpLaunchType::Cpuid => {
+                    let cpuid = self.cpu_manager.lock().unwrap().common_cpuid();
+                    let page = create_cpuid_page(cpuid.as_slice())
+                        .map_err(Error::CreateSevSnpCpuidPage)?;
+                    self.memory_manager
+                        .lock()
+                        .unwrap()
+                        .guest_memory()
+                        .memory()
+                        .write_slice(&page, address)
+                        .map_err(Error::SevSnpGuestMemory)?;
+                    SevSnpPageType::Cpuid
+                }
+            };
+            info!(
+                "Adding to the SEV-SNP launch measurement: address = 0x{:x}, size = 0x{:x}, type = {:?}",
+                range.address, range.size, page_type
+            );
+            self.sev_snp_launch_update(address, range.size, page_type)?;
+        }
+
+        self.vm.sev_snp_finalize().map_err(Error::FinalizeSevSnp)
+    }
+
     #[cfg(feature = "tdx")]
     fn extract_tdvf_sections(&mut self) -> Result<Vec<TdvfSection>> {
         use arch::x86_64::tdx::*;
@@ -1899,7 +2762,7 @@ impl Vm {
                 }
                 TdvfSectionType::PayloadParam => {
                     info!("Copying payload parameters to guest memory");
-                    let cmdline = Self::generate_cmdline(&self.config)?;
+                    let cmdline = Self::generate_cmdline(&self.config, &self.memory_manager)?;
                     mem.write_slice(cmdline.as_str().as_bytes(), GuestAddress(section.address))
                         .unwrap();
                 }
@@ -1950,11 +2813,16 @@ impl Vm {
 
         // Loop over the ACPI tables and copy them to the HOB.
 
+        let acpi_config = self.config.lock().unwrap().acpi.clone();
+        let ssdt_tables =
+            crate::acpi::load_ssdt_tables(acpi_config.as_ref()).map_err(Error::LoadSsdtTables)?;
         for acpi_table in crate::acpi::create_acpi_tables_tdx(
             &self.device_manager,
             &self.cpu_manager,
             &self.memory_manager,
             &self.numa_nodes,
+            acpi_config.as_ref(),
+            ssdt_tables,
         ) {
             hob.add_acpi_table(&mem, acpi_table.as_slice())
                 .map_err(Error::PopulateHob)?;
@@ -1991,47 +2859,6 @@ impl Vm {
         Ok(())
     }
 
-    fn setup_signal_handler(&mut self) -> Result<()> {
-        let console = self.device_manager.lock().unwrap().console().clone();
-        let signals = Signals::new(&HANDLED_SIGNALS);
-        match signals {
-            Ok(signals) => {
-                self.signals = Some(signals.handle());
-                let exit_evt = self.exit_evt.try_clone().map_err(Error::EventFdClone)?;
-                let on_tty = self.on_tty;
-                let signal_handler_seccomp_filter =
-                    get_seccomp_filter(&self.seccomp_action, Thread::SignalHandler)
-                        .map_err(Error::CreateSeccompFilter)?;
-                self.threads.push(
-                    thread::Builder::new()
-                        .name("signal_handler".to_string())
-                        .spawn(move || {
-                            if !signal_handler_seccomp_filter.is_empty() {
-                                if let Err(e) = apply_filter(&signal_handler_seccomp_filter)
-                                    .map_err(Error::ApplySeccompFilter)
-                                {
-                                    error!("Error applying seccomp filter: {:?}", e);
-                                    exit_evt.write(1).ok();
-                                    return;
-                                }
-                            }
-                            std::panic::catch_unwind(AssertUnwindSafe(|| {
-                                Vm::os_signal_handler(signals, console, on_tty, &exit_evt);
-                            }))
-                            .map_err(|_| {
-                                error!("signal_handler thead panicked");
-                                exit_evt.write(1).ok()
-                            })
-                            .ok();
-                        })
-                        .map_err(Error::SignalHandlerSpawn)?,
-                );
-            }
-            Err(e) => error!("Signal not found {}", e),
-        }
-        Ok(())
-    }
-
     fn setup_tty(&self) -> Result<()> {
         if self.on_tty {
             io::stdin()
@@ -2047,10 +2874,20 @@ impl Vm {
     // In case of TDX being used, this is a no-op since the tables will be
     // created and passed when populating the HOB.
 
-    fn create_acpi_tables(&self) -> Option<GuestAddress> {
+    fn create_acpi_tables(&self) -> Result<Option<GuestAddress>> {
         #[cfg(feature = "tdx")]
         if self.config.lock().unwrap().tdx.is_some() {
-            return None;
+            return Ok(None);
+        }
+
+        let acpi_config = self.config.lock().unwrap().acpi.clone();
+        let mut ssdt_tables =
+            crate::acpi::load_ssdt_tables(acpi_config.as_ref()).map_err(Error::LoadSsdtTables)?;
+
+        if !self.payload_blobs.is_empty() {
+            let boot_mem = self.memory_manager.lock().unwrap().boot_guest_memory();
+            let (_, payload_blobs) = self.payload_layout(&boot_mem)?;
+            ssdt_tables.push(crate::acpi::create_payload_blobs_ssdt(&payload_blobs));
         }
 
         let mem = self.memory_manager.lock().unwrap().guest_memory().memory();
@@ -2061,18 +2898,26 @@ impl Vm {
             &self.cpu_manager,
             &self.memory_manager,
             &self.numa_nodes,
+            acpi_config.as_ref(),
+            ssdt_tables,
         );
         info!("Created ACPI tables: rsdp_addr = 0x{:x}", rsdp_addr.0);
 
-        Some(rsdp_addr)
+        Ok(Some(rsdp_addr))
     }
 
     #[cfg(target_arch = "x86_64")]
     fn entry_point(&mut self) -> Result<Option<EntryPoint>> {
-        self.load_kernel_handle
+        let loaded = self
+            .load_kernel_handle
             .take()
             .map(|handle| handle.join().map_err(Error::KernelLoadThreadJoin)?)
-            .transpose()
+            .transpose()?;
+
+        Ok(loaded.map(|(entry_point, kernel_region)| {
+            self.kernel_region = Some(kernel_region);
+            entry_point
+        }))
     }
 
     #[cfg(target_arch = "aarch64")]
@@ -2085,6 +2930,18 @@ impl Vm {
     }
 
     pub fn boot(&mut self) -> Result<()> {
+        self.boot_with_state(VmState::Running)
+    }
+
+    /// Boots the VM in the paused state: the vCPUs are created, configured
+    /// and their threads started, but they don't run any guest instruction
+    /// until the VM is resumed. This gives a chance to external debuggers or
+    /// device setup scripts to attach to the VM beforehand.
+    pub fn boot_paused(&mut self) -> Result<()> {
+        self.boot_with_state(VmState::Paused)
+    }
+
+    fn boot_with_state(&mut self, boot_state: VmState) -> Result<()> {
         info!("Booting VM");
         event!("vm", "booting");
         let current_state = self.get_state()?;
@@ -2092,23 +2949,49 @@ impl Vm {
             return self.resume().map_err(Error::Resume);
         }
 
+        let _boot_phase = BootPhase::start("boot");
+
         let new_state = if self.stop_on_boot {
             VmState::BreakPoint
         } else {
-            VmState::Running
+            boot_state
         };
         current_state.valid_transition(new_state)?;
 
         // Do earlier to parallelise with loading kernel
         #[cfg(target_arch = "x86_64")]
-        let rsdp_addr = self.create_acpi_tables();
+        let rsdp_addr = self.create_acpi_tables()?;
 
-        self.setup_signal_handler()?;
         self.setup_tty()?;
 
         // Load kernel synchronously or if asynchronous then wait for load to
         // finish.
+        let phase = BootPhase::start("kernel-load");
         let entry_point = self.entry_point()?;
+        drop(phase);
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.boot_entry_point = entry_point;
+        }
+        // The guest uses ACPI when booted through the UEFI firmware or told
+        // to do so, rather than the device tree.
+        #[cfg(target_arch = "aarch64")]
+        {
+            let uefi_boot = entry_point.map_or(false, |entry_point| {
+                entry_point.entry_addr == arch::layout::UEFI_START
+            });
+            let acpi_direct_boot = self
+                .config
+                .lock()
+                .unwrap()
+                .acpi
+                .as_ref()
+                .map_or(false, |acpi| acpi.direct_boot);
+            self.device_manager
+                .lock()
+                .unwrap()
+                .set_acpi_guest(uefi_boot || acpi_direct_boot);
+        }
 
         // The initial TDX configuration must be done before the vCPUs are
         // created
@@ -2117,12 +3000,21 @@ impl Vm {
             self.init_tdx()?;
         }
 
+        // Same goes for SEV-SNP, whose launch must be started before the
+        // vCPUs are created
+        #[cfg(feature = "sev_snp")]
+        if self.config.lock().unwrap().sev_snp.is_some() {
+            self.init_sev_snp()?;
+        }
+
         // Create and configure vcpus
+        let phase = BootPhase::start("vcpus-create");
         self.cpu_manager
             .lock()
             .unwrap()
             .create_boot_vcpus(entry_point)
             .map_err(Error::CpuManager)?;
+        drop(phase);
 
         #[cfg(feature = "tdx")]
         let sections = if self.config.lock().unwrap().tdx.is_some() {
@@ -2143,7 +3035,7 @@ impl Vm {
         // On aarch64 the ACPI tables depend on the vCPU mpidr which is only
         // available after they are configured
         #[cfg(target_arch = "aarch64")]
-        let rsdp_addr = self.create_acpi_tables();
+        let rsdp_addr = self.create_acpi_tables()?;
 
         // Configure shared state based on loaded kernel
         entry_point
@@ -2171,17 +3063,146 @@ impl Vm {
             self.vm.tdx_finalize().map_err(Error::FinalizeTdx)?;
         }
 
+        #[cfg(feature = "sev_snp")]
+        if self.config.lock().unwrap().sev_snp.is_some() {
+            self.launch_sev_snp()?;
+        }
+
         if new_state == VmState::Running {
+            let _phase = BootPhase::start("vcpus-start");
             self.cpu_manager
                 .lock()
                 .unwrap()
                 .start_boot_vcpus()
                 .map_err(Error::CpuManager)?;
+        } else if new_state == VmState::Paused {
+            let _phase = BootPhase::start("vcpus-start");
+            self.cpu_manager
+                .lock()
+                .unwrap()
+                .start_boot_vcpus_paused()
+                .map_err(Error::CpuManager)?;
         }
 
         let mut state = self.state.try_write().map_err(|_| Error::PoisonedState)?;
         *state = new_state;
         event!("vm", "booted");
+        if new_state == VmState::Paused {
+            event!("vm", "paused");
+        }
+        Ok(())
+    }
+
+    // An in-place reboot relies on the vCPUs being configured to directly
+    // jump into the kernel, as they can't be brought back to the reset
+    // vector firmware expects. Devices passed through to the guest can't
+    // be reset without being re-created either.
+    #[cfg(target_arch = "x86_64")]
+    fn can_reboot_in_place(&self) -> bool {
+        #[cfg(feature = "tdx")]
+        if self.config.lock().unwrap().tdx.is_some() {
+            return false;
+        }
+
+        #[cfg(feature = "sev_snp")]
+        if self.config.lock().unwrap().sev_snp.is_some() {
+            return false;
+        }
+
+        matches!(
+            self.boot_entry_point,
+            Some(EntryPoint {
+                entry_addr: Some(_)
+            })
+        ) && matches!(self.get_state(), Ok(VmState::Running) | Ok(VmState::Paused))
+            && !self
+                .device_manager
+                .lock()
+                .unwrap()
+                .has_passthrough_devices()
+    }
+
+    /// Reboots the VM without re-creating it. The vCPUs are stopped, the
+    /// devices are quiesced and reset (see `DeviceManager::reset_devices()`
+    /// for the ordering), then the kernel, initramfs and ACPI tables are
+    /// loaded again before the boot vCPUs are configured and restarted.
+    /// The VM is left in the `Running` state on failure, so that it can
+    /// still be shut down and re-created instead.
+    #[cfg(target_arch = "x86_64")]
+    pub fn reboot(&mut self) -> Result<()> {
+        if !self.can_reboot_in_place() {
+            return Err(Error::InPlaceRebootNotSupported);
+        }
+
+        info!("Rebooting VM in place");
+        event!("vm", "rebooting");
+
+        if self.get_state()? == VmState::Paused {
+            self.resume().map_err(Error::Resume)?;
+        }
+
+        // Complete any pending activation as the vCPU which triggered it
+        // is waiting for it.
+        self.activate_virtio_devices()?;
+
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .stop_vcpus()
+            .map_err(Error::CpuManager)?;
+
+        // The VM reboots with the vCPUs it has, as resized, before the ACPI
+        // tables describing them are generated again.
+        let boot_vcpus = self.config.lock().unwrap().cpus.boot_vcpus;
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .set_boot_vcpus(boot_vcpus)
+            .map_err(Error::CpuManager)?;
+
+        // No vCPU can reach the devices anymore, they can be quiesced and
+        // reset before the guest memory gets overwritten.
+        self.device_manager
+            .lock()
+            .unwrap()
+            .reset_devices()
+            .map_err(Error::DeviceManager)?;
+
+        let kernel = self
+            .config
+            .lock()
+            .unwrap()
+            .kernel
+            .as_ref()
+            .map(|k| File::open(&k.path))
+            .transpose()
+            .map_err(Error::KernelFile)?;
+        self.load_kernel_handle =
+            Self::load_kernel_async(&kernel, &self.memory_manager, &self.config)?;
+
+        let rsdp_addr = self.create_acpi_tables()?;
+        let entry_point = self.entry_point()?;
+
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .reset_boot_vcpus(entry_point)
+            .map_err(Error::CpuManager)?;
+
+        entry_point
+            .map(|_| self.configure_system(rsdp_addr.unwrap()))
+            .transpose()?;
+
+        self.cpu_manager
+            .lock()
+            .unwrap()
+            .start_boot_vcpus()
+            .map_err(Error::CpuManager)?;
+
+        let mut state = self.state.try_write().map_err(|_| Error::PoisonedState)?;
+        *state = VmState::Running;
+        event!("vm", "rebooted");
+
         Ok(())
     }
 
@@ -2243,7 +3264,7 @@ impl Vm {
     /// Restore the vGIC from the VM snapshot and enable the interrupt controller routing.
     fn restore_vgic_and_enable_interrupt(
         &self,
-        vm_snapshot: &Snapshot,
+        vm_snapshot: &mut Snapshot,
     ) -> std::result::Result<(), MigratableError> {
         let saved_vcpu_states = self.cpu_manager.lock().unwrap().get_saved_states();
         // The number of vCPUs is the same as the number of saved vCPU states.
@@ -2280,7 +3301,7 @@ impl Vm {
             .set_gicr_typers(&saved_vcpu_states);
 
         // Restore GIC states.
-        if let Some(gicv3_its_snapshot) = vm_snapshot.snapshots.get(GIC_V3_ITS_SNAPSHOT_ID) {
+        if let Some(gicv3_its_snapshot) = vm_snapshot.take_snapshot(GIC_V3_ITS_SNAPSHOT_ID) {
             self.device_manager
                 .lock()
                 .unwrap()
@@ -2288,7 +3309,7 @@ impl Vm {
                 .unwrap()
                 .lock()
                 .unwrap()
-                .restore(*gicv3_its_snapshot.clone())?;
+                .restore(gicv3_its_snapshot)?;
         } else {
             return Err(MigratableError::Restore(anyhow!(
                 "Missing GicV3Its snapshot"
@@ -2319,6 +3340,20 @@ impl Vm {
         self.device_manager.lock().unwrap().balloon_size()
     }
 
+    pub fn guest_panic_events(&self) -> u8 {
+        self.device_manager.lock().unwrap().guest_panic_events()
+    }
+
+    /// Epoll file descriptor of the device events handled by the control
+    /// loop of the VMM, closed along with the VM.
+    pub fn device_events_fd(&self) -> RawFd {
+        self.device_manager.lock().unwrap().device_events_fd()
+    }
+
+    pub fn handle_device_events(&self) {
+        self.device_manager.lock().unwrap().handle_device_events()
+    }
+
     pub fn receive_memory_regions<F>(
         &mut self,
         ranges: &MemoryRangeTable,
@@ -2361,6 +3396,92 @@ impl Vm {
         Ok(())
     }
 
+    /// Select the format the guest memory is written with by the next
+    /// snapshot sent through `Transportable::send()`.
+    pub fn set_snapshot_format(&self, format: SnapshotFormat) {
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .set_snapshot_format(format);
+    }
+
+    /// Select whether the next snapshot only stores the guest memory written
+    /// since the previous one, which it is then based on.
+    pub fn set_snapshot_incremental(&self, incremental: bool) {
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .set_snapshot_incremental(incremental);
+    }
+
+    /// Track the guest memory written between the scheduled snapshots. The
+    /// dirty pages logging then keeps running until the VM is shut down,
+    /// whatever else stops it.
+    pub fn start_scheduled_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        if self.scheduled_dirty.is_none() {
+            self.start_dirty_log()?;
+            self.scheduled_dirty = Some(0);
+        }
+
+        Ok(())
+    }
+
+    /// Guest memory written since the last scheduled snapshot, in bytes.
+    pub fn scheduled_dirty(&mut self) -> std::result::Result<u64, MigratableError> {
+        self.dirty_log()?;
+        Ok(self.scheduled_dirty.unwrap_or_default())
+    }
+
+    /// Account for a scheduled snapshot taken, the guest memory written
+    /// until now being part of it.
+    pub fn reset_scheduled_dirty(&mut self) -> std::result::Result<(), MigratableError> {
+        self.dirty_log()?;
+        if let Some(dirty) = self.scheduled_dirty.as_mut() {
+            *dirty = 0;
+        }
+
+        Ok(())
+    }
+
+    /// End the chain of incremental snapshots, if any, the next incremental
+    /// snapshot storing all the guest memory again.
+    pub fn end_snapshot_chain(&mut self) -> std::result::Result<(), MigratableError> {
+        if !self.memory_manager.lock().unwrap().snapshot_chain_started() {
+            return Ok(());
+        }
+
+        self.memory_manager.lock().unwrap().end_snapshot_chain();
+        self.stop_dirty_log()
+    }
+
+    // Incremental snapshots depend on the dirty pages logging of the whole
+    // VM, as the devices write to the guest memory as well. The logging is
+    // started along with a chain of incremental snapshots, and stopped once
+    // a full snapshot ends it. Devices which can't log the pages they write,
+    // such as vhost-net, fail starting it.
+    fn snapshot_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        let (incremental, chain_started) = {
+            let memory_manager = self.memory_manager.lock().unwrap();
+            (
+                memory_manager.snapshot_incremental(),
+                memory_manager.snapshot_chain_started(),
+            )
+        };
+
+        match (incremental, chain_started) {
+            (true, true) => self.dirty_log().map(|_| ()),
+            (true, false) => self.start_dirty_log().map_err(|e| {
+                // Don't leave the guest memory logging on when a device
+                // failed starting it.
+                if let Err(e) = self.memory_manager.lock().unwrap().stop_dirty_log() {
+                    warn!("Error stopping the dirty pages logging: {:?}", e);
+                }
+                e
+            }),
+            (false, _) => self.end_snapshot_chain(),
+        }
+    }
+
     pub fn send_memory_fds(
         &mut self,
         socket: &mut UnixStream,
@@ -2450,6 +3571,34 @@ impl Vm {
         self.device_manager.lock().unwrap().device_tree()
     }
 
+    pub fn device_tree_info(&self) -> Vec<DeviceNodeInfo> {
+        self.device_tree().lock().unwrap().info()
+    }
+
+    /// Write the memory of each zone to the destination, to be used as the
+    /// template of VMs restored from a snapshot of this one.
+    pub fn memory_template(&self, destination_url: &str) -> Result<()> {
+        if self.get_state()? != VmState::Paused {
+            return Err(Error::VmNotPaused);
+        }
+
+        let directory = url_to_path(destination_url)
+            .map_err(|e| Error::MemoryTemplate(MemoryManagerError::MemoryTemplate(e)))?;
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .write_memory_templates(&directory)
+            .map_err(Error::MemoryTemplate)
+    }
+
+    /// Select the devices which won't be restored from the snapshot
+    pub fn set_fresh_devices(&self, fresh_devices: Vec<String>) {
+        self.device_manager
+            .lock()
+            .unwrap()
+            .set_fresh_devices(fresh_devices);
+    }
+
     pub fn activate_virtio_devices(&self) -> Result<()> {
         self.device_manager
             .lock()
@@ -2481,6 +3630,18 @@ impl Vm {
         self.memory_manager.lock().unwrap().snapshot_data()
     }
 
+    /// Host address and size of the mappings of the guest memory.
+    pub fn guest_memory_mappings(&self) -> Vec<(u64, u64)> {
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .guest_memory()
+            .memory()
+            .iter()
+            .map(|region| (region.as_ptr() as u64, region.len()))
+            .collect()
+    }
+
     #[cfg(all(target_arch = "x86_64", feature = "gdb"))]
     pub fn debug_request(
         &mut self,
@@ -2528,33 +3689,60 @@ impl Vm {
     #[cfg(feature = "guest_debug")]
     fn get_dump_state(
         &mut self,
-        destination_url: &str,
+        coredump_data: &VmCoredumpData,
     ) -> std::result::Result<DumpState, GuestDebuggableError> {
         let nr_cpus = self.config.lock().unwrap().cpus.boot_vcpus as u32;
         let elf_note_size = self.get_note_size(NoteDescType::ElfAndVmm, nr_cpus) as isize;
         let mut elf_phdr_num = 1;
         let elf_sh_info = 0;
-        let coredump_file_path = url_to_file(destination_url)?;
-        let mapping_num = self.memory_manager.lock().unwrap().num_guest_ram_mappings();
+        let coredump_file_path = url_to_file(&coredump_data.destination_url)?;
+        let free_ranges = if coredump_data.exclude_free_pages {
+            self.device_manager
+                .lock()
+                .unwrap()
+                .balloon_inflated_ranges()
+        } else {
+            Vec::new()
+        };
+        let mem_ranges = self
+            .memory_manager
+            .lock()
+            .unwrap()
+            .coredump_memory_ranges(&free_ranges);
 
-        if mapping_num < UINT16_MAX - 2 {
-            elf_phdr_num += mapping_num as u16;
+        if (mem_ranges.len() as u32) < UINT16_MAX - 2 {
+            elf_phdr_num += mem_ranges.len() as u16;
         } else {
-            panic!("mapping num beyond 65535 not supported");
+            return Err(GuestDebuggableError::Coredump(anyhow!(
+                "Too many memory ranges to dump: {}",
+                mem_ranges.len()
+            )));
         }
-        let coredump_file = OpenOptions::new()
+        let mut coredump_file = OpenOptions::new()
             .read(true)
             .write(true)
             .create_new(true)
-            .open(coredump_file_path)
+            .open(&coredump_file_path)
             .map_err(|e| GuestDebuggableError::Coredump(e.into()))?;
 
+        // A compressed dump is first written to a scratch file, unlinked
+        // right away so that it doesn't outlive the VMM.
+        let mut destination = None;
+        if coredump_data.compress {
+            let mut scratch_path = coredump_file_path.into_os_string();
+            scratch_path.push(".partial");
+            let scratch_file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&scratch_path)
+                .map_err(|e| GuestDebuggableError::Coredump(e.into()))?;
+            std::fs::remove_file(&scratch_path).map_err(GuestDebuggableError::CoredumpFile)?;
+            destination = Some(std::mem::replace(&mut coredump_file, scratch_file));
+        }
+
         let mem_offset = self.coredump_get_mem_offset(elf_phdr_num, elf_note_size);
-        let mem_data = self
-            .memory_manager
-            .lock()
-            .unwrap()
-            .coredump_memory_regions(mem_offset);
+        let mem_data = CoredumpMemoryRegions::new(&mem_ranges, mem_offset);
 
         Ok(DumpState {
             elf_note_size,
@@ -2563,6 +3751,8 @@ impl Vm {
             mem_offset,
             mem_info: Some(mem_data),
             file: Some(coredump_file),
+            sparse: coredump_data.sparse,
+            destination,
         })
     }
 
@@ -2670,6 +3860,22 @@ impl Snapshottable for Vm {
             }
         }
 
+        #[cfg(feature = "sev_snp")]
+        {
+            if self.config.lock().unwrap().sev_snp.is_some() {
+                return Err(MigratableError::Snapshot(anyhow!(
+                    "Snapshot not possible with SEV-SNP VM"
+                )));
+            }
+        }
+
+        // The state of the nested guests isn't saved.
+        if self.config.lock().unwrap().cpus.nested {
+            return Err(MigratableError::Snapshot(anyhow!(
+                "Snapshot not possible with nested virtualization enabled"
+            )));
+        }
+
         let current_state = self.get_state().unwrap();
         if current_state != VmState::Paused {
             return Err(MigratableError::Snapshot(anyhow!(
@@ -2688,6 +3894,9 @@ impl Snapshottable for Vm {
                 None,
                 phys_bits,
                 self.config.lock().unwrap().cpus.kvm_hyperv,
+                self.config.lock().unwrap().cpus.nested,
+                self.config.lock().unwrap().cpus.baseline,
+                self.config.lock().unwrap().cpus.tsc_khz,
                 #[cfg(feature = "tdx")]
                 tdx_enabled,
             )
@@ -2710,6 +3919,8 @@ impl Snapshottable for Vm {
         })
         .map_err(|e| MigratableError::Snapshot(e.into()))?;
 
+        self.snapshot_dirty_log()?;
+
         vm_snapshot.add_snapshot(self.cpu_manager.lock().unwrap().snapshot()?);
         vm_snapshot.add_snapshot(self.memory_manager.lock().unwrap().snapshot()?);
 
@@ -2718,16 +3929,13 @@ impl Snapshottable for Vm {
             .map_err(|e| MigratableError::Snapshot(e.into()))?;
 
         vm_snapshot.add_snapshot(self.device_manager.lock().unwrap().snapshot()?);
-        vm_snapshot.add_data_section(SnapshotDataSection {
-            id: format!("{}-section", VM_SNAPSHOT_ID),
-            snapshot: vm_snapshot_data,
-        });
+        vm_snapshot.add_data_section(SnapshotDataSection::new(VM_SNAPSHOT_ID, vm_snapshot_data));
 
         event!("vm", "snapshotted");
         Ok(vm_snapshot)
     }
 
-    fn restore(&mut self, snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
+    fn restore(&mut self, mut snapshot: Snapshot) -> std::result::Result<(), MigratableError> {
         event!("vm", "restoring");
 
         let current_state = self
@@ -2742,33 +3950,32 @@ impl Snapshottable for Vm {
         self.load_clock_from_snapshot(&snapshot)
             .map_err(|e| MigratableError::Restore(anyhow!("Error restoring clock: {:?}", e)))?;
 
-        if let Some(memory_manager_snapshot) = snapshot.snapshots.get(MEMORY_MANAGER_SNAPSHOT_ID) {
+        if let Some(memory_manager_snapshot) = snapshot.take_snapshot(MEMORY_MANAGER_SNAPSHOT_ID) {
             self.memory_manager
                 .lock()
                 .unwrap()
-                .restore(*memory_manager_snapshot.clone())?;
+                .restore(memory_manager_snapshot)?;
         } else {
             return Err(MigratableError::Restore(anyhow!(
                 "Missing memory manager snapshot"
             )));
         }
 
-        if let Some(device_manager_snapshot) = snapshot.snapshots.get(DEVICE_MANAGER_SNAPSHOT_ID) {
-            self.device_manager
-                .lock()
-                .unwrap()
-                .restore(*device_manager_snapshot.clone())?;
-        } else {
-            return Err(MigratableError::Restore(anyhow!(
-                "Missing device manager snapshot"
-            )));
-        }
+        // The devices are restored from the device manager snapshot once the
+        // vCPUs are, the device manager only needs its own section for now.
+        let device_manager_snapshot = snapshot
+            .take_snapshot(DEVICE_MANAGER_SNAPSHOT_ID)
+            .ok_or_else(|| MigratableError::Restore(anyhow!("Missing device manager snapshot")))?;
+        self.device_manager
+            .lock()
+            .unwrap()
+            .restore(device_manager_snapshot.without_snapshots())?;
 
-        if let Some(cpu_manager_snapshot) = snapshot.snapshots.get(CPU_MANAGER_SNAPSHOT_ID) {
+        if let Some(cpu_manager_snapshot) = snapshot.take_snapshot(CPU_MANAGER_SNAPSHOT_ID) {
             self.cpu_manager
                 .lock()
                 .unwrap()
-                .restore(*cpu_manager_snapshot.clone())?;
+                .restore(cpu_manager_snapshot)?;
         } else {
             return Err(MigratableError::Restore(anyhow!(
                 "Missing CPU manager snapshot"
@@ -2776,18 +3983,12 @@ impl Snapshottable for Vm {
         }
 
         #[cfg(target_arch = "aarch64")]
-        self.restore_vgic_and_enable_interrupt(&snapshot)?;
+        self.restore_vgic_and_enable_interrupt(&mut snapshot)?;
 
-        if let Some(device_manager_snapshot) = snapshot.snapshots.get(DEVICE_MANAGER_SNAPSHOT_ID) {
-            self.device_manager
-                .lock()
-                .unwrap()
-                .restore_devices(*device_manager_snapshot.clone())?;
-        } else {
-            return Err(MigratableError::Restore(anyhow!(
-                "Missing device manager snapshot"
-            )));
-        }
+        self.device_manager
+            .lock()
+            .unwrap()
+            .restore_devices(device_manager_snapshot)?;
 
         // Now we can start all vCPUs from here.
         self.cpu_manager
@@ -2798,9 +3999,6 @@ impl Snapshottable for Vm {
                 MigratableError::Restore(anyhow!("Cannot start restored vCPUs: {:#?}", e))
             })?;
 
-        self.setup_signal_handler().map_err(|e| {
-            MigratableError::Restore(anyhow!("Could not setup signal handler: {:#?}", e))
-        })?;
         self.setup_tty()
             .map_err(|e| MigratableError::Restore(anyhow!("Could not setup tty: {:#?}", e)))?;
 
@@ -2841,30 +4039,42 @@ impl Transportable for Vm {
             .map_err(|e| MigratableError::MigrateSend(e.into()))?;
 
         let mut snapshot_state_path = url_to_path(destination_url)?;
+        let mut snapshot_state_data_path = snapshot_state_path.clone();
         snapshot_state_path.push(SNAPSHOT_STATE_FILE);
+        snapshot_state_data_path.push(SNAPSHOT_STATE_DATA_FILE);
 
-        // Create the snapshot state file
-        let mut snapshot_state_file = OpenOptions::new()
+        // Create the snapshot state files
+        let snapshot_state_file = OpenOptions::new()
             .read(true)
             .write(true)
             .create_new(true)
             .open(snapshot_state_path)
             .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+        let snapshot_state_data_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(snapshot_state_data_path)
+            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
 
-        // Serialize and write the snapshot state
-        let vm_state =
-            serde_json::to_vec(snapshot).map_err(|e| MigratableError::MigrateSend(e.into()))?;
-
-        snapshot_state_file
-            .write(&vm_state)
+        // Write the description of the snapshot tree, and the data of its
+        // sections to the data file, so that they can be streamed back one
+        // at a time on restore.
+        let mut snapshot_state_writer = BufWriter::new(snapshot_state_file);
+        let mut snapshot_state_data_writer = BufWriter::new(snapshot_state_data_file);
+        snapshot.write_state(&mut snapshot_state_writer, &mut snapshot_state_data_writer)?;
+        snapshot_state_writer
+            .flush()
+            .map_err(|e| MigratableError::MigrateSend(e.into()))?;
+        snapshot_state_data_writer
+            .flush()
             .map_err(|e| MigratableError::MigrateSend(e.into()))?;
 
         // Tell the memory manager to also send/write its own snapshot.
         if let Some(memory_manager_snapshot) = snapshot.snapshots.get(MEMORY_MANAGER_SNAPSHOT_ID) {
-            self.memory_manager
-                .lock()
-                .unwrap()
-                .send(&*memory_manager_snapshot.clone(), destination_url)?;
+            let mut memory_manager = self.memory_manager.lock().unwrap();
+            memory_manager.send(memory_manager_snapshot, destination_url)?;
+            memory_manager.set_snapshot_base(destination_url)?;
         } else {
             return Err(MigratableError::Restore(anyhow!(
                 "Missing memory manager snapshot"
@@ -2877,20 +4087,49 @@ impl Transportable for Vm {
 
 impl Migratable for Vm {
     fn start_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        // The logging is already running for the chain of incremental
+        // snapshots or the scheduled ones. Reading the log accounts the
+        // pages dirtied so far for them, and resets it as starting it would.
+        if self.memory_manager.lock().unwrap().snapshot_chain_started()
+            || self.scheduled_dirty.is_some()
+        {
+            return self.dirty_log().map(|_| ());
+        }
+
         self.memory_manager.lock().unwrap().start_dirty_log()?;
         self.device_manager.lock().unwrap().start_dirty_log()
     }
 
     fn stop_dirty_log(&mut self) -> std::result::Result<(), MigratableError> {
+        // The chain of incremental snapshots or the scheduled ones still
+        // depend on the logging.
+        if self.memory_manager.lock().unwrap().snapshot_chain_started()
+            || self.scheduled_dirty.is_some()
+        {
+            return self.dirty_log().map(|_| ());
+        }
+
         self.memory_manager.lock().unwrap().stop_dirty_log()?;
         self.device_manager.lock().unwrap().stop_dirty_log()
     }
 
     fn dirty_log(&mut self) -> std::result::Result<MemoryRangeTable, MigratableError> {
-        Ok(MemoryRangeTable::new_from_tables(vec![
+        let table = MemoryRangeTable::new_from_tables(vec![
             self.memory_manager.lock().unwrap().dirty_log()?,
             self.device_manager.lock().unwrap().dirty_log()?,
-        ]))
+        ]);
+
+        // Whoever reads the log, the pages are still dirty for the next
+        // incremental or scheduled snapshot.
+        self.memory_manager
+            .lock()
+            .unwrap()
+            .add_snapshot_dirty(&table);
+        if let Some(dirty) = self.scheduled_dirty.as_mut() {
+            *dirty += table.regions().iter().map(|r| r.length).sum::<u64>();
+        }
+
+        Ok(table)
     }
 
     fn start_migration(&mut self) -> std::result::Result<(), MigratableError> {
@@ -3008,7 +4247,10 @@ impl Elf64Writable for Vm {}
 
 #[cfg(feature = "guest_debug")]
 impl GuestDebuggable for Vm {
-    fn coredump(&mut self, destination_url: &str) -> std::result::Result<(), GuestDebuggableError> {
+    fn coredump(
+        &mut self,
+        coredump_data: &VmCoredumpData,
+    ) -> std::result::Result<(), GuestDebuggableError> {
         event!("vm", "coredumping");
 
         #[cfg(feature = "tdx")]
@@ -3020,6 +4262,15 @@ impl GuestDebuggable for Vm {
             }
         }
 
+        #[cfg(feature = "sev_snp")]
+        {
+            if self.config.lock().unwrap().sev_snp.is_some() {
+                return Err(GuestDebuggableError::Coredump(anyhow!(
+                    "Coredump not possible with SEV-SNP VM"
+                )));
+            }
+        }
+
         let current_state = self.get_state().unwrap();
         if current_state != VmState::Paused {
             return Err(GuestDebuggableError::Coredump(anyhow!(
@@ -3027,7 +4278,7 @@ impl GuestDebuggable for Vm {
             )));
         }
 
-        let coredump_state = self.get_dump_state(destination_url)?;
+        let coredump_state = self.get_dump_state(coredump_data)?;
 
         self.write_header(&coredump_state)?;
         self.write_note(&coredump_state)?;
@@ -3045,7 +4296,18 @@ impl GuestDebuggable for Vm {
         self.memory_manager
             .lock()
             .unwrap()
-            .coredump_iterate_save_mem(&coredump_state)
+            .coredump_iterate_save_mem(&coredump_state)?;
+
+        if let Some(destination) = coredump_state.destination.as_ref() {
+            let mut coredump_file = coredump_state.file.as_ref().unwrap();
+            coredump_file
+                .seek(SeekFrom::Start(0))
+                .map_err(GuestDebuggableError::CoredumpFile)?;
+            zstd::stream::copy_encode(coredump_file, destination, 0)
+                .map_err(GuestDebuggableError::CoredumpFile)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -3119,6 +4381,30 @@ mod tests {
         test_vm_state_transitions(VmState::Paused);
     }
 
+    #[test]
+    fn test_initramfs_layout() {
+        use std::io::Write;
+        use vmm_sys_util::tempfile::TempFile;
+
+        assert_eq!(Vm::initramfs_layout(&[]).unwrap(), (vec![], 0));
+
+        let initramfs: Vec<File> = [&b"12345"[..], b"", b"678", b"9abc"]
+            .iter()
+            .map(|content| {
+                let mut file = TempFile::new().unwrap().into_file();
+                file.write_all(content).unwrap();
+                file
+            })
+            .collect();
+
+        // Each file starts on a 4 bytes boundary, the empty ones taking no
+        // space.
+        assert_eq!(
+            Vm::initramfs_layout(&initramfs).unwrap(),
+            (vec![(0, 5), (8, 0), (8, 3), (12, 4)], 16)
+        );
+    }
+
     #[cfg(feature = "tdx")]
     #[test]
     fn test_hob_memory_resources() {
@@ -3323,6 +4609,59 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_layout_payloads() {
+        let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x1000_0000)]).unwrap();
+        let kernel_region = Some((GuestAddress(0x100_0000), 0x100_0000));
+
+        let blobs = vec![
+            ("fw".to_owned(), 0x2000, Some(GuestAddress(0x800_0000))),
+            ("cfg".to_owned(), 0x1000, None),
+        ];
+        let (initramfs, layout) =
+            layout_payloads(&gm, Some(0x1800), &blobs, kernel_region).unwrap();
+        let initramfs = initramfs.unwrap();
+        assert_eq!(initramfs.address, GuestAddress(0xfff_e000));
+        assert_eq!(initramfs.size, 0x1800);
+        assert_eq!(
+            layout,
+            vec![
+                arch::PayloadBlob {
+                    id: "fw".to_owned(),
+                    address: GuestAddress(0x800_0000),
+                    size: 0x2000,
+                },
+                arch::PayloadBlob {
+                    id: "cfg".to_owned(),
+                    address: GuestAddress(0xfff_d000),
+                    size: 0x1000,
+                },
+            ]
+        );
+
+        // Overlapping the ACPI tables, the kernel, the initramfs or another
+        // blob.
+        for address in [0xa_0000, 0x180_0000, 0xfff_e000, 0x800_1000] {
+            let blobs = vec![
+                ("fw".to_owned(), 0x2000, Some(GuestAddress(0x800_0000))),
+                ("bad".to_owned(), 0x1000, Some(GuestAddress(address))),
+            ];
+            assert!(matches!(
+                layout_payloads(&gm, Some(0x1800), &blobs, kernel_region),
+                Err(Error::PayloadBlobOverlap(id)) if id == "bad"
+            ));
+        }
+
+        // Beyond the end of the RAM.
+        for address in [0xfff_f000, 0x2000_0000] {
+            let blobs = vec![("bad".to_owned(), 0x2000, Some(GuestAddress(address)))];
+            assert!(matches!(
+                layout_payloads(&gm, None, &blobs, kernel_region),
+                Err(Error::PayloadBlobOutOfRam(id)) if id == "bad"
+            ));
+        }
+    }
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -3387,17 +4726,60 @@ mod tests {
             &mem,
             "console=tty0",
             vec![0],
-            Some((0, 0, 0)),
+            Some((0, 0, 0, 0)),
             &dev_info,
             &gic,
             &None,
+            &[],
             &Vec::new(),
             &BTreeMap::new(),
             None,
             true,
+            None,
+            false,
         )
         .is_ok())
     }
+
+    #[test]
+    fn test_layout_payloads() {
+        let gm = GuestMemoryMmap::from_ranges(&[(layout::RAM_START, 0x1000_0000)]).unwrap();
+        let kernel_start = layout::KERNEL_START;
+        let kernel_region = Some((kernel_start, 0x100_0000));
+
+        let blobs = vec![
+            ("fw".to_owned(), 0x2000, Some(GuestAddress(0x4800_0000))),
+            ("cfg".to_owned(), 0x1000, None),
+        ];
+        let (initramfs, payloads) =
+            layout_payloads(&gm, Some(0x1800), &blobs, kernel_region).unwrap();
+        assert_eq!(initramfs.unwrap().address, GuestAddress(0x4fff_e000));
+        assert_eq!(payloads[0].address, GuestAddress(0x4800_0000));
+        assert_eq!(payloads[1].address, GuestAddress(0x4fff_d000));
+
+        // Overlapping the FDT, the ACPI tables, the kernel or the initramfs.
+        for address in [
+            layout::FDT_START.0,
+            layout::ACPI_START.0,
+            kernel_start.0 + 0x1000,
+            0x4fff_e000,
+        ] {
+            let blobs = vec![("bad".to_owned(), 0x1000, Some(GuestAddress(address)))];
+            assert!(matches!(
+                layout_payloads(&gm, Some(0x1800), &blobs, kernel_region),
+                Err(Error::PayloadBlobOverlap(id)) if id == "bad"
+            ));
+        }
+
+        // Out of the RAM.
+        for address in [0x3000_0000, 0x5000_0000] {
+            let blobs = vec![("bad".to_owned(), 0x1000, Some(GuestAddress(address)))];
+            assert!(matches!(
+                layout_payloads(&gm, None, &blobs, kernel_region),
+                Err(Error::PayloadBlobOutOfRam(id)) if id == "bad"
+            ));
+        }
+    }
 }
 
 #[cfg(all(feature = "kvm", target_arch = "x86_64"))]